@@ -74,6 +74,9 @@ pub mod methods {
     pub const CELLULAR_SET_BANDS: &str = "cellular.set_bands";
     pub const CELLULAR_SCAN: &str = "cellular.scan";
     pub const RECOVERY_USB_CYCLE: &str = "recovery.usb_cycle";
+    pub const SMS_LIST: &str = "sms.list";
+    pub const SMS_SEND: &str = "sms.send";
+    pub const SMS_DELETE: &str = "sms.delete";
 }
 
 pub mod capabilities {
@@ -81,6 +84,7 @@ pub mod capabilities {
     pub const LINK_CELLULAR: &str = "link.cellular";
     pub const CELLULAR_BAND_CONTROL: &str = "cellular.band_control";
     pub const CELLULAR_SCAN: &str = "cellular.scan";
+    pub const CELLULAR_SMS: &str = "cellular.sms";
     pub const RECOVERY_USB_CYCLE: &str = "recovery.usb_cycle";
     pub const RECOVERY_TUNNEL_WATCHDOG: &str = "recovery.tunnel_watchdog";
 }
@@ -9,6 +9,7 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
+use sctl::apn_failover::ApnFailover;
 use sctl::config::{GpsConfig, LteConfig};
 use sctl::gps::GpsState;
 use sctl::lte::LteState;
@@ -30,6 +31,7 @@ struct Runtime {
     gps_state: Option<Arc<Mutex<GpsState>>>,
     lte_state: Option<Arc<Mutex<LteState>>>,
     watchdog_snapshot: Option<Arc<Mutex<WatchdogSnapshot>>>,
+    apn_failover: Option<Arc<Mutex<ApnFailover>>>,
     tunnel_stats: Arc<TunnelStats>,
     tasks: Vec<tokio::task::JoinHandle<()>>,
     data_dir: String,
@@ -59,6 +61,8 @@ struct LinkPollParams {
 #[derive(Default, Deserialize)]
 struct StatusParams {
     tunnel_connected: Option<bool>,
+    /// The tunnel client's current run of failed connection attempts.
+    tunnel_failures: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -83,6 +87,17 @@ struct ScanParams {
     tunnel_connected: bool,
 }
 
+#[derive(Deserialize)]
+struct SmsSendParams {
+    number: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct SmsDeleteParams {
+    index: u32,
+}
+
 #[derive(Deserialize)]
 struct SpeedTestParams {
     interface: String,
@@ -149,6 +164,9 @@ async fn handle_request(req: CommsRequest, runtime: Arc<Mutex<Runtime>>) -> Comm
         methods::CELLULAR_SET_BANDS => set_bands(req.params, runtime).await,
         methods::CELLULAR_SCAN => scan(req.params, runtime).await,
         methods::RECOVERY_USB_CYCLE => usb_cycle(runtime).await,
+        methods::SMS_LIST => sms_list(runtime).await,
+        methods::SMS_SEND => sms_send(req.params, runtime).await,
+        methods::SMS_DELETE => sms_delete(req.params, runtime).await,
         _ => Err((
             "COMMS_CAPABILITY_UNSUPPORTED",
             format!("unsupported method {}", req.method),
@@ -176,6 +194,7 @@ fn provider_capabilities() -> Vec<&'static str> {
         capabilities::LINK_CELLULAR,
         capabilities::CELLULAR_BAND_CONTROL,
         capabilities::CELLULAR_SCAN,
+        capabilities::CELLULAR_SMS,
         capabilities::RECOVERY_USB_CYCLE,
         capabilities::RECOVERY_TUNNEL_WATCHDOG,
     ]
@@ -284,6 +303,13 @@ async fn open(
 
         if lte_cfg.watchdog && params.tunnel_url.is_some() {
             let snapshot = Arc::new(Mutex::new(WatchdogSnapshot::new()));
+            let apn_failover = if lte_cfg.apn_profiles.is_empty() {
+                None
+            } else {
+                let failover =
+                    ApnFailover::load(lte_cfg.apn_profiles.clone(), &params.data_dir).await;
+                Some(Arc::new(Mutex::new(failover)))
+            };
             let task = sctl::lte_watchdog::spawn_lte_watchdog(
                 modem,
                 tx,
@@ -295,15 +321,19 @@ async fn open(
                 params.tunnel_url,
                 snapshot.clone(),
                 guard.modem_detected_path.clone(),
+                apn_failover.clone(),
             );
             guard.watchdog_snapshot = Some(snapshot);
+            guard.apn_failover = apn_failover;
             guard.tasks.push(task);
         } else {
             guard.watchdog_snapshot = None;
+            guard.apn_failover = None;
         }
     } else {
         guard.lte_state = None;
         guard.watchdog_snapshot = None;
+        guard.apn_failover = None;
     }
 
     Ok(status_value(&guard))
@@ -322,6 +352,12 @@ async fn status(
             .connected
             .store(connected, Ordering::Relaxed);
     }
+    if let Some(failures) = params.tunnel_failures {
+        guard
+            .tunnel_stats
+            .connect_failures
+            .store(failures, Ordering::Relaxed);
+    }
     Ok(status_value(&guard))
 }
 
@@ -331,6 +367,7 @@ fn status_value(runtime: &Runtime) -> Value {
         "status": if runtime.modem.is_some() { "ok" } else { "not_open" },
         "detected_path": runtime.detected_path.clone(),
         "capabilities": provider_capabilities(),
+        "link_generation": runtime.tunnel_stats.link_generation.load(Ordering::Relaxed),
     })
 }
 
@@ -366,11 +403,12 @@ async fn link_poll(
 ) -> Result<Value, (&'static str, String)> {
     let params: LinkPollParams =
         serde_json::from_value(params).map_err(|e| ("INVALID_REQUEST", e.to_string()))?;
-    let (lte_state, watchdog_snapshot, notify, tunnel_stats) = {
+    let (lte_state, watchdog_snapshot, apn_failover, notify, tunnel_stats) = {
         let guard = runtime.lock().await;
         (
             guard.lte_state.clone(),
             guard.watchdog_snapshot.clone(),
+            guard.apn_failover.clone(),
             guard.lte_poll_notify.clone(),
             guard.tunnel_stats.clone(),
         )
@@ -394,7 +432,12 @@ async fn link_poll(
         }
     }
 
-    Ok(snapshot_lte(&lte_state, watchdog_snapshot.as_ref()).await)
+    Ok(snapshot_lte(
+        &lte_state,
+        watchdog_snapshot.as_ref(),
+        apn_failover.as_ref(),
+    )
+    .await)
 }
 
 async fn speed_test(params: Value) -> Result<Value, (&'static str, String)> {
@@ -443,12 +486,13 @@ async fn set_bands(
         }
     };
 
-    let (modem, lte_state, watchdog_snapshot, data_dir, lte_config, tunnel_stats) = {
+    let (modem, lte_state, watchdog_snapshot, apn_failover, data_dir, lte_config, tunnel_stats) = {
         let guard = runtime.lock().await;
         (
             guard.modem.clone(),
             guard.lte_state.clone(),
             guard.watchdog_snapshot.clone(),
+            guard.apn_failover.clone(),
             guard.data_dir.clone(),
             guard.lte_config.clone(),
             guard.tunnel_stats.clone(),
@@ -541,7 +585,12 @@ async fn set_bands(
             }
         }
 
-        let snapshot = snapshot_lte(&lte_state, watchdog_snapshot.as_ref()).await;
+        let snapshot = snapshot_lte(
+            &lte_state,
+            watchdog_snapshot.as_ref(),
+            apn_failover.as_ref(),
+        )
+        .await;
         return Ok(json!({
             "status": "ok",
             "mode": params.mode,
@@ -643,7 +692,12 @@ async fn set_bands(
         "pending"
     };
 
-    let snapshot = snapshot_lte(&lte_state, watchdog_snapshot.as_ref()).await;
+    let snapshot = snapshot_lte(
+        &lte_state,
+        watchdog_snapshot.as_ref(),
+        apn_failover.as_ref(),
+    )
+    .await;
     Ok(json!({
         "status": "ok",
         "mode": params.mode,
@@ -749,6 +803,53 @@ async fn usb_cycle(runtime: Arc<Mutex<Runtime>>) -> Result<Value, (&'static str,
     }))
 }
 
+/// The live modem handle: the watchdog's latest re-open if there is one.
+async fn current_modem(runtime: &Arc<Mutex<Runtime>>) -> Result<Modem, (&'static str, String)> {
+    let guard = runtime.lock().await;
+    guard
+        .modem_tx
+        .as_ref()
+        .map(|tx| tx.borrow().clone())
+        .or_else(|| guard.modem.clone())
+        .ok_or_else(|| ("MODEM_UNAVAILABLE", "modem is not open".to_string()))
+}
+
+async fn sms_list(runtime: Arc<Mutex<Runtime>>) -> Result<Value, (&'static str, String)> {
+    let modem = current_modem(&runtime).await?;
+    let messages = sctl::modem::sms_list(&modem)
+        .await
+        .map_err(|e| ("SMS_FAILED", e))?;
+    Ok(json!({ "messages": messages }))
+}
+
+async fn sms_send(
+    params: Value,
+    runtime: Arc<Mutex<Runtime>>,
+) -> Result<Value, (&'static str, String)> {
+    let params: SmsSendParams =
+        serde_json::from_value(params).map_err(|e| ("INVALID_REQUEST", e.to_string()))?;
+    sctl::modem::validate_sms(&params.number, &params.text).map_err(|e| ("INVALID_REQUEST", e))?;
+    let modem = current_modem(&runtime).await?;
+    let reference = sctl::modem::sms_send(&modem, &params.number, &params.text)
+        .await
+        .map_err(|e| ("SMS_FAILED", e))?;
+    info!("sms: sent to {} (reference {reference:?})", params.number);
+    Ok(json!({ "reference": reference }))
+}
+
+async fn sms_delete(
+    params: Value,
+    runtime: Arc<Mutex<Runtime>>,
+) -> Result<Value, (&'static str, String)> {
+    let params: SmsDeleteParams =
+        serde_json::from_value(params).map_err(|e| ("INVALID_REQUEST", e.to_string()))?;
+    let modem = current_modem(&runtime).await?;
+    sctl::modem::sms_delete(&modem, params.index)
+        .await
+        .map_err(|e| ("SMS_FAILED", e))?;
+    Ok(json!({ "deleted": params.index }))
+}
+
 fn snapshot_gps(gs: &GpsState) -> Value {
     let last_fix = gs.last_fix.as_ref().map(|fix| {
         json!({
@@ -795,6 +896,7 @@ fn snapshot_gps(gs: &GpsState) -> Value {
 async fn snapshot_lte(
     lte_state: &Arc<Mutex<LteState>>,
     watchdog_snapshot: Option<&Arc<Mutex<WatchdogSnapshot>>>,
+    apn_failover: Option<&Arc<Mutex<ApnFailover>>>,
 ) -> Value {
     let ls = lte_state.lock().await;
     let signal = ls.signal.as_ref().map(|sig| {
@@ -848,6 +950,12 @@ async fn snapshot_lte(
     } else {
         None
     };
+    let apn_failover = if let Some(failover) = apn_failover {
+        let snapshot = failover.lock().await.snapshot();
+        Some(serde_json::to_value(snapshot).unwrap_or_default())
+    } else {
+        None
+    };
 
     json!({
         "signal": signal,
@@ -858,5 +966,6 @@ async fn snapshot_lte(
         "scan_status": scan_status,
         "registration_pending": registration_pending,
         "watchdog": watchdog,
+        "apn_failover": apn_failover,
     })
 }
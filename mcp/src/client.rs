@@ -14,8 +14,27 @@
 //! parsing fails, the raw response body is returned as the error message.
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// gawdxfer chunk size for uploads and downloads.
+const STP_CHUNK_SIZE: usize = 256 * 1024;
+/// Tries per chunk before a transfer is given up (and left to resume).
+const STP_CHUNK_ATTEMPTS: u32 = 4;
+
+/// Options for [`SctlClient::upload_file`] and [`SctlClient::download_file`].
+#[derive(Default)]
+pub struct TransferOptions<'a> {
+    /// Continue this transfer instead of starting a new one.
+    pub resume: Option<&'a str>,
+    /// File permissions for uploads, e.g. `"0644"`.
+    pub mode: Option<&'a str>,
+    /// Bandwidth cap in bytes/s, at most the device's own cap.
+    pub rate_limit_bps: Option<u64>,
+}
+
 /// HTTP client for a single sctl device.
 #[derive(Clone)]
 pub struct SctlClient {
@@ -87,8 +106,25 @@ impl SctlClient {
         timeout_ms: Option<u64>,
         working_dir: Option<&str>,
         env: Option<&HashMap<String, String>>,
+    ) -> Result<serde_json::Value, ClientError> {
+        self.exec_with(command, timeout_ms, working_dir, env, None)
+            .await
+    }
+
+    /// `POST /api/exec` with an explicit `full_output` choice (`None` lets the
+    /// device summarize large output).
+    pub async fn exec_with(
+        &self,
+        command: &str,
+        timeout_ms: Option<u64>,
+        working_dir: Option<&str>,
+        env: Option<&HashMap<String, String>>,
+        full_output: Option<bool>,
     ) -> Result<serde_json::Value, ClientError> {
         let mut body = serde_json::json!({ "command": command });
+        if let Some(f) = full_output {
+            body["full_output"] = serde_json::json!(f);
+        }
         if let Some(t) = timeout_ms {
             body["timeout_ms"] = serde_json::json!(t);
         }
@@ -118,8 +154,12 @@ impl SctlClient {
         commands: &[serde_json::Value],
         working_dir: Option<&str>,
         env: Option<&HashMap<String, String>>,
+        full_output: Option<bool>,
     ) -> Result<serde_json::Value, ClientError> {
         let mut body = serde_json::json!({ "commands": commands });
+        if let Some(f) = full_output {
+            body["full_output"] = serde_json::json!(f);
+        }
         if let Some(d) = working_dir {
             body["working_dir"] = serde_json::json!(d);
         }
@@ -138,6 +178,46 @@ impl SctlClient {
         Self::handle_response(resp).await
     }
 
+    /// `GET /api/activity/{id}/result` — full output of a cached exec.
+    pub async fn exec_result(&self, activity_id: u64) -> Result<serde_json::Value, ClientError> {
+        let resp = self
+            .http
+            .get(format!(
+                "{}/api/activity/{activity_id}/result",
+                self.base_url
+            ))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        Self::handle_response(resp).await
+    }
+
+    /// `GET /api/sessions/{id}/history` — commands run in a session.
+    pub async fn session_history(
+        &self,
+        session_id: &str,
+        limit: Option<u64>,
+    ) -> Result<serde_json::Value, ClientError> {
+        let mut url = reqwest::Url::parse(&format!(
+            "{}/api/sessions/{session_id}/history",
+            self.base_url
+        ))
+        .map_err(|e| ClientError::Protocol(format!("Invalid base URL: {e}")))?;
+        if let Some(limit) = limit {
+            url.query_pairs_mut()
+                .append_pair("limit", &limit.to_string());
+        }
+        let resp = self
+            .http
+            .get(url)
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        Self::handle_response(resp).await
+    }
+
     /// `GET /api/files` — read a file or list a directory.
     pub async fn file_read(
         &self,
@@ -308,68 +388,28 @@ impl SctlClient {
         data: &[u8],
         mode: Option<&str>,
     ) -> Result<serde_json::Value, ClientError> {
-        const CHUNK_SIZE: usize = 256 * 1024; // 256KB
-
         let file_hash = sha256_hex(data);
-        let total_chunks = data.len().div_ceil(CHUNK_SIZE);
-
-        // Extract filename from path
-        let filename = path.rsplit('/').next().unwrap_or(path).to_string();
+        let total_chunks = data.len().div_ceil(STP_CHUNK_SIZE);
 
         // 1. Init transfer
+        let (dir, filename) = split_remote_path(path)?;
         let mut init_body = serde_json::json!({
-            "path": path,
+            "path": dir,
             "filename": filename,
             "file_size": data.len() as u64,
             "file_hash": file_hash,
-            "chunk_size": CHUNK_SIZE as u32,
+            "chunk_size": STP_CHUNK_SIZE as u32,
             "total_chunks": total_chunks as u32,
         });
         if let Some(m) = mode {
             init_body["mode"] = serde_json::json!(m);
         }
+        let init_result = self.stp_post("upload", &init_body).await?;
+        let transfer_id = stp_transfer_id(&init_result)?;
 
-        let resp = self
-            .http
-            .post(format!("{}/api/stp/upload", self.base_url))
-            .bearer_auth(&self.api_key)
-            .json(&init_body)
-            .send()
-            .await
-            .map_err(ClientError::Request)?;
-        let init_result = Self::handle_response(resp).await?;
-
-        let transfer_id = init_result["transfer_id"]
-            .as_str()
-            .ok_or_else(|| {
-                ClientError::Protocol("Missing transfer_id in upload init response".into())
-            })?
-            .to_string();
-
-        // 2. Upload chunks — use a longer timeout for chunk uploads
-        let chunk_client = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(10))
-            .timeout(Duration::from_secs(120))
-            .build()
-            .map_err(|e| ClientError::Protocol(format!("Failed to build chunk client: {e}")))?;
-
-        for (idx, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
-            let chunk_hash = sha256_hex(chunk);
-
-            let resp = chunk_client
-                .post(format!(
-                    "{}/api/stp/chunk/{}/{}",
-                    self.base_url, transfer_id, idx
-                ))
-                .bearer_auth(&self.api_key)
-                .header("content-type", "application/octet-stream")
-                .header("x-gx-chunk-hash", &chunk_hash)
-                .body(chunk.to_vec())
-                .send()
-                .await
-                .map_err(ClientError::Request)?;
-            let ack = Self::handle_response(resp).await?;
-
+        // 2. Upload chunks
+        for (idx, chunk) in data.chunks(STP_CHUNK_SIZE).enumerate() {
+            let ack = self.stp_put_chunk(&transfer_id, idx as u32, chunk).await?;
             if ack["ok"].as_bool() != Some(true) {
                 let err_msg = ack["error"].as_str().unwrap_or("chunk rejected");
                 return Err(ClientError::Protocol(format!(
@@ -387,6 +427,327 @@ impl SctlClient {
         }))
     }
 
+    /// Upload a local file to `remote_path` with gawdxfer, reading it one
+    /// chunk at a time. Each chunk is retried a few times (resuming the
+    /// transfer on the device in between); if it still fails, the error
+    /// carries the transfer id so a later call with `resume` picks up from
+    /// the chunks the device already has.
+    ///
+    /// `progress` is called with bytes done and the file size after every
+    /// chunk.
+    pub async fn upload_file(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        opts: &TransferOptions<'_>,
+        progress: &(dyn Fn(u64, u64) + Sync),
+    ) -> Result<serde_json::Value, ClientError> {
+        let start = std::time::Instant::now();
+        let mut file = tokio::fs::File::open(local_path)
+            .await
+            .map_err(|e| local_error(local_path, &e))?;
+        let file_size = file
+            .metadata()
+            .await
+            .map_err(|e| local_error(local_path, &e))?
+            .len();
+        let file_hash = sha256_file(&mut file, local_path).await?;
+        let total_chunks = file_size.div_ceil(STP_CHUNK_SIZE as u64).max(1) as u32;
+
+        let (transfer_id, done) = if let Some(id) = opts.resume {
+            let resumed = self.stp_resume(id).await?;
+            if resumed["file_size"].as_u64() != Some(file_size)
+                || resumed["file_hash"]
+                    .as_str()
+                    .is_some_and(|h| h != file_hash)
+            {
+                return Err(ClientError::Protocol(format!(
+                    "Transfer {id} is for a different file than {}",
+                    local_path.display()
+                )));
+            }
+            (id.to_string(), chunks_received(&resumed))
+        } else {
+            let (dir, filename) = split_remote_path(remote_path)?;
+            let mut body = serde_json::json!({
+                "path": dir,
+                "filename": filename,
+                "file_size": file_size,
+                "file_hash": file_hash,
+                "chunk_size": STP_CHUNK_SIZE as u32,
+                "total_chunks": total_chunks,
+            });
+            if let Some(m) = opts.mode {
+                body["mode"] = serde_json::json!(m);
+            }
+            if let Some(r) = opts.rate_limit_bps {
+                body["rate_limit_bps"] = serde_json::json!(r);
+            }
+            let init = self.stp_post("upload", &body).await?;
+            (stp_transfer_id(&init)?, Vec::new())
+        };
+
+        let mut bytes_done: u64 = done.iter().map(|&idx| chunk_len(idx, file_size)).sum();
+        progress(bytes_done, file_size);
+        let mut buf = vec![0u8; STP_CHUNK_SIZE];
+        for idx in (0..total_chunks).filter(|i| !done.contains(i)) {
+            let len = chunk_len(idx, file_size) as usize;
+            file.seek(std::io::SeekFrom::Start(
+                u64::from(idx) * STP_CHUNK_SIZE as u64,
+            ))
+            .await
+            .map_err(|e| local_error(local_path, &e))?;
+            file.read_exact(&mut buf[..len])
+                .await
+                .map_err(|e| local_error(local_path, &e))?;
+            let chunk = &buf[..len];
+            self.with_retries(&transfer_id, || async {
+                let ack = self.stp_put_chunk(&transfer_id, idx, chunk).await?;
+                if ack["ok"].as_bool() == Some(true) {
+                    Ok(())
+                } else {
+                    Err(ClientError::Protocol(format!(
+                        "Chunk {idx}/{total_chunks} rejected: {}",
+                        ack["error"].as_str().unwrap_or("chunk rejected")
+                    )))
+                }
+            })
+            .await?;
+            bytes_done += len as u64;
+            progress(bytes_done, file_size);
+        }
+
+        Ok(serde_json::json!({
+            "ok": true,
+            "direction": "upload",
+            "transfer_id": transfer_id,
+            "remote_path": remote_path,
+            "local_path": local_path.display().to_string(),
+            "size": file_size,
+            "chunks": total_chunks,
+            "resumed_chunks": done.len(),
+            "file_hash": file_hash,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        }))
+    }
+
+    /// Download `remote_path` to a local file with gawdxfer. Chunks are
+    /// appended to `<local_path>.part` and checked against their hashes; the
+    /// whole file is checked against the device's hash before it is renamed
+    /// into place. Failed chunks are retried like in [`Self::upload_file`],
+    /// and `resume` continues from what the `.part` file already holds.
+    pub async fn download_file(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        opts: &TransferOptions<'_>,
+        progress: &(dyn Fn(u64, u64) + Sync),
+    ) -> Result<serde_json::Value, ClientError> {
+        let start = std::time::Instant::now();
+        let part_path = {
+            let mut p = local_path.as_os_str().to_owned();
+            p.push(".part");
+            std::path::PathBuf::from(p)
+        };
+
+        let (transfer_id, info, mut part, first) = if let Some(id) = opts.resume {
+            let info = self.stp_resume(id).await?;
+            let chunk_size = info["chunk_size"].as_u64().unwrap_or(1).max(1);
+            let part = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&part_path)
+                .await
+                .map_err(|e| local_error(&part_path, &e))?;
+            let have = part
+                .metadata()
+                .await
+                .map_err(|e| local_error(&part_path, &e))?
+                .len();
+            // Keep only whole chunks; a partly written one is fetched again.
+            let first = have / chunk_size;
+            part.set_len(first * chunk_size)
+                .await
+                .map_err(|e| local_error(&part_path, &e))?;
+            (id.to_string(), info, part, first as u32)
+        } else {
+            let mut body = serde_json::json!({
+                "path": remote_path,
+                "chunk_size": STP_CHUNK_SIZE as u32,
+            });
+            if let Some(r) = opts.rate_limit_bps {
+                body["rate_limit_bps"] = serde_json::json!(r);
+            }
+            let info = self.stp_post("download", &body).await?;
+            let part = tokio::fs::File::create(&part_path)
+                .await
+                .map_err(|e| local_error(&part_path, &e))?;
+            (stp_transfer_id(&info)?, info, part, 0)
+        };
+        let file_size = info["file_size"].as_u64().unwrap_or(0);
+        let file_hash = info["file_hash"].as_str().unwrap_or("").to_string();
+        let chunk_size = info["chunk_size"].as_u64().unwrap_or(1).max(1);
+        let total_chunks = info["total_chunks"].as_u64().unwrap_or(0) as u32;
+
+        let mut bytes_done = (u64::from(first) * chunk_size).min(file_size);
+        progress(bytes_done, file_size);
+        part.seek(std::io::SeekFrom::Start(bytes_done))
+            .await
+            .map_err(|e| local_error(&part_path, &e))?;
+        for idx in first..total_chunks {
+            let data = self
+                .with_retries(&transfer_id, || self.stp_get_chunk(&transfer_id, idx))
+                .await?;
+            part.write_all(&data)
+                .await
+                .map_err(|e| local_error(&part_path, &e))?;
+            bytes_done += data.len() as u64;
+            progress(bytes_done, file_size);
+        }
+        part.flush()
+            .await
+            .map_err(|e| local_error(&part_path, &e))?;
+        drop(part);
+
+        let mut part = tokio::fs::File::open(&part_path)
+            .await
+            .map_err(|e| local_error(&part_path, &e))?;
+        let got_hash = sha256_file(&mut part, &part_path).await?;
+        if !file_hash.is_empty() && got_hash != file_hash {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(ClientError::Protocol(format!(
+                "Downloaded file hash {got_hash} does not match the device's {file_hash}"
+            )));
+        }
+        tokio::fs::rename(&part_path, local_path)
+            .await
+            .map_err(|e| local_error(local_path, &e))?;
+
+        Ok(serde_json::json!({
+            "ok": true,
+            "direction": "download",
+            "transfer_id": transfer_id,
+            "remote_path": remote_path,
+            "local_path": local_path.display().to_string(),
+            "size": file_size,
+            "chunks": total_chunks,
+            "resumed_chunks": first,
+            "file_hash": got_hash,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        }))
+    }
+
+    /// Run one chunk operation, retrying it up to [`STP_CHUNK_ATTEMPTS`]
+    /// times. Between attempts the transfer is resumed on the device, which
+    /// pauses transfers whose tunnel dropped. A final failure is wrapped in
+    /// [`ClientError::Transfer`] so the caller can resume later.
+    async fn with_retries<T, F, Fut>(&self, transfer_id: &str, mut op: F) -> Result<T, ClientError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, ClientError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(v) => return Ok(v),
+                Err(e) if attempt < STP_CHUNK_ATTEMPTS && !e.is_not_found() => {
+                    tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                    let _ = self.stp_resume(transfer_id).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(ClientError::Transfer {
+                        transfer_id: transfer_id.to_string(),
+                        source: Box::new(e),
+                    })
+                }
+            }
+        }
+    }
+
+    /// `POST /api/stp/{upload,download}` — init a chunked transfer.
+    async fn stp_post(
+        &self,
+        what: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value, ClientError> {
+        let resp = self
+            .http
+            .post(format!("{}/api/stp/{what}", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(body)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        Self::handle_response(resp).await
+    }
+
+    /// `POST /api/stp/resume/{id}` — resume a paused transfer; lists the
+    /// chunks the device has.
+    async fn stp_resume(&self, transfer_id: &str) -> Result<serde_json::Value, ClientError> {
+        let resp = self
+            .http
+            .post(format!("{}/api/stp/resume/{transfer_id}", self.base_url))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        Self::handle_response(resp).await
+    }
+
+    /// `POST /api/stp/chunk/{id}/{idx}` — send one upload chunk.
+    async fn stp_put_chunk(
+        &self,
+        transfer_id: &str,
+        idx: u32,
+        chunk: &[u8],
+    ) -> Result<serde_json::Value, ClientError> {
+        let resp = self
+            .http
+            .post(format!(
+                "{}/api/stp/chunk/{transfer_id}/{idx}",
+                self.base_url
+            ))
+            .bearer_auth(&self.api_key)
+            .header("content-type", "application/octet-stream")
+            .header("x-gx-chunk-hash", sha256_hex(chunk))
+            .body(chunk.to_vec())
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        Self::handle_response(resp).await
+    }
+
+    /// `GET /api/stp/chunk/{id}/{idx}` — fetch one download chunk, checked
+    /// against its `X-Gx-Chunk-Hash`.
+    async fn stp_get_chunk(&self, transfer_id: &str, idx: u32) -> Result<Vec<u8>, ClientError> {
+        let resp = self
+            .http
+            .get(format!(
+                "{}/api/stp/chunk/{transfer_id}/{idx}",
+                self.base_url
+            ))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+        if !resp.status().is_success() {
+            return Self::handle_response(resp).await.map(|_| Vec::new());
+        }
+        let expected = resp
+            .headers()
+            .get("x-gx-chunk-hash")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let data = resp.bytes().await.map_err(ClientError::Request)?;
+        if sha256_hex(&data) != expected {
+            return Err(ClientError::Protocol(format!(
+                "Chunk {idx} failed its integrity check"
+            )));
+        }
+        Ok(data.to_vec())
+    }
+
     /// Parse an HTTP response — returns the JSON body on success, or a
     /// [`ClientError`] with the error message on failure.
     async fn handle_response(resp: reqwest::Response) -> Result<serde_json::Value, ClientError> {
@@ -410,6 +771,70 @@ impl SctlClient {
     }
 }
 
+/// Split a device file path into the directory and file name gawdxfer
+/// uploads take.
+fn split_remote_path(path: &str) -> Result<(&str, &str), ClientError> {
+    match path.rsplit_once('/') {
+        Some((dir, name)) if !name.is_empty() => Ok((if dir.is_empty() { "/" } else { dir }, name)),
+        _ => Err(ClientError::Protocol(format!(
+            "Remote path must be an absolute file path: {path}"
+        ))),
+    }
+}
+
+fn stp_transfer_id(init: &serde_json::Value) -> Result<String, ClientError> {
+    init["transfer_id"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| {
+            ClientError::Protocol("Missing transfer_id in transfer init response".into())
+        })
+}
+
+/// Chunk indexes from a `POST /api/stp/resume` response.
+fn chunks_received(resumed: &serde_json::Value) -> Vec<u32> {
+    resumed["chunks_received"]
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_u64())
+                .map(|v| v as u32)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Bytes in chunk `idx` of a `file_size`-byte file.
+fn chunk_len(idx: u32, file_size: u64) -> u64 {
+    let offset = u64::from(idx) * STP_CHUNK_SIZE as u64;
+    file_size.saturating_sub(offset).min(STP_CHUNK_SIZE as u64)
+}
+
+fn local_error(path: &Path, e: &std::io::Error) -> ClientError {
+    ClientError::Local(format!("{}: {e}", path.display()))
+}
+
+/// SHA-256 of a whole local file, read from the start.
+async fn sha256_file(file: &mut tokio::fs::File, path: &Path) -> Result<String, ClientError> {
+    use sha2::{Digest, Sha256};
+    file.seek(std::io::SeekFrom::Start(0))
+        .await
+        .map_err(|e| local_error(path, &e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| local_error(path, &e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 /// Compute SHA-256 hash of data, returning lowercase hex string.
 fn sha256_hex(data: &[u8]) -> String {
     use sha2::{Digest, Sha256};
@@ -427,12 +852,24 @@ pub enum ClientError {
     Device { status: u16, message: String },
     /// The response body was not valid JSON.
     Protocol(String),
+    /// Reading or writing a file on this machine failed.
+    Local(String),
+    /// A chunked transfer stopped after retries; it can be resumed with
+    /// `transfer_id`.
+    Transfer {
+        transfer_id: String,
+        source: Box<ClientError>,
+    },
 }
 
 impl ClientError {
     /// Returns `true` if the error is an HTTP 404 Not Found response.
     pub fn is_not_found(&self) -> bool {
-        matches!(self, ClientError::Device { status: 404, .. })
+        match self {
+            ClientError::Device { status, .. } => *status == 404,
+            ClientError::Transfer { source, .. } => source.is_not_found(),
+            _ => false,
+        }
     }
 }
 
@@ -444,6 +881,35 @@ impl std::fmt::Display for ClientError {
                 write!(f, "Device error (HTTP {}): {}", status, message)
             }
             ClientError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            ClientError::Local(msg) => write!(f, "Local file error: {}", msg),
+            ClientError::Transfer {
+                transfer_id,
+                source,
+            } => write!(f, "Transfer {} interrupted: {}", transfer_id, source),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_paths_split_into_directory_and_name() {
+        assert_eq!(
+            split_remote_path("/tmp/fw/image.bin").unwrap(),
+            ("/tmp/fw", "image.bin")
+        );
+        assert_eq!(split_remote_path("/image.bin").unwrap(), ("/", "image.bin"));
+        assert!(split_remote_path("/tmp/").is_err());
+        assert!(split_remote_path("image.bin").is_err());
+    }
+
+    #[test]
+    fn last_chunk_holds_the_remainder() {
+        let size = STP_CHUNK_SIZE as u64 * 2 + 10;
+        assert_eq!(chunk_len(0, size), STP_CHUNK_SIZE as u64);
+        assert_eq!(chunk_len(2, size), 10);
+        assert_eq!(chunk_len(0, 0), 0);
+    }
+}
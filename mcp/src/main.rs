@@ -14,6 +14,7 @@
 //! mcp.rs               — MCP JSON-RPC protocol handler (stdio)
 //! tools.rs             — tool definitions and handlers
 //! websocket.rs         — WebSocket client with auto-reconnect and local buffers
+//! subscriptions.rs     — session output pattern watchers (push notifications)
 //! playbooks.rs         — playbook model, parsing, rendering (pure data)
 //! playbook_registry.rs — per-device playbook cache with lazy fetch
 //! ```
@@ -23,7 +24,8 @@
 //! - **Device tools** (HTTP): `device_list`, `device_health`, `device_info`,
 //!   `device_exec`, `device_exec_batch`, `device_file_read`, `device_file_write`
 //! - **Session tools** (WebSocket): `session_start`, `session_exec`,
-//!   `session_send`, `session_read`, `session_signal`, `session_kill`
+//!   `session_send`, `session_read`, `session_signal`, `session_kill`,
+//!   `session_wait_for`, `session_subscribe`, `session_unsubscribe`
 //! - **Playbook management**: `playbook_list`, `playbook_get`, `playbook_put`
 //! - **Dynamic playbook tools** (`pb_*`): one per playbook discovered on devices
 
@@ -33,6 +35,7 @@ mod devices;
 mod mcp;
 mod playbook_registry;
 mod playbooks;
+mod subscriptions;
 mod supervisor;
 mod tools;
 mod websocket;
@@ -14,16 +14,25 @@
 //! | `ping`              | Liveness check                   |
 //!
 //! Notifications (`notifications/initialized`, `notifications/cancelled`) are
-//! acknowledged silently.
+//! acknowledged silently. A `tools/call` carrying `_meta.progressToken` gets
+//! `notifications/progress` from tools that report it (see [`Progress`]).
+//! `session_subscribe` matches are pushed as `notifications/session_output`
+//! (see [`crate::subscriptions`]).
+//!
+//! `tools/call` requests run concurrently, each in its own task, so a
+//! long-polling tool like `session_wait_for` doesn't hold up other calls.
+//! Responses can therefore arrive out of order; clients match them by `id`.
 
 use std::sync::Arc;
 
 use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 
 use crate::devices::DeviceRegistry;
 use crate::playbook_registry::PlaybookRegistry;
+use crate::subscriptions::Subscriptions;
 use crate::tools;
 
 const SERVER_NAME: &str = "mcp-sctl";
@@ -49,8 +58,13 @@ pub async fn run_stdio(registry: DeviceRegistry, pb_registry: PlaybookRegistry)
 
     let registry = Arc::new(registry);
     let pb_registry = Arc::new(pb_registry);
+    let subs = Arc::new(Subscriptions::new(tx.clone()));
+    let mut calls = JoinSet::new();
 
     loop {
+        // Reap finished tool calls.
+        while calls.try_join_next().is_some() {}
+
         line.clear();
         match reader.read_line(&mut line).await {
             Ok(0) => break, // EOF
@@ -96,10 +110,22 @@ pub async fn run_stdio(registry: DeviceRegistry, pb_registry: PlaybookRegistry)
             continue;
         }
 
+        if method == "tools/call" {
+            let registry = Arc::clone(&registry);
+            let pb_registry = Arc::clone(&pb_registry);
+            let subs = Arc::clone(&subs);
+            let tx = tx.clone();
+            calls.spawn(async move {
+                let (response, notify_tools_changed) =
+                    handle_tools_call(&request, &registry, &pb_registry, &subs, &tx).await;
+                send_response(&tx, response, id, notify_tools_changed).await;
+            });
+            continue;
+        }
+
         let (response, notify_tools_changed) = match method {
             "initialize" => (handle_initialize(&request), false),
             "tools/list" => handle_tools_list(&registry, &pb_registry, tx.clone()).await,
-            "tools/call" => handle_tools_call(&request, &registry, &pb_registry).await,
             "ping" => (json!({ "jsonrpc": "2.0", "id": id, "result": {} }), false),
             _ => (
                 json!({
@@ -114,25 +140,36 @@ pub async fn run_stdio(registry: DeviceRegistry, pb_registry: PlaybookRegistry)
             ),
         };
 
-        // Inject the request id into the response
-        let response = inject_id(response, id);
-        let _ = tx.send(response).await;
-
-        // Send tools/list_changed notification after the response
-        if notify_tools_changed {
-            let notification = json!({
-                "jsonrpc": "2.0",
-                "method": "notifications/tools/list_changed"
-            });
-            let _ = tx.send(notification).await;
-        }
+        send_response(&tx, response, id, notify_tools_changed).await;
     }
 
-    // Drop sender to close the writer task.
+    // Nobody is left to read the results: cancel in-flight calls and
+    // subscriptions, then drop the last sender to close the writer task.
+    calls.shutdown().await;
+    subs.shutdown().await;
+    drop(subs);
     drop(tx);
     let _ = writer_handle.await;
 }
 
+/// Send a response with its request `id`, followed by
+/// `notifications/tools/list_changed` if the tool list changed.
+async fn send_response(
+    tx: &mpsc::Sender<Value>,
+    response: Value,
+    id: Option<Value>,
+    notify_tools_changed: bool,
+) {
+    let _ = tx.send(inject_id(response, id)).await;
+    if notify_tools_changed {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/tools/list_changed"
+        });
+        let _ = tx.send(notification).await;
+    }
+}
+
 /// Handle `initialize` — return protocol version, capabilities, and server info.
 fn handle_initialize(request: &Value) -> Value {
     let _params = request.get("params");
@@ -198,12 +235,18 @@ async fn handle_tools_call(
     request: &Value,
     registry: &DeviceRegistry,
     pb_reg: &PlaybookRegistry,
+    subs: &Subscriptions,
+    tx: &mpsc::Sender<Value>,
 ) -> (Value, bool) {
     let params = request.get("params").cloned().unwrap_or(json!({}));
     let name = params.get("name").and_then(Value::as_str).unwrap_or("");
     let args = params.get("arguments").cloned().unwrap_or(json!({}));
+    let progress = Progress {
+        token: params.pointer("/_meta/progressToken").cloned(),
+        tx: tx.clone(),
+    };
 
-    let result = tools::handle_tool_call(name, &args, registry, pb_reg).await;
+    let result = tools::handle_tool_call(name, &args, registry, pb_reg, &progress, subs).await;
     let tools_changed = result.tools_changed;
 
     let mut response_result = json!({
@@ -222,6 +265,31 @@ async fn handle_tools_call(
     )
 }
 
+/// Progress reporting for one `tools/call`.
+pub struct Progress {
+    /// The request's `_meta.progressToken`; without one nothing is sent.
+    token: Option<Value>,
+    tx: mpsc::Sender<Value>,
+}
+
+impl Progress {
+    /// Send `notifications/progress`. Dropped rather than waited for if
+    /// stdout is backed up — a later report supersedes it anyway.
+    pub fn report(&self, progress: u64, total: u64) {
+        if let Some(ref token) = self.token {
+            let _ = self.tx.try_send(json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/progress",
+                "params": {
+                    "progressToken": token,
+                    "progress": progress,
+                    "total": total
+                }
+            }));
+        }
+    }
+}
+
 /// Inject the request `id` into a response object.
 fn inject_id(mut response: Value, id: Option<Value>) -> Value {
     if let Some(id) = id {
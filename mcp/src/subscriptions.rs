@@ -0,0 +1,153 @@
+//! Session output subscriptions.
+//!
+//! `session_subscribe` registers a pattern on a session. A background task
+//! watches the session's local buffer and pushes a
+//! `notifications/session_output` to the MCP client when the pattern shows
+//! up, so an agent waiting for a build doesn't have to poll `session_read`.
+//! Any number of subscriptions can run at once; each one ends when its
+//! pattern matches (unless `once` is false), when the session exits, or on
+//! `session_unsubscribe`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::websocket::{DeviceWsConnection, PatternScanner, SessionStatus};
+
+/// How long each `wait_for` slice runs before the watcher loops again.
+const WATCH_SLICE_MS: u64 = 60_000;
+
+/// Active subscriptions, keyed by subscription ID.
+pub struct Subscriptions {
+    tx: mpsc::Sender<Value>,
+    next_id: AtomicU64,
+    active: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+}
+
+/// What a new subscription watches.
+pub struct Subscription {
+    pub session_id: String,
+    pub pattern: String,
+    pub since: u64,
+    /// Stop after the first match.
+    pub once: bool,
+}
+
+impl Subscriptions {
+    /// `tx` is the MCP stdout channel notifications are written to.
+    pub fn new(tx: mpsc::Sender<Value>) -> Self {
+        Self {
+            tx,
+            next_id: AtomicU64::new(1),
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Start watching and return the new subscription's ID.
+    pub async fn subscribe(&self, ws: Arc<DeviceWsConnection>, sub: Subscription) -> String {
+        let id = format!("sub-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let tx = self.tx.clone();
+        let active = Arc::clone(&self.active);
+        let task_id = id.clone();
+
+        // Hold the lock across the spawn so a watcher that finishes at once
+        // can't try to remove itself before it has been inserted.
+        let mut map = self.active.lock().await;
+        let handle = tokio::spawn(async move {
+            watch(&ws, &sub, &task_id, &tx).await;
+            active.lock().await.remove(&task_id);
+        });
+        map.insert(id.clone(), handle);
+        id
+    }
+
+    /// Cancel a subscription. Returns false if it doesn't exist (or already ended).
+    pub async fn unsubscribe(&self, id: &str) -> bool {
+        match self.active.lock().await.remove(id) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// IDs of subscriptions still running.
+    pub async fn list(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.active.lock().await.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Cancel every subscription (on MCP shutdown).
+    pub async fn shutdown(&self) {
+        for (_, handle) in self.active.lock().await.drain() {
+            handle.abort();
+        }
+    }
+}
+
+/// Watch loop for one subscription.
+async fn watch(ws: &DeviceWsConnection, sub: &Subscription, id: &str, tx: &mpsc::Sender<Value>) {
+    let mut scanner = PatternScanner::new(&sub.pattern);
+    let mut since = sub.since;
+
+    loop {
+        let result = match ws
+            .wait_for(&sub.session_id, &mut scanner, since, WATCH_SLICE_MS)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                notify(tx, id, sub, json!({ "error": e })).await;
+                return;
+            }
+        };
+        since = result.last_seq;
+
+        if let Some(m) = result.matched {
+            notify(
+                tx,
+                id,
+                sub,
+                json!({ "matched": true, "line": m.line, "seq": m.seq }),
+            )
+            .await;
+            if sub.once {
+                return;
+            }
+        } else if result.status == SessionStatus::Exited {
+            notify(
+                tx,
+                id,
+                sub,
+                json!({ "matched": false, "exited": true, "exit_code": result.exit_code }),
+            )
+            .await;
+            return;
+        }
+    }
+}
+
+/// Send a `notifications/session_output` for `sub`, merging `fields` into params.
+async fn notify(tx: &mpsc::Sender<Value>, id: &str, sub: &Subscription, fields: Value) {
+    let mut params = json!({
+        "subscription_id": id,
+        "session_id": sub.session_id,
+        "pattern": sub.pattern,
+    });
+    if let (Some(params), Value::Object(fields)) = (params.as_object_mut(), fields) {
+        params.extend(fields);
+    }
+    let _ = tx
+        .send(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/session_output",
+            "params": params
+        }))
+        .await;
+}
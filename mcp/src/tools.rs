@@ -9,10 +9,14 @@
 //! - `device_list`, `device_health`, `device_info`
 //! - `device_exec`, `device_exec_batch`
 //! - `device_file_read`, `device_file_write`
+//! - `device_file_transfer` (gawdxfer chunked upload/download, with progress and resume)
 //!
 //! **Session tools** use the WebSocket API via [`DeviceWsConnection`](crate::websocket::DeviceWsConnection):
 //! - `session_start`, `session_exec`, `session_send`
-//! - `session_read`, `session_signal`, `session_kill`
+//! - `session_read`, `session_read_screen`, `session_signal`, `session_kill`
+//! - `session_history` (REST)
+//! - `session_wait_for` (long-poll), `session_subscribe` / `session_unsubscribe`
+//!   (push via `notifications/session_output`, see [`crate::subscriptions`])
 //!
 //! **Playbook management tools** (always present):
 //! - `playbook_list`, `playbook_get`, `playbook_put`
@@ -23,9 +27,12 @@ use std::collections::HashMap;
 
 use serde_json::{json, Value};
 
+use crate::client::TransferOptions;
 use crate::devices::DeviceRegistry;
+use crate::mcp::Progress;
 use crate::playbook_registry::PlaybookRegistry;
 use crate::playbooks;
+use crate::subscriptions::{Subscription, Subscriptions};
 
 /// Returns all tool definitions: builtins + playbook management + dynamic pb_* tools.
 pub async fn all_tool_definitions(pb_reg: &PlaybookRegistry) -> Vec<Value> {
@@ -101,8 +108,12 @@ fn builtin_tool_definitions() -> Vec<Value> {
                     },
                     "env": {
                         "type": "object",
-                        "description": "Environment variables to set for the command.",
+                        "description": "Environment variables to set for the command. Use 'secret://<name>' values for credentials — they are resolved on the device and redacted from output, never pass raw secrets.",
                         "additionalProperties": { "type": "string" }
+                    },
+                    "full_output": {
+                        "type": "boolean",
+                        "description": "Return complete stdout/stderr. By default, large output (over 16 KiB per stream) is replaced by a compact summary and the response carries a 'summary' object; pass its activity_id to device_exec_result to fetch the full output later."
                     }
                 },
                 "required": ["command"],
@@ -149,12 +160,35 @@ fn builtin_tool_definitions() -> Vec<Value> {
                         "type": "object",
                         "description": "Default environment variables for all commands.",
                         "additionalProperties": { "type": "string" }
+                    },
+                    "full_output": {
+                        "type": "boolean",
+                        "description": "Return complete stdout/stderr for every command instead of summaries of large output (see device_exec)."
                     }
                 },
                 "required": ["commands"],
                 "additionalProperties": false
             }
         }),
+        json!({
+            "name": "device_exec_result",
+            "description": "Fetch the full stdout/stderr of an earlier device_exec whose output was summarized. Use the activity_id from the response's 'summary' object. Results are kept in a bounded in-memory cache on the device, so fetch soon after the exec.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "activity_id": {
+                        "type": "integer",
+                        "description": "activity_id from the summarized exec response."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    }
+                },
+                "required": ["activity_id"],
+                "additionalProperties": false
+            }
+        }),
         json!({
             "name": "device_file_read",
             "description": "Read a file or list a directory on a sctl device. For directories, set list=true.",
@@ -214,6 +248,46 @@ fn builtin_tool_definitions() -> Vec<Value> {
                 "additionalProperties": false
             }
         }),
+        json!({
+            "name": "device_file_transfer",
+            "description": "Copy a file between this machine and a sctl device in checksummed 256KB chunks (gawdxfer). Use it instead of device_file_read/device_file_write for large or binary files. Each chunk is retried on failure; if the transfer still stops, the error names a transfer_id — call again with the same arguments plus transfer_id to resume where it left off.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "direction": {
+                        "type": "string",
+                        "description": "'upload' copies local_path to the device, 'download' copies remote_path from it.",
+                        "enum": ["upload", "download"]
+                    },
+                    "local_path": {
+                        "type": "string",
+                        "description": "File path on the machine running mcp-sctl. Downloads are written to '<local_path>.part' and renamed when complete."
+                    },
+                    "remote_path": {
+                        "type": "string",
+                        "description": "Absolute file path on the device. For uploads its directory must exist."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    },
+                    "transfer_id": {
+                        "type": "string",
+                        "description": "Resume this interrupted transfer instead of starting over."
+                    },
+                    "mode": {
+                        "type": "string",
+                        "description": "Uploads: file permissions as octal string, e.g. '0755'."
+                    },
+                    "rate_limit_bps": {
+                        "type": "integer",
+                        "description": "Bandwidth cap in bytes per second."
+                    }
+                },
+                "required": ["direction", "local_path", "remote_path"],
+                "additionalProperties": false
+            }
+        }),
         json!({
             "name": "device_file_delete",
             "description": "Delete a file on a sctl device.",
@@ -289,7 +363,7 @@ fn builtin_tool_definitions() -> Vec<Value> {
                     },
                     "env": {
                         "type": "object",
-                        "description": "Environment variables to set.",
+                        "description": "Environment variables to set. Use 'secret://<name>' values for credentials — they are resolved on the device.",
                         "additionalProperties": { "type": "string" }
                     },
                     "persistent": {
@@ -315,6 +389,10 @@ fn builtin_tool_definitions() -> Vec<Value> {
                     "name": {
                         "type": "string",
                         "description": "Human-readable name for the session. Optional. Helps identify sessions in multi-client environments."
+                    },
+                    "template": {
+                        "type": "string",
+                        "description": "Name of a [session_templates] entry in the device config. Fields you omit (shell, working_dir, env, pty, rows, cols, idle_timeout, name) come from the template."
                     }
                 },
                 "additionalProperties": false
@@ -366,6 +444,52 @@ fn builtin_tool_definitions() -> Vec<Value> {
                 "additionalProperties": false
             }
         }),
+        json!({
+            "name": "session_read_screen",
+            "description": "Read the current screen of a PTY session as plain text rows (no ANSI codes). Pass the returned token as since on the next call to get only the rows that changed — far cheaper than session_read for polling TUIs like htop, top, or a progress display. The response has full=true (every row) on the first read, after a resize, when a program enters or leaves the alternate screen, or when the token is stale. Colours and attributes are not included.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": {
+                        "type": "string",
+                        "description": "Session ID of a PTY session (session_start with pty=true)."
+                    },
+                    "since": {
+                        "type": "integer",
+                        "description": "token from a previous session_read_screen. Omit for the full screen."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    }
+                },
+                "required": ["session_id"],
+                "additionalProperties": false
+            }
+        }),
+        json!({
+            "name": "session_history",
+            "description": "List commands already run in a session, oldest first: command text, source ('exec' = sent via session_exec, 'shell' = typed at the prompt and reported by shell integration), start/finish times, and exit_code where the shell reports it. Use this after re-attaching to a session to see what was tried without reading back the whole scrollback.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": {
+                        "type": "string",
+                        "description": "Session ID."
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "description": "Most recent commands to return. Default 100, max 500."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    }
+                },
+                "required": ["session_id"],
+                "additionalProperties": false
+            }
+        }),
         json!({
             "name": "session_read",
             "description": "Read buffered output from a session. Returns entries since the given sequence number. In PTY mode, output contains ANSI escape codes for cursor movement, colors, etc. After sending input, allow 0.5-2s before reading to let the program process and render.",
@@ -503,6 +627,82 @@ fn builtin_tool_definitions() -> Vec<Value> {
                 "additionalProperties": false
             }
         }),
+        json!({
+            "name": "session_wait_for",
+            "description": "Wait until a text pattern appears in a session's output, then return the matching line. Use this instead of polling session_read while a build or long command runs, e.g. pattern 'BUILD SUCCESSFUL' or a shell prompt. Plain substring match (case-sensitive); in PTY mode, ANSI codes inside the text can prevent a match. Returns early if the session exits. Reports notifications/progress while waiting if the call carries a progress token.\n\nResponse: matched, line, seq, last_seq, status, exit_code, timed_out. Pass last_seq as since on the next call to only match newer output.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": {
+                        "type": "string",
+                        "description": "Session ID from session_start."
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Text to wait for."
+                    },
+                    "since": {
+                        "type": "integer",
+                        "description": "Sequence number. Only output with seq > since is searched. Default 0 (whole buffer); pass last_seq from session_read to skip output you've seen."
+                    },
+                    "timeout_ms": {
+                        "type": "integer",
+                        "description": "Max milliseconds to wait. Default 60000, max 600000."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    }
+                },
+                "required": ["session_id", "pattern"],
+                "additionalProperties": false
+            }
+        }),
+        json!({
+            "name": "session_subscribe",
+            "description": "Subscribe to a pattern in a session's output. Returns a subscription_id immediately; when the pattern appears, mcp-sctl sends a notifications/session_output message with subscription_id, session_id, pattern, matched=true, line and seq. If the session exits first, the notification has matched=false, exited=true and exit_code. Several subscriptions can be active at once, on any sessions.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "session_id": {
+                        "type": "string",
+                        "description": "Session ID from session_start."
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Text to watch for (plain substring, case-sensitive)."
+                    },
+                    "since": {
+                        "type": "integer",
+                        "description": "Sequence number. Only output with seq > since is watched. Default: the session's current last sequence, i.e. only new output."
+                    },
+                    "once": {
+                        "type": "boolean",
+                        "description": "End the subscription after the first match. Default true; false notifies on every match until the session exits or session_unsubscribe."
+                    },
+                    "device": {
+                        "type": "string",
+                        "description": "Device name. Omit to use the default device."
+                    }
+                },
+                "required": ["session_id", "pattern"],
+                "additionalProperties": false
+            }
+        }),
+        json!({
+            "name": "session_unsubscribe",
+            "description": "Cancel a subscription from session_subscribe. Omit subscription_id to list the active subscriptions instead.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "subscription_id": {
+                        "type": "string",
+                        "description": "Subscription ID from session_subscribe."
+                    }
+                },
+                "additionalProperties": false
+            }
+        }),
         json!({
             "name": "session_attach",
             "description": "Re-attach to an existing persistent session. Use this after MCP restart to reconnect to sessions that are still alive on the daemon. Combined with session_list to discover session IDs. Returns buffered output since the given sequence number.",
@@ -675,6 +875,8 @@ pub async fn handle_tool_call(
     args: &Value,
     registry: &DeviceRegistry,
     pb_reg: &PlaybookRegistry,
+    progress: &Progress,
+    subs: &Subscriptions,
 ) -> ToolResult {
     match name {
         "device_list" => handle_device_list(registry).await,
@@ -682,8 +884,10 @@ pub async fn handle_tool_call(
         "device_info" => handle_device_info(args, registry).await,
         "device_exec" => handle_device_exec(args, registry).await,
         "device_exec_batch" => handle_device_exec_batch(args, registry).await,
+        "device_exec_result" => handle_device_exec_result(args, registry).await,
         "device_file_read" => handle_device_file_read(args, registry).await,
         "device_file_write" => handle_device_file_write(args, registry).await,
+        "device_file_transfer" => handle_device_file_transfer(args, registry, progress).await,
         "device_file_delete" => handle_device_file_delete(args, registry).await,
         "device_activity" => handle_device_activity(args, registry).await,
         "device_gps" => handle_device_gps(args, registry).await,
@@ -691,11 +895,16 @@ pub async fn handle_tool_call(
         "session_exec" => handle_session_exec(args, registry).await,
         "session_send" => handle_session_send(args, registry).await,
         "session_read" => handle_session_read(args, registry).await,
+        "session_read_screen" => handle_session_read_screen(args, registry).await,
+        "session_history" => handle_session_history(args, registry).await,
         "session_signal" => handle_session_signal(args, registry).await,
         "session_kill" => handle_session_kill(args, registry).await,
         "session_resize" => handle_session_resize(args, registry).await,
         "session_list" => handle_session_list(args, registry).await,
         "session_exec_wait" => handle_session_exec_wait(args, registry).await,
+        "session_wait_for" => handle_session_wait_for(args, registry, progress).await,
+        "session_subscribe" => handle_session_subscribe(args, registry, subs).await,
+        "session_unsubscribe" => handle_session_unsubscribe(args, subs).await,
         "session_attach" => handle_session_attach(args, registry).await,
         "session_rename" => handle_session_rename(args, registry).await,
         "session_allow_ai" => handle_session_allow_ai(args, registry).await,
@@ -793,9 +1002,10 @@ async fn handle_device_exec(args: &Value, registry: &DeviceRegistry) -> ToolResu
     let env: Option<HashMap<String, String>> = args
         .get("env")
         .and_then(|v| serde_json::from_value(v.clone()).ok());
+    let full_output = args.get("full_output").and_then(Value::as_bool);
 
     match client
-        .exec(command, timeout_ms, working_dir, env.as_ref())
+        .exec_with(command, timeout_ms, working_dir, env.as_ref(), full_output)
         .await
     {
         Ok(v) => ToolResult::success(v),
@@ -830,9 +1040,10 @@ async fn handle_device_exec_batch(args: &Value, registry: &DeviceRegistry) -> To
     let env: Option<HashMap<String, String>> = args
         .get("env")
         .and_then(|v| serde_json::from_value(v.clone()).ok());
+    let full_output = args.get("full_output").and_then(Value::as_bool);
 
     match client
-        .exec_batch(&normalized, working_dir, env.as_ref())
+        .exec_batch(&normalized, working_dir, env.as_ref(), full_output)
         .await
     {
         Ok(v) => ToolResult::success(v),
@@ -840,6 +1051,31 @@ async fn handle_device_exec_batch(args: &Value, registry: &DeviceRegistry) -> To
     }
 }
 
+async fn handle_device_exec_result(args: &Value, registry: &DeviceRegistry) -> ToolResult {
+    let client = match registry.resolve(get_device_param(args)).await {
+        Ok(c) => c,
+        Err(e) => return ToolResult::error(e),
+    };
+
+    let activity_id = match args.get("activity_id").and_then(Value::as_u64) {
+        Some(id) => id,
+        None => return ToolResult::error("Missing required parameter: activity_id".into()),
+    };
+
+    match client.exec_result(activity_id).await {
+        Ok(v) => ToolResult::success(v),
+        Err(e) => {
+            if e.is_not_found() {
+                ToolResult::error(format!(
+                    "No cached result for activity {activity_id} (evicted from the device's exec cache)"
+                ))
+            } else {
+                ToolResult::error(e.to_string())
+            }
+        }
+    }
+}
+
 async fn handle_device_file_read(args: &Value, registry: &DeviceRegistry) -> ToolResult {
     let client = match registry.resolve(get_device_param(args)).await {
         Ok(c) => c,
@@ -918,6 +1154,65 @@ async fn handle_device_file_write(args: &Value, registry: &DeviceRegistry) -> To
     }
 }
 
+async fn handle_device_file_transfer(
+    args: &Value,
+    registry: &DeviceRegistry,
+    progress: &Progress,
+) -> ToolResult {
+    let client = match registry.resolve(get_device_param(args)).await {
+        Ok(c) => c,
+        Err(e) => return ToolResult::error(e),
+    };
+
+    let direction = match args.get("direction").and_then(Value::as_str) {
+        Some(d) => d,
+        None => return ToolResult::error("Missing required parameter: direction".into()),
+    };
+    let local_path = match args.get("local_path").and_then(Value::as_str) {
+        Some(p) => std::path::Path::new(p),
+        None => return ToolResult::error("Missing required parameter: local_path".into()),
+    };
+    let remote_path = match args.get("remote_path").and_then(Value::as_str) {
+        Some(p) => p,
+        None => return ToolResult::error("Missing required parameter: remote_path".into()),
+    };
+
+    let opts = TransferOptions {
+        resume: args.get("transfer_id").and_then(Value::as_str),
+        mode: args.get("mode").and_then(Value::as_str),
+        rate_limit_bps: args.get("rate_limit_bps").and_then(Value::as_u64),
+    };
+    let report = |done, total| progress.report(done, total);
+    let result = match direction {
+        "upload" => {
+            client
+                .upload_file(local_path, remote_path, &opts, &report)
+                .await
+        }
+        "download" => {
+            client
+                .download_file(remote_path, local_path, &opts, &report)
+                .await
+        }
+        other => {
+            return ToolResult::error(format!(
+                "Invalid direction '{other}': expected 'upload' or 'download'"
+            ))
+        }
+    };
+
+    match result {
+        Ok(v) => ToolResult::success(v),
+        Err(crate::client::ClientError::Transfer {
+            transfer_id,
+            source,
+        }) => ToolResult::error(format!(
+            "Transfer {transfer_id} interrupted: {source}. Call device_file_transfer again with transfer_id=\"{transfer_id}\" to resume."
+        )),
+        Err(e) => ToolResult::error(e.to_string()),
+    }
+}
+
 async fn handle_device_file_delete(args: &Value, registry: &DeviceRegistry) -> ToolResult {
     let client = match registry.resolve(get_device_param(args)).await {
         Ok(c) => c,
@@ -1015,6 +1310,7 @@ async fn handle_session_start(args: &Value, registry: &DeviceRegistry) -> ToolRe
     let cols = args.get("cols").and_then(Value::as_u64);
     let idle_timeout = args.get("idle_timeout").and_then(Value::as_u64);
     let name = args.get("name").and_then(Value::as_str);
+    let template = args.get("template").and_then(Value::as_str);
 
     match ws
         .start_session(
@@ -1027,6 +1323,7 @@ async fn handle_session_start(args: &Value, registry: &DeviceRegistry) -> ToolRe
             cols,
             idle_timeout,
             name,
+            template,
             true,
         )
         .await
@@ -1266,6 +1563,52 @@ async fn handle_session_read(args: &Value, registry: &DeviceRegistry) -> ToolRes
     }
 }
 
+async fn handle_session_read_screen(args: &Value, registry: &DeviceRegistry) -> ToolResult {
+    let ws = match get_ws_connection(args, registry).await {
+        Ok(ws) => ws,
+        Err(e) => return e,
+    };
+
+    let session_id = match args.get("session_id").and_then(Value::as_str) {
+        Some(s) => s,
+        None => return ToolResult::error("Missing required parameter: session_id".into()),
+    };
+    let since = args.get("since").and_then(Value::as_u64);
+
+    ws.auto_set_ai_working(session_id, "read").await;
+
+    match ws.read_screen_diff(session_id, since).await {
+        Ok(diff) => ToolResult::success(json!({
+            "token": diff["token"],
+            "full": diff["full"],
+            "rows": diff["rows"],
+            "cols": diff["cols"],
+            "cursor": diff["cursor"],
+            "alt_screen": diff["alt_screen"],
+            "lines": diff["lines"],
+        })),
+        Err(e) => ToolResult::error(e),
+    }
+}
+
+async fn handle_session_history(args: &Value, registry: &DeviceRegistry) -> ToolResult {
+    let client = match registry.resolve(get_device_param(args)).await {
+        Ok(c) => c,
+        Err(e) => return ToolResult::error(e),
+    };
+
+    let session_id = match args.get("session_id").and_then(Value::as_str) {
+        Some(s) => s,
+        None => return ToolResult::error("Missing required parameter: session_id".into()),
+    };
+    let limit = args.get("limit").and_then(Value::as_u64);
+
+    match client.session_history(session_id, limit).await {
+        Ok(v) => ToolResult::success(v),
+        Err(e) => ToolResult::error(e.to_string()),
+    }
+}
+
 async fn handle_session_signal(args: &Value, registry: &DeviceRegistry) -> ToolResult {
     let ws = match get_ws_connection(args, registry).await {
         Ok(ws) => ws,
@@ -1450,6 +1793,129 @@ async fn handle_session_exec_wait(args: &Value, registry: &DeviceRegistry) -> To
     }
 }
 
+/// Default and max `timeout_ms` for `session_wait_for`.
+const WAIT_FOR_DEFAULT_MS: u64 = 60_000;
+const WAIT_FOR_MAX_MS: u64 = 600_000;
+/// How often `session_wait_for` reports progress while waiting.
+const WAIT_FOR_TICK_MS: u64 = 5_000;
+
+async fn handle_session_wait_for(
+    args: &Value,
+    registry: &DeviceRegistry,
+    progress: &Progress,
+) -> ToolResult {
+    let ws = match get_ws_connection(args, registry).await {
+        Ok(ws) => ws,
+        Err(e) => return e,
+    };
+
+    let session_id = match args.get("session_id").and_then(Value::as_str) {
+        Some(s) => s,
+        None => return ToolResult::error("Missing required parameter: session_id".into()),
+    };
+    let pattern = match args.get("pattern").and_then(Value::as_str) {
+        Some(p) if !p.is_empty() => p,
+        _ => return ToolResult::error("Missing required parameter: pattern".into()),
+    };
+    let since = args.get("since").and_then(Value::as_u64).unwrap_or(0);
+    let timeout_ms = args
+        .get("timeout_ms")
+        .and_then(Value::as_u64)
+        .unwrap_or(WAIT_FOR_DEFAULT_MS)
+        .min(WAIT_FOR_MAX_MS);
+
+    ws.auto_set_ai_working(session_id, "read").await;
+
+    // Wait in slices so the client sees progress and doesn't time the call out.
+    let mut scanner = crate::websocket::PatternScanner::new(pattern);
+    let mut last_seq = since;
+    let mut waited = 0;
+    loop {
+        let slice = WAIT_FOR_TICK_MS.min(timeout_ms - waited);
+        let started = tokio::time::Instant::now();
+        let result = match ws.wait_for(session_id, &mut scanner, last_seq, slice).await {
+            Ok(r) => r,
+            Err(e) => return ToolResult::error(e),
+        };
+        waited = (waited + started.elapsed().as_millis() as u64).min(timeout_ms);
+        last_seq = result.last_seq;
+
+        let exited = result.status == crate::websocket::SessionStatus::Exited;
+        if result.matched.is_some() || exited || waited >= timeout_ms {
+            let status = if exited { "exited" } else { "running" };
+            let (line, seq) = result
+                .matched
+                .map_or((None, None), |m| (Some(m.line), Some(m.seq)));
+            return ToolResult::success(json!({
+                "matched": line.is_some(),
+                "line": line,
+                "seq": seq,
+                "last_seq": last_seq,
+                "status": status,
+                "exit_code": result.exit_code,
+                "timed_out": line.is_none() && !exited,
+            }));
+        }
+        progress.report(waited, timeout_ms);
+    }
+}
+
+async fn handle_session_subscribe(
+    args: &Value,
+    registry: &DeviceRegistry,
+    subs: &Subscriptions,
+) -> ToolResult {
+    let ws = match get_ws_connection(args, registry).await {
+        Ok(ws) => ws,
+        Err(e) => return e,
+    };
+
+    let session_id = match args.get("session_id").and_then(Value::as_str) {
+        Some(s) => s,
+        None => return ToolResult::error("Missing required parameter: session_id".into()),
+    };
+    let pattern = match args.get("pattern").and_then(Value::as_str) {
+        Some(p) if !p.is_empty() => p,
+        _ => return ToolResult::error("Missing required parameter: pattern".into()),
+    };
+    let since = match args.get("since").and_then(Value::as_u64) {
+        Some(s) => s,
+        None => match ws.last_seq(session_id).await {
+            Some(s) => s,
+            None => return ToolResult::error(format!("Session {session_id} not found locally")),
+        },
+    };
+    let once = args.get("once").and_then(Value::as_bool).unwrap_or(true);
+
+    let id = subs
+        .subscribe(
+            ws,
+            Subscription {
+                session_id: session_id.to_string(),
+                pattern: pattern.to_string(),
+                since,
+                once,
+            },
+        )
+        .await;
+    ToolResult::success(json!({
+        "subscription_id": id,
+        "session_id": session_id,
+        "since": since,
+        "once": once,
+    }))
+}
+
+async fn handle_session_unsubscribe(args: &Value, subs: &Subscriptions) -> ToolResult {
+    match args.get("subscription_id").and_then(Value::as_str) {
+        Some(id) if subs.unsubscribe(id).await => {
+            ToolResult::success(json!({ "ok": true, "subscription_id": id }))
+        }
+        Some(id) => ToolResult::error(format!("No active subscription {id}")),
+        None => ToolResult::success(json!({ "subscriptions": subs.list().await })),
+    }
+}
+
 async fn handle_session_attach(args: &Value, registry: &DeviceRegistry) -> ToolResult {
     let ws = match get_ws_connection(args, registry).await {
         Ok(ws) => ws,
@@ -92,6 +92,8 @@ struct WsNotifiers {
     start_result: Arc<Mutex<Option<Value>>>,
     list_notify: Arc<Notify>,
     list_result: Arc<Mutex<Option<Value>>>,
+    diff_notify: Arc<Notify>,
+    diff_result: Arc<Mutex<Option<Value>>>,
     ai_status_notify: Arc<Notify>,
     ai_status_result: Arc<Mutex<Option<Value>>>,
 }
@@ -122,6 +124,8 @@ impl DeviceWsConnection {
             start_result: Arc::new(Mutex::new(None)),
             list_notify: Arc::new(Notify::new()),
             list_result: Arc::new(Mutex::new(None)),
+            diff_notify: Arc::new(Notify::new()),
+            diff_result: Arc::new(Mutex::new(None)),
             ai_status_notify: Arc::new(Notify::new()),
             ai_status_result: Arc::new(Mutex::new(None)),
         });
@@ -178,6 +182,7 @@ impl DeviceWsConnection {
         cols: Option<u64>,
         idle_timeout: Option<u64>,
         name: Option<&str>,
+        template: Option<&str>,
         user_allows_ai: bool,
     ) -> Result<Value, String> {
         // Clear any stale start result
@@ -214,6 +219,9 @@ impl DeviceWsConnection {
         if let Some(n) = name {
             msg["name"] = json!(n);
         }
+        if let Some(t) = template {
+            msg["template"] = json!(t);
+        }
 
         // Register waiter BEFORE sending to avoid Notify race: if the I/O
         // loop receives the response between send() and notified(), the
@@ -365,6 +373,15 @@ impl DeviceWsConnection {
         })
     }
 
+    /// Highest sequence number buffered for a session, if it is known locally.
+    pub async fn last_seq(&self, session_id: &str) -> Option<u64> {
+        self.sessions
+            .lock()
+            .await
+            .get(session_id)
+            .map(|b| b.last_seq)
+    }
+
     /// Check whether the WebSocket is currently connected.
     pub fn is_connected(&self) -> bool {
         self.connected.load(Ordering::SeqCst)
@@ -436,6 +453,44 @@ impl DeviceWsConnection {
             .ok_or_else(|| "No session.listed response received".to_string())
     }
 
+    /// Read the changed screen rows of a PTY session by sending
+    /// `session.read_diff`. `since` is the `token` from the previous read.
+    pub async fn read_screen_diff(
+        &self,
+        session_id: &str,
+        since: Option<u64>,
+    ) -> Result<Value, String> {
+        *self.notifiers.diff_result.lock().await = None;
+
+        let notified = self.notifiers.diff_notify.notified();
+        let mut msg = json!({ "type": "session.read_diff", "session_id": session_id });
+        if let Some(since) = since {
+            msg["since"] = json!(since);
+        }
+        self.send(msg).await?;
+
+        let result = tokio::time::timeout(tokio::time::Duration::from_secs(10), notified).await;
+
+        if result.is_err() {
+            return Err("Timeout waiting for session.diff".to_string());
+        }
+
+        let resp = self
+            .notifiers
+            .diff_result
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| "No session.diff response received".to_string())?;
+        if resp["type"] == "error" {
+            return Err(resp["message"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_string());
+        }
+        Ok(resp)
+    }
+
     /// Attach to an existing persistent session and replay buffered output.
     ///
     /// Creates a local `SessionBuffer` if one doesn't exist yet, sends
@@ -647,6 +702,128 @@ impl DeviceWsConnection {
             }
         }
     }
+
+    /// Wait until `scanner`'s pattern appears in output after `since`.
+    ///
+    /// Returns on the first match, when the session exits, or after
+    /// `timeout_ms`. On a match, `last_seq` is the matching entry's seq, so
+    /// calling again with it (and the same scanner) finds the next occurrence.
+    pub async fn wait_for(
+        &self,
+        session_id: &str,
+        scanner: &mut PatternScanner,
+        since: u64,
+        timeout_ms: u64,
+    ) -> Result<WaitForResult, String> {
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_millis(timeout_ms);
+        let mut last_seq = since;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let result = self
+                .read_output(session_id, last_seq, remaining.as_millis() as u64)
+                .await?;
+
+            for entry in &result.entries {
+                last_seq = entry.seq;
+                if let Some(line) = scanner.feed(&entry.data) {
+                    return Ok(WaitForResult {
+                        matched: Some(PatternMatch {
+                            seq: entry.seq,
+                            line,
+                        }),
+                        last_seq,
+                        status: result.status,
+                        exit_code: result.exit_code,
+                    });
+                }
+            }
+
+            if result.entries.is_empty()
+                && (result.status == SessionStatus::Exited || remaining.is_zero())
+            {
+                return Ok(WaitForResult {
+                    matched: None,
+                    last_seq,
+                    status: result.status,
+                    exit_code: result.exit_code,
+                });
+            }
+        }
+    }
+}
+
+/// Max bytes of unmatched output a [`PatternScanner`] keeps between feeds.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// Incremental substring search over session output.
+///
+/// Output arrives in arbitrary chunks, so a match can straddle two entries.
+/// The scanner keeps the tail of the current line between feeds and reports
+/// the whole line a match lands in.
+pub struct PatternScanner {
+    pattern: String,
+    pending: String,
+}
+
+impl PatternScanner {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            pending: String::new(),
+        }
+    }
+
+    /// Append `data` and return the line containing the first match, if any.
+    ///
+    /// Text up to the end of a match is consumed, so a later feed only
+    /// reports a new occurrence.
+    pub fn feed(&mut self, data: &str) -> Option<String> {
+        self.pending.push_str(data);
+
+        if let Some(pos) = self.pending.find(&self.pattern) {
+            let end = pos + self.pattern.len();
+            let start = self.pending[..pos].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = self.pending[end..]
+                .find('\n')
+                .map_or(self.pending.len(), |i| end + i);
+            let line = self.pending[start..line_end]
+                .trim_end_matches('\r')
+                .to_string();
+            self.pending.drain(..end);
+            return Some(line);
+        }
+
+        // Keep the current line (for context) but never less than a
+        // pattern's worth of bytes, which a match may still need.
+        let line_start = self.pending.rfind('\n').map_or(0, |i| i + 1);
+        let overlap = (self.pending.len() + 1).saturating_sub(self.pattern.len().max(1));
+        let mut cut = line_start
+            .max(self.pending.len().saturating_sub(MAX_PENDING_BYTES))
+            .min(overlap);
+        while !self.pending.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        self.pending.drain(..cut);
+        None
+    }
+}
+
+/// A pattern hit found by [`DeviceWsConnection::wait_for`].
+pub struct PatternMatch {
+    pub seq: u64,
+    /// The output line the match landed in (possibly still incomplete).
+    pub line: String,
+}
+
+/// Result of a `wait_for` call.
+pub struct WaitForResult {
+    /// `None` if the session exited or the wait timed out first.
+    pub matched: Option<PatternMatch>,
+    /// Last seq scanned; pass it as `since` to continue.
+    pub last_seq: u64,
+    pub status: SessionStatus,
+    pub exit_code: Option<i32>,
 }
 
 /// Result of a `read_output` call.
@@ -675,7 +852,9 @@ fn build_ws_url(base_url: &str, api_key: &str) -> Result<String, String> {
     } else {
         return Err(format!("Invalid URL scheme: {base}"));
     };
-    Ok(format!("{ws_base}/api/ws?token={api_key}"))
+    // `client=mcp` subjects session traffic to the device's AI guard, as the
+    // `X-Sctl-Client` header does for REST calls.
+    Ok(format!("{ws_base}/api/ws?token={api_key}&client=mcp"))
 }
 
 /// Parse an incoming WS message into an `OutputEntry` if it's a session output message.
@@ -845,6 +1024,10 @@ async fn dispatch_message(
             *n.list_result.lock().await = Some(msg.clone());
             n.list_notify.notify_waiters();
         }
+        "session.diff" => {
+            *n.diff_result.lock().await = Some(msg.clone());
+            n.diff_notify.notify_waiters();
+        }
         "session.attached" => {
             // Replay entries from attach response into local buffer
             let session_id = msg["session_id"].as_str().unwrap_or("");
@@ -892,6 +1075,11 @@ async fn dispatch_message(
             if code == "AI_NOT_ALLOWED" {
                 *n.ai_status_result.lock().await = Some(msg.clone());
                 n.ai_status_notify.notify_waiters();
+            } else if code == "SCREEN_UNAVAILABLE" {
+                // session.read_diff on a missing or non-PTY session; the
+                // session itself may be fine.
+                *n.diff_result.lock().await = Some(msg.clone());
+                n.diff_notify.notify_waiters();
             } else if let Some(session_id) = msg.get("session_id").and_then(Value::as_str) {
                 // Error targeting a specific session (e.g. attach to a session
                 // that no longer exists after device reboot). Mark it dead.
@@ -1109,4 +1297,20 @@ mod tests {
         // Gap 1→5 = 3, gap 5→10 = 4, total = 7
         assert_eq!(buf.dropped_count, 7);
     }
+
+    #[test]
+    fn pattern_scanner_matches_across_chunks() {
+        let mut scanner = PatternScanner::new("BUILD OK");
+        assert!(scanner.feed("compiling...\nBUI").is_none());
+        assert_eq!(
+            scanner.feed("LD OK in 3s\r\nnext").as_deref(),
+            Some("BUILD OK in 3s")
+        );
+        // The consumed match is not reported again.
+        assert!(scanner.feed(" line\n").is_none());
+        assert_eq!(
+            scanner.feed("again BUILD OK\n").as_deref(),
+            Some("again BUILD OK")
+        );
+    }
 }
@@ -13,15 +13,23 @@
 //! - **Zero-copy broadcast**: `log()` serializes the entry once and sends it through
 //!   the existing `broadcast::Sender<Value>` — the WS event loop already forwards
 //!   all broadcast messages to connected clients.
+//! - **Persistence**: with an [`ActivityJournal`] attached, entries are also
+//!   written to disk and reads older than the ring buffer are served from there.
 
 use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use axum::http::HeaderMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::{broadcast, RwLock};
 
+use crate::activity_journal::ActivityJournal;
+use crate::exec_spill::ExecSpill;
+use crate::hooks::Hooks;
+use crate::shell::classify::Risk;
+
 /// Types of activities tracked by the journal.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[cfg_attr(test, derive(ts_rs::TS))]
@@ -47,6 +55,40 @@ pub enum ActivityType {
     TunnelDisconnect,
     TransferStart,
     TransferComplete,
+    SshKeyList,
+    SshKeyWrite,
+    SshKeyDelete,
+    UserList,
+    UserModify,
+    TimeSet,
+    FirewallApply,
+    FirewallRollback,
+    ExecConfirm,
+    ExecRollback,
+    SessionShare,
+    SupportBundle,
+    ActivityExport,
+    AiKillSwitch,
+    PluginRun,
+    PlaybookRun,
+    TwinUpdate,
+    TwinApply,
+    TunnelKeyRotate,
+    AuthRotate,
+    PolicyDenied,
+    Fetch,
+    Forward,
+    Sftp,
+    Snapshot,
+    ArtifactPush,
+    NetmanUp,
+    NetmanDown,
+    SmsSend,
+    SmsDelete,
+    SmsCommand,
+    ServiceControl,
+    ConnectionClose,
+    FirmwareUpdate,
 }
 
 /// Where the request originated.
@@ -59,6 +101,8 @@ pub enum ActivitySource {
     Ws,
     Rest,
     Tunnel,
+    Mqtt,
+    Sms,
     Unknown,
 }
 
@@ -77,6 +121,10 @@ pub struct ActivityEntry {
     pub detail: Option<Value>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub request_id: Option<String>,
+    /// Name of the API key the action was made with (`default` for
+    /// `auth.api_key`, `local` on loopback listeners).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
 }
 
 impl ActivityType {
@@ -102,6 +150,40 @@ impl ActivityType {
             "tunnel_disconnect" => Some(Self::TunnelDisconnect),
             "transfer_start" => Some(Self::TransferStart),
             "transfer_complete" => Some(Self::TransferComplete),
+            "ssh_key_list" => Some(Self::SshKeyList),
+            "ssh_key_write" => Some(Self::SshKeyWrite),
+            "ssh_key_delete" => Some(Self::SshKeyDelete),
+            "user_list" => Some(Self::UserList),
+            "user_modify" => Some(Self::UserModify),
+            "time_set" => Some(Self::TimeSet),
+            "firewall_apply" => Some(Self::FirewallApply),
+            "firewall_rollback" => Some(Self::FirewallRollback),
+            "exec_confirm" => Some(Self::ExecConfirm),
+            "exec_rollback" => Some(Self::ExecRollback),
+            "session_share" => Some(Self::SessionShare),
+            "support_bundle" => Some(Self::SupportBundle),
+            "activity_export" => Some(Self::ActivityExport),
+            "ai_kill_switch" => Some(Self::AiKillSwitch),
+            "plugin_run" => Some(Self::PluginRun),
+            "playbook_run" => Some(Self::PlaybookRun),
+            "twin_update" => Some(Self::TwinUpdate),
+            "twin_apply" => Some(Self::TwinApply),
+            "tunnel_key_rotate" => Some(Self::TunnelKeyRotate),
+            "auth_rotate" => Some(Self::AuthRotate),
+            "policy_denied" => Some(Self::PolicyDenied),
+            "fetch" => Some(Self::Fetch),
+            "forward" => Some(Self::Forward),
+            "sftp" => Some(Self::Sftp),
+            "snapshot" => Some(Self::Snapshot),
+            "artifact_push" => Some(Self::ArtifactPush),
+            "netman_up" => Some(Self::NetmanUp),
+            "netman_down" => Some(Self::NetmanDown),
+            "sms_send" => Some(Self::SmsSend),
+            "sms_delete" => Some(Self::SmsDelete),
+            "sms_command" => Some(Self::SmsCommand),
+            "service_control" => Some(Self::ServiceControl),
+            "connection_close" => Some(Self::ConnectionClose),
+            "firmware_update" => Some(Self::FirmwareUpdate),
             _ => None,
         }
     }
@@ -115,18 +197,66 @@ impl ActivitySource {
             "ws" => Some(Self::Ws),
             "rest" => Some(Self::Rest),
             "tunnel" => Some(Self::Tunnel),
+            "mqtt" => Some(Self::Mqtt),
+            "sms" => Some(Self::Sms),
             "unknown" => Some(Self::Unknown),
             _ => None,
         }
     }
 }
 
+/// Conditions an entry must meet to be read (AND logic). All are optional.
+#[derive(Debug, Clone, Default)]
+pub struct ActivityFilter {
+    pub activity_type: Option<ActivityType>,
+    pub source: Option<ActivitySource>,
+    /// Matches `detail.session_id`.
+    pub session_id: Option<String>,
+    /// Matches `detail.risk` against any of these levels.
+    pub risk: Option<Vec<Risk>>,
+    /// Epoch ms, inclusive.
+    pub from: Option<u64>,
+    /// Epoch ms, inclusive.
+    pub to: Option<u64>,
+}
+
+impl ActivityFilter {
+    pub fn matches(&self, e: &ActivityEntry) -> bool {
+        self.activity_type.is_none_or(|t| e.activity_type == t)
+            && self.source.is_none_or(|s| e.source == s)
+            && self.from.is_none_or(|f| e.timestamp >= f)
+            && self.to.is_none_or(|t| e.timestamp <= t)
+            && self.session_id.as_deref().is_none_or(|sid| {
+                e.detail
+                    .as_ref()
+                    .and_then(|d| d["session_id"].as_str())
+                    .is_some_and(|s| s == sid)
+            })
+            && self.risk.as_deref().is_none_or(|levels| {
+                e.detail
+                    .as_ref()
+                    .and_then(|d| d["risk"].as_str())
+                    .and_then(Risk::from_str_opt)
+                    .is_some_and(|r| levels.contains(&r))
+            })
+    }
+}
+
+/// Receives every entry as it's logged, e.g. to persist or ship activity
+/// elsewhere. Called inline from [`ActivityLog::log`], so keep it cheap.
+pub trait ActivitySink: Send + Sync {
+    fn record(&self, entry: &ActivityEntry);
+}
+
 /// In-memory ring buffer of activity entries with broadcast support.
 pub struct ActivityLog {
     entries: RwLock<VecDeque<ActivityEntry>>,
     next_id: AtomicU64,
     max_entries: usize,
     broadcast_tx: broadcast::Sender<Value>,
+    sink: Option<Arc<dyn ActivitySink>>,
+    journal: Option<Arc<ActivityJournal>>,
+    hooks: Option<Arc<Hooks>>,
 }
 
 impl ActivityLog {
@@ -137,9 +267,39 @@ impl ActivityLog {
             next_id: AtomicU64::new(1),
             max_entries,
             broadcast_tx,
+            sink: None,
+            journal: None,
+            hooks: None,
         }
     }
 
+    /// Persist entries to `journal`, continuing its ids.
+    #[must_use]
+    pub fn with_journal(mut self, journal: Arc<ActivityJournal>) -> Self {
+        self.next_id = AtomicU64::new(journal.last_id() + 1);
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Pass entries through the `activity` Lua hook before keeping them.
+    #[must_use]
+    pub fn with_hooks(mut self, hooks: Arc<Hooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// The on-disk journal, if configured.
+    pub fn journal(&self) -> Option<&Arc<ActivityJournal>> {
+        self.journal.as_ref()
+    }
+
+    /// Also hand every entry to `sink`.
+    #[must_use]
+    pub fn with_sink(mut self, sink: Arc<dyn ActivitySink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
     /// Append an entry, broadcast it, and return the assigned ID.
     pub async fn log(
         &self,
@@ -156,7 +316,7 @@ impl ActivityLog {
             .unwrap_or_default()
             .as_millis() as u64;
 
-        let entry = ActivityEntry {
+        let mut entry = ActivityEntry {
             id,
             timestamp,
             activity_type,
@@ -164,7 +324,13 @@ impl ActivityLog {
             summary,
             detail,
             request_id,
+            key: crate::auth::current_key(),
         };
+        if let Some(hooks) = &self.hooks {
+            if !hooks.filter_activity(&mut entry) {
+                return id;
+            }
+        }
 
         // Broadcast before acquiring the write lock (non-blocking for readers)
         let _ = self.broadcast_tx.send(
@@ -173,6 +339,12 @@ impl ActivityLog {
             }
             .to_value(),
         );
+        if let Some(sink) = &self.sink {
+            sink.record(&entry);
+        }
+        if let Some(journal) = &self.journal {
+            journal.append(&entry);
+        }
 
         let mut entries = self.entries.write().await;
         if entries.len() >= self.max_entries {
@@ -183,43 +355,70 @@ impl ActivityLog {
         id
     }
 
+    /// Number of entries currently held.
+    pub async fn entry_count(&self) -> usize {
+        self.entries.read().await.len()
+    }
+
+    /// Number of entries logged since start, including evicted ones.
+    pub fn total(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed) - 1
+    }
+
     /// Read entries with `id > since_id`, up to `limit`.
     pub async fn read_since(&self, since_id: u64, limit: usize) -> Vec<ActivityEntry> {
-        let entries = self.entries.read().await;
-        entries
-            .iter()
-            .filter(|e| e.id > since_id)
-            .take(limit)
-            .cloned()
-            .collect()
+        self.read_since_filtered(since_id, limit, &ActivityFilter::default())
+            .await
     }
 
-    /// Read entries with optional filters (AND logic). All filters are optional.
+    /// Read entries with `id > since_id` that match `filter`, up to `limit`,
+    /// oldest first. Entries older than the ring buffer come from the
+    /// journal, if there is one.
     pub async fn read_since_filtered(
         &self,
         since_id: u64,
         limit: usize,
-        activity_type: Option<ActivityType>,
-        source: Option<ActivitySource>,
-        session_id: Option<&str>,
+        filter: &ActivityFilter,
     ) -> Vec<ActivityEntry> {
+        let oldest_held = {
+            let entries = self.entries.read().await;
+            entries
+                .front()
+                .map_or_else(|| self.next_id.load(Ordering::Relaxed), |e| e.id)
+        };
+        let mut out = Vec::new();
+        if let Some(journal) = self.journal.as_ref().filter(|_| since_id + 1 < oldest_held) {
+            match journal
+                .query(since_id, oldest_held, limit, filter.clone())
+                .await
+            {
+                Ok(entries) => out = entries,
+                Err(e) => tracing::warn!("Activity journal: read failed: {e}"),
+            }
+        }
+
         let entries = self.entries.read().await;
-        entries
-            .iter()
-            .filter(|e| e.id > since_id)
-            .filter(|e| activity_type.is_none_or(|t| e.activity_type == t))
-            .filter(|e| source.is_none_or(|s| e.source == s))
-            .filter(|e| {
-                session_id.is_none_or(|sid| {
-                    e.detail
-                        .as_ref()
-                        .and_then(|d| d["session_id"].as_str())
-                        .is_some_and(|s| s == sid)
-                })
-            })
-            .take(limit)
-            .cloned()
-            .collect()
+        let remaining = limit.saturating_sub(out.len());
+        out.extend(
+            entries
+                .iter()
+                .filter(|e| e.id > since_id && e.id >= oldest_held)
+                .filter(|e| filter.matches(e))
+                .take(remaining)
+                .cloned(),
+        );
+        out
+    }
+
+    /// All entries with `from <= timestamp <= to` (epoch ms), oldest first,
+    /// including the journal's.
+    pub async fn read_range(&self, from: Option<u64>, to: Option<u64>) -> Vec<ActivityEntry> {
+        let filter = ActivityFilter {
+            from,
+            to,
+            ..ActivityFilter::default()
+        };
+        self.read_since_filtered(0, usize::MAX, &filter).await
     }
 }
 
@@ -231,6 +430,7 @@ pub fn source_from_headers(headers: &HeaderMap) -> ActivitySource {
     match headers.get("x-sctl-client").and_then(|v| v.to_str().ok()) {
         Some("mcp") => ActivitySource::Mcp,
         Some("tunnel") => ActivitySource::Tunnel,
+        Some("mqtt") => ActivitySource::Mqtt,
         _ => ActivitySource::Rest,
     }
 }
@@ -248,7 +448,7 @@ pub fn request_id_from_headers(headers: &HeaderMap) -> Option<String> {
 // ---------------------------------------------------------------------------
 
 /// Full exec result cached in memory, keyed by activity ID.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedExecResult {
     pub activity_id: u64,
     pub exit_code: i32,
@@ -260,12 +460,20 @@ pub struct CachedExecResult {
     pub status: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
+    /// Compact summary, present when the output exceeded `summary.threshold_bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<crate::shell::summary::OutputSummary>,
+    /// Output was cut to `exec_results.max_result_bytes` when written to disk.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
-/// FIFO cache of recent exec results, keyed by activity ID.
+/// FIFO cache of recent exec results, keyed by activity ID, optionally
+/// backed by [`ExecSpill`] on disk.
 pub struct ExecResultsCache {
     inner: RwLock<ExecResultsCacheInner>,
     max_entries: usize,
+    spill: Option<Arc<ExecSpill>>,
 }
 
 struct ExecResultsCacheInner {
@@ -281,11 +489,32 @@ impl ExecResultsCache {
                 map: HashMap::with_capacity(max_entries),
             }),
             max_entries,
+            spill: None,
         }
     }
 
-    /// Store a result, evicting the oldest entry if at capacity.
+    /// Also write results to `spill`, and read from it on a miss.
+    #[must_use]
+    pub fn with_spill(mut self, spill: Arc<ExecSpill>) -> Self {
+        self.spill = Some(spill);
+        self
+    }
+
+    /// The on-disk results, if configured.
+    pub fn spill(&self) -> Option<&Arc<ExecSpill>> {
+        self.spill.as_ref()
+    }
+
+    /// Store a result, evicting the oldest entry if at capacity. With a
+    /// spill, the result is written to disk first and results over
+    /// `memory_bytes` are not kept in memory.
     pub async fn store(&self, result: CachedExecResult) {
+        if let Some(spill) = &self.spill {
+            spill.write(&result).await;
+            if !spill.keeps_in_memory(&result) {
+                return;
+            }
+        }
         let mut inner = self.inner.write().await;
         if inner.order.len() >= self.max_entries {
             if let Some(old_id) = inner.order.pop_front() {
@@ -296,10 +525,13 @@ impl ExecResultsCache {
         inner.map.insert(result.activity_id, result);
     }
 
-    /// Retrieve a cached result by activity ID, if it hasn't been evicted.
+    /// Retrieve a cached result by activity ID, if it hasn't been evicted
+    /// from memory and (with a spill) from disk.
     pub async fn get(&self, activity_id: u64) -> Option<CachedExecResult> {
-        let inner = self.inner.read().await;
-        inner.map.get(&activity_id).cloned()
+        if let Some(result) = self.inner.read().await.map.get(&activity_id) {
+            return Some(result.clone());
+        }
+        self.spill.as_ref()?.read(activity_id).await
     }
 }
 
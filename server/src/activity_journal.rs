@@ -0,0 +1,470 @@
+//! On-disk activity history.
+//!
+//! [`crate::activity::ActivityLog`] keeps the last `activity_log_max_entries`
+//! in memory and loses them on restart. With `[activity_journal]` configured,
+//! every entry is also appended to [`ActivityJournal`], and reads that reach
+//! past the ring buffer are answered from disk.
+//!
+//! ## Design
+//!
+//! - **Segments**: entries are queued in memory and appended by a writer task
+//!   as JSON lines to `<dir>/<first_id>.jsonl`, synced every
+//!   [`SYNC_INTERVAL`]. Once a segment reaches `segment_bytes` the next entry
+//!   starts a new one, so a segment holds ids from its name up to the next
+//!   segment's name.
+//! - **Retention**: after each rotation and every [`PRUNE_INTERVAL`], closed
+//!   segments last written more than `max_age_days` ago are deleted, then the
+//!   oldest until the total fits in `max_bytes`. The live segment is kept.
+//! - **Ids**: at startup the last id on disk is read back, so ids keep
+//!   increasing across restarts and `since_id` cursors stay valid.
+//! - **Reads**: [`ActivityJournal::query`] scans the segments that can hold
+//!   the requested ids on the [`crate::io_pool`]. Torn lines from a power cut
+//!   are skipped.
+
+use std::collections::VecDeque;
+use std::io::BufRead as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::activity::{ActivityEntry, ActivityFilter};
+use crate::config::ActivityJournalConfig;
+
+/// Interval between syncs of the live segment.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval between retention passes when no rotation happened.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Entries held in memory while the writer is behind; oldest dropped first.
+const MAX_QUEUED: usize = 10_000;
+
+/// The live segment and its size.
+struct Segment {
+    file: tokio::fs::File,
+    bytes: u64,
+}
+
+/// Persisted activity shared by the activity log, the writer and the routes.
+pub struct ActivityJournal {
+    config: ActivityJournalConfig,
+    dir: PathBuf,
+    /// `(id, line)` pairs waiting for the writer.
+    queue: Mutex<VecDeque<(u64, String)>>,
+    notify: Notify,
+    dropped: AtomicU64,
+    last_id: u64,
+}
+
+impl ActivityJournal {
+    /// Open the journal directory and read back the last id written.
+    pub fn open(config: ActivityJournalConfig, data_dir: &str) -> Arc<Self> {
+        let dir = config
+            .dir
+            .as_ref()
+            .map_or_else(|| Path::new(data_dir).join("activity"), PathBuf::from);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("Activity journal: cannot create {}: {e}", dir.display());
+        }
+        let last_id = list_segments(&dir)
+            .iter()
+            .rev()
+            .find_map(|(_, path)| last_entry_id(path))
+            .unwrap_or(0);
+        Arc::new(Self {
+            config,
+            dir,
+            queue: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            dropped: AtomicU64::new(0),
+            last_id,
+        })
+    }
+
+    /// Highest id found on disk at open, 0 for an empty journal.
+    pub fn last_id(&self) -> u64 {
+        self.last_id
+    }
+
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, VecDeque<(u64, String)>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue an entry for the writer.
+    pub fn append(&self, entry: &ActivityEntry) {
+        let Ok(mut line) = serde_json::to_string(entry) else {
+            return;
+        };
+        line.push('\n');
+        {
+            let mut queue = self.lock_queue();
+            if queue.len() >= MAX_QUEUED {
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back((entry.id, line));
+        }
+        self.notify.notify_one();
+    }
+
+    /// Start the writer. Returns its handle for abort on shutdown.
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(self.clone().run_writer())
+    }
+
+    /// Entries with `since_id < id < before_id` that match `filter`, oldest
+    /// first, up to `limit`.
+    pub async fn query(
+        self: &Arc<Self>,
+        since_id: u64,
+        before_id: u64,
+        limit: usize,
+        filter: ActivityFilter,
+    ) -> std::io::Result<Vec<ActivityEntry>> {
+        let dir = self.dir.clone();
+        crate::io_pool::run(move || {
+            Ok(scan(
+                &list_segments(&dir),
+                since_id,
+                before_id,
+                limit,
+                &filter,
+            ))
+        })
+        .await
+    }
+
+    /// Segments and counters for `GET /api/health`.
+    pub fn status(&self) -> Value {
+        let segments = list_segments(&self.dir);
+        let bytes: u64 = segments
+            .iter()
+            .filter_map(|(_, path)| std::fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum();
+        json!({
+            "dir": self.dir.to_string_lossy(),
+            "segments": segments.len(),
+            "bytes": bytes,
+            "first_id": segments.first().map(|(id, _)| id),
+            "queued": self.lock_queue().len(),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Write the remaining entries. Call after the writer has been aborted.
+    pub async fn close(&self) {
+        let batch: Vec<(u64, String)> = self.lock_queue().drain(..).collect();
+        let Some(&(first_id, _)) = batch.first() else {
+            return;
+        };
+        let lines: String = batch.into_iter().map(|(_, line)| line).collect();
+        let result = async {
+            let mut segment = self.open_segment(first_id).await?;
+            segment.file.write_all(lines.as_bytes()).await?;
+            segment.file.sync_all().await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Activity journal: final write failed: {e}");
+        }
+    }
+
+    /// The newest segment if it still has room, otherwise a new one starting
+    /// at `first_id`.
+    async fn open_segment(&self, first_id: u64) -> std::io::Result<Segment> {
+        if let Some((_, path)) = list_segments(&self.dir).pop() {
+            let bytes = tokio::fs::metadata(&path).await?.len();
+            if bytes < self.config.segment_bytes {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .await?;
+                // A torn last line must not swallow the next entry.
+                if bytes > 0 && !ends_with_newline(&path) {
+                    file.write_all(b"\n").await?;
+                }
+                return Ok(Segment { file, bytes });
+            }
+        }
+        let path = self.dir.join(format!("{first_id}.jsonl"));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Segment { file, bytes: 0 })
+    }
+
+    async fn run_writer(self: Arc<Self>) {
+        let mut sync = tokio::time::interval(SYNC_INTERVAL);
+        let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+        let mut segment: Option<Segment> = None;
+        let mut dirty = false;
+        info!(
+            "Activity journal: keeping {} days / {} bytes in {}",
+            self.config.max_age_days,
+            self.config.max_bytes,
+            self.dir.display()
+        );
+
+        loop {
+            tokio::select! {
+                () = self.notify.notified() => {}
+                _ = sync.tick() => {
+                    if let Some(live) = segment.as_ref().filter(|_| dirty) {
+                        let _ = live.file.sync_data().await;
+                        dirty = false;
+                    }
+                    continue;
+                }
+                _ = prune.tick() => {
+                    self.spawn_prune().await;
+                    continue;
+                }
+            }
+
+            let batch: Vec<(u64, String)> = self.lock_queue().drain(..).collect();
+            for (id, line) in batch {
+                if segment
+                    .as_ref()
+                    .is_some_and(|s| s.bytes >= self.config.segment_bytes)
+                {
+                    if let Some(full) = segment.take() {
+                        let _ = full.file.sync_all().await;
+                    }
+                    dirty = false;
+                    self.spawn_prune().await;
+                }
+                if segment.is_none() {
+                    match self.open_segment(id).await {
+                        Ok(s) => segment = Some(s),
+                        Err(e) => {
+                            warn!("Activity journal: cannot open segment: {e}");
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+                    }
+                }
+                if let Some(live) = segment.as_mut() {
+                    if let Err(e) = live.file.write_all(line.as_bytes()).await {
+                        warn!("Activity journal: write failed: {e}");
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        segment = None;
+                    } else {
+                        live.bytes += line.len() as u64;
+                        dirty = true;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn spawn_prune(self: &Arc<Self>) {
+        let journal = self.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            prune(
+                &journal.dir,
+                journal.config.max_age_days,
+                journal.config.max_bytes,
+            );
+        })
+        .await;
+    }
+}
+
+/// Segment files (`<first_id>.jsonl`) in `dir`, oldest first.
+fn list_segments(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut segments: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            Some((name.strip_suffix(".jsonl")?.parse().ok()?, entry.path()))
+        })
+        .collect();
+    segments.sort();
+    segments
+}
+
+/// Matching entries from `segments` with `since_id < id < before_id`.
+fn scan(
+    segments: &[(u64, PathBuf)],
+    since_id: u64,
+    before_id: u64,
+    limit: usize,
+    filter: &ActivityFilter,
+) -> Vec<ActivityEntry> {
+    let mut out = Vec::new();
+    for (i, (first_id, path)) in segments.iter().enumerate() {
+        if *first_id >= before_id || out.len() >= limit {
+            break;
+        }
+        // A segment ends where the next one starts.
+        let next_first = segments.get(i + 1).map_or(u64::MAX, |(id, _)| *id);
+        if next_first <= since_id.saturating_add(1) {
+            continue;
+        }
+        let Ok(file) = std::fs::File::open(path) else {
+            continue;
+        };
+        for line in std::io::BufReader::new(file).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let Ok(entry) = serde_json::from_str::<ActivityEntry>(&line) else {
+                continue;
+            };
+            if entry.id >= before_id {
+                break;
+            }
+            if entry.id > since_id && filter.matches(&entry) {
+                out.push(entry);
+                if out.len() >= limit {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Id of the last parseable entry in a segment.
+fn last_entry_id(path: &Path) -> Option<u64> {
+    let content = std::fs::read_to_string(path).ok()?;
+    content
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str::<ActivityEntry>(line).ok())
+        .map(|e| e.id)
+}
+
+fn ends_with_newline(path: &Path) -> bool {
+    use std::io::{Read as _, Seek as _};
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return true;
+    };
+    let mut last = [0u8; 1];
+    file.seek(std::io::SeekFrom::End(-1)).is_ok()
+        && file.read_exact(&mut last).is_ok()
+        && last[0] == b'\n'
+}
+
+/// Delete closed segments older than `max_age_days`, then the oldest until
+/// the total is within `max_bytes`. The newest segment is never deleted.
+fn prune(dir: &Path, max_age_days: u64, max_bytes: u64) {
+    let mut segments: Vec<(PathBuf, u64, SystemTime)> = list_segments(dir)
+        .into_iter()
+        .filter_map(|(_, path)| {
+            let meta = std::fs::metadata(&path).ok()?;
+            Some((path, meta.len(), meta.modified().ok()?))
+        })
+        .collect();
+    let Some((_, live_len, _)) = segments.pop() else {
+        return;
+    };
+    let max_age = Duration::from_secs(max_age_days.saturating_mul(86_400));
+    let mut total = live_len + segments.iter().map(|(_, len, _)| len).sum::<u64>();
+    for (path, len, modified) in segments {
+        let expired = modified.elapsed().is_ok_and(|age| age > max_age);
+        if !expired && total <= max_bytes {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total = total.saturating_sub(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activity::{ActivitySource, ActivityType};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sctl-aj-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(id: u64, activity_type: ActivityType) -> ActivityEntry {
+        ActivityEntry {
+            id,
+            timestamp: 1_760_000_000_000 + id,
+            activity_type,
+            source: ActivitySource::Rest,
+            summary: format!("entry {id}"),
+            detail: None,
+            request_id: None,
+            key: None,
+        }
+    }
+
+    fn write_segment(dir: &Path, ids: std::ops::RangeInclusive<u64>) {
+        let lines: String = ids
+            .clone()
+            .map(|id| serde_json::to_string(&entry(id, ActivityType::Exec)).unwrap() + "\n")
+            .collect();
+        std::fs::write(dir.join(format!("{}.jsonl", ids.start())), lines).unwrap();
+    }
+
+    #[test]
+    fn scan_pages_across_segments() {
+        let dir = temp_dir("scan");
+        write_segment(&dir, 1..=5);
+        write_segment(&dir, 6..=10);
+        let segments = list_segments(&dir);
+        let all = ActivityFilter::default();
+
+        let ids = |v: Vec<ActivityEntry>| v.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(scan(&segments, 3, u64::MAX, 4, &all)), [4, 5, 6, 7]);
+        assert_eq!(ids(scan(&segments, 7, u64::MAX, 10, &all)), [8, 9, 10]);
+        assert_eq!(ids(scan(&segments, 0, 3, 10, &all)), [1, 2]);
+
+        let range = ActivityFilter {
+            from: Some(1_760_000_000_004),
+            to: Some(1_760_000_000_006),
+            ..ActivityFilter::default()
+        };
+        assert_eq!(ids(scan(&segments, 0, u64::MAX, 10, &range)), [4, 5, 6]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn open_resumes_after_last_id_and_skips_torn_lines() {
+        let dir = temp_dir("resume");
+        write_segment(&dir, 1..=3);
+        let mut torn = std::fs::read(dir.join("1.jsonl")).unwrap();
+        torn.extend_from_slice(b"{\"id\":4,\"timest");
+        std::fs::write(dir.join("1.jsonl"), torn).unwrap();
+
+        let config = ActivityJournalConfig {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            max_age_days: 30,
+            max_bytes: 1 << 20,
+            segment_bytes: 1 << 20,
+        };
+        let journal = ActivityJournal::open(config, "/nonexistent");
+        assert_eq!(journal.last_id(), 3);
+        assert!(!ends_with_newline(&dir.join("1.jsonl")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn prune_drops_oldest_over_budget_but_keeps_live() {
+        let dir = temp_dir("prune");
+        write_segment(&dir, 1..=5);
+        write_segment(&dir, 6..=10);
+        write_segment(&dir, 11..=15);
+        prune(&dir, 30, 0);
+        let left: Vec<u64> = list_segments(&dir).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(left, [11]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
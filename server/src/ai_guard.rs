@@ -0,0 +1,534 @@
+//! AI action budget and device-wide kill-switch.
+//!
+//! A request is **AI-sourced** when its `X-Sctl-Client` header is listed in
+//! `[ai] sources` (default `mcp`). Tunnel requests count too: the relay's
+//! `_source` becomes the same header (see `tunnel::client`). A WebSocket
+//! client names itself once at the upgrade, with the header or `?client=`
+//! (see [`upgrade_client`]); the relay's WS proxy stamps it on every message
+//! it forwards as `_source`.
+//!
+//! - **Budget**: each source gets a rolling one-hour allowance of exec
+//!   operations and file bytes written (`max_execs_per_hour`,
+//!   `max_write_bytes_per_hour`, 0 = unlimited). The exec and file-write
+//!   handlers charge it up front; past the limit they answer
+//!   `429 AI_BUDGET_EXCEEDED` and `ai.budget_exceeded` is broadcast once
+//!   until the source is back under budget.
+//! - **Kill-switch**: `POST /api/ai/disable` blocks every AI-sourced
+//!   mutation — any REST method other than GET/HEAD/OPTIONS (see
+//!   [`enforce`]), every `tunnel.*` / `gx.*` message not listed in
+//!   [`TUNNEL_READ_ONLY`] and every WebSocket `session.*` / `job.*` message
+//!   not listed in [`WS_READ_ONLY`] — with `403 AI_DISABLED` until
+//!   `POST /api/ai/enable`, which AI sources can't call. It is independent
+//!   of per-session `allow_ai` flags, kept in `<data_dir>/ai_disabled.json`
+//!   so a restart doesn't lift it, and broadcast as `ai.disabled` /
+//!   `ai.enabled`.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::config::AiConfig;
+use crate::error::{codes, ApiError};
+use crate::state::AppState;
+
+/// Budget window.
+const WINDOW: Duration = Duration::from_secs(3600);
+
+/// `tunnel.*` and `gx.*` message types that only read. Every other message
+/// of those families counts as a mutation and is blocked for AI sources by
+/// the kill-switch, so a new type is guarded until it is listed here.
+pub const TUNNEL_READ_ONLY: &[&str] = &[
+    "tunnel.info",
+    "tunnel.info.diff",
+    "tunnel.health",
+    "tunnel.health.history",
+    "tunnel.resolve",
+    "tunnel.diag",
+    "tunnel.diagnostics",
+    "tunnel.activity",
+    "tunnel.exec.pending",
+    "tunnel.exec_result",
+    "tunnel.support_bundle.status",
+    "tunnel.flightrecorder.status",
+    "tunnel.ai.status",
+    "tunnel.file.read",
+    "tunnel.file.copy.list",
+    "tunnel.sessions",
+    "tunnel.shells",
+    "tunnel.session.history",
+    "tunnel.session.journal",
+    "tunnel.playbooks.list",
+    "tunnel.playbooks.get",
+    "tunnel.playbooks.runs",
+    "tunnel.playbooks.runs.get",
+    "tunnel.plugins.list",
+    "tunnel.plugins.get",
+    "tunnel.snapshots.list",
+    "tunnel.snapshots.get",
+    "tunnel.snapshots.diff",
+    "tunnel.ssh.keys.list",
+    "tunnel.users.list",
+    "tunnel.firewall.get",
+    "tunnel.time.get",
+    "tunnel.hooks.get",
+    "tunnel.twin.get",
+    "tunnel.gps",
+    "tunnel.gps.track",
+    "tunnel.lte",
+    "tunnel.lte.history",
+    "tunnel.infra.results",
+    "tunnel.infra.discover.progress",
+    "tunnel.infra.discover.subnets",
+    "gx.download.init",
+    "gx.chunk.request",
+    "gx.status",
+    "gx.list",
+];
+
+/// Whether a tunnel message type is a mutation for the kill-switch.
+pub fn is_tunnel_mutation(msg_type: &str) -> bool {
+    (msg_type.starts_with("tunnel.") || msg_type.starts_with("gx."))
+        && !TUNNEL_READ_ONLY.contains(&msg_type)
+}
+
+/// WebSocket `session.*` and `job.*` message types that don't change the
+/// device. Every other message of those families counts as a mutation, as
+/// with [`TUNNEL_READ_ONLY`]. `session.ai_status` is how an agent reports
+/// what it is doing, so it stays allowed.
+pub const WS_READ_ONLY: &[&str] = &[
+    "session.list",
+    "session.attach",
+    "session.detach",
+    "session.history",
+    "session.read_diff",
+    "session.ai_status",
+    "session.release_control",
+];
+
+/// Whether a WebSocket message type is a mutation for the kill-switch.
+pub fn is_ws_mutation(msg_type: &str) -> bool {
+    (msg_type.starts_with("session.") || msg_type.starts_with("job."))
+        && !WS_READ_ONLY.contains(&msg_type)
+}
+
+/// The client a WebSocket upgrade names: its `X-Sctl-Client` header, else
+/// `?client=` (browsers can't set headers on an upgrade).
+pub fn upgrade_client(headers: &HeaderMap, query_client: Option<&str>) -> Option<String> {
+    headers
+        .get("x-sctl-client")
+        .and_then(|v| v.to_str().ok())
+        .or(query_client)
+        .filter(|c| !c.is_empty())
+        .map(ToString::to_string)
+}
+
+/// Headers carrying just [`upgrade_client`] as `X-Sctl-Client`, for
+/// applying the guard to a WebSocket upgrade and its messages.
+pub fn upgrade_headers(headers: &HeaderMap, query_client: Option<&str>) -> HeaderMap {
+    let mut client_headers = HeaderMap::new();
+    if let Some(client) =
+        upgrade_client(headers, query_client).and_then(|c| HeaderValue::from_str(&c).ok())
+    {
+        client_headers.insert("x-sctl-client", client);
+    }
+    client_headers
+}
+
+type GuardError = (StatusCode, Json<ApiError>);
+
+/// Why and when AI mutations were disabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillSwitch {
+    /// Operator-supplied reason, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Client that flipped the switch (`X-Sctl-Client`, or `rest`).
+    pub by: String,
+    /// Unix ms.
+    pub since_ms: u64,
+}
+
+/// One source's charges within the last [`WINDOW`].
+#[derive(Default)]
+struct Usage {
+    charges: VecDeque<(Instant, u64, u64)>,
+    execs: u64,
+    bytes: u64,
+    /// `ai.budget_exceeded` already broadcast for the current overrun.
+    notified: bool,
+}
+
+impl Usage {
+    fn prune(&mut self, now: Instant) {
+        while let Some(&(at, execs, bytes)) = self.charges.front() {
+            if now.duration_since(at) < WINDOW {
+                break;
+            }
+            self.charges.pop_front();
+            self.execs -= execs;
+            self.bytes -= bytes;
+        }
+    }
+}
+
+/// Budget counters and kill-switch state.
+pub struct AiGuard {
+    config: AiConfig,
+    flag_path: PathBuf,
+    disabled: Mutex<Option<KillSwitch>>,
+    usage: Mutex<HashMap<String, Usage>>,
+    events: broadcast::Sender<Value>,
+}
+
+impl AiGuard {
+    /// Load the kill-switch from `<data_dir>/ai_disabled.json`, if present.
+    pub fn new(config: AiConfig, data_dir: &str, events: broadcast::Sender<Value>) -> Self {
+        let flag_path = PathBuf::from(data_dir).join("ai_disabled.json");
+        let disabled = std::fs::read_to_string(&flag_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<KillSwitch>(&s).ok());
+        if let Some(ks) = &disabled {
+            warn!(by = %ks.by, reason = ?ks.reason, "AI kill-switch is on: AI-sourced mutations blocked");
+        }
+        Self {
+            config,
+            flag_path,
+            disabled: Mutex::new(disabled),
+            usage: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    /// The AI source named by `headers`, if the request is AI-sourced.
+    pub fn ai_source<'h>(&self, headers: &'h HeaderMap) -> Option<&'h str> {
+        let client = headers.get("x-sctl-client")?.to_str().ok()?;
+        self.config
+            .sources
+            .iter()
+            .any(|s| s == client)
+            .then_some(client)
+    }
+
+    /// The kill-switch, if on.
+    pub fn kill_switch(&self) -> Option<KillSwitch> {
+        self.disabled.lock().unwrap().clone()
+    }
+
+    /// Refuse an AI-sourced mutation while the kill-switch is on.
+    ///
+    /// # Errors
+    ///
+    /// - `403 Forbidden` with `{"code":"AI_DISABLED"}`
+    pub fn check_mutation(&self, headers: &HeaderMap) -> Result<(), GuardError> {
+        if self.ai_source(headers).is_none() {
+            return Ok(());
+        }
+        match self.kill_switch() {
+            Some(ks) => Err(disabled_error(&ks)),
+            None => Ok(()),
+        }
+    }
+
+    /// Charge `count` exec operations to the request's AI source.
+    ///
+    /// # Errors
+    ///
+    /// - `403 Forbidden` with `{"code":"AI_DISABLED"}` — kill-switch on
+    /// - `429 Too Many Requests` with `{"code":"AI_BUDGET_EXCEEDED"}`
+    pub fn charge_execs(&self, headers: &HeaderMap, count: u64) -> Result<(), GuardError> {
+        self.charge(headers, count, 0)
+    }
+
+    /// Charge `bytes` written to the request's AI source.
+    ///
+    /// # Errors
+    ///
+    /// As [`charge_execs`](Self::charge_execs).
+    pub fn charge_write(&self, headers: &HeaderMap, bytes: u64) -> Result<(), GuardError> {
+        self.charge(headers, 0, bytes)
+    }
+
+    fn charge(&self, headers: &HeaderMap, execs: u64, bytes: u64) -> Result<(), GuardError> {
+        self.check_mutation(headers)?;
+        let Some(source) = self.ai_source(headers) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut all = self.usage.lock().unwrap();
+        let usage = all.entry(source.to_string()).or_default();
+        usage.prune(now);
+
+        let over = |used: u64, add: u64, max: u64| max > 0 && add > 0 && used + add > max;
+        let exceeded = if over(usage.execs, execs, self.config.max_execs_per_hour) {
+            Some(("execs", usage.execs, self.config.max_execs_per_hour))
+        } else if over(usage.bytes, bytes, self.config.max_write_bytes_per_hour) {
+            Some((
+                "write_bytes",
+                usage.bytes,
+                self.config.max_write_bytes_per_hour,
+            ))
+        } else {
+            None
+        };
+        if let Some((kind, used, limit)) = exceeded {
+            if !std::mem::replace(&mut usage.notified, true) {
+                warn!(source, kind, used, limit, "AI budget exceeded");
+                let _ = self.events.send(json!({
+                    "type": "ai.budget_exceeded",
+                    "source": source,
+                    "budget": kind,
+                    "used": used,
+                    "limit": limit,
+                }));
+            }
+            let retry_after_secs = usage.charges.front().map_or(0, |(at, _, _)| {
+                WINDOW.saturating_sub(now.duration_since(*at)).as_secs()
+            });
+            return Err(ApiError::new(
+                codes::AI_BUDGET_EXCEEDED,
+                format!("AI {kind} budget for '{source}' exhausted ({used}/{limit} per hour)"),
+            )
+            .with_detail(json!({
+                "source": source,
+                "budget": kind,
+                "used": used,
+                "limit": limit,
+                "retry_after_secs": retry_after_secs,
+            }))
+            .into_response_with(StatusCode::TOO_MANY_REQUESTS));
+        }
+
+        usage.notified = false;
+        usage.execs += execs;
+        usage.bytes += bytes;
+        usage.charges.push_back((now, execs, bytes));
+        Ok(())
+    }
+
+    /// Apply the kill-switch and exec budget to a WebSocket message, direct
+    /// or relayed. `session.exec` and `job.start` are charged one exec each;
+    /// `session.stdin` is keystrokes and only checked against the switch.
+    ///
+    /// # Errors
+    ///
+    /// As [`charge_execs`](Self::charge_execs).
+    pub fn check_ws_message(&self, headers: &HeaderMap, msg_type: &str) -> Result<(), GuardError> {
+        match msg_type {
+            _ if !is_ws_mutation(msg_type) => Ok(()),
+            "session.exec" | "job.start" => self.charge_execs(headers, 1),
+            _ => self.check_mutation(headers),
+        }
+    }
+
+    /// Turn the kill-switch on (replacing any earlier reason) and persist it.
+    pub async fn disable(&self, reason: Option<String>, by: &str) -> KillSwitch {
+        let ks = KillSwitch {
+            reason,
+            by: by.to_string(),
+            since_ms: crate::sessions::journal::now_ms(),
+        };
+        *self.disabled.lock().unwrap() = Some(ks.clone());
+        let body = serde_json::to_vec_pretty(&ks).unwrap_or_default();
+        if let Err(e) = tokio::fs::write(&self.flag_path, body).await {
+            warn!("Failed to persist AI kill-switch: {e}");
+        }
+        warn!(by, reason = ?ks.reason, "AI kill-switch on");
+        let _ = self.events.send(json!({
+            "type": "ai.disabled",
+            "reason": ks.reason,
+            "by": ks.by,
+            "since_ms": ks.since_ms,
+        }));
+        ks
+    }
+
+    /// Turn the kill-switch off. Returns whether it was on.
+    pub async fn enable(&self, by: &str) -> bool {
+        let was = self.disabled.lock().unwrap().take();
+        if was.is_none() {
+            return false;
+        }
+        if let Err(e) = tokio::fs::remove_file(&self.flag_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove AI kill-switch flag: {e}");
+            }
+        }
+        warn!(by, "AI kill-switch off");
+        let _ = self.events.send(json!({ "type": "ai.enabled", "by": by }));
+        true
+    }
+
+    /// Kill-switch, limits and per-source usage, for `GET /api/ai`.
+    pub fn status(&self) -> Value {
+        let now = Instant::now();
+        let mut all = self.usage.lock().unwrap();
+        let usage: serde_json::Map<String, Value> = all
+            .iter_mut()
+            .map(|(source, usage)| {
+                usage.prune(now);
+                (
+                    source.clone(),
+                    json!({ "execs": usage.execs, "write_bytes": usage.bytes }),
+                )
+            })
+            .collect();
+        json!({
+            "disabled": self.kill_switch(),
+            "sources": self.config.sources,
+            "limits": {
+                "max_execs_per_hour": self.config.max_execs_per_hour,
+                "max_write_bytes_per_hour": self.config.max_write_bytes_per_hour,
+            },
+            "usage": usage,
+        })
+    }
+}
+
+fn disabled_error(ks: &KillSwitch) -> GuardError {
+    ApiError::new(
+        codes::AI_DISABLED,
+        "AI-sourced changes are disabled on this device",
+    )
+    .with_detail(json!(ks))
+    .into_response_with(StatusCode::FORBIDDEN)
+}
+
+/// Middleware: refuse AI-sourced REST mutations while the kill-switch is on.
+///
+/// # Errors
+///
+/// - `403 Forbidden` with `{"code":"AI_DISABLED"}`
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        if let Err(e) = state.ai_guard.check_mutation(request.headers()) {
+            return e.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tunnel_mutations_are_everything_not_read_only() {
+        for t in [
+            "tunnel.exec",
+            "tunnel.file.write",
+            "tunnel.snapshots.create",
+            "tunnel.snapshots.delete",
+            "gx.upload.init",
+            "tunnel.some.future.type",
+        ] {
+            assert!(is_tunnel_mutation(t), "{t}");
+        }
+        for t in [
+            "tunnel.info",
+            "tunnel.snapshots.list",
+            "gx.download.init",
+            "ping",
+        ] {
+            assert!(!is_tunnel_mutation(t), "{t}");
+        }
+    }
+
+    #[test]
+    fn ws_execs_are_charged_and_reads_are_free() {
+        let (guard, _dir) = guard("ws", 1, 0);
+        let mcp = from("mcp");
+        assert!(!is_ws_mutation("session.list"));
+        assert!(is_ws_mutation("session.kill"));
+        guard.check_ws_message(&mcp, "session.stdin").unwrap();
+        guard.check_ws_message(&mcp, "session.exec").unwrap();
+        let (status, _) = guard.check_ws_message(&mcp, "job.start").unwrap_err();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        guard.check_ws_message(&mcp, "session.attach").unwrap();
+    }
+
+    fn temp_data_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sctl-ai-guard-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("mkdir temp data_dir");
+        dir
+    }
+
+    fn guard(name: &str, max_execs: u64, max_bytes: u64) -> (AiGuard, PathBuf) {
+        let dir = temp_data_dir(name);
+        let config = AiConfig {
+            sources: vec!["mcp".to_string()],
+            max_execs_per_hour: max_execs,
+            max_write_bytes_per_hour: max_bytes,
+        };
+        let (tx, _) = broadcast::channel(16);
+        (AiGuard::new(config, dir.to_str().unwrap(), tx), dir)
+    }
+
+    fn from(client: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-sctl-client", client.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn budgets_are_per_source_and_skip_non_ai() {
+        let (guard, _dir) = guard("budget", 2, 100);
+        let mcp = from("mcp");
+        guard.charge_execs(&mcp, 2).unwrap();
+        let (status, Json(err)) = guard.charge_execs(&mcp, 1).unwrap_err();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(err.code, codes::AI_BUDGET_EXCEEDED);
+        // Writes have their own budget.
+        guard.charge_write(&mcp, 100).unwrap();
+        assert!(guard.charge_write(&mcp, 1).is_err());
+        // Operators aren't budgeted.
+        guard.charge_execs(&from("rest"), 50).unwrap();
+        guard.charge_execs(&HeaderMap::new(), 50).unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_switch_blocks_ai_and_persists() {
+        let (guard, dir) = guard("kill", 0, 0);
+        let mcp = from("mcp");
+        guard.charge_execs(&mcp, 1).unwrap();
+        guard
+            .disable(Some("runaway agent".to_string()), "rest")
+            .await;
+
+        let (status, Json(err)) = guard.check_mutation(&mcp).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(err.code, codes::AI_DISABLED);
+        assert!(guard.charge_write(&mcp, 1).is_err());
+        guard.check_mutation(&from("rest")).unwrap();
+
+        // Survives a restart.
+        let (tx, _) = broadcast::channel(16);
+        let reloaded = AiGuard::new(guard.config.clone(), dir.to_str().unwrap(), tx);
+        assert_eq!(
+            reloaded.kill_switch().unwrap().reason.as_deref(),
+            Some("runaway agent")
+        );
+
+        assert!(guard.enable("rest").await);
+        assert!(!guard.enable("rest").await);
+        guard.check_mutation(&mcp).unwrap();
+        assert!(!dir.join("ai_disabled.json").exists());
+    }
+}
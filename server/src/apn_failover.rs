@@ -0,0 +1,378 @@
+//! APN / SIM profile failover, driven by the LTE watchdog.
+//!
+//! `[[lte.apn_profiles]]` lists data profiles in order of preference: an APN,
+//! optionally a SIM slot and AT commands (e.g. enabling an eSIM profile). A
+//! dead data plan looks like a healthy modem to the rest of the watchdog —
+//! registered, sometimes even with an IP — while the tunnel client retries
+//! forever. So when the diagnosis points at the plan rather than the radio
+//! and the tunnel has failed `apn_failover_after_failures` attempts on the
+//! active profile, the watchdog moves to the next one.
+//!
+//! The tunnel's reconnect loop sets the pace: each profile gets that many
+//! attempts, counted from when it was applied. After a switch the provider
+//! bumps the link generation it reports in `status`; sctl then wakes the
+//! tunnel client and drops its backoff, so the new profile is tried at once
+//! (see [`crate::state::TunnelStats::link_generation`]).
+//!
+//! After a full round without recovery the watchdog goes back to the last
+//! profile that carried a stable tunnel (the first one if none has) and stops
+//! switching until the tunnel is back. The active profile, the last good one
+//! and the switch history survive restarts in `{data_dir}/apn_failover.json`
+//! and are shown as `apn_failover` in `GET /api/lte`.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::ApnProfile;
+use crate::infra::now_epoch;
+use crate::modem::Modem;
+
+/// Switches kept in the history.
+const HISTORY_LEN: usize = 20;
+
+/// How long to wait for the SIM after a slot switch.
+const SIM_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One profile switch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApnSwitch {
+    /// Unix seconds.
+    pub timestamp: u64,
+    pub from: String,
+    pub to: String,
+    /// Watchdog symptom that triggered the switch, or `revert` when going
+    /// back to the last good profile after a full round.
+    pub reason: String,
+    /// Failed tunnel attempts on `from` when it was given up.
+    pub tunnel_failures: u64,
+    /// `failed` if the profile could not be applied, else `pending` until
+    /// verified, then `recovered` or `no_recovery`.
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What `apn_failover.json` holds. Profiles are matched by label, so
+/// reordering `[[lte.apn_profiles]]` keeps the state.
+#[derive(Default, Serialize, Deserialize)]
+struct Persisted {
+    active: Option<String>,
+    last_good: Option<String>,
+    #[serde(default)]
+    history: VecDeque<ApnSwitch>,
+}
+
+/// Failover state for the configured profiles.
+pub struct ApnFailover {
+    profiles: Vec<ApnProfile>,
+    active: usize,
+    last_good: Option<usize>,
+    history: VecDeque<ApnSwitch>,
+    path: PathBuf,
+    /// Tunnel failure count when the active profile was applied.
+    failures_at_switch: u64,
+    /// Switches since the tunnel was last up.
+    episode_switches: usize,
+}
+
+/// `apn_failover` in `GET /api/lte`.
+#[derive(Serialize)]
+pub struct ApnFailoverSnapshot {
+    pub profiles: Vec<String>,
+    pub active: String,
+    pub last_good: Option<String>,
+    /// A full round failed; no more switches until the tunnel is back.
+    pub exhausted: bool,
+    pub history: VecDeque<ApnSwitch>,
+}
+
+impl ApnFailover {
+    /// Load saved state for `profiles` (which must not be empty). Without
+    /// saved state the first profile is assumed active.
+    pub async fn load(profiles: Vec<ApnProfile>, data_dir: &str) -> Self {
+        let path = std::path::Path::new(data_dir).join("apn_failover.json");
+        let saved = match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => serde_json::from_str::<Persisted>(&raw).unwrap_or_else(|e| {
+                warn!("APN failover: ignoring unreadable {}: {e}", path.display());
+                Persisted::default()
+            }),
+            Err(_) => Persisted::default(),
+        };
+        let find = |label: Option<&String>| {
+            label.and_then(|l| profiles.iter().position(|p| p.label() == l.as_str()))
+        };
+        let active = find(saved.active.as_ref()).unwrap_or(0);
+        let last_good = find(saved.last_good.as_ref());
+        Self {
+            profiles,
+            active,
+            last_good,
+            history: saved.history,
+            path,
+            failures_at_switch: 0,
+            episode_switches: 0,
+        }
+    }
+
+    pub fn profile(&self, index: usize) -> &ApnProfile {
+        &self.profiles[index]
+    }
+
+    /// Whether the active profile has used up its tunnel attempts.
+    /// `failures` is the tunnel client's current run of failed attempts.
+    pub fn due(&mut self, failures: u64, after: u64) -> bool {
+        if failures < self.failures_at_switch {
+            // The tunnel got through since the switch and started a new run.
+            self.failures_at_switch = 0;
+        }
+        failures - self.failures_at_switch >= after
+    }
+
+    /// The profile to try next, or `None` once this round is over: every
+    /// other profile in order, then back to the last good one.
+    pub fn next(&self) -> Option<usize> {
+        let len = self.profiles.len();
+        if len < 2 {
+            return None;
+        }
+        if self.episode_switches + 1 < len {
+            return Some((self.active + 1) % len);
+        }
+        let home = self.last_good.unwrap_or(0);
+        (self.episode_switches + 1 == len && home != self.active).then_some(home)
+    }
+
+    /// Record a switch to `to`, applied or not (a half-applied profile is
+    /// still the one the modem is closest to). The round's last switch is
+    /// recorded with reason `revert`.
+    pub async fn record_switch(
+        &mut self,
+        to: usize,
+        reason: &str,
+        failures: u64,
+        error: Option<String>,
+    ) {
+        let revert = self.episode_switches + 1 >= self.profiles.len();
+        let switch = ApnSwitch {
+            timestamp: now_epoch(),
+            from: self.profiles[self.active].label().to_string(),
+            to: self.profiles[to].label().to_string(),
+            reason: if revert { "revert" } else { reason }.to_string(),
+            tunnel_failures: failures.saturating_sub(self.failures_at_switch),
+            outcome: if error.is_some() { "failed" } else { "pending" }.to_string(),
+            error,
+        };
+        if self.history.len() >= HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(switch);
+        self.active = to;
+        self.failures_at_switch = failures;
+        self.episode_switches += 1;
+        self.save().await;
+    }
+
+    /// Settle the outcome of the latest switch.
+    pub async fn settle(&mut self, recovered: bool) {
+        if let Some(last) = self.history.back_mut() {
+            if last.outcome == "pending" {
+                last.outcome = if recovered {
+                    "recovered"
+                } else {
+                    "no_recovery"
+                }
+                .to_string();
+                self.save().await;
+            }
+        }
+    }
+
+    /// The tunnel is back: start a fresh round next time it drops.
+    pub fn end_episode(&mut self) {
+        self.episode_switches = 0;
+    }
+
+    /// The tunnel has been stable on the active profile.
+    pub async fn mark_good(&mut self) {
+        self.episode_switches = 0;
+        if self.last_good != Some(self.active) {
+            self.last_good = Some(self.active);
+            info!(
+                "APN failover: profile '{}' is known good",
+                self.profiles[self.active].label()
+            );
+            self.save().await;
+        }
+    }
+
+    #[must_use]
+    pub fn snapshot(&self) -> ApnFailoverSnapshot {
+        ApnFailoverSnapshot {
+            profiles: self
+                .profiles
+                .iter()
+                .map(|p| p.label().to_string())
+                .collect(),
+            active: self.profiles[self.active].label().to_string(),
+            last_good: self.last_good.map(|i| self.profiles[i].label().to_string()),
+            exhausted: self.profiles.len() > 1 && self.next().is_none(),
+            history: self.history.clone(),
+        }
+    }
+
+    /// Persist (atomic: tmp + rename). Best-effort.
+    async fn save(&self) {
+        let state = Persisted {
+            active: Some(self.profiles[self.active].label().to_string()),
+            last_good: self.last_good.map(|i| self.profiles[i].label().to_string()),
+            history: self.history.clone(),
+        };
+        let Ok(json) = serde_json::to_string_pretty(&state) else {
+            return;
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        if let Err(e) = tokio::fs::write(&tmp, json).await {
+            warn!("APN failover: write {}: {e}", tmp.display());
+            return;
+        }
+        if let Err(e) = tokio::fs::rename(&tmp, &self.path).await {
+            warn!("APN failover: rename {}: {e}", tmp.display());
+        }
+    }
+}
+
+/// Apply `profile`: select its SIM slot, run its AT commands, write its APN
+/// and bring the data interface back up on it.
+pub async fn apply(
+    modem: &Modem,
+    profile: &ApnProfile,
+    interface: &str,
+    openwrt: bool,
+    interface_restart_cmd: Option<&str>,
+) -> Result<(), String> {
+    // On OpenWrt a changed APN restarts the interface itself; elsewhere, or
+    // after a SIM change, we have to.
+    let mut restart = !openwrt;
+    if let Some(slot) = profile.sim_slot {
+        let current = modem
+            .command("AT+QUIMSLOT?")
+            .await
+            .ok()
+            .and_then(|r| parse_quimslot(&r));
+        if current != Some(slot) {
+            modem
+                .command(&format!("AT+QUIMSLOT={slot}"))
+                .await
+                .map_err(|e| format!("AT+QUIMSLOT={slot}: {e}"))?;
+            wait_sim_ready(modem).await?;
+            restart = true;
+        }
+    }
+    for command in &profile.at_commands {
+        modem
+            .command(command)
+            .await
+            .map_err(|e| format!("{command}: {e}"))?;
+        restart = true;
+    }
+    crate::lte::configure_apn_openwrt(modem, interface, &profile.apn).await?;
+    if restart {
+        let _ = modem.command("AT+COPS=0").await;
+        crate::lte_watchdog::action_restart_interface(interface, openwrt, interface_restart_cmd)
+            .await;
+    }
+    Ok(())
+}
+
+/// Poll `AT+CPIN?` until the SIM reports `READY`.
+async fn wait_sim_ready(modem: &Modem) -> Result<(), String> {
+    let started = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        if let Ok(resp) = modem.command("AT+CPIN?").await {
+            if resp.contains("READY") {
+                return Ok(());
+            }
+        }
+        if started.elapsed() >= SIM_READY_TIMEOUT {
+            return Err(format!(
+                "SIM not ready {}s after the slot switch",
+                SIM_READY_TIMEOUT.as_secs()
+            ));
+        }
+    }
+}
+
+/// Parse `+QUIMSLOT: <slot>`.
+fn parse_quimslot(response: &str) -> Option<u8> {
+    response
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("+QUIMSLOT:"))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(apn: &str) -> ApnProfile {
+        ApnProfile {
+            name: None,
+            apn: apn.to_string(),
+            sim_slot: None,
+            at_commands: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_tries_each_profile_then_returns_home() {
+        let dir = std::env::temp_dir().join(format!("sctl-apn-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_dir = dir.to_str().unwrap();
+        let profiles = vec![profile("a"), profile("b"), profile("c")];
+        let mut fo = ApnFailover::load(profiles.clone(), data_dir).await;
+
+        assert!(!fo.due(5, 6));
+        assert!(fo.due(6, 6));
+        assert_eq!(fo.next(), Some(1));
+        fo.record_switch(1, "internet_unreachable", 6, None).await;
+        // The new profile gets its own attempts.
+        assert!(!fo.due(11, 6));
+        assert!(fo.due(12, 6));
+        assert_eq!(fo.next(), Some(2));
+        fo.record_switch(2, "internet_unreachable", 12, None).await;
+        // Nothing was ever good: back to the first, then stop.
+        assert_eq!(fo.next(), Some(0));
+        fo.record_switch(0, "internet_unreachable", 18, Some("AT timeout".into()))
+            .await;
+        assert_eq!(fo.next(), None);
+        assert!(fo.snapshot().exhausted);
+        let last = fo.history.back().unwrap();
+        assert_eq!(
+            (last.reason.as_str(), last.outcome.as_str()),
+            ("revert", "failed")
+        );
+
+        // Stable on "b" after a later round; a restart keeps it.
+        fo.end_episode();
+        fo.record_switch(1, "registered_no_data", 24, None).await;
+        fo.settle(true).await;
+        fo.mark_good().await;
+        let fo = ApnFailover::load(profiles, data_dir).await;
+        let snap = fo.snapshot();
+        assert_eq!(
+            (snap.active.as_str(), snap.last_good.as_deref()),
+            ("b", Some("b"))
+        );
+        assert_eq!(snap.history.len(), 4);
+        assert_eq!(snap.history[3].outcome, "recovered");
+        assert_eq!(fo.next(), Some(2));
+
+        assert_eq!(parse_quimslot("\r\n+QUIMSLOT: 2\r\n\r\nOK\r\n"), Some(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,26 +3,295 @@
 //! All endpoints except `/api/health` and the WebSocket upgrade require a
 //! `Authorization: Bearer <key>` header. The WebSocket path uses a `?token=`
 //! query parameter instead (browsers can't set headers on WebSocket upgrades).
+//! Listeners configured with `auth = false` (loopback only) mark requests
+//! with [`AuthExempt`] and skip both checks. On a TLS listener with
+//! `client_auth = "cert"`, a verified client certificate ([`ClientCert`])
+//! stands in for the key.
+//!
+//! ## Named keys
+//!
+//! Besides `auth.api_key` (identity `default`, every scope), `[[auth.keys]]`
+//! adds named keys limited to scopes. [`require_api_key`] resolves the key to
+//! an [`Identity`], checks the scope the route needs ([`required_scope`]),
+//! and attaches the identity to the request extensions. The handler runs
+//! inside [`with_identity`], so [`crate::activity::ActivityLog::log`] records
+//! the key name without every call site passing it.
+//!
+//! | Scope         | Grants                                                   |
+//! |---------------|----------------------------------------------------------|
+//! | `exec`        | `/api/exec*`                                             |
+//! | `files:read`  | `GET /api/files*`, gawdxfer downloads, `/dav`            |
+//! | `files:write` | Other `/api/files*` methods, gawdxfer uploads            |
+//! | `sessions`    | `/api/sessions*`, `/api/shells`, the WebSocket           |
+//! | `playbooks`   | `/api/playbooks*`                                        |
+//! | `read`        | Any other `GET` (info, activity, health, GPS, logs, ...) |
+//! | `admin`       | Any other method (firewall, users, time, AI switch, ...) |
+//! | `*`           | Everything                                               |
+//!
+//! `/dav` takes its key from Basic auth, so the WebDAV handler checks the
+//! scope itself. `files.watch` and file tails (`/api/logs?path=`,
+//! `logs.follow` with `path`) need `files:read` on top of the route's own
+//! scope; their handlers check it with [`current_allows`].
+//!
+//! The relay checks requests against the device's primary key; tunnel
+//! messages don't pass through here and carry no identity. Loopback
+//! listeners run as `local`.
+//!
+//! ## Rotation
+//!
+//! `POST /api/auth/rotate` replaces the primary key in the [`KeyRing`]. The
+//! key it replaced keeps working for a grace window, attributed as
+//! `previous`, so clients can switch over without a restart.
+
+use std::future::Future;
+use std::sync::{Arc, PoisonError, RwLock};
 
 use axum::{
     extract::Request,
-    http::StatusCode,
+    http::{Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use serde_json::json;
 
+use crate::config::NamedKeyConfig;
 use crate::error::{codes, ApiError};
 
+/// Scope names accepted in `[[auth.keys]] scopes`.
+pub const SCOPES: &[&str] = &[
+    "exec",
+    "files:read",
+    "files:write",
+    "sessions",
+    "playbooks",
+    "read",
+    "admin",
+    "*",
+];
+
+tokio::task_local! {
+    static IDENTITY: Identity;
+}
+
+/// Who made a request: the key's name and what it may do.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Identity {
+    /// An identity holding every scope.
+    pub fn full(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            scopes: vec!["*".to_string()],
+        }
+    }
+
+    /// Whether this identity holds `scope`.
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == "*" || s == scope)
+    }
+}
+
+/// Run `f` with `identity` as the current identity (see [`current_key`]).
+pub async fn with_identity<F: Future>(identity: Identity, f: F) -> F::Output {
+    IDENTITY.scope(identity, f).await
+}
+
+/// Spawn `f` as a task that keeps the current identity, so scope checks in
+/// it still apply.
+pub fn spawn_with_identity<F>(f: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match IDENTITY.try_with(Clone::clone) {
+        Ok(identity) => tokio::spawn(IDENTITY.scope(identity, f)),
+        Err(_) => tokio::spawn(f),
+    }
+}
+
+/// Name of the key the current request was made with, if any.
+pub fn current_key() -> Option<String> {
+    IDENTITY.try_with(|i| i.name.clone()).ok()
+}
+
+/// Whether the current identity holds `scope`. Tunnel messages carry no
+/// identity and are allowed.
+pub fn current_allows(scope: &str) -> bool {
+    IDENTITY.try_with(|i| i.allows(scope)).unwrap_or(true)
+}
+
+/// Resolve a presented token to an identity. Every key is compared, so the
+/// time taken doesn't reveal which one matched.
+pub fn resolve_key(api_key: &str, keys: &[NamedKeyConfig], provided: &str) -> Option<Identity> {
+    let mut found = None;
+    if constant_time_eq(api_key.as_bytes(), provided.as_bytes()) {
+        found = Some(Identity::full("default"));
+    }
+    for k in keys {
+        if constant_time_eq(k.key.as_bytes(), provided.as_bytes()) && found.is_none() {
+            found = Some(Identity {
+                name: k.name.clone(),
+                scopes: k.scopes.clone(),
+            });
+        }
+    }
+    found
+}
+
+/// Identity of requests made with the primary key's predecessor during its
+/// grace window.
+pub const PREVIOUS_KEY: &str = "previous";
+
+/// A primary key replaced by a rotation, accepted until `expires_at`.
+#[derive(Debug, Clone)]
+pub struct PreviousKey {
+    pub key: String,
+    /// Unix ms.
+    pub expires_at: u64,
+}
+
+#[derive(Clone)]
+struct Keys {
+    current: String,
+    previous: Option<PreviousKey>,
+}
+
+/// The primary API key, and after a rotation the key it replaced. Shared by
+/// the HTTP and WebSocket auth checks and the tunnel client, which pushes
+/// changes to the relay.
+pub struct KeyRing {
+    keys: RwLock<Keys>,
+    changed: tokio::sync::watch::Sender<()>,
+}
+
+impl KeyRing {
+    pub fn new(key: String) -> Self {
+        Self {
+            keys: RwLock::new(Keys {
+                current: key,
+                previous: None,
+            }),
+            changed: tokio::sync::watch::Sender::new(()),
+        }
+    }
+
+    fn keys(&self) -> Keys {
+        self.keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The primary key.
+    pub fn current(&self) -> String {
+        self.keys().current
+    }
+
+    /// The replaced key, while its grace window is open.
+    pub fn previous(&self) -> Option<PreviousKey> {
+        let now = crate::sessions::journal::now_ms();
+        self.keys().previous.filter(|p| p.expires_at > now)
+    }
+
+    /// Make `key` the primary key. The old one stays valid for `grace_secs`
+    /// (not at all if 0) and is returned.
+    pub fn rotate(&self, key: String, grace_secs: u64) -> Option<PreviousKey> {
+        let previous = {
+            let mut keys = self.keys.write().unwrap_or_else(PoisonError::into_inner);
+            let old = std::mem::replace(&mut keys.current, key);
+            keys.previous = (grace_secs > 0).then(|| PreviousKey {
+                key: old,
+                expires_at: crate::sessions::journal::now_ms() + grace_secs * 1000,
+            });
+            keys.previous.clone()
+        };
+        self.changed.send_replace(());
+        previous
+    }
+
+    /// Notified after every [`rotate`](Self::rotate).
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    /// [`resolve_key`] against the primary key, falling back to the
+    /// previous one as [`PREVIOUS_KEY`].
+    pub fn resolve(&self, keys: &[NamedKeyConfig], provided: &str) -> Option<Identity> {
+        let found = resolve_key(&self.current(), keys, provided);
+        let old = self
+            .previous()
+            .is_some_and(|p| constant_time_eq(p.key.as_bytes(), provided.as_bytes()));
+        found.or_else(|| old.then(|| Identity::full(PREVIOUS_KEY)))
+    }
+}
+
+/// `/api/playbooks/{name}/run`, which runs commands like `/api/exec`.
+fn is_playbook_run(path: &str) -> bool {
+    path.strip_prefix("/api/playbooks/")
+        .and_then(|rest| rest.strip_suffix("/run"))
+        .is_some_and(|name| !name.is_empty() && !name.contains('/'))
+}
+
+/// The scope a request needs, from its method and path.
+pub fn required_scope(method: &Method, path: &str) -> &'static str {
+    let is_read = matches!(*method, Method::GET | Method::HEAD);
+    let under = |prefix: &str| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    if under("/api/exec") || (!is_read && is_playbook_run(path)) {
+        "exec"
+    } else if under("/api/files") {
+        if is_read {
+            "files:read"
+        } else {
+            "files:write"
+        }
+    } else if under("/api/stp") {
+        if is_read || path == "/api/stp/download" {
+            "files:read"
+        } else {
+            "files:write"
+        }
+    } else if under("/dav") {
+        "files:read"
+    } else if under("/api/sessions") || under("/api/shells") || under("/api/ws") {
+        "sessions"
+    } else if under("/api/playbooks") {
+        "playbooks"
+    } else if is_read {
+        "read"
+    } else {
+        "admin"
+    }
+}
+
 /// Axum middleware that rejects requests without a valid `Authorization: Bearer`
-/// header. The expected key is injected via the [`ApiKey`] extension.
+/// header. The expected keys are injected via the [`ApiKey`] extension, named
+/// keys via [`NamedKeys`].
 ///
 /// # Error responses
 ///
 /// - `401 Unauthorized` — header missing or malformed
 /// - `403 Forbidden` — key present but invalid
+/// - `403 Forbidden` with `AUTH_INSUFFICIENT_SCOPE` — named key lacks the route's scope
 /// - `500 Internal Server Error` — [`ApiKey`] extension not found (misconfiguration)
-pub async fn require_api_key(request: Request, next: Next) -> Response {
-    let api_key = match request.extensions().get::<ApiKey>() {
+pub async fn require_api_key(mut request: Request, next: Next) -> Response {
+    if request.extensions().get::<AuthExempt>().is_some() {
+        let identity = Identity::full("local");
+        crate::connections::identify(request.extensions(), &identity.name);
+        request.extensions_mut().insert(identity.clone());
+        return with_identity(identity, next.run(request)).await;
+    }
+    if let Some(identity) = keyless_identity(request.extensions()) {
+        return run_as(identity, request, next).await;
+    }
+
+    let key_ring = match request.extensions().get::<ApiKey>() {
         Some(key) => key.0.clone(),
         None => {
             return ApiError::new("SERVER_CONFIG_ERROR", "Server configuration error")
@@ -48,13 +317,36 @@ pub async fn require_api_key(request: Request, next: Next) -> Response {
         }
     };
 
-    if !constant_time_eq(api_key.as_bytes(), provided.as_bytes()) {
+    let keys = request
+        .extensions()
+        .get::<NamedKeys>()
+        .map(|k| k.0.clone())
+        .unwrap_or_default();
+    let Some(identity) = key_ring.resolve(&keys, provided) else {
         return ApiError::new(codes::AUTH_INVALID_TOKEN, "Invalid API key")
             .into_response_with(StatusCode::FORBIDDEN)
             .into_response();
+    };
+
+    run_as(identity, request, next).await
+}
+
+/// Check `identity` against the route's scope, then run the handler as it.
+async fn run_as(identity: Identity, mut request: Request, next: Next) -> Response {
+    let scope = required_scope(request.method(), request.uri().path());
+    if !identity.allows(scope) {
+        return ApiError::new(
+            codes::AUTH_INSUFFICIENT_SCOPE,
+            format!("Key '{}' lacks the '{scope}' scope", identity.name),
+        )
+        .with_detail(json!({ "key": identity.name, "required_scope": scope }))
+        .into_response_with(StatusCode::FORBIDDEN)
+        .into_response();
     }
 
-    next.run(request).await
+    crate::connections::identify(request.extensions(), &identity.name);
+    request.extensions_mut().insert(identity.clone());
+    with_identity(identity, next.run(request)).await
 }
 
 /// Constant-time byte comparison to prevent timing side-channel attacks.
@@ -75,7 +367,108 @@ pub fn constant_time_eq(expected: &[u8], provided: &[u8]) -> bool {
     diff == 0
 }
 
-/// Extension type carrying the expected API key, injected into the router
-/// layer so [`require_api_key`] can access it without touching `AppState`.
+/// Extension type carrying the expected API keys, injected into the router
+/// layer so [`require_api_key`] can access them without touching `AppState`.
 #[derive(Clone)]
-pub struct ApiKey(pub String);
+pub struct ApiKey(pub Arc<KeyRing>);
+
+/// Extension carrying the `[[auth.keys]]` entries, injected next to [`ApiKey`].
+#[derive(Clone, Default)]
+pub struct NamedKeys(pub Arc<[NamedKeyConfig]>);
+
+/// Extension marking requests from a listener that doesn't require the API
+/// key. See [`crate::config::ListenerConfig::auth`].
+#[derive(Clone, Copy)]
+pub struct AuthExempt;
+
+/// Connection info from a TLS listener (see [`crate::tls`]): who the client
+/// certificate says the client is, when `client_auth = "cert"` lets it stand
+/// in for the API key. Arrives as `ConnectInfo<ClientCert>`.
+#[derive(Debug, Clone, Default)]
+pub struct ClientCert(pub Option<Identity>);
+
+/// Who a request is without looking at a key: `local` on [`AuthExempt`]
+/// listeners, or its client certificate's identity.
+pub fn keyless_identity(extensions: &axum::http::Extensions) -> Option<Identity> {
+    if extensions.get::<AuthExempt>().is_some() {
+        return Some(Identity::full("local"));
+    }
+    extensions
+        .get::<axum::extract::ConnectInfo<ClientCert>>()
+        .and_then(|info| info.0 .0.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str, key: &str, scopes: &[&str]) -> NamedKeyConfig {
+        NamedKeyConfig {
+            name: name.to_string(),
+            key: key.to_string(),
+            scopes: scopes.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn resolves_primary_and_named_keys() {
+        let keys = [named("ci", "ci-secret", &["exec"])];
+        assert_eq!(resolve_key("main", &keys, "main").unwrap().name, "default");
+        let ci = resolve_key("main", &keys, "ci-secret").unwrap();
+        assert_eq!(ci.name, "ci");
+        assert!(ci.allows("exec"));
+        assert!(!ci.allows("files:write"));
+        assert!(resolve_key("main", &keys, "nope").is_none());
+    }
+
+    #[test]
+    fn rotated_key_is_accepted_until_grace_ends() {
+        let ring = KeyRing::new("old".to_string());
+        ring.rotate("new".to_string(), 60);
+        assert_eq!(ring.resolve(&[], "new").unwrap().name, "default");
+        assert_eq!(ring.resolve(&[], "old").unwrap().name, PREVIOUS_KEY);
+
+        ring.rotate("newer".to_string(), 0);
+        assert!(ring.previous().is_none());
+        assert!(ring.resolve(&[], "new").is_none());
+        assert!(ring.resolve(&[], "old").is_none());
+    }
+
+    #[test]
+    fn scope_follows_method_and_path() {
+        assert_eq!(required_scope(&Method::POST, "/api/exec/batch"), "exec");
+        assert_eq!(required_scope(&Method::GET, "/api/files/raw"), "files:read");
+        assert_eq!(required_scope(&Method::PUT, "/api/files"), "files:write");
+        assert_eq!(
+            required_scope(&Method::POST, "/api/stp/download"),
+            "files:read"
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/api/stp/upload"),
+            "files:write"
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/api/sessions/abc"),
+            "sessions"
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/api/playbooks/deploy"),
+            "playbooks"
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/api/playbooks/deploy/run"),
+            "exec"
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/api/playbooks/deploy/runs"),
+            "playbooks"
+        );
+        assert_eq!(required_scope(&Method::GET, "/dav/logs/"), "files:read");
+        assert_eq!(required_scope(&Method::GET, "/api/logs"), "read");
+        assert_eq!(required_scope(&Method::GET, "/api/executive"), "read");
+        assert_eq!(
+            required_scope(&Method::POST, "/api/firewall/apply"),
+            "admin"
+        );
+    }
+}
@@ -9,7 +9,7 @@ use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tokio::sync::{Mutex, Notify};
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
 use crate::config::{CommsConfig, Config};
 use crate::state::TunnelStats;
@@ -334,16 +334,21 @@ pub fn spawn_poller(
             }
 
             let tunnel_connected = tunnel_stats.connected.load(Ordering::Relaxed);
+            let tunnel_failures = tunnel_stats.connect_failures.load(Ordering::Relaxed);
             match client
                 .call(
                     methods::STATUS,
                     json!({
                         "tunnel_connected": tunnel_connected,
+                        "tunnel_failures": tunnel_failures,
                     }),
                 )
                 .await
             {
-                Ok(value) => comms_state.lock().await.apply_status(&value),
+                Ok(value) => {
+                    comms_state.lock().await.apply_status(&value);
+                    observe_link_generation(&value, &tunnel_stats);
+                }
                 Err(err) => {
                     warn!("comms status failed: {err}");
                     comms_state.lock().await.mark_error(&err);
@@ -363,6 +368,25 @@ pub fn spawn_poller(
     })
 }
 
+/// The provider reports a `link_generation` that changes whenever it
+/// switches the data link (APN or SIM profile). On a change, wake the tunnel
+/// client so it retries at once on the new link instead of sitting out a
+/// backoff earned on the old one.
+fn observe_link_generation(status: &Value, tunnel_stats: &TunnelStats) {
+    let Some(generation) = status.get("link_generation").and_then(Value::as_u64) else {
+        return;
+    };
+    // A restarted provider counts from zero again; that is no switch.
+    if generation
+        > tunnel_stats
+            .link_generation
+            .swap(generation, Ordering::Relaxed)
+    {
+        info!("comms: provider switched the data link, reconnecting tunnel");
+        tunnel_stats.reconnect_now.notify_waiters();
+    }
+}
+
 pub async fn poll_location(client: &CommsClient, state: &Arc<Mutex<CommsState>>) {
     match client.call(methods::LOCATION_POLL, json!({})).await {
         Ok(value) => {
@@ -432,5 +456,6 @@ pub fn starting_lte_response() -> Value {
         "scan_status": null,
         "registration_pending": false,
         "watchdog": null,
+        "apn_failover": null,
     })
 }
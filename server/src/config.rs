@@ -21,39 +21,279 @@
 //! transfer_chunk_size = 262144  # 256 KiB
 //! transfer_max_file_size = 1073741824  # 1 GiB
 //! transfer_stale_timeout_secs = 3600
+//! transfer_rate_limit_bps = 0            # per transfer, bytes/s; 0 = unlimited
+//! transfer_global_rate_limit_bps = 0     # all transfers together, bytes/s; 0 = unlimited
+//! io_pool_size = 4
+//! session_encoding = "utf8"                # utf8 | latin1 | raw
+//!
+//! # Optional — several listeners with their own policy (replaces `listen`)
+//! [[server.listeners]]
+//! listen = "127.0.0.1:1337"
+//! auth = false                             # loopback only
+//! [[server.listeners]]
+//! listen = "0.0.0.0:1337"
+//! expose = ["health", "ws"]                # all | health | api | ws | relay
+//! tls = true                               # serve [server.tls] here (default true)
+//!
+//! # Optional — HTTPS/WSS on the listeners, and client certificates (mTLS)
+//! [server.tls]
+//! cert = "/etc/sctl/server.crt"            # PEM chain, leaf first
+//! key = "/etc/sctl/server.key"             # PEM private key
+//! client_ca = "/etc/sctl/clients-ca.crt"   # optional: clients must present a cert it signed
+//! client_auth = "key"                      # key = cert and API key | cert = cert replaces the key
+//!
+//! # Optional — HTTP/2 and connection reuse on the listeners
+//! [server.http]
+//! http2 = true                             # h2c (prior knowledge) and h2 over TLS
+//! keep_alive = true                        # HTTP/1.1 persistent connections
+//! idle_timeout_secs = 120                  # close connections quiet this long, 0 = never
+//! http2_max_streams = 64                   # concurrent requests per HTTP/2 connection
+//! http2_keepalive_secs = 0                 # PING interval (and timeout), 0 = off
 //!
 //! [auth]
 //! api_key = "your-secret-key"
+//! rotation_grace_secs = 600                # old key keeps working this long after POST /api/auth/rotate
+//!
+//! # Optional — named keys limited to scopes, attributed in the activity log
+//! [[auth.keys]]
+//! name = "ci"
+//! key = "another-secret"
+//! scopes = ["exec", "files:read"]          # exec | files:read | files:write | sessions | playbooks | read | admin | *
 //!
 //! [shell]
 //! default_shell = "/bin/sh"
 //! default_working_dir = "/"
 //!
+//! # Optional — presets that session.start / POST /api/sessions name with "template"
+//! [session_templates.build]
+//! shell = "/bin/bash"
+//! working_dir = "/srv/build"
+//! env = { CC = "gcc", MAKEFLAGS = "-j4" }
+//! pty = true
+//! rows = 50
+//! cols = 200
+//! idle_timeout = 3600
+//! name_prefix = "build"                    # unnamed sessions become build-1, build-2, ...
+//!
+//! # Optional — cgroup v2 limits for every session; "limits" in session.start can only tighten them
+//! [session_limits]
+//! cgroup = "/sys/fs/cgroup/sctl-sessions"  # parent cgroup, created if missing
+//! cpu_percent = 100                        # of one CPU (200 = two cores), 0 = unlimited
+//! memory_mb = 256                          # memory.max per session, 0 = unlimited
+//! pids_max = 256                           # processes per session, 0 = unlimited
+//!
 //! [device]
 //! serial = "SCTL-0001-DEV-001"
+//! tags = { site = "warehouse-3", hw = "rv1126" }
+//! tpm_ak_handle = "0x81010002"             # optional, TPM attestation key for /api/info/identity?nonce=
+//! tpm_quote_pcrs = "sha256:0,1,2,3,4,5,6,7"
 //!
 //! [logging]
 //! level = "info"
 //!
+//! # Optional — ship activity entries and warnings to a remote collector
+//! [logging.forward]
+//! address = "logs.example.com:6514"        # host:port, TCP
+//! tls = true
+//! format = "syslog"                        # syslog (RFC 5424, octet-counted) | json (NDJSON, e.g. Vector)
+//! log_level = "warn"                       # tracing events at or above this level; "off" = activity only
+//! buffer_size = 1000                       # records held while disconnected; oldest dropped first
+//!
 //! # Optional — omit entirely to disable tunnel
 //! [tunnel]
 //! relay = false                            # true = relay mode, false = client mode
-//! tunnel_key = "shared-secret"             # device<->relay auth
+//! tunnel_key = "shared-secret"             # device<->relay auth (client: or a provisioning token)
+//! enrollment_token = "provisioning-secret" # relay mode, lets new devices enroll
+//! require_enrollment = false               # relay mode, refuse tunnel_key for registration
 //! url = "wss://relay.example.com/api/tunnel/register"  # client mode only
+//! extra_urls = ["wss://relay-b.example.com/api/tunnel/register"]  # client mode, active-active
+//! failover_urls = ["wss://relay-standby.example.com/api/tunnel/register"]  # client mode, standbys for url
+//! failover_after_attempts = 3              # client mode, failures before trying the next relay
+//! failback_check_secs = 60                 # client mode, probe interval for preferred relays
 //! reconnect_delay_secs = 2                 # client mode, initial backoff
 //! reconnect_max_delay_secs = 30            # client mode, max backoff
 //! heartbeat_interval_secs = 5              # client mode, ping interval
 //! bind_address = "wwan0"                   # client mode, interface name or IP
+//! offline_spool_max_entries = 1000         # client mode, 0 disables spooling
+//! forward_ports = [8080, 502]              # client mode, local TCP ports /d/{serial}/forward/{port} may reach
+//! encoding = "json"                        # client mode, json | msgpack (smaller frames if the relay agrees)
+//! expose_rate_limit_bps = 0                # relay mode, bandwidth cap per exposed port (0 = unlimited)
+//! enforce_identity = false                 # relay mode, refuse devices whose hardware fingerprint changed
 //!
 //! # Optional — external comms provider helper
 //! [comms]
 //! provider = "quectel-at"
 //! command = "/usr/libexec/sctl/comms/sctl-comms-quectel"
 //! device = "/dev/ttyUSB2"
+//!
+//! # Optional — signed SMS commands, a last-resort control path when the tunnel is down (needs [comms])
+//! [sms_commands]
+//! key = "..."                              # shared secret the commands are signed with, 16+ characters
+//! actions = ["ping", "reconnect_tunnel"]   # ping | reconnect_tunnel | reboot
+//! senders = ["+15551234567"]               # optional: only these numbers; empty = any
+//! poll_secs = 60                           # how often the modem's inbox is checked
+//! max_age_secs = 900                       # refuse commands signed longer ago (or ahead) than this
+//! reply = true                             # answer accepted commands by SMS
+//!
+//! # Optional — resolve `secret://name` env values at spawn time
+//! [secrets]
+//! provider = "file"                        # file | env | command
+//! file = "/etc/sctl/secrets.toml"          # file: flat `name = "value"` table, mode 0600
+//! env_prefix = "SCTL_SECRET_"              # env: secret://db_pass -> $SCTL_SECRET_DB_PASS
+//! command = "/usr/libexec/sctl/secret-get" # command: `<command> <name>`, stdout is the value
+//!
+//! # Optional — firewall templates for POST /api/firewall/apply
+//! [firewall]
+//! templates_dir = "/etc/sctl/firewall"     # <name>.nft or <name>.iptables (default: <data_dir>/firewall)
+//! grace_secs = 60                          # roll back unless the relay is reachable after this long
+//!
+//! # Compact summaries of large exec output (on by default, returned to MCP callers)
+//! [summary]
+//! threshold_bytes = 16384                  # per stream; smaller output is returned as-is
+//! strategy = "head_tail"                   # head_tail | errors
+//! head_lines = 40
+//! tail_lines = 60
+//! command = "/usr/libexec/sctl/summarize"  # optional: output on stdin, summary on stdout
+//!
+//! # Per-source hourly budget for AI callers (0 = unlimited)
+//! [ai]
+//! sources = ["mcp"]                        # X-Sctl-Client values counted as AI
+//! max_execs_per_hour = 500
+//! max_write_bytes_per_hour = 104857600     # 100 MiB
+//!
+//! # Device-specific executables exposed under /api/plugins/{name}
+//! [plugins]
+//! dir = "/etc/sctl/plugins.d"              # default: <data_dir>/plugins.d
+//! timeout_secs = 30                        # per invocation, describe included
+//! max_output_bytes = 1048576               # stdout cap; larger replies fail
+//!
+//! # Outbound HTTP(S) requests for POST /api/fetch
+//! [fetch]
+//! allow = ["https://registry.example.com/**"] # scheme://host[:port]/path patterns; empty = disabled
+//! max_bytes = 104857600                    # 100 MiB response body, inline or saved
+//! timeout_secs = 60                        # connect to last byte
+//! max_redirects = 5                        # each hop must match allow too
+//!
+//! # Optional — S3-compatible bucket for POST /api/artifacts/push
+//! [artifacts]
+//! endpoint = "https://s3.eu-central-1.amazonaws.com" # or MinIO, R2, ...
+//! bucket = "device-artifacts"
+//! region = "eu-central-1"                  # default us-east-1
+//! access_key = "AKIA..."
+//! secret_key = "..."
+//! prefix = "{serial}/"                     # prepended to every object key
+//! path_style = true                        # false = https://bucket.host/key
+//! part_size_mb = 8                         # multipart part size, at least 5
+//! max_retries = 3                          # per request, with backoff
+//! rate_limit_bps = 0                       # upload cap in bytes/s, 0 = unlimited
+//! timeout_secs = 3600                      # whole upload
+//!
+//! # Optional — hold back gawdxfer transfers and artifact pushes at peak times
+//! [quiet_hours]
+//! mode = "defer"                           # defer (pause until the window ends) | throttle
+//! rate_limit_bps = 32768                   # throttle mode: cap while quiet
+//! utc = false                              # windows in local time (default) or UTC
+//!
+//! [[quiet_hours.windows]]
+//! start = "07:00"
+//! end = "19:00"                            # end before start spans midnight
+//! days = ["mon", "tue", "wed", "thu", "fri"] # day the window starts; default every day
+//!
+//! # Optional — MQTT bridge: telemetry to a broker, commands from it
+//! [mqtt]
+//! url = "mqtts://broker.example.com:8883"  # mqtt:// (port 1883) or mqtts:// (8883)
+//! client_id = "sctl-{serial}"              # default
+//! username = "device"                      # optional, with password
+//! password = "..."
+//! topic_prefix = "sctl/{serial}"           # status, activity, health, metrics, cmd, cmd/result
+//! qos = 1                                  # 0 or 1, for everything published
+//! keepalive_secs = 60
+//! activity = true                          # each activity entry on {prefix}/activity
+//! heartbeat_secs = 60                      # GET /api/health body on {prefix}/health, 0 = off
+//! metrics_secs = 300                       # system sample on {prefix}/metrics, 0 = off
+//! commands = []                            # "exec", "playbook"; empty = don't subscribe to {prefix}/cmd
+//! reconnect_max_secs = 60
+//!
+//! # Optional — read-only SNMP v1/v2c agent for core metrics (GET /api/snmp has the same objects)
+//! [snmp]
+//! listen = "0.0.0.0:161"                   # UDP
+//! community = "public"                     # required
+//! sys_contact = "ops@example.com"          # sysContact.0
+//! sys_location = "Plant 4, cabinet 2"      # sysLocation.0
+//! enterprise_oid = "1.3.6.1.4.1.8072.9999.9999"  # root of the sctl objects
+//!
+//! # Optional — A/B firmware updates through RAUC, SWUpdate or Mender (/api/firmware)
+//! [firmware]
+//! backend = "auto"                         # auto | rauc | swupdate | mender
+//! bundle_dirs = ["/data/updates"]          # bundles must be under one of these; empty = anywhere
+//! install_timeout_secs = 1800              # the update agent's install is killed after this
+//! commit_after_secs = 0                    # mark the new slot good after this much uptime; 0 = POST /api/firmware/commit only
+//!
+//! # Desired-state reconciliation for PUT /api/twin
+//! [twin]
+//! interval_secs = 300                      # 0 = only on PUT and POST /api/twin/reconcile
+//! apply = false                            # false = report drift, true = fix it
+//!
+//! # Optional — rolling on-disk capture of the last minutes, dumped on panic
+//! [flight_recorder]
+//! minutes = 15
+//! dir = "/var/lib/sctl/flightrecorder"     # default: <data_dir>/flightrecorder
+//! segment_secs = 60                        # a segment is gzipped once closed
+//! max_dumps = 10
+//!
+//! # Optional — keep activity entries on disk across restarts
+//! [activity_journal]
+//! dir = "/var/lib/sctl/activity"           # default: <data_dir>/activity
+//! max_age_days = 30
+//! max_bytes = 16777216                     # 16 MiB across all segments
+//! segment_bytes = 1048576                  # a new segment starts past this size
+//!
+//! # Optional — keep full exec results on disk for GET /api/activity/{id}/result
+//! [exec_results]
+//! dir = "/var/lib/sctl/exec-results"       # default: <data_dir>/exec-results
+//! memory_bytes = 65536                     # larger results are kept on disk only
+//! max_result_bytes = 4194304               # stdout/stderr truncated past 4 MiB
+//! max_bytes = 33554432                     # 32 MiB across all results
+//! max_age_hours = 72
+//!
+//! # Optional — Lua hooks: pre_exec policy, post_exec enrichment, activity filter
+//! [hooks]
+//! script = "/etc/sctl/hooks.lua"
+//! timeout_ms = 50                          # per call; the call fails past this
+//! memory_limit_bytes = 8388608             # 8 MiB for the script's heap
+//! fail_open = false                        # true = allow exec when pre_exec errors
+//!
+//! # Optional — sftp/scp through /api/sftp and the relay (OpenSSH sftp-server)
+//! [sftp]
+//! server = "/usr/lib/sftp-server"          # default: first of the usual OpenSSH paths
+//! start_dir = "/root"                      # initial directory (default: the user's home)
+//! read_only = false                        # true = refuse every write
+//!
+//! # Optional — read-only WebDAV at /dav/{name}/ for OS file browsers
+//! [dav.roots]
+//! logs = "/var/log"
+//! artifacts = "/var/lib/app/out"
+//!
+//! # Extra risk rules for exec activity, checked before the built-in table
+//! [[classify.rules]]
+//! pattern = "fw_setenv *"                  # shell glob on each simple command
+//! risk = "destructive"                     # read | modify | privileged | destructive
+//!
+//! # Command allow/deny rules for exec and sessions, first match wins
+//! [policy]
+//! default = "allow"                        # verdict when no rule matches
+//!
+//! [[policy.rules]]
+//! action = "deny"                          # allow | deny
+//! regex = '^rm\s+-\w*[rR].*\s/\*?(\s|$)'    # or pattern = "mkfs*" (shell glob)
+//! sources = ["mcp"]                        # optional: mcp, ws, rest, tunnel, mqtt
+//! keys = ["ci"]                            # optional: [[auth.keys]] names
+//! reason = "no recursive delete of /"
 //! ```
 
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// Top-level configuration, deserialized from TOML.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -70,6 +310,25 @@ pub struct Config {
     pub logging: LoggingConfig,
     #[serde(default)]
     pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub ai: AiConfig,
+    #[serde(default)]
+    pub classify: ClassifyConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub fetch: FetchConfig,
+    #[serde(default)]
+    pub twin: TwinConfig,
+    /// Named session presets, keyed by template name.
+    #[serde(default)]
+    pub session_templates: BTreeMap<String, SessionTemplateConfig>,
+    /// Optional cgroup limits for sessions.
+    pub session_limits: Option<SessionLimitsConfig>,
     /// Optional tunnel configuration for relay or client mode.
     pub tunnel: Option<TunnelConfig>,
     /// Optional external comms provider binding.
@@ -78,6 +337,471 @@ pub struct Config {
     pub gps: Option<GpsConfig>,
     /// Optional LTE/cellular signal monitoring.
     pub lte: Option<LteConfig>,
+    /// Optional secrets provider for `secret://` env references.
+    pub secrets: Option<SecretsConfig>,
+    /// Optional firewall template settings.
+    pub firewall: Option<FirewallConfig>,
+    /// Optional flight recorder.
+    pub flight_recorder: Option<FlightRecorderConfig>,
+    /// Optional on-disk activity journal.
+    pub activity_journal: Option<ActivityJournalConfig>,
+    /// Optional on-disk exec results.
+    pub exec_results: Option<ExecResultsConfig>,
+    /// Optional Lua request hooks.
+    pub hooks: Option<HooksConfig>,
+    /// Optional SFTP bridge.
+    pub sftp: Option<SftpConfig>,
+    /// Optional read-only WebDAV export.
+    pub dav: Option<DavConfig>,
+    /// Optional S3-compatible bucket for artifact uploads.
+    pub artifacts: Option<ArtifactsConfig>,
+    /// Optional quiet hours for transfers and artifact pushes.
+    pub quiet_hours: Option<QuietHoursConfig>,
+    /// Optional MQTT bridge.
+    pub mqtt: Option<MqttConfig>,
+    /// Optional read-only SNMP agent.
+    pub snmp: Option<SnmpConfig>,
+    /// Optional signed SMS commands (needs `[comms]`).
+    pub sms_commands: Option<SmsCommandsConfig>,
+    /// Optional A/B firmware updates.
+    pub firmware: Option<FirmwareConfig>,
+}
+
+/// Broker that the MQTT bridge connects to. See [`crate::mqtt`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MqttConfig {
+    /// `mqtt://host[:port]` or `mqtts://host[:port]` (TLS).
+    pub url: String,
+    /// `{serial}` is the device serial (default `sctl-{serial}`).
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Root of every topic; `{serial}` is the device serial (default
+    /// `sctl/{serial}`).
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    /// QoS for published messages, 0 or 1 (default 1).
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+    /// Default 60.
+    #[serde(default = "default_mqtt_keepalive_secs")]
+    pub keepalive_secs: u16,
+    /// Publish activity entries (default true).
+    #[serde(default = "default_mqtt_activity")]
+    pub activity: bool,
+    /// Health heartbeat interval, 0 = off (default 60).
+    #[serde(default = "default_mqtt_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// System metrics sample interval, 0 = off (default 300).
+    #[serde(default = "default_mqtt_metrics_secs")]
+    pub metrics_secs: u64,
+    /// Command types accepted on `{prefix}/cmd`: `exec`, `playbook`. Empty
+    /// (default) = no subscription.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// Maximum reconnect backoff in seconds (default 60).
+    #[serde(default = "default_forward_reconnect_max_secs")]
+    pub reconnect_max_secs: u64,
+}
+
+/// Read-only SNMP agent. See [`crate::snmp`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SnmpConfig {
+    /// UDP address to answer on (default `0.0.0.0:161`).
+    #[serde(default = "default_snmp_listen")]
+    pub listen: String,
+    /// v1/v2c community; requests with any other are dropped.
+    pub community: String,
+    #[serde(default)]
+    pub sys_contact: String,
+    #[serde(default)]
+    pub sys_location: String,
+    /// Root of the sctl objects and the `sysObjectID` (default
+    /// `1.3.6.1.4.1.8072.9999.9999`, the Net-SNMP experimental arc).
+    #[serde(default = "default_snmp_enterprise_oid")]
+    pub enterprise_oid: String,
+}
+
+/// A/B firmware updates. See [`crate::firmware`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FirmwareConfig {
+    /// Update agent: `auto` (the first of RAUC, Mender and SWUpdate found on
+    /// `PATH`), `rauc`, `swupdate` or `mender`.
+    #[serde(default = "default_firmware_backend")]
+    pub backend: String,
+    /// Directories bundles may be installed from. Empty = any path.
+    #[serde(default)]
+    pub bundle_dirs: Vec<String>,
+    /// Seconds the agent's install may run before it's killed (default 1800).
+    #[serde(default = "default_firmware_install_timeout_secs")]
+    pub install_timeout_secs: u64,
+    /// Mark a newly booted slot good once sctl has been up this long
+    /// (default 0 = only on `POST /api/firmware/commit`).
+    #[serde(default)]
+    pub commit_after_secs: u64,
+}
+
+/// Signed commands accepted by SMS. See [`crate::sms_commands`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SmsCommandsConfig {
+    /// Shared secret; each command carries an HMAC made with it.
+    pub key: String,
+    /// Actions that may be run: `ping`, `reconnect_tunnel`, `reboot`.
+    pub actions: Vec<String>,
+    /// Sender numbers accepted, as the modem reports them. Empty = any.
+    #[serde(default)]
+    pub senders: Vec<String>,
+    /// Inbox check interval (default 60).
+    #[serde(default = "default_sms_commands_poll_secs")]
+    pub poll_secs: u64,
+    /// Largest difference between a command's timestamp and the device
+    /// clock, in seconds (default 900).
+    #[serde(default = "default_sms_commands_max_age_secs")]
+    pub max_age_secs: u64,
+    /// Reply to accepted commands by SMS (default true).
+    #[serde(default = "default_sms_commands_reply")]
+    pub reply: bool,
+}
+
+/// Bucket that `POST /api/artifacts/push` uploads to. See
+/// [`crate::artifacts`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArtifactsConfig {
+    /// Service URL, e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO
+    /// server.
+    pub endpoint: String,
+    pub bucket: String,
+    /// Signing region (default `us-east-1`, which MinIO accepts).
+    #[serde(default = "default_artifacts_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key; `{serial}` is the device serial
+    /// (default `{serial}/`).
+    #[serde(default = "default_artifacts_prefix")]
+    pub prefix: String,
+    /// `endpoint/bucket/key` URLs (default) instead of `bucket.endpoint/key`.
+    #[serde(default = "default_artifacts_path_style")]
+    pub path_style: bool,
+    /// Multipart part size in MiB (default 8, at least 5). Smaller files go
+    /// up in one `PUT`.
+    #[serde(default = "default_artifacts_part_size_mb")]
+    pub part_size_mb: u64,
+    /// Retries per request after a network error or a 5xx (default 3).
+    #[serde(default = "default_artifacts_max_retries")]
+    pub max_retries: u32,
+    /// Upload cap in bytes per second (default 0 = unlimited).
+    #[serde(default)]
+    pub rate_limit_bps: u64,
+    /// Seconds the whole upload may take (default 3600).
+    #[serde(default = "default_artifacts_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Times when bulk transfers are held back. See [`crate::quiet_hours`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuietHoursConfig {
+    pub windows: Vec<QuietWindowConfig>,
+    /// What happens inside a window (default `defer`).
+    #[serde(default)]
+    pub mode: QuietMode,
+    /// Bytes per second shared by transfers and pushes in `throttle` mode.
+    #[serde(default)]
+    pub rate_limit_bps: u64,
+    /// Read `windows` in UTC instead of the device's local time.
+    #[serde(default)]
+    pub utc: bool,
+}
+
+/// One `[[quiet_hours.windows]]` entry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuietWindowConfig {
+    /// `HH:MM`.
+    pub start: String,
+    /// `HH:MM`; before `start` means the window spans midnight, equal to
+    /// `start` means the whole day.
+    pub end: String,
+    /// Days the window starts on (`mon` .. `sun`); empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+/// `[quiet_hours] mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietMode {
+    /// Transfer chunks are refused and artifact pushes queued until the
+    /// window ends.
+    #[default]
+    Defer,
+    /// Transfers and pushes run, capped at `rate_limit_bps`.
+    Throttle,
+}
+
+/// Directories served read-only over WebDAV. See [`crate::dav`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DavConfig {
+    /// Name → absolute directory, served at `/dav/{name}/`.
+    #[serde(default)]
+    pub roots: BTreeMap<String, String>,
+}
+
+/// SFTP for standard `sftp`/`scp` clients, served by the system's OpenSSH
+/// `sftp-server`. See [`crate::sftp`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SftpConfig {
+    /// Path to `sftp-server` (default: the first of
+    /// [`crate::sftp::SERVER_PATHS`] that exists).
+    pub server: Option<String>,
+    /// Directory sessions start in (default: the user's home).
+    pub start_dir: Option<String>,
+    /// Refuse every write (`sftp-server -R`).
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Operator Lua hooks. See [`crate::hooks`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HooksConfig {
+    /// Lua script defining any of `pre_exec`, `post_exec` and `activity`.
+    pub script: String,
+    /// Milliseconds one hook call may run before it is aborted (default 50).
+    #[serde(default = "default_hooks_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Heap limit for the script (default 8 MiB).
+    #[serde(default = "default_hooks_memory_limit_bytes")]
+    pub memory_limit_bytes: usize,
+    /// Let commands run when `pre_exec` errors or the script failed to load
+    /// (default false: they are refused).
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+/// Persisted activity history. See [`crate::activity_journal`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ActivityJournalConfig {
+    /// Directory for segment files (default `<data_dir>/activity`).
+    pub dir: Option<String>,
+    /// Segments last written longer ago than this are deleted (default 30).
+    #[serde(default = "default_activity_journal_max_age_days")]
+    pub max_age_days: u64,
+    /// Total size kept; oldest segments are deleted first (default 16 MiB).
+    #[serde(default = "default_activity_journal_max_bytes")]
+    pub max_bytes: u64,
+    /// Size at which a new segment is started (default 1 MiB).
+    #[serde(default = "default_activity_journal_segment_bytes")]
+    pub segment_bytes: u64,
+}
+
+/// Exec results kept on disk. See [`crate::exec_spill`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecResultsConfig {
+    /// Directory for result files (default `<data_dir>/exec-results`).
+    pub dir: Option<String>,
+    /// Results whose stdout plus stderr is larger than this are not kept in
+    /// memory, only on disk (default 64 KiB).
+    #[serde(default = "default_exec_results_memory_bytes")]
+    pub memory_bytes: u64,
+    /// Largest result written; stdout and stderr are cut to fit and the
+    /// result is marked `truncated` (default 4 MiB).
+    #[serde(default = "default_exec_results_max_result_bytes")]
+    pub max_result_bytes: u64,
+    /// Total size kept; oldest results are deleted first (default 32 MiB).
+    #[serde(default = "default_exec_results_max_bytes")]
+    pub max_bytes: u64,
+    /// Results older than this are deleted (default 72).
+    #[serde(default = "default_exec_results_max_age_hours")]
+    pub max_age_hours: u64,
+}
+
+/// Rolling on-disk capture of recent activity. See [`crate::flight_recorder`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FlightRecorderConfig {
+    /// Minutes of history kept (default 15).
+    #[serde(default = "default_flight_recorder_minutes")]
+    pub minutes: u64,
+    /// Directory for segments and dumps (default `<data_dir>/flightrecorder`).
+    /// Must survive reboots.
+    pub dir: Option<String>,
+    /// Seconds per segment file (default 60).
+    #[serde(default = "default_flight_recorder_segment_secs")]
+    pub segment_secs: u64,
+    /// Dumps kept; older ones are deleted (default 10).
+    #[serde(default = "default_flight_recorder_max_dumps")]
+    pub max_dumps: usize,
+}
+
+/// Firewall templates and rollback guard for `POST /api/firewall/apply`.
+/// See [`crate::routes::firewall`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FirewallConfig {
+    /// Directory of `<name>.nft` / `<name>.iptables` templates
+    /// (default `<data_dir>/firewall`).
+    pub templates_dir: Option<String>,
+    /// Seconds after an apply before the relay connection is checked and the
+    /// previous ruleset restored if it is down (default 60).
+    #[serde(default = "default_firewall_grace")]
+    pub grace_secs: u64,
+}
+
+/// Summarization of large exec output. See [`crate::shell::summary`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SummaryConfig {
+    /// Produce summaries at all (default true).
+    #[serde(default = "default_summary_enabled")]
+    pub enabled: bool,
+    /// Streams longer than this many bytes are summarized (default 16 KiB).
+    #[serde(default = "default_summary_threshold")]
+    pub threshold_bytes: usize,
+    /// Built-in strategy: `head_tail` or `errors` (default `head_tail`).
+    /// Also the fallback when `command` fails.
+    #[serde(default = "default_summary_strategy")]
+    pub strategy: String,
+    /// Lines kept from the start of the stream (default 40).
+    #[serde(default = "default_summary_head_lines")]
+    pub head_lines: usize,
+    /// Lines kept from the end of the stream (default 60).
+    #[serde(default = "default_summary_tail_lines")]
+    pub tail_lines: usize,
+    /// External summarizer: receives the stream on stdin (with `SCTL_STREAM`
+    /// and `SCTL_COMMAND` set), its stdout is the summary.
+    pub command: Option<String>,
+    /// Seconds to wait for `command` (default 10).
+    #[serde(default = "default_summary_command_timeout")]
+    pub command_timeout_secs: u64,
+}
+
+/// AI action budget and kill-switch. See [`crate::ai_guard`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AiConfig {
+    /// `X-Sctl-Client` values whose requests count as AI-sourced
+    /// (default `["mcp"]`).
+    #[serde(default = "default_ai_sources")]
+    pub sources: Vec<String>,
+    /// Exec operations per source per rolling hour (default 0 = unlimited).
+    #[serde(default)]
+    pub max_execs_per_hour: u64,
+    /// File bytes written per source per rolling hour (default 0 = unlimited).
+    #[serde(default)]
+    pub max_write_bytes_per_hour: u64,
+}
+
+/// Executable plugins discovered at runtime. See [`crate::plugins`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginsConfig {
+    /// Directory scanned for plugin executables (default
+    /// `<data_dir>/plugins.d`).
+    pub dir: Option<String>,
+    /// Seconds a plugin may run before it is killed (default 30).
+    #[serde(default = "default_plugins_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Largest stdout accepted from a plugin (default 1 MiB).
+    #[serde(default = "default_plugins_max_output_bytes")]
+    pub max_output_bytes: usize,
+}
+
+/// Outbound HTTP(S) requests from the device. See [`crate::fetch`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FetchConfig {
+    /// `scheme://host[:port]/path` patterns; a URL must match one. The host
+    /// may start with `*.`, and the path is a glob where `**` spans segments.
+    /// Empty (the default) disables `POST /api/fetch`.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Largest response body accepted, inline or saved (default 100 MiB).
+    /// Inline bodies are also capped at `server.max_file_size`.
+    #[serde(default = "default_fetch_max_bytes")]
+    pub max_bytes: u64,
+    /// Seconds a fetch may take from connect to last byte (default 60).
+    #[serde(default = "default_fetch_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Redirects followed (default 5).
+    #[serde(default = "default_fetch_max_redirects")]
+    pub max_redirects: u32,
+}
+
+/// Desired-state reconciler. See [`crate::twin`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TwinConfig {
+    /// Seconds between reconcile passes; `0` only reconciles on request
+    /// (default 300).
+    #[serde(default = "default_twin_interval_secs")]
+    pub interval_secs: u64,
+    /// Fix drift on periodic passes instead of only reporting it.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Risk classification of exec commands. See [`crate::shell::classify`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ClassifyConfig {
+    /// Rules tried in order before the built-in table; the first match wins.
+    #[serde(default)]
+    pub rules: Vec<ClassifyRule>,
+}
+
+/// One `[[classify.rules]]` entry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClassifyRule {
+    /// Shell glob (`*`, `?`, `[...]`) matched against a whole simple command,
+    /// e.g. `"fw_setenv *"`.
+    pub pattern: String,
+    /// Risk assigned on a match.
+    pub risk: crate::shell::classify::Risk,
+}
+
+/// Command allow/deny policy for exec and session commands. See
+/// [`crate::shell::policy`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PolicyConfig {
+    /// Verdict for a command no rule matches (default `allow`).
+    #[serde(default)]
+    pub default: crate::shell::policy::Action,
+    /// Rules tried in order; the first match wins.
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+}
+
+/// One `[[policy.rules]]` entry. Exactly one of `pattern` and `regex` is set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PolicyRule {
+    pub action: crate::shell::policy::Action,
+    /// Shell glob matched against a whole simple command, e.g. `"mkfs*"`.
+    pub pattern: Option<String>,
+    /// Regex searched for in each simple command, e.g. `'^dd .*of=/dev/'`.
+    pub regex: Option<String>,
+    /// Key names (`default`, `[[auth.keys]] name`) the rule applies to;
+    /// empty = every caller. Tunnel requests carry no key.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Sources (`mcp`, `ws`, `rest`, `tunnel`) the rule applies to;
+    /// empty = every source.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Message returned when the rule denies a command.
+    pub reason: Option<String>,
+}
+
+/// Secrets provider used to resolve `secret://name` values in exec and
+/// session `env` maps. See [`crate::shell::secrets`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SecretsConfig {
+    /// `file`, `env`, or `command`.
+    #[serde(default = "default_secrets_provider")]
+    pub provider: String,
+    /// File provider: TOML file of `name = "value"` pairs
+    /// (default `<data_dir>/secrets.toml`). Must not be group/world accessible.
+    pub file: Option<String>,
+    /// Env provider: prefix prepended to the upper-cased secret name.
+    #[serde(default = "default_secrets_env_prefix")]
+    pub env_prefix: String,
+    /// Command provider: executable invoked as `<command> <name>`; its stdout
+    /// (minus the trailing newline) is the secret value.
+    pub command: Option<String>,
+    /// Command provider: seconds to wait for the helper (default 10).
+    #[serde(default = "default_secrets_command_timeout")]
+    pub command_timeout_secs: u64,
 }
 
 /// External comms provider helper process.
@@ -130,7 +854,9 @@ pub struct ServerConfig {
     /// Socket address to bind (default `0.0.0.0:1337`).
     #[serde(default = "default_listen")]
     pub listen: String,
-    /// Maximum concurrent TCP connections (default 10). Enforced via tower `ConcurrencyLimitLayer`.
+    /// Maximum concurrent requests per listener (default 10); more are refused
+    /// with `503 SERVER_BUSY`. Health and control requests have a lane of
+    /// their own on top. See [`crate::limit`].
     #[serde(default = "default_max_connections")]
     pub max_connections: usize,
     /// Maximum concurrent WebSocket shell sessions (default 20).
@@ -153,7 +879,8 @@ pub struct ServerConfig {
     /// Maximum output entries kept per session buffer (default 1000).
     #[serde(default = "default_session_buffer_size")]
     pub session_buffer_size: usize,
-    /// Directory for persistent data (journals, etc). Default `/var/lib/sctl`.
+    /// Directory for persistent data (journals, etc). Default `/var/lib/sctl`,
+    /// or `%ProgramData%\sctl` on Windows.
     #[serde(default = "default_data_dir")]
     pub data_dir: String,
     /// Enable output journaling to disk (default true).
@@ -165,6 +892,14 @@ pub struct ServerConfig {
     /// Auto-delete journals older than this many hours (default 72).
     #[serde(default = "default_journal_max_age_hours")]
     pub journal_max_age_hours: u64,
+    /// Directory for `record: true` session recordings (default
+    /// `<data_dir>/recordings`). Recordings are never auto-deleted.
+    #[serde(default)]
+    pub recording_dir: Option<String>,
+    /// How session output bytes are decoded: `utf8` (default), `latin1` or
+    /// `raw` (text plus base64 bytes). Sessions can override it at start.
+    #[serde(default)]
+    pub session_encoding: crate::sessions::decode::OutputEncoding,
     /// Directory containing playbook markdown files (default `/etc/sctl/playbooks`).
     #[serde(default = "default_playbooks_dir")]
     pub playbooks_dir: String,
@@ -192,6 +927,153 @@ pub struct ServerConfig {
     /// Stale transfer timeout in seconds (default 3600).
     #[serde(default = "default_transfer_stale_timeout")]
     pub transfer_stale_timeout_secs: u64,
+    /// Default and maximum bandwidth per gawdxfer transfer in bytes/s
+    /// (default 0, unlimited). See [`crate::gawdxfer::throttle`].
+    #[serde(default)]
+    pub transfer_rate_limit_bps: u64,
+    /// Bandwidth shared by all gawdxfer transfers in bytes/s (default 0,
+    /// unlimited). Keeps transfers from starving tunnel heartbeats on LTE.
+    #[serde(default)]
+    pub transfer_global_rate_limit_bps: u64,
+    /// Max concurrent blocking filesystem jobs — hashing, large reads,
+    /// directory scans, copies (default 4). See [`crate::io_pool`].
+    #[serde(default = "default_io_pool_size")]
+    pub io_pool_size: usize,
+    /// Listeners with their own bind address, auth and route exposure. When
+    /// empty (the default), a single listener on `listen` serves everything.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// TLS termination for the listeners. See [`crate::tls`].
+    pub tls: Option<TlsConfig>,
+    /// HTTP/2 and connection reuse on the listeners. See [`crate::serve`].
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+/// `[server.http]`: protocol and keep-alive settings for the listeners.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Accept HTTP/2: prior-knowledge h2c, and `h2` over TLS (default true).
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open between requests (default true).
+    #[serde(default = "default_http_keep_alive")]
+    pub keep_alive: bool,
+    /// Close a connection after this many seconds without traffic
+    /// (default 120, 0 = never). Requests in flight are allowed to finish.
+    #[serde(default = "default_http_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    /// Concurrent requests on one HTTP/2 connection (default 64).
+    #[serde(default = "default_http2_max_streams")]
+    pub http2_max_streams: u32,
+    /// Send an HTTP/2 PING this often and drop the connection if it goes
+    /// unanswered for as long (default 0, off).
+    #[serde(default)]
+    pub http2_keepalive_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            http2: default_http2(),
+            keep_alive: default_http_keep_alive(),
+            idle_timeout_secs: default_http_idle_timeout_secs(),
+            http2_max_streams: default_http2_max_streams(),
+            http2_keepalive_secs: 0,
+        }
+    }
+}
+
+/// Certificates for HTTPS/WSS, and optional client certificate checks.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: String,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: String,
+    /// PEM bundle of CAs for client certificates. When set, the handshake
+    /// fails without a certificate one of them signed.
+    pub client_ca: Option<String>,
+    /// What a verified client certificate is worth (default `key`).
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+}
+
+/// `[server.tls] client_auth`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    /// The certificate and the API key are both required.
+    #[default]
+    Key,
+    /// The certificate alone authenticates, as its subject CN. A
+    /// `[[auth.keys]]` entry of that name limits it to its scopes.
+    Cert,
+}
+
+impl ServerConfig {
+    /// The listeners to bind: `listeners`, or one on `listen` exposing all
+    /// routes with auth.
+    pub fn effective_listeners(&self) -> Vec<ListenerConfig> {
+        if self.listeners.is_empty() {
+            vec![ListenerConfig {
+                listen: self.listen.clone(),
+                auth: true,
+                expose: default_expose(),
+                tls: true,
+            }]
+        } else {
+            self.listeners.clone()
+        }
+    }
+}
+
+/// One HTTP listener.
+///
+/// ```toml
+/// [[server.listeners]]
+/// listen = "192.168.1.10:1337"
+/// expose = ["health", "ws"]
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ListenerConfig {
+    /// Socket address to bind.
+    pub listen: String,
+    /// Require the API key (default true). May only be turned off on
+    /// loopback addresses.
+    #[serde(default = "default_listener_auth")]
+    pub auth: bool,
+    /// Route groups served (default `["all"]`).
+    #[serde(default = "default_expose")]
+    pub expose: Vec<RouteGroup>,
+    /// Serve `[server.tls]` on this listener when it is set (default true).
+    /// Turn it off for loopback listeners used by local tools.
+    #[serde(default = "default_listener_tls")]
+    pub tls: bool,
+}
+
+impl ListenerConfig {
+    pub fn exposes(&self, group: RouteGroup) -> bool {
+        self.expose
+            .iter()
+            .any(|g| *g == group || *g == RouteGroup::All)
+    }
+}
+
+/// A set of routes a listener can expose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteGroup {
+    /// Every group below.
+    All,
+    /// `GET /api/health`.
+    Health,
+    /// The authenticated REST API (`/api/*` except health and ws).
+    Api,
+    /// `GET /api/ws`.
+    Ws,
+    /// Relay routes (`/d/{serial}/*`, tunnel registration, web UI) in relay mode.
+    Relay,
 }
 
 /// Supervisor settings for `sctl supervise`.
@@ -209,28 +1091,95 @@ pub struct SupervisorConfig {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AuthConfig {
     /// Pre-shared Bearer token. Override with `SCTL_API_KEY` env var.
-    /// Defaults to `"change-me"` which triggers a startup warning.
+    /// Defaults to `"change-me"` which triggers a startup warning. Grants
+    /// every scope and is attributed as `default`.
     #[serde(default = "default_api_key")]
     pub api_key: String,
+    /// Additional named keys, each limited to its scopes.
+    #[serde(default)]
+    pub keys: Vec<NamedKeyConfig>,
+    /// Seconds the replaced key stays valid after `POST /api/auth/rotate`
+    /// when the request doesn't say (default 600).
+    #[serde(default = "default_rotation_grace_secs")]
+    pub rotation_grace_secs: u64,
+}
+
+/// One `[[auth.keys]]` entry. See [`crate::auth`] for the scopes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NamedKeyConfig {
+    /// Name recorded in the activity journal (`key`).
+    pub name: String,
+    /// Bearer token.
+    pub key: String,
+    /// Granted scopes, e.g. `["exec", "files:read"]`; `"*"` grants all.
+    #[serde(default)]
+    pub scopes: Vec<String>,
 }
 
 /// Shell defaults used when requests don't specify overrides.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ShellConfig {
-    /// Shell binary for exec and sessions (default `/bin/sh`).
+    /// Shell binary for exec and sessions (default `/bin/sh`; `%COMSPEC%` on
+    /// Windows).
     #[serde(default = "default_shell")]
     pub default_shell: String,
-    /// Working directory for exec and sessions (default `/`).
+    /// Working directory for exec and sessions (default `/`; the system drive
+    /// on Windows).
     #[serde(default = "default_working_dir")]
     pub default_working_dir: String,
 }
 
+/// A named preset for `session.start`. Fields left unset fall back to the
+/// request, then to the usual defaults. See [`crate::sessions::templates`].
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SessionTemplateConfig {
+    pub shell: Option<String>,
+    pub working_dir: Option<String>,
+    /// Merged under the request's `env`; request values win.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub pty: Option<bool>,
+    pub rows: Option<u16>,
+    pub cols: Option<u16>,
+    pub idle_timeout: Option<u64>,
+    /// Sessions started without a `name` are named `<name_prefix>-<n>`.
+    pub name_prefix: Option<String>,
+}
+
+/// Resource limits applied to every session. See [`crate::shell::cgroup`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionLimitsConfig {
+    /// Parent cgroup for the per-session ones.
+    #[serde(default = "default_session_cgroup")]
+    pub cgroup: String,
+    /// Percent of one CPU; 0 = unlimited.
+    #[serde(default)]
+    pub cpu_percent: u32,
+    /// `memory.max` in MiB; 0 = unlimited.
+    #[serde(default)]
+    pub memory_mb: u64,
+    /// `pids.max`; 0 = unlimited.
+    #[serde(default)]
+    pub pids_max: u64,
+}
+
 /// Device identity, embedded in `/api/info` responses.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DeviceConfig {
     /// Unique device serial number. Override with `SCTL_DEVICE_SERIAL`.
     #[serde(default = "default_serial")]
     pub serial: String,
+    /// Labels sent to the relay on registration, for filtering and grouping
+    /// in `GET /api/tunnel/devices`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Persistent handle of a TPM attestation key. When set,
+    /// `GET /api/info/identity?nonce=` returns a quote signed by it. See
+    /// [`crate::identity`].
+    pub tpm_ak_handle: Option<String>,
+    /// PCR selection quoted with `tpm_ak_handle` (default `sha256:0,1,2,3,4,5,6,7`).
+    #[serde(default = "default_tpm_quote_pcrs")]
+    pub tpm_quote_pcrs: String,
 }
 
 /// Logging configuration.
@@ -239,6 +1188,33 @@ pub struct LoggingConfig {
     /// tracing filter level (default `info`). Overridden by `RUST_LOG` env var.
     #[serde(default = "default_log_level")]
     pub level: String,
+    /// Remote log forwarding. Omit to keep logs local.
+    #[serde(default)]
+    pub forward: Option<LogForwardConfig>,
+}
+
+/// Remote syslog / Vector forwarding of activity entries and tracing events.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogForwardConfig {
+    /// Collector address as `host:port`.
+    pub address: String,
+    /// Wrap the TCP connection in TLS (default false).
+    #[serde(default)]
+    pub tls: bool,
+    /// `syslog` (RFC 5424 with octet-counting framing) or `json` (one object
+    /// per line). Default `syslog`.
+    #[serde(default = "default_forward_format")]
+    pub format: String,
+    /// Minimum tracing level forwarded alongside activity (default `warn`).
+    /// `off` forwards activity entries only.
+    #[serde(default = "default_forward_log_level")]
+    pub log_level: String,
+    /// Records buffered while the collector is unreachable (default 1000).
+    #[serde(default = "default_forward_buffer_size")]
+    pub buffer_size: usize,
+    /// Maximum reconnect backoff in seconds (default 60).
+    #[serde(default = "default_forward_reconnect_max_secs")]
+    pub reconnect_max_secs: u64,
 }
 
 /// Tunnel configuration — enables relay mode or client (outbound) mode.
@@ -256,6 +1232,25 @@ pub struct TunnelConfig {
     pub tunnel_key: String,
     /// Relay URL for client mode (e.g. `wss://relay.example.com/api/tunnel/register`).
     pub url: Option<String>,
+    /// Additional relays to register with at the same time (client mode,
+    /// active-active). Each gets its own connection; the device accepts
+    /// proxied requests from all of them and runs a request a client sent
+    /// through more than one (same `Idempotency-Key`) only once.
+    #[serde(default)]
+    pub extra_urls: Vec<String>,
+    /// Standby relays for `url`, in order of preference (client mode). Only
+    /// one of `url` and these is connected at a time; see
+    /// [`crate::tunnel::failover`].
+    #[serde(default)]
+    pub failover_urls: Vec<String>,
+    /// Failed connection attempts in a row before a relay counts as down and
+    /// the next one is tried (client mode, default 3).
+    #[serde(default = "default_failover_after_attempts")]
+    pub failover_after_attempts: u32,
+    /// Seconds between checks whether a preferred relay is back while on a
+    /// standby (client mode, default 60).
+    #[serde(default = "default_failback_check_secs")]
+    pub failback_check_secs: u64,
     /// Seconds between reconnect attempts (client mode, default 2).
     #[serde(default = "default_reconnect_delay")]
     pub reconnect_delay_secs: u64,
@@ -277,6 +1272,78 @@ pub struct TunnelConfig {
     /// Interface names are resolved to their current IPv4 on each connect
     /// attempt, surviving DHCP/carrier IP changes across reboots.
     pub bind_address: Option<String>,
+    /// Max events spooled to disk while the relay is unreachable, replayed on
+    /// reconnect (client mode, default 1000, 0 disables).
+    #[serde(default = "default_offline_spool_max_entries")]
+    pub offline_spool_max_entries: usize,
+    /// Provisioning token new devices register with to be issued their own
+    /// tunnel and API keys (relay mode). See [`crate::tunnel::enrollment`].
+    pub enrollment_token: Option<String>,
+    /// Only accept enrolled devices' own keys and the enrollment token for
+    /// registration, not `tunnel_key` (relay mode, default false).
+    #[serde(default)]
+    pub require_enrollment: bool,
+    /// Local TCP ports clients may reach through `/d/{serial}/forward/{port}`
+    /// (client mode, default none). See [`crate::tunnel::forward`].
+    #[serde(default)]
+    pub forward_ports: Vec<u16>,
+    /// Bandwidth cap in bytes/s for each port a device exposes at
+    /// `/x/{id}/` (relay mode, default 0 = unlimited). See
+    /// [`crate::tunnel::expose`].
+    #[serde(default)]
+    pub expose_rate_limit_bps: u64,
+    /// Refuse a registration whose hardware fingerprint differs from the one
+    /// pinned for its serial, instead of only flagging it (relay mode,
+    /// default false). See [`crate::tunnel::identity`].
+    #[serde(default)]
+    pub enforce_identity: bool,
+    /// Encoding offered to the relay for tunnel messages (client mode,
+    /// default `json`). See [`crate::tunnel::codec`].
+    #[serde(default)]
+    pub encoding: TunnelEncoding,
+}
+
+/// Wire encoding of tunnel messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelEncoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl TunnelConfig {
+    /// All relay URLs in client mode: `url`, `failover_urls`, then
+    /// `extra_urls` (duplicates removed).
+    pub fn relay_urls(&self) -> Vec<String> {
+        self.relay_groups().into_iter().flatten().collect()
+    }
+
+    /// The relays registered with at the same time in client mode, each an
+    /// ordered failover list: `url` with its `failover_urls`, then each of
+    /// `extra_urls` on its own (duplicates removed).
+    pub fn relay_groups(&self) -> Vec<Vec<String>> {
+        let mut seen: Vec<&String> = Vec::new();
+        let mut groups = Vec::new();
+        let primary = self.url.iter().chain(self.failover_urls.iter());
+        let mut group = Vec::new();
+        for url in primary {
+            if !seen.contains(&url) {
+                seen.push(url);
+                group.push(url.clone());
+            }
+        }
+        if !group.is_empty() {
+            groups.push(group);
+        }
+        for url in &self.extra_urls {
+            if !seen.contains(&url) {
+                seen.push(url);
+                groups.push(vec![url.clone()]);
+            }
+        }
+        groups
+    }
 }
 
 /// GPS/location configuration.
@@ -290,6 +1357,8 @@ pub struct TunnelConfig {
 /// poll_interval_secs = 30
 /// history_size = 100
 /// auto_enable = true
+/// track_interval_secs = 60
+/// track_max_points = 10080
 /// ```
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GpsConfig {
@@ -308,6 +1377,14 @@ pub struct GpsConfig {
     /// Auto-enable GNSS engine on startup (default true).
     #[serde(default = "default_gps_auto_enable")]
     pub auto_enable: bool,
+    /// Seconds between track samples for `/api/gps/track` (default 60,
+    /// 0 disables track recording).
+    #[serde(default = "default_gps_track_interval")]
+    pub track_interval_secs: u64,
+    /// Track points kept, oldest dropped first (default 10080, a week at
+    /// one per minute).
+    #[serde(default = "default_gps_track_max_points")]
+    pub track_max_points: usize,
 }
 
 /// LTE/cellular signal monitoring.
@@ -321,6 +1398,8 @@ pub struct GpsConfig {
 /// poll_interval_secs = 60
 /// watchdog = true
 /// interface = "wwan0"
+/// history_interval_secs = 60
+/// degraded_rsrp_dbm = -110
 /// ```
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LteConfig {
@@ -395,6 +1474,65 @@ pub struct LteConfig {
     /// Both knobs default to `false` — diagnose-and-wait rather than mutate.
     #[serde(default)]
     pub unknown_action: UnknownAction,
+    /// Data-plan failover: APN / SIM profiles tried in order when the tunnel
+    /// keeps failing on a link the modem says is up. Empty (the default)
+    /// leaves the APN alone. Needs at least two entries to do anything.
+    #[serde(default)]
+    pub apn_profiles: Vec<ApnProfile>,
+    /// Failed tunnel connection attempts on the active profile before the
+    /// watchdog moves to the next one (default 6).
+    #[serde(default = "default_apn_failover_after")]
+    pub apn_failover_after_failures: u64,
+    /// Seconds between samples for `/api/lte/history` (default 60, 0
+    /// disables the history and `lte.signal_degraded` events).
+    #[serde(default = "default_lte_history_interval")]
+    pub history_interval_secs: u64,
+    /// Signal samples kept (default 1440, a day at one per minute).
+    #[serde(default = "default_lte_history_size")]
+    pub history_size: usize,
+    /// RSRP (dBm) below which the signal counts as degraded (default -110).
+    #[serde(default = "default_degraded_rsrp")]
+    pub degraded_rsrp_dbm: i64,
+    /// RSRQ (dB) below which the signal counts as degraded (default -15).
+    #[serde(default = "default_degraded_rsrq")]
+    pub degraded_rsrq_db: i64,
+    /// SINR (dB) below which the signal counts as degraded (default 0).
+    #[serde(default = "default_degraded_sinr")]
+    pub degraded_sinr_db: f64,
+}
+
+/// One `[[lte.apn_profiles]]` entry.
+///
+/// ```toml
+/// [[lte.apn_profiles]]
+/// apn = "em"
+///
+/// [[lte.apn_profiles]]
+/// name = "backup"
+/// apn = "iot.1nce.net"
+/// sim_slot = 2
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApnProfile {
+    /// Label for logs and switch history (default: the APN).
+    #[serde(default)]
+    pub name: Option<String>,
+    pub apn: String,
+    /// SIM slot to select first (`AT+QUIMSLOT`, 1 or 2), for dual-SIM
+    /// modules and modules whose eSIM sits in a slot.
+    #[serde(default)]
+    pub sim_slot: Option<u8>,
+    /// AT commands run after the slot switch and before the APN is applied,
+    /// e.g. the module's command to enable an eSIM profile.
+    #[serde(default)]
+    pub at_commands: Vec<String>,
+}
+
+impl ApnProfile {
+    #[must_use]
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.apn)
+    }
 }
 
 /// Evidence gates for the automatic USB power-cycle path. Manual
@@ -438,32 +1576,219 @@ fn default_lte_poll_interval() -> u64 {
 fn default_lte_watchdog() -> bool {
     true
 }
-fn default_comms_provider() -> String {
-    "quectel-at".to_string()
+fn default_lte_history_interval() -> u64 {
+    60
 }
-fn default_comms_startup_timeout() -> u64 {
-    15
+fn default_lte_history_size() -> usize {
+    1440
 }
-fn default_comms_request_timeout() -> u64 {
-    20
+fn default_degraded_rsrp() -> i64 {
+    -110
 }
-fn default_lte_interface() -> String {
-    "wwan0".to_string()
+fn default_degraded_rsrq() -> i64 {
+    -15
 }
-fn default_active_poll_interval() -> u64 {
-    120
+fn default_degraded_sinr() -> f64 {
+    0.0
 }
-fn default_inter_command_delay() -> u64 {
-    1000
+fn default_secrets_provider() -> String {
+    "file".to_string()
 }
-fn default_watchdog_grace() -> u64 {
-    120
+fn default_secrets_env_prefix() -> String {
+    "SCTL_SECRET_".to_string()
 }
-fn default_max_escalation_level() -> u8 {
-    3
+fn default_secrets_command_timeout() -> u64 {
+    10
 }
-fn default_notregistered_grace() -> u64 {
-    180
+fn default_firewall_grace() -> u64 {
+    60
+}
+fn default_ai_sources() -> Vec<String> {
+    vec!["mcp".to_string()]
+}
+
+fn default_flight_recorder_minutes() -> u64 {
+    15
+}
+
+fn default_flight_recorder_segment_secs() -> u64 {
+    60
+}
+
+fn default_flight_recorder_max_dumps() -> usize {
+    10
+}
+
+fn default_activity_journal_max_age_days() -> u64 {
+    30
+}
+
+fn default_activity_journal_max_bytes() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_activity_journal_segment_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_exec_results_memory_bytes() -> u64 {
+    64 * 1024
+}
+
+fn default_exec_results_max_result_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_exec_results_max_bytes() -> u64 {
+    32 * 1024 * 1024
+}
+
+fn default_exec_results_max_age_hours() -> u64 {
+    72
+}
+
+fn default_hooks_timeout_ms() -> u64 {
+    50
+}
+
+fn default_hooks_memory_limit_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_twin_interval_secs() -> u64 {
+    300
+}
+
+fn default_plugins_timeout_secs() -> u64 {
+    30
+}
+
+fn default_plugins_max_output_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_fetch_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_fetch_timeout_secs() -> u64 {
+    60
+}
+
+fn default_fetch_max_redirects() -> u32 {
+    5
+}
+fn default_artifacts_region() -> String {
+    "us-east-1".to_string()
+}
+fn default_artifacts_prefix() -> String {
+    "{serial}/".to_string()
+}
+fn default_artifacts_path_style() -> bool {
+    true
+}
+fn default_artifacts_part_size_mb() -> u64 {
+    8
+}
+fn default_artifacts_max_retries() -> u32 {
+    3
+}
+fn default_artifacts_timeout_secs() -> u64 {
+    3600
+}
+fn default_session_cgroup() -> String {
+    "/sys/fs/cgroup/sctl-sessions".to_string()
+}
+fn default_mqtt_client_id() -> String {
+    "sctl-{serial}".to_string()
+}
+fn default_mqtt_topic_prefix() -> String {
+    "sctl/{serial}".to_string()
+}
+fn default_mqtt_qos() -> u8 {
+    1
+}
+fn default_mqtt_keepalive_secs() -> u16 {
+    60
+}
+fn default_mqtt_activity() -> bool {
+    true
+}
+fn default_mqtt_heartbeat_secs() -> u64 {
+    60
+}
+fn default_mqtt_metrics_secs() -> u64 {
+    300
+}
+fn default_snmp_listen() -> String {
+    "0.0.0.0:161".to_string()
+}
+pub(crate) fn default_snmp_enterprise_oid() -> String {
+    "1.3.6.1.4.1.8072.9999.9999".to_string()
+}
+fn default_firmware_backend() -> String {
+    "auto".to_string()
+}
+fn default_firmware_install_timeout_secs() -> u64 {
+    1800
+}
+fn default_sms_commands_poll_secs() -> u64 {
+    60
+}
+fn default_sms_commands_max_age_secs() -> u64 {
+    900
+}
+fn default_sms_commands_reply() -> bool {
+    true
+}
+
+fn default_summary_enabled() -> bool {
+    true
+}
+fn default_summary_threshold() -> usize {
+    16 * 1024
+}
+fn default_summary_strategy() -> String {
+    "head_tail".to_string()
+}
+fn default_summary_head_lines() -> usize {
+    40
+}
+fn default_summary_tail_lines() -> usize {
+    60
+}
+fn default_summary_command_timeout() -> u64 {
+    10
+}
+fn default_comms_provider() -> String {
+    "quectel-at".to_string()
+}
+fn default_comms_startup_timeout() -> u64 {
+    15
+}
+fn default_comms_request_timeout() -> u64 {
+    20
+}
+fn default_lte_interface() -> String {
+    "wwan0".to_string()
+}
+fn default_active_poll_interval() -> u64 {
+    120
+}
+fn default_inter_command_delay() -> u64 {
+    1000
+}
+fn default_watchdog_grace() -> u64 {
+    120
+}
+fn default_max_escalation_level() -> u8 {
+    3
+}
+fn default_notregistered_grace() -> u64 {
+    180
+}
+fn default_apn_failover_after() -> u64 {
+    6
 }
 fn default_evidence_sustained() -> u64 {
     600
@@ -499,21 +1824,55 @@ fn default_session_buffer_size() -> usize {
 fn default_api_key() -> String {
     "change-me".to_string()
 }
+fn default_rotation_grace_secs() -> u64 {
+    600
+}
+#[cfg(unix)]
 fn default_shell() -> String {
     "/bin/sh".to_string()
 }
+#[cfg(windows)]
+fn default_shell() -> String {
+    std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+}
+#[cfg(unix)]
 fn default_working_dir() -> String {
     "/".to_string()
 }
+#[cfg(windows)]
+fn default_working_dir() -> String {
+    std::env::var("SystemDrive").map_or_else(|_| r"C:\".to_string(), |d| format!(r"{d}\"))
+}
 fn default_serial() -> String {
     "SCTL-0000-DEV-001".to_string()
 }
+fn default_tpm_quote_pcrs() -> String {
+    "sha256:0,1,2,3,4,5,6,7".to_string()
+}
 fn default_log_level() -> String {
     "info".to_string()
 }
+fn default_forward_format() -> String {
+    "syslog".to_string()
+}
+fn default_forward_log_level() -> String {
+    "warn".to_string()
+}
+fn default_forward_buffer_size() -> usize {
+    1000
+}
+fn default_forward_reconnect_max_secs() -> u64 {
+    60
+}
+#[cfg(unix)]
 fn default_data_dir() -> String {
     "/var/lib/sctl".to_string()
 }
+#[cfg(windows)]
+fn default_data_dir() -> String {
+    let base = std::env::var("ProgramData").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+    format!(r"{base}\sctl")
+}
 fn default_journal_enabled() -> bool {
     true
 }
@@ -556,6 +1915,30 @@ fn default_transfer_max_file_size() -> u64 {
 fn default_transfer_stale_timeout() -> u64 {
     3600 // 1 hour
 }
+fn default_listener_auth() -> bool {
+    true
+}
+fn default_listener_tls() -> bool {
+    true
+}
+fn default_expose() -> Vec<RouteGroup> {
+    vec![RouteGroup::All]
+}
+fn default_io_pool_size() -> usize {
+    crate::io_pool::DEFAULT_SIZE
+}
+fn default_http2() -> bool {
+    true
+}
+fn default_http_keep_alive() -> bool {
+    true
+}
+fn default_http_idle_timeout_secs() -> u64 {
+    120
+}
+fn default_http2_max_streams() -> u32 {
+    64
+}
 fn default_gps_poll_interval() -> u64 {
     30
 }
@@ -565,9 +1948,21 @@ fn default_gps_history_size() -> usize {
 fn default_gps_auto_enable() -> bool {
     true
 }
+fn default_gps_track_interval() -> u64 {
+    60
+}
+fn default_gps_track_max_points() -> usize {
+    10_080
+}
 fn default_reconnect_delay() -> u64 {
     2
 }
+fn default_failover_after_attempts() -> u32 {
+    3
+}
+fn default_failback_check_secs() -> u64 {
+    60
+}
 fn default_reconnect_max_delay() -> u64 {
     30
 }
@@ -580,6 +1975,9 @@ fn default_heartbeat_timeout() -> u64 {
 fn default_tunnel_proxy_timeout() -> u64 {
     60
 }
+fn default_offline_spool_max_entries() -> usize {
+    1000
+}
 
 impl Default for ServerConfig {
     fn default() -> Self {
@@ -596,6 +1994,8 @@ impl Default for ServerConfig {
             journal_enabled: default_journal_enabled(),
             journal_fsync_interval_ms: default_journal_fsync_interval_ms(),
             journal_max_age_hours: default_journal_max_age_hours(),
+            recording_dir: None,
+            session_encoding: crate::sessions::decode::OutputEncoding::default(),
             activity_log_max_entries: default_activity_log_max_entries(),
             exec_result_cache_size: default_exec_result_cache_size(),
             default_terminal_rows: default_terminal_rows(),
@@ -605,6 +2005,12 @@ impl Default for ServerConfig {
             transfer_chunk_size: default_transfer_chunk_size(),
             transfer_max_file_size: default_transfer_max_file_size(),
             transfer_stale_timeout_secs: default_transfer_stale_timeout(),
+            transfer_rate_limit_bps: 0,
+            transfer_global_rate_limit_bps: 0,
+            io_pool_size: default_io_pool_size(),
+            listeners: Vec::new(),
+            tls: None,
+            http: HttpConfig::default(),
         }
     }
 }
@@ -618,10 +2024,66 @@ impl Default for SupervisorConfig {
     }
 }
 
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_summary_enabled(),
+            threshold_bytes: default_summary_threshold(),
+            strategy: default_summary_strategy(),
+            head_lines: default_summary_head_lines(),
+            tail_lines: default_summary_tail_lines(),
+            command: None,
+            command_timeout_secs: default_summary_command_timeout(),
+        }
+    }
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            sources: default_ai_sources(),
+            max_execs_per_hour: 0,
+            max_write_bytes_per_hour: 0,
+        }
+    }
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            timeout_secs: default_plugins_timeout_secs(),
+            max_output_bytes: default_plugins_max_output_bytes(),
+        }
+    }
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            allow: Vec::new(),
+            max_bytes: default_fetch_max_bytes(),
+            timeout_secs: default_fetch_timeout_secs(),
+            max_redirects: default_fetch_max_redirects(),
+        }
+    }
+}
+
+impl Default for TwinConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_twin_interval_secs(),
+            apply: false,
+        }
+    }
+}
+
 impl Default for AuthConfig {
     fn default() -> Self {
         Self {
             api_key: default_api_key(),
+            keys: Vec::new(),
+            rotation_grace_secs: default_rotation_grace_secs(),
         }
     }
 }
@@ -639,6 +2101,9 @@ impl Default for DeviceConfig {
     fn default() -> Self {
         Self {
             serial: default_serial(),
+            tags: BTreeMap::new(),
+            tpm_ak_handle: None,
+            tpm_quote_pcrs: default_tpm_quote_pcrs(),
         }
     }
 }
@@ -647,6 +2112,7 @@ impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             level: default_log_level(),
+            forward: None,
         }
     }
 }
@@ -663,6 +2129,30 @@ impl Config {
                 self.server.listen
             ));
         }
+        for (i, listener) in self.server.listeners.iter().enumerate() {
+            match listener.listen.parse::<std::net::SocketAddr>() {
+                Err(_) => errors.push(format!(
+                    "server.listeners[{i}].listen '{}' is not a valid socket address",
+                    listener.listen
+                )),
+                Ok(addr) if !listener.auth && !addr.ip().is_loopback() => errors.push(format!(
+                    "server.listeners[{i}]: auth = false is only allowed on loopback addresses, not '{}'",
+                    listener.listen
+                )),
+                Ok(_) => {}
+            }
+            if listener.expose.is_empty() {
+                errors.push(format!("server.listeners[{i}].expose is empty"));
+            }
+        }
+        if let Some(ref tls) = self.server.tls {
+            if !cfg!(feature = "server-tls") {
+                errors.push("server.tls needs a build with the server-tls feature".to_string());
+            }
+            if tls.client_auth == ClientAuth::Cert && tls.client_ca.is_none() {
+                errors.push("server.tls.client_auth = \"cert\" needs client_ca".to_string());
+            }
+        }
 
         if !(1..=500).contains(&self.server.default_terminal_rows) {
             errors.push(format!(
@@ -702,6 +2192,21 @@ impl Config {
             errors.push("server.max_concurrent_transfers must be >= 1".to_string());
         }
 
+        for (key, value) in &self.device.tags {
+            if !crate::tunnel::valid_tag(key, value) {
+                errors.push(format!(
+                    "device.tags '{key}' = '{value}' is not a valid tag"
+                ));
+            }
+        }
+        if self.device.tags.len() > crate::tunnel::MAX_DEVICE_TAGS {
+            errors.push(format!(
+                "device.tags has {} entries (max {})",
+                self.device.tags.len(),
+                crate::tunnel::MAX_DEVICE_TAGS
+            ));
+        }
+
         if let Some(ref tc) = self.tunnel {
             if !tc.relay {
                 if let Some(ref url) = tc.url {
@@ -711,6 +2216,49 @@ impl Config {
                         ));
                     }
                 }
+                for url in &tc.extra_urls {
+                    if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                        errors.push(format!(
+                            "tunnel.extra_urls entry '{url}' must start with ws:// or wss://"
+                        ));
+                    }
+                }
+                for url in &tc.failover_urls {
+                    if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                        errors.push(format!(
+                            "tunnel.failover_urls entry '{url}' must start with ws:// or wss://"
+                        ));
+                    }
+                }
+                if tc.url.is_none() && !tc.extra_urls.is_empty() {
+                    errors.push("tunnel.extra_urls requires tunnel.url".to_string());
+                }
+                if tc.url.is_none() && !tc.failover_urls.is_empty() {
+                    errors.push("tunnel.failover_urls requires tunnel.url".to_string());
+                }
+                if tc.failover_after_attempts == 0 {
+                    errors.push("tunnel.failover_after_attempts must be at least 1".to_string());
+                }
+                if tc.failback_check_secs == 0 {
+                    errors.push("tunnel.failback_check_secs must be at least 1".to_string());
+                }
+            }
+            if tc.relay
+                && tc
+                    .enrollment_token
+                    .as_ref()
+                    .is_some_and(|t| t.len() < 8 || *t == tc.tunnel_key)
+            {
+                errors.push(
+                    "tunnel.enrollment_token must be at least 8 characters and differ from tunnel_key"
+                        .to_string(),
+                );
+            }
+            if tc.forward_ports.contains(&0) {
+                errors.push("tunnel.forward_ports must not contain port 0".to_string());
+            }
+            if tc.relay && tc.require_enrollment && tc.enrollment_token.is_none() {
+                errors.push("tunnel.require_enrollment needs tunnel.enrollment_token".to_string());
             }
             if tc.relay && tc.tunnel_key.len() < 8 {
                 errors.push(format!(
@@ -720,22 +2268,303 @@ impl Config {
             }
         }
 
+        if let Some(ref fc) = self.logging.forward {
+            if fc
+                .address
+                .rsplit_once(':')
+                .is_none_or(|(host, port)| host.is_empty() || port.parse::<u16>().is_err())
+            {
+                errors.push(format!(
+                    "logging.forward.address '{}' must be host:port",
+                    fc.address
+                ));
+            }
+            if !matches!(fc.format.as_str(), "syslog" | "json") {
+                errors.push(format!(
+                    "logging.forward.format '{}' must be one of syslog, json",
+                    fc.format
+                ));
+            }
+            if fc
+                .log_level
+                .parse::<tracing_subscriber::filter::LevelFilter>()
+                .is_err()
+            {
+                errors.push(format!(
+                    "logging.forward.log_level '{}' must be one of off, error, warn, info, debug, trace",
+                    fc.log_level
+                ));
+            }
+            if fc.buffer_size < 1 {
+                errors.push("logging.forward.buffer_size must be >= 1".to_string());
+            }
+        }
+
+        if let Some(ref sc) = self.secrets {
+            match sc.provider.as_str() {
+                "file" | "env" => {}
+                "command" if sc.command.is_some() => {}
+                "command" => {
+                    errors.push("secrets.command is required for provider \"command\"".to_string());
+                }
+                other => errors.push(format!(
+                    "secrets.provider '{other}' must be one of file, env, command"
+                )),
+            }
+        }
+
+        if !matches!(self.summary.strategy.as_str(), "head_tail" | "errors") {
+            errors.push(format!(
+                "summary.strategy '{}' must be one of head_tail, errors",
+                self.summary.strategy
+            ));
+        }
+        if self.summary.threshold_bytes < 1024 {
+            errors.push(format!(
+                "summary.threshold_bytes {} must be at least 1024",
+                self.summary.threshold_bytes
+            ));
+        }
+
+        if self.fetch.timeout_secs == 0 {
+            errors.push("fetch.timeout_secs must be at least 1".to_string());
+        }
+        for pattern in &self.fetch.allow {
+            if let Err(e) = crate::fetch::validate_allow(pattern) {
+                errors.push(format!("fetch.allow '{pattern}': {e}"));
+            }
+        }
+
+        if let Err(e) = crate::shell::policy::Policy::new(&self.policy) {
+            errors.push(e);
+        }
+
+        for (name, t) in &self.session_templates {
+            if t.rows == Some(0) || t.cols == Some(0) {
+                errors.push(format!(
+                    "session_templates.{name}: rows and cols must be at least 1"
+                ));
+            }
+            if t.name_prefix.as_deref().is_some_and(str::is_empty) {
+                errors.push(format!(
+                    "session_templates.{name}.name_prefix must not be empty"
+                ));
+            }
+        }
+
+        if let Some(ref dav) = self.dav {
+            for (name, dir) in &dav.roots {
+                if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                    errors.push(format!("dav.roots name '{name}' must be one path segment"));
+                }
+                if !dir.starts_with('/') {
+                    errors.push(format!("dav.roots.{name} '{dir}' must be an absolute path"));
+                }
+            }
+        }
+
+        if let Some(ref ec) = self.exec_results {
+            if ec.max_result_bytes == 0 || ec.max_result_bytes > ec.max_bytes {
+                errors.push(format!(
+                    "exec_results.max_result_bytes {} must be between 1 and max_bytes ({})",
+                    ec.max_result_bytes, ec.max_bytes
+                ));
+            }
+        }
+
+        if let Some(ref ac) = self.artifacts {
+            if let Err(e) = crate::fetch::Target::parse(&ac.endpoint) {
+                errors.push(format!("artifacts.endpoint: {e}"));
+            }
+            if ac.bucket.is_empty() || ac.bucket.contains('/') {
+                errors.push(format!(
+                    "artifacts.bucket '{}' is not a bucket name",
+                    ac.bucket
+                ));
+            }
+            if !(5..=5120).contains(&ac.part_size_mb) {
+                errors.push(format!(
+                    "artifacts.part_size_mb must be 5-5120, got {}",
+                    ac.part_size_mb
+                ));
+            }
+        }
+
+        if let Some(ref qc) = self.quiet_hours {
+            if let Err(e) = crate::quiet_hours::QuietHours::new(qc.clone()) {
+                errors.push(format!("quiet_hours: {e}"));
+            }
+            if qc.mode == QuietMode::Throttle && qc.rate_limit_bps == 0 {
+                errors.push("quiet_hours.rate_limit_bps must be set in throttle mode".to_string());
+            }
+        }
+
+        if let Some(ref mc) = self.mqtt {
+            if let Err(e) = crate::mqtt::Broker::parse(&mc.url) {
+                errors.push(format!("mqtt.url: {e}"));
+            }
+            if mc.qos > 1 {
+                errors.push(format!("mqtt.qos must be 0 or 1, got {}", mc.qos));
+            }
+            if mc.keepalive_secs < 5 {
+                errors.push(format!(
+                    "mqtt.keepalive_secs must be at least 5, got {}",
+                    mc.keepalive_secs
+                ));
+            }
+            if mc.topic_prefix.is_empty() || mc.topic_prefix.contains(['+', '#']) {
+                errors.push(format!(
+                    "mqtt.topic_prefix '{}' must be non-empty without wildcards",
+                    mc.topic_prefix
+                ));
+            }
+            for command in &mc.commands {
+                if !crate::mqtt::COMMANDS.contains(&command.as_str()) {
+                    errors.push(format!(
+                        "mqtt.commands: unknown command '{command}' (exec, playbook)"
+                    ));
+                }
+            }
+        }
+
+        if let Some(ref sc) = self.snmp {
+            if sc.listen.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!(
+                    "snmp.listen '{}' must be an address:port",
+                    sc.listen
+                ));
+            }
+            if sc.community.is_empty() {
+                errors.push("snmp.community must not be empty".to_string());
+            }
+            if let Err(e) = crate::snmp::ber::parse_oid(&sc.enterprise_oid) {
+                errors.push(format!("snmp.enterprise_oid: {e}"));
+            }
+        }
+
+        if let Some(ref sc) = self.sms_commands {
+            if sc.key.len() < 16 {
+                errors.push("sms_commands.key must be at least 16 characters".to_string());
+            }
+            if sc.actions.is_empty() {
+                errors.push("sms_commands.actions must not be empty".to_string());
+            }
+            for action in &sc.actions {
+                if !crate::sms_commands::ACTIONS.contains(&action.as_str()) {
+                    errors.push(format!(
+                        "sms_commands.actions: unknown action '{action}' (ping, reconnect_tunnel, reboot)"
+                    ));
+                }
+            }
+            if sc.poll_secs < 10 {
+                errors.push(format!(
+                    "sms_commands.poll_secs must be at least 10, got {}",
+                    sc.poll_secs
+                ));
+            }
+            if !(60..=86_400).contains(&sc.max_age_secs) {
+                errors.push(format!(
+                    "sms_commands.max_age_secs {} must be between 60 and 86400",
+                    sc.max_age_secs
+                ));
+            }
+            if self.effective_comms_config().is_none() {
+                errors.push("sms_commands needs a [comms] provider".to_string());
+            }
+        }
+
+        if let Some(ref fc) = self.firmware {
+            if !crate::firmware::BACKENDS.contains(&fc.backend.as_str()) {
+                errors.push(format!(
+                    "firmware.backend: unknown backend '{}' (auto, rauc, swupdate, mender)",
+                    fc.backend
+                ));
+            }
+            for dir in &fc.bundle_dirs {
+                if !dir.starts_with('/') {
+                    errors.push(format!("firmware.bundle_dirs: '{dir}' must be absolute"));
+                }
+            }
+            if fc.install_timeout_secs < 60 {
+                errors.push(format!(
+                    "firmware.install_timeout_secs must be at least 60, got {}",
+                    fc.install_timeout_secs
+                ));
+            }
+        }
+
+        if let Some(ref lc) = self.lte {
+            for (i, profile) in lc.apn_profiles.iter().enumerate() {
+                if lc.apn_profiles[..i]
+                    .iter()
+                    .any(|p| p.label() == profile.label())
+                {
+                    errors.push(format!(
+                        "lte.apn_profiles[{i}]: '{}' is used twice; give one a name",
+                        profile.label()
+                    ));
+                }
+                if profile.apn.is_empty() || profile.apn.contains(['"', '\r', '\n']) {
+                    errors.push(format!(
+                        "lte.apn_profiles[{i}].apn '{}' is not an APN",
+                        profile.apn
+                    ));
+                }
+                if profile
+                    .sim_slot
+                    .is_some_and(|slot| !(1..=2).contains(&slot))
+                {
+                    errors.push(format!("lte.apn_profiles[{i}].sim_slot must be 1 or 2"));
+                }
+                for command in &profile.at_commands {
+                    if !command
+                        .get(..2)
+                        .is_some_and(|p| p.eq_ignore_ascii_case("at"))
+                        || command.contains(['\r', '\n'])
+                    {
+                        errors.push(format!(
+                            "lte.apn_profiles[{i}].at_commands: '{command}' is not one AT command"
+                        ));
+                    }
+                }
+            }
+            if lc.apn_failover_after_failures == 0 {
+                errors.push("lte.apn_failover_after_failures must be at least 1".to_string());
+            }
+        }
+
+        if let Some(ref fc) = self.firewall {
+            if !(10..=3600).contains(&fc.grace_secs) {
+                errors.push(format!(
+                    "firewall.grace_secs {} must be between 10 and 3600",
+                    fc.grace_secs
+                ));
+            }
+        }
+
         errors
     }
 
+    /// The file [`load`](Self::load) reads: `path`, or `sctl.toml` in the
+    /// current directory if it exists.
+    #[must_use]
+    pub fn file_path(path: Option<&str>) -> Option<PathBuf> {
+        path.map(PathBuf::from).or_else(|| {
+            let local = Path::new("sctl.toml");
+            local.exists().then(|| local.to_path_buf())
+        })
+    }
+
     /// Load configuration with the precedence chain: env vars > file > defaults.
     ///
     /// If `path` is `Some`, reads that file (panics on failure). Otherwise looks
     /// for `sctl.toml` in the current directory, falling back to compiled defaults.
     pub fn load(path: Option<&str>) -> Self {
-        let mut config = if let Some(p) = path {
-            let content = std::fs::read_to_string(p)
-                .unwrap_or_else(|e| panic!("Failed to read config file {p}: {e}"));
+        let mut config = if let Some(p) = Self::file_path(path) {
+            let content = std::fs::read_to_string(&p)
+                .unwrap_or_else(|e| panic!("Failed to read config file {}: {e}", p.display()));
             toml::from_str(&content)
-                .unwrap_or_else(|e| panic!("Failed to parse config file {p}: {e}"))
-        } else if Path::new("sctl.toml").exists() {
-            let content = std::fs::read_to_string("sctl.toml").expect("Failed to read sctl.toml");
-            toml::from_str(&content).expect("Failed to parse sctl.toml")
+                .unwrap_or_else(|e| panic!("Failed to parse config file {}: {e}", p.display()))
         } else {
             Config {
                 server: ServerConfig::default(),
@@ -744,10 +2573,33 @@ impl Config {
                 device: DeviceConfig::default(),
                 logging: LoggingConfig::default(),
                 supervisor: SupervisorConfig::default(),
+                summary: SummaryConfig::default(),
+                ai: AiConfig::default(),
+                classify: ClassifyConfig::default(),
+                policy: PolicyConfig::default(),
+                session_templates: BTreeMap::new(),
+                session_limits: None,
+                plugins: PluginsConfig::default(),
+                fetch: FetchConfig::default(),
+                twin: TwinConfig::default(),
                 tunnel: None,
                 comms: None,
                 gps: None,
                 lte: None,
+                secrets: None,
+                firewall: None,
+                flight_recorder: None,
+                activity_journal: None,
+                exec_results: None,
+                hooks: None,
+                sftp: None,
+                dav: None,
+                artifacts: None,
+                quiet_hours: None,
+                mqtt: None,
+                snmp: None,
+                sms_commands: None,
+                firmware: None,
             }
         };
 
@@ -0,0 +1,402 @@
+//! Registry of open connections, for `GET /api/connections`.
+//!
+//! Every connection accepted by a listener ([`crate::serve`]) is registered
+//! for as long as its socket is open, including after a WebSocket upgrade,
+//! and so is the device's own tunnel to its relay. Each entry tracks:
+//!
+//! - **kind** — `http`, then `ws`, `sse` or `tunnel` once it carries one
+//!   (`tunnel` is a device's connection to its relay, or on a relay a
+//!   device's registration socket)
+//! - **peer** and the **local** listener (or relay URL)
+//! - **identity** — the key name of the last authenticated request
+//! - **bytes** in and out, **requests** served, and those **in flight** —
+//!   the ones holding `max_connections` slots
+//! - age and time since the last traffic
+//!
+//! [`Connections::disconnect`] makes the socket fail its next read and
+//! write, which ends the connection whatever is running on it.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::Extensions;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+/// What a connection is carrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionKind {
+    Http,
+    Ws,
+    Sse,
+    Tunnel,
+}
+
+impl ConnectionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Ws => "ws",
+            Self::Sse => "sse",
+            Self::Tunnel => "tunnel",
+        }
+    }
+}
+
+/// One open connection.
+pub struct Connection {
+    pub id: u64,
+    peer: String,
+    local: String,
+    opened: Instant,
+    /// Unix ms.
+    opened_at: u64,
+    kind: Mutex<ConnectionKind>,
+    protocol: Mutex<&'static str>,
+    identity: Mutex<Option<String>>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    requests: AtomicU64,
+    in_flight: AtomicU64,
+    /// Milliseconds after `opened`.
+    last: AtomicU64,
+    close: CancellationToken,
+}
+
+/// A connection as listed by `GET /api/connections`.
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub kind: ConnectionKind,
+    /// `http/1.1`, `h2` or `ws`.
+    pub protocol: &'static str,
+    pub peer: String,
+    pub local: String,
+    pub identity: Option<String>,
+    /// Unix ms.
+    pub opened_at: u64,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub requests: u64,
+    pub in_flight: u64,
+}
+
+impl Connection {
+    /// Change what the connection is carrying.
+    pub fn set_kind(&self, kind: ConnectionKind) {
+        *lock(&self.kind) = kind;
+    }
+
+    pub fn set_protocol(&self, protocol: &'static str) {
+        *lock(&self.protocol) = protocol;
+    }
+
+    /// Record the key name a request on this connection authenticated as.
+    pub fn set_identity(&self, name: &str) {
+        let mut identity = lock(&self.identity);
+        if identity.as_deref() != Some(name) {
+            *identity = Some(name.to_string());
+        }
+    }
+
+    /// Count a request until the returned guard is dropped.
+    pub fn begin_request(self: &Arc<Self>) -> InFlight {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// When the connection last moved data.
+    pub fn last_activity(&self) -> Instant {
+        self.opened + Duration::from_millis(self.last.load(Ordering::Relaxed))
+    }
+
+    fn touch(&self) {
+        let ms = u64::try_from(self.opened.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.last.store(ms, Ordering::Relaxed);
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        let now = Instant::now();
+        ConnectionInfo {
+            id: self.id,
+            kind: *lock(&self.kind),
+            protocol: *lock(&self.protocol),
+            peer: self.peer.clone(),
+            local: self.local.clone(),
+            identity: lock(&self.identity).clone(),
+            opened_at: self.opened_at,
+            age_secs: now.duration_since(self.opened).as_secs(),
+            idle_secs: now.duration_since(self.last_activity()).as_secs(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A request in progress on a [`Connection`].
+pub struct InFlight(Arc<Connection>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The open connections.
+#[derive(Default)]
+pub struct Connections {
+    open: Mutex<BTreeMap<u64, Arc<Connection>>>,
+    next_id: AtomicU64,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a connection; it stays listed until the returned handle drops.
+    pub fn register(
+        self: &Arc<Self>,
+        kind: ConnectionKind,
+        protocol: &'static str,
+        peer: String,
+        local: String,
+    ) -> Registration {
+        let connection = Arc::new(Connection {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            peer,
+            local,
+            opened: Instant::now(),
+            opened_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            kind: Mutex::new(kind),
+            protocol: Mutex::new(protocol),
+            identity: Mutex::new(None),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            last: AtomicU64::new(0),
+            close: CancellationToken::new(),
+        });
+        lock(&self.open).insert(connection.id, connection.clone());
+        Registration {
+            connections: self.clone(),
+            connection,
+        }
+    }
+
+    /// The open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        lock(&self.open).values().map(|c| c.info()).collect()
+    }
+
+    /// Close a connection. Returns `false` if there is none with that id.
+    pub fn disconnect(&self, id: u64) -> bool {
+        match lock(&self.open).get(&id) {
+            Some(connection) => {
+                connection.close.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps a connection listed; see [`Connections::register`].
+pub struct Registration {
+    connections: Arc<Connections>,
+    connection: Arc<Connection>,
+}
+
+impl Registration {
+    pub fn connection(&self) -> &Arc<Connection> {
+        &self.connection
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        lock(&self.connections.open).remove(&self.connection.id);
+    }
+}
+
+/// Record `name` on the connection a request arrived on, if it has one.
+pub fn identify(extensions: &Extensions, name: &str) {
+    if let Some(connection) = extensions.get::<Arc<Connection>>() {
+        connection.set_identity(name);
+    }
+}
+
+/// An IO stream that counts its traffic into a [`Connection`] and fails
+/// once the connection is disconnected.
+pub struct Tracked<I> {
+    inner: I,
+    registration: Registration,
+    closed: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl<I> Tracked<I> {
+    pub fn new(inner: I, registration: Registration) -> Self {
+        let closed = Box::pin(registration.connection.close.clone().cancelled_owned());
+        Self {
+            inner,
+            registration,
+            closed,
+        }
+    }
+
+    pub fn connection(&self) -> &Arc<Connection> {
+        &self.registration.connection
+    }
+
+    fn check_closed(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        match self.closed.as_mut().poll(cx) {
+            Poll::Ready(()) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection closed by an administrator",
+            )),
+            Poll::Pending => Ok(()),
+        }
+    }
+
+    fn count_out(&self, poll: &Poll<io::Result<usize>>) {
+        if let Poll::Ready(Ok(n)) = poll {
+            if *n > 0 {
+                let connection = &self.registration.connection;
+                connection.bytes_out.fetch_add(*n as u64, Ordering::Relaxed);
+                connection.touch();
+            }
+        }
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Tracked<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.check_closed(cx)?;
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        if read > 0 {
+            let connection = &this.registration.connection;
+            connection
+                .bytes_in
+                .fetch_add(read as u64, Ordering::Relaxed);
+            connection.touch();
+        }
+        poll
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Tracked<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.check_closed(cx)?;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.count_out(&poll);
+        poll
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.check_closed(cx)?;
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.count_out(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn counts_traffic_and_disconnects() {
+        let connections = Arc::new(Connections::new());
+        let (client, server) = tokio::io::duplex(64);
+        let registration = connections.register(
+            ConnectionKind::Http,
+            "http/1.1",
+            "10.0.0.2:50000".to_string(),
+            "0.0.0.0:1337".to_string(),
+        );
+        let connection = registration.connection().clone();
+        let mut tracked = Tracked::new(server, registration);
+        let mut client = client;
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tracked.read_exact(&mut buf).await.unwrap();
+        tracked.write_all(b"hi").await.unwrap();
+        {
+            let _request = connection.begin_request();
+            connection.set_identity("ci");
+            let listed = connections.list();
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].bytes_in, 5);
+            assert_eq!(listed[0].bytes_out, 2);
+            assert_eq!(listed[0].in_flight, 1);
+            assert_eq!(listed[0].identity.as_deref(), Some("ci"));
+        }
+        assert_eq!(connections.list()[0].in_flight, 0);
+
+        // A pending read fails as soon as the connection is disconnected.
+        let id = connection.id;
+        let read = tokio::spawn(async move {
+            let result = tracked.read(&mut buf).await;
+            drop(tracked);
+            result
+        });
+        tokio::task::yield_now().await;
+        assert!(connections.disconnect(id));
+        let err = read.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(connections.list().is_empty());
+        assert!(!connections.disconnect(id));
+    }
+}
@@ -0,0 +1,671 @@
+//! Read-only WebDAV export of selected directories.
+//!
+//! With `[dav.roots]` configured, `/dav/{name}/` serves each named directory
+//! as WebDAV class 1, so operators can mount device logs and artifacts in
+//! Finder, Explorer, GNOME Files or `davfs2`. Only `OPTIONS`, `PROPFIND`,
+//! `GET` and `HEAD` are answered. Anything that would write gets `405`, and
+//! no locks are offered, so clients mount the share read-only. `/dav/`
+//! itself lists the roots.
+//!
+//! File browsers only speak Basic auth. The password is taken as the API
+//! key, or the user name if the password is empty, and the key needs the
+//! `files:read` scope. `Bearer` works too. Through a relay, each request to
+//! `/d/{serial}/dav/...` travels over its own forwarded stream
+//! ([`crate::tunnel::forward`]), opened with `tunnel.dav.open`. The device
+//! answers it with [`serve_connection`].
+//!
+//! Paths are resolved under their root and canonicalized, so neither `..`
+//! nor a symlink leads outside it.
+
+use std::fmt::Write as _;
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::any,
+    Extension, Router,
+};
+use base64::Engine;
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tracing::debug;
+
+use crate::activity::{self, ActivitySource, ActivityType};
+use crate::config::DavConfig;
+use crate::util::format_http_date;
+use crate::AppState;
+
+/// Methods the export answers, for `Allow`.
+pub const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+/// Where the export is served on the device's own listeners.
+pub const PREFIX: &str = "/dav";
+
+/// Marks a request that came through a relay, with the prefix the relay
+/// serves the device under (`/d/{serial}`). It is put in front of every
+/// `href` so the client's next request finds its way back.
+#[derive(Clone)]
+pub struct RelayPrefix(pub String);
+
+/// `/dav`, `/dav/` and everything below, any method.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/dav", any(crate::routes::dav::dav))
+        .route("/dav/", any(crate::routes::dav::dav))
+        .route("/dav/{*path}", any(crate::routes::dav::dav))
+}
+
+/// The API key in a `Basic` or `Bearer` `Authorization` header.
+pub fn credentials(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return Some(token.to_string());
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(value.strip_prefix("Basic ")?.trim())
+        .ok()?;
+    let text = String::from_utf8(decoded).ok()?;
+    let (user, password) = text.split_once(':')?;
+    let key = if password.is_empty() { user } else { password };
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+/// `401` asking for Basic credentials, so file browsers prompt for the key.
+pub fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            r#"Basic realm="sctl", charset="UTF-8""#,
+        )],
+        "Unauthorized",
+    )
+        .into_response()
+}
+
+/// `OPTIONS`: class 1, read-only.
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [
+            ("DAV", "1"),
+            ("MS-Author-Via", "DAV"),
+            (header::ALLOW.as_str(), ALLOW),
+        ],
+    )
+        .into_response()
+}
+
+/// Middleware answering `OPTIONS` on `/dav` and `/d/{serial}/dav` before the
+/// CORS layer, which takes every `OPTIONS` for a preflight. Clients probe
+/// with it, unauthenticated, to find out the share speaks WebDAV. Real
+/// preflights (with `Access-Control-Request-Method`) pass through.
+pub async fn answer_options(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let under_dav = |p: &str| p == PREFIX || p.starts_with("/dav/");
+    let is_dav = under_dav(path)
+        || path
+            .strip_prefix("/d/")
+            .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+            .is_some_and(under_dav);
+    if req.method() == Method::OPTIONS
+        && is_dav
+        && !req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return options();
+    }
+    next.run(req).await
+}
+
+/// Answer one authorized request.
+pub async fn respond(
+    state: &AppState,
+    config: &DavConfig,
+    relay_prefix: Option<&str>,
+    req: &Parts,
+) -> Response {
+    let path = req.uri.path().strip_prefix(PREFIX).unwrap_or_default();
+    let Some(segments) = segments(path) else {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    };
+    let base = format!("{}{PREFIX}", relay_prefix.unwrap_or_default());
+    match req.method.as_str() {
+        "OPTIONS" => options(),
+        "PROPFIND" => {
+            let depth_zero = req
+                .headers
+                .get("depth")
+                .is_some_and(|d| d.as_bytes() == b"0");
+            propfind(config, &base, &segments, depth_zero)
+                .await
+                .unwrap_or_else(IntoResponse::into_response)
+        }
+        "GET" | "HEAD" => {
+            let source = if relay_prefix.is_some() {
+                ActivitySource::Tunnel
+            } else {
+                ActivitySource::Rest
+            };
+            get(state, config, &base, &segments, req, source)
+                .await
+                .unwrap_or_else(IntoResponse::into_response)
+        }
+        _ => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, ALLOW)],
+            "Read-only",
+        )
+            .into_response(),
+    }
+}
+
+/// Answer HTTP/1.1 requests arriving on a forwarded stream until the relay
+/// closes it. The relay has already checked the device key, so requests go
+/// straight to [`respond`].
+pub async fn serve_connection<IO>(state: AppState, io: IO, relay_prefix: String)
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    use tower::ServiceExt;
+
+    let app = router()
+        .with_state(state)
+        .layer(Extension(crate::auth::AuthExempt))
+        .layer(Extension(RelayPrefix(relay_prefix)));
+    let service = hyper::service::service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+        app.clone().oneshot(req.map(Body::new))
+    });
+    if let Err(e) = hyper::server::conn::http1::Builder::new()
+        .serve_connection(hyper_util::rt::TokioIo::new(io), service)
+        .await
+    {
+        debug!("WebDAV stream ended: {e}");
+    }
+}
+
+// ─── Paths ───────────────────────────────────────────────────────────────────
+
+/// The decoded segments of a URL path below `/dav`. `None` for `.`, `..`,
+/// an encoded `/` or NUL, or bad percent-encoding.
+fn segments(path: &str) -> Option<Vec<String>> {
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let decoded = percent_decode(s)?;
+            (decoded != "." && decoded != ".." && !decoded.contains(['/', '\0'])).then_some(decoded)
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(char::from(b));
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+    out
+}
+
+/// The `href` of `segments` under `base`, with a trailing `/` for
+/// collections.
+fn href(base: &str, segments: &[String], collection: bool) -> String {
+    let mut href = base.to_string();
+    for segment in segments {
+        href.push('/');
+        href.push_str(&percent_encode(segment));
+    }
+    if collection || segments.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+/// A root's directory, canonicalized.
+async fn root_dir(config: &DavConfig, name: &str) -> Result<PathBuf, StatusCode> {
+    let dir = config.roots.get(name).ok_or(StatusCode::NOT_FOUND)?;
+    tokio::fs::canonicalize(dir)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+/// Where `segments` (root name first) leads on disk, and what is there.
+/// Anything resolving outside the root is `404`.
+async fn resolve(
+    config: &DavConfig,
+    segments: &[String],
+) -> Result<(PathBuf, Metadata), StatusCode> {
+    let root = root_dir(config, &segments[0]).await?;
+    let mut path = root.clone();
+    path.extend(&segments[1..]);
+    let path = tokio::fs::canonicalize(&path)
+        .await
+        .map_err(|e| io_status(&e))?;
+    if !path.starts_with(&root) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let meta = tokio::fs::metadata(&path)
+        .await
+        .map_err(|e| io_status(&e))?;
+    Ok((path, meta))
+}
+
+fn io_status(e: &std::io::Error) -> StatusCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// One entry in a listing. `meta` is `None` for `/dav/` itself.
+struct Item {
+    name: String,
+    href: String,
+    meta: Option<Metadata>,
+}
+
+impl Item {
+    fn is_collection(&self) -> bool {
+        self.meta.as_ref().is_none_or(Metadata::is_dir)
+    }
+}
+
+/// The entries of a collection: the roots for `/dav/`, else the directory's
+/// children. Symlinks leading outside the root are left out.
+async fn children(
+    config: &DavConfig,
+    base: &str,
+    segments: &[String],
+    dir: Option<&PathBuf>,
+) -> Vec<Item> {
+    let mut items = Vec::new();
+    let Some(dir) = dir else {
+        for name in config.roots.keys() {
+            let Ok(root) = root_dir(config, name).await else {
+                continue;
+            };
+            if let Ok(meta) = tokio::fs::metadata(&root).await {
+                let segments = [name.clone()];
+                items.push(Item {
+                    name: name.clone(),
+                    href: href(base, &segments, meta.is_dir()),
+                    meta: Some(meta),
+                });
+            }
+        }
+        return items;
+    };
+    let Ok(root) = root_dir(config, &segments[0]).await else {
+        return items;
+    };
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return items;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if entry.file_type().await.is_ok_and(|t| t.is_symlink()) {
+            let inside = tokio::fs::canonicalize(entry.path())
+                .await
+                .is_ok_and(|target| target.starts_with(&root));
+            if !inside {
+                continue;
+            }
+        }
+        let Ok(meta) = tokio::fs::metadata(entry.path()).await else {
+            continue;
+        };
+        let mut child = segments.to_vec();
+        child.push(name.clone());
+        items.push(Item {
+            name,
+            href: href(base, &child, meta.is_dir()),
+            meta: Some(meta),
+        });
+    }
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+    items
+}
+
+// ─── PROPFIND ────────────────────────────────────────────────────────────────
+
+/// `207 Multi-Status` for the resource, and its children unless `Depth: 0`.
+/// `Depth: infinity` is answered as `1`.
+async fn propfind(
+    config: &DavConfig,
+    base: &str,
+    segments: &[String],
+    depth_zero: bool,
+) -> Result<Response, StatusCode> {
+    let (path, meta) = if segments.is_empty() {
+        (None, None)
+    } else {
+        let (path, meta) = resolve(config, segments).await?;
+        (Some(path), Some(meta))
+    };
+    let this = Item {
+        name: segments.last().cloned().unwrap_or_default(),
+        href: href(base, segments, meta.as_ref().is_none_or(Metadata::is_dir)),
+        meta,
+    };
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    write_response(&mut xml, &this);
+    if this.is_collection() && !depth_zero {
+        for item in children(config, base, segments, path.as_ref()).await {
+            write_response(&mut xml, &item);
+        }
+    }
+    xml.push_str("</D:multistatus>\n");
+    Ok((
+        StatusCode::MULTI_STATUS,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response())
+}
+
+fn write_response(xml: &mut String, item: &Item) {
+    let _ = write!(
+        xml,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>",
+        xml_escape(&item.href),
+        xml_escape(&item.name)
+    );
+    if item.is_collection() {
+        xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        xml.push_str("<D:resourcetype/>");
+    }
+    if let Some(meta) = &item.meta {
+        let modified = modified_secs(meta);
+        let _ = write!(
+            xml,
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            format_http_date(modified)
+        );
+        if !meta.is_dir() {
+            let _ = write!(
+                xml,
+                "<D:getcontentlength>{}</D:getcontentlength>\
+                 <D:getcontenttype>{}</D:getcontenttype>\
+                 <D:getetag>{}</D:getetag>",
+                meta.len(),
+                content_type(&item.name),
+                xml_escape(&etag(meta))
+            );
+        }
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn modified_secs(meta: &Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+fn etag(meta: &Metadata) -> String {
+    format!("\"{:x}-{:x}\"", modified_secs(meta), meta.len())
+}
+
+/// Text for what is obviously text, so browsers show logs inline; anything
+/// else downloads. Nothing is rendered as HTML.
+fn content_type(name: &str) -> &'static str {
+    let ext = name.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("log" | "txt" | "conf" | "cfg" | "ini" | "csv" | "md" | "yaml" | "yml" | "toml") => {
+            "text/plain; charset=utf-8"
+        }
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+// ─── GET / HEAD ──────────────────────────────────────────────────────────────
+
+/// A file's bytes, honouring a single `Range`, or a plain HTML index for a
+/// collection. File reads are journaled as `file_read`, except ranges that
+/// continue a read already logged.
+async fn get(
+    state: &AppState,
+    config: &DavConfig,
+    base: &str,
+    segments: &[String],
+    req: &Parts,
+    source: ActivitySource,
+) -> Result<Response, StatusCode> {
+    if segments.is_empty() {
+        return Ok(index_html(
+            base,
+            segments,
+            &children(config, base, segments, None).await,
+        ));
+    }
+    let (path, meta) = resolve(config, segments).await?;
+    if meta.is_dir() {
+        let items = children(config, base, segments, Some(&path)).await;
+        return Ok(index_html(base, segments, &items));
+    }
+
+    let len = meta.len();
+    let range = req
+        .headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(Ok(None), |v| byte_range(v, len));
+    let Ok(range) = range else {
+        return Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+        )
+            .into_response());
+    };
+    let (start, end) = range.unwrap_or((0, len.saturating_sub(1)));
+    let count = if len == 0 { 0 } else { end - start + 1 };
+
+    let mut response = Response::builder()
+        .status(if range.is_some() {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        })
+        .header(
+            header::CONTENT_TYPE,
+            content_type(&segments[segments.len() - 1]),
+        )
+        .header(header::CONTENT_LENGTH, count)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(
+            header::LAST_MODIFIED,
+            format_http_date(modified_secs(&meta)),
+        )
+        .header(header::ETAG, etag(&meta))
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+    if range.is_some() {
+        response = response.header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"));
+    }
+    if req.method == Method::HEAD {
+        return Ok(response.body(Body::empty()).unwrap());
+    }
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| io_status(&e))?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if start == 0 {
+        state
+            .activity_log
+            .log(
+                ActivityType::FileRead,
+                source,
+                activity::truncate_str(&path.to_string_lossy(), 80),
+                Some(json!({ "via": "dav", "size": len })),
+                None,
+            )
+            .await;
+    }
+    let stream = tokio_util::io::ReaderStream::new(file.take(count));
+    Ok(response.body(Body::from_stream(stream)).unwrap())
+}
+
+/// The one `bytes=` range asked for, clamped to a file of `len` bytes.
+/// `Ok(None)` means serve the whole file: no usable range (several ranges or
+/// a malformed header are ignored, as RFC 9110 allows). `Err` means the
+/// range starts past the end.
+fn byte_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let range = if first.is_empty() {
+        let Ok(suffix) = last.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || len == 0 {
+            return Err(());
+        }
+        (len - suffix.min(len), len - 1)
+    } else {
+        let Ok(start) = first.parse::<u64>() else {
+            return Ok(None);
+        };
+        let end = match last {
+            "" => len.saturating_sub(1),
+            last => match last.parse::<u64>() {
+                Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                _ => return Ok(None),
+            },
+        };
+        if start >= len {
+            return Err(());
+        }
+        (start, end)
+    };
+    Ok(Some(range))
+}
+
+fn index_html(base: &str, segments: &[String], items: &[Item]) -> Response {
+    let title = xml_escape(&href(base, segments, true));
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title></head>\
+         <body><h1>{title}</h1><ul>\n"
+    );
+    if !segments.is_empty() {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for item in items {
+        let slash = if item.is_collection() { "/" } else { "" };
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{}\">{}{slash}</a></li>",
+            xml_escape(&item.href),
+            xml_escape(&item.name)
+        );
+    }
+    html.push_str("</ul></body></html>\n");
+    (
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        html,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_inside_and_hrefs_round_trip() {
+        assert_eq!(
+            segments("/logs/sub%20dir/a%2Bb.log").unwrap(),
+            ["logs", "sub dir", "a+b.log"]
+        );
+        assert_eq!(segments("/").unwrap(), Vec::<String>::new());
+        assert!(segments("/logs/../etc").is_none());
+        assert!(segments("/logs/%2e%2e/etc").is_none());
+        assert!(segments("/logs/a%2Fb").is_none());
+        assert!(segments("/logs/%zz").is_none());
+
+        let segs = segments("/logs/sub%20dir").unwrap();
+        assert_eq!(
+            href("/d/DEV-1/dav", &segs, true),
+            "/d/DEV-1/dav/logs/sub%20dir/"
+        );
+        assert_eq!(href("/dav", &[], false), "/dav/");
+
+        assert_eq!(byte_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(byte_range("bytes=900-", 1000), Ok(Some((900, 999))));
+        assert_eq!(byte_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(byte_range("bytes=500-5000", 1000), Ok(Some((500, 999))));
+        assert_eq!(byte_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(byte_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(byte_range("items=0-1", 1000), Ok(None));
+    }
+
+    #[test]
+    fn basic_auth_carries_the_key() {
+        let mut headers = HeaderMap::new();
+        // "ops:secret"
+        headers.insert(
+            header::AUTHORIZATION,
+            "Basic b3BzOnNlY3JldA==".parse().unwrap(),
+        );
+        assert_eq!(credentials(&headers).as_deref(), Some("secret"));
+        // "secret:"
+        headers.insert(header::AUTHORIZATION, "Basic c2VjcmV0Og==".parse().unwrap());
+        assert_eq!(credentials(&headers).as_deref(), Some("secret"));
+        headers.insert(header::AUTHORIZATION, "Bearer k".parse().unwrap());
+        assert_eq!(credentials(&headers).as_deref(), Some("k"));
+    }
+}
@@ -0,0 +1,906 @@
+//! Minimal D-Bus client for method calls on the system bus.
+//!
+//! Just enough of the wire protocol for [`crate::routes::netman`] and
+//! [`crate::routes::services`]: SASL `EXTERNAL` authentication, `Hello`, and
+//! method calls whose arguments are basic types or string arrays. Replies of any signature are decoded into [`Value`], in
+//! either byte order. Signals and other traffic arriving while a call waits
+//! for its reply are discarded.
+//!
+//! The bus address comes from `DBUS_SYSTEM_BUS_ADDRESS` (first
+//! `unix:path=` entry), else `/var/run/dbus/system_bus_socket`. Windows has
+//! no system bus, so connecting there always fails with [`Error::Connect`].
+
+use std::fmt;
+use std::time::Duration;
+
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(windows)]
+type UnixStream = tokio::net::TcpStream;
+
+const DEFAULT_SYSTEM_BUS: &str = "/var/run/dbus/system_bus_socket";
+
+/// Connect, authenticate and `Hello` must be done within this.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A method call must be answered within this.
+const CALL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Largest message accepted (the spec's limit is 128 MiB).
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// Containers and variants nested deeper than this are refused.
+const MAX_DEPTH: u32 = 64;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// A D-Bus value. Arrays of dict entries decode as [`Value::Dict`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    Double(f64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    Variant(Box<Value>),
+    Array(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+    Struct(Vec<Value>),
+}
+
+impl Value {
+    /// The value inside any number of variants.
+    fn inner(&self) -> &Self {
+        match self {
+            Self::Variant(v) => v.inner(),
+            v => v,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.inner() {
+            Self::Str(s) | Self::ObjectPath(s) | Self::Signature(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self.inner() {
+            Self::Byte(v) => Some(u64::from(v)),
+            Self::U16(v) => Some(u64::from(v)),
+            Self::U32(v) => Some(u64::from(v)),
+            Self::U64(v) => Some(v),
+            Self::I16(v) => u64::try_from(v).ok(),
+            Self::I32(v) => u64::try_from(v).ok(),
+            Self::I64(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self.inner() {
+            Self::I16(v) => Some(i64::from(v)),
+            Self::I32(v) => Some(i64::from(v)),
+            Self::I64(v) => Some(v),
+            _ => self.as_u64().and_then(|v| i64::try_from(v).ok()),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self.inner() {
+            Self::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Elements of an array; empty for anything else.
+    pub fn as_array(&self) -> &[Value] {
+        match self.inner() {
+            Self::Array(items) | Self::Struct(items) => items,
+            _ => &[],
+        }
+    }
+
+    /// The entry with string key `key` in a dict.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self.inner() {
+            Self::Dict(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v.inner()),
+            _ => None,
+        }
+    }
+
+    /// JSON form: variants are unwrapped, dicts with string keys become
+    /// objects, and byte arrays that are printable UTF-8 (SSIDs, mostly)
+    /// become strings.
+    pub fn to_json(&self) -> Json {
+        match self.inner() {
+            Self::Byte(v) => json!(v),
+            Self::Bool(v) => json!(v),
+            Self::I16(v) => json!(v),
+            Self::U16(v) => json!(v),
+            Self::I32(v) => json!(v),
+            Self::U32(v) => json!(v),
+            Self::I64(v) => json!(v),
+            Self::U64(v) => json!(v),
+            Self::Double(v) => json!(v),
+            Self::Str(s) | Self::ObjectPath(s) | Self::Signature(s) => json!(s),
+            Self::Array(items) => {
+                let bytes: Option<Vec<u8>> = items
+                    .iter()
+                    .map(|v| match v {
+                        Self::Byte(b) => Some(*b),
+                        _ => None,
+                    })
+                    .collect();
+                match bytes.and_then(|b| String::from_utf8(b).ok()) {
+                    Some(s) if !items.is_empty() && !s.chars().any(char::is_control) => json!(s),
+                    _ => Json::Array(items.iter().map(Self::to_json).collect()),
+                }
+            }
+            Self::Struct(items) => Json::Array(items.iter().map(Self::to_json).collect()),
+            Self::Dict(entries) => {
+                if entries.iter().all(|(k, _)| k.as_str().is_some()) {
+                    Json::Object(
+                        entries
+                            .iter()
+                            .map(|(k, v)| (k.as_str().unwrap_or_default().to_string(), v.to_json()))
+                            .collect(),
+                    )
+                } else {
+                    Json::Array(
+                        entries
+                            .iter()
+                            .map(|(k, v)| json!([k.to_json(), v.to_json()]))
+                            .collect(),
+                    )
+                }
+            }
+            Self::Variant(_) => unreachable!("inner() unwraps variants"),
+        }
+    }
+
+    /// Signature of a basic value or variant; `None` for containers, which
+    /// can't be sent.
+    fn signature(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Byte(_) => "y",
+            Self::Bool(_) => "b",
+            Self::I16(_) => "n",
+            Self::U16(_) => "q",
+            Self::I32(_) => "i",
+            Self::U32(_) => "u",
+            Self::I64(_) => "x",
+            Self::U64(_) => "t",
+            Self::Double(_) => "d",
+            Self::Str(_) => "s",
+            Self::ObjectPath(_) => "o",
+            Self::Signature(_) => "g",
+            Self::Variant(_) => "v",
+            Self::Array(items) if items.iter().all(|v| matches!(v, Self::Str(_))) => "as",
+            Self::Array(_) | Self::Dict(_) | Self::Struct(_) => return None,
+        })
+    }
+}
+
+/// Why a call failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The bus couldn't be reached or refused us.
+    Connect(String),
+    /// The callee replied with a D-Bus error.
+    Remote { name: String, message: String },
+    /// The connection broke, timed out or carried something unreadable.
+    Io(String),
+}
+
+impl Error {
+    /// The D-Bus error name of a [`Error::Remote`].
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::Remote { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "system bus: {e}"),
+            Self::Remote { name, message } if message.is_empty() => f.write_str(name),
+            Self::Remote { name, message } => write!(f, "{name}: {message}"),
+            Self::Io(e) => write!(f, "D-Bus: {e}"),
+        }
+    }
+}
+
+/// One authenticated connection to the system bus.
+pub struct Connection {
+    stream: BufReader<UnixStream>,
+    serial: u32,
+}
+
+impl Connection {
+    /// Connect to the system bus and register with `Hello`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Connect`] when the socket is missing, authentication is
+    /// refused or the bus doesn't answer in time.
+    pub async fn system() -> Result<Self, Error> {
+        let path = std::env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .ok()
+            .and_then(|address| unix_path(&address))
+            .unwrap_or_else(|| DEFAULT_SYSTEM_BUS.to_string());
+        tokio::time::timeout(CONNECT_TIMEOUT, Self::open(&path))
+            .await
+            .map_err(|_| Error::Connect(format!("{path}: timed out")))?
+    }
+
+    #[cfg(windows)]
+    async fn open(path: &str) -> Result<Self, Error> {
+        Err(Error::Connect(format!(
+            "{path}: no D-Bus system bus on Windows"
+        )))
+    }
+
+    #[cfg(unix)]
+    async fn open(path: &str) -> Result<Self, Error> {
+        use std::fmt::Write as _;
+        use tokio::io::AsyncBufReadExt;
+
+        let connect = |e: std::io::Error| Error::Connect(format!("{path}: {e}"));
+        let stream = UnixStream::connect(path).await.map_err(connect)?;
+        let mut stream = BufReader::new(stream);
+        // SAFETY: getuid has no preconditions and can't fail.
+        let uid = unsafe { libc::getuid() };
+        let hex = uid.to_string().bytes().fold(String::new(), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        });
+        stream
+            .get_mut()
+            .write_all(format!("\0AUTH EXTERNAL {hex}\r\n").as_bytes())
+            .await
+            .map_err(connect)?;
+        let mut line = String::new();
+        stream.read_line(&mut line).await.map_err(connect)?;
+        if !line.starts_with("OK ") {
+            return Err(Error::Connect(format!(
+                "authentication refused: {}",
+                line.trim()
+            )));
+        }
+        stream
+            .get_mut()
+            .write_all(b"BEGIN\r\n")
+            .await
+            .map_err(connect)?;
+        let mut conn = Self { stream, serial: 0 };
+        conn.call_inner(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        )
+        .await
+        .map_err(|e| Error::Connect(e.to_string()))?;
+        Ok(conn)
+    }
+
+    /// Call `interface.member` on `path` at `destination` and return the
+    /// reply's arguments.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Remote`] for an error reply, [`Error::Io`] when the
+    /// connection fails or the reply doesn't come within 25 seconds.
+    pub async fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, Error> {
+        tokio::time::timeout(
+            CALL_TIMEOUT,
+            self.call_inner(destination, path, interface, member, args),
+        )
+        .await
+        .map_err(|_| Error::Io(format!("{interface}.{member} timed out")))?
+    }
+
+    /// `org.freedesktop.DBus.Properties.Get`, unwrapped.
+    ///
+    /// # Errors
+    ///
+    /// As [`call`](Self::call).
+    pub async fn property(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        name: &str,
+    ) -> Result<Value, Error> {
+        let args = [Value::Str(interface.into()), Value::Str(name.into())];
+        let reply = self
+            .call(destination, path, PROPERTIES, "Get", &args)
+            .await?;
+        Ok(reply
+            .into_iter()
+            .next()
+            .map_or(Value::Struct(Vec::new()), |v| v.inner().clone()))
+    }
+
+    /// `org.freedesktop.DBus.Properties.GetAll`, as a [`Value::Dict`].
+    ///
+    /// # Errors
+    ///
+    /// As [`call`](Self::call).
+    pub async fn properties(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+    ) -> Result<Value, Error> {
+        let args = [Value::Str(interface.into())];
+        let reply = self
+            .call(destination, path, PROPERTIES, "GetAll", &args)
+            .await?;
+        Ok(reply.into_iter().next().unwrap_or(Value::Dict(Vec::new())))
+    }
+
+    async fn call_inner(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, Error> {
+        self.serial = self.serial.wrapping_add(1).max(1);
+        let serial = self.serial;
+        let msg = method_call(serial, destination, path, interface, member, args)?;
+        let io = |e: std::io::Error| Error::Io(e.to_string());
+        self.stream.get_mut().write_all(&msg).await.map_err(io)?;
+        loop {
+            let mut fixed = [0u8; 16];
+            self.stream.read_exact(&mut fixed).await.map_err(io)?;
+            let total = message_len(&fixed).map_err(Error::Io)?;
+            let mut buf = fixed.to_vec();
+            buf.resize(total, 0);
+            self.stream.read_exact(&mut buf[16..]).await.map_err(io)?;
+            let reply = parse_message(&buf).map_err(Error::Io)?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            return match reply.kind {
+                METHOD_RETURN => Ok(reply.body),
+                ERROR => Err(Error::Remote {
+                    name: reply.error_name.unwrap_or_default(),
+                    message: reply
+                        .body
+                        .first()
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                }),
+                kind => Err(Error::Io(format!("unexpected message type {kind}"))),
+            };
+        }
+    }
+}
+
+/// The socket path from a bus address such as
+/// `unix:path=/run/dbus/system_bus_socket;tcp:...`.
+fn unix_path(address: &str) -> Option<String> {
+    address.split(';').find_map(|entry| {
+        let params = entry.strip_prefix("unix:")?;
+        let value = params.split(',').find_map(|p| p.strip_prefix("path="))?;
+        let mut out = Vec::with_capacity(value.len());
+        let mut bytes = value.bytes();
+        while let Some(b) = bytes.next() {
+            if b == b'%' {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            } else {
+                out.push(b);
+            }
+        }
+        String::from_utf8(out).ok()
+    })
+}
+
+// ─── Encoding ────────────────────────────────────────────────────────────────
+
+/// Little-endian marshalling of basic values.
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, n: usize) {
+        while self.buf.len() % n != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        #[allow(clippy::cast_possible_truncation)]
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        #[allow(clippy::cast_possible_truncation)]
+        self.buf.push(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(), Error> {
+        match value {
+            Value::Byte(v) => self.buf.push(*v),
+            Value::Bool(v) => self.u32(u32::from(*v)),
+            Value::I16(v) => {
+                self.align(2);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::U16(v) => {
+                self.align(2);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::I32(v) => {
+                self.align(4);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::U32(v) => self.u32(*v),
+            Value::I64(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::U64(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Double(v) => {
+                self.align(8);
+                self.buf.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Str(s) | Value::ObjectPath(s) => self.string(s),
+            Value::Signature(s) => self.signature(s),
+            Value::Variant(inner) => {
+                let sig = inner
+                    .signature()
+                    .ok_or_else(|| Error::Io("can't send a container".into()))?;
+                self.signature(sig);
+                self.value(inner)?;
+            }
+            Value::Array(items) if value.signature() == Some("as") => {
+                self.u32(0); // byte length, patched below
+                let start = self.buf.len();
+                for item in items {
+                    self.value(item)?;
+                }
+                #[allow(clippy::cast_possible_truncation)]
+                let len = (self.buf.len() - start) as u32;
+                self.buf[start - 4..start].copy_from_slice(&len.to_le_bytes());
+            }
+            Value::Array(_) | Value::Dict(_) | Value::Struct(_) => {
+                return Err(Error::Io("can't send a container".into()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn method_call(
+    serial: u32,
+    destination: &str,
+    path: &str,
+    interface: &str,
+    member: &str,
+    args: &[Value],
+) -> Result<Vec<u8>, Error> {
+    let mut body = Writer { buf: Vec::new() };
+    let mut signature = String::new();
+    for arg in args {
+        signature.push_str(
+            arg.signature()
+                .ok_or_else(|| Error::Io("can't send a container".into()))?,
+        );
+        body.value(arg)?;
+    }
+
+    let mut msg = Writer {
+        buf: vec![b'l', METHOD_CALL, 0, 1],
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    msg.u32(body.buf.len() as u32);
+    msg.u32(serial);
+    msg.u32(0); // header field array length, patched below
+    let fields_start = msg.buf.len();
+    let mut fields = vec![
+        (1, Value::ObjectPath(path.into())),
+        (2, Value::Str(interface.into())),
+        (3, Value::Str(member.into())),
+        (6, Value::Str(destination.into())),
+    ];
+    if !signature.is_empty() {
+        fields.push((8, Value::Signature(signature)));
+    }
+    for (code, value) in fields {
+        msg.align(8);
+        msg.buf.push(code);
+        msg.value(&Value::Variant(Box::new(value)))?;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let fields_len = (msg.buf.len() - fields_start) as u32;
+    msg.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+    msg.align(8);
+    msg.buf.extend_from_slice(&body.buf);
+    Ok(msg.buf)
+}
+
+// ─── Decoding ────────────────────────────────────────────────────────────────
+
+struct Message {
+    kind: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<Value>,
+}
+
+/// Total length of the message whose fixed 16-byte header is `fixed`.
+fn message_len(fixed: &[u8; 16]) -> Result<usize, String> {
+    let big = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        b => return Err(format!("bad endianness byte 0x{b:02x}")),
+    };
+    let word = |at: usize| {
+        let b = [fixed[at], fixed[at + 1], fixed[at + 2], fixed[at + 3]];
+        if big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    };
+    let body = word(4) as usize;
+    let fields = word(12) as usize;
+    let total = (16 + fields).next_multiple_of(8) + body;
+    if total > MAX_MESSAGE {
+        return Err(format!("message of {total} bytes is too large"));
+    }
+    Ok(total)
+}
+
+fn parse_message(buf: &[u8]) -> Result<Message, String> {
+    let mut r = Reader {
+        buf,
+        pos: 12,
+        big: buf.first() == Some(&b'B'),
+        depth: 0,
+    };
+    let fields = r.read(b"a(yv)")?;
+    let mut reply_serial = None;
+    let mut error_name = None;
+    let mut signature = String::new();
+    for field in fields.as_array() {
+        match field.as_array() {
+            [Value::Byte(4), v] => error_name = v.as_str().map(ToString::to_string),
+            [Value::Byte(5), v] => reply_serial = v.as_u64().and_then(|s| u32::try_from(s).ok()),
+            [Value::Byte(8), v] => signature = v.as_str().unwrap_or_default().to_string(),
+            _ => {}
+        }
+    }
+    r.align(8)?;
+    let mut body = Vec::new();
+    let mut sig = signature.as_bytes();
+    while !sig.is_empty() {
+        let len = type_len(sig)?;
+        body.push(r.read(&sig[..len])?);
+        sig = &sig[len..];
+    }
+    Ok(Message {
+        kind: buf[1],
+        reply_serial,
+        error_name,
+        body,
+    })
+}
+
+/// Length of the first complete type in `sig`.
+fn type_len(sig: &[u8]) -> Result<usize, String> {
+    let bad = || format!("bad signature '{}'", String::from_utf8_lossy(sig));
+    match sig.first() {
+        Some(b'a') => Ok(1 + type_len(&sig[1..]).map_err(|_| bad())?),
+        Some(&open @ (b'(' | b'{')) => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut i = 1;
+            while *sig.get(i).ok_or_else(bad)? != close {
+                i += type_len(&sig[i..]).map_err(|_| bad())?;
+            }
+            Ok(i + 1)
+        }
+        Some(
+            b'y' | b'b' | b'n' | b'q' | b'i' | b'u' | b'x' | b't' | b'd' | b's' | b'o' | b'g'
+            | b'h' | b'v',
+        ) => Ok(1),
+        _ => Err(bad()),
+    }
+}
+
+fn alignment(code: u8) -> usize {
+    match code {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'b' | b'i' | b'u' | b'h' | b's' | b'o' | b'a' => 4,
+        _ => 8,
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big: bool,
+    depth: u32,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, n: usize) -> Result<(), String> {
+        let pos = self.pos.next_multiple_of(n);
+        if pos > self.buf.len() {
+            return Err("truncated message".into());
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or("truncated message")?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        self.align(N)?;
+        let mut bytes: [u8; N] = self.take(N)?.try_into().expect("took N bytes");
+        if self.big {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.fixed::<4>().map(u32::from_le_bytes)
+    }
+
+    fn text(&mut self, len: usize) -> Result<String, String> {
+        let bytes = self.take(len)?.to_vec();
+        self.take(1)?;
+        String::from_utf8(bytes).map_err(|_| "string isn't UTF-8".to_string())
+    }
+
+    /// Read one value of the complete type `sig`.
+    fn read(&mut self, sig: &[u8]) -> Result<Value, String> {
+        Ok(match sig[0] {
+            b'y' => Value::Byte(self.take(1)?[0]),
+            b'b' => Value::Bool(self.u32()? != 0),
+            b'n' => Value::I16(i16::from_le_bytes(self.fixed()?)),
+            b'q' => Value::U16(u16::from_le_bytes(self.fixed()?)),
+            b'i' => Value::I32(i32::from_le_bytes(self.fixed()?)),
+            b'u' | b'h' => Value::U32(self.u32()?),
+            b'x' => Value::I64(i64::from_le_bytes(self.fixed()?)),
+            b't' => Value::U64(u64::from_le_bytes(self.fixed()?)),
+            b'd' => Value::Double(f64::from_le_bytes(self.fixed()?)),
+            b's' => {
+                let len = self.u32()? as usize;
+                Value::Str(self.text(len)?)
+            }
+            b'o' => {
+                let len = self.u32()? as usize;
+                Value::ObjectPath(self.text(len)?)
+            }
+            b'g' => {
+                let len = usize::from(self.take(1)?[0]);
+                Value::Signature(self.text(len)?)
+            }
+            b'v' => {
+                let len = usize::from(self.take(1)?[0]);
+                let inner = self.text(len)?;
+                if inner.is_empty() || type_len(inner.as_bytes())? != inner.len() {
+                    return Err(format!("bad variant signature '{inner}'"));
+                }
+                self.nested(|r| r.read(inner.as_bytes()))
+                    .map(|v| Value::Variant(Box::new(v)))?
+            }
+            b'a' => {
+                let len = self.u32()? as usize;
+                let elem = &sig[1..];
+                self.align(alignment(elem[0]))?;
+                let end = self
+                    .pos
+                    .checked_add(len)
+                    .filter(|&end| end <= self.buf.len())
+                    .ok_or("truncated array")?;
+                let value = self.nested(|r| {
+                    if elem[0] == b'{' {
+                        let key_len = type_len(&elem[1..])?;
+                        let (key, value) = (&elem[1..=key_len], &elem[1 + key_len..elem.len() - 1]);
+                        let mut entries = Vec::new();
+                        while r.pos < end {
+                            r.align(8)?;
+                            entries.push((r.read(key)?, r.read(value)?));
+                        }
+                        Ok(Value::Dict(entries))
+                    } else {
+                        let mut items = Vec::new();
+                        while r.pos < end {
+                            items.push(r.read(elem)?);
+                        }
+                        Ok(Value::Array(items))
+                    }
+                })?;
+                if self.pos != end {
+                    return Err("array length mismatch".into());
+                }
+                value
+            }
+            b'(' => {
+                self.align(8)?;
+                self.nested(|r| {
+                    let mut fields = Vec::new();
+                    let mut rest = &sig[1..sig.len() - 1];
+                    while !rest.is_empty() {
+                        let len = type_len(rest)?;
+                        fields.push(r.read(&rest[..len])?);
+                        rest = &rest[len..];
+                    }
+                    Ok(Value::Struct(fields))
+                })?
+            }
+            c => return Err(format!("unsupported type '{}'", char::from(c))),
+        })
+    }
+
+    fn nested(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<Value, String>,
+    ) -> Result<Value, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("nested too deeply".into());
+        }
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_reply_decodes_in_both_byte_orders() {
+        // GetSettings reply body `a{sa{sv}}`: {"connection": {"id": <"LTE">,
+        // "autoconnect": <false>}}, hand-encoded big-endian.
+        let mut body = Vec::new();
+        body.extend_from_slice(&[0, 0, 0, 0]); // outer array length, patched
+        body.extend_from_slice(&[0; 4]); // pad to 8
+        let start = body.len();
+        body.extend_from_slice(&10u32.to_be_bytes());
+        body.extend_from_slice(b"connection\0");
+        body.extend_from_slice(&[0; 1]); // pad to 4
+        body.extend_from_slice(&[0, 0, 0, 0]); // inner array length, patched
+        let inner_len_at = body.len() - 4;
+        body.extend_from_slice(&[0; 4]); // pad to 8
+        let inner_start = body.len();
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.extend_from_slice(b"id\0");
+        body.extend_from_slice(b"\x01s\0");
+        body.extend_from_slice(&[0; 2]);
+        body.extend_from_slice(&3u32.to_be_bytes());
+        body.extend_from_slice(b"LTE\0");
+        body.extend_from_slice(&[0; 4]); // pad to 8
+        body.extend_from_slice(&11u32.to_be_bytes());
+        body.extend_from_slice(b"autoconnect\0");
+        body.extend_from_slice(b"\x01b\0");
+        body.extend_from_slice(&[0; 1]);
+        body.extend_from_slice(&0u32.to_be_bytes());
+        let inner_len = u32::try_from(body.len() - inner_start).unwrap();
+        body[inner_len_at..inner_len_at + 4].copy_from_slice(&inner_len.to_be_bytes());
+        let outer_len = u32::try_from(body.len() - start).unwrap();
+        body[..4].copy_from_slice(&outer_len.to_be_bytes());
+
+        let mut msg = vec![b'B', METHOD_RETURN, 0, 1];
+        msg.extend_from_slice(&u32::try_from(body.len()).unwrap().to_be_bytes());
+        msg.extend_from_slice(&7u32.to_be_bytes());
+        // Fields: REPLY_SERIAL <u32 3>, SIGNATURE <"a{sa{sv}}">.
+        let mut fields = vec![5, 1, b'u', 0];
+        fields.extend_from_slice(&3u32.to_be_bytes());
+        fields.extend_from_slice(&[8, 1, b'g', 0, 9]);
+        fields.extend_from_slice(b"a{sa{sv}}\0");
+        msg.extend_from_slice(&u32::try_from(fields.len()).unwrap().to_be_bytes());
+        msg.extend_from_slice(&fields);
+        msg.resize(msg.len().next_multiple_of(8), 0);
+        msg.extend_from_slice(&body);
+
+        let fixed: [u8; 16] = msg[..16].try_into().unwrap();
+        assert_eq!(message_len(&fixed).unwrap(), msg.len());
+        let reply = parse_message(&msg).unwrap();
+        assert_eq!(reply.kind, METHOD_RETURN);
+        assert_eq!(reply.reply_serial, Some(3));
+        let connection = reply.body[0].get("connection").unwrap();
+        assert_eq!(connection.get("id").and_then(Value::as_str), Some("LTE"));
+        assert_eq!(
+            connection.get("autoconnect").and_then(Value::as_bool),
+            Some(false)
+        );
+        assert_eq!(
+            reply.body[0].to_json(),
+            json!({"connection": {"id": "LTE", "autoconnect": false}})
+        );
+    }
+
+    #[test]
+    fn method_call_round_trips_through_the_parser() {
+        let args = [
+            Value::Str("org.freedesktop.NetworkManager".into()),
+            Value::U32(9),
+            Value::Array(vec![Value::Str("a".into()), Value::Str("bc".into())]),
+        ];
+        let mut msg = method_call(
+            5,
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.DBus.Properties",
+            "Get",
+            &args,
+        )
+        .unwrap();
+        // Pretend it's a reply to serial 5 so the parser keeps it.
+        msg[1] = METHOD_RETURN;
+        let fixed: [u8; 16] = msg[..16].try_into().unwrap();
+        assert_eq!(message_len(&fixed).unwrap(), msg.len());
+        let parsed = parse_message(&msg).unwrap();
+        assert_eq!(parsed.body, args);
+        assert!(method_call(1, "d", "/", "i", "m", &[Value::Array(vec![Value::U32(1)])]).is_err());
+
+        assert_eq!(
+            unix_path("unix:path=/run/dbus/system%5fbus,guid=1;tcp:host=x").as_deref(),
+            Some("/run/dbus/system_bus")
+        );
+        assert_eq!(unix_path("unix:abstract=/tmp/x"), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Per-request deadlines.
+//!
+//! A client may send `X-Request-Deadline: <unix-ms>` to say when it will stop
+//! waiting. The [`propagate`] middleware turns that into a [`RequestDeadline`]
+//! whose cancellation token fires at the deadline, or as soon as the request
+//! finishes or is dropped (client disconnected). Handlers pick it up as an
+//! extractor and pass it down:
+//!
+//! - **exec** caps its `timeout_ms` at the time remaining and kills the
+//!   command's process group when cancelled.
+//! - **file reads** are abandoned at the deadline.
+//! - **gawdxfer chunk ops** check the token before touching disk and before
+//!   committing a chunk, so a late chunk is rejected (retryable) instead of
+//!   being written after the client has given up.
+//!
+//! Over the tunnel the relay sends `deadline_in_ms` (its proxy timeout, or less
+//! if the client sent a deadline) as a relative value, so relay/device clock
+//! skew doesn't matter.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{codes, ApiError};
+
+/// Header carrying the absolute deadline in Unix milliseconds.
+pub const HEADER: &str = "x-request-deadline";
+
+/// When a request must be done by, and a token cancelled when it is over.
+#[derive(Debug, Clone, Default)]
+pub struct RequestDeadline {
+    at: Option<Instant>,
+    token: CancellationToken,
+}
+
+impl RequestDeadline {
+    /// Parse [`HEADER`]. A missing or malformed header means no deadline.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let at = headers
+            .get(HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(|deadline_ms| {
+                let now_ms = crate::sessions::journal::now_ms();
+                Instant::now() + Duration::from_millis(deadline_ms.saturating_sub(now_ms))
+            });
+        Self {
+            at,
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// A deadline `ms` from now.
+    pub fn after_ms(ms: u64) -> Self {
+        Self {
+            at: Some(Instant::now() + Duration::from_millis(ms)),
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Time left, or `None` without a deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the deadline has passed or the request was cancelled.
+    pub fn is_expired(&self) -> bool {
+        self.token.is_cancelled() || self.at.is_some_and(|at| at <= Instant::now())
+    }
+
+    /// `timeout_ms` capped at the time remaining.
+    pub fn cap_timeout_ms(&self, timeout_ms: u64) -> u64 {
+        #[allow(clippy::cast_possible_truncation)]
+        self.remaining()
+            .map_or(timeout_ms, |r| timeout_ms.min(r.as_millis() as u64))
+    }
+
+    /// Cancelled at the deadline (once armed) or when the request ends.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Start the deadline timer. The returned guard cancels the token when
+    /// dropped, so hold it for the lifetime of the request.
+    pub fn arm(&self) -> DeadlineGuard {
+        let timer = self.at.map(|at| {
+            let token = self.token.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(at).await;
+                token.cancel();
+            })
+        });
+        DeadlineGuard {
+            token: self.token.clone(),
+            timer,
+        }
+    }
+
+    /// Run `fut` until it finishes or the token is cancelled. On cancellation
+    /// `fut` is dropped, which is what stops the work.
+    pub async fn run<F: std::future::Future>(&self, fut: F) -> Option<F::Output> {
+        tokio::select! {
+            out = fut => Some(out),
+            () = self.token.cancelled() => None,
+        }
+    }
+}
+
+/// Cancels a [`RequestDeadline`] and stops its timer on drop.
+pub struct DeadlineGuard {
+    token: CancellationToken,
+    timer: Option<JoinHandle<()>>,
+}
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+        self.token.cancel();
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestDeadline {
+    type Rejection = std::convert::Infallible;
+
+    /// The deadline armed by [`propagate`], or one parsed from the headers
+    /// (unarmed) for routes outside the middleware.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .unwrap_or_else(|| Self::from_headers(&parts.headers)))
+    }
+}
+
+/// Axum middleware that arms the request's deadline for its handler.
+///
+/// # Error responses
+///
+/// - `504 Gateway Timeout` with `{"code":"TIMEOUT"}` — the deadline had
+///   already passed when the request arrived
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let deadline = RequestDeadline::from_headers(request.headers());
+    if deadline.is_expired() {
+        return ApiError::new(codes::TIMEOUT, "Request deadline already passed")
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT)
+            .into_response();
+    }
+    let _guard = deadline.arm();
+    request.extensions_mut().insert(deadline);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_header(value: &str) -> RequestDeadline {
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER, value.parse().unwrap());
+        RequestDeadline::from_headers(&headers)
+    }
+
+    #[test]
+    fn header_sets_the_deadline() {
+        let none = RequestDeadline::from_headers(&HeaderMap::new());
+        assert!(none.remaining().is_none());
+        assert!(!none.is_expired());
+        assert!(with_header("soon").remaining().is_none());
+
+        let now_ms = crate::sessions::journal::now_ms();
+        let future = with_header(&format!(" {} ", now_ms + 60_000));
+        let remaining = future.remaining().unwrap();
+        assert!(remaining > Duration::from_secs(55) && remaining <= Duration::from_secs(60));
+        assert!(!future.is_expired());
+
+        let past = with_header(&(now_ms - 1000).to_string());
+        assert_eq!(past.remaining(), Some(Duration::ZERO));
+        assert!(past.is_expired());
+    }
+
+    #[test]
+    fn timeout_is_capped_at_the_time_remaining() {
+        assert_eq!(RequestDeadline::default().cap_timeout_ms(30_000), 30_000);
+        let deadline = RequestDeadline::after_ms(5_000);
+        assert!(deadline.cap_timeout_ms(30_000) <= 5_000);
+        assert!(deadline.cap_timeout_ms(30_000) > 4_000);
+        assert_eq!(deadline.cap_timeout_ms(1_000), 1_000);
+    }
+
+    #[tokio::test]
+    async fn armed_deadline_cancels_its_work() {
+        let deadline = RequestDeadline::after_ms(50);
+        let _guard = deadline.arm();
+        let out = deadline.run(std::future::pending::<()>()).await;
+        assert!(out.is_none());
+        assert!(deadline.is_expired());
+
+        let ended = RequestDeadline::default();
+        drop(ended.arm());
+        assert!(ended.token().is_cancelled());
+    }
+}
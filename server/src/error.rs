@@ -69,6 +69,7 @@ impl IntoResponse for ApiError {
 pub mod codes {
     pub const AUTH_MISSING_TOKEN: &str = "AUTH_MISSING_TOKEN";
     pub const AUTH_INVALID_TOKEN: &str = "AUTH_INVALID_TOKEN";
+    pub const AUTH_INSUFFICIENT_SCOPE: &str = "AUTH_INSUFFICIENT_SCOPE";
     pub const INVALID_REQUEST: &str = "INVALID_REQUEST";
     pub const INVALID_PATH: &str = "INVALID_PATH";
     pub const INVALID_MODE: &str = "INVALID_MODE";
@@ -81,13 +82,45 @@ pub mod codes {
     pub const PERMISSION_DENIED: &str = "PERMISSION_DENIED";
     pub const IO_ERROR: &str = "IO_ERROR";
     pub const SESSION_NOT_FOUND: &str = "SESSION_NOT_FOUND";
+    pub const SESSION_LIMIT: &str = "SESSION_LIMIT";
     pub const EXEC_FAILED: &str = "EXEC_FAILED";
     pub const TIMEOUT: &str = "TIMEOUT";
     pub const BATCH_TOO_LARGE: &str = "BATCH_TOO_LARGE";
     pub const MULTIPART_ERROR: &str = "MULTIPART_ERROR";
     pub const AI_NOT_ALLOWED: &str = "AI_NOT_ALLOWED";
+    pub const SCREEN_UNAVAILABLE: &str = "SCREEN_UNAVAILABLE";
     pub const MODEM_UNAVAILABLE: &str = "MODEM_UNAVAILABLE";
     pub const MODEM_AT_FAILED: &str = "MODEM_AT_FAILED";
+    pub const SMS_FAILED: &str = "SMS_FAILED";
     pub const TUNNEL_CONNECTED: &str = "TUNNEL_CONNECTED";
     pub const SCAN_RUNNING: &str = "SCAN_RUNNING";
+    pub const APPLY_PENDING: &str = "APPLY_PENDING";
+    pub const TUNNEL_DISCONNECTED: &str = "TUNNEL_DISCONNECTED";
+    pub const SHARE_UNAVAILABLE: &str = "SHARE_UNAVAILABLE";
+    pub const BUNDLE_RUNNING: &str = "BUNDLE_RUNNING";
+    pub const XATTR_UNSUPPORTED: &str = "XATTR_UNSUPPORTED";
+    pub const FILE_EXISTS: &str = "FILE_EXISTS";
+    pub const AI_DISABLED: &str = "AI_DISABLED";
+    pub const AI_BUDGET_EXCEEDED: &str = "AI_BUDGET_EXCEEDED";
+    pub const PLUGIN_FAILED: &str = "PLUGIN_FAILED";
+    pub const HOOK_DENIED: &str = "HOOK_DENIED";
+    pub const HOOK_FAILED: &str = "HOOK_FAILED";
+    pub const POLICY_DENIED: &str = "POLICY_DENIED";
+    pub const FETCH_NOT_ALLOWED: &str = "FETCH_NOT_ALLOWED";
+    pub const FETCH_FAILED: &str = "FETCH_FAILED";
+    pub const FORWARD_NOT_ALLOWED: &str = "FORWARD_NOT_ALLOWED";
+    pub const FORWARD_FAILED: &str = "FORWARD_FAILED";
+    pub const PUSH_FAILED: &str = "PUSH_FAILED";
+    pub const NETMAN_UNAVAILABLE: &str = "NETMAN_UNAVAILABLE";
+    pub const NETMAN_FAILED: &str = "NETMAN_FAILED";
+    pub const SERVICE_FAILED: &str = "SERVICE_FAILED";
+    pub const SERVICES_UNAVAILABLE: &str = "SERVICES_UNAVAILABLE";
+    pub const SERVER_BUSY: &str = "SERVER_BUSY";
+    pub const ATTESTATION_UNAVAILABLE: &str = "ATTESTATION_UNAVAILABLE";
+    pub const ATTESTATION_FAILED: &str = "ATTESTATION_FAILED";
+    pub const FIRMWARE_UNAVAILABLE: &str = "FIRMWARE_UNAVAILABLE";
+    pub const FIRMWARE_BUSY: &str = "FIRMWARE_BUSY";
+    pub const FIRMWARE_FAILED: &str = "FIRMWARE_FAILED";
+    pub const DUPLICATE_IN_FLIGHT: &str = "DUPLICATE_IN_FLIGHT";
+    pub const DUPLICATE_REQUEST: &str = "DUPLICATE_REQUEST";
 }
@@ -0,0 +1,320 @@
+//! On-disk exec results.
+//!
+//! [`crate::activity::ExecResultsCache`] keeps the last
+//! `exec_result_cache_size` results in memory, so `GET /api/activity/{id}/result`
+//! forgets them on eviction and on restart, and one large build log can take
+//! megabytes of RAM. With `[exec_results]` configured, every result is also
+//! written to [`ExecSpill`] and reads that miss the memory cache are answered
+//! from disk.
+//!
+//! ## Design
+//!
+//! - **Files**: one `<dir>/<activity_id>.json` per result, written to a
+//!   temporary name and renamed so a power cut never leaves half a file.
+//! - **Memory**: results whose stdout plus stderr exceeds `memory_bytes` are
+//!   only kept on disk.
+//! - **Caps**: stdout and stderr are cut to `max_result_bytes` between them
+//!   (the result is marked `truncated`). After each write,
+//!   results older than `max_age_hours` are deleted, then the oldest until the
+//!   total fits in `max_bytes`.
+//! - **Ids**: files are keyed by activity id. At startup, files with an id
+//!   above the activity log's last id are deleted, since without
+//!   `[activity_journal]` ids start again from 1 and the old files would
+//!   answer for new entries.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::activity::CachedExecResult;
+use crate::config::ExecResultsConfig;
+use crate::infra::now_epoch;
+
+/// A result file on disk.
+#[derive(Debug, Clone, Copy)]
+struct FileInfo {
+    bytes: u64,
+    /// Unix seconds when written.
+    written: u64,
+}
+
+/// Result files shared by the exec results cache.
+pub struct ExecSpill {
+    config: ExecResultsConfig,
+    dir: PathBuf,
+    index: Mutex<BTreeMap<u64, FileInfo>>,
+}
+
+impl ExecSpill {
+    /// Open the result directory, dropping files that are expired or newer
+    /// than `last_activity_id`.
+    pub fn open(config: ExecResultsConfig, data_dir: &str, last_activity_id: u64) -> Arc<Self> {
+        let dir = config
+            .dir
+            .as_ref()
+            .map_or_else(|| Path::new(data_dir).join("exec-results"), PathBuf::from);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("Exec results: cannot create {}: {e}", dir.display());
+        }
+        let mut index = BTreeMap::new();
+        let mut stale = 0usize;
+        for (id, path, info) in list_results(&dir) {
+            if id > last_activity_id {
+                let _ = std::fs::remove_file(&path);
+                stale += 1;
+            } else {
+                index.insert(id, info);
+            }
+        }
+        let spill = Self {
+            config,
+            dir,
+            index: Mutex::new(index),
+        };
+        for path in spill.prune(now_epoch()) {
+            let _ = std::fs::remove_file(path);
+        }
+        let kept = spill.lock().len();
+        info!(
+            "Exec results: {kept} on disk in {}{}",
+            spill.dir.display(),
+            if stale > 0 {
+                format!(", {stale} from an earlier id sequence removed")
+            } else {
+                String::new()
+            }
+        );
+        Arc::new(spill)
+    }
+
+    /// Whether `result` is small enough to also keep in memory.
+    pub fn keeps_in_memory(&self, result: &CachedExecResult) -> bool {
+        (result.stdout.len() + result.stderr.len()) as u64 <= self.config.memory_bytes
+    }
+
+    /// Write `result` to disk, then apply the retention caps.
+    pub async fn write(&self, result: &CachedExecResult) {
+        let id = result.activity_id;
+        let mut capped = result.clone();
+        cap_output(&mut capped, self.config.max_result_bytes);
+        let body = match serde_json::to_vec(&capped) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Exec results: cannot serialize result {id}: {e}");
+                return;
+            }
+        };
+        let bytes = body.len() as u64;
+        let path = self.path(id);
+        let written = crate::io_pool::run(move || {
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, body)?;
+            std::fs::rename(&tmp, &path)
+        })
+        .await;
+        if let Err(e) = written {
+            warn!("Exec results: cannot write result {id}: {e}");
+            return;
+        }
+        let now = now_epoch();
+        self.lock().insert(
+            id,
+            FileInfo {
+                bytes,
+                written: now,
+            },
+        );
+        let victims = self.prune(now);
+        if !victims.is_empty() {
+            let _ = crate::io_pool::run(move || {
+                for path in victims {
+                    let _ = std::fs::remove_file(path);
+                }
+                Ok(())
+            })
+            .await;
+        }
+    }
+
+    /// Read the result for `activity_id`, if it is on disk and not expired.
+    pub async fn read(&self, activity_id: u64) -> Option<CachedExecResult> {
+        let info = *self.lock().get(&activity_id)?;
+        if now_epoch().saturating_sub(info.written) > self.max_age().as_secs() {
+            return None;
+        }
+        let path = self.path(activity_id);
+        let body = crate::io_pool::run(move || std::fs::read(path))
+            .await
+            .ok()?;
+        match serde_json::from_slice(&body) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                warn!("Exec results: result {activity_id} is unreadable: {e}");
+                None
+            }
+        }
+    }
+
+    /// Directory, result count and total size, for `GET /api/health`.
+    pub fn status(&self) -> Value {
+        let index = self.lock();
+        json!({
+            "dir": self.dir.to_string_lossy(),
+            "results": index.len(),
+            "bytes": index.values().map(|i| i.bytes).sum::<u64>(),
+        })
+    }
+
+    /// Drop expired results, then the oldest while over `max_bytes`, from the
+    /// index. Returns the files to delete.
+    fn prune(&self, now: u64) -> Vec<PathBuf> {
+        let max_age = self.max_age().as_secs();
+        let mut index = self.lock();
+        let mut victims: Vec<u64> = index
+            .iter()
+            .filter(|(_, info)| now.saturating_sub(info.written) > max_age)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &victims {
+            index.remove(id);
+        }
+        let mut total: u64 = index.values().map(|i| i.bytes).sum();
+        while total > self.config.max_bytes {
+            let Some((id, info)) = index.pop_first() else {
+                break;
+            };
+            total -= info.bytes;
+            victims.push(id);
+        }
+        victims.into_iter().map(|id| self.path(id)).collect()
+    }
+
+    fn max_age(&self) -> Duration {
+        Duration::from_secs(self.config.max_age_hours.saturating_mul(3600))
+    }
+
+    fn path(&self, activity_id: u64) -> PathBuf {
+        self.dir.join(format!("{activity_id}.json"))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, FileInfo>> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `<id>.json` files in `dir` with their size and modification time.
+fn list_results(dir: &Path) -> Vec<(u64, PathBuf, FileInfo)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let id = name.strip_suffix(".json")?.parse().ok()?;
+            let meta = entry.metadata().ok()?;
+            let written = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            Some((
+                id,
+                entry.path(),
+                FileInfo {
+                    bytes: meta.len(),
+                    written,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Cut stdout and stderr so together they fit in `max` bytes. Stderr keeps
+/// up to half; stdout gets the rest.
+fn cap_output(result: &mut CachedExecResult, max: u64) {
+    let max = usize::try_from(max).unwrap_or(usize::MAX);
+    if result.stdout.len() + result.stderr.len() <= max {
+        return;
+    }
+    let stderr_keep = result.stderr.len().min(max / 2);
+    let stdout_keep = max - stderr_keep;
+    truncate_at_char(&mut result.stderr, stderr_keep);
+    truncate_at_char(&mut result.stdout, stdout_keep);
+    result.truncated = true;
+}
+
+fn truncate_at_char(s: &mut String, max: usize) {
+    if s.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(id: u64, stdout: &str) -> CachedExecResult {
+        CachedExecResult {
+            activity_id: id,
+            exit_code: 0,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            duration_ms: 5,
+            command: "echo".to_string(),
+            status: "ok".to_string(),
+            error_message: None,
+            summary: None,
+            truncated: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn results_survive_reopen_and_respect_caps() {
+        let dir = std::env::temp_dir().join(format!("sctl-exec-spill-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ExecResultsConfig {
+            dir: Some(dir.to_string_lossy().into_owned()),
+            memory_bytes: 4,
+            max_result_bytes: 200,
+            max_bytes: 700,
+            max_age_hours: 1,
+        };
+
+        let spill = ExecSpill::open(config.clone(), "/unused", 0);
+        spill.write(&result(1, "small")).await;
+        spill.write(&result(2, &"x".repeat(1000))).await;
+        assert!(!spill.keeps_in_memory(&result(3, "large")));
+
+        let big = spill.read(2).await.unwrap();
+        assert!(big.truncated);
+        assert_eq!(big.stdout.len(), 200);
+
+        // Reopened with the same ids: results are read back.
+        let spill = ExecSpill::open(config.clone(), "/unused", 2);
+        assert_eq!(spill.read(1).await.unwrap().stdout, "small");
+
+        // Oldest results go once the total passes max_bytes.
+        for id in 3..=6 {
+            spill.write(&result(id, &"y".repeat(150))).await;
+        }
+        assert!(spill.read(1).await.is_none());
+        assert!(spill.read(6).await.is_some());
+        assert!(spill.status()["bytes"].as_u64().unwrap() <= 700);
+
+        // Ids started over: the old files no longer belong to anything.
+        let spill = ExecSpill::open(config, "/unused", 0);
+        assert_eq!(spill.status()["results"], 0);
+        assert!(spill.read(6).await.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,160 @@
+//! Extension points for crates that embed sctl.
+//!
+//! Downstream servers add their own REST routes and WebSocket/tunnel
+//! message types by implementing [`ServerExtension`] and registering it in
+//! [`Extensions`] before building [`AppState`]. This keeps the built-in
+//! `match` tables in `ws` and `tunnel::client` closed: they consult the
+//! registry only for types they don't handle themselves, so an extension
+//! can add types but never shadow a built-in one.
+//!
+//! A message handler sees the raw JSON message and returns the same result
+//! type as a REST handler. Both transports reply with
+//! `{"type": "<type>.result", "request_id", "status", "body"}` — the shape
+//! tunnel clients already use for proxied REST calls, which the relay
+//! routes back to the requesting client without any extra allowlisting.
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::{Json, Router};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::error::{codes, ApiError};
+use crate::state::AppState;
+
+/// What an extension message handler returns.
+pub type MessageResult = Result<Json<Value>, (StatusCode, Json<ApiError>)>;
+
+/// A bundle of routes and message handlers contributed by an embedder.
+pub trait ServerExtension: Send + Sync + 'static {
+    /// Short name, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Routes merged into the authenticated API. They get the same API-key
+    /// check and request deadline as the built-in routes. Paths must not
+    /// collide with built-in or other extensions' routes (axum panics on
+    /// overlap at startup).
+    fn routes(&self) -> Router<AppState> {
+        Router::new()
+    }
+
+    /// Message types handled by [`handle_message`](Self::handle_message)
+    /// on `/api/ws` and the tunnel.
+    fn message_types(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Handle one message whose `type` is listed in
+    /// [`message_types`](Self::message_types). Route-only extensions can
+    /// leave the default, which answers 404.
+    fn handle_message<'a>(
+        &'a self,
+        state: &'a AppState,
+        msg: &'a Value,
+    ) -> BoxFuture<'a, MessageResult> {
+        let _ = state;
+        let msg_type = msg["type"].as_str().unwrap_or("").to_string();
+        Box::pin(async move {
+            Err(ApiError::new(
+                codes::NOT_FOUND,
+                format!("No handler for message type: {msg_type}"),
+            )
+            .into_response_with(StatusCode::NOT_FOUND))
+        })
+    }
+}
+
+/// The registered extensions, in registration order.
+#[derive(Default)]
+pub struct Extensions {
+    registered: Vec<Arc<dyn ServerExtension>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an extension. A message type already claimed by an earlier
+    /// extension stays with that extension.
+    #[must_use]
+    pub fn with(mut self, ext: impl ServerExtension) -> Self {
+        for t in ext.message_types() {
+            if let Some(owner) = self.handler(t) {
+                warn!(
+                    msg_type = t,
+                    extension = ext.name(),
+                    owner = owner.name(),
+                    "Extension message type already registered, ignoring"
+                );
+            }
+        }
+        self.registered.push(Arc::new(ext));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registered.is_empty()
+    }
+
+    /// All extension routes merged into one router.
+    pub fn routes(&self) -> Router<AppState> {
+        self.registered
+            .iter()
+            .fold(Router::new(), |router, ext| router.merge(ext.routes()))
+    }
+
+    /// The extension that handles `msg_type`, if any.
+    pub fn handler(&self, msg_type: &str) -> Option<&Arc<dyn ServerExtension>> {
+        self.registered
+            .iter()
+            .find(|ext| ext.message_types().contains(&msg_type))
+    }
+
+    /// Run the handler for `msg` and build its `<type>.result` reply, or
+    /// return `None` when no extension handles the message's type.
+    pub async fn dispatch(&self, state: &AppState, msg: &Value) -> Option<Value> {
+        let msg_type = msg["type"].as_str()?;
+        let ext = self.handler(msg_type)?;
+        let (status, body) = match ext.handle_message(state, msg).await {
+            Ok(Json(body)) => (200, body),
+            Err((status, Json(err))) => (status.as_u16(), json!(err)),
+        };
+        Some(json!({
+            "type": format!("{msg_type}.result"),
+            "request_id": msg["request_id"].clone(),
+            "status": status,
+            "body": body,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str, &'static [&'static str]);
+
+    impl ServerExtension for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn message_types(&self) -> &[&'static str] {
+            self.1
+        }
+    }
+
+    #[test]
+    fn first_registration_owns_message_type() {
+        let exts = Extensions::new()
+            .with(Named("a", &["net.scan", "net.ping"]))
+            .with(Named("b", &["net.ping", "net.trace"]));
+        assert_eq!(exts.handler("net.scan").unwrap().name(), "a");
+        assert_eq!(exts.handler("net.ping").unwrap().name(), "a");
+        assert_eq!(exts.handler("net.trace").unwrap().name(), "b");
+        assert!(exts.handler("session.start").is_none());
+    }
+}
@@ -0,0 +1,587 @@
+//! Outbound HTTP(S) requests made from the device.
+//!
+//! `POST /api/fetch` (see [`crate::routes::fetch`]) lets a client pull
+//! artifacts from hosts only the device can reach — a registry on the site
+//! LAN, a vendor mirror behind the device's VPN. Every URL, including each
+//! redirect, must match a `[fetch] allow` pattern `scheme://host[:port]/path`.
+//! Scheme and port match exactly (default ports implied); the host matches
+//! exactly or, for a leading `*.`, any one label in its place. The path is a
+//! glob whose `*`, `?` and `[...]` stay within a segment and whose `**`
+//! segment spans any number of them; the query is ignored. URLs with
+//! credentials (`user@host`) or `.`/`..` path segments are refused outright.
+//!
+//! Requests are HTTP/1.1 over a fresh connection, TLS through whichever
+//! backend the build has (`native-tls` or `rustls`).
+
+use std::collections::HashMap;
+use std::ffi::CString;
+
+use axum::body::Bytes;
+use http_body_util::Full;
+use hyper::body::{Body, Incoming};
+use hyper::{Method, Uri};
+use hyper_util::rt::TokioIo;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::config::FetchConfig;
+
+/// Why a fetch didn't produce a response.
+#[derive(Debug)]
+pub enum FetchError {
+    /// Malformed URL, or a method or header that can't be sent.
+    Invalid(String),
+    /// The URL (or a redirect target) isn't in `[fetch] allow`.
+    NotAllowed(String),
+    /// Connecting, TLS, or the HTTP exchange failed.
+    Failed(String),
+}
+
+/// A parsed, allowlist-checkable URL.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub uri: Uri,
+    https: bool,
+    host: String,
+    port: u16,
+}
+
+impl Target {
+    /// Parse an absolute `http`/`https` URL without credentials.
+    ///
+    /// # Errors
+    ///
+    /// Relative or malformed URLs, other schemes, and `user@host`.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let uri: Uri = url.parse().map_err(|e| format!("invalid url: {e}"))?;
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err("url must be http:// or https://".to_string()),
+        };
+        let authority = uri.authority().ok_or("url has no host")?;
+        if authority.as_str().contains('@') {
+            return Err("url must not contain credentials".to_string());
+        }
+        let host = authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        if host.is_empty() {
+            return Err("url has no host".to_string());
+        }
+        if uri.path().split('/').any(is_dot_segment) {
+            return Err("url path must not contain '.' or '..' segments".to_string());
+        }
+        let port = authority.port_u16().unwrap_or(if https { 443 } else { 80 });
+        Ok(Self {
+            uri,
+            https,
+            host,
+            port,
+        })
+    }
+
+    /// `host` or `host:port` when the port isn't the scheme's default.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == if self.https { 443 } else { 80 } {
+            host
+        } else {
+            format!("{host}:{}", self.port)
+        }
+    }
+
+    /// `scheme://host[:port]/path`, as `[fetch] allow` patterns are written.
+    pub fn match_key(&self) -> String {
+        let scheme = if self.https { "https" } else { "http" };
+        format!("{scheme}://{}{}", self.authority(), self.uri.path())
+    }
+
+    /// Whether a `[fetch] allow` pattern matches.
+    pub fn allowed(&self, config: &FetchConfig) -> bool {
+        config.allow.iter().any(|pattern| {
+            AllowPattern::parse(pattern).is_ok_and(|p| {
+                p.https == self.https
+                    && p.port == self.port
+                    && host_matches(p.host, &self.host)
+                    && path_matches(p.path, self.uri.path())
+            })
+        })
+    }
+
+    /// Same scheme, host and port.
+    fn same_origin(&self, other: &Self) -> bool {
+        self.https == other.https && self.host == other.host && self.port == other.port
+    }
+
+    /// `location` resolved against this URL.
+    fn join(&self, location: &str) -> Result<Self, String> {
+        if location.contains("://") {
+            return Self::parse(location);
+        }
+        let scheme = if self.https { "https" } else { "http" };
+        let base = format!("{scheme}://{}", self.authority());
+        if let Some(rest) = location.strip_prefix("//") {
+            return Self::parse(&format!("{scheme}://{rest}"));
+        }
+        if location.starts_with('/') {
+            return Self::parse(&format!("{base}{location}"));
+        }
+        let path = self.uri.path();
+        let dir = &path[..=path.rfind('/').unwrap_or(0)];
+        Self::parse(&format!("{base}{dir}{location}"))
+    }
+}
+
+/// A `[fetch] allow` entry split into the parts matched separately.
+struct AllowPattern<'a> {
+    https: bool,
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+impl<'a> AllowPattern<'a> {
+    fn parse(pattern: &'a str) -> Result<Self, String> {
+        let (https, rest) = if let Some(rest) = pattern.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = pattern.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err("must start with http:// or https://".to_string());
+        };
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            let (host, after) = v6.split_once(']').ok_or("unclosed '[' in host")?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| format!("invalid port '{port}'"))?,
+            None if https => 443,
+            None => 80,
+        };
+        let name = host.strip_prefix("*.").unwrap_or(host);
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':'))
+        {
+            return Err(format!(
+                "host '{host}' must be a name or address, optionally with a leading '*.'"
+            ));
+        }
+        Ok(Self {
+            https,
+            host,
+            port,
+            path,
+        })
+    }
+}
+
+/// Check a `[fetch] allow` entry at config load.
+///
+/// # Errors
+///
+/// Says what is wrong with the pattern.
+pub fn validate_allow(pattern: &str) -> Result<(), String> {
+    AllowPattern::parse(pattern).map(|_| ())
+}
+
+/// Exact (case-insensitive) host, or `*.suffix` standing for one label.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let Some(suffix) = pattern.strip_prefix("*.") else {
+        return pattern.eq_ignore_ascii_case(host);
+    };
+    host.len() > suffix.len() + 1
+        && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+        && host[..host.len() - suffix.len()]
+            .strip_suffix('.')
+            .is_some_and(|label| !label.contains('.'))
+}
+
+/// Glob `path` segment by segment; a `**` segment spans zero or more.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    segments_match(&pattern, &path)
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| segments_match(rest, &path[i..])),
+        Some((glob, rest)) => path.split_first().is_some_and(|(segment, tail)| {
+            segment_matches(glob, segment) && segments_match(rest, tail)
+        }),
+    }
+}
+
+/// Segments hold no `/`, so this is `FNM_PATHNAME` matching of the path.
+fn segment_matches(pattern: &str, segment: &str) -> bool {
+    let (Ok(pattern), Ok(segment)) = (CString::new(pattern), CString::new(segment)) else {
+        return false;
+    };
+    crate::shell::classify::glob(&pattern, &segment)
+}
+
+/// `.` or `..`, percent-encoded or not.
+fn is_dot_segment(segment: &str) -> bool {
+    matches!(
+        segment.to_ascii_lowercase().as_str(),
+        "." | ".." | "%2e" | "%2e%2e" | ".%2e" | "%2e."
+    )
+}
+
+/// Headers describing the connection or the body's framing. The client sets
+/// these itself; a caller's copy could make the server read the body
+/// differently from how it is sent.
+const RESERVED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A response whose body hasn't been read yet.
+pub struct Fetched {
+    /// The URL that answered, after redirects.
+    pub url: String,
+    pub status: u16,
+    /// Header names (lowercase) to values; repeated headers are joined
+    /// with `, `.
+    pub headers: Map<String, Value>,
+    pub content_length: Option<u64>,
+    pub body: Incoming,
+}
+
+/// Send `method url` with `headers` and `body`, following up to
+/// `max_redirects` redirects that also pass the allowlist. `Authorization` is
+/// only sent on to the same origin. The caller bounds the whole exchange with
+/// its timeout.
+///
+/// # Errors
+///
+/// See [`FetchError`].
+pub async fn send(
+    config: &FetchConfig,
+    method: &str,
+    url: &str,
+    headers: &HashMap<String, String>,
+    body: Option<Bytes>,
+) -> Result<Fetched, FetchError> {
+    let mut method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| FetchError::Invalid(format!("invalid method '{method}'")))?;
+    let mut target = Target::parse(url).map_err(FetchError::Invalid)?;
+    if let Some(name) = headers
+        .keys()
+        .find(|k| RESERVED_HEADERS.contains(&k.to_ascii_lowercase().as_str()))
+    {
+        return Err(FetchError::Invalid(format!(
+            "header '{name}' is set by the connection and can't be sent"
+        )));
+    }
+    let mut body = body;
+    let mut headers = headers.clone();
+    let mut redirects = 0;
+    loop {
+        if !target.allowed(config) {
+            return Err(FetchError::NotAllowed(format!(
+                "{} is not in [fetch] allow",
+                target.match_key()
+            )));
+        }
+        let response = send_once(&target, &method, &headers, body.clone()).await?;
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(hyper::header::LOCATION)
+            .and_then(|v| v.to_str().ok());
+        if let (true, Some(location)) = (status.is_redirection(), location) {
+            if redirects >= config.max_redirects {
+                return Err(FetchError::Failed(format!(
+                    "more than {} redirects",
+                    config.max_redirects
+                )));
+            }
+            redirects += 1;
+            let next = target.join(location).map_err(FetchError::Failed)?;
+            if !next.same_origin(&target) {
+                headers.retain(|k, _| !k.eq_ignore_ascii_case("authorization"));
+            }
+            // 301/302/303 turn into a body-less GET, as browsers do.
+            if !matches!(status.as_u16(), 307 | 308) && method != Method::HEAD {
+                method = Method::GET;
+                body = None;
+            }
+            target = next;
+            continue;
+        }
+
+        let mut out = Map::new();
+        for (name, value) in response.headers() {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            match out.get_mut(name.as_str()) {
+                Some(Value::String(existing)) => {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                }
+                _ => {
+                    out.insert(name.as_str().to_string(), Value::String(value));
+                }
+            }
+        }
+        let content_length = response
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        return Ok(Fetched {
+            url: target.uri.to_string(),
+            status: status.as_u16(),
+            headers: out,
+            content_length,
+            body: response.into_body(),
+        });
+    }
+}
+
+/// One request over a new connection.
+async fn send_once(
+    target: &Target,
+    method: &Method,
+    headers: &HashMap<String, String>,
+    body: Option<Bytes>,
+) -> Result<hyper::Response<Incoming>, FetchError> {
+    let path = target
+        .uri
+        .path_and_query()
+        .map_or("/", hyper::http::uri::PathAndQuery::as_str);
+    let mut builder = hyper::Request::builder()
+        .method(method.clone())
+        .uri(path)
+        .header(hyper::header::HOST, target.authority())
+        .header(
+            hyper::header::USER_AGENT,
+            format!("sctl/{}", crate::VERSION),
+        );
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let request = builder
+        .body(Full::new(body.unwrap_or_default()))
+        .map_err(|e| FetchError::Invalid(format!("invalid request: {e}")))?;
+    request_once(target, request).await
+}
+
+/// Send a prepared `request` to `target` over a new connection. The request
+/// carries its own `Host` header; no allowlist check is made.
+///
+/// # Errors
+///
+/// [`FetchError::Failed`] when connecting, TLS or the exchange fails.
+pub async fn request_once<B>(
+    target: &Target,
+    request: hyper::Request<B>,
+) -> Result<hyper::Response<Incoming>, FetchError>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let stream = TcpStream::connect((target.host.as_str(), target.port))
+        .await
+        .map_err(|e| FetchError::Failed(format!("connect {}: {e}", target.authority())))?;
+    if target.https {
+        let tls = tls_connect(&target.host, stream)
+            .await
+            .map_err(FetchError::Failed)?;
+        exchange(tls, request).await
+    } else {
+        exchange(stream, request).await
+    }
+}
+
+async fn exchange<S, B>(
+    io: S,
+    request: hyper::Request<B>,
+) -> Result<hyper::Response<Incoming>, FetchError>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(io))
+        .await
+        .map_err(|e| FetchError::Failed(format!("http handshake: {e}")))?;
+    tokio::spawn(async move {
+        let _ = conn.await;
+    });
+    sender
+        .send_request(request)
+        .await
+        .map_err(|e| FetchError::Failed(format!("request failed: {e}")))
+}
+
+/// Wrap `stream` in TLS, verifying the certificate for `host`.
+#[cfg(feature = "native-tls")]
+pub(crate) async fn tls_connect(
+    host: &str,
+    stream: TcpStream,
+) -> Result<tokio_native_tls::TlsStream<TcpStream>, String> {
+    let connector =
+        tokio_native_tls::native_tls::TlsConnector::new().map_err(|e| format!("tls init: {e}"))?;
+    tokio_native_tls::TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| format!("tls handshake: {e}"))
+}
+
+/// Wrap `stream` in TLS, verifying the certificate for `host` against the
+/// bundled webpki roots.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+pub(crate) async fn tls_connect(
+    host: &str,
+    stream: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>, String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .map_err(|e| format!("tls init: {e}"))?;
+    tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| format!("tls handshake: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_matches_normalized_urls_only() {
+        let config = FetchConfig {
+            allow: vec![
+                "https://registry.example.com/**".into(),
+                "http://10.0.0.5:8080/artifacts/*".into(),
+            ],
+            ..FetchConfig::default()
+        };
+        let allowed = |url: &str| Target::parse(url).is_ok_and(|t| t.allowed(&config));
+        assert!(allowed(
+            "https://registry.example.com/v2/app/blobs/sha256:ab?x=1"
+        ));
+        assert!(allowed("https://registry.example.com:443/v2/"));
+        assert!(allowed("http://10.0.0.5:8080/artifacts/fw.bin"));
+        assert!(!allowed("http://registry.example.com/v2/"));
+        assert!(!allowed("https://registry.example.com.evil.net/v2/"));
+        assert!(!allowed("http://10.0.0.5/artifacts/fw.bin"));
+        assert!(Target::parse("https://registry.example.com@evil.net/").is_err());
+        assert!(Target::parse("ftp://registry.example.com/").is_err());
+        assert!(!allowed("http://10.0.0.5:8080/artifacts/sub/fw.bin"));
+
+        let base = Target::parse("https://registry.example.com/v2/app/manifest").unwrap();
+        let join = |loc: &str| base.join(loc).unwrap().uri.to_string();
+        assert_eq!(
+            join("blob?x=1"),
+            "https://registry.example.com/v2/app/blob?x=1"
+        );
+        assert_eq!(join("/other"), "https://registry.example.com/other");
+        assert_eq!(join("//cdn.example.com/b"), "https://cdn.example.com/b");
+        assert_eq!(
+            join("http://x.example.com:81/a"),
+            "http://x.example.com:81/a"
+        );
+    }
+
+    #[test]
+    fn allowlist_wildcards_stay_in_their_part() {
+        let config = FetchConfig {
+            allow: vec![
+                "https://*.example.com/**".into(),
+                "https://host.example.net/fw/*".into(),
+            ],
+            ..FetchConfig::default()
+        };
+        let allowed = |url: &str| Target::parse(url).is_ok_and(|t| t.allowed(&config));
+        assert!(allowed("https://cdn.example.com/a/b.bin"));
+        assert!(allowed("https://CDN.Example.com/"));
+        assert!(!allowed("https://evil.net/x.example.com/a"));
+        assert!(!allowed("https://example.com/a"));
+        assert!(!allowed("https://a.b.example.com/a"));
+        assert!(!allowed("https://cdn.example.com:8443/a"));
+
+        assert!(allowed("https://host.example.net/fw/image.bin"));
+        assert!(!allowed("https://host.example.net/fw/sub/image.bin"));
+        assert!(!allowed("https://host.example.net/fw/../admin"));
+        assert!(Target::parse("https://host.example.net/fw/../admin").is_err());
+        assert!(Target::parse("https://host.example.net/fw/%2E%2e/admin").is_err());
+        assert!(Target::parse("https://host.example.net/fw/./image.bin").is_err());
+
+        assert!(validate_allow("https://[fd00::1]:8080/**").is_ok());
+        assert!(validate_allow("https://reg*.example.com/**").is_err());
+        assert!(validate_allow("https://*/**").is_err());
+        assert!(validate_allow("ftp://host/**").is_err());
+        assert!(validate_allow("https://host:port/").is_err());
+    }
+
+    #[test]
+    fn authorization_stays_with_one_origin() {
+        let target = Target::parse("http://mirror.example.com/a").unwrap();
+        for (location, same) in [
+            ("/b", true),
+            ("http://mirror.example.com:80/c", true),
+            ("https://mirror.example.com/a", false),
+            ("http://mirror.example.com:8080/a", false),
+            ("//other.example.com/a", false),
+        ] {
+            assert_eq!(
+                target.join(location).unwrap().same_origin(&target),
+                same,
+                "{location}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn framing_headers_are_refused() {
+        let config = FetchConfig {
+            allow: vec!["http://127.0.0.1:9/**".into()],
+            ..FetchConfig::default()
+        };
+        for name in ["Transfer-Encoding", "content-length", "Connection"] {
+            let headers = HashMap::from([(name.to_string(), "x".to_string())]);
+            let err = send(&config, "POST", "http://127.0.0.1:9/x", &headers, None)
+                .await
+                .err()
+                .unwrap();
+            assert!(
+                matches!(err, FetchError::Invalid(ref msg) if msg.contains(name)),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,576 @@
+//! Directory watches for `files.watch` (WebSocket and tunnel).
+//!
+//! Each watch is one inotify instance on a single directory (not recursive).
+//! A watch on a file watches its parent directory filtered to that name, so
+//! it survives editors that save by renaming a temp file over the original,
+//! and can be set up before the file exists. A glob in the last path
+//! component (or the `glob` field) filters a directory watch by name.
+//!
+//! Changes are batched for `debounce_ms` after the first one and coalesced
+//! per path: a file created and then written reports `create`, one created
+//! and deleted within the window reports nothing, one deleted and recreated
+//! reports `modify`. A batch is sent as `files.changed` carrying the
+//! `request_id` of the `files.watch` that created it, which is how the relay
+//! routes it back to the right client.
+//!
+//! Watches belong to a connection ([`FileWatches`]) and stop when it closes.
+//! They need inotify, so on other platforms `files.watch` fails with
+//! `UNSUPPORTED`.
+
+#![cfg_attr(not(target_os = "linux"), allow(dead_code, unused_imports))]
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CString, OsStr, OsString};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::error::{codes, ApiError};
+use crate::ws::messages::WsServerMsg;
+
+/// Watches one connection may hold at once.
+pub const MAX_WATCHES: usize = 32;
+
+const DEFAULT_DEBOUNCE_MS: u64 = 200;
+const MAX_DEBOUNCE_MS: u64 = 60_000;
+
+/// What happened to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export))]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// One entry of a `files.changed` batch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export))]
+pub struct FileChange {
+    /// Absolute path of the changed entry.
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// Which names in the watched directory are reported.
+enum Filter {
+    All,
+    Name(OsString),
+    Glob(CString),
+}
+
+impl Filter {
+    fn matches(&self, name: &OsStr) -> bool {
+        match self {
+            Self::All => true,
+            Self::Name(n) => n == name,
+            Self::Glob(pattern) => CString::new(name.as_encoded_bytes())
+                .is_ok_and(|name| crate::shell::classify::glob(pattern, &name)),
+        }
+    }
+}
+
+/// A parsed `files.watch` request.
+pub struct WatchSpec {
+    dir: PathBuf,
+    filter: Filter,
+    glob: Option<String>,
+    debounce: Duration,
+}
+
+impl WatchSpec {
+    /// Parse `path`, `glob?` and `debounce_ms?` from a `files.watch` message.
+    pub fn from_message(msg: &Value) -> Result<Self, ApiError> {
+        let path = msg["path"]
+            .as_str()
+            .ok_or_else(|| ApiError::new(codes::INVALID_REQUEST, "Missing 'path'"))?;
+        let path = crate::routes::files::validate_path(path).map_err(|(_, e)| e.0)?;
+        let mut glob = msg["glob"].as_str().map(ToString::to_string);
+
+        let name = path.file_name().map(OsStr::to_os_string);
+        let is_glob = name
+            .as_ref()
+            .is_some_and(|n| n.as_encoded_bytes().iter().any(|b| b"*?[".contains(b)));
+        let (dir, filter) = if path.is_dir() && !is_glob {
+            (path, None)
+        } else {
+            let name = name.ok_or_else(|| {
+                ApiError::new(codes::INVALID_PATH, "Path has no file name to watch")
+            })?;
+            let parent = path.parent().map(PathBuf::from).unwrap_or_default();
+            if !parent.is_dir() {
+                return Err(ApiError::new(
+                    codes::FILE_NOT_FOUND,
+                    format!("Directory not found: {}", parent.display()),
+                ));
+            }
+            if is_glob {
+                if glob.is_some() {
+                    return Err(ApiError::new(
+                        codes::INVALID_REQUEST,
+                        "Give the pattern in 'path' or 'glob', not both",
+                    ));
+                }
+                glob = Some(name.to_string_lossy().into_owned());
+                (parent, None)
+            } else if glob.is_some() {
+                return Err(ApiError::new(
+                    codes::NOT_A_DIRECTORY,
+                    "'glob' needs a directory path",
+                ));
+            } else {
+                (parent, Some(Filter::Name(name)))
+            }
+        };
+        let filter = match (filter, &glob) {
+            (Some(filter), _) => filter,
+            (None, Some(g)) => Filter::Glob(CString::new(g.as_str()).map_err(|_| {
+                ApiError::new(codes::INVALID_REQUEST, "glob must not contain NUL bytes")
+            })?),
+            (None, None) => Filter::All,
+        };
+
+        let debounce_ms = msg["debounce_ms"]
+            .as_u64()
+            .unwrap_or(DEFAULT_DEBOUNCE_MS)
+            .min(MAX_DEBOUNCE_MS);
+        Ok(Self {
+            dir,
+            filter,
+            glob,
+            debounce: Duration::from_millis(debounce_ms),
+        })
+    }
+
+    /// Create the inotify instance for this watch.
+    #[cfg(target_os = "linux")]
+    fn open(&self) -> Result<AsyncFd<InotifyFd>, ApiError> {
+        let inotify =
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(watch_error)?;
+        inotify
+            .add_watch(
+                &self.dir,
+                AddWatchFlags::IN_CREATE
+                    | AddWatchFlags::IN_MODIFY
+                    | AddWatchFlags::IN_DELETE
+                    | AddWatchFlags::IN_MOVED_FROM
+                    | AddWatchFlags::IN_MOVED_TO
+                    | AddWatchFlags::IN_DELETE_SELF
+                    | AddWatchFlags::IN_MOVE_SELF
+                    | AddWatchFlags::IN_ONLYDIR,
+            )
+            .map_err(watch_error)?;
+        AsyncFd::new(InotifyFd(inotify))
+            .map_err(|e| ApiError::new(codes::IO_ERROR, format!("Cannot watch: {e}")))
+    }
+
+    /// The change an event describes, if it passes the filter.
+    #[cfg(target_os = "linux")]
+    fn change(&self, event: &InotifyEvent) -> Option<FileChange> {
+        let name = event.name.as_deref()?;
+        if !self.filter.matches(name) {
+            return None;
+        }
+        let kind = if event
+            .mask
+            .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+        {
+            ChangeKind::Create
+        } else if event
+            .mask
+            .intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM)
+        {
+            ChangeKind::Delete
+        } else if event.mask.contains(AddWatchFlags::IN_MODIFY) {
+            ChangeKind::Modify
+        } else {
+            return None;
+        };
+        Some(FileChange {
+            path: self.dir.join(name).to_string_lossy().into_owned(),
+            kind,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch_error(e: Errno) -> ApiError {
+    match e {
+        Errno::ENOENT => ApiError::new(codes::FILE_NOT_FOUND, "Directory not found"),
+        Errno::ENOTDIR => ApiError::new(codes::NOT_A_DIRECTORY, "Not a directory"),
+        Errno::EACCES => ApiError::new(codes::PERMISSION_DENIED, "Permission denied"),
+        Errno::ENOSPC | Errno::EMFILE => ApiError::new(
+            codes::IO_ERROR,
+            "System inotify limit reached (fs.inotify.max_user_watches / max_user_instances)",
+        ),
+        e => ApiError::new(codes::IO_ERROR, format!("Cannot watch: {e}")),
+    }
+}
+
+/// [`Inotify`] only exposes [`AsFd`]; [`AsyncFd`] wants [`AsRawFd`].
+#[cfg(target_os = "linux")]
+struct InotifyFd(Inotify);
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Fold `change` into the pending batch.
+fn merge(pending: &mut BTreeMap<String, ChangeKind>, change: FileChange) {
+    match (pending.get(&change.path), change.kind) {
+        (Some(ChangeKind::Create), ChangeKind::Modify) => {}
+        (Some(ChangeKind::Create), ChangeKind::Delete) => {
+            pending.remove(&change.path);
+        }
+        (Some(ChangeKind::Delete), ChangeKind::Create) => {
+            pending.insert(change.path, ChangeKind::Modify);
+        }
+        (_, kind) => {
+            pending.insert(change.path, kind);
+        }
+    }
+}
+
+struct Watch {
+    owner: Option<String>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// The watches of one connection. Dropping it stops them all.
+#[derive(Default)]
+pub struct FileWatches {
+    watches: HashMap<String, Watch>,
+}
+
+impl FileWatches {
+    /// Start a watch that sends its events to `tx`. `owner` groups watches
+    /// for [`Self::unwatch_owner`] (the relay client id on the tunnel).
+    pub fn watch(
+        &mut self,
+        spec: WatchSpec,
+        owner: Option<String>,
+        request_id: Option<String>,
+        tx: mpsc::Sender<Value>,
+    ) -> Result<String, ApiError> {
+        self.watches.retain(|_, w| !w.task.is_finished());
+        if self.watches.len() >= MAX_WATCHES {
+            return Err(ApiError::new(
+                "WATCH_LIMIT",
+                format!("At most {MAX_WATCHES} file watches per connection"),
+            ));
+        }
+        let watch_id = uuid::Uuid::new_v4().to_string();
+        let task = start(spec, watch_id.clone(), request_id, tx)?;
+        self.watches.insert(watch_id.clone(), Watch { owner, task });
+        Ok(watch_id)
+    }
+
+    /// Stop a watch. Returns `false` if there is no such watch.
+    pub fn unwatch(&mut self, watch_id: &str) -> bool {
+        let Some(w) = self.watches.remove(watch_id) else {
+            return false;
+        };
+        w.task.abort();
+        true
+    }
+
+    /// Stop every watch started for `owner`.
+    pub fn unwatch_owner(&mut self, owner: &str) {
+        self.watches.retain(|_, w| {
+            let keep = w.owner.as_deref() != Some(owner);
+            if !keep {
+                w.task.abort();
+            }
+            keep
+        });
+    }
+
+    /// Stop every watch.
+    pub fn clear(&mut self) {
+        for (_, w) in self.watches.drain() {
+            w.task.abort();
+        }
+    }
+}
+
+impl Drop for FileWatches {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Handle a `files.watch` message; returns the reply (`files.watch.ack` or
+/// `error`). Events go to `tx`.
+pub fn handle_watch(
+    watches: &mut FileWatches,
+    msg: &Value,
+    owner: Option<String>,
+    tx: mpsc::Sender<Value>,
+) -> Value {
+    let request_id = msg["request_id"].as_str().map(ToString::to_string);
+    let started = if crate::auth::current_allows("files:read") {
+        WatchSpec::from_message(msg).and_then(|spec| {
+            let path = spec.dir.to_string_lossy().into_owned();
+            let glob = spec.glob.clone();
+            watches
+                .watch(spec, owner, request_id.clone(), tx)
+                .map(|id| (id, path, glob))
+        })
+    } else {
+        Err(ApiError::new(
+            codes::AUTH_INSUFFICIENT_SCOPE,
+            "files.watch needs the 'files:read' scope",
+        ))
+    };
+    match started {
+        Ok((watch_id, path, glob)) => WsServerMsg::FilesWatchAck {
+            watch_id,
+            path,
+            glob,
+            request_id,
+        },
+        Err(e) => WsServerMsg::Error {
+            code: e.code,
+            message: e.message,
+            session_id: None,
+            request_id,
+        },
+    }
+    .to_value()
+}
+
+/// Handle a `files.unwatch` message; returns the reply.
+pub fn handle_unwatch(watches: &mut FileWatches, msg: &Value) -> Value {
+    let request_id = msg["request_id"].as_str().map(ToString::to_string);
+    let watch_id = msg["watch_id"].as_str().unwrap_or_default();
+    if watches.unwatch(watch_id) {
+        WsServerMsg::FilesUnwatchAck {
+            watch_id: watch_id.to_string(),
+            request_id,
+        }
+    } else {
+        WsServerMsg::Error {
+            code: codes::NOT_FOUND.into(),
+            message: format!("No file watch '{watch_id}'"),
+            session_id: None,
+            request_id,
+        }
+    }
+    .to_value()
+}
+
+/// Open the inotify instance and spawn the task reading it.
+#[cfg(target_os = "linux")]
+fn start(
+    spec: WatchSpec,
+    watch_id: String,
+    request_id: Option<String>,
+    tx: mpsc::Sender<Value>,
+) -> Result<tokio::task::JoinHandle<()>, ApiError> {
+    let fd = spec.open()?;
+    Ok(tokio::spawn(run(fd, spec, watch_id, request_id, tx)))
+}
+
+#[cfg(not(target_os = "linux"))]
+#[allow(clippy::needless_pass_by_value)]
+fn start(
+    _spec: WatchSpec,
+    _watch_id: String,
+    _request_id: Option<String>,
+    _tx: mpsc::Sender<Value>,
+) -> Result<tokio::task::JoinHandle<()>, ApiError> {
+    Err(ApiError::new(
+        "UNSUPPORTED",
+        "File watches need inotify (Linux only)",
+    ))
+}
+
+/// Read events until the directory goes away or the receiver closes.
+#[cfg(target_os = "linux")]
+async fn run(
+    fd: AsyncFd<InotifyFd>,
+    spec: WatchSpec,
+    watch_id: String,
+    request_id: Option<String>,
+    tx: mpsc::Sender<Value>,
+) {
+    let mut pending = BTreeMap::new();
+    let mut overflow = false;
+    let mut flush_at: Option<Instant> = None;
+    let mut closed: Option<String> = None;
+
+    while closed.is_none() {
+        let deadline = flush_at;
+        let flush = async move {
+            match deadline {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            ready = fd.readable() => {
+                let mut guard = match ready {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        closed = Some(format!("Watch failed: {e}"));
+                        continue;
+                    }
+                };
+                match guard.get_inner().0.read_events() {
+                    Ok(events) => {
+                        for event in &events {
+                            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                                overflow = true;
+                            }
+                            if event.mask.intersects(
+                                AddWatchFlags::IN_DELETE_SELF
+                                    | AddWatchFlags::IN_MOVE_SELF
+                                    | AddWatchFlags::IN_IGNORED,
+                            ) {
+                                closed = Some("Watched directory was removed or moved".into());
+                            } else if let Some(change) = spec.change(event) {
+                                merge(&mut pending, change);
+                            }
+                        }
+                        if (overflow || !pending.is_empty()) && flush_at.is_none() {
+                            flush_at = Some(Instant::now() + spec.debounce);
+                        }
+                    }
+                    Err(Errno::EAGAIN) => guard.clear_ready(),
+                    Err(e) => closed = Some(format!("Watch failed: {e}")),
+                }
+            }
+            () = flush => {
+                flush_at = None;
+                if !send_changes(&tx, &watch_id, &mut pending, &mut overflow, request_id.as_deref()).await {
+                    return;
+                }
+            }
+            () = tx.closed() => return,
+        }
+    }
+
+    if send_changes(
+        &tx,
+        &watch_id,
+        &mut pending,
+        &mut overflow,
+        request_id.as_deref(),
+    )
+    .await
+    {
+        let _ = tx
+            .send(
+                WsServerMsg::FilesWatchClosed {
+                    watch_id,
+                    reason: closed.unwrap_or_default(),
+                    request_id,
+                }
+                .to_value(),
+            )
+            .await;
+    }
+}
+
+/// Send and clear the pending batch. Returns `false` once the receiver is gone.
+async fn send_changes(
+    tx: &mpsc::Sender<Value>,
+    watch_id: &str,
+    pending: &mut BTreeMap<String, ChangeKind>,
+    overflow: &mut bool,
+    request_id: Option<&str>,
+) -> bool {
+    if pending.is_empty() && !*overflow {
+        return true;
+    }
+    let changes = std::mem::take(pending)
+        .into_iter()
+        .map(|(path, kind)| FileChange { path, kind })
+        .collect();
+    let msg = WsServerMsg::FilesChanged {
+        watch_id: watch_id.to_string(),
+        changes,
+        overflow: std::mem::take(overflow),
+        request_id: request_id.map(ToString::to_string),
+    };
+    tx.send(msg.to_value()).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(path: &str, kind: ChangeKind) -> FileChange {
+        FileChange {
+            path: path.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn merge_coalesces_per_path() {
+        let mut pending = BTreeMap::new();
+        merge(&mut pending, change("/a", ChangeKind::Create));
+        merge(&mut pending, change("/a", ChangeKind::Modify));
+        merge(&mut pending, change("/b", ChangeKind::Create));
+        merge(&mut pending, change("/b", ChangeKind::Delete));
+        merge(&mut pending, change("/c", ChangeKind::Delete));
+        merge(&mut pending, change("/c", ChangeKind::Create));
+        merge(&mut pending, change("/d", ChangeKind::Modify));
+        merge(&mut pending, change("/d", ChangeKind::Delete));
+        let got: Vec<_> = pending.into_iter().collect();
+        assert_eq!(
+            got,
+            vec![
+                ("/a".to_string(), ChangeKind::Create),
+                ("/c".to_string(), ChangeKind::Modify),
+                ("/d".to_string(), ChangeKind::Delete),
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_splits_file_and_glob_paths() {
+        let dir = std::env::temp_dir();
+        let dir_str = dir.to_string_lossy().into_owned();
+
+        let spec = WatchSpec::from_message(&serde_json::json!({ "path": dir_str })).unwrap();
+        assert_eq!(spec.dir, dir);
+        assert!(matches!(spec.filter, Filter::All));
+
+        let file = dir.join("sctl-watch-test.log");
+        let spec = WatchSpec::from_message(&serde_json::json!({ "path": file })).unwrap();
+        assert_eq!(spec.dir, dir);
+        assert!(spec.filter.matches(OsStr::new("sctl-watch-test.log")));
+        assert!(!spec.filter.matches(OsStr::new("other.log")));
+
+        let spec =
+            WatchSpec::from_message(&serde_json::json!({ "path": dir.join("*.log") })).unwrap();
+        assert_eq!(spec.glob.as_deref(), Some("*.log"));
+        assert!(spec.filter.matches(OsStr::new("x.log")));
+        assert!(!spec.filter.matches(OsStr::new("x.txt")));
+
+        let err = WatchSpec::from_message(&serde_json::json!({ "path": "/no/such/dir/x" }))
+            .err()
+            .unwrap();
+        assert_eq!(err.code, codes::FILE_NOT_FOUND);
+    }
+}
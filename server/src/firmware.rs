@@ -0,0 +1,681 @@
+//! A/B firmware updates for `/api/firmware`.
+//!
+//! With `[firmware]` configured, sctl drives the system's update agent —
+//! RAUC, SWUpdate or Mender — to install a bundle that is already on the
+//! device (typically uploaded with gawdxfer) into the inactive slot, reboot
+//! into it, and report whether the device came back on the new slot or the
+//! bootloader fell back to the old one:
+//!
+//! 1. `POST /api/firmware/install` runs the agent's install in the
+//!    background: `installing`, then `installed` or `failed`.
+//! 2. `POST /api/firmware/reboot` (or `"reboot": true` on the install)
+//!    reboots: `rebooting`.
+//! 3. On the next start the install is settled against the new boot: back on
+//!    the slot it was installed from → `rolled_back`; on another slot →
+//!    `booted`.
+//! 4. `POST /api/firmware/commit`, or `commit_after_secs` of uptime, marks the
+//!    booted slot good: `committed`. Until then the bootloader falls back on
+//!    a failed boot, which step 3 reports as `rolled_back`.
+//!
+//! The install record is kept in `<data_dir>/firmware.json`, and each
+//! transition is broadcast as `firmware.<status>` with the record. Events
+//! raised at startup go through the offline spool, so a rollback reaches the
+//! relay once the tunnel is back.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+
+use crate::config::FirmwareConfig;
+use crate::sessions::journal::now_ms;
+
+/// Accepted `[firmware] backend` values.
+pub const BACKENDS: &[&str] = &["auto", "rauc", "swupdate", "mender"];
+
+/// Time between answering a reboot request and running it, so the response
+/// and the `firmware.rebooting` event get out first.
+const REBOOT_DELAY: Duration = Duration::from_secs(5);
+
+/// Agent output kept on the install record.
+const OUTPUT_TAIL_BYTES: usize = 4096;
+
+/// How long `rauc status` and commit commands get.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Rauc,
+    Swupdate,
+    Mender,
+}
+
+impl Backend {
+    /// The backend `configured` names, or with `auto` the first agent found
+    /// on `PATH`. `None` when it isn't installed.
+    pub fn detect(configured: &str) -> Option<Self> {
+        let candidates: &[Self] = match configured {
+            "rauc" => &[Self::Rauc],
+            "swupdate" => &[Self::Swupdate],
+            "mender" => &[Self::Mender],
+            _ => &[Self::Rauc, Self::Mender, Self::Swupdate],
+        };
+        candidates
+            .iter()
+            .copied()
+            .find(|b| b.programs().iter().any(|p| crate::twin::has_tool(p)))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rauc => "rauc",
+            Self::Swupdate => "swupdate",
+            Self::Mender => "mender",
+        }
+    }
+
+    /// Agent executables, preferred first (Mender 4 renamed its client).
+    fn programs(self) -> &'static [&'static str] {
+        match self {
+            Self::Rauc => &["rauc"],
+            Self::Swupdate => &["swupdate"],
+            Self::Mender => &["mender-update", "mender"],
+        }
+    }
+
+    fn program(self) -> &'static str {
+        let programs = self.programs();
+        programs
+            .iter()
+            .copied()
+            .find(|p| crate::twin::has_tool(p))
+            .unwrap_or(programs[0])
+    }
+
+    /// The install command for `bundle`, as program and arguments.
+    pub fn install_command(self, bundle: &str) -> (&'static str, Vec<String>) {
+        let program = self.program();
+        let args = match self {
+            Self::Rauc | Self::Mender => vec!["install".to_string(), bundle.to_string()],
+            Self::Swupdate => vec!["-i".to_string(), bundle.to_string()],
+        };
+        (program, args)
+    }
+
+    /// The command that marks the booted slot good.
+    pub fn commit_command(self) -> (&'static str, Vec<String>) {
+        match self {
+            Self::Rauc => ("rauc", vec!["status".into(), "mark-good".into()]),
+            Self::Mender => (self.program(), vec!["commit".into()]),
+            Self::Swupdate => ("fw_setenv", vec!["upgrade_available".into(), "0".into()]),
+        }
+    }
+}
+
+/// Where an install is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Installing,
+    Installed,
+    Failed,
+    Rebooting,
+    Booted,
+    RolledBack,
+    Committed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Installing => "installing",
+            Self::Installed => "installed",
+            Self::Failed => "failed",
+            Self::Rebooting => "rebooting",
+            Self::Booted => "booted",
+            Self::RolledBack => "rolled_back",
+            Self::Committed => "committed",
+        }
+    }
+}
+
+/// The latest install, as persisted in `firmware.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Install {
+    pub id: String,
+    pub backend: String,
+    pub bundle: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub status: Status,
+    /// Slot and version running when the install started.
+    pub from_slot: Option<String>,
+    pub from_version: Option<String>,
+    /// Slot and version booted afterwards, once settled.
+    #[serde(default)]
+    pub to_slot: Option<String>,
+    #[serde(default)]
+    pub to_version: Option<String>,
+    /// Kernel boot id the record was last written under; a different one on
+    /// startup means the device rebooted.
+    pub boot_id: String,
+    /// Unix ms.
+    pub started_at: u64,
+    pub updated_at: u64,
+    /// Tail of the agent's output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Why an install, reboot or commit wasn't started.
+#[derive(Debug)]
+pub enum FirmwareError {
+    /// No update agent is installed.
+    Unavailable(String),
+    /// The latest install isn't in a state that allows it.
+    Busy(String),
+    /// The agent's command failed.
+    Failed(String),
+}
+
+impl std::fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unavailable(e) | Self::Busy(e) | Self::Failed(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Booted slot and OS version.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SlotInfo {
+    pub booted_slot: Option<String>,
+    pub version: Option<String>,
+    /// `PRETTY_NAME` from `/etc/os-release`.
+    pub os: Option<String>,
+    /// The agent's view of every slot (RAUC only).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slots: Vec<Value>,
+}
+
+/// The update agent and the latest install.
+pub struct Firmware {
+    config: FirmwareConfig,
+    backend: Option<Backend>,
+    path: PathBuf,
+    install: Mutex<Option<Install>>,
+    session_events: broadcast::Sender<Value>,
+}
+
+impl Firmware {
+    pub fn open(
+        config: FirmwareConfig,
+        data_dir: &str,
+        session_events: broadcast::Sender<Value>,
+    ) -> Self {
+        let backend = Backend::detect(&config.backend);
+        if let Some(b) = backend {
+            info!("Firmware updates via {}", b.as_str());
+        } else {
+            warn!(
+                "Firmware updates: no update agent found for backend '{}'",
+                config.backend
+            );
+        }
+        let path = Path::new(data_dir).join("firmware.json");
+        let install = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok());
+        Self {
+            config,
+            backend,
+            path,
+            install: Mutex::new(install),
+            session_events,
+        }
+    }
+
+    pub fn backend(&self) -> Option<Backend> {
+        self.backend
+    }
+
+    pub fn config(&self) -> &FirmwareConfig {
+        &self.config
+    }
+
+    pub async fn install(&self) -> Option<Install> {
+        self.install.lock().await.clone()
+    }
+
+    /// Booted slot, version and the agent's slot list.
+    pub async fn slot_info(&self) -> SlotInfo {
+        let rauc = match self.backend {
+            Some(Backend::Rauc) => rauc_status().await,
+            _ => None,
+        };
+        let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+        let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+        slot_info(rauc.as_ref(), &os_release, &cmdline)
+    }
+
+    /// Start installing `bundle` in the background, rebooting into it
+    /// afterwards when `reboot` is set.
+    ///
+    /// # Errors
+    ///
+    /// No agent is installed, or an install is running.
+    pub async fn start(
+        self: &Arc<Self>,
+        bundle: String,
+        sha256: Option<String>,
+        reboot: bool,
+    ) -> Result<Install, FirmwareError> {
+        let backend = self.backend.ok_or_else(|| {
+            FirmwareError::Unavailable(format!(
+                "no update agent found for backend '{}'",
+                self.config.backend
+            ))
+        })?;
+        let slot = self.slot_info().await;
+        let record = {
+            let mut current = self.install.lock().await;
+            if let Some(running) = current
+                .as_ref()
+                .filter(|i| matches!(i.status, Status::Installing | Status::Rebooting))
+            {
+                return Err(FirmwareError::Busy(format!(
+                    "install {} is {}",
+                    running.id,
+                    running.status.as_str()
+                )));
+            }
+            let now = now_ms();
+            let record = Install {
+                id: uuid::Uuid::new_v4().to_string(),
+                backend: backend.as_str().to_string(),
+                bundle: bundle.clone(),
+                sha256,
+                status: Status::Installing,
+                from_slot: slot.booted_slot,
+                from_version: slot.version,
+                to_slot: None,
+                to_version: None,
+                boot_id: boot_id(),
+                started_at: now,
+                updated_at: now,
+                output: None,
+                error: None,
+            };
+            *current = Some(record.clone());
+            self.save(&record);
+            record
+        };
+        self.broadcast(&record);
+        info!(bundle = %bundle, backend = backend.as_str(), "Firmware install started");
+
+        let firmware = Arc::clone(self);
+        tokio::spawn(async move {
+            let (program, args) = backend.install_command(&bundle);
+            let timeout = Duration::from_secs(firmware.config.install_timeout_secs);
+            let result = run(program, &args, timeout).await;
+            let ok = result.is_ok();
+            let record = firmware
+                .update(|install| {
+                    match result {
+                        Ok(output) => {
+                            install.status = Status::Installed;
+                            install.output = Some(output);
+                        }
+                        Err(e) => {
+                            install.status = Status::Failed;
+                            install.error = Some(e);
+                        }
+                    }
+                    true
+                })
+                .await;
+            if let Some(record) = &record {
+                if let Some(e) = &record.error {
+                    warn!(bundle = %record.bundle, "Firmware install failed: {e}");
+                } else {
+                    info!(bundle = %record.bundle, "Firmware installed");
+                }
+            }
+            if ok && reboot {
+                if let Err(e) = firmware.reboot().await {
+                    warn!("Firmware reboot not started: {e}");
+                }
+            }
+        });
+        Ok(record)
+    }
+
+    /// Reboot into the installed slot after [`REBOOT_DELAY`].
+    ///
+    /// # Errors
+    ///
+    /// The latest install isn't `installed`.
+    pub async fn reboot(self: &Arc<Self>) -> Result<Install, FirmwareError> {
+        let record = self
+            .update(|install| {
+                if install.status != Status::Installed {
+                    return false;
+                }
+                install.status = Status::Rebooting;
+                true
+            })
+            .await;
+        let Some(record) = record.filter(|r| r.status == Status::Rebooting) else {
+            return Err(FirmwareError::Busy(
+                "no installed update waiting for a reboot".to_string(),
+            ));
+        };
+        tokio::spawn(async {
+            tokio::time::sleep(REBOOT_DELAY).await;
+            warn!("Firmware: rebooting into the new slot");
+            crate::util::sync_filesystems();
+            match tokio::process::Command::new("reboot").status().await {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("Firmware: reboot exited with {status}"),
+                Err(e) => warn!("Firmware: failed to run reboot: {e}"),
+            }
+        });
+        Ok(record)
+    }
+
+    /// Mark the booted slot good.
+    ///
+    /// # Errors
+    ///
+    /// The latest install isn't `booted`, or the agent refused.
+    pub async fn commit(&self) -> Result<Install, FirmwareError> {
+        let backend = self
+            .backend
+            .ok_or_else(|| FirmwareError::Unavailable("no update agent found".to_string()))?;
+        if self.install().await.map(|i| i.status) != Some(Status::Booted) {
+            return Err(FirmwareError::Busy(
+                "no booted update waiting to be committed".to_string(),
+            ));
+        }
+        let (program, args) = backend.commit_command();
+        let output = run(program, &args, QUERY_TIMEOUT)
+            .await
+            .map_err(FirmwareError::Failed)?;
+        let record = self
+            .update(|install| {
+                if install.status != Status::Booted {
+                    return false;
+                }
+                install.status = Status::Committed;
+                install.output = Some(output);
+                true
+            })
+            .await;
+        info!("Firmware: booted slot marked good");
+        record.ok_or_else(|| FirmwareError::Busy("install record is gone".to_string()))
+    }
+
+    /// Settle the latest install against this boot, then commit after
+    /// `commit_after_secs` if it booted the new slot.
+    pub fn spawn(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        let firmware = Arc::clone(self);
+        tokio::spawn(async move {
+            let slot = firmware.slot_info().await;
+            let boot = boot_id();
+            let settled = firmware
+                .update(|install| settle(install, &boot, &slot))
+                .await;
+            let Some(record) = settled else {
+                return;
+            };
+            match record.status {
+                Status::RolledBack => warn!(
+                    slot = record.to_slot.as_deref().unwrap_or("unknown"),
+                    "Firmware: rolled back to the previous slot"
+                ),
+                Status::Booted => info!(
+                    slot = record.to_slot.as_deref().unwrap_or("unknown"),
+                    "Firmware: booted the new slot"
+                ),
+                _ => {}
+            }
+            let delay = firmware.config.commit_after_secs;
+            if record.status != Status::Booted || delay == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+            if let Err(e) = firmware.commit().await {
+                warn!("Firmware: automatic commit failed: {e}");
+            }
+        })
+    }
+
+    /// Apply `change` to the latest install; when it returns true, save and
+    /// broadcast the result. Returns the record as it stands afterwards.
+    async fn update(&self, change: impl FnOnce(&mut Install) -> bool) -> Option<Install> {
+        let mut current = self.install.lock().await;
+        let install = current.as_mut()?;
+        if !change(install) {
+            return Some(install.clone());
+        }
+        install.updated_at = now_ms();
+        install.boot_id = boot_id();
+        let record = install.clone();
+        drop(current);
+        self.save(&record);
+        self.broadcast(&record);
+        Some(record)
+    }
+
+    fn broadcast(&self, record: &Install) {
+        let mut event = json!(record);
+        event["type"] = json!(format!("firmware.{}", record.status.as_str()));
+        let _ = self.session_events.send(event);
+    }
+
+    /// Atomically write `record`.
+    fn save(&self, record: &Install) {
+        let tmp = self.path.with_extension("json.tmp");
+        let result = serde_json::to_vec_pretty(record)
+            .map_err(std::io::Error::from)
+            .and_then(|data| std::fs::write(&tmp, data))
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+        if let Err(e) = result {
+            warn!("Failed to save firmware install state: {e}");
+        }
+    }
+}
+
+/// Move `install` on after a restart. Returns whether it changed.
+fn settle(install: &mut Install, boot: &str, slot: &SlotInfo) -> bool {
+    let rebooted = install.boot_id != boot;
+    match install.status {
+        Status::Installing => {
+            install.status = Status::Failed;
+            install.error = Some(if rebooted {
+                "interrupted by a reboot".to_string()
+            } else {
+                "interrupted by an sctl restart".to_string()
+            });
+        }
+        Status::Installed | Status::Rebooting | Status::Booted if rebooted => {
+            install.to_slot.clone_from(&slot.booted_slot);
+            install.to_version.clone_from(&slot.version);
+            let same_slot = slot.booted_slot.is_some() && slot.booted_slot == install.from_slot;
+            install.status = if same_slot {
+                Status::RolledBack
+            } else {
+                Status::Booted
+            };
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Booted slot and version from `rauc status` JSON (when RAUC is the agent),
+/// `/etc/os-release` and the kernel command line.
+fn slot_info(rauc: Option<&Value>, os_release: &str, cmdline: &str) -> SlotInfo {
+    let os_field = |key: &str| {
+        os_release.lines().find_map(|l| {
+            l.strip_prefix(key)
+                .and_then(|v| v.strip_prefix('='))
+                .map(|v| v.trim_matches('"').to_string())
+        })
+    };
+    let root = cmdline
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("root="))
+        .map(ToString::to_string);
+    let mut info = SlotInfo {
+        booted_slot: root,
+        version: os_field("VERSION_ID").or_else(|| os_field("VERSION")),
+        os: os_field("PRETTY_NAME"),
+        slots: Vec::new(),
+    };
+    let Some(rauc) = rauc else {
+        return info;
+    };
+    if let Some(booted) = rauc["booted"].as_str() {
+        info.booted_slot = Some(booted.to_string());
+    }
+    // `slots` is a list of single-entry objects: `[{"rootfs.0": {...}}, ...]`.
+    for (name, slot) in rauc["slots"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object)
+        .flatten()
+    {
+        let version = slot["slot_status"]["bundle"]["version"].clone();
+        if slot["bootname"].as_str().is_some() && slot["bootname"] == rauc["booted"] {
+            if let Some(v) = version.as_str() {
+                info.version = Some(v.to_string());
+            }
+        }
+        info.slots.push(json!({
+            "name": name,
+            "class": slot["class"],
+            "bootname": slot["bootname"],
+            "state": slot["state"],
+            "boot_status": slot["boot_status"],
+            "version": version,
+        }));
+    }
+    info
+}
+
+async fn rauc_status() -> Option<Value> {
+    let args = ["status".to_string(), "--output-format=json".to_string()];
+    let output = run("rauc", &args, QUERY_TIMEOUT).await.ok()?;
+    serde_json::from_str(&output).ok()
+}
+
+/// Run `program`, returning the tail of its combined output.
+async fn run(program: &str, args: &[String], timeout: Duration) -> Result<String, String> {
+    let output = tokio::time::timeout(
+        timeout,
+        tokio::process::Command::new(program)
+            .args(args)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| format!("{program} timed out after {}s", timeout.as_secs()))?
+    .map_err(|e| format!("{program}: {e}"))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let text = tail(text.trim());
+    if output.status.success() {
+        Ok(text)
+    } else {
+        Err(format!("{program} exited with {}: {text}", output.status))
+    }
+}
+
+fn tail(text: &str) -> String {
+    let mut start = text.len().saturating_sub(OUTPUT_TAIL_BYTES);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].to_string()
+}
+
+fn boot_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settles_installs_against_the_booted_slot() {
+        let rauc = json!({
+            "booted": "B",
+            "slots": [
+                {"rootfs.0": {"class": "rootfs", "bootname": "A", "state": "inactive",
+                              "slot_status": {"bundle": {"version": "1.0"}}}},
+                {"rootfs.1": {"class": "rootfs", "bootname": "B", "state": "booted",
+                              "slot_status": {"bundle": {"version": "1.1"}}}}
+            ]
+        });
+        let os_release = "NAME=\"Acme\"\nVERSION_ID=\"0.9\"\nPRETTY_NAME=\"Acme 0.9\"\n";
+        let on_b = slot_info(
+            Some(&rauc),
+            os_release,
+            "console=ttyS0 root=/dev/mmcblk0p3 rw",
+        );
+        assert_eq!(on_b.booted_slot.as_deref(), Some("B"));
+        assert_eq!(on_b.version.as_deref(), Some("1.1"));
+        assert_eq!(on_b.os.as_deref(), Some("Acme 0.9"));
+        assert_eq!(on_b.slots.len(), 2);
+        let on_a = slot_info(None, os_release, "root=/dev/mmcblk0p2 rw");
+        assert_eq!(on_a.booted_slot.as_deref(), Some("/dev/mmcblk0p2"));
+        assert_eq!(on_a.version.as_deref(), Some("0.9"));
+
+        let mut install = Install {
+            id: "1".into(),
+            backend: "rauc".into(),
+            bundle: "/data/updates/acme-1.1.raucb".into(),
+            sha256: None,
+            status: Status::Rebooting,
+            from_slot: Some("A".into()),
+            from_version: Some("1.0".into()),
+            to_slot: None,
+            to_version: None,
+            boot_id: "boot-1".into(),
+            started_at: 0,
+            updated_at: 0,
+            output: None,
+            error: None,
+        };
+        assert!(!settle(&mut install.clone(), "boot-1", &on_b), "same boot");
+        assert!(settle(&mut install, "boot-2", &on_b));
+        assert_eq!(install.status, Status::Booted);
+        assert_eq!(install.to_version.as_deref(), Some("1.1"));
+
+        // The new slot failed to come up and the bootloader fell back to A.
+        install.boot_id = "boot-2".into();
+        let back_on_a = slot_info(Some(&json!({"booted": "A"})), os_release, "");
+        assert!(settle(&mut install, "boot-3", &back_on_a));
+        assert_eq!(install.status, Status::RolledBack);
+
+        install.status = Status::Installing;
+        install.boot_id = "boot-3".into();
+        assert!(settle(&mut install, "boot-3", &back_on_a));
+        assert_eq!(install.status, Status::Failed);
+        assert_eq!(
+            install.error.as_deref(),
+            Some("interrupted by an sctl restart")
+        );
+    }
+}
@@ -0,0 +1,638 @@
+//! Flight recorder: a rolling on-disk capture of the last few minutes.
+//!
+//! When a device reboots on its own, the activity ring, tunnel counters and
+//! session buffers go with it. With `[flight_recorder]` configured,
+//! [`FlightRecorder`] keeps the last `minutes` of what happened on persistent
+//! storage as NDJSON records `{"ts": <ms>, "kind": ..., "data": ...}`:
+//!
+//! | `kind`    | `data`                                                        |
+//! |-----------|---------------------------------------------------------------|
+//! | `start`   | Version, pid and serial, once per process                     |
+//! | `event`   | Every `session_events` broadcast (activity, session lifecycle, AI, infra) except transfer progress |
+//! | `tunnel`  | Tunnel connection events (`connected`, `pong_timeout`, ...)   |
+//! | `session` | Per session with new output since the last sample: entry and byte counts, the last output |
+//! | `system`  | Load average, available memory, uptime                        |
+//! | `stop`    | Clean shutdown                                                |
+//! | `panic`   | Panic message and location (dumps only)                       |
+//!
+//! `session` and `system` are sampled every [`SAMPLE_INTERVAL`].
+//!
+//! ## Design
+//!
+//! - **Segments**: records are queued in memory and appended by a writer task
+//!   to `<dir>/<start_ms>.ndjson`, synced every [`SYNC_INTERVAL`], so a power
+//!   cut loses seconds rather than the window. Every `segment_secs` the
+//!   writer starts a new segment, compresses closed ones with the system
+//!   `gzip` and deletes those that ended more than `minutes` ago.
+//! - **Dumps**: a dump freezes the window in `<dir>/dumps/<ms>-<reason>/`:
+//!   closed segments are hard-linked, the live one copied, and records still
+//!   queued written to `<ms>-pending.ndjson`. Dumps are taken on
+//!   `POST /api/flightrecorder/dump`, from the panic hook, and at startup
+//!   when the previous run did not shut down cleanly (`unclean_shutdown`).
+//!   The newest `max_dumps` are kept. `zcat -f <dump>/*` prints the records
+//!   in order.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::FlightRecorderConfig;
+use crate::sessions::buffer::OutputStream;
+use crate::sessions::journal::now_ms;
+use crate::AppState;
+
+/// Interval between `session` and `system` samples.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval between syncs of the live segment.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Records held in memory while the writer is behind; oldest dropped first.
+const MAX_QUEUED: usize = 10_000;
+
+/// Characters of output kept per `session` sample.
+const TAIL_CHARS: usize = 400;
+
+/// Event types that are not recorded.
+const SKIP_TYPES: &[&str] = &["gx.progress"];
+
+/// Marker written on clean shutdown; its absence at startup means the
+/// previous run crashed or lost power.
+const CLEAN_MARKER: &str = "clean_shutdown";
+
+/// Timeout for compressing one segment.
+const GZIP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A frozen copy of the window.
+#[derive(Debug, Clone, Serialize)]
+pub struct Dump {
+    pub name: String,
+    pub path: String,
+    pub reason: String,
+    /// Epoch milliseconds.
+    pub created_at: u64,
+    /// File names inside the dump directory, oldest first.
+    pub files: Vec<String>,
+    pub bytes: u64,
+}
+
+/// Rolling capture state shared by the writer, the sources and the routes.
+pub struct FlightRecorder {
+    config: FlightRecorderConfig,
+    dir: PathBuf,
+    queue: Mutex<VecDeque<String>>,
+    notify: Notify,
+    dropped: AtomicU64,
+}
+
+impl FlightRecorder {
+    /// Open the recorder directory. If segments from a run that did not shut
+    /// down cleanly are found, they are dumped first.
+    pub fn open(config: FlightRecorderConfig, data_dir: &str, serial: &str) -> Arc<Self> {
+        let dir = config
+            .dir
+            .as_ref()
+            .map_or_else(|| Path::new(data_dir).join("flightrecorder"), PathBuf::from);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("Flight recorder: cannot create {}: {e}", dir.display());
+        }
+        let recorder = Arc::new(Self {
+            config,
+            dir,
+            queue: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            dropped: AtomicU64::new(0),
+        });
+
+        let marker = recorder.dir.join(CLEAN_MARKER);
+        let clean = marker.exists();
+        let _ = std::fs::remove_file(&marker);
+        if !clean && !list_segments(&recorder.dir).is_empty() {
+            match recorder.dump_with("unclean_shutdown", &[]) {
+                Ok(dump) => warn!(
+                    "Flight recorder: previous run did not shut down cleanly, saved {}",
+                    dump.path
+                ),
+                Err(e) => warn!("Flight recorder: failed to save previous run: {e}"),
+            }
+        }
+
+        recorder.record(
+            "start",
+            json!({
+                "version": crate::VERSION,
+                "pid": std::process::id(),
+                "serial": serial,
+            }),
+        );
+        recorder
+    }
+
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, VecDeque<String>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue a record for the writer.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn record(&self, kind: &str, data: Value) {
+        let mut line = json!({ "ts": now_ms(), "kind": kind, "data": data }).to_string();
+        line.push('\n');
+        {
+            let mut queue = self.lock_queue();
+            if queue.len() >= MAX_QUEUED {
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(line);
+        }
+        self.notify.notify_one();
+    }
+
+    /// Start the event subscriber, the sampler and the writer, and chain the
+    /// panic hook. Returns the handles for abort on shutdown.
+    pub fn spawn(self: &Arc<Self>, state: &AppState) -> [JoinHandle<()>; 3] {
+        self.install_panic_hook();
+
+        let recorder = self.clone();
+        let mut rx = state.session_events.subscribe();
+        let subscriber = tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        let msg_type = event["type"].as_str().unwrap_or("");
+                        if !SKIP_TYPES.contains(&msg_type) {
+                            recorder.record("event", event);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        recorder.dropped.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let sampler = tokio::spawn(self.clone().run_sampler(state.clone()));
+        let writer = tokio::spawn(self.clone().run_writer());
+        [subscriber, sampler, writer]
+    }
+
+    /// Dump from the panic hook, before the previous hook runs (and, with
+    /// `panic = "abort"`, the process ends).
+    fn install_panic_hook(self: &Arc<Self>) {
+        let recorder = self.clone();
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Never block in the panic hook: the panicking thread may hold the lock.
+            let mut pending: Vec<String> = recorder
+                .queue
+                .try_lock()
+                .map(|q| q.iter().cloned().collect())
+                .unwrap_or_default();
+            let mut line = json!({
+                "ts": now_ms(),
+                "kind": "panic",
+                "data": {
+                    "message": info.to_string(),
+                    "thread": std::thread::current().name().unwrap_or("<unnamed>"),
+                },
+            })
+            .to_string();
+            line.push('\n');
+            pending.push(line);
+            let _ = recorder.dump_with("panic", &pending);
+            prev(info);
+        }));
+    }
+
+    /// Freeze the current window, including records not yet written.
+    pub fn dump(&self, reason: &str) -> std::io::Result<Dump> {
+        let pending: Vec<String> = self.lock_queue().iter().cloned().collect();
+        self.dump_with(reason, &pending)
+    }
+
+    fn dump_with(&self, reason: &str, pending: &[String]) -> std::io::Result<Dump> {
+        let created_at = now_ms();
+        let name = format!("{created_at}-{reason}");
+        let dumps = self.dir.join("dumps");
+        let path = dumps.join(&name);
+        std::fs::create_dir_all(&path)?;
+
+        let mut files = Vec::new();
+        for (_, segment) in list_segments(&self.dir) {
+            let Some(file_name) = segment.file_name() else {
+                continue;
+            };
+            let target = path.join(file_name);
+            // Gzipped segments are final and can share their blocks; the live
+            // segment is still being appended to and must be copied.
+            let is_closed = segment.extension().is_some_and(|e| e == "gz");
+            let linked = is_closed && std::fs::hard_link(&segment, &target).is_ok();
+            if !linked {
+                if let Err(e) = std::fs::copy(&segment, &target) {
+                    // Rotated away since the listing.
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                    continue;
+                }
+            }
+            files.push(file_name.to_string_lossy().into_owned());
+        }
+        if !pending.is_empty() {
+            let file_name = format!("{created_at}-pending.ndjson");
+            let mut file = std::fs::File::create(path.join(&file_name))?;
+            for line in pending {
+                file.write_all(line.as_bytes())?;
+            }
+            file.sync_all()?;
+            files.push(file_name);
+        }
+
+        let bytes = files
+            .iter()
+            .filter_map(|f| std::fs::metadata(path.join(f)).ok())
+            .map(|m| m.len())
+            .sum();
+        prune_dumps(&dumps, self.config.max_dumps);
+        Ok(Dump {
+            name,
+            path: path.to_string_lossy().into_owned(),
+            reason: reason.to_string(),
+            created_at,
+            files,
+            bytes,
+        })
+    }
+
+    /// Segments, dumps and counters for `GET /api/flightrecorder`.
+    pub fn status(&self) -> Value {
+        let segments: Vec<Value> = list_segments(&self.dir)
+            .into_iter()
+            .map(|(start_ms, path)| {
+                json!({
+                    "name": path.file_name().map(|n| n.to_string_lossy().into_owned()),
+                    "start_ms": start_ms,
+                    "bytes": std::fs::metadata(&path).map_or(0, |m| m.len()),
+                })
+            })
+            .collect();
+        json!({
+            "dir": self.dir.to_string_lossy(),
+            "minutes": self.config.minutes,
+            "segment_secs": self.config.segment_secs,
+            "segments": segments,
+            "queued": self.lock_queue().len(),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "dumps": list_dumps(&self.dir.join("dumps")),
+        })
+    }
+
+    /// Write the remaining records and mark the shutdown as clean. Call after
+    /// the background tasks have been aborted.
+    pub async fn close(&self) {
+        self.record("stop", json!({}));
+        let lines: Vec<String> = self.lock_queue().drain(..).collect();
+        let path = list_segments(&self.dir)
+            .into_iter()
+            .map(|(_, path)| path)
+            .rfind(|p| p.extension().is_some_and(|e| e == "ndjson"))
+            .unwrap_or_else(|| self.dir.join(format!("{}.ndjson", now_ms())));
+        let result = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(lines.concat().as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::write(self.dir.join(CLEAN_MARKER), b"").await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Flight recorder: final write failed: {e}");
+        }
+    }
+
+    async fn run_writer(self: Arc<Self>) {
+        let segment_len = Duration::from_secs(self.config.segment_secs.max(1));
+        let mut rotate = tokio::time::interval(segment_len);
+        let mut sync = tokio::time::interval(SYNC_INTERVAL);
+        let mut segment: Option<tokio::fs::File> = None;
+        let mut dirty = false;
+        info!(
+            "Flight recorder: keeping {} min in {}",
+            self.config.minutes,
+            self.dir.display()
+        );
+
+        loop {
+            tokio::select! {
+                () = self.notify.notified() => {}
+                _ = sync.tick() => {
+                    if let Some(file) = segment.as_ref().filter(|_| dirty) {
+                        let _ = file.sync_data().await;
+                        dirty = false;
+                    }
+                    continue;
+                }
+                _ = rotate.tick() => {
+                    if let Some(file) = segment.take() {
+                        let _ = file.sync_all().await;
+                    }
+                    dirty = false;
+                    let recorder = self.clone();
+                    let _ = tokio::task::spawn_blocking(move || recorder.compact()).await;
+                    continue;
+                }
+            }
+
+            let lines: Vec<String> = self.lock_queue().drain(..).collect();
+            if lines.is_empty() {
+                continue;
+            }
+            if segment.is_none() {
+                let path = self.dir.join(format!("{}.ndjson", now_ms()));
+                match tokio::fs::File::create(&path).await {
+                    Ok(file) => segment = Some(file),
+                    Err(e) => {
+                        warn!("Flight recorder: cannot create {}: {e}", path.display());
+                        self.dropped
+                            .fetch_add(lines.len() as u64, Ordering::Relaxed);
+                        continue;
+                    }
+                }
+            }
+            if let Some(file) = segment.as_mut() {
+                if let Err(e) = file.write_all(lines.concat().as_bytes()).await {
+                    warn!("Flight recorder: write failed: {e}");
+                    segment = None;
+                } else {
+                    dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Compress closed segments and delete those outside the window. Runs on
+    /// the blocking pool between segments, when none is open.
+    fn compact(&self) {
+        let segments = list_segments(&self.dir);
+        let cutoff = now_ms().saturating_sub(self.config.minutes.saturating_mul(60_000));
+        // A segment ends where the next one starts.
+        for (i, (_, path)) in segments.iter().enumerate() {
+            let end = segments.get(i + 1).map_or(u64::MAX, |(start, _)| *start);
+            if end < cutoff {
+                let _ = std::fs::remove_file(path);
+            } else if path.extension().is_some_and(|e| e == "ndjson") {
+                gzip_file(path);
+            }
+        }
+    }
+
+    async fn run_sampler(self: Arc<Self>, state: AppState) {
+        let mut seen: HashMap<String, u64> = HashMap::new();
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.record("system", system_sample());
+
+            let sessions = state.session_manager.list_sessions().await;
+            seen.retain(|id, _| sessions.iter().any(|s| &s.session_id == id));
+            for session in sessions {
+                let Some(buffer) = state.session_manager.get_buffer(&session.session_id).await
+                else {
+                    continue;
+                };
+                let since = seen.get(&session.session_id).copied().unwrap_or(0);
+                let (entries, dropped) = buffer.lock().await.read_since(since);
+                let Some(last) = entries.last() else {
+                    continue;
+                };
+                seen.insert(session.session_id.clone(), last.seq);
+                let output: String = entries
+                    .iter()
+                    .filter(|e| e.stream != OutputStream::System)
+                    .map(|e| e.data.as_str())
+                    .collect();
+                self.record(
+                    "session",
+                    json!({
+                        "session_id": session.session_id,
+                        "name": session.name,
+                        "status": session.status,
+                        "entries": entries.len() as u64 + dropped,
+                        "bytes": entries.iter().map(|e| e.data.len()).sum::<usize>(),
+                        "tail": tail(&output, TAIL_CHARS),
+                    }),
+                );
+            }
+        }
+    }
+}
+
+/// Load, memory and uptime for a `system` record.
+fn system_sample() -> Value {
+    use crate::routes::info::{parse_loadavg, parse_meminfo, read_proc_file};
+
+    let (mem_total_kb, mem_available_kb) = parse_meminfo(&read_proc_file("/proc/meminfo"));
+    let uptime_secs = read_proc_file("/proc/uptime")
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok());
+    json!({
+        "load_average": parse_loadavg(&read_proc_file("/proc/loadavg")),
+        "mem_total_kb": mem_total_kb,
+        "mem_available_kb": mem_available_kb,
+        "uptime_secs": uptime_secs,
+    })
+}
+
+/// The last `max_chars` characters of `s`.
+fn tail(s: &str, max_chars: usize) -> &str {
+    let start = s
+        .char_indices()
+        .rev()
+        .nth(max_chars.saturating_sub(1))
+        .map_or(0, |(i, _)| i);
+    &s[start..]
+}
+
+/// Segment files (`<start_ms>.ndjson[.gz]`) in `dir`, oldest first.
+fn list_segments(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut segments: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stem = name
+                .strip_suffix(".ndjson.gz")
+                .or_else(|| name.strip_suffix(".ndjson"))?;
+            Some((stem.parse().ok()?, entry.path()))
+        })
+        .collect();
+    segments.sort();
+    segments
+}
+
+/// Dumps in `dumps_dir`, newest first.
+fn list_dumps(dumps_dir: &Path) -> Vec<Value> {
+    let Ok(entries) = std::fs::read_dir(dumps_dir) else {
+        return Vec::new();
+    };
+    let mut dumps: Vec<(u64, String, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let created_at = name.split_once('-')?.0.parse().ok()?;
+            Some((created_at, name, entry.path()))
+        })
+        .collect();
+    dumps.sort_by_key(|d| std::cmp::Reverse(d.0));
+    dumps
+        .into_iter()
+        .map(|(created_at, name, path)| {
+            let bytes: u64 = std::fs::read_dir(&path)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|f| f.metadata().ok())
+                .map(|m| m.len())
+                .sum();
+            json!({
+                "reason": name.split_once('-').map_or("", |(_, r)| r),
+                "name": name,
+                "path": path.to_string_lossy(),
+                "created_at": created_at,
+                "bytes": bytes,
+            })
+        })
+        .collect()
+}
+
+/// Delete all but the newest `keep` dumps.
+fn prune_dumps(dumps_dir: &Path, keep: usize) {
+    let Ok(entries) = std::fs::read_dir(dumps_dir) else {
+        return;
+    };
+    let mut dumps: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            Some((name.split_once('-')?.0.parse().ok()?, entry.path()))
+        })
+        .collect();
+    dumps.sort_by_key(|d| std::cmp::Reverse(d.0));
+    for (_, path) in dumps.into_iter().skip(keep.max(1)) {
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
+
+/// Compress `path` in place with `gzip` (leaving `<path>.gz`). Failures keep
+/// the plain file, which dumps and `zcat -f` handle as well.
+fn gzip_file(path: &Path) {
+    let child = std::process::Command::new("gzip")
+        .arg("-f")
+        .arg(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return;
+    };
+    let deadline = std::time::Instant::now() + GZIP_TIMEOUT;
+    while std::time::Instant::now() < deadline {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sctl-fr-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(dir: &Path) -> FlightRecorderConfig {
+        FlightRecorderConfig {
+            minutes: 15,
+            dir: Some(dir.to_string_lossy().into_owned()),
+            segment_secs: 60,
+            max_dumps: 2,
+        }
+    }
+
+    #[test]
+    fn tail_keeps_the_last_chars() {
+        assert_eq!(tail("hello world", 5), "world");
+        assert_eq!(tail("héllo", 4), "éllo");
+        assert_eq!(tail("ab", 10), "ab");
+    }
+
+    #[test]
+    fn unclean_restart_dumps_previous_segments() {
+        let dir = temp_dir("unclean");
+        std::fs::write(dir.join("1000.ndjson.gz"), b"gz").unwrap();
+        std::fs::write(dir.join("2000.ndjson"), b"{\"kind\":\"event\"}\n").unwrap();
+
+        let recorder = FlightRecorder::open(config(&dir), "/nonexistent", "DEV-1");
+        let dumps = list_dumps(&dir.join("dumps"));
+        assert_eq!(dumps.len(), 1);
+        assert_eq!(dumps[0]["reason"], "unclean_shutdown");
+
+        // The `start` record is still queued and goes into the next dump.
+        let dump = recorder.dump("manual").unwrap();
+        assert_eq!(dump.files.len(), 3);
+        assert_eq!(dump.files[..2], ["1000.ndjson.gz", "2000.ndjson"]);
+        assert!(dump.files[2].ends_with("-pending.ndjson"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clean_restart_does_not_dump() {
+        let dir = temp_dir("clean");
+        std::fs::write(dir.join("1000.ndjson"), b"\n").unwrap();
+        std::fs::write(dir.join(CLEAN_MARKER), b"").unwrap();
+
+        let _recorder = FlightRecorder::open(config(&dir), "/nonexistent", "DEV-1");
+        assert!(list_dumps(&dir.join("dumps")).is_empty());
+        assert!(!dir.join(CLEAN_MARKER).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn old_dumps_are_pruned() {
+        let dumps = temp_dir("prune");
+        for name in ["100-panic", "300-manual", "200-unclean_shutdown"] {
+            std::fs::create_dir_all(dumps.join(name)).unwrap();
+        }
+        prune_dumps(&dumps, 2);
+        let names: Vec<Value> = list_dumps(&dumps)
+            .into_iter()
+            .map(|d| d["name"].clone())
+            .collect();
+        assert_eq!(names, [json!("300-manual"), json!("200-unclean_shutdown")]);
+        let _ = std::fs::remove_dir_all(&dumps);
+    }
+}
@@ -0,0 +1,216 @@
+//! Directory transfers: a tree travels as one tar archive.
+//!
+//! A directory download tars the tree (or a manifest of files in it) into a
+//! temp file at init, then serves it like any other file, so chunking, hashes
+//! and resume are unchanged. A directory upload receives an archive the same
+//! way and unpacks it into the target once the whole-file hash checks out.
+//! Permissions and mtimes come along in the tar headers.
+//!
+//! Archives are made and unpacked with the system `tar` (GNU or `BusyBox`), as
+//! support bundles are. Entries that are absolute or climb out with `..` are
+//! refused before anything is unpacked, and so are symlinks and hard links
+//! whose target is — `tar` (`BusyBox` in particular) would otherwise happily
+//! write `d/passwd` through a `d -> /etc` member.
+
+use std::path::{Component, Path};
+
+use crate::infra::checks::exec_args_pub;
+
+/// Timeout for one `tar` run. Trees can be large and flash slow.
+const TAR_TIMEOUT_MS: u64 = 600_000;
+
+/// Check a download manifest: paths relative to the directory, without `..`,
+/// and nothing `tar -T` could read as an option or a second name.
+///
+/// # Errors
+///
+/// Names the first bad entry.
+pub fn validate_manifest(files: &[String]) -> Result<(), String> {
+    if files.is_empty() {
+        return Err("files must not be empty".to_string());
+    }
+    for file in files {
+        if file.is_empty() || file.starts_with('-') || file.contains(['\n', '\0']) {
+            return Err(format!("Invalid file in manifest: {file:?}"));
+        }
+        if !is_contained(file) {
+            return Err(format!(
+                "Manifest entries must be relative without '..': {file}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether an archive entry stays inside the directory it is unpacked into.
+fn is_contained(entry: &str) -> bool {
+    Path::new(entry)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// The target of a link entry in a `tar -tv` line, given the entry's name
+/// from `tar -t`. GNU prints `name -> target` for symlinks and
+/// `name link to target` for hard links; `BusyBox` uses `->` for both.
+fn link_target<'a>(name: &str, line: &'a str) -> Option<&'a str> {
+    [" -> ", " link to "].iter().find_map(|sep| {
+        let marker = format!(" {name}{sep}");
+        line.find(&marker).map(|i| &line[i + marker.len()..])
+    })
+}
+
+/// Check one entry of an archive listing: its name, and for links the target.
+fn check_entry(name: &str, verbose: &str) -> Result<(), String> {
+    if !is_contained(name) {
+        return Err(format!("archive entry escapes the target: {name}"));
+    }
+    match link_target(name, verbose) {
+        Some(target) if !is_contained(target) => Err(format!(
+            "archive link escapes the target: {name} -> {target}"
+        )),
+        None if verbose.starts_with(['l', 'h']) => {
+            Err(format!("archive link target unreadable: {name}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Tar `dir` into `out`: everything, or just `files` (relative to `dir`).
+///
+/// # Errors
+///
+/// `tar` failed, e.g. a manifest entry doesn't exist or the disk is full.
+pub async fn pack(dir: &Path, files: Option<&[String]>, out: &Path) -> Result<(), String> {
+    let out_str = out.to_string_lossy();
+    let dir_str = dir.to_string_lossy();
+    let list = out.with_extension("list");
+    let list_str = list.to_string_lossy();
+    let mut args = vec!["-cf", &out_str, "-C", &dir_str];
+    if let Some(files) = files {
+        let mut text = files.join("\n");
+        text.push('\n');
+        tokio::fs::write(&list, text)
+            .await
+            .map_err(|e| format!("write manifest: {e}"))?;
+        args.extend(["-T", &list_str]);
+    } else {
+        args.push(".");
+    }
+    let result = exec_args_pub("tar", &args, TAR_TIMEOUT_MS).await;
+    if files.is_some() {
+        let _ = tokio::fs::remove_file(&list).await;
+    }
+    match result? {
+        (0, _, _) => Ok(()),
+        (code, _, stderr) => Err(format!("tar exited with {code}: {}", stderr.trim())),
+    }
+}
+
+/// List `archive` with `tar <flag>`.
+async fn list(archive: &str, flag: &str) -> Result<String, String> {
+    match exec_args_pub("tar", &[flag, archive], TAR_TIMEOUT_MS).await? {
+        (0, stdout, _) => Ok(stdout),
+        (code, _, stderr) => Err(format!("not a tar archive ({code}): {}", stderr.trim())),
+    }
+}
+
+/// Unpack `archive` into `dest`, which must exist, after checking that
+/// every entry and every link target stays inside it.
+///
+/// # Errors
+///
+/// The archive is unreadable, has an escaping entry or link, or `tar` failed.
+pub async fn unpack(archive: &Path, dest: &Path) -> Result<(), String> {
+    let archive_str = archive.to_string_lossy();
+    let dest_str = dest.to_string_lossy();
+    let names = list(&archive_str, "-tf").await?;
+    let verbose = list(&archive_str, "-tvf").await?;
+    if names.lines().count() != verbose.lines().count() {
+        return Err("archive listing is ambiguous".to_string());
+    }
+    for (name, line) in names.lines().zip(verbose.lines()) {
+        check_entry(name, line)?;
+    }
+    match exec_args_pub(
+        "tar",
+        &["-xf", &archive_str, "-C", &dest_str],
+        TAR_TIMEOUT_MS,
+    )
+    .await?
+    {
+        (0, _, _) => Ok(()),
+        (code, _, stderr) => Err(format!("tar exited with {code}: {}", stderr.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_and_entries_stay_inside() {
+        let ok = |files: &[&str]| {
+            validate_manifest(&files.iter().map(ToString::to_string).collect::<Vec<_>>())
+        };
+        assert!(ok(&["etc/config/network", "./www/index.html", "bin"]).is_ok());
+        assert!(ok(&[]).is_err());
+        assert!(ok(&["/etc/shadow"]).is_err());
+        assert!(ok(&["logs/../../etc/shadow"]).is_err());
+        assert!(ok(&["-C/"]).is_err());
+        assert!(ok(&["a\nb"]).is_err());
+
+        assert!(is_contained("./app/"));
+        assert!(is_contained("app/bin/run"));
+        assert!(!is_contained("../outside"));
+        assert!(!is_contained("/etc/passwd"));
+    }
+
+    #[test]
+    fn link_targets_are_checked() {
+        let gnu = "lrwxrwxrwx root/root         0 2024-01-01 00:00 d -> /etc";
+        assert_eq!(link_target("d", gnu), Some("/etc"));
+        assert!(check_entry("d", gnu).is_err());
+
+        let gnu_hard = "hrw-r--r-- root/root         0 2024-01-01 00:00 g link to ../f";
+        assert_eq!(link_target("g", gnu_hard), Some("../f"));
+        assert!(check_entry("g", gnu_hard).is_err());
+
+        let busybox_hard = "-rw-r--r-- 0/0         0 2024-01-01 00:00:00 g -> /etc/shadow";
+        assert!(check_entry("g", busybox_hard).is_err());
+
+        let inside = "lrwxrwxrwx root/root         0 2024-01-01 00:00 my app -> bin/run";
+        assert_eq!(link_target("my app", inside), Some("bin/run"));
+        assert!(check_entry("my app", inside).is_ok());
+
+        let file = "-rw-r--r-- root/root         3 2024-01-01 00:00 notes";
+        assert!(check_entry("notes", file).is_ok());
+        assert!(check_entry("x", "lrwxrwxrwx garbage").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unpack_refuses_escaping_symlink() {
+        let root = std::env::temp_dir().join(format!("sctl-archive-{}", std::process::id()));
+        let src = root.join("src");
+        let dest = root.join("dest");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(src.join("f"), "hi").unwrap();
+        std::os::unix::fs::symlink("f", src.join("ok")).unwrap();
+        let good = root.join("good.tar");
+        pack(&src, Some(&["f".to_string(), "ok".to_string()]), &good)
+            .await
+            .unwrap();
+        unpack(&good, &dest).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dest.join("ok")).unwrap(), "hi");
+
+        std::os::unix::fs::symlink("/etc", src.join("d")).unwrap();
+        let bad = root.join("bad.tar");
+        pack(&src, Some(&["d".to_string()]), &bad).await.unwrap();
+        let err = unpack(&bad, &dest).await.unwrap_err();
+        assert!(err.contains("d -> /etc"), "{err}");
+        assert!(!dest.join("d").exists());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -3,43 +3,44 @@
 //! All functions stream data in 64 KiB blocks — never loads a full file into memory.
 
 use sha2::{Digest, Sha256};
-use std::io;
+use std::io::{self, Read, Seek};
 use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 const BUF_SIZE: usize = 64 * 1024; // 64 KiB
 
 /// Compute SHA-256 of an entire file by streaming. Returns lowercase hex string.
+///
+/// Runs on the [`crate::io_pool`], so hashing a large file doesn't stall the runtime.
 pub async fn hash_file(path: &Path) -> io::Result<String> {
-    let mut file = tokio::fs::File::open(path).await?;
-    let mut hasher = Sha256::new();
-    let mut buf = vec![0u8; BUF_SIZE];
-    loop {
-        let n = file.read(&mut buf).await?;
-        if n == 0 {
-            break;
-        }
-        hasher.update(&buf[..n]);
-    }
-    Ok(hex::encode(hasher.finalize()))
+    let path = path.to_path_buf();
+    crate::io_pool::run(move || hash_reader(std::fs::File::open(path)?, u64::MAX)).await
 }
 
 /// Compute SHA-256 of a file region (for chunk serving). Returns lowercase hex string.
 #[allow(dead_code)]
 pub async fn hash_file_region(path: &Path, offset: u64, len: usize) -> io::Result<String> {
-    let mut file = tokio::fs::File::open(path).await?;
-    file.seek(io::SeekFrom::Start(offset)).await?;
+    let path = path.to_path_buf();
+    crate::io_pool::run(move || {
+        let mut file = std::fs::File::open(path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+        hash_reader(file, len as u64)
+    })
+    .await
+}
+
+/// Hash up to `len` bytes from `reader` in 64 KiB blocks.
+fn hash_reader(reader: impl Read, len: u64) -> io::Result<String> {
+    let mut reader = reader.take(len);
     let mut hasher = Sha256::new();
-    let mut remaining = len;
-    let mut buf = vec![0u8; BUF_SIZE.min(remaining)];
-    while remaining > 0 {
-        let to_read = buf.len().min(remaining);
-        let n = file.read(&mut buf[..to_read]).await?;
-        if n == 0 {
-            break;
-        }
+    let mut buf = vec![0u8; BUF_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
         hasher.update(&buf[..n]);
-        remaining -= n;
     }
     Ok(hex::encode(hasher.finalize()))
 }
@@ -52,7 +53,7 @@ pub fn hash_bytes(data: &[u8]) -> String {
 }
 
 /// Hex-encode a byte slice (replacement for the `hex` crate, to avoid extra deps).
-mod hex {
+pub mod hex {
     pub fn encode(bytes: impl AsRef<[u8]>) -> String {
         bytes.as_ref().iter().fold(
             String::with_capacity(bytes.as_ref().len() * 2),
@@ -5,22 +5,27 @@
 //! chunks by seek+read from the source file.
 
 use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use serde_json::{json, Value};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+use super::archive;
 use super::hasher;
+use super::throttle::{effective_rate, TokenBucket};
 use super::types::{
     ChunkAck, ChunkHeader, Complete, Direction, InitDownloadResult, InitUpload, InitUploadResult,
-    ListResult, Phase, Progress, ResumeResult, StatusResult, TransferConfig, TransferError,
-    TransferProgress, TransferSpec, TransferSummary,
+    ListResult, Phase, Progress, ResumeResult, StatusResult, Throttle, TransferConfig,
+    TransferError, TransferProgress, TransferSpec, TransferSummary,
 };
 use crate::activity::{ActivityLog, ActivitySource, ActivityType};
+use crate::quiet_hours::QuietHours;
 
 /// Owns the set of active transfers and their lifecycle.
 pub struct TransferManager {
@@ -28,11 +33,20 @@ pub struct TransferManager {
     config: TransferConfig,
     progress_tx: broadcast::Sender<Value>,
     activity_log: Arc<ActivityLog>,
+    bytes_uploaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    /// Shared by every transfer (see [`super::throttle`]).
+    global_bucket: std::sync::Mutex<TokenBucket>,
+    /// Defers or caps transfers inside `[quiet_hours]` windows.
+    quiet_hours: Option<Arc<QuietHours>>,
+    /// Charged only while a throttling quiet period is running.
+    quiet_bucket: std::sync::Mutex<TokenBucket>,
 }
 
 struct Transfer {
     spec: TransferSpec,
     progress: TransferProgress,
+    bucket: TokenBucket,
 }
 
 impl TransferManager {
@@ -43,72 +57,177 @@ impl TransferManager {
     ) -> Self {
         Self {
             transfers: RwLock::new(HashMap::new()),
+            global_bucket: std::sync::Mutex::new(TokenBucket::new(config.global_rate_limit_bps)),
+            quiet_hours: None,
+            quiet_bucket: std::sync::Mutex::new(TokenBucket::new(0)),
             config,
             progress_tx,
             activity_log,
+            bytes_uploaded: AtomicU64::new(0),
+            bytes_downloaded: AtomicU64::new(0),
         }
     }
 
+    /// Hold transfers back inside the `quiet` windows.
+    #[must_use]
+    pub fn with_quiet_hours(mut self, quiet: Arc<QuietHours>) -> Self {
+        self.quiet_bucket = std::sync::Mutex::new(TokenBucket::new(quiet.rate_limit_bps()));
+        self.quiet_hours = Some(quiet);
+        self
+    }
+
+    /// Refuse a chunk while quiet hours defer transfers.
+    fn check_quiet(&self, transfer_id: &str) -> Result<(), TransferError> {
+        match self.quiet_hours.as_ref().and_then(|q| q.deferring()) {
+            Some(until) => Err(make_error(
+                transfer_id,
+                "QUIET_HOURS",
+                &format!(
+                    "Deferred by quiet hours until {}",
+                    crate::util::format_iso8601_utc(until)
+                ),
+                true,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// `deferred` for a running transfer while quiet hours defer it.
+    fn phase_str<'a>(&self, phase: &'a Phase) -> &'a str {
+        let deferred = matches!(phase, Phase::Transferring)
+            && self
+                .quiet_hours
+                .as_ref()
+                .is_some_and(|q| q.deferring().is_some());
+        if deferred {
+            "deferred"
+        } else {
+            phase.as_str()
+        }
+    }
+
+    /// Chunk bytes received and served since start, `(upload, download)`.
+    pub fn bytes_total(&self) -> (u64, u64) {
+        (
+            self.bytes_uploaded.load(Ordering::Relaxed),
+            self.bytes_downloaded.load(Ordering::Relaxed),
+        )
+    }
+
     // ─── Download Init ───────────────────────────────────────────────────────
 
     pub async fn init_download(
         &self,
         path: &str,
         chunk_size: Option<u32>,
+        rate_limit_bps: Option<u64>,
     ) -> Result<InitDownloadResult, TransferError> {
         let validated = validate_transfer_path(path)?;
-
-        let metadata = tokio::fs::metadata(&validated).await.map_err(|e| {
-            let (code, msg) = match e.kind() {
-                std::io::ErrorKind::NotFound => ("FILE_NOT_FOUND", "File not found"),
-                std::io::ErrorKind::PermissionDenied => ("PERMISSION_DENIED", "Permission denied"),
-                _ => ("IO_ERROR", "I/O error"),
-            };
-            make_error("", code, &format!("{msg}: {e}"), false)
-        })?;
+        let metadata = source_metadata(&validated).await?;
 
         if metadata.is_dir() {
-            return Err(make_error("", "INVALID_PATH", "Path is a directory", false));
+            return Err(make_error(
+                "",
+                "INVALID_PATH",
+                "Path is a directory (set directory: true to download it as a tar archive)",
+                false,
+            ));
         }
 
-        let file_size = metadata.len();
-        if file_size > self.config.max_file_size {
+        self.check_file_size(metadata.len())?;
+        self.check_capacity().await?;
+
+        let filename = validated.file_name().map_or_else(
+            || "download".to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        self.start_download(
+            validated,
+            filename,
+            PathBuf::new(),
+            chunk_size,
+            false,
+            rate_limit_bps,
+        )
+        .await
+    }
+
+    /// Download the directory at `path` (or just `files` in it) as a tar
+    /// archive. The archive is built in the temp dir before this returns and
+    /// removed when the transfer is aborted or swept.
+    pub async fn init_directory_download(
+        &self,
+        path: &str,
+        files: Option<&[String]>,
+        chunk_size: Option<u32>,
+        rate_limit_bps: Option<u64>,
+    ) -> Result<InitDownloadResult, TransferError> {
+        let validated = validate_transfer_path(path)?;
+        if !source_metadata(&validated).await?.is_dir() {
             return Err(make_error(
                 "",
-                "FILE_TOO_LARGE",
-                &format!(
-                    "File too large ({file_size} bytes, max {})",
-                    self.config.max_file_size
-                ),
+                "INVALID_PATH",
+                "Path is not a directory",
                 false,
             ));
         }
-
-        // Check concurrent transfer limit
-        {
-            let transfers = self.transfers.read().await;
-            let active = transfers
-                .values()
-                .filter(|t| matches!(t.progress.phase, Phase::Init | Phase::Transferring))
-                .count();
-            if active >= self.config.max_concurrent {
-                return Err(make_error(
-                    "",
-                    "MAX_TRANSFERS",
-                    &format!(
-                        "Concurrent transfer limit reached (max {})",
-                        self.config.max_concurrent
-                    ),
-                    true,
-                ));
-            }
+        if let Some(files) = files {
+            archive::validate_manifest(files)
+                .map_err(|e| make_error("", "INVALID_REQUEST", &e, false))?;
         }
+        self.check_capacity().await?;
+
+        let archive_path =
+            std::env::temp_dir().join(format!(".gx_tmp_{}.tar", uuid::Uuid::new_v4().simple()));
+        let result = async {
+            archive::pack(&validated, files, &archive_path)
+                .await
+                .map_err(|e| {
+                    make_error("", "IO_ERROR", &format!("Failed to archive: {e}"), false)
+                })?;
+            let size = tokio::fs::metadata(&archive_path)
+                .await
+                .map_err(|e| make_error("", "IO_ERROR", &format!("Failed to archive: {e}"), false))?
+                .len();
+            self.check_file_size(size)?;
+            let name = validated
+                .file_name()
+                .map_or_else(|| "root".to_string(), |n| n.to_string_lossy().into_owned());
+            self.start_download(
+                archive_path.clone(),
+                format!("{name}.tar"),
+                archive_path.clone(),
+                chunk_size,
+                true,
+                rate_limit_bps,
+            )
+            .await
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&archive_path).await;
+        }
+        result
+    }
 
+    /// Hash `source` and register a download of it. `temp_path` is removed
+    /// with the transfer (the archive of a directory download).
+    async fn start_download(
+        &self,
+        source: PathBuf,
+        filename: String,
+        temp_path: PathBuf,
+        chunk_size: Option<u32>,
+        directory: bool,
+        rate_limit_bps: Option<u64>,
+    ) -> Result<InitDownloadResult, TransferError> {
+        let metadata = source_metadata(&source).await?;
+        let file_size = metadata.len();
         let chunk_size = chunk_size.unwrap_or(self.config.chunk_size);
         let total_chunks = compute_chunks(file_size, chunk_size);
 
         // Compute whole-file hash (streaming, 64KB blocks)
-        let file_hash = hasher::hash_file(&validated)
+        let file_hash = hasher::hash_file(&source)
             .await
             .map_err(|e| make_error("", "IO_ERROR", &format!("Failed to hash file: {e}"), false))?;
 
@@ -118,17 +237,12 @@ impl TransferManager {
             .and_then(|t| t.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
             .map(|d| d.as_secs());
 
-        let filename = validated.file_name().map_or_else(
-            || "download".to_string(),
-            |n| n.to_string_lossy().into_owned(),
-        );
-
         let transfer_id = uuid::Uuid::new_v4().to_string();
 
         let spec = TransferSpec {
             transfer_id: transfer_id.clone(),
             direction: Direction::Download,
-            path: validated,
+            path: source,
             filename: filename.clone(),
             file_size,
             file_hash: file_hash.clone(),
@@ -137,6 +251,7 @@ impl TransferManager {
             mode: None,
             created_at: Instant::now(),
             source_mtime,
+            directory,
         };
 
         let progress = TransferProgress {
@@ -144,14 +259,19 @@ impl TransferManager {
             chunks_done: vec![false; total_chunks as usize],
             bytes_transferred: 0,
             last_activity: Instant::now(),
-            temp_path: PathBuf::new(), // No temp file for downloads
+            temp_path, // Empty (nothing to clean up) unless an archive was built
             error_count: 0,
         };
-
-        self.transfers
-            .write()
-            .await
-            .insert(transfer_id.clone(), Transfer { spec, progress });
+        let bucket = TokenBucket::new(effective_rate(rate_limit_bps, self.config.rate_limit_bps));
+
+        self.transfers.write().await.insert(
+            transfer_id.clone(),
+            Transfer {
+                spec,
+                progress,
+                bucket,
+            },
+        );
 
         info!(
             transfer_id = %transfer_id,
@@ -159,6 +279,7 @@ impl TransferManager {
             file_size,
             total_chunks,
             chunk_size,
+            directory,
             "Download init"
         );
 
@@ -173,6 +294,7 @@ impl TransferManager {
                     "filename": filename,
                     "file_size": file_size,
                     "total_chunks": total_chunks,
+                    "directory": directory,
                 })),
                 None,
             )
@@ -185,9 +307,46 @@ impl TransferManager {
             chunk_size,
             total_chunks,
             filename,
+            directory,
         })
     }
 
+    fn check_file_size(&self, file_size: u64) -> Result<(), TransferError> {
+        if file_size > self.config.max_file_size {
+            return Err(make_error(
+                "",
+                "FILE_TOO_LARGE",
+                &format!(
+                    "File too large ({file_size} bytes, max {})",
+                    self.config.max_file_size
+                ),
+                false,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuse a new transfer when `max_concurrent` are already running.
+    async fn check_capacity(&self) -> Result<(), TransferError> {
+        let transfers = self.transfers.read().await;
+        let active = transfers
+            .values()
+            .filter(|t| matches!(t.progress.phase, Phase::Init | Phase::Transferring))
+            .count();
+        if active >= self.config.max_concurrent {
+            return Err(make_error(
+                "",
+                "MAX_TRANSFERS",
+                &format!(
+                    "Concurrent transfer limit reached (max {})",
+                    self.config.max_concurrent
+                ),
+                true,
+            ));
+        }
+        Ok(())
+    }
+
     // ─── Upload Init ─────────────────────────────────────────────────────────
 
     #[allow(clippy::too_many_lines)]
@@ -227,40 +386,17 @@ impl TransferManager {
             ));
         }
 
-        if req.file_size > self.config.max_file_size {
-            return Err(make_error(
-                "",
-                "FILE_TOO_LARGE",
-                &format!(
-                    "File too large ({} bytes, max {})",
-                    req.file_size, self.config.max_file_size
-                ),
-                false,
-            ));
-        }
+        self.check_file_size(req.file_size)?;
+        self.check_capacity().await?;
 
-        // Check concurrent transfer limit
-        {
-            let transfers = self.transfers.read().await;
-            let active = transfers
-                .values()
-                .filter(|t| matches!(t.progress.phase, Phase::Init | Phase::Transferring))
-                .count();
-            if active >= self.config.max_concurrent {
-                return Err(make_error(
-                    "",
-                    "MAX_TRANSFERS",
-                    &format!(
-                        "Concurrent transfer limit reached (max {})",
-                        self.config.max_concurrent
-                    ),
-                    true,
-                ));
-            }
-        }
-
-        // Disk space pre-check via statvfs
-        check_disk_space(&dir_path, req.file_size)?;
+        // Disk space pre-check via statvfs; a tree needs room for the
+        // archive and its unpacked copy
+        let needed = if req.directory {
+            req.file_size.saturating_mul(2)
+        } else {
+            req.file_size
+        };
+        check_disk_space(&dir_path, needed)?;
 
         let chunk_size = req.chunk_size.max(1024); // Minimum 1 KiB
         let total_chunks = compute_chunks(req.file_size, chunk_size);
@@ -313,6 +449,7 @@ impl TransferManager {
             mode: req.mode,
             created_at: Instant::now(),
             source_mtime: None,
+            directory: req.directory,
         };
 
         let progress = TransferProgress {
@@ -323,11 +460,19 @@ impl TransferManager {
             temp_path: temp_path.clone(),
             error_count: 0,
         };
+        let bucket = TokenBucket::new(effective_rate(
+            req.rate_limit_bps,
+            self.config.rate_limit_bps,
+        ));
 
-        self.transfers
-            .write()
-            .await
-            .insert(transfer_id.clone(), Transfer { spec, progress });
+        self.transfers.write().await.insert(
+            transfer_id.clone(),
+            Transfer {
+                spec,
+                progress,
+                bucket,
+            },
+        );
 
         info!(
             transfer_id = %transfer_id,
@@ -363,12 +508,16 @@ impl TransferManager {
 
     // ─── Serve Chunk (Download) ──────────────────────────────────────────────
 
+    /// Read and hash one chunk. `cancel` is the request's deadline token;
+    /// once it fires the chunk is abandoned with a recoverable `TIMEOUT`.
     #[allow(clippy::too_many_lines)]
     pub async fn serve_chunk(
         &self,
         transfer_id: &str,
         chunk_index: u32,
+        cancel: &CancellationToken,
     ) -> Result<(ChunkHeader, Vec<u8>), TransferError> {
+        self.check_quiet(transfer_id)?;
         let transfers = self.transfers.read().await;
         let transfer = transfers.get(transfer_id).ok_or_else(|| {
             make_error(
@@ -441,36 +590,41 @@ impl TransferManager {
 
         drop(transfers); // Release lock during I/O
 
-        // Read chunk from disk
-        let mut file = tokio::fs::File::open(&source_path).await.map_err(|e| {
-            make_error(
-                transfer_id,
-                "IO_ERROR",
-                &format!("Failed to open source: {e}"),
-                false,
-            )
-        })?;
-        file.seek(std::io::SeekFrom::Start(offset))
-            .await
-            .map_err(|e| {
-                make_error(transfer_id, "IO_ERROR", &format!("Seek failed: {e}"), false)
-            })?;
-        let mut buf = vec![0u8; chunk_len];
-        file.read_exact(&mut buf).await.map_err(|e| {
-            make_error(transfer_id, "IO_ERROR", &format!("Read failed: {e}"), false)
-        })?;
+        if cancel.is_cancelled() {
+            return Err(deadline_exceeded(transfer_id));
+        }
 
-        let chunk_hash = hasher::hash_bytes(&buf);
+        // Read and hash the chunk on the I/O pool
+        let (buf, chunk_hash) = crate::io_pool::run(move || {
+            let mut file = std::fs::File::open(&source_path)
+                .map_err(|e| with_context(&e, "Failed to open source"))?;
+            file.seek(SeekFrom::Start(offset))
+                .map_err(|e| with_context(&e, "Seek failed"))?;
+            let mut buf = vec![0u8; chunk_len];
+            file.read_exact(&mut buf)
+                .map_err(|e| with_context(&e, "Read failed"))?;
+            let chunk_hash = hasher::hash_bytes(&buf);
+            Ok((buf, chunk_hash))
+        })
+        .await
+        .map_err(|e| make_error(transfer_id, "IO_ERROR", &e.to_string(), false))?;
+        if cancel.is_cancelled() {
+            return Err(deadline_exceeded(transfer_id));
+        }
 
         // Update progress
+        let mut delay = Duration::ZERO;
         {
             let mut transfers = self.transfers.write().await;
             if let Some(t) = transfers.get_mut(transfer_id) {
+                delay = self.charge(t, chunk_len as u64);
                 t.progress.last_activity = Instant::now();
                 if let Some(slot) = t.progress.chunks_done.get_mut(chunk_index as usize) {
                     *slot = true;
                 }
                 t.progress.bytes_transferred += chunk_len as u64;
+                self.bytes_downloaded
+                    .fetch_add(chunk_len as u64, Ordering::Relaxed);
 
                 // Mark download complete when all chunks have been served
                 let all_done = t.progress.chunks_done.iter().all(|&v| v);
@@ -522,6 +676,7 @@ impl TransferManager {
                 self.emit_progress(t);
             }
         }
+        pace(delay, cancel).await;
 
         Ok((
             ChunkHeader {
@@ -535,6 +690,9 @@ impl TransferManager {
 
     // ─── Receive Chunk (Upload) ──────────────────────────────────────────────
 
+    /// Verify and write one chunk. `cancel` is the request's deadline token;
+    /// it is checked once more after hashing so a chunk the client has given
+    /// up on is never committed. The final whole-file verify is not cancelled.
     #[allow(clippy::too_many_lines)]
     pub async fn receive_chunk(
         &self,
@@ -542,7 +700,9 @@ impl TransferManager {
         chunk_index: u32,
         chunk_hash: &str,
         data: &[u8],
+        cancel: &CancellationToken,
     ) -> Result<ChunkAck, TransferError> {
+        self.check_quiet(transfer_id)?;
         let (offset, _chunk_size, temp_path, total_chunks, file_hash, file_size, final_path, mode) = {
             let transfers = self.transfers.read().await;
             let transfer = transfers.get(transfer_id).ok_or_else(|| {
@@ -600,9 +760,33 @@ impl TransferManager {
             )
         };
 
-        // Verify chunk hash
-        let actual_hash = hasher::hash_bytes(data);
-        if actual_hash != chunk_hash {
+        if cancel.is_cancelled() {
+            return Err(deadline_exceeded(transfer_id));
+        }
+
+        // Verify the chunk hash and write it at its offset on the I/O pool
+        let chunk = data.to_vec();
+        let expected_hash = chunk_hash.to_string();
+        let write_path = temp_path.clone();
+        let verified = crate::io_pool::run(move || {
+            if hasher::hash_bytes(&chunk) != expected_hash {
+                return Ok(false);
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(&write_path)
+                .map_err(|e| with_context(&e, "Failed to open temp file"))?;
+            file.seek(SeekFrom::Start(offset))
+                .map_err(|e| with_context(&e, "Seek failed"))?;
+            file.write_all(&chunk)
+                .map_err(|e| with_context(&e, "Write failed"))?;
+            file.sync_data()
+                .map_err(|e| with_context(&e, "Sync failed"))?;
+            Ok(true)
+        })
+        .await
+        .map_err(|e| make_error(transfer_id, "IO_ERROR", &e.to_string(), false))?;
+        if !verified {
             let mut transfers = self.transfers.write().await;
             if let Some(t) = transfers.get_mut(transfer_id) {
                 t.progress.error_count += 1;
@@ -619,38 +803,8 @@ impl TransferManager {
             });
         }
 
-        // Write chunk to temp file at correct offset
-        let mut file = tokio::fs::OpenOptions::new()
-            .write(true)
-            .open(&temp_path)
-            .await
-            .map_err(|e| {
-                make_error(
-                    transfer_id,
-                    "IO_ERROR",
-                    &format!("Failed to open temp file: {e}"),
-                    false,
-                )
-            })?;
-        file.seek(std::io::SeekFrom::Start(offset))
-            .await
-            .map_err(|e| {
-                make_error(transfer_id, "IO_ERROR", &format!("Seek failed: {e}"), false)
-            })?;
-        file.write_all(data).await.map_err(|e| {
-            make_error(
-                transfer_id,
-                "IO_ERROR",
-                &format!("Write failed: {e}"),
-                false,
-            )
-        })?;
-        file.sync_data().await.map_err(|e| {
-            make_error(transfer_id, "IO_ERROR", &format!("Sync failed: {e}"), false)
-        })?;
-
         // Update progress
-        let all_done = {
+        let (all_done, delay) = {
             let mut transfers = self.transfers.write().await;
             let t = transfers.get_mut(transfer_id).ok_or_else(|| {
                 make_error(
@@ -664,14 +818,17 @@ impl TransferManager {
                 *slot = true;
             }
             t.progress.bytes_transferred += data.len() as u64;
+            self.bytes_uploaded
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
             t.progress.last_activity = Instant::now();
+            let delay = self.charge(t, data.len() as u64);
 
             let all_done = t.progress.chunks_done.iter().all(|&v| v);
             if all_done {
                 t.progress.phase = Phase::Verifying;
             }
             self.emit_progress(t);
-            all_done
+            (all_done, delay)
         };
 
         // If all chunks received, verify whole-file hash
@@ -685,6 +842,8 @@ impl TransferManager {
                 mode.as_deref(),
             )
             .await?;
+        } else {
+            pace(delay, cancel).await;
         }
 
         Ok(ChunkAck {
@@ -695,7 +854,8 @@ impl TransferManager {
         })
     }
 
-    /// Verify whole-file hash and atomically move temp → final.
+    /// Verify whole-file hash and atomically move temp → final, or unpack it
+    /// there for a directory upload.
     async fn verify_and_finalize(
         &self,
         transfer_id: &str,
@@ -739,18 +899,47 @@ impl TransferManager {
             ));
         }
 
-        // Set file permissions if specified
-        if let Some(mode_str) = mode {
-            if let Ok(mode_val) = u32::from_str_radix(mode_str, 8) {
-                use std::os::unix::fs::PermissionsExt;
-                let perms = std::fs::Permissions::from_mode(mode_val);
-                let _ = tokio::fs::set_permissions(temp_path, perms).await;
+        let directory = self
+            .transfers
+            .read()
+            .await
+            .get(transfer_id)
+            .is_some_and(|t| t.spec.directory);
+        let mode = mode.and_then(|m| u32::from_str_radix(m, 8).ok());
+        let finalized = if directory {
+            // Unpack into the target directory; the archive itself is not kept
+            let result = async {
+                tokio::fs::create_dir_all(final_path)
+                    .await
+                    .map_err(|e| format!("create {}: {e}", final_path.display()))?;
+                archive::unpack(temp_path, final_path).await?;
+                // Permissions from `mode` go on the top directory
+                if let Some(mode) = mode {
+                    let _ = crate::util::set_mode(final_path, mode);
+                }
+                Ok(())
             }
-        }
-
-        // Atomic rename
-        if let Err(e) = tokio::fs::rename(temp_path, final_path).await {
+            .await;
             let _ = tokio::fs::remove_file(temp_path).await;
+            result
+        } else {
+            // Set file permissions if specified, then rename atomically
+            if let Some(mode) = mode {
+                let _ = crate::util::set_mode(temp_path, mode);
+            }
+            let result = tokio::fs::rename(temp_path, final_path)
+                .await
+                .map_err(|e| e.to_string());
+            if result.is_err() {
+                let _ = tokio::fs::remove_file(temp_path).await;
+            }
+            result
+        };
+        if let Err(e) = finalized {
+            let mut transfers = self.transfers.write().await;
+            if let Some(t) = transfers.get_mut(transfer_id) {
+                t.progress.phase = Phase::Failed(e.clone());
+            }
             return Err(make_error(
                 transfer_id,
                 "IO_ERROR",
@@ -906,7 +1095,7 @@ impl TransferManager {
         Ok(StatusResult {
             transfer_id: transfer_id.to_string(),
             direction: transfer.spec.direction,
-            phase: transfer.progress.phase.as_str().to_string(),
+            phase: self.phase_str(&transfer.progress.phase).to_string(),
             filename: transfer.spec.filename.clone(),
             file_size: transfer.spec.file_size,
             chunks_done,
@@ -914,9 +1103,74 @@ impl TransferManager {
             bytes_transferred: transfer.progress.bytes_transferred,
             elapsed_ms,
             error_count: transfer.progress.error_count,
+            rate_limit_bps: transfer.bucket.rate_bps(),
         })
     }
 
+    // ─── Throttle ────────────────────────────────────────────────────────────
+
+    /// Change a transfer's bandwidth cap (kept within the configured
+    /// per-transfer cap), or the global cap when `transfer_id` is `None`.
+    /// Takes effect from the next chunk.
+    pub async fn throttle(
+        &self,
+        transfer_id: Option<&str>,
+        rate_limit_bps: u64,
+    ) -> Result<Throttle, TransferError> {
+        let Some(transfer_id) = transfer_id else {
+            self.global_bucket
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .set_rate(rate_limit_bps);
+            info!(rate_limit_bps, "Global transfer rate limit changed");
+            return Ok(Throttle {
+                transfer_id: None,
+                rate_limit_bps,
+            });
+        };
+        let mut transfers = self.transfers.write().await;
+        let transfer = transfers.get_mut(transfer_id).ok_or_else(|| {
+            make_error(
+                transfer_id,
+                "TRANSFER_NOT_FOUND",
+                "Transfer not found",
+                false,
+            )
+        })?;
+        let rate = effective_rate(Some(rate_limit_bps), self.config.rate_limit_bps);
+        transfer.bucket.set_rate(rate);
+        info!(transfer_id = %transfer_id, rate_limit_bps = rate, "Transfer rate limit changed");
+        Ok(Throttle {
+            transfer_id: Some(transfer_id.to_string()),
+            rate_limit_bps: rate,
+        })
+    }
+
+    /// Charge a chunk to the transfer's bucket and the global one (and the
+    /// quiet-hours one while it applies); returns how long its reply should
+    /// wait.
+    fn charge(&self, transfer: &mut Transfer, bytes: u64) -> Duration {
+        let own = transfer.bucket.take(bytes);
+        let global = self
+            .global_bucket
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take(bytes);
+        let quiet = if self
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|q| q.throttle_bps().is_some())
+        {
+            self.quiet_bucket
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .take(bytes)
+        } else {
+            Duration::ZERO
+        };
+        own.max(global).max(quiet)
+    }
+
     // ─── List ────────────────────────────────────────────────────────────────
 
     pub async fn list(&self) -> ListResult {
@@ -931,7 +1185,7 @@ impl TransferManager {
                     direction: t.spec.direction,
                     filename: t.spec.filename.clone(),
                     file_size: t.spec.file_size,
-                    phase: t.progress.phase.as_str().to_string(),
+                    phase: self.phase_str(&t.progress.phase).to_string(),
                     chunks_done,
                     total_chunks: t.spec.total_chunks,
                     bytes_transferred: t.progress.bytes_transferred,
@@ -1028,6 +1282,17 @@ impl TransferManager {
 
 // ─── Helpers ─────────────────────────────────────────────────────────────────
 
+/// Wait out a throttle delay, or until the request's deadline fires (the
+/// reply is no use after that anyway).
+async fn pace(delay: Duration, cancel: &CancellationToken) {
+    if !delay.is_zero() {
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {}
+            () = cancel.cancelled() => {}
+        }
+    }
+}
+
 /// Compute total chunks for a file of given size.
 pub fn compute_chunks(file_size: u64, chunk_size: u32) -> u32 {
     if file_size == 0 {
@@ -1039,6 +1304,18 @@ pub fn compute_chunks(file_size: u64, chunk_size: u32) -> u32 {
     }
 }
 
+/// `stat` a download source, mapping failures to transfer errors.
+async fn source_metadata(path: &Path) -> Result<std::fs::Metadata, TransferError> {
+    tokio::fs::metadata(path).await.map_err(|e| {
+        let (code, msg) = match e.kind() {
+            std::io::ErrorKind::NotFound => ("FILE_NOT_FOUND", "File not found"),
+            std::io::ErrorKind::PermissionDenied => ("PERMISSION_DENIED", "Permission denied"),
+            _ => ("IO_ERROR", "I/O error"),
+        };
+        make_error("", code, &format!("{msg}: {e}"), false)
+    })
+}
+
 /// Validate an absolute path (reuses logic from routes/files.rs).
 fn validate_transfer_path(path: &str) -> Result<PathBuf, TransferError> {
     let p = Path::new(path);
@@ -1072,11 +1349,9 @@ fn validate_transfer_path(path: &str) -> Result<PathBuf, TransferError> {
 }
 
 /// Check available disk space via statvfs.
-fn check_disk_space(path: &Path, required_bytes: u64) -> Result<(), TransferError> {
-    match nix::sys::statvfs::statvfs(path) {
-        Ok(stat) => {
-            #[allow(clippy::useless_conversion)]
-            let available = u64::from(stat.blocks_available()) * u64::from(stat.fragment_size());
+pub(crate) fn check_disk_space(path: &Path, required_bytes: u64) -> Result<(), TransferError> {
+    match crate::util::disk_space(path) {
+        Ok((_, available, _)) => {
             // Require file_size + 10% headroom
             let needed = required_bytes + required_bytes / 10;
             if available < needed {
@@ -1110,3 +1385,12 @@ fn make_error(transfer_id: &str, code: &str, message: &str, recoverable: bool) -
         recoverable,
     }
 }
+
+fn deadline_exceeded(transfer_id: &str) -> TransferError {
+    make_error(transfer_id, "TIMEOUT", "Request deadline exceeded", true)
+}
+
+/// Prefix an I/O error from a pool job with the step that failed.
+fn with_context(e: &std::io::Error, what: &str) -> std::io::Error {
+    std::io::Error::new(e.kind(), format!("{what}: {e}"))
+}
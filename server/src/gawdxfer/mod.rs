@@ -2,9 +2,13 @@
 //!
 //! A self-contained module with shared types, streaming SHA-256, and a
 //! `TransferManager` that owns transfer lifecycle, temp files, and chunk I/O.
+//! Directories travel as tar archives ([`archive`]), and bandwidth is shaped
+//! per transfer and overall ([`throttle`]).
 //! Integration layers (HTTP routes, tunnel relay, tunnel client) adapt gawdxfer
 //! to their transport.
 
+pub mod archive;
 pub mod hasher;
 pub mod manager;
+pub mod throttle;
 pub mod types;
@@ -0,0 +1,106 @@
+//! Bandwidth shaping for chunk transfers.
+//!
+//! A full-speed transfer over LTE fills the uplink, and the tunnel's
+//! heartbeats queue behind it until the pong watchdog reconnects. Each
+//! transfer has its own [`TokenBucket`], and one more is shared by all of
+//! them. Every chunk served or received is charged to both. Once a bucket is
+//! overdrawn, the chunk's reply waits until the debt is paid off. The sender
+//! runs a window of chunks ahead of its acks, so this paces it too.
+//!
+//! Rates are in bytes per second, and `0` means unlimited.
+
+use std::time::{Duration, Instant};
+
+/// A token bucket that may go into debt: a chunk larger than the bucket is
+/// still let through, and the wait comes afterwards.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate_bps: u64,
+    /// Bytes that may be sent right now; negative when overdrawn.
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_bps: u64) -> Self {
+        Self {
+            rate_bps,
+            tokens: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn rate_bps(&self) -> u64 {
+        self.rate_bps
+    }
+
+    /// Change the rate. What was sent so far stays charged.
+    pub fn set_rate(&mut self, rate_bps: u64) {
+        self.refill();
+        self.rate_bps = rate_bps;
+        if rate_bps == 0 {
+            self.tokens = 0.0;
+        }
+    }
+
+    /// Charge `bytes` and return how long to wait before the next send.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn take(&mut self, bytes: u64) -> Duration {
+        if self.rate_bps == 0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate_bps as f64)
+        }
+    }
+
+    /// Add the tokens earned since the last call, up to one second's worth.
+    #[allow(clippy::cast_precision_loss)]
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.last).as_secs_f64() * self.rate_bps as f64;
+        self.tokens = (self.tokens + earned).min(self.rate_bps as f64);
+        self.last = now;
+    }
+}
+
+/// The rate a transfer runs at: what the client asked for, but no faster
+/// than the configured per-transfer cap. Either may be `0` (unlimited).
+pub fn effective_rate(requested: Option<u64>, cap: u64) -> u64 {
+    match (requested.unwrap_or(0), cap) {
+        (0, cap) => cap,
+        (requested, 0) => requested,
+        (requested, cap) => requested.min(cap),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_paces_and_caps_apply() {
+        let mut unlimited = TokenBucket::new(0);
+        assert_eq!(unlimited.take(u64::MAX), Duration::ZERO);
+
+        // 100 KB/s with no savings: a 50 KB chunk is half a second of debt,
+        // the next one a whole second
+        let mut bucket = TokenBucket::new(100_000);
+        let first = bucket.take(50_000);
+        assert!(first > Duration::from_millis(450) && first <= Duration::from_millis(500));
+        assert!(bucket.take(50_000) > Duration::from_millis(950));
+        bucket.set_rate(0);
+        assert_eq!(bucket.take(50_000), Duration::ZERO);
+
+        assert_eq!(effective_rate(None, 0), 0);
+        assert_eq!(effective_rate(None, 500_000), 500_000);
+        assert_eq!(effective_rate(Some(0), 500_000), 500_000);
+        assert_eq!(effective_rate(Some(100_000), 500_000), 100_000);
+        assert_eq!(effective_rate(Some(900_000), 500_000), 500_000);
+        assert_eq!(effective_rate(Some(900_000), 0), 900_000);
+    }
+}
@@ -46,6 +46,8 @@ pub struct TransferSpec {
     pub created_at: Instant,
     /// Source file mtime at init (download only) — detect `FILE_CHANGED`.
     pub source_mtime: Option<u64>,
+    /// The bytes are a tar of a directory (see [`super::archive`]).
+    pub directory: bool,
 }
 
 /// Mutable progress state for a transfer.
@@ -67,6 +69,15 @@ pub struct InitDownload {
     pub path: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub chunk_size: Option<u32>,
+    /// `path` is a directory, sent as a tar archive.
+    #[serde(default)]
+    pub directory: bool,
+    /// With `directory`, archive only these paths (relative to `path`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// Bandwidth cap in bytes/s, at most `transfer_rate_limit_bps`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_bps: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -79,6 +90,8 @@ pub struct InitDownloadResult {
     pub chunk_size: u32,
     pub total_chunks: u32,
     pub filename: String,
+    /// The download is a tar of the directory at `path`.
+    pub directory: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -95,6 +108,13 @@ pub struct InitUpload {
     pub total_chunks: u32,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mode: Option<String>,
+    /// The upload is a tar archive, unpacked into the directory `path/filename`
+    /// (created if missing) instead of saved as a file.
+    #[serde(default)]
+    pub directory: bool,
+    /// Bandwidth cap in bytes/s, at most `transfer_rate_limit_bps`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_bps: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -182,6 +202,19 @@ pub struct Resume {
     pub transfer_id: String,
 }
 
+/// `gx.throttle`: change a transfer's bandwidth cap, or the global one when
+/// `transfer_id` is absent. Also the reply, with the cap now in effect.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export, optional_fields))]
+pub struct Throttle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_id: Option<String>,
+    /// Bytes/s, `0` for unlimited (a transfer still can't exceed
+    /// `transfer_rate_limit_bps`).
+    pub rate_limit_bps: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[cfg_attr(test, derive(ts_rs::TS))]
 #[cfg_attr(test, ts(export, optional_fields))]
@@ -209,6 +242,8 @@ pub struct StatusResult {
     pub bytes_transferred: u64,
     pub elapsed_ms: u64,
     pub error_count: u32,
+    /// Current bandwidth cap in bytes/s, `0` when unlimited.
+    pub rate_limit_bps: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -239,6 +274,10 @@ pub struct TransferConfig {
     pub max_file_size: u64,
     pub stale_timeout_secs: u64,
     pub max_chunk_retries: u32,
+    /// Default and maximum per-transfer rate in bytes/s (`0` = unlimited).
+    pub rate_limit_bps: u64,
+    /// Rate shared by all transfers in bytes/s (`0` = unlimited).
+    pub global_rate_limit_bps: u64,
 }
 
 impl TransferConfig {
@@ -254,8 +293,18 @@ impl TransferConfig {
             max_file_size,
             stale_timeout_secs,
             max_chunk_retries: 3,
+            rate_limit_bps: 0,
+            global_rate_limit_bps: 0,
         }
     }
+
+    /// Set the per-transfer and global bandwidth caps (bytes/s, `0` = unlimited).
+    #[must_use]
+    pub fn with_rate_limits(mut self, rate_limit_bps: u64, global_rate_limit_bps: u64) -> Self {
+        self.rate_limit_bps = rate_limit_bps;
+        self.global_rate_limit_bps = global_rate_limit_bps;
+        self
+    }
 }
 
 impl Phase {
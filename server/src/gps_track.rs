@@ -0,0 +1,306 @@
+//! GPS track recording for `GET /api/gps/track`.
+//!
+//! `/api/gps` only knows the provider's latest fix and a short history. With
+//! `[gps]` configured, a sampler task reads the current fix every
+//! `track_interval_secs` and, when it is newer than the last recorded one,
+//! appends it to [`GpsTrack`]:
+//!
+//! - **Bounded**: a ring of `track_max_points` points, oldest dropped first.
+//! - **Survives restarts**: each point is appended as a JSON line to
+//!   `<data_dir>/gps_track.jsonl` and the newest points are loaded on
+//!   startup. Once the file holds twice the ring's worth of lines it is
+//!   rewritten from the ring.
+//! - **Live**: each recorded point is broadcast as a `gps.update` event, so
+//!   map dashboards can extend the track without polling.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, warn};
+
+use crate::comms::CommsState;
+use crate::infra::now_epoch;
+
+/// One recorded fix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackPoint {
+    /// Unix seconds the fix was taken.
+    pub timestamp: u64,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_kmh: Option<f64>,
+    /// Course over ground in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub course: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hdop: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub satellites: Option<u64>,
+}
+
+impl TrackPoint {
+    /// The point for a provider `last_fix`; `None` without coordinates.
+    /// A fix without a numeric `recorded_at` is stamped with the current time.
+    pub fn from_fix(fix: &Value) -> Option<Self> {
+        Some(Self {
+            timestamp: fix["recorded_at"].as_u64().unwrap_or_else(now_epoch),
+            latitude: fix["latitude"].as_f64()?,
+            longitude: fix["longitude"].as_f64()?,
+            altitude: fix["altitude"].as_f64(),
+            speed_kmh: fix["speed_kmh"].as_f64(),
+            course: fix["course"].as_f64(),
+            hdop: fix["hdop"].as_f64(),
+            satellites: fix["satellites"].as_u64(),
+        })
+    }
+
+    /// `[longitude, latitude]`, plus altitude when known.
+    fn position(&self) -> Value {
+        match self.altitude {
+            Some(alt) => json!([self.longitude, self.latitude, alt]),
+            None => json!([self.longitude, self.latitude]),
+        }
+    }
+}
+
+struct Ring {
+    points: VecDeque<TrackPoint>,
+    /// Lines in the backing file, compacted points included.
+    file_lines: usize,
+}
+
+/// The recorded track, newest last.
+pub struct GpsTrack {
+    ring: Mutex<Ring>,
+    max_points: usize,
+    /// File the track is appended to.
+    path: PathBuf,
+}
+
+impl GpsTrack {
+    /// Track backed by `<data_dir>/gps_track.jsonl`, loading the newest
+    /// `max_points` points a previous run recorded.
+    pub fn open(data_dir: &str, max_points: usize) -> Self {
+        let path = Path::new(data_dir).join("gps_track.jsonl");
+        let max_points = max_points.max(1);
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+        let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+        let skip = lines.len().saturating_sub(max_points);
+        let points = lines[skip..]
+            .iter()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect();
+        Self {
+            ring: Mutex::new(Ring {
+                points,
+                file_lines: lines.len(),
+            }),
+            max_points,
+            path,
+        }
+    }
+
+    /// Append `point` if it is newer than the last one. Returns whether it
+    /// was recorded.
+    pub async fn record(&self, point: TrackPoint) -> bool {
+        let mut ring = self.ring.lock().await;
+        if ring
+            .points
+            .back()
+            .is_some_and(|last| last.timestamp >= point.timestamp)
+        {
+            return false;
+        }
+        if ring.points.len() >= self.max_points {
+            ring.points.pop_front();
+        }
+        ring.points.push_back(point.clone());
+
+        let compact = ring.file_lines >= 2 * self.max_points;
+        let (lines, count): (String, usize) = if compact {
+            (ring.points.iter().map(to_line).collect(), ring.points.len())
+        } else {
+            (to_line(&point), 1)
+        };
+        let path = self.path.clone();
+        match crate::io_pool::run(move || write_lines(&path, &lines, compact)).await {
+            Ok(()) if compact => ring.file_lines = count,
+            Ok(()) => ring.file_lines += count,
+            Err(e) => warn!("GPS track: write failed: {e}"),
+        }
+        true
+    }
+
+    /// Points from `since` to `until` (Unix seconds, inclusive), at most the
+    /// newest `limit`.
+    pub async fn points(&self, since: u64, until: u64, limit: usize) -> Vec<TrackPoint> {
+        let ring = self.ring.lock().await;
+        let mut points: Vec<TrackPoint> = ring
+            .points
+            .iter()
+            .rev()
+            .filter(|p| p.timestamp >= since && p.timestamp <= until)
+            .take(limit)
+            .cloned()
+            .collect();
+        points.reverse();
+        points
+    }
+
+    pub fn max_points(&self) -> usize {
+        self.max_points
+    }
+}
+
+fn to_line(point: &TrackPoint) -> String {
+    let mut line = serde_json::to_string(point).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+fn write_lines(path: &Path, lines: &str, replace: bool) -> std::io::Result<()> {
+    use std::io::Write;
+    if replace {
+        let tmp = path.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, lines)?;
+        return std::fs::rename(&tmp, path);
+    }
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    f.write_all(lines.as_bytes())
+}
+
+/// `points` as a GeoJSON `FeatureCollection`: a `LineString` of the whole
+/// track (when there are two or more points), then one `Point` per fix with
+/// its time, speed, course, HDOP and satellites as properties.
+pub fn geojson(points: &[TrackPoint]) -> Value {
+    let mut features = Vec::with_capacity(points.len() + 1);
+    if let [first, .., last] = points {
+        features.push(json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": points.iter().map(TrackPoint::position).collect::<Vec<_>>(),
+            },
+            "properties": {
+                "kind": "track",
+                "start": first.timestamp,
+                "end": last.timestamp,
+                "points": points.len(),
+            },
+        }));
+    }
+    features.extend(points.iter().map(|p| {
+        let mut properties = json!(p);
+        if let Some(props) = properties.as_object_mut() {
+            props.remove("latitude");
+            props.remove("longitude");
+            props.remove("altitude");
+            props.insert("kind".into(), json!("fix"));
+        }
+        json!({
+            "type": "Feature",
+            "geometry": { "type": "Point", "coordinates": p.position() },
+            "properties": properties,
+        })
+    }));
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Spawn the sampler: every `interval_secs`, record the provider's current
+/// fix if it is new and broadcast it as `gps.update`.
+pub fn spawn_sampler(
+    track: Arc<GpsTrack>,
+    comms_state: Arc<Mutex<CommsState>>,
+    session_events: broadcast::Sender<Value>,
+    interval_secs: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            let point = comms_state
+                .lock()
+                .await
+                .gps
+                .as_ref()
+                .and_then(|gps| TrackPoint::from_fix(&gps["last_fix"]));
+            let Some(point) = point else {
+                continue;
+            };
+            if track.record(point.clone()).await {
+                debug!(
+                    "GPS track: {:.6},{:.6} at {}",
+                    point.latitude, point.longitude, point.timestamp
+                );
+                let mut event = json!(point);
+                event["type"] = json!("gps.update");
+                let _ = session_events.send(event);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(timestamp: u64) -> TrackPoint {
+        TrackPoint::from_fix(&json!({
+            "latitude": 45.5,
+            "longitude": -73.5,
+            "altitude": 50.0,
+            "satellites": 8,
+            "recorded_at": timestamp,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_compacts_and_reloads() {
+        let dir = std::env::temp_dir().join(format!("sctl-gps-track-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_dir = dir.to_str().unwrap();
+        let track = GpsTrack::open(data_dir, 2);
+        assert!(track.record(point(10)).await);
+        assert!(!track.record(point(10)).await, "same fix twice");
+        for t in 11..=14 {
+            assert!(track.record(point(t)).await);
+        }
+        let kept: Vec<u64> = track
+            .points(0, u64::MAX, usize::MAX)
+            .await
+            .iter()
+            .map(|p| p.timestamp)
+            .collect();
+        assert_eq!(kept, [13, 14]);
+        let lines = std::fs::read_to_string(dir.join("gps_track.jsonl")).unwrap();
+        assert!(lines.lines().count() <= 4, "file is compacted");
+
+        let reloaded = GpsTrack::open(data_dir, 2);
+        assert_eq!(reloaded.points(14, u64::MAX, 10).await, [point(14)]);
+
+        let collection = geojson(&reloaded.points(0, u64::MAX, 10).await);
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        assert_eq!(
+            features[0]["geometry"]["coordinates"][1],
+            json!([-73.5, 45.5, 50.0])
+        );
+        assert_eq!(features[2]["properties"]["timestamp"], 14);
+        assert!(features[2]["properties"].get("latitude").is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
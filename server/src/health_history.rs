@@ -0,0 +1,547 @@
+//! Health history and flapping detection for the device itself.
+//!
+//! `GET /api/health` is a point-in-time view: a tunnel that drops for ten
+//! seconds every few minutes, or a disk that keeps filling and being cleaned,
+//! looks healthy whenever someone checks. [`HealthHistory`] keeps a persisted
+//! ring of health-relevant transitions so intermittent problems show up:
+//!
+//! | Condition | States               | Source                                   |
+//! |-----------|----------------------|------------------------------------------|
+//! | `tunnel`  | `up` / `down`        | Tunnel client registration changes       |
+//! | `disk`    | `ok` / `pressure`    | `/` and `data_dir` usage, polled         |
+//! | `oom`     | `kill`               | OOM killer lines in the kernel log       |
+//! | `service` | `started` / `stopped`| sctl startup and clean shutdown          |
+//! | `load`    | `saturated` / `ok`   | Requests refused at `max_connections`    |
+//!
+//! A condition is **flapping** when it went bad (see [`is_bad`])
+//! [`FLAP_THRESHOLD`] or more times within [`FLAP_WINDOW_SECS`] — e.g. three
+//! tunnel drops or three restarts in fifteen minutes.
+//!
+//! Transitions are written to `<data_dir>/health_history.json` by the monitor
+//! task and on shutdown; entries older than [`MAX_AGE_SECS`] are pruned on
+//! load.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::infra::now_epoch;
+use crate::AppState;
+
+/// Maximum number of transitions retained.
+pub const MAX_TRANSITIONS: usize = 500;
+
+/// Transitions older than this are pruned on load.
+pub const MAX_AGE_SECS: u64 = 7 * 24 * 3600;
+
+/// Window and count for flapping detection.
+pub const FLAP_WINDOW_SECS: u64 = 15 * 60;
+pub const FLAP_THRESHOLD: usize = 3;
+
+/// Disk usage (percent) that enters and leaves `pressure`. The gap keeps a
+/// disk hovering at the threshold from flapping on its own.
+const DISK_PRESSURE_PCT: u64 = 90;
+const DISK_OK_PCT: u64 = 85;
+
+/// Monitor poll interval.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Timeout for the `dmesg` invocation.
+const DMESG_TIMEOUT_MS: u64 = 5_000;
+
+/// Something whose health is tracked over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Tunnel,
+    Disk,
+    Oom,
+    Service,
+    Load,
+}
+
+impl Condition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tunnel => "tunnel",
+            Self::Disk => "disk",
+            Self::Oom => "oom",
+            Self::Service => "service",
+            Self::Load => "load",
+        }
+    }
+
+    pub fn from_str_opt(s: &str) -> Option<Self> {
+        match s {
+            "tunnel" => Some(Self::Tunnel),
+            "disk" => Some(Self::Disk),
+            "oom" => Some(Self::Oom),
+            "service" => Some(Self::Service),
+            "load" => Some(Self::Load),
+            _ => None,
+        }
+    }
+}
+
+/// One recorded change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthTransition {
+    /// Unix timestamp (seconds since epoch).
+    pub timestamp: u64,
+    pub condition: Condition,
+    pub state: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+/// A condition that went bad too often within the flap window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Flapping {
+    pub condition: Condition,
+    /// Bad transitions inside the window.
+    pub count: usize,
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Whether `state` is the unhealthy side of its condition. Restarts count as
+/// bad: a healthy service starts once.
+pub fn is_bad(condition: Condition, state: &str) -> bool {
+    match condition {
+        Condition::Tunnel => state == "down",
+        Condition::Disk => state == "pressure",
+        Condition::Oom => true,
+        Condition::Service => state == "started",
+        Condition::Load => state == "saturated",
+    }
+}
+
+/// Conditions with [`FLAP_THRESHOLD`] or more bad transitions in the
+/// [`FLAP_WINDOW_SECS`] before `now`.
+pub fn flapping<'a>(
+    transitions: impl IntoIterator<Item = &'a HealthTransition>,
+    now: u64,
+) -> Vec<Flapping> {
+    let since = now.saturating_sub(FLAP_WINDOW_SECS);
+    let mut bad: BTreeMap<Condition, Vec<u64>> = BTreeMap::new();
+    for t in transitions {
+        if t.timestamp >= since && is_bad(t.condition, &t.state) {
+            bad.entry(t.condition).or_default().push(t.timestamp);
+        }
+    }
+    bad.into_iter()
+        .filter(|(_, times)| times.len() >= FLAP_THRESHOLD)
+        .map(|(condition, times)| Flapping {
+            condition,
+            count: times.len(),
+            first: times.iter().copied().min().unwrap_or(0),
+            last: times.iter().copied().max().unwrap_or(0),
+        })
+        .collect()
+}
+
+/// Parse an OOM-killer line from `dmesg`, returning the kernel timestamp
+/// (seconds since boot) and what was killed.
+///
+/// ```text
+/// [12345.678901] Out of memory: Killed process 4321 (java) total-vm:...
+/// [  210.000000] Memory cgroup out of memory: Killed process 77 (node) ...
+/// ```
+pub fn parse_oom_line(line: &str) -> Option<(f64, String)> {
+    let rest = line.trim_start().strip_prefix('[')?;
+    let (ts, msg) = rest.split_once(']')?;
+    let ts: f64 = ts.trim().parse().ok()?;
+    if !msg.to_ascii_lowercase().contains("out of memory") {
+        return None;
+    }
+    let kill = msg.find("Kill")?;
+    let detail = msg[kill..]
+        .split(" total-vm")
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(',')
+        .trim();
+    Some((ts, detail.to_string()))
+}
+
+/// Persisted ring of health transitions.
+pub struct HealthHistory {
+    transitions: Mutex<VecDeque<HealthTransition>>,
+    /// File the ring is saved to (`None` keeps it in memory only).
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl HealthHistory {
+    /// In-memory history.
+    pub fn new() -> Self {
+        Self {
+            transitions: Mutex::new(VecDeque::new()),
+            path: None,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// History backed by `path`, loading what a previous run saved.
+    pub fn load(path: PathBuf) -> Self {
+        let transitions = match std::fs::read_to_string(&path) {
+            Ok(data) => match serde_json::from_str::<Vec<HealthTransition>>(&data) {
+                Ok(v) => {
+                    let now = now_epoch();
+                    v.into_iter()
+                        .filter(|t| now.saturating_sub(t.timestamp) < MAX_AGE_SECS)
+                        .collect()
+                }
+                Err(e) => {
+                    warn!("Failed to parse {}: {e}", path.display());
+                    VecDeque::new()
+                }
+            },
+            Err(_) => VecDeque::new(),
+        };
+        Self {
+            transitions: Mutex::new(transitions),
+            path: Some(path),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    fn push(
+        transitions: &mut VecDeque<HealthTransition>,
+        timestamp: u64,
+        condition: Condition,
+        state: &str,
+        detail: String,
+    ) {
+        if transitions.len() >= MAX_TRANSITIONS {
+            transitions.pop_front();
+        }
+        transitions.push_back(HealthTransition {
+            timestamp,
+            condition,
+            state: state.to_string(),
+            detail,
+        });
+    }
+
+    /// Last recorded state of `condition`.
+    pub async fn current(&self, condition: Condition) -> Option<String> {
+        self.transitions
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .find(|t| t.condition == condition)
+            .map(|t| t.state.clone())
+    }
+
+    /// Record `state` for `condition` if it differs from the last recorded
+    /// one. Returns whether a transition was recorded.
+    pub async fn observe(&self, condition: Condition, state: &str, detail: &str) -> bool {
+        let mut transitions = self.transitions.lock().await;
+        let last = transitions.iter().rev().find(|t| t.condition == condition);
+        if last.is_some_and(|t| t.state == state) {
+            return false;
+        }
+        info!(
+            condition = condition.as_str(),
+            state, detail, "Health transition"
+        );
+        Self::push(
+            &mut transitions,
+            now_epoch(),
+            condition,
+            state,
+            detail.to_string(),
+        );
+        drop(transitions);
+        self.dirty.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Record an event (`oom` kill, restart) unconditionally. An identical
+    /// entry at the same timestamp is not recorded twice.
+    pub async fn record(&self, timestamp: u64, condition: Condition, state: &str, detail: &str) {
+        let mut transitions = self.transitions.lock().await;
+        if transitions.iter().any(|t| {
+            t.timestamp == timestamp
+                && t.condition == condition
+                && t.state == state
+                && t.detail == detail
+        }) {
+            return;
+        }
+        info!(
+            condition = condition.as_str(),
+            state, detail, "Health event"
+        );
+        Self::push(
+            &mut transitions,
+            timestamp,
+            condition,
+            state,
+            detail.to_string(),
+        );
+        drop(transitions);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Record this process starting, noting when the previous run ended
+    /// without a clean shutdown.
+    pub async fn record_start(&self) {
+        let unclean = self.current(Condition::Service).await.as_deref() == Some("started");
+        let detail = if unclean {
+            format!(
+                "{} (previous run did not shut down cleanly)",
+                crate::VERSION
+            )
+        } else {
+            crate::VERSION.to_string()
+        };
+        self.record(now_epoch(), Condition::Service, "started", &detail)
+            .await;
+    }
+
+    /// Record a clean shutdown.
+    pub async fn record_stop(&self) {
+        self.record(now_epoch(), Condition::Service, "stopped", "")
+            .await;
+    }
+
+    /// Conditions currently flapping.
+    pub async fn flapping(&self) -> Vec<Flapping> {
+        flapping(self.transitions.lock().await.iter(), now_epoch())
+    }
+
+    /// Transitions at or after `since` (optionally one condition), newest
+    /// `limit`, plus current states and flapping conditions.
+    pub async fn snapshot(&self, since: u64, condition: Option<Condition>, limit: usize) -> Value {
+        let transitions = self.transitions.lock().await;
+        let mut current: BTreeMap<&'static str, &str> = BTreeMap::new();
+        for t in transitions.iter() {
+            current.insert(t.condition.as_str(), &t.state);
+        }
+        let matching: Vec<&HealthTransition> = transitions
+            .iter()
+            .filter(|t| t.timestamp >= since && condition.is_none_or(|c| t.condition == c))
+            .collect();
+        let skip = matching.len().saturating_sub(limit);
+        json!({
+            "transitions": &matching[skip..],
+            "current": current,
+            "flapping": flapping(transitions.iter(), now_epoch()),
+            "flap_window_secs": FLAP_WINDOW_SECS,
+            "flap_threshold": FLAP_THRESHOLD,
+        })
+    }
+
+    /// Persist to disk if changed (atomic write via tmp + rename).
+    pub async fn save(&self) -> bool {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        let Some(ref path) = self.path else {
+            return false;
+        };
+        let transitions = self.transitions.lock().await;
+        let snapshot: Vec<&HealthTransition> = transitions.iter().collect();
+        let Ok(data) = serde_json::to_string_pretty(&snapshot) else {
+            warn!("Failed to serialize health history");
+            return false;
+        };
+        drop(transitions);
+        let tmp = path.with_extension("json.tmp");
+        if let Err(e) = tokio::fs::write(&tmp, &data).await {
+            warn!("Failed to write health history tmp file: {e}");
+            return false;
+        }
+        if let Err(e) = tokio::fs::rename(&tmp, path).await {
+            warn!("Failed to rename health history file: {e}");
+            return false;
+        }
+        true
+    }
+}
+
+impl Default for HealthHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawn the monitor task: polls disk usage and the kernel log, and saves
+/// the history when it changed. Tunnel and service transitions are recorded
+/// where they happen.
+pub fn spawn_monitor(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let history = state.health_history.clone();
+        let data_dir = state.config.server.data_dir.clone();
+        let mut paths = vec!["/".to_string()];
+        if data_dir != "/" {
+            paths.push(data_dir);
+        }
+        // Kernel timestamp of the newest OOM line already seen.
+        let mut oom_seen = 0.0_f64;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            check_disk(&history, &paths).await;
+            check_oom(&history, &mut oom_seen).await;
+            check_load(&state, &history).await;
+            history.save().await;
+        }
+    })
+}
+
+async fn check_disk(history: &HealthHistory, paths: &[String]) {
+    let mut worst: Option<(u64, &str)> = None;
+    for path in paths {
+        let usage = crate::routes::info::get_disk_usage(path);
+        let (Some(total), Some(available)) = (
+            usage["total_bytes"].as_u64(),
+            usage["available_bytes"].as_u64(),
+        ) else {
+            continue;
+        };
+        if total == 0 {
+            continue;
+        }
+        let pct = total.saturating_sub(available) * 100 / total;
+        if worst.is_none_or(|(w, _)| pct > w) {
+            worst = Some((pct, path));
+        }
+    }
+    let Some((pct, path)) = worst else {
+        return;
+    };
+    let state = if pct >= DISK_PRESSURE_PCT {
+        "pressure"
+    } else if pct < DISK_OK_PCT {
+        "ok"
+    } else {
+        return;
+    };
+    history
+        .observe(Condition::Disk, state, &format!("{path} {pct}% used"))
+        .await;
+}
+
+async fn check_oom(history: &HealthHistory, seen: &mut f64) {
+    // dmesg may be restricted (kernel.dmesg_restrict) — skip quietly.
+    let Ok((0, stdout, _)) =
+        crate::infra::checks::exec_args_pub("dmesg", &[], DMESG_TIMEOUT_MS).await
+    else {
+        return;
+    };
+    // Boot time from `btime`, so the same line maps to the same timestamp
+    // across restarts (and isn't recorded twice).
+    let boot: u64 = crate::routes::info::read_proc_file("/proc/stat")
+        .lines()
+        .find_map(|l| l.strip_prefix("btime "))
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0);
+    for (ts, detail) in stdout.lines().filter_map(parse_oom_line) {
+        if ts <= *seen {
+            continue;
+        }
+        *seen = ts;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let timestamp = boot + ts as u64;
+        history
+            .record(timestamp, Condition::Oom, "kill", &detail)
+            .await;
+    }
+}
+
+/// Back to `ok` once no request has been refused for a poll interval.
+async fn check_load(state: &AppState, history: &HealthHistory) {
+    if history.current(Condition::Load).await.as_deref() == Some("saturated")
+        && state.saturation.recovered(POLL_INTERVAL.as_secs())
+    {
+        history
+            .observe(Condition::Load, "ok", "no requests refused")
+            .await;
+    }
+}
+
+/// Where the history is persisted.
+pub fn history_path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("health_history.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(timestamp: u64, condition: Condition, state: &str) -> HealthTransition {
+        HealthTransition {
+            timestamp,
+            condition,
+            state: state.into(),
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn flapping_needs_threshold_inside_window() {
+        let now = 10_000;
+        let history = vec![
+            t(now - 2000, Condition::Tunnel, "down"),
+            t(now - 600, Condition::Tunnel, "down"),
+            t(now - 590, Condition::Tunnel, "up"),
+            t(now - 300, Condition::Tunnel, "down"),
+            t(now - 290, Condition::Tunnel, "up"),
+            t(now - 10, Condition::Disk, "pressure"),
+        ];
+        // Only two drops inside the window.
+        assert!(flapping(&history, now).is_empty());
+
+        let mut history = history;
+        history.push(t(now - 5, Condition::Tunnel, "down"));
+        let flaps = flapping(&history, now);
+        assert_eq!(
+            flaps,
+            vec![Flapping {
+                condition: Condition::Tunnel,
+                count: 3,
+                first: now - 600,
+                last: now - 5,
+            }]
+        );
+    }
+
+    #[test]
+    fn restarts_count_stops_do_not() {
+        let history = vec![
+            t(100, Condition::Service, "started"),
+            t(200, Condition::Service, "stopped"),
+            t(300, Condition::Service, "started"),
+            t(400, Condition::Service, "started"),
+        ];
+        assert_eq!(flapping(&history, 500)[0].count, 3);
+    }
+
+    #[test]
+    fn parses_oom_lines() {
+        assert_eq!(
+            parse_oom_line(
+                "[12345.678901] Out of memory: Killed process 4321 (java) total-vm:123kB, anon-rss:1kB"
+            ),
+            Some((12_345.678_901, "Killed process 4321 (java)".to_string()))
+        );
+        assert_eq!(
+            parse_oom_line(
+                "[  210.5] Memory cgroup out of memory: Kill process 77 (node) score 900 or sacrifice child"
+            ),
+            Some((210.5, "Kill process 77 (node) score 900 or sacrifice child".to_string()))
+        );
+        assert_eq!(parse_oom_line("[ 1.0] usb 1-1: new device"), None);
+        assert_eq!(parse_oom_line("no timestamp out of memory"), None);
+    }
+}
@@ -0,0 +1,477 @@
+//! Operator Lua hooks: exec policy, exec enrichment and activity filtering.
+//!
+//! `[hooks] script` is a Lua 5.4 file that may define any of three global
+//! functions. Each gets its arguments as plain tables and runs under
+//! `timeout_ms` and `memory_limit_bytes`; only the `table`, `string`,
+//! `math` and `utf8` libraries are loaded, plus `sctl.serial` and
+//! `sctl.log(msg)`.
+//!
+//! - `pre_exec(req)` — before a one-shot command (REST exec, stream, batch
+//!   and their tunnel forms) runs. `req` has `command`, `shell`,
+//!   `working_dir`, `source` and `risk`. Returning `nil` or `true` allows
+//!   it; `false`, a string, or `{deny = "reason"}` refuses it with
+//!   `403 HOOK_DENIED`. If the call errors (or the script didn't load) the
+//!   command is refused with `500 HOOK_FAILED` unless `fail_open` is set.
+//! - `post_exec(req, result)` — after a command ran. `result` has
+//!   `exit_code`, `stdout`, `stderr` and `duration_ms`. A returned table is
+//!   stored as `enrichment` in the exec's activity entry.
+//! - `activity(entry)` — before an activity entry is kept and broadcast.
+//!   Returning `false` drops it; a table with `summary` and/or `detail`
+//!   replaces those fields.
+//!
+//! Errors in `post_exec` and `activity` are logged and otherwise ignored.
+//! `POST /api/hooks/reload` re-reads the script (see [`crate::routes::hooks`]).
+//! Without the `scripting` cargo feature the script never loads, so
+//! `pre_exec` refuses everything unless `fail_open` is set.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use axum::Json;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::activity::{ActivityEntry, ActivitySource};
+use crate::config::HooksConfig;
+use crate::error::{codes, ApiError};
+use crate::shell::process::ExecResult;
+use crate::state::AppState;
+
+type GuardError = (StatusCode, Json<ApiError>);
+
+/// The loaded script and the hooks it defines.
+pub struct Hooks {
+    config: HooksConfig,
+    serial: String,
+    engine: Mutex<Result<engine::Engine, String>>,
+    denied: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Hooks {
+    /// Load `config.script`. A script that fails to load is reported by
+    /// [`status`](Self::status) and handled as described in the module docs.
+    pub fn load(config: HooksConfig, serial: &str) -> Self {
+        let engine = engine::Engine::load(&config, serial);
+        match &engine {
+            Ok(e) => info!(script = %config.script, hooks = ?e.functions(), "Hooks loaded"),
+            Err(e) => warn!(script = %config.script, "Hooks failed to load: {e}"),
+        }
+        Self {
+            config,
+            serial: serial.to_string(),
+            engine: Mutex::new(engine),
+            denied: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Re-read the script. On failure the previous script stays active.
+    ///
+    /// # Errors
+    ///
+    /// The load error, as text.
+    pub fn reload(&self) -> Result<(), String> {
+        let engine = engine::Engine::load(&self.config, &self.serial).inspect_err(|e| {
+            warn!(script = %self.config.script, "Hooks reload failed: {e}");
+        })?;
+        info!(script = %self.config.script, hooks = ?engine.functions(), "Hooks reloaded");
+        *self.engine.lock().unwrap() = Ok(engine);
+        Ok(())
+    }
+
+    /// Script, defined hooks and counters for `GET /api/hooks`.
+    pub fn status(&self) -> Value {
+        let engine = self.engine.lock().unwrap();
+        json!({
+            "enabled": true,
+            "script": self.config.script,
+            "loaded": engine.is_ok(),
+            "error": engine.as_ref().err(),
+            "hooks": engine.as_ref().map(engine::Engine::functions).unwrap_or_default(),
+            "fail_open": self.config.fail_open,
+            "denied": self.denied.load(Ordering::Relaxed),
+            "errors": self.errors.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Run hook `name`, or `None` if the script doesn't define it.
+    fn call(&self, name: &str, args: &[&Value]) -> Option<Result<Value, String>> {
+        let engine = self.engine.lock().unwrap();
+        let result = match engine.as_ref() {
+            Ok(e) => e.call(name, args, Duration::from_millis(self.config.timeout_ms))?,
+            Err(e) => Err(format!("script not loaded: {e}")),
+        };
+        if let Err(e) = &result {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            warn!(hook = name, "Hook failed: {e}");
+        }
+        Some(result)
+    }
+
+    /// Apply `pre_exec` to a command.
+    ///
+    /// # Errors
+    ///
+    /// - `403 Forbidden` with `{"code":"HOOK_DENIED"}`
+    /// - `500 Internal Server Error` with `{"code":"HOOK_FAILED"}`
+    pub fn pre_exec(&self, req: &Value) -> Result<(), GuardError> {
+        let reason = match self.call("pre_exec", &[req]) {
+            None | Some(Ok(Value::Null | Value::Bool(true))) => return Ok(()),
+            Some(Err(_)) if self.config.fail_open => return Ok(()),
+            Some(Err(e)) => {
+                return Err(
+                    ApiError::new(codes::HOOK_FAILED, format!("pre_exec hook failed: {e}"))
+                        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+                )
+            }
+            Some(Ok(Value::String(reason))) => reason,
+            Some(Ok(Value::Object(o))) => match o.get("deny") {
+                None | Some(Value::Null | Value::Bool(false)) => return Ok(()),
+                Some(Value::String(reason)) => reason.clone(),
+                Some(_) => String::new(),
+            },
+            Some(Ok(_)) => String::new(),
+        };
+        self.denied.fetch_add(1, Ordering::Relaxed);
+        let message = if reason.is_empty() {
+            "Command refused by pre_exec hook".to_string()
+        } else {
+            reason
+        };
+        Err(ApiError::new(codes::HOOK_DENIED, message)
+            .with_detail(json!({ "command": req["command"] }))
+            .into_response_with(StatusCode::FORBIDDEN))
+    }
+
+    /// Apply `post_exec`; returns the enrichment table, if any.
+    pub fn post_exec(&self, req: &Value, result: &Value) -> Option<Value> {
+        match self.call("post_exec", &[req, result])? {
+            Ok(v @ (Value::Object(_) | Value::Array(_))) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Apply `activity` to an entry. Returns `false` if it should be dropped.
+    pub fn filter_activity(&self, entry: &mut ActivityEntry) -> bool {
+        let Ok(input) = serde_json::to_value(&*entry) else {
+            return true;
+        };
+        match self.call("activity", &[&input]) {
+            Some(Ok(Value::Bool(false))) => false,
+            Some(Ok(Value::Object(o))) => {
+                if let Some(summary) = o.get("summary").and_then(Value::as_str) {
+                    entry.summary = summary.to_string();
+                }
+                if let Some(detail) = o.get("detail") {
+                    entry.detail = Some(detail.clone());
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Run `pre_exec` for a one-shot command, if hooks are configured.
+///
+/// # Errors
+///
+/// See [`Hooks::pre_exec`].
+pub fn check_exec(
+    state: &AppState,
+    source: ActivitySource,
+    command: &str,
+    shell: &str,
+    working_dir: &str,
+) -> Result<(), GuardError> {
+    let Some(hooks) = &state.hooks else {
+        return Ok(());
+    };
+    hooks.pre_exec(&json!({
+        "command": command,
+        "shell": shell,
+        "working_dir": working_dir,
+        "source": source,
+        "risk": crate::shell::classify::classify(&state.config.classify, command),
+    }))
+}
+
+/// Run `post_exec` for a finished command, if hooks are configured.
+pub fn enrich_exec(
+    state: &AppState,
+    source: ActivitySource,
+    command: &str,
+    result: &ExecResult,
+) -> Option<Value> {
+    state.hooks.as_ref()?.post_exec(
+        &json!({ "command": command, "source": source }),
+        &json!({
+            "exit_code": result.exit_code,
+            "stdout": result.stdout,
+            "stderr": result.stderr,
+            "duration_ms": result.duration_ms,
+        }),
+    )
+}
+
+#[cfg(feature = "scripting")]
+mod engine {
+    use std::time::{Duration, Instant};
+
+    use mlua::{HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Value as LuaValue};
+    use serde_json::Value;
+    use tracing::info;
+
+    use crate::config::HooksConfig;
+
+    /// Global functions a script may define.
+    const HOOK_NAMES: [&str; 3] = ["pre_exec", "post_exec", "activity"];
+
+    /// VM instructions between deadline checks.
+    const CHECK_EVERY: u32 = 1000;
+
+    /// `nil` for absent fields rather than mlua's `null` sentinel.
+    const TO_LUA: mlua::SerializeOptions = mlua::SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+
+    /// Functions and other non-data values in a reply are skipped.
+    const FROM_LUA: mlua::DeserializeOptions =
+        mlua::DeserializeOptions::new().deny_unsupported_types(false);
+
+    pub struct Engine {
+        lua: Lua,
+        functions: Vec<&'static str>,
+    }
+
+    /// First line of a Lua error; the rest is a traceback.
+    fn describe(e: &mlua::Error) -> String {
+        e.to_string().lines().next().unwrap_or_default().to_string()
+    }
+
+    impl Engine {
+        pub fn load(config: &HooksConfig, serial: &str) -> Result<Self, String> {
+            let source = std::fs::read_to_string(&config.script)
+                .map_err(|e| format!("cannot read {}: {e}", config.script))?;
+            let lua = Lua::new_with(
+                StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+                LuaOptions::default(),
+            )
+            .map_err(|e| describe(&e))?;
+            lua.set_memory_limit(config.memory_limit_bytes)
+                .map_err(|e| describe(&e))?;
+            let setup = || -> mlua::Result<()> {
+                let sctl = lua.create_table()?;
+                sctl.set("serial", serial)?;
+                sctl.set(
+                    "log",
+                    lua.create_function(|_, msg: String| {
+                        info!(target: "sctl::hooks", "{msg}");
+                        Ok(())
+                    })?,
+                )?;
+                lua.globals().set("sctl", sctl)?;
+                Ok(())
+            };
+            setup().map_err(|e| describe(&e))?;
+
+            let engine = Self {
+                lua,
+                functions: Vec::new(),
+            };
+            engine.arm(Duration::from_millis(config.timeout_ms));
+            let loaded = engine
+                .lua
+                .load(source.as_str())
+                .set_name(config.script.as_str())
+                .exec();
+            engine.lua.remove_hook();
+            loaded.map_err(|e| describe(&e))?;
+
+            let globals = engine.lua.globals();
+            let functions = HOOK_NAMES
+                .into_iter()
+                .filter(|name| matches!(globals.get(*name), Ok(LuaValue::Function(_))))
+                .collect();
+            drop(globals);
+            Ok(Self {
+                functions,
+                ..engine
+            })
+        }
+
+        pub fn functions(&self) -> Vec<&'static str> {
+            self.functions.clone()
+        }
+
+        /// Call global `name`, or `None` if the script doesn't define it.
+        pub fn call(
+            &self,
+            name: &str,
+            args: &[&Value],
+            timeout: Duration,
+        ) -> Option<Result<Value, String>> {
+            if !self.functions.contains(&name) {
+                return None;
+            }
+            let run = || -> mlua::Result<Value> {
+                let f: mlua::Function = self.lua.globals().get(name)?;
+                let args = args
+                    .iter()
+                    .map(|a| self.lua.to_value_with(a, TO_LUA))
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                self.arm(timeout);
+                let ret = f.call::<_, LuaValue>(mlua::MultiValue::from_vec(args));
+                self.lua.remove_hook();
+                self.lua.from_value_with(ret?, FROM_LUA)
+            };
+            Some(run().map_err(|e| describe(&e)))
+        }
+
+        /// Abort whatever runs next once `timeout` has passed.
+        fn arm(&self, timeout: Duration) {
+            let deadline = Instant::now() + timeout;
+            self.lua.set_hook(
+                HookTriggers::new().every_nth_instruction(CHECK_EVERY),
+                move |_, _| {
+                    if Instant::now() >= deadline {
+                        Err(mlua::Error::RuntimeError(format!(
+                            "hook exceeded {}ms",
+                            timeout.as_millis()
+                        )))
+                    } else {
+                        Ok(())
+                    }
+                },
+            );
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod engine {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use crate::config::HooksConfig;
+
+    /// Never constructed: without Lua every load fails.
+    pub struct Engine(Infallible);
+
+    impl Engine {
+        pub fn load(_config: &HooksConfig, _serial: &str) -> Result<Self, String> {
+            Err("sctl was built without the `scripting` feature".to_string())
+        }
+
+        pub fn functions(&self) -> Vec<&'static str> {
+            match self.0 {}
+        }
+
+        pub fn call(
+            &self,
+            _name: &str,
+            _args: &[&Value],
+            _timeout: Duration,
+        ) -> Option<Result<Value, String>> {
+            match self.0 {}
+        }
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+
+    fn hooks(name: &str, script: &str) -> Hooks {
+        let path =
+            std::env::temp_dir().join(format!("sctl-hooks-{name}-{}.lua", std::process::id()));
+        std::fs::write(&path, script).unwrap();
+        let hooks = Hooks::load(
+            HooksConfig {
+                script: path.display().to_string(),
+                timeout_ms: 50,
+                memory_limit_bytes: 8 * 1024 * 1024,
+                fail_open: false,
+            },
+            "TEST-1",
+        );
+        let _ = std::fs::remove_file(&path);
+        hooks
+    }
+
+    fn req(command: &str) -> Value {
+        json!({ "command": command, "source": "rest", "risk": "modify" })
+    }
+
+    #[test]
+    fn pre_exec_allows_denies_and_fails_closed() {
+        let h = hooks(
+            "pre",
+            r#"
+            function pre_exec(req)
+              if req.command:find("reboot") then return { deny = "no reboots on " .. sctl.serial } end
+              if req.command == "spin" then while true do end end
+              if req.command == "rm" then return false end
+            end
+            "#,
+        );
+        assert!(h.pre_exec(&req("uptime")).is_ok());
+        let (status, Json(err)) = h.pre_exec(&req("reboot now")).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(err.code, codes::HOOK_DENIED);
+        assert_eq!(err.message, "no reboots on TEST-1");
+        assert_eq!(h.pre_exec(&req("rm")).unwrap_err().0, StatusCode::FORBIDDEN);
+        let (status, Json(err)) = h.pre_exec(&req("spin")).unwrap_err();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.code, codes::HOOK_FAILED);
+        assert_eq!(h.status()["denied"], 2);
+        assert_eq!(h.status()["hooks"], json!(["pre_exec"]));
+    }
+
+    #[test]
+    fn unloadable_script_refuses_execs() {
+        let h = hooks("broken", "function pre_exec(");
+        assert_eq!(h.status()["loaded"], false);
+        assert_eq!(
+            h.pre_exec(&req("uptime")).unwrap_err().0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn post_exec_and_activity_rewrite() {
+        let h = hooks(
+            "post",
+            r#"
+            function post_exec(req, result)
+              return { failed = result.exit_code ~= 0, lines = #result.stdout }
+            end
+            function activity(entry)
+              if entry.activity_type == "file_list" then return false end
+              return { summary = "[dev] " .. entry.summary }
+            end
+            "#,
+        );
+        let enrichment = h
+            .post_exec(
+                &req("ls"),
+                &json!({ "exit_code": 2, "stdout": "abc", "stderr": "", "duration_ms": 1 }),
+            )
+            .unwrap();
+        assert_eq!(enrichment, json!({ "failed": true, "lines": 3 }));
+
+        let mut entry: ActivityEntry = serde_json::from_value(json!({
+            "id": 1, "timestamp": 0, "activity_type": "exec", "source": "rest", "summary": "ls",
+        }))
+        .unwrap();
+        assert!(h.filter_activity(&mut entry));
+        assert_eq!(entry.summary, "[dev] ls");
+        entry.activity_type = crate::activity::ActivityType::FileList;
+        assert!(!h.filter_activity(&mut entry));
+    }
+}
@@ -0,0 +1,301 @@
+//! Hardware identity for the `identity` info group, `GET /api/info/identity`
+//! and tunnel registration.
+//!
+//! The serial and keys in `sctl.toml` say which device this claims to be; a
+//! copied config says the same thing on different hardware. The identity
+//! describes the hardware itself, so the relay can pin it (see
+//! [`crate::tunnel::identity`]):
+//!
+//! | Field             | Source                                                    |
+//! |-------------------|-----------------------------------------------------------|
+//! | `machine_id`      | `/etc/machine-id` (fallback `/var/lib/dbus/machine-id`)   |
+//! | `model`           | `/proc/device-tree/model` (fallback DMI vendor + product) |
+//! | `compatible`      | `/proc/device-tree/compatible`                            |
+//! | `hardware_serial` | `/proc/device-tree/serial-number`, DMI `product_serial` or the `Serial` line of `/proc/cpuinfo` |
+//! | `tpm`             | `/sys/class/tpm/tpm0`; the EK name via `tpm2_readpublic` when `tpm2-tools` is installed |
+//!
+//! `fingerprint` is a SHA-256 over machine-id, model, hardware serial and EK
+//! name. The identity is read once and cached: none of it changes without a
+//! reboot.
+//!
+//! With `[device] tpm_ak_handle` set, `GET /api/info/identity?nonce=<hex>`
+//! also returns a `tpm2_quote` over `tpm_quote_pcrs` signed by that
+//! attestation key, for verifiers that want proof from the TPM itself rather
+//! than the device's word.
+
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use base64::Engine as _;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+/// TCG's persistent handle for the RSA endorsement key.
+const EK_HANDLE: &str = "0x81010001";
+
+/// How long `tpm2_readpublic` and `tpm2_quote` get before they're abandoned.
+const TPM_TIMEOUT: Duration = Duration::from_secs(15);
+
+static IDENTITY: OnceLock<Identity> = OnceLock::new();
+
+/// What the hardware says about itself.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Identity {
+    pub machine_id: Option<String>,
+    pub model: Option<String>,
+    /// Device-tree `compatible` strings, most specific first.
+    pub compatible: Vec<String>,
+    pub hardware_serial: Option<String>,
+    pub tpm: Option<Tpm>,
+    /// Hex SHA-256 over the stable fields above.
+    pub fingerprint: String,
+}
+
+/// A TPM found in sysfs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tpm {
+    /// `"2"` or `"1.2"`.
+    pub version: Option<String>,
+    /// Name (hash of the public area) of the endorsement key, when
+    /// `tpm2_readpublic` could read it.
+    pub ek_name: Option<String>,
+}
+
+impl Identity {
+    /// Read everything but the EK under `root` (`/` on a device).
+    pub fn read_at(root: &Path) -> Self {
+        let text = |path: &str| {
+            std::fs::read_to_string(root.join(path))
+                .ok()
+                .map(|s| {
+                    s.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                        .to_string()
+                })
+                .filter(|s| !s.is_empty())
+        };
+        let dmi_model = match (
+            text("sys/class/dmi/id/sys_vendor"),
+            text("sys/class/dmi/id/product_name"),
+        ) {
+            (Some(vendor), Some(product)) => Some(format!("{vendor} {product}")),
+            (vendor, product) => product.or(vendor),
+        };
+        let cpuinfo_serial = text("proc/cpuinfo").and_then(|info| {
+            info.lines()
+                .find_map(|l| l.strip_prefix("Serial"))
+                .and_then(|rest| rest.split(':').nth(1))
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty() && s.chars().any(|c| c != '0'))
+        });
+        let tpm_dir = root.join("sys/class/tpm/tpm0");
+        let tpm = tpm_dir.exists().then(|| Tpm {
+            version: text("sys/class/tpm/tpm0/tpm_version_major").map(|v| {
+                if v == "1" {
+                    "1.2".to_string()
+                } else {
+                    v
+                }
+            }),
+            ek_name: None,
+        });
+        let mut identity = Self {
+            machine_id: text("etc/machine-id").or_else(|| text("var/lib/dbus/machine-id")),
+            model: text("proc/device-tree/model").or(dmi_model),
+            compatible: std::fs::read(root.join("proc/device-tree/compatible"))
+                .map(|raw| {
+                    raw.split(|b| *b == 0)
+                        .filter(|s| !s.is_empty())
+                        .map(|s| String::from_utf8_lossy(s).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            hardware_serial: text("proc/device-tree/serial-number")
+                .or_else(|| text("sys/class/dmi/id/product_serial"))
+                .or(cpuinfo_serial),
+            tpm,
+            fingerprint: String::new(),
+        };
+        identity.fingerprint = identity.compute_fingerprint();
+        identity
+    }
+
+    fn compute_fingerprint(&self) -> String {
+        let field = |v: Option<&String>| v.map_or("", String::as_str).to_string();
+        let ek_name = self.tpm.as_ref().and_then(|t| t.ek_name.as_ref());
+        let material = [
+            field(self.machine_id.as_ref()),
+            field(self.model.as_ref()),
+            field(self.hardware_serial.as_ref()),
+            field(ek_name),
+        ]
+        .join("\n");
+        crate::gawdxfer::hasher::hash_bytes(material.as_bytes())
+    }
+}
+
+/// This device's identity, read on first use.
+pub async fn current() -> Identity {
+    if let Some(identity) = IDENTITY.get() {
+        return identity.clone();
+    }
+    let mut identity = crate::io_pool::run(|| Ok(Identity::read_at(Path::new("/"))))
+        .await
+        .unwrap_or_default();
+    if let Some(tpm) = identity.tpm.as_mut() {
+        tpm.ek_name = read_ek_name().await;
+        identity.fingerprint = identity.compute_fingerprint();
+    }
+    info!(
+        fingerprint = %identity.fingerprint,
+        model = identity.model.as_deref().unwrap_or("unknown"),
+        "Device identity"
+    );
+    IDENTITY.get_or_init(|| identity).clone()
+}
+
+/// The EK's name from `tpm2_readpublic`, if tpm2-tools is installed and the
+/// EK is provisioned at the standard handle.
+async fn read_ek_name() -> Option<String> {
+    let output = tokio::time::timeout(
+        TPM_TIMEOUT,
+        tokio::process::Command::new("tpm2_readpublic")
+            .args(["-c", EK_HANDLE])
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await;
+    match output {
+        Ok(Ok(o)) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .lines()
+            .find_map(|l| l.strip_prefix("name:"))
+            .map(|name| name.trim().to_string()),
+        Ok(Ok(o)) => {
+            debug!(
+                "tpm2_readpublic failed: {}",
+                String::from_utf8_lossy(&o.stderr).trim()
+            );
+            None
+        }
+        Ok(Err(e)) => {
+            debug!("tpm2_readpublic unavailable: {e}");
+            None
+        }
+        Err(_) => {
+            debug!("tpm2_readpublic timed out");
+            None
+        }
+    }
+}
+
+/// Have the TPM quote `pcrs` with the attestation key at `ak_handle`,
+/// binding `nonce_hex` into the signed message. Returns the message,
+/// signature and PCR values, base64-encoded as `tpm2_quote` wrote them.
+///
+/// # Errors
+///
+/// `tpm2_quote` is missing, failed or timed out.
+pub async fn quote(ak_handle: &str, pcrs: &str, nonce_hex: &str) -> Result<Value, String> {
+    let dir = std::env::temp_dir().join(format!("sctl-quote-{}", uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&dir).map_err(|e| format!("temp dir: {e}"))?;
+    let result = run_quote(&dir, ak_handle, pcrs, nonce_hex).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+async fn run_quote(
+    dir: &Path,
+    ak_handle: &str,
+    pcrs: &str,
+    nonce_hex: &str,
+) -> Result<Value, String> {
+    let message = dir.join("quote.msg");
+    let signature = dir.join("quote.sig");
+    let pcr_values = dir.join("quote.pcrs");
+    let output = tokio::time::timeout(
+        TPM_TIMEOUT,
+        tokio::process::Command::new("tpm2_quote")
+            .arg("-c")
+            .arg(ak_handle)
+            .arg("-l")
+            .arg(pcrs)
+            .arg("-q")
+            .arg(nonce_hex)
+            .arg("-m")
+            .arg(&message)
+            .arg("-s")
+            .arg(&signature)
+            .arg("-o")
+            .arg(&pcr_values)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| "tpm2_quote timed out".to_string())?
+    .map_err(|e| format!("tpm2_quote: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tpm2_quote failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let encode = |path: &Path| {
+        std::fs::read(path)
+            .map(|data| base64::engine::general_purpose::STANDARD.encode(data))
+            .map_err(|e| format!("reading {}: {e}", path.display()))
+    };
+    Ok(json!({
+        "ak_handle": ak_handle,
+        "pcrs": pcrs,
+        "nonce": nonce_hex,
+        "message": encode(&message)?,
+        "signature": encode(&signature)?,
+        "pcr_values": encode(&pcr_values)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_device_tree_identity() {
+        let root = std::env::temp_dir().join(format!("sctl-identity-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let write = |path: &str, data: &[u8]| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        };
+        write("etc/machine-id", b"0123456789abcdef0123456789abcdef\n");
+        write("proc/device-tree/model", b"Rockchip RV1126 EVB\0");
+        write(
+            "proc/device-tree/compatible",
+            b"rockchip,rv1126-evb\0rockchip,rv1126\0",
+        );
+        write(
+            "proc/cpuinfo",
+            b"processor\t: 0\nSerial\t\t: 00000000c0ffee42\n",
+        );
+        write("sys/class/tpm/tpm0/tpm_version_major", b"2\n");
+
+        let identity = Identity::read_at(&root);
+        assert_eq!(identity.model.as_deref(), Some("Rockchip RV1126 EVB"));
+        assert_eq!(
+            identity.compatible,
+            ["rockchip,rv1126-evb", "rockchip,rv1126"]
+        );
+        assert_eq!(
+            identity.hardware_serial.as_deref(),
+            Some("00000000c0ffee42")
+        );
+        assert_eq!(identity.tpm.as_ref().unwrap().version.as_deref(), Some("2"));
+        assert_eq!(identity.fingerprint.len(), 64);
+
+        // Same config, different board: the fingerprint changes.
+        write("proc/cpuinfo", b"Serial\t\t: 00000000deadbeef\n");
+        assert_ne!(Identity::read_at(&root).fingerprint, identity.fingerprint);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
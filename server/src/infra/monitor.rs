@@ -2,12 +2,17 @@
 //!
 //! Spawned when config is first pushed (or loaded from disk at startup).
 //! Aborted and re-spawned on config change.
+//!
+//! Status transitions and recovery actions are broadcast on `session_events`
+//! (`infra.status`, `infra.recovery`) so they reach the relay live, or get
+//! spooled for replay when the tunnel is down.
 
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::sync::Mutex;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Mutex};
 use tracing::{debug, info};
 
 use super::checks::{self, CheckResult};
@@ -20,6 +25,7 @@ use super::{
 pub fn spawn_monitor(
     infra_state: Arc<Mutex<InfraState>>,
     config: InfraConfig,
+    events: broadcast::Sender<Value>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         info!(
@@ -102,7 +108,17 @@ pub fn spawn_monitor(
                 if new_status == TargetStatus::Down {
                     if let Some(ref recovery) = target.recovery {
                         if recovery.enabled {
-                            try_recovery(&mut state, &target.id, recovery, now).await;
+                            if let Some(entry) =
+                                try_recovery(&mut state, &target.id, recovery, now).await
+                            {
+                                let _ = events.send(json!({
+                                    "type": "infra.recovery",
+                                    "target_id": entry.target_id,
+                                    "command": entry.command,
+                                    "exit_code": entry.exit_code,
+                                    "ts": entry.ts,
+                                }));
+                            }
                         }
                     }
                 }
@@ -118,6 +134,16 @@ pub fn spawn_monitor(
                         "Target {} ({}) status: {prev_status} → {new_status}",
                         target.id, target.name
                     );
+                    let _ = events.send(json!({
+                        "type": "infra.status",
+                        "target_id": target.id,
+                        "name": target.name,
+                        "from": prev_status.to_string(),
+                        "to": new_status.to_string(),
+                        "latency_ms": result.latency_ms,
+                        "detail": result.detail,
+                        "ts": now_iso(),
+                    }));
                 }
             }
         }
@@ -177,12 +203,13 @@ fn compute_status(
 }
 
 /// Attempt to execute a recovery action (respecting cooldown and max retries).
+/// Returns the log entry when the command actually ran.
 async fn try_recovery(
     state: &mut InfraState,
     target_id: &str,
     recovery: &super::RecoveryConfig,
     now: u64,
-) {
+) -> Option<RecoveryLogEntry> {
     let (last_exec, count) = state
         .recovery_tracker
         .get(target_id)
@@ -195,7 +222,7 @@ async fn try_recovery(
             "Recovery for {target_id}: exhausted ({count}/{} retries)",
             recovery.max_retries
         );
-        return;
+        return None;
     }
 
     // Check cooldown
@@ -205,7 +232,7 @@ async fn try_recovery(
             now - last_exec,
             recovery.cooldown_secs
         );
-        return;
+        return None;
     }
 
     info!("Executing recovery for {target_id}: {}", recovery.command);
@@ -227,15 +254,18 @@ async fn try_recovery(
 
     info!("Recovery for {target_id}: exit={exit_code}, output={stdout_trunc}");
 
-    state.push_recovery_log(RecoveryLogEntry {
+    let entry = RecoveryLogEntry {
         ts: now_iso(),
         target_id: target_id.to_string(),
         command: recovery.command.clone(),
         exit_code,
         stdout: stdout_trunc,
-    });
+    };
+    state.push_recovery_log(entry.clone());
 
     state
         .recovery_tracker
         .insert(target_id.to_string(), (now, count + 1));
+
+    Some(entry)
 }
@@ -50,7 +50,7 @@ pub async fn push_config(
     guard.save_config();
 
     // Spawn new monitor
-    let handle = monitor::spawn_monitor(infra.clone(), config);
+    let handle = monitor::spawn_monitor(infra.clone(), config, state.session_events.clone());
     guard.monitor_handle = Some(handle);
 
     info!("Infra config v{version} applied: {target_count} targets");
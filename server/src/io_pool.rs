@@ -0,0 +1,207 @@
+//! Bounded pool for blocking filesystem work.
+//!
+//! Tokio's blocking pool grows to hundreds of threads, so a handful of large
+//! hashes, reads or directory scans can all run at once and starve the async
+//! runtime on single-core devices. [`run`] moves a closure onto the blocking
+//! pool but caps how many run concurrently at `server.io_pool_size`
+//! (default 4); further jobs wait for a slot without holding a thread.
+//!
+//! Counters are reported under `io_pool` in `GET /api/health`, so a pool
+//! that is constantly saturated shows up before requests start timing out.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+/// Concurrent jobs when [`init`] hasn't been called (tests, tools).
+pub const DEFAULT_SIZE: usize = 4;
+
+static POOL: OnceLock<IoPool> = OnceLock::new();
+
+/// Set the pool size. Only the first call has any effect, and only if no
+/// job has run yet.
+pub fn init(size: usize) {
+    let _ = POOL.set(IoPool::new(size));
+}
+
+fn pool() -> &'static IoPool {
+    POOL.get_or_init(|| IoPool::new(DEFAULT_SIZE))
+}
+
+/// Run `f` on the shared pool. See [`IoPool::run`].
+pub async fn run<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    pool().run(f).await
+}
+
+/// Counters for the shared pool.
+pub fn stats() -> IoPoolStats {
+    pool().stats()
+}
+
+/// Snapshot of pool usage, as reported by `GET /api/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct IoPoolStats {
+    /// Maximum concurrent jobs.
+    pub size: usize,
+    /// Jobs running now.
+    pub active: usize,
+    /// Jobs waiting for a slot now.
+    pub queued: usize,
+    /// Jobs finished since startup.
+    pub completed: u64,
+    /// Jobs that had to wait for a slot since startup.
+    pub waited: u64,
+    /// Longest wait for a slot since startup.
+    pub max_wait_ms: u64,
+}
+
+/// A concurrency limit in front of `spawn_blocking`.
+pub struct IoPool {
+    permits: Arc<Semaphore>,
+    size: usize,
+    active: Arc<AtomicUsize>,
+    queued: AtomicUsize,
+    completed: Arc<AtomicU64>,
+    waited: AtomicU64,
+    max_wait_ms: AtomicU64,
+}
+
+/// Decrements a gauge when dropped, so cancelled callers don't leak counts.
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl IoPool {
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(size)),
+            size,
+            active: Arc::new(AtomicUsize::new(0)),
+            queued: AtomicUsize::new(0),
+            completed: Arc::new(AtomicU64::new(0)),
+            waited: AtomicU64::new(0),
+            max_wait_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for a free slot, then run `f` on the blocking pool.
+    ///
+    /// The slot is held until `f` returns, even if the caller is dropped
+    /// first: the work itself can't be cancelled.
+    pub async fn run<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = if let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() {
+            permit
+        } else {
+            let started = Instant::now();
+            let permit = {
+                let _queued = Gauge::enter(&self.queued);
+                Arc::clone(&self.permits)
+                    .acquire_owned()
+                    .await
+                    .map_err(io::Error::other)?
+            };
+            self.waited.fetch_add(1, Ordering::Relaxed);
+            let waited_ms = started.elapsed().as_millis() as u64;
+            self.max_wait_ms.fetch_max(waited_ms, Ordering::Relaxed);
+            permit
+        };
+
+        let active = Arc::clone(&self.active);
+        let completed = Arc::clone(&self.completed);
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let result = {
+                let _active = Gauge::enter(&active);
+                f()
+            };
+            completed.fetch_add(1, Ordering::Relaxed);
+            result
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    pub fn stats(&self) -> IoPoolStats {
+        IoPoolStats {
+            size: self.size,
+            active: self.active.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            waited: self.waited.load(Ordering::Relaxed),
+            max_wait_ms: self.max_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn limits_concurrent_jobs() {
+        let pool = Arc::new(IoPool::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let jobs: Vec<_> = (0..6)
+            .map(|_| {
+                let (pool, running, peak) = (pool.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    pool.run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .await
+                })
+            })
+            .collect();
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        let stats = pool.stats();
+        assert_eq!(stats.completed, 6);
+        assert!(stats.waited >= 4);
+        assert_eq!((stats.active, stats.queued), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn returns_job_errors() {
+        let pool = IoPool::new(1);
+        let err = pool
+            .run(|| Err::<(), _>(io::Error::other("boom")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+        assert_eq!(pool.stats().completed, 1);
+    }
+}
@@ -28,43 +28,125 @@
 //! - `config` — configuration loading
 //! - `sessions` — interactive shell session management
 //! - `activity` — in-memory activity journal
+//! - `activity_journal` — optional on-disk activity history
+//! - `exec_spill` — optional on-disk exec results
+//! - `ai_guard` — AI action budget and kill-switch
+//! - `health_history` — persisted health transitions and flapping detection
+//! - `flight_recorder` — rolling on-disk capture of recent activity, dumped on panic
 //! - `routes` — REST API route handlers
+//! - `msgpack` — MessagePack responses for `Accept: application/msgpack`
 //! - `ws` — WebSocket protocol handling
 //! - `shell` — process spawning and PTY management
 //! - `gawdxfer` — chunked file transfer
+//! - `sftp` — `sftp-server` bridge for standard `sftp`/`scp` clients
+//! - `snapshot` — system state snapshots and diffs
+//! - `sensors` — temperatures, voltages, fans and batteries from sysfs
+//! - `identity` — machine-id, board model, TPM and the hardware fingerprint the relay pins
+//! - `gps_track` — recorded GPS track for `/api/gps/track` and `gps.update`
+//! - `lte_history` — LTE signal samples for `/api/lte/history` and `lte.signal_degraded`
+//! - `firmware` — A/B firmware installs through RAUC, SWUpdate or Mender
+//! - `log_tail` — file and journal tails for `/api/logs` and `logs.follow`
+//! - `dav` — read-only WebDAV export of selected directories
+//! - `artifacts` — file uploads to an S3-compatible bucket
+//! - `quiet_hours` — off-peak windows for transfers and artifact pushes
+//! - `sms_commands` — signed SMS commands for when the tunnel is down
+//! - `tls` — HTTPS/WSS and client certificates for the listeners
+//! - `serve` — HTTP/1.1 and HTTP/2 connections on the listeners, with keep-alive tuning
+//! - `connections` — open connections, for `GET /api/connections`
+//! - `limit` — `max_connections`, with `503 SERVER_BUSY` when a listener is full
+//! - `io_pool` — bounded pool for blocking filesystem work
+//! - `extensions` — extra routes and message types for embedding crates
+//! - `server` — `ServerBuilder`, the setup behind `sctl serve`
+//!
+//! Cargo features: `native-tls` (default) or `rustls` picks the TLS backend
+//! for tunnels, log forwarding and `POST /api/fetch`, `comms` (default) includes GPS/LTE/modem
+//! support, `server-tls` (default) adds `[server.tls]` for the listeners, and `minimal`
+//! is `rustls` with `server-tls` for small-flash devices.
+//!
+//! Builds for Linux (the supported target), macOS and Windows. On Windows,
+//! PTY sessions run on ConPTY and process groups are job objects (see
+//! [`shell::pty`] and [`shell::process`]).
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+compile_error!("sctl builds for Linux, macOS and Windows only");
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable a TLS backend: the `native-tls` (default) or `rustls` feature");
 
 /// Full version: `<cargo-version>.<git-commit-count>`. Bumps on every commit
 /// so a fresh binary is never mistaken for an older one.
 pub const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), ".", env!("SCTL_BUILD_NUMBER"));
 
 pub mod activity;
+pub mod activity_journal;
+pub mod ai_guard;
+#[cfg(feature = "quectel-driver")]
+pub mod apn_failover;
+pub mod artifacts;
 pub mod auth;
 pub mod comms;
 pub mod config;
+pub mod connections;
+pub mod dav;
+pub mod dbus;
+pub mod deadline;
 pub mod error;
+pub mod exec_spill;
+pub mod extensions;
+pub mod fetch;
+pub mod file_watch;
+pub mod firmware;
+pub mod flight_recorder;
 pub mod gawdxfer;
 #[cfg(feature = "quectel-driver")]
 pub mod gps;
+pub mod gps_track;
+pub mod health_history;
+pub mod hooks;
+pub mod identity;
 pub mod infra;
+pub mod io_pool;
+pub mod limit;
+pub mod log_forward;
+pub mod log_tail;
 #[cfg(feature = "quectel-driver")]
 pub mod lte;
+pub mod lte_history;
 #[cfg(feature = "quectel-driver")]
 pub mod lte_watchdog;
+pub mod metrics;
 #[cfg(feature = "quectel-driver")]
 pub mod modem;
+pub mod mqtt;
+pub mod msgpack;
 pub mod platform;
+pub mod plugins;
+pub mod quiet_hours;
 pub mod routes;
+pub mod sensors;
+pub mod serve;
+pub mod server;
 pub mod sessions;
+pub mod sftp;
 pub mod shell;
+pub mod sms_commands;
+pub mod snapshot;
+pub mod snmp;
+pub mod startup;
 pub mod state;
+#[cfg(feature = "server-tls")]
+pub mod tls;
 pub mod tunnel;
+pub mod twin;
 pub mod util;
 pub mod ws;
+pub mod xattr;
 
 // Re-export key types at crate root for convenience.
 pub use activity::{ActivityLog, ExecResultsCache};
 pub use auth::ApiKey;
 pub use config::Config;
+pub use server::{Server, ServerBuilder};
 pub use sessions::SessionManager;
 pub use state::AppState;
 pub use tunnel::relay::RelayState;
@@ -0,0 +1,334 @@
+//! `max_connections`: how many requests a listener serves at once.
+//!
+//! A request that arrives while every slot is taken is refused at once with
+//! `503 Service Unavailable`, code `SERVER_BUSY` and `Retry-After`, instead of
+//! waiting in an unbounded queue for a slot (as `tower`'s
+//! `ConcurrencyLimitLayer` did) while the client times out.
+//!
+//! `/api/health` is served whatever the load, so liveness probes never see a
+//! `503`. Other requests needed to inspect an overloaded device and stop its
+//! runaway work — auth, listing and killing or signalling sessions, the AI
+//! kill-switch, connections (see [`is_priority`]) — take a slot in a separate
+//! priority lane of [`PRIORITY_SLOTS`] instead, so exec, file and transfer
+//! requests filling `max_connections` can't lock them out.
+//!
+//! Refusals are counted across listeners in [`Saturation`] — for
+//! `/api/health` and `sctl_requests_rejected_total` in `/api/metrics` — and
+//! the first one records a `load` → `saturated` transition in the health
+//! history. The history monitor records `ok` once nothing has been refused
+//! for a poll interval.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::error::{codes, ApiError};
+use crate::health_history::{Condition, HealthHistory};
+use crate::infra::now_epoch;
+use crate::AppState;
+
+/// Seconds a refused client is told to wait.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Paths served whatever the load, outside both lanes.
+const EXEMPT: &[&str] = &["/api/health"];
+
+/// Slots in each listener's priority lane, on top of `max_connections`.
+pub const PRIORITY_SLOTS: usize = 16;
+
+/// Requests refused because a listener was full.
+#[derive(Default)]
+pub struct Saturation {
+    rejected: AtomicU64,
+    /// Unix seconds of the last refusal, 0 if none.
+    last_rejected: AtomicU64,
+    /// Set by the first refusal, cleared by [`Saturation::recovered`].
+    saturated: AtomicBool,
+}
+
+impl Saturation {
+    /// Count a refusal. Returns `true` for the first one since the last
+    /// recovery.
+    fn reject(&self) -> bool {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        self.last_rejected.store(now_epoch(), Ordering::Relaxed);
+        !self.saturated.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Whether nothing has been refused for `quiet_secs`; clears the
+    /// saturated flag when so.
+    pub fn recovered(&self, quiet_secs: u64) -> bool {
+        let last = self.last_rejected.load(Ordering::Relaxed);
+        if now_epoch().saturating_sub(last) < quiet_secs {
+            return false;
+        }
+        self.saturated.store(false, Ordering::Relaxed);
+        true
+    }
+
+    /// `{rejected_total, last_rejected_at}` for `/api/health`.
+    pub fn summary(&self) -> Value {
+        let last = self.last_rejected.load(Ordering::Relaxed);
+        json!({
+            "rejected_total": self.rejected(),
+            "last_rejected_at": (last > 0).then_some(last),
+        })
+    }
+}
+
+/// One listener's slots, for [`enforce`].
+#[derive(Clone)]
+pub struct RequestLimit {
+    slots: Arc<Semaphore>,
+    max: usize,
+    priority: Arc<Semaphore>,
+    saturation: Arc<Saturation>,
+    history: Arc<HealthHistory>,
+}
+
+impl RequestLimit {
+    /// `max_connections` slots, with refusals counted in the state's
+    /// [`Saturation`].
+    pub fn new(state: &AppState) -> Self {
+        Self::with_max(
+            state.config.server.max_connections,
+            state.saturation.clone(),
+            state.health_history.clone(),
+        )
+    }
+
+    fn with_max(max: usize, saturation: Arc<Saturation>, history: Arc<HealthHistory>) -> Self {
+        let max = max.max(1);
+        Self {
+            slots: Arc::new(Semaphore::new(max)),
+            max,
+            priority: Arc::new(Semaphore::new(PRIORITY_SLOTS)),
+            saturation,
+            history,
+        }
+    }
+}
+
+/// Whether a request goes in the priority lane: what it takes to see what an
+/// overloaded device is doing and stop it.
+pub fn is_priority(method: &Method, path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match *method {
+        Method::GET => matches!(
+            segments.as_slice(),
+            ["api", "sessions" | "connections"] | ["api", "health", "history"]
+        ),
+        Method::POST => matches!(
+            segments.as_slice(),
+            ["api", "auth", _] | ["api", "ai", "disable"] | ["api", "sessions", _, "signal"]
+        ),
+        Method::DELETE => matches!(segments.as_slice(), ["api", "sessions" | "connections", _]),
+        _ => false,
+    }
+}
+
+/// Middleware: run the request in a free slot of its lane, or refuse it.
+pub async fn enforce(State(limit): State<RequestLimit>, request: Request, next: Next) -> Response {
+    if EXEMPT.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let priority = is_priority(request.method(), request.uri().path());
+    let slots = if priority {
+        &limit.priority
+    } else {
+        &limit.slots
+    };
+    let Ok(_slot) = slots.clone().try_acquire_owned() else {
+        return refuse(&limit, request.uri().path(), priority);
+    };
+    next.run(request).await
+}
+
+fn refuse(limit: &RequestLimit, path: &str, priority: bool) -> Response {
+    let (max, lane) = if priority {
+        (PRIORITY_SLOTS, "priority")
+    } else {
+        (limit.max, "standard")
+    };
+    if limit.saturation.reject() {
+        warn!(
+            max_connections = max,
+            lane, path, "Listener saturated, refusing requests"
+        );
+        let history = limit.history.clone();
+        tokio::spawn(async move {
+            history
+                .observe(
+                    Condition::Load,
+                    "saturated",
+                    &format!("{lane} lane full ({max} requests)"),
+                )
+                .await;
+        });
+    }
+    let mut response = ApiError::new(
+        codes::SERVER_BUSY,
+        format!("Server busy: {max} requests already in progress"),
+    )
+    .with_detail(json!({
+        "lane": lane,
+        "max_connections": max,
+        "retry_after_secs": RETRY_AFTER_SECS,
+    }))
+    .into_response_with(StatusCode::SERVICE_UNAVAILABLE)
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    #[test]
+    fn control_requests_take_the_priority_lane() {
+        assert!(is_priority(&Method::GET, "/api/health/history"));
+        assert!(is_priority(&Method::GET, "/api/sessions"));
+        assert!(is_priority(&Method::DELETE, "/api/sessions/abc"));
+        assert!(is_priority(&Method::POST, "/api/sessions/abc/signal"));
+        assert!(is_priority(&Method::POST, "/api/ai/disable"));
+        assert!(!is_priority(&Method::POST, "/api/sessions"));
+        assert!(!is_priority(&Method::POST, "/api/exec"));
+        assert!(!is_priority(&Method::GET, "/api/files"));
+        assert!(!is_priority(&Method::GET, "/api/sessions/abc/recording"));
+    }
+
+    #[tokio::test]
+    async fn full_listener_still_serves_the_priority_lane() {
+        let saturation = Arc::new(Saturation::default());
+        let history = Arc::new(HealthHistory::new());
+        let limit = RequestLimit::with_max(1, saturation.clone(), history.clone());
+        let (entered, release) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let (enter, held) = (entered.clone(), release.clone());
+        let app = Router::new()
+            .route(
+                "/api/slow",
+                get(move || {
+                    let (enter, held) = (enter.clone(), held.clone());
+                    async move {
+                        enter.notify_one();
+                        held.notified().await;
+                    }
+                }),
+            )
+            .route("/api/health", get(|| async { "ok" }))
+            .route(
+                "/api/sessions/{id}",
+                axum::routing::delete(|| async { "killed" }),
+            )
+            .layer(middleware::from_fn_with_state(limit, enforce));
+        let get = |path: &str| {
+            app.clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        };
+
+        let slow = tokio::spawn(get("/api/slow"));
+        entered.notified().await;
+        let refused = get("/api/slow").await.unwrap();
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(refused.headers()[header::RETRY_AFTER], "1");
+        let body = axum::body::to_bytes(refused.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], codes::SERVER_BUSY);
+        assert_eq!(body["detail"]["lane"], "standard");
+        assert_eq!(get("/api/health").await.unwrap().status(), StatusCode::OK);
+        let kill = app
+            .clone()
+            .oneshot(
+                Request::delete("/api/sessions/abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(kill.status(), StatusCode::OK);
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        // The slot is free again.
+        release.notify_one();
+        assert_eq!(get("/api/slow").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(saturation.rejected(), 1);
+        tokio::task::yield_now().await;
+        assert_eq!(
+            history.current(Condition::Load).await.as_deref(),
+            Some("saturated")
+        );
+        assert!(!saturation.recovered(60));
+        assert!(saturation.recovered(0));
+    }
+
+    #[tokio::test]
+    async fn health_is_served_with_both_lanes_full() {
+        let saturation = Arc::new(Saturation::default());
+        let limit = RequestLimit::with_max(1, saturation.clone(), Arc::new(HealthHistory::new()));
+        let entered = Arc::new(tokio::sync::Semaphore::new(0));
+        let release = Arc::new(Notify::new());
+        let (enter, wait) = (entered.clone(), release.clone());
+        let hold = move || {
+            let (enter, wait) = (enter.clone(), wait.clone());
+            async move {
+                enter.add_permits(1);
+                wait.notified().await;
+            }
+        };
+        let app = Router::new()
+            .route("/api/slow", get(hold.clone()))
+            .route("/api/sessions", get(hold))
+            .route("/api/health", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(limit, enforce));
+        let get = |path: &str| {
+            app.clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        };
+
+        // One standard request and PRIORITY_SLOTS priority ones hold every slot.
+        let mut holders = vec![tokio::spawn(get("/api/slow"))];
+        for _ in 0..PRIORITY_SLOTS {
+            holders.push(tokio::spawn(get("/api/sessions")));
+        }
+        let all = u32::try_from(holders.len()).unwrap();
+        let _ = entered.acquire_many(all).await.unwrap();
+        assert_eq!(
+            get("/api/sessions").await.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            get("/api/slow").await.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        assert_eq!(get("/api/health").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(saturation.rejected(), 2);
+
+        release.notify_waiters();
+        for request in holders {
+            assert_eq!(request.await.unwrap().unwrap().status(), StatusCode::OK);
+        }
+    }
+}
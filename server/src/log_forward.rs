@@ -0,0 +1,485 @@
+//! Remote log forwarding.
+//!
+//! Ships activity entries and selected tracing events to a remote syslog
+//! collector or Vector over TCP (optionally TLS), configured under
+//! `[logging.forward]`. Without it, audit data only lives in the in-memory
+//! activity ring and the local journal.
+//!
+//! ## Design
+//!
+//! - **Sources**: activity entries are taken from the `activity.new` broadcast;
+//!   tracing events at or above `log_level` come from a [`tracing_subscriber`]
+//!   layer installed at startup. Events from this module are never forwarded,
+//!   so connection errors can't feed back into the queue.
+//! - **Buffering**: records are formatted on arrival and queued (bounded by
+//!   `buffer_size`). While the collector is unreachable the oldest records are
+//!   dropped first and counted in `dropped`.
+//! - **Retry**: the writer reconnects with exponential backoff up to
+//!   `reconnect_max_secs`. A batch that fails mid-write is requeued at the
+//!   front, so records are delivered at least once.
+//! - **Framing**: `syslog` sends RFC 5424 messages with RFC 6587 octet-counting
+//!   (required for TLS by RFC 5425); `json` sends one object per line.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::LogForwardConfig;
+
+/// Syslog facility `local0`.
+const FACILITY: u8 = 16;
+
+/// Maximum records written per batch.
+const BATCH_SIZE: usize = 256;
+
+/// Record wire format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Syslog,
+    Json,
+}
+
+/// Syslog severity for a tracing level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Keyword used for `severity` in JSON records.
+fn severity_name(severity: u8) -> &'static str {
+    match severity {
+        3 => "error",
+        4 => "warning",
+        6 => "info",
+        _ => "debug",
+    }
+}
+
+/// RFC 5424 HOSTNAME: printable ASCII without spaces, max 255 chars.
+fn syslog_hostname(serial: &str) -> String {
+    let host: String = serial
+        .chars()
+        .filter(char::is_ascii_graphic)
+        .take(255)
+        .collect();
+    if host.is_empty() {
+        "-".to_string()
+    } else {
+        host
+    }
+}
+
+/// Format one framed record.
+///
+/// `msgid` is `activity` or `log`. For syslog, `body` is rendered as the MSG
+/// (compact JSON for objects); for JSON it is merged into the record object.
+fn format_record(
+    format: Format,
+    hostname: &str,
+    timestamp_ms: u64,
+    severity: u8,
+    msgid: &str,
+    body: &Value,
+) -> String {
+    match format {
+        Format::Syslog => {
+            let pri = u16::from(FACILITY) * 8 + u16::from(severity);
+            let ts = crate::util::format_iso8601_utc(timestamp_ms / 1000);
+            let msg = match body {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            let line = format!("<{pri}>1 {ts} {hostname} sctl - {msgid} - {msg}");
+            format!("{} {line}", line.len())
+        }
+        Format::Json => {
+            let mut record = json!({
+                "timestamp": timestamp_ms,
+                "host": hostname,
+                "app": "sctl",
+                "kind": msgid,
+                "severity": severity_name(severity),
+            });
+            match body {
+                Value::Object(fields) => {
+                    for (k, v) in fields {
+                        record[k] = v.clone();
+                    }
+                }
+                other => record["message"] = other.clone(),
+            }
+            let mut line = record.to_string();
+            line.push('\n');
+            line
+        }
+    }
+}
+
+/// Shared forwarding queue and connection state.
+pub struct LogForwarder {
+    config: LogForwardConfig,
+    format: Format,
+    level: LevelFilter,
+    hostname: String,
+    queue: Mutex<VecDeque<String>>,
+    notify: Notify,
+    connected: AtomicBool,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl LogForwarder {
+    /// Create the forwarder. Nothing is sent until [`spawn`](Self::spawn).
+    pub fn new(config: LogForwardConfig, serial: &str) -> Arc<Self> {
+        let format = if config.format == "json" {
+            Format::Json
+        } else {
+            Format::Syslog
+        };
+        let level = config.log_level.parse().unwrap_or(LevelFilter::WARN);
+        Arc::new(Self {
+            format,
+            level,
+            hostname: syslog_hostname(serial),
+            queue: Mutex::new(VecDeque::with_capacity(config.buffer_size.min(1024))),
+            notify: Notify::new(),
+            connected: AtomicBool::new(false),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            config,
+        })
+    }
+
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, VecDeque<String>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue a formatted record, dropping the oldest if the buffer is full.
+    fn push(&self, record: String) {
+        {
+            let mut queue = self.lock_queue();
+            if queue.len() >= self.config.buffer_size {
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(record);
+        }
+        self.notify.notify_one();
+    }
+
+    /// Queue an activity entry (the `entry` object of an `activity.new` event).
+    pub fn push_activity(&self, entry: &Value) {
+        let timestamp = entry["timestamp"]
+            .as_u64()
+            .unwrap_or_else(crate::sessions::journal::now_ms);
+        let body = match self.format {
+            Format::Json => json!({ "entry": entry }),
+            // Syslog MSG is the bare entry as compact JSON.
+            Format::Syslog => entry.clone(),
+        };
+        self.push(format_record(
+            self.format,
+            &self.hostname,
+            timestamp,
+            6,
+            "activity",
+            &body,
+        ));
+    }
+
+    fn push_event(&self, level: Level, target: &str, message: &str) {
+        let body = match self.format {
+            Format::Json => json!({ "target": target, "message": message }),
+            Format::Syslog => Value::String(format!("{target}: {message}")),
+        };
+        self.push(format_record(
+            self.format,
+            &self.hostname,
+            crate::sessions::journal::now_ms(),
+            severity(level),
+            "log",
+            &body,
+        ));
+    }
+
+    /// Tracing layer that queues events at or above `log_level`.
+    pub fn layer<S>(self: &Arc<Self>) -> impl Layer<S>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        ForwardLayer {
+            forwarder: self.clone(),
+        }
+        .with_filter(self.level)
+    }
+
+    /// Forwarding status for `/api/info`.
+    pub fn status(&self) -> Value {
+        json!({
+            "address": self.config.address,
+            "tls": self.config.tls,
+            "format": self.config.format,
+            "connected": self.connected.load(Ordering::Relaxed),
+            "queued": self.lock_queue().len(),
+            "sent": self.sent.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Start the activity subscriber and the writer. Returns both handles for
+    /// abort on shutdown.
+    pub fn spawn(
+        self: &Arc<Self>,
+        session_events: &broadcast::Sender<Value>,
+    ) -> [JoinHandle<()>; 2] {
+        let forwarder = self.clone();
+        let mut rx = session_events.subscribe();
+        let subscriber = tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) if msg["type"] == "activity.new" => {
+                        forwarder.push_activity(&msg["entry"]);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        forwarder.dropped.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let writer = tokio::spawn(self.clone().run_writer());
+        [subscriber, writer]
+    }
+
+    async fn connect(&self) -> Result<Box<dyn AsyncWrite + Send + Unpin>, String> {
+        let stream = tokio::time::timeout(
+            Duration::from_secs(10),
+            TcpStream::connect(&self.config.address),
+        )
+        .await
+        .map_err(|_| "connect timed out".to_string())?
+        .map_err(|e| format!("connect: {e}"))?;
+        let _ = stream.set_nodelay(true);
+
+        if !self.config.tls {
+            return Ok(Box::new(stream));
+        }
+        let host = self
+            .config
+            .address
+            .rsplit_once(':')
+            .map_or(self.config.address.as_str(), |(h, _)| h)
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        tls_connect(host, stream).await
+    }
+
+    async fn run_writer(self: Arc<Self>) {
+        let initial = Duration::from_secs(1);
+        let max = Duration::from_secs(self.config.reconnect_max_secs.max(1));
+        let mut delay = initial;
+
+        loop {
+            let mut conn = match self.connect().await {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!(
+                        "Log forwarding to {} failed: {e}; retrying in {}s",
+                        self.config.address,
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(max);
+                    continue;
+                }
+            };
+            delay = initial;
+            self.connected.store(true, Ordering::Relaxed);
+            info!("Log forwarding connected to {}", self.config.address);
+
+            loop {
+                let batch: Vec<String> = {
+                    let mut queue = self.lock_queue();
+                    let n = queue.len().min(BATCH_SIZE);
+                    queue.drain(..n).collect()
+                };
+                if batch.is_empty() {
+                    self.notify.notified().await;
+                    continue;
+                }
+
+                let result = async {
+                    conn.write_all(batch.concat().as_bytes()).await?;
+                    conn.flush().await
+                }
+                .await;
+                if let Err(e) = result {
+                    warn!(
+                        "Log forwarding to {} lost: {e}; requeueing {} record(s)",
+                        self.config.address,
+                        batch.len()
+                    );
+                    let mut queue = self.lock_queue();
+                    for record in batch.into_iter().rev() {
+                        queue.push_front(record);
+                    }
+                    while queue.len() > self.config.buffer_size {
+                        queue.pop_back();
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    break;
+                }
+                self.sent.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+
+            self.connected.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Tracing layer feeding a [`LogForwarder`].
+struct ForwardLayer {
+    forwarder: Arc<LogForwarder>,
+}
+
+impl<S: tracing::Subscriber> Layer<S> for ForwardLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        // Our own connection warnings would otherwise refill the queue.
+        if meta.target().starts_with(module_path!()) {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.forwarder
+            .push_event(*meta.level(), meta.target(), &visitor.0);
+    }
+}
+
+/// Renders an event as `message key=value ...`.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            if !self.0.is_empty() {
+                self.0.push(' ');
+            }
+            self.0.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+}
+
+/// Wrap `stream` in TLS, verifying the certificate for `host`.
+#[cfg(feature = "native-tls")]
+async fn tls_connect(
+    host: &str,
+    stream: TcpStream,
+) -> Result<Box<dyn AsyncWrite + Send + Unpin>, String> {
+    let connector =
+        tokio_native_tls::native_tls::TlsConnector::new().map_err(|e| format!("tls init: {e}"))?;
+    let tls = tokio_native_tls::TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| format!("tls handshake: {e}"))?;
+    Ok(Box::new(tls))
+}
+
+/// Wrap `stream` in TLS, verifying the certificate for `host` against the
+/// bundled webpki roots.
+#[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+async fn tls_connect(
+    host: &str,
+    stream: TcpStream,
+) -> Result<Box<dyn AsyncWrite + Send + Unpin>, String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .map_err(|e| format!("tls init: {e}"))?;
+    let tls = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| format!("tls handshake: {e}"))?;
+    Ok(Box::new(tls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syslog_record_is_octet_counted_rfc5424() {
+        let rec = format_record(
+            Format::Syslog,
+            "SCTL-0001",
+            1_760_000_000_000,
+            4,
+            "log",
+            &Value::String("sctl::tunnel: lost relay".into()),
+        );
+        let (len, line) = rec.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), line.len());
+        assert_eq!(
+            line,
+            "<132>1 2025-10-09T08:53:20Z SCTL-0001 sctl - log - sctl::tunnel: lost relay"
+        );
+    }
+
+    #[test]
+    fn json_record_merges_body_fields() {
+        let rec = format_record(
+            Format::Json,
+            "dev",
+            5,
+            6,
+            "activity",
+            &json!({"entry": {"id": 1}}),
+        );
+        assert!(rec.ends_with('\n'));
+        let v: Value = serde_json::from_str(rec.trim_end()).unwrap();
+        assert_eq!(v["kind"], "activity");
+        assert_eq!(v["severity"], "info");
+        assert_eq!(v["entry"]["id"], 1);
+        assert_eq!(v["host"], "dev");
+    }
+
+    #[test]
+    fn hostname_strips_spaces_and_control_chars() {
+        assert_eq!(syslog_hostname("SCTL 01\n"), "SCTL01");
+        assert_eq!(syslog_hostname(" "), "-");
+    }
+}
@@ -0,0 +1,791 @@
+//! Log tails for `GET /api/logs` and `logs.follow` (WebSocket and tunnel).
+//!
+//! A tail reads a file (`path`) or a systemd unit's journal (`unit`, through
+//! `journalctl -o json`) and sends it line by line: first the last `lines`
+//! lines, then, when following, each new one. Lines longer than 16 KiB are
+//! cut and marked `truncated`; `grep` keeps only lines matching a regex.
+//!
+//! Files are polled: appended data is read as it arrives, and a file that is
+//! truncated (`copytruncate`) or replaced (rotated by rename) is reopened
+//! from the start, after reading what was left in the old one. The journal
+//! is followed with `journalctl --follow --after-cursor`, so the backlog and
+//! the live entries neither overlap nor leave a gap.
+//!
+//! New lines are batched for 50 ms and sent as `logs.lines`. A tail never
+//! waits for a slow client: when the connection's queue is full a batch is
+//! dropped and counted, and the next batch that gets through carries the
+//! count in `dropped`.
+//!
+//! Tails belong to a connection ([`LogTails`]) and stop when it closes.
+
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use axum::Json;
+use regex_lite::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::Instant;
+
+use crate::error::{codes, ApiError};
+use crate::ws::messages::WsServerMsg;
+
+/// Tails one connection may hold at once.
+pub const MAX_TAILS: usize = 16;
+
+const DEFAULT_LINES: usize = 10;
+const MAX_LINES: usize = 1000;
+
+/// Longer lines are cut here and marked `truncated`.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// How far back from the end of a file the backlog looks.
+const BACKLOG_WINDOW: u64 = 256 * 1024;
+
+/// Most bytes read from a file per poll.
+const MAX_READ: usize = 1024 * 1024;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const BATCH_WINDOW: Duration = Duration::from_millis(50);
+const MAX_BATCH: usize = 256;
+
+/// Bound on the one-shot `journalctl` for the backlog.
+const JOURNAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+type ErrorPair = (StatusCode, Json<ApiError>);
+
+/// One line of a tail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export, optional_fields))]
+pub struct LogLine {
+    pub line: String,
+    /// Journal entry time, Unix ms. Not set for files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts_ms: Option<u64>,
+    /// Journal priority, 0 (emerg) to 7 (debug). Not set for files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// The line was longer than 16 KiB and has been cut.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl LogLine {
+    fn new(bytes: &[u8]) -> Self {
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        let truncated = bytes.len() > MAX_LINE_BYTES;
+        Self {
+            line: String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_LINE_BYTES)]).into_owned(),
+            ts_ms: None,
+            priority: None,
+            truncated,
+        }
+    }
+}
+
+/// Query of `GET /api/logs` and fields of `logs.follow`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TailRequest {
+    /// Absolute path of a file to tail.
+    pub path: Option<String>,
+    /// systemd unit whose journal to tail (instead of `path`).
+    pub unit: Option<String>,
+    /// Lines of backlog (default 10, max 1000; 0 for none).
+    pub lines: Option<usize>,
+    /// Only lines matching this regex.
+    pub grep: Option<String>,
+}
+
+enum Source {
+    File(PathBuf),
+    Unit(String),
+}
+
+/// A parsed [`TailRequest`].
+pub struct TailSpec {
+    source: Source,
+    lines: usize,
+    grep: Option<Regex>,
+}
+
+fn invalid(message: impl Into<String>) -> ErrorPair {
+    ApiError::new(codes::INVALID_REQUEST, message).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+fn io_error(path: &std::path::Path, e: &std::io::Error) -> ErrorPair {
+    let (status, code) = match e.kind() {
+        std::io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, codes::FILE_NOT_FOUND),
+        std::io::ErrorKind::PermissionDenied => (StatusCode::FORBIDDEN, codes::PERMISSION_DENIED),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, codes::IO_ERROR),
+    };
+    ApiError::new(code, format!("{}: {e}", path.display())).into_response_with(status)
+}
+
+impl TailSpec {
+    /// Validate a request. A `path` tail needs the `files:read` scope.
+    ///
+    /// # Errors
+    ///
+    /// `400` for a bad request (neither or both sources, bad unit name or
+    /// regex), `403 AUTH_INSUFFICIENT_SCOPE`, or the errors of
+    /// [`validate_path`](crate::routes::files::validate_path).
+    pub fn parse(req: &TailRequest) -> Result<Self, ErrorPair> {
+        let source = match (&req.path, &req.unit) {
+            (Some(path), None) => {
+                if !crate::auth::current_allows("files:read") {
+                    return Err(ApiError::new(
+                        codes::AUTH_INSUFFICIENT_SCOPE,
+                        "Tailing a file needs the 'files:read' scope",
+                    )
+                    .into_response_with(StatusCode::FORBIDDEN));
+                }
+                Source::File(crate::routes::files::validate_path(path)?)
+            }
+            (None, Some(unit)) => {
+                let valid = !unit.is_empty()
+                    && unit.len() <= 255
+                    && !unit.starts_with('-')
+                    && unit
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_.@:\\".contains(c));
+                if !valid {
+                    return Err(invalid(format!("Invalid unit name '{unit}'")));
+                }
+                Source::Unit(unit.clone())
+            }
+            _ => return Err(invalid("Give exactly one of 'path' or 'unit'")),
+        };
+        let lines = req.lines.unwrap_or(DEFAULT_LINES);
+        if lines > MAX_LINES {
+            return Err(invalid(format!("lines must be at most {MAX_LINES}")));
+        }
+        let grep = req
+            .grep
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| invalid(format!("Invalid grep regex: {e}")))?;
+        Ok(Self {
+            source,
+            lines,
+            grep,
+        })
+    }
+
+    fn keep(&self, line: &LogLine) -> bool {
+        self.grep.as_ref().is_none_or(|re| re.is_match(&line.line))
+    }
+
+    /// Where the lines come from, for acks and responses:
+    /// `(path, unit)`.
+    pub fn describe(&self) -> (Option<String>, Option<String>) {
+        match &self.source {
+            Source::File(path) => (Some(path.to_string_lossy().into_owned()), None),
+            Source::Unit(unit) => (None, Some(unit.clone())),
+        }
+    }
+}
+
+/// Splits a byte stream into [`LogLine`]s, holding back an unfinished last
+/// line. A line that grows past the limit is sent cut, and the rest of it
+/// skipped.
+#[derive(Default)]
+struct LineSplitter {
+    partial: Vec<u8>,
+    skipping: bool,
+}
+
+impl LineSplitter {
+    fn push(&mut self, data: &[u8], out: &mut Vec<LogLine>) {
+        for chunk in data.split_inclusive(|&b| b == b'\n') {
+            let complete = chunk.ends_with(b"\n");
+            let body = chunk.strip_suffix(b"\n").unwrap_or(chunk);
+            if self.skipping {
+                self.skipping = !complete;
+                continue;
+            }
+            self.partial.extend_from_slice(body);
+            if self.partial.len() > MAX_LINE_BYTES {
+                out.push(LogLine::new(&self.partial));
+                self.partial.clear();
+                self.skipping = !complete;
+            } else if complete {
+                out.push(LogLine::new(&self.partial));
+                self.partial.clear();
+            }
+        }
+    }
+}
+
+// ─── Backlog ─────────────────────────────────────────────────────────────────
+
+/// Where a followed file was read up to.
+struct FilePos {
+    file: tokio::fs::File,
+    offset: u64,
+    inode: u64,
+}
+
+/// The last `spec.lines` matching lines of a file, and the position after
+/// them. An unfinished last line is included, unless `follow`ing: then it is
+/// left to be read once it is finished.
+async fn file_backlog(
+    path: &PathBuf,
+    spec: &TailSpec,
+    follow: bool,
+) -> Result<(Vec<LogLine>, FilePos), ErrorPair> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| io_error(path, &e))?;
+    let meta = file.metadata().await.map_err(|e| io_error(path, &e))?;
+    if meta.is_dir() {
+        return Err(ApiError::new(codes::IS_DIRECTORY, "Path is a directory")
+            .into_response_with(StatusCode::BAD_REQUEST));
+    }
+    let len = meta.len();
+    let start = len.saturating_sub(BACKLOG_WINDOW);
+    let mut lines = Vec::new();
+    let mut offset = len;
+    if len > 0 {
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| io_error(path, &e))?;
+        let mut data = Vec::new();
+        (&mut file)
+            .take(len - start)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| io_error(path, &e))?;
+        // Mid-file, the first line is probably partial.
+        let data = match data.iter().position(|&b| b == b'\n') {
+            Some(i) if start > 0 => &data[i + 1..],
+            _ => &data[..],
+        };
+        let mut splitter = LineSplitter::default();
+        splitter.push(data, &mut lines);
+        if follow {
+            offset -= splitter.partial.len() as u64;
+        } else if !splitter.partial.is_empty() {
+            lines.push(LogLine::new(&splitter.partial));
+        }
+        lines.retain(|l| spec.keep(l));
+        lines.drain(..lines.len().saturating_sub(spec.lines));
+    }
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| io_error(path, &e))?;
+    Ok((
+        lines,
+        FilePos {
+            file,
+            offset,
+            inode: file_id(&meta),
+        },
+    ))
+}
+
+/// What tells a replacement file from the one being read: the inode, or on
+/// Windows, where std exposes no file index, the creation time.
+fn file_id(meta: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::MetadataExt::ino(meta)
+    }
+    #[cfg(windows)]
+    {
+        meta.created()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+    }
+}
+
+/// A `journalctl -o json` record as a line, with its cursor.
+fn journal_line(record: &str) -> Option<(LogLine, Option<String>)> {
+    let v: Value = serde_json::from_str(record).ok()?;
+    let mut line = match &v["MESSAGE"] {
+        Value::String(s) => LogLine::new(s.as_bytes()),
+        // Messages that aren't valid UTF-8 come as byte arrays.
+        Value::Array(bytes) => LogLine::new(
+            &bytes
+                .iter()
+                .filter_map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Vec<u8>>(),
+        ),
+        _ => LogLine::new(b""),
+    };
+    line.ts_ms = v["__REALTIME_TIMESTAMP"]
+        .as_str()
+        .and_then(|t| t.parse::<u64>().ok())
+        .map(|usec| usec / 1000);
+    line.priority = v["PRIORITY"].as_str().and_then(|p| p.parse().ok());
+    let cursor = v["__CURSOR"].as_str().map(ToString::to_string);
+    Some((line, cursor))
+}
+
+fn journalctl(unit: &str) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new("journalctl");
+    cmd.args(["--no-pager", "-o", "json", "-u", unit])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    cmd
+}
+
+fn journal_error(e: &std::io::Error) -> ErrorPair {
+    ApiError::new(codes::EXEC_FAILED, format!("journalctl: {e}"))
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The last `spec.lines` matching entries of `unit`'s journal, and the
+/// cursor of the last entry read.
+async fn journal_backlog(
+    unit: &str,
+    spec: &TailSpec,
+) -> Result<(Vec<LogLine>, Option<String>), ErrorPair> {
+    // With a filter, look further back for enough matches.
+    let scan = if spec.grep.is_some() {
+        MAX_LINES
+    } else {
+        spec.lines.max(1)
+    };
+    let output = tokio::time::timeout(
+        JOURNAL_TIMEOUT,
+        journalctl(unit).args(["-n", &scan.to_string()]).output(),
+    )
+    .await
+    .map_err(|_| {
+        ApiError::new(codes::TIMEOUT, "journalctl did not finish")
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT)
+    })?
+    .map_err(|e| journal_error(&e))?;
+    if !output.status.success() {
+        return Err(ApiError::new(
+            codes::EXEC_FAILED,
+            format!(
+                "journalctl: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+    let mut cursor = None;
+    let mut lines = Vec::new();
+    for record in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((line, c)) = journal_line(record) {
+            cursor = c.or(cursor);
+            if spec.keep(&line) {
+                lines.push(line);
+            }
+        }
+    }
+    lines.drain(..lines.len().saturating_sub(spec.lines));
+    Ok((lines, cursor))
+}
+
+/// The backlog alone, for `GET /api/logs` without `follow`.
+///
+/// # Errors
+///
+/// `404 FILE_NOT_FOUND`, `403 PERMISSION_DENIED`, `400 IS_DIRECTORY` or
+/// `500 IO_ERROR` for a file; `500 EXEC_FAILED` when `journalctl` fails.
+pub async fn read(spec: &TailSpec) -> Result<Vec<LogLine>, ErrorPair> {
+    match &spec.source {
+        Source::File(path) => file_backlog(path, spec, false)
+            .await
+            .map(|(lines, _)| lines),
+        Source::Unit(unit) => journal_backlog(unit, spec).await.map(|(lines, _)| lines),
+    }
+}
+
+// ─── Following ───────────────────────────────────────────────────────────────
+
+/// Sends batches without waiting, counting what a full queue made it drop.
+struct Emitter {
+    tx: mpsc::Sender<Value>,
+    tail_id: String,
+    request_id: Option<String>,
+    dropped: u64,
+}
+
+impl Emitter {
+    /// Send `lines` in batches. With no lines but drops not yet reported,
+    /// sends an empty batch to report them. Returns `false` once the receiver
+    /// is gone.
+    fn send(&mut self, lines: &mut Vec<LogLine>) -> bool {
+        while !lines.is_empty() || self.dropped > 0 {
+            let batch: Vec<LogLine> = lines.drain(..lines.len().min(MAX_BATCH)).collect();
+            let count = batch.len() as u64;
+            let msg = WsServerMsg::LogsLines {
+                tail_id: self.tail_id.clone(),
+                lines: batch,
+                dropped: self.dropped,
+                request_id: self.request_id.clone(),
+            };
+            match self.tx.try_send(msg.to_value()) {
+                Ok(()) => self.dropped = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += count;
+                    if lines.is_empty() {
+                        break;
+                    }
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+        true
+    }
+
+    async fn close(self, reason: String) {
+        let _ = self
+            .tx
+            .send(
+                WsServerMsg::LogsClosed {
+                    tail_id: self.tail_id,
+                    reason,
+                    request_id: self.request_id,
+                }
+                .to_value(),
+            )
+            .await;
+    }
+}
+
+/// Read what was appended to the file since `pos`, following truncation and
+/// replacement.
+async fn read_new(
+    path: &PathBuf,
+    pos: &mut FilePos,
+    splitter: &mut LineSplitter,
+    out: &mut Vec<LogLine>,
+) {
+    // A missing file is probably mid-rotation; keep the old one until a new
+    // one appears.
+    let meta = tokio::fs::metadata(path).await.ok();
+    let replaced = meta.as_ref().is_some_and(|m| file_id(m) != pos.inode);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut read = 0;
+    while read < MAX_READ {
+        match pos.file.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                splitter.push(&buf[..n], out);
+                pos.offset += n as u64;
+                read += n;
+            }
+        }
+    }
+    if replaced {
+        if let Ok(file) = tokio::fs::File::open(path).await {
+            pos.file = file;
+            pos.offset = 0;
+            pos.inode = meta.as_ref().map_or(0, file_id);
+            *splitter = LineSplitter::default();
+        }
+    } else if meta.is_some_and(|m| m.len() < pos.offset) {
+        // Truncated in place.
+        if pos.file.seek(SeekFrom::Start(0)).await.is_ok() {
+            pos.offset = 0;
+            *splitter = LineSplitter::default();
+        }
+    }
+}
+
+async fn follow_file(path: PathBuf, spec: TailSpec, mut pos: FilePos, mut emit: Emitter) {
+    let mut splitter = LineSplitter::default();
+    let mut pending = Vec::new();
+    loop {
+        tokio::select! {
+            () = tokio::time::sleep(POLL_INTERVAL) => {}
+            () = emit.tx.closed() => return,
+        }
+        read_new(&path, &mut pos, &mut splitter, &mut pending).await;
+        pending.retain(|l| spec.keep(l));
+        if !emit.send(&mut pending) {
+            return;
+        }
+    }
+}
+
+async fn follow_journal(mut child: tokio::process::Child, spec: TailSpec, mut emit: Emitter) {
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+    let mut records = BufReader::new(stdout).lines();
+    let mut pending = Vec::new();
+    let mut flush_at: Option<Instant> = None;
+    let reason = loop {
+        let deadline = flush_at;
+        let flush = async move {
+            match deadline {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            record = records.next_line() => match record {
+                Ok(Some(record)) => {
+                    if let Some((line, _)) = journal_line(&record) {
+                        if spec.keep(&line) {
+                            pending.push(line);
+                            flush_at.get_or_insert_with(|| Instant::now() + BATCH_WINDOW);
+                        }
+                    }
+                    if pending.len() >= MAX_BATCH {
+                        if !emit.send(&mut pending) {
+                            return;
+                        }
+                        if emit.dropped > 0 {
+                            flush_at.get_or_insert_with(|| Instant::now() + POLL_INTERVAL);
+                        }
+                    }
+                }
+                Ok(None) | Err(_) => break match child.wait().await {
+                    Ok(status) => format!("journalctl exited ({status})"),
+                    Err(e) => format!("journalctl failed: {e}"),
+                },
+            },
+            () = flush => {
+                flush_at = None;
+                if !emit.send(&mut pending) {
+                    return;
+                }
+                // Retry until the drops are reported.
+                if emit.dropped > 0 {
+                    flush_at = Some(Instant::now() + POLL_INTERVAL);
+                }
+            }
+            () = emit.tx.closed() => return,
+        }
+    };
+    if emit.send(&mut pending) {
+        emit.close(reason).await;
+    }
+}
+
+/// A tail whose backlog has been read, ready to follow.
+enum Prepared {
+    File(PathBuf, FilePos, Vec<LogLine>),
+    Journal(tokio::process::Child, Vec<LogLine>),
+}
+
+/// Read the backlog and, for the journal, start `journalctl --follow`.
+async fn prepare(spec: &TailSpec) -> Result<Prepared, ErrorPair> {
+    match &spec.source {
+        Source::File(path) => {
+            let (lines, pos) = file_backlog(path, spec, true).await?;
+            Ok(Prepared::File(path.clone(), pos, lines))
+        }
+        Source::Unit(unit) => {
+            let (lines, cursor) = journal_backlog(unit, spec).await?;
+            let mut cmd = journalctl(unit);
+            cmd.arg("--follow").stdout(std::process::Stdio::piped());
+            match cursor {
+                Some(cursor) => cmd.arg(format!("--after-cursor={cursor}")),
+                None => cmd.args(["-n", "0"]),
+            };
+            let child = cmd.spawn().map_err(|e| journal_error(&e))?;
+            Ok(Prepared::Journal(child, lines))
+        }
+    }
+}
+
+/// Send the backlog and spawn the task that follows.
+fn run(prepared: Prepared, spec: TailSpec, mut emit: Emitter) -> tokio::task::JoinHandle<()> {
+    match prepared {
+        Prepared::File(path, pos, mut lines) => {
+            emit.send(&mut lines);
+            tokio::spawn(follow_file(path, spec, pos, emit))
+        }
+        Prepared::Journal(child, mut lines) => {
+            emit.send(&mut lines);
+            tokio::spawn(follow_journal(child, spec, emit))
+        }
+    }
+}
+
+/// Follow `spec` until `tx` closes, sending `logs.lines` and, if the source
+/// ends, `logs.closed` (for `GET /api/logs?follow=true`).
+///
+/// # Errors
+///
+/// As [`read`].
+pub async fn follow(spec: TailSpec, tx: mpsc::Sender<Value>) -> Result<(), ErrorPair> {
+    let prepared = prepare(&spec).await?;
+    let emit = Emitter {
+        tx,
+        tail_id: uuid::Uuid::new_v4().to_string(),
+        request_id: None,
+        dropped: 0,
+    };
+    run(prepared, spec, emit);
+    Ok(())
+}
+
+struct Tail {
+    owner: Option<String>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// The tails of one connection. Dropping it stops them all.
+#[derive(Default)]
+pub struct LogTails {
+    tails: HashMap<String, Tail>,
+}
+
+impl LogTails {
+    /// Stop a tail. Returns `false` if there is no such tail.
+    pub fn unfollow(&mut self, tail_id: &str) -> bool {
+        let Some(t) = self.tails.remove(tail_id) else {
+            return false;
+        };
+        t.task.abort();
+        true
+    }
+
+    /// Stop every tail started for `owner` (the relay client id on the
+    /// tunnel).
+    pub fn unfollow_owner(&mut self, owner: &str) {
+        self.tails.retain(|_, t| {
+            let keep = t.owner.as_deref() != Some(owner);
+            if !keep {
+                t.task.abort();
+            }
+            keep
+        });
+    }
+
+    /// Stop every tail.
+    pub fn clear(&mut self) {
+        for (_, t) in self.tails.drain() {
+            t.task.abort();
+        }
+    }
+}
+
+impl Drop for LogTails {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Handle a `logs.follow` message. The reply (`logs.follow.ack` or `error`)
+/// goes to `tx`, followed by the backlog and new lines.
+pub async fn handle_follow(
+    tails: &mut LogTails,
+    msg: &Value,
+    owner: Option<String>,
+    tx: mpsc::Sender<Value>,
+) {
+    let request_id = msg["request_id"].as_str().map(ToString::to_string);
+    tails.tails.retain(|_, t| !t.task.is_finished());
+    let prepared = if tails.tails.len() >= MAX_TAILS {
+        Err(ApiError::new(
+            "TAIL_LIMIT",
+            format!("At most {MAX_TAILS} log tails per connection"),
+        ))
+    } else {
+        match serde_json::from_value::<TailRequest>(msg.clone()) {
+            Ok(req) => match TailSpec::parse(&req) {
+                Ok(spec) => prepare(&spec).await.map(|p| (spec, p)),
+                Err(e) => Err(e),
+            }
+            .map_err(|(_, Json(e))| e),
+            Err(e) => Err(ApiError::new(codes::INVALID_REQUEST, e.to_string())),
+        }
+    };
+    let (spec, prepared) = match prepared {
+        Ok(p) => p,
+        Err(e) => {
+            let _ = tx
+                .send(
+                    WsServerMsg::Error {
+                        code: e.code,
+                        message: e.message,
+                        session_id: None,
+                        request_id,
+                    }
+                    .to_value(),
+                )
+                .await;
+            return;
+        }
+    };
+    let (path, unit) = spec.describe();
+    let tail_id = uuid::Uuid::new_v4().to_string();
+    let ack = WsServerMsg::LogsFollowAck {
+        tail_id: tail_id.clone(),
+        path,
+        unit,
+        request_id: request_id.clone(),
+    };
+    if tx.send(ack.to_value()).await.is_err() {
+        return;
+    }
+    let emit = Emitter {
+        tx,
+        tail_id: tail_id.clone(),
+        request_id,
+        dropped: 0,
+    };
+    let task = run(prepared, spec, emit);
+    tails.tails.insert(tail_id, Tail { owner, task });
+}
+
+/// Handle a `logs.unfollow` message; returns the reply.
+pub fn handle_unfollow(tails: &mut LogTails, msg: &Value) -> Value {
+    let request_id = msg["request_id"].as_str().map(ToString::to_string);
+    let tail_id = msg["tail_id"].as_str().unwrap_or_default();
+    if tails.unfollow(tail_id) {
+        WsServerMsg::LogsUnfollowAck {
+            tail_id: tail_id.to_string(),
+            request_id,
+        }
+    } else {
+        WsServerMsg::Error {
+            code: codes::NOT_FOUND.into(),
+            message: format!("No log tail '{tail_id}'"),
+            session_id: None,
+            request_id,
+        }
+    }
+    .to_value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitter_frames_and_cuts_long_lines() {
+        let mut splitter = LineSplitter::default();
+        let mut out = Vec::new();
+        splitter.push(b"one\r\ntw", &mut out);
+        splitter.push(b"o\n", &mut out);
+        let long = vec![b'x'; MAX_LINE_BYTES + 10];
+        splitter.push(&long, &mut out);
+        splitter.push(b"yyy\nthree\npart", &mut out);
+        let lines: Vec<(&str, bool)> = out.iter().map(|l| (l.line.as_str(), l.truncated)).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(&lines[..2], &[("one", false), ("two", false)]);
+        assert_eq!(lines[2].0.len(), MAX_LINE_BYTES);
+        assert!(lines[2].1);
+        assert_eq!(lines[3], ("three", false));
+        assert_eq!(splitter.partial, b"part");
+
+        let record = r#"{"MESSAGE":"started","__REALTIME_TIMESTAMP":"1700000000123456","PRIORITY":"6","__CURSOR":"s=1"}"#;
+        let (line, cursor) = journal_line(record).unwrap();
+        assert_eq!(line.line, "started");
+        assert_eq!(line.ts_ms, Some(1_700_000_000_123));
+        assert_eq!(line.priority, Some(6));
+        assert_eq!(cursor.as_deref(), Some("s=1"));
+        let (line, _) = journal_line(r#"{"MESSAGE":[104,105,255]}"#).unwrap();
+        assert_eq!(line.line, "hi\u{fffd}");
+    }
+}
@@ -593,7 +593,11 @@ async fn uci_set(key: &str, value: &str) -> Result<(), String> {
 }
 
 /// Configure APN and pdptype on OpenWrt via uci. Skips if already correct.
-async fn configure_apn_openwrt(modem: &Modem, interface: &str, apn: &str) -> Result<(), String> {
+pub(crate) async fn configure_apn_openwrt(
+    modem: &Modem,
+    interface: &str,
+    apn: &str,
+) -> Result<(), String> {
     // Write modem PDP context directly — this is critical because netifd's QMI
     // proto passes APN through QMI at connect time, but the modem's internal
     // AT+CGDCONT context can conflict and prevent registration. After SIM swaps
@@ -2265,7 +2269,7 @@ async fn append_modem_state_line(data_dir: &str, signal: &LteSignal, tunnel_conn
 
     let line = format!(
         "{unix_secs} {iso} | rsrp={rsrp} sinr={sinr} rssi={rssi} band={band} op={op} conn={conn} tech={tech} tunnel={tunnel}\n",
-        iso = format_iso8601_utc(unix_secs),
+        iso = crate::util::format_iso8601_utc(unix_secs),
         rsrp = signal.rsrp.map_or("-".into(), |v| v.to_string()),
         sinr = signal.sinr.map_or("-".into(), |v| format!("{v:.1}")),
         rssi = signal.rssi_dbm,
@@ -2283,54 +2287,6 @@ async fn append_modem_state_line(data_dir: &str, signal: &LteSignal, tunnel_conn
     }
 }
 
-/// Format a UTC unix timestamp as `YYYY-MM-DDTHH:MM:SSZ` without pulling in
-/// `chrono`. Uses Howard Hinnant's `civil_from_days` algorithm — exact for
-/// any valid `time_t`, no leap-second handling required (matches kernel clock).
-#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
-fn format_iso8601_utc(unix_secs: u64) -> String {
-    let secs_of_day = unix_secs % 86_400;
-    let hour = secs_of_day / 3600;
-    let minute = (secs_of_day / 60) % 60;
-    let second = secs_of_day % 60;
-
-    // Days since 1970-01-01 → (year, month, day) via civil_from_days.
-    let z: i64 = (unix_secs / 86_400) as i64 + 719_468;
-    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
-    let doe = (z - era * 146_097) as u64;
-    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
-    let mut y = yoe as i64 + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    if m <= 2 {
-        y += 1;
-    }
-    format!("{y:04}-{m:02}-{d:02}T{hour:02}:{minute:02}:{second:02}Z")
-}
-
-#[cfg(test)]
-mod modem_state_log_tests {
-    use super::format_iso8601_utc;
-
-    #[test]
-    fn iso8601_epoch() {
-        assert_eq!(format_iso8601_utc(0), "1970-01-01T00:00:00Z");
-    }
-
-    #[test]
-    fn iso8601_known_timestamp() {
-        // 2026-05-20T13:07:50 UTC (LiveBarn first wedge symptom onset)
-        assert_eq!(format_iso8601_utc(1_779_282_470), "2026-05-20T13:07:50Z");
-    }
-
-    #[test]
-    fn iso8601_leap_year_boundary() {
-        // 2024-02-29 12:00:00 UTC
-        assert_eq!(format_iso8601_utc(1_709_208_000), "2024-02-29T12:00:00Z");
-    }
-}
-
 /// Spawn the background LTE signal poller. Returns a `JoinHandle` for abort on shutdown.
 ///
 /// The poller uses data-path-aware polling modes instead of binary tunnel suppression:
@@ -0,0 +1,228 @@
+//! LTE signal history for `GET /api/lte/history`.
+//!
+//! `/api/lte` shows the radio as of the last poll, which says little about
+//! why the tunnel dropped an hour ago. With `[lte]` configured, a collector
+//! samples the provider's latest signal every `history_interval_secs` into a
+//! ring of `history_size` [`SignalSample`]s: RSSI, RSRP, RSRQ, SINR, cell,
+//! band and operator, the LTE interface's byte counters, and whether the
+//! tunnel was connected — so tunnel flaps line up with radio conditions.
+//!
+//! A sample is **degraded** when there is no signal or RSRP, RSRQ or SINR is
+//! below its `degraded_*` threshold. The first degraded sample broadcasts
+//! `lte.signal_degraded` with the reasons; the first good one after it
+//! broadcasts `lte.signal_recovered`. The ring is kept in memory only.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+
+use crate::comms::CommsState;
+use crate::config::LteConfig;
+use crate::infra::now_epoch;
+use crate::state::TunnelStats;
+
+/// One sample of the cellular link.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignalSample {
+    /// Unix seconds.
+    pub timestamp: u64,
+    pub rssi_dbm: Option<i64>,
+    pub rsrp: Option<i64>,
+    pub rsrq: Option<i64>,
+    pub sinr: Option<f64>,
+    pub cell_id: Option<String>,
+    pub band: Option<String>,
+    pub operator: Option<String>,
+    pub technology: Option<String>,
+    /// Bytes received on the LTE interface since it came up.
+    pub rx_bytes: Option<u64>,
+    /// Bytes sent on the LTE interface since it came up.
+    pub tx_bytes: Option<u64>,
+    pub tunnel_connected: bool,
+    /// Why the sample is degraded (`no_signal`, `rsrp`, `rsrq`, `sinr`);
+    /// empty when it isn't.
+    pub degraded: Vec<&'static str>,
+}
+
+impl SignalSample {
+    /// A sample from the provider's `signal` projection (`null` when the
+    /// modem has none), judged against `config`'s thresholds.
+    fn new(signal: &Value, config: &LteConfig, net: &Path, tunnel_connected: bool) -> Self {
+        let counter = |name: &str| {
+            std::fs::read_to_string(net.join(&config.interface).join("statistics").join(name))
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+        };
+        let text = |key: &str| signal[key].as_str().map(ToString::to_string);
+        let mut sample = Self {
+            timestamp: now_epoch(),
+            rssi_dbm: signal["rssi_dbm"].as_i64(),
+            rsrp: signal["rsrp"].as_i64(),
+            rsrq: signal["rsrq"].as_i64(),
+            sinr: signal["sinr"].as_f64(),
+            cell_id: text("cell_id"),
+            band: text("band"),
+            operator: text("operator"),
+            technology: text("technology"),
+            rx_bytes: counter("rx_bytes"),
+            tx_bytes: counter("tx_bytes"),
+            tunnel_connected,
+            degraded: Vec::new(),
+        };
+        if sample.rssi_dbm.is_none() && sample.rsrp.is_none() {
+            sample.degraded.push("no_signal");
+        }
+        if sample.rsrp.is_some_and(|v| v < config.degraded_rsrp_dbm) {
+            sample.degraded.push("rsrp");
+        }
+        if sample.rsrq.is_some_and(|v| v < config.degraded_rsrq_db) {
+            sample.degraded.push("rsrq");
+        }
+        if sample.sinr.is_some_and(|v| v < config.degraded_sinr_db) {
+            sample.degraded.push("sinr");
+        }
+        sample
+    }
+}
+
+/// Recent signal samples, newest last.
+pub struct LteHistory {
+    samples: Mutex<VecDeque<SignalSample>>,
+    max_samples: usize,
+}
+
+impl LteHistory {
+    pub fn new(max_samples: usize) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::new()),
+            max_samples: max_samples.max(1),
+        }
+    }
+
+    /// Append `sample`. Returns the event to broadcast when it moves the link
+    /// into or out of the degraded state.
+    pub async fn record(&self, sample: SignalSample) -> Option<Value> {
+        let mut samples = self.samples.lock().await;
+        let was_degraded = samples.back().is_some_and(|s| !s.degraded.is_empty());
+        let event_type = match (was_degraded, sample.degraded.is_empty()) {
+            (false, false) => Some("lte.signal_degraded"),
+            (true, true) => Some("lte.signal_recovered"),
+            _ => None,
+        };
+        let event = event_type.map(|t| {
+            let mut event = json!(sample);
+            event["type"] = json!(t);
+            event
+        });
+        if samples.len() >= self.max_samples {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+        event
+    }
+
+    /// Samples at or after `since` (Unix seconds), at most the newest `limit`.
+    pub async fn samples(&self, since: u64, limit: usize) -> Vec<SignalSample> {
+        let samples = self.samples.lock().await;
+        let mut found: Vec<SignalSample> = samples
+            .iter()
+            .rev()
+            .filter(|s| s.timestamp >= since)
+            .take(limit)
+            .cloned()
+            .collect();
+        found.reverse();
+        found
+    }
+
+    pub fn max_samples(&self) -> usize {
+        self.max_samples
+    }
+}
+
+/// Spawn the collector: every `history_interval_secs`, sample the provider's
+/// last link poll into `history` and broadcast degraded/recovered changes.
+pub fn spawn_collector(
+    history: Arc<LteHistory>,
+    config: LteConfig,
+    comms_state: Arc<Mutex<CommsState>>,
+    tunnel_stats: Arc<TunnelStats>,
+    session_events: broadcast::Sender<Value>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(config.history_interval_secs.max(1));
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            // Nothing to sample until the provider has answered a link poll.
+            let Some(signal) = comms_state
+                .lock()
+                .await
+                .lte
+                .as_ref()
+                .map(|lte| lte["signal"].clone())
+            else {
+                continue;
+            };
+            let connected = tunnel_stats.connected.load(Ordering::Relaxed);
+            let sample =
+                SignalSample::new(&signal, &config, Path::new("/sys/class/net"), connected);
+            let Some(event) = history.record(sample).await else {
+                continue;
+            };
+            if event["type"] == "lte.signal_degraded" {
+                warn!(reasons = %event["degraded"], "LTE signal degraded");
+            } else {
+                info!("LTE signal recovered");
+            }
+            let _ = session_events.send(event);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn degrading_and_recovering_emit_one_event_each() {
+        let config: LteConfig = toml::from_str(r#"interface = "wwan0""#).unwrap();
+        let net = Path::new("/nonexistent");
+        let good = json!({"rssi_dbm": -70, "rsrp": -95, "rsrq": -9, "sinr": 12.5});
+        let weak = json!({"rssi_dbm": -100, "rsrp": -118, "rsrq": -9, "sinr": -2.0});
+        let history = LteHistory::new(3);
+
+        assert!(history
+            .record(SignalSample::new(&good, &config, net, true))
+            .await
+            .is_none());
+        let event = history
+            .record(SignalSample::new(&weak, &config, net, false))
+            .await
+            .unwrap();
+        assert_eq!(event["type"], "lte.signal_degraded");
+        assert_eq!(event["degraded"], json!(["rsrp", "sinr"]));
+        assert_eq!(event["tunnel_connected"], false);
+        assert!(history
+            .record(SignalSample::new(&Value::Null, &config, net, false))
+            .await
+            .is_none());
+        let event = history
+            .record(SignalSample::new(&good, &config, net, true))
+            .await
+            .unwrap();
+        assert_eq!(event["type"], "lte.signal_recovered");
+
+        let kept = history.samples(0, usize::MAX).await;
+        assert_eq!(kept.len(), 3);
+        assert_eq!(kept[1].degraded, ["no_signal"]);
+        assert_eq!(history.samples(0, 1).await[0].rsrp, Some(-95));
+    }
+}
@@ -20,6 +20,14 @@
 //! The watchdog tracks which band config last sustained a stable tunnel
 //! connection (5+ minutes). When the tunnel drops after a recent band change,
 //! it can quickly revert to the known-working config before symptom dispatch.
+//!
+//! ## APN failover
+//!
+//! With `[[lte.apn_profiles]]`, symptoms that point at the data plan rather
+//! than the radio (`InternetUnreachable`, `RegisteredNoData` after interface
+//! restarts, registration denied after a re-register) switch to the next
+//! profile once the tunnel client has failed enough attempts on the active
+//! one. See [`crate::apn_failover`].
 
 use std::collections::VecDeque;
 use std::sync::atomic::Ordering;
@@ -31,6 +39,7 @@ use serde_json::Value;
 use tokio::sync::{broadcast, watch, Mutex};
 use tracing::{info, warn};
 
+use crate::apn_failover::ApnFailover;
 use crate::config::LteConfig;
 use crate::lte::{BandChangeSource, LteState, ScanStatus};
 use crate::modem::Modem;
@@ -77,6 +86,9 @@ const MAX_AIRPLANE_CYCLES_PER_EPISODE: u32 = 2;
 /// Maximum recent events in the snapshot.
 const MAX_SNAPSHOT_EVENTS: usize = 20;
 
+/// How long the tunnel gets to come back after an APN profile switch.
+const APN_SWITCH_VERIFY: Duration = Duration::from_secs(90);
+
 // ── Symptom diagnosis ──────────────────────────────────────────────────────
 
 /// What the watchdog thinks is wrong.
@@ -632,6 +644,7 @@ pub fn spawn_lte_watchdog(
     tunnel_url: Option<String>,
     snapshot: Arc<Mutex<WatchdogSnapshot>>,
     detected_path: Arc<tokio::sync::RwLock<Option<String>>>,
+    apn_failover: Option<Arc<Mutex<ApnFailover>>>,
 ) -> tokio::task::JoinHandle<()> {
     let interface = config.interface.clone();
     // Hint only — sysfs detection in action_usb_power_cycle is authoritative.
@@ -649,6 +662,7 @@ pub fn spawn_lte_watchdog(
     let usb_cycle_evidence = config.usb_cycle_evidence.clone();
     let unknown_action = config.unknown_action.clone();
     let notregistered_grace_secs = config.notregistered_grace_secs;
+    let apn_failover_after = config.apn_failover_after_failures;
 
     tokio::spawn(async move {
         let mut modem = modem;
@@ -753,6 +767,14 @@ pub fn spawn_lte_watchdog(
                     }
                 }
 
+                if let Some(ref failover) = apn_failover {
+                    if connected_duration >= SAFE_PROMOTE_THRESHOLD {
+                        failover.lock().await.mark_good().await;
+                    } else if connected_duration >= LIGHT_RESET_THRESHOLD {
+                        failover.lock().await.end_episode();
+                    }
+                }
+
                 // Safe-bands promotion (uses cached band config, no AT commands)
                 {
                     let lock_started = Instant::now();
@@ -1029,6 +1051,97 @@ pub fn spawn_lte_watchdog(
             state.last_symptom = Some(symptom);
             let reg_str = reg_status.map_or("unknown", |s| s.as_str());
 
+            // ── APN / SIM profile failover ──
+            let mut failover_target = None;
+            if let (Some(failover), Some(reason)) =
+                (&apn_failover, failover_reason(symptom, reg_status, &state))
+            {
+                let failures = tunnel_stats.connect_failures.load(Ordering::Relaxed);
+                let mut fo = failover.lock().await;
+                if fo.due(failures, apn_failover_after) {
+                    if let Some(to) = fo.next() {
+                        let profile = fo.profile(to).clone();
+                        failover_target = Some((failover.clone(), to, profile, reason, failures));
+                    }
+                }
+            }
+            if let Some((failover, to, profile, reason, failures)) = failover_target {
+                info!(
+                    "LTE watchdog: {reason} after {failures} failed tunnel attempts, \
+                     switching to APN profile '{}'",
+                    profile.label()
+                );
+                let result = crate::apn_failover::apply(
+                    &modem,
+                    &profile,
+                    &interface,
+                    openwrt,
+                    interface_restart_cmd.as_deref(),
+                )
+                .await;
+                if let Err(ref e) = result {
+                    warn!(
+                        "LTE watchdog: APN profile '{}' failed: {e}",
+                        profile.label()
+                    );
+                }
+                failover
+                    .lock()
+                    .await
+                    .record_switch(to, reason, failures, result.err())
+                    .await;
+                tunnel_stats.link_generation.fetch_add(1, Ordering::Relaxed);
+
+                let detail = format!(
+                    "symptom={} action=apn_switch profile={} apn={} reg={reg_str} \
+                     tunnel_failures={failures} disconnect={disconnect_secs}s",
+                    symptom.as_str(),
+                    profile.label(),
+                    profile.apn
+                );
+                log_action(
+                    &detail,
+                    &tunnel_stats,
+                    &session_events,
+                    &mut state,
+                    "apn_switch",
+                    2,
+                    disconnect_secs,
+                )
+                .await;
+                update_snapshot_event(
+                    &snapshot,
+                    &mut state,
+                    symptom.as_str(),
+                    "apn_switch",
+                    &detail,
+                )
+                .await;
+                record_history_and_detect_regression(
+                    &data_dir,
+                    symptom.as_str(),
+                    "apn_switch",
+                    2,
+                    &detail,
+                    &session_events,
+                )
+                .await;
+
+                let recovered = verify_recovery(&interface, &tunnel_stats, APN_SWITCH_VERIFY).await;
+                failover.lock().await.settle(recovered).await;
+                if recovered {
+                    info!(
+                        "LTE watchdog: recovery verified on APN profile '{}'",
+                        profile.label()
+                    );
+                    state.light_reset();
+                    update_snapshot(&snapshot, &state, "recovered").await;
+                } else {
+                    update_snapshot(&snapshot, &state, "acting").await;
+                }
+                continue;
+            }
+
             // ── Symptom-based dispatch ──
             let (action, level, new_modem): (&str, u8, Option<Modem>) = match symptom {
                 Symptom::Searching { secs } => {
@@ -1455,6 +1568,28 @@ pub fn spawn_lte_watchdog(
     })
 }
 
+/// Symptoms that point at the data plan (APN, SIM, exhausted plan) rather
+/// than the radio, once the cheaper fix for each has been tried.
+fn failover_reason(
+    symptom: Symptom,
+    reg: Option<RegistrationStatus>,
+    state: &WatchdogState,
+) -> Option<&'static str> {
+    match symptom {
+        Symptom::InternetUnreachable => Some("internet_unreachable"),
+        Symptom::RegisteredNoData if state.iface_restarts >= MAX_IFACE_RESTARTS_PER_EPISODE => {
+            Some("registered_no_data")
+        }
+        Symptom::NotRegistered
+            if reg == Some(RegistrationStatus::Denied)
+                && state.reregisters >= MAX_REREGISTERS_PER_EPISODE =>
+        {
+            Some("registration_denied")
+        }
+        _ => None,
+    }
+}
+
 // ── Snapshot update helpers ────────────────────────────────────────────────
 
 async fn update_snapshot(snapshot: &Mutex<WatchdogSnapshot>, state: &WatchdogState, status: &str) {
@@ -15,39 +15,33 @@
 //!
 //! - `sctl serve` (default) — run the HTTP/WS server
 //! - `sctl supervise` — run as supervisor: starts server and restarts on crash
+//! - `sctl exec -- <command>` — run one command through the exec pipeline locally
+//! - `sctl forward LOCAL:REMOTE --url <device>` — forward a local port to a
+//!   device port through a relay
+//! - `sctl sftp --url <device>` — stdin/stdout bridge to a device's SFTP
+//!   server, for `sftp -D`, `scp -S` and `sshfs`
 
 mod sctlin_proxy;
 mod supervisor;
 
-use std::path::Path;
-use std::sync::atomic::AtomicU32;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 
-use sctl::gawdxfer::manager::TransferManager;
-use sctl::gawdxfer::types::TransferConfig;
-
-use axum::{
-    middleware,
-    routing::{delete, get, post},
-    Extension, Router,
-};
+use axum::Router;
 use clap::{Parser, Subcommand};
+use futures::future::BoxFuture;
 use tokio::net::TcpListener;
-use tokio::sync::broadcast;
-use tower_http::cors::{Any, CorsLayer};
-use tower_http::trace::TraceLayer;
-use tracing::{info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
 
 use sctl::{
-    activity::ActivityLog,
-    auth::ApiKey,
-    comms,
     config::Config,
-    infra, routes, sessions,
-    sessions::SessionManager,
-    state::{AppState, TunnelStats},
-    tunnel, ws, ExecResultsCache,
+    error::{codes, ApiError},
+    log_forward::LogForwarder,
+    server::ServerBuilder,
+    shell::process,
+    startup::StartupProfile,
 };
 
 use sctl::VERSION;
@@ -77,6 +71,94 @@ enum Commands {
         #[arg(long)]
         config: Option<String>,
     },
+    /// Run one command through the `/api/exec` pipeline, locally and without
+    /// an API key. Exits with the command's exit code.
+    Exec(ExecArgs),
+    /// Forward a local TCP port to a port on a device, through the relay the
+    /// device is tunnelled to. The port must be in the device's
+    /// `[tunnel] forward_ports`.
+    Forward(ForwardArgs),
+    /// Bridge stdin/stdout to a device's SFTP server (`[sftp]`). Use it as
+    /// `sftp -D`, or as the `ssh` of `scp -S` and `sshfs -o ssh_command=`.
+    Sftp(SftpArgs),
+}
+
+#[derive(clap::Args)]
+struct ExecArgs {
+    /// Path to TOML config file (for defaults and `[secrets]`).
+    #[arg(long)]
+    config: Option<String>,
+    /// Shell to run the command with (default `shell.default_shell`).
+    #[arg(long)]
+    shell: Option<String>,
+    /// Working directory (default `shell.default_working_dir`).
+    #[arg(long = "cwd", value_name = "DIR")]
+    working_dir: Option<String>,
+    /// Environment variable to add; the value may be `secret://name`.
+    #[arg(short, long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+    /// Timeout in milliseconds (default `server.exec_timeout_ms`).
+    #[arg(long, value_name = "MS")]
+    timeout_ms: Option<u64>,
+    /// Print the result as JSON, in the shape `POST /api/exec` returns.
+    #[arg(long)]
+    json: bool,
+    /// The command, after `--`. Arguments are joined with spaces and passed
+    /// to `<shell> -c`.
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+}
+
+#[derive(clap::Args)]
+struct ForwardArgs {
+    /// Device URL on the relay, e.g. `https://relay.example.com/d/DEV-1`.
+    #[arg(long)]
+    url: String,
+    /// The device's API key (default `$SCTL_API_KEY`).
+    #[arg(long)]
+    token: Option<String>,
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1")]
+    bind: std::net::IpAddr,
+    /// Local port, then the device port, e.g. `8080:80`.
+    #[arg(value_name = "LOCAL:REMOTE", value_parser = parse_port_pair)]
+    ports: (u16, u16),
+}
+
+#[derive(clap::Args)]
+struct SftpArgs {
+    /// Device URL: `http://device:1337`, or `https://relay.example.com/d/DEV-1`.
+    #[arg(long, conflicts_with = "relay")]
+    url: Option<String>,
+    /// Relay URL; the device is the host `scp` or `sshfs` passes (`DEV-1:/path`).
+    #[arg(long)]
+    relay: Option<String>,
+    /// The device's API key (default `$SCTL_API_KEY`).
+    #[arg(long)]
+    token: Option<String>,
+    /// `ssh` arguments from `scp -S` or `sshfs`; only the host is used.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
+    ssh_args: Vec<String>,
+}
+
+fn parse_port_pair(s: &str) -> Result<(u16, u16), String> {
+    let (local, remote) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected LOCAL:REMOTE, got '{s}'"))?;
+    let port = |p: &str| {
+        p.parse::<u16>()
+            .ok()
+            .filter(|&p| p != 0)
+            .ok_or_else(|| format!("invalid port '{p}'"))
+    };
+    Ok((port(local)?, port(remote)?))
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{s}'")),
+    }
 }
 
 #[tokio::main]
@@ -90,6 +172,15 @@ async fn main() {
         Some(Commands::Serve { config, skip_lock }) => {
             run_server(config.as_deref(), skip_lock).await;
         }
+        Some(Commands::Exec(args)) => {
+            run_exec(args).await;
+        }
+        Some(Commands::Forward(args)) => {
+            run_forward(args).await;
+        }
+        Some(Commands::Sftp(args)) => {
+            run_sftp(args).await;
+        }
         None => {
             // Backward compat: no subcommand but --config may be passed
             let args: Vec<String> = std::env::args().collect();
@@ -102,6 +193,117 @@ async fn main() {
     }
 }
 
+/// `sctl exec` — run a command like `POST /api/exec` would, print the result
+/// and exit with the command's exit code. Timeouts exit 124; failures to
+/// start exit 125.
+async fn run_exec(args: ExecArgs) -> ! {
+    let config = Config::load(args.config.as_deref());
+    let shell = args.shell.unwrap_or(config.shell.default_shell);
+    let raw_dir = args.working_dir.unwrap_or(config.shell.default_working_dir);
+    let working_dir = sctl::util::expand_tilde(&raw_dir);
+    let timeout_ms = args.timeout_ms.unwrap_or(config.server.exec_timeout_ms);
+    let command = args.command.join(" ");
+
+    let env: Option<HashMap<String, String>> =
+        (!args.env.is_empty()).then(|| args.env.into_iter().collect());
+    let env = match sctl::shell::secrets::resolve_env(
+        config.secrets.as_ref(),
+        &config.server.data_dir,
+        env.as_ref(),
+    )
+    .await
+    {
+        Ok(env) => env,
+        Err(e) => exit_with_error(args.json, codes::INVALID_REQUEST, &e, 2),
+    };
+
+    match process::exec_command(&shell, &working_dir, &command, timeout_ms, env.env()).await {
+        Ok(mut result) => {
+            env.redact_result(&mut result);
+            if args.json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&result).expect("serializable")
+                );
+            } else {
+                print!("{}", result.stdout);
+                eprint!("{}", result.stderr);
+            }
+            std::process::exit(result.exit_code);
+        }
+        Err(process::ExecError::Timeout) => exit_with_error(
+            args.json,
+            codes::TIMEOUT,
+            &format!("Command timed out after {timeout_ms}ms"),
+            124,
+        ),
+        Err(e) => exit_with_error(args.json, codes::EXEC_FAILED, &e.to_string(), 125),
+    }
+}
+
+/// `sctl forward` — listen locally and carry each connection to the device
+/// over its own forwarded stream. Runs until interrupted.
+async fn run_forward(args: ForwardArgs) -> ! {
+    let log_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+    tracing_subscriber::fmt().with_env_filter(log_filter).init();
+
+    let Some(token) = args.token.or_else(|| std::env::var("SCTL_API_KEY").ok()) else {
+        eprintln!("sctl forward: no API key (pass --token or set SCTL_API_KEY)");
+        std::process::exit(2);
+    };
+    let (local, remote) = args.ports;
+    let listen = std::net::SocketAddr::new(args.bind, local);
+    tokio::select! {
+        result = sctl::tunnel::forward::listen_local(listen, &args.url, remote, &token) => {
+            if let Err(e) = result {
+                eprintln!("sctl forward: {e}");
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        _ = tokio::signal::ctrl_c() => std::process::exit(0),
+    }
+}
+
+/// `sctl sftp` — carry SFTP between stdin/stdout and the device. Nothing
+/// else may go to stdout, so there is no logging; errors go to stderr.
+async fn run_sftp(args: SftpArgs) -> ! {
+    let fail = |message: &str| -> ! {
+        eprintln!("sctl sftp: {message}");
+        std::process::exit(1);
+    };
+    let Some(token) = args.token.or_else(|| std::env::var("SCTL_API_KEY").ok()) else {
+        fail("no API key (pass --token or set SCTL_API_KEY)");
+    };
+    let device_url = match (args.url, args.relay) {
+        (Some(url), _) => url,
+        (None, Some(relay)) => match sctl::sftp::ssh_host(&args.ssh_args) {
+            Some(host) => format!("{}/d/{host}", relay.trim_end_matches('/')),
+            None => fail("no device: with --relay, pass the host as scp or sshfs would"),
+        },
+        (None, None) => fail("pass --url, or --relay when run by scp or sshfs"),
+    };
+    let url = sctl::tunnel::forward::ws_url(&device_url, "/api/sftp", &token)
+        .unwrap_or_else(|e| fail(&e));
+    match sctl::sftp::bridge_stdio(&url).await {
+        Ok(()) => std::process::exit(0),
+        Err(e) => fail(&e),
+    }
+}
+
+fn exit_with_error(json: bool, code: &str, message: &str, status: i32) -> ! {
+    if json {
+        let error = ApiError::new(code, message);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&error).expect("serializable")
+        );
+    } else {
+        eprintln!("sctl exec: {message}");
+    }
+    std::process::exit(status);
+}
+
 /// Install a panic hook that persists the panic trace to disk for post-mortem.
 ///
 /// Writes `<data_dir>/last_panic.log` with the panic message, thread name, and
@@ -131,19 +333,16 @@ fn install_panic_hook(data_dir: &str) {
 /// Acquire exclusive process lock. Returns the held file (must not be dropped).
 /// Retries 3 times with 1s delay to handle the race between old process dying
 /// and new process starting (e.g. during upgrades). Exits with code 99 if still locked.
-#[cfg(unix)]
 fn acquire_process_lock(data_dir: &str) -> std::fs::File {
-    use std::os::unix::io::AsRawFd;
-
     let lock_path = format!("{data_dir}/sctl.lock");
-    let f = std::fs::File::create(&lock_path).unwrap_or_else(|e| {
-        eprintln!("Failed to create lock file {lock_path}: {e}");
-        std::process::exit(1);
-    });
     for attempt in 0..3 {
-        let rc = unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
-        if rc == 0 {
-            return f;
+        match try_lock(&lock_path) {
+            Ok(Some(f)) => return f,
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Failed to create lock file {lock_path}: {e}");
+                std::process::exit(1);
+            }
         }
         if attempt < 2 {
             eprintln!(
@@ -157,6 +356,36 @@ fn acquire_process_lock(data_dir: &str) -> std::fs::File {
     std::process::exit(99);
 }
 
+/// Open and lock the lock file; `None` if another process holds it.
+#[cfg(unix)]
+fn try_lock(lock_path: &str) -> std::io::Result<Option<std::fs::File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let f = std::fs::File::create(lock_path)?;
+    let rc = unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    Ok((rc == 0).then_some(f))
+}
+
+/// Open the lock file without sharing, which fails while another process
+/// has it open; `None` if one does.
+#[cfg(windows)]
+fn try_lock(lock_path: &str) -> std::io::Result<Option<std::fs::File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Foundation::ERROR_SHARING_VIOLATION;
+
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .share_mode(0)
+        .open(lock_path)
+    {
+        Ok(f) => Ok(Some(f)),
+        Err(e) if e.raw_os_error() == i32::try_from(ERROR_SHARING_VIOLATION).ok() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 async fn run_supervisor_mode(config_path: Option<&str>) -> ! {
     let config = Config::load(config_path);
 
@@ -165,7 +394,6 @@ async fn run_supervisor_mode(config_path: Option<&str>) -> ! {
     tracing_subscriber::fmt().with_env_filter(log_filter).init();
 
     // Acquire lock at supervisor level — prevents two supervisors from running
-    #[cfg(unix)]
     let _lock = acquire_process_lock(&config.server.data_dir);
 
     info!("sctl supervisor starting");
@@ -174,30 +402,39 @@ async fn run_supervisor_mode(config_path: Option<&str>) -> ! {
 
 #[allow(clippy::too_many_lines)]
 async fn run_server(config_path: Option<&str>, skip_lock: bool) {
+    let startup = Arc::new(StartupProfile::new());
+    let phase_started = Instant::now();
     let config = Config::load(config_path);
 
-    // Initialize tracing
+    // Initialize tracing, with the remote forwarding layer if configured.
+    // Records queue until the forwarder is spawned below.
     let log_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| config.logging.level.clone());
-    tracing_subscriber::fmt().with_env_filter(log_filter).init();
+    let log_forwarder = config
+        .logging
+        .forward
+        .clone()
+        .map(|fc| LogForwarder::new(fc, &config.device.serial));
+    {
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::util::SubscriberInitExt;
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::EnvFilter::new(log_filter))
+            .with(tracing_subscriber::fmt::layer())
+            .with(
+                log_forwarder
+                    .as_ref()
+                    .map(sctl::log_forward::LogForwarder::layer),
+            )
+            .init();
+    }
 
     // Install panic hook early so panics in any spawned subsystem leave a
     // persisted trace on disk for post-mortem. Without this, the supervisor
     // restarts blindly and the underlying cause is lost.
     install_panic_hook(&config.server.data_dir);
 
-    // Honor safe-mode flag if the supervisor wrote one (crash-loop). When set
-    // we skip every optional subsystem (modem, GPS, LTE, watchdog, infra) and
-    // keep only the management plane (HTTP + tunnel + sessions) live so an
-    // operator can reach the box, inspect logs, and clear the flag.
-    let safe_mode_flag_path = std::path::Path::new(&config.server.data_dir).join("safe_mode.flag");
-    let safe_mode_active = safe_mode_flag_path.exists();
-    if safe_mode_active {
-        warn!(
-            "==== SAFE MODE ACTIVE ==== modem/GPS/LTE/watchdog/infra subsystems will be skipped. \
-             Flag: {} — clear via DELETE /api/safe_mode/flag (auth required).",
-            safe_mode_flag_path.display()
-        );
-    }
+    // Before journal recovery, the first user of the pool.
+    sctl::io_pool::init(config.server.io_pool_size);
 
     // Validate config before proceeding
     let validation_errors = config.validate();
@@ -207,10 +444,10 @@ async fn run_server(config_path: Option<&str>, skip_lock: bool) {
         }
         std::process::exit(1);
     }
+    startup.record("config", phase_started, None);
 
     // Acquire exclusive lock — prevents dual instances (e.g. upgrade race, cron watchdog).
     // Skipped when launched by supervisor (which holds its own lock).
-    #[cfg(unix)]
     let _lock = if skip_lock {
         None
     } else {
@@ -219,433 +456,78 @@ async fn run_server(config_path: Option<&str>, skip_lock: bool) {
 
     info!("sctl v{} starting", VERSION);
     info!("Device serial: {}", config.device.serial);
-    info!("Listening on {}", config.server.listen);
 
     // Best-effort: self-heal persistent log capture on OpenWrt. No-op elsewhere.
     sctl::platform::openwrt::ensure_persistent_logs().await;
 
-    if let Some(tc) = &config.tunnel {
-        if !tc.relay && tc.heartbeat_interval_secs > 15 {
-            warn!(
-                configured_secs = tc.heartbeat_interval_secs,
-                effective_secs = config.effective_client_heartbeat_interval_secs(),
-                "Tunnel client heartbeat interval too high for LTE/CGNAT; clamping to safe keepalive interval"
-            );
-        }
-    }
-
-    if config.auth.api_key == "change-me" {
-        warn!("Using default API key — set SCTL_API_KEY or update config");
+    let mut builder = ServerBuilder::new(config)
+        .config_path(Config::file_path(config_path))
+        .startup(startup.clone())
+        // sctlin web UI: reverse proxy /sctlin/* → localhost:3000 (relay mode only)
+        .relay_fallback(Router::new().fallback(sctlin_proxy::sctlin_proxy));
+    if let Some(forwarder) = &log_forwarder {
+        builder = builder.log_forwarder(forwarder.clone());
     }
+    let server = builder.build().await;
+
+    // Every listener stops accepting on the same signal.
+    let shutdown_token = CancellationToken::new();
+
+    let http = server.state.config.server.http.clone();
+    #[cfg(feature = "server-tls")]
+    let tls = server.state.config.server.tls.as_ref().map(|tls| {
+        let acceptor = sctl::tls::acceptor(tls, http.http2).unwrap_or_else(|e| {
+            tracing::error!("server.tls: {e}");
+            std::process::exit(1);
+        });
+        (acceptor, tls.client_auth)
+    });
 
-    let journal_enabled = config.server.journal_enabled;
-    let data_dir = config.server.data_dir.clone();
-    let journal_max_age_hours = config.server.journal_max_age_hours;
-
-    let session_manager = if journal_enabled {
-        info!("Journaling enabled, data_dir: {data_dir}");
-        SessionManager::with_journal(
-            config.server.max_sessions,
-            config.server.session_buffer_size,
-            &data_dir,
-        )
-    } else {
-        SessionManager::new(
-            config.server.max_sessions,
-            config.server.session_buffer_size,
-        )
-    };
-
-    // Recover archived sessions from journal and clean up orphans
-    if journal_enabled {
-        // Kill any shell processes orphaned by a previous crash
-        sessions::journal::kill_orphaned_processes(Path::new(&data_dir)).await;
-        // Reload output history from journals
-        session_manager
-            .recover_from_journal(Path::new(&data_dir))
-            .await;
-        // Delete stale journal files
-        sessions::journal::cleanup_old_journals(Path::new(&data_dir), journal_max_age_hours).await;
-    }
-
-    let (session_events, _) = broadcast::channel(256);
-    let activity_log = Arc::new(ActivityLog::new(
-        config.server.activity_log_max_entries,
-        session_events.clone(),
-    ));
-
-    let exec_results_cache = Arc::new(ExecResultsCache::new(config.server.exec_result_cache_size));
-
-    let transfer_config = TransferConfig::new(
-        config.server.max_concurrent_transfers,
-        config.server.transfer_chunk_size,
-        config.server.transfer_max_file_size,
-        config.server.transfer_stale_timeout_secs,
-    );
-    let transfer_manager = Arc::new(TransferManager::new(
-        transfer_config,
-        session_events.clone(),
-        activity_log.clone(),
-    ));
-
-    if let Some(gc) = config.gps.as_ref() {
-        info!("GPS tracking enabled (poll: {}s)", gc.poll_interval_secs);
-    }
-    if let Some(lc) = config.lte.as_ref() {
-        info!(
-            "LTE monitoring enabled (poll: {}s, interface: {})",
-            lc.poll_interval_secs, lc.interface
-        );
-    }
-
-    // Tunnel event persistence: load previous events from disk
-    let events_path = std::path::Path::new(&data_dir).join("tunnel_events.json");
-    let mut tun_stats = TunnelStats::new();
-    tun_stats.events = tokio::sync::Mutex::new(TunnelStats::load_events(&events_path));
-    tun_stats.events_path = Some(events_path);
-
-    // ─── Infra monitoring state ───────────────────────────────────
-    let infra_state = {
-        let mut is = infra::InfraState::new(&config.server.data_dir);
-        is.load_config();
-        Arc::new(tokio::sync::Mutex::new(is))
-    };
-
-    let mut state = AppState {
-        session_manager,
-        config: Arc::new(config),
-        start_time: Instant::now(),
-        session_events,
-        activity_log,
-        exec_results_cache,
-        tunnel_stats: Arc::new(tun_stats),
-        transfer_manager,
-        sse_connections: Arc::new(AtomicU32::new(0)),
-        comms_client: None,
-        comms_state: None,
-        comms_poll_notify: None,
-        relay_history: None,
-        device_snapshots: None,
-        relay_state: None,
-        infra_state: Some(infra_state.clone()),
-    };
-
-    // Build router
-    let public_routes = Router::new().route("/api/health", get(routes::health::health));
-
-    let authed_routes = Router::new()
-        .route("/api/info", get(routes::info::info))
-        .route(
-            "/api/safe_mode/flag",
-            get(routes::safe_mode::get_flag).delete(routes::safe_mode::clear_flag),
-        )
-        .route("/api/diagnostics", get(routes::diagnostics::diagnostics))
-        .route("/api/exec", post(routes::exec::exec))
-        .route("/api/exec/batch", post(routes::exec::batch_exec))
-        .route(
-            "/api/files",
-            get(routes::files::get_file)
-                .put(routes::files::put_file)
-                .delete(routes::files::delete_file),
-        )
-        .route("/api/files/raw", get(routes::files::download_file))
-        .route("/api/files/upload", post(routes::files::upload_file))
-        .route("/api/activity", get(routes::activity::get_activity))
-        .route(
-            "/api/activity/{id}/result",
-            get(routes::activity::get_exec_result),
-        )
-        .route("/api/sessions", get(routes::sessions::list_sessions))
-        .route(
-            "/api/sessions/{id}",
-            delete(routes::sessions::kill_session).patch(routes::sessions::patch_session),
-        )
-        .route(
-            "/api/sessions/{id}/signal",
-            post(routes::sessions::signal_session),
-        )
-        .route("/api/shells", get(routes::shells::list_shells))
-        .route("/api/events", get(routes::events::event_stream))
-        .route("/api/stp/download", post(routes::stp::init_download))
-        .route("/api/stp/upload", post(routes::stp::init_upload))
-        .route(
-            "/api/stp/chunk/{xfer}/{idx}",
-            get(routes::stp::get_chunk).post(routes::stp::post_chunk),
-        )
-        .route("/api/stp/resume/{xfer}", post(routes::stp::resume_transfer))
-        .route("/api/stp/status/{xfer}", get(routes::stp::transfer_status))
-        .route("/api/stp/transfers", get(routes::stp::list_transfers))
-        .route("/api/stp/{xfer}", delete(routes::stp::abort_transfer))
-        .route("/api/playbooks", get(routes::playbooks::list_playbooks))
-        .route(
-            "/api/playbooks/{name}",
-            get(routes::playbooks::get_playbook)
-                .put(routes::playbooks::put_playbook)
-                .delete(routes::playbooks::delete_playbook),
-        )
-        .route("/api/gps", get(routes::gps::gps))
-        .route("/api/lte", get(routes::lte::lte))
-        .route("/api/lte/bands", post(routes::lte::set_bands))
-        .route("/api/lte/scan", post(routes::lte::start_scan))
-        .route("/api/lte/speedtest", post(routes::lte::speed_test))
-        .route("/api/lte/usb_cycle", post(routes::lte::manual_usb_cycle))
-        .route(
-            "/api/lte/watchdog/history",
-            get(routes::lte::watchdog_history),
-        )
-        .route(
-            "/api/infra/config",
-            post(infra::routes::push_config).delete(infra::routes::delete_config),
-        )
-        .route("/api/infra/results", get(infra::routes::get_results))
-        .route(
-            "/api/infra/check/{target_id}",
-            post(infra::routes::check_target),
-        )
-        .route("/api/infra/discover", post(infra::discovery::discover))
-        .route(
-            "/api/infra/discover/progress",
-            get(infra::routes::discover_progress),
-        )
-        .route(
-            "/api/infra/discover/subnets",
-            get(infra::routes::discover_subnets),
-        )
-        .layer(middleware::from_fn(sctl::auth::require_api_key));
-
-    let ws_route = Router::new().route("/api/ws", get(ws::ws_upgrade));
-
-    // GUARD: Headers must be listed explicitly — `allow_headers(Any)` works in
-    // Chrome but Firefox rejects credentialed requests without explicit listing.
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers([
-            axum::http::header::AUTHORIZATION,
-            axum::http::header::CONTENT_TYPE,
-            axum::http::header::ACCEPT,
-            axum::http::HeaderName::from_static("x-gx-chunk-hash"),
-            axum::http::HeaderName::from_static("x-gx-chunk-index"),
-            axum::http::HeaderName::from_static("x-gx-transfer-id"),
-        ])
-        .expose_headers([
-            axum::http::HeaderName::from_static("x-gx-chunk-hash"),
-            axum::http::HeaderName::from_static("x-gx-chunk-index"),
-            axum::http::HeaderName::from_static("x-gx-transfer-id"),
-        ]);
-
-    // Tunnel: create relay state early so relay_history is set before .with_state() clones
-    let tunnel_config = state.config.tunnel.clone();
-    let mut relay_state_opt: Option<tunnel::relay::RelayState> = None;
-    if let Some(ref tc) = tunnel_config {
-        if tc.relay {
-            info!("Tunnel relay mode enabled");
-            let relay_state = tunnel::relay::RelayState::new(
-                tc.tunnel_key.clone(),
-                tc.heartbeat_timeout_secs,
-                tc.tunnel_proxy_timeout_secs,
-                Some(&data_dir),
+    let phase_started = Instant::now();
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = Vec::new();
+    for lc in server.state.config.server.effective_listeners() {
+        let listener = TcpListener::bind(&lc.listen)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to bind {}: {e}", lc.listen));
+        let app = server.listener_router(&lc);
+        let stop = shutdown_token.clone().cancelled_owned();
+
+        #[cfg(feature = "server-tls")]
+        if let Some((acceptor, client_auth)) = tls.clone().filter(|_| lc.tls) {
+            info!(
+                "Listening on {} with TLS (auth: {}, client_auth: {client_auth:?}, expose: {:?})",
+                lc.listen, lc.auth, lc.expose
             );
-            // Seed connection history from journald (survives restarts)
-            relay_state.history.seed_from_journal().await;
-            state.relay_history = Some(relay_state.history.clone());
-            state.device_snapshots = Some(relay_state.device_snapshots.clone());
-            state.relay_state = Some(relay_state.clone());
-            relay_state_opt = Some(relay_state);
+            let keys = server.state.config.auth.keys.clone().into();
+            let listener = sctl::tls::TlsListener::new(listener, acceptor, client_auth, keys)
+                .unwrap_or_else(|e| panic!("Failed to listen on {}: {e}", lc.listen));
+            servers.push(Box::pin(sctl::serve::serve(
+                listener,
+                app,
+                http.clone(),
+                server.state.connections.clone(),
+                stop,
+            )));
+            continue;
         }
-    }
 
-    let mut comms_task = None;
-    if safe_mode_active {
-        info!("Comms provider skipped in safe mode");
-    } else if let Some(comms_cfg) = state.config.effective_comms_config() {
         info!(
-            "Starting comms provider '{}' via {}",
-            comms_cfg.provider,
-            comms_cfg.effective_command()
+            "Listening on {} (auth: {}, expose: {:?})",
+            lc.listen, lc.auth, lc.expose
         );
-        match comms::start_provider(&state.config, &comms_cfg).await {
-            Ok((client, comms_snapshot)) => {
-                let comms_state = Arc::new(tokio::sync::Mutex::new(comms_snapshot));
-                let notify = Arc::new(tokio::sync::Notify::new());
-                comms_task = Some(comms::spawn_poller(
-                    client.clone(),
-                    comms_state.clone(),
-                    state.config.gps.is_some(),
-                    state
-                        .config
-                        .gps
-                        .as_ref()
-                        .map_or(30, |gc| gc.poll_interval_secs),
-                    state.config.lte.is_some(),
-                    state
-                        .config
-                        .lte
-                        .as_ref()
-                        .map_or(60, |lc| lc.poll_interval_secs),
-                    state.tunnel_stats.clone(),
-                    notify.clone(),
-                ));
-                state.comms_client = Some(client);
-                state.comms_state = Some(comms_state);
-                state.comms_poll_notify = Some(notify);
-            }
-            Err(err) => {
-                warn!(
-                    "Comms provider '{}' unavailable: {err}. Management plane unaffected.",
-                    comms_cfg.provider
-                );
-                state.comms_state = Some(Arc::new(tokio::sync::Mutex::new(
-                    comms::CommsState::new(comms_cfg.provider),
-                )));
-            }
-        }
+        servers.push(Box::pin(sctl::serve::serve(
+            listener,
+            app,
+            http.clone(),
+            server.state.connections.clone(),
+            stop,
+        )));
     }
-
-    let mut app = Router::new()
-        .merge(public_routes)
-        .merge(authed_routes)
-        .merge(ws_route)
-        .layer(Extension(ApiKey(state.config.auth.api_key.clone())))
-        .with_state(state.clone());
-
-    // Tunnel: add relay routes if configured (before global layers so CORS/tracing apply)
-    if let Some(ref relay_state) = relay_state_opt {
-        let relay_routes = tunnel::relay::relay_router(relay_state.clone());
-        app = app.merge(relay_routes);
-    }
-
-    // sctlin web UI: reverse proxy /sctlin/* → localhost:3000 (relay mode only)
-    if relay_state_opt.is_some() {
-        app = app.fallback(sctlin_proxy::sctlin_proxy);
-    }
-
-    // GUARD: .layer() only applies to routes merged BEFORE the call.
-    let app = app.layer(cors).layer(TraceLayer::new_for_http()).layer(
-        tower::limit::ConcurrencyLimitLayer::new(state.config.server.max_connections),
-    );
-
-    let listener = TcpListener::bind(&state.config.server.listen)
-        .await
-        .expect("Failed to bind");
+    startup.record("listener_bind", phase_started, None);
+    startup.mark_ready();
 
     info!("Server ready");
 
-    // Tunnel: spawn client if configured, with panic-recovery supervisor.
-    // If the tunnel task panics it will be restarted after 5s. A normal return
-    // (e.g. permanent auth error) stops the supervisor loop.
-    let _tunnel_client_task = if let Some(ref tc) = tunnel_config {
-        if tc.url.is_some() && !tc.relay {
-            info!(
-                "Tunnel client mode enabled, will connect to {}",
-                tc.url.as_deref().unwrap()
-            );
-            let tc = tc.clone();
-            let tunnel_state = state.clone();
-            Some(tokio::spawn(async move {
-                loop {
-                    let handle = tunnel::client::spawn(tunnel_state.clone(), tc.clone());
-                    match handle.await {
-                        Ok(()) => {
-                            // Normal return — tunnel client decided to stop (e.g. permanent auth error)
-                            info!("Tunnel client exited normally, not restarting");
-                            break;
-                        }
-                        Err(e) => {
-                            // JoinError means panic — restart after delay
-                            tracing::error!("Tunnel client panicked: {e}, restarting in 5s");
-                            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                        }
-                    }
-                }
-            }))
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
-    // Start infra monitor if config was loaded from disk (skipped in safe mode)
-    if !safe_mode_active {
-        let mut guard = infra_state.lock().await;
-        if let Some(ref cfg) = guard.config {
-            info!(
-                "Infra: resuming monitoring with {} targets (config v{})",
-                cfg.targets.len(),
-                cfg.version
-            );
-            let handle = infra::monitor::spawn_monitor(infra_state.clone(), cfg.clone());
-            guard.monitor_handle = Some(handle);
-        }
-    }
-
-    // Periodic sweep: clean up sessions whose process has exited + stale transfers
-    let mgr = state.session_manager.clone();
-    let sweep_tx = state.session_events.clone();
-    let sweep_transfers = state.transfer_manager.clone();
-    let sweep_task = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
-        loop {
-            interval.tick().await;
-            let events = mgr.sweep().await;
-            for event in events {
-                match event {
-                    sessions::SweepEvent::Destroyed(session_id, reason) => {
-                        let _ = sweep_tx.send(serde_json::json!({
-                            "type": "session.destroyed",
-                            "session_id": session_id,
-                            "reason": reason,
-                        }));
-                    }
-                    sessions::SweepEvent::AiAutoCleared(session_id) => {
-                        let _ = sweep_tx.send(serde_json::json!({
-                            "type": "session.ai_status_changed",
-                            "session_id": session_id,
-                            "working": false,
-                        }));
-                    }
-                }
-            }
-            // Sweep stale gawdxfer transfers
-            sweep_transfers.sweep_stale().await;
-        }
-    });
-
-    // Tunnel relay: periodic sweep to evict dead devices
-    let relay_sweep_task = relay_state_opt.clone().map(|rs| {
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
-            loop {
-                interval.tick().await;
-                rs.sweep_dead_devices().await;
-            }
-        })
-    });
-
-    // Tunnel relay: periodic snapshot persistence (60s, debounced via dirty flag)
-    let relay_snapshot_task = relay_state_opt.clone().map(|rs| {
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
-            loop {
-                interval.tick().await;
-                rs.save_snapshots().await;
-            }
-        })
-    });
-
-    // Tunnel events: periodic persistence (60s, debounced via dirty flag)
-    let tunnel_events_flush_task = {
-        let flush_stats = state.tunnel_stats.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
-            loop {
-                interval.tick().await;
-                flush_stats.save_events().await;
-            }
-        })
-    };
-
     // Graceful shutdown
     let shutdown = async {
         let ctrl_c = tokio::signal::ctrl_c();
@@ -666,52 +548,18 @@ async fn run_server(config_path: Option<&str>, skip_lock: bool) {
         }
     };
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown)
+    tokio::spawn({
+        let token = shutdown_token.clone();
+        async move {
+            shutdown.await;
+            token.cancel();
+        }
+    });
+    futures::future::try_join_all(servers)
         .await
         .expect("Server error");
 
-    // Cleanup
     info!("Shutting down...");
-    sweep_task.abort();
-    tunnel_events_flush_task.abort();
-    if let Some(task) = relay_sweep_task {
-        task.abort();
-    }
-    if let Some(task) = relay_snapshot_task {
-        task.abort();
-    }
-
-    // Tunnel relay: notify devices, drain state, and do a final snapshot save
-    if let Some(ref rs) = relay_state_opt {
-        info!("Notifying tunnel devices of relay shutdown...");
-        rs.broadcast_to_devices(serde_json::json!({
-            "type": "tunnel.relay_shutdown",
-        }))
-        .await;
-        rs.drain_all().await;
-        if rs.save_snapshots().await {
-            info!("Saved device snapshots to disk");
-        }
-    }
-
-    if let Some(ref client) = state.comms_client {
-        let _ = client
-            .call(
-                sctl_comms_protocol::methods::LOCATION_DISABLE,
-                serde_json::json!({}),
-            )
-            .await;
-    }
-    if let Some(task) = comms_task {
-        task.abort();
-    }
-
-    // Tunnel events: final flush
-    if state.tunnel_stats.save_events().await {
-        info!("Saved tunnel events to disk");
-    }
-
-    state.session_manager.kill_all().await;
+    server.shutdown().await;
     info!("Goodbye");
 }
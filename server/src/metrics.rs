@@ -0,0 +1,258 @@
+//! Prometheus metrics for `GET /api/metrics`.
+//!
+//! Most values are read from existing state at scrape time (sessions, SSE
+//! connections, tunnel stats, activity log, transfer byte counters). Only
+//! exec outcomes and durations are counted here, since nothing else keeps
+//! them once the result cache rolls over.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::AppState;
+
+/// Upper bounds (seconds) of the exec duration histogram buckets.
+const EXEC_BUCKETS_SECS: &[f64] = &[0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0, 120.0, 600.0];
+
+/// Counters that aren't derivable from other state.
+#[allow(clippy::struct_field_names)]
+pub struct Metrics {
+    /// Exec count per status (`ok`, `timeout`, `error`, ...).
+    exec_total: Mutex<BTreeMap<String, u64>>,
+    /// Non-cumulative bucket counts; the last slot is `+Inf`.
+    exec_buckets: [AtomicU64; EXEC_BUCKETS_SECS.len() + 1],
+    exec_duration_ms_sum: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            exec_total: Mutex::new(BTreeMap::new()),
+            exec_buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            exec_duration_ms_sum: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count one finished exec (REST, batch, stream or tunnel).
+    pub fn record_exec(&self, status: &str, duration_ms: u64) {
+        *self
+            .exec_total
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .entry(status.to_string())
+            .or_default() += 1;
+        #[allow(clippy::cast_precision_loss)]
+        let secs = duration_ms as f64 / 1000.0;
+        let idx = EXEC_BUCKETS_SECS
+            .iter()
+            .position(|&le| secs <= le)
+            .unwrap_or(EXEC_BUCKETS_SECS.len());
+        self.exec_buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.exec_duration_ms_sum
+            .fetch_add(duration_ms, Ordering::Relaxed);
+    }
+
+    fn write_exec(&self, out: &mut String) {
+        header(
+            out,
+            "sctl_exec_total",
+            "counter",
+            "Commands executed, by outcome.",
+        );
+        let totals = self
+            .exec_total
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone();
+        for (status, count) in &totals {
+            let _ = writeln!(out, "sctl_exec_total{{status=\"{status}\"}} {count}");
+        }
+
+        header(
+            out,
+            "sctl_exec_duration_seconds",
+            "histogram",
+            "Command execution time.",
+        );
+        let mut cumulative = 0;
+        for (i, bucket) in self.exec_buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = EXEC_BUCKETS_SECS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), ToString::to_string);
+            let _ = writeln!(
+                out,
+                "sctl_exec_duration_seconds_bucket{{le=\"{le}\"}} {cumulative}"
+            );
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let sum = self.exec_duration_ms_sum.load(Ordering::Relaxed) as f64 / 1000.0;
+        let _ = writeln!(out, "sctl_exec_duration_seconds_sum {sum}");
+        let _ = writeln!(out, "sctl_exec_duration_seconds_count {cumulative}");
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn single(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Render every metric in the Prometheus text exposition format (0.0.4).
+pub async fn render(state: &AppState) -> String {
+    let mut out = String::with_capacity(4096);
+
+    single(
+        &mut out,
+        "sctl_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+        state.start_time.elapsed().as_secs(),
+    );
+
+    state.metrics.write_exec(&mut out);
+
+    single(
+        &mut out,
+        "sctl_sessions_active",
+        "gauge",
+        "Interactive sessions currently open.",
+        state.session_manager.session_count().await,
+    );
+    single(
+        &mut out,
+        "sctl_sse_connections",
+        "gauge",
+        "Open Server-Sent Events streams.",
+        state.sse_connections.load(Ordering::Relaxed),
+    );
+    single(
+        &mut out,
+        "sctl_requests_rejected_total",
+        "counter",
+        "Requests refused with 503 because a listener was at max_connections.",
+        state.saturation.rejected(),
+    );
+
+    let (upload, download) = state.transfer_manager.bytes_total();
+    header(
+        &mut out,
+        "sctl_transfer_bytes_total",
+        "counter",
+        "Bytes moved by gawdxfer chunked transfers.",
+    );
+    let _ = writeln!(
+        out,
+        "sctl_transfer_bytes_total{{direction=\"upload\"}} {upload}"
+    );
+    let _ = writeln!(
+        out,
+        "sctl_transfer_bytes_total{{direction=\"download\"}} {download}"
+    );
+
+    single(
+        &mut out,
+        "sctl_activity_log_entries",
+        "gauge",
+        "Entries currently held in the activity log.",
+        state.activity_log.entry_count().await,
+    );
+    single(
+        &mut out,
+        "sctl_activity_entries_total",
+        "counter",
+        "Activity entries logged since start.",
+        state.activity_log.total(),
+    );
+
+    if state.config.tunnel.is_some() {
+        write_tunnel(&mut out, state).await;
+    }
+
+    out
+}
+
+async fn write_tunnel(out: &mut String, state: &AppState) {
+    let tunnel = &state.tunnel_stats;
+    single(
+        out,
+        "sctl_tunnel_connected",
+        "gauge",
+        "1 while at least one relay registration is up.",
+        u8::from(tunnel.connected.load(Ordering::Relaxed)),
+    );
+    single(
+        out,
+        "sctl_tunnel_reconnects_total",
+        "counter",
+        "Tunnel reconnect attempts.",
+        tunnel.reconnects.load(Ordering::Relaxed),
+    );
+    single(
+        out,
+        "sctl_tunnel_messages_sent_total",
+        "counter",
+        "Messages sent over the tunnel.",
+        tunnel.messages_sent.load(Ordering::Relaxed),
+    );
+    single(
+        out,
+        "sctl_tunnel_messages_received_total",
+        "counter",
+        "Messages received over the tunnel.",
+        tunnel.messages_received.load(Ordering::Relaxed),
+    );
+    single(
+        out,
+        "sctl_tunnel_quality_score",
+        "gauge",
+        "Link-quality score, 0 (unusable) to 100 (ideal).",
+        tunnel.quality_score.load(Ordering::Relaxed),
+    );
+    if let Some((median, p95)) = tunnel.rtt_stats().await {
+        header(
+            out,
+            "sctl_tunnel_rtt_seconds",
+            "gauge",
+            "Heartbeat round-trip time over the recent sample window.",
+        );
+        for (quantile, ms) in [("0.5", median), ("0.95", p95)] {
+            #[allow(clippy::cast_precision_loss)]
+            let secs = ms as f64 / 1000.0;
+            let _ = writeln!(
+                out,
+                "sctl_tunnel_rtt_seconds{{quantile=\"{quantile}\"}} {secs}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_histogram_is_cumulative() {
+        let metrics = Metrics::default();
+        metrics.record_exec("ok", 5);
+        metrics.record_exec("ok", 700);
+        metrics.record_exec("timeout", 900_000);
+        let mut out = String::new();
+        metrics.write_exec(&mut out);
+        assert!(out.contains("sctl_exec_total{status=\"ok\"} 2\n"));
+        assert!(out.contains("sctl_exec_total{status=\"timeout\"} 1\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_bucket{le=\"0.01\"} 1\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_bucket{le=\"600\"} 2\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_sum 900.705\n"));
+        assert!(out.contains("sctl_exec_duration_seconds_count 3\n"));
+    }
+}
@@ -24,6 +24,9 @@ const READ_BUF_SIZE: usize = 1024;
 
 struct AtRequest {
     command: String,
+    /// Body sent after the modem's `> ` prompt, terminated with Ctrl-Z
+    /// (e.g. the text of `AT+CMGS`).
+    payload: Option<String>,
     timeout: Duration,
     reply: oneshot::Sender<Result<String, String>>,
 }
@@ -105,10 +108,31 @@ impl Modem {
         &self,
         cmd: &str,
         timeout: Duration,
+    ) -> Result<String, String> {
+        self.request(cmd, None, timeout).await
+    }
+
+    /// Send an AT command that prompts for a body (`> `), such as `AT+CMGS`.
+    /// `payload` is written after the prompt and terminated with Ctrl-Z.
+    pub async fn command_with_payload(
+        &self,
+        cmd: &str,
+        payload: &str,
+        timeout: Duration,
+    ) -> Result<String, String> {
+        self.request(cmd, Some(payload), timeout).await
+    }
+
+    async fn request(
+        &self,
+        cmd: &str,
+        payload: Option<&str>,
+        timeout: Duration,
     ) -> Result<String, String> {
         let (reply_tx, reply_rx) = oneshot::channel();
         let req = AtRequest {
             command: cmd.to_string(),
+            payload: payload.map(str::to_string),
             timeout,
             reply: reply_tx,
         };
@@ -224,7 +248,7 @@ fn modem_thread(fd: RawFd, mut rx: mpsc::Receiver<AtRequest>, device: &str) {
     }
 
     while let Some(req) = rx.blocking_recv() {
-        let result = execute_at(fd, &req.command, req.timeout);
+        let result = execute_at(fd, &req.command, req.payload.as_deref(), req.timeout);
         match &result {
             Ok(resp) => debug!(
                 "Modem {device} AT {}: {:?}",
@@ -242,7 +266,15 @@ fn modem_thread(fd: RawFd, mut rx: mpsc::Receiver<AtRequest>, device: &str) {
 }
 
 /// Execute a single AT command: flush → write → read until terminator.
-fn execute_at(fd: RawFd, command: &str, timeout: Duration) -> Result<String, String> {
+///
+/// With a `payload`, waits for the `> ` prompt first, then writes the
+/// payload and Ctrl-Z before reading the final result.
+fn execute_at(
+    fd: RawFd,
+    command: &str,
+    payload: Option<&str>,
+    timeout: Duration,
+) -> Result<String, String> {
     // SAFETY: fd is valid — owned by the I/O thread for its entire lifetime
     let bfd = unsafe { borrow_fd(fd) };
 
@@ -253,11 +285,42 @@ fn execute_at(fd: RawFd, command: &str, timeout: Duration) -> Result<String, Str
     let cmd_bytes = format!("{command}\r");
     unistd::write(bfd, cmd_bytes.as_bytes()).map_err(|e| format!("write: {e}"))?;
 
-    // Read response until OK/ERROR or timeout
-    let mut buf = [0u8; READ_BUF_SIZE];
     let mut response = String::with_capacity(256);
     let deadline = Instant::now() + timeout;
 
+    if let Some(payload) = payload {
+        if let Err(e) = read_until(fd, &mut response, deadline, timeout, |r| {
+            r.contains('>') || response_is_complete(r)
+        }) {
+            // ESC cancels the body entry in case the prompt arrived late
+            let _ = unistd::write(bfd, b"\x1b");
+            return Err(e);
+        }
+        if response_is_complete(&response) {
+            // Refused before the prompt (e.g. +CMS ERROR)
+            return Ok(strip_echo(&sanitize_response(&response)));
+        }
+        let body = format!("{payload}\x1a");
+        unistd::write(bfd, body.as_bytes()).map_err(|e| format!("write payload: {e}"))?;
+    }
+
+    // Read response until OK/ERROR or timeout
+    read_until(fd, &mut response, deadline, timeout, response_is_complete)?;
+
+    let cleaned = sanitize_response(&response);
+    Ok(strip_echo(&cleaned))
+}
+
+/// Read into `response` until `done` accepts it or `deadline` passes.
+fn read_until(
+    fd: RawFd,
+    response: &mut String,
+    deadline: Instant,
+    timeout: Duration,
+    done: impl Fn(&str) -> bool,
+) -> Result<(), String> {
+    let mut buf = [0u8; READ_BUF_SIZE];
+
     loop {
         if Instant::now() >= deadline {
             return Err(format!(
@@ -268,29 +331,21 @@ fn execute_at(fd: RawFd, command: &str, timeout: Duration) -> Result<String, Str
         }
 
         match unistd::read(fd, &mut buf) {
-            Ok(0) => {
+            Ok(0) | Err(nix::errno::Errno::EAGAIN) => {
                 // VTIME expired with no data
-                if response_is_complete(&response) {
-                    break;
+                if done(response) {
+                    return Ok(());
                 }
             }
             Ok(n) => {
                 response.push_str(&String::from_utf8_lossy(&buf[..n]));
-                if response_is_complete(&response) {
-                    break;
-                }
-            }
-            Err(nix::errno::Errno::EAGAIN) => {
-                if response_is_complete(&response) {
-                    break;
+                if done(response) {
+                    return Ok(());
                 }
             }
             Err(e) => return Err(format!("read: {e}")),
         }
     }
-
-    let cleaned = sanitize_response(&response);
-    Ok(strip_echo(&cleaned))
 }
 
 /// Check if the AT response contains a final result code.
@@ -598,3 +653,233 @@ mod sysfs_tests {
         );
     }
 }
+
+// ── SMS (text mode) ─────────────────────────────────────────────────
+
+/// `AT+CMGS` only answers once the network has accepted the message.
+const SMS_SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest text accepted for a single (non-concatenated) text-mode SMS.
+pub const SMS_MAX_TEXT: usize = 160;
+
+/// One message from the modem's SMS storage (`AT+CMGL`).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Sms {
+    /// Storage index, used to delete the message.
+    pub index: u32,
+    /// `unread`, `read`, `unsent` or `sent`.
+    pub status: String,
+    /// Sender for received messages, recipient for stored outgoing ones.
+    pub number: String,
+    /// Service-centre timestamp as RFC 3339, when the modem reports one.
+    pub timestamp: Option<String>,
+    pub text: String,
+}
+
+/// List every stored message. Reading marks unread messages as read.
+pub async fn sms_list(modem: &Modem) -> Result<Vec<Sms>, String> {
+    sms_text_mode(modem).await?;
+    let response = modem
+        .command_with_timeout("AT+CMGL=\"ALL\"", Duration::from_secs(10))
+        .await?;
+    if let Some(err) = final_error(&response) {
+        return Err(err.to_string());
+    }
+    Ok(parse_cmgl(&response))
+}
+
+/// Send a text-mode SMS and return the network's message reference.
+///
+/// The text must be printable ASCII (plus newlines) and at most
+/// [`SMS_MAX_TEXT`] characters; anything else is refused before it reaches
+/// the modem.
+pub async fn sms_send(modem: &Modem, number: &str, text: &str) -> Result<Option<u32>, String> {
+    validate_sms(number, text)?;
+    sms_text_mode(modem).await?;
+    let response = modem
+        .command_with_payload(
+            &format!("AT+CMGS=\"{number}\""),
+            &text.replace('\n', "\r"),
+            SMS_SEND_TIMEOUT,
+        )
+        .await?;
+    if let Some(err) = final_error(&response) {
+        return Err(err.to_string());
+    }
+    Ok(response.lines().find_map(|l| {
+        l.trim()
+            .strip_prefix("+CMGS:")
+            .and_then(|mr| mr.trim().parse().ok())
+    }))
+}
+
+/// Delete the message at storage `index`.
+pub async fn sms_delete(modem: &Modem, index: u32) -> Result<(), String> {
+    let response = modem.command(&format!("AT+CMGD={index}")).await?;
+    match final_error(&response) {
+        Some(err) => Err(err.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Check a recipient number and text for [`sms_send`].
+pub fn validate_sms(number: &str, text: &str) -> Result<(), String> {
+    let digits = number.strip_prefix('+').unwrap_or(number);
+    if digits.len() < 3 || digits.len() > 20 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid phone number: {number}"));
+    }
+    if text.is_empty() {
+        return Err("text cannot be empty".to_string());
+    }
+    if text.chars().count() > SMS_MAX_TEXT {
+        return Err(format!("text longer than {SMS_MAX_TEXT} characters"));
+    }
+    if let Some(c) = text
+        .chars()
+        .find(|&c| c != '\n' && !(c.is_ascii_graphic() || c == ' '))
+    {
+        return Err(format!("unsupported character in text: {c:?}"));
+    }
+    Ok(())
+}
+
+async fn sms_text_mode(modem: &Modem) -> Result<(), String> {
+    let response = modem.command("AT+CMGF=1").await?;
+    match final_error(&response) {
+        Some(err) => Err(format!("AT+CMGF=1: {err}")),
+        None => Ok(()),
+    }
+}
+
+/// The `ERROR` / `+CME ERROR` / `+CMS ERROR` line of a failed response.
+fn final_error(response: &str) -> Option<&str> {
+    response
+        .lines()
+        .map(str::trim)
+        .find(|l| *l == "ERROR" || l.starts_with("+CME ERROR:") || l.starts_with("+CMS ERROR:"))
+}
+
+/// Parse a text-mode `AT+CMGL` response:
+///
+/// ```text
+/// +CMGL: 1,"REC UNREAD","+15551234567",,"24/05/01,12:34:56+08"
+/// Hello
+/// OK
+/// ```
+fn parse_cmgl(response: &str) -> Vec<Sms> {
+    let mut messages: Vec<Sms> = Vec::new();
+    let mut current: Option<(Sms, Vec<&str>)> = None;
+
+    for line in response.lines() {
+        let trimmed = line.trim_end_matches('\r');
+        if let Some(header) = trimmed.strip_prefix("+CMGL:") {
+            if let Some((mut sms, body)) = current.take() {
+                sms.text = body.join("\n");
+                messages.push(sms);
+            }
+            let fields = split_quoted(header.trim());
+            let Some(index) = fields.first().and_then(|f| f.parse().ok()) else {
+                continue;
+            };
+            let status = match fields.get(1).map(String::as_str) {
+                Some("REC UNREAD") => "unread",
+                Some("REC READ") => "read",
+                Some("STO UNSENT") => "unsent",
+                Some("STO SENT") => "sent",
+                Some(other) => other,
+                None => "",
+            };
+            let sms = Sms {
+                index,
+                status: status.to_string(),
+                number: fields.get(2).cloned().unwrap_or_default(),
+                timestamp: fields.get(4).and_then(|t| scts_to_rfc3339(t)),
+                text: String::new(),
+            };
+            current = Some((sms, Vec::new()));
+        } else if trimmed.trim() == "OK" {
+            break;
+        } else if let Some((_, ref mut body)) = current {
+            body.push(trimmed);
+        }
+    }
+    if let Some((mut sms, body)) = current {
+        sms.text = body.join("\n");
+        messages.push(sms);
+    }
+    messages
+}
+
+/// Split AT response fields on commas outside double quotes, unquoting them.
+fn split_quoted(s: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// `yy/MM/dd,hh:mm:ss±zz` (zone in quarter hours) → RFC 3339.
+fn scts_to_rfc3339(scts: &str) -> Option<String> {
+    let (date, rest) = scts.split_once(',')?;
+    let sign_pos = rest.find(['+', '-'])?;
+    let (time, zone) = rest.split_at(sign_pos);
+    let date: Vec<&str> = date.split('/').collect();
+    if date.len() != 3 || time.len() != 8 {
+        return None;
+    }
+    let quarters: i32 = zone.parse().ok()?;
+    let minutes = quarters.abs() * 15;
+    Some(format!(
+        "20{}-{}-{}T{time}{}{:02}:{:02}",
+        date[0],
+        date[1],
+        date[2],
+        if quarters < 0 { '-' } else { '+' },
+        minutes / 60,
+        minutes % 60
+    ))
+}
+
+#[cfg(test)]
+mod sms_tests {
+    use super::*;
+
+    #[test]
+    fn parses_cmgl_listing() {
+        let response = "+CMGL: 1,\"REC UNREAD\",\"+15551234567\",,\"24/05/01,12:34:56+08\"\r\n\
+                        Your data plan\r\nis exhausted\r\n\
+                        +CMGL: 4,\"STO SENT\",\"+15550000000\",\r\nwake\r\n\
+                        OK";
+        let messages = parse_cmgl(response);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].index, 1);
+        assert_eq!(messages[0].status, "unread");
+        assert_eq!(messages[0].number, "+15551234567");
+        assert_eq!(
+            messages[0].timestamp.as_deref(),
+            Some("2024-05-01T12:34:56+02:00")
+        );
+        assert_eq!(messages[0].text, "Your data plan\nis exhausted");
+        assert_eq!(messages[1].status, "sent");
+        assert_eq!(messages[1].timestamp, None);
+        assert_eq!(messages[1].text, "wake");
+    }
+
+    #[test]
+    fn validates_sms_input() {
+        assert!(validate_sms("+15551234567", "reboot").is_ok());
+        assert!(validate_sms("555-1234", "x").is_err());
+        assert!(validate_sms("+15551234567", "").is_err());
+        assert!(validate_sms("+15551234567", "caf\u{e9}").is_err());
+        assert!(validate_sms("+15551234567", "a\x1b").is_err());
+        assert!(validate_sms("+15551234567", &"a".repeat(161)).is_err());
+    }
+}
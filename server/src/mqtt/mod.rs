@@ -0,0 +1,587 @@
+//! MQTT bridge (`[mqtt]`).
+//!
+//! Keeps one connection to a broker and publishes device telemetry under
+//! `topic_prefix`:
+//!
+//! | Topic                 | Payload                                      | When                         |
+//! |-----------------------|----------------------------------------------|------------------------------|
+//! | `{prefix}/status`     | `{"state":"online", ...}`, retained          | on connect; `offline` is the will |
+//! | `{prefix}/activity`   | activity entry                               | each `activity.new`          |
+//! | `{prefix}/health`     | `GET /api/health` body                       | every `heartbeat_secs`       |
+//! | `{prefix}/metrics`    | process, system and network sample           | every `metrics_secs`         |
+//! | `{prefix}/cmd/result` | `{id, type, status, body}`                   | after each command           |
+//!
+//! ## Commands
+//!
+//! With `commands` set, the bridge subscribes to `{prefix}/cmd` and runs
+//! JSON messages of an enabled type:
+//!
+//! - `{"id": "...", "type": "exec", ...}` — the rest is a `POST /api/exec`
+//!   body
+//! - `{"id": "...", "type": "playbook", "name": "...", ...}` — the rest is a
+//!   `POST /api/playbooks/{name}/run` body
+//!
+//! They go through the REST handlers as `X-Sctl-Client: mqtt` with `id` as
+//! the request id, so policy, hooks, the AI budget and the activity log
+//! apply. The broker's ACLs are the only authentication: enable commands
+//! only when nobody else can publish to `{prefix}/cmd`.
+//!
+//! ## Delivery
+//!
+//! Messages are queued while the broker is unreachable (oldest dropped
+//! first past [`QUEUE_SIZE`], counted in `dropped`) and the connection is
+//! retried with exponential backoff up to `reconnect_max_secs`. With
+//! `qos = 1`, messages the broker hasn't acknowledged when the connection
+//! drops are sent again after reconnecting.
+
+mod packet;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    Json,
+};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::MqttConfig;
+use crate::error::{codes, ApiError};
+use crate::routes::{exec, playbooks};
+use crate::sessions::journal::now_ms;
+use crate::AppState;
+
+/// Command types `[mqtt] commands` may enable.
+pub const COMMANDS: &[&str] = &["exec", "playbook"];
+
+/// Messages buffered while the broker is unreachable.
+pub const QUEUE_SIZE: usize = 1000;
+
+/// QoS 1 messages sent but not yet acknowledged.
+const MAX_INFLIGHT: usize = 32;
+
+/// Connect, TLS and `CONNACK` must be done within this.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the broker is, from `[mqtt] url`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Broker {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+}
+
+impl Broker {
+    /// Parse `mqtt://host[:port]` or `mqtts://host[:port]`.
+    ///
+    /// # Errors
+    ///
+    /// Another scheme, a path, no host or a bad port.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("mqtts://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("mqtt://") {
+            (false, rest)
+        } else {
+            return Err(format!("'{url}' must start with mqtt:// or mqtts://"));
+        };
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        if rest.contains(['/', '?', '@']) {
+            return Err(format!("'{url}' must be scheme://host[:port]"));
+        }
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse().map_err(|_| format!("bad port in '{url}'"))?,
+            ),
+            _ => (rest, if tls { 8883 } else { 1883 }),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("no host in '{url}'"));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+}
+
+/// A message waiting to be published.
+#[derive(Debug, Clone)]
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+}
+
+/// Queue, topics and counters shared by the bridge tasks.
+pub struct Bridge {
+    config: MqttConfig,
+    broker: Option<Broker>,
+    client_id: String,
+    prefix: String,
+    serial: String,
+    queue: Mutex<VecDeque<Message>>,
+    notify: Notify,
+    connected: AtomicBool,
+    published: AtomicU64,
+    dropped: AtomicU64,
+    commands: AtomicU64,
+}
+
+impl Bridge {
+    /// Create the bridge. Nothing is sent until [`spawn`](Self::spawn).
+    pub fn new(config: MqttConfig, serial: &str) -> Arc<Self> {
+        Arc::new(Self {
+            broker: Broker::parse(&config.url).ok(),
+            client_id: config.client_id.replace("{serial}", serial),
+            prefix: config
+                .topic_prefix
+                .trim_end_matches('/')
+                .replace("{serial}", serial),
+            serial: serial.to_string(),
+            queue: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            connected: AtomicBool::new(false),
+            published: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            config,
+        })
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{name}", self.prefix)
+    }
+
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, VecDeque<Message>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue `payload` for `{prefix}/{name}`, dropping the oldest message if
+    /// the queue is full.
+    fn publish(&self, name: &str, payload: &Value) {
+        let message = Message {
+            topic: self.topic(name),
+            payload: payload.to_string().into_bytes(),
+        };
+        {
+            let mut queue = self.lock_queue();
+            if queue.len() >= QUEUE_SIZE {
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(message);
+        }
+        self.notify.notify_one();
+    }
+
+    /// Bridge status for `/api/info`.
+    pub fn status(&self) -> Value {
+        json!({
+            "url": self.config.url,
+            "client_id": self.client_id,
+            "topic_prefix": self.prefix,
+            "connected": self.connected.load(Ordering::Relaxed),
+            "queued": self.lock_queue().len(),
+            "published": self.published.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "commands": self.commands.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Start the activity subscriber, the heartbeat/metrics sampler and the
+    /// connection. Returns all handles for abort on shutdown.
+    pub fn spawn(self: &Arc<Self>, state: &AppState) -> [JoinHandle<()>; 3] {
+        let bridge = self.clone();
+        let mut rx = state.session_events.subscribe();
+        let subscriber = tokio::spawn(async move {
+            if !bridge.config.activity {
+                return;
+            }
+            loop {
+                match rx.recv().await {
+                    Ok(msg) if msg["type"] == "activity.new" => {
+                        bridge.publish("activity", &msg["entry"]);
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        bridge.dropped.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let sampler = tokio::spawn(self.clone().run_sampler(state.clone()));
+        let connection = tokio::spawn(self.clone().run_connection(state.clone()));
+        [subscriber, sampler, connection]
+    }
+
+    /// Publish heartbeats and metrics samples when they're due.
+    async fn run_sampler(self: Arc<Self>, state: AppState) {
+        let every = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        let heartbeat = every(self.config.heartbeat_secs);
+        let metrics = every(self.config.metrics_secs);
+        let (mut next_heartbeat, mut next_metrics) = (Instant::now(), Instant::now());
+        loop {
+            let now = Instant::now();
+            if let Some(interval) = heartbeat.filter(|_| now >= next_heartbeat) {
+                let Json(mut health) = crate::routes::health::health(State(state.clone())).await;
+                health["timestamp"] = json!(now_ms());
+                self.publish("health", &health);
+                next_heartbeat = now + interval;
+            }
+            if let Some(interval) = metrics.filter(|_| now >= next_metrics) {
+                let mut sample = crate::routes::diagnostics::system_snapshot(&state);
+                sample["timestamp"] = json!(now_ms());
+                self.publish("metrics", &sample);
+                next_metrics = now + interval;
+            }
+            let next = match (heartbeat, metrics) {
+                (Some(_), Some(_)) => next_heartbeat.min(next_metrics),
+                (Some(_), None) => next_heartbeat,
+                (None, Some(_)) => next_metrics,
+                (None, None) => return,
+            };
+            tokio::time::sleep_until(next.into()).await;
+        }
+    }
+
+    async fn run_connection(self: Arc<Self>, state: AppState) {
+        let Some(broker) = self.broker.clone() else {
+            return;
+        };
+        let initial = Duration::from_secs(1);
+        let max = Duration::from_secs(self.config.reconnect_max_secs.max(1));
+        let mut delay = initial;
+
+        loop {
+            let mut inflight = BTreeMap::new();
+            let mut established = false;
+            let result = self
+                .session(&broker, &state, &mut inflight, &mut established)
+                .await;
+            self.connected.store(false, Ordering::Relaxed);
+            if !inflight.is_empty() {
+                let mut queue = self.lock_queue();
+                for message in inflight.into_values().rev() {
+                    queue.push_front(message);
+                }
+                while queue.len() > QUEUE_SIZE {
+                    queue.pop_back();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            if established {
+                delay = initial;
+            }
+            let e = result.err().unwrap_or_else(|| "closed".to_string());
+            warn!(
+                "MQTT broker {} {e}; retrying in {}s",
+                self.config.url,
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(max);
+        }
+    }
+
+    /// One connection: connect, then publish and handle commands until it
+    /// fails. Unacknowledged QoS 1 messages are left in `inflight`.
+    async fn session(
+        self: &Arc<Self>,
+        broker: &Broker,
+        state: &AppState,
+        inflight: &mut BTreeMap<u16, Message>,
+        established: &mut bool,
+    ) -> Result<(), String> {
+        let stream = tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            TcpStream::connect((broker.host.as_str(), broker.port)),
+        )
+        .await
+        .map_err(|_| "connect timed out".to_string())?
+        .map_err(|e| format!("connect: {e}"))?;
+        let _ = stream.set_nodelay(true);
+        if broker.tls {
+            let tls = tokio::time::timeout(
+                CONNECT_TIMEOUT,
+                crate::fetch::tls_connect(&broker.host, stream),
+            )
+            .await
+            .map_err(|_| "TLS handshake timed out".to_string())??;
+            self.converse(tls, state, inflight, established).await
+        } else {
+            self.converse(stream, state, inflight, established).await
+        }
+    }
+
+    async fn converse<S>(
+        self: &Arc<Self>,
+        stream: S,
+        state: &AppState,
+        inflight: &mut BTreeMap<u16, Message>,
+        established: &mut bool,
+    ) -> Result<(), String>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let qos = self.config.qos;
+        let status_topic = self.topic("status");
+        let will = json!({ "state": "offline", "serial": self.serial }).to_string();
+        let connect = packet::connect(&packet::Connect {
+            client_id: &self.client_id,
+            keepalive_secs: self.config.keepalive_secs,
+            username: self.config.username.as_deref(),
+            password: self.config.password.as_deref(),
+            will: Some(packet::Will {
+                topic: &status_topic,
+                payload: will.as_bytes(),
+                qos,
+                retain: true,
+            }),
+        });
+        let write_err = |e: std::io::Error| format!("connection lost: {e}");
+        writer.write_all(&connect).await.map_err(write_err)?;
+        match tokio::time::timeout(CONNECT_TIMEOUT, packet::read(&mut reader)).await {
+            Ok(Ok(packet::Packet::ConnAck { code: 0 })) => {}
+            Ok(Ok(packet::Packet::ConnAck { code })) => {
+                return Err(format!(
+                    "refused the connection: {}",
+                    packet::connack_reason(code)
+                ));
+            }
+            Ok(Ok(other)) => return Err(format!("sent {other:?} instead of CONNACK")),
+            Ok(Err(e)) => return Err(format!("connect: {e}")),
+            Err(_) => return Err("sent no CONNACK".to_string()),
+        }
+        *established = true;
+        self.connected.store(true, Ordering::Relaxed);
+        info!(
+            "MQTT connected to {} as {}",
+            self.config.url, self.client_id
+        );
+
+        // Packet reads aren't cancel-safe, so they get their own task.
+        let (packets_tx, mut packets) = mpsc::channel(16);
+        let _reader = AbortOnDrop(tokio::spawn(async move {
+            loop {
+                let packet = packet::read(&mut reader).await;
+                let failed = packet.is_err();
+                if packets_tx.send(packet).await.is_err() || failed {
+                    break;
+                }
+            }
+        }));
+
+        let mut next_id = 0u16;
+        let mut take_id = || {
+            next_id = next_id.checked_add(1).unwrap_or(1);
+            next_id
+        };
+        let online = json!({
+            "state": "online",
+            "serial": self.serial,
+            "version": crate::VERSION,
+            "timestamp": now_ms(),
+        });
+        writer
+            .write_all(&packet::publish(
+                &status_topic,
+                online.to_string().as_bytes(),
+                0,
+                true,
+                0,
+            ))
+            .await
+            .map_err(write_err)?;
+        let cmd_topic = self.topic("cmd");
+        if !self.config.commands.is_empty() {
+            writer
+                .write_all(&packet::subscribe(take_id(), &cmd_topic, 1))
+                .await
+                .map_err(write_err)?;
+        }
+
+        let keepalive = Duration::from_secs(u64::from(self.config.keepalive_secs));
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + keepalive, keepalive);
+        let mut last_heard = Instant::now();
+        loop {
+            while inflight.len() < MAX_INFLIGHT {
+                let Some(message) = self.lock_queue().pop_front() else {
+                    break;
+                };
+                let id = if qos > 0 { take_id() } else { 0 };
+                let bytes = packet::publish(&message.topic, &message.payload, qos, false, id);
+                if let Err(e) = writer.write_all(&bytes).await {
+                    self.lock_queue().push_front(message);
+                    return Err(write_err(e));
+                }
+                if qos > 0 {
+                    inflight.insert(id, message);
+                } else {
+                    self.published.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+
+            tokio::select! {
+                () = self.notify.notified() => {}
+                packet = packets.recv() => {
+                    let packet = match packet {
+                        Some(Ok(packet)) => packet,
+                        Some(Err(e)) => return Err(format!("connection lost: {e}")),
+                        None => return Err("connection lost".to_string()),
+                    };
+                    last_heard = Instant::now();
+                    self.handle(packet, &mut writer, inflight, &cmd_topic, state).await?;
+                }
+                _ = ping.tick() => {
+                    if last_heard.elapsed() > keepalive * 3 / 2 {
+                        return Err("stopped answering".to_string());
+                    }
+                    writer.write_all(&packet::pingreq()).await.map_err(write_err)?;
+                }
+            }
+        }
+    }
+    /// Act on a packet from the broker.
+    async fn handle<W: AsyncWrite + Unpin>(
+        self: &Arc<Self>,
+        packet: packet::Packet,
+        writer: &mut W,
+        inflight: &mut BTreeMap<u16, Message>,
+        cmd_topic: &str,
+        state: &AppState,
+    ) -> Result<(), String> {
+        match packet {
+            packet::Packet::PubAck(id) if inflight.remove(&id).is_some() => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+            }
+            packet::Packet::Publish {
+                topic,
+                payload,
+                packet_id,
+                ..
+            } => {
+                if let Some(id) = packet_id {
+                    writer
+                        .write_all(&packet::puback(id))
+                        .await
+                        .map_err(|e| format!("connection lost: {e}"))?;
+                }
+                if topic == cmd_topic && !self.config.commands.is_empty() {
+                    self.commands.fetch_add(1, Ordering::Relaxed);
+                    let (bridge, state) = (self.clone(), state.clone());
+                    tokio::spawn(async move {
+                        let reply = command(&state, &bridge.config.commands, &payload).await;
+                        bridge.publish("cmd/result", &reply);
+                    });
+                }
+            }
+            packet::Packet::SubAck { codes, .. } if codes.contains(&0x80) => {
+                warn!(
+                    "MQTT broker {} refused the subscription to {cmd_topic}",
+                    self.config.url
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Aborts the task when dropped.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run one command message and build its `cmd/result` reply.
+async fn command(state: &AppState, enabled: &[String], payload: &[u8]) -> Value {
+    let msg: Value = serde_json::from_slice(payload).unwrap_or(Value::Null);
+    let kind = msg["type"].as_str().unwrap_or_default();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-sctl-client", HeaderValue::from_static("mqtt"));
+    if let Some(id) = msg["id"]
+        .as_str()
+        .and_then(|id| HeaderValue::from_str(id).ok())
+    {
+        headers.insert("x-request-id", id);
+    }
+
+    let result = match kind {
+        _ if !msg.is_object() => Err(invalid_request("Command must be a JSON object".to_string())),
+        _ if !enabled.iter().any(|e| e == kind) => Err(invalid_request(format!(
+            "Command type {kind:?} is not enabled ([mqtt] commands)"
+        ))),
+        "exec" => match serde_json::from_value(msg.clone()) {
+            Ok(req) => exec::exec(
+                State(state.clone()),
+                headers,
+                crate::deadline::RequestDeadline::default(),
+                Query(exec::ExecQuery {
+                    confirm_within: None,
+                }),
+                Json(req),
+            )
+            .await
+            .map(|Json(resp)| serde_json::to_value(resp).unwrap_or_default()),
+            Err(e) => Err(invalid_request(format!("Invalid exec command: {e}"))),
+        },
+        "playbook" => match serde_json::from_value(msg.clone()) {
+            Ok(req) => {
+                let name = msg["name"].as_str().unwrap_or_default();
+                playbooks::run(state, &headers, name, req)
+                    .await
+                    .map(|Json(report)| serde_json::to_value(report).unwrap_or_default())
+            }
+            Err(e) => Err(invalid_request(format!("Invalid playbook command: {e}"))),
+        },
+        _ => Err(invalid_request(format!("Unknown command type {kind:?}"))),
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err((status, Json(err))) => (status, serde_json::to_value(err).unwrap_or_default()),
+    };
+    json!({
+        "id": msg["id"],
+        "type": kind,
+        "status": status.as_u16(),
+        "body": body,
+    })
+}
+
+fn invalid_request(message: String) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_REQUEST, message).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broker_url_defaults_port_by_scheme() {
+        let broker = Broker::parse("mqtts://broker.example.com").unwrap();
+        assert_eq!((broker.port, broker.tls), (8883, true));
+        let broker = Broker::parse("mqtt://[::1]:1884/").unwrap();
+        assert_eq!((broker.host.as_str(), broker.port), ("::1", 1884));
+        assert_eq!(Broker::parse("mqtt://[::1]").unwrap().port, 1883);
+        assert!(Broker::parse("tcp://broker:1883").is_err());
+        assert!(Broker::parse("mqtt://user@broker").is_err());
+        assert!(Broker::parse("mqtt://broker:x").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! The MQTT 3.1.1 packets the bridge sends and understands.
+//!
+//! Only what a QoS 0/1 client needs: `CONNECT`, `PUBLISH`, `PUBACK`,
+//! `SUBSCRIBE` and `PINGREQ` out; `CONNACK`, `PUBLISH`, `PUBACK`, `SUBACK`
+//! and `PINGRESP` in. Anything else from the broker is decoded as
+//! [`Packet::Other`] and ignored.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest packet accepted from the broker.
+pub const MAX_PACKET: usize = 1024 * 1024;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+/// A message the broker holds for us if we vanish.
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// `CONNECT` options. Always a clean session.
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keepalive_secs: u16,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub will: Option<Will<'a>>,
+}
+
+/// A packet received from the broker.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+        packet_id: Option<u16>,
+    },
+    PubAck(u16),
+    SubAck {
+        packet_id: u16,
+        codes: Vec<u8>,
+    },
+    PingResp,
+    Other(u8),
+}
+
+/// What a `CONNACK` return code means.
+pub fn connack_reason(code: u8) -> &'static str {
+    match code {
+        0 => "accepted",
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown return code",
+    }
+}
+
+pub fn connect(opts: &Connect<'_>) -> Vec<u8> {
+    let mut flags = 0x02;
+    let mut body = Vec::new();
+    put_str(&mut body, "MQTT");
+    body.push(4);
+    body.push(0); // flags, filled in below
+    body.extend_from_slice(&opts.keepalive_secs.to_be_bytes());
+    put_str(&mut body, opts.client_id);
+    if let Some(will) = &opts.will {
+        flags |= 0x04 | (will.qos << 3);
+        if will.retain {
+            flags |= 0x20;
+        }
+        put_str(&mut body, will.topic);
+        put_bytes(&mut body, will.payload);
+    }
+    if let Some(username) = opts.username {
+        flags |= 0x80;
+        put_str(&mut body, username);
+    }
+    if let Some(password) = opts.password {
+        flags |= 0x40;
+        put_str(&mut body, password);
+    }
+    body[7] = flags;
+    frame(CONNECT << 4, &body)
+}
+
+pub fn publish(topic: &str, payload: &[u8], qos: u8, retain: bool, packet_id: u16) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+    put_str(&mut body, topic);
+    if qos > 0 {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    frame((PUBLISH << 4) | (qos << 1) | u8::from(retain), &body)
+}
+
+pub fn puback(packet_id: u16) -> Vec<u8> {
+    frame(PUBACK << 4, &packet_id.to_be_bytes())
+}
+
+pub fn subscribe(packet_id: u16, topic: &str, qos: u8) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    put_str(&mut body, topic);
+    body.push(qos);
+    frame((SUBSCRIBE << 4) | 0x02, &body)
+}
+
+pub fn pingreq() -> Vec<u8> {
+    frame(PINGREQ << 4, &[])
+}
+
+/// Read one packet.
+///
+/// # Errors
+///
+/// The stream fails or ends, or the packet is malformed or over
+/// [`MAX_PACKET`].
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Packet> {
+    let header = reader.read_u8().await?;
+    let mut len = 0usize;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await?;
+        len |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if shift == 21 {
+            return Err(invalid("remaining length too long"));
+        }
+    }
+    if len > MAX_PACKET {
+        return Err(invalid(&format!("{len}-byte packet")));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    decode(header, &body).map_err(|e| invalid(&e))
+}
+
+/// Decode a packet from its first byte and the rest after the length.
+fn decode(header: u8, body: &[u8]) -> Result<Packet, String> {
+    let u16_at = |i: usize| {
+        body.get(i..i + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| format!("truncated packet type {}", header >> 4))
+    };
+    Ok(match header >> 4 {
+        CONNACK => Packet::ConnAck {
+            code: *body.get(1).ok_or("truncated CONNACK")?,
+        },
+        PUBLISH => {
+            let qos = (header >> 1) & 0x03;
+            let topic_len = usize::from(u16_at(0)?);
+            let topic = body
+                .get(2..2 + topic_len)
+                .ok_or("truncated PUBLISH topic")?;
+            let topic = String::from_utf8(topic.to_vec()).map_err(|_| "PUBLISH topic not UTF-8")?;
+            let mut rest = 2 + topic_len;
+            let packet_id = if qos > 0 {
+                rest += 2;
+                Some(u16_at(2 + topic_len)?)
+            } else {
+                None
+            };
+            Packet::Publish {
+                topic,
+                payload: body[rest..].to_vec(),
+                qos,
+                packet_id,
+            }
+        }
+        PUBACK => Packet::PubAck(u16_at(0)?),
+        SUBACK => Packet::SubAck {
+            packet_id: u16_at(0)?,
+            codes: body.get(2..).unwrap_or_default().to_vec(),
+        },
+        PINGRESP => Packet::PingResp,
+        other => Packet::Other(other),
+    })
+}
+
+fn frame(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 5);
+    out.push(header);
+    let mut len = body.len();
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_bytes(out, s.as_bytes());
+}
+
+/// Length-prefixed bytes; MQTT strings are at most 65535 bytes, longer ones
+/// are cut.
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let bytes = &bytes[..bytes.len().min(usize::from(u16::MAX))];
+    #[allow(clippy::cast_possible_truncation)]
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn packets_round_trip_through_read() {
+        let payload = vec![b'x'; 200];
+        let bytes = publish("sctl/DEV-1/cmd", &payload, 1, false, 7);
+        // 2-byte remaining length: 16 topic + 2 id + 200 payload = 218
+        assert_eq!(&bytes[..3], &[0x32, 0xda, 0x01]);
+        let packet = read(&mut bytes.as_slice()).await.unwrap();
+        assert_eq!(
+            packet,
+            Packet::Publish {
+                topic: "sctl/DEV-1/cmd".into(),
+                payload,
+                qos: 1,
+                packet_id: Some(7),
+            }
+        );
+
+        let mut stream = [0x20, 2, 0, 5, 0x90, 3, 0, 9, 1, 0xd0, 0].as_slice();
+        assert_eq!(
+            read(&mut stream).await.unwrap(),
+            Packet::ConnAck { code: 5 }
+        );
+        assert_eq!(
+            read(&mut stream).await.unwrap(),
+            Packet::SubAck {
+                packet_id: 9,
+                codes: vec![1]
+            }
+        );
+        assert_eq!(read(&mut stream).await.unwrap(), Packet::PingResp);
+        assert!(read(&mut [0x30, 0xff, 0xff, 0xff, 0x7f].as_slice())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn connect_sets_flags_for_will_and_credentials() {
+        let bytes = connect(&Connect {
+            client_id: "c",
+            keepalive_secs: 30,
+            username: Some("u"),
+            password: Some("p"),
+            will: Some(Will {
+                topic: "t",
+                payload: b"off",
+                qos: 1,
+                retain: true,
+            }),
+        });
+        assert_eq!(bytes[0], 0x10);
+        assert_eq!(&bytes[2..9], b"\0\x04MQTT\x04");
+        assert_eq!(bytes[9], 0x80 | 0x40 | 0x20 | 0x08 | 0x04 | 0x02);
+        assert_eq!(&bytes[10..12], &30u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,127 @@
+//! MessagePack responses for `Accept: application/msgpack`.
+//!
+//! Large lists (activity, sessions, directory listings) cost a small client
+//! as much to parse as to download. A client that sends
+//! `Accept: application/msgpack` (or `application/x-msgpack`) gets any JSON
+//! response as the same document in MessagePack instead, with
+//! `Content-Type: application/msgpack`. Error bodies are converted too, so a
+//! client only needs one decoder. Other responses (file downloads, SSE,
+//! WebDAV) pass through, and without the header nothing changes.
+//!
+//! The [`negotiate`] middleware wraps the whole router, so the relay's
+//! `/d/{serial}/...` proxies answer the same way.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+/// Media type sent back for converted responses.
+pub const CONTENT_TYPE: &str = "application/msgpack";
+
+/// Whether `Accept` asks for MessagePack.
+pub fn wants_msgpack(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media = parts.next().unwrap_or_default();
+            let refused = parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            !refused
+                && (media.eq_ignore_ascii_case(CONTENT_TYPE)
+                    || media.eq_ignore_ascii_case("application/x-msgpack"))
+        })
+}
+
+/// Convert JSON responses to MessagePack when the request asks for it.
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let wanted = wants_msgpack(request.headers());
+    let response = next.run(request).await;
+    if !wanted || !is_json(response.headers()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let encoded = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|value| rmp_serde::to_vec_named(&value).ok());
+    let Some(encoded) = encoded else {
+        warn!("MessagePack: response is not valid JSON, sent as is");
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Json, Router};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn get_with(accept: Option<&str>) -> Response {
+        let app = Router::new()
+            .route(
+                "/list",
+                get(|| async { Json(json!({"entries": [{"id": 1, "name": "a"}]})) }),
+            )
+            .layer(middleware::from_fn(negotiate));
+        let mut request = Request::get("/list");
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_is_converted_only_when_asked() {
+        let response = get_with(Some("application/msgpack, application/json;q=0.5")).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CONTENT_TYPE);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value: Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(value["entries"][0]["name"], "a");
+
+        let response = get_with(None).await;
+        assert!(is_json(response.headers()));
+        let response = get_with(Some("application/msgpack;q=0")).await;
+        assert!(is_json(response.headers()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/X-MsgPack"),
+        );
+        assert!(wants_msgpack(&headers));
+    }
+}
@@ -0,0 +1,600 @@
+//! Device-specific plugins: executables dropped into `plugins.d/`.
+//!
+//! Integrators add capabilities sctl doesn't ship — camera control, relay
+//! boards, vendor tooling — by placing an executable in `[plugins] dir`
+//! (default `<data_dir>/plugins.d`). The file name up to the first `.` is
+//! the plugin name and must be `[A-Za-z0-9_-]+`. Dotfiles and files without
+//! an execute bit are ignored; files writable by group or others are listed
+//! with an error but never run, since a plugin runs with sctl's privileges.
+//!
+//! Each invocation is one JSON request on stdin (stdin is then closed) and
+//! one JSON reply on stdout. The process is killed after `timeout_secs`.
+//!
+//! - `{"command": "describe"}` → `{"description"?, "commands": [{"name",
+//!   "description"?}]}`. Run when a plugin first appears and again whenever
+//!   its file changes; a plugin whose describe fails can't be invoked.
+//! - `{"command": "<name>", "args": <any>}` → any JSON value, returned to
+//!   the caller as `result`. A non-zero exit is a failure whose message is
+//!   the reply's `error` string if there is one, else the last stderr line.
+//!
+//! `SCTL_PLUGIN_COMMAND` and `SCTL_DEVICE_SERIAL` are set in the plugin's
+//! environment. The directory is rescanned on every request, so plugins can
+//! be added, replaced, or removed without restarting sctl.
+//!
+//! Commands are reachable as `POST /api/plugins/{name}/{command}` (see
+//! [`crate::routes::plugins`]) and as `plugin.<name>.<command>` tunnel
+//! messages carrying `args`, which reply with `<type>.result`. They are not
+//! offered on `/api/ws`, whose `sessions` scope doesn't cover them.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+
+use crate::config::PluginsConfig;
+use crate::error::{codes, ApiError};
+
+/// Command name reserved for discovery.
+const DESCRIBE: &str = "describe";
+
+/// Stderr bytes kept for error replies.
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// One command a plugin offers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginCommand {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A discovered plugin, as reported by `GET /api/plugins`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub commands: Vec<PluginCommand>,
+    /// Why the plugin can't be run (rejected file or failed describe).
+    /// Cleared once the file changes and describes cleanly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What a plugin's `describe` reply must look like.
+#[derive(Deserialize)]
+struct Description {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    commands: Vec<PluginCommand>,
+}
+
+/// An executable found by [`scan`].
+struct Found {
+    name: String,
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+    /// Set when the file must not be run.
+    rejected: Option<String>,
+}
+
+/// A plugin as of its last describe.
+struct Cached {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+    info: PluginInfo,
+}
+
+/// Why a plugin invocation failed.
+#[derive(Debug)]
+pub enum PluginError {
+    /// No such plugin, or the plugin has no such command.
+    NotFound(String),
+    /// The plugin exists but was rejected or its describe failed.
+    Unavailable { plugin: String, reason: String },
+    /// The plugin ran past `timeout_secs` and was killed.
+    Timeout(u64),
+    /// Spawn failure, non-zero exit, or an oversized or non-JSON reply.
+    Failed {
+        message: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl PluginError {
+    /// Map to the REST error shape.
+    pub fn into_response(self) -> (StatusCode, Json<ApiError>) {
+        match self {
+            Self::NotFound(message) => {
+                ApiError::new(codes::NOT_FOUND, message).into_response_with(StatusCode::NOT_FOUND)
+            }
+            Self::Unavailable { plugin, reason } => ApiError::new(
+                codes::PLUGIN_FAILED,
+                format!("Plugin '{plugin}' is unavailable: {reason}"),
+            )
+            .into_response_with(StatusCode::BAD_GATEWAY),
+            Self::Timeout(secs) => ApiError::new(
+                codes::TIMEOUT,
+                format!("Plugin did not finish within {secs}s"),
+            )
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT),
+            Self::Failed {
+                message,
+                exit_code,
+                stderr,
+            } => ApiError::new(codes::PLUGIN_FAILED, message)
+                .with_detail(json!({ "exit_code": exit_code, "stderr": stderr }))
+                .into_response_with(StatusCode::BAD_GATEWAY),
+        }
+    }
+
+    fn failed(message: impl Into<String>) -> Self {
+        Self::Failed {
+            message: message.into(),
+            exit_code: None,
+            stderr: String::new(),
+        }
+    }
+
+    /// Short human-readable reason, for logs and the activity journal.
+    pub fn message(&self) -> String {
+        match self {
+            Self::NotFound(message) | Self::Failed { message, .. } => message.clone(),
+            Self::Unavailable { reason, .. } => reason.clone(),
+            Self::Timeout(secs) => format!("timed out after {secs}s"),
+        }
+    }
+}
+
+/// The plugin directory and what was last discovered in it.
+pub struct Plugins {
+    config: PluginsConfig,
+    dir: PathBuf,
+    serial: String,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl Plugins {
+    pub fn new(config: PluginsConfig, data_dir: &str, serial: &str) -> Self {
+        let dir = config
+            .dir
+            .as_ref()
+            .map_or_else(|| Path::new(data_dir).join("plugins.d"), PathBuf::from);
+        Self {
+            config,
+            dir,
+            serial: serial.to_string(),
+            cache: Mutex::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// All plugins currently in the directory, sorted by name.
+    pub async fn list(&self) -> Vec<PluginInfo> {
+        let mut list: Vec<PluginInfo> = self
+            .refresh()
+            .await
+            .values()
+            .map(|c| c.info.clone())
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// One plugin by name.
+    pub async fn get(&self, name: &str) -> Option<PluginInfo> {
+        self.refresh().await.get(name).map(|c| c.info.clone())
+    }
+
+    /// Run `command` of plugin `name` and return its reply.
+    ///
+    /// # Errors
+    ///
+    /// See [`PluginError`].
+    pub async fn run(&self, name: &str, command: &str, args: Value) -> Result<Value, PluginError> {
+        let path = {
+            let cache = self.refresh().await;
+            let Some(plugin) = cache.get(name) else {
+                return Err(PluginError::NotFound(format!("No plugin named '{name}'")));
+            };
+            if let Some(reason) = &plugin.info.error {
+                return Err(PluginError::Unavailable {
+                    plugin: name.to_string(),
+                    reason: reason.clone(),
+                });
+            }
+            if !plugin.info.commands.iter().any(|c| c.name == command) {
+                return Err(PluginError::NotFound(format!(
+                    "Plugin '{name}' has no command '{command}'"
+                )));
+            }
+            plugin.path.clone()
+        };
+        self.invoke(&path, command, &json!({ "command": command, "args": args }))
+            .await
+    }
+
+    /// Rescan the directory, describing new and changed plugins.
+    async fn refresh(&self) -> MutexGuard<'_, HashMap<String, Cached>> {
+        let dir = self.dir.clone();
+        let found = crate::io_pool::run(move || scan(&dir))
+            .await
+            .unwrap_or_else(|e| {
+                warn!(dir = %self.dir.display(), "Plugin directory scan failed: {e}");
+                Vec::new()
+            });
+        let mut cache = self.cache.lock().await;
+        cache.retain(|name, _| found.iter().any(|f| &f.name == name));
+        for f in found {
+            let unchanged = cache
+                .get(&f.name)
+                .is_some_and(|c| c.path == f.path && c.modified == f.modified && c.len == f.len);
+            if unchanged {
+                continue;
+            }
+            let mut info = PluginInfo {
+                name: f.name.clone(),
+                path: f.path.display().to_string(),
+                description: None,
+                commands: Vec::new(),
+                error: None,
+            };
+            if let Some(reason) = f.rejected {
+                warn!(plugin = %f.name, "Plugin not loaded: {reason}");
+                info.error = Some(reason);
+            } else {
+                match self.describe(&f.path).await {
+                    Ok(desc) => {
+                        info!(
+                            plugin = %f.name,
+                            commands = desc.commands.len(),
+                            "Plugin loaded"
+                        );
+                        info.description = desc.description;
+                        info.commands = desc.commands;
+                    }
+                    Err(e) => {
+                        let reason = format!("describe failed: {}", e.message());
+                        warn!(plugin = %f.name, "Plugin not loaded: {reason}");
+                        info.error = Some(reason);
+                    }
+                }
+            }
+            cache.insert(
+                f.name,
+                Cached {
+                    path: f.path,
+                    modified: f.modified,
+                    len: f.len,
+                    info,
+                },
+            );
+        }
+        cache
+    }
+
+    async fn describe(&self, path: &Path) -> Result<Description, PluginError> {
+        let reply = self
+            .invoke(path, DESCRIBE, &json!({ "command": DESCRIBE }))
+            .await?;
+        let mut desc: Description = serde_json::from_value(reply)
+            .map_err(|e| PluginError::failed(format!("invalid describe reply: {e}")))?;
+        desc.commands
+            .retain(|c| valid_name(&c.name) && c.name != DESCRIBE);
+        Ok(desc)
+    }
+
+    /// Spawn the plugin, feed it `request`, and parse its stdout as JSON.
+    async fn invoke(
+        &self,
+        path: &Path,
+        command: &str,
+        request: &Value,
+    ) -> Result<Value, PluginError> {
+        let mut child = tokio::process::Command::new(path)
+            .env("SCTL_PLUGIN_COMMAND", command)
+            .env("SCTL_DEVICE_SERIAL", &self.serial)
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| PluginError::failed(format!("failed to start plugin: {e}")))?;
+        let (Some(mut stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(PluginError::failed("plugin stdio unavailable"));
+        };
+        let input = format!("{request}\n").into_bytes();
+        // Feed stdin concurrently so a plugin that writes before reading all
+        // of its input can't deadlock against us; dropping it sends EOF.
+        let writer = tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+
+        let cap = self.config.max_output_bytes;
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let outcome = tokio::time::timeout(timeout, async {
+            let mut out = Vec::new();
+            let mut stdout = stdout.take(cap as u64 + 1);
+            let (read, err) = tokio::join!(
+                stdout.read_to_end(&mut out),
+                read_tail(stderr, STDERR_TAIL_BYTES),
+            );
+            read?;
+            if out.len() > cap {
+                return Ok((None, out, err));
+            }
+            let status = child.wait().await?;
+            Ok::<_, io::Error>((Some(status), out, err))
+        })
+        .await;
+        writer.abort();
+
+        let (status, out, err) = match outcome {
+            Err(_) => return Err(PluginError::Timeout(self.config.timeout_secs)),
+            Ok(Err(e)) => return Err(PluginError::failed(format!("plugin I/O failed: {e}"))),
+            Ok(Ok(done)) => done,
+        };
+        let stderr = String::from_utf8_lossy(&err).into_owned();
+        let Some(status) = status else {
+            return Err(PluginError::Failed {
+                message: format!("plugin reply exceeds {cap} bytes"),
+                exit_code: None,
+                stderr,
+            });
+        };
+        let reply = serde_json::from_slice::<Value>(&out);
+        if !status.success() {
+            let message = reply
+                .ok()
+                .and_then(|r| r["error"].as_str().map(ToString::to_string))
+                .or_else(|| stderr.lines().last().map(ToString::to_string))
+                .unwrap_or_else(|| "plugin failed".to_string());
+            return Err(PluginError::Failed {
+                message,
+                exit_code: status.code(),
+                stderr,
+            });
+        }
+        reply.map_err(|e| PluginError::Failed {
+            message: format!("plugin reply is not JSON: {e}"),
+            exit_code: status.code(),
+            stderr,
+        })
+    }
+}
+
+/// Plugin and command names: non-empty `[A-Za-z0-9_-]+`, so they can be
+/// joined with `.` into a message type and split back unambiguously.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Split `plugin.<name>.<command>` into its parts.
+pub fn parse_message_type(msg_type: &str) -> Option<(&str, &str)> {
+    let (name, command) = msg_type.strip_prefix("plugin.")?.split_once('.')?;
+    (valid_name(name) && valid_name(command)).then_some((name, command))
+}
+
+/// List the executables in `dir`. A missing directory has no plugins.
+fn scan(dir: &Path) -> io::Result<Vec<Found>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+
+    let mut found: Vec<Found> = Vec::new();
+    for path in paths {
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let Ok(meta) = std::fs::metadata(&path) else {
+            continue;
+        };
+        let mode = crate::util::file_mode(&meta);
+        if !meta.is_file() || !is_executable(&path, mode) {
+            continue;
+        }
+        let name = file_name.split('.').next().unwrap_or_default().to_string();
+        let rejected = if !valid_name(&name) {
+            Some("name must be [A-Za-z0-9_-]+".to_string())
+        } else if found.iter().any(|f| f.name == name) {
+            // Sorted, so the first file with this name keeps it.
+            continue;
+        } else if mode & 0o022 != 0 {
+            Some("file is writable by group or others".to_string())
+        } else {
+            None
+        };
+        found.push(Found {
+            name,
+            path,
+            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            len: meta.len(),
+            rejected,
+        });
+    }
+    Ok(found)
+}
+
+/// Unix needs an execute bit; Windows goes by the extension instead.
+#[cfg(unix)]
+fn is_executable(_path: &Path, mode: u32) -> bool {
+    mode & 0o111 != 0
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path, _mode: u32) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+        ["exe", "bat", "cmd", "com"]
+            .iter()
+            .any(|x| e.eq_ignore_ascii_case(x))
+    })
+}
+
+/// Read `r` to EOF, keeping only the last `keep` bytes.
+async fn read_tail(mut r: impl AsyncRead + Unpin, keep: usize) -> Vec<u8> {
+    let mut tail = Vec::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(n) = r.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > keep {
+            tail.drain(..tail.len() - keep);
+        }
+    }
+    tail
+}
+
+// The test plugins are shell scripts.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sctl-plugins-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_plugin(dir: &Path, file: &str, mode: u32, script: &str) {
+        let path = dir.join(file);
+        std::fs::write(&path, script).unwrap();
+        crate::util::set_mode(&path, mode).unwrap();
+    }
+
+    fn plugins(dir: &Path, timeout_secs: u64) -> Plugins {
+        let config = PluginsConfig {
+            dir: Some(dir.display().to_string()),
+            timeout_secs,
+            ..PluginsConfig::default()
+        };
+        Plugins::new(config, "/nonexistent", "TEST-1")
+    }
+
+    const RELAY: &str = r#"#!/bin/sh
+read req
+case "$SCTL_PLUGIN_COMMAND" in
+  describe) echo '{"description":"Relay board","commands":[{"name":"toggle"},{"name":"bad.name"}]}' ;;
+  toggle) echo "{\"serial\":\"$SCTL_DEVICE_SERIAL\",\"req\":$req}" ;;
+esac
+"#;
+
+    #[test]
+    fn message_types_split_into_plugin_and_command() {
+        assert_eq!(
+            parse_message_type("plugin.relay-board.toggle"),
+            Some(("relay-board", "toggle"))
+        );
+        assert_eq!(parse_message_type("plugin.relay"), None);
+        assert_eq!(parse_message_type("plugin.a.b.c"), None);
+        assert_eq!(parse_message_type("session.start"), None);
+    }
+
+    #[tokio::test]
+    async fn describes_and_runs_plugins() {
+        let dir = temp_dir("run");
+        write_plugin(&dir, "relay.sh", 0o755, RELAY);
+        write_plugin(&dir, "README", 0o644, "not a plugin");
+        write_plugin(&dir, "open", 0o777, RELAY);
+        let plugins = plugins(&dir, 5);
+
+        let list = plugins.list().await;
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name, "open");
+        assert!(list[0].error.is_some());
+        assert_eq!(list[1].name, "relay");
+        assert_eq!(list[1].description.as_deref(), Some("Relay board"));
+        let commands: Vec<_> = list[1].commands.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(commands, ["toggle"]);
+
+        let reply = plugins
+            .run("relay", "toggle", json!({ "channel": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(reply["serial"], "TEST-1");
+        assert_eq!(reply["req"]["command"], "toggle");
+        assert_eq!(reply["req"]["args"]["channel"], 2);
+
+        assert!(matches!(
+            plugins.run("relay", "describe", Value::Null).await,
+            Err(PluginError::NotFound(_))
+        ));
+        assert!(matches!(
+            plugins.run("open", "toggle", Value::Null).await,
+            Err(PluginError::Unavailable { .. })
+        ));
+
+        std::fs::remove_file(dir.join("relay.sh")).unwrap();
+        assert!(plugins.get("relay").await.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn reports_failures_and_timeouts() {
+        let dir = temp_dir("fail");
+        write_plugin(
+            &dir,
+            "cam",
+            0o755,
+            r#"#!/bin/sh
+read req
+case "$SCTL_PLUGIN_COMMAND" in
+  describe) echo '{"commands":[{"name":"snap"},{"name":"hang"}]}' ;;
+  snap) echo 'sensor offline' >&2; echo '{"error":"no camera"}'; exit 3 ;;
+  hang) sleep 10 ;;
+esac
+"#,
+        );
+        let plugins = plugins(&dir, 1);
+
+        match plugins.run("cam", "snap", Value::Null).await {
+            Err(PluginError::Failed {
+                message,
+                exit_code,
+                stderr,
+            }) => {
+                assert_eq!(message, "no camera");
+                assert_eq!(exit_code, Some(3));
+                assert_eq!(stderr.trim(), "sensor offline");
+            }
+            other => panic!("expected failure, got {other:?}"),
+        }
+        assert!(matches!(
+            plugins.run("cam", "hang", Value::Null).await,
+            Err(PluginError::Timeout(1))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,293 @@
+//! Quiet hours for bandwidth-heavy work.
+//!
+//! On a metered or shared uplink, bulk data should move off-peak. With
+//! `[quiet_hours]` configured, gawdxfer transfers and artifact pushes are
+//! held back inside the configured windows:
+//!
+//! - **defer** — transfer chunks are refused with a recoverable
+//!   `QUIET_HOURS` error (the transfer's phase reads `deferred`), and
+//!   `POST /api/artifacts/push` queues the upload and starts it once the
+//!   window ends. Pushes marked `urgent` run anyway.
+//! - **throttle** — everything runs, but transfers together get an extra
+//!   `rate_limit_bps` cap on top of their own, and each push that starts
+//!   inside a window is capped to it.
+//!
+//! Windows are `HH:MM` ranges in local time (or UTC with `utc = true`),
+//! optionally limited to days of the week. A window whose end is before its
+//! start runs past midnight and belongs to the day it starts on.
+//!
+//! The queue lives in memory: pushes still waiting when sctl restarts are
+//! dropped. `GET /api/quiet-hours` lists them, along with the deferred
+//! transfers; `GET /api/health` carries a summary.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::{QuietHoursConfig, QuietMode};
+use crate::infra::now_epoch;
+
+/// Longest sleep between checks while waiting for a window to end, so a
+/// changed clock or timezone is noticed.
+const RECHECK: Duration = Duration::from_secs(60);
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A parsed window: minutes of the day and the days it starts on
+/// (bit 0 = Monday).
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: u32,
+    end: u32,
+    days: u8,
+}
+
+impl Window {
+    /// Minutes left in the window at `minute` of `weekday`, if inside it.
+    fn remaining(&self, weekday: u32, minute: u32) -> Option<u32> {
+        let starts_on = |day: u32| self.days & (1 << day) != 0;
+        let yesterday = (weekday + 6) % 7;
+        if self.start < self.end {
+            (starts_on(weekday) && (self.start..self.end).contains(&minute))
+                .then(|| self.end - minute)
+        } else if starts_on(weekday) && minute >= self.start {
+            // Started today, ends tomorrow (or a whole day when start == end).
+            Some(1440 - minute + self.end)
+        } else if starts_on(yesterday) && minute < self.end {
+            Some(self.end - minute)
+        } else {
+            None
+        }
+    }
+}
+
+/// An artifact push waiting for the window to end.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedPush {
+    pub id: u64,
+    pub path: String,
+    pub key: String,
+    /// Unix ms.
+    pub queued_at: u64,
+}
+
+/// Compiled `[quiet_hours]` plus the queue of deferred pushes.
+pub struct QuietHours {
+    config: QuietHoursConfig,
+    windows: Vec<Window>,
+    queue: Mutex<Vec<QueuedPush>>,
+    next_id: AtomicU64,
+}
+
+impl QuietHours {
+    /// Parse the windows in `config`.
+    pub fn new(config: QuietHoursConfig) -> Result<Self, String> {
+        let windows = config
+            .windows
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let at = |what: &str, v: &str| {
+                    parse_hhmm(v).ok_or_else(|| format!("windows[{i}].{what} '{v}' is not HH:MM"))
+                };
+                let mut days = 0u8;
+                for day in &w.days {
+                    let n = DAYS
+                        .iter()
+                        .position(|d| d.eq_ignore_ascii_case(day))
+                        .ok_or_else(|| format!("windows[{i}].days: unknown day '{day}'"))?;
+                    days |= 1 << n;
+                }
+                Ok(Window {
+                    start: at("start", &w.start)?,
+                    end: at("end", &w.end)?,
+                    days: if days == 0 { 0x7f } else { days },
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if windows.is_empty() {
+            return Err("at least one window is required".to_string());
+        }
+        Ok(Self {
+            config,
+            windows,
+            queue: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// When the current quiet period ends (unix secs), if one is running.
+    pub fn active(&self) -> Option<u64> {
+        let now = now_epoch();
+        let (weekday, minute) = clock(now, self.config.utc);
+        let left = self
+            .windows
+            .iter()
+            .filter_map(|w| w.remaining(weekday, minute))
+            .max()?;
+        // Whole minutes, counted from the start of the current one.
+        Some(now - now % 60 + u64::from(left) * 60)
+    }
+
+    /// End of the current quiet period when it defers work.
+    pub fn deferring(&self) -> Option<u64> {
+        (self.config.mode == QuietMode::Defer)
+            .then(|| self.active())
+            .flatten()
+    }
+
+    /// The shared cap while a throttling quiet period is running.
+    pub fn throttle_bps(&self) -> Option<u64> {
+        (self.config.mode == QuietMode::Throttle && self.active().is_some())
+            .then_some(self.config.rate_limit_bps)
+    }
+
+    /// `rate_limit_bps` in throttle mode, for the transfer manager's bucket.
+    pub fn rate_limit_bps(&self) -> u64 {
+        match self.config.mode {
+            QuietMode::Throttle => self.config.rate_limit_bps,
+            QuietMode::Defer => 0,
+        }
+    }
+
+    /// Sleep until no deferring window is running.
+    pub async fn wait_until_clear(&self) {
+        while let Some(until) = self.deferring() {
+            let left = Duration::from_secs(until.saturating_sub(now_epoch()).max(1));
+            tokio::time::sleep(left.min(RECHECK)).await;
+        }
+    }
+
+    /// Record a deferred push; returns its queue id.
+    pub fn enqueue(&self, path: &str, key: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock_queue().push(QueuedPush {
+            id,
+            path: path.to_string(),
+            key: key.to_string(),
+            queued_at: now_epoch() * 1000,
+        });
+        id
+    }
+
+    /// Drop a push from the queue once it starts.
+    pub fn dequeue(&self, id: u64) {
+        self.lock_queue().retain(|p| p.id != id);
+    }
+
+    /// Pushes waiting for the window to end.
+    pub fn queued(&self) -> Vec<QueuedPush> {
+        self.lock_queue().clone()
+    }
+
+    /// Summary for `GET /api/health`.
+    pub fn summary(&self) -> Value {
+        let until = self.active();
+        json!({
+            "active": until.is_some(),
+            "mode": self.config.mode,
+            "until": until,
+            "queued_pushes": self.lock_queue().len(),
+        })
+    }
+
+    /// The configuration as given, for `GET /api/quiet-hours`.
+    pub fn config(&self) -> &QuietHoursConfig {
+        &self.config
+    }
+
+    fn lock_queue(&self) -> std::sync::MutexGuard<'_, Vec<QueuedPush>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `HH:MM` to minutes of the day.
+fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60 && s.len() == 5).then_some(h * 60 + m)
+}
+
+/// `(weekday with Monday = 0, minute of the day)` at `unix_secs`.
+fn clock(unix_secs: u64, utc: bool) -> (u32, u32) {
+    if !utc {
+        if let Ok(t) = libc::time_t::try_from(unix_secs) {
+            // SAFETY: `tm` is plain data, and both pointers are valid for
+            // the call; localtime_r/localtime_s don't keep them.
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            #[cfg(unix)]
+            let ok = !unsafe { libc::localtime_r(&raw const t, &raw mut tm) }.is_null();
+            #[cfg(windows)]
+            let ok = unsafe { libc::localtime_s(&raw mut tm, &raw const t) } == 0;
+            if ok {
+                #[allow(clippy::cast_sign_loss)]
+                return (
+                    ((tm.tm_wday + 6) % 7) as u32,
+                    (tm.tm_hour * 60 + tm.tm_min) as u32,
+                );
+            }
+        }
+    }
+    // 1970-01-01 was a Thursday.
+    (
+        ((unix_secs / 86_400 + 3) % 7) as u32,
+        ((unix_secs % 86_400) / 60) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuietWindowConfig;
+
+    fn quiet(windows: &[(&str, &str, &[&str])]) -> Result<QuietHours, String> {
+        QuietHours::new(QuietHoursConfig {
+            windows: windows
+                .iter()
+                .map(|(start, end, days)| QuietWindowConfig {
+                    start: (*start).to_string(),
+                    end: (*end).to_string(),
+                    days: days.iter().map(|d| (*d).to_string()).collect(),
+                })
+                .collect(),
+            mode: QuietMode::Defer,
+            rate_limit_bps: 0,
+            utc: true,
+        })
+    }
+
+    #[test]
+    fn windows_cover_days_and_midnight() {
+        // Weekdays 07:00-19:00, plus Friday 22:00 to Saturday 06:00.
+        let q = quiet(&[
+            ("07:00", "19:00", &["mon", "tue", "wed", "thu", "fri"]),
+            ("22:00", "06:00", &["fri"]),
+        ])
+        .unwrap();
+        let left = |day: u32, hhmm: &str| {
+            let minute = parse_hhmm(hhmm).unwrap();
+            q.windows
+                .iter()
+                .filter_map(|w| w.remaining(day, minute))
+                .max()
+        };
+        assert_eq!(left(0, "06:59"), None);
+        assert_eq!(left(0, "07:00"), Some(720));
+        assert_eq!(left(2, "18:30"), Some(30));
+        assert_eq!(left(5, "12:00"), None);
+        assert_eq!(left(4, "23:00"), Some(420));
+        assert_eq!(left(5, "05:00"), Some(60));
+        assert_eq!(left(6, "05:00"), None);
+
+        assert!(quiet(&[("7:00", "19:00", &[])]).is_err());
+        assert!(quiet(&[("07:00", "24:00", &[])]).is_err());
+        assert!(quiet(&[("07:00", "19:00", &["someday"])]).is_err());
+        assert!(quiet(&[]).is_err());
+
+        assert_eq!(clock(0, true), (3, 0));
+        assert_eq!(clock(86_400 * 4 + 3_600, true), (0, 60));
+    }
+}
@@ -1,16 +1,26 @@
-//! Activity journal endpoint.
+//! Activity journal endpoints.
 //!
-//! `GET /api/activity?since_id=N&limit=N&activity_type=exec&source=mcp&session_id=abc`
-//! — returns recent activity entries with optional filtering.
+//! `GET /api/activity?since_id=N&limit=N&activity_type=exec&source=mcp&session_id=abc&risk=privileged,destructive&from=MS&to=MS`
+//! — returns activity entries with optional filtering, reaching into the
+//! on-disk journal when one is configured. `&fields=id,summary` trims each
+//! entry.
+//!
+//! `GET /api/activity/export?format=ndjson|csv&from=MS&to=MS&gzip=true`
+//! — streams every retained entry in a format log shippers can ingest.
 
+use axum::body::{Body, Bytes};
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
 use axum::Json;
+use futures::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
 
-use crate::activity::{ActivitySource, ActivityType};
+use crate::activity::{self, ActivityEntry, ActivityFilter, ActivitySource, ActivityType};
 use crate::error::{codes, ApiError};
+use crate::shell::classify::Risk;
 use crate::AppState;
 
 /// Query parameters for `GET /api/activity`.
@@ -28,6 +38,15 @@ pub struct ActivityQuery {
     pub source: Option<String>,
     /// Filter by session ID (matches `detail.session_id`).
     pub session_id: Option<String>,
+    /// Filter by risk level, comma-separated (matches `detail.risk`, e.g.
+    /// `privileged,destructive`).
+    pub risk: Option<String>,
+    /// Only entries at or after this epoch-ms timestamp.
+    pub from: Option<u64>,
+    /// Only entries at or before this epoch-ms timestamp.
+    pub to: Option<u64>,
+    /// Comma-separated fields to keep in each entry (e.g. `id,summary,detail.risk`).
+    pub fields: Option<String>,
 }
 
 fn default_limit() -> usize {
@@ -40,26 +59,37 @@ pub async fn get_activity(
     Query(query): Query<ActivityQuery>,
 ) -> Json<Value> {
     let limit = query.limit.min(200);
-    let activity_type = query
-        .activity_type
-        .as_deref()
-        .and_then(ActivityType::from_str_opt);
-    let source = query
-        .source
-        .as_deref()
-        .and_then(ActivitySource::from_str_opt);
+    let filter = ActivityFilter {
+        activity_type: query
+            .activity_type
+            .as_deref()
+            .and_then(ActivityType::from_str_opt),
+        source: query
+            .source
+            .as_deref()
+            .and_then(ActivitySource::from_str_opt),
+        session_id: query.session_id,
+        risk: query.risk.as_deref().map(|r| {
+            r.split(',')
+                .filter_map(|s| Risk::from_str_opt(s.trim()))
+                .collect()
+        }),
+        from: query.from,
+        to: query.to,
+    };
 
     let entries = state
         .activity_log
-        .read_since_filtered(
-            query.since_id,
-            limit,
-            activity_type,
-            source,
-            query.session_id.as_deref(),
-        )
+        .read_since_filtered(query.since_id, limit, &filter)
         .await;
-    Json(json!({ "entries": entries }))
+    // A full page may have more behind it; page on with `since_id=next_since_id`.
+    let next_since_id = entries
+        .last()
+        .filter(|_| entries.len() == limit)
+        .map(|e| e.id);
+    let mut entries = serde_json::to_value(entries).unwrap_or_default();
+    crate::util::project_fields(&mut entries, query.fields.as_deref());
+    Json(json!({ "entries": entries, "next_since_id": next_since_id }))
 }
 
 /// `GET /api/activity/{id}/result` — retrieve a cached full exec result.
@@ -78,3 +108,211 @@ pub async fn get_exec_result(
         ),
     }
 }
+
+// ─── Export ──────────────────────────────────────────────────────────────────
+
+/// Query parameters for `GET /api/activity/export`.
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// `ndjson` (default) or `csv`.
+    #[serde(default = "default_format")]
+    pub format: String,
+    /// Only entries at or after this epoch-ms timestamp.
+    pub from: Option<u64>,
+    /// Only entries at or before this epoch-ms timestamp.
+    pub to: Option<u64>,
+    /// Compress the body with gzip.
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+fn default_format() -> String {
+    "ndjson".to_string()
+}
+
+/// Column order for CSV exports.
+const CSV_HEADER: &str = "id,timestamp,activity_type,source,summary,request_id,detail\n";
+
+/// Quote a CSV field if it contains a delimiter, quote or line break (RFC 4180).
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Serde name of an enum value, e.g. `"file_read"`.
+fn enum_name<T: serde::Serialize>(v: T) -> String {
+    serde_json::to_value(v)
+        .ok()
+        .and_then(|v| v.as_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
+/// One CSV row, `detail` as compact JSON.
+fn csv_line(e: &ActivityEntry) -> String {
+    let detail = e.detail.as_ref().map(Value::to_string).unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{},{}\n",
+        e.id,
+        e.timestamp,
+        enum_name(e.activity_type),
+        enum_name(e.source),
+        csv_field(&e.summary),
+        csv_field(e.request_id.as_deref().unwrap_or("")),
+        csv_field(&detail),
+    )
+}
+
+/// One NDJSON line.
+fn ndjson_line(e: &ActivityEntry) -> String {
+    let mut line = serde_json::to_string(e).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// `GET /api/activity/export` — stream retained activity as NDJSON or CSV.
+///
+/// Exports what the in-memory ring (the last `activity_log_max_entries`) and
+/// the on-disk journal, if configured, still hold, filtered by `from`/`to`. An `activity_export`
+/// entry is logged once the body has been fully sent. With `gzip=true` the body
+/// is piped through the system `gzip`.
+pub async fn export_activity(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let (line_fn, header, content_type, ext): (fn(&ActivityEntry) -> String, _, _, _) =
+        match query.format.as_str() {
+            "ndjson" => (ndjson_line, None, "application/x-ndjson", "ndjson"),
+            "csv" => (csv_line, Some(CSV_HEADER), "text/csv", "csv"),
+            other => {
+                return Err(ApiError::new(
+                    codes::INVALID_REQUEST,
+                    format!("Unknown format '{other}' (expected ndjson or csv)"),
+                )
+                .into_response_with(StatusCode::BAD_REQUEST));
+            }
+        };
+
+    let entries = state.activity_log.read_range(query.from, query.to).await;
+    let count = entries.len();
+    let source = activity::source_from_headers(&headers);
+    let req_id = activity::request_id_from_headers(&headers);
+    let format = query.format.clone();
+    let gzip = query.gzip;
+    let log_state = state.clone();
+
+    let lines = futures::stream::iter(header.map(ToString::to_string))
+        .chain(futures::stream::iter(entries).map(move |e| line_fn(&e)))
+        .map(|line| Ok::<_, std::io::Error>(Bytes::from(line)))
+        // Log only after the last line has been handed to the client.
+        .chain(futures::stream::once(async move {
+            log_state
+                .activity_log
+                .log(
+                    ActivityType::ActivityExport,
+                    source,
+                    format!("Exported {count} activity entries ({format})"),
+                    Some(json!({"format": format, "count": count, "gzip": gzip})),
+                    req_id,
+                )
+                .await;
+            Ok(Bytes::new())
+        }));
+
+    let mut filename = format!("sctl-activity-{}.{ext}", state.config.device.serial);
+    let (body, content_type) = if gzip {
+        filename.push_str(".gz");
+        (gzip_body(Box::pin(lines))?, "application/gzip")
+    } else {
+        (Body::from_stream(lines), content_type)
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename.replace('"', "_")),
+        )
+        .body(body)
+        .unwrap())
+}
+
+/// Pipe a byte stream through `gzip -c` and return its output as a body.
+fn gzip_body(
+    mut input: impl futures::Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin + 'static,
+) -> Result<Body, (StatusCode, Json<ApiError>)> {
+    let mut child = tokio::process::Command::new("gzip")
+        .arg("-c")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            ApiError::new(codes::EXEC_FAILED, format!("Failed to start gzip: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(ApiError::new(codes::EXEC_FAILED, "gzip pipes unavailable")
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+    };
+
+    tokio::spawn(async move {
+        while let Some(Ok(chunk)) = input.next().await {
+            if stdin.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+        drop(stdin);
+        let _ = child.wait().await;
+    });
+
+    Ok(Body::from_stream(tokio_util::io::ReaderStream::new(stdout)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(summary: &str, detail: Option<Value>) -> ActivityEntry {
+        ActivityEntry {
+            id: 7,
+            timestamp: 1_760_000_000_000,
+            activity_type: ActivityType::FileRead,
+            source: ActivitySource::Rest,
+            summary: summary.to_string(),
+            detail,
+            request_id: None,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn csv_quotes_only_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_line_uses_serde_names_and_json_detail() {
+        let line = csv_line(&entry("/etc/hosts", Some(json!({"size": 1}))));
+        assert_eq!(
+            line,
+            "7,1760000000000,file_read,rest,/etc/hosts,,\"{\"\"size\"\":1}\"\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_line_is_one_json_object() {
+        let line = ndjson_line(&entry("x", None));
+        assert!(line.ends_with('\n'));
+        let v: Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(v["activity_type"], "file_read");
+        assert_eq!(v["id"], 7);
+    }
+}
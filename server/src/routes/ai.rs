@@ -0,0 +1,98 @@
+//! AI kill-switch and budget endpoints (see [`crate::ai_guard`]).
+//!
+//! - `GET /api/ai` — kill-switch state, limits and per-source usage
+//! - `POST /api/ai/disable` — block AI-sourced mutations device-wide
+//! - `POST /api/ai/enable` — lift the block (not callable by AI sources)
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{self, request_id_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+/// Request body for `POST /api/ai/disable`.
+#[derive(Deserialize)]
+pub struct DisableRequest {
+    /// Shown in `GET /api/ai`, `AI_DISABLED` errors and the `ai.disabled` event.
+    pub reason: Option<String>,
+}
+
+/// `GET /api/ai` — kill-switch, limits and usage in the current window.
+pub async fn status(State(state): State<AppState>) -> Json<Value> {
+    Json(state.ai_guard.status())
+}
+
+/// `POST /api/ai/disable` — turn the kill-switch on.
+///
+/// AI sources may pull the brake on themselves; once it is on, the
+/// middleware refuses their further POSTs. Calling it again replaces the
+/// reason.
+pub async fn disable(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DisableRequest>,
+) -> Json<Value> {
+    let by = client_name(&headers);
+    let ks = state.ai_guard.disable(payload.reason, &by).await;
+    state
+        .activity_log
+        .log(
+            ActivityType::AiKillSwitch,
+            activity::source_from_headers(&headers),
+            "AI disabled".to_string(),
+            Some(json!({ "disabled": true, "reason": ks.reason })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    Json(json!({ "disabled": ks }))
+}
+
+/// `POST /api/ai/enable` — turn the kill-switch off.
+///
+/// # Errors
+///
+/// - `403 Forbidden` with `{"code":"AI_DISABLED"}` — called by an AI source
+pub async fn enable(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, (StatusCode, Json<ApiError>)> {
+    if let Some(source) = state.ai_guard.ai_source(&headers) {
+        return Err(ApiError::new(
+            codes::AI_DISABLED,
+            format!("AI source '{source}' cannot re-enable AI"),
+        )
+        .into_response_with(StatusCode::FORBIDDEN));
+    }
+    let by = client_name(&headers);
+    let was_disabled = state.ai_guard.enable(&by).await;
+    if was_disabled {
+        state
+            .activity_log
+            .log(
+                ActivityType::AiKillSwitch,
+                activity::source_from_headers(&headers),
+                "AI enabled".to_string(),
+                Some(json!({ "disabled": false })),
+                request_id_from_headers(&headers),
+            )
+            .await;
+    }
+    Ok(Json(
+        json!({ "enabled": true, "was_disabled": was_disabled }),
+    ))
+}
+
+/// `X-Sctl-Client`, or `rest` without one.
+fn client_name(headers: &HeaderMap) -> String {
+    headers
+        .get("x-sctl-client")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("rest")
+        .to_string()
+}
@@ -0,0 +1,235 @@
+//! `POST /api/artifacts/push` — upload a device file to the `[artifacts]`
+//! bucket. See [`crate::artifacts`].
+
+use std::path::Path;
+use std::time::Instant;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::artifacts::{self, PushError};
+use crate::config::ArtifactsConfig;
+use crate::error::{codes, ApiError};
+use crate::gawdxfer::throttle::effective_rate;
+use crate::routes::files::validate_path;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PushRequest {
+    /// Absolute path of the file to upload.
+    pub path: String,
+    /// Object key under `[artifacts] prefix` (default: the file name).
+    #[serde(default)]
+    pub key: Option<String>,
+    /// `Content-Type` stored with the object.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Push now even inside a `[quiet_hours]` window that defers work.
+    #[serde(default)]
+    pub urgent: bool,
+}
+
+/// `POST /api/artifacts/push` — body [`PushRequest`], returns
+/// `{url, bucket, key, size, sha256, parts, etag, duration_ms}`.
+///
+/// Inside a deferring `[quiet_hours]` window a push that isn't `urgent` is
+/// queued instead and the reply is `{queued, queue_id, key, resumes_at}`;
+/// it starts once the window ends (see [`crate::quiet_hours`]).
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_PATH"}` — bad `path` or `key`
+/// - `400 Bad Request` with `{"code":"IS_DIRECTORY"}`
+/// - `403 Forbidden` with `{"code":"PERMISSION_DENIED"}`
+/// - `404 Not Found` with `{"code":"FILE_NOT_FOUND"}`
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — no `[artifacts]` section
+/// - `502 Bad Gateway` with `{"code":"PUSH_FAILED"}` — the bucket refused the
+///   upload or couldn't be reached, after retries; `detail.status` is the
+///   last HTTP status when there was one
+/// - `504 Gateway Timeout` with `{"code":"TIMEOUT"}` — past
+///   `[artifacts] timeout_secs`
+pub async fn push(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PushRequest>,
+) -> ApiResult<Value> {
+    run(&state, &headers, req).await
+}
+
+/// Push a file for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`push`].
+pub async fn run(state: &AppState, headers: &HeaderMap, req: PushRequest) -> ApiResult<Value> {
+    let Some(config) = &state.config.artifacts else {
+        return Err(ApiError::new(
+            codes::NOT_FOUND,
+            "Artifact push is not enabled ([artifacts])",
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    };
+    let path = validate_path(&req.path)?;
+    let name = match req.key.as_deref() {
+        Some(key) => valid_key(key)?,
+        None => path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| invalid_path("path has no file name"))?,
+    };
+    match tokio::fs::metadata(&path).await {
+        Ok(meta) if meta.is_dir() => {
+            return Err(ApiError::new(
+                codes::IS_DIRECTORY,
+                format!("{} is a directory", path.display()),
+            )
+            .into_response_with(StatusCode::BAD_REQUEST));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(io_error(&e, &req.path)),
+    }
+    let key = artifacts::object_key(config, &state.config.device.serial, &name);
+
+    if let Some(quiet) = state.quiet_hours.as_ref().filter(|_| !req.urgent) {
+        if let Some(until) = quiet.deferring() {
+            let queue_id = quiet.enqueue(&req.path, &key);
+            let reply = json!({
+                "queued": true,
+                "queue_id": queue_id,
+                "key": key,
+                "resumes_at": crate::util::format_iso8601_utc(until),
+            });
+            let (quiet, state, headers) = (quiet.clone(), state.clone(), headers.clone());
+            tokio::spawn(async move {
+                quiet.wait_until_clear().await;
+                quiet.dequeue(queue_id);
+                if let Err((_, Json(e))) = push_now(&state, &headers, &req, &path, &key).await {
+                    warn!("Queued artifact push of {} failed: {}", req.path, e.message);
+                }
+            });
+            return Ok(Json(reply));
+        }
+    }
+    push_now(state, headers, &req, &path, &key).await
+}
+
+/// Upload `path` to `key` and log it.
+async fn push_now(
+    state: &AppState,
+    headers: &HeaderMap,
+    req: &PushRequest,
+    path: &Path,
+    key: &str,
+) -> ApiResult<Value> {
+    let Some(config) = &state.config.artifacts else {
+        return Err(
+            ApiError::new(codes::NOT_FOUND, "Artifact push is not enabled")
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    };
+    // Quiet hours in throttle mode cap the upload on top of its own limit.
+    let throttled;
+    let config = match state.quiet_hours.as_ref().and_then(|q| q.throttle_bps()) {
+        Some(bps) => {
+            throttled = ArtifactsConfig {
+                rate_limit_bps: effective_rate(Some(bps), config.rate_limit_bps),
+                ..config.clone()
+            };
+            &throttled
+        }
+        None => config,
+    };
+
+    let started = Instant::now();
+    let pushed = artifacts::push(config, path, key, req.content_type.as_deref())
+        .await
+        .map_err(|e| match e {
+            PushError::Io(e) => io_error(&e, &req.path),
+            PushError::Failed { message, status } => ApiError::new(codes::PUSH_FAILED, message)
+                .with_detail(json!({ "status": status }))
+                .into_response_with(StatusCode::BAD_GATEWAY),
+            PushError::Timeout => ApiError::new(
+                codes::TIMEOUT,
+                format!("Upload timed out after {}s", config.timeout_secs),
+            )
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT),
+        })?;
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+    let reply = json!({
+        "url": pushed.url,
+        "bucket": config.bucket,
+        "key": pushed.key,
+        "size": pushed.size,
+        "sha256": pushed.sha256,
+        "parts": pushed.parts,
+        "etag": pushed.etag,
+        "duration_ms": duration_ms,
+    });
+    state
+        .activity_log
+        .log(
+            ActivityType::ArtifactPush,
+            source_from_headers(headers),
+            format!(
+                "Pushed {} to {}",
+                crate::activity::truncate_str(&req.path, 60),
+                crate::activity::truncate_str(&pushed.url, 80)
+            ),
+            Some(json!({
+                "path": req.path,
+                "url": pushed.url,
+                "bucket": config.bucket,
+                "key": pushed.key,
+                "size": pushed.size,
+                "sha256": pushed.sha256,
+                "parts": pushed.parts,
+                "duration_ms": duration_ms,
+            })),
+            request_id_from_headers(headers),
+        )
+        .await;
+    Ok(Json(reply))
+}
+
+/// A client-chosen key: relative, no empty, `.` or `..` segments.
+fn valid_key(key: &str) -> Result<String, (StatusCode, Json<ApiError>)> {
+    let bad = key.is_empty()
+        || key.len() > 512
+        || key.chars().any(char::is_control)
+        || key
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..");
+    if bad {
+        return Err(invalid_path(&format!("invalid object key {key:?}")));
+    }
+    Ok(key.to_string())
+}
+
+fn invalid_path(message: &str) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_PATH, message).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+fn io_error(e: &std::io::Error, path: &str) -> (StatusCode, Json<ApiError>) {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            ApiError::new(codes::FILE_NOT_FOUND, format!("File not found: {path}"))
+                .into_response_with(StatusCode::NOT_FOUND)
+        }
+        std::io::ErrorKind::PermissionDenied => {
+            ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+                .into_response_with(StatusCode::FORBIDDEN)
+        }
+        _ => ApiError::new(codes::IO_ERROR, e.to_string())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
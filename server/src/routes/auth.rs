@@ -0,0 +1,241 @@
+//! `POST /api/auth/rotate` — replace the primary API key.
+//!
+//! The new key is written back to where the current one came from, then
+//! installed in the [`KeyRing`](crate::auth::KeyRing). The replaced key keeps
+//! working for a grace window so connected clients can switch over; requests
+//! made with it are logged under the `previous` key name.
+
+use std::path::Path;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Shortest key accepted.
+const MIN_KEY_LEN: usize = 16;
+/// Longest grace window a request may ask for (7 days).
+const MAX_GRACE_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateRequest {
+    /// The new key; a random one is generated if omitted.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Seconds the old key stays valid (default `[auth] rotation_grace_secs`).
+    #[serde(default)]
+    pub grace_secs: Option<u64>,
+}
+
+/// Where a rotated key was saved so it survives a restart.
+enum Persisted {
+    Config,
+    TunnelCredentials,
+}
+
+/// `POST /api/auth/rotate` — body `{api_key?, grace_secs?}`, returns
+/// `{api_key, previous_expires_at, persisted}`. `persisted` is `"config"`,
+/// `"tunnel_credentials"` (the key was issued by the relay), or `null` when
+/// the key only lasts until restart.
+///
+/// # Errors
+///
+/// - `403 Forbidden` with `{"code":"AUTH_INSUFFICIENT_SCOPE"}` — named keys
+///   need the `*` scope, and the previous key can't rotate
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — key too short,
+///   contains whitespace, is already in use, or `grace_secs` over 7 days
+/// - `500 Internal Server Error` with `{"code":"IO_ERROR"}` — the new key
+///   couldn't be saved; the old key stays in place
+pub async fn rotate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<RotateRequest>>,
+) -> ApiResult<Value> {
+    let Json(req) = body.unwrap_or_default();
+    // The old key can't rotate again during its grace window, or a leaked
+    // key could keep itself alive.
+    let previous_key = crate::auth::current_key().as_deref() == Some(crate::auth::PREVIOUS_KEY);
+    if previous_key || !crate::auth::current_allows("*") {
+        return Err(ApiError::new(
+            codes::AUTH_INSUFFICIENT_SCOPE,
+            "Rotating the API key requires the current key or the '*' scope",
+        )
+        .into_response_with(StatusCode::FORBIDDEN));
+    }
+    let bad_request = |msg: &str| {
+        ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+    };
+    let grace_secs = req
+        .grace_secs
+        .unwrap_or(state.config.auth.rotation_grace_secs);
+    if grace_secs > MAX_GRACE_SECS {
+        return Err(bad_request("grace_secs must be at most 604800 (7 days)"));
+    }
+    let api_key = req.api_key.unwrap_or_else(|| {
+        format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        )
+    });
+    if api_key.len() < MIN_KEY_LEN || api_key.chars().any(char::is_whitespace) {
+        return Err(bad_request(
+            "api_key must be at least 16 characters without whitespace",
+        ));
+    }
+    let in_use = std::iter::once(state.api_keys.current())
+        .chain(state.api_keys.previous().map(|p| p.key))
+        .chain(state.config.auth.keys.iter().map(|k| k.key.clone()))
+        .any(|k| crate::auth::constant_time_eq(k.as_bytes(), api_key.as_bytes()));
+    if in_use {
+        return Err(bad_request("api_key is already in use"));
+    }
+
+    let persisted = persist(&state, &api_key).await.map_err(|e| {
+        ApiError::new(codes::IO_ERROR, format!("Failed to save the new key: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    let previous = state.api_keys.rotate(api_key.clone(), grace_secs);
+    let persisted = persisted.map(|p| match p {
+        Persisted::Config => "config",
+        Persisted::TunnelCredentials => "tunnel_credentials",
+    });
+
+    state
+        .activity_log
+        .log(
+            ActivityType::AuthRotate,
+            source_from_headers(&headers),
+            format!("API key rotated, old key valid for {grace_secs}s"),
+            Some(json!({ "grace_secs": grace_secs, "persisted": persisted })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({
+        "api_key": api_key,
+        "previous_expires_at": previous.map(|p| p.expires_at),
+        "persisted": persisted,
+    })))
+}
+
+/// Save `api_key` where the running key came from.
+async fn persist(state: &AppState, api_key: &str) -> std::io::Result<Option<Persisted>> {
+    if std::env::var_os("SCTL_API_KEY").is_some() {
+        tracing::warn!("API key rotated, but SCTL_API_KEY will override it on restart");
+        return Ok(None);
+    }
+    let data_dir = state.config.server.data_dir.clone();
+    let config_path = state.config_path.clone();
+    let api_key = api_key.to_string();
+    tokio::task::spawn_blocking(move || {
+        use crate::tunnel::credentials::Credentials;
+        if Credentials::load(&data_dir).api_key.is_some() {
+            Credentials::store_api_key(&data_dir, &api_key)?;
+            return Ok(Some(Persisted::TunnelCredentials));
+        }
+        let Some(path) = config_path else {
+            return Ok(None);
+        };
+        write_config_key(&path, &api_key)?;
+        Ok(Some(Persisted::Config))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Atomically rewrite `[auth] api_key` in the config file at `path`,
+/// keeping its permissions.
+fn write_config_key(path: &Path, api_key: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let content = std::fs::read_to_string(path)?;
+    let updated = with_api_key(&content, api_key).map_err(std::io::Error::other)?;
+    let name = path
+        .file_name()
+        .map_or_else(|| "sctl.toml".into(), |n| n.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{name}.tmp"));
+    let mut file = std::fs::File::create(&tmp)?;
+    file.set_permissions(std::fs::metadata(path)?.permissions())?;
+    file.write_all(updated.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// `content` with `[auth] api_key` set to `api_key`. Other lines, comments
+/// included, are kept as they are.
+fn with_api_key(content: &str, api_key: &str) -> Result<String, String> {
+    let line = format!("api_key = {}", toml::Value::String(api_key.to_string()));
+    let mut lines: Vec<String> = content.lines().map(ToString::to_string).collect();
+    let mut auth_header = None;
+    let mut replaced = false;
+    let mut in_auth = false;
+    for (i, raw) in lines.iter_mut().enumerate() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with('[') {
+            in_auth = trimmed
+                .strip_prefix("[auth]")
+                .is_some_and(|rest| rest.trim().is_empty() || rest.trim().starts_with('#'));
+            if in_auth {
+                auth_header = Some(i);
+            }
+        } else if in_auth
+            && trimmed
+                .strip_prefix("api_key")
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        {
+            let indent = &raw[..raw.len() - trimmed.len()];
+            *raw = format!("{indent}{line}");
+            replaced = true;
+            break;
+        }
+    }
+    if !replaced {
+        match auth_header {
+            Some(i) => lines.insert(i + 1, line),
+            None => lines.extend([String::new(), "[auth]".to_string(), line]),
+        }
+    }
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+
+    let parsed: toml::Table = toml::from_str(&updated).map_err(|e| e.to_string())?;
+    if parsed
+        .get("auth")
+        .and_then(|a| a.get("api_key"))
+        .and_then(toml::Value::as_str)
+        != Some(api_key)
+    {
+        return Err("couldn't locate [auth] api_key in the config file".to_string());
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_only_the_auth_key() {
+        let content = "# sctl\n[server]\napi_key = \"not-auth\"\n\n[auth]  # keys\n  api_key = \"old\" \n\n[[auth.keys]]\nname = \"ci\"\nkey = \"k\"\n";
+        let updated = with_api_key(content, "new-key").unwrap();
+        assert!(updated.contains("api_key = \"not-auth\""));
+        assert!(updated.contains("  api_key = \"new-key\"\n"));
+        assert!(updated.contains("# sctl\n") && updated.contains("[auth]  # keys\n"));
+        assert!(updated.contains("[[auth.keys]]\nname = \"ci\""));
+
+        let inserted = with_api_key("[auth]\n[shell]\n", "k2").unwrap();
+        assert_eq!(inserted, "[auth]\napi_key = \"k2\"\n[shell]\n");
+        let appended = with_api_key("[shell]\ndefault_shell = \"/bin/sh\"\n", "k3").unwrap();
+        assert!(appended.ends_with("\n[auth]\napi_key = \"k3\"\n"));
+    }
+}
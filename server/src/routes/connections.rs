@@ -0,0 +1,68 @@
+//! `GET /api/connections` and `DELETE /api/connections/{id}` — the open
+//! connections and who holds them. See [`crate::connections`].
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// `GET /api/connections` — returns `{connections, total, in_flight,
+/// max_connections}`. Each connection has `{id, kind, protocol, peer, local,
+/// identity, opened_at, age_secs, idle_secs, bytes_in, bytes_out, requests,
+/// in_flight}`; the `in_flight` requests are the ones holding
+/// `max_connections` slots.
+pub async fn list(State(state): State<AppState>) -> Json<Value> {
+    let connections = state.connections.list();
+    let in_flight: u64 = connections.iter().map(|c| c.in_flight).sum();
+    Json(json!({
+        "total": connections.len(),
+        "in_flight": in_flight,
+        "max_connections": state.config.server.max_connections,
+        "connections": connections,
+    }))
+}
+
+/// `DELETE /api/connections/{id}` — close a connection. Requests in flight
+/// on it fail, and a WebSocket or tunnel on it drops without a close frame.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — no open connection with that id
+pub async fn disconnect(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> ApiResult<Value> {
+    let Some(connection) = state.connections.list().into_iter().find(|c| c.id == id) else {
+        return Err(
+            ApiError::new(codes::NOT_FOUND, format!("Connection {id} not found"))
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    };
+    state
+        .activity_log
+        .log(
+            ActivityType::ConnectionClose,
+            source_from_headers(&headers),
+            format!(
+                "closed {} connection from {}",
+                connection.kind.as_str(),
+                connection.peer
+            ),
+            Some(json!(connection)),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    // Logged first: closing our own connection ends this request too.
+    state.connections.disconnect(id);
+
+    Ok(Json(json!({ "ok": true, "id": id })))
+}
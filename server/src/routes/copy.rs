@@ -0,0 +1,549 @@
+//! Server-side file copy.
+//!
+//! `POST /api/files/copy` copies a file on the device without moving its bytes
+//! over the link. The copy runs on the [`crate::io_pool`] and lands
+//! atomically: data goes to a temp file beside the destination, which is
+//! renamed into place once complete. The fastest method that works is used:
+//!
+//! 1. A reflink — `FICLONE` on btrfs, XFS or bcachefs, `fclonefileat` on
+//!    APFS. The copy shares the source's extents, so it is instant and uses
+//!    no extra disk space.
+//! 2. `copy_file_range` (Linux) — an in-kernel copy, offloaded to the server
+//!    on NFS.
+//! 3. Buffered read/write in 1 MiB chunks.
+//!
+//! Free space is checked only when a reflink isn't possible. Progress is
+//! broadcast on the session event channel (WS, SSE, relay):
+//!
+//! - `file.copy.progress` — `{copy_id, bytes, total}`, at most once a second
+//! - `file.copy.done` — `{copy_id, method, bytes, duration_ms}`
+//! - `file.copy.failed` — `{copy_id, error}`
+//!
+//! `GET /api/files/copy` lists running and recent copies for clients that
+//! missed the events.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::files::{validate_path, WRITE_COUNTER};
+use crate::activity::{self, request_id_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::sessions::journal::now_ms;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Finished copies kept for `GET /api/files/copy`.
+const KEEP_RECENT: usize = 16;
+
+/// Bytes per `copy_file_range` call.
+#[cfg(target_os = "linux")]
+const RANGE_CHUNK: usize = 16 << 20;
+
+/// Buffer for the read/write fallback.
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// Minimum time between `file.copy.progress` events.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `_IOW(0x94, 9, int)`; the direction bits differ on these architectures.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc64"
+    )
+))]
+const FICLONE: u32 = 0x8004_9409;
+#[cfg(all(
+    target_os = "linux",
+    not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc64"
+    ))
+))]
+const FICLONE: u32 = 0x4004_9409;
+
+/// How a copy was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyMethod {
+    Reflink,
+    CopyFileRange,
+    Buffered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CopyState {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CopyStatus {
+    copy_id: String,
+    state: CopyState,
+    source: String,
+    destination: String,
+    total: u64,
+    bytes: u64,
+    started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<CopyMethod>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Running copies and the most recent finished ones, oldest first.
+static COPIES: std::sync::Mutex<VecDeque<CopyStatus>> = std::sync::Mutex::new(VecDeque::new());
+
+fn with_copies<T>(f: impl FnOnce(&mut VecDeque<CopyStatus>) -> T) -> T {
+    f(&mut COPIES
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner))
+}
+
+fn update_copy(copy_id: &str, f: impl FnOnce(&mut CopyStatus)) {
+    with_copies(|copies| {
+        if let Some(status) = copies.iter_mut().find(|s| s.copy_id == copy_id) {
+            f(status);
+        }
+        // Drop the oldest finished copies beyond the limit.
+        while copies.len() > KEEP_RECENT {
+            let Some(i) = copies.iter().position(|s| s.state != CopyState::Running) else {
+                break;
+            };
+            copies.remove(i);
+        }
+    });
+}
+
+/// Request body for `POST /api/files/copy`.
+#[derive(Deserialize)]
+pub struct CopyRequest {
+    /// Absolute path of the regular file to copy.
+    pub source: String,
+    /// Absolute path of the copy.
+    pub destination: String,
+    /// Replace `destination` if it exists.
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Create missing parent directories of `destination`.
+    #[serde(default)]
+    pub create_dirs: bool,
+}
+
+fn fs_error(e: &io::Error, what: &str) -> (StatusCode, Json<ApiError>) {
+    match e.kind() {
+        io::ErrorKind::NotFound => {
+            ApiError::new(codes::FILE_NOT_FOUND, format!("{what} not found"))
+                .into_response_with(StatusCode::NOT_FOUND)
+        }
+        io::ErrorKind::PermissionDenied => {
+            ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+                .into_response_with(StatusCode::FORBIDDEN)
+        }
+        _ => ApiError::new(codes::IO_ERROR, e.to_string())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// `POST /api/files/copy` — start copying a file on the device.
+///
+/// The source's permission bits are kept; ownership and timestamps are not.
+///
+/// # Error codes
+///
+/// | HTTP | Code               | Meaning                                      |
+/// |------|--------------------|----------------------------------------------|
+/// | 400  | `INVALID_PATH`     | Path validation failed                       |
+/// | 400  | `INVALID_REQUEST`  | Source is not a regular file, or is the destination |
+/// | 400  | `IS_DIRECTORY`     | Destination is a directory                   |
+/// | 403  | `PERMISSION_DENIED`| OS permission error                          |
+/// | 404  | `FILE_NOT_FOUND`   | Source or destination directory missing      |
+/// | 409  | `FILE_EXISTS`      | Destination exists and `overwrite` is off    |
+/// | 500  | `IO_ERROR`         | Other I/O failure                            |
+///
+/// Failures after the copy has started are reported by `file.copy.failed`.
+pub async fn copy_file(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<CopyRequest>,
+) -> ApiResult<Value> {
+    let source = validate_path(&req.source)?;
+    let destination = validate_path(&req.destination)?;
+    if source == destination {
+        return Err(ApiError::new(
+            codes::INVALID_REQUEST,
+            "Source and destination are the same",
+        )
+        .into_response_with(StatusCode::BAD_REQUEST));
+    }
+
+    let meta = tokio::fs::metadata(&source)
+        .await
+        .map_err(|e| fs_error(&e, "Source"))?;
+    if !meta.is_file() {
+        return Err(
+            ApiError::new(codes::INVALID_REQUEST, "Source is not a regular file")
+                .into_response_with(StatusCode::BAD_REQUEST),
+        );
+    }
+    match tokio::fs::symlink_metadata(&destination).await {
+        Ok(m) if m.is_dir() => {
+            return Err(
+                ApiError::new(codes::IS_DIRECTORY, "Destination is a directory")
+                    .into_response_with(StatusCode::BAD_REQUEST),
+            );
+        }
+        Ok(_) if !req.overwrite => {
+            return Err(
+                ApiError::new(codes::FILE_EXISTS, "Destination already exists")
+                    .into_response_with(StatusCode::CONFLICT),
+            );
+        }
+        _ => {}
+    }
+    let parent = destination.parent().unwrap_or(Path::new("/"));
+    if req.create_dirs {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| fs_error(&e, "Destination directory"))?;
+    } else if !tokio::fs::metadata(parent).await.is_ok_and(|m| m.is_dir()) {
+        return Err(
+            ApiError::new(codes::FILE_NOT_FOUND, "Destination directory not found")
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    }
+
+    let copy_id = uuid::Uuid::new_v4().to_string();
+    let total = meta.len();
+    let source_str = source.to_string_lossy().into_owned();
+    let destination_str = destination.to_string_lossy().into_owned();
+    let started_at = now_ms();
+    with_copies(|copies| {
+        copies.push_back(CopyStatus {
+            copy_id: copy_id.clone(),
+            state: CopyState::Running,
+            source: source_str.clone(),
+            destination: destination_str.clone(),
+            total,
+            bytes: 0,
+            started_at,
+            method: None,
+            finished_at: None,
+            error: None,
+        });
+    });
+    state
+        .activity_log
+        .log(
+            ActivityType::FileWrite,
+            activity::source_from_headers(&headers),
+            activity::truncate_str(&destination_str, 80),
+            Some(json!({ "copy_from": source_str, "size": total, "copy_id": copy_id })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    info!(copy_id = %copy_id, source = %source_str, destination = %destination_str, total, "File copy: started");
+
+    let events = state.session_events.clone();
+    let task_id = copy_id.clone();
+    tokio::spawn(async move {
+        let progress_events = events.clone();
+        let progress_id = task_id.clone();
+        let result = crate::io_pool::run(move || {
+            let mut last = Instant::now();
+            copy_atomic(&source, &destination, total, &mut |bytes| {
+                if last.elapsed() < PROGRESS_INTERVAL {
+                    return;
+                }
+                last = Instant::now();
+                update_copy(&progress_id, |s| s.bytes = bytes);
+                let _ = progress_events.send(json!({
+                    "type": "file.copy.progress",
+                    "copy_id": progress_id,
+                    "bytes": bytes,
+                    "total": total,
+                }));
+            })
+        })
+        .await;
+
+        let finished_at = now_ms();
+        match result {
+            Ok((method, bytes)) => {
+                info!(copy_id = %task_id, ?method, bytes, "File copy: done");
+                update_copy(&task_id, |s| {
+                    s.state = CopyState::Done;
+                    s.method = Some(method);
+                    s.bytes = bytes;
+                    s.finished_at = Some(finished_at);
+                });
+                let _ = events.send(json!({
+                    "type": "file.copy.done",
+                    "copy_id": task_id,
+                    "method": method,
+                    "bytes": bytes,
+                    "duration_ms": finished_at.saturating_sub(started_at),
+                }));
+            }
+            Err(e) => {
+                warn!(copy_id = %task_id, error = %e, "File copy: failed");
+                update_copy(&task_id, |s| {
+                    s.state = CopyState::Failed;
+                    s.finished_at = Some(finished_at);
+                    s.error = Some(e.to_string());
+                });
+                let _ = events.send(json!({
+                    "type": "file.copy.failed",
+                    "copy_id": task_id,
+                    "error": e.to_string(),
+                }));
+            }
+        }
+    });
+
+    Ok(Json(json!({
+        "copy_id": copy_id,
+        "state": CopyState::Running,
+        "source": source_str,
+        "destination": destination_str,
+        "total": total,
+    })))
+}
+
+/// `GET /api/files/copy` — running and recent copies, oldest first.
+pub async fn list_copies() -> ApiResult<Value> {
+    let copies: Vec<CopyStatus> = with_copies(|copies| copies.iter().cloned().collect());
+    Ok(Json(json!({ "copies": copies })))
+}
+
+/// Copy `source` to a temp file beside `destination`, then rename it into
+/// place. The temp file is removed on failure.
+fn copy_atomic(
+    source: &Path,
+    destination: &Path,
+    total: u64,
+    progress: &mut dyn FnMut(u64),
+) -> io::Result<(CopyMethod, u64)> {
+    let src = File::open(source)?;
+    let mode = crate::util::file_mode(&src.metadata()?);
+    let parent = destination.parent().unwrap_or(Path::new("/"));
+    let seq = WRITE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path = parent.join(format!(".sctl_tmp_{}_{}", std::process::id(), seq));
+
+    let result = (|| {
+        let (dst, cloned) = open_temp(&src, &temp_path)?;
+        let copied = if cloned {
+            progress(total);
+            (CopyMethod::Reflink, total)
+        } else {
+            ensure_space(&temp_path, total)?;
+            copy_data(&src, &dst, total, progress)?
+        };
+        dst.sync_all()?;
+        crate::util::set_mode(&temp_path, mode)?;
+        std::fs::rename(&temp_path, destination)?;
+        Ok(copied)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn create_temp(path: &Path) -> io::Result<File> {
+    crate::util::create_mode(OpenOptions::new().write(true).create_new(true), 0o600).open(path)
+}
+
+/// Create the temp file at `path`, as a reflink of `src` if the filesystem
+/// can; the flag says whether it is one.
+#[cfg(target_os = "linux")]
+fn open_temp(src: &File, path: &Path) -> io::Result<(File, bool)> {
+    let dst = create_temp(path)?;
+    // The request argument is `c_ulong` on glibc and `c_int` on musl.
+    #[allow(clippy::cast_possible_wrap, clippy::cast_lossless)]
+    // SAFETY: FICLONE takes the source fd as its argument; both fds are open.
+    let cloned = unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } == 0;
+    Ok((dst, cloned))
+}
+
+/// Create the temp file at `path`, as a reflink of `src` if the filesystem
+/// can; the flag says whether it is one.
+#[cfg(target_os = "macos")]
+fn open_temp(src: &File, path: &Path) -> io::Result<(File, bool)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: the fd is open and `c_path` is NUL-terminated. `fclonefileat`
+    // creates the file and fails if it exists, like `create_new`.
+    if unsafe { libc::fclonefileat(src.as_raw_fd(), libc::AT_FDCWD, c_path.as_ptr(), 0) } == 0 {
+        return Ok((OpenOptions::new().write(true).open(path)?, true));
+    }
+    Ok((create_temp(path)?, false))
+}
+
+/// Windows has no reflink call for an open file; the data is copied.
+#[cfg(windows)]
+fn open_temp(_src: &File, path: &Path) -> io::Result<(File, bool)> {
+    Ok((create_temp(path)?, false))
+}
+
+/// Copy all of `src` into the empty `dst` by the fastest method that works
+/// short of a reflink.
+fn copy_data(
+    src: &File,
+    dst: &File,
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] total: u64,
+    progress: &mut dyn FnMut(u64),
+) -> io::Result<(CopyMethod, u64)> {
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut copied = 0u64;
+    #[cfg(target_os = "linux")]
+    loop {
+        match copy_range(src, dst, RANGE_CHUNK) {
+            // Some kernels report 0 instead of an error when they can't copy
+            // between these files.
+            Ok(0) if copied == 0 && total > 0 => break,
+            Ok(0) => return Ok((CopyMethod::CopyFileRange, copied)),
+            Ok(n) => {
+                copied += n as u64;
+                progress(copied);
+            }
+            Err(e) if copied == 0 && range_unsupported(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    let (mut src, mut dst) = (src, dst);
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => return Ok((CopyMethod::Buffered, copied)),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buf[..n])?;
+        copied += n as u64;
+        progress(copied);
+    }
+}
+
+/// One `copy_file_range` call using and advancing both files' offsets.
+#[cfg(target_os = "linux")]
+fn copy_range(src: &File, dst: &File, len: usize) -> io::Result<usize> {
+    // SAFETY: both fds are open; null offset pointers make the kernel use the
+    // file offsets.
+    let n = unsafe {
+        libc::syscall(
+            libc::SYS_copy_file_range,
+            src.as_raw_fd(),
+            std::ptr::null_mut::<libc::loff_t>(),
+            dst.as_raw_fd(),
+            std::ptr::null_mut::<libc::loff_t>(),
+            len,
+            0u32,
+        )
+    };
+    usize::try_from(n).map_err(|_| io::Error::last_os_error())
+}
+
+/// Errors meaning `copy_file_range` can't be used here (old kernel,
+/// cross-filesystem before 5.3, unsupported filesystem).
+#[cfg(target_os = "linux")]
+fn range_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL)
+    )
+}
+
+fn ensure_space(path: &Path, needed: u64) -> io::Result<()> {
+    let Ok((_, available, _)) = crate::util::disk_space(path) else {
+        return Ok(());
+    };
+    if available < needed {
+        return Err(io::Error::other(format!(
+            "Insufficient disk space: {available} bytes available, {needed} bytes needed"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("sctl_test_copy_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn copies_content_and_mode() {
+        let dir = temp_dir("content");
+        let (src, dst) = (dir.join("src.bin"), dir.join("dst.bin"));
+        let data: Vec<u8> = (0..3 * BUFFER_SIZE + 7)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        std::fs::write(&src, &data).unwrap();
+        crate::util::set_mode(&src, 0o640).unwrap();
+
+        let mut last = 0;
+        let (_, bytes) = copy_atomic(&src, &dst, data.len() as u64, &mut |b| last = b).unwrap();
+        assert_eq!(bytes, data.len() as u64);
+        assert_eq!(last, bytes);
+        assert_eq!(std::fs::read(&dst).unwrap(), data);
+        let mode = crate::util::file_mode(&std::fs::metadata(&dst).unwrap());
+        assert_eq!(mode, if cfg!(unix) { 0o640 } else { 0o644 });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_destination_and_leaves_no_temp() {
+        let dir = temp_dir("replace");
+        let (src, dst) = (dir.join("src"), dir.join("dst"));
+        std::fs::write(&src, b"new").unwrap();
+        std::fs::write(&dst, b"old contents").unwrap();
+
+        copy_atomic(&src, &dst, 3, &mut |_| {}).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"new");
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
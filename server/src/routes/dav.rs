@@ -0,0 +1,41 @@
+//! `/dav/...` — read-only WebDAV. See [`crate::dav`].
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+
+use crate::dav::RelayPrefix;
+use crate::AppState;
+
+/// Any method on `/dav/...`. Takes the API key from Basic or Bearer auth
+/// and needs the `files:read` scope.
+///
+/// # Errors
+///
+/// - `401 Unauthorized` with `WWW-Authenticate: Basic` — no key, a bad key
+///   or a missing scope
+/// - `404 Not Found` — no `[dav]` section, or no such root or file
+/// - `405 Method Not Allowed` — anything that would write
+pub async fn dav(
+    State(state): State<AppState>,
+    relay: Option<Extension<RelayPrefix>>,
+    req: Request,
+) -> Response {
+    let identity = crate::auth::keyless_identity(req.extensions()).or_else(|| {
+        crate::dav::credentials(req.headers())
+            .and_then(|key| state.api_keys.resolve(&state.config.auth.keys, &key))
+    });
+    let scope = crate::auth::required_scope(req.method(), req.uri().path());
+    if !identity.is_some_and(|i| i.allows(scope)) {
+        return crate::dav::unauthorized();
+    }
+    let Some(config) = &state.config.dav else {
+        return (StatusCode::NOT_FOUND, "WebDAV is not enabled ([dav])").into_response();
+    };
+    let prefix = relay.map(|Extension(RelayPrefix(prefix))| prefix);
+    let (parts, _) = req.into_parts();
+    crate::dav::respond(&state, config, prefix.as_deref(), &parts).await
+}
@@ -46,6 +46,16 @@ pub async fn diagnostics(
     })))
 }
 
+/// Process, system, and network sections of the diagnostics snapshot (used
+/// by the support bundle).
+pub(crate) fn system_snapshot(state: &AppState) -> Value {
+    json!({
+        "process": collect_process_info(state),
+        "system": collect_system_info(),
+        "network": collect_network_info(),
+    })
+}
+
 /// Collect process-level diagnostics from /proc/self.
 fn collect_process_info(state: &AppState) -> Value {
     let pid = std::process::id();
@@ -143,7 +153,7 @@ fn collect_network_info() -> Value {
 }
 
 /// Collect service logs, trying journalctl first then logread.
-async fn collect_logs(max_lines: u32, since: &str) -> (Vec<Value>, Value) {
+pub(crate) async fn collect_logs(max_lines: u32, since: &str) -> (Vec<Value>, Value) {
     // Try journalctl (systemd)
     if let Some(logs) = try_journalctl(max_lines, since).await {
         let stats = compute_log_stats(&logs);
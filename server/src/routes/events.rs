@@ -19,7 +19,7 @@ use std::sync::atomic::Ordering;
 use crate::AppState;
 
 /// Maximum concurrent SSE connections before rejecting with 429.
-const MAX_SSE_CONNECTIONS: u32 = 64;
+pub(crate) const MAX_SSE_CONNECTIONS: u32 = 64;
 
 /// `GET /api/events` — SSE event stream.
 pub async fn event_stream(State(state): State<AppState>) -> impl IntoResponse {
@@ -67,10 +67,10 @@ pub async fn event_stream(State(state): State<AppState>) -> impl IntoResponse {
 }
 
 /// Wrapper that decrements the SSE connection counter when the stream is dropped.
-struct DropCounterStream<S> {
-    inner: std::pin::Pin<Box<S>>,
-    counter: std::sync::Arc<std::sync::atomic::AtomicU32>,
-    decremented: bool,
+pub(crate) struct DropCounterStream<S> {
+    pub(crate) inner: std::pin::Pin<Box<S>>,
+    pub(crate) counter: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    pub(crate) decremented: bool,
 }
 
 impl<S: Stream<Item = Result<Event, Infallible>>> Stream for DropCounterStream<S> {
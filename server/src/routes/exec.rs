@@ -1,24 +1,62 @@
 //! Command execution endpoints.
 //!
 //! - `POST /api/exec` — execute a single command
+//! - `POST /api/exec/stream` — execute a single command, streaming its output
 //! - `POST /api/exec/batch` — execute multiple commands sequentially
 //!
-//! Both endpoints support per-request overrides for `shell`, `working_dir`, and
-//! `env` (environment variables merged into the inherited environment).
+//! All three endpoints support per-request overrides for `shell`, `working_dir`, and
+//! `env` (environment variables merged into the inherited environment), plus an
+//! optional `sudo` object to run the command elevated (see [`crate::shell::sudo`]).
+//! `env` values of the form `secret://name` are resolved on the device and
+//! redacted from the returned output (see [`crate::shell::secrets`]).
+//!
+//! `POST /api/exec?confirm_within=<secs>` with a `rollback` command arms a
+//! commit-confirm window (see [`crate::shell::confirm`]):
+//!
+//! - `GET /api/exec/pending` — list open windows
+//! - `POST /api/exec/pending/{id}/confirm` — keep the change, cancel the rollback
+//!
+//! Output larger than `summary.threshold_bytes` is summarized (see
+//! [`crate::shell::summary`]). MCP callers get the summary in place of
+//! `stdout`/`stderr` unless they pass `full_output: true`; other callers get
+//! the full output unless they pass `full_output: false`. The full output is
+//! always kept under the response's `summary.activity_id`.
+//!
+//! An `X-Request-Deadline` header caps each command's `timeout_ms` at the time
+//! remaining (see [`crate::deadline`]); batch commands not yet started when it
+//! passes are skipped.
+//!
+//! With `[hooks]` configured, every command first goes through the Lua
+//! `pre_exec` hook and its activity entry is enriched by `post_exec` (see
+//! [`crate::hooks`]); a denied batch command gets `exit_code: -1` and the
+//! reason in `stderr`.
 
 use std::collections::HashMap;
 
 use axum::{
-    extract::State,
+    body::Body,
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
+    response::Response,
     Json,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::mpsc;
 
-use crate::activity::{self, request_id_from_headers, ActivityType, CachedExecResult};
+use crate::activity::{
+    self, request_id_from_headers, ActivitySource, ActivityType, CachedExecResult,
+};
+use crate::deadline::RequestDeadline;
 use crate::error::{codes, ApiError};
+use crate::hooks;
+use crate::shell::classify;
+use crate::shell::confirm::{self, GuardedExec};
+use crate::shell::policy;
 use crate::shell::process;
+use crate::shell::secrets;
+use crate::shell::sudo::{self, ExecSudo, SudoOptions};
+use crate::shell::summary::{self, OutputSummary};
 use crate::AppState;
 
 /// Request body for `POST /api/exec`.
@@ -36,9 +74,46 @@ pub struct ExecRequest {
     /// Override the working directory for this command.
     pub working_dir: Option<String>,
     /// Extra environment variables **merged into** the inherited environment.
+    /// Values may be `secret://name` references.
     pub env: Option<HashMap<String, String>>,
     /// Override the shell binary (e.g. `/bin/bash`).
     pub shell: Option<String>,
+    /// Run the command under `sudo`.
+    pub sudo: Option<SudoOptions>,
+    /// Command run if a `confirm_within` window expires unconfirmed.
+    pub rollback: Option<String>,
+    /// `true` returns full output even to MCP callers; `false` returns the
+    /// summary of large output to any caller.
+    pub full_output: Option<bool>,
+}
+
+/// Query parameters for `POST /api/exec`.
+#[derive(Deserialize)]
+pub struct ExecQuery {
+    /// Seconds to wait for confirmation before running `rollback`.
+    pub confirm_within: Option<u64>,
+}
+
+/// Commit-confirm window armed by a `confirm_within` exec.
+#[derive(Serialize)]
+pub struct ConfirmInfo {
+    /// Id for `POST /api/exec/pending/{id}/confirm`.
+    pub id: String,
+    /// Unix ms at which `rollback` runs unless confirmed.
+    pub deadline_ms: u64,
+}
+
+/// Returned in place of full output when `stdout`/`stderr` were summarized.
+#[derive(Serialize)]
+pub struct SummaryInfo {
+    /// `head_tail`, `errors`, or `command`.
+    pub strategy: String,
+    /// Size of the full stdout in bytes.
+    pub stdout_bytes: usize,
+    /// Size of the full stderr in bytes.
+    pub stderr_bytes: usize,
+    /// Full output: `GET /api/activity/{activity_id}/result`.
+    pub activity_id: u64,
 }
 
 /// Response body for `POST /api/exec` (and each item in a batch response).
@@ -55,21 +130,41 @@ pub struct ExecResponse {
     /// Echoed from request, omitted if not provided.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub request_id: Option<String>,
+    /// Present when the command ran under a commit-confirm window.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<ConfirmInfo>,
+    /// Present when `stdout`/`stderr` hold a summary rather than the full output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<SummaryInfo>,
 }
 
 /// `POST /api/exec` — execute a single shell command.
 ///
+/// With `?confirm_within=<secs>` the command runs detached from the request
+/// and `rollback` runs after the window unless confirmed.
+///
 /// # Errors
 ///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — unusable `sudo` options,
+///   an unresolvable `secret://` reference, or `confirm_within`/`rollback`
+///   given without the other or out of range
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — the command or rollback
+///   is refused by `[policy]`
 /// - `504 Gateway Timeout` with `{"code":"TIMEOUT"}` — command exceeded its timeout
 /// - `500 Internal Server Error` with `{"code":"EXEC_FAILED"}` — spawn or wait failure
 pub async fn exec(
     State(state): State<AppState>,
     headers: HeaderMap,
+    deadline: RequestDeadline,
+    Query(query): Query<ExecQuery>,
     Json(payload): Json<ExecRequest>,
 ) -> Result<Json<ExecResponse>, (StatusCode, Json<ApiError>)> {
     let source = activity::source_from_headers(&headers);
     let req_id = request_id_from_headers(&headers);
+    let window =
+        confirm_window(query.confirm_within, payload.rollback.as_deref()).map_err(|e| {
+            ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+        })?;
     let timeout = payload
         .timeout_ms
         .unwrap_or(state.config.server.exec_timeout_ms);
@@ -83,24 +178,82 @@ pub async fn exec(
         .unwrap_or(&state.config.shell.default_working_dir);
     let expanded_dir = crate::util::expand_tilde(raw_dir);
     let working_dir = expanded_dir.as_ref();
-
-    match Box::pin(process::exec_command(
-        shell,
-        working_dir,
-        &payload.command,
-        timeout,
+    let sudo = sudo::resolve_exec(payload.sudo.as_ref()).map_err(|e| {
+        ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+    })?;
+    let env = secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
         payload.env.as_ref(),
-    ))
+    )
     .await
-    {
-        Ok(result) => {
-            log_exec_ok(&state, source, &payload.command, &result, req_id).await;
+    .map_err(|e| {
+        ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+    })?;
+    policy::check(&state, source, &payload.command).await?;
+    if let Some(rollback) = window.and(payload.rollback.as_deref()) {
+        policy::check(&state, source, rollback).await?;
+    }
+    state.ai_guard.charge_execs(&headers, 1)?;
+    hooks::check_exec(&state, source, &payload.command, shell, working_dir)?;
+
+    let (result, confirm) = if let Some(window) = window {
+        let (id, outcome) = confirm::spawn_guarded(
+            &state,
+            GuardedExec {
+                shell: shell.to_string(),
+                working_dir: working_dir.to_string(),
+                command: payload.command.clone(),
+                rollback: payload.rollback.clone().unwrap_or_default(),
+                timeout_ms: timeout,
+                env,
+                sudo,
+                window,
+                source,
+            },
+        );
+        let outcome = outcome.await.map_err(|_| {
+            ApiError::new(codes::EXEC_FAILED, "Guarded exec task failed")
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        let confirm = outcome
+            .deadline_ms
+            .map(|deadline_ms| ConfirmInfo { id, deadline_ms });
+        (outcome.result, confirm)
+    } else {
+        // Guarded commands above run detached on purpose; this one stops with
+        // the request.
+        let mut result = deadline
+            .run(Box::pin(process::exec_command_with(
+                shell,
+                working_dir,
+                &payload.command,
+                deadline.cap_timeout_ms(timeout),
+                env.env(),
+                sudo.as_ref(),
+            )))
+            .await
+            .unwrap_or(Err(process::ExecError::Timeout));
+        if let Ok(ref mut r) = result {
+            env.redact_result(r);
+        }
+        (result, None)
+    };
+
+    match result {
+        Ok(mut result) => {
+            let summary = log_exec_ok(&state, source, &payload.command, &result, req_id)
+                .await
+                .filter(|_| wants_summary(source, payload.full_output))
+                .map(|(s, activity_id)| apply_summary(&mut result, s, activity_id));
             Ok(Json(ExecResponse {
                 exit_code: result.exit_code,
                 stdout: result.stdout,
                 stderr: result.stderr,
                 duration_ms: result.duration_ms,
                 request_id: payload.request_id,
+                confirm,
+                summary,
             }))
         }
         Err(process::ExecError::Timeout) => {
@@ -115,8 +268,12 @@ pub async fn exec(
             )
             .await;
             let mut err = ApiError::new(codes::TIMEOUT, "Command timed out");
-            if let Some(ref rid) = payload.request_id {
-                err = err.with_detail(json!({ "request_id": rid }));
+            if payload.request_id.is_some() || confirm.is_some() {
+                // A timed-out command may have half-applied: its window stays armed.
+                err = err.with_detail(json!({
+                    "request_id": payload.request_id,
+                    "confirm": confirm,
+                }));
             }
             Err(err.into_response_with(StatusCode::GATEWAY_TIMEOUT))
         }
@@ -141,6 +298,291 @@ pub async fn exec(
     }
 }
 
+/// Longest run of output held back waiting for a newline when redacting a
+/// streamed exec.
+const STREAM_HOLD_MAX: usize = 64 * 1024;
+
+/// `POST /api/exec/stream` — execute a single shell command, streaming its
+/// output as NDJSON while it runs.
+///
+/// Takes the same body as [`exec`] minus `rollback` (commit-confirm windows
+/// need the buffered endpoint) and `full_output` (nothing is summarized).
+/// Each line is one of:
+///
+/// - `{"type":"stdout"|"stderr","data":"..."}` — output as it is read
+/// - `{"type":"exit","exit_code":0,"duration_ms":1234,"request_id":"..."}` —
+///   the last line when the command ran
+/// - `{"type":"error","code":"TIMEOUT"|"EXEC_FAILED","message":"..."}` —
+///   the last line when it didn't
+///
+/// With `secret://` env values, output is released a line at a time (or every
+/// 64 KiB without a newline) so a secret split across reads is still
+/// redacted. Closing the response kills the command. The activity log and
+/// result cache get the same entry as [`exec`].
+///
+/// # Errors
+///
+/// Returned as JSON before the stream starts:
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — `rollback` given,
+///   unusable `sudo` options, or an unresolvable `secret://` reference
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — refused by `[policy]`
+pub async fn exec_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    deadline: RequestDeadline,
+    Json(payload): Json<ExecRequest>,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let bad_request = |e: String| {
+        ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+    };
+    if payload.rollback.is_some() {
+        return Err(bad_request(
+            "rollback is not supported by /api/exec/stream".to_string(),
+        ));
+    }
+    let source = activity::source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let timeout = payload
+        .timeout_ms
+        .unwrap_or(state.config.server.exec_timeout_ms);
+    let shell = payload
+        .shell
+        .clone()
+        .unwrap_or_else(|| state.config.shell.default_shell.clone());
+    let raw_dir = payload
+        .working_dir
+        .as_deref()
+        .unwrap_or(&state.config.shell.default_working_dir);
+    let working_dir = crate::util::expand_tilde(raw_dir).into_owned();
+    let sudo = sudo::resolve_exec(payload.sudo.as_ref()).map_err(bad_request)?;
+    let env = secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
+        payload.env.as_ref(),
+    )
+    .await
+    .map_err(bad_request)?;
+    policy::check(&state, source, &payload.command).await?;
+    state.ai_guard.charge_execs(&headers, 1)?;
+    hooks::check_exec(&state, source, &payload.command, &shell, &working_dir)?;
+    let timeout_ms = deadline.cap_timeout_ms(timeout);
+
+    let (tx, rx) = mpsc::channel::<String>(64);
+    tokio::spawn(async move {
+        let (chunk_tx, mut chunk_rx) = mpsc::channel(64);
+        let run = process::exec_command_streaming(
+            &shell,
+            &working_dir,
+            &payload.command,
+            timeout_ms,
+            env.env(),
+            sudo.as_ref(),
+            chunk_tx,
+        );
+        tokio::pin!(run);
+        let mut held = HeldOutput::default();
+        let result = loop {
+            tokio::select! {
+                result = &mut run => break result,
+                Some(chunk) = chunk_rx.recv() => {
+                    if let Some(line) = held.push(&env, chunk) {
+                        if tx.send(line).await.is_err() {
+                            // Client went away; dropping `run` kills the command.
+                            return;
+                        }
+                    }
+                }
+                () = tx.closed() => return,
+            }
+        };
+        while let Some(chunk) = chunk_rx.recv().await {
+            if let Some(line) = held.push(&env, chunk) {
+                let _ = tx.send(line).await;
+            }
+        }
+        for line in held.flush(&env) {
+            let _ = tx.send(line).await;
+        }
+
+        let last = match result {
+            Ok(mut result) => {
+                env.redact_result(&mut result);
+                log_exec_ok(&state, source, &payload.command, &result, req_id).await;
+                json!({
+                    "type": "exit",
+                    "exit_code": result.exit_code,
+                    "duration_ms": result.duration_ms,
+                    "request_id": payload.request_id,
+                })
+            }
+            Err(e) => {
+                let (code, status, duration_ms) = match e {
+                    process::ExecError::Timeout => (codes::TIMEOUT, "timeout", timeout_ms),
+                    _ => (codes::EXEC_FAILED, "error", 0),
+                };
+                let message = e.to_string();
+                log_exec_err(
+                    &state,
+                    source,
+                    &payload.command,
+                    status,
+                    &message,
+                    duration_ms,
+                    req_id,
+                )
+                .await;
+                json!({
+                    "type": "error",
+                    "code": code,
+                    "message": message,
+                    "request_id": payload.request_id,
+                })
+            }
+        };
+        let _ = tx.send(format!("{last}\n")).await;
+    });
+
+    let lines = futures::stream::unfold(rx, |mut rx| async move {
+        let line = rx.recv().await?;
+        Some((Ok::<_, std::convert::Infallible>(line), rx))
+    });
+    Ok(Response::builder()
+        .header("Content-Type", "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .unwrap())
+}
+
+/// Streamed output not yet released, per stream. Without secrets every
+/// chunk goes straight out; with them, output is cut at the last newline
+/// (or [`STREAM_HOLD_MAX`]) and redacted.
+#[derive(Default)]
+struct HeldOutput {
+    stdout: String,
+    stderr: String,
+}
+
+impl HeldOutput {
+    /// Take in a chunk and return the NDJSON line to send, if any.
+    #[allow(clippy::needless_pass_by_value)]
+    fn push(&mut self, env: &secrets::ResolvedEnv, chunk: process::OutputChunk) -> Option<String> {
+        if !env.has_secrets() {
+            return Some(output_line(chunk.stream, &chunk.data));
+        }
+        let held = match chunk.stream {
+            process::OutputStream::Stdout => &mut self.stdout,
+            process::OutputStream::Stderr => &mut self.stderr,
+        };
+        held.push_str(&chunk.data);
+        let cut = match held.rfind('\n') {
+            Some(i) => i + 1,
+            None if held.len() >= STREAM_HOLD_MAX => held.len(),
+            None => return None,
+        };
+        let out: String = held.drain(..cut).collect();
+        Some(output_line(chunk.stream, &env.redact(&out)))
+    }
+
+    /// Lines for whatever is still held once the command has exited.
+    fn flush(self, env: &secrets::ResolvedEnv) -> Vec<String> {
+        [
+            (process::OutputStream::Stdout, self.stdout),
+            (process::OutputStream::Stderr, self.stderr),
+        ]
+        .into_iter()
+        .filter(|(_, held)| !held.is_empty())
+        .map(|(stream, held)| output_line(stream, &env.redact(&held)))
+        .collect()
+    }
+}
+
+fn output_line(stream: process::OutputStream, data: &str) -> String {
+    format!("{}\n", json!({ "type": stream, "data": data }))
+}
+
+/// Whether a caller gets summaries in place of large output: MCP callers by
+/// default, anyone with `full_output: false`.
+pub(crate) fn wants_summary(source: ActivitySource, full_output: Option<bool>) -> bool {
+    full_output.map_or(source == ActivitySource::Mcp, |full| !full)
+}
+
+/// Swap `result`'s output for its summary.
+pub(crate) fn apply_summary(
+    result: &mut process::ExecResult,
+    summary: OutputSummary,
+    activity_id: u64,
+) -> SummaryInfo {
+    result.stdout = summary.stdout;
+    result.stderr = summary.stderr;
+    SummaryInfo {
+        strategy: summary.strategy,
+        stdout_bytes: summary.stdout_bytes,
+        stderr_bytes: summary.stderr_bytes,
+        activity_id,
+    }
+}
+
+/// Validate the `confirm_within` / `rollback` pair into a window duration.
+pub(crate) fn confirm_window(
+    confirm_within: Option<u64>,
+    rollback: Option<&str>,
+) -> Result<Option<std::time::Duration>, String> {
+    match (confirm_within, rollback.map(str::trim)) {
+        (None, None) => Ok(None),
+        (Some(_), None | Some("")) => {
+            Err("confirm_within requires a non-empty rollback command".to_string())
+        }
+        (None, Some(_)) => Err("rollback requires confirm_within".to_string()),
+        (Some(secs), Some(_)) => {
+            if (confirm::MIN_WINDOW_SECS..=confirm::MAX_WINDOW_SECS).contains(&secs) {
+                Ok(Some(std::time::Duration::from_secs(secs)))
+            } else {
+                Err(format!(
+                    "confirm_within must be between {} and {} seconds",
+                    confirm::MIN_WINDOW_SECS,
+                    confirm::MAX_WINDOW_SECS
+                ))
+            }
+        }
+    }
+}
+
+/// `GET /api/exec/pending` — open commit-confirm windows.
+pub async fn list_pending(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!({ "pending": state.exec_confirms.list() }))
+}
+
+/// `POST /api/exec/pending/{id}/confirm` — keep a change, cancel its rollback.
+///
+/// # Errors
+///
+/// - `404 Not Found` — unknown id, or the window already expired (the
+///   rollback ran; see the `exec_rollback` activity entry)
+pub async fn confirm_pending(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ApiError>)> {
+    let Some(command) = state.exec_confirms.confirm(&id) else {
+        return Err(ApiError::new(
+            codes::NOT_FOUND,
+            format!("No pending confirmation '{id}' (expired or already confirmed)"),
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    };
+    state
+        .activity_log
+        .log(
+            ActivityType::ExecConfirm,
+            activity::source_from_headers(&headers),
+            format!("Confirmed: {}", activity::truncate_str(&command, 60)),
+            Some(json!({ "confirm_id": id, "command": command })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    Ok(Json(json!({ "ok": true, "id": id, "confirmed": true })))
+}
+
 // ---------------------------------------------------------------------------
 // Batch exec
 // ---------------------------------------------------------------------------
@@ -159,8 +601,12 @@ pub struct BatchExecRequest {
     pub env: Option<HashMap<String, String>>,
     /// Default shell for all commands.
     pub shell: Option<String>,
+    /// Default `sudo` options for all commands.
+    pub sudo: Option<SudoOptions>,
     /// Correlation ID echoed in the batch response.
     pub request_id: Option<String>,
+    /// As [`ExecRequest::full_output`], for every command.
+    pub full_output: Option<bool>,
 }
 
 /// A single command within a [`BatchExecRequest`].
@@ -176,6 +622,8 @@ pub struct BatchCommand {
     pub env: Option<HashMap<String, String>>,
     /// Per-command shell override.
     pub shell: Option<String>,
+    /// Per-command `sudo` override (replaces the batch-level options).
+    pub sudo: Option<SudoOptions>,
 }
 
 /// Response body for `POST /api/exec/batch`.
@@ -199,10 +647,14 @@ pub struct BatchExecResponse {
 /// # Errors
 ///
 /// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — empty commands array
+///   or unusable `sudo` options
 /// - `400 Bad Request` with `{"code":"BATCH_TOO_LARGE"}` — exceeds `max_batch_size`
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — a command is refused by
+///   `[policy]`; nothing runs
 pub async fn batch_exec(
     State(state): State<AppState>,
     headers: HeaderMap,
+    deadline: RequestDeadline,
     Json(payload): Json<BatchExecRequest>,
 ) -> Result<Json<BatchExecResponse>, (StatusCode, Json<ApiError>)> {
     let source = activity::source_from_headers(&headers);
@@ -234,8 +686,26 @@ pub async fn batch_exec(
         .unwrap_or(&state.config.shell.default_working_dir);
     let expanded_default_dir = crate::util::expand_tilde(default_dir);
 
-    let mut results = Vec::with_capacity(payload.commands.len());
+    // Resolve every command's sudo options up front so a bad entry rejects
+    // the batch before anything has run.
+    let sudos = payload
+        .commands
+        .iter()
+        .map(|cmd| sudo::resolve_exec(cmd.sudo.as_ref().or(payload.sudo.as_ref())))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+        })?;
     for cmd in &payload.commands {
+        policy::check(&state, source, &cmd.command).await?;
+    }
+    state
+        .ai_guard
+        .charge_execs(&headers, payload.commands.len() as u64)?;
+
+    let summarize = wants_summary(source, payload.full_output);
+    let mut results = Vec::with_capacity(payload.commands.len());
+    for (cmd, sudo) in payload.commands.iter().zip(&sudos) {
         let merged_env = merge_env(payload.env.as_ref(), cmd.env.as_ref());
         let resp = run_batch_command(
             &state,
@@ -244,6 +714,9 @@ pub async fn batch_exec(
             default_shell,
             &expanded_default_dir,
             merged_env.as_ref(),
+            sudo.as_ref(),
+            summarize,
+            &deadline,
             req_id.clone(),
         )
         .await;
@@ -258,27 +731,41 @@ pub async fn batch_exec(
 
 // ── Shared helpers ────────────────────────────────────────────────────
 
-/// Log a successful exec to the activity log and cache the result.
+/// Log a successful exec to the activity log and cache the result, with its
+/// summary if the output was large. Returns the summary and activity id.
 async fn log_exec_ok(
     state: &AppState,
     source: activity::ActivitySource,
     command: &str,
     result: &process::ExecResult,
     request_id: Option<String>,
-) {
+) -> Option<(OutputSummary, u64)> {
+    let summary = summary::summarize(
+        &state.config.summary,
+        command,
+        &result.stdout,
+        &result.stderr,
+    )
+    .await;
+    state.metrics.record_exec("ok", result.duration_ms);
+    let mut detail = json!({
+        "exit_code": result.exit_code,
+        "duration_ms": result.duration_ms,
+        "risk": classify::classify(&state.config.classify, command),
+        "stdout_preview": activity::truncate_str(&result.stdout, 200),
+        "stderr_preview": activity::truncate_str(&result.stderr, 200),
+        "has_full_output": true,
+    });
+    if let Some(enrichment) = hooks::enrich_exec(state, source, command, result) {
+        detail["enrichment"] = enrichment;
+    }
     let activity_id = state
         .activity_log
         .log(
             ActivityType::Exec,
             source,
             activity::truncate_str(command, 80),
-            Some(json!({
-                "exit_code": result.exit_code,
-                "duration_ms": result.duration_ms,
-                "stdout_preview": activity::truncate_str(&result.stdout, 200),
-                "stderr_preview": activity::truncate_str(&result.stderr, 200),
-                "has_full_output": true,
-            })),
+            Some(detail),
             request_id,
         )
         .await;
@@ -293,8 +780,11 @@ async fn log_exec_ok(
             command: command.to_string(),
             status: "ok".to_string(),
             error_message: None,
+            summary: summary.clone(),
+            truncated: false,
         })
         .await;
+    summary.map(|s| (s, activity_id))
 }
 
 /// Log a failed exec (timeout or spawn error) to the activity log and cache the result.
@@ -307,6 +797,7 @@ async fn log_exec_err(
     duration_ms: u64,
     request_id: Option<String>,
 ) {
+    state.metrics.record_exec(status, duration_ms);
     let activity_id = state
         .activity_log
         .log(
@@ -316,6 +807,7 @@ async fn log_exec_err(
             Some(json!({
                 "exit_code": -1,
                 "duration_ms": duration_ms,
+                "risk": classify::classify(&state.config.classify, command),
                 "status": status,
                 "error": error_msg,
                 "has_full_output": true,
@@ -334,6 +826,8 @@ async fn log_exec_err(
             command: command.to_string(),
             status: status.to_string(),
             error_message: Some(error_msg.to_string()),
+            summary: None,
+            truncated: false,
         })
         .await;
 }
@@ -356,6 +850,7 @@ fn merge_env(
 }
 
 /// Execute a single command within a batch, logging the result.
+#[allow(clippy::too_many_arguments)]
 async fn run_batch_command(
     state: &AppState,
     source: activity::ActivitySource,
@@ -363,33 +858,90 @@ async fn run_batch_command(
     default_shell: &str,
     default_dir: &str,
     env: Option<&HashMap<String, String>>,
+    sudo: Option<&ExecSudo>,
+    summarize: bool,
+    deadline: &RequestDeadline,
     req_id: Option<String>,
 ) -> ExecResponse {
+    if deadline.is_expired() {
+        return ExecResponse {
+            exit_code: -1,
+            stdout: String::new(),
+            stderr: "Request deadline passed; command not run".to_string(),
+            duration_ms: 0,
+            request_id: None,
+            confirm: None,
+            summary: None,
+        };
+    }
     let shell = cmd.shell.as_deref().unwrap_or(default_shell);
     let raw_dir = cmd.working_dir.as_deref().unwrap_or(default_dir);
     let expanded_dir = crate::util::expand_tilde(raw_dir);
     let working_dir = expanded_dir.as_ref();
-    let timeout = cmd
-        .timeout_ms
-        .unwrap_or(state.config.server.exec_timeout_ms);
-
-    match Box::pin(process::exec_command(
-        shell,
-        working_dir,
-        &cmd.command,
-        timeout,
+    let timeout = deadline.cap_timeout_ms(
+        cmd.timeout_ms
+            .unwrap_or(state.config.server.exec_timeout_ms),
+    );
+    let env = match secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
         env,
-    ))
+    )
     .await
     {
-        Ok(result) => {
-            log_exec_ok(state, source, &cmd.command, &result, req_id).await;
+        Ok(env) => env,
+        Err(error_msg) => {
+            log_exec_err(state, source, &cmd.command, "error", &error_msg, 0, req_id).await;
+            return ExecResponse {
+                exit_code: -1,
+                stdout: String::new(),
+                stderr: error_msg,
+                duration_ms: 0,
+                request_id: None,
+                confirm: None,
+                summary: None,
+            };
+        }
+    };
+    if let Err((_, Json(err))) = hooks::check_exec(state, source, &cmd.command, shell, working_dir)
+    {
+        return ExecResponse {
+            exit_code: -1,
+            stdout: String::new(),
+            stderr: err.message,
+            duration_ms: 0,
+            request_id: None,
+            confirm: None,
+            summary: None,
+        };
+    }
+
+    match deadline
+        .run(Box::pin(process::exec_command_with(
+            shell,
+            working_dir,
+            &cmd.command,
+            timeout,
+            env.env(),
+            sudo,
+        )))
+        .await
+        .unwrap_or(Err(process::ExecError::Timeout))
+    {
+        Ok(mut result) => {
+            env.redact_result(&mut result);
+            let summary = log_exec_ok(state, source, &cmd.command, &result, req_id)
+                .await
+                .filter(|_| summarize)
+                .map(|(s, activity_id)| apply_summary(&mut result, s, activity_id));
             ExecResponse {
                 exit_code: result.exit_code,
                 stdout: result.stdout,
                 stderr: result.stderr,
                 duration_ms: result.duration_ms,
                 request_id: None,
+                confirm: None,
+                summary,
             }
         }
         Err(process::ExecError::Timeout) => {
@@ -409,6 +961,8 @@ async fn run_batch_command(
                 stderr: "Command timed out".to_string(),
                 duration_ms: timeout,
                 request_id: None,
+                confirm: None,
+                summary: None,
             }
         }
         Err(e) => {
@@ -420,7 +974,47 @@ async fn run_batch_command(
                 stderr: error_msg,
                 duration_ms: 0,
                 request_id: None,
+                confirm: None,
+                summary: None,
             }
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::server::{testing, ServerBuilder};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn denied_rollback_refuses_the_whole_exec() {
+        let (mut config, dir) = testing::config("confirm-policy");
+        config.policy =
+            toml::from_str("[[rules]]\naction = \"deny\"\npattern = \"reboot*\"\n").unwrap();
+        let server = ServerBuilder::new(config).build().await;
+        let marker = dir.join("ran");
+
+        let response = server
+            .router()
+            .oneshot(testing::request(
+                "POST",
+                "/api/exec?confirm_within=60",
+                &json!({
+                    "command": format!("touch '{}'", marker.display()),
+                    "rollback": "reboot",
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = testing::body_json(response).await;
+        assert_eq!(body["code"], "POLICY_DENIED");
+        assert_eq!(body["detail"]["command"], "reboot");
+        assert!(!marker.exists());
+        assert!(server.state.exec_confirms.list().is_empty());
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,137 @@
+//! `POST /api/tunnel/expose` — publish a local port at the relay.
+//!
+//! See [`crate::tunnel::expose`] for how the relay serves it.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::tunnel::{expose, forward};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub struct ExposeRequest {
+    pub port: u64,
+    /// Default 3600, at most 86400.
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    /// Bytes/s for all traffic through the link, capped by the relay's
+    /// `[tunnel] expose_rate_limit_bps`. Default: the relay's cap.
+    #[serde(default)]
+    pub rate_limit_bps: Option<u64>,
+}
+
+/// `POST /api/tunnel/expose` — returns `{expose_id, url, token, port,
+/// expires_at, rate_limit_bps}`. `url` carries the token; share it as-is.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — bad port or TTL,
+///   or the relay refused (too many exposures)
+/// - `403 Forbidden` with `{"code":"FORWARD_NOT_ALLOWED"}` — port not in
+///   `[tunnel] forward_ports`
+/// - `409 Conflict` with `{"code":"TUNNEL_DISCONNECTED"}` — no relay answered
+pub async fn expose(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<ExposeRequest>,
+) -> ApiResult<Value> {
+    let allowed = state
+        .config
+        .tunnel
+        .as_ref()
+        .map_or(&[][..], |t| t.forward_ports.as_slice());
+    if !forward::port_allowed(allowed, req.port) {
+        return Err(ApiError::new(
+            codes::FORWARD_NOT_ALLOWED,
+            format!("Port {} is not in [tunnel] forward_ports", req.port),
+        )
+        .into_response_with(StatusCode::FORBIDDEN));
+    }
+    let (relay_url, reply) = state
+        .relay_requests
+        .request_via(
+            json!({
+                "type": "tunnel.expose",
+                "port": req.port,
+                "ttl_secs": req.ttl_secs,
+                "rate_limit_bps": req.rate_limit_bps,
+            }),
+            RELAY_TIMEOUT,
+        )
+        .await
+        .map_err(disconnected)?;
+    let mut body = relay_body(&reply)?;
+    let url = expose::expose_url(
+        &relay_url,
+        body["expose_id"].as_str().unwrap_or(""),
+        body["token"].as_str().unwrap_or(""),
+    );
+    body["url"] = json!(url);
+
+    state
+        .activity_log
+        .log(
+            ActivityType::Forward,
+            source_from_headers(&headers),
+            format!("Port {} exposed at relay", req.port),
+            Some(json!({
+                "expose_id": body["expose_id"],
+                "port": req.port,
+                "expires_at": body["expires_at"],
+                "rate_limit_bps": body["rate_limit_bps"],
+            })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    Ok(Json(body))
+}
+
+/// `DELETE /api/tunnel/expose/{id}` — withdraw a link before its TTL.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — no such exposure at the
+///   relay
+/// - `409 Conflict` with `{"code":"TUNNEL_DISCONNECTED"}` — no relay answered
+pub async fn unexpose(State(state): State<AppState>, Path(id): Path<String>) -> ApiResult<Value> {
+    let reply = state
+        .relay_requests
+        .request(
+            json!({"type": "tunnel.unexpose", "expose_id": id}),
+            RELAY_TIMEOUT,
+        )
+        .await
+        .map_err(disconnected)?;
+    relay_body(&reply).map(Json)
+}
+
+/// The body of a relay reply, or its error as ours.
+fn relay_body(reply: &Value) -> Result<Value, (StatusCode, Json<ApiError>)> {
+    let message = reply["body"]["error"].as_str().unwrap_or("Relay refused");
+    match reply["status"].as_u64() {
+        Some(200) => Ok(reply["body"].clone()),
+        Some(404) => {
+            Err(ApiError::new(codes::NOT_FOUND, message).into_response_with(StatusCode::NOT_FOUND))
+        }
+        _ => Err(ApiError::new(codes::INVALID_REQUEST, message)
+            .into_response_with(StatusCode::BAD_REQUEST)),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn disconnected(e: String) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::TUNNEL_DISCONNECTED, e).into_response_with(StatusCode::CONFLICT)
+}
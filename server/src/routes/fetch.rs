@@ -0,0 +1,335 @@
+//! `POST /api/fetch` — an HTTP(S) request made from the device.
+//!
+//! The response comes back inline, or with `save_to` is streamed to a
+//! `.gx_tmp_*` file next to the target (the same temp naming gawdxfer
+//! uploads use), hashed, and renamed into place once complete. A failed or
+//! timed-out fetch leaves nothing behind. See [`crate::fetch`] for the
+//! allowlist and redirect rules.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::fetch::{self, FetchError};
+use crate::routes::files::{rename_temp_to_final, validate_path};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+type RouteError = (StatusCode, Json<ApiError>);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FetchRequest {
+    pub url: String,
+    /// Default `GET`.
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Request body, sent as-is.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Capped at `[fetch] timeout_secs`.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Capped at `[fetch] max_bytes`.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Absolute path to save a 2xx response body to instead of returning it.
+    #[serde(default)]
+    pub save_to: Option<String>,
+    /// Octal mode for the saved file, e.g. `"0755"`.
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub create_dirs: bool,
+}
+
+/// What came back, before it is turned into the reply.
+struct Outcome {
+    fetched_url: String,
+    status: u16,
+    headers: Map<String, Value>,
+    size: u64,
+    /// Inline body; `None` when saved.
+    body: Option<Vec<u8>>,
+}
+
+/// `POST /api/fetch` — body [`FetchRequest`], returns
+/// `{url, status, headers, size, duration_ms}` plus either `body` (and
+/// `encoding: "base64"` when it isn't UTF-8) or `saved_to` and `sha256`.
+/// A non-2xx response is returned inline even with `save_to`, and nothing
+/// is written.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — malformed URL,
+///   method or header
+/// - `400 Bad Request` with `{"code":"INVALID_PATH"}` / `{"code":"INVALID_MODE"}`
+///   — unusable `save_to` or `mode`
+/// - `403 Forbidden` with `{"code":"FETCH_NOT_ALLOWED"}` — the URL or a
+///   redirect target isn't in `[fetch] allow`, or the list is empty
+/// - `502 Bad Gateway` with `{"code":"FETCH_FAILED"}` — connect, TLS or HTTP
+///   failure, or too many redirects
+/// - `502 Bad Gateway` with `{"code":"FILE_TOO_LARGE"}` — the body exceeds
+///   `max_bytes`
+/// - `504 Gateway Timeout` with `{"code":"TIMEOUT"}`
+/// - `507 Insufficient Storage` with `{"code":"DISK_FULL"}`, or `500`
+///   with `{"code":"IO_ERROR"}` — the file couldn't be written
+pub async fn fetch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<FetchRequest>,
+) -> ApiResult<Value> {
+    run(&state, &headers, req).await
+}
+
+/// Perform a fetch for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`fetch`].
+pub async fn run(state: &AppState, headers: &HeaderMap, req: FetchRequest) -> ApiResult<Value> {
+    let config = &state.config.fetch;
+    if config.allow.is_empty() {
+        return Err(ApiError::new(
+            codes::FETCH_NOT_ALLOWED,
+            "Fetching is disabled: [fetch] allow is empty",
+        )
+        .into_response_with(StatusCode::FORBIDDEN));
+    }
+    let save_to = req.save_to.as_deref().map(validate_path).transpose()?;
+    let mode = req
+        .mode
+        .as_deref()
+        .map(|m| {
+            u32::from_str_radix(m, 8).map_err(|_| {
+                ApiError::new(codes::INVALID_MODE, format!("Invalid octal mode: {m:?}"))
+                    .into_response_with(StatusCode::BAD_REQUEST)
+            })
+        })
+        .transpose()?;
+    let mut limit = req
+        .max_bytes
+        .map_or(config.max_bytes, |m| m.min(config.max_bytes));
+    if save_to.is_none() {
+        limit = limit.min(state.config.server.max_file_size as u64);
+    }
+    let max_ms = config.timeout_secs.saturating_mul(1000);
+    let timeout = Duration::from_millis(req.timeout_ms.map_or(max_ms, |t| t.min(max_ms)));
+    let method = req.method.as_deref().unwrap_or("GET").to_ascii_uppercase();
+    let temp = save_to
+        .as_ref()
+        .map(|p| p.with_file_name(format!(".gx_tmp_fetch_{}", uuid::Uuid::new_v4().simple())));
+
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(
+        timeout,
+        transfer(state, headers, &req, &method, temp.as_deref(), limit),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        Err(ApiError::new(
+            codes::TIMEOUT,
+            format!("Fetch timed out after {}ms", timeout.as_millis()),
+        )
+        .into_response_with(StatusCode::GATEWAY_TIMEOUT))
+    });
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            if let Some(temp) = &temp {
+                let _ = tokio::fs::remove_file(temp).await;
+            }
+            return Err(e);
+        }
+    };
+
+    let mut reply = json!({
+        "url": outcome.fetched_url,
+        "status": outcome.status,
+        "headers": outcome.headers,
+        "size": outcome.size,
+    });
+    let mut saved = None;
+    match (outcome.body, temp, save_to) {
+        (Some(body), _, _) => match String::from_utf8(body) {
+            Ok(text) => reply["body"] = json!(text),
+            Err(e) => {
+                use base64::Engine;
+                reply["body"] =
+                    json!(base64::engine::general_purpose::STANDARD.encode(e.as_bytes()));
+                reply["encoding"] = json!("base64");
+            }
+        },
+        (None, Some(temp), Some(path)) => {
+            let sha256 = save(&temp, &path, mode).await?;
+            reply["saved_to"] = json!(path.to_string_lossy());
+            reply["sha256"] = json!(sha256);
+            saved = Some(path);
+        }
+        (None, _, _) => {}
+    }
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    reply["duration_ms"] = json!(duration_ms);
+
+    state
+        .activity_log
+        .log(
+            ActivityType::Fetch,
+            source_from_headers(headers),
+            format!(
+                "{method} {} ({})",
+                crate::activity::truncate_str(&req.url, 80),
+                outcome.status
+            ),
+            Some(json!({
+                "url": req.url,
+                "method": method,
+                "status": outcome.status,
+                "size": outcome.size,
+                "saved_to": saved,
+                "sha256": reply.get("sha256"),
+                "duration_ms": duration_ms,
+            })),
+            request_id_from_headers(headers),
+        )
+        .await;
+    Ok(Json(reply))
+}
+
+/// Send the request and read the body, into memory or into `temp` when the
+/// response is a success and there is somewhere to save it.
+async fn transfer(
+    state: &AppState,
+    headers: &HeaderMap,
+    req: &FetchRequest,
+    method: &str,
+    temp: Option<&Path>,
+    limit: u64,
+) -> Result<Outcome, RouteError> {
+    let fetched =
+        fetch::send(
+            &state.config.fetch,
+            method,
+            &req.url,
+            &req.headers,
+            req.body.clone().map(Bytes::from),
+        )
+        .await
+        .map_err(|e| match e {
+            FetchError::Invalid(msg) => ApiError::new(codes::INVALID_REQUEST, msg)
+                .into_response_with(StatusCode::BAD_REQUEST),
+            FetchError::NotAllowed(msg) => ApiError::new(codes::FETCH_NOT_ALLOWED, msg)
+                .into_response_with(StatusCode::FORBIDDEN),
+            FetchError::Failed(msg) => {
+                ApiError::new(codes::FETCH_FAILED, msg).into_response_with(StatusCode::BAD_GATEWAY)
+            }
+        })?;
+    let too_large = || {
+        ApiError::new(
+            codes::FILE_TOO_LARGE,
+            format!("Response body exceeds {limit} bytes"),
+        )
+        .into_response_with(StatusCode::BAD_GATEWAY)
+    };
+    if fetched.content_length.is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+    let temp = temp.filter(|_| (200..300).contains(&fetched.status));
+
+    let mut file = match temp {
+        Some(temp) => {
+            let dir = temp.parent().unwrap_or(Path::new("/"));
+            if req.create_dirs {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .map_err(|e| io_error(&e))?;
+            }
+            if let Some(len) = fetched.content_length {
+                crate::gawdxfer::manager::check_disk_space(dir, len).map_err(|e| {
+                    ApiError::new(e.code, e.message)
+                        .into_response_with(StatusCode::INSUFFICIENT_STORAGE)
+                })?;
+            }
+            Some(
+                tokio::fs::File::create(temp)
+                    .await
+                    .map_err(|e| io_error(&e))?,
+            )
+        }
+        None => None,
+    };
+    let mut inline = Vec::new();
+    let mut size = 0u64;
+    let mut body = fetched.body;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| {
+            ApiError::new(codes::FETCH_FAILED, format!("Reading the body failed: {e}"))
+                .into_response_with(StatusCode::BAD_GATEWAY)
+        })?;
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+        size += data.len() as u64;
+        if size > limit {
+            return Err(too_large());
+        }
+        match &mut file {
+            Some(file) => file.write_all(&data).await.map_err(|e| io_error(&e))?,
+            None => inline.extend_from_slice(&data),
+        }
+    }
+    if let Some(file) = &mut file {
+        file.sync_all().await.map_err(|e| io_error(&e))?;
+        state.ai_guard.charge_write(headers, size)?;
+    }
+    Ok(Outcome {
+        fetched_url: fetched.url,
+        status: fetched.status,
+        headers: fetched.headers,
+        size,
+        body: file.is_none().then_some(inline),
+    })
+}
+
+/// Hash the downloaded `temp`, apply `mode`, and move it to `path`.
+async fn save(temp: &Path, path: &Path, mode: Option<u32>) -> Result<String, RouteError> {
+    let sha256 = match crate::gawdxfer::hasher::hash_file(temp).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(temp).await;
+            return Err(io_error(&e));
+        }
+    };
+    if let Some(mode) = mode {
+        if let Err(e) = crate::util::set_mode(temp, mode) {
+            let _ = tokio::fs::remove_file(temp).await;
+            return Err(io_error(&e));
+        }
+    }
+    rename_temp_to_final(temp, path).await?;
+    Ok(sha256)
+}
+
+fn io_error(e: &std::io::Error) -> RouteError {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+            .into_response_with(StatusCode::FORBIDDEN)
+    } else {
+        ApiError::new(codes::IO_ERROR, e.to_string())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
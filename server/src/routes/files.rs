@@ -2,6 +2,7 @@
 //!
 //! - `GET  /api/files?path=...`            — read a file
 //! - `GET  /api/files?path=...&list=true`  — list a directory
+//! - `GET  /api/files?path=...&list=true&format=ndjson` — stream a listing
 //! - `PUT  /api/files`                     — write a file (atomic)
 //!
 //! ## Path validation
@@ -14,13 +15,24 @@
 //! Reads and writes are capped at `server.max_file_size` (default 2 MB).
 //! Binary files are returned/accepted with base64 encoding.
 //!
+//! ## Large directories
+//!
+//! Listings take `limit` (entries per page) and return a `continuation_token`
+//! while entries remain. Sorted listings (`name`, `mtime`, `size`) pick a page
+//! with a bounded heap, so only `limit` entries are held at once; `sort=none`
+//! walks the directory in its on-disk order and streams in constant memory.
+//! `glob`, `dirs_only` and `files_only` filter before paging. The token is
+//! opaque: the sort key of the last entry, or the count of directory entries
+//! already walked.
+//!
 //! ## Atomicity
 //!
 //! File writes use a temp-file-then-rename pattern. On the same filesystem this
 //! is atomic — readers never see a partially-written file. Cross-filesystem
 //! renames will fail.
 
-use std::os::unix::fs::PermissionsExt;
+use std::collections::BinaryHeap;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::SystemTime;
@@ -32,20 +44,25 @@ use axum::{
     body::Body,
     extract::{Multipart, Query, State},
     http::{HeaderMap, StatusCode},
-    response::Response,
+    response::{IntoResponse, Response},
     Json,
 };
+use base64::Engine;
+use futures::stream::{self, BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use crate::activity::{self, request_id_from_headers, ActivityType};
+use crate::deadline::RequestDeadline;
 use crate::error::{codes, ApiError};
+use crate::xattr::{self, Attrs, Xattr};
 use crate::AppState;
 
 type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
 
 /// Query parameters for `GET /api/files`.
 #[derive(Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FilesQuery {
     /// Absolute path to the file or directory.
     pub path: String,
@@ -56,11 +73,40 @@ pub struct FilesQuery {
     /// Byte offset to start reading from (for partial reads).
     #[serde(default)]
     pub offset: Option<u64>,
-    /// Maximum number of bytes to read (for partial reads).
+    /// Maximum number of bytes to read (for partial reads), or of entries
+    /// per page when listing (capped at 10 000).
     #[serde(default)]
     pub limit: Option<usize>,
+    /// Listing order: `name` (default), `mtime`, `size`, or `none`
+    /// (directory order).
+    #[serde(default)]
+    pub sort: Option<String>,
+    /// `asc` (default) or `desc`.
+    #[serde(default)]
+    pub order: Option<String>,
+    /// Only list names matching this shell pattern (`*`, `?`, `[...]`).
+    #[serde(default)]
+    pub glob: Option<String>,
+    /// Only list directories.
+    #[serde(default)]
+    pub dirs_only: bool,
+    /// Only list regular files.
+    #[serde(default)]
+    pub files_only: bool,
+    /// Token from the previous listing page.
+    #[serde(default)]
+    pub continuation_token: Option<String>,
+    /// `json` (default) or `ndjson` to stream a listing one entry per line.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Include extended attributes and POSIX ACLs.
+    #[serde(default)]
+    pub xattrs: bool,
 }
 
+/// Upper bound on `limit` for a listing page.
+const MAX_LIST_PAGE: usize = 10_000;
+
 /// JSON response for a successful file read.
 #[derive(Serialize)]
 pub struct FileReadResponse {
@@ -76,6 +122,9 @@ pub struct FileReadResponse {
     /// `"base64"` for binary files, absent for UTF-8 text.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<String>,
+    /// `xattrs`, `acl` and `default_acl`, when requested with `xattrs=true`.
+    #[serde(flatten)]
+    pub attrs: Option<Attrs>,
     /// `true` when the file is larger than the returned content (partial read).
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub truncated: bool,
@@ -88,6 +137,10 @@ pub struct DirListResponse {
     pub path: String,
     /// Sorted entries in the directory.
     pub entries: Vec<DirEntry>,
+    /// Pass back as `continuation_token` for the next page. Absent on the
+    /// last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
 }
 
 /// A single entry within a [`DirListResponse`].
@@ -109,6 +162,38 @@ pub struct DirEntry {
     /// For symlinks, the target path. Absent for other types.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub symlink_target: Option<String>,
+    /// `xattrs`, `acl` and `default_acl`, when requested with `xattrs=true`.
+    #[serde(flatten)]
+    pub attrs: Option<Attrs>,
+}
+
+/// Request body for `PUT /api/files/xattrs`.
+#[derive(Deserialize)]
+pub struct XattrsRequest {
+    /// Absolute path of the file or directory.
+    pub path: String,
+    /// Attributes to create or replace.
+    #[serde(default)]
+    pub set: Vec<Xattr>,
+    /// Attribute names to remove. Names that aren't set are ignored.
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Replace the access ACL. An empty list removes it, leaving the mode bits.
+    #[serde(default)]
+    pub acl: Option<Vec<String>>,
+    /// Replace a directory's default ACL. An empty list removes it.
+    #[serde(default)]
+    pub default_acl: Option<Vec<String>>,
+}
+
+/// JSON response for `PUT /api/files/xattrs`.
+#[derive(Serialize)]
+pub struct XattrsResponse {
+    /// Path that was changed.
+    pub path: String,
+    /// Attributes after the change.
+    #[serde(flatten)]
+    pub attrs: Attrs,
 }
 
 /// Request body for `DELETE /api/files`.
@@ -180,29 +265,92 @@ fn format_system_time(time: SystemTime) -> Option<String> {
         .map(|d| d.as_secs().to_string())
 }
 
-/// `GET /api/files` — read a file or list a directory.
+/// `GET /api/files` — read a file or list a directory, or stream a listing
+/// as NDJSON with `format=ndjson`.
+///
+/// NDJSON listings carry one [`DirEntry`] per line, followed by
+/// `{"continuation_token": ...}` when the page is full and entries remain.
+/// Errors are as for [`read_or_list`].
+pub async fn get_file(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    deadline: RequestDeadline,
+    Query(query): Query<FilesQuery>,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    match query.format.as_deref() {
+        None | Some("json") => {}
+        Some("ndjson") if is_listing(&query) => {
+            let path = validate_path(&query.path)?;
+            let items = list_stream(path, ListOptions::from_query(&query)?).await?;
+            state
+                .activity_log
+                .log(
+                    ActivityType::FileList,
+                    activity::source_from_headers(&headers),
+                    activity::truncate_str(&query.path, 80),
+                    None,
+                    request_id_from_headers(&headers),
+                )
+                .await;
+            let lines = items.map(|item| {
+                let value = match item {
+                    ListItem::Entry(entry) => serde_json::to_value(entry).unwrap_or_default(),
+                    ListItem::Next(token) => json!({ "continuation_token": token }),
+                };
+                Ok::<_, std::convert::Infallible>(format!("{value}\n"))
+            });
+            return Ok(Response::builder()
+                .header("Content-Type", "application/x-ndjson")
+                .body(Body::from_stream(lines))
+                .unwrap());
+        }
+        Some("ndjson") => {
+            return Err(ApiError::new(
+                codes::INVALID_REQUEST,
+                "format=ndjson applies to directory listings",
+            )
+            .into_response_with(StatusCode::BAD_REQUEST));
+        }
+        Some(other) => {
+            return Err(ApiError::new(
+                codes::INVALID_REQUEST,
+                format!("Unknown format '{other}' (expected json or ndjson)"),
+            )
+            .into_response_with(StatusCode::BAD_REQUEST));
+        }
+    }
+    read_or_list(State(state), headers, deadline, Query(query))
+        .await
+        .map(IntoResponse::into_response)
+}
+
+/// Read a file or list a directory as a single JSON document. Shared by
+/// [`get_file`] and the tunnel's `tunnel.file.read`.
 ///
 /// # Error codes
 ///
 /// | HTTP | Code               | Meaning                          |
 /// |------|--------------------|----------------------------------|
+/// | 400  | `INVALID_REQUEST`  | Bad listing option or token      |
 /// | 400  | `INVALID_PATH`     | Path is relative, has `..`, etc. |
 /// | 400  | `IS_DIRECTORY`     | Path is a dir but `list` is off  |
 /// | 400  | `FILE_TOO_LARGE`   | File exceeds `max_file_size`     |
 /// | 403  | `PERMISSION_DENIED`| OS permission error              |
 /// | 404  | `FILE_NOT_FOUND`   | File or directory does not exist |
 /// | 500  | `IO_ERROR`         | Other I/O failure                |
-pub async fn get_file(
+/// | 504  | `TIMEOUT`          | Request deadline passed mid-read |
+pub async fn read_or_list(
     State(state): State<AppState>,
     headers: HeaderMap,
+    deadline: RequestDeadline,
     Query(query): Query<FilesQuery>,
 ) -> ApiResult<Value> {
     let source = activity::source_from_headers(&headers);
     let req_id = request_id_from_headers(&headers);
     let path = validate_path(&query.path)?;
 
-    if query.list || query.path.ends_with('/') {
-        let result = list_directory(&path).await?;
+    if is_listing(&query) {
+        let result = list_directory(path, ListOptions::from_query(&query)?).await?;
         state
             .activity_log
             .log(
@@ -216,13 +364,28 @@ pub async fn get_file(
         return Ok(result);
     }
 
-    let result = read_file(
-        &path,
-        state.config.server.max_file_size,
-        query.offset,
-        query.limit,
-    )
-    .await?;
+    let attrs = if query.xattrs {
+        Some(
+            read_attrs(path.clone())
+                .await
+                .map_err(|e| xattr_error(&e))?,
+        )
+    } else {
+        None
+    };
+    let result = deadline
+        .run(read_file(
+            &path,
+            state.config.server.max_file_size,
+            query.offset,
+            query.limit,
+            attrs,
+        ))
+        .await
+        .ok_or_else(|| {
+            ApiError::new(codes::TIMEOUT, "Request deadline exceeded")
+                .into_response_with(StatusCode::GATEWAY_TIMEOUT)
+        })??;
     state
         .activity_log
         .log(
@@ -248,6 +411,7 @@ async fn read_file(
     max_size: usize,
     offset: Option<u64>,
     limit: Option<usize>,
+    attrs: Option<Attrs>,
 ) -> ApiResult<Value> {
     let metadata = match tokio::fs::metadata(path).await {
         Ok(m) => m,
@@ -288,145 +452,536 @@ async fn read_file(
 
     let modified = metadata.modified().ok().and_then(format_system_time);
 
-    // Open, seek, and read up to `read_limit` bytes.
-    let bytes = {
-        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    // Open, seek, read up to `read_limit` bytes and encode on the I/O pool.
+    #[allow(clippy::cast_possible_truncation)]
+    let to_read = read_limit.min(file_size.saturating_sub(read_offset) as usize);
+    let read_path = path.to_path_buf();
+    let (content, encoding, len) = crate::io_pool::run(move || {
+        use std::io::{Read, Seek};
 
-        let mut file = match tokio::fs::File::open(path).await {
-            Ok(f) => f,
-            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                return Err(ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
-                    .into_response_with(StatusCode::FORBIDDEN));
-            }
+        let mut file = std::fs::File::open(&read_path)?;
+        if read_offset > 0 {
+            file.seek(std::io::SeekFrom::Start(read_offset))?;
+        }
+        let mut bytes = Vec::with_capacity(to_read);
+        file.take(to_read as u64).read_to_end(&mut bytes)?;
+        let len = bytes.len();
+
+        // Try to interpret as UTF-8; fall back to base64 for binary files.
+        Ok(match String::from_utf8(bytes) {
+            Ok(text) => (text, None, len),
             Err(e) => {
-                return Err(ApiError::new(codes::IO_ERROR, e.to_string())
-                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+                use base64::Engine;
+                let encoded = base64::engine::general_purpose::STANDARD.encode(e.as_bytes());
+                (encoded, Some("base64".to_string()), len)
             }
+        })
+    })
+    .await
+    .map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+                .into_response_with(StatusCode::FORBIDDEN)
+        } else {
+            ApiError::new(codes::IO_ERROR, e.to_string())
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    })?;
+
+    let truncated = (read_offset + len as u64) < file_size;
+
+    Ok(Json(
+        serde_json::to_value(FileReadResponse {
+            path: path.to_string_lossy().into_owned(),
+            content,
+            size: file_size,
+            modified,
+            encoding,
+            truncated,
+            attrs,
+        })
+        .unwrap(),
+    ))
+}
+
+fn is_listing(query: &FilesQuery) -> bool {
+    query.list || query.path.ends_with('/')
+}
+
+/// Listing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListSort {
+    /// By raw name bytes.
+    Name,
+    /// By modification time, then name.
+    Mtime,
+    /// By size, then name.
+    Size,
+    /// Directory order, whatever `readdir` returns.
+    Unsorted,
+}
+
+impl ListSort {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Mtime => "mtime",
+            Self::Size => "size",
+            Self::Unsorted => "none",
+        }
+    }
+}
+
+/// Which entry types a listing keeps. Types come from `lstat`, so a symlink
+/// is neither a file nor a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TypeFilter {
+    Any,
+    Dirs,
+    Files,
+}
+
+/// Position of an entry in a sorted listing: the sort's primary key (0 for
+/// `name`), then the raw name. `desc` flips the comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SortKey {
+    primary: i128,
+    name: Vec<u8>,
+    desc: bool,
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let ord = (self.primary, &self.name).cmp(&(other.primary, &other.name));
+        if self.desc {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Where the next page starts.
+#[derive(Debug, PartialEq, Eq)]
+enum Cursor {
+    /// After this key (sorted listings).
+    After { primary: i128, name: Vec<u8> },
+    /// After this many directory entries, filtered or not (`sort=none`).
+    Skip(u64),
+}
+
+impl Cursor {
+    /// Encode with `tag` naming the sort and order, so a token can't be
+    /// replayed against a different one.
+    fn encode(&self, tag: &str) -> String {
+        let raw = match self {
+            Self::After { primary, name } => {
+                [format!("{tag}:{primary}:").as_bytes(), name].concat()
+            }
+            Self::Skip(n) => format!("{tag}:{n}").into_bytes(),
         };
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
 
-        if read_offset > 0 {
-            file.seek(std::io::SeekFrom::Start(read_offset))
-                .await
-                .map_err(|e| {
-                    ApiError::new(codes::IO_ERROR, e.to_string())
-                        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
-                })?;
+    fn decode(token: &str, tag: &str) -> Option<Self> {
+        let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()?;
+        let rest = raw.strip_prefix(tag.as_bytes())?.strip_prefix(b":")?;
+        if tag == ListSort::Unsorted.as_str() {
+            return std::str::from_utf8(rest).ok()?.parse().ok().map(Self::Skip);
         }
+        let colon = rest.iter().position(|&b| b == b':')?;
+        let primary = std::str::from_utf8(&rest[..colon]).ok()?.parse().ok()?;
+        Some(Self::After {
+            primary,
+            name: rest[colon + 1..].to_vec(),
+        })
+    }
+}
 
-        #[allow(clippy::cast_possible_truncation)]
-        let remaining = file_size.saturating_sub(read_offset) as usize;
-        let to_read = read_limit.min(remaining);
-        let mut buf = vec![0u8; to_read];
-        let n = file.read(&mut buf).await.map_err(|e| {
-            ApiError::new(codes::IO_ERROR, e.to_string())
-                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
-        })?;
-        buf.truncate(n);
-        buf
-    };
+struct ListOptions {
+    sort: ListSort,
+    desc: bool,
+    limit: Option<usize>,
+    cursor: Option<Cursor>,
+    glob: Option<CString>,
+    types: TypeFilter,
+    xattrs: bool,
+}
 
-    let truncated = (read_offset + bytes.len() as u64) < file_size;
-
-    // Try to interpret as UTF-8; fall back to base64 for binary files.
-    let path_str = path.to_string_lossy().into_owned();
-    if std::str::from_utf8(&bytes).is_ok() {
-        let text = String::from_utf8(bytes).expect("validated UTF-8 above");
-        Ok(Json(
-            serde_json::to_value(FileReadResponse {
-                path: path_str,
-                content: text,
-                size: file_size,
-                modified,
-                encoding: None,
-                truncated,
-            })
-            .unwrap(),
-        ))
-    } else {
-        use base64::Engine;
-        let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
-        Ok(Json(
-            serde_json::to_value(FileReadResponse {
-                path: path_str,
-                content: encoded,
-                size: file_size,
-                modified,
-                encoding: Some("base64".to_string()),
-                truncated,
-            })
-            .unwrap(),
-        ))
+impl ListOptions {
+    fn from_query(query: &FilesQuery) -> Result<Self, (StatusCode, Json<ApiError>)> {
+        let invalid = |msg: String| {
+            ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+        };
+        let sort = match query.sort.as_deref() {
+            None | Some("name") => ListSort::Name,
+            Some("mtime") => ListSort::Mtime,
+            Some("size") => ListSort::Size,
+            Some("none") => ListSort::Unsorted,
+            Some(other) => {
+                return Err(invalid(format!(
+                    "Unknown sort '{other}' (expected name, mtime, size or none)"
+                )))
+            }
+        };
+        let desc = match query.order.as_deref() {
+            None | Some("asc") => false,
+            Some("desc") if sort != ListSort::Unsorted => true,
+            Some("desc") => return Err(invalid("order=desc needs a sort".into())),
+            Some(other) => {
+                return Err(invalid(format!(
+                    "Unknown order '{other}' (expected asc or desc)"
+                )))
+            }
+        };
+        let types = match (query.dirs_only, query.files_only) {
+            (false, false) => TypeFilter::Any,
+            (true, false) => TypeFilter::Dirs,
+            (false, true) => TypeFilter::Files,
+            (true, true) => {
+                return Err(invalid(
+                    "dirs_only and files_only are mutually exclusive".into(),
+                ))
+            }
+        };
+        let glob = query
+            .glob
+            .as_deref()
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| invalid("glob must not contain NUL bytes".into()))?;
+        let mut opts = Self {
+            sort,
+            desc,
+            limit: query.limit.map(|l| l.clamp(1, MAX_LIST_PAGE)),
+            cursor: None,
+            glob,
+            types,
+            xattrs: query.xattrs,
+        };
+        if let Some(token) = query.continuation_token.as_deref() {
+            opts.cursor = Some(Cursor::decode(token, &opts.tag()).ok_or_else(|| {
+                invalid("continuation_token is invalid or was issued for another sort".into())
+            })?);
+        }
+        Ok(opts)
+    }
+
+    /// Names the sort and order for [`Cursor::encode`].
+    fn tag(&self) -> String {
+        match (self.sort, self.desc) {
+            (ListSort::Unsorted, _) => ListSort::Unsorted.as_str().to_string(),
+            (sort, false) => format!("{}-asc", sort.as_str()),
+            (sort, true) => format!("{}-desc", sort.as_str()),
+        }
+    }
+
+    /// Whether an entry passes `glob` and the type filter. `file_type` is
+    /// only looked at (and need only be fetched) when a type filter is set.
+    fn keeps(&self, name: &OsStr, file_type: Option<std::fs::FileType>) -> bool {
+        let types_ok = match self.types {
+            TypeFilter::Any => true,
+            TypeFilter::Dirs => file_type.is_some_and(|t| t.is_dir()),
+            TypeFilter::Files => file_type.is_some_and(|t| t.is_file()),
+        };
+        types_ok && self.glob.as_deref().is_none_or(|g| glob_match(g, name))
     }
 }
 
-/// List a directory's contents, sorted by name.
-async fn list_directory(path: &Path) -> ApiResult<Value> {
-    let mut read_dir = match tokio::fs::read_dir(path).await {
-        Ok(rd) => rd,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            return Err(ApiError::new(codes::FILE_NOT_FOUND, "Directory not found")
-                .into_response_with(StatusCode::NOT_FOUND));
+/// Shell-style match of a file name: `*`, `?` and `[...]`.
+fn glob_match(pattern: &CStr, name: &OsStr) -> bool {
+    CString::new(name.as_encoded_bytes())
+        .is_ok_and(|name| crate::shell::classify::glob(pattern, &name))
+}
+
+/// Signed nanoseconds since the epoch, so pre-1970 times still sort.
+fn epoch_nanos(t: SystemTime) -> i128 {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => i128::try_from(d.as_nanos()).unwrap_or(i128::MAX),
+        Err(e) => -i128::try_from(e.duration().as_nanos()).unwrap_or(i128::MAX),
+    }
+}
+
+/// One item of a listing: an entry, or the token for the next page (always
+/// last).
+enum ListItem {
+    Entry(DirEntry),
+    Next(String),
+}
+
+/// The first `limit` keys after a cursor, keeping at most `limit + 1` keys
+/// however large the directory is.
+struct Page {
+    after: Option<SortKey>,
+    limit: Option<usize>,
+    heap: BinaryHeap<SortKey>,
+}
+
+impl Page {
+    fn new(after: Option<SortKey>, limit: Option<usize>) -> Self {
+        Self {
+            after,
+            limit,
+            heap: BinaryHeap::new(),
         }
-        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-            return Err(ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
-                .into_response_with(StatusCode::FORBIDDEN));
+    }
+
+    fn push(&mut self, key: SortKey) {
+        if self.after.as_ref().is_some_and(|after| key <= *after) {
+            return;
         }
-        Err(e) => {
-            return Err(ApiError::new(codes::IO_ERROR, e.to_string())
-                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+        self.heap.push(key);
+        // One extra tells us whether another page follows.
+        if self.limit.is_some_and(|limit| self.heap.len() > limit + 1) {
+            self.heap.pop();
+        }
+    }
+
+    /// The page's keys in order and whether more follow.
+    fn finish(self) -> (Vec<SortKey>, bool) {
+        let mut keys = self.heap.into_sorted_vec();
+        let more = self.limit.is_some_and(|limit| keys.len() > limit);
+        if let Some(limit) = self.limit {
+            keys.truncate(limit);
+        }
+        (keys, more)
+    }
+}
+
+fn read_dir_error(e: &std::io::Error) -> (StatusCode, Json<ApiError>) {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ApiError::new(codes::FILE_NOT_FOUND, "Directory not found")
+            .into_response_with(StatusCode::NOT_FOUND),
+        std::io::ErrorKind::PermissionDenied => {
+            ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+                .into_response_with(StatusCode::FORBIDDEN)
+        }
+        _ => ApiError::new(codes::IO_ERROR, e.to_string())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Read a path's xattrs and ACLs off the runtime.
+async fn read_attrs(path: PathBuf) -> std::io::Result<Attrs> {
+    crate::io_pool::run(move || xattr::read_all(&path)).await
+}
+
+fn xattr_error(e: &std::io::Error) -> (StatusCode, Json<ApiError>) {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ApiError::new(codes::FILE_NOT_FOUND, "File not found")
+            .into_response_with(StatusCode::NOT_FOUND),
+        std::io::ErrorKind::PermissionDenied => {
+            ApiError::new(codes::PERMISSION_DENIED, "Permission denied")
+                .into_response_with(StatusCode::FORBIDDEN)
+        }
+        _ if e.raw_os_error() == Some(libc::ENOTSUP) => ApiError::new(
+            codes::XATTR_UNSUPPORTED,
+            "Filesystem does not support this attribute",
+        )
+        .into_response_with(StatusCode::BAD_REQUEST),
+        std::io::ErrorKind::InvalidInput => {
+            ApiError::new(codes::INVALID_REQUEST, format!("Rejected: {e}"))
+                .into_response_with(StatusCode::BAD_REQUEST)
         }
+        _ => ApiError::new(codes::IO_ERROR, e.to_string())
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Stat one directory entry, with its xattrs and ACLs if `with_attrs`.
+async fn describe_entry(dir: &Path, name: OsString, with_attrs: bool) -> DirEntry {
+    let path = dir.join(&name);
+    // lstat, so symlinks are reported as themselves.
+    let metadata = tokio::fs::symlink_metadata(&path).await.ok();
+    let file_type = metadata.as_ref().map(std::fs::Metadata::file_type);
+
+    let (entry_type, symlink_target) = if file_type
+        .as_ref()
+        .is_some_and(std::fs::FileType::is_symlink)
+    {
+        let target = tokio::fs::read_link(&path)
+            .await
+            .ok()
+            .map(|p: PathBuf| p.to_string_lossy().into_owned());
+        ("symlink".to_string(), target)
+    } else if file_type.as_ref().is_some_and(std::fs::FileType::is_dir) {
+        ("dir".to_string(), None)
+    } else if file_type.as_ref().is_some_and(std::fs::FileType::is_file) {
+        ("file".to_string(), None)
+    } else {
+        ("other".to_string(), None)
     };
 
-    let mut entries = Vec::new();
-    while let Ok(Some(entry)) = read_dir.next_entry().await {
-        let name = entry.file_name().to_string_lossy().into_owned();
-        // file_type() uses lstat (doesn't follow symlinks), so is_symlink() works.
-        // metadata() uses stat (follows symlinks), so we use it only for size/modified.
-        let file_type = entry.file_type().await.ok();
-        let metadata = entry.metadata().await.ok();
-
-        let (entry_type, symlink_target) = if file_type
-            .as_ref()
-            .is_some_and(std::fs::FileType::is_symlink)
-        {
-            let target = tokio::fs::read_link(entry.path())
-                .await
-                .ok()
-                .map(|p: PathBuf| p.to_string_lossy().into_owned());
-            ("symlink".to_string(), target)
-        } else if file_type.as_ref().is_some_and(std::fs::FileType::is_dir) {
-            ("dir".to_string(), None)
-        } else if file_type.as_ref().is_some_and(std::fs::FileType::is_file) {
-            ("file".to_string(), None)
-        } else {
-            ("other".to_string(), None)
-        };
+    let size = metadata.as_ref().map_or(0, std::fs::Metadata::len);
+    let mode = metadata
+        .as_ref()
+        .map(|m| format!("{:04o}", crate::util::file_mode(m)));
+    let modified = metadata
+        .as_ref()
+        .and_then(|m: &std::fs::Metadata| m.modified().ok())
+        .and_then(format_system_time);
+    let attrs = if with_attrs {
+        read_attrs(path).await.ok()
+    } else {
+        None
+    };
 
-        let size = metadata.as_ref().map_or(0, std::fs::Metadata::len);
-        let mode = metadata
-            .as_ref()
-            .map(|m| format!("{:04o}", m.permissions().mode() & 0o7777));
-        let modified = metadata
-            .as_ref()
-            .and_then(|m: &std::fs::Metadata| m.modified().ok())
-            .and_then(format_system_time);
-
-        entries.push(DirEntry {
-            name,
-            entry_type,
-            size,
-            mode,
-            modified,
-            symlink_target,
-        });
+    DirEntry {
+        name: name.to_string_lossy().into_owned(),
+        entry_type,
+        size,
+        mode,
+        modified,
+        symlink_target,
+        attrs,
+    }
+}
+
+/// List one page of a directory as a stream. Entries are stat'ed as the
+/// stream is polled, so a consumer that stops early does no further I/O.
+async fn list_stream(
+    dir: PathBuf,
+    opts: ListOptions,
+) -> Result<BoxStream<'static, ListItem>, (StatusCode, Json<ApiError>)> {
+    let needs_type = opts.types != TypeFilter::Any;
+
+    if opts.sort == ListSort::Unsorted {
+        let mut read_dir = tokio::fs::read_dir(&dir)
+            .await
+            .map_err(|e| read_dir_error(&e))?;
+        let start = match opts.cursor {
+            Some(Cursor::Skip(n)) => n,
+            _ => 0,
+        };
+        for _ in 0..start {
+            if !matches!(read_dir.next_entry().await, Ok(Some(_))) {
+                break;
+            }
+        }
+        // (read_dir, dir, opts, entries consumed, entries emitted)
+        let state = (read_dir, dir, opts, start, 0usize);
+        return Ok(stream::unfold(Some(state), move |state| async move {
+            let (mut read_dir, dir, opts, mut pos, emitted) = state?;
+            loop {
+                let entry = read_dir.next_entry().await.ok().flatten()?;
+                pos += 1;
+                let name = entry.file_name();
+                let file_type = if needs_type {
+                    entry.file_type().await.ok()
+                } else {
+                    None
+                };
+                if !opts.keeps(&name, file_type) {
+                    continue;
+                }
+                if opts.limit.is_some_and(|limit| emitted >= limit) {
+                    // This entry starts the next page.
+                    let token = Cursor::Skip(pos - 1).encode(&opts.tag());
+                    return Some((ListItem::Next(token), None));
+                }
+                let item = ListItem::Entry(describe_entry(&dir, name, opts.xattrs).await);
+                return Some((item, Some((read_dir, dir, opts, pos, emitted + 1))));
+            }
+        })
+        .boxed());
     }
 
-    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    // Sorting needs every entry, so the whole scan runs on the I/O pool.
+    let scan_dir = dir.clone();
+    let (keys, more, opts) = crate::io_pool::run(move || {
+        let after = match opts.cursor {
+            Some(Cursor::After { primary, ref name }) => Some(SortKey {
+                primary,
+                name: name.clone(),
+                desc: opts.desc,
+            }),
+            _ => None,
+        };
+        let mut page = Page::new(after, opts.limit);
+        for entry in std::fs::read_dir(&scan_dir)?.map_while(Result::ok) {
+            let name = entry.file_name();
+            let file_type = if needs_type {
+                entry.file_type().ok()
+            } else {
+                None
+            };
+            if !opts.keeps(&name, file_type) {
+                continue;
+            }
+            let primary = match opts.sort {
+                ListSort::Mtime => entry
+                    .metadata()
+                    .ok()
+                    .and_then(|m| m.modified().ok())
+                    .map_or(0, epoch_nanos),
+                ListSort::Size => entry.metadata().map_or(0, |m| i128::from(m.len())),
+                ListSort::Name | ListSort::Unsorted => 0,
+            };
+            page.push(SortKey {
+                primary,
+                name: name.into_encoded_bytes(),
+                desc: opts.desc,
+            });
+        }
+        let (keys, more) = page.finish();
+        Ok((keys, more, opts))
+    })
+    .await
+    .map_err(|e| read_dir_error(&e))?;
+    let tag = opts.tag();
+    let next = more.then(|| keys.last()).flatten().map(|last| {
+        let cursor = Cursor::After {
+            primary: last.primary,
+            name: last.name.clone(),
+        };
+        ListItem::Next(cursor.encode(&tag))
+    });
+
+    let with_attrs = opts.xattrs;
+    Ok(stream::iter(keys)
+        .then(move |key| {
+            let dir = dir.clone();
+            async move {
+                ListItem::Entry(
+                    // SAFETY: the bytes came from `into_encoded_bytes` above.
+                    describe_entry(
+                        &dir,
+                        unsafe { OsString::from_encoded_bytes_unchecked(key.name) },
+                        with_attrs,
+                    )
+                    .await,
+                )
+            }
+        })
+        .chain(stream::iter(next))
+        .boxed())
+}
+
+/// List a directory page as a single [`DirListResponse`].
+async fn list_directory(path: PathBuf, opts: ListOptions) -> ApiResult<Value> {
+    let mut items = list_stream(path.clone(), opts).await?;
+    let mut entries = Vec::new();
+    let mut continuation_token = None;
+    while let Some(item) = items.next().await {
+        match item {
+            ListItem::Entry(entry) => entries.push(entry),
+            ListItem::Next(token) => continuation_token = Some(token),
+        }
+    }
 
     Ok(Json(
         serde_json::to_value(DirListResponse {
             path: path.to_string_lossy().into_owned(),
             entries,
+            continuation_token,
         })
         .unwrap(),
     ))
@@ -478,6 +1033,7 @@ pub async fn put_file(
         )
         .into_response_with(StatusCode::BAD_REQUEST));
     }
+    state.ai_guard.charge_write(&headers, bytes.len() as u64)?;
 
     if payload.create_dirs {
         if let Some(parent) = path.parent() {
@@ -521,8 +1077,7 @@ pub async fn put_file(
             )
             .into_response_with(StatusCode::BAD_REQUEST)
         })?;
-        let perms = std::fs::Permissions::from_mode(mode);
-        if let Err(e) = tokio::fs::set_permissions(&temp_path, perms).await {
+        if let Err(e) = crate::util::set_mode(&temp_path, mode) {
             let _ = tokio::fs::remove_file(&temp_path).await;
             return Err(
                 ApiError::new(codes::IO_ERROR, format!("Failed to set mode: {e}"))
@@ -583,6 +1138,89 @@ async fn log_file_write(
         .await;
 }
 
+/// `PUT /api/files/xattrs` — set or remove extended attributes and ACLs.
+///
+/// Every value and ACL entry is decoded before anything is changed. Changes
+/// are then applied in order (`set`, `remove`, `acl`, `default_acl`); if one
+/// fails, the earlier ones stay applied. Symlinks are changed themselves, not
+/// followed.
+///
+/// # Error codes
+///
+/// | HTTP | Code                | Meaning                                   |
+/// |------|---------------------|-------------------------------------------|
+/// | 400  | `INVALID_PATH`      | Path validation failed                    |
+/// | 400  | `INVALID_REQUEST`   | Nothing to change, bad value or ACL entry |
+/// | 400  | `XATTR_UNSUPPORTED` | Filesystem lacks xattr/ACL support        |
+/// | 403  | `PERMISSION_DENIED` | Not the owner, or `trusted.*`/`security.*` without root |
+/// | 404  | `FILE_NOT_FOUND`    | Path does not exist                       |
+/// | 500  | `IO_ERROR`          | Other I/O failure                         |
+pub async fn put_xattrs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<XattrsRequest>,
+) -> ApiResult<Value> {
+    let invalid = |msg: String| {
+        ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+    };
+    let path = validate_path(&req.path)?;
+
+    let mut ops: Vec<(String, Option<Vec<u8>>)> = Vec::new();
+    for attr in &req.set {
+        ops.push((attr.name.clone(), Some(attr.bytes().map_err(invalid)?)));
+    }
+    ops.extend(req.remove.iter().map(|name| (name.clone(), None)));
+    for (name, acl) in [
+        (xattr::ACL_ACCESS, &req.acl),
+        (xattr::ACL_DEFAULT, &req.default_acl),
+    ] {
+        match acl {
+            None => {}
+            Some(entries) if entries.is_empty() => ops.push((name.to_string(), None)),
+            Some(entries) => ops.push((
+                name.to_string(),
+                Some(xattr::encode_acl(entries).map_err(invalid)?),
+            )),
+        }
+    }
+    if ops.is_empty() {
+        return Err(invalid("Nothing to change".into()));
+    }
+
+    let changes = ops.len();
+    let target = path.clone();
+    let attrs = crate::io_pool::run(move || {
+        for (name, value) in &ops {
+            match value {
+                Some(value) => xattr::set(&target, name, value)?,
+                None => xattr::remove(&target, name)?,
+            }
+        }
+        xattr::read_all(&target)
+    })
+    .await
+    .map_err(|e| xattr_error(&e))?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::FileWrite,
+            activity::source_from_headers(&headers),
+            activity::truncate_str(&req.path, 80),
+            Some(json!({ "xattrs": changes })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+
+    Ok(Json(
+        serde_json::to_value(XattrsResponse {
+            path: path.to_string_lossy().into_owned(),
+            attrs,
+        })
+        .unwrap(),
+    ))
+}
+
 /// `DELETE /api/files` — delete a file.
 ///
 /// # Error codes
@@ -774,6 +1412,7 @@ pub async fn upload_file(
             )
             .into_response_with(StatusCode::BAD_REQUEST));
         }
+        state.ai_guard.charge_write(&headers, bytes.len() as u64)?;
 
         let final_path = dir_path.join(&file_name);
         let seq = WRITE_COUNTER.fetch_add(1, Ordering::Relaxed);
@@ -812,3 +1451,153 @@ pub async fn upload_file(
         "files": uploaded
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(primary: i128, name: &str, desc: bool) -> SortKey {
+        SortKey {
+            primary,
+            name: name.as_bytes().to_vec(),
+            desc,
+        }
+    }
+
+    fn names(keys: &[SortKey]) -> Vec<&str> {
+        keys.iter()
+            .map(|k| std::str::from_utf8(&k.name).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        for (cursor, tag) in [
+            (
+                Cursor::After {
+                    primary: 0,
+                    name: b"snap-0001.jpg".to_vec(),
+                },
+                "name-asc",
+            ),
+            (
+                Cursor::After {
+                    primary: -1_500_000_000,
+                    name: vec![0xff, b':', 0x00],
+                },
+                "mtime-desc",
+            ),
+            (Cursor::Skip(12_345), "none"),
+        ] {
+            assert_eq!(Cursor::decode(&cursor.encode(tag), tag), Some(cursor));
+        }
+        assert_eq!(Cursor::decode("not base64!", "name-asc"), None);
+    }
+
+    #[test]
+    fn cursor_rejects_other_sort() {
+        let token = Cursor::After {
+            primary: 42,
+            name: b"a".to_vec(),
+        }
+        .encode("size-asc");
+        assert_eq!(Cursor::decode(&token, "size-desc"), None);
+        assert_eq!(Cursor::decode(&token, "size"), None);
+        assert_eq!(Cursor::decode(&token, "none"), None);
+    }
+
+    #[test]
+    fn page_keeps_first_keys_after_cursor() {
+        let all = ["e", "a", "d", "b", "c", "f"];
+        let mut page = Page::new(Some(key(0, "a", false)), Some(2));
+        for name in all {
+            page.push(key(0, name, false));
+            assert!(page.heap.len() <= 3);
+        }
+        let (keys, more) = page.finish();
+        assert_eq!((names(&keys), more), (vec!["b", "c"], true));
+
+        let mut page = Page::new(Some(key(0, "d", false)), Some(2));
+        for name in all {
+            page.push(key(0, name, false));
+        }
+        let (keys, more) = page.finish();
+        assert_eq!((names(&keys), more), (vec!["e", "f"], false));
+    }
+
+    #[test]
+    fn page_descending_by_primary_then_name() {
+        let mut page = Page::new(None, Some(3));
+        for (size, name) in [(10, "a"), (30, "b"), (20, "c"), (30, "d"), (5, "e")] {
+            page.push(key(size, name, true));
+        }
+        let (keys, more) = page.finish();
+        assert_eq!((names(&keys), more), (vec!["d", "b", "c"], true));
+
+        let mut page = Page::new(Some(key(20, "c", true)), Some(3));
+        for (size, name) in [(10, "a"), (30, "b"), (20, "c"), (30, "d"), (5, "e")] {
+            page.push(key(size, name, true));
+        }
+        let (keys, more) = page.finish();
+        assert_eq!((names(&keys), more), (vec!["a", "e"], false));
+    }
+
+    #[test]
+    fn glob_matches_shell_patterns() {
+        let pattern = CString::new("snap-*.jp[e]g").unwrap();
+        assert!(glob_match(&pattern, OsStr::new("snap-0001.jpeg")));
+        assert!(!glob_match(&pattern, OsStr::new("snap-0001.jpg")));
+        assert!(!glob_match(&pattern, OsStr::new("log-0001.jpeg")));
+        let pattern = CString::new("?.log").unwrap();
+        assert!(glob_match(&pattern, OsStr::new("a.log")));
+        assert!(!glob_match(&pattern, OsStr::new("ab.log")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn read_past_its_deadline_times_out() {
+        use crate::server::{testing, ServerBuilder};
+        use std::os::unix::fs::OpenOptionsExt;
+        use tower::ServiceExt;
+
+        let (config, dir) = testing::config("files-deadline");
+        let server = ServerBuilder::new(config).build().await;
+        // Opening a FIFO with no writer blocks, so the read outlives the deadline.
+        let fifo = dir.join("fifo");
+        nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRWXU).unwrap();
+        let get = |deadline_ms: u64| {
+            let mut request = testing::request(
+                "GET",
+                &format!("/api/files?path={}", fifo.display()),
+                &Value::Null,
+            );
+            request.headers_mut().insert(
+                crate::deadline::HEADER,
+                deadline_ms.to_string().parse().unwrap(),
+            );
+            server.router().oneshot(request)
+        };
+
+        let now_ms = crate::sessions::journal::now_ms();
+        let response = get(now_ms + 200).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = testing::body_json(response).await;
+        assert_eq!(body["code"], "TIMEOUT");
+        assert_eq!(body["message"], "Request deadline exceeded");
+
+        let response = get(now_ms - 1000).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = testing::body_json(response).await;
+        assert_eq!(body["message"], "Request deadline already passed");
+
+        // Release the read still blocked on the I/O pool.
+        drop(
+            std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&fifo),
+        );
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
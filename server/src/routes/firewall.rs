@@ -0,0 +1,757 @@
+//! Firewall inspection and templated updates.
+//!
+//! - `GET /api/firewall` — parsed ruleset (nftables, else iptables), the
+//!   available templates, and the state of the last apply
+//! - `POST /api/firewall/apply` — render a named template with variables,
+//!   dry-run it, apply it, and arm an automatic rollback
+//!
+//! Templates live in `[firewall] templates_dir` as `<name>.nft` (loaded with
+//! `nft -f`) or `<name>.iptables` (loaded with `iptables-restore`), with
+//! `{{var}}` placeholders. Before applying, the current ruleset is saved to
+//! `<data_dir>/firewall-rollback.<ext>`. When sctl runs as a tunnel client,
+//! a guard task checks the relay connection once the grace period is over and
+//! restores the saved ruleset if it is down — a rule that cuts the tunnel
+//! undoes itself instead of stranding the device.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::infra::checks::exec_args_pub;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Timeout for `nft` / `iptables-*` invocations.
+const TOOL_TIMEOUT_MS: u64 = 15_000;
+
+/// Default grace period when `[firewall]` is not configured.
+const DEFAULT_GRACE_SECS: u64 = 60;
+
+/// State of the most recent apply, reported by `GET /api/firewall`.
+static LAST_APPLY: std::sync::Mutex<Option<ApplyStatus>> = std::sync::Mutex::new(None);
+
+// ─── Types ───────────────────────────────────────────────────────────────────
+
+/// Ruleset tooling a template targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    Nftables,
+    Iptables,
+}
+
+impl Backend {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Nftables => "nft",
+            Self::Iptables => "iptables",
+        }
+    }
+
+    /// Command that checks a ruleset file without applying it.
+    fn check_args(self, path: &str) -> (&'static str, Vec<String>) {
+        match self {
+            Self::Nftables => ("nft", vec!["-c".into(), "-f".into(), path.into()]),
+            Self::Iptables => ("iptables-restore", vec!["--test".into(), path.into()]),
+        }
+    }
+
+    /// Command that atomically loads a ruleset file.
+    fn apply_args(self, path: &str) -> (&'static str, Vec<String>) {
+        match self {
+            Self::Nftables => ("nft", vec!["-f".into(), path.into()]),
+            Self::Iptables => ("iptables-restore", vec![path.into()]),
+        }
+    }
+}
+
+/// One parsed chain.
+#[derive(Debug, Default, Serialize)]
+struct Chain {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<String>,
+    rules: Vec<Rule>,
+}
+
+/// One rule in its textual form.
+#[derive(Debug, Serialize)]
+struct Rule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handle: Option<u64>,
+    rule: String,
+}
+
+/// One parsed table.
+#[derive(Debug, Serialize)]
+struct Table {
+    family: String,
+    name: String,
+    chains: Vec<Chain>,
+}
+
+/// Lifecycle of an apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ApplyState {
+    /// Waiting for the grace period to end.
+    Pending,
+    /// Relay reachable after the grace period (or no guard): rules kept.
+    Kept,
+    /// Relay unreachable: previous ruleset restored.
+    RolledBack,
+    /// Relay unreachable and restoring failed.
+    RollbackFailed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ApplyStatus {
+    template: String,
+    backend: Backend,
+    applied_at_ms: u64,
+    guarded: bool,
+    deadline_ms: u64,
+    state: ApplyState,
+    rollback_file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Body for `POST /api/firewall/apply`.
+#[derive(Deserialize)]
+pub struct ApplyRequest {
+    /// Template name (file stem in the templates directory).
+    pub template: String,
+    /// Values for `{{var}}` placeholders.
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// Override the configured grace period (10–3600 s).
+    pub grace_secs: Option<u64>,
+    /// Apply even though the tunnel is down, so the guard can't verify it.
+    #[serde(default)]
+    pub force: bool,
+}
+
+// ─── Parsing ─────────────────────────────────────────────────────────────────
+
+/// Parse `nft -a list ruleset` output.
+fn parse_nft(text: &str) -> Vec<Table> {
+    let mut tables: Vec<Table> = Vec::new();
+    let mut depth = 0usize;
+    // Whether depth 2 is a chain body; other blocks (sets, maps, flowtables)
+    // are skipped.
+    let mut in_chain = false;
+    for raw in text.lines() {
+        let (line, handle) = match raw.split_once(" # handle ") {
+            Some((l, h)) => (l.trim(), h.trim().parse().ok()),
+            None => (raw.trim(), None),
+        };
+        let opens = line.matches('{').count();
+        let closes = line.matches('}').count();
+        match depth {
+            0 if line.starts_with("table ") => {
+                let mut parts = line.split_whitespace().skip(1);
+                let (family, name) = (parts.next(), parts.next());
+                if let (Some(family), Some(name)) = (family, name) {
+                    tables.push(Table {
+                        family: family.to_string(),
+                        name: name.to_string(),
+                        chains: Vec::new(),
+                    });
+                }
+            }
+            1 if line.starts_with("chain ") => {
+                if let (Some(table), Some(name)) =
+                    (tables.last_mut(), line.split_whitespace().nth(1))
+                {
+                    table.chains.push(Chain {
+                        name: name.to_string(),
+                        ..Chain::default()
+                    });
+                    in_chain = true;
+                }
+            }
+            2 if in_chain && !line.is_empty() && line != "}" => {
+                let chain = tables
+                    .last_mut()
+                    .and_then(|t| t.chains.last_mut())
+                    .expect("in_chain implies a chain was pushed");
+                if line.starts_with("type ") {
+                    for stmt in line.split(';') {
+                        let words: Vec<&str> = stmt.split_whitespace().collect();
+                        if let Some(i) = words.iter().position(|w| *w == "hook") {
+                            chain.hook = words.get(i + 1).map(ToString::to_string);
+                        }
+                        if let Some(i) = words.iter().position(|w| *w == "priority") {
+                            chain.priority = words.get(i + 1).map(ToString::to_string);
+                        }
+                        if words.first() == Some(&"policy") {
+                            chain.policy = words.get(1).map(ToString::to_string);
+                        }
+                    }
+                } else if opens == closes {
+                    chain.rules.push(Rule {
+                        handle,
+                        rule: line.to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        depth = (depth + opens).saturating_sub(closes);
+        if depth < 2 {
+            in_chain = false;
+        }
+    }
+    tables
+}
+
+/// Parse `iptables-save` output.
+fn parse_iptables_save(text: &str, family: &str) -> Vec<Table> {
+    let mut tables: Vec<Table> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('*') {
+            tables.push(Table {
+                family: family.to_string(),
+                name: name.to_string(),
+                chains: Vec::new(),
+            });
+        } else if let Some(rest) = line.strip_prefix(':') {
+            let Some(table) = tables.last_mut() else {
+                continue;
+            };
+            let mut parts = rest.split_whitespace();
+            let name = parts.next().unwrap_or_default().to_string();
+            let policy = parts.next().filter(|p| *p != "-").map(ToString::to_string);
+            table.chains.push(Chain {
+                name,
+                policy,
+                ..Chain::default()
+            });
+        } else if let Some(rest) = line.strip_prefix("-A ") {
+            let Some(table) = tables.last_mut() else {
+                continue;
+            };
+            let (chain, rule) = rest.split_once(' ').unwrap_or((rest, ""));
+            if let Some(c) = table.chains.iter_mut().find(|c| c.name == chain) {
+                c.rules.push(Rule {
+                    handle: None,
+                    rule: rule.to_string(),
+                });
+            }
+        }
+    }
+    tables
+}
+
+/// Placeholder names used in a template.
+fn template_vars(template: &str) -> BTreeSet<String> {
+    let mut vars = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        vars.insert(rest[start + 2..start + end].trim().to_string());
+        rest = &rest[start + end + 2..];
+    }
+    vars
+}
+
+/// Substitute `{{var}}` placeholders. Values are restricted to characters
+/// that can't escape a token in either ruleset syntax.
+fn render_template(template: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let missing: Vec<String> = template_vars(template)
+        .into_iter()
+        .filter(|v| !vars.contains_key(v))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Missing template variables: {}",
+            missing.join(", ")
+        ));
+    }
+    for (name, value) in vars {
+        if value.is_empty()
+            || !value.chars().all(|c| {
+                c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '/' | ',' | '-' | '_')
+            })
+        {
+            return Err(format!("Invalid value for template variable '{name}'"));
+        }
+    }
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&vars[rest[start + 2..start + end].trim()]);
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// ─── Helpers ─────────────────────────────────────────────────────────────────
+
+fn templates_dir(state: &AppState) -> PathBuf {
+    state
+        .config
+        .firewall
+        .as_ref()
+        .and_then(|f| f.templates_dir.clone())
+        .map_or_else(
+            || Path::new(&state.config.server.data_dir).join("firewall"),
+            PathBuf::from,
+        )
+}
+
+/// `(name, backend, variables)` for every template file.
+async fn list_templates(dir: &Path) -> Vec<Value> {
+    let mut out = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return out;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let backend = match path.extension().and_then(|e| e.to_str()) {
+            Some("nft") => Backend::Nftables,
+            Some("iptables") => Backend::Iptables,
+            _ => continue,
+        };
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let vars = tokio::fs::read_to_string(&path)
+            .await
+            .map(|t| template_vars(&t))
+            .unwrap_or_default();
+        out.push(json!({ "name": name, "backend": backend, "variables": vars }));
+    }
+    out.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    out
+}
+
+/// Run a tool, returning stdout or a message built from its stderr.
+async fn run_tool(program: &str, args: &[String]) -> Result<String, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match exec_args_pub(program, &args, TOOL_TIMEOUT_MS).await? {
+        (0, stdout, _) => Ok(stdout),
+        (code, _, stderr) => Err(format!("{program} exited with {code}: {}", stderr.trim())),
+    }
+}
+
+/// Current ruleset as a file that restores it when loaded.
+async fn snapshot(backend: Backend) -> Result<String, String> {
+    match backend {
+        Backend::Nftables => {
+            let current = run_tool("nft", &["list".into(), "ruleset".into()]).await?;
+            Ok(format!("flush ruleset\n{current}"))
+        }
+        Backend::Iptables => run_tool("iptables-save", &[]).await,
+    }
+}
+
+fn tunnel_guarded(state: &AppState) -> bool {
+    state
+        .config
+        .tunnel
+        .as_ref()
+        .is_some_and(|tc| tc.url.is_some() && !tc.relay)
+}
+
+fn set_last_apply(f: impl FnOnce(&mut Option<ApplyStatus>)) {
+    f(&mut LAST_APPLY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner));
+}
+
+/// Wait out the grace period, then restore `rollback_file` if the relay is
+/// unreachable. The rollback is journaled under the apply's `source`.
+fn spawn_rollback_guard(
+    state: AppState,
+    source: crate::activity::ActivitySource,
+    backend: Backend,
+    template: String,
+    rollback_file: String,
+    grace: Duration,
+) {
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        // A reconnect may be in flight — give it a short extra window.
+        let mut connected = false;
+        for _ in 0..10 {
+            if state.tunnel_stats.connected.load(Ordering::Relaxed) {
+                connected = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        if connected {
+            info!(template = %template, "firewall: relay reachable after grace period, keeping rules");
+            set_last_apply(|s| {
+                if let Some(s) = s.as_mut() {
+                    s.state = ApplyState::Kept;
+                }
+            });
+            return;
+        }
+
+        warn!(template = %template, "firewall: relay unreachable after grace period, rolling back");
+        let (program, args) = backend.apply_args(&rollback_file);
+        let result = run_tool(program, &args).await;
+        set_last_apply(|s| {
+            if let Some(s) = s.as_mut() {
+                match &result {
+                    Ok(_) => s.state = ApplyState::RolledBack,
+                    Err(e) => {
+                        s.state = ApplyState::RollbackFailed;
+                        s.error = Some(e.clone());
+                    }
+                }
+            }
+        });
+        if let Err(ref e) = result {
+            warn!(template = %template, "firewall: rollback failed: {e}");
+        }
+        state
+            .activity_log
+            .log(
+                ActivityType::FirewallRollback,
+                source,
+                format!(
+                    "Firewall template {template} {}",
+                    if result.is_ok() {
+                        "rolled back"
+                    } else {
+                        "rollback FAILED"
+                    }
+                ),
+                Some(json!({
+                    "template": template,
+                    "rollback_file": rollback_file,
+                    "error": result.err(),
+                })),
+                None,
+            )
+            .await;
+    });
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// `GET /api/firewall` — current ruleset, templates, and last apply.
+pub async fn get_firewall(State(state): State<AppState>) -> ApiResult<Value> {
+    let (backend, tables) =
+        match run_tool("nft", &["-a".into(), "list".into(), "ruleset".into()]).await {
+            Ok(text) => (Some(Backend::Nftables), parse_nft(&text)),
+            Err(nft_err) => match run_tool("iptables-save", &[]).await {
+                Ok(text) => {
+                    let mut tables = parse_iptables_save(&text, "ip");
+                    if let Ok(text6) = run_tool("ip6tables-save", &[]).await {
+                        tables.extend(parse_iptables_save(&text6, "ip6"));
+                    }
+                    (Some(Backend::Iptables), tables)
+                }
+                Err(ipt_err) => {
+                    return Err(ApiError::new(
+                        codes::EXEC_FAILED,
+                        format!("No readable firewall: {nft_err}; {ipt_err}"),
+                    )
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+                }
+            },
+        };
+    let last_apply = LAST_APPLY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone();
+
+    Ok(Json(json!({
+        "backend": backend,
+        "tables": tables,
+        "templates": list_templates(&templates_dir(&state)).await,
+        "last_apply": last_apply,
+    })))
+}
+
+/// `POST /api/firewall/apply` — apply a template with rollback guard.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — invalid template name or variables, or the rendered
+///   ruleset fails the dry run (`nft -c` / `iptables-restore --test`)
+/// - `404 Not Found` — no such template
+/// - `409 Conflict` with `{"code":"APPLY_PENDING"}` — a previous apply is
+///   still inside its grace period
+/// - `409 Conflict` with `{"code":"TUNNEL_DISCONNECTED"}` — the tunnel is
+///   configured but down, so the guard couldn't judge the change (use `force`)
+/// - `500` with `{"code":"EXEC_FAILED"}` — snapshot or apply failed
+pub async fn apply_firewall(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ApplyRequest>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let bad_request = |msg: String| {
+        ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+    };
+    let exec_failed = |msg: String| {
+        ApiError::new(codes::EXEC_FAILED, msg).into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+
+    let name = payload.template.as_str();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        || name.starts_with('.')
+    {
+        return Err(bad_request("Invalid template name".to_string()));
+    }
+    let grace_secs = payload.grace_secs.unwrap_or_else(|| {
+        state
+            .config
+            .firewall
+            .as_ref()
+            .map_or(DEFAULT_GRACE_SECS, |f| f.grace_secs)
+    });
+    if !(10..=3600).contains(&grace_secs) {
+        return Err(bad_request(
+            "grace_secs must be between 10 and 3600".to_string(),
+        ));
+    }
+
+    let dir = templates_dir(&state);
+    let mut found = None;
+    for backend in [Backend::Nftables, Backend::Iptables] {
+        let path = dir.join(format!("{name}.{}", backend.extension()));
+        if let Ok(text) = tokio::fs::read_to_string(&path).await {
+            found = Some((backend, text));
+            break;
+        }
+    }
+    let Some((backend, template)) = found else {
+        return Err(
+            ApiError::new(codes::NOT_FOUND, format!("Template '{name}' not found"))
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    };
+    let rendered = render_template(&template, &payload.vars).map_err(bad_request)?;
+
+    let guarded = tunnel_guarded(&state);
+    if guarded && !payload.force && !state.tunnel_stats.connected.load(Ordering::Relaxed) {
+        return Err(ApiError::new(
+            codes::TUNNEL_DISCONNECTED,
+            "tunnel is down, so the rollback guard can't verify the change. Use force:true to apply anyway.",
+        )
+        .into_response_with(StatusCode::CONFLICT));
+    }
+    {
+        let last = LAST_APPLY
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(ref s) = *last {
+            if s.state == ApplyState::Pending {
+                return Err(ApiError::new(
+                    codes::APPLY_PENDING,
+                    format!("Template {} is still inside its grace period", s.template),
+                )
+                .with_detail(json!({ "deadline_ms": s.deadline_ms }))
+                .into_response_with(StatusCode::CONFLICT));
+            }
+        }
+    }
+
+    let data_dir = Path::new(&state.config.server.data_dir);
+    let ext = backend.extension();
+    let rollback_file = data_dir.join(format!("firewall-rollback.{ext}"));
+    let apply_file = data_dir.join(format!("firewall-apply.{ext}"));
+    let rollback_path = rollback_file.to_string_lossy().into_owned();
+    let apply_path = apply_file.to_string_lossy().into_owned();
+
+    let previous = snapshot(backend)
+        .await
+        .map_err(|e| exec_failed(format!("Failed to snapshot current ruleset: {e}")))?;
+    tokio::fs::write(&rollback_file, previous)
+        .await
+        .map_err(|e| exec_failed(format!("Failed to write {rollback_path}: {e}")))?;
+    tokio::fs::write(&apply_file, &rendered)
+        .await
+        .map_err(|e| exec_failed(format!("Failed to write {apply_path}: {e}")))?;
+
+    let (program, args) = backend.check_args(&apply_path);
+    run_tool(program, &args)
+        .await
+        .map_err(|e| bad_request(format!("Ruleset rejected: {e}")))?;
+    let (program, args) = backend.apply_args(&apply_path);
+    run_tool(program, &args)
+        .await
+        .map_err(|e| exec_failed(format!("Apply failed: {e}")))?;
+
+    let now = crate::sessions::journal::now_ms();
+    let deadline_ms = now + grace_secs * 1000;
+    set_last_apply(|s| {
+        *s = Some(ApplyStatus {
+            template: name.to_string(),
+            backend,
+            applied_at_ms: now,
+            guarded,
+            deadline_ms,
+            state: if guarded {
+                ApplyState::Pending
+            } else {
+                ApplyState::Kept
+            },
+            rollback_file: rollback_path.clone(),
+            error: None,
+        });
+    });
+    if guarded {
+        spawn_rollback_guard(
+            state.clone(),
+            source,
+            backend,
+            name.to_string(),
+            rollback_path.clone(),
+            Duration::from_secs(grace_secs),
+        );
+    }
+
+    state
+        .activity_log
+        .log(
+            ActivityType::FirewallApply,
+            source,
+            format!("Applied firewall template {name}"),
+            Some(json!({
+                "template": name,
+                "backend": backend,
+                "vars": payload.vars,
+                "guarded": guarded,
+                "grace_secs": grace_secs,
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "ok": true,
+        "template": name,
+        "backend": backend,
+        "guarded": guarded,
+        "grace_secs": grace_secs,
+        "deadline_ms": guarded.then_some(deadline_ms),
+        "rollback_file": rollback_path,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NFT: &str = "\
+table inet filter { # handle 1
+\tchain input { # handle 1
+\t\ttype filter hook input priority filter; policy drop;
+\t\tct state established,related accept # handle 4
+\t\ttcp dport { 22, 1337 } accept # handle 5
+\t}
+\tset blocked { # handle 2
+\t\ttype ipv4_addr
+\t\telements = { 10.0.0.1 }
+\t}
+\tchain output { # handle 3
+\t\ttype filter hook output priority filter; policy accept;
+\t}
+}
+table ip nat { # handle 2
+\tchain postrouting { # handle 1
+\t\ttype nat hook postrouting priority srcnat; policy accept;
+\t\toifname \"wwan0\" masquerade # handle 2
+\t}
+}
+";
+
+    #[test]
+    fn parses_nft_ruleset() {
+        let tables = parse_nft(NFT);
+        assert_eq!(tables.len(), 2);
+        let filter = &tables[0];
+        assert_eq!(
+            (filter.family.as_str(), filter.name.as_str()),
+            ("inet", "filter")
+        );
+        assert_eq!(filter.chains.len(), 2);
+        let input = &filter.chains[0];
+        assert_eq!(input.hook.as_deref(), Some("input"));
+        assert_eq!(input.policy.as_deref(), Some("drop"));
+        assert_eq!(input.rules.len(), 2);
+        assert_eq!(input.rules[1].rule, "tcp dport { 22, 1337 } accept");
+        assert_eq!(input.rules[1].handle, Some(5));
+        assert!(filter.chains[1].rules.is_empty());
+        assert_eq!(
+            tables[1].chains[0].rules[0].rule,
+            "oifname \"wwan0\" masquerade"
+        );
+    }
+
+    #[test]
+    fn parses_iptables_save() {
+        let text = "*filter\n:INPUT DROP [0:0]\n:FORWARD ACCEPT [0:0]\n:custom - [0:0]\n\
+                    -A INPUT -i lo -j ACCEPT\n-A custom -j RETURN\nCOMMIT\n";
+        let tables = parse_iptables_save(text, "ip");
+        assert_eq!(tables.len(), 1);
+        let chains = &tables[0].chains;
+        assert_eq!(chains[0].policy.as_deref(), Some("DROP"));
+        assert_eq!(chains[0].rules[0].rule, "-i lo -j ACCEPT");
+        assert!(chains[2].policy.is_none());
+        assert_eq!(chains[2].rules.len(), 1);
+    }
+
+    #[test]
+    fn renders_template_variables() {
+        let tpl = "tcp dport {{ ssh_port }} ip saddr {{allow}} accept\n";
+        let vars = HashMap::from([
+            ("ssh_port".to_string(), "2222".to_string()),
+            ("allow".to_string(), "10.0.0.0/8".to_string()),
+        ]);
+        assert_eq!(
+            render_template(tpl, &vars).unwrap(),
+            "tcp dport 2222 ip saddr 10.0.0.0/8 accept\n"
+        );
+        assert_eq!(
+            template_vars(tpl).into_iter().collect::<Vec<_>>(),
+            ["allow", "ssh_port"]
+        );
+
+        let missing = render_template(tpl, &HashMap::new()).unwrap_err();
+        assert!(missing.contains("allow") && missing.contains("ssh_port"));
+
+        let injected = HashMap::from([
+            ("ssh_port".to_string(), "22; flush ruleset".to_string()),
+            ("allow".to_string(), "any".to_string()),
+        ]);
+        assert!(render_template(tpl, &injected).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! A/B firmware updates through the system's update agent.
+//!
+//! - `GET /api/firmware` — agent, booted slot, version and the latest install
+//! - `POST /api/firmware/install` — install a bundle already on the device
+//! - `POST /api/firmware/reboot` — reboot into the installed slot
+//! - `POST /api/firmware/commit` — mark the booted slot good
+//!
+//! All of them need `[firmware]`. The install, reboot and commit are checked
+//! against `[policy]` as the agent command they run (`rauc install
+//! /data/updates/acme-1.1.raucb`, `reboot`, `rauc status mark-good`) and
+//! journaled as `firmware_update`. See [`crate::firmware`] for the install
+//! lifecycle.
+
+use std::path::Path as FsPath;
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::firmware::{Firmware, FirmwareError, Install};
+use crate::shell::policy;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+type ErrorPair = (StatusCode, Json<ApiError>);
+
+/// Body for `POST /api/firmware/install`.
+#[derive(Debug, Deserialize)]
+pub struct InstallRequest {
+    /// Absolute path of the bundle on the device.
+    pub path: String,
+    /// Expected SHA-256 of the bundle (hex), checked before installing.
+    pub sha256: Option<String>,
+    /// Reboot into the new slot once the install succeeds.
+    #[serde(default)]
+    pub reboot: bool,
+}
+
+fn firmware(state: &AppState) -> Result<&Arc<Firmware>, ErrorPair> {
+    state.firmware.as_ref().ok_or_else(|| {
+        ApiError::new(
+            codes::NOT_FOUND,
+            "Firmware updates not configured ([firmware])",
+        )
+        .into_response_with(StatusCode::NOT_FOUND)
+    })
+}
+
+fn bad_request(message: String) -> ErrorPair {
+    ApiError::new(codes::INVALID_REQUEST, message).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+fn firmware_error(e: FirmwareError) -> ErrorPair {
+    match e {
+        FirmwareError::Unavailable(m) => ApiError::new(codes::FIRMWARE_UNAVAILABLE, m)
+            .into_response_with(StatusCode::SERVICE_UNAVAILABLE),
+        FirmwareError::Busy(m) => {
+            ApiError::new(codes::FIRMWARE_BUSY, m).into_response_with(StatusCode::CONFLICT)
+        }
+        FirmwareError::Failed(m) => {
+            ApiError::new(codes::FIRMWARE_FAILED, m).into_response_with(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Resolve `path` to a regular file inside one of `bundle_dirs` (any
+/// directory when empty).
+fn bundle_path(path: &str, bundle_dirs: &[String]) -> Result<String, ErrorPair> {
+    if !path.starts_with('/') {
+        return Err(bad_request(format!(
+            "Bundle path '{path}' must be absolute"
+        )));
+    }
+    let resolved = std::fs::canonicalize(path).map_err(|_| {
+        ApiError::new(codes::FILE_NOT_FOUND, format!("No bundle at '{path}'"))
+            .into_response_with(StatusCode::NOT_FOUND)
+    })?;
+    if !resolved.is_file() {
+        return Err(bad_request(format!("'{path}' is not a file")));
+    }
+    let allowed = bundle_dirs.is_empty()
+        || bundle_dirs
+            .iter()
+            .any(|dir| std::fs::canonicalize(dir).is_ok_and(|dir| resolved.starts_with(dir)));
+    if !allowed {
+        return Err(ApiError::new(
+            codes::INVALID_PATH,
+            format!("'{path}' is outside firmware.bundle_dirs"),
+        )
+        .with_detail(json!({ "bundle_dirs": bundle_dirs }))
+        .into_response_with(StatusCode::FORBIDDEN));
+    }
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+/// `GET /api/firmware` — the update agent, booted slot and version, and the
+/// latest install.
+///
+/// Returns `{"backend", "booted_slot", "version", "os", "slots"?, "install"}`.
+/// `backend` is `null` when no agent is installed; `slots` is RAUC's view of
+/// each slot; `install` is `null` before the first install.
+pub async fn status(State(state): State<AppState>) -> ApiResult<Value> {
+    let firmware = firmware(&state)?;
+    let mut body = json!(firmware.slot_info().await);
+    body["backend"] = json!(firmware.backend().map(crate::firmware::Backend::as_str));
+    body["install"] = json!(firmware.install().await);
+    Ok(Json(body))
+}
+
+/// `POST /api/firmware/install` — install a bundle into the inactive slot.
+///
+/// Body: `{"path": "/data/updates/acme-1.1.raucb", "sha256"?: "...",
+/// "reboot"?: false}`. The bundle is usually uploaded first with gawdxfer.
+/// Returns `202 Accepted` with the install record once the agent has
+/// started; progress follows as `firmware.*` events and in
+/// `GET /api/firmware`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — relative path, not a file, or `sha256` mismatch
+/// - `403 Forbidden` with `{"code":"INVALID_PATH"}` — outside `bundle_dirs`
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — refused by `[policy]`
+/// - `404 Not Found` — no `[firmware]`, or no such bundle
+/// - `409 Conflict` with `{"code":"FIRMWARE_BUSY"}` — an install or reboot
+///   is in progress
+/// - `503` with `{"code":"FIRMWARE_UNAVAILABLE"}` — no update agent installed
+pub async fn install(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<InstallRequest>,
+) -> Result<(StatusCode, Json<Install>), ErrorPair> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let firmware = firmware(&state)?;
+    let backend = firmware.backend().ok_or_else(|| {
+        firmware_error(FirmwareError::Unavailable(format!(
+            "No update agent found for backend '{}'",
+            firmware.config().backend
+        )))
+    })?;
+    let bundle = bundle_path(&req.path, &firmware.config().bundle_dirs)?;
+    let sha256 = req.sha256.map(|h| h.to_ascii_lowercase());
+    if let Some(expected) = &sha256 {
+        let actual = crate::gawdxfer::hasher::hash_file(FsPath::new(&bundle))
+            .await
+            .map_err(|e| {
+                ApiError::new(codes::IO_ERROR, format!("Reading {bundle}: {e}"))
+                    .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+            })?;
+        if &actual != expected {
+            return Err(ApiError::new(
+                codes::INVALID_CONTENT,
+                format!("{bundle} does not match the expected sha256"),
+            )
+            .with_detail(json!({ "expected": expected, "actual": actual }))
+            .into_response_with(StatusCode::BAD_REQUEST));
+        }
+    }
+    let (program, args) = backend.install_command(&bundle);
+    policy::check(&state, source, &format!("{program} {}", args.join(" "))).await?;
+
+    let record = firmware
+        .start(bundle.clone(), sha256, req.reboot)
+        .await
+        .map_err(firmware_error)?;
+    state
+        .activity_log
+        .log(
+            ActivityType::FirmwareUpdate,
+            source,
+            format!("Firmware install {bundle}"),
+            Some(json!({
+                "action": "install",
+                "id": record.id,
+                "backend": record.backend,
+                "bundle": bundle,
+                "from_slot": record.from_slot,
+                "reboot": req.reboot,
+            })),
+            req_id,
+        )
+        .await;
+    Ok((StatusCode::ACCEPTED, Json(record)))
+}
+
+/// `POST /api/firmware/reboot` — reboot into the slot the latest install
+/// wrote, after 5 seconds. Returns `202 Accepted` with the record, now
+/// `rebooting`.
+///
+/// # Errors
+///
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — `reboot` refused by
+///   `[policy]`
+/// - `404 Not Found` — no `[firmware]`
+/// - `409 Conflict` with `{"code":"FIRMWARE_BUSY"}` — nothing `installed`
+pub async fn reboot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<Install>), ErrorPair> {
+    let source = source_from_headers(&headers);
+    let firmware = firmware(&state)?;
+    policy::check(&state, source, "reboot").await?;
+    let record = firmware.reboot().await.map_err(firmware_error)?;
+    state
+        .activity_log
+        .log(
+            ActivityType::FirmwareUpdate,
+            source,
+            "Firmware reboot into the new slot".to_string(),
+            Some(json!({ "action": "reboot", "id": record.id, "from_slot": record.from_slot })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    Ok((StatusCode::ACCEPTED, Json(record)))
+}
+
+/// `POST /api/firmware/commit` — mark the booted slot good, so the
+/// bootloader stops falling back to the previous one. Returns the record,
+/// now `committed`.
+///
+/// # Errors
+///
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — refused by `[policy]`
+/// - `404 Not Found` — no `[firmware]`
+/// - `409 Conflict` with `{"code":"FIRMWARE_BUSY"}` — nothing `booted`
+/// - `502` with `{"code":"FIRMWARE_FAILED"}` — the agent refused
+/// - `503` with `{"code":"FIRMWARE_UNAVAILABLE"}` — no update agent installed
+pub async fn commit(State(state): State<AppState>, headers: HeaderMap) -> ApiResult<Install> {
+    let source = source_from_headers(&headers);
+    let firmware = firmware(&state)?;
+    if let Some(backend) = firmware.backend() {
+        let (program, args) = backend.commit_command();
+        policy::check(&state, source, &format!("{program} {}", args.join(" "))).await?;
+    }
+    let record = firmware.commit().await.map_err(firmware_error)?;
+    state
+        .activity_log
+        .log(
+            ActivityType::FirmwareUpdate,
+            source,
+            format!(
+                "Firmware commit of slot {}",
+                record.to_slot.as_deref().unwrap_or("unknown")
+            ),
+            Some(json!({ "action": "commit", "id": record.id, "to_slot": record.to_slot })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+    Ok(Json(record))
+}
@@ -0,0 +1,60 @@
+//! Flight recorder endpoints (see [`crate::flight_recorder`]).
+//!
+//! - `GET /api/flightrecorder` — window settings, live segments and dumps
+//! - `POST /api/flightrecorder/dump` — freeze the current window
+
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+use crate::error::{codes, ApiError};
+use crate::flight_recorder::FlightRecorder;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+fn recorder(state: &AppState) -> Result<Arc<FlightRecorder>, (StatusCode, Json<ApiError>)> {
+    state.flight_recorder.clone().ok_or_else(|| {
+        ApiError::new(
+            codes::NOT_FOUND,
+            "Flight recorder not configured on this device",
+        )
+        .into_response_with(StatusCode::NOT_FOUND)
+    })
+}
+
+/// `GET /api/flightrecorder` — recorder status.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — `[flight_recorder]` is not configured
+pub async fn status(State(state): State<AppState>) -> ApiResult<Value> {
+    let recorder = recorder(&state)?;
+    let status = tokio::task::spawn_blocking(move || recorder.status())
+        .await
+        .unwrap_or_default();
+    Ok(Json(status))
+}
+
+/// `POST /api/flightrecorder/dump` — copy the window to
+/// `<dir>/dumps/<ms>-api/`.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — `[flight_recorder]` is not configured
+/// - `500` with `{"code":"IO_ERROR"}` — the dump could not be written
+pub async fn dump(State(state): State<AppState>) -> ApiResult<Value> {
+    let recorder = recorder(&state)?;
+    let result = tokio::task::spawn_blocking(move || recorder.dump("api"))
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))
+        .and_then(|r| r);
+    match result {
+        Ok(dump) => Ok(Json(json!({ "dump": dump }))),
+        Err(e) => Err(
+            ApiError::new(codes::IO_ERROR, format!("Flight recorder dump failed: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+        ),
+    }
+}
@@ -1,9 +1,10 @@
-//! GPS location endpoint.
+//! GPS location endpoints.
 
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::{json, Value};
 
 use crate::error::{codes, ApiError};
 use crate::AppState;
@@ -37,3 +38,59 @@ pub async fn gps(
 
     Ok(Json(snapshot))
 }
+
+/// Query parameters for `GET /api/gps/track`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TrackQuery {
+    /// Only points at or after this Unix timestamp (seconds).
+    pub since: Option<u64>,
+    /// Only points at or before this Unix timestamp (seconds).
+    pub until: Option<u64>,
+    /// Newest points to return (default and max `track_max_points`).
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/gps/track` — the recorded track as a GeoJSON
+/// `FeatureCollection`, oldest point first. See [`crate::gps_track`].
+///
+/// # Errors
+///
+/// - `404 Not Found` — GPS not configured, or `track_interval_secs = 0`
+/// - `503 Service Unavailable` with `{"code":"MODEM_UNAVAILABLE"}` — no comms provider
+pub async fn track(
+    State(state): State<AppState>,
+    Query(query): Query<TrackQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<ApiError>)> {
+    let Some(gc) = &state.config.gps else {
+        return Err(
+            ApiError::new(codes::NOT_FOUND, "GPS not configured on this device")
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    };
+    if gc.track_interval_secs == 0 {
+        return Err(ApiError::new(
+            codes::NOT_FOUND,
+            "GPS track recording is disabled (track_interval_secs = 0)",
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    }
+    let Some(track) = &state.gps_track else {
+        return Err(
+            ApiError::new(codes::MODEM_UNAVAILABLE, "comms provider not available")
+                .into_response_with(StatusCode::SERVICE_UNAVAILABLE),
+        );
+    };
+
+    let limit = query.limit.unwrap_or(usize::MAX).min(track.max_points());
+    let points = track
+        .points(
+            query.since.unwrap_or(0),
+            query.until.unwrap_or(u64::MAX),
+            limit,
+        )
+        .await;
+    let mut body = crate::gps_track::geojson(&points);
+    body["count"] = json!(points.len());
+    body["interval_secs"] = json!(gc.track_interval_secs);
+    Ok(Json(body))
+}
@@ -1,12 +1,23 @@
-//! Unauthenticated health-check endpoint.
+//! Health-check endpoints.
+//!
+//! `GET /api/health` is the unauthenticated liveness probe.
+//! `GET /api/health/history` (authenticated) returns recorded health
+//! transitions and flapping conditions (see [`crate::health_history`]).
 
 use std::sync::atomic::Ordering;
 use std::time::Instant;
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use tracing::{info, warn};
 
+use crate::error::{codes, ApiError};
+use crate::health_history::Condition;
 use crate::AppState;
 
 /// `GET /api/health` — liveness probe.
@@ -40,6 +51,8 @@ pub async fn health(State(state): State<AppState>) -> Json<Value> {
         let stream_backpressure_events = ts.stream_backpressure_events.load(Ordering::Relaxed);
         let stream_replay_events = ts.stream_replay_events.load(Ordering::Relaxed);
 
+        let quality_score = ts.quality_score.load(Ordering::Relaxed);
+        let quality = ts.link_quality();
         let rtt = ts.rtt_stats().await;
         let (rtt_median, rtt_p95) = rtt.unwrap_or((0, 0));
 
@@ -82,6 +95,13 @@ pub async fn health(State(state): State<AppState>) -> Json<Value> {
             "stream_replay_events": stream_replay_events,
             "rtt_median_ms": rtt_median,
             "rtt_p95_ms": rtt_p95,
+            "quality_score": quality_score,
+            "quality": quality.as_str(),
+            "connected_relays": *ts.connected_relays.lock().await,
+            "offline_spool": state.offline_spool.as_ref().map(|s| json!({
+                "pending": s.len(),
+                "dropped": s.dropped(),
+            })),
             "recent_events": recent_events,
         })
     } else {
@@ -209,7 +229,16 @@ pub async fn health(State(state): State<AppState>) -> Json<Value> {
         "tunnel": tunnel,
         "gps": gps,
         "lte": lte,
+        "io_pool": crate::io_pool::stats(),
+        "requests": state.saturation.summary(),
     });
+    let flapping = state.health_history.flapping().await;
+    if !flapping.is_empty() {
+        resp["flapping"] = json!(flapping
+            .iter()
+            .map(|f| f.condition.as_str())
+            .collect::<Vec<_>>());
+    }
     if let Some(ch) = connection_history {
         resp["connection_history"] = json!(ch);
     }
@@ -219,6 +248,15 @@ pub async fn health(State(state): State<AppState>) -> Json<Value> {
     if let Some(ld) = live_devices {
         resp["live_devices"] = json!(ld);
     }
+    if let Some(journal) = state.activity_log.journal() {
+        resp["activity_journal"] = journal.status();
+    }
+    if let Some(spill) = state.exec_results_cache.spill() {
+        resp["exec_results"] = spill.status();
+    }
+    if let Some(quiet) = &state.quiet_hours {
+        resp["quiet_hours"] = quiet.summary();
+    }
     #[allow(clippy::cast_possible_truncation)]
     let total_ms = start.elapsed().as_millis() as u64;
     if lte_lock_wait_ms >= 250 {
@@ -230,3 +268,43 @@ pub async fn health(State(state): State<AppState>) -> Json<Value> {
     info!(total_ms, lte_lock_wait_ms, "api.health: end");
     Json(resp)
 }
+
+/// Query parameters for `GET /api/health/history`.
+#[derive(Debug, Default, Deserialize)]
+pub struct HealthHistoryQuery {
+    /// Only transitions at or after this Unix timestamp (seconds).
+    pub since: Option<u64>,
+    /// Only this condition (`tunnel`, `disk`, `oom`, `service`).
+    pub condition: Option<String>,
+    /// Newest entries to return (default 100, max 500).
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/health/history` — recorded health transitions, current state per
+/// condition, and conditions that are flapping.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — unknown `condition`
+pub async fn health_history(
+    State(state): State<AppState>,
+    Query(query): Query<HealthHistoryQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<ApiError>)> {
+    let condition = match query.condition.as_deref() {
+        None | Some("") => None,
+        Some(c) => Some(Condition::from_str_opt(c).ok_or_else(|| {
+            ApiError::new(codes::INVALID_REQUEST, format!("Unknown condition '{c}'"))
+                .into_response_with(StatusCode::BAD_REQUEST)
+        })?),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(100)
+        .min(crate::health_history::MAX_TRANSITIONS);
+    Ok(Json(
+        state
+            .health_history
+            .snapshot(query.since.unwrap_or(0), condition, limit)
+            .await,
+    ))
+}
@@ -0,0 +1,41 @@
+//! Lua hook endpoints (see [`crate::hooks`]).
+//!
+//! - `GET /api/hooks` — script, defined hooks, load error and counters
+//! - `POST /api/hooks/reload` — re-read the script
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// `GET /api/hooks` — hook status; `{"enabled": false}` without `[hooks]`.
+pub async fn get_hooks(State(state): State<AppState>) -> Json<Value> {
+    Json(
+        state
+            .hooks
+            .as_ref()
+            .map_or_else(|| json!({ "enabled": false }), |hooks| hooks.status()),
+    )
+}
+
+/// `POST /api/hooks/reload` — re-read `[hooks] script`. If the new script
+/// fails to load the previous one stays active.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — `[hooks]` not configured
+/// - `500 Internal Server Error` with `{"code":"HOOK_FAILED"}`
+pub async fn reload_hooks(State(state): State<AppState>) -> ApiResult<Value> {
+    let Some(hooks) = &state.hooks else {
+        return Err(ApiError::new(codes::NOT_FOUND, "Hooks are not configured")
+            .into_response_with(StatusCode::NOT_FOUND));
+    };
+    hooks.reload().map_err(|e| {
+        ApiError::new(codes::HOOK_FAILED, format!("Reload failed: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    Ok(Json(hooks.status()))
+}
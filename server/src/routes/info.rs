@@ -15,6 +15,8 @@
 //! | `memory`       | `/proc/meminfo`                                     |
 //! | `disk`         | `statvfs("/")` syscall                              |
 //! | `interfaces`   | `ip -j addr show` (fallback: `/proc/net/dev` + sysfs) |
+//! | `sensors`      | hwmon, thermal zones, power supplies ([`crate::sensors`]) |
+//! | `identity`     | machine-id, board model, TPM ([`crate::identity`])   |
 
 use axum::{
     extract::{Query, State},
@@ -27,11 +29,14 @@ use std::collections::HashSet;
 use std::time::Instant;
 use tracing::{info, warn};
 
+use crate::error::{codes, ApiError};
 use crate::AppState;
 
 #[derive(Debug, Default, Deserialize)]
 pub struct InfoQuery {
     pub groups: Option<String>,
+    /// Comma-separated fields to keep, dotted for nested ones (`tunnel.connected`).
+    pub fields: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -43,10 +48,12 @@ pub(crate) struct InfoGroups {
     tunnel: bool,
     gps: bool,
     lte: bool,
+    sensors: bool,
+    identity: bool,
 }
 
 impl InfoGroups {
-    fn all() -> Self {
+    pub(crate) fn all() -> Self {
         Self {
             core: true,
             interfaces: true,
@@ -54,6 +61,8 @@ impl InfoGroups {
             tunnel: true,
             gps: true,
             lte: true,
+            sensors: true,
+            identity: true,
         }
     }
 
@@ -76,6 +85,8 @@ impl InfoGroups {
             tunnel: requested.contains("tunnel"),
             gps: requested.contains("gps"),
             lte: requested.contains("lte"),
+            sensors: requested.contains("sensors"),
+            identity: requested.contains("identity"),
         }
     }
 }
@@ -89,7 +100,10 @@ pub async fn info(
     State(state): State<AppState>,
     Query(query): Query<InfoQuery>,
 ) -> Result<Json<Value>, StatusCode> {
-    info_with_groups(state, InfoGroups::from_csv(query.groups.as_deref())).await
+    let mut response =
+        info_with_groups(state, InfoGroups::from_csv(query.groups.as_deref())).await?;
+    crate::util::project_fields(&mut response.0, query.fields.as_deref());
+    Ok(response)
 }
 
 pub(crate) async fn info_with_groups(
@@ -157,7 +171,17 @@ pub(crate) async fn info_with_groups(
                 "used_bytes": mem_total.saturating_sub(mem_available) * 1024,
             },
             "safe_mode": safe_mode_block,
+            "startup": state.startup.snapshot(),
         });
+        if let Some(ref forwarder) = state.log_forwarder {
+            response["log_forward"] = forwarder.status();
+        }
+        if let Some(ref bridge) = state.mqtt {
+            response["mqtt"] = bridge.status();
+        }
+        if let Some(ref agent) = state.snmp {
+            response["snmp"] = agent.status();
+        }
     }
 
     if groups.interfaces {
@@ -193,6 +217,9 @@ pub(crate) async fn info_with_groups(
                 response["tunnel"] = json!({
                     "connected": state.tunnel_stats.connected.load(std::sync::atomic::Ordering::Relaxed),
                     "relay_url": tc.url,
+                    "extra_relay_urls": tc.extra_urls,
+                    "failover_relay_urls": tc.failover_urls,
+                    "connected_relays": *state.tunnel_stats.connected_relays.lock().await,
                     "reconnects": state.tunnel_stats.reconnects.load(std::sync::atomic::Ordering::Relaxed),
                 });
             }
@@ -248,6 +275,21 @@ pub(crate) async fn info_with_groups(
         }
     }
 
+    if groups.sensors {
+        let sensors_started = Instant::now();
+        // hwmon reads can block on slow buses (I2C, SMBus).
+        response["sensors"] = crate::io_pool::run(|| Ok(crate::sensors::read()))
+            .await
+            .unwrap_or(Value::Null);
+        #[allow(clippy::cast_possible_truncation)]
+        let sensors_ms = sensors_started.elapsed().as_millis() as u64;
+        info!(req_id, sensors_ms, "api.info: phase sensors complete");
+    }
+
+    if groups.identity {
+        response["identity"] = json!(crate::identity::current().await);
+    }
+
     let serialize_started = Instant::now();
     let response_body_len = serde_json::to_string(&response).map_or(0, |s| s.len());
     #[allow(clippy::cast_possible_truncation)]
@@ -273,6 +315,61 @@ pub(crate) async fn info_with_groups(
     Ok(Json(response))
 }
 
+/// `GET /api/info/sensors` — temperatures, voltages, fans, thermal zones,
+/// cooling devices, power supplies and CPU frequencies. See
+/// [`crate::sensors`].
+pub async fn sensors() -> Result<Json<Value>, StatusCode> {
+    crate::io_pool::run(|| Ok(crate::sensors::read()))
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdentityQuery {
+    /// Hex nonce to bind into a TPM quote (up to 64 bytes).
+    pub nonce: Option<String>,
+}
+
+/// `GET /api/info/identity` — machine-id, board model, hardware serial, TPM
+/// and fingerprint. With `?nonce=<hex>` and `[device] tpm_ak_handle` set,
+/// also a TPM quote over the configured PCRs. See [`crate::identity`].
+pub async fn identity(
+    State(state): State<AppState>,
+    Query(query): Query<IdentityQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<ApiError>)> {
+    let mut body = json!(crate::identity::current().await);
+    let Some(nonce) = query.nonce else {
+        return Ok(Json(body));
+    };
+    if nonce.is_empty()
+        || nonce.len() > 128
+        || nonce.len() % 2 != 0
+        || !nonce.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(
+            ApiError::new(codes::INVALID_REQUEST, "nonce must be 1-64 bytes of hex")
+                .into_response_with(StatusCode::BAD_REQUEST),
+        );
+    }
+    let Some(ak_handle) = state.config.device.tpm_ak_handle.as_deref() else {
+        return Err(ApiError::new(
+            codes::ATTESTATION_UNAVAILABLE,
+            "no TPM attestation key configured (device.tpm_ak_handle)",
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    };
+    let quote = crate::identity::quote(ak_handle, &state.config.device.tpm_quote_pcrs, &nonce)
+        .await
+        .map_err(|e| {
+            warn!("TPM quote failed: {e}");
+            ApiError::new(codes::ATTESTATION_FAILED, e)
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    body["quote"] = quote;
+    Ok(Json(body))
+}
+
 pub(crate) fn read_proc_file(path: &str) -> String {
     std::fs::read_to_string(path).unwrap_or_default()
 }
@@ -405,7 +502,16 @@ async fn collect_interfaces(req_id: &str, include_addresses: bool) -> Vec<Value>
     interfaces
 }
 
+/// Interface addresses come from `getifaddrs`, which Windows doesn't have.
+#[cfg(windows)]
+fn collect_interface_addresses(
+    _req_id: &str,
+) -> Option<std::collections::HashMap<String, Vec<String>>> {
+    None
+}
+
 /// Enumerate interface addresses without spawning external commands.
+#[cfg(unix)]
 fn collect_interface_addresses(
     req_id: &str,
 ) -> Option<std::collections::HashMap<String, Vec<String>>> {
@@ -461,6 +567,7 @@ fn collect_interface_addresses(
 }
 
 /// Format an interface address as `ip/prefixlen`.
+#[cfg(unix)]
 #[allow(clippy::cast_ptr_alignment)] // kernel guarantees sockaddr alignment
 unsafe fn format_interface_address(
     addr: *const libc::sockaddr,
@@ -483,6 +590,7 @@ unsafe fn format_interface_address(
     }
 }
 
+#[cfg(unix)]
 #[allow(clippy::cast_ptr_alignment)]
 unsafe fn prefix_len_v4(netmask: *const libc::sockaddr) -> u32 {
     if netmask.is_null() || i32::from((*netmask).sa_family) != libc::AF_INET {
@@ -492,6 +600,7 @@ unsafe fn prefix_len_v4(netmask: *const libc::sockaddr) -> u32 {
     u32::from_be(mask.sin_addr.s_addr).count_ones()
 }
 
+#[cfg(unix)]
 #[allow(clippy::cast_ptr_alignment)]
 unsafe fn prefix_len_v6(netmask: *const libc::sockaddr) -> u32 {
     if netmask.is_null() || i32::from((*netmask).sa_family) != libc::AF_INET6 {
@@ -505,40 +614,17 @@ fn read_sys_file(path: &str) -> String {
     std::fs::read_to_string(path).unwrap_or_default()
 }
 
-/// Get disk usage for a filesystem via the POSIX `statvfs` syscall.
+/// Get disk usage for the filesystem holding `path`.
 ///
 /// Returns `null` on failure (e.g. path doesn't exist, or `statvfs` errors).
 pub(crate) fn get_disk_usage(path: &str) -> Value {
-    use std::ffi::CString;
-    use std::mem::MaybeUninit;
-
-    let Ok(c_path) = CString::new(path) else {
+    let Ok((total, available, free)) = crate::util::disk_space(std::path::Path::new(path)) else {
         return json!(null);
     };
-
-    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
-
-    // SAFETY: statvfs is a standard POSIX call, we pass a valid C string
-    // and a pointer to uninitialized but properly aligned memory.
-    let ret = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
-
-    if ret != 0 {
-        return json!(null);
-    }
-
-    // SAFETY: statvfs returned 0, so stat is fully initialized.
-    let stat = unsafe { stat.assume_init() };
-
-    #[allow(clippy::unnecessary_cast)]
-    let block_size = stat.f_frsize as u64;
-    let total = stat.f_blocks * block_size;
-    let available = stat.f_bavail * block_size;
-    let used = total - (stat.f_bfree * block_size);
-
     json!({
         "path": path,
         "total_bytes": total,
-        "used_bytes": used,
+        "used_bytes": total.saturating_sub(free),
         "available_bytes": available,
     })
 }
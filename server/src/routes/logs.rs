@@ -0,0 +1,103 @@
+//! `GET /api/logs` — the end of a log file or a unit's journal, optionally
+//! followed as Server-Sent Events.
+//!
+//! The tailing itself is [`crate::log_tail`], shared with `logs.follow` on
+//! the WebSocket. Followed tails count against the SSE connection limit of
+//! [`super::events`]. Like `/api/events`, following isn't proxied through
+//! the relay; use `logs.follow` there.
+
+use std::convert::Infallible;
+use std::sync::atomic::Ordering;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::ApiError;
+use crate::log_tail::{self, TailRequest, TailSpec};
+use crate::AppState;
+
+use super::events::{DropCounterStream, MAX_SSE_CONNECTIONS};
+
+/// Queue of a followed tail; lines beyond it are dropped and counted.
+const FOLLOW_QUEUE: usize = 64;
+
+/// [`TailRequest`] plus `follow`. Spelled out rather than flattened, since
+/// flattened query fields only deserialize as strings.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogsQuery {
+    pub path: Option<String>,
+    pub unit: Option<String>,
+    pub lines: Option<usize>,
+    pub grep: Option<String>,
+    /// Keep the response open and stream new lines.
+    #[serde(default)]
+    pub follow: bool,
+}
+
+/// `GET /api/logs?path=|unit=&lines=&grep=&follow=`
+///
+/// Without `follow`, returns `{"path"|"unit", "lines": [...]}` with the last
+/// `lines` (default 10, max 1000) lines matching `grep`. With
+/// `follow=true`, returns an SSE stream: a `logs.lines` event with the
+/// backlog, then one per batch of new lines, and `logs.closed` if the source
+/// ends.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — neither or both of `path` and `unit`, invalid path,
+///   unit name or regex, `lines` over 1000
+/// - `403 Forbidden` — `path` without the `files:read` scope, or unreadable
+/// - `404 Not Found` with `{"code":"FILE_NOT_FOUND"}`
+/// - `429 Too Many Requests` — too many SSE streams open
+/// - `500` with `{"code":"EXEC_FAILED"}` — `journalctl` failed
+pub async fn get_logs(
+    State(state): State<AppState>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let spec = TailSpec::parse(&TailRequest {
+        path: query.path,
+        unit: query.unit,
+        lines: query.lines,
+        grep: query.grep,
+    })?;
+    let (path, unit) = spec.describe();
+    if !query.follow {
+        let lines = log_tail::read(&spec).await?;
+        let mut body = json!({ "lines": lines });
+        if let Some(path) = path {
+            body["path"] = json!(path);
+        }
+        if let Some(unit) = unit {
+            body["unit"] = json!(unit);
+        }
+        return Ok(Json(body).into_response());
+    }
+
+    if state.sse_connections.load(Ordering::Relaxed) >= MAX_SSE_CONNECTIONS {
+        return Ok((StatusCode::TOO_MANY_REQUESTS, "Too many SSE connections").into_response());
+    }
+    let (tx, rx) = tokio::sync::mpsc::channel(FOLLOW_QUEUE);
+    log_tail::follow(spec, tx).await?;
+    state.sse_connections.fetch_add(1, Ordering::Relaxed);
+
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        let value = rx.recv().await?;
+        let event_type = value["type"].as_str().unwrap_or("message").to_string();
+        let data = serde_json::to_string(&value).unwrap_or_default();
+        let event = Event::default().event(event_type).data(data);
+        Some((Ok::<_, Infallible>(event), rx))
+    });
+    let stream = DropCounterStream {
+        inner: Box::pin(stream),
+        counter: state.sse_connections.clone(),
+        decremented: false,
+    };
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::default().interval(std::time::Duration::from_secs(15)))
+        .into_response())
+}
@@ -45,6 +45,59 @@ pub async fn lte(
     Ok(Json(snapshot))
 }
 
+/// Query parameters for `GET /api/lte/history`.
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// Only samples at or after this Unix timestamp (seconds).
+    pub since: Option<u64>,
+    /// Newest samples to return (default and max `history_size`).
+    pub limit: Option<usize>,
+}
+
+/// `GET /api/lte/history` — recorded signal samples, oldest first, with the
+/// degraded thresholds. See [`crate::lte_history`].
+///
+/// # Errors
+///
+/// - `404 Not Found` — LTE not configured, or `history_interval_secs = 0`
+/// - `503 Service Unavailable` with `{"code":"MODEM_UNAVAILABLE"}` — no comms provider
+pub async fn history(
+    State(state): State<AppState>,
+    axum::extract::Query(query): axum::extract::Query<HistoryQuery>,
+) -> ApiResult<Value> {
+    ensure_lte_configured(&state)?;
+    let Some(lc) = state
+        .config
+        .lte
+        .as_ref()
+        .filter(|lc| lc.history_interval_secs > 0)
+    else {
+        return Err(ApiError::new(
+            codes::NOT_FOUND,
+            "LTE history is disabled (history_interval_secs = 0)",
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    };
+    let Some(history) = &state.lte_history else {
+        return comms_unavailable();
+    };
+
+    let limit = query.limit.unwrap_or(usize::MAX).min(history.max_samples());
+    let samples = history.samples(query.since.unwrap_or(0), limit).await;
+    let degraded = samples.last().is_some_and(|s| !s.degraded.is_empty());
+    Ok(Json(json!({
+        "interval_secs": lc.history_interval_secs,
+        "thresholds": {
+            "rsrp_dbm": lc.degraded_rsrp_dbm,
+            "rsrq_db": lc.degraded_rsrq_db,
+            "sinr_db": lc.degraded_sinr_db,
+        },
+        "degraded": degraded,
+        "count": samples.len(),
+        "samples": samples,
+    })))
+}
+
 #[derive(Deserialize)]
 pub struct SetBandsRequest {
     pub mode: String,
@@ -221,7 +274,7 @@ fn ensure_lte_configured(state: &AppState) -> Result<(), (StatusCode, Json<ApiEr
     }
 }
 
-async fn ensure_capability(
+pub(super) async fn ensure_capability(
     state: &AppState,
     capability: &str,
 ) -> Result<(), (StatusCode, Json<ApiError>)> {
@@ -247,16 +300,17 @@ fn comms_unavailable<T>() -> Result<T, (StatusCode, Json<ApiError>)> {
     Err(unavailable_pair())
 }
 
-fn unavailable_pair() -> (StatusCode, Json<ApiError>) {
+pub(super) fn unavailable_pair() -> (StatusCode, Json<ApiError>) {
     ApiError::new(codes::MODEM_UNAVAILABLE, "comms provider not available")
         .into_response_with(StatusCode::SERVICE_UNAVAILABLE)
 }
 
-fn provider_error(err: crate::comms::CommsCallError) -> (StatusCode, Json<ApiError>) {
+pub(super) fn provider_error(err: crate::comms::CommsCallError) -> (StatusCode, Json<ApiError>) {
     let status = match err.code.as_str() {
         "COMMS_CAPABILITY_UNSUPPORTED" | "UNSUPPORTED" => StatusCode::NOT_IMPLEMENTED,
         "SCAN_RUNNING" | "TUNNEL_CONNECTED" => StatusCode::CONFLICT,
         "MODEM_UNAVAILABLE" => StatusCode::SERVICE_UNAVAILABLE,
+        "SMS_FAILED" => StatusCode::BAD_GATEWAY,
         "INVALID_REQUEST" => StatusCode::BAD_REQUEST,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
     };
@@ -0,0 +1,21 @@
+//! `GET /api/metrics` — Prometheus scrape endpoint (see [`crate::metrics`]).
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+/// `GET /api/metrics` — all metrics in the Prometheus text format.
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        crate::metrics::render(&state).await,
+    )
+        .into_response()
+}
@@ -3,18 +3,59 @@
 //! Each sub-module corresponds to an API endpoint group. All handlers except
 //! [`health`] require authentication via the [`crate::auth::require_api_key`]
 //! middleware.
+//!
+//! List-heavy endpoints (sessions, activity, transfers, info) accept
+//! `?fields=a,b.c` to trim their response; see [`crate::util::project_fields`].
 
 pub mod activity;
+pub mod ai;
+pub mod artifacts;
+pub mod auth;
+pub mod connections;
+pub mod copy;
+pub mod dav;
 pub mod diagnostics;
 pub mod events;
 pub mod exec;
+pub mod expose;
+pub mod fetch;
 pub mod files;
+pub mod firewall;
+pub mod firmware;
+pub mod flight_recorder;
+#[cfg(feature = "comms")]
 pub mod gps;
 pub mod health;
+pub mod hooks;
 pub mod info;
+pub mod logs;
+#[cfg(feature = "comms")]
 pub mod lte;
+pub mod metrics;
+#[cfg(feature = "comms")]
+pub mod modem;
+pub mod netman;
 pub mod playbooks;
+pub mod plugins;
+pub mod quiet_hours;
+pub mod resolve;
 pub mod safe_mode;
+pub mod services;
 pub mod sessions;
+pub mod sftp;
 pub mod shells;
+pub mod snapshots;
+pub mod snmp;
+pub mod ssh;
 pub mod stp;
+pub mod support_bundle;
+pub mod time;
+pub mod twin;
+pub mod users;
+
+/// Query parameters for endpoints that only support `?fields=`.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct FieldsQuery {
+    /// Comma-separated fields to keep in each item.
+    pub fields: Option<String>,
+}
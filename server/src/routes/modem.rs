@@ -0,0 +1,172 @@
+//! Carrier SMS through the comms provider's modem.
+//!
+//! - `GET /api/modem/sms` — messages in the modem's storage
+//! - `POST /api/modem/sms` — send a text message
+//! - `DELETE /api/modem/sms/{index}` — delete a stored message
+//!
+//! SMS works without a data connection, so it doubles as an out-of-band
+//! channel: a device whose data plan has run out can still be reached by
+//! text, and carrier provisioning messages can be read back. All three need
+//! the provider's `cellular.sms` capability.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use sctl_comms_protocol::{capabilities, methods};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::lte::{ensure_capability, provider_error, unavailable_pair};
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Sending waits for the network to accept the message; leave the provider
+/// room for its own 60s AT timeout.
+const SEND_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Longest text the provider sends as a single text-mode SMS.
+const MAX_TEXT: usize = 160;
+
+#[derive(Deserialize)]
+pub struct SendRequest {
+    pub number: String,
+    pub text: String,
+}
+
+/// `GET /api/modem/sms` — list stored messages, oldest first.
+///
+/// Returns `{"messages": [{index, status, number, timestamp, text}]}`.
+/// `status` is `unread`, `read`, `unsent` or `sent`; listing marks unread
+/// messages as read on the modem.
+pub async fn sms_list(State(state): State<AppState>) -> ApiResult<Value> {
+    ensure_capability(&state, capabilities::CELLULAR_SMS).await?;
+    let client = state.comms_client.as_ref().ok_or_else(unavailable_pair)?;
+    let result = client
+        .call(methods::SMS_LIST, json!({}))
+        .await
+        .map_err(provider_error)?;
+    Ok(Json(result))
+}
+
+/// `POST /api/modem/sms` — send `{"number": "+15551234567", "text": "..."}`.
+///
+/// Returns `{"ok": true, "reference": <network message reference>}`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — bad number, or text empty, over 160 characters or
+///   outside printable ASCII
+/// - `502` with `{"code":"SMS_FAILED"}` — the modem or network refused it
+/// - `503` with `{"code":"MODEM_UNAVAILABLE"}` — no provider or modem
+pub async fn sms_send(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<SendRequest>,
+) -> ApiResult<Value> {
+    validate(&req).map_err(|msg| {
+        ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+    })?;
+    ensure_capability(&state, capabilities::CELLULAR_SMS).await?;
+    let client = state.comms_client.as_ref().ok_or_else(unavailable_pair)?;
+    let result = client
+        .call_with_timeout(
+            methods::SMS_SEND,
+            json!({ "number": req.number, "text": req.text }),
+            SEND_TIMEOUT,
+        )
+        .await
+        .map_err(provider_error)?;
+    let reference = result.get("reference").cloned().unwrap_or(Value::Null);
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SmsSend,
+            source_from_headers(&headers),
+            format!("SMS to {}", req.number),
+            Some(json!({
+                "number": req.number,
+                "chars": req.text.chars().count(),
+                "reference": reference,
+            })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "ok": true, "reference": reference })))
+}
+
+/// `DELETE /api/modem/sms/{index}` — delete the message at a storage index
+/// from `GET /api/modem/sms`.
+pub async fn sms_delete(
+    State(state): State<AppState>,
+    Path(index): Path<u32>,
+    headers: HeaderMap,
+) -> ApiResult<Value> {
+    ensure_capability(&state, capabilities::CELLULAR_SMS).await?;
+    let client = state.comms_client.as_ref().ok_or_else(unavailable_pair)?;
+    client
+        .call(methods::SMS_DELETE, json!({ "index": index }))
+        .await
+        .map_err(provider_error)?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SmsDelete,
+            source_from_headers(&headers),
+            format!("SMS {index} deleted"),
+            Some(json!({ "index": index })),
+            request_id_from_headers(&headers),
+        )
+        .await;
+
+    Ok(Json(json!({ "ok": true, "index": index })))
+}
+
+/// Reject what the provider would refuse, before waking the modem.
+fn validate(req: &SendRequest) -> Result<(), String> {
+    let digits = req.number.strip_prefix('+').unwrap_or(&req.number);
+    if !(3..=20).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid phone number: {}", req.number));
+    }
+    if req.text.is_empty() {
+        return Err("text cannot be empty".to_string());
+    }
+    if req.text.chars().count() > MAX_TEXT {
+        return Err(format!("text longer than {MAX_TEXT} characters"));
+    }
+    if req
+        .text
+        .chars()
+        .any(|c| c != '\n' && !(c.is_ascii_graphic() || c == ' '))
+    {
+        return Err("text must be printable ASCII".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_bad_input() {
+        let req = |number: &str, text: &str| SendRequest {
+            number: number.to_string(),
+            text: text.to_string(),
+        };
+        assert!(validate(&req("+15551234567", "wake up\nnow")).is_ok());
+        assert!(validate(&req("+1 555", "x")).is_err());
+        assert!(validate(&req("+15551234567", "")).is_err());
+        assert!(validate(&req("+15551234567", "\u{1a}")).is_err());
+        assert!(validate(&req("+15551234567", &"x".repeat(161))).is_err());
+    }
+}
@@ -0,0 +1,663 @@
+//! NetworkManager connection management over D-Bus.
+//!
+//! - `GET /api/netman/connections` — saved connections and which are active
+//! - `POST /api/netman/connections/{conn}/up` — activate, waiting until it
+//!   is up (or `wait_secs` passes)
+//! - `POST /api/netman/connections/{conn}/down` — deactivate
+//! - `GET /api/netman/wwan` — modem devices with NetworkManager's and, when
+//!   running, ModemManager's view of them
+//!
+//! `{conn}` is a connection UUID or name, as with `nmcli connection up`.
+//! Calls go to the system bus through [`crate::dbus`]; up and down are
+//! journaled as `netman_up` / `netman_down`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::dbus::{Connection, Error, Value as DbusValue};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+const NM: &str = "org.freedesktop.NetworkManager";
+const NM_PATH: &str = "/org/freedesktop/NetworkManager";
+const SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+const SETTINGS: &str = "org.freedesktop.NetworkManager.Settings";
+const SETTINGS_CONNECTION: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+const ACTIVE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+const DEVICE: &str = "org.freedesktop.NetworkManager.Device";
+const DEVICE_MODEM: &str = "org.freedesktop.NetworkManager.Device.Modem";
+const IP4_CONFIG: &str = "org.freedesktop.NetworkManager.IP4Config";
+const MM: &str = "org.freedesktop.ModemManager1";
+const MM_MODEM: &str = "org.freedesktop.ModemManager1.Modem";
+const MM_3GPP: &str = "org.freedesktop.ModemManager1.Modem.Modem3gpp";
+
+/// `NM_DEVICE_TYPE_MODEM`.
+const DEVICE_TYPE_MODEM: u64 = 8;
+
+/// Default and maximum for `wait_secs` on up.
+const DEFAULT_WAIT_SECS: u64 = 30;
+const MAX_WAIT_SECS: u64 = 300;
+
+/// How often an activating connection's state is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Body for `POST /api/netman/connections/{conn}/up`.
+#[derive(Debug, Default, Deserialize)]
+pub struct UpRequest {
+    /// Seconds to wait for the connection to come up (default 30, max 300,
+    /// 0 = return once activation has started).
+    pub wait_secs: Option<u64>,
+}
+
+// ─── Names ───────────────────────────────────────────────────────────────────
+
+/// `NMActiveConnectionState`.
+fn active_state(state: u64) -> &'static str {
+    match state {
+        1 => "activating",
+        2 => "activated",
+        3 => "deactivating",
+        4 => "deactivated",
+        _ => "unknown",
+    }
+}
+
+/// `NMDeviceState`.
+fn device_state(state: u64) -> &'static str {
+    match state {
+        10 => "unmanaged",
+        20 => "unavailable",
+        30 => "disconnected",
+        40 => "prepare",
+        50 => "config",
+        60 => "need_auth",
+        70 => "ip_config",
+        80 => "ip_check",
+        90 => "secondaries",
+        100 => "activated",
+        110 => "deactivating",
+        120 => "failed",
+        _ => "unknown",
+    }
+}
+
+/// `MMModemState`.
+fn modem_state(state: i64) -> &'static str {
+    match state {
+        -1 => "failed",
+        1 => "initializing",
+        2 => "locked",
+        3 => "disabled",
+        4 => "disabling",
+        5 => "enabling",
+        6 => "enabled",
+        7 => "searching",
+        8 => "registered",
+        9 => "disconnecting",
+        10 => "connecting",
+        11 => "connected",
+        _ => "unknown",
+    }
+}
+
+/// `MMModem3gppRegistrationState`.
+fn registration_state(state: u64) -> &'static str {
+    match state {
+        0 => "idle",
+        1 => "home",
+        2 => "searching",
+        3 => "denied",
+        5 => "roaming",
+        6 | 8 => "home_sms_only",
+        7 | 9 => "roaming_sms_only",
+        10 => "emergency_only",
+        _ => "unknown",
+    }
+}
+
+/// Set bits of `MMModemAccessTechnology`.
+fn access_technologies(mask: u64) -> Vec<&'static str> {
+    const NAMES: [&str; 18] = [
+        "pots",
+        "gsm",
+        "gsm_compact",
+        "gprs",
+        "edge",
+        "umts",
+        "hsdpa",
+        "hsupa",
+        "hspa",
+        "hspa_plus",
+        "1xrtt",
+        "evdo0",
+        "evdoa",
+        "evdob",
+        "lte",
+        "5gnr",
+        "lte_cat_m",
+        "lte_nb_iot",
+    ];
+    NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+// ─── Helpers ─────────────────────────────────────────────────────────────────
+
+/// Map a D-Bus failure to a response.
+fn dbus_error(e: &Error) -> (StatusCode, Json<ApiError>) {
+    let (status, code) = match e {
+        Error::Connect(_) => (StatusCode::SERVICE_UNAVAILABLE, codes::NETMAN_UNAVAILABLE),
+        Error::Remote { name, .. }
+            if name == "org.freedesktop.DBus.Error.ServiceUnknown"
+                || name == "org.freedesktop.DBus.Error.NameHasNoOwner" =>
+        {
+            (StatusCode::SERVICE_UNAVAILABLE, codes::NETMAN_UNAVAILABLE)
+        }
+        _ => (StatusCode::BAD_GATEWAY, codes::NETMAN_FAILED),
+    };
+    ApiError::new(code, e.to_string()).into_response_with(status)
+}
+
+async fn bus() -> Result<Connection, (StatusCode, Json<ApiError>)> {
+    Connection::system().await.map_err(|e| dbus_error(&e))
+}
+
+/// A saved connection: its settings object and `connection` section.
+struct Saved {
+    path: String,
+    settings: DbusValue,
+}
+
+impl Saved {
+    fn field(&self, key: &str) -> Option<&DbusValue> {
+        self.settings.get("connection").and_then(|c| c.get(key))
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        self.field(key).and_then(DbusValue::as_str)
+    }
+}
+
+async fn saved_connections(bus: &mut Connection) -> Result<Vec<Saved>, Error> {
+    let reply = bus
+        .call(NM, SETTINGS_PATH, SETTINGS, "ListConnections", &[])
+        .await?;
+    let mut out = Vec::new();
+    for path in reply.first().map(DbusValue::as_array).unwrap_or_default() {
+        let Some(path) = path.as_str() else {
+            continue;
+        };
+        // A connection deleted between the list and this call is skipped.
+        let Ok(settings) = bus
+            .call(NM, path, SETTINGS_CONNECTION, "GetSettings", &[])
+            .await
+        else {
+            continue;
+        };
+        out.push(Saved {
+            path: path.to_string(),
+            settings: settings
+                .into_iter()
+                .next()
+                .unwrap_or(DbusValue::Dict(Vec::new())),
+        });
+    }
+    Ok(out)
+}
+
+/// An active connection's path and properties.
+async fn active_connections(bus: &mut Connection) -> Result<Vec<(String, DbusValue)>, Error> {
+    let paths = bus.property(NM, NM_PATH, NM, "ActiveConnections").await?;
+    let mut out = Vec::new();
+    for path in paths.as_array() {
+        let Some(path) = path.as_str() else {
+            continue;
+        };
+        if let Ok(props) = bus.properties(NM, path, ACTIVE).await {
+            out.push((path.to_string(), props));
+        }
+    }
+    Ok(out)
+}
+
+/// Interface names of the devices behind `paths`.
+async fn device_names(bus: &mut Connection, paths: &[DbusValue]) -> Vec<String> {
+    let mut names = Vec::new();
+    for path in paths.iter().filter_map(DbusValue::as_str) {
+        if let Ok(name) = bus.property(NM, path, DEVICE, "Interface").await {
+            names.extend(name.as_str().map(ToString::to_string));
+        }
+    }
+    names
+}
+
+fn matches(key: &str, uuid: Option<&str>, id: Option<&str>) -> bool {
+    uuid == Some(key) || id == Some(key)
+}
+
+fn not_found(key: &str) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::NOT_FOUND, format!("No connection '{key}'"))
+        .into_response_with(StatusCode::NOT_FOUND)
+}
+
+/// `{"addresses": ["10.0.0.2/24"], "gateway": ..., "dns": [...]}` for an
+/// `IP4Config` object, or null.
+async fn ip4(bus: &mut Connection, path: Option<&str>) -> Value {
+    let Some(path) = path.filter(|p| *p != "/") else {
+        return Value::Null;
+    };
+    let Ok(props) = bus.properties(NM, path, IP4_CONFIG).await else {
+        return Value::Null;
+    };
+    let list = |key: &str, with_prefix: bool| -> Vec<String> {
+        props
+            .get(key)
+            .map(DbusValue::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|entry| {
+                let address = entry.get("address")?.as_str()?;
+                Some(match entry.get("prefix").and_then(DbusValue::as_u64) {
+                    Some(prefix) if with_prefix => format!("{address}/{prefix}"),
+                    _ => address.to_string(),
+                })
+            })
+            .collect()
+    };
+    json!({
+        "addresses": list("AddressData", true),
+        "gateway": props.get("Gateway").and_then(DbusValue::as_str),
+        "dns": list("NameserverData", false),
+    })
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// `GET /api/netman/connections` — saved connections, sorted by name.
+///
+/// # Errors
+///
+/// - `503` with `{"code":"NETMAN_UNAVAILABLE"}` — no system bus or
+///   NetworkManager isn't running
+/// - `502` with `{"code":"NETMAN_FAILED"}` — NetworkManager refused a call
+pub async fn list_connections() -> ApiResult<Value> {
+    let mut bus = bus().await?;
+    let saved = saved_connections(&mut bus)
+        .await
+        .map_err(|e| dbus_error(&e))?;
+    let active = active_connections(&mut bus)
+        .await
+        .map_err(|e| dbus_error(&e))?;
+
+    let mut by_settings: HashMap<String, Value> = HashMap::new();
+    for (_, props) in &active {
+        let Some(settings) = props.get("Connection").and_then(DbusValue::as_str) else {
+            continue;
+        };
+        let devices = device_names(
+            &mut bus,
+            props
+                .get("Devices")
+                .map(DbusValue::as_array)
+                .unwrap_or_default(),
+        )
+        .await;
+        by_settings.insert(
+            settings.to_string(),
+            json!({
+                "state": active_state(props.get("State").and_then(DbusValue::as_u64).unwrap_or(0)),
+                "devices": devices,
+                "default_route": props.get("Default").and_then(DbusValue::as_bool).unwrap_or(false)
+                    || props.get("Default6").and_then(DbusValue::as_bool).unwrap_or(false),
+            }),
+        );
+    }
+
+    let mut connections: Vec<Value> = saved
+        .iter()
+        .map(|c| {
+            let active = by_settings.remove(&c.path);
+            json!({
+                "uuid": c.text("uuid"),
+                "id": c.text("id"),
+                "type": c.text("type"),
+                "interface": c.text("interface-name"),
+                "autoconnect": c.field("autoconnect").and_then(DbusValue::as_bool).unwrap_or(true),
+                "active": active.is_some(),
+                "state": active.as_ref().map(|a| a["state"].clone()),
+                "devices": active.as_ref().map_or_else(|| json!([]), |a| a["devices"].clone()),
+                "default_route": active.is_some_and(|a| a["default_route"] == true),
+            })
+        })
+        .collect();
+    connections.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+    Ok(Json(json!({ "connections": connections })))
+}
+
+/// `POST /api/netman/connections/{conn}/up` — activate a saved connection.
+///
+/// Returns once NetworkManager reports it activated, or right after the
+/// request is accepted with `wait_secs: 0`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — `wait_secs` over 300
+/// - `404 Not Found` — no connection with that UUID or name
+/// - `502` with `{"code":"NETMAN_FAILED"}` — activation refused or failed
+/// - `503` with `{"code":"NETMAN_UNAVAILABLE"}`
+/// - `504` with `{"code":"TIMEOUT"}` — still activating after `wait_secs`
+pub async fn connection_up(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Option<Json<UpRequest>>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let wait_secs = body
+        .and_then(|Json(b)| b.wait_secs)
+        .unwrap_or(DEFAULT_WAIT_SECS);
+    if wait_secs > MAX_WAIT_SECS {
+        return Err(ApiError::new(
+            codes::INVALID_REQUEST,
+            format!("wait_secs must be at most {MAX_WAIT_SECS}"),
+        )
+        .into_response_with(StatusCode::BAD_REQUEST));
+    }
+
+    let mut bus = bus().await?;
+    let saved = saved_connections(&mut bus)
+        .await
+        .map_err(|e| dbus_error(&e))?;
+    let conn = saved
+        .iter()
+        .find(|c| matches(&key, c.text("uuid"), c.text("id")))
+        .ok_or_else(|| not_found(&key))?;
+    let (uuid, id) = (conn.text("uuid"), conn.text("id"));
+
+    let started = Instant::now();
+    let args = [
+        DbusValue::ObjectPath(conn.path.clone()),
+        DbusValue::ObjectPath("/".into()),
+        DbusValue::ObjectPath("/".into()),
+    ];
+    let reply = bus
+        .call(NM, NM_PATH, NM, "ActivateConnection", &args)
+        .await
+        .map_err(|e| dbus_error(&e))?;
+    let active_path = reply
+        .first()
+        .and_then(DbusValue::as_str)
+        .unwrap_or_default()
+        .to_string();
+
+    let deadline = started + Duration::from_secs(wait_secs);
+    let current = loop {
+        let current = match bus.property(NM, &active_path, ACTIVE, "State").await {
+            Ok(v) => v.as_u64().unwrap_or(0),
+            // The object goes away once activation gives up.
+            Err(Error::Remote { .. }) => 4,
+            Err(e) => return Err(dbus_error(&e)),
+        };
+        if current != 1 || wait_secs == 0 || Instant::now() >= deadline {
+            break current;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    #[allow(clippy::cast_possible_truncation)]
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    let outcome = active_state(current);
+    state
+        .activity_log
+        .log(
+            ActivityType::NetmanUp,
+            source,
+            format!("Connection {} up: {outcome}", id.unwrap_or(&key)),
+            Some(json!({
+                "uuid": uuid,
+                "id": id,
+                "state": outcome,
+                "elapsed_ms": elapsed_ms,
+            })),
+            req_id,
+        )
+        .await;
+
+    match current {
+        2 => {}
+        1 | 3 if wait_secs == 0 => {}
+        1 => {
+            return Err(ApiError::new(
+                codes::TIMEOUT,
+                format!("Connection still activating after {wait_secs}s"),
+            )
+            .with_detail(json!({ "uuid": uuid, "state": outcome }))
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT));
+        }
+        _ => {
+            return Err(ApiError::new(
+                codes::NETMAN_FAILED,
+                format!("Activation failed ({outcome})"),
+            )
+            .with_detail(json!({ "uuid": uuid, "state": outcome }))
+            .into_response_with(StatusCode::BAD_GATEWAY));
+        }
+    }
+
+    Ok(Json(json!({
+        "ok": true,
+        "uuid": uuid,
+        "id": id,
+        "state": outcome,
+        "elapsed_ms": elapsed_ms,
+    })))
+}
+
+/// `POST /api/netman/connections/{conn}/down` — deactivate. Deactivating a
+/// saved connection that isn't active succeeds with `changed: false`.
+///
+/// # Errors
+///
+/// - `404 Not Found` — no saved or active connection with that UUID or name
+/// - `502` with `{"code":"NETMAN_FAILED"}`
+/// - `503` with `{"code":"NETMAN_UNAVAILABLE"}`
+pub async fn connection_down(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    let mut bus = bus().await?;
+    let active = active_connections(&mut bus)
+        .await
+        .map_err(|e| dbus_error(&e))?;
+    let found = active.iter().find(|(_, props)| {
+        matches(
+            &key,
+            props.get("Uuid").and_then(DbusValue::as_str),
+            props.get("Id").and_then(DbusValue::as_str),
+        )
+    });
+    let Some((path, props)) = found else {
+        let saved = saved_connections(&mut bus)
+            .await
+            .map_err(|e| dbus_error(&e))?;
+        let conn = saved
+            .iter()
+            .find(|c| matches(&key, c.text("uuid"), c.text("id")))
+            .ok_or_else(|| not_found(&key))?;
+        return Ok(Json(json!({
+            "ok": true,
+            "uuid": conn.text("uuid"),
+            "id": conn.text("id"),
+            "changed": false,
+        })));
+    };
+    let (uuid, id) = (
+        props.get("Uuid").and_then(DbusValue::as_str),
+        props.get("Id").and_then(DbusValue::as_str),
+    );
+
+    bus.call(
+        NM,
+        NM_PATH,
+        NM,
+        "DeactivateConnection",
+        &[DbusValue::ObjectPath(path.clone())],
+    )
+    .await
+    .map_err(|e| dbus_error(&e))?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::NetmanDown,
+            source,
+            format!("Connection {} down", id.unwrap_or(&key)),
+            Some(json!({ "uuid": uuid, "id": id })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "ok": true,
+        "uuid": uuid,
+        "id": id,
+        "changed": true,
+    })))
+}
+
+/// `GET /api/netman/wwan` — modem devices and their connection state.
+///
+/// # Errors
+///
+/// - `503` with `{"code":"NETMAN_UNAVAILABLE"}`
+/// - `502` with `{"code":"NETMAN_FAILED"}`
+pub async fn wwan() -> ApiResult<Value> {
+    let mut bus = bus().await?;
+    let reply = bus
+        .call(NM, NM_PATH, NM, "GetDevices", &[])
+        .await
+        .map_err(|e| dbus_error(&e))?;
+
+    let mut devices = Vec::new();
+    for path in reply.first().map(DbusValue::as_array).unwrap_or_default() {
+        let Some(path) = path.as_str() else {
+            continue;
+        };
+        let Ok(dev) = bus.properties(NM, path, DEVICE).await else {
+            continue;
+        };
+        if dev.get("DeviceType").and_then(DbusValue::as_u64) != Some(DEVICE_TYPE_MODEM) {
+            continue;
+        }
+        let text = |v: &DbusValue, key: &str| {
+            v.get(key)
+                .and_then(DbusValue::as_str)
+                .map(ToString::to_string)
+        };
+        let number = |v: &DbusValue, key: &str| v.get(key).and_then(DbusValue::as_u64);
+
+        let connection = match dev.get("ActiveConnection").and_then(DbusValue::as_str) {
+            Some(active) if active != "/" => {
+                bus.properties(NM, active, ACTIVE).await.ok().map(|a| {
+                    json!({
+                        "uuid": text(&a, "Uuid"),
+                        "id": text(&a, "Id"),
+                        "state": active_state(number(&a, "State").unwrap_or(0)),
+                    })
+                })
+            }
+            _ => None,
+        };
+        let ip4 = ip4(&mut bus, dev.get("Ip4Config").and_then(DbusValue::as_str)).await;
+        let nm_modem = bus.properties(NM, path, DEVICE_MODEM).await.ok();
+
+        // The device's UDI is the ModemManager object path.
+        let udi = text(&dev, "Udi").unwrap_or_default();
+        let modem_manager = if udi.starts_with("/org/freedesktop/ModemManager1/") {
+            match bus.properties(MM, &udi, MM_MODEM).await {
+                Ok(m) => {
+                    let gpp = bus.properties(MM, &udi, MM_3GPP).await.ok();
+                    let signal = m
+                        .get("SignalQuality")
+                        .map(DbusValue::as_array)
+                        .unwrap_or_default();
+                    Some(json!({
+                        "manufacturer": text(&m, "Manufacturer"),
+                        "model": text(&m, "Model"),
+                        "revision": text(&m, "Revision"),
+                        "imei": gpp.as_ref().and_then(|g| text(g, "Imei"))
+                            .or_else(|| text(&m, "EquipmentIdentifier")),
+                        "state": modem_state(m.get("State").and_then(DbusValue::as_i64).unwrap_or(0)),
+                        "signal_quality": signal.first().and_then(DbusValue::as_u64),
+                        "access_technologies": access_technologies(number(&m, "AccessTechnologies").unwrap_or(0)),
+                        "own_numbers": m.get("OwnNumbers").map_or(Value::Null, DbusValue::to_json),
+                        "operator_name": gpp.as_ref().and_then(|g| text(g, "OperatorName")),
+                        "operator_code": gpp.as_ref().and_then(|g| text(g, "OperatorCode")),
+                        "registration": gpp.as_ref().map(|g| {
+                            registration_state(number(g, "RegistrationState").unwrap_or(4))
+                        }),
+                    }))
+                }
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        let reason = dev
+            .get("StateReason")
+            .map(DbusValue::as_array)
+            .unwrap_or_default();
+        devices.push(json!({
+            "interface": text(&dev, "Interface"),
+            "ip_interface": text(&dev, "IpInterface"),
+            "driver": text(&dev, "Driver"),
+            "state": device_state(number(&dev, "State").unwrap_or(0)),
+            "state_reason": reason.get(1).and_then(DbusValue::as_u64),
+            "connection": connection,
+            "ip4": ip4,
+            "modem": nm_modem.map(|m| json!({
+                "device_id": text(&m, "DeviceId"),
+                "operator_code": text(&m, "OperatorCode"),
+                "apn": text(&m, "Apn"),
+                "capabilities": number(&m, "CurrentCapabilities"),
+            })),
+            "modem_manager": modem_manager,
+        }));
+    }
+    Ok(Json(json!({ "devices": devices })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enum_names() {
+        assert_eq!(access_technologies(0x4000 | 0x200), ["hspa_plus", "lte"]);
+        assert_eq!(active_state(2), "activated");
+        assert_eq!(device_state(120), "failed");
+        assert_eq!(modem_state(-1), "failed");
+        assert_eq!(registration_state(5), "roaming");
+    }
+}
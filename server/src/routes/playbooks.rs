@@ -1,10 +1,20 @@
-//! Playbook CRUD endpoints — list, get, create/update, delete.
+//! Playbook endpoints — list, get, create/update, delete, run.
 //!
 //! Playbooks are Markdown files with YAML frontmatter stored in the configured
 //! `playbooks_dir`. The frontmatter defines name, description, and typed
 //! parameters; the body must contain a fenced `sh` or `bash` code block.
+//!
+//! `POST /api/playbooks/{name}/run` runs every such block in order as a step
+//! (see [`run_playbook`]). The nearest heading above a block names its step,
+//! and a fence like ```` ```sh timeout_ms=60000 ```` sets its timeout. Each
+//! run's report is kept under `<data_dir>/playbook_runs/<name>/`:
+//!
+//! - `GET /api/playbooks/{name}/runs` — recent runs, newest first
+//! - `GET /api/playbooks/{name}/runs/{run_id}` — one full report
 
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
 
 use axum::{
     extract::{Path, State},
@@ -14,8 +24,9 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::activity::{request_id_from_headers, source_from_headers, ActivitySource, ActivityType};
 use crate::error::{codes, ApiError};
+use crate::shell::process::{self, ExecError};
 use crate::AppState;
 
 type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
@@ -23,6 +34,9 @@ type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
 /// Maximum playbook content size (1 MB).
 const MAX_PLAYBOOK_SIZE: usize = 1024 * 1024;
 
+/// Run reports kept per playbook; older ones are deleted.
+const MAX_RUN_REPORTS: usize = 20;
+
 // ─── Types ───────────────────────────────────────────────────────────────────
 
 #[derive(Deserialize)]
@@ -69,8 +83,8 @@ struct ParamDetail {
 
 // ─── Helpers ─────────────────────────────────────────────────────────────────
 
-/// Parse YAML frontmatter and script from markdown content.
-fn parse_playbook(markdown: &str) -> Result<(FrontMatter, String), String> {
+/// Split markdown content into its YAML frontmatter and body.
+fn split_frontmatter(markdown: &str) -> Result<(&str, &str), String> {
     let trimmed = markdown.trim_start();
     if !trimmed.starts_with("---") {
         return Err("Missing YAML frontmatter (must start with ---)".into());
@@ -79,8 +93,12 @@ fn parse_playbook(markdown: &str) -> Result<(FrontMatter, String), String> {
     let close_pos = after_open
         .find("\n---")
         .ok_or("Missing closing --- for frontmatter")?;
-    let yaml_str = &after_open[..close_pos];
-    let body = &after_open[close_pos + 4..];
+    Ok((&after_open[..close_pos], &after_open[close_pos + 4..]))
+}
+
+/// Parse YAML frontmatter and script from markdown content.
+fn parse_playbook(markdown: &str) -> Result<(FrontMatter, String), String> {
+    let (yaml_str, body) = split_frontmatter(markdown)?;
 
     let fm: FrontMatter =
         serde_yaml::from_str(yaml_str).map_err(|e| format!("YAML parse error: {e}"))?;
@@ -127,6 +145,112 @@ fn extract_script_block(body: &str) -> Result<String, String> {
     Err("No ```sh or ```bash code block found".into())
 }
 
+/// One fenced `sh`/`bash` block of a playbook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    name: String,
+    script: String,
+    timeout_ms: Option<u64>,
+}
+
+/// Every `sh`/`bash` block in `body`, named after the nearest heading above
+/// it (`step <n>` if there is none).
+fn extract_steps(body: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut heading: Option<String> = None;
+    let mut current: Option<(Option<u64>, Vec<&str>)> = None;
+    for line in body.lines() {
+        let trimmed = line.trim();
+        match current.as_mut() {
+            None => {
+                if let Some(info) = trimmed
+                    .strip_prefix("```sh")
+                    .or_else(|| trimmed.strip_prefix("```bash"))
+                {
+                    let timeout_ms = info
+                        .split_whitespace()
+                        .find_map(|kv| kv.strip_prefix("timeout_ms="))
+                        .and_then(|v| v.parse().ok());
+                    current = Some((timeout_ms, Vec::new()));
+                } else if trimmed.starts_with('#') {
+                    let text = trimmed.trim_start_matches('#').trim();
+                    heading = (!text.is_empty()).then(|| text.to_string());
+                }
+            }
+            Some((_, lines)) if !trimmed.starts_with("```") => lines.push(line),
+            Some(_) => {
+                let (timeout_ms, lines) = current.take().unwrap_or_default();
+                steps.push(Step {
+                    name: heading
+                        .take()
+                        .unwrap_or_else(|| format!("step {}", steps.len() + 1)),
+                    script: lines.join("\n"),
+                    timeout_ms,
+                });
+            }
+        }
+    }
+    steps
+}
+
+/// Substitute `{{param}}` placeholders the way the MCP server does: values
+/// from `args`, falling back to the param's default.
+fn render_script(
+    script: &str,
+    params: &HashMap<String, RawParam>,
+    args: &Value,
+) -> Result<String, String> {
+    let mut script = script.to_string();
+    for (name, def) in params {
+        let placeholder = format!("{{{{{name}}}}}");
+        if !script.contains(&placeholder) {
+            continue;
+        }
+        let value = args
+            .get(name)
+            .filter(|v| !v.is_null())
+            .or(def.default.as_ref())
+            .ok_or_else(|| format!("Missing required parameter: {name}"))?;
+        let rendered = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        script = script.replace(&placeholder, &rendered);
+    }
+    if let Some(start) = script.find("{{") {
+        if let Some(end) = script[start + 2..].find("}}") {
+            return Err(format!(
+                "Script references undeclared parameter: {{{{{}}}}}",
+                &script[start + 2..start + 2 + end]
+            ));
+        }
+    }
+    Ok(script)
+}
+
+/// Read `<playbooks_dir>/<name>.md`.
+async fn read_playbook(
+    state: &AppState,
+    name: &str,
+) -> Result<String, (StatusCode, Json<ApiError>)> {
+    let file_path = format!("{}/{}.md", state.config.server.playbooks_dir, name);
+    tokio::fs::read_to_string(&file_path).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            ApiError::new(codes::NOT_FOUND, format!("Playbook '{name}' not found"))
+                .into_response_with(StatusCode::NOT_FOUND)
+        } else {
+            ApiError::new(codes::IO_ERROR, format!("Failed to read playbook: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    })
+}
+
+/// A stored playbook that no longer parses.
+fn invalid_playbook(e: &str) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_CONTENT, format!("Invalid playbook: {e}"))
+        .into_response_with(StatusCode::UNPROCESSABLE_ENTITY)
+}
+
 fn validate_playbook_name(name: &str) -> Result<(), (StatusCode, Json<ApiError>)> {
     if name.is_empty()
         || !name
@@ -219,22 +343,8 @@ pub async fn get_playbook(
     validate_playbook_name(&name)?;
     let source = source_from_headers(&headers);
     let req_id = request_id_from_headers(&headers);
-    let file_path = format!("{}/{}.md", state.config.server.playbooks_dir, name);
-
-    let content = tokio::fs::read_to_string(&file_path).await.map_err(|e| {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            ApiError::new(codes::NOT_FOUND, format!("Playbook '{name}' not found"))
-                .into_response_with(StatusCode::NOT_FOUND)
-        } else {
-            ApiError::new(codes::IO_ERROR, format!("Failed to read playbook: {e}"))
-                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-    })?;
-
-    let (fm, script) = parse_playbook(&content).map_err(|e| {
-        ApiError::new(codes::INVALID_CONTENT, format!("Invalid playbook: {e}"))
-            .into_response_with(StatusCode::UNPROCESSABLE_ENTITY)
-    })?;
+    let content = read_playbook(&state, &name).await?;
+    let (fm, script) = parse_playbook(&content).map_err(|e| invalid_playbook(&e))?;
 
     let params: HashMap<String, ParamDetail> = fm
         .params
@@ -364,3 +474,461 @@ pub async fn delete_playbook(
 
     Ok(Json(json!({"ok": true, "name": name})))
 }
+
+// ─── Runs ────────────────────────────────────────────────────────────────────
+
+/// Request body for `POST /api/playbooks/{name}/run`.
+#[derive(Debug, Default, Deserialize)]
+pub struct RunRequest {
+    /// Values for the playbook's `{{param}}` placeholders.
+    #[serde(default)]
+    pub args: Value,
+    /// Timeout for steps without their own `timeout_ms` (default
+    /// `server.exec_timeout_ms`).
+    pub timeout_ms: Option<u64>,
+    /// Run the remaining steps after one fails instead of skipping them.
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// Outcome of one step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepReport {
+    pub name: String,
+    /// `ok`, `failed` (non-zero exit), `timeout`, `denied` (by the
+    /// `pre_exec` hook), `error` or `skipped`.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    /// Command stderr, or why the step didn't run.
+    pub stderr: String,
+    pub duration_ms: u64,
+}
+
+impl StepReport {
+    fn not_run(name: &str, status: &str, reason: String, duration_ms: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            status: status.to_string(),
+            exit_code: None,
+            stdout: String::new(),
+            stderr: reason,
+            duration_ms,
+        }
+    }
+}
+
+/// A finished run, as returned and as kept on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub playbook: String,
+    /// `ok` if every step succeeded, else `failed`.
+    pub status: String,
+    /// Unix ms.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub source: ActivitySource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub args: Value,
+    pub steps: Vec<StepReport>,
+}
+
+/// `POST /api/playbooks/{name}/run` -- run a playbook's steps in order.
+///
+/// Each step runs with the default shell and working directory, under its
+/// own timeout, and goes through the `pre_exec` hook like any exec. After
+/// the first step that doesn't succeed the rest are `skipped`, unless
+/// `continue_on_error` is set. Progress is broadcast to `/api/events` and
+/// `/api/ws` clients as `playbook.run.started`, `playbook.step.started`,
+/// `playbook.step.finished` and `playbook.run.finished`. The run finishes,
+/// and its report is kept, even if the client goes away. A run whose steps
+/// fail still returns `200` with `status: "failed"`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — a missing or
+///   undeclared parameter
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+/// - `422 Unprocessable Entity` with `{"code":"INVALID_CONTENT"}`
+/// - `403`/`429` from the AI guard (each step counts as one exec)
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — a step is refused by
+///   `[policy]`; nothing runs
+pub async fn run_playbook(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<RunRequest>,
+) -> ApiResult<RunReport> {
+    run(&state, &headers, &name, payload).await
+}
+
+/// Run a playbook for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`run_playbook`].
+pub async fn run(
+    state: &AppState,
+    headers: &HeaderMap,
+    name: &str,
+    payload: RunRequest,
+) -> ApiResult<RunReport> {
+    validate_playbook_name(name)?;
+    let content = read_playbook(state, name).await?;
+    let (fm, _script) = parse_playbook(&content).map_err(|e| invalid_playbook(&e))?;
+    let (_, body) = split_frontmatter(&content).map_err(|e| invalid_playbook(&e))?;
+    let steps = extract_steps(body)
+        .into_iter()
+        .map(|step| {
+            Ok(Step {
+                script: render_script(&step.script, &fm.params, &payload.args)?,
+                ..step
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| {
+            ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+        })?;
+    for step in &steps {
+        crate::shell::policy::check(state, source_from_headers(headers), &step.script).await?;
+    }
+    state.ai_guard.charge_execs(headers, steps.len() as u64)?;
+
+    let task = tokio::spawn(execute(
+        state.clone(),
+        name.to_string(),
+        steps,
+        payload,
+        source_from_headers(headers),
+        request_id_from_headers(headers),
+    ));
+    let report = task.await.map_err(|_| {
+        ApiError::new(codes::EXEC_FAILED, "Playbook run task failed")
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    Ok(Json(report))
+}
+
+/// Run `steps`, broadcasting progress, then journal and store the report.
+async fn execute(
+    state: AppState,
+    name: String,
+    steps: Vec<Step>,
+    payload: RunRequest,
+    source: ActivitySource,
+    request_id: Option<String>,
+) -> RunReport {
+    let run_id = uuid::Uuid::new_v4().to_string();
+    let started = Instant::now();
+    #[allow(clippy::cast_possible_truncation)]
+    let started_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let event = |kind: &str, extra: Value| {
+        let mut event = json!({
+            "type": format!("playbook.{kind}"),
+            "run_id": run_id,
+            "playbook": name,
+            "request_id": request_id,
+        });
+        if let (Some(event), Value::Object(extra)) = (event.as_object_mut(), extra) {
+            event.extend(extra);
+        }
+        let _ = state.session_events.send(event);
+    };
+    event(
+        "run.started",
+        json!({ "steps": steps.iter().map(|s| &s.name).collect::<Vec<_>>() }),
+    );
+
+    let default_timeout = payload
+        .timeout_ms
+        .unwrap_or(state.config.server.exec_timeout_ms);
+    let mut failed = false;
+    let mut reports = Vec::with_capacity(steps.len());
+    for (index, step) in steps.iter().enumerate() {
+        if failed && !payload.continue_on_error {
+            reports.push(StepReport::not_run(
+                &step.name,
+                "skipped",
+                "An earlier step failed".to_string(),
+                0,
+            ));
+            continue;
+        }
+        event("step.started", json!({ "index": index, "name": step.name }));
+        let report = run_step(
+            &state,
+            source,
+            step,
+            step.timeout_ms.unwrap_or(default_timeout),
+        )
+        .await;
+        event(
+            "step.finished",
+            json!({
+                "index": index,
+                "name": step.name,
+                "status": report.status,
+                "exit_code": report.exit_code,
+                "duration_ms": report.duration_ms,
+            }),
+        );
+        failed |= report.status != "ok";
+        reports.push(report);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let report = RunReport {
+        run_id,
+        playbook: name,
+        status: if failed { "failed" } else { "ok" }.to_string(),
+        started_at,
+        duration_ms,
+        source,
+        request_id,
+        args: payload.args,
+        steps: reports,
+    };
+    let failed_step = report
+        .steps
+        .iter()
+        .find(|s| s.status != "ok" && s.status != "skipped")
+        .map(|s| s.name.clone());
+    let _ = state.session_events.send(json!({
+        "type": "playbook.run.finished",
+        "run_id": report.run_id,
+        "playbook": report.playbook,
+        "request_id": report.request_id,
+        "status": report.status,
+        "failed_step": failed_step,
+        "duration_ms": duration_ms,
+    }));
+    state
+        .activity_log
+        .log(
+            ActivityType::PlaybookRun,
+            source,
+            format!("Ran playbook '{}' ({})", report.playbook, report.status),
+            Some(json!({
+                "run_id": report.run_id,
+                "status": report.status,
+                "steps": report.steps.len(),
+                "failed_step": failed_step,
+                "duration_ms": duration_ms,
+            })),
+            report.request_id.clone(),
+        )
+        .await;
+    if let Err(e) = save_report(&state, &report).await {
+        tracing::warn!(playbook = %report.playbook, error = %e, "Failed to store playbook run report");
+    }
+    report
+}
+
+/// Run one step with the default shell and working directory.
+async fn run_step(
+    state: &AppState,
+    source: ActivitySource,
+    step: &Step,
+    timeout_ms: u64,
+) -> StepReport {
+    let shell = &state.config.shell.default_shell;
+    let working_dir = crate::util::expand_tilde(&state.config.shell.default_working_dir);
+    if let Err((_, Json(err))) =
+        crate::hooks::check_exec(state, source, &step.script, shell, &working_dir)
+    {
+        return StepReport::not_run(&step.name, "denied", err.message, 0);
+    }
+    match process::exec_command(shell, &working_dir, &step.script, timeout_ms, None).await {
+        Ok(result) => StepReport {
+            name: step.name.clone(),
+            status: if result.exit_code == 0 {
+                "ok"
+            } else {
+                "failed"
+            }
+            .to_string(),
+            exit_code: Some(result.exit_code),
+            stdout: result.stdout,
+            stderr: result.stderr,
+            duration_ms: result.duration_ms,
+        },
+        Err(ExecError::Timeout) => StepReport::not_run(
+            &step.name,
+            "timeout",
+            format!("Step timed out after {timeout_ms}ms"),
+            timeout_ms,
+        ),
+        Err(e) => StepReport::not_run(&step.name, "error", e.to_string(), 0),
+    }
+}
+
+/// `<data_dir>/playbook_runs/<name>`.
+fn runs_dir(state: &AppState, name: &str) -> PathBuf {
+    std::path::Path::new(&state.config.server.data_dir)
+        .join("playbook_runs")
+        .join(name)
+}
+
+/// Report files in `dir`, oldest first (names start with `started_at`).
+async fn report_files(dir: &std::path::Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Write `report` and drop all but the newest [`MAX_RUN_REPORTS`].
+async fn save_report(state: &AppState, report: &RunReport) -> std::io::Result<()> {
+    let dir = runs_dir(state, &report.playbook);
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!("{}_{}.json", report.started_at, report.run_id));
+    tokio::fs::write(&path, serde_json::to_vec_pretty(report)?).await?;
+    let files = report_files(&dir).await;
+    for old in &files[..files.len().saturating_sub(MAX_RUN_REPORTS)] {
+        let _ = tokio::fs::remove_file(old).await;
+    }
+    Ok(())
+}
+
+/// `started_at` of the newest stored run of `name`, if any.
+pub async fn last_run_at(state: &AppState, name: &str) -> Option<u64> {
+    let files = report_files(&runs_dir(state, name)).await;
+    let stem = files.last()?.file_stem()?.to_str()?.to_string();
+    stem.split('_').next()?.parse().ok()
+}
+
+/// `GET /api/playbooks/{name}/runs` -- stored runs, newest first, without
+/// step output.
+pub async fn list_runs(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> ApiResult<Value> {
+    validate_playbook_name(&name)?;
+    let mut runs = Vec::new();
+    for path in report_files(&runs_dir(&state, &name)).await.iter().rev() {
+        let Ok(data) = tokio::fs::read(path).await else {
+            continue;
+        };
+        let Ok(report) = serde_json::from_slice::<RunReport>(&data) else {
+            continue;
+        };
+        runs.push(json!({
+            "run_id": report.run_id,
+            "status": report.status,
+            "started_at": report.started_at,
+            "duration_ms": report.duration_ms,
+            "source": report.source,
+            "steps": report
+                .steps
+                .iter()
+                .map(|s| json!({ "name": s.name, "status": s.status, "exit_code": s.exit_code }))
+                .collect::<Vec<_>>(),
+        }));
+    }
+    Ok(Json(json!({ "playbook": name, "runs": runs })))
+}
+
+/// `GET /api/playbooks/{name}/runs/{run_id}` -- one stored run report.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+pub async fn get_run(
+    State(state): State<AppState>,
+    Path((name, run_id)): Path<(String, String)>,
+) -> ApiResult<RunReport> {
+    validate_playbook_name(&name)?;
+    let suffix = format!("_{run_id}.json");
+    let not_found = || {
+        ApiError::new(
+            codes::NOT_FOUND,
+            format!("No run '{run_id}' of playbook '{name}'"),
+        )
+        .into_response_with(StatusCode::NOT_FOUND)
+    };
+    let path = report_files(&runs_dir(&state, &name))
+        .await
+        .into_iter()
+        .find(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.ends_with(&suffix))
+        })
+        .ok_or_else(not_found)?;
+    let data = tokio::fs::read(&path).await.map_err(|_| not_found())?;
+    let report = serde_json::from_slice(&data).map_err(|e| {
+        ApiError::new(codes::IO_ERROR, format!("Corrupt run report: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYBOOK: &str = r"---
+name: rotate-logs
+description: Rotate logs
+params:
+  keep:
+    type: number
+    default: 5
+  unit:
+    type: string
+---
+# Stop
+```sh
+systemctl stop app
+```
+Some prose.
+## Rotate {{unit}}
+```bash timeout_ms=60000
+logrotate --keep {{keep}} {{unit}}
+```
+```sh
+systemctl start app
+```
+";
+
+    #[test]
+    fn steps_are_fenced_blocks_named_by_heading() {
+        let (_, body) = split_frontmatter(PLAYBOOK).unwrap();
+        let steps = extract_steps(body);
+        let names: Vec<_> = steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Stop", "Rotate {{unit}}", "step 3"]);
+        assert_eq!(steps[0].timeout_ms, None);
+        assert_eq!(steps[1].timeout_ms, Some(60_000));
+        assert_eq!(steps[1].script, "logrotate --keep {{keep}} {{unit}}");
+    }
+
+    #[test]
+    fn render_uses_args_then_defaults() {
+        let (fm, _) = parse_playbook(PLAYBOOK).unwrap();
+        let script = "logrotate --keep {{keep}} {{unit}}";
+        assert_eq!(
+            render_script(script, &fm.params, &json!({ "unit": "nginx" })).unwrap(),
+            "logrotate --keep 5 nginx"
+        );
+        assert!(render_script(script, &fm.params, &json!({}))
+            .unwrap_err()
+            .contains("unit"));
+        assert!(render_script("echo {{other}}", &fm.params, &json!({}))
+            .unwrap_err()
+            .contains("undeclared"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Plugin endpoints (see [`crate::plugins`]).
+//!
+//! - `GET /api/plugins` — discovered plugins and their commands
+//! - `GET /api/plugins/{name}` — one plugin
+//! - `POST /api/plugins/{name}/{command}` — run a command with `{"args"}`
+//!
+//! A run counts as one exec against the caller's AI budget and is journaled
+//! as `plugin_run`, whether or not the plugin succeeds.
+
+use std::time::Instant;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Request body for `POST /api/plugins/{name}/{command}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct RunRequest {
+    /// Passed to the plugin unchanged as the request's `args`.
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// `GET /api/plugins` — every plugin in the directory, rescanned now.
+pub async fn list_plugins(State(state): State<AppState>) -> Json<Value> {
+    let plugins = state.plugins.list().await;
+    Json(json!({
+        "dir": state.plugins.dir().display().to_string(),
+        "plugins": plugins,
+    }))
+}
+
+/// `GET /api/plugins/{name}` — one plugin's description and commands.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+pub async fn get_plugin(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> ApiResult<Value> {
+    match state.plugins.get(&name).await {
+        Some(info) => Ok(Json(json!(info))),
+        None => Err(
+            ApiError::new(codes::NOT_FOUND, format!("No plugin named '{name}'"))
+                .into_response_with(StatusCode::NOT_FOUND),
+        ),
+    }
+}
+
+/// `POST /api/plugins/{name}/{command}` — run a plugin command.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — unknown plugin or command
+/// - `502 Bad Gateway` with `{"code":"PLUGIN_FAILED"}`
+/// - `504 Gateway Timeout` with `{"code":"TIMEOUT"}`
+/// - `403`/`429` from the AI guard
+pub async fn run_plugin(
+    State(state): State<AppState>,
+    Path((name, command)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(payload): Json<RunRequest>,
+) -> ApiResult<Value> {
+    run(&state, &headers, &name, &command, payload.args).await
+}
+
+/// Run a plugin command for any transport: REST, `/api/ws` and the tunnel
+/// all end up here.
+///
+/// # Errors
+///
+/// As [`run_plugin`].
+pub async fn run(
+    state: &AppState,
+    headers: &HeaderMap,
+    name: &str,
+    command: &str,
+    args: Value,
+) -> ApiResult<Value> {
+    state.ai_guard.charge_execs(headers, 1)?;
+
+    let started = Instant::now();
+    let result = state.plugins.run(name, command, args).await;
+    #[allow(clippy::cast_possible_truncation)]
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    let (summary, error) = match &result {
+        Ok(_) => (format!("Plugin {name} {command}"), None),
+        Err(e) => (format!("Plugin {name} {command} FAILED"), Some(e.message())),
+    };
+    state
+        .activity_log
+        .log(
+            ActivityType::PluginRun,
+            source_from_headers(headers),
+            summary,
+            Some(json!({
+                "plugin": name,
+                "command": command,
+                "duration_ms": duration_ms,
+                "error": error,
+            })),
+            request_id_from_headers(headers),
+        )
+        .await;
+
+    match result {
+        Ok(reply) => Ok(Json(json!({
+            "plugin": name,
+            "command": command,
+            "result": reply,
+            "duration_ms": duration_ms,
+        }))),
+        Err(e) => Err(e.into_response()),
+    }
+}
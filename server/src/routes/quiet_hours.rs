@@ -0,0 +1,42 @@
+//! `GET /api/quiet-hours` — the `[quiet_hours]` windows, whether one is
+//! running, and what it is holding back. See [`crate::quiet_hours`].
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// `GET /api/quiet-hours` — returns `{config, active, mode, until,
+/// queued_pushes, deferred_transfers}`. `until` is when the running quiet
+/// period ends (unix seconds), `queued_pushes` lists the artifact pushes
+/// waiting for it, and `deferred_transfers` the ids of held-back transfers.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — `[quiet_hours]` is not configured
+pub async fn status(State(state): State<AppState>) -> ApiResult<Value> {
+    let Some(quiet) = &state.quiet_hours else {
+        return Err(ApiError::new(
+            codes::NOT_FOUND,
+            "Quiet hours not configured on this device",
+        )
+        .into_response_with(StatusCode::NOT_FOUND));
+    };
+    let deferred: Vec<String> = state
+        .transfer_manager
+        .list()
+        .await
+        .transfers
+        .into_iter()
+        .filter(|t| t.phase == "deferred")
+        .map(|t| t.transfer_id)
+        .collect();
+    let mut resp = quiet.summary();
+    resp["config"] = json!(quiet.config());
+    resp["queued_pushes"] = json!(quiet.queued());
+    resp["deferred_transfers"] = json!(deferred);
+    Ok(Json(resp))
+}
@@ -0,0 +1,90 @@
+//! `GET /api/resolve` — resolve a name on the device, or at its relay.
+//!
+//! See [`crate::tunnel::resolve`] for why both views are useful.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::{codes, ApiError};
+use crate::tunnel::resolve;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// How long to wait for the relay's answer. The relay's own lookup is
+/// bounded by a shorter timeout.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveQuery {
+    pub name: String,
+    /// `device` (default) or `relay`.
+    #[serde(default)]
+    pub via: Option<String>,
+}
+
+/// `GET /api/resolve?name=<host>[&via=relay]` — returns `{name, addresses,
+/// error, duration_ms, nameservers, search, via}`. A lookup that fails is
+/// still `200` with empty `addresses` and `error` set.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — bad `name` or `via`
+/// - `409 Conflict` with `{"code":"TUNNEL_DISCONNECTED"}` — `via=relay` and
+///   no relay answered
+pub async fn resolve(
+    State(state): State<AppState>,
+    Query(query): Query<ResolveQuery>,
+) -> ApiResult<Value> {
+    match query.via.as_deref().unwrap_or("device") {
+        "device" => local(&query.name).await,
+        "relay" => via_relay(&state, &query.name).await,
+        other => Err(invalid(format!(
+            "Invalid via '{other}': expected device or relay"
+        ))),
+    }
+}
+
+/// Resolve `name` here. Also answers the relay's `tunnel.resolve`.
+///
+/// # Errors
+///
+/// `400 INVALID_REQUEST` for a missing or malformed name.
+pub async fn local(name: &str) -> ApiResult<Value> {
+    if !resolve::valid_name(name) {
+        return Err(invalid("Invalid or missing 'name'".to_string()));
+    }
+    let mut body = resolve::lookup(name).await;
+    body["via"] = json!("device");
+    Ok(Json(body))
+}
+
+async fn via_relay(state: &AppState, name: &str) -> ApiResult<Value> {
+    if !resolve::valid_name(name) {
+        return Err(invalid("Invalid or missing 'name'".to_string()));
+    }
+    let reply = state
+        .relay_requests
+        .request(
+            json!({"type": "tunnel.resolve", "name": name}),
+            RELAY_TIMEOUT,
+        )
+        .await
+        .map_err(|e| {
+            ApiError::new(codes::TUNNEL_DISCONNECTED, e).into_response_with(StatusCode::CONFLICT)
+        })?;
+    let mut body = reply["body"].clone();
+    body["via"] = json!("relay");
+    Ok(Json(body))
+}
+
+fn invalid(message: String) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_REQUEST, message).into_response_with(StatusCode::BAD_REQUEST)
+}
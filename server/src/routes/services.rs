@@ -0,0 +1,838 @@
+//! System service management: systemd over D-Bus, OpenRC as a fallback.
+//!
+//! - `GET /api/services` — services and their state (`?q=` name substring,
+//!   `?state=` active state)
+//! - `GET /api/services/{name}` — one service in detail
+//! - `POST /api/services/{name}/{action}` — `start`, `stop`, `restart`,
+//!   `reload`, `enable` or `disable`
+//!
+//! The init system is detected per request: systemd when
+//! `/run/systemd/system` exists (the `sd_booted()` test), else OpenRC when
+//! `/run/openrc` does. systemd is driven over the system bus through
+//! [`crate::dbus`], so nothing parses `systemctl` output; OpenRC state is read
+//! from its state and runlevel directories, and changes go through
+//! `rc-service` / `rc-update`.
+//!
+//! `{name}` is a systemd unit (`.service` is added when there's no suffix;
+//! other unit types are refused) or an `/etc/init.d` script. Actions are
+//! checked against `[policy]` as the equivalent command — `systemctl restart
+//! nginx.service`, `rc-service nginx restart` — so rules written for exec
+//! apply here too, and are journaled as `service_control`.
+
+use std::path::Path as FsPath;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::dbus::{Connection, Error, Value as DbusValue};
+use crate::error::{codes, ApiError};
+use crate::shell::policy;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+type ErrorPair = (StatusCode, Json<ApiError>);
+
+const SYSTEMD: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER: &str = "org.freedesktop.systemd1.Manager";
+const UNIT: &str = "org.freedesktop.systemd1.Unit";
+const SERVICE: &str = "org.freedesktop.systemd1.Service";
+const JOB: &str = "org.freedesktop.systemd1.Job";
+
+const OPENRC_RUN: &str = "/run/openrc";
+const OPENRC_INIT_D: &str = "/etc/init.d";
+const OPENRC_RUNLEVELS: &str = "/etc/runlevels";
+
+/// OpenRC state directories under `/run/openrc`; a service in none of them
+/// is stopped.
+const OPENRC_STATES: [&str; 5] = ["started", "starting", "stopping", "inactive", "failed"];
+
+/// Default and maximum for `wait_secs`.
+const DEFAULT_WAIT_SECS: u64 = 30;
+const MAX_WAIT_SECS: u64 = 300;
+
+/// How often a queued systemd job is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    /// Substring of the name.
+    pub q: Option<String>,
+    /// `active_state` to keep (`active`, `inactive`, `failed`, ...).
+    pub state: Option<String>,
+}
+
+/// Body for `POST /api/services/{name}/{action}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ActionRequest {
+    /// Seconds to wait for the change to finish (default 30, max 300). With
+    /// systemd, 0 returns once the job is queued.
+    pub wait_secs: Option<u64>,
+}
+
+// ─── Backends ────────────────────────────────────────────────────────────────
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Systemd,
+    OpenRc,
+}
+
+impl Backend {
+    fn detect() -> Result<Self, ErrorPair> {
+        if FsPath::new("/run/systemd/system").is_dir() {
+            Ok(Self::Systemd)
+        } else if FsPath::new(OPENRC_RUN).is_dir() {
+            Ok(Self::OpenRc)
+        } else {
+            Err(ApiError::new(
+                codes::SERVICES_UNAVAILABLE,
+                "Neither systemd nor OpenRC is running",
+            )
+            .into_response_with(StatusCode::SERVICE_UNAVAILABLE))
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Systemd => "systemd",
+            Self::OpenRc => "openrc",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+    Enable,
+    Disable,
+}
+
+impl Action {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "start" => Self::Start,
+            "stop" => Self::Stop,
+            "restart" => Self::Restart,
+            "reload" => Self::Reload,
+            "enable" => Self::Enable,
+            "disable" => Self::Disable,
+            _ => return None,
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+            Self::Reload => "reload",
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+        }
+    }
+
+    /// The command an operator would type, for `[policy]`.
+    fn command(self, backend: Backend, name: &str) -> String {
+        let action = self.as_str();
+        match (backend, self) {
+            (Backend::Systemd, _) => format!("systemctl {action} {name}"),
+            (Backend::OpenRc, Self::Enable) => format!("rc-update add {name} default"),
+            (Backend::OpenRc, Self::Disable) => format!("rc-update del {name}"),
+            (Backend::OpenRc, _) => format!("rc-service {name} {action}"),
+        }
+    }
+}
+
+/// Validate `name` for `backend`: a `.service` unit for systemd, a plain
+/// script name for OpenRC.
+fn service_name(backend: Backend, name: &str) -> Result<String, String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "-_.@:\\".contains(c);
+    if name.is_empty() || name.len() > 255 || name.starts_with(['-', '.']) {
+        return Err(format!("Invalid service name '{name}'"));
+    }
+    if !name.chars().all(allowed) {
+        return Err(format!("Invalid service name '{name}'"));
+    }
+    match backend {
+        Backend::Systemd => {
+            const OTHER: [&str; 10] = [
+                ".socket",
+                ".timer",
+                ".target",
+                ".mount",
+                ".automount",
+                ".path",
+                ".slice",
+                ".scope",
+                ".device",
+                ".swap",
+            ];
+            if OTHER.iter().any(|s| name.ends_with(s)) {
+                return Err(format!("'{name}' is not a .service unit"));
+            }
+            Ok(if name.ends_with(".service") {
+                name.to_string()
+            } else {
+                format!("{name}.service")
+            })
+        }
+        Backend::OpenRc => {
+            let name = name.strip_suffix(".service").unwrap_or(name);
+            if name.contains(['@', ':', '\\']) {
+                return Err(format!("Invalid OpenRC service name '{name}'"));
+            }
+            Ok(name.to_string())
+        }
+    }
+}
+
+// ─── systemd ─────────────────────────────────────────────────────────────────
+
+/// Map a D-Bus failure to a response.
+fn dbus_error(e: &Error) -> ErrorPair {
+    let (status, code) = match e {
+        Error::Connect(_) => (StatusCode::SERVICE_UNAVAILABLE, codes::SERVICES_UNAVAILABLE),
+        Error::Remote { name, .. } => match name.as_str() {
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+            | "org.freedesktop.DBus.Error.NameHasNoOwner" => {
+                (StatusCode::SERVICE_UNAVAILABLE, codes::SERVICES_UNAVAILABLE)
+            }
+            "org.freedesktop.systemd1.NoSuchUnit" => (StatusCode::NOT_FOUND, codes::NOT_FOUND),
+            "org.freedesktop.DBus.Error.AccessDenied"
+            | "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" => {
+                (StatusCode::FORBIDDEN, codes::PERMISSION_DENIED)
+            }
+            _ => (StatusCode::BAD_GATEWAY, codes::SERVICE_FAILED),
+        },
+        Error::Io(_) => (StatusCode::BAD_GATEWAY, codes::SERVICE_FAILED),
+    };
+    ApiError::new(code, e.to_string()).into_response_with(status)
+}
+
+async fn bus() -> Result<Connection, ErrorPair> {
+    Connection::system().await.map_err(|e| dbus_error(&e))
+}
+
+fn strings(names: &[&str]) -> DbusValue {
+    DbusValue::Array(names.iter().map(|n| DbusValue::Str((*n).into())).collect())
+}
+
+async fn systemd_list(bus: &mut Connection) -> Result<Vec<Value>, Error> {
+    let pattern = strings(&["*.service"]);
+    let units = bus
+        .call(
+            SYSTEMD,
+            SYSTEMD_PATH,
+            MANAGER,
+            "ListUnitsByPatterns",
+            &[strings(&[]), pattern.clone()],
+        )
+        .await?;
+    let files = bus
+        .call(
+            SYSTEMD,
+            SYSTEMD_PATH,
+            MANAGER,
+            "ListUnitFilesByPatterns",
+            &[strings(&[]), pattern],
+        )
+        .await?;
+
+    // a(ss): unit file path, enablement state.
+    let mut file_states: std::collections::BTreeMap<String, String> = files
+        .first()
+        .map(DbusValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|f| {
+            let f = f.as_array();
+            let path = f.first()?.as_str()?;
+            let name = path.rsplit('/').next()?;
+            Some((name.to_string(), f.get(1)?.as_str()?.to_string()))
+        })
+        .collect();
+
+    // a(ssssssouso): name, description, load, active, sub, following, path, ...
+    let mut out = Vec::new();
+    for unit in units.first().map(DbusValue::as_array).unwrap_or_default() {
+        let u = unit.as_array();
+        let text = |i: usize| u.get(i).and_then(DbusValue::as_str);
+        let Some(name) = text(0) else {
+            continue;
+        };
+        out.push(json!({
+            "name": name,
+            "description": text(1),
+            "load_state": text(2),
+            "active_state": text(3),
+            "sub_state": text(4),
+            "enabled": file_states.remove(name),
+        }));
+    }
+    // Installed but not loaded: never started since boot, or stopped and
+    // garbage-collected. Templates (`foo@.service`) can't run as such.
+    for (name, enabled) in file_states {
+        if name.ends_with("@.service") {
+            continue;
+        }
+        out.push(json!({
+            "name": name,
+            "description": null,
+            "load_state": "not-loaded",
+            "active_state": "inactive",
+            "sub_state": "dead",
+            "enabled": enabled,
+        }));
+    }
+    Ok(out)
+}
+
+/// Object path of `unit`, loading it if needed.
+async fn systemd_unit_path(bus: &mut Connection, unit: &str) -> Result<String, Error> {
+    let reply = bus
+        .call(
+            SYSTEMD,
+            SYSTEMD_PATH,
+            MANAGER,
+            "LoadUnit",
+            &[DbusValue::Str(unit.into())],
+        )
+        .await?;
+    Ok(reply
+        .first()
+        .and_then(DbusValue::as_str)
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Detailed state of `unit`, or `None` if there is no such unit file.
+async fn systemd_status(bus: &mut Connection, unit: &str) -> Result<Option<Value>, Error> {
+    let path = systemd_unit_path(bus, unit).await?;
+    let props = bus.properties(SYSTEMD, &path, UNIT).await?;
+    let text = |key: &str| props.get(key).and_then(DbusValue::as_str);
+    if text("LoadState") == Some("not-found") {
+        return Ok(None);
+    }
+    let service = bus
+        .properties(SYSTEMD, &path, SERVICE)
+        .await
+        .unwrap_or(DbusValue::Dict(Vec::new()));
+    let number = |key: &str| service.get(key).and_then(DbusValue::as_u64);
+    let since = props
+        .get("ActiveEnterTimestamp")
+        .and_then(DbusValue::as_u64)
+        .filter(|&usec| usec > 0)
+        .map(|usec| crate::util::format_iso8601_utc(usec / 1_000_000));
+
+    Ok(Some(json!({
+        "name": unit,
+        "description": text("Description"),
+        "load_state": text("LoadState"),
+        "active_state": text("ActiveState"),
+        "sub_state": text("SubState"),
+        "enabled": text("UnitFileState").filter(|s| !s.is_empty()),
+        "fragment_path": text("FragmentPath").filter(|s| !s.is_empty()),
+        "active_since": since,
+        "main_pid": number("MainPID").filter(|&pid| pid > 0),
+        "exec_main_status": service.get("ExecMainStatus").and_then(DbusValue::as_i64),
+        "restarts": number("NRestarts"),
+        // u64::MAX means no memory accounting.
+        "memory_bytes": number("MemoryCurrent").filter(|&b| b != u64::MAX),
+        "result": service.get("Result").and_then(DbusValue::as_str),
+    })))
+}
+
+/// Outcome of an action, for the response and the journal.
+struct Outcome {
+    active_state: Option<String>,
+    sub_state: Option<String>,
+    enabled: Option<String>,
+    detail: Value,
+}
+
+/// Run `action` on `unit` and wait up to `wait` for its job to finish.
+async fn systemd_action(
+    bus: &mut Connection,
+    unit: &str,
+    action: Action,
+    wait: Duration,
+) -> Result<(Outcome, bool), Error> {
+    let name = DbusValue::Str(unit.into());
+    let mut detail = json!({});
+    let mut finished = true;
+    match action {
+        Action::Start | Action::Stop | Action::Restart | Action::Reload => {
+            let method = match action {
+                Action::Start => "StartUnit",
+                Action::Stop => "StopUnit",
+                Action::Restart => "RestartUnit",
+                _ => "ReloadUnit",
+            };
+            let reply = bus
+                .call(
+                    SYSTEMD,
+                    SYSTEMD_PATH,
+                    MANAGER,
+                    method,
+                    &[name, DbusValue::Str("replace".into())],
+                )
+                .await?;
+            let job = reply
+                .first()
+                .and_then(DbusValue::as_str)
+                .unwrap_or_default()
+                .to_string();
+            // The job object goes away once it has run.
+            let deadline = Instant::now() + wait;
+            loop {
+                match bus.property(SYSTEMD, &job, JOB, "State").await {
+                    Err(Error::Remote { .. }) => break,
+                    Err(e) => return Err(e),
+                    Ok(_) if Instant::now() >= deadline => {
+                        finished = false;
+                        break;
+                    }
+                    Ok(_) => tokio::time::sleep(POLL_INTERVAL).await,
+                }
+            }
+        }
+        Action::Enable | Action::Disable => {
+            let files = DbusValue::Array(vec![name]);
+            let reply = if action == Action::Enable {
+                let args = [files, DbusValue::Bool(false), DbusValue::Bool(false)];
+                bus.call(SYSTEMD, SYSTEMD_PATH, MANAGER, "EnableUnitFiles", &args)
+                    .await?
+            } else {
+                let args = [files, DbusValue::Bool(false)];
+                bus.call(SYSTEMD, SYSTEMD_PATH, MANAGER, "DisableUnitFiles", &args)
+                    .await?
+            };
+            // Enable returns (b carries_install_info, a(sss) changes);
+            // disable just the changes.
+            let changes: Vec<Value> = reply
+                .last()
+                .map(DbusValue::as_array)
+                .unwrap_or_default()
+                .iter()
+                .map(|c| {
+                    let c = c.as_array();
+                    let text = |i: usize| c.get(i).and_then(DbusValue::as_str);
+                    json!({ "type": text(0), "file": text(1), "destination": text(2) })
+                })
+                .collect();
+            detail = json!({ "changes": changes });
+            // As `systemctl enable` does, so the new state is picked up.
+            bus.call(SYSTEMD, SYSTEMD_PATH, MANAGER, "Reload", &[])
+                .await?;
+        }
+    }
+
+    let path = systemd_unit_path(bus, unit).await?;
+    let props = bus.properties(SYSTEMD, &path, UNIT).await?;
+    let text = |key: &str| {
+        props
+            .get(key)
+            .and_then(DbusValue::as_str)
+            .filter(|s| !s.is_empty())
+            .map(ToString::to_string)
+    };
+    Ok((
+        Outcome {
+            active_state: text("ActiveState"),
+            sub_state: text("SubState"),
+            enabled: text("UnitFileState"),
+            detail,
+        },
+        finished,
+    ))
+}
+
+// ─── OpenRC ──────────────────────────────────────────────────────────────────
+
+fn openrc_exists(name: &str) -> bool {
+    FsPath::new(OPENRC_INIT_D).join(name).is_file()
+}
+
+/// `description="..."` from the init script, if it sets one.
+fn openrc_description(name: &str) -> Option<String> {
+    let script = std::fs::read_to_string(FsPath::new(OPENRC_INIT_D).join(name)).ok()?;
+    script.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("description=")?;
+        Some(value.trim_matches(['"', '\'']).to_string())
+    })
+}
+
+/// Runlevels `name` is added to.
+fn openrc_runlevels(name: &str) -> Vec<String> {
+    let mut levels: Vec<String> = std::fs::read_dir(OPENRC_RUNLEVELS)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|level| level.path().join(name).exists())
+        .map(|level| level.file_name().to_string_lossy().into_owned())
+        .collect();
+    levels.sort();
+    levels
+}
+
+fn openrc_service(name: &str) -> Value {
+    let sub = OPENRC_STATES
+        .iter()
+        .find(|s| FsPath::new(OPENRC_RUN).join(s).join(name).exists())
+        .copied()
+        .unwrap_or("stopped");
+    let active = match sub {
+        "started" => "active",
+        "starting" => "activating",
+        "stopping" => "deactivating",
+        "failed" => "failed",
+        _ => "inactive",
+    };
+    let runlevels = openrc_runlevels(name);
+    json!({
+        "name": name,
+        "description": openrc_description(name),
+        "load_state": "loaded",
+        "active_state": active,
+        "sub_state": sub,
+        "enabled": if runlevels.is_empty() { "disabled" } else { "enabled" },
+        "runlevels": runlevels,
+    })
+}
+
+fn openrc_list() -> Vec<Value> {
+    std::fs::read_dir(OPENRC_INIT_D)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        // Helpers like `functions.sh` live alongside the scripts.
+        .filter(|name| {
+            !name.starts_with('.') && FsPath::new(name).extension().is_none_or(|ext| ext != "sh")
+        })
+        .map(|name| openrc_service(&name))
+        .collect()
+}
+
+/// Run `rc-service` / `rc-update` for `action`.
+async fn openrc_action(name: &str, action: Action, wait: Duration) -> Result<Value, ErrorPair> {
+    let commands: Vec<(&str, Vec<String>)> = match action {
+        Action::Enable => vec![(
+            "rc-update",
+            vec!["add".into(), name.into(), "default".into()],
+        )],
+        Action::Disable => openrc_runlevels(name)
+            .into_iter()
+            .map(|level| ("rc-update", vec!["del".into(), name.into(), level]))
+            .collect(),
+        _ => vec![("rc-service", vec![name.into(), action.as_str().into()])],
+    };
+    let mut ran = Vec::new();
+    for (program, args) in commands {
+        let output = tokio::time::timeout(
+            wait,
+            tokio::process::Command::new(program)
+                .args(&args)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .map_err(|_| {
+            ApiError::new(
+                codes::TIMEOUT,
+                format!("{program} still running after {}s", wait.as_secs()),
+            )
+            .into_response_with(StatusCode::GATEWAY_TIMEOUT)
+        })?
+        .map_err(|e| {
+            ApiError::new(codes::SERVICE_FAILED, format!("{program}: {e}"))
+                .into_response_with(StatusCode::BAD_GATEWAY)
+        })?;
+        if !output.status.success() {
+            let mut message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if message.is_empty() {
+                message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            }
+            return Err(ApiError::new(
+                codes::SERVICE_FAILED,
+                format!("{program} {} failed: {message}", args.join(" ")),
+            )
+            .into_response_with(StatusCode::BAD_GATEWAY));
+        }
+        ran.push(format!("{program} {}", args.join(" ")));
+    }
+    Ok(json!({ "commands": ran }))
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+fn not_found(name: &str) -> ErrorPair {
+    ApiError::new(codes::NOT_FOUND, format!("No service '{name}'"))
+        .into_response_with(StatusCode::NOT_FOUND)
+}
+
+fn bad_request(message: String) -> ErrorPair {
+    ApiError::new(codes::INVALID_REQUEST, message).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+/// `GET /api/services` — every service, sorted by name.
+///
+/// Returns `{"backend": "systemd"|"openrc", "services": [...]}`. Each service
+/// has `name`, `description`, `load_state`, `active_state` (`active`,
+/// `inactive`, `failed`, `activating`, `deactivating`), `sub_state` (the init
+/// system's own word: `running`, `exited`, `started`, ...) and `enabled`
+/// (`enabled`, `disabled`, `static`, `masked`, ...; OpenRC adds `runlevels`).
+///
+/// # Errors
+///
+/// - `503` with `{"code":"SERVICES_UNAVAILABLE"}` — no systemd or OpenRC, or
+///   the system bus is unreachable
+/// - `502` with `{"code":"SERVICE_FAILED"}` — systemd refused the call
+pub async fn list_services(Query(query): Query<ListQuery>) -> ApiResult<Value> {
+    let backend = Backend::detect()?;
+    let mut services = match backend {
+        Backend::Systemd => {
+            let mut bus = bus().await?;
+            systemd_list(&mut bus).await.map_err(|e| dbus_error(&e))?
+        }
+        Backend::OpenRc => openrc_list(),
+    };
+    services.retain(|s| {
+        query
+            .q
+            .as_deref()
+            .is_none_or(|q| s["name"].as_str().is_some_and(|n| n.contains(q)))
+            && query
+                .state
+                .as_deref()
+                .is_none_or(|state| s["active_state"] == state)
+    });
+    services.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    Ok(Json(json!({
+        "backend": backend.as_str(),
+        "services": services,
+    })))
+}
+
+/// `GET /api/services/{name}` — one service.
+///
+/// The list fields plus, with systemd, `fragment_path`, `active_since`
+/// (RFC 3339), `main_pid`, `exec_main_status`, `restarts`, `memory_bytes`
+/// and `result`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — invalid name, or a unit that isn't a service
+/// - `404 Not Found` — no such service
+/// - `502` / `503` as for the list
+pub async fn service_status(Path(name): Path<String>) -> ApiResult<Value> {
+    let backend = Backend::detect()?;
+    let name = service_name(backend, &name).map_err(bad_request)?;
+    let mut service = match backend {
+        Backend::Systemd => {
+            let mut bus = bus().await?;
+            systemd_status(&mut bus, &name)
+                .await
+                .map_err(|e| dbus_error(&e))?
+                .ok_or_else(|| not_found(&name))?
+        }
+        Backend::OpenRc if openrc_exists(&name) => openrc_service(&name),
+        Backend::OpenRc => return Err(not_found(&name)),
+    };
+    service["backend"] = json!(backend.as_str());
+    Ok(Json(service))
+}
+
+/// `POST /api/services/{name}/{action}` — start, stop, restart, reload,
+/// enable or disable a service.
+///
+/// With systemd, start/stop/restart/reload queue a job and wait for it (the
+/// optional body `{"wait_secs": 30}`, max 300, 0 = don't wait). Enable and
+/// disable change the install symlinks, reload the manager and return the
+/// `changes`. OpenRC runs `rc-service` or `rc-update` to completion, for at
+/// most `wait_secs`. The response has the service's `active_state`,
+/// `sub_state` and `enabled` afterwards.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — unknown action, invalid name, `wait_secs` over 300
+/// - `403 Forbidden` with `{"code":"POLICY_DENIED"}` — refused by `[policy]`
+/// - `403 Forbidden` with `{"code":"PERMISSION_DENIED"}` — systemd refused
+///   the caller
+/// - `404 Not Found` — no such service
+/// - `502` with `{"code":"SERVICE_FAILED"}` — the action failed, or the
+///   service didn't end up in the expected state (e.g. `failed` after start)
+/// - `503` with `{"code":"SERVICES_UNAVAILABLE"}`
+/// - `504` with `{"code":"TIMEOUT"}` — not finished within `wait_secs`
+pub async fn service_action(
+    State(state): State<AppState>,
+    Path((name, action)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Option<Json<ActionRequest>>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let action = Action::parse(&action).ok_or_else(|| {
+        bad_request(format!(
+            "Unknown action '{action}' (start, stop, restart, reload, enable, disable)"
+        ))
+    })?;
+    let wait_secs = body
+        .and_then(|Json(b)| b.wait_secs)
+        .unwrap_or(DEFAULT_WAIT_SECS);
+    if wait_secs > MAX_WAIT_SECS {
+        return Err(bad_request(format!(
+            "wait_secs must be at most {MAX_WAIT_SECS}"
+        )));
+    }
+    let backend = Backend::detect()?;
+    let name = service_name(backend, &name).map_err(bad_request)?;
+
+    let mut bus = match backend {
+        Backend::Systemd => {
+            let mut bus = bus().await?;
+            systemd_status(&mut bus, &name)
+                .await
+                .map_err(|e| dbus_error(&e))?
+                .ok_or_else(|| not_found(&name))?;
+            Some(bus)
+        }
+        Backend::OpenRc if openrc_exists(&name) => None,
+        Backend::OpenRc => return Err(not_found(&name)),
+    };
+    policy::check(&state, source, &action.command(backend, &name)).await?;
+
+    let started = Instant::now();
+    let (outcome, finished) = if let Some(bus) = bus.as_mut() {
+        systemd_action(bus, &name, action, Duration::from_secs(wait_secs))
+            .await
+            .map_err(|e| dbus_error(&e))?
+    } else {
+        let wait = Duration::from_secs(if wait_secs == 0 {
+            DEFAULT_WAIT_SECS
+        } else {
+            wait_secs
+        });
+        let detail = openrc_action(&name, action, wait).await?;
+        let service = openrc_service(&name);
+        let text = |key: &str| service[key].as_str().map(ToString::to_string);
+        (
+            Outcome {
+                active_state: text("active_state"),
+                sub_state: text("sub_state"),
+                enabled: text("enabled"),
+                detail,
+            },
+            true,
+        )
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    let active = outcome.active_state.as_deref().unwrap_or("unknown");
+    let expected = match action {
+        Action::Start | Action::Restart | Action::Reload => active == "active",
+        Action::Stop => active != "active",
+        Action::Enable | Action::Disable => true,
+    };
+    state
+        .activity_log
+        .log(
+            ActivityType::ServiceControl,
+            source,
+            format!("Service {name} {}: {active}", action.as_str()),
+            Some(json!({
+                "name": name,
+                "action": action.as_str(),
+                "backend": backend.as_str(),
+                "active_state": outcome.active_state,
+                "enabled": outcome.enabled,
+                "elapsed_ms": elapsed_ms,
+            })),
+            req_id,
+        )
+        .await;
+
+    let detail = json!({
+        "name": name,
+        "active_state": outcome.active_state,
+        "sub_state": outcome.sub_state,
+    });
+    if !finished && wait_secs > 0 {
+        return Err(ApiError::new(
+            codes::TIMEOUT,
+            format!(
+                "{name} {} still running after {wait_secs}s",
+                action.as_str()
+            ),
+        )
+        .with_detail(detail)
+        .into_response_with(StatusCode::GATEWAY_TIMEOUT));
+    }
+    if finished && wait_secs > 0 && !expected {
+        return Err(ApiError::new(
+            codes::SERVICE_FAILED,
+            format!("{name} is {active} after {}", action.as_str()),
+        )
+        .with_detail(detail)
+        .into_response_with(StatusCode::BAD_GATEWAY));
+    }
+
+    let mut response = json!({
+        "ok": true,
+        "name": name,
+        "action": action.as_str(),
+        "backend": backend.as_str(),
+        "active_state": outcome.active_state,
+        "sub_state": outcome.sub_state,
+        "enabled": outcome.enabled,
+        "elapsed_ms": elapsed_ms,
+    });
+    if let (Some(out), Value::Object(extra)) = (response.as_object_mut(), outcome.detail) {
+        out.extend(extra);
+    }
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_names_per_backend() {
+        let systemd = |n| service_name(Backend::Systemd, n);
+        assert_eq!(systemd("nginx").unwrap(), "nginx.service");
+        assert_eq!(systemd("nginx.service").unwrap(), "nginx.service");
+        assert_eq!(systemd("getty@tty1").unwrap(), "getty@tty1.service");
+        assert!(systemd("reboot.target").is_err());
+        assert!(systemd("a b").is_err());
+        assert!(systemd("-x").is_err());
+        assert!(systemd("../etc").is_err());
+
+        let openrc = |n| service_name(Backend::OpenRc, n);
+        assert_eq!(openrc("sshd.service").unwrap(), "sshd");
+        assert!(openrc("getty@tty1").is_err());
+        assert!(openrc("x/y").is_err());
+
+        assert_eq!(
+            Action::Restart.command(Backend::Systemd, "nginx.service"),
+            "systemctl restart nginx.service"
+        );
+        assert_eq!(
+            Action::Disable.command(Backend::OpenRc, "sshd"),
+            "rc-update del sshd"
+        );
+    }
+}
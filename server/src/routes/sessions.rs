@@ -1,28 +1,48 @@
 //! REST endpoints for session management.
 //!
 //! - `GET    /api/sessions`            — list all sessions
+//! - `POST   /api/sessions`            — start a session, optionally from a template
+//! - `GET    /api/sessions/{id}/history` — commands run in a session, or with
+//!   `?since=` output scrolled back past the in-memory buffer
+//! - `GET    /api/sessions/{id}/journal` — page through on-disk output
+//! - `GET    /api/sessions/{id}/recording` — download an asciinema recording
+//! - `GET    /api/sessions/{id}/processes` — process tree with CPU and memory
 //! - `POST   /api/sessions/{id}/signal` — send POSIX signal
 //! - `DELETE  /api/sessions/{id}`       — kill session
 //! - `PATCH   /api/sessions/{id}`       — rename, set AI permission/status
+//! - `POST   /api/sessions/{id}/share` — mint a relay share link
 
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
+    response::Response,
     Json,
 };
+use std::collections::HashMap;
+
+use base64::Engine;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
+use super::FieldsQuery;
 use crate::activity::{self, request_id_from_headers, ActivityType};
 use crate::error::{codes, ApiError};
+use crate::sessions::decode::OutputEncoding;
+use crate::tunnel::share::{self, ShareClaims, ShareMode};
+use crate::ws::messages::WsServerMsg;
 use crate::AppState;
 
 type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
 
-/// `GET /api/sessions` — list all active sessions (same shape as WS `session.listed`).
-pub async fn list_sessions(State(state): State<AppState>) -> Json<Value> {
+/// `GET /api/sessions?fields=` — list all active sessions (same shape as WS
+/// `session.listed`).
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Query(query): Query<FieldsQuery>,
+) -> Json<Value> {
     let items = state.session_manager.list_sessions().await;
-    let sessions_json: Vec<Value> = items
+    let mut sessions_json: Value = items
         .iter()
         .map(|s| {
             let mut obj = json!({
@@ -51,15 +71,489 @@ pub async fn list_sessions(State(state): State<AppState>) -> Json<Value> {
             if let Some(ref msg) = s.ai_status_message {
                 obj["ai_status_message"] = json!(msg);
             }
+            if let Some(ref title) = s.title {
+                obj["title"] = json!(title);
+            }
+            if let Some(ref cwd) = s.cwd {
+                obj["cwd"] = json!(cwd);
+            }
             obj
         })
         .collect();
+    crate::util::project_fields(&mut sessions_json, query.fields.as_deref());
 
     Json(json!({
         "sessions": sessions_json,
     }))
 }
 
+// ─── Start ───────────────────────────────────────────────────────────────────
+
+/// `POST /api/sessions` — start a session. Takes the same fields as WS
+/// `session.start`, including `template`. Sessions started here have no
+/// owning connection, so they are always persistent; attach over WS or read
+/// them with `/history` and `/journal`.
+pub async fn start_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> ApiResult<Value> {
+    start(&state, &headers, &body).await
+}
+
+/// Start a session for REST and the tunnel (`tunnel.session.start`).
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — unknown template,
+///   bad `env`, `encoding`, `sudo`, `limits` or secret reference
+/// - `500` with `{"code":"IO_ERROR"}` — recording could not be started
+/// - `503 Service Unavailable` with `{"code":"SESSION_LIMIT"}` — no session
+///   could be spawned, usually `max_sessions`
+#[allow(clippy::too_many_lines)]
+pub async fn start(state: &AppState, headers: &HeaderMap, body: &Value) -> ApiResult<Value> {
+    let source = activity::source_from_headers(headers);
+    let req_id = request_id_from_headers(headers);
+    let bad_request = |e: String| {
+        ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+    };
+
+    let body = crate::sessions::templates::expand(state, body)
+        .await
+        .map_err(bad_request)?;
+    let env: Option<HashMap<String, String>> = match body.get("env") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(
+            serde_json::from_value(v.clone())
+                .map_err(|_| bad_request("env must map names to strings".to_string()))?,
+        ),
+    };
+    let use_pty = body["pty"].as_bool().unwrap_or(false);
+    let name = body["name"].as_str();
+    let allows_ai = body["user_allows_ai"].as_bool().unwrap_or(true);
+    #[allow(clippy::cast_possible_truncation)]
+    let rows = body["rows"]
+        .as_u64()
+        .unwrap_or(u64::from(state.config.server.default_terminal_rows)) as u16;
+    #[allow(clippy::cast_possible_truncation)]
+    let cols = body["cols"]
+        .as_u64()
+        .unwrap_or(u64::from(state.config.server.default_terminal_cols)) as u16;
+    let idle_timeout = body["idle_timeout"].as_u64().unwrap_or(0);
+    let record = body["record"].as_bool().unwrap_or(false);
+    let raw_dir = body["working_dir"]
+        .as_str()
+        .unwrap_or(&state.config.shell.default_working_dir);
+    let dir = crate::util::expand_tilde(raw_dir);
+    let sh = body["shell"]
+        .as_str()
+        .unwrap_or(&state.config.shell.default_shell);
+
+    let encoding = OutputEncoding::from_message(&body).map_err(bad_request)?;
+    let limits = state
+        .session_manager
+        .resource_limits(&body)
+        .map_err(bad_request)?;
+    let resolved = crate::shell::secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
+        env.as_ref(),
+    )
+    .await
+    .map_err(bad_request)?;
+    let sudo = crate::shell::sudo::SudoOptions::from_message(&body)
+        .and_then(|s| {
+            s.map(|s| s.prepare_session(use_pty, resolved.env()))
+                .transpose()
+        })
+        .map_err(bad_request)?;
+    let (env, sudo) = match sudo {
+        Some((merged, sudo)) => (Some(merged), Some(sudo)),
+        None => (resolved.env().cloned(), None),
+    };
+
+    let (session_id, pid) = state
+        .session_manager
+        .create_session_with_pty(
+            sh,
+            dir.as_ref(),
+            env.as_ref(),
+            true,
+            use_pty,
+            rows,
+            cols,
+            idle_timeout,
+            name,
+            limits.as_ref(),
+            Some(state.session_events.clone()),
+        )
+        .await
+        .map_err(|e| {
+            ApiError::new(codes::SESSION_LIMIT, e)
+                .into_response_with(StatusCode::SERVICE_UNAVAILABLE)
+        })?;
+    if let Some(sudo) = sudo {
+        crate::shell::sudo::spawn_session_responder(
+            state.session_manager.clone(),
+            session_id.clone(),
+            sudo,
+        );
+    }
+    if !allows_ai {
+        let _ = state
+            .session_manager
+            .set_user_allows_ai(&session_id, false)
+            .await;
+    }
+    if let Some(encoding) = encoding {
+        let _ = state
+            .session_manager
+            .set_encoding(&session_id, encoding)
+            .await;
+    }
+    if record {
+        if let Err(e) = state
+            .session_manager
+            .start_recording(&session_id, sh, rows, cols)
+            .await
+        {
+            state.session_manager.kill_session(&session_id).await;
+            return Err(ApiError::new(codes::IO_ERROR, e)
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+
+    let _ = state.session_events.send(
+        WsServerMsg::SessionCreated {
+            session_id: session_id.clone(),
+            pid,
+            pty: use_pty,
+            persistent: true,
+            user_allows_ai: allows_ai,
+            name: name.map(String::from),
+        }
+        .to_value(),
+    );
+    let limits = state.session_manager.limits(&session_id).await;
+    state
+        .activity_log
+        .log(
+            ActivityType::SessionStart,
+            source,
+            format!("session {}", &session_id[..8.min(session_id.len())]),
+            Some(json!({
+                "session_id": session_id,
+                "pty": use_pty,
+                "persistent": true,
+                "recording": record,
+                "template": body.get("template"),
+                "limits": limits,
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "session_id": session_id,
+        "pid": pid,
+        "persistent": true,
+        "pty": use_pty,
+        "user_allows_ai": allows_ai,
+        "recording": record,
+        "name": name,
+        "limits": limits,
+    })))
+}
+
+// ─── History ─────────────────────────────────────────────────────────────────
+
+/// Query parameters for `GET /api/sessions/{id}/history`.
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Most entries to return. Commands default to the 100 most recent, max
+    /// [`crate::sessions::history::MAX_ENTRIES`]; output defaults to 500, max
+    /// [`MAX_JOURNAL_PAGE`].
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Return output entries with `seq > since` from the journal instead of
+    /// commands.
+    #[serde(default)]
+    pub since: Option<u64>,
+}
+
+/// `GET /api/sessions/{id}/history` — commands run in a session, oldest first.
+///
+/// With `?since=N` it returns output instead: entries after `N`, oldest
+/// first, read from the session's journal. That reaches back past the
+/// in-memory buffer, so a client told by `session.attach` that entries were
+/// `dropped` can fetch them. While `has_more`, pass the last entry's `seq`
+/// as `since` for the next page.
+pub async fn session_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Value> {
+    if let Some(since) = query.since {
+        let limit = query.limit.unwrap_or_else(default_journal_limit);
+        let page = scrollback(&state, &id, since, limit).await?;
+        let entries: Vec<Value> = page.entries.iter().map(journal_entry_json).collect();
+        return Ok(Json(json!({
+            "session_id": id,
+            "entries": entries,
+            "next_seq": page.next_seq,
+            "has_more": page.next_seq.is_some(),
+        })));
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(100)
+        .min(crate::sessions::history::MAX_ENTRIES);
+    let (commands, total) = state
+        .session_manager
+        .command_history(&id, limit)
+        .await
+        .map_err(|e| {
+            ApiError::new(codes::SESSION_NOT_FOUND, e).into_response_with(StatusCode::NOT_FOUND)
+        })?;
+
+    Ok(Json(json!({
+        "session_id": id,
+        "commands": commands,
+        "total": total,
+    })))
+}
+
+/// Up to `limit` output entries with `seq > since` from a session's journal.
+/// Shared by `GET /api/sessions/{id}/history?since=` and the WS and tunnel
+/// `session.history` messages.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — journaling is disabled
+/// - `404 Not Found` with `{"code":"SESSION_NOT_FOUND"}` — no journal for `id`
+/// - `500 Internal Server Error` with `{"code":"IO_ERROR"}`
+pub async fn scrollback(
+    state: &AppState,
+    id: &str,
+    since: u64,
+    limit: usize,
+) -> Result<crate::sessions::journal::JournalPage, (StatusCode, Json<ApiError>)> {
+    journal_page(state, id, since.saturating_add(1), limit).await
+}
+
+// ─── Journal ─────────────────────────────────────────────────────────────────
+
+/// Maximum entries per `GET /api/sessions/{id}/journal` page.
+const MAX_JOURNAL_PAGE: usize = 5000;
+
+/// Query parameters for `GET /api/sessions/{id}/journal`.
+#[derive(Deserialize)]
+pub struct JournalQuery {
+    /// First sequence number to return (inclusive). Defaults to 0.
+    #[serde(default)]
+    pub from_seq: u64,
+    /// Entries per page. Defaults to 500, max [`MAX_JOURNAL_PAGE`].
+    #[serde(default = "default_journal_limit")]
+    pub limit: usize,
+}
+
+fn default_journal_limit() -> usize {
+    500
+}
+
+/// `GET /api/sessions/{id}/journal` — page through a session's full output
+/// history from its on-disk journal, beyond what the in-memory buffer holds.
+///
+/// Works for sessions that are no longer in memory as long as the journal
+/// file hasn't been removed by age-based cleanup.
+pub async fn session_journal(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<JournalQuery>,
+) -> ApiResult<Value> {
+    let page = journal_page(&state, &id, query.from_seq, query.limit).await?;
+    let entries: Vec<Value> = page.entries.iter().map(journal_entry_json).collect();
+
+    Ok(Json(json!({
+        "session_id": id,
+        "shell": page.metadata.shell,
+        "working_dir": page.metadata.working_dir,
+        "created_at": page.metadata.created,
+        "entries": entries,
+        "next_seq": page.next_seq,
+        "has_more": page.next_seq.is_some(),
+    })))
+}
+
+/// `GET /api/sessions/{id}/recording` — download a session's asciinema v2
+/// recording (`record: true` on `session.start`).
+///
+/// Recordings outlive their sessions; a running session's file grows as it
+/// produces output, so a download is a snapshot.
+pub async fn session_recording(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let not_found = || {
+        ApiError::new(
+            codes::SESSION_NOT_FOUND,
+            format!("No recording for session {id}"),
+        )
+        .into_response_with(StatusCode::NOT_FOUND)
+    };
+    let path = state
+        .session_manager
+        .recording_dir()
+        .and_then(|dir| crate::sessions::recording::recording_path(dir, &id))
+        .ok_or_else(not_found)?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => not_found(),
+            _ => ApiError::new(codes::IO_ERROR, format!("Failed to open recording: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+        })?;
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/x-asciicast")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{id}.cast\""),
+        )
+        .body(Body::from_stream(tokio_util::io::ReaderStream::new(file)))
+        .unwrap())
+}
+
+/// Read a page from `id`'s journal, mapping failures to API errors.
+async fn journal_page(
+    state: &AppState,
+    id: &str,
+    from_seq: u64,
+    limit: usize,
+) -> Result<crate::sessions::journal::JournalPage, (StatusCode, Json<ApiError>)> {
+    let Some(dir) = state.session_manager.journal_dir() else {
+        return Err(
+            ApiError::new(codes::NOT_FOUND, "Session journaling is disabled")
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    };
+    let limit = limit.clamp(1, MAX_JOURNAL_PAGE);
+
+    crate::sessions::journal::read_page(&dir, id, from_seq, limit)
+        .await
+        .map_err(|e| {
+            ApiError::new(codes::IO_ERROR, format!("Failed to read journal: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?
+        .ok_or_else(|| {
+            ApiError::new(
+                codes::SESSION_NOT_FOUND,
+                format!("No journal for session {id}"),
+            )
+            .into_response_with(StatusCode::NOT_FOUND)
+        })
+}
+
+fn journal_entry_json(e: &crate::sessions::buffer::OutputEntry) -> Value {
+    let mut entry = json!({
+        "seq": e.seq,
+        "stream": e.stream.as_str(),
+        "data": e.data,
+        "timestamp_ms": e.timestamp_ms,
+    });
+    if let Some(raw) = &e.raw {
+        entry["data_b64"] = json!(base64::engine::general_purpose::STANDARD.encode(raw));
+    }
+    entry
+}
+
+// ─── Processes ───────────────────────────────────────────────────────────────
+
+/// Interval between the two `/proc` samples CPU% is computed from.
+const CPU_SAMPLE_MS: u64 = 250;
+
+/// `GET /api/sessions/{id}/processes` — every process the session's shell
+/// has spawned, as a tree, without running another command in it.
+///
+/// Each entry has `pid`, `ppid`, `pgid`, `depth` (0 for the shell), `state`
+/// (`/proc` letter) and `state_name`, `comm`, `cmdline`, `threads`,
+/// `rss_bytes` and `cpu_percent` over a 250ms sample (100 = one full core).
+/// Parents come before their children. A session that has exited, or was
+/// recovered read-only from the journal, returns an empty list.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"SESSION_NOT_FOUND"}`
+pub async fn session_processes(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Value> {
+    use crate::sessions::processes;
+
+    let (leader, pgid) = state
+        .session_manager
+        .process_ids(&id)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(codes::SESSION_NOT_FOUND, format!("Session {id} not found"))
+                .into_response_with(StatusCode::NOT_FOUND)
+        })?;
+    if leader == 0 {
+        return Ok(Json(json!({ "session_id": id, "processes": [] })));
+    }
+
+    let before: std::collections::HashMap<u32, u64> = processes::scan()
+        .into_iter()
+        .map(|p| (p.pid, p.cpu_ticks))
+        .collect();
+    tokio::time::sleep(std::time::Duration::from_millis(CPU_SAMPLE_MS)).await;
+    let procs = processes::scan();
+
+    // SAFETY: sysconf has no preconditions.
+    #[cfg(unix)]
+    let (ticks_per_sec, page_size) = unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK).max(1),
+            libc::sysconf(libc::_SC_PAGESIZE).max(1),
+        )
+    };
+    // There is no /proc to scan, so the list is empty anyway.
+    #[cfg(windows)]
+    let (ticks_per_sec, page_size) = (100_i64, 4096_i64);
+    #[allow(clippy::cast_precision_loss, clippy::cast_sign_loss)]
+    let list: Vec<Value> = processes::session_tree(&procs, leader, pgid)
+        .into_iter()
+        .map(|(i, depth)| {
+            let p = &procs[i];
+            let delta = before
+                .get(&p.pid)
+                .map_or(0, |&t| p.cpu_ticks.saturating_sub(t));
+            let cpu = delta as f64 / ticks_per_sec as f64 / (CPU_SAMPLE_MS as f64 / 1000.0);
+            json!({
+                "pid": p.pid,
+                "ppid": p.ppid,
+                "pgid": p.pgid,
+                "depth": depth,
+                "state": p.state.to_string(),
+                "state_name": processes::state_name(p.state),
+                "comm": p.comm,
+                "cmdline": processes::cmdline(p.pid, &p.comm),
+                "threads": p.threads,
+                "rss_bytes": p.rss_pages * page_size as u64,
+                "cpu_percent": (cpu * 1000.0).round() / 10.0,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "session_id": id,
+        "pid": leader,
+        "pgid": pgid,
+        "processes": list,
+    })))
+}
+
 // ─── Signal ──────────────────────────────────────────────────────────────────
 
 #[derive(Deserialize)]
@@ -145,7 +639,91 @@ pub async fn kill_session(
     })))
 }
 
-// ─── Patch (rename, AI permission, AI status) ────────────────────────────────
+// ─── Share ───────────────────────────────────────────────────────────────────
+
+#[derive(Deserialize, Default)]
+pub struct ShareRequest {
+    #[serde(default)]
+    pub mode: ShareMode,
+    /// Link lifetime in seconds. Defaults to 1 hour, max 7 days.
+    pub ttl_secs: Option<u64>,
+}
+
+/// `POST /api/sessions/{id}/share` — mint a time-limited share link for the
+/// session, served by the relay this device tunnels to.
+pub async fn share_session(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ShareRequest>,
+) -> ApiResult<Value> {
+    let source = activity::source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    let relay_url = state
+        .config
+        .tunnel
+        .as_ref()
+        .filter(|t| !t.relay)
+        .and_then(|t| t.url.as_deref())
+        .ok_or_else(|| {
+            ApiError::new(
+                codes::SHARE_UNAVAILABLE,
+                "Share links need a relay: tunnel.url is not configured",
+            )
+            .into_response_with(StatusCode::CONFLICT)
+        })?;
+    if state.session_manager.get_status(&id).await.is_none() {
+        return Err(
+            ApiError::new(codes::SESSION_NOT_FOUND, format!("Session {id} not found"))
+                .into_response_with(StatusCode::NOT_FOUND),
+        );
+    }
+
+    let ttl_secs = payload
+        .ttl_secs
+        .unwrap_or(share::DEFAULT_TTL_SECS)
+        .clamp(60, share::MAX_TTL_SECS);
+    let claims = ShareClaims {
+        id: uuid::Uuid::new_v4().to_string(),
+        serial: state.config.device.serial.clone(),
+        session_id: id.clone(),
+        mode: payload.mode,
+        exp: crate::sessions::journal::now_ms() / 1000 + ttl_secs,
+    };
+    let token = share::mint(&state.api_keys.current(), &claims);
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SessionShare,
+            source,
+            format!(
+                "share {} ({:?}, {ttl_secs}s)",
+                &id[..8.min(id.len())],
+                claims.mode
+            ),
+            Some(json!({
+                "session_id": id,
+                "share_id": claims.id,
+                "mode": claims.mode,
+                "expires_at": claims.exp,
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "session_id": id,
+        "share_id": claims.id,
+        "mode": claims.mode,
+        "expires_at": claims.exp,
+        "url": share::share_url(relay_url, &token),
+        "token": token,
+    })))
+}
+
+// ─── Patch (rename, AI permission, AI status, encoding) ──────────────────────
 
 #[derive(Deserialize)]
 pub struct SessionPatch {
@@ -154,9 +732,12 @@ pub struct SessionPatch {
     pub working: Option<bool>,
     pub activity: Option<String>,
     pub message: Option<String>,
+    /// Output encoding from the next read on (`utf8`, `latin1`, `raw`).
+    pub encoding: Option<OutputEncoding>,
 }
 
-/// `PATCH /api/sessions/{id}` — combined update: rename, AI permission, AI status.
+/// `PATCH /api/sessions/{id}` — combined update: rename, AI permission, AI
+/// status, output encoding.
 pub async fn patch_session(
     State(state): State<AppState>,
     Path(id): Path<String>,
@@ -201,6 +782,17 @@ pub async fn patch_session(
         }
     }
 
+    // Output encoding
+    if let Some(encoding) = patch.encoding {
+        state
+            .session_manager
+            .set_encoding(&id, encoding)
+            .await
+            .map_err(|e| {
+                ApiError::new(codes::SESSION_NOT_FOUND, e).into_response_with(StatusCode::NOT_FOUND)
+            })?;
+    }
+
     // AI status
     if let Some(working) = patch.working {
         state
@@ -234,3 +826,82 @@ pub async fn patch_session(
         "session_id": id,
     })))
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::server::{testing, ServerBuilder};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn post_starts_a_persistent_session() {
+        let (mut config, dir) = testing::config("sessions-start");
+        config.session_templates.insert(
+            "build".to_string(),
+            toml::from_str(&format!(
+                "working_dir = {:?}\nname_prefix = \"build\"\n",
+                dir.display().to_string()
+            ))
+            .unwrap(),
+        );
+        let server = ServerBuilder::new(config).build().await;
+        let app = server.router();
+
+        let response = app
+            .clone()
+            .oneshot(testing::request(
+                "POST",
+                "/api/sessions",
+                &json!({ "template": "build", "user_allows_ai": false }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let started = testing::body_json(response).await;
+        let id = started["session_id"].as_str().unwrap();
+        assert!(started["pid"].as_u64().unwrap() > 0);
+        assert_eq!(started["persistent"], true);
+        assert_eq!(started["user_allows_ai"], false);
+        assert!(started["name"].as_str().unwrap().starts_with("build-"));
+
+        let listed = app
+            .clone()
+            .oneshot(testing::request("GET", "/api/sessions", &Value::Null))
+            .await
+            .unwrap();
+        let listed = testing::body_json(listed).await;
+        let session = listed["sessions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|s| s["session_id"] == id)
+            .expect("listed");
+        assert_eq!(session["name"], started["name"]);
+        let logged = server.state.activity_log.read_since(0, 100).await;
+        assert!(logged
+            .iter()
+            .any(|e| e.activity_type == ActivityType::SessionStart
+                && e.detail.as_ref().unwrap()["session_id"] == id));
+
+        for (body, message) in [
+            (json!({ "template": "nope" }), "nope"),
+            (
+                json!({ "env": { "N": 1 } }),
+                "env must map names to strings",
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(testing::request("POST", "/api/sessions", &body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let err = testing::body_json(response).await;
+            assert_eq!(err["code"], "INVALID_REQUEST");
+            assert!(err["message"].as_str().unwrap().contains(message), "{err}");
+        }
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
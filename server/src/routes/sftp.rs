@@ -0,0 +1,62 @@
+//! `GET /api/sftp` — SFTP over a WebSocket. See [`crate::sftp`].
+
+use axum::{
+    extract::{Query, State, WebSocketUpgrade},
+    http::{Extensions, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::activity::{ActivitySource, ActivityType};
+use crate::ws::WsQuery;
+use crate::AppState;
+
+/// `GET /api/sftp?token=<key>` — upgrade, start `sftp-server`, and carry its
+/// stdin/stdout as binary messages. Needs the `files:write` scope, even with
+/// `[sftp] read_only`. AI clients (`X-Sctl-Client` or `?client=`) are refused
+/// while the kill-switch is on, since the session can write.
+///
+/// # Errors
+///
+/// - `403 Forbidden` — bad token or missing scope, or `AI_DISABLED`
+/// - `404 Not Found` — no `[sftp]` section
+/// - `503 Service Unavailable` — `sftp-server` missing or failed to start
+pub async fn sftp_upgrade(
+    State(state): State<AppState>,
+    Query(query): Query<WsQuery>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let identity = crate::auth::keyless_identity(&extensions).or_else(|| {
+        state
+            .api_keys
+            .resolve(&state.config.auth.keys, &query.token)
+    });
+    let Some(identity) = identity.filter(|i| i.allows("files:write")) else {
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    };
+    crate::connections::identify(&extensions, &identity.name);
+    let client_headers = crate::ai_guard::upgrade_headers(&headers, query.client.as_deref());
+    if let Err(e) = state.ai_guard.check_mutation(&client_headers) {
+        return e.into_response();
+    }
+    let Some(config) = &state.config.sftp else {
+        return (StatusCode::NOT_FOUND, "SFTP is not enabled ([sftp])").into_response();
+    };
+    let (child, stdin, stdout) = match crate::sftp::spawn(config) {
+        Ok(server) => server,
+        Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+    };
+    state
+        .activity_log
+        .log(
+            ActivityType::Sftp,
+            ActivitySource::Ws,
+            "SFTP session opened".to_string(),
+            Some(json!({"read_only": config.read_only})),
+            None,
+        )
+        .await;
+    ws.on_upgrade(move |socket| crate::sftp::serve_ws(socket, child, stdin, stdout))
+}
@@ -0,0 +1,181 @@
+//! System snapshot endpoints (see [`crate::snapshot`]).
+//!
+//! - `POST /api/snapshots` — record the current system state
+//! - `GET /api/snapshots` — stored snapshots, newest first
+//! - `GET/DELETE /api/snapshots/{id}` — one snapshot, with all entries
+//! - `GET /api/snapshots/{a}/diff/{b}` — what changed from `a` to `b`
+//! - `GET /api/info/diff?since={id}` — what changed since `id`, up to now
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::snapshot::{self, Snapshot};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Request body for `POST /api/snapshots`.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateRequest {
+    /// Free text, e.g. `"before upgrade"`.
+    pub label: Option<String>,
+    /// Sections to record; all when empty.
+    #[serde(default)]
+    pub sections: Vec<String>,
+}
+
+/// `POST /api/snapshots` — record and store a snapshot. Returns its summary:
+/// `{id, created_at, label, counts, unavailable}`.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — unknown section
+/// - `500 Internal Server Error` with `{"code":"IO_ERROR"}`
+pub async fn create_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<CreateRequest>>,
+) -> ApiResult<Value> {
+    let Json(req) = body.unwrap_or_default();
+    create(&state, &headers, req).await
+}
+
+/// Record a snapshot for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`create_snapshot`].
+pub async fn create(state: &AppState, headers: &HeaderMap, req: CreateRequest) -> ApiResult<Value> {
+    snapshot::validate_sections(&req.sections).map_err(invalid)?;
+    let taken = snapshot::take(req.label, &req.sections).await;
+    snapshot::save(&state.config.server.data_dir, &taken)
+        .await
+        .map_err(|e| {
+            ApiError::new(codes::IO_ERROR, format!("Failed to store snapshot: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    let summary = taken.summary();
+    state
+        .activity_log
+        .log(
+            ActivityType::Snapshot,
+            source_from_headers(headers),
+            match &taken.label {
+                Some(label) => format!("Snapshot {} ({label})", taken.id),
+                None => format!("Snapshot {}", taken.id),
+            },
+            Some(summary.clone()),
+            request_id_from_headers(headers),
+        )
+        .await;
+    Ok(Json(summary))
+}
+
+/// `GET /api/snapshots` — `{snapshots: [summary]}`, newest first.
+pub async fn list_snapshots(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({
+        "snapshots": snapshot::list(&state.config.server.data_dir).await,
+    }))
+}
+
+/// `GET /api/snapshots/{id}` — the full snapshot.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+pub async fn get_snapshot(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Snapshot> {
+    load(&state, &id).await.map(Json)
+}
+
+/// `DELETE /api/snapshots/{id}`.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+pub async fn delete_snapshot(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ApiResult<Value> {
+    match snapshot::remove(&state.config.server.data_dir, &id).await {
+        Ok(true) => Ok(Json(json!({ "ok": true }))),
+        Ok(false) => Err(not_found(&id)),
+        Err(e) => Err(
+            ApiError::new(codes::IO_ERROR, format!("Failed to delete snapshot: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR),
+        ),
+    }
+}
+
+/// `GET /api/snapshots/{a}/diff/{b}` — `{from, to, identical, sections,
+/// skipped}`; each changed section has `added`, `removed` and `changed`.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — either snapshot
+pub async fn diff_snapshots(
+    State(state): State<AppState>,
+    Path((a, b)): Path<(String, String)>,
+) -> ApiResult<Value> {
+    let from = load(&state, &a).await?;
+    let to = load(&state, &b).await?;
+    Ok(Json(snapshot::diff(&from, &to)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InfoDiffQuery {
+    pub since: String,
+}
+
+/// `GET /api/info/diff?since={id}` — diff from a stored snapshot to the
+/// current state of the same sections. Nothing is stored; `to.id` is `null`.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}`
+pub async fn info_diff(
+    State(state): State<AppState>,
+    Query(query): Query<InfoDiffQuery>,
+) -> ApiResult<Value> {
+    let from = load(&state, &query.since).await?;
+    let sections: Vec<String> = from
+        .sections
+        .keys()
+        .chain(from.unavailable.keys())
+        .cloned()
+        .collect();
+    let now = snapshot::take(None, &sections).await;
+    let mut diff = snapshot::diff(&from, &now);
+    diff["to"]["id"] = Value::Null;
+    Ok(Json(diff))
+}
+
+async fn load(state: &AppState, id: &str) -> Result<Snapshot, (StatusCode, Json<ApiError>)> {
+    match snapshot::load(&state.config.server.data_dir, id).await {
+        Ok(Some(s)) => Ok(s),
+        Ok(None) => Err(not_found(id)),
+        Err(e) => {
+            Err(ApiError::new(codes::IO_ERROR, e)
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+fn not_found(id: &str) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::NOT_FOUND, format!("No snapshot '{id}'"))
+        .into_response_with(StatusCode::NOT_FOUND)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn invalid(e: String) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_REQUEST, e).into_response_with(StatusCode::BAD_REQUEST)
+}
@@ -0,0 +1,11 @@
+//! `GET /api/snmp` — the SNMP agent's objects as JSON (see [`crate::snmp`]).
+
+use axum::{extract::State, Json};
+use serde_json::Value;
+
+use crate::AppState;
+
+/// `GET /api/snmp` — every object with its OID, name, SMI type and value.
+pub async fn snmp(State(state): State<AppState>) -> Json<Value> {
+    Json(crate::snmp::snapshot(&state).await)
+}
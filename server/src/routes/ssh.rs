@@ -0,0 +1,671 @@
+//! SSH `authorized_keys` management endpoints.
+//!
+//! - `GET /api/ssh/authorized_keys?user=<name>` — list keys with fingerprints
+//! - `POST /api/ssh/authorized_keys` — add keys, or replace the whole set
+//! - `DELETE /api/ssh/authorized_keys?user=<name>&fingerprint=SHA256:...` — remove a key
+//!
+//! `user` defaults to the account sctl runs as. Every key is parsed and its
+//! base64 blob checked against the declared type before anything is written.
+//! Writes are atomic (temp file + rename, `0600`, owned by the target user),
+//! serialized process-wide, and preceded by a timestamped backup of the
+//! previous file (`authorized_keys.sctl-bak-<unix-ms>`, last [`MAX_BACKUPS`] kept)
+//! so a bad rotation can be undone by hand.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Backups kept per `authorized_keys` file.
+const MAX_BACKUPS: usize = 5;
+
+/// Maximum keys accepted in one request.
+const MAX_KEYS_PER_REQUEST: usize = 100;
+
+/// Key algorithms accepted by OpenSSH.
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ssh-dss",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// Serializes read-modify-write cycles so concurrent requests can't lose keys.
+static WRITE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// ─── Types ───────────────────────────────────────────────────────────────────
+
+/// Query for `GET /api/ssh/authorized_keys`.
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// Target account (default: the user sctl runs as).
+    pub user: Option<String>,
+}
+
+/// Query for `DELETE /api/ssh/authorized_keys`.
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    /// Target account (default: the user sctl runs as).
+    pub user: Option<String>,
+    /// `SHA256:...` fingerprint of the key to remove.
+    pub fingerprint: String,
+}
+
+/// Body for `POST /api/ssh/authorized_keys`.
+#[derive(Deserialize)]
+pub struct AddKeysRequest {
+    /// Target account (default: the user sctl runs as).
+    pub user: Option<String>,
+    /// Keys in `authorized_keys` line format (options prefix allowed).
+    pub keys: Vec<String>,
+    /// Replace the file contents with exactly `keys` instead of appending.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// A parsed `authorized_keys` entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyEntry {
+    /// 1-based line number in the file (0 for keys not yet written).
+    pub line: usize,
+    #[serde(rename = "type")]
+    pub key_type: String,
+    /// OpenSSH-style `SHA256:<base64>` fingerprint.
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<String>,
+    /// Normalized line as written to the file.
+    #[serde(skip)]
+    raw: String,
+}
+
+/// Resolved target account.
+struct Account {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: PathBuf,
+}
+
+impl Account {
+    fn keys_path(&self) -> PathBuf {
+        self.home.join(".ssh").join("authorized_keys")
+    }
+}
+
+// ─── Parsing ─────────────────────────────────────────────────────────────────
+
+/// Parse one `authorized_keys` line: `[options] type base64 [comment]`.
+pub fn parse_key_line(line: &str) -> Result<KeyEntry, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Err("empty line".into());
+    }
+    if line.contains(['\n', '\r', '\0']) {
+        return Err("key must be a single line".into());
+    }
+
+    // Options precede the key type and may contain quoted spaces.
+    let (options, rest) = if KEY_TYPES.iter().any(|t| line.starts_with(&format!("{t} "))) {
+        (None, line)
+    } else {
+        let mut in_quotes = false;
+        let mut split = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ' ' | '\t' if !in_quotes => {
+                    split = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let split = split.ok_or("missing key type")?;
+        (Some(line[..split].to_string()), line[split..].trim_start())
+    };
+
+    let mut parts = rest.splitn(3, [' ', '\t']);
+    let key_type = parts.next().unwrap_or_default();
+    if !KEY_TYPES.contains(&key_type) {
+        return Err(format!("unsupported key type '{key_type}'"));
+    }
+    let b64 = parts.next().ok_or("missing key data")?.trim();
+    let comment = parts
+        .next()
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(ToString::to_string);
+
+    let blob = base64::engine::general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|_| "key data is not valid base64".to_string())?;
+    // The blob starts with the length-prefixed key type; a mismatch means a
+    // truncated or pasted-together key.
+    let embedded = blob
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .and_then(|len| blob.get(4..len.checked_add(4)?));
+    if embedded != Some(key_type.as_bytes()) {
+        return Err(format!("key data does not match type '{key_type}'"));
+    }
+
+    let fingerprint = format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(&blob))
+    );
+    let mut raw = String::new();
+    if let Some(ref opts) = options {
+        raw.push_str(opts);
+        raw.push(' ');
+    }
+    raw.push_str(key_type);
+    raw.push(' ');
+    raw.push_str(b64);
+    if let Some(ref c) = comment {
+        raw.push(' ');
+        raw.push_str(c);
+    }
+
+    Ok(KeyEntry {
+        line: 0,
+        key_type: key_type.to_string(),
+        fingerprint,
+        comment,
+        options,
+        raw,
+    })
+}
+
+/// Parsed file contents: valid entries plus lines that failed to parse.
+struct KeysFile {
+    entries: Vec<KeyEntry>,
+    invalid: Vec<Value>,
+    /// Lines that aren't keys (comments, blanks) are preserved on rewrite.
+    passthrough: Vec<(usize, String)>,
+}
+
+fn parse_keys_file(content: &str) -> KeysFile {
+    let mut file = KeysFile {
+        entries: Vec::new(),
+        invalid: Vec::new(),
+        passthrough: Vec::new(),
+    };
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            file.passthrough.push((i, line.to_string()));
+            continue;
+        }
+        match parse_key_line(trimmed) {
+            Ok(mut entry) => {
+                entry.line = i + 1;
+                file.entries.push(entry);
+            }
+            Err(e) => {
+                file.invalid.push(json!({"line": i + 1, "error": e}));
+                // Unparseable lines are kept verbatim — sctl didn't write
+                // them and sshd may understand something we don't.
+                file.passthrough.push((i, line.to_string()));
+            }
+        }
+    }
+    file
+}
+
+// ─── Filesystem ──────────────────────────────────────────────────────────────
+
+/// Look up `user` (or the effective uid) in `/etc/passwd`.
+fn resolve_account(user: Option<&str>) -> Result<Account, (StatusCode, Json<ApiError>)> {
+    let not_found = |msg: String| {
+        ApiError::new(codes::NOT_FOUND, msg).into_response_with(StatusCode::NOT_FOUND)
+    };
+    if let Some(u) = user {
+        if !super::users::valid_user_name(u) {
+            return Err(ApiError::new(codes::INVALID_REQUEST, "Invalid user name")
+                .into_response_with(StatusCode::BAD_REQUEST));
+        }
+    }
+    let euid = crate::util::effective_uid();
+    let entries = super::users::read_passwd().map_err(|e| {
+        ApiError::new(codes::IO_ERROR, format!("Failed to read /etc/passwd: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    let found = entries.into_iter().find(|e| match user {
+        Some(u) => e.name == u,
+        None => e.uid == euid,
+    });
+    if let Some(e) = found {
+        return Ok(Account {
+            name: e.name,
+            uid: e.uid,
+            gid: e.gid,
+            home: PathBuf::from(e.home),
+        });
+    }
+    Err(not_found(match user {
+        Some(u) => format!("User '{u}' not found"),
+        None => format!("No passwd entry for uid {euid}"),
+    }))
+}
+
+fn read_keys(path: &Path) -> std::io::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn list_backups(ssh_dir: &Path) -> Vec<String> {
+    let mut backups: Vec<String> = std::fs::read_dir(ssh_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|n| n.starts_with("authorized_keys.sctl-bak-"))
+        .collect();
+    backups.sort();
+    backups
+}
+
+/// Back up the current file (if any), then atomically write `content`.
+///
+/// Returns the backup path.
+fn write_keys(
+    account: &Account,
+    current: Option<&str>,
+    content: &str,
+) -> std::io::Result<Option<String>> {
+    use std::io::Write;
+
+    let path = account.keys_path();
+    let ssh_dir = path.parent().unwrap_or(Path::new("."));
+    if !ssh_dir.exists() {
+        std::fs::create_dir_all(ssh_dir)?;
+        crate::util::set_mode(ssh_dir, 0o700)?;
+        crate::util::chown(ssh_dir, account.uid, account.gid)?;
+    }
+
+    let backup = if current.is_some() {
+        let stamp = crate::sessions::journal::now_ms();
+        let backup = ssh_dir.join(format!("authorized_keys.sctl-bak-{stamp}"));
+        std::fs::copy(&path, &backup)?;
+        crate::util::set_mode(&backup, 0o600)?;
+        let backups = list_backups(ssh_dir);
+        for old in backups
+            .iter()
+            .take(backups.len().saturating_sub(MAX_BACKUPS))
+        {
+            let _ = std::fs::remove_file(ssh_dir.join(old));
+        }
+        Some(backup.to_string_lossy().into_owned())
+    } else {
+        None
+    };
+
+    // Keep the existing owner if the file was there (e.g. root-managed keys).
+    let (uid, gid) = std::fs::metadata(&path)
+        .ok()
+        .and_then(|m| crate::util::file_owner(&m))
+        .unwrap_or((account.uid, account.gid));
+    let tmp = ssh_dir.join(".authorized_keys.sctl-tmp");
+    {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        crate::util::set_mode(&tmp, 0o600)?;
+        f.write_all(content.as_bytes())?;
+        f.sync_all()?;
+    }
+    crate::util::chown(&tmp, uid, gid)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(backup)
+}
+
+fn io_error(context: &str, e: &std::io::Error) -> (StatusCode, Json<ApiError>) {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        ApiError::new(codes::PERMISSION_DENIED, format!("{context}: {e}"))
+            .into_response_with(StatusCode::FORBIDDEN)
+    } else {
+        ApiError::new(codes::IO_ERROR, format!("{context}: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// Render entries plus preserved lines back into file order.
+fn render(file: &KeysFile, entries: &[KeyEntry]) -> String {
+    let mut lines: Vec<(usize, &str)> = file
+        .passthrough
+        .iter()
+        .map(|(i, l)| (*i, l.as_str()))
+        .collect();
+    // Kept entries retain their position; new ones go at the end.
+    lines.extend(entries.iter().map(|e| {
+        (
+            if e.line == 0 { usize::MAX } else { e.line - 1 },
+            e.raw.as_str(),
+        )
+    }));
+    lines.sort_by_key(|(i, _)| *i);
+    let mut out = lines
+        .into_iter()
+        .map(|(_, l)| l)
+        .collect::<Vec<_>>()
+        .join("\n");
+    out.push('\n');
+    out
+}
+
+fn join_error(e: &tokio::task::JoinError) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::IO_ERROR, format!("Key update task failed: {e}"))
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// `GET /api/ssh/authorized_keys` — list keys for a user.
+pub async fn list_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ListQuery>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    let result = tokio::task::spawn_blocking(move || {
+        let account = resolve_account(query.user.as_deref())?;
+        let path = account.keys_path();
+        let content =
+            read_keys(&path).map_err(|e| io_error("Failed to read authorized_keys", &e))?;
+        let file = parse_keys_file(content.as_deref().unwrap_or(""));
+        let backups = path.parent().map(list_backups).unwrap_or_default();
+        Ok::<_, (StatusCode, Json<ApiError>)>((
+            account.name,
+            path,
+            content.is_some(),
+            file,
+            backups,
+        ))
+    })
+    .await
+    .map_err(|e| join_error(&e))?;
+    let (user, path, exists, file, backups) = result?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SshKeyList,
+            source,
+            format!("Listed {} SSH keys for {user}", file.entries.len()),
+            None,
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "user": user,
+        "path": path,
+        "exists": exists,
+        "keys": file.entries,
+        "invalid": file.invalid,
+        "backups": backups,
+    })))
+}
+
+/// `POST /api/ssh/authorized_keys` — add keys (or replace the set).
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — no keys, too many,
+///   or a key fails validation (`detail.index` names the offending entry)
+/// - `404 Not Found` — unknown user
+/// - `403 Forbidden` with `{"code":"PERMISSION_DENIED"}` — sctl can't write
+///   the target user's file
+pub async fn add_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<AddKeysRequest>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    if payload.keys.is_empty() {
+        return Err(
+            ApiError::new(codes::INVALID_REQUEST, "keys must not be empty")
+                .into_response_with(StatusCode::BAD_REQUEST),
+        );
+    }
+    if payload.keys.len() > MAX_KEYS_PER_REQUEST {
+        return Err(ApiError::new(
+            codes::INVALID_REQUEST,
+            format!("Too many keys (max {MAX_KEYS_PER_REQUEST})"),
+        )
+        .into_response_with(StatusCode::BAD_REQUEST));
+    }
+    let mut new_entries = Vec::with_capacity(payload.keys.len());
+    for (index, key) in payload.keys.iter().enumerate() {
+        let entry = parse_key_line(key).map_err(|e| {
+            ApiError::new(codes::INVALID_REQUEST, format!("Invalid key: {e}"))
+                .with_detail(json!({ "index": index }))
+                .into_response_with(StatusCode::BAD_REQUEST)
+        })?;
+        new_entries.push(entry);
+    }
+
+    let replace = payload.replace;
+    let result = tokio::task::spawn_blocking(move || {
+        let _guard = WRITE_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let account = resolve_account(payload.user.as_deref())?;
+        let path = account.keys_path();
+        let current =
+            read_keys(&path).map_err(|e| io_error("Failed to read authorized_keys", &e))?;
+        let mut file = parse_keys_file(current.as_deref().unwrap_or(""));
+
+        let previous: Vec<String> = file.entries.iter().map(|e| e.fingerprint.clone()).collect();
+        if replace {
+            file.entries.clear();
+            // Drop preserved invalid lines too: replace means "exactly these keys".
+            file.passthrough.retain(|(_, l)| {
+                let t = l.trim();
+                t.is_empty() || t.starts_with('#')
+            });
+        }
+        let mut seen: HashSet<String> =
+            file.entries.iter().map(|e| e.fingerprint.clone()).collect();
+        let mut added = Vec::new();
+        let mut skipped = Vec::new();
+        let mut entries = std::mem::take(&mut file.entries);
+        for entry in new_entries {
+            if seen.insert(entry.fingerprint.clone()) {
+                added.push(entry.fingerprint.clone());
+                entries.push(entry);
+            } else {
+                skipped.push(entry.fingerprint);
+            }
+        }
+        let removed: Vec<&String> = previous.iter().filter(|f| !seen.contains(*f)).collect();
+
+        let backup = if added.is_empty() && !replace {
+            None
+        } else {
+            write_keys(&account, current.as_deref(), &render(&file, &entries))
+                .map_err(|e| io_error("Failed to write authorized_keys", &e))?
+        };
+        Ok::<_, (StatusCode, Json<ApiError>)>(json!({
+            "ok": true,
+            "user": account.name,
+            "path": path,
+            "added": added,
+            "skipped": skipped,
+            "removed": removed,
+            "replaced": replace,
+            "total": entries.len(),
+            "backup": backup,
+        }))
+    })
+    .await
+    .map_err(|e| join_error(&e))?;
+    let body = result?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SshKeyWrite,
+            source,
+            format!(
+                "{} {} SSH key(s) for {}",
+                if replace { "Set" } else { "Added" },
+                body["added"].as_array().map_or(0, Vec::len),
+                body["user"].as_str().unwrap_or("")
+            ),
+            Some(json!({
+                "fingerprints": body["added"],
+                "removed": body["removed"],
+                "replace": replace,
+                "backup": body["backup"],
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(body))
+}
+
+/// `DELETE /api/ssh/authorized_keys` — remove a key by fingerprint.
+///
+/// # Errors
+///
+/// - `404 Not Found` — unknown user or no key with that fingerprint
+pub async fn delete_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<DeleteQuery>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let fingerprint = query.fingerprint.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let _guard = WRITE_LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let account = resolve_account(query.user.as_deref())?;
+        let path = account.keys_path();
+        let current =
+            read_keys(&path).map_err(|e| io_error("Failed to read authorized_keys", &e))?;
+        let mut file = parse_keys_file(current.as_deref().unwrap_or(""));
+        let before = file.entries.len();
+        let mut entries = std::mem::take(&mut file.entries);
+        entries.retain(|e| e.fingerprint != query.fingerprint);
+        if entries.len() == before {
+            return Err(ApiError::new(
+                codes::NOT_FOUND,
+                format!("No key with fingerprint {}", query.fingerprint),
+            )
+            .into_response_with(StatusCode::NOT_FOUND));
+        }
+        let backup = write_keys(&account, current.as_deref(), &render(&file, &entries))
+            .map_err(|e| io_error("Failed to write authorized_keys", &e))?;
+        Ok(json!({
+            "ok": true,
+            "user": account.name,
+            "path": path,
+            "removed": before - entries.len(),
+            "remaining": entries.len(),
+            "backup": backup,
+        }))
+    })
+    .await
+    .map_err(|e| join_error(&e))?;
+    let body = result?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::SshKeyDelete,
+            source,
+            format!(
+                "Removed SSH key {fingerprint} for {}",
+                body["user"].as_str().unwrap_or("")
+            ),
+            Some(json!({ "fingerprint": fingerprint, "backup": body["backup"] })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4f";
+    const ED25519_FP: &str = "SHA256:ZkAslGjFiUHdGf/WUL8rQvkib4PTvQatUV0OUQSncCA";
+
+    #[test]
+    fn parses_plain_key_with_comment() {
+        let entry = parse_key_line(&format!("{ED25519} ops@fleet")).unwrap();
+        assert_eq!(entry.key_type, "ssh-ed25519");
+        assert_eq!(entry.fingerprint, ED25519_FP);
+        assert_eq!(entry.comment.as_deref(), Some("ops@fleet"));
+        assert!(entry.options.is_none());
+    }
+
+    #[test]
+    fn parses_options_with_quoted_spaces() {
+        let line = format!(r#"from="10.0.0.0/8",command="echo hi there" {ED25519}"#);
+        let entry = parse_key_line(&line).unwrap();
+        assert_eq!(
+            entry.options.as_deref(),
+            Some(r#"from="10.0.0.0/8",command="echo hi there""#)
+        );
+        assert_eq!(entry.fingerprint, ED25519_FP);
+        assert_eq!(entry.raw, line);
+    }
+
+    #[test]
+    fn rejects_type_mismatch_and_garbage() {
+        let mismatched = ED25519.replace("ssh-ed25519", "ssh-rsa");
+        assert!(parse_key_line(&mismatched).is_err());
+        assert!(parse_key_line("ssh-ed25519 not-base64!").is_err());
+        assert!(parse_key_line("ssh-foo AAAA").is_err());
+        assert!(parse_key_line(&format!("{ED25519}\nssh-rsa AAAA")).is_err());
+    }
+
+    #[test]
+    fn render_preserves_comments_and_order() {
+        let content = format!("# managed\n{ED25519} a\nbogus line\n");
+        let file = parse_keys_file(&content);
+        assert_eq!(file.entries.len(), 1);
+        assert_eq!(file.invalid.len(), 1);
+        let entries = file.entries.clone();
+        assert_eq!(render(&file, &entries), content);
+    }
+}
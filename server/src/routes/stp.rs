@@ -5,29 +5,42 @@
 
 use axum::{
     body::Body,
-    extract::{Path as AxumPath, State},
+    extract::{Path as AxumPath, Query, State},
     http::{HeaderMap, StatusCode},
     response::Response,
     Json,
 };
 use serde_json::{json, Value};
 
+use crate::deadline::RequestDeadline;
 use crate::error::{codes, ApiError};
-use crate::gawdxfer::types::{InitDownload, InitUpload, TransferError};
+use crate::gawdxfer::types::{InitDownload, InitUpload, Throttle, TransferError};
 use crate::AppState;
 
 type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
 
-/// `POST /api/stp/download` — init a chunked download.
+/// `POST /api/stp/download` — init a chunked download, of a directory as a
+/// tar archive with `directory: true`.
 pub async fn init_download(
     State(state): State<AppState>,
     Json(req): Json<InitDownload>,
 ) -> ApiResult<Value> {
-    let result = state
-        .transfer_manager
-        .init_download(&req.path, req.chunk_size)
-        .await
-        .map_err(transfer_error_to_http)?;
+    let manager = &state.transfer_manager;
+    let result = if req.directory {
+        manager
+            .init_directory_download(
+                &req.path,
+                req.files.as_deref(),
+                req.chunk_size,
+                req.rate_limit_bps,
+            )
+            .await
+    } else {
+        manager
+            .init_download(&req.path, req.chunk_size, req.rate_limit_bps)
+            .await
+    }
+    .map_err(transfer_error_to_http)?;
     Ok(Json(serde_json::to_value(&result).unwrap()))
 }
 
@@ -48,10 +61,11 @@ pub async fn init_upload(
 pub async fn get_chunk(
     State(state): State<AppState>,
     AxumPath((xfer, idx)): AxumPath<(String, u32)>,
+    deadline: RequestDeadline,
 ) -> Result<Response, (StatusCode, Json<ApiError>)> {
     let (header, data) = state
         .transfer_manager
-        .serve_chunk(&xfer, idx)
+        .serve_chunk(&xfer, idx, deadline.token())
         .await
         .map_err(transfer_error_to_http)?;
 
@@ -66,10 +80,12 @@ pub async fn get_chunk(
 }
 
 /// `POST /api/stp/chunk/{xfer}/{idx}` — receive a chunk (binary body, X-Gx-Chunk-Hash header).
+/// AI clients are charged its length against their write budget.
 pub async fn post_chunk(
     State(state): State<AppState>,
     AxumPath((xfer, idx)): AxumPath<(String, u32)>,
     headers: HeaderMap,
+    deadline: RequestDeadline,
     body: axum::body::Bytes,
 ) -> ApiResult<Value> {
     let chunk_hash = headers
@@ -84,10 +100,11 @@ pub async fn post_chunk(
                 .into_response_with(StatusCode::BAD_REQUEST),
         );
     }
+    state.ai_guard.charge_write(&headers, body.len() as u64)?;
 
     let ack = state
         .transfer_manager
-        .receive_chunk(&xfer, idx, &chunk_hash, &body)
+        .receive_chunk(&xfer, idx, &chunk_hash, &body, deadline.token())
         .await
         .map_err(transfer_error_to_http)?;
     Ok(Json(serde_json::to_value(&ack).unwrap()))
@@ -106,6 +123,20 @@ pub async fn resume_transfer(
     Ok(Json(serde_json::to_value(&result).unwrap()))
 }
 
+/// `POST /api/stp/throttle` — change a transfer's bandwidth cap, or the
+/// global cap without `transfer_id`.
+pub async fn throttle(
+    State(state): State<AppState>,
+    Json(req): Json<Throttle>,
+) -> ApiResult<Value> {
+    let result = state
+        .transfer_manager
+        .throttle(req.transfer_id.as_deref(), req.rate_limit_bps)
+        .await
+        .map_err(transfer_error_to_http)?;
+    Ok(Json(serde_json::to_value(&result).unwrap()))
+}
+
 /// `GET /api/stp/status/{xfer}` — get transfer status.
 pub async fn transfer_status(
     State(state): State<AppState>,
@@ -119,10 +150,15 @@ pub async fn transfer_status(
     Ok(Json(serde_json::to_value(&result).unwrap()))
 }
 
-/// `GET /api/stp/transfers` — list all transfers.
-pub async fn list_transfers(State(state): State<AppState>) -> ApiResult<Value> {
+/// `GET /api/stp/transfers?fields=` — list all transfers.
+pub async fn list_transfers(
+    State(state): State<AppState>,
+    Query(query): Query<super::FieldsQuery>,
+) -> ApiResult<Value> {
     let result = state.transfer_manager.list().await;
-    Ok(Json(serde_json::to_value(&result).unwrap()))
+    let mut body = serde_json::to_value(&result).unwrap();
+    crate::util::project_fields(&mut body["transfers"], query.fields.as_deref());
+    Ok(Json(body))
 }
 
 /// `DELETE /api/stp/{xfer}` — abort a transfer.
@@ -152,6 +188,8 @@ fn transfer_error_to_http(e: TransferError) -> (StatusCode, Json<ApiError>) {
         | "CHUNK_INTEGRITY" | "FILE_CHANGED" => StatusCode::BAD_REQUEST,
         "DISK_FULL" => StatusCode::INSUFFICIENT_STORAGE,
         "MAX_TRANSFERS" => StatusCode::TOO_MANY_REQUESTS,
+        "QUIET_HOURS" => StatusCode::SERVICE_UNAVAILABLE,
+        "TIMEOUT" => StatusCode::GATEWAY_TIMEOUT,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
     };
     ApiError::new(e.code, e.message)
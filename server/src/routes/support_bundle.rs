@@ -0,0 +1,515 @@
+//! Support bundle generation.
+//!
+//! `POST /api/support-bundle` collects what support would otherwise ask for
+//! one command at a time into a single archive,
+//! `<data_dir>/support/sctl-support-<serial>-<secs>.tar.gz`. The build runs in
+//! the background; the request returns the bundle id and target path at once.
+//! Progress is broadcast on the session event channel (WS, SSE, relay):
+//!
+//! - `support_bundle.progress` — `{bundle_id, step, index, total}` per step
+//! - `support_bundle.ready` — `{bundle_id, path, size}`; fetch the file with
+//!   gawdxfer (`POST /api/stp/download`)
+//! - `support_bundle.failed` — `{bundle_id, error}`
+//!
+//! `GET /api/support-bundle` reports the most recent build for clients that
+//! missed the events. One build runs at a time; the newest [`KEEP_BUNDLES`]
+//! archives are kept.
+//!
+//! ## Contents
+//!
+//! | File            | Source                                              |
+//! |-----------------|-----------------------------------------------------|
+//! | `manifest.json` | Serial, version, creation time, file list           |
+//! | `config.json`   | Running config, credentials redacted                |
+//! | `system.json`   | `/api/info` plus process/system/network diagnostics |
+//! | `logs.json`     | Service logs (same query params as diagnostics)     |
+//! | `activity.json` | Activity journal                                    |
+//! | `tunnel.json`   | Tunnel counters, RTT history, connection events     |
+//! | `journals.json` | Session journal summaries (metadata, size — no output) |
+//! | `last_panic.log`| Previous crash report, if any                       |
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::AsyncBufReadExt;
+use tracing::{info, warn};
+
+use super::diagnostics::DiagnosticsQuery;
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::config::Config;
+use crate::error::{codes, ApiError};
+use crate::infra::checks::exec_args_pub;
+use crate::sessions::journal::now_ms;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Archives kept in `<data_dir>/support`; older ones are deleted after a build.
+pub const KEEP_BUNDLES: usize = 3;
+
+/// Timeout for the `tar` invocation.
+const TAR_TIMEOUT_MS: u64 = 60_000;
+
+/// Replacement for credential values in `config.json`.
+const REDACTED: &str = "[REDACTED]";
+
+/// Collection steps, in order, as reported by `support_bundle.progress`. Each
+/// but the last writes `<step>.json`.
+const STEPS: [&str; 7] = [
+    "config", "system", "logs", "activity", "tunnel", "journals", "archive",
+];
+
+/// Set while a build is running.
+static BUILDING: AtomicBool = AtomicBool::new(false);
+
+/// Most recent build, reported by `GET /api/support-bundle`.
+static LAST_BUNDLE: std::sync::Mutex<Option<BundleStatus>> = std::sync::Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BundleState {
+    Running,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BundleStatus {
+    bundle_id: String,
+    state: BundleState,
+    path: String,
+    started_at: u64,
+    /// Step in progress while running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    step: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn set_last_bundle(f: impl FnOnce(&mut Option<BundleStatus>)) {
+    f(&mut LAST_BUNDLE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner));
+}
+
+/// Clears [`BUILDING`] when the build task ends, even by panic.
+struct BuildGuard;
+
+impl Drop for BuildGuard {
+    fn drop(&mut self) {
+        BUILDING.store(false, Ordering::Release);
+    }
+}
+
+/// `POST /api/support-bundle` — start building a support bundle.
+///
+/// # Errors
+///
+/// - `409 Conflict` with `{"code":"BUNDLE_RUNNING"}` — a build is already in
+///   progress
+/// - `500` with `{"code":"IO_ERROR"}` — the support directory can't be created
+pub async fn create_support_bundle(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<DiagnosticsQuery>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    if BUILDING
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        let running = LAST_BUNDLE
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .as_ref()
+            .map(|s| s.bundle_id.clone());
+        return Err(ApiError::new(
+            codes::BUNDLE_RUNNING,
+            "A support bundle is already being built",
+        )
+        .with_detail(json!({ "bundle_id": running }))
+        .into_response_with(StatusCode::CONFLICT));
+    }
+    let guard = BuildGuard;
+
+    let dir = support_dir(&state.config);
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        return Err(ApiError::new(
+            codes::IO_ERROR,
+            format!("Failed to create {}: {e}", dir.display()),
+        )
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    let bundle_id = uuid::Uuid::new_v4().to_string();
+    let started_at = now_ms();
+    let path = dir.join(format!(
+        "sctl-support-{}-{}.tar.gz",
+        file_safe(&state.config.device.serial),
+        started_at / 1000
+    ));
+    let path_str = path.to_string_lossy().into_owned();
+
+    set_last_bundle(|s| {
+        *s = Some(BundleStatus {
+            bundle_id: bundle_id.clone(),
+            state: BundleState::Running,
+            path: path_str.clone(),
+            started_at,
+            step: None,
+            finished_at: None,
+            size: None,
+            error: None,
+        });
+    });
+    state
+        .activity_log
+        .log(
+            ActivityType::SupportBundle,
+            source,
+            format!("support bundle {}", &bundle_id[..8]),
+            Some(json!({ "bundle_id": bundle_id, "path": path_str })),
+            req_id,
+        )
+        .await;
+    info!(bundle_id = %bundle_id, path = %path_str, "Support bundle: building");
+
+    let task_state = state.clone();
+    let task_id = bundle_id.clone();
+    tokio::spawn(async move {
+        let _guard = guard;
+        let staging = support_dir(&task_state.config).join(&task_id);
+        let result = build(&task_state, &task_id, &query, &staging, &path).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let finished_at = now_ms();
+        match result {
+            Ok(size) => {
+                info!(bundle_id = %task_id, size, "Support bundle: ready");
+                prune(&support_dir(&task_state.config)).await;
+                set_last_bundle(|s| {
+                    if let Some(s) = s {
+                        s.state = BundleState::Ready;
+                        s.step = None;
+                        s.finished_at = Some(finished_at);
+                        s.size = Some(size);
+                    }
+                });
+                let _ = task_state.session_events.send(json!({
+                    "type": "support_bundle.ready",
+                    "bundle_id": task_id,
+                    "path": path.to_string_lossy(),
+                    "size": size,
+                }));
+            }
+            Err(e) => {
+                warn!(bundle_id = %task_id, error = %e, "Support bundle: failed");
+                let _ = tokio::fs::remove_file(&path).await;
+                set_last_bundle(|s| {
+                    if let Some(s) = s {
+                        s.state = BundleState::Failed;
+                        s.step = None;
+                        s.finished_at = Some(finished_at);
+                        s.error = Some(e.clone());
+                    }
+                });
+                let _ = task_state.session_events.send(json!({
+                    "type": "support_bundle.failed",
+                    "bundle_id": task_id,
+                    "error": e,
+                }));
+            }
+        }
+    });
+
+    Ok(Json(json!({
+        "bundle_id": bundle_id,
+        "state": BundleState::Running,
+        "path": path_str,
+        "steps": STEPS,
+    })))
+}
+
+/// `GET /api/support-bundle` — status of the most recent build.
+///
+/// # Errors
+///
+/// - `404 Not Found` — no bundle has been built since startup
+pub async fn support_bundle_status() -> ApiResult<Value> {
+    let last = LAST_BUNDLE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone();
+    match last {
+        Some(status) => Ok(Json(json!(status))),
+        None => Err(
+            ApiError::new(codes::NOT_FOUND, "No support bundle has been built")
+                .into_response_with(StatusCode::NOT_FOUND),
+        ),
+    }
+}
+
+/// Collect every step into `staging` and archive it to `out`. Returns the
+/// archive size.
+async fn build(
+    state: &AppState,
+    bundle_id: &str,
+    query: &DiagnosticsQuery,
+    staging: &Path,
+    out: &Path,
+) -> Result<u64, String> {
+    tokio::fs::create_dir_all(staging)
+        .await
+        .map_err(|e| format!("create {}: {e}", staging.display()))?;
+    let data_dir = Path::new(&state.config.server.data_dir);
+    let mut files = Vec::new();
+
+    for (index, step) in STEPS.iter().copied().enumerate() {
+        set_last_bundle(|s| {
+            if let Some(s) = s {
+                s.step = Some(step);
+            }
+        });
+        let _ = state.session_events.send(json!({
+            "type": "support_bundle.progress",
+            "bundle_id": bundle_id,
+            "step": step,
+            "index": index,
+            "total": STEPS.len(),
+        }));
+
+        if step == "archive" {
+            let manifest = json!({
+                "bundle_id": bundle_id,
+                "serial": state.config.device.serial,
+                "version": crate::VERSION,
+                "created_at": now_ms(),
+                "files": files,
+            });
+            write_json(staging, "manifest.json", &manifest).await?;
+            return archive(staging, out).await;
+        }
+
+        let content = match step {
+            "config" => redacted_config(&state.config),
+            "system" => {
+                let info =
+                    super::info::info_with_groups(state.clone(), super::info::InfoGroups::all())
+                        .await
+                        .map_or_else(|status| json!({ "error": status.as_u16() }), |j| j.0);
+                let diagnostics = super::diagnostics::system_snapshot(state);
+                json!({ "info": info, "diagnostics": diagnostics })
+            }
+            "logs" => {
+                let log_lines = query.log_lines.unwrap_or(1000).min(5000);
+                let log_since = query.log_since.as_deref().unwrap_or("24h");
+                let (logs, log_stats) =
+                    super::diagnostics::collect_logs(log_lines, log_since).await;
+                if let Ok(panic) = tokio::fs::read_to_string(data_dir.join("last_panic.log")).await
+                {
+                    write_file(staging, "last_panic.log", panic.as_bytes()).await?;
+                    files.push("last_panic.log".to_string());
+                }
+                json!({ "stats": log_stats, "logs": logs })
+            }
+            "activity" => json!(state.activity_log.read_since(0, usize::MAX).await),
+            "tunnel" => state.tunnel_stats.diag_snapshot().await,
+            _ => json!(journal_summaries(data_dir).await),
+        };
+        let name = format!("{step}.json");
+        write_json(staging, &name, &content).await?;
+        files.push(name);
+    }
+    Err("archive step did not run".to_string())
+}
+
+async fn write_file(dir: &Path, name: &str, data: &[u8]) -> Result<(), String> {
+    tokio::fs::write(dir.join(name), data)
+        .await
+        .map_err(|e| format!("write {name}: {e}"))
+}
+
+async fn write_json(dir: &Path, name: &str, value: &Value) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(value).map_err(|e| format!("serialize {name}: {e}"))?;
+    write_file(dir, name, &data).await
+}
+
+/// `tar -czf out -C staging .`, returning the archive size.
+async fn archive(staging: &Path, out: &Path) -> Result<u64, String> {
+    let out_str = out.to_string_lossy();
+    let staging_str = staging.to_string_lossy();
+    match exec_args_pub(
+        "tar",
+        &["-czf", &out_str, "-C", &staging_str, "."],
+        TAR_TIMEOUT_MS,
+    )
+    .await?
+    {
+        (0, _, _) => {}
+        (code, _, stderr) => return Err(format!("tar exited with {code}: {}", stderr.trim())),
+    }
+    tokio::fs::metadata(out)
+        .await
+        .map(|m| m.len())
+        .map_err(|e| format!("stat {out_str}: {e}"))
+}
+
+/// Per-journal metadata line, size, and modification time. Output entries
+/// are left out — they can hold anything that was typed or printed.
+async fn journal_summaries(data_dir: &Path) -> Vec<Value> {
+    let mut summaries = Vec::new();
+    let Ok(mut read_dir) =
+        tokio::fs::read_dir(crate::sessions::journal::sessions_dir(data_dir)).await
+    else {
+        return summaries;
+    };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let Some(session_id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let meta = entry.metadata().await.ok();
+        let modified_ms = meta
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis());
+        let first_line = match tokio::fs::File::open(&path).await {
+            Ok(file) => tokio::io::BufReader::new(file)
+                .lines()
+                .next_line()
+                .await
+                .ok()
+                .flatten(),
+            Err(_) => None,
+        };
+        summaries.push(json!({
+            "session_id": session_id,
+            "size": meta.map(|m| m.len()),
+            "modified_ms": modified_ms,
+            "metadata": first_line.and_then(|l| serde_json::from_str::<Value>(&l).ok()),
+        }));
+    }
+    summaries
+}
+
+/// Delete all but the newest [`KEEP_BUNDLES`] archives in `dir`.
+async fn prune(dir: &Path) {
+    let Ok(mut read_dir) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    let mut bundles: Vec<(std::time::SystemTime, PathBuf)> = Vec::new();
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !(name.starts_with("sctl-support-") && name.ends_with(".tar.gz")) {
+            continue;
+        }
+        if let Ok(modified) = entry.metadata().await.and_then(|m| m.modified()) {
+            bundles.push((modified, entry.path()));
+        }
+    }
+    bundles.sort_by_key(|b| std::cmp::Reverse(b.0));
+    for (_, path) in bundles.into_iter().skip(KEEP_BUNDLES) {
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!("Support bundle: failed to remove {}: {e}", path.display());
+        }
+    }
+}
+
+fn support_dir(config: &Config) -> PathBuf {
+    Path::new(&config.server.data_dir).join("support")
+}
+
+/// Serial with anything unsuitable for a file name replaced by `_`.
+fn file_safe(serial: &str) -> String {
+    serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Config as JSON with credential values replaced by `[REDACTED]`.
+fn redacted_config(config: &Config) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    redact(&mut value);
+    value
+}
+
+/// Replace string values under credential-like keys (`api_key`, `tunnel_key`,
+/// `*password*`, `*secret*`, `*token*`), recursively.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if v.is_string() && is_secret_key(key) {
+                    *v = json!(REDACTED);
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key == "key"
+        || key.ends_with("_key")
+        || ["password", "secret", "token"]
+            .iter()
+            .any(|s| key.contains(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_credentials_only() {
+        let mut value = json!({
+            "auth": { "api_key": "abc" },
+            "tunnel": { "tunnel_key": "xyz", "url": "wss://relay/api/tunnel/register" },
+            "secrets": { "provider": "file", "file": "/etc/sctl/secrets.toml" },
+            "hooks": [{ "name": "n", "auth_token": "t" }],
+            "lte": { "device": "/dev/ttyUSB2", "keep_alive": true },
+        });
+        redact(&mut value);
+        assert_eq!(value["auth"]["api_key"], REDACTED);
+        assert_eq!(value["tunnel"]["tunnel_key"], REDACTED);
+        assert_eq!(value["tunnel"]["url"], "wss://relay/api/tunnel/register");
+        assert_eq!(value["secrets"]["file"], "/etc/sctl/secrets.toml");
+        assert_eq!(value["hooks"][0]["auth_token"], REDACTED);
+        assert_eq!(value["hooks"][0]["name"], "n");
+        assert_eq!(value["lte"]["keep_alive"], true);
+    }
+
+    #[test]
+    fn file_safe_serial() {
+        assert_eq!(file_safe("DEV-001"), "DEV-001");
+        assert_eq!(file_safe("a/b c"), "a_b_c");
+    }
+}
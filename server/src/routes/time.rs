@@ -0,0 +1,435 @@
+//! Clock and NTP endpoints.
+//!
+//! - `GET /api/time` — system clock, timezone, and sync status from the
+//!   kernel (`adjtimex`), `timedatectl`, and `chronyc` (whichever exist)
+//! - `POST /api/time` — set the timezone, toggle NTP, and/or force a sync
+//!
+//! A wrong clock breaks TLS to the relay and skews activity timestamps, so the
+//! read side works with no tools installed (kernel state only) and each source
+//! that is present adds detail.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::infra::checks::exec_args_pub;
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Timeout for status queries.
+const QUERY_TIMEOUT_MS: u64 = 5_000;
+/// Timeout for set/sync operations (a one-shot `ntpd -q` can take a while).
+const ACTION_TIMEOUT_MS: u64 = 30_000;
+
+/// Default server for the one-shot `ntpd` fallback.
+const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+
+/// `adjtimex` return value when the clock is not synchronized.
+#[cfg(target_os = "linux")]
+const TIME_ERROR: i32 = 5;
+/// `timex.status` flag: clock unsynchronized.
+#[cfg(target_os = "linux")]
+const STA_UNSYNC: i32 = 0x0040;
+
+/// Body for `POST /api/time`.
+#[derive(Deserialize)]
+pub struct TimeSetRequest {
+    /// IANA timezone name, e.g. `"Europe/Berlin"`.
+    pub timezone: Option<String>,
+    /// Enable or disable automatic NTP sync (`timedatectl set-ntp`).
+    pub ntp: Option<bool>,
+    /// Force an immediate sync.
+    #[serde(default)]
+    pub sync: bool,
+    /// Server for the one-shot `ntpd -q` fallback (default `pool.ntp.org`).
+    pub server: Option<String>,
+}
+
+// ─── Status sources ──────────────────────────────────────────────────────────
+
+/// Kernel clock discipline state via `adjtimex(2)` (read-only).
+#[cfg(target_os = "linux")]
+fn kernel_status() -> Value {
+    // SAFETY: an all-zero timex with modes = 0 only reads state.
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: tx is a valid, exclusively borrowed timex.
+    let state = unsafe { libc::adjtimex(&raw mut tx) };
+    if state < 0 {
+        return Value::Null;
+    }
+    json!({
+        "synchronized": state != TIME_ERROR && tx.status & STA_UNSYNC == 0,
+        "max_error_us": tx.maxerror,
+        "est_error_us": tx.esterror,
+    })
+}
+
+/// `adjtimex` is Linux-only.
+#[cfg(not(target_os = "linux"))]
+fn kernel_status() -> Value {
+    Value::Null
+}
+
+/// Parse `timedatectl show` output (`Key=Value` lines).
+fn parse_timedatectl(output: &str) -> Value {
+    let mut map = serde_json::Map::new();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let field = match key {
+            "Timezone" => "timezone",
+            "NTP" => "ntp_enabled",
+            "NTPSynchronized" => "ntp_synchronized",
+            "CanNTP" => "can_ntp",
+            "LocalRTC" => "local_rtc",
+            _ => continue,
+        };
+        let value = match value {
+            "yes" => json!(true),
+            "no" => json!(false),
+            v => json!(v),
+        };
+        map.insert(field.to_string(), value);
+    }
+    Value::Object(map)
+}
+
+/// Parse `chronyc -c tracking` (one CSV line).
+fn parse_chrony_tracking(output: &str) -> Option<Value> {
+    let fields: Vec<&str> = output.trim().split(',').collect();
+    if fields.len() < 14 {
+        return None;
+    }
+    let float = |i: usize| fields[i].parse::<f64>().ok();
+    Some(json!({
+        "reference_id": fields[0],
+        "reference": fields[1],
+        "stratum": fields[2].parse::<u32>().ok(),
+        "reference_time": float(3),
+        "offset_secs": float(4),
+        "last_offset_secs": float(5),
+        "rms_offset_secs": float(6),
+        "frequency_ppm": float(7),
+        "root_delay_secs": float(10),
+        "root_dispersion_secs": float(11),
+        "update_interval_secs": float(12),
+        "leap_status": fields[13],
+        "synchronized": fields[13] != "Not synchronised" && fields[0] != "00000000",
+    }))
+}
+
+/// Timezone without `timedatectl`: `/etc/timezone`, then the `/etc/localtime` link.
+fn timezone_from_files() -> Option<String> {
+    if let Ok(tz) = std::fs::read_to_string("/etc/timezone") {
+        let tz = tz.trim();
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+    let target = std::fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_string_lossy();
+    target.split_once("zoneinfo/").map(|(_, tz)| tz.to_string())
+}
+
+fn has_tool(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+}
+
+async fn run_stdout(program: &str, args: &[&str]) -> Option<String> {
+    match exec_args_pub(program, args, QUERY_TIMEOUT_MS).await {
+        Ok((0, stdout, _)) => Some(stdout),
+        _ => None,
+    }
+}
+
+async fn time_status() -> Value {
+    let kernel = tokio::task::spawn_blocking(kernel_status)
+        .await
+        .unwrap_or(Value::Null);
+    let timedatectl = run_stdout("timedatectl", &["show"])
+        .await
+        .map(|o| parse_timedatectl(&o));
+    let chrony = run_stdout("chronyc", &["-c", "tracking"])
+        .await
+        .and_then(|o| parse_chrony_tracking(&o));
+
+    let timezone = timedatectl
+        .as_ref()
+        .and_then(|t| t["timezone"].as_str().map(ToString::to_string))
+        .or_else(timezone_from_files);
+    // Most specific source wins.
+    let synchronized = timedatectl
+        .as_ref()
+        .and_then(|t| t["ntp_synchronized"].as_bool())
+        .or_else(|| chrony.as_ref().and_then(|c| c["synchronized"].as_bool()))
+        .or_else(|| kernel["synchronized"].as_bool());
+
+    json!({
+        "time_unix_ms": crate::sessions::journal::now_ms(),
+        "time_utc": crate::infra::now_iso(),
+        "timezone": timezone,
+        "synchronized": synchronized,
+        "kernel": kernel,
+        "timedatectl": timedatectl,
+        "chrony": chrony,
+    })
+}
+
+// ─── Actions ─────────────────────────────────────────────────────────────────
+
+/// Check `tz` names a zone file under `/usr/share/zoneinfo`.
+fn validate_timezone(tz: &str) -> Result<(), String> {
+    if tz.is_empty()
+        || tz.starts_with('/')
+        || tz.split('/').any(|part| part.is_empty() || part == "..")
+        || !tz
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'))
+    {
+        return Err(format!("Invalid timezone '{tz}'"));
+    }
+    if !std::path::Path::new(ZONEINFO_DIR).join(tz).is_file() {
+        return Err(format!("Unknown timezone '{tz}'"));
+    }
+    Ok(())
+}
+
+/// Run an action step, returning the tool's error text on failure.
+async fn run_action(program: &str, args: &[&str]) -> Result<(), String> {
+    match exec_args_pub(program, args, ACTION_TIMEOUT_MS).await? {
+        (0, _, _) => Ok(()),
+        (code, stdout, stderr) => {
+            let msg = if stderr.trim().is_empty() {
+                stdout
+            } else {
+                stderr
+            };
+            Err(format!("{program} exited with {code}: {}", msg.trim()))
+        }
+    }
+}
+
+/// Point `/etc/localtime` at the zone file (atomic symlink swap) and update
+/// `/etc/timezone` when the distro uses it.
+#[cfg(windows)]
+fn link_timezone(_tz: &str) -> Result<(), String> {
+    Err("setting the timezone is not supported on Windows".to_string())
+}
+
+/// Point `/etc/localtime` at the zone file (atomic symlink swap) and update
+/// `/etc/timezone` when the distro uses it.
+#[cfg(unix)]
+fn link_timezone(tz: &str) -> Result<(), String> {
+    let target = std::path::Path::new(ZONEINFO_DIR).join(tz);
+    let tmp = "/etc/.localtime.sctl-tmp";
+    let _ = std::fs::remove_file(tmp);
+    std::os::unix::fs::symlink(&target, tmp).map_err(|e| format!("symlink: {e}"))?;
+    std::fs::rename(tmp, "/etc/localtime").map_err(|e| format!("rename: {e}"))?;
+    if std::path::Path::new("/etc/timezone").exists() {
+        std::fs::write("/etc/timezone", format!("{tz}\n"))
+            .map_err(|e| format!("write /etc/timezone: {e}"))?;
+    }
+    Ok(())
+}
+
+async fn set_timezone(tz: &str) -> (&'static str, Result<(), String>) {
+    if has_tool("timedatectl") {
+        (
+            "timedatectl",
+            run_action("timedatectl", &["set-timezone", tz]).await,
+        )
+    } else {
+        let tz = tz.to_string();
+        let result = tokio::task::spawn_blocking(move || link_timezone(&tz))
+            .await
+            .unwrap_or_else(|e| Err(format!("task failed: {e}")));
+        ("localtime", result)
+    }
+}
+
+async fn force_sync(server: &str) -> (&'static str, Result<(), String>) {
+    if has_tool("chronyc") {
+        ("chronyc", run_action("chronyc", &["-a", "makestep"]).await)
+    } else if has_tool("timedatectl") && has_tool("systemctl") {
+        (
+            "systemd-timesyncd",
+            run_action("systemctl", &["restart", "systemd-timesyncd"]).await,
+        )
+    } else {
+        // busybox and ntp.org ntpd both accept -q (set once and exit).
+        (
+            "ntpd",
+            run_action("ntpd", &["-n", "-q", "-p", server]).await,
+        )
+    }
+}
+
+fn step(action: &str, method: &str, result: Result<(), String>) -> Value {
+    match result {
+        Ok(()) => json!({ "action": action, "method": method, "ok": true }),
+        Err(e) => json!({ "action": action, "method": method, "ok": false, "error": e }),
+    }
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// `GET /api/time` — clock, timezone, and NTP sync status.
+pub async fn get_time() -> Json<Value> {
+    Json(time_status().await)
+}
+
+/// `POST /api/time` — set timezone, toggle NTP, and/or force a sync.
+///
+/// Steps run in that order; each reports its own `ok`/`error` under
+/// `actions`, and the response carries the status read afterwards.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — nothing requested,
+///   invalid or unknown timezone, or invalid server name
+pub async fn set_time(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<TimeSetRequest>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+    let bad_request = |msg: String| {
+        ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+    };
+
+    if payload.timezone.is_none() && payload.ntp.is_none() && !payload.sync {
+        return Err(bad_request(
+            "Nothing to do: set timezone, ntp, or sync".to_string(),
+        ));
+    }
+    if let Some(ref tz) = payload.timezone {
+        validate_timezone(tz).map_err(bad_request)?;
+    }
+    let server = payload.server.as_deref().unwrap_or(DEFAULT_NTP_SERVER);
+    // Passed as an argument, never through a shell — but keep it to a host/IP.
+    if server.is_empty()
+        || server.len() > 253
+        || server.starts_with('-')
+        || !server
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'))
+    {
+        return Err(bad_request(format!("Invalid server '{server}'")));
+    }
+
+    let mut actions = Vec::new();
+    if let Some(ref tz) = payload.timezone {
+        let (method, result) = set_timezone(tz).await;
+        actions.push(step("timezone", method, result));
+    }
+    if let Some(ntp) = payload.ntp {
+        let result = if has_tool("timedatectl") {
+            run_action(
+                "timedatectl",
+                &["set-ntp", if ntp { "true" } else { "false" }],
+            )
+            .await
+        } else {
+            Err("Toggling NTP requires timedatectl".to_string())
+        };
+        actions.push(step("ntp", "timedatectl", result));
+    }
+    if payload.sync {
+        let (method, result) = force_sync(server).await;
+        actions.push(step("sync", method, result));
+    }
+    let ok = actions.iter().all(|a| a["ok"] == true);
+
+    state
+        .activity_log
+        .log(
+            ActivityType::TimeSet,
+            source,
+            actions
+                .iter()
+                .map(|a| {
+                    format!(
+                        "{} {}",
+                        a["action"].as_str().unwrap_or(""),
+                        if a["ok"] == true { "ok" } else { "failed" }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            Some(json!({
+                "timezone": payload.timezone,
+                "ntp": payload.ntp,
+                "sync": payload.sync,
+                "actions": actions,
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({
+        "ok": ok,
+        "actions": actions,
+        "status": time_status().await,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timedatectl_show() {
+        let out = "Timezone=Europe/Berlin\nLocalRTC=no\nCanNTP=yes\nNTP=yes\n\
+                   NTPSynchronized=no\nTimeUSec=Fri 2026-10-16 10:00:00 CEST\n";
+        let t = parse_timedatectl(out);
+        assert_eq!(t["timezone"], "Europe/Berlin");
+        assert_eq!(t["ntp_enabled"], true);
+        assert_eq!(t["ntp_synchronized"], false);
+        assert_eq!(t["local_rtc"], false);
+        assert!(t.get("TimeUSec").is_none());
+    }
+
+    #[test]
+    fn parses_chrony_tracking_csv() {
+        let out = "A29FC87B,time.cloudflare.com,3,1760608800.123,-0.000012,0.000003,\
+                   0.000020,-12.345,0.001,0.050,0.012,0.001,64.2,Normal\n";
+        let c = parse_chrony_tracking(out).unwrap();
+        assert_eq!(c["reference"], "time.cloudflare.com");
+        assert_eq!(c["stratum"], 3);
+        assert_eq!(c["leap_status"], "Normal");
+        assert_eq!(c["synchronized"], true);
+
+        let unsynced = "00000000,,0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,1.0,1.0,0.0,Not synchronised";
+        assert_eq!(
+            parse_chrony_tracking(unsynced).unwrap()["synchronized"],
+            false
+        );
+        assert!(parse_chrony_tracking("garbage").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_timezone_names() {
+        for tz in [
+            "",
+            "/etc/passwd",
+            "../../etc/shadow",
+            "Europe/../x",
+            "Europe//Berlin",
+            "A B",
+        ] {
+            assert!(validate_timezone(tz).is_err(), "{tz:?}");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Device twin endpoints (see [`crate::twin`]).
+//!
+//! - `GET /api/twin` — desired state, latest drift report and reconciler settings
+//! - `PUT /api/twin` — replace the desired state and reconcile
+//! - `POST /api/twin/reconcile` — run a pass now and return its report
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::twin::{self, DesiredState};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// `GET /api/twin` — `{desired, report, apply, interval_secs}`. `desired`
+/// and `report` are `null` until a document is stored and reconciled.
+pub async fn get_twin(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({
+        "desired": state.twin.desired().await,
+        "report": state.twin.report().await,
+        "apply": state.config.twin.apply,
+        "interval_secs": state.config.twin.interval_secs,
+    }))
+}
+
+/// `PUT /api/twin` — replace the desired-state document. A reconcile pass
+/// starts right away in the background.
+///
+/// # Errors
+///
+/// - `400 Bad Request` with `{"code":"INVALID_REQUEST"}` — unknown fields or
+///   an entry the reconciler can't act on
+/// - `500 Internal Server Error` with `{"code":"IO_ERROR"}`
+pub async fn put_twin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> ApiResult<Value> {
+    put(&state, &headers, body).await
+}
+
+/// Store a desired-state document for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`put_twin`].
+pub async fn put(state: &AppState, headers: &HeaderMap, body: Value) -> ApiResult<Value> {
+    let desired = serde_json::from_value::<DesiredState>(body)
+        .map_err(|e| e.to_string())
+        .and_then(|d| d.validate().map(|()| d))
+        .map_err(|e| {
+            ApiError::new(
+                codes::INVALID_REQUEST,
+                format!("Invalid twin document: {e}"),
+            )
+            .into_response_with(StatusCode::BAD_REQUEST)
+        })?;
+    let counts = json!({
+        "files": desired.files.len(),
+        "services": desired.services.len(),
+        "packages": desired.packages.len(),
+        "playbooks": desired.playbooks.len(),
+    });
+    state.twin.set_desired(desired).await.map_err(|e| {
+        ApiError::new(
+            codes::IO_ERROR,
+            format!("Failed to store twin document: {e}"),
+        )
+        .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    state
+        .activity_log
+        .log(
+            ActivityType::TwinUpdate,
+            source_from_headers(headers),
+            "Updated twin desired state".to_string(),
+            Some(counts.clone()),
+            request_id_from_headers(headers),
+        )
+        .await;
+    Ok(Json(json!({ "ok": true, "counts": counts })))
+}
+
+/// Request body for `POST /api/twin/reconcile`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ReconcileRequest {
+    /// Fix drift; defaults to `[twin] apply`.
+    pub apply: Option<bool>,
+}
+
+/// `POST /api/twin/reconcile` — run a pass now (waiting for one already in
+/// progress) and return its report.
+///
+/// # Errors
+///
+/// - `404 Not Found` with `{"code":"NOT_FOUND"}` — no document stored
+pub async fn reconcile(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<ReconcileRequest>>,
+) -> ApiResult<Value> {
+    let Json(payload) = body.unwrap_or_default();
+    run_reconcile(&state, &headers, &payload).await
+}
+
+/// Run a pass for REST and the tunnel.
+///
+/// # Errors
+///
+/// As [`reconcile`].
+pub async fn run_reconcile(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &ReconcileRequest,
+) -> ApiResult<Value> {
+    let apply = payload.apply.unwrap_or(state.config.twin.apply);
+    match twin::reconcile(state, apply, source_from_headers(headers)).await {
+        Some(report) => Ok(Json(json!(report))),
+        None => Err(
+            ApiError::new(codes::NOT_FOUND, "No twin document has been stored")
+                .into_response_with(StatusCode::NOT_FOUND),
+        ),
+    }
+}
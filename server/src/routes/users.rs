@@ -0,0 +1,589 @@
+//! Local user account endpoints.
+//!
+//! - `GET /api/users` — accounts from `/etc/passwd`, with password/lock state
+//!   from `/etc/shadow` (when readable) and last login from `/var/log/lastlog`
+//! - `POST /api/users/{name}/lock` — lock the password (`passwd -l`)
+//! - `POST /api/users/{name}/unlock` — unlock it (`passwd -u`)
+//! - `POST /api/users/{name}/reset-password` — set a new password (`chpasswd`)
+//!
+//! Modifying operations are guarded: the body must repeat the account name as
+//! `confirm`, system accounts (uid below `UID_MIN`, including root) also need
+//! `allow_system: true`, and sctl must run as root. Passwords never reach the
+//! activity journal; a generated password is returned once in the response.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::activity::{request_id_from_headers, source_from_headers, ActivityType};
+use crate::error::{codes, ApiError};
+use crate::AppState;
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+/// Size of one `struct lastlog` record (`int32_t ll_time; char ll_line[32];
+/// char ll_host[256]`), indexed by uid.
+const LASTLOG_RECORD: u64 = 292;
+
+/// Fallback when `/etc/login.defs` has no `UID_MIN`.
+const DEFAULT_UID_MIN: u32 = 1000;
+
+/// Timeout for `passwd` / `chpasswd`.
+const TOOL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ─── Types ───────────────────────────────────────────────────────────────────
+
+/// One `/etc/passwd` entry.
+#[derive(Debug, Clone)]
+pub(crate) struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+/// Password state derived from the `/etc/shadow` hash field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PasswordStatus {
+    /// A usable password hash.
+    Set,
+    /// Hash prefixed with `!` (`passwd -l`).
+    Locked,
+    /// `*` — no password login possible.
+    Disabled,
+    /// Empty field — login without a password.
+    Empty,
+}
+
+/// The `/etc/shadow` fields the listing reports.
+struct ShadowEntry {
+    status: PasswordStatus,
+    last_change_days: Option<u64>,
+    expire_days: Option<u64>,
+}
+
+/// Body for the lock/unlock endpoints.
+#[derive(Deserialize)]
+pub struct UserActionRequest {
+    /// Must equal the account name in the path.
+    #[serde(default)]
+    pub confirm: String,
+    /// Permit acting on system accounts (uid below `UID_MIN`, including root).
+    #[serde(default)]
+    pub allow_system: bool,
+}
+
+/// Body for `POST /api/users/{name}/reset-password`.
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    /// Must equal the account name in the path.
+    #[serde(default)]
+    pub confirm: String,
+    /// Permit acting on system accounts (uid below `UID_MIN`, including root).
+    #[serde(default)]
+    pub allow_system: bool,
+    /// New password, or a `secret://` reference. Omit to generate one.
+    pub password: Option<String>,
+    /// Force a password change at next login (`passwd -e`).
+    #[serde(default)]
+    pub expire: bool,
+}
+
+// ─── Parsing ─────────────────────────────────────────────────────────────────
+
+/// Parse `/etc/passwd` content, skipping malformed lines.
+pub(crate) fn parse_passwd(content: &str) -> Vec<PasswdEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            Some(PasswdEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                gecos: fields[4].to_string(),
+                home: fields[5].to_string(),
+                shell: fields[6].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Read and parse `/etc/passwd`.
+pub(crate) fn read_passwd() -> std::io::Result<Vec<PasswdEntry>> {
+    std::fs::read_to_string("/etc/passwd").map(|c| parse_passwd(&c))
+}
+
+/// Whether `name` is an acceptable account name for a lookup.
+pub(crate) fn valid_user_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn parse_shadow_line(line: &str) -> Option<(String, ShadowEntry)> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 8 {
+        return None;
+    }
+    let hash = fields[1];
+    let status = if hash.is_empty() {
+        PasswordStatus::Empty
+    } else if hash.starts_with('!') {
+        PasswordStatus::Locked
+    } else if hash.starts_with('*') {
+        PasswordStatus::Disabled
+    } else {
+        PasswordStatus::Set
+    };
+    Some((
+        fields[0].to_string(),
+        ShadowEntry {
+            status,
+            last_change_days: fields[2].parse().ok(),
+            expire_days: fields[7].parse().ok(),
+        },
+    ))
+}
+
+/// `UID_MIN` from `/etc/login.defs` content.
+fn parse_uid_min(login_defs: &str) -> Option<u32> {
+    login_defs.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        (parts.next() == Some("UID_MIN"))
+            .then(|| parts.next()?.parse().ok())
+            .flatten()
+    })
+}
+
+fn uid_min() -> u32 {
+    std::fs::read_to_string("/etc/login.defs")
+        .ok()
+        .and_then(|c| parse_uid_min(&c))
+        .unwrap_or(DEFAULT_UID_MIN)
+}
+
+/// Decode one `struct lastlog` record. `None` if the user never logged in.
+fn parse_lastlog_record(record: &[u8]) -> Option<Value> {
+    let time = i32::from_ne_bytes(record.get(..4)?.try_into().ok()?);
+    if time <= 0 {
+        return None;
+    }
+    let text = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    Some(json!({
+        "time": time,
+        "line": text(record.get(4..36)?),
+        "host": text(record.get(36..292)?),
+    }))
+}
+
+fn last_login(lastlog: Option<&std::fs::File>, uid: u32) -> Value {
+    let Some(mut file) = lastlog else {
+        return Value::Null;
+    };
+    // The file is sparse and indexed by uid; short reads mean "never".
+    let mut record = [0u8; LASTLOG_RECORD as usize];
+    file.seek(SeekFrom::Start(u64::from(uid) * LASTLOG_RECORD))
+        .and_then(|_| file.read_exact(&mut record))
+        .ok()
+        .and_then(|()| parse_lastlog_record(&record))
+        .unwrap_or(Value::Null)
+}
+
+// ─── Helpers ─────────────────────────────────────────────────────────────────
+
+fn bad_request(msg: impl Into<String>) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::INVALID_REQUEST, msg).into_response_with(StatusCode::BAD_REQUEST)
+}
+
+fn forbidden(msg: impl Into<String>) -> (StatusCode, Json<ApiError>) {
+    ApiError::new(codes::PERMISSION_DENIED, msg).into_response_with(StatusCode::FORBIDDEN)
+}
+
+/// Apply the guards shared by all modifying operations and return the target.
+fn check_target(
+    name: &str,
+    confirm: &str,
+    allow_system: bool,
+) -> Result<PasswdEntry, (StatusCode, Json<ApiError>)> {
+    if !valid_user_name(name) {
+        return Err(bad_request("Invalid user name"));
+    }
+    if confirm != name {
+        return Err(bad_request(
+            "confirm must repeat the account name to modify it",
+        ));
+    }
+    if crate::util::effective_uid() != 0 {
+        return Err(forbidden("Account management requires sctl to run as root"));
+    }
+    let entries = read_passwd().map_err(|e| {
+        ApiError::new(codes::IO_ERROR, format!("Failed to read /etc/passwd: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    let entry = entries
+        .into_iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| {
+            ApiError::new(codes::NOT_FOUND, format!("User '{name}' not found"))
+                .into_response_with(StatusCode::NOT_FOUND)
+        })?;
+    if entry.uid < uid_min() && !allow_system {
+        return Err(forbidden(format!(
+            "'{name}' is a system account (uid {}); set allow_system to modify it",
+            entry.uid
+        )));
+    }
+    Ok(entry)
+}
+
+/// Run an account tool, feeding `stdin` if given. Returns trimmed stderr on failure.
+async fn run_tool(
+    program: &str,
+    args: &[&str],
+    stdin: Option<&str>,
+) -> Result<(), (StatusCode, Json<ApiError>)> {
+    use tokio::io::AsyncWriteExt;
+
+    let failed = |msg: String| {
+        ApiError::new(codes::EXEC_FAILED, msg).into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            std::process::Stdio::piped()
+        } else {
+            std::process::Stdio::null()
+        })
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| failed(format!("Failed to run {program}: {e}")))?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes())
+            .await
+            .map_err(|e| failed(format!("Failed to write to {program}: {e}")))?;
+    }
+    let output = tokio::time::timeout(TOOL_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| failed(format!("{program} timed out")))?
+        .map_err(|e| failed(format!("Failed to run {program}: {e}")))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(failed(format!(
+            "{program} exited with {}: {}",
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// 24-character random password (144 bits from `/dev/urandom`).
+fn generate_password() -> Result<String, (StatusCode, Json<ApiError>)> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; 18];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(|e| {
+            ApiError::new(codes::IO_ERROR, format!("Failed to generate password: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+// ─── Handlers ────────────────────────────────────────────────────────────────
+
+/// `GET /api/users` — list local accounts.
+pub async fn list_users(State(state): State<AppState>, headers: HeaderMap) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    let result = tokio::task::spawn_blocking(|| {
+        let entries = read_passwd().map_err(|e| {
+            ApiError::new(codes::IO_ERROR, format!("Failed to read /etc/passwd: {e}"))
+                .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+        let shadow: Option<std::collections::HashMap<String, ShadowEntry>> =
+            std::fs::read_to_string("/etc/shadow")
+                .ok()
+                .map(|c| c.lines().filter_map(parse_shadow_line).collect());
+        let lastlog = std::fs::File::open("/var/log/lastlog").ok();
+        let uid_min = uid_min();
+        let users: Vec<Value> = entries
+            .iter()
+            .map(|e| {
+                let sh = shadow.as_ref().and_then(|s| s.get(&e.name));
+                json!({
+                    "name": e.name,
+                    "uid": e.uid,
+                    "gid": e.gid,
+                    "gecos": e.gecos,
+                    "home": e.home,
+                    "shell": e.shell,
+                    "system": e.uid < uid_min,
+                    "password_status": sh.map(|s| s.status),
+                    "locked": sh.map(|s| s.status == PasswordStatus::Locked),
+                    "password_changed": sh.and_then(|s| s.last_change_days).map(|d| d * 86400),
+                    "expires": sh.and_then(|s| s.expire_days).map(|d| d * 86400),
+                    "last_login": last_login(lastlog.as_ref(), e.uid),
+                })
+            })
+            .collect();
+        Ok::<_, (StatusCode, Json<ApiError>)>(json!({
+            "users": users,
+            "uid_min": uid_min,
+            "shadow_readable": shadow.is_some(),
+        }))
+    })
+    .await
+    .map_err(|e| {
+        ApiError::new(codes::IO_ERROR, format!("User listing task failed: {e}"))
+            .into_response_with(StatusCode::INTERNAL_SERVER_ERROR)
+    })?;
+    let body = result?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::UserList,
+            source,
+            format!(
+                "Listed {} users",
+                body["users"].as_array().map_or(0, Vec::len)
+            ),
+            None,
+            req_id,
+        )
+        .await;
+
+    Ok(Json(body))
+}
+
+/// `POST /api/users/{name}/lock` — lock the account password.
+///
+/// # Errors
+///
+/// - `400 Bad Request` — invalid name or `confirm` mismatch
+/// - `403 Forbidden` — system account without `allow_system`, or sctl not root
+/// - `404 Not Found` — unknown user
+/// - `500` with `{"code":"EXEC_FAILED"}` — `passwd` failed
+pub async fn lock_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(payload): Json<UserActionRequest>,
+) -> ApiResult<Value> {
+    set_locked(&state, &headers, &name, &payload, true).await
+}
+
+/// `POST /api/users/{name}/unlock` — unlock the account password.
+///
+/// Errors as for [`lock_user`]; `passwd -u` also refuses to unlock an account
+/// that would be left without a password.
+pub async fn unlock_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(payload): Json<UserActionRequest>,
+) -> ApiResult<Value> {
+    set_locked(&state, &headers, &name, &payload, false).await
+}
+
+async fn set_locked(
+    state: &AppState,
+    headers: &HeaderMap,
+    name: &str,
+    payload: &UserActionRequest,
+    lock: bool,
+) -> ApiResult<Value> {
+    let source = source_from_headers(headers);
+    let req_id = request_id_from_headers(headers);
+
+    let entry = check_target(name, &payload.confirm, payload.allow_system)?;
+    run_tool("passwd", &[if lock { "-l" } else { "-u" }, name], None).await?;
+
+    state
+        .activity_log
+        .log(
+            ActivityType::UserModify,
+            source,
+            format!("{} user {name}", if lock { "Locked" } else { "Unlocked" }),
+            Some(json!({
+                "user": name,
+                "uid": entry.uid,
+                "action": if lock { "lock" } else { "unlock" },
+            })),
+            req_id,
+        )
+        .await;
+
+    Ok(Json(json!({ "ok": true, "user": name, "locked": lock })))
+}
+
+/// `POST /api/users/{name}/reset-password` — set a new password.
+///
+/// `password` may be a literal or a `secret://` reference resolved through the
+/// `[secrets]` provider; if omitted, a random password is generated and
+/// returned in the response (the only place it appears).
+///
+/// # Errors
+///
+/// As for [`lock_user`], plus `400` for an unresolvable secret reference or a
+/// password containing a newline.
+pub async fn reset_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> ApiResult<Value> {
+    let source = source_from_headers(&headers);
+    let req_id = request_id_from_headers(&headers);
+
+    let entry = check_target(&name, &payload.confirm, payload.allow_system)?;
+
+    let generated = payload.password.is_none();
+    let password = match payload.password {
+        None => generate_password()?,
+        Some(p) if p.starts_with(crate::shell::secrets::SECRET_SCHEME) => {
+            let env = std::collections::HashMap::from([("password".to_string(), p)]);
+            let resolved = crate::shell::secrets::resolve_env(
+                state.config.secrets.as_ref(),
+                &state.config.server.data_dir,
+                Some(&env),
+            )
+            .await
+            .map_err(bad_request)?;
+            resolved
+                .env()
+                .and_then(|e| e.get("password"))
+                .cloned()
+                .unwrap_or_default()
+        }
+        Some(p) => p,
+    };
+    if password.is_empty() {
+        return Err(bad_request("password must not be empty"));
+    }
+    if password.contains(['\n', '\r']) {
+        return Err(bad_request("password must not contain newlines"));
+    }
+
+    run_tool("chpasswd", &[], Some(&format!("{name}:{password}\n"))).await?;
+    let expire_error = if payload.expire {
+        run_tool("passwd", &["-e", &name], None)
+            .await
+            .err()
+            .map(|(_, Json(e))| e.message)
+    } else {
+        None
+    };
+
+    state
+        .activity_log
+        .log(
+            ActivityType::UserModify,
+            source,
+            format!("Reset password for user {name}"),
+            Some(json!({
+                "user": name,
+                "uid": entry.uid,
+                "action": "reset_password",
+                "generated": generated,
+                "expire": payload.expire,
+            })),
+            req_id,
+        )
+        .await;
+
+    let mut body = json!({
+        "ok": true,
+        "user": name,
+        "generated": generated,
+        "expired": payload.expire && expire_error.is_none(),
+    });
+    if generated {
+        body["password"] = json!(password);
+    }
+    if let Some(e) = expire_error {
+        body["expire_error"] = json!(e);
+    }
+    Ok(Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_passwd_and_skips_garbage() {
+        let content = "root:x:0:0:root:/root:/bin/bash\n\
+                       broken line\n\
+                       ops:x:1001:1001:Ops Team,,,:/home/ops:/bin/sh\n";
+        let entries = parse_passwd(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "ops");
+        assert_eq!(entries[1].uid, 1001);
+        assert_eq!(entries[1].gecos, "Ops Team,,,");
+        assert_eq!(entries[1].shell, "/bin/sh");
+    }
+
+    #[test]
+    fn shadow_password_status() {
+        let status = |line: &str| parse_shadow_line(line).unwrap().1.status;
+        assert_eq!(
+            status("a:$6$salt$hash:19000:0:99999:7:::"),
+            PasswordStatus::Set
+        );
+        assert_eq!(
+            status("a:!$6$salt$hash:19000:0:99999:7:::"),
+            PasswordStatus::Locked
+        );
+        assert_eq!(status("a:!!:19000::::::"), PasswordStatus::Locked);
+        assert_eq!(status("a:*:19000:0:99999:7:::"), PasswordStatus::Disabled);
+        assert_eq!(status("a::19000:0:99999:7:::"), PasswordStatus::Empty);
+        let (_, entry) = parse_shadow_line("a:*:19000:0:99999:7::20000:").unwrap();
+        assert_eq!(entry.last_change_days, Some(19000));
+        assert_eq!(entry.expire_days, Some(20000));
+    }
+
+    #[test]
+    fn uid_min_from_login_defs() {
+        let defs = "# comment\nUID_MIN\t\t\t  500\nUID_MAX 60000\n";
+        assert_eq!(parse_uid_min(defs), Some(500));
+        assert_eq!(parse_uid_min("UID_MAX 60000\n"), None);
+    }
+
+    #[test]
+    fn lastlog_record_decoding() {
+        let mut record = [0u8; LASTLOG_RECORD as usize];
+        assert!(parse_lastlog_record(&record).is_none());
+        record[..4].copy_from_slice(&1_700_000_000i32.to_ne_bytes());
+        record[4..9].copy_from_slice(b"pts/0");
+        record[36..44].copy_from_slice(b"10.0.0.5");
+        let login = parse_lastlog_record(&record).unwrap();
+        assert_eq!(login["time"], 1_700_000_000);
+        assert_eq!(login["line"], "pts/0");
+        assert_eq!(login["host"], "10.0.0.5");
+    }
+}
@@ -0,0 +1,313 @@
+//! Hardware sensors for `GET /api/info/sensors` and the `sensors` info group.
+//!
+//! Everything comes from sysfs, so it works without `lm-sensors` or `upower`:
+//!
+//! | Field             | Source                                                  |
+//! |-------------------|---------------------------------------------------------|
+//! | `temperatures`    | `/sys/class/hwmon/*/temp*_input` (with `_label`, `_max`, `_crit`) |
+//! | `voltages`        | `/sys/class/hwmon/*/in*_input`                          |
+//! | `fans`            | `/sys/class/hwmon/*/fan*_input`                         |
+//! | `thermal_zones`   | `/sys/class/thermal/thermal_zone*` and their trip points |
+//! | `cooling_devices` | `/sys/class/thermal/cooling_device*` — a processor at `cur_state > 0` is being throttled |
+//! | `power_supplies`  | `/sys/class/power_supply/*` (batteries, mains, USB)     |
+//! | `cpu_frequency`   | `/sys/devices/system/cpu/cpu*/cpufreq`                  |
+//!
+//! Sections the device doesn't have are empty lists; `hottest_celsius` is the
+//! highest temperature seen across hwmon and thermal zones.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+/// Read every sensor under `/sys`.
+pub fn read() -> Value {
+    read_at(Path::new("/sys"))
+}
+
+/// Read every sensor under `sys` (a sysfs mount).
+pub fn read_at(sys: &Path) -> Value {
+    let mut temperatures = Vec::new();
+    let mut voltages = Vec::new();
+    let mut fans = Vec::new();
+    for dir in entries(&sys.join("class/hwmon"), "hwmon") {
+        read_hwmon(&dir, &mut temperatures, &mut voltages, &mut fans);
+    }
+    let thermal = sys.join("class/thermal");
+    let thermal_zones: Vec<Value> = entries(&thermal, "thermal_zone")
+        .iter()
+        .filter_map(|dir| thermal_zone(dir))
+        .collect();
+    let cooling_devices: Vec<Value> = entries(&thermal, "cooling_device")
+        .iter()
+        .filter_map(|dir| cooling_device(dir))
+        .collect();
+    let power_supplies: Vec<Value> = entries(&sys.join("class/power_supply"), "")
+        .iter()
+        .filter_map(|dir| power_supply(dir))
+        .collect();
+    let cpu_frequency: Vec<Value> = entries(&sys.join("devices/system/cpu"), "cpu")
+        .iter()
+        .filter_map(|dir| cpu_frequency(dir))
+        .collect();
+
+    let hottest = temperatures
+        .iter()
+        .chain(&thermal_zones)
+        .filter_map(|t| t["celsius"].as_f64())
+        .reduce(f64::max);
+    json!({
+        "hottest_celsius": hottest,
+        "temperatures": temperatures,
+        "voltages": voltages,
+        "fans": fans,
+        "thermal_zones": thermal_zones,
+        "cooling_devices": cooling_devices,
+        "power_supplies": power_supplies,
+        "cpu_frequency": cpu_frequency,
+    })
+}
+
+/// The `<prefix>N` entries of `dir` (all entries for an empty prefix),
+/// in numeric order.
+fn entries(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let Ok(read) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<(u64, String, PathBuf)> = read
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(prefix)?;
+            let index = if prefix.is_empty() {
+                0
+            } else {
+                rest.parse().ok()?
+            };
+            Some((index, name, e.path()))
+        })
+        .collect();
+    found.sort();
+    found.into_iter().map(|(_, _, path)| path).collect()
+}
+
+fn read_str(path: &Path) -> Option<String> {
+    let s = fs::read_to_string(path).ok()?;
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+fn read_i64(path: &Path) -> Option<i64> {
+    read_str(path)?.parse().ok()
+}
+
+/// A sysfs integer divided by `scale`, rounded to two decimals.
+#[allow(clippy::cast_precision_loss)]
+fn read_scaled(path: &Path, scale: f64) -> Option<f64> {
+    read_i64(path).map(|v| (v as f64 / scale * 100.0).round() / 100.0)
+}
+
+/// One hwmon chip. Older drivers keep their files under `device/`.
+fn read_hwmon(
+    dir: &Path,
+    temperatures: &mut Vec<Value>,
+    voltages: &mut Vec<Value>,
+    fans: &mut Vec<Value>,
+) {
+    let base = if dir.join("name").exists() || !dir.join("device/name").exists() {
+        dir.to_path_buf()
+    } else {
+        dir.join("device")
+    };
+    let chip = read_str(&base.join("name")).unwrap_or_else(|| file_name(dir));
+    let Ok(read) = fs::read_dir(&base) else {
+        return;
+    };
+    let mut inputs: Vec<(String, u64, String)> = read
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let stem = name.strip_suffix("_input")?;
+            let split = stem.find(|c: char| c.is_ascii_digit())?;
+            let (kind, index) = stem.split_at(split);
+            Some((kind.to_string(), index.parse().ok()?, stem.to_string()))
+        })
+        .collect();
+    inputs.sort();
+    for (kind, _, stem) in inputs {
+        let file = |suffix: &str| base.join(format!("{stem}_{suffix}"));
+        let label = read_str(&file("label")).unwrap_or_else(|| stem.clone());
+        match kind.as_str() {
+            "temp" => {
+                let Some(celsius) = read_scaled(&file("input"), 1000.0) else {
+                    continue;
+                };
+                let mut entry = json!({"chip": chip, "label": label, "celsius": celsius});
+                if let Some(max) = read_scaled(&file("max"), 1000.0) {
+                    entry["max_celsius"] = json!(max);
+                }
+                if let Some(crit) = read_scaled(&file("crit"), 1000.0) {
+                    entry["crit_celsius"] = json!(crit);
+                }
+                temperatures.push(entry);
+            }
+            "in" => {
+                if let Some(volts) = read_scaled(&file("input"), 1000.0) {
+                    voltages.push(json!({"chip": chip, "label": label, "volts": volts}));
+                }
+            }
+            "fan" => {
+                if let Some(rpm) = read_i64(&file("input")) {
+                    fans.push(json!({"chip": chip, "label": label, "rpm": rpm}));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn thermal_zone(dir: &Path) -> Option<Value> {
+    let celsius = read_scaled(&dir.join("temp"), 1000.0)?;
+    let trip_points: Vec<Value> = (0..)
+        .map_while(|i| {
+            let kind = read_str(&dir.join(format!("trip_point_{i}_type")))?;
+            let temp = read_scaled(&dir.join(format!("trip_point_{i}_temp")), 1000.0);
+            Some(json!({"type": kind, "celsius": temp}))
+        })
+        .collect();
+    Some(json!({
+        "zone": file_name(dir),
+        "type": read_str(&dir.join("type")),
+        "celsius": celsius,
+        "trip_points": trip_points,
+    }))
+}
+
+fn cooling_device(dir: &Path) -> Option<Value> {
+    let cur_state = read_i64(&dir.join("cur_state"))?;
+    Some(json!({
+        "device": file_name(dir),
+        "type": read_str(&dir.join("type")),
+        "cur_state": cur_state,
+        "max_state": read_i64(&dir.join("max_state")),
+    }))
+}
+
+fn power_supply(dir: &Path) -> Option<Value> {
+    let kind = read_str(&dir.join("type"))?;
+    let mut entry = Map::new();
+    entry.insert("name".into(), json!(file_name(dir)));
+    entry.insert("type".into(), json!(kind));
+    let mut put = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            entry.insert(key.into(), value);
+        }
+    };
+    put(
+        "online",
+        read_i64(&dir.join("online")).map(|v| json!(v == 1)),
+    );
+    put("status", read_str(&dir.join("status")).map(Value::from));
+    put("health", read_str(&dir.join("health")).map(Value::from));
+    put(
+        "capacity_percent",
+        read_i64(&dir.join("capacity")).map(Value::from),
+    );
+    put(
+        "volts",
+        read_scaled(&dir.join("voltage_now"), 1_000_000.0).map(Value::from),
+    );
+    put(
+        "amps",
+        read_scaled(&dir.join("current_now"), 1_000_000.0).map(Value::from),
+    );
+    // Tenths of a degree, unlike hwmon.
+    put(
+        "celsius",
+        read_scaled(&dir.join("temp"), 10.0).map(Value::from),
+    );
+    Some(Value::Object(entry))
+}
+
+fn cpu_frequency(dir: &Path) -> Option<Value> {
+    let cpufreq = dir.join("cpufreq");
+    let current = read_i64(&cpufreq.join("scaling_cur_freq"))?;
+    let cpu: u64 = file_name(dir).strip_prefix("cpu")?.parse().ok()?;
+    Some(json!({
+        "cpu": cpu,
+        "current_mhz": current / 1000,
+        "max_mhz": read_i64(&cpufreq.join("cpuinfo_max_freq")).map(|v| v / 1000),
+        "governor": read_str(&cpufreq.join("scaling_governor")),
+    }))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_sysfs_tree() {
+        let sys = std::env::temp_dir().join(format!("sctl-sensors-{}", std::process::id()));
+        let files = [
+            ("class/hwmon/hwmon0/name", "coretemp"),
+            ("class/hwmon/hwmon0/temp1_input", "51000"),
+            ("class/hwmon/hwmon0/temp1_label", "Package id 0"),
+            ("class/hwmon/hwmon0/temp1_crit", "100000"),
+            ("class/hwmon/hwmon1/name", "nct6775"),
+            ("class/hwmon/hwmon1/in0_input", "1216"),
+            ("class/hwmon/hwmon1/fan2_input", "1830"),
+            ("class/thermal/thermal_zone0/type", "cpu-thermal"),
+            ("class/thermal/thermal_zone0/temp", "78500"),
+            ("class/thermal/thermal_zone0/trip_point_0_type", "passive"),
+            ("class/thermal/thermal_zone0/trip_point_0_temp", "75000"),
+            ("class/thermal/cooling_device0/type", "Processor"),
+            ("class/thermal/cooling_device0/cur_state", "2"),
+            ("class/thermal/cooling_device0/max_state", "3"),
+            ("class/power_supply/BAT0/type", "Battery"),
+            ("class/power_supply/BAT0/status", "Discharging"),
+            ("class/power_supply/BAT0/capacity", "87"),
+            ("class/power_supply/BAT0/voltage_now", "12150000"),
+            ("class/power_supply/AC/type", "Mains"),
+            ("class/power_supply/AC/online", "0"),
+            ("devices/system/cpu/cpu0/cpufreq/scaling_cur_freq", "600000"),
+            (
+                "devices/system/cpu/cpu0/cpufreq/cpuinfo_max_freq",
+                "1800000",
+            ),
+        ];
+        for (path, content) in files {
+            let path = sys.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{content}\n")).unwrap();
+        }
+
+        let sensors = read_at(&sys);
+        fs::remove_dir_all(&sys).unwrap();
+        assert_eq!(sensors["hottest_celsius"], 78.5);
+        assert_eq!(sensors["temperatures"][0]["label"], "Package id 0");
+        assert_eq!(sensors["temperatures"][0]["celsius"], 51.0);
+        assert_eq!(sensors["temperatures"][0]["crit_celsius"], 100.0);
+        assert_eq!(sensors["voltages"][0]["volts"], 1.22);
+        assert_eq!(sensors["fans"][0]["label"], "fan2");
+        assert_eq!(
+            sensors["thermal_zones"][0]["trip_points"][0]["celsius"],
+            75.0
+        );
+        assert_eq!(sensors["cooling_devices"][0]["cur_state"], 2);
+        assert_eq!(sensors["power_supplies"][0]["online"], false);
+        assert_eq!(sensors["power_supplies"][1]["capacity_percent"], 87);
+        assert_eq!(sensors["power_supplies"][1]["volts"], 12.15);
+        assert_eq!(sensors["cpu_frequency"][0]["current_mhz"], 600);
+
+        assert_eq!(
+            read_at(Path::new("/nonexistent"))["temperatures"],
+            json!([])
+        );
+    }
+}
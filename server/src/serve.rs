@@ -0,0 +1,287 @@
+//! Connection handling for the HTTP/WebSocket listeners (`[server.http]`).
+//!
+//! Takes the place of `axum::serve` so connections can be tuned. A dashboard
+//! polling a dozen endpoints every few seconds would otherwise open a TCP
+//! connection per request, each one counted against `max_connections`; here
+//! it can multiplex them over one HTTP/2 connection, or at least reuse
+//! HTTP/1.1 ones.
+//!
+//! - **HTTP/2** (`http2`, on by default) — plain listeners accept h2c with
+//!   prior knowledge next to HTTP/1.1, and TLS listeners offer `h2` in ALPN.
+//!   WebSockets stay on HTTP/1.1: extended CONNECT is not advertised, so
+//!   clients open a separate connection for them.
+//! - **Keep-alive** (`keep_alive`) — HTTP/1.1 connections serve more than one
+//!   request.
+//! - **Idle timeout** (`idle_timeout_secs`) — a connection with no traffic for
+//!   that long is shut down gracefully: requests in flight finish first.
+//!   Upgraded connections (WebSockets) are no longer tracked.
+//! - **HTTP/2 keep-alive** (`http2_keepalive_secs`) — PINGs that drop dead
+//!   peers instead of holding their streams open.
+//!
+//! Every connection is listed in [`Connections`] (`GET /api/connections`)
+//! while its socket is open.
+//!
+//! On shutdown, listeners stop accepting and open connections are closed
+//! gracefully, as with `axum::serve(..).with_graceful_shutdown(..)`.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, Extensions, Request, StatusCode, Version};
+use axum::serve::Listener;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tracing::trace;
+
+use crate::config::HttpConfig;
+use crate::connections::{Connection, ConnectionKind, Connections, Tracked};
+
+/// What a listener knows about a peer, handed to handlers as `ConnectInfo`.
+pub trait PeerInfo: Clone + Send + Sync + 'static {
+    /// Add this peer's `ConnectInfo` values to a request.
+    fn insert_into(&self, extensions: &mut Extensions);
+
+    /// The peer's address.
+    fn addr(&self) -> SocketAddr;
+}
+
+impl PeerInfo for SocketAddr {
+    fn addr(&self) -> SocketAddr {
+        *self
+    }
+
+    fn insert_into(&self, extensions: &mut Extensions) {
+        extensions.insert(ConnectInfo(*self));
+    }
+}
+
+/// Serve `app` on `listener` until `shutdown` completes, then wait for the
+/// open connections to close.
+///
+/// # Errors
+///
+/// None so far; the signature matches `axum::serve` so listeners can be
+/// collected together.
+pub async fn serve<L>(
+    mut listener: L,
+    app: Router,
+    config: HttpConfig,
+    connections: Arc<Connections>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()>
+where
+    L: Listener,
+    L::Addr: PeerInfo,
+{
+    let local: Arc<str> = listener
+        .local_addr()
+        .map_or_else(|_| "unknown".into(), |a| a.addr().to_string().into());
+    let builder = Arc::new(builder(&config));
+    let idle =
+        (config.idle_timeout_secs > 0).then(|| Duration::from_secs(config.idle_timeout_secs));
+    let stop = CancellationToken::new();
+    // Every connection holds a receiver; the sender closes when the last
+    // one is gone.
+    let (open_tx, open_rx) = watch::channel(());
+    let mut shutdown = pin!(shutdown);
+
+    loop {
+        let (io, peer) = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = &mut shutdown => break,
+        };
+        let registration = connections.register(
+            ConnectionKind::Http,
+            "http/1.1",
+            peer.addr().to_string(),
+            local.to_string(),
+        );
+        tokio::spawn(connection(
+            Tracked::new(io, registration),
+            peer,
+            app.clone(),
+            builder.clone(),
+            idle,
+            stop.clone(),
+            open_rx.clone(),
+        ));
+    }
+
+    drop(listener);
+    drop(open_rx);
+    stop.cancel();
+    open_tx.closed().await;
+    Ok(())
+}
+
+/// The connection builder for `config`.
+fn builder(config: &HttpConfig) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder.http1().keep_alive(config.keep_alive);
+    if !config.http2 {
+        return builder.http1_only();
+    }
+    let mut http2 = builder.http2();
+    http2
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.http2_max_streams.max(1));
+    if config.http2_keepalive_secs > 0 {
+        let every = Duration::from_secs(config.http2_keepalive_secs);
+        http2.keep_alive_interval(every).keep_alive_timeout(every);
+    }
+    builder
+}
+
+/// Serve one connection until it ends, goes idle or the listener stops.
+async fn connection<I, P>(
+    io: Tracked<I>,
+    peer: P,
+    app: Router,
+    builder: Arc<Builder<TokioExecutor>>,
+    idle: Option<Duration>,
+    stop: CancellationToken,
+    _open: watch::Receiver<()>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    P: PeerInfo,
+{
+    let activity = io.connection().clone();
+    let tracked = activity.clone();
+    let service = tower::service_fn(move |mut req: Request<Incoming>| {
+        peer.insert_into(req.extensions_mut());
+        req.extensions_mut().insert(tracked.clone());
+        request(tracked.clone(), app.clone(), req.map(Body::new))
+    });
+    let io = TokioIo::new(io);
+    let mut conn =
+        pin!(builder.serve_connection_with_upgrades(io, TowerToHyperService::new(service)));
+
+    let mut closing = false;
+    loop {
+        let quiet = async {
+            match idle {
+                Some(idle) => tokio::time::sleep_until(activity.last_activity() + idle).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = conn.as_mut() => {
+                if let Err(e) = result {
+                    trace!("connection ended with an error: {e}");
+                }
+                break;
+            }
+            () = stop.cancelled(), if !closing => {
+                closing = true;
+                conn.as_mut().graceful_shutdown();
+            }
+            () = quiet, if !closing => {
+                // Traffic may have moved the deadline while we slept.
+                if idle.is_some_and(|idle| activity.last_activity() + idle <= Instant::now()) {
+                    trace!("closing idle connection");
+                    closing = true;
+                    conn.as_mut().graceful_shutdown();
+                }
+            }
+        }
+    }
+}
+
+/// Serve one request, noting on `connection` what it turned out to carry.
+async fn request(
+    connection: Arc<Connection>,
+    app: Router,
+    req: Request<Body>,
+) -> Result<axum::response::Response, Infallible> {
+    let _in_flight = connection.begin_request();
+    if req.version() == Version::HTTP_2 {
+        connection.set_protocol("h2");
+    }
+    let tunnel = req.uri().path() == "/api/tunnel/register";
+    let response = app.oneshot(req).await?;
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        connection.set_protocol("ws");
+        connection.set_kind(if tunnel {
+            ConnectionKind::Tunnel
+        } else {
+            ConnectionKind::Ws
+        });
+    } else if response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"))
+    {
+        connection.set_kind(ConnectionKind::Sse);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn keep_alive_reuses_and_idle_timeout_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/peer",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.ip().to_string() }),
+        );
+        let config = HttpConfig {
+            idle_timeout_secs: 1,
+            ..HttpConfig::default()
+        };
+        let stop = CancellationToken::new();
+        let connections = Arc::new(Connections::new());
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            config,
+            connections.clone(),
+            stop.clone().cancelled_owned(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET /peer HTTP/1.1\r\nhost: x\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = [0u8; 512];
+            let n = stream.read(&mut buf).await.unwrap();
+            let response = String::from_utf8_lossy(&buf[..n]);
+            assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+            assert!(response.ends_with("127.0.0.1"), "{response}");
+        }
+        let listed = connections.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].requests, 2);
+
+        // Left alone, the connection is closed after the idle timeout.
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0))), "{read:?}");
+
+        stop.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,1456 @@
+//! Embeddable server setup.
+//!
+//! [`ServerBuilder`] does everything `sctl serve` does between loading the
+//! config and binding sockets: journal recovery, shared state, the tunnel
+//! relay or client, the comms provider, infra monitoring and the periodic
+//! sweeps. [`ServerBuilder::build`] returns a [`Server`] holding the state,
+//! the background tasks it started and a router per listener; the caller
+//! binds and serves those however it likes, then calls [`Server::shutdown`].
+//!
+//! The `sctl` binary is one caller. Crates embedding sctl are the other:
+//! they add routes and extensions, and switch off subsystems they run
+//! themselves.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Extension, Router,
+};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
+
+use crate::activity::{ActivityLog, ActivitySink, ExecResultsCache};
+use crate::activity_journal::ActivityJournal;
+use crate::ai_guard::AiGuard;
+use crate::auth::{ApiKey, AuthExempt, NamedKeys};
+#[cfg(feature = "comms")]
+use crate::comms;
+use crate::config::{Config, ListenerConfig, RouteGroup};
+use crate::exec_spill::ExecSpill;
+use crate::extensions::{Extensions, ServerExtension};
+use crate::flight_recorder::FlightRecorder;
+use crate::gawdxfer::manager::TransferManager;
+use crate::gawdxfer::types::TransferConfig;
+use crate::health_history::{self, HealthHistory};
+use crate::hooks::Hooks;
+use crate::log_forward::LogForwarder;
+use crate::plugins::Plugins;
+use crate::quiet_hours::QuietHours;
+use crate::sessions::{self, SessionManager};
+use crate::shell::cgroup::Cgroups;
+use crate::shell::policy::Policy;
+use crate::startup::StartupProfile;
+use crate::state::{AppState, TunnelStats};
+use crate::tunnel::relay::RelayState;
+use crate::twin::{self, Twin};
+use crate::{infra, routes, tunnel, ws};
+
+/// Configures and starts the server's state and background work.
+#[allow(clippy::struct_excessive_bools)]
+pub struct ServerBuilder {
+    config: Config,
+    config_path: Option<PathBuf>,
+    startup: Arc<StartupProfile>,
+    log_forwarder: Option<Arc<LogForwarder>>,
+    extensions: Extensions,
+    routes: Router<AppState>,
+    relay_fallback: Option<Router>,
+    activity_sink: Option<Arc<dyn ActivitySink>>,
+    tunnel: bool,
+    transfers: bool,
+    infra: bool,
+    comms: bool,
+}
+
+impl ServerBuilder {
+    /// A builder with every subsystem the config enables switched on.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            config_path: None,
+            startup: Arc::new(StartupProfile::new()),
+            log_forwarder: None,
+            extensions: Extensions::new(),
+            routes: Router::new(),
+            relay_fallback: None,
+            activity_sink: None,
+            tunnel: true,
+            transfers: true,
+            infra: true,
+            comms: true,
+        }
+    }
+
+    /// The file the config was loaded from. `POST /api/auth/rotate` writes
+    /// the new key back to it; without one, rotations last until restart.
+    #[must_use]
+    pub fn config_path(mut self, path: Option<PathBuf>) -> Self {
+        self.config_path = path;
+        self
+    }
+
+    /// Record startup phases into `startup` (reported in `/api/info`)
+    /// instead of a fresh profile.
+    #[must_use]
+    pub fn startup(mut self, startup: Arc<StartupProfile>) -> Self {
+        self.startup = startup;
+        self
+    }
+
+    /// Use a forwarder whose tracing layer the caller already installed.
+    /// Its tasks are started by [`build`](Self::build).
+    #[must_use]
+    pub fn log_forwarder(mut self, forwarder: Arc<LogForwarder>) -> Self {
+        self.log_forwarder = Some(forwarder);
+        self
+    }
+
+    /// Register an extension (routes and message handlers).
+    #[must_use]
+    pub fn extension(mut self, ext: impl ServerExtension) -> Self {
+        self.extensions = self.extensions.with(ext);
+        self
+    }
+
+    /// Extra authenticated API routes.
+    #[must_use]
+    pub fn routes(mut self, routes: Router<AppState>) -> Self {
+        self.routes = self.routes.merge(routes);
+        self
+    }
+
+    /// Router whose fallback handles unmatched paths on listeners that
+    /// expose the relay (the binary proxies the sctlin web UI here).
+    #[must_use]
+    pub fn relay_fallback(mut self, fallback: Router) -> Self {
+        self.relay_fallback = Some(fallback);
+        self
+    }
+
+    /// Also hand every activity entry to `sink`.
+    #[must_use]
+    pub fn activity_sink(mut self, sink: Arc<dyn ActivitySink>) -> Self {
+        self.activity_sink = Some(sink);
+        self
+    }
+
+    /// Run the tunnel relay or client from `[tunnel]` (default true).
+    #[must_use]
+    pub fn tunnel(mut self, enabled: bool) -> Self {
+        self.tunnel = enabled;
+        self
+    }
+
+    /// Serve gawdxfer chunked transfers under `/api/stp` (default true).
+    #[must_use]
+    pub fn transfers(mut self, enabled: bool) -> Self {
+        self.transfers = enabled;
+        self
+    }
+
+    /// Keep infra monitoring state and resume its monitor (default true).
+    /// When off, `/api/infra/*` answers as if no config had been pushed.
+    #[must_use]
+    pub fn infra(mut self, enabled: bool) -> Self {
+        self.infra = enabled;
+        self
+    }
+
+    /// Start the comms provider from `[comms]` (default true).
+    #[must_use]
+    pub fn comms(mut self, enabled: bool) -> Self {
+        self.comms = enabled;
+        self
+    }
+
+    /// Recover journals, build the state and routers, and start background
+    /// tasks. The config is assumed to have passed [`Config::validate`].
+    pub async fn build(self) -> Server {
+        let Self {
+            mut config,
+            config_path,
+            startup,
+            log_forwarder,
+            extensions,
+            routes: extra_routes,
+            relay_fallback,
+            activity_sink,
+            tunnel: tunnel_enabled,
+            transfers,
+            infra: infra_enabled,
+            comms: comms_enabled,
+        } = self;
+
+        // Honor safe-mode flag if the supervisor wrote one (crash-loop). When set
+        // we skip every optional subsystem (modem, GPS, LTE, watchdog, infra) and
+        // keep only the management plane (HTTP + tunnel + sessions) live so an
+        // operator can reach the box, inspect logs, and clear the flag.
+        let safe_mode_flag_path = Path::new(&config.server.data_dir).join("safe_mode.flag");
+        let safe_mode_active = safe_mode_flag_path.exists();
+        if safe_mode_active {
+            warn!(
+                "==== SAFE MODE ACTIVE ==== modem/GPS/LTE/watchdog/infra subsystems will be skipped. \
+                 Flag: {} — clear via DELETE /api/safe_mode/flag (auth required).",
+                safe_mode_flag_path.display()
+            );
+        }
+
+        if let Some(tc) = &config.tunnel {
+            if !tc.relay && tc.heartbeat_interval_secs > 15 {
+                warn!(
+                    configured_secs = tc.heartbeat_interval_secs,
+                    effective_secs = config.effective_client_heartbeat_interval_secs(),
+                    "Tunnel client heartbeat interval too high for LTE/CGNAT; clamping to safe keepalive interval"
+                );
+            }
+        }
+
+        // A primary relay that enrolled this device issued its API key.
+        if config
+            .tunnel
+            .as_ref()
+            .is_some_and(|tc| !tc.relay && tc.url.is_some())
+        {
+            if let Some(api_key) =
+                tunnel::credentials::Credentials::load(&config.server.data_dir).api_key
+            {
+                info!("Using API key issued by the tunnel relay");
+                config.auth.api_key = api_key;
+            }
+        }
+
+        if config.auth.api_key == "change-me" {
+            warn!("Using default API key — set SCTL_API_KEY or update config");
+        }
+        for key in &config.auth.keys {
+            for scope in key
+                .scopes
+                .iter()
+                .filter(|s| !crate::auth::SCOPES.contains(&s.as_str()))
+            {
+                warn!("auth.keys '{}': unknown scope '{scope}' ignored", key.name);
+            }
+        }
+
+        let journal_enabled = config.server.journal_enabled;
+        let data_dir = config.server.data_dir.clone();
+        let journal_max_age_hours = config.server.journal_max_age_hours;
+
+        let recording_dir = config.server.recording_dir.as_ref().map_or_else(
+            || Path::new(&data_dir).join("recordings"),
+            std::path::PathBuf::from,
+        );
+        let session_manager = if journal_enabled {
+            info!("Journaling enabled, data_dir: {data_dir}");
+            SessionManager::with_journal(
+                config.server.max_sessions,
+                config.server.session_buffer_size,
+                &data_dir,
+            )
+        } else {
+            SessionManager::new(
+                config.server.max_sessions,
+                config.server.session_buffer_size,
+            )
+        }
+        .with_recording_dir(recording_dir)
+        .with_default_encoding(config.server.session_encoding);
+        let session_manager = match config.session_limits.as_ref().map(Cgroups::open) {
+            Some(Ok(cgroups)) => session_manager.with_cgroups(Arc::new(cgroups)),
+            Some(Err(e)) => {
+                warn!("Session limits disabled: {e}");
+                session_manager
+            }
+            None => session_manager,
+        };
+
+        // Recover archived sessions from journal and clean up orphans
+        if journal_enabled {
+            // Kill any shell processes orphaned by a previous crash
+            let phase_started = Instant::now();
+            let killed = sessions::journal::kill_orphaned_processes(Path::new(&data_dir)).await;
+            startup.record(
+                "orphan_kill",
+                phase_started,
+                (killed > 0).then(|| format!("{killed} killed")),
+            );
+            // Reload output history from journals
+            let phase_started = Instant::now();
+            let scan = session_manager
+                .recover_from_journal(Path::new(&data_dir))
+                .await;
+            let detail = if scan.quarantined > 0 {
+                format!("{} journals, {} quarantined", scan.read, scan.quarantined)
+            } else {
+                format!("{} journals", scan.read)
+            };
+            startup.record("journal_recovery", phase_started, Some(detail));
+            // Delete stale journal files
+            sessions::journal::cleanup_old_journals(Path::new(&data_dir), journal_max_age_hours)
+                .await;
+        }
+
+        let (session_events, _) = broadcast::channel(256);
+        let mut activity_log = ActivityLog::new(
+            config.server.activity_log_max_entries,
+            session_events.clone(),
+        );
+        if let Some(sink) = activity_sink {
+            activity_log = activity_log.with_sink(sink);
+        }
+        if let Some(jc) = config.activity_journal.clone() {
+            activity_log = activity_log.with_journal(ActivityJournal::open(jc, &data_dir));
+        }
+        let hooks = config
+            .hooks
+            .clone()
+            .map(|hc| Arc::new(Hooks::load(hc, &config.device.serial)));
+        if let Some(hooks) = &hooks {
+            activity_log = activity_log.with_hooks(hooks.clone());
+        }
+        let activity_log = Arc::new(activity_log);
+        let policy = Policy::new(&config.policy).unwrap_or_else(|e| {
+            tracing::error!("Invalid [policy], refusing all commands: {e}");
+            Policy::deny_all()
+        });
+
+        let mut exec_results_cache = ExecResultsCache::new(config.server.exec_result_cache_size);
+        if let Some(ec) = config.exec_results.clone() {
+            exec_results_cache =
+                exec_results_cache.with_spill(ExecSpill::open(ec, &data_dir, activity_log.total()));
+        }
+        let exec_results_cache = Arc::new(exec_results_cache);
+
+        let transfer_config = TransferConfig::new(
+            config.server.max_concurrent_transfers,
+            config.server.transfer_chunk_size,
+            config.server.transfer_max_file_size,
+            config.server.transfer_stale_timeout_secs,
+        )
+        .with_rate_limits(
+            config.server.transfer_rate_limit_bps,
+            config.server.transfer_global_rate_limit_bps,
+        );
+        let quiet_hours = config.quiet_hours.clone().and_then(|qc| {
+            QuietHours::new(qc)
+                .map(Arc::new)
+                .map_err(|e| warn!("Quiet hours disabled: {e}"))
+                .ok()
+        });
+        let mut transfer_manager = TransferManager::new(
+            transfer_config,
+            session_events.clone(),
+            activity_log.clone(),
+        );
+        if let Some(quiet) = &quiet_hours {
+            transfer_manager = transfer_manager.with_quiet_hours(quiet.clone());
+        }
+        let transfer_manager = Arc::new(transfer_manager);
+
+        if let Some(gc) = config.gps.as_ref() {
+            info!("GPS tracking enabled (poll: {}s)", gc.poll_interval_secs);
+        }
+        if let Some(lc) = config.lte.as_ref() {
+            info!(
+                "LTE monitoring enabled (poll: {}s, interface: {})",
+                lc.poll_interval_secs, lc.interface
+            );
+        }
+
+        // Flight recorder: dumps the previous run first if it ended uncleanly
+        let flight_recorder = config
+            .flight_recorder
+            .clone()
+            .map(|fc| FlightRecorder::open(fc, &data_dir, &config.device.serial));
+
+        let mqtt = config
+            .mqtt
+            .clone()
+            .map(|mc| crate::mqtt::Bridge::new(mc, &config.device.serial));
+        let snmp = config.snmp.clone().map(crate::snmp::Agent::new);
+        let firmware = config.firmware.clone().map(|fc| {
+            Arc::new(crate::firmware::Firmware::open(
+                fc,
+                &data_dir,
+                session_events.clone(),
+            ))
+        });
+
+        // Tunnel event persistence: load previous events from disk
+        let events_path = Path::new(&data_dir).join("tunnel_events.json");
+        let mut tun_stats = TunnelStats::new();
+        tun_stats.events = tokio::sync::Mutex::new(TunnelStats::load_events(&events_path));
+        tun_stats.events_path = Some(events_path);
+        tun_stats.flight_recorder = flight_recorder.clone();
+
+        // Health history: load previous transitions and record this start
+        let health = HealthHistory::load(health_history::history_path(&data_dir));
+        health.record_start().await;
+
+        // ─── Infra monitoring state ───────────────────────────────────
+        let infra_state = infra_enabled.then(|| {
+            let mut is = infra::InfraState::new(&config.server.data_dir);
+            is.load_config();
+            Arc::new(tokio::sync::Mutex::new(is))
+        });
+
+        let ai_guard = Arc::new(AiGuard::new(
+            config.ai.clone(),
+            &data_dir,
+            session_events.clone(),
+        ));
+
+        let plugins = Arc::new(Plugins::new(
+            config.plugins.clone(),
+            &data_dir,
+            &config.device.serial,
+        ));
+
+        let mut state = AppState {
+            session_manager,
+            api_keys: Arc::new(crate::auth::KeyRing::new(config.auth.api_key.clone())),
+            config: Arc::new(config),
+            config_path,
+            start_time: Instant::now(),
+            session_events,
+            activity_log,
+            exec_results_cache,
+            exec_confirms: Arc::default(),
+            tunnel_stats: Arc::new(tun_stats),
+            health_history: Arc::new(health),
+            startup,
+            transfer_manager,
+            quiet_hours,
+            sse_connections: Arc::new(AtomicU32::new(0)),
+            connections: Arc::default(),
+            saturation: Arc::default(),
+            comms_client: None,
+            comms_state: None,
+            comms_poll_notify: None,
+            gps_track: None,
+            lte_history: None,
+            relay_history: None,
+            device_snapshots: None,
+            relay_state: None,
+            infra_state: infra_state.clone(),
+            offline_spool: None,
+            log_forwarder: log_forwarder.clone(),
+            mqtt,
+            snmp,
+            firmware,
+            ai_guard,
+            metrics: Arc::default(),
+            flight_recorder,
+            extensions: Arc::new(extensions),
+            plugins,
+            hooks,
+            policy: Arc::new(policy),
+            twin: Arc::new(Twin::load(&data_dir)),
+            relay_requests: Arc::new(crate::tunnel::client::RelayRequests::new()),
+        };
+
+        let mut api = api_routes();
+        if transfers {
+            api = api.merge(stp_routes());
+        }
+        #[cfg(feature = "comms")]
+        {
+            api = api.merge(comms_routes());
+        }
+        let authed_routes = api
+            .merge(extra_routes)
+            .merge(state.extensions.routes())
+            .layer(middleware::from_fn(crate::deadline::propagate))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                crate::ai_guard::enforce,
+            ))
+            .layer(middleware::from_fn(crate::auth::require_api_key));
+
+        // Tunnel: create relay state early so relay_history is set before .with_state() clones
+        let tunnel_config = state.config.tunnel.clone().filter(|_| tunnel_enabled);
+        let mut relay_state_opt: Option<RelayState> = None;
+        if let Some(ref tc) = tunnel_config {
+            if tc.relay {
+                info!("Tunnel relay mode enabled");
+                let relay_state = RelayState::new(
+                    tc.tunnel_key.clone(),
+                    tc.heartbeat_timeout_secs,
+                    tc.tunnel_proxy_timeout_secs,
+                    Some(&data_dir),
+                )
+                .with_enrollment(tc.enrollment_token.clone(), tc.require_enrollment)
+                .with_expose_rate_limit(tc.expose_rate_limit_bps)
+                .with_identity_enforcement(tc.enforce_identity);
+                // Seed connection history from journald (survives restarts)
+                relay_state.history.seed_from_journal().await;
+                state.relay_history = Some(relay_state.history.clone());
+                state.device_snapshots = Some(relay_state.device_snapshots.clone());
+                state.relay_state = Some(relay_state.clone());
+                relay_state_opt = Some(relay_state);
+            } else if tc.url.is_some() && tc.offline_spool_max_entries > 0 {
+                state.offline_spool = Some(Arc::new(tunnel::spool::OfflineSpool::open(
+                    &data_dir,
+                    tc.offline_spool_max_entries,
+                )));
+            }
+        }
+
+        let mut tasks = BackgroundTasks::default();
+        #[cfg(not(feature = "comms"))]
+        if comms_enabled && state.config.effective_comms_config().is_some() {
+            warn!("[comms] is configured but this build has no `comms` feature; GPS/LTE disabled");
+        }
+        #[cfg(feature = "comms")]
+        if safe_mode_active {
+            info!("Comms provider skipped in safe mode");
+        } else if let Some(comms_cfg) = state
+            .config
+            .effective_comms_config()
+            .filter(|_| comms_enabled)
+        {
+            info!(
+                "Starting comms provider '{}' via {}",
+                comms_cfg.provider,
+                comms_cfg.effective_command()
+            );
+            match comms::start_provider(&state.config, &comms_cfg).await {
+                Ok((client, comms_snapshot)) => {
+                    let comms_state = Arc::new(tokio::sync::Mutex::new(comms_snapshot));
+                    let notify = Arc::new(tokio::sync::Notify::new());
+                    tasks.push(
+                        "comms_poller",
+                        comms::spawn_poller(
+                            client.clone(),
+                            comms_state.clone(),
+                            state.config.gps.is_some(),
+                            state
+                                .config
+                                .gps
+                                .as_ref()
+                                .map_or(30, |gc| gc.poll_interval_secs),
+                            state.config.lte.is_some(),
+                            state
+                                .config
+                                .lte
+                                .as_ref()
+                                .map_or(60, |lc| lc.poll_interval_secs),
+                            state.tunnel_stats.clone(),
+                            notify.clone(),
+                        ),
+                    );
+                    if let Some(sc) = state.config.sms_commands.clone() {
+                        tasks.push(
+                            "sms_commands",
+                            crate::sms_commands::spawn(state.clone(), client.clone(), sc),
+                        );
+                    }
+                    if let Some(gc) = state
+                        .config
+                        .gps
+                        .as_ref()
+                        .filter(|gc| gc.track_interval_secs > 0)
+                    {
+                        let track = Arc::new(crate::gps_track::GpsTrack::open(
+                            &state.config.server.data_dir,
+                            gc.track_max_points,
+                        ));
+                        tasks.push(
+                            "gps_track",
+                            crate::gps_track::spawn_sampler(
+                                track.clone(),
+                                comms_state.clone(),
+                                state.session_events.clone(),
+                                gc.track_interval_secs,
+                            ),
+                        );
+                        state.gps_track = Some(track);
+                    }
+                    if let Some(lc) = state
+                        .config
+                        .lte
+                        .clone()
+                        .filter(|lc| lc.history_interval_secs > 0)
+                    {
+                        let history =
+                            Arc::new(crate::lte_history::LteHistory::new(lc.history_size));
+                        tasks.push(
+                            "lte_history",
+                            crate::lte_history::spawn_collector(
+                                history.clone(),
+                                lc,
+                                comms_state.clone(),
+                                state.tunnel_stats.clone(),
+                                state.session_events.clone(),
+                            ),
+                        );
+                        state.lte_history = Some(history);
+                    }
+                    state.comms_client = Some(client);
+                    state.comms_state = Some(comms_state);
+                    state.comms_poll_notify = Some(notify);
+                }
+                Err(err) => {
+                    warn!(
+                        "Comms provider '{}' unavailable: {err}. Management plane unaffected.",
+                        comms_cfg.provider
+                    );
+                    state.comms_state = Some(Arc::new(tokio::sync::Mutex::new(
+                        comms::CommsState::new(comms_cfg.provider),
+                    )));
+                }
+            }
+        }
+
+        // Route groups, exposed per listener (see `[[server.listeners]]`)
+        let health_routes = Router::new()
+            .route("/api/health", get(routes::health::health))
+            .with_state(state.clone());
+        // WebDAV checks the key itself: file browsers only send Basic auth
+        let api_routes = authed_routes
+            .merge(crate::dav::router())
+            .with_state(state.clone());
+        let ws_routes = Router::new()
+            .route("/api/ws", get(ws::ws_upgrade))
+            .route("/api/sftp", get(routes::sftp::sftp_upgrade))
+            .with_state(state.clone());
+        let relay_routes = relay_state_opt
+            .as_ref()
+            .map(|relay_state| tunnel::relay::relay_router(relay_state.clone()));
+
+        // Tunnel: spawn client if configured, with panic-recovery supervisor.
+        // If the tunnel task panics it will be restarted after 5s. A normal return
+        // (e.g. permanent auth error) stops the supervisor loop.
+        if let Some(tc) = tunnel_config.filter(|tc| tc.url.is_some() && !tc.relay) {
+            info!(
+                "Tunnel client mode enabled, will connect to {}",
+                tc.relay_urls().join(", ")
+            );
+            let tunnel_state = state.clone();
+            tasks.push(
+                "tunnel_client",
+                tokio::spawn(async move {
+                    loop {
+                        let handle = tunnel::client::spawn(tunnel_state.clone(), tc.clone());
+                        match handle.await {
+                            Ok(()) => {
+                                // Normal return — tunnel client decided to stop (e.g. permanent auth error)
+                                info!("Tunnel client exited normally, not restarting");
+                                break;
+                            }
+                            Err(e) => {
+                                // JoinError means panic — restart after delay
+                                tracing::error!("Tunnel client panicked: {e}, restarting in 5s");
+                                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                            }
+                        }
+                    }
+                }),
+            );
+        }
+
+        // Start infra monitor if config was loaded from disk (skipped in safe mode)
+        if let Some(infra_state) = infra_state.filter(|_| !safe_mode_active) {
+            let mut guard = infra_state.lock().await;
+            if let Some(ref cfg) = guard.config {
+                info!(
+                    "Infra: resuming monitoring with {} targets (config v{})",
+                    cfg.targets.len(),
+                    cfg.version
+                );
+                let handle = infra::monitor::spawn_monitor(
+                    infra_state.clone(),
+                    cfg.clone(),
+                    state.session_events.clone(),
+                );
+                guard.monitor_handle = Some(handle);
+            }
+        }
+
+        // Periodic sweep: clean up sessions whose process has exited + stale transfers
+        let mgr = state.session_manager.clone();
+        let sweep_tx = state.session_events.clone();
+        let sweep_transfers = transfers.then(|| state.transfer_manager.clone());
+        tasks.push(
+            "sweep",
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
+                loop {
+                    interval.tick().await;
+                    let events = mgr.sweep().await;
+                    for event in events {
+                        match event {
+                            sessions::SweepEvent::Destroyed(session_id, reason) => {
+                                let _ = sweep_tx.send(serde_json::json!({
+                                    "type": "session.destroyed",
+                                    "session_id": session_id,
+                                    "reason": reason,
+                                }));
+                            }
+                            sessions::SweepEvent::AiAutoCleared(session_id) => {
+                                let _ = sweep_tx.send(serde_json::json!({
+                                    "type": "session.ai_status_changed",
+                                    "session_id": session_id,
+                                    "working": false,
+                                }));
+                            }
+                        }
+                    }
+                    // Sweep stale gawdxfer transfers
+                    if let Some(tm) = &sweep_transfers {
+                        tm.sweep_stale().await;
+                    }
+                }
+            }),
+        );
+
+        if let Some(rs) = relay_state_opt.clone() {
+            // Tunnel relay: periodic sweep to evict dead devices
+            let sweep_rs = rs.clone();
+            tasks.push(
+                "relay_sweep",
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
+                    loop {
+                        interval.tick().await;
+                        sweep_rs.sweep_dead_devices().await;
+                    }
+                }),
+            );
+            // Tunnel relay: periodic snapshot persistence (60s, debounced via dirty flag)
+            tasks.push(
+                "relay_snapshots",
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+                    loop {
+                        interval.tick().await;
+                        rs.save_snapshots().await;
+                    }
+                }),
+            );
+        }
+
+        // Tunnel events: periodic persistence (60s, debounced via dirty flag)
+        let flush_stats = state.tunnel_stats.clone();
+        tasks.push(
+            "tunnel_events_flush",
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+                loop {
+                    interval.tick().await;
+                    flush_stats.save_events().await;
+                }
+            }),
+        );
+
+        // Health history: disk/OOM polling and periodic persistence
+        tasks.push(
+            "health_monitor",
+            health_history::spawn_monitor(state.clone()),
+        );
+
+        // Device twin: periodic desired-state reconciliation
+        tasks.push("twin", twin::spawn(state.clone()));
+
+        // Remote log forwarding: activity subscriber + collector writer
+        if let Some(forwarder) = &log_forwarder {
+            for task in forwarder.spawn(&state.session_events) {
+                tasks.push("log_forward", task);
+            }
+        }
+
+        // MQTT bridge: activity subscriber, heartbeat/metrics sampler, broker connection
+        if let Some(bridge) = &state.mqtt {
+            for task in bridge.spawn(&state) {
+                tasks.push("mqtt", task);
+            }
+        }
+
+        // SNMP agent: UDP listener
+        if let Some(agent) = &state.snmp {
+            tasks.push("snmp", agent.spawn(&state));
+        }
+
+        // Activity journal: segment writer
+        if let Some(journal) = state.activity_log.journal() {
+            tasks.push("activity_journal", journal.spawn());
+        }
+
+        // Flight recorder: event subscriber, session/system sampler, segment writer
+        if let Some(recorder) = &state.flight_recorder {
+            for task in recorder.spawn(&state) {
+                tasks.push("flight_recorder", task);
+            }
+        }
+
+        // Tunnel client: spool lifecycle events to disk while the relay is unreachable
+        if let Some(spool) = state.offline_spool.clone() {
+            tasks.push(
+                "offline_spool",
+                tunnel::spool::spawn_recorder(state.clone(), spool),
+            );
+        }
+
+        // Firmware: settle the last install against this boot, auto-commit
+        // (after the spool subscribes, so a rollback is spooled too)
+        if let Some(firmware) = &state.firmware {
+            tasks.push("firmware", firmware.spawn());
+        }
+
+        Server {
+            state,
+            health_routes,
+            api_routes,
+            ws_routes,
+            relay_routes,
+            relay_fallback,
+            relay_state: relay_state_opt,
+            tasks,
+        }
+    }
+}
+
+/// A built server: shared state, running background tasks and routers.
+pub struct Server {
+    pub state: AppState,
+    health_routes: Router,
+    api_routes: Router,
+    ws_routes: Router,
+    relay_routes: Option<Router>,
+    relay_fallback: Option<Router>,
+    relay_state: Option<RelayState>,
+    tasks: BackgroundTasks,
+}
+
+impl Server {
+    /// Every route group behind the API key, for serving on one socket.
+    pub fn router(&self) -> Router {
+        self.app(true, |_| true)
+    }
+
+    /// The router for one of `[[server.listeners]]`.
+    pub fn listener_router(&self, lc: &ListenerConfig) -> Router {
+        self.app(lc.auth, |group| lc.exposes(group))
+    }
+
+    fn app(&self, auth: bool, exposes: impl Fn(RouteGroup) -> bool) -> Router {
+        let mut app = Router::new();
+        if exposes(RouteGroup::Health) {
+            app = app.merge(self.health_routes.clone());
+        }
+        if exposes(RouteGroup::Api) {
+            app = app.merge(self.api_routes.clone());
+        }
+        if exposes(RouteGroup::Ws) {
+            app = app.merge(self.ws_routes.clone());
+        }
+        app = app
+            .layer(Extension(ApiKey(self.state.api_keys.clone())))
+            .layer(Extension(NamedKeys(
+                self.state.config.auth.keys.clone().into(),
+            )));
+
+        // Tunnel: add relay routes if configured (before global layers so CORS/tracing apply)
+        if let Some(relay_routes) = self
+            .relay_routes
+            .as_ref()
+            .filter(|_| exposes(RouteGroup::Relay))
+        {
+            app = app.merge(relay_routes.clone());
+            if let Some(fallback) = &self.relay_fallback {
+                app = app.merge(fallback.clone());
+            }
+        }
+        if !auth {
+            app = app.layer(Extension(AuthExempt));
+        }
+
+        // GUARD: .layer() only applies to routes merged BEFORE the call.
+        // The limit sits inside CORS and MessagePack so a browser or msgpack
+        // client can read its 503.
+        app.layer(middleware::from_fn_with_state(
+            crate::limit::RequestLimit::new(&self.state),
+            crate::limit::enforce,
+        ))
+        .layer(cors_layer())
+        .layer(middleware::from_fn(crate::dav::answer_options))
+        .layer(middleware::from_fn(crate::msgpack::negotiate))
+        .layer(TraceLayer::new_for_http())
+    }
+
+    /// Background tasks started by [`ServerBuilder::build`].
+    pub fn tasks(&self) -> &BackgroundTasks {
+        &self.tasks
+    }
+
+    /// Stop background work and persist state. Call after the listeners
+    /// have stopped.
+    pub async fn shutdown(mut self) {
+        let comms_task = self.tasks.take("comms_poller");
+        self.tasks.abort_all();
+
+        // Tunnel relay: notify devices, drain state, and do a final snapshot save
+        if let Some(ref rs) = self.relay_state {
+            info!("Notifying tunnel devices of relay shutdown...");
+            rs.broadcast_to_devices(serde_json::json!({
+                "type": "tunnel.relay_shutdown",
+            }))
+            .await;
+            rs.drain_all().await;
+            if rs.save_snapshots().await {
+                info!("Saved device snapshots to disk");
+            }
+        }
+
+        let state = &self.state;
+        if let Some(ref client) = state.comms_client {
+            let _ = client
+                .call(
+                    sctl_comms_protocol::methods::LOCATION_DISABLE,
+                    serde_json::json!({}),
+                )
+                .await;
+        }
+        if let Some(task) = comms_task {
+            task.abort();
+        }
+
+        // Tunnel events: final flush
+        if state.tunnel_stats.save_events().await {
+            info!("Saved tunnel events to disk");
+        }
+
+        // Health history: record the clean stop
+        state.health_history.record_stop().await;
+        state.health_history.save().await;
+
+        state.session_manager.kill_all().await;
+
+        // Activity journal: entries not yet written
+        if let Some(journal) = state.activity_log.journal() {
+            journal.close().await;
+        }
+
+        // Flight recorder: last records and the clean-shutdown marker
+        if let Some(recorder) = &state.flight_recorder {
+            recorder.close().await;
+        }
+    }
+}
+
+/// Named handles for the long-running tasks a [`Server`] started.
+#[derive(Default)]
+pub struct BackgroundTasks {
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl BackgroundTasks {
+    fn push(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.tasks.push((name, handle));
+    }
+
+    fn take(&mut self, name: &'static str) -> Option<JoinHandle<()>> {
+        let idx = self.tasks.iter().position(|(n, _)| *n == name)?;
+        Some(self.tasks.remove(idx).1)
+    }
+
+    /// Task names, in start order (`"sweep"`, `"tunnel_client"`, ...).
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tasks.iter().map(|(name, _)| *name)
+    }
+
+    fn abort_all(&mut self) {
+        for (_, handle) in self.tasks.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+// GUARD: Headers must be listed explicitly — `allow_headers(Any)` works in
+// Chrome but Firefox rejects credentialed requests without explicit listing.
+fn cors_layer() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers([
+            axum::http::header::AUTHORIZATION,
+            axum::http::header::CONTENT_TYPE,
+            axum::http::header::ACCEPT,
+            axum::http::HeaderName::from_static("x-gx-chunk-hash"),
+            axum::http::HeaderName::from_static("x-gx-chunk-index"),
+            axum::http::HeaderName::from_static("x-gx-transfer-id"),
+            axum::http::HeaderName::from_static(crate::deadline::HEADER),
+        ])
+        .expose_headers([
+            axum::http::HeaderName::from_static("x-gx-chunk-hash"),
+            axum::http::HeaderName::from_static("x-gx-chunk-index"),
+            axum::http::HeaderName::from_static("x-gx-transfer-id"),
+        ])
+}
+
+/// The authenticated REST API, minus optional groups.
+fn api_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/info", get(routes::info::info))
+        .route("/api/info/sensors", get(routes::info::sensors))
+        .route("/api/info/identity", get(routes::info::identity))
+        .route("/api/info/diff", get(routes::snapshots::info_diff))
+        .route(
+            "/api/safe_mode/flag",
+            get(routes::safe_mode::get_flag).delete(routes::safe_mode::clear_flag),
+        )
+        .route("/api/diagnostics", get(routes::diagnostics::diagnostics))
+        .route("/api/ai", get(routes::ai::status))
+        .route("/api/ai/disable", post(routes::ai::disable))
+        .route("/api/ai/enable", post(routes::ai::enable))
+        .route("/api/flightrecorder", get(routes::flight_recorder::status))
+        .route(
+            "/api/flightrecorder/dump",
+            post(routes::flight_recorder::dump),
+        )
+        .route("/api/health/history", get(routes::health::health_history))
+        .route("/api/metrics", get(routes::metrics::metrics))
+        .route("/api/snmp", get(routes::snmp::snmp))
+        .route("/api/firmware", get(routes::firmware::status))
+        .route("/api/firmware/install", post(routes::firmware::install))
+        .route("/api/firmware/reboot", post(routes::firmware::reboot))
+        .route("/api/firmware/commit", post(routes::firmware::commit))
+        .route(
+            "/api/support-bundle",
+            get(routes::support_bundle::support_bundle_status)
+                .post(routes::support_bundle::create_support_bundle),
+        )
+        .route("/api/exec", post(routes::exec::exec))
+        .route("/api/exec/stream", post(routes::exec::exec_stream))
+        .route("/api/exec/batch", post(routes::exec::batch_exec))
+        .route("/api/exec/pending", get(routes::exec::list_pending))
+        .route(
+            "/api/exec/pending/{id}/confirm",
+            post(routes::exec::confirm_pending),
+        )
+        .route(
+            "/api/files",
+            get(routes::files::get_file)
+                .put(routes::files::put_file)
+                .delete(routes::files::delete_file),
+        )
+        .route("/api/files/raw", get(routes::files::download_file))
+        .route("/api/files/xattrs", put(routes::files::put_xattrs))
+        .route(
+            "/api/files/copy",
+            get(routes::copy::list_copies).post(routes::copy::copy_file),
+        )
+        .route("/api/files/upload", post(routes::files::upload_file))
+        .route("/api/activity", get(routes::activity::get_activity))
+        .route(
+            "/api/activity/export",
+            get(routes::activity::export_activity),
+        )
+        .route(
+            "/api/activity/{id}/result",
+            get(routes::activity::get_exec_result),
+        )
+        .route(
+            "/api/sessions",
+            get(routes::sessions::list_sessions).post(routes::sessions::start_session),
+        )
+        .route(
+            "/api/sessions/{id}",
+            delete(routes::sessions::kill_session).patch(routes::sessions::patch_session),
+        )
+        .route(
+            "/api/sessions/{id}/processes",
+            get(routes::sessions::session_processes),
+        )
+        .route(
+            "/api/sessions/{id}/signal",
+            post(routes::sessions::signal_session),
+        )
+        .route(
+            "/api/sessions/{id}/history",
+            get(routes::sessions::session_history),
+        )
+        .route(
+            "/api/sessions/{id}/journal",
+            get(routes::sessions::session_journal),
+        )
+        .route(
+            "/api/sessions/{id}/recording",
+            get(routes::sessions::session_recording),
+        )
+        .route(
+            "/api/sessions/{id}/share",
+            post(routes::sessions::share_session),
+        )
+        .route("/api/shells", get(routes::shells::list_shells))
+        .route("/api/events", get(routes::events::event_stream))
+        .route("/api/logs", get(routes::logs::get_logs))
+        .route("/api/playbooks", get(routes::playbooks::list_playbooks))
+        .route(
+            "/api/playbooks/{name}",
+            get(routes::playbooks::get_playbook)
+                .put(routes::playbooks::put_playbook)
+                .delete(routes::playbooks::delete_playbook),
+        )
+        .route(
+            "/api/playbooks/{name}/run",
+            post(routes::playbooks::run_playbook),
+        )
+        .route(
+            "/api/playbooks/{name}/runs",
+            get(routes::playbooks::list_runs),
+        )
+        .route(
+            "/api/playbooks/{name}/runs/{run_id}",
+            get(routes::playbooks::get_run),
+        )
+        .route("/api/hooks", get(routes::hooks::get_hooks))
+        .route("/api/hooks/reload", post(routes::hooks::reload_hooks))
+        .route(
+            "/api/twin",
+            get(routes::twin::get_twin).put(routes::twin::put_twin),
+        )
+        .route("/api/twin/reconcile", post(routes::twin::reconcile))
+        .route(
+            "/api/snapshots",
+            get(routes::snapshots::list_snapshots).post(routes::snapshots::create_snapshot),
+        )
+        .route(
+            "/api/snapshots/{id}",
+            get(routes::snapshots::get_snapshot).delete(routes::snapshots::delete_snapshot),
+        )
+        .route(
+            "/api/snapshots/{a}/diff/{b}",
+            get(routes::snapshots::diff_snapshots),
+        )
+        .route("/api/auth/rotate", post(routes::auth::rotate))
+        .route("/api/fetch", post(routes::fetch::fetch))
+        .route("/api/artifacts/push", post(routes::artifacts::push))
+        .route("/api/quiet-hours", get(routes::quiet_hours::status))
+        .route("/api/connections", get(routes::connections::list))
+        .route(
+            "/api/connections/{id}",
+            delete(routes::connections::disconnect),
+        )
+        .route("/api/resolve", get(routes::resolve::resolve))
+        .route("/api/tunnel/expose", post(routes::expose::expose))
+        .route("/api/tunnel/expose/{id}", delete(routes::expose::unexpose))
+        .route("/api/plugins", get(routes::plugins::list_plugins))
+        .route("/api/plugins/{name}", get(routes::plugins::get_plugin))
+        .route(
+            "/api/plugins/{name}/{command}",
+            post(routes::plugins::run_plugin),
+        )
+        .route(
+            "/api/ssh/authorized_keys",
+            get(routes::ssh::list_keys)
+                .post(routes::ssh::add_keys)
+                .delete(routes::ssh::delete_key),
+        )
+        .route("/api/firewall", get(routes::firewall::get_firewall))
+        .route(
+            "/api/firewall/apply",
+            post(routes::firewall::apply_firewall),
+        )
+        .route(
+            "/api/netman/connections",
+            get(routes::netman::list_connections),
+        )
+        .route(
+            "/api/netman/connections/{conn}/up",
+            post(routes::netman::connection_up),
+        )
+        .route(
+            "/api/netman/connections/{conn}/down",
+            post(routes::netman::connection_down),
+        )
+        .route("/api/netman/wwan", get(routes::netman::wwan))
+        .route("/api/services", get(routes::services::list_services))
+        .route(
+            "/api/services/{name}",
+            get(routes::services::service_status),
+        )
+        .route(
+            "/api/services/{name}/{action}",
+            post(routes::services::service_action),
+        )
+        .route(
+            "/api/time",
+            get(routes::time::get_time).post(routes::time::set_time),
+        )
+        .route("/api/users", get(routes::users::list_users))
+        .route("/api/users/{name}/lock", post(routes::users::lock_user))
+        .route("/api/users/{name}/unlock", post(routes::users::unlock_user))
+        .route(
+            "/api/users/{name}/reset-password",
+            post(routes::users::reset_password),
+        )
+        .route(
+            "/api/infra/config",
+            post(infra::routes::push_config).delete(infra::routes::delete_config),
+        )
+        .route("/api/infra/results", get(infra::routes::get_results))
+        .route(
+            "/api/infra/check/{target_id}",
+            post(infra::routes::check_target),
+        )
+        .route("/api/infra/discover", post(infra::discovery::discover))
+        .route(
+            "/api/infra/discover/progress",
+            get(infra::routes::discover_progress),
+        )
+        .route(
+            "/api/infra/discover/subnets",
+            get(infra::routes::discover_subnets),
+        )
+}
+
+/// GPS and LTE routes, served from the comms provider.
+#[cfg(feature = "comms")]
+fn comms_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/gps", get(routes::gps::gps))
+        .route("/api/gps/track", get(routes::gps::track))
+        .route("/api/lte", get(routes::lte::lte))
+        .route("/api/lte/history", get(routes::lte::history))
+        .route("/api/lte/bands", post(routes::lte::set_bands))
+        .route("/api/lte/scan", post(routes::lte::start_scan))
+        .route("/api/lte/speedtest", post(routes::lte::speed_test))
+        .route("/api/lte/usb_cycle", post(routes::lte::manual_usb_cycle))
+        .route(
+            "/api/lte/watchdog/history",
+            get(routes::lte::watchdog_history),
+        )
+        .route(
+            "/api/modem/sms",
+            get(routes::modem::sms_list).post(routes::modem::sms_send),
+        )
+        .route("/api/modem/sms/{index}", delete(routes::modem::sms_delete))
+}
+
+/// gawdxfer chunked transfer routes.
+fn stp_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/stp/download", post(routes::stp::init_download))
+        .route("/api/stp/upload", post(routes::stp::init_upload))
+        .route(
+            "/api/stp/chunk/{xfer}/{idx}",
+            get(routes::stp::get_chunk).post(routes::stp::post_chunk),
+        )
+        .route("/api/stp/resume/{xfer}", post(routes::stp::resume_transfer))
+        .route("/api/stp/throttle", post(routes::stp::throttle))
+        .route("/api/stp/status/{xfer}", get(routes::stp::transfer_status))
+        .route("/api/stp/transfers", get(routes::stp::list_transfers))
+        .route("/api/stp/{xfer}", delete(routes::stp::abort_transfer))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn status(app: &Router, path: &str, key: bool) -> StatusCode {
+        let mut request = testing::request("GET", path, &Value::Null);
+        if !key {
+            request.headers_mut().remove("authorization");
+        }
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn built_server_layers_custom_routes_like_the_builtin_api() {
+        let (mut config, dir) = testing::config("builder");
+        config.server.max_connections = 1;
+        config.tunnel = Some(
+            toml::from_str("url = \"ws://127.0.0.1:9/api/tunnel/register\"\ntunnel_key = \"k\"\n")
+                .unwrap(),
+        );
+        let release = Arc::new(tokio::sync::Notify::new());
+        let held = release.clone();
+        let custom = Router::new()
+            .route("/api/custom", post(|| async { "ok" }))
+            .route(
+                "/api/custom/slow",
+                get(move || async move { held.notified().await }),
+            );
+        let server = ServerBuilder::new(config)
+            .routes(custom)
+            .tunnel(false)
+            .transfers(false)
+            .build()
+            .await;
+        // What `sctl serve` does with the default listener.
+        let [listener] = &server.state.config.server.effective_listeners()[..] else {
+            panic!("one default listener");
+        };
+        let app = server.listener_router(listener);
+        let post = |client: Option<&str>| {
+            let mut request = testing::request("POST", "/api/custom", &Value::Null);
+            if let Some(client) = client {
+                request
+                    .headers_mut()
+                    .insert("x-sctl-client", client.parse().unwrap());
+            }
+            app.clone().oneshot(request)
+        };
+
+        // Auth.
+        assert_eq!(status(&app, "/api/info", true).await, StatusCode::OK);
+        let mut keyless = testing::request("POST", "/api/custom", &Value::Null);
+        keyless.headers_mut().remove("authorization");
+        let response = app.clone().oneshot(keyless).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(post(None).await.unwrap().status(), StatusCode::OK);
+
+        // AI guard.
+        server.state.ai_guard.disable(None, "rest").await;
+        let refused = post(Some("mcp")).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(testing::body_json(refused).await["code"], "AI_DISABLED");
+        assert_eq!(post(Some("cli")).await.unwrap().status(), StatusCode::OK);
+
+        // Request limit: the held request takes the only standard slot.
+        let slow = tokio::spawn(app.clone().oneshot(testing::request(
+            "GET",
+            "/api/custom/slow",
+            &Value::Null,
+        )));
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while post(None).await.unwrap().status() != StatusCode::SERVICE_UNAVAILABLE {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("limit applies to custom routes");
+        release.notify_waiters();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // Switched-off subsystems.
+        assert_eq!(
+            status(&app, "/api/stp/transfers", true).await,
+            StatusCode::NOT_FOUND
+        );
+        assert!(!server.tasks().names().any(|n| n == "tunnel_client"));
+        assert!(server.state.offline_spool.is_none());
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn keyless(listen: &str, expose: &[RouteGroup]) -> ListenerConfig {
+        ListenerConfig {
+            listen: listen.to_string(),
+            auth: false,
+            expose: expose.to_vec(),
+            tls: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn keyless_listener_serves_only_its_groups() {
+        let (mut config, dir) = testing::config("listeners");
+        config.server.listeners = vec![keyless("0.0.0.0:1337", &[RouteGroup::Health])];
+        assert!(config
+            .validate()
+            .iter()
+            .any(|e| e.contains("auth = false is only allowed on loopback")));
+        config.server.listeners.clear();
+
+        let server = ServerBuilder::new(config).build().await;
+        let local = server.listener_router(&keyless(
+            "127.0.0.1:1337",
+            &[RouteGroup::Health, RouteGroup::Api],
+        ));
+        let health_only = server.listener_router(&keyless("127.0.0.1:1338", &[RouteGroup::Health]));
+        let main = server.router();
+
+        assert_eq!(status(&local, "/api/info", false).await, StatusCode::OK);
+        assert_eq!(
+            status(&local, "/api/ws", false).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&health_only, "/api/health", false).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&health_only, "/api/info", false).await,
+            StatusCode::NOT_FOUND
+        );
+
+        // The main listener still wants the key.
+        assert_eq!(
+            status(&main, "/api/info", false).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(&main, "/api/info", true).await, StatusCode::OK);
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+/// Shared setup for tests that send requests to a built [`Server`].
+#[cfg(all(test, unix))]
+pub(crate) mod testing {
+    use std::path::PathBuf;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::Value;
+
+    use crate::config::Config;
+
+    pub(crate) const API_KEY: &str = "test-key";
+
+    /// Defaults plus [`API_KEY`] and a fresh data directory, which the caller
+    /// removes.
+    pub(crate) fn config(name: &str) -> (Config, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("sctl-server-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sctl.toml");
+        std::fs::write(
+            &path,
+            format!(
+                "[server]\ndata_dir = {:?}\n\n[auth]\napi_key = {API_KEY:?}\n",
+                dir.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        (Config::load(path.to_str()), dir)
+    }
+
+    /// `method path` with [`API_KEY`] and a JSON body (`Null` for none).
+    pub(crate) fn request(method: &str, path: &str, body: &Value) -> Request<Body> {
+        let builder = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("authorization", format!("Bearer {API_KEY}"));
+        if body.is_null() {
+            builder.body(Body::empty()).unwrap()
+        } else {
+            builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        }
+    }
+
+    pub(crate) async fn body_json(response: axum::response::Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+}
@@ -11,6 +11,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, Notify};
 
 use super::journal::JournalEntry;
+use super::recording::RecordEvent;
 
 /// Which output stream produced the data.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -38,8 +39,11 @@ pub struct OutputEntry {
     pub seq: u64,
     /// Which stream produced this entry.
     pub stream: OutputStream,
-    /// The output data (lossy UTF-8).
+    /// The output data, decoded per the session's encoding (see
+    /// [`super::decode`]).
     pub data: String,
+    /// The bytes `data` was decoded from, for `raw` encoding sessions.
+    pub raw: Option<Vec<u8>>,
     /// Unix timestamp in milliseconds when the entry was created.
     pub timestamp_ms: u64,
 }
@@ -52,6 +56,8 @@ pub struct OutputBuffer {
     notify: Arc<Notify>,
     /// Optional channel to the journal writer task.
     journal_tx: Option<mpsc::Sender<JournalEntry>>,
+    /// Optional channel to the recording writer task.
+    recording_tx: Option<mpsc::Sender<RecordEvent>>,
 }
 
 impl OutputBuffer {
@@ -63,6 +69,7 @@ impl OutputBuffer {
             max_entries,
             notify: Arc::new(Notify::new()),
             journal_tx: None,
+            recording_tx: None,
         }
     }
 
@@ -72,9 +79,36 @@ impl OutputBuffer {
         self.journal_tx = Some(tx);
     }
 
+    /// Attach a recording writer channel. Entries pushed after this call will
+    /// also be recorded.
+    pub fn set_recording(&mut self, tx: mpsc::Sender<RecordEvent>) {
+        self.recording_tx = Some(tx);
+    }
+
+    /// Whether a recording is attached.
+    pub fn is_recording(&self) -> bool {
+        self.recording_tx.is_some()
+    }
+
+    /// Record a terminal resize, if a recording is attached.
+    pub fn record_resize(&self, rows: u16, cols: u16) {
+        if let Some(ref tx) = self.recording_tx {
+            let _ = tx.try_send(RecordEvent::Resize {
+                timestamp_ms: super::journal::now_ms(),
+                rows,
+                cols,
+            });
+        }
+    }
+
     /// Push a new entry, evicting the oldest if full, and notify all waiters.
-    /// Also sends the entry to the journal if one is attached.
+    /// Also sends the entry to the journal and recording if attached.
     pub fn push(&mut self, stream: OutputStream, data: String) {
+        self.push_raw(stream, data, None);
+    }
+
+    /// [`Self::push`], keeping the original bytes alongside the text.
+    pub fn push_raw(&mut self, stream: OutputStream, data: String, raw: Option<Vec<u8>>) {
         let seq = self.next_seq;
         self.next_seq += 1;
 
@@ -91,6 +125,7 @@ impl OutputBuffer {
             seq,
             stream,
             data,
+            raw,
             timestamp_ms,
         };
 
@@ -98,6 +133,11 @@ impl OutputBuffer {
         if let Some(ref tx) = self.journal_tx {
             let _ = tx.try_send(JournalEntry::from_output_entry(&entry));
         }
+        if let Some(ref tx) = self.recording_tx {
+            if let Some(event) = RecordEvent::from_output_entry(&entry) {
+                let _ = tx.try_send(event);
+            }
+        }
 
         self.entries.push_back(entry);
         self.notify.notify_waiters();
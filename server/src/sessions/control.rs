@@ -0,0 +1,101 @@
+//! Input arbitration for sessions shared by several clients.
+//!
+//! Any number of clients may attach to a session and watch its output. By
+//! default any of them may also type into it. `session.take_control` gives one
+//! client the session's [`InputLock`]: from then on `session.stdin`,
+//! `session.exec` and `session.signal` from anyone else are refused with
+//! `INPUT_LOCKED` until the holder sends `session.release_control` or
+//! disconnects. This keeps a human and an AI agent sharing a terminal from
+//! interleaving keystrokes.
+//!
+//! Holders are identified by connection: `ws:<uuid>` for a direct WebSocket,
+//! `relay:<client_id>` for a client reaching the device through a relay.
+
+/// Who holds a session's input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputLock {
+    /// Connection id of the holder.
+    pub holder: String,
+    /// What the holder calls itself (`"alice"`, `"claude"`), for other clients
+    /// to display.
+    pub label: Option<String>,
+    /// Epoch milliseconds when control was taken.
+    pub since_ms: u64,
+}
+
+impl InputLock {
+    /// Give `lock` to `holder`. Taking a lock one already holds refreshes its
+    /// label. A lock held by someone else is only taken with `force`.
+    ///
+    /// # Errors
+    ///
+    /// Someone else holds the lock and `force` is false.
+    pub fn take(
+        lock: &mut Option<InputLock>,
+        holder: &str,
+        label: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        if let Some(current) = lock.as_ref() {
+            if current.holder != holder && !force {
+                return Err(current.held_message());
+            }
+        }
+        *lock = Some(InputLock {
+            holder: holder.to_string(),
+            label: label.map(String::from),
+            since_ms: super::journal::now_ms(),
+        });
+        Ok(())
+    }
+
+    /// Release `lock` if `holder` holds it. Returns whether it was released.
+    pub fn release(lock: &mut Option<InputLock>, holder: &str) -> bool {
+        if lock.as_ref().is_some_and(|l| l.holder == holder) {
+            *lock = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether `client` may send input under `lock`.
+    pub fn permits(lock: Option<&InputLock>, client: &str) -> bool {
+        lock.is_none_or(|l| l.holder == client)
+    }
+
+    /// The `INPUT_LOCKED` error message.
+    pub fn held_message(&self) -> String {
+        match &self.label {
+            Some(label) => format!("Session input is held by {label}"),
+            None => "Session input is held by another client".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_holder_types_until_released() {
+        let mut lock = None;
+        assert!(InputLock::permits(lock.as_ref(), "ws:a"));
+
+        InputLock::take(&mut lock, "ws:a", Some("alice"), false).unwrap();
+        assert!(InputLock::permits(lock.as_ref(), "ws:a"));
+        assert!(!InputLock::permits(lock.as_ref(), "relay:b"));
+        assert_eq!(
+            InputLock::take(&mut lock, "relay:b", None, false).unwrap_err(),
+            "Session input is held by alice"
+        );
+        assert!(!InputLock::release(&mut lock, "relay:b"));
+
+        InputLock::take(&mut lock, "relay:b", Some("claude"), true).unwrap();
+        assert!(!InputLock::permits(lock.as_ref(), "ws:a"));
+        assert_eq!(lock.as_ref().unwrap().label.as_deref(), Some("claude"));
+
+        assert!(InputLock::release(&mut lock, "relay:b"));
+        assert!(InputLock::permits(lock.as_ref(), "ws:a"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Decoding of raw session output into buffer entries.
+//!
+//! Output arrives in arbitrary 4 KiB reads, so a UTF-8 sequence can be split
+//! across two reads. [`OutputDecoder`] carries an incomplete trailing
+//! sequence over to the next read instead of turning it into U+FFFD.
+//!
+//! Output that isn't UTF-8 at all (Latin-1 or GBK from older tools) can be
+//! handled per session with an [`OutputEncoding`]:
+//!
+//! - `utf8` (default) — lossy UTF-8; invalid bytes become U+FFFD.
+//! - `latin1` — every byte maps to the code point of the same value, so
+//!   nothing is lost and the text reads correctly for ISO-8859-1 output.
+//! - `raw` — lossy UTF-8 `data` plus the exact bytes base64-encoded in
+//!   `data_b64`, for clients that decode themselves.
+//!
+//! The first time `utf8` output fails to decode, the decoder guesses a
+//! charset (see [`guess_charset`]) so the session can say which encoding to
+//! switch to.
+
+use serde::{Deserialize, Serialize};
+
+/// How a session's output bytes become entry text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+    Raw,
+}
+
+impl OutputEncoding {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf8",
+            Self::Latin1 => "latin1",
+            Self::Raw => "raw",
+        }
+    }
+
+    pub fn from_str_opt(s: &str) -> Option<Self> {
+        match s {
+            "utf8" | "utf-8" => Some(Self::Utf8),
+            "latin1" | "iso-8859-1" => Some(Self::Latin1),
+            "raw" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    /// The optional `encoding` field of a `session.start` message.
+    pub fn from_message(msg: &serde_json::Value) -> Result<Option<Self>, String> {
+        msg["encoding"]
+            .as_str()
+            .map(|e| {
+                Self::from_str_opt(e)
+                    .ok_or_else(|| format!("Unknown encoding '{e}' (utf8, latin1 or raw)"))
+            })
+            .transpose()
+    }
+}
+
+/// A session's output encoding and the charset guessed from output that
+/// didn't decode.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Charset {
+    pub encoding: OutputEncoding,
+    pub hint: Option<&'static str>,
+}
+
+/// One decoded read.
+pub struct Decoded {
+    pub text: String,
+    /// The bytes of this read, in `raw` mode.
+    pub raw: Option<Vec<u8>>,
+    /// Charset guess, set on the first read that wasn't valid UTF-8.
+    pub hint: Option<&'static str>,
+}
+
+/// Per-stream decoder state.
+#[derive(Default)]
+pub struct OutputDecoder {
+    /// Start of a UTF-8 sequence cut off by the end of the previous read.
+    pending: Vec<u8>,
+    hinted: bool,
+}
+
+impl OutputDecoder {
+    /// Decode one read. The encoding may change between calls.
+    pub fn decode(&mut self, encoding: OutputEncoding, bytes: &[u8]) -> Decoded {
+        if encoding == OutputEncoding::Latin1 {
+            let mut text: String = self.pending.drain(..).map(char::from).collect();
+            text.extend(bytes.iter().copied().map(char::from));
+            return Decoded {
+                text,
+                raw: None,
+                hint: None,
+            };
+        }
+
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(bytes);
+        let keep = incomplete_tail(&buf);
+        self.pending = buf.split_off(buf.len() - keep);
+
+        let (text, hint) = match String::from_utf8(buf) {
+            Ok(text) => (text, None),
+            Err(e) => {
+                let invalid = e.into_bytes();
+                let hint = (!self.hinted && encoding == OutputEncoding::Utf8).then(|| {
+                    self.hinted = true;
+                    guess_charset(&invalid)
+                });
+                (String::from_utf8_lossy(&invalid).into_owned(), hint)
+            }
+        };
+        Decoded {
+            text,
+            raw: (encoding == OutputEncoding::Raw).then(|| bytes.to_vec()),
+            hint,
+        }
+    }
+}
+
+/// Length of an incomplete UTF-8 sequence at the end of `buf` (0..=3).
+fn incomplete_tail(buf: &[u8]) -> usize {
+    for back in 1..=buf.len().min(3) {
+        let b = buf[buf.len() - back];
+        if b & 0xC0 == 0x80 {
+            continue; // continuation byte, keep looking for the lead
+        }
+        let needed = match b {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return 0,
+        };
+        return if needed > back { back } else { 0 };
+    }
+    0
+}
+
+/// Best guess at the charset of bytes that aren't valid UTF-8.
+///
+/// GBK encodes CJK text as pairs of a lead byte `0x81..=0xFE` and a trail
+/// byte `0x40..=0xFE`, so high bytes nearly always come in such pairs;
+/// Latin-1 text mostly has isolated high bytes between ASCII.
+pub fn guess_charset(bytes: &[u8]) -> &'static str {
+    let (mut pairs, mut singles) = (0usize, 0usize);
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] < 0x80 {
+            i += 1;
+        } else if (0x81..=0xFE).contains(&bytes[i])
+            && bytes
+                .get(i + 1)
+                .is_some_and(|&t| (0x40..=0xFE).contains(&t) && t != 0x7F)
+        {
+            pairs += 1;
+            i += 2;
+        } else {
+            singles += 1;
+            i += 1;
+        }
+    }
+    if pairs > singles {
+        "gbk"
+    } else {
+        "latin1"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_split_utf8_sequences() {
+        let mut decoder = OutputDecoder::default();
+        let bytes = "héllo €".as_bytes();
+        let (a, b) = bytes.split_at(bytes.len() - 2);
+        let first = decoder.decode(OutputEncoding::Utf8, a);
+        let second = decoder.decode(OutputEncoding::Utf8, b);
+        assert_eq!(first.text + &second.text, "héllo €");
+        assert!(first.hint.is_none() && second.hint.is_none());
+    }
+
+    #[test]
+    fn latin1_and_raw_keep_the_bytes() {
+        let bytes = b"caf\xe9";
+        let mut decoder = OutputDecoder::default();
+        assert_eq!(decoder.decode(OutputEncoding::Latin1, bytes).text, "café");
+        let raw = decoder.decode(OutputEncoding::Raw, bytes);
+        assert_eq!(raw.raw.as_deref(), Some(&bytes[..]));
+        assert!(raw.hint.is_none());
+    }
+
+    #[test]
+    fn hints_once_for_invalid_utf8() {
+        let mut decoder = OutputDecoder::default();
+        // "中文" in GBK
+        let gbk = decoder.decode(OutputEncoding::Utf8, b"\xd6\xd0\xce\xc4 ok\n");
+        assert_eq!(gbk.hint, Some("gbk"));
+        assert!(gbk.text.contains('\u{FFFD}'));
+        let again = decoder.decode(OutputEncoding::Utf8, b"\xe9t\xe9\n");
+        assert!(again.hint.is_none());
+        assert_eq!(guess_charset(b"caf\xe9 cr\xe8me"), "latin1");
+    }
+}
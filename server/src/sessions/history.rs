@@ -0,0 +1,183 @@
+//! Per-session command history.
+//!
+//! An agent re-attaching to a session wants to know what was already tried,
+//! not to replay (and re-read) the whole scrollback. [`CommandHistory`] keeps
+//! a bounded list of command lines run in the session:
+//!
+//! - **exec** entries are recorded when a command is sent with
+//!   `session.exec` (WS, tunnel). The command text is exactly what was sent.
+//! - **shell** entries come from semantic-prompt shell integration (OSC
+//!   133/633, see [`super::osc`]) for commands typed at the prompt. The text is
+//!   only known when the shell reports it (OSC 633 `E`).
+//!
+//! Exit codes and finish times are only available with shell integration: the
+//! `D` mark closes the most recent running entry. Without it, entries carry
+//! just the command and start time.
+
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use super::osc::OscEvent;
+
+/// Entries kept per session; the oldest are dropped first.
+pub const MAX_ENTRIES: usize = 500;
+
+/// One command run in a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// Increasing per session.
+    pub id: u64,
+    /// Command line, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// `"exec"` or `"shell"`.
+    pub source: &'static str,
+    /// Epoch milliseconds.
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The shell marked this command as running (OSC `C`), so a `D` mark
+    /// belongs to it.
+    #[serde(skip)]
+    running: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct CommandHistory {
+    entries: VecDeque<HistoryEntry>,
+    next_id: u64,
+    /// Command line announced by OSC 633 `E`, waiting for its `C`.
+    pending_line: Option<String>,
+}
+
+impl CommandHistory {
+    fn push(&mut self, command: Option<String>, source: &'static str, now_ms: u64) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.next_id += 1;
+        self.entries.push_back(HistoryEntry {
+            id: self.next_id,
+            command,
+            source,
+            started_at: now_ms,
+            finished_at: None,
+            exit_code: None,
+            running: false,
+        });
+    }
+
+    /// Record a command sent with `session.exec`.
+    pub fn record_exec(&mut self, command: &str, now_ms: u64) {
+        self.push(Some(command.to_string()), "exec", now_ms);
+    }
+
+    /// Apply shell-integration marks from the session's output.
+    pub fn apply(&mut self, events: &[OscEvent], now_ms: u64) {
+        for event in events {
+            match event {
+                OscEvent::CommandLine(line) => self.pending_line = Some(line.clone()),
+                OscEvent::CommandStart => {
+                    let line = self.pending_line.take();
+                    // The shell starting a command we just sent: attach to
+                    // the exec entry instead of recording it twice.
+                    match self.entries.back_mut() {
+                        Some(last)
+                            if last.source == "exec"
+                                && !last.running
+                                && last.finished_at.is_none() =>
+                        {
+                            last.running = true;
+                        }
+                        _ => {
+                            self.push(line, "shell", now_ms);
+                            if let Some(last) = self.entries.back_mut() {
+                                last.running = true;
+                            }
+                        }
+                    }
+                }
+                OscEvent::CommandEnd(code) => {
+                    if let Some(entry) = self.entries.iter_mut().rev().find(|e| e.running) {
+                        entry.running = false;
+                        entry.finished_at = Some(now_ms);
+                        entry.exit_code = *code;
+                    }
+                }
+                OscEvent::Title(_) | OscEvent::Cwd(_) => {}
+            }
+        }
+    }
+
+    /// The last `limit` entries, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<HistoryEntry> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+
+    /// Total entries recorded, including ones already dropped.
+    pub fn total(&self) -> u64 {
+        self.next_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_is_closed_by_shell_marks() {
+        let mut h = CommandHistory::default();
+        h.record_exec("make", 10);
+        h.apply(
+            &[
+                OscEvent::CommandLine("make".into()),
+                OscEvent::CommandStart,
+                OscEvent::CommandEnd(Some(2)),
+            ],
+            20,
+        );
+        let entries = h.recent(10);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, "exec");
+        assert_eq!(entries[0].exit_code, Some(2));
+        assert_eq!(entries[0].finished_at, Some(20));
+    }
+
+    #[test]
+    fn typed_commands_recorded_from_shell() {
+        let mut h = CommandHistory::default();
+        h.apply(
+            &[
+                OscEvent::CommandLine("ls -l".into()),
+                OscEvent::CommandStart,
+                OscEvent::CommandEnd(Some(0)),
+                OscEvent::CommandStart,
+            ],
+            5,
+        );
+        let entries = h.recent(10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command.as_deref(), Some("ls -l"));
+        assert_eq!(entries[0].exit_code, Some(0));
+        // 133 without 633 `E`: command text unknown, still running.
+        assert_eq!(entries[1].command, None);
+        assert_eq!(entries[1].finished_at, None);
+    }
+
+    #[test]
+    fn end_without_start_ignored_and_bounded() {
+        let mut h = CommandHistory::default();
+        h.record_exec("sleep 1", 1);
+        h.apply(&[OscEvent::CommandEnd(Some(1))], 2);
+        assert_eq!(h.recent(1)[0].exit_code, None);
+        for i in 0..MAX_ENTRIES as u64 + 5 {
+            h.record_exec("true", i);
+        }
+        assert_eq!(h.recent(usize::MAX).len(), MAX_ENTRIES);
+        assert_eq!(h.recent(2).last().map(|e| e.id), Some(h.total()));
+    }
+}
@@ -4,18 +4,22 @@
 //! is metadata (version, pid, shell, etc.) and subsequent lines are compact
 //! output entries. On startup, journals are scanned to recover archived sessions.
 
+use std::io::BufRead;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use base64::Engine;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
 use super::buffer::{OutputEntry, OutputStream};
+use crate::shell::process::{process_alive, signal_group, SIGKILL, SIGTERM};
 
 /// Metadata header written as the first line of each journal file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,6 +51,9 @@ pub struct JournalEntry {
     pub t: char,
     /// Output data.
     pub d: String,
+    /// Original bytes, base64 (`raw` encoding sessions only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b: Option<String>,
     /// Timestamp in milliseconds since epoch.
     pub ts: u64,
 }
@@ -61,6 +68,10 @@ impl JournalEntry {
                 OutputStream::System => 'x',
             },
             d: entry.data.clone(),
+            b: entry
+                .raw
+                .as_ref()
+                .map(|r| base64::engine::general_purpose::STANDARD.encode(r)),
             ts: entry.timestamp_ms,
         }
     }
@@ -74,6 +85,10 @@ impl JournalEntry {
                 _ => OutputStream::System,
             },
             data: self.d.clone(),
+            raw: self
+                .b
+                .as_ref()
+                .and_then(|b| base64::engine::general_purpose::STANDARD.decode(b).ok()),
             timestamp_ms: self.ts,
         }
     }
@@ -191,13 +206,48 @@ async fn journal_writer_task(
     }
 }
 
-/// Scan the journal directory and recover archived sessions from disk.
-pub async fn recover_sessions(dir: &Path) -> Vec<ArchivedSession> {
-    let sessions_dir = dir.join("sessions");
-    let mut archived = Vec::new();
+/// Maximum number of journals parsed at once during startup scans.
+///
+/// High enough that hundreds of stale journals don't serialize behind each
+/// other, low enough to stay well clear of the fd limit on small devices.
+const SCAN_CONCURRENCY: usize = 16;
+
+/// Subdirectory of `sessions/` that unreadable journals are moved into.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Counts from a journal recovery scan.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryStats {
+    /// Journals that parsed successfully.
+    pub read: usize,
+    /// Journals moved aside because their metadata could not be parsed.
+    pub quarantined: usize,
+    /// Journals that could not be opened or read (left in place).
+    pub failed: usize,
+}
 
-    let Ok(mut read_dir) = fs::read_dir(&sessions_dir).await else {
-        return archived;
+/// Why a journal could not be recovered.
+#[derive(Debug)]
+enum RecoverError {
+    /// I/O failure — possibly transient, leave the file alone.
+    Io(String),
+    /// The file itself is bad (empty or unparsable metadata).
+    Corrupt(String),
+}
+
+impl std::fmt::Display for RecoverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) | Self::Corrupt(e) => f.write_str(e),
+        }
+    }
+}
+
+/// List `(path, session_id)` for every `.jsonl` journal in `sessions_dir`.
+async fn list_journals(sessions_dir: &Path) -> Vec<(PathBuf, String)> {
+    let mut journals = Vec::new();
+    let Ok(mut read_dir) = fs::read_dir(sessions_dir).await else {
+        return journals;
     };
 
     while let Ok(Some(entry)) = read_dir.next_entry().await {
@@ -210,47 +260,112 @@ pub async fn recover_sessions(dir: &Path) -> Vec<ArchivedSession> {
             Some(s) => s.to_string(),
             None => continue,
         };
+        journals.push((path, session_id));
+    }
+    journals
+}
 
-        match recover_single_journal(&path, &session_id).await {
+/// Move a corrupt journal into `sessions/quarantine/` so later scans skip it
+/// but the file is kept for inspection. Age-based cleanup removes it.
+async fn quarantine_journal(sessions_dir: &Path, path: &Path) -> bool {
+    let dir = sessions_dir.join(QUARANTINE_DIR);
+    if let Err(e) = fs::create_dir_all(&dir).await {
+        warn!("Cannot create journal quarantine dir: {e}");
+        return false;
+    }
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    match fs::rename(path, dir.join(name)).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to quarantine journal {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+/// Scan the journal directory and recover archived sessions from disk.
+///
+/// Journals are parsed concurrently (up to [`SCAN_CONCURRENCY`] at a time) and
+/// each recovered session is handed to `on_session` as soon as it is parsed,
+/// so callers can drop sessions they don't need without holding every
+/// journal's output in memory at once. Journals with unreadable metadata are
+/// moved to `sessions/quarantine/` instead of being retried on every start.
+pub async fn recover_sessions(
+    dir: &Path,
+    mut on_session: impl FnMut(ArchivedSession),
+) -> RecoveryStats {
+    let sessions_dir = dir.join("sessions");
+    let journals = list_journals(&sessions_dir).await;
+    let mut stats = RecoveryStats::default();
+
+    let mut results = futures::stream::iter(journals)
+        .map(|(path, session_id)| async move {
+            let result = recover_single_journal(&path, &session_id).await;
+            (path, result)
+        })
+        .buffer_unordered(SCAN_CONCURRENCY);
+
+    while let Some((path, result)) = results.next().await {
+        match result {
             Ok(session) => {
-                info!(
-                    "Recovered archived session {} ({} entries)",
-                    session.session_id,
-                    session.entries.len()
-                );
-                archived.push(session);
+                stats.read += 1;
+                on_session(session);
+            }
+            Err(RecoverError::Corrupt(e)) => {
+                if quarantine_journal(&sessions_dir, &path).await {
+                    warn!("Quarantined corrupt journal {}: {e}", path.display());
+                    stats.quarantined += 1;
+                } else {
+                    stats.failed += 1;
+                }
             }
             Err(e) => {
                 warn!("Failed to recover journal {}: {e}", path.display());
+                stats.failed += 1;
             }
         }
     }
 
-    archived
+    if stats.quarantined > 0 || stats.failed > 0 {
+        warn!(
+            "Journal scan: {} read, {} quarantined, {} failed",
+            stats.read, stats.quarantined, stats.failed
+        );
+    }
+    stats
 }
 
-/// Parse a single journal file into an `ArchivedSession`.
-async fn recover_single_journal(path: &Path, session_id: &str) -> Result<ArchivedSession, String> {
-    let file = fs::File::open(path)
+/// Parse a single journal file into an `ArchivedSession` on the I/O pool.
+async fn recover_single_journal(
+    path: &Path,
+    session_id: &str,
+) -> Result<ArchivedSession, RecoverError> {
+    let (path, session_id) = (path.to_path_buf(), session_id.to_string());
+    crate::io_pool::run(move || Ok(parse_journal(&path, &session_id)))
         .await
-        .map_err(|e| format!("open: {e}"))?;
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
+        .unwrap_or_else(|e| Err(RecoverError::Io(e.to_string())))
+}
+
+fn parse_journal(path: &Path, session_id: &str) -> Result<ArchivedSession, RecoverError> {
+    let file = std::fs::File::open(path).map_err(|e| RecoverError::Io(format!("open: {e}")))?;
+    let mut lines = std::io::BufReader::new(file).lines();
 
     // First line is metadata
     let meta_line = lines
-        .next_line()
-        .await
-        .map_err(|e| format!("read metadata: {e}"))?
-        .ok_or_else(|| "empty journal file".to_string())?;
+        .next()
+        .ok_or_else(|| RecoverError::Corrupt("empty journal file".to_string()))?
+        .map_err(|e| RecoverError::Corrupt(format!("read metadata: {e}")))?;
 
-    let metadata: SessionMetadata =
-        serde_json::from_str(&meta_line).map_err(|e| format!("parse metadata: {e}"))?;
+    let metadata: SessionMetadata = serde_json::from_str(&meta_line)
+        .map_err(|e| RecoverError::Corrupt(format!("parse metadata: {e}")))?;
 
     let mut entries = Vec::new();
     let mut exit_code = None;
+    let mut corrupt_lines = 0usize;
 
-    while let Ok(Some(line)) = lines.next_line().await {
+    for line in lines.map_while(Result::ok) {
         if line.is_empty() {
             continue;
         }
@@ -264,12 +379,19 @@ async fn recover_single_journal(path: &Path, session_id: &str) -> Result<Archive
                 }
                 entries.push(je.to_output_entry());
             }
-            Err(e) => {
-                warn!("Skipping corrupt journal line: {e}");
-            }
+            Err(_) => corrupt_lines += 1,
         }
     }
 
+    // Usually a torn last line from a crash mid-write; one warning per file
+    // rather than one per line.
+    if corrupt_lines > 0 {
+        warn!(
+            "Skipped {corrupt_lines} corrupt line(s) in journal {}",
+            path.display()
+        );
+    }
+
     Ok(ArchivedSession {
         session_id: session_id.to_string(),
         metadata,
@@ -278,38 +400,120 @@ async fn recover_single_journal(path: &Path, session_id: &str) -> Result<Archive
     })
 }
 
+/// One page of a session journal read from disk.
+pub struct JournalPage {
+    pub metadata: SessionMetadata,
+    /// Entries with `seq >= from_seq`, oldest first.
+    pub entries: Vec<OutputEntry>,
+    /// Sequence number of the first entry after this page, if there is one.
+    pub next_seq: Option<u64>,
+}
+
+/// Read up to `limit` entries with `seq >= from_seq` from a session's journal.
+///
+/// Reads the file from the start each call (journals are append-only and not
+/// indexed), stopping as soon as the page is full, so paging from the
+/// beginning is cheap and later pages cost a linear scan up to `from_seq`.
+/// Returns `Ok(None)` if the session has no journal.
+pub async fn read_page(
+    sessions_dir: &Path,
+    session_id: &str,
+    from_seq: u64,
+    limit: usize,
+) -> Result<Option<JournalPage>, String> {
+    // Session IDs are UUIDs; anything else must not reach the filesystem.
+    if session_id.is_empty()
+        || !session_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Ok(None);
+    }
+
+    let path = sessions_dir.join(format!("{session_id}.jsonl"));
+    // A linear scan parsing every line, so it runs on the I/O pool.
+    crate::io_pool::run(move || Ok(read_page_blocking(&path, from_seq, limit)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn read_page_blocking(
+    path: &Path,
+    from_seq: u64,
+    limit: usize,
+) -> Result<Option<JournalPage>, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("open: {e}")),
+    };
+    let mut lines = std::io::BufReader::new(file).lines();
+
+    let meta_line = lines
+        .next()
+        .ok_or_else(|| "empty journal file".to_string())?
+        .map_err(|e| format!("read metadata: {e}"))?;
+    let metadata: SessionMetadata =
+        serde_json::from_str(&meta_line).map_err(|e| format!("parse metadata: {e}"))?;
+
+    let mut entries = Vec::new();
+    let mut next_seq = None;
+
+    for line in lines.map_while(Result::ok) {
+        // Torn or corrupt lines are skipped, as in recovery.
+        let Ok(je) = serde_json::from_str::<JournalEntry>(&line) else {
+            continue;
+        };
+        if je.s < from_seq {
+            continue;
+        }
+        if entries.len() >= limit {
+            next_seq = Some(je.s);
+            break;
+        }
+        entries.push(je.to_output_entry());
+    }
+
+    Ok(Some(JournalPage {
+        metadata,
+        entries,
+        next_seq,
+    }))
+}
+
 /// Try to parse "Process exited with code N" from a system message.
 fn parse_exit_code(msg: &str) -> Option<i32> {
     msg.strip_prefix("Process exited with code ")
         .and_then(|s| s.trim().parse().ok())
 }
 
-/// Delete journal files older than `max_age_hours`.
+/// Delete journal files older than `max_age_hours`, including quarantined ones.
 pub async fn cleanup_old_journals(dir: &Path, max_age_hours: u64) {
     let sessions_dir = dir.join("sessions");
     let max_age = std::time::Duration::from_secs(max_age_hours * 3600);
-
-    let Ok(mut read_dir) = fs::read_dir(&sessions_dir).await else {
-        return;
-    };
-
     let now = SystemTime::now();
 
-    while let Ok(Some(entry)) = read_dir.next_entry().await {
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
-            continue;
-        }
-        let Ok(metadata) = fs::metadata(&path).await else {
+    for scan_dir in [sessions_dir.clone(), sessions_dir.join(QUARANTINE_DIR)] {
+        let Ok(mut read_dir) = fs::read_dir(&scan_dir).await else {
             continue;
         };
-        let Ok(modified) = metadata.modified() else {
-            continue;
-        };
-        if let Ok(age) = now.duration_since(modified) {
-            if age > max_age {
-                info!("Removing old journal: {}", path.display());
-                let _ = fs::remove_file(&path).await;
+
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&path).await else {
+                continue;
+            };
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            if let Ok(age) = now.duration_since(modified) {
+                if age > max_age {
+                    info!("Removing old journal: {}", path.display());
+                    let _ = fs::remove_file(&path).await;
+                }
             }
         }
     }
@@ -318,84 +522,78 @@ pub async fn cleanup_old_journals(dir: &Path, max_age_hours: u64) {
 /// Scan journals for sessions that were running when the server last died
 /// (no exit code in journal). If those PIDs are still alive, gracefully kill
 /// them — they're orphans we can't reconnect to (PTY/pipe fds are gone).
-pub async fn kill_orphaned_processes(dir: &Path) {
+///
+/// Journals are checked concurrently so one orphan's SIGTERM grace period
+/// doesn't delay the rest. Unreadable journals are skipped here and left for
+/// [`recover_sessions`] to quarantine. Returns the number of orphans killed.
+pub async fn kill_orphaned_processes(dir: &Path) -> usize {
     let sessions_dir = dir.join("sessions");
+    let journals = list_journals(&sessions_dir).await;
 
-    let Ok(mut read_dir) = fs::read_dir(&sessions_dir).await else {
-        return;
-    };
-
-    while let Ok(Some(entry)) = read_dir.next_entry().await {
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
-            continue;
-        }
-        let session_id = match path.file_stem().and_then(|s| s.to_str()) {
-            Some(s) => s.to_string(),
-            None => continue,
-        };
-        let Ok(archived) = recover_single_journal(&path, &session_id).await else {
-            continue;
-        };
-
-        // Only care about sessions with no exit code (were still running)
-        if archived.exit_code.is_some() {
-            continue;
-        }
+    futures::stream::iter(journals)
+        .map(|(path, session_id)| async move {
+            let Ok(archived) = recover_single_journal(&path, &session_id).await else {
+                return false;
+            };
+            kill_orphan(&session_id, &archived.metadata, archived.exit_code).await
+        })
+        .buffer_unordered(SCAN_CONCURRENCY)
+        .filter(|killed| std::future::ready(*killed))
+        .count()
+        .await
+}
 
-        let pid = archived.metadata.pid;
-        if pid == 0 {
-            continue;
-        }
+/// Kill one orphaned session's process group if it is still alive and still
+/// looks like our shell. Returns whether a kill was sent.
+async fn kill_orphan(session_id: &str, metadata: &SessionMetadata, exit_code: Option<i32>) -> bool {
+    // Only care about sessions with no exit code (were still running)
+    if exit_code.is_some() {
+        return false;
+    }
 
-        // Check if the PID is still alive
-        #[allow(clippy::cast_possible_wrap)]
-        let alive = unsafe { libc::kill(pid as i32, 0) } == 0;
-        if !alive {
-            continue;
-        }
+    let pid = metadata.pid;
+    if pid == 0 {
+        return false;
+    }
 
-        // Verify it's plausibly our shell by checking /proc/PID/cmdline
-        let cmdline_path = format!("/proc/{pid}/cmdline");
-        let is_shell = std::fs::read(&cmdline_path)
-            .ok()
-            .and_then(|bytes| {
-                // cmdline is NUL-separated; first arg is the executable
-                let exe = bytes.split(|&b| b == 0).next()?;
-                let exe_str = std::str::from_utf8(exe).ok()?;
-                Some(exe_str.contains(&archived.metadata.shell))
-            })
-            .unwrap_or(false);
-
-        if !is_shell {
-            info!(
-                "PID {pid} from session {session_id} is alive but doesn't match shell '{}', skipping",
-                archived.metadata.shell
-            );
-            continue;
-        }
+    // Check if the PID is still alive
+    if !process_alive(pid) {
+        return false;
+    }
 
-        // Gracefully kill the orphan's process group
+    // Verify it's plausibly our shell by checking /proc/PID/cmdline
+    let cmdline_path = format!("/proc/{pid}/cmdline");
+    let is_shell = std::fs::read(&cmdline_path)
+        .ok()
+        .and_then(|bytes| {
+            // cmdline is NUL-separated; first arg is the executable
+            let exe = bytes.split(|&b| b == 0).next()?;
+            let exe_str = std::str::from_utf8(exe).ok()?;
+            Some(exe_str.contains(&metadata.shell))
+        })
+        .unwrap_or(false);
+
+    if !is_shell {
         info!(
-            "Killing orphaned session {session_id} (PID {pid}, shell '{}')",
-            archived.metadata.shell
+            "PID {pid} from session {session_id} is alive but doesn't match shell '{}', skipping",
+            metadata.shell
         );
-        #[allow(clippy::cast_possible_wrap)]
-        let neg_pgid = -(pid as i32);
-        unsafe {
-            libc::kill(neg_pgid, libc::SIGTERM);
-        }
-        // Give it a moment, then force-kill if needed
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        #[allow(clippy::cast_possible_wrap)]
-        let still_alive = unsafe { libc::kill(pid as i32, 0) } == 0;
-        if still_alive {
-            unsafe {
-                libc::kill(neg_pgid, libc::SIGKILL);
-            }
-            info!("Orphaned PID {pid} required SIGKILL");
-        }
+        return false;
+    }
+
+    // Gracefully kill the orphan's process group
+    info!(
+        "Killing orphaned session {session_id} (PID {pid}, shell '{}')",
+        metadata.shell
+    );
+    let _ = signal_group(pid, SIGTERM);
+    // Give it a moment, then force-kill if needed
+    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    if process_alive(pid) {
+        let _ = signal_group(pid, SIGKILL);
+        info!("Orphaned PID {pid} required SIGKILL");
     }
+    true
 }
 
 /// Get the sessions subdirectory path.
@@ -11,6 +11,22 @@
 //!   that exceed their client-requested `idle_timeout`.
 //! - **Journal** — session output is persisted to disk for crash recovery.
 //! - **PTY** — sessions can be backed by a PTY for full terminal emulation.
+//! - **Title/cwd** — PTY sessions track the OSC title and working directory
+//!   the shell announces, exposed in listings and `session.updated`.
+//! - **Command history** — commands sent with `session.exec` or reported by
+//!   shell integration, with exit codes where the shell reports them.
+//! - **Screen diffs** — PTY sessions keep a screen model; `session.read_diff`
+//!   returns only the rows changed since the caller's last read.
+//! - **Recording** — sessions started with `record: true` are written to an
+//!   asciinema v2 file for later replay (see [`recording`]).
+//! - **Input control** — one of several attached clients can hold a session's
+//!   input; the others watch (see [`control`]).
+//! - **Process tree** — what a session's shell has spawned, read from `/proc`
+//!   (see [`processes`]).
+//! - **Templates** — named `session.start` presets from `[session_templates]`
+//!   (see [`templates`]).
+//! - **Resource limits** — with `[session_limits]`, each session runs in its
+//!   own cgroup (see [`crate::shell::cgroup`]).
 //!
 //! ## Concurrency
 //!
@@ -20,11 +36,19 @@
 //! insert to prevent TOCTOU races.
 
 pub mod buffer;
+pub mod control;
+pub mod decode;
+pub mod history;
 pub mod journal;
+pub mod osc;
+pub mod processes;
+pub mod recording;
+pub mod screen;
 pub mod session;
+pub mod templates;
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -32,10 +56,15 @@ use tokio::sync::{broadcast, RwLock};
 use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::shell::process::{spawn_command_pgroup, spawn_shell_pgroup};
+use crate::shell::cgroup::{Cgroups, ResourceLimits, SessionCgroup};
+use crate::shell::process::{signal_group, spawn_command_pgroup, spawn_shell_pgroup, SIGTERM};
 use crate::shell::pty::{allocate_pty, spawn_shell_pty};
 use buffer::OutputBuffer;
+use control::InputLock;
+use decode::OutputEncoding;
+use history::HistoryEntry;
 use journal::{SessionJournal, SessionMetadata};
+use screen::ScreenDiff;
 use session::{ManagedSession, SessionStatus};
 
 /// Manages the pool of active interactive shell sessions.
@@ -48,6 +77,12 @@ pub struct SessionManager {
     buffer_size: usize,
     /// Data directory for journals. `None` if journaling is disabled.
     data_dir: Option<String>,
+    /// Directory for `.cast` recordings. `None` if recording is unavailable.
+    recording_dir: Option<PathBuf>,
+    /// Output encoding for new sessions (`server.session_encoding`).
+    default_encoding: OutputEncoding,
+    /// Parent cgroup for session limits. `None` without `[session_limits]`.
+    cgroups: Option<Arc<Cgroups>>,
 }
 
 /// Summary of a session returned by [`SessionManager::list_sessions`].
@@ -83,6 +118,18 @@ pub struct SessionListItem {
     pub ai_activity: Option<String>,
     /// Short status message from the AI (e.g. "Running tests").
     pub ai_status_message: Option<String>,
+    /// Terminal title last set by the program (OSC 0/2), PTY sessions only.
+    pub title: Option<String>,
+    /// Working directory last reported by the shell (OSC 7), PTY sessions only.
+    pub cwd: Option<String>,
+    /// Output encoding: `"utf8"`, `"latin1"` or `"raw"`.
+    pub encoding: String,
+    /// Charset guessed from output that wasn't valid UTF-8 (`"gbk"`, `"latin1"`).
+    pub charset_hint: Option<String>,
+    /// Connection holding the session's input (`session.take_control`).
+    pub controller: Option<String>,
+    /// Label the controller gave itself.
+    pub controller_label: Option<String>,
 }
 
 /// Whether a session is an interactive terminal or a one-shot streaming "job".
@@ -149,6 +196,10 @@ pub struct SessionEntry {
     pub ai_status_message: Option<String>,
     /// Last time the AI sent a command or status update. Used for idle auto-clear.
     pub ai_last_activity: Option<Instant>,
+    /// Client holding the input, if any.
+    pub input_lock: Option<InputLock>,
+    /// The session's cgroup, removed with the entry.
+    pub cgroup: Option<SessionCgroup>,
 }
 
 impl SessionManager {
@@ -158,6 +209,9 @@ impl SessionManager {
             max_sessions,
             buffer_size,
             data_dir: None,
+            recording_dir: None,
+            default_encoding: OutputEncoding::default(),
+            cgroups: None,
         }
     }
 
@@ -168,9 +222,59 @@ impl SessionManager {
             max_sessions,
             buffer_size,
             data_dir: Some(data_dir.to_string()),
+            recording_dir: None,
+            default_encoding: OutputEncoding::default(),
+            cgroups: None,
         }
     }
 
+    /// Enable `record: true` sessions, writing recordings to `dir`.
+    #[must_use]
+    pub fn with_recording_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.recording_dir = Some(dir.into());
+        self
+    }
+
+    /// Decode new sessions' output with `encoding` unless they pick their own.
+    #[must_use]
+    pub fn with_default_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.default_encoding = encoding;
+        self
+    }
+
+    /// Run every new session in its own cgroup under `cgroups`.
+    #[must_use]
+    pub fn with_cgroups(mut self, cgroups: Arc<Cgroups>) -> Self {
+        self.cgroups = Some(cgroups);
+        self
+    }
+
+    /// The `limits` a `session.start` message asks for, checked against what
+    /// this device can enforce.
+    pub fn resource_limits(
+        &self,
+        msg: &serde_json::Value,
+    ) -> Result<Option<ResourceLimits>, String> {
+        let Some(limits) = ResourceLimits::from_message(msg)? else {
+            return Ok(None);
+        };
+        let cgroups = self
+            .cgroups
+            .as_ref()
+            .ok_or("Session limits are not enabled on this device ([session_limits])")?;
+        cgroups.check(&cgroups.effective(Some(&limits)))?;
+        Ok(Some(limits))
+    }
+
+    /// The limits a session runs under, if it has any.
+    pub async fn limits(&self, session_id: &str) -> Option<ResourceLimits> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .and_then(|e| e.cgroup.as_ref())
+            .map(|c| *c.limits())
+    }
+
     /// Create a new shell session. Returns `(session_id, pid)`.
     ///
     /// Holds the write lock through the entire check-and-insert to prevent
@@ -193,13 +297,18 @@ impl SessionManager {
             0,
             None,
             None,
+            None,
             SessionKind::Terminal,
             None,
+            None,
         )
         .await
     }
 
     /// Create a new session with optional PTY support.
+    ///
+    /// For PTY sessions, title/cwd changes are broadcast as `session.updated`
+    /// on `updates`. `limits` tightens the configured session limits.
     #[allow(clippy::too_many_arguments)]
     pub async fn create_session_with_pty(
         &self,
@@ -212,6 +321,8 @@ impl SessionManager {
         cols: u16,
         idle_timeout: u64,
         name: Option<&str>,
+        limits: Option<&ResourceLimits>,
+        updates: Option<broadcast::Sender<serde_json::Value>>,
     ) -> Result<(String, u32), String> {
         self.create_session_inner(
             shell,
@@ -223,9 +334,11 @@ impl SessionManager {
             cols,
             idle_timeout,
             name,
+            limits,
             None,
             SessionKind::Terminal,
             None,
+            updates,
         )
         .await
     }
@@ -259,9 +372,11 @@ impl SessionManager {
             80,
             idle_timeout,
             name,
+            None,
             Some(command),
             SessionKind::Job,
             Some(exit_events),
+            None,
         )
         .await
     }
@@ -278,9 +393,11 @@ impl SessionManager {
         cols: u16,
         idle_timeout: u64,
         name: Option<&str>,
+        limits: Option<&ResourceLimits>,
         command: Option<&str>,
         kind: SessionKind,
         exit_events: Option<broadcast::Sender<serde_json::Value>>,
+        updates: Option<broadcast::Sender<serde_json::Value>>,
     ) -> Result<(String, u32), String> {
         let mut sessions = self.sessions.write().await;
 
@@ -290,6 +407,26 @@ impl SessionManager {
 
         let session_id = Uuid::new_v4().to_string();
 
+        let cgroup = match &self.cgroups {
+            Some(cgroups) => {
+                let limits = cgroups.effective(limits);
+                if limits == ResourceLimits::default() {
+                    None
+                } else {
+                    Some(
+                        cgroups
+                            .create(&session_id, &limits)
+                            .map_err(|e| format!("Failed to create session cgroup: {e}"))?,
+                    )
+                }
+            }
+            None if limits.is_some() => {
+                return Err("Session limits are not enabled on this device".to_string());
+            }
+            None => None,
+        };
+        let procs = cgroup.as_ref().map(SessionCgroup::procs);
+
         let session = if use_pty {
             // PTY-backed session
             let pty_pair =
@@ -301,27 +438,43 @@ impl SessionManager {
                 .entry("TERM".to_string())
                 .or_insert_with(|| "xterm-256color".to_string());
 
-            let child = spawn_shell_pty(&pty_pair, shell, working_dir, Some(&pty_env))
+            let child = spawn_shell_pty(&pty_pair, shell, working_dir, Some(&pty_env), procs)
                 .map_err(|e| format!("Failed to spawn PTY shell: {e}"))?;
 
             ManagedSession::spawn_pty(
                 session_id.clone(),
                 child,
                 pty_pair.master,
+                rows,
+                cols,
                 self.buffer_size,
+                self.default_encoding,
                 exit_events,
+                updates,
             )?
         } else if let Some(cmd) = command {
             // Job: the child process *is* the command; it runs and exits on its
             // own, streaming stdout/stderr over the session's pipe.
-            let child = spawn_command_pgroup(shell, working_dir, cmd, env)
+            let child = spawn_command_pgroup(shell, working_dir, cmd, env, procs)
                 .map_err(|e| format!("Failed to spawn command: {e}"))?;
-            ManagedSession::spawn(session_id.clone(), child, self.buffer_size, exit_events)?
+            ManagedSession::spawn(
+                session_id.clone(),
+                child,
+                self.buffer_size,
+                self.default_encoding,
+                exit_events,
+            )?
         } else {
             // Pipe-backed interactive session
-            let child = spawn_shell_pgroup(shell, working_dir, env)
+            let child = spawn_shell_pgroup(shell, working_dir, env, procs)
                 .map_err(|e| format!("Failed to spawn shell: {e}"))?;
-            ManagedSession::spawn(session_id.clone(), child, self.buffer_size, exit_events)?
+            ManagedSession::spawn(
+                session_id.clone(),
+                child,
+                self.buffer_size,
+                self.default_encoding,
+                exit_events,
+            )?
         };
 
         let pid = session.pid;
@@ -369,6 +522,8 @@ impl SessionManager {
                 ai_activity: None,
                 ai_status_message: None,
                 ai_last_activity: None,
+                input_lock: None,
+                cgroup,
             },
         );
 
@@ -407,9 +562,10 @@ impl SessionManager {
     pub async fn exec_command(&self, session_id: &str, command: &str) -> Result<(), String> {
         let session = {
             let sessions = self.sessions.read().await;
-            sessions
-                .get(session_id)
-                .map(|entry| (entry.session.stdin_sender(), entry.session.is_pty()))
+            sessions.get(session_id).map(|entry| {
+                entry.session.record_command(command);
+                (entry.session.stdin_sender(), entry.session.is_pty())
+            })
         };
         match session {
             Some((tx, is_pty)) => {
@@ -469,12 +625,8 @@ impl SessionManager {
 
         // Phase 1: SIGTERM all
         for (id, entry) in sessions.iter() {
-            #[allow(clippy::cast_possible_wrap)]
-            let pgid = entry.session.pgid as i32;
-            if pgid > 0 {
-                unsafe {
-                    libc::kill(-pgid, libc::SIGTERM);
-                }
+            if entry.session.pgid > 0 {
+                let _ = signal_group(entry.session.pgid, SIGTERM);
             }
             info!("Session {id}: SIGTERM sent (shutdown)");
         }
@@ -572,6 +724,15 @@ impl SessionManager {
         Some((status, code))
     }
 
+    /// The shell's pid and process group of a session. Both are 0 for
+    /// sessions recovered read-only from the journal.
+    pub async fn process_ids(&self, session_id: &str) -> Option<(u32, u32)> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .map(|entry| (entry.session.pid, entry.session.pgid))
+    }
+
     /// Check whether a session is persistent.
     pub async fn is_persistent(&self, session_id: &str) -> bool {
         let sessions = self.sessions.read().await;
@@ -603,6 +764,22 @@ impl SessionManager {
         }
     }
 
+    /// Switch a session's output encoding; applies from the next read.
+    pub async fn set_encoding(
+        &self,
+        session_id: &str,
+        encoding: OutputEncoding,
+    ) -> Result<(), String> {
+        let sessions = self.sessions.read().await;
+        match sessions.get(session_id) {
+            Some(entry) => {
+                entry.session.set_encoding(encoding);
+                Ok(())
+            }
+            None => Err(format!("Session {session_id} not found")),
+        }
+    }
+
     /// Set whether the user allows AI to control a session.
     ///
     /// If `allowed` is `false` and the AI is currently working, the AI state
@@ -667,6 +844,63 @@ impl SessionManager {
         }
     }
 
+    /// Give `client` the session's input (`session.take_control`). Returns the
+    /// new lock, or `None` if the session doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Another client holds the input and `force` is false.
+    pub async fn take_control(
+        &self,
+        session_id: &str,
+        client: &str,
+        label: Option<&str>,
+        force: bool,
+    ) -> Option<Result<InputLock, String>> {
+        let mut sessions = self.sessions.write().await;
+        let entry = sessions.get_mut(session_id)?;
+        Some(
+            InputLock::take(&mut entry.input_lock, client, label, force)
+                .map(|()| entry.input_lock.clone().expect("just taken")),
+        )
+    }
+
+    /// Release the session's input if `client` holds it. Returns whether it
+    /// did, or `None` if the session doesn't exist.
+    pub async fn release_control(&self, session_id: &str, client: &str) -> Option<bool> {
+        let mut sessions = self.sessions.write().await;
+        let entry = sessions.get_mut(session_id)?;
+        Some(InputLock::release(&mut entry.input_lock, client))
+    }
+
+    /// Release every session input held by a client matching `holder`, e.g.
+    /// on disconnect. Returns the sessions released.
+    pub async fn release_controls_where(&self, holder: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut sessions = self.sessions.write().await;
+        let mut released = Vec::new();
+        for (id, entry) in sessions.iter_mut() {
+            if entry.input_lock.as_ref().is_some_and(|l| holder(&l.holder)) {
+                entry.input_lock = None;
+                released.push(id.clone());
+            }
+        }
+        released
+    }
+
+    /// Check that `client` may send input to the session.
+    ///
+    /// # Errors
+    ///
+    /// Another client holds the input; the error is the `INPUT_LOCKED`
+    /// message. A missing session passes, so callers report it as usual.
+    pub async fn check_input(&self, session_id: &str, client: &str) -> Result<(), String> {
+        let sessions = self.sessions.read().await;
+        match sessions.get(session_id).and_then(|e| e.input_lock.as_ref()) {
+            Some(lock) if !InputLock::permits(Some(lock), client) => Err(lock.held_message()),
+            _ => Ok(()),
+        }
+    }
+
     /// Count of active sessions.
     pub async fn session_count(&self) -> usize {
         self.sessions.read().await.len()
@@ -696,6 +930,9 @@ impl SessionManager {
                         entry.last_activity,
                         entry.session.status_handle(),
                         entry.session.exit_code_handle(),
+                        entry.session.term_meta(),
+                        entry.session.charset(),
+                        entry.input_lock.clone(),
                     )
                 })
                 .collect::<Vec<_>>()
@@ -719,6 +956,9 @@ impl SessionManager {
             last_activity,
             status_handle,
             exit_code_handle,
+            term_meta,
+            charset,
+            input_lock,
         ) in sessions_snapshot
         {
             let status = *status_handle.lock().await;
@@ -745,6 +985,12 @@ impl SessionManager {
                 ai_is_working,
                 ai_activity,
                 ai_status_message,
+                title: term_meta.title,
+                cwd: term_meta.cwd,
+                encoding: charset.encoding.as_str().to_string(),
+                charset_hint: charset.hint.map(String::from),
+                controller: input_lock.as_ref().map(|l| l.holder.clone()),
+                controller_label: input_lock.and_then(|l| l.label),
             });
         }
         items
@@ -758,10 +1004,107 @@ impl SessionManager {
         cols: u16,
     ) -> Result<(), String> {
         let sessions = self.sessions.read().await;
-        match sessions.get(session_id) {
-            Some(entry) => entry.session.resize(rows, cols),
-            None => Err(format!("Session {session_id} not found")),
+        let Some(entry) = sessions.get(session_id) else {
+            return Err(format!("Session {session_id} not found"));
+        };
+        entry.session.resize(rows, cols)?;
+        entry.session.buffer.lock().await.record_resize(rows, cols);
+        Ok(())
+    }
+
+    /// Start recording a session's output to `<recording_dir>/<id>.cast`.
+    ///
+    /// Output already in the buffer is written first with its original
+    /// timing, and the buffer stays locked until the recording is attached,
+    /// so nothing between session creation and this call is lost. Returns
+    /// the recording's path.
+    pub async fn start_recording(
+        &self,
+        session_id: &str,
+        shell: &str,
+        rows: u16,
+        cols: u16,
+    ) -> Result<PathBuf, String> {
+        let Some(dir) = self.recording_dir.as_deref() else {
+            return Err("Session recording is not enabled".to_string());
+        };
+        let (buffer, created_at) = {
+            let sessions = self.sessions.read().await;
+            let entry = sessions
+                .get(session_id)
+                .ok_or_else(|| format!("Session {session_id} not found"))?;
+            (Arc::clone(&entry.session.buffer), entry.created_at)
+        };
+
+        let mut buf = buffer.lock().await;
+        if buf.is_recording() {
+            return Err(format!("Session {session_id} is already recording"));
         }
+        let backlog: Vec<_> = buf
+            .read_since(0)
+            .0
+            .iter()
+            .filter_map(recording::RecordEvent::from_output_entry)
+            .collect();
+        let tx = recording::create(dir, session_id, rows, cols, shell, created_at, &backlog)
+            .await
+            .map_err(|e| format!("Failed to create recording: {e}"))?;
+        buf.set_recording(tx);
+        drop(buf);
+
+        info!("Session {session_id} recording started");
+        Ok(recording::recording_path(dir, session_id).unwrap_or_default())
+    }
+
+    /// Directory holding session recordings, if recording is enabled.
+    pub fn recording_dir(&self) -> Option<&Path> {
+        self.recording_dir.as_deref()
+    }
+
+    /// The last `limit` commands run in a session, oldest first, and the total
+    /// ever recorded.
+    pub async fn command_history(
+        &self,
+        session_id: &str,
+        limit: usize,
+    ) -> Result<(Vec<HistoryEntry>, u64), String> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .map(|entry| entry.session.command_history(limit))
+            .ok_or_else(|| format!("Session {session_id} not found"))
+    }
+
+    /// Directory holding session journals, or `None` if journaling is disabled.
+    pub fn journal_dir(&self) -> Option<std::path::PathBuf> {
+        self.data_dir
+            .as_deref()
+            .map(|d| journal::sessions_dir(Path::new(d)))
+    }
+
+    /// Changed screen rows of a PTY session since the `since` token.
+    pub async fn read_screen_diff(
+        &self,
+        session_id: &str,
+        since: Option<u64>,
+    ) -> Result<ScreenDiff, String> {
+        let sessions = self.sessions.read().await;
+        let entry = sessions
+            .get(session_id)
+            .ok_or_else(|| format!("Session {session_id} not found"))?;
+        entry
+            .session
+            .screen_diff(since)
+            .ok_or_else(|| "Not a PTY session".to_string())
+    }
+
+    /// Whether a PTY session's terminal currently echoes input. `None` when the
+    /// session doesn't exist or isn't PTY-backed.
+    pub async fn pty_echo_enabled(&self, session_id: &str) -> Option<bool> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .and_then(|entry| entry.session.echo_enabled())
     }
 
     /// Load archived sessions from disk journals. Called once at startup.
@@ -770,26 +1113,26 @@ impl SessionManager {
     /// are loaded into memory. Sessions that already exited are left on disk for
     /// the age-based journal cleanup to handle — loading them just to have the
     /// sweep immediately remove them is pointless noise.
-    pub async fn recover_from_journal(&self, data_dir: &Path) {
-        let archived = journal::recover_sessions(data_dir).await;
-        if archived.is_empty() {
-            return;
-        }
-
-        let total_on_disk = archived.len();
+    ///
+    /// Sessions arrive from the journal scan as each file is parsed, so exited
+    /// sessions are dropped without holding their output in memory.
+    ///
+    /// Returns the scan counts (journals read, quarantined, failed).
+    pub async fn recover_from_journal(&self, data_dir: &Path) -> journal::RecoveryStats {
         let mut skipped = 0usize;
+        let mut recovered = 0usize;
         let mut sessions = self.sessions.write().await;
-        for arch in archived {
+        let stats = journal::recover_sessions(data_dir, |arch| {
             // Skip already-exited sessions — they can't be reattached and their
             // output is still available on disk in the journal file.
             if arch.exit_code.is_some() {
                 skipped += 1;
-                continue;
+                return;
             }
 
             let mut buf = OutputBuffer::new(self.buffer_size);
             for entry in arch.entries {
-                buf.push(entry.stream, entry.data);
+                buf.push_raw(entry.stream, entry.data, entry.raw);
             }
 
             let session = ManagedSession::archived(buf, arch.exit_code);
@@ -811,14 +1154,19 @@ impl SessionManager {
                     ai_activity: None,
                     ai_status_message: None,
                     ai_last_activity: None,
+                    input_lock: None,
+                    cgroup: None,
                 },
             );
+            recovered += 1;
 
             info!(
                 "Recovered archived session {} (exit_code={:?})",
                 arch.session_id, arch.exit_code
             );
-        }
+        })
+        .await;
+
         // Cap recovered sessions against max_sessions — trim oldest if over limit
         if sessions.len() > self.max_sessions {
             let excess = sessions.len() - self.max_sessions;
@@ -836,13 +1184,13 @@ impl SessionManager {
             );
         }
 
-        let recovered = total_on_disk - skipped;
         if recovered > 0 || skipped > 0 {
             info!(
                 "Journal recovery: {recovered} session(s) loaded, {skipped} already-exited skipped, total in memory: {}",
                 sessions.len()
             );
         }
+        stats
     }
 
     /// Periodic sweep that handles three cases:
@@ -0,0 +1,340 @@
+//! OSC title/cwd tracking for PTY sessions.
+//!
+//! Shells and TUI programs announce what they're doing through xterm OSC
+//! escape sequences: `ESC ] 0 ; <title> BEL` (and `2`) sets the window title,
+//! `ESC ] 7 ; file://<host>/<path> BEL` reports the working directory (bash
+//! and zsh with vte/iTerm integration, fish, and most prompt frameworks).
+//!
+//! Shells with semantic-prompt integration (FinalTerm/iTerm OSC 133, VS Code
+//! OSC 633) also mark command boundaries: `C` when a command line starts
+//! running, `D;<status>` when it finishes, and (633 only) `E;<command line>`.
+//! These feed the session's command history (see [`super::history`]).
+//!
+//! [`OscScanner`] picks these out of raw PTY output as it streams past. It is
+//! incremental — a sequence split across two reads is still recognised — and
+//! never modifies the output itself: the bytes reach the buffer unchanged.
+
+/// Longest OSC payload we collect. Longer sequences (e.g. OSC 52 clipboard
+/// pushes) are skipped up to their terminator.
+const MAX_PAYLOAD: usize = 4096;
+
+/// Longest title/cwd kept, in characters.
+const MAX_VALUE_CHARS: usize = 512;
+
+/// An announcement by the program in the terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OscEvent {
+    /// OSC 0/2. Empty means the program cleared its title.
+    Title(String),
+    /// OSC 7, decoded to a plain path.
+    Cwd(String),
+    /// OSC 633 `E`: the command line about to run.
+    CommandLine(String),
+    /// OSC 133/633 `C`: a command line started running.
+    CommandStart,
+    /// OSC 133/633 `D`: the command finished, with its exit status if the
+    /// shell reported one.
+    CommandEnd(Option<i32>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Esc,
+    Osc,
+    /// Saw `ESC` inside an OSC — expecting `\` (ST).
+    OscEsc,
+}
+
+/// Incremental OSC 0/2/7 parser over a byte stream.
+#[derive(Debug)]
+pub struct OscScanner {
+    state: State,
+    payload: Vec<u8>,
+    overflow: bool,
+}
+
+impl Default for OscScanner {
+    fn default() -> Self {
+        Self {
+            state: State::Ground,
+            payload: Vec::new(),
+            overflow: false,
+        }
+    }
+}
+
+impl OscScanner {
+    /// Feed the next chunk of output, appending any completed title/cwd
+    /// announcements to `out`.
+    pub fn feed(&mut self, bytes: &[u8], out: &mut Vec<OscEvent>) {
+        let mut i = 0;
+        while i < bytes.len() {
+            if self.state == State::Ground {
+                // Fast path: nothing interesting until the next ESC.
+                match bytes[i..].iter().position(|&b| b == 0x1b) {
+                    Some(off) => {
+                        i += off + 1;
+                        self.state = State::Esc;
+                    }
+                    None => return,
+                }
+                continue;
+            }
+            let b = bytes[i];
+            i += 1;
+            match self.state {
+                State::Ground => unreachable!(),
+                State::Esc => {
+                    if b == b']' {
+                        self.payload.clear();
+                        self.overflow = false;
+                        self.state = State::Osc;
+                    } else if b != 0x1b {
+                        self.state = State::Ground;
+                    }
+                }
+                State::Osc => match b {
+                    0x07 => self.finish(out),
+                    0x1b => self.state = State::OscEsc,
+                    // CAN/SUB abort the sequence.
+                    0x18 | 0x1a => self.state = State::Ground,
+                    _ => self.push(b),
+                },
+                State::OscEsc => {
+                    if b == b'\\' {
+                        self.finish(out);
+                    } else if b == b']' {
+                        // Unterminated OSC followed by a new one.
+                        self.payload.clear();
+                        self.overflow = false;
+                        self.state = State::Osc;
+                    } else {
+                        self.state = State::Ground;
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.payload.len() < MAX_PAYLOAD {
+            self.payload.push(b);
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<OscEvent>) {
+        self.state = State::Ground;
+        if self.overflow {
+            return;
+        }
+        let Some(sep) = self.payload.iter().position(|&b| b == b';') else {
+            return;
+        };
+        let text = &self.payload[sep + 1..];
+        match &self.payload[..sep] {
+            b"0" | b"2" => out.push(OscEvent::Title(clean(&String::from_utf8_lossy(text)))),
+            b"7" => {
+                if let Some(path) = cwd_from_uri(text) {
+                    out.push(OscEvent::Cwd(path));
+                }
+            }
+            b"133" | b"633" => {
+                let mut parts = text.splitn(2, |&b| b == b';');
+                let mark = parts.next().unwrap_or_default();
+                let args = parts.next().unwrap_or_default();
+                match mark {
+                    b"C" => out.push(OscEvent::CommandStart),
+                    b"D" => {
+                        let status = args.split(|&b| b == b';').next().unwrap_or_default();
+                        out.push(OscEvent::CommandEnd(
+                            std::str::from_utf8(status)
+                                .ok()
+                                .and_then(|s| s.parse().ok()),
+                        ));
+                    }
+                    b"E" => {
+                        // Args are `<command line>[;<nonce>]`, with `;` and
+                        // `\` escaped inside the command line.
+                        let line = args.split(|&b| b == b';').next().unwrap_or_default();
+                        let line = clean(&String::from_utf8_lossy(&unescape_633(line)));
+                        if !line.is_empty() {
+                            out.push(OscEvent::CommandLine(line));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Strip control characters and cap the length.
+fn clean(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_VALUE_CHARS)
+        .collect()
+}
+
+/// Undo OSC 633 escaping: `\\` is a backslash, `\xHH` a raw byte.
+fn unescape_633(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'\\' {
+            if raw.get(i + 1) == Some(&b'\\') {
+                out.push(b'\\');
+                i += 2;
+                continue;
+            }
+            if raw.get(i + 1) == Some(&b'x') {
+                if let Some(byte) = raw
+                    .get(i + 2..i + 4)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                {
+                    out.push(byte);
+                    i += 4;
+                    continue;
+                }
+            }
+        }
+        out.push(raw[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Decode an OSC 7 payload (`file://host/path`, percent-encoded) to a path.
+/// Any `scheme://host` prefix is accepted, as is a bare absolute path.
+fn cwd_from_uri(raw: &[u8]) -> Option<String> {
+    let path = match raw.windows(3).position(|w| w == b"://") {
+        Some(p) => {
+            let rest = &raw[p + 3..];
+            &rest[rest.iter().position(|&b| b == b'/')?..]
+        }
+        None if raw.first() == Some(&b'/') => raw,
+        None => return None,
+    };
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        if path[i] == b'%' {
+            if let Some(byte) = path
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(path[i]);
+        i += 1;
+    }
+    let path = clean(&String::from_utf8_lossy(&decoded));
+    (!path.is_empty()).then_some(path)
+}
+
+/// Live terminal metadata for a PTY session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TermMeta {
+    pub title: Option<String>,
+    pub cwd: Option<String>,
+}
+
+impl TermMeta {
+    /// Apply announcements in order. Returns `true` if anything changed.
+    pub fn apply(&mut self, events: impl IntoIterator<Item = OscEvent>) -> bool {
+        let before = self.clone();
+        for event in events {
+            match event {
+                OscEvent::Title(t) => self.title = (!t.is_empty()).then_some(t),
+                OscEvent::Cwd(c) => self.cwd = Some(c),
+                OscEvent::CommandLine(_) | OscEvent::CommandStart | OscEvent::CommandEnd(_) => {}
+            }
+        }
+        *self != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(chunks: &[&str]) -> Vec<OscEvent> {
+        let mut scanner = OscScanner::default();
+        let mut out = Vec::new();
+        for chunk in chunks {
+            scanner.feed(chunk.as_bytes(), &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn title_and_cwd_with_both_terminators() {
+        let out =
+            scan(&["$ \x1b]0;vim nginx.conf\x07text\x1b]7;file://router/etc/nginx\x1b\\more"]);
+        assert_eq!(
+            out,
+            vec![
+                OscEvent::Title("vim nginx.conf".into()),
+                OscEvent::Cwd("/etc/nginx".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_split_across_reads() {
+        let out = scan(&["ab\x1b", "]2;top", " - 3 users\x1b", "\\"]);
+        assert_eq!(out, vec![OscEvent::Title("top - 3 users".into())]);
+    }
+
+    #[test]
+    fn cwd_percent_decoding_and_other_oscs_ignored() {
+        let out = scan(&[
+            "\x1b]7;file://host/home/me/My%20Docs\x07",
+            "\x1b]52;c;aGVsbG8=\x07\x1b]1;icon\x07\x1b]8;;http://x\x07",
+            "\x1b[1;32mgreen\x1b[0m",
+        ]);
+        assert_eq!(out, vec![OscEvent::Cwd("/home/me/My Docs".into())]);
+    }
+
+    #[test]
+    fn oversized_and_aborted_payloads_dropped() {
+        let big = format!("\x1b]0;{}\x07", "x".repeat(MAX_PAYLOAD + 10));
+        assert!(scan(&[&big, "\x1b]0;half\x18\x07"]).is_empty());
+    }
+
+    #[test]
+    fn command_marks_from_133_and_633() {
+        let out = scan(&[
+            "\x1b]133;A\x07$ \x1b]133;B\x07",
+            "\x1b]633;E;echo a\\x3bb \\\\n;nonce\x07\x1b]133;C\x07",
+            "a;b\r\n\x1b]133;D;2\x07\x1b]633;D\x07",
+        ]);
+        assert_eq!(
+            out,
+            vec![
+                OscEvent::CommandLine("echo a;b \\n".into()),
+                OscEvent::CommandStart,
+                OscEvent::CommandEnd(Some(2)),
+                OscEvent::CommandEnd(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_apply_reports_changes() {
+        let mut meta = TermMeta::default();
+        assert!(meta.apply([OscEvent::Title("bash".into())]));
+        assert!(!meta.apply([OscEvent::Title("bash".into())]));
+        assert!(meta.apply([OscEvent::Title(String::new())]));
+        assert_eq!(meta.title, None);
+    }
+}
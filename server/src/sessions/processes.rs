@@ -0,0 +1,194 @@
+//! Process tree of a session, read from `/proc`.
+//!
+//! A session's shell leads its own process group, so everything it starts
+//! normally shares its pgid. Children that called `setsid`/`setpgid` (daemons,
+//! some build tools) leave the group but keep the parent link, so the tree
+//! also follows `ppid` from every member down.
+
+use std::collections::{HashMap, HashSet};
+
+/// One process as parsed from `/proc/<pid>/stat`.
+#[derive(Debug, Clone)]
+pub struct ProcStat {
+    pub pid: u32,
+    pub ppid: u32,
+    pub pgid: u32,
+    /// One-letter state (`R`, `S`, `D`, `Z`, `T`, ...).
+    pub state: char,
+    pub comm: String,
+    /// `utime + stime`, in clock ticks.
+    pub cpu_ticks: u64,
+    pub threads: u64,
+    pub rss_pages: u64,
+}
+
+/// Parse a `/proc/<pid>/stat` line. `comm` can hold spaces and parens, so
+/// the fixed fields are read after its last `)`.
+#[must_use]
+pub fn parse_stat(content: &str) -> Option<ProcStat> {
+    let open = content.find('(')?;
+    let close = content.rfind(')')?;
+    let pid = content[..open].trim().parse().ok()?;
+    let comm = content[open + 1..close].to_string();
+    // Fields from 3 (state) on; index 0 here is field 3.
+    let fields: Vec<&str> = content[close + 1..].split_whitespace().collect();
+    let num = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    Some(ProcStat {
+        pid,
+        ppid: u32::try_from(num(1)?).ok()?,
+        pgid: u32::try_from(num(2)?).ok()?,
+        state: fields.first()?.chars().next()?,
+        comm,
+        cpu_ticks: num(11)? + num(12)?,
+        threads: num(17)?,
+        rss_pages: num(21)?,
+    })
+}
+
+/// Every process currently in `/proc`.
+#[must_use]
+pub fn scan() -> Vec<ProcStat> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+        })
+        .filter_map(|e| std::fs::read_to_string(e.path().join("stat")).ok())
+        .filter_map(|s| parse_stat(&s))
+        .collect()
+}
+
+/// `/proc/<pid>/cmdline` with NULs as spaces, or `[comm]` when it is empty
+/// (zombies, kernel threads).
+#[must_use]
+pub fn cmdline(pid: u32, comm: &str) -> String {
+    let raw = std::fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+    let args: Vec<String> = raw
+        .split(|&b| b == 0)
+        .filter(|a| !a.is_empty())
+        .map(|a| String::from_utf8_lossy(a).into_owned())
+        .collect();
+    if args.is_empty() {
+        format!("[{comm}]")
+    } else {
+        args.join(" ")
+    }
+}
+
+/// Readable name for a `/proc` state letter.
+#[must_use]
+pub fn state_name(state: char) -> &'static str {
+    match state {
+        'R' => "running",
+        'S' => "sleeping",
+        'D' => "disk_sleep",
+        'Z' => "zombie",
+        'T' => "stopped",
+        't' => "tracing_stop",
+        'X' | 'x' => "dead",
+        'I' => "idle",
+        _ => "unknown",
+    }
+}
+
+/// The session's processes as `(index into procs, depth)`, in tree order:
+/// each parent followed by its children, sorted by pid.
+///
+/// Members are the `leader`, everything in process group `pgid`, and any
+/// descendant of a member.
+#[must_use]
+pub fn session_tree(procs: &[ProcStat], leader: u32, pgid: u32) -> Vec<(usize, usize)> {
+    let mut members: HashSet<u32> = procs
+        .iter()
+        .filter(|p| p.pid == leader || p.pgid == pgid)
+        .map(|p| p.pid)
+        .collect();
+    loop {
+        let before = members.len();
+        for p in procs {
+            if members.contains(&p.ppid) {
+                members.insert(p.pid);
+            }
+        }
+        if members.len() == before {
+            break;
+        }
+    }
+
+    let mut children: HashMap<u32, Vec<usize>> = HashMap::new();
+    let mut roots = Vec::new();
+    for (i, p) in procs.iter().enumerate() {
+        if !members.contains(&p.pid) {
+            continue;
+        }
+        if members.contains(&p.ppid) && p.ppid != p.pid {
+            children.entry(p.ppid).or_default().push(i);
+        } else {
+            roots.push(i);
+        }
+    }
+    let by_pid = |a: &usize, b: &usize| procs[*a].pid.cmp(&procs[*b].pid);
+    roots.sort_by(by_pid);
+    for list in children.values_mut() {
+        list.sort_by(by_pid);
+    }
+
+    let mut order = Vec::with_capacity(members.len());
+    let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|i| (i, 0)).collect();
+    while let Some((i, depth)) = stack.pop() {
+        order.push((i, depth));
+        if let Some(kids) = children.get(&procs[i].pid) {
+            stack.extend(kids.iter().rev().map(|&k| (k, depth + 1)));
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_and_builds_tree() {
+        let stat = "4321 (make (ci)) S 100 100 100 0 -1 4194304 500 0 0 0 \
+                    70 30 0 0 20 0 3 0 12345 1000000 256 18446744073709551615";
+        let p = parse_stat(stat).unwrap();
+        assert_eq!((p.pid, p.ppid, p.pgid), (4321, 100, 100));
+        assert_eq!(p.comm, "make (ci)");
+        assert_eq!(p.state, 'S');
+        assert_eq!(p.cpu_ticks, 100);
+        assert_eq!(p.threads, 3);
+        assert_eq!(p.rss_pages, 256);
+
+        let proc = |pid, ppid, pgid| ProcStat {
+            pid,
+            ppid,
+            pgid,
+            state: 'S',
+            comm: String::new(),
+            cpu_ticks: 0,
+            threads: 1,
+            rss_pages: 0,
+        };
+        // 100 shell; 200 and 150 in its group; 300 left the group via
+        // setsid but is still 200's child; 999 is unrelated.
+        let procs = vec![
+            proc(300, 200, 300),
+            proc(200, 100, 100),
+            proc(999, 1, 999),
+            proc(100, 1, 100),
+            proc(150, 100, 100),
+        ];
+        let tree: Vec<(u32, usize)> = session_tree(&procs, 100, 100)
+            .into_iter()
+            .map(|(i, d)| (procs[i].pid, d))
+            .collect();
+        assert_eq!(tree, vec![(100, 0), (150, 1), (200, 1), (300, 2)]);
+    }
+}
@@ -0,0 +1,165 @@
+//! Session output recording in asciinema v2 format.
+//!
+//! A session started with `record: true` gets a `<session_id>.cast` file in
+//! the recording directory (`server.recording_dir`, default
+//! `<data_dir>/recordings`). The first line is the asciicast header, every
+//! later line is `[seconds, "o", data]` for output or `[seconds, "r",
+//! "COLSxROWS"]` for a resize, with `seconds` relative to the session's
+//! creation. `asciinema play` replays it with the original timing.
+//!
+//! Like the journal, the writer runs as a background task fed through a
+//! channel, so pushing output never waits on disk. Unlike journals,
+//! recordings are not aged out: they exist for audits.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use super::buffer::{OutputEntry, OutputStream};
+
+/// One event for the recording writer.
+#[derive(Debug, Clone)]
+pub enum RecordEvent {
+    /// Terminal output.
+    Output { timestamp_ms: u64, data: String },
+    /// Terminal resized.
+    Resize {
+        timestamp_ms: u64,
+        rows: u16,
+        cols: u16,
+    },
+}
+
+impl RecordEvent {
+    /// The event for a buffer entry; runtime messages (`system`) aren't
+    /// terminal output and are left out.
+    pub fn from_output_entry(entry: &OutputEntry) -> Option<Self> {
+        (entry.stream != OutputStream::System).then(|| Self::Output {
+            timestamp_ms: entry.timestamp_ms,
+            data: entry.data.clone(),
+        })
+    }
+
+    /// The asciicast event line, timed relative to `start_ms`.
+    fn to_line(&self, start_ms: u64) -> Value {
+        #[allow(clippy::cast_precision_loss)]
+        let secs = |ts: u64| ts.saturating_sub(start_ms) as f64 / 1000.0;
+        match self {
+            Self::Output { timestamp_ms, data } => json!([secs(*timestamp_ms), "o", data]),
+            Self::Resize {
+                timestamp_ms,
+                rows,
+                cols,
+            } => json!([secs(*timestamp_ms), "r", format!("{cols}x{rows}")]),
+        }
+    }
+}
+
+/// Path of a session's recording, or `None` for an ID that isn't a UUID.
+pub fn recording_path(dir: &Path, session_id: &str) -> Option<PathBuf> {
+    // Session IDs are UUIDs; anything else must not reach the filesystem.
+    if session_id.is_empty()
+        || !session_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return None;
+    }
+    Some(dir.join(format!("{session_id}.cast")))
+}
+
+/// Create `<dir>/<session_id>.cast`, write the header and `backlog`, and
+/// spawn the writer. Returns the channel for further events.
+pub async fn create(
+    dir: &Path,
+    session_id: &str,
+    rows: u16,
+    cols: u16,
+    shell: &str,
+    created_ms: u64,
+    backlog: &[RecordEvent],
+) -> Result<mpsc::Sender<RecordEvent>, std::io::Error> {
+    let path = recording_path(dir, session_id).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid session id")
+    })?;
+    fs::create_dir_all(dir).await?;
+    let mut file = fs::File::create(&path).await?;
+
+    let header = json!({
+        "version": 2,
+        "width": cols,
+        "height": rows,
+        "timestamp": created_ms / 1000,
+        "env": { "SHELL": shell, "TERM": "xterm-256color" },
+    });
+    let mut out = format!("{header}\n");
+    for event in backlog {
+        out.push_str(&event.to_line(created_ms).to_string());
+        out.push('\n');
+    }
+    file.write_all(out.as_bytes()).await?;
+    file.flush().await?;
+
+    let (tx, rx) = mpsc::channel(10_000);
+    tokio::spawn(recording_writer_task(file, rx, created_ms));
+    Ok(tx)
+}
+
+/// Background task that drains events and appends them to the recording.
+/// Ends when the session (and with it the sender) is dropped.
+async fn recording_writer_task(
+    mut file: fs::File,
+    mut rx: mpsc::Receiver<RecordEvent>,
+    start_ms: u64,
+) {
+    while let Some(event) = rx.recv().await {
+        let mut out = String::new();
+        for event in std::iter::once(event).chain(std::iter::from_fn(|| rx.try_recv().ok())) {
+            out.push_str(&event.to_line(start_ms).to_string());
+            out.push('\n');
+        }
+        if let Err(e) = file.write_all(out.as_bytes()).await {
+            error!("Recording write error: {e}");
+            return;
+        }
+        if let Err(e) = file.flush().await {
+            error!("Recording flush error: {e}");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_lines_use_relative_seconds() {
+        let out = RecordEvent::Output {
+            timestamp_ms: 2_500,
+            data: "ls\r\n".to_string(),
+        };
+        assert_eq!(out.to_line(1_000), json!([1.5, "o", "ls\r\n"]));
+        let resize = RecordEvent::Resize {
+            timestamp_ms: 1_000,
+            rows: 40,
+            cols: 120,
+        };
+        assert_eq!(resize.to_line(1_000), json!([0.0, "r", "120x40"]));
+    }
+
+    #[test]
+    fn recording_path_rejects_traversal() {
+        let dir = Path::new("/var/lib/sctl/recordings");
+        assert!(recording_path(dir, "../etc/passwd").is_none());
+        assert!(recording_path(dir, "").is_none());
+        assert_eq!(
+            recording_path(dir, "3f2a-9c").unwrap(),
+            dir.join("3f2a-9c.cast")
+        );
+    }
+}
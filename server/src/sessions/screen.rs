@@ -0,0 +1,664 @@
+//! Server-side screen model for PTY sessions.
+//!
+//! Reading a TUI like `htop` through the output buffer means replaying a
+//! stream of cursor movements and partial redraws, and every poll re-sends
+//! whatever the program repainted. [`Screen`] instead interprets the VT/xterm
+//! control sequences as they arrive and keeps the visible character grid, so
+//! a reader can ask for the screen as text — or, with `session.read_diff`,
+//! only the rows that changed since its last read.
+//!
+//! ## Change tracking
+//!
+//! Every [`Screen::feed`] bumps a generation counter and stamps each row it
+//! touches. A read returns the current generation as its `token`; passing that
+//! token back returns only rows stamped after it. Resizes and alternate-screen
+//! switches stamp every row, so a stale token degrades to a full read rather
+//! than a wrong one.
+//!
+//! ## Scope
+//!
+//! This is a text model for agents, not a terminal emulator: attributes and
+//! colours are discarded, every character occupies one cell, and only the
+//! sequences that full-screen programs commonly use are interpreted (cursor
+//! movement, erase, insert/delete, scroll regions, save/restore, alternate
+//! screen). Anything else is consumed and ignored.
+
+use serde::{Deserialize, Serialize};
+
+/// Bounds on screen dimensions, to keep the grid allocation sane whatever a
+/// client asks for in `session.resize`.
+const MAX_ROWS: usize = 500;
+const MAX_COLS: usize = 1000;
+
+/// Longest CSI parameter list kept.
+const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parse {
+    Ground,
+    Esc,
+    /// `ESC (`, `ESC #`, ... — one more byte to swallow.
+    EscIntermediate,
+    Csi,
+    /// OSC / DCS / APC / PM / SOS payload, skipped up to BEL or ST.
+    Str,
+    StrEsc,
+}
+
+struct AltSaved {
+    grid: Vec<Vec<char>>,
+    cursor: (usize, usize),
+}
+
+/// Visible character grid of a PTY session.
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    grid: Vec<Vec<char>>,
+    row_gen: Vec<u64>,
+    gen: u64,
+    /// Generation of the last resize / alternate-screen switch.
+    layout_gen: u64,
+    row: usize,
+    col: usize,
+    /// Cursor sits past the last column; the next printable wraps first.
+    wrap_pending: bool,
+    saved: (usize, usize),
+    top: usize,
+    bottom: usize,
+    alt: Option<AltSaved>,
+    parse: Parse,
+    params: Vec<u16>,
+    param: Option<u16>,
+    private: bool,
+    utf8: Vec<u8>,
+}
+
+/// One changed row in a [`ScreenDiff`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export))]
+pub struct ScreenLine {
+    pub row: usize,
+    /// Row text with trailing blanks trimmed.
+    pub text: String,
+}
+
+/// Result of [`Screen::diff_since`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(ts_rs::TS))]
+#[cfg_attr(test, ts(export))]
+pub struct ScreenDiff {
+    /// Pass back as `since` to get only later changes.
+    pub token: u64,
+    /// `true` if `lines` holds every row (first read, stale token, resize).
+    pub full: bool,
+    pub rows: usize,
+    pub cols: usize,
+    /// `[row, col]`, zero-based.
+    pub cursor: [usize; 2],
+    /// Whether a full-screen program has the alternate screen active.
+    pub alt_screen: bool,
+    pub lines: Vec<ScreenLine>,
+}
+
+impl Screen {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        let rows = usize::from(rows).clamp(1, MAX_ROWS);
+        let cols = usize::from(cols).clamp(1, MAX_COLS);
+        Self {
+            rows,
+            cols,
+            grid: vec![vec![' '; cols]; rows],
+            row_gen: vec![0; rows],
+            gen: 0,
+            layout_gen: 0,
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            saved: (0, 0),
+            top: 0,
+            bottom: rows - 1,
+            alt: None,
+            parse: Parse::Ground,
+            params: Vec::new(),
+            param: None,
+            private: false,
+            utf8: Vec::new(),
+        }
+    }
+
+    /// Rows changed after `since`, or every row when `since` is `None`,
+    /// unknown, or predates a resize.
+    pub fn diff_since(&self, since: Option<u64>) -> ScreenDiff {
+        let full = since.is_none_or(|t| t > self.gen || t < self.layout_gen);
+        let since = if full { 0 } else { since.unwrap_or(0) };
+        let lines = (0..self.rows)
+            .filter(|&r| full || self.row_gen[r] > since)
+            .map(|r| ScreenLine {
+                row: r,
+                text: self.grid[r]
+                    .iter()
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string(),
+            })
+            .collect();
+        ScreenDiff {
+            token: self.gen,
+            full,
+            rows: self.rows,
+            cols: self.cols,
+            cursor: [self.row, self.col.min(self.cols - 1)],
+            alt_screen: self.alt.is_some(),
+            lines,
+        }
+    }
+
+    /// Change the dimensions. Content is kept top-left aligned.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.gen += 1;
+        let rows = usize::from(rows).clamp(1, MAX_ROWS);
+        let cols = usize::from(cols).clamp(1, MAX_COLS);
+        for line in &mut self.grid {
+            line.resize(cols, ' ');
+        }
+        self.grid.resize(rows, vec![' '; cols]);
+        if let Some(ref mut alt) = self.alt {
+            for line in &mut alt.grid {
+                line.resize(cols, ' ');
+            }
+            alt.grid.resize(rows, vec![' '; cols]);
+        }
+        self.rows = rows;
+        self.cols = cols;
+        self.row_gen = vec![self.gen; rows];
+        self.layout_gen = self.gen;
+        self.row = self.row.min(rows - 1);
+        self.col = self.col.min(cols - 1);
+        self.wrap_pending = false;
+        self.top = 0;
+        self.bottom = rows - 1;
+    }
+
+    /// Interpret the next chunk of PTY output.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.gen += 1;
+        for &b in bytes {
+            if !self.utf8.is_empty() || b >= 0x80 {
+                if let Some(c) = self.decode_utf8(b) {
+                    self.input(c);
+                }
+            } else {
+                self.input(char::from(b));
+            }
+        }
+    }
+
+    fn decode_utf8(&mut self, b: u8) -> Option<char> {
+        if self.utf8.is_empty() && b & 0xC0 == 0x80 {
+            return Some(char::REPLACEMENT_CHARACTER);
+        }
+        if !self.utf8.is_empty() && b & 0xC0 != 0x80 {
+            // Truncated sequence: drop it and reprocess this byte fresh.
+            self.utf8.clear();
+            return if b < 0x80 {
+                Some(char::from(b))
+            } else {
+                self.decode_utf8(b)
+            };
+        }
+        self.utf8.push(b);
+        let want = match self.utf8[0] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => {
+                self.utf8.clear();
+                return Some(char::REPLACEMENT_CHARACTER);
+            }
+        };
+        if self.utf8.len() < want {
+            return None;
+        }
+        let c = std::str::from_utf8(&self.utf8)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.utf8.clear();
+        Some(c)
+    }
+
+    fn input(&mut self, c: char) {
+        match self.parse {
+            Parse::Ground => self.ground(c),
+            Parse::Esc => self.escape(c),
+            Parse::EscIntermediate => self.parse = Parse::Ground,
+            Parse::Csi => self.csi(c),
+            Parse::Str => match c {
+                '\x07' => self.parse = Parse::Ground,
+                '\x1b' => self.parse = Parse::StrEsc,
+                _ => {}
+            },
+            Parse::StrEsc => {
+                self.parse = if c == '\\' { Parse::Ground } else { Parse::Str };
+            }
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.parse = Parse::Esc,
+            '\r' => {
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            '\n' | '\x0b' | '\x0c' => self.index(),
+            '\x08' => {
+                self.col = self.col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            '\t' => {
+                self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1);
+                self.wrap_pending = false;
+            }
+            c if c.is_control() => {}
+            c => self.print(c),
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.wrap_pending {
+            self.col = 0;
+            self.index();
+            self.wrap_pending = false;
+        }
+        self.grid[self.row][self.col] = c;
+        self.touch(self.row);
+        if self.col + 1 < self.cols {
+            self.col += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        self.parse = Parse::Ground;
+        match c {
+            '[' => {
+                self.params.clear();
+                self.param = None;
+                self.private = false;
+                self.parse = Parse::Csi;
+            }
+            ']' | 'P' | 'X' | '^' | '_' => self.parse = Parse::Str,
+            '(' | ')' | '*' | '+' | '#' | '%' => self.parse = Parse::EscIntermediate,
+            '7' => self.saved = (self.row, self.col),
+            '8' => self.restore_cursor(),
+            'D' => self.index(),
+            'E' => {
+                self.col = 0;
+                self.index();
+            }
+            'M' => self.reverse_index(),
+            'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                let digit = u16::try_from(u32::from(c) - u32::from('0')).unwrap_or(0);
+                self.param = Some(
+                    self.param
+                        .unwrap_or(0)
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+            }
+            ';' | ':' => {
+                if self.params.len() < MAX_PARAMS {
+                    self.params.push(self.param.unwrap_or(0));
+                }
+                self.param = None;
+            }
+            '?' | '>' | '=' | '<' => self.private = true,
+            '\x40'..='\x7e' => {
+                if let Some(p) = self.param.take() {
+                    if self.params.len() < MAX_PARAMS {
+                        self.params.push(p);
+                    }
+                }
+                self.parse = Parse::Ground;
+                self.dispatch(c);
+            }
+            // CAN/SUB abort; ESC starts a new sequence.
+            '\x18' | '\x1a' => self.parse = Parse::Ground,
+            '\x1b' => self.parse = Parse::Esc,
+            _ => {}
+        }
+    }
+
+    /// Parameter `i`, with 0/missing mapped to `default`.
+    fn arg(&self, i: usize, default: usize) -> usize {
+        match self.params.get(i) {
+            Some(&p) if p > 0 => usize::from(p),
+            _ => default,
+        }
+    }
+
+    fn dispatch(&mut self, c: char) {
+        if self.private {
+            if matches!(c, 'h' | 'l') {
+                for &mode in &self.params.clone() {
+                    if matches!(mode, 47 | 1047 | 1049) {
+                        if c == 'h' {
+                            self.enter_alt();
+                        } else {
+                            self.leave_alt();
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        let n = self.arg(0, 1);
+        match c {
+            'A' => self.move_to(self.row.saturating_sub(n), self.col),
+            'B' | 'e' => self.move_to(self.row + n, self.col),
+            'C' | 'a' => self.move_to(self.row, self.col + n),
+            'D' => self.move_to(self.row, self.col.saturating_sub(n)),
+            'E' => self.move_to(self.row + n, 0),
+            'F' => self.move_to(self.row.saturating_sub(n), 0),
+            'G' | '`' => self.move_to(self.row, n - 1),
+            'd' => self.move_to(n - 1, self.col),
+            'H' | 'f' => self.move_to(n - 1, self.arg(1, 1) - 1),
+            'J' => self.erase_display(self.arg(0, 0)),
+            'K' => self.erase_line(self.arg(0, 0)),
+            'L' => self.insert_lines(n),
+            'M' => self.delete_lines(n),
+            '@' => self.insert_chars(n),
+            'P' => self.delete_chars(n),
+            'X' => {
+                let end = (self.col + n).min(self.cols);
+                self.blank(self.row, self.col, end);
+            }
+            'S' => self.scroll_up(n),
+            'T' => self.scroll_down(n),
+            'r' => {
+                let top = self.arg(0, 1) - 1;
+                let bottom = self.arg(1, self.rows).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            's' => self.saved = (self.row, self.col),
+            'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn touch(&mut self, row: usize) {
+        self.row_gen[row] = self.gen;
+    }
+
+    fn touch_all(&mut self) {
+        self.row_gen.iter_mut().for_each(|g| *g = self.gen);
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+
+    fn restore_cursor(&mut self) {
+        let (row, col) = self.saved;
+        self.move_to(row, col);
+    }
+
+    fn blank(&mut self, row: usize, from: usize, to: usize) {
+        if from < to {
+            self.grid[row][from..to].fill(' ');
+            self.touch(row);
+        }
+    }
+
+    fn index(&mut self) {
+        if self.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    /// Scroll the region up `n` lines, blank lines entering at the bottom.
+    fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.bottom - self.top + 1);
+        let blank = vec![' '; self.cols];
+        self.grid[self.top..=self.bottom].rotate_left(n);
+        for line in &mut self.grid[self.bottom + 1 - n..=self.bottom] {
+            line.clone_from(&blank);
+        }
+        for r in self.top..=self.bottom {
+            self.touch(r);
+        }
+    }
+
+    /// Scroll the region down `n` lines, blank lines entering at the top.
+    fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.bottom - self.top + 1);
+        let blank = vec![' '; self.cols];
+        self.grid[self.top..=self.bottom].rotate_right(n);
+        for line in &mut self.grid[self.top..self.top + n] {
+            line.clone_from(&blank);
+        }
+        for r in self.top..=self.bottom {
+            self.touch(r);
+        }
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        if (self.top..=self.bottom).contains(&self.row) {
+            let top = self.top;
+            self.top = self.row;
+            self.scroll_down(n);
+            self.top = top;
+            self.col = 0;
+        }
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        if (self.top..=self.bottom).contains(&self.row) {
+            let top = self.top;
+            self.top = self.row;
+            self.scroll_up(n);
+            self.top = top;
+            self.col = 0;
+        }
+    }
+
+    fn insert_chars(&mut self, n: usize) {
+        let (row, col, cols) = (self.row, self.col, self.cols);
+        let n = n.min(cols - col);
+        self.grid[row][col..].rotate_right(n);
+        self.blank(row, col, col + n);
+    }
+
+    fn delete_chars(&mut self, n: usize) {
+        let (row, col, cols) = (self.row, self.col, self.cols);
+        let n = n.min(cols - col);
+        self.grid[row][col..].rotate_left(n);
+        self.blank(row, cols - n, cols);
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let (row, col, cols) = (self.row, self.col, self.cols);
+        match mode {
+            0 => self.blank(row, col, cols),
+            1 => self.blank(row, 0, col + 1),
+            _ => self.blank(row, 0, cols),
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let (row, cols) = (self.row, self.cols);
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for r in row + 1..self.rows {
+                    self.blank(r, 0, cols);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.blank(r, 0, cols);
+                }
+                self.erase_line(1);
+            }
+            _ => {
+                for r in 0..self.rows {
+                    self.blank(r, 0, cols);
+                }
+            }
+        }
+    }
+
+    fn enter_alt(&mut self) {
+        if self.alt.is_some() {
+            return;
+        }
+        let blank = vec![vec![' '; self.cols]; self.rows];
+        self.alt = Some(AltSaved {
+            grid: std::mem::replace(&mut self.grid, blank),
+            cursor: (self.row, self.col),
+        });
+        self.layout_gen = self.gen;
+        self.touch_all();
+    }
+
+    fn leave_alt(&mut self) {
+        if let Some(saved) = self.alt.take() {
+            self.grid = saved.grid;
+            self.move_to(saved.cursor.0, saved.cursor.1);
+            self.layout_gen = self.gen;
+            self.touch_all();
+        }
+    }
+
+    fn reset(&mut self) {
+        let fresh = Self::new(
+            u16::try_from(self.rows).unwrap_or(u16::MAX),
+            u16::try_from(self.cols).unwrap_or(u16::MAX),
+        );
+        let gen = self.gen;
+        *self = Self { gen, ..fresh };
+        self.layout_gen = gen;
+        self.touch_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(screen: &Screen) -> Vec<String> {
+        screen
+            .diff_since(None)
+            .lines
+            .into_iter()
+            .map(|l| l.text)
+            .collect()
+    }
+
+    #[test]
+    fn prints_wraps_and_scrolls() {
+        let mut s = Screen::new(3, 5);
+        s.feed(b"hello world\r\nx\r\ny");
+        assert_eq!(text(&s), vec!["d", "x", "y"]);
+        assert_eq!(s.diff_since(None).cursor, [2, 1]);
+    }
+
+    #[test]
+    fn cursor_addressing_and_erase() {
+        let mut s = Screen::new(4, 10);
+        s.feed(b"aaaaaaaaaa\r\nbbbbbbbbbb\r\ncccccccccc");
+        s.feed(b"\x1b[2;3HXY\x1b[K\x1b[3;1H\x1b[2P\x1b[1;5H\x1b[1K");
+        assert_eq!(text(&s), vec!["     aaaaa", "bbXY", "cccccccc", ""]);
+        s.feed(b"\x1b[2J");
+        assert!(text(&s).iter().all(String::is_empty));
+    }
+
+    #[test]
+    fn diff_returns_only_touched_rows() {
+        let mut s = Screen::new(5, 20);
+        s.feed(b"\x1b[1;1Hcpu 10%\x1b[3;1Hmem 40%");
+        let first = s.diff_since(None);
+        assert!(first.full);
+        s.feed(b"\x1b[3;5H55%");
+        let diff = s.diff_since(Some(first.token));
+        assert!(!diff.full);
+        assert_eq!(
+            diff.lines,
+            vec![ScreenLine {
+                row: 2,
+                text: "mem 55%".into()
+            }]
+        );
+        assert!(s.diff_since(Some(diff.token)).lines.is_empty());
+        // Unknown tokens fall back to a full read.
+        assert!(s.diff_since(Some(diff.token + 100)).full);
+    }
+
+    #[test]
+    fn alt_screen_and_resize_force_full() {
+        let mut s = Screen::new(3, 10);
+        s.feed(b"$ htop");
+        let token = s.diff_since(None).token;
+        s.feed(b"\x1b[?1049h\x1b[HPID USER");
+        let diff = s.diff_since(Some(token));
+        assert!(diff.full && diff.alt_screen);
+        assert_eq!(diff.lines[0].text, "PID USER");
+        s.feed(b"\x1b[?1049l");
+        assert_eq!(text(&s)[0], "$ htop");
+        let token = s.diff_since(None).token;
+        s.resize(4, 12);
+        let diff = s.diff_since(Some(token));
+        assert!(diff.full);
+        assert_eq!((diff.rows, diff.cols), (4, 12));
+    }
+
+    #[test]
+    fn scroll_region_and_insert_delete_lines() {
+        let mut s = Screen::new(4, 5);
+        s.feed(b"1\r\n2\r\n3\r\n4");
+        s.feed(b"\x1b[2;3r\x1b[3;1H\n");
+        assert_eq!(text(&s), vec!["1", "3", "", "4"]);
+        s.feed(b"\x1b[2;1H\x1b[L");
+        assert_eq!(text(&s), vec!["1", "", "3", "4"]);
+        s.feed(b"\x1b[r\x1b[1;1H\x1b[M");
+        assert_eq!(text(&s), vec!["", "3", "4", ""]);
+    }
+
+    #[test]
+    fn utf8_split_across_feeds_and_osc_skipped() {
+        let mut s = Screen::new(2, 10);
+        let bytes = "é€".as_bytes();
+        s.feed(&bytes[..1]);
+        s.feed(&bytes[1..4]);
+        s.feed(&bytes[4..]);
+        s.feed(b"\x1b]0;title\x07!");
+        assert_eq!(text(&s)[0], "é€!");
+    }
+}
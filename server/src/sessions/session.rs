@@ -16,20 +16,32 @@
 //! When `pty: true` is requested, the session uses a PTY instead of pipes.
 //! This enables TUI programs, `isatty()` detection, and terminal resize. The
 //! PTY merges stdout+stderr into a single stream.
+//!
+//! PTY output is also scanned for OSC title/cwd announcements (see
+//! [`super::osc`]); changes update [`ManagedSession::term_meta`] and are
+//! broadcast as `session.updated`.
+//!
+//! Commands sent with `session.exec` and those the shell reports through
+//! semantic-prompt marks are kept in a [`CommandHistory`].
+//!
+//! PTY output also drives a [`Screen`] model, so `session.read_diff` can
+//! return the visible screen (or just its changed rows) instead of raw output.
 
-use std::io::{Read, Write};
-use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
-use std::os::unix::io::RawFd;
 use std::sync::Arc;
 
-use tokio::io::unix::AsyncFd;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Child;
 use tokio::sync::{broadcast, mpsc, Mutex};
 use tracing::{error, info};
 
 use super::buffer::{OutputBuffer, OutputStream};
-use crate::shell::pty;
+use super::decode::{Charset, OutputDecoder, OutputEncoding};
+use super::history::{CommandHistory, HistoryEntry};
+use super::journal::now_ms;
+use super::osc::{OscScanner, TermMeta};
+use super::screen::{Screen, ScreenDiff};
+use crate::shell::process::{signal_group, SIGKILL, SIGTERM};
+use crate::shell::pty::{self, PtyChild, PtyMaster};
 
 /// Session lifecycle status.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -54,28 +66,50 @@ pub struct ManagedSession {
     stdin_tx: mpsc::Sender<Vec<u8>>,
     /// Handles to the background I/O tasks — aborted on kill.
     tasks: Vec<tokio::task::JoinHandle<()>>,
-    /// PTY master fd (only set for PTY sessions). Kept alive for resize.
-    pty_master: Option<OwnedFd>,
+    /// PTY master (only set for PTY sessions). Kept alive for resize.
+    pty_master: Option<PtyMaster>,
+    /// Title and cwd last announced by the program in the PTY.
+    term_meta: Arc<std::sync::Mutex<TermMeta>>,
+    /// Visible screen contents (only set for PTY sessions).
+    screen: Option<Arc<std::sync::Mutex<Screen>>>,
+    /// Commands run in the session.
+    history: Arc<std::sync::Mutex<CommandHistory>>,
+    /// Output encoding, read by the reader tasks on every read.
+    charset: Arc<std::sync::Mutex<Charset>>,
 }
 
-impl ManagedSession {
-    fn set_nonblocking(fd: RawFd) -> Result<(), String> {
-        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
-        if flags < 0 {
-            return Err(format!(
-                "fcntl(F_GETFL) failed for fd {fd}: {}",
-                std::io::Error::last_os_error()
-            ));
-        }
-        if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
-            return Err(format!(
-                "fcntl(F_SETFL,O_NONBLOCK) failed for fd {fd}: {}",
-                std::io::Error::last_os_error()
-            ));
-        }
-        Ok(())
+/// Decode one read into `buffer`. The first time the output fails to decode
+/// as UTF-8, record the charset guess and say so in a system entry.
+async fn push_decoded(
+    buffer: &Mutex<OutputBuffer>,
+    charset: &std::sync::Mutex<Charset>,
+    decoder: &mut OutputDecoder,
+    stream: OutputStream,
+    bytes: &[u8],
+) {
+    let encoding = charset
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .encoding;
+    let out = decoder.decode(encoding, bytes);
+    let mut buffer = buffer.lock().await;
+    buffer.push_raw(stream, out.text, out.raw);
+    if let Some(hint) = out.hint {
+        charset
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .hint = Some(hint);
+        let suggest = if hint == "latin1" { "latin1" } else { "raw" };
+        buffer.push(
+            OutputStream::System,
+            format!(
+                "Output is not valid UTF-8 (looks like {hint}); set the session encoding to \"{suggest}\" to keep the original bytes"
+            ),
+        );
     }
+}
 
+impl ManagedSession {
     /// Spawn a new pipe-backed managed session from an already-created `Child`.
     ///
     /// Takes ownership of the child's stdio handles and spawns four background
@@ -85,6 +119,7 @@ impl ManagedSession {
         session_id: String,
         mut child: Child,
         buffer_size: usize,
+        encoding: OutputEncoding,
         exit_events: Option<broadcast::Sender<serde_json::Value>>,
     ) -> Result<Self, String> {
         let process_id = child.id().unwrap_or(0);
@@ -100,6 +135,10 @@ impl ManagedSession {
         let buffer = Arc::new(Mutex::new(OutputBuffer::new(buffer_size)));
         let status = Arc::new(Mutex::new(SessionStatus::Running));
         let exit_code: Arc<Mutex<Option<i32>>> = Arc::new(Mutex::new(None));
+        let charset = Arc::new(std::sync::Mutex::new(Charset {
+            encoding,
+            hint: None,
+        }));
 
         // stdin writer task
         let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(64);
@@ -125,15 +164,23 @@ impl ManagedSession {
         // stdout reader task — chunk-based for immediate delivery
         let sid_out = session_id.clone();
         let buf_out = Arc::clone(&buffer);
+        let charset_out = Arc::clone(&charset);
         let stdout_task = tokio::spawn(async move {
             let mut stdout = stdout;
             let mut tmp = [0u8; 4096];
+            let mut decoder = OutputDecoder::default();
             loop {
                 match stdout.read(&mut tmp).await {
                     Ok(0) | Err(_) => break,
                     Ok(n) => {
-                        let data = String::from_utf8_lossy(&tmp[..n]).into_owned();
-                        buf_out.lock().await.push(OutputStream::Stdout, data);
+                        push_decoded(
+                            &buf_out,
+                            &charset_out,
+                            &mut decoder,
+                            OutputStream::Stdout,
+                            &tmp[..n],
+                        )
+                        .await;
                     }
                 }
             }
@@ -143,15 +190,23 @@ impl ManagedSession {
         // stderr reader task — chunk-based
         let sid_err = session_id.clone();
         let buf_err = Arc::clone(&buffer);
+        let charset_err = Arc::clone(&charset);
         let stderr_task = tokio::spawn(async move {
             let mut stderr = stderr;
             let mut tmp = [0u8; 4096];
+            let mut decoder = OutputDecoder::default();
             loop {
                 match stderr.read(&mut tmp).await {
                     Ok(0) | Err(_) => break,
                     Ok(n) => {
-                        let data = String::from_utf8_lossy(&tmp[..n]).into_owned();
-                        buf_err.lock().await.push(OutputStream::Stderr, data);
+                        push_decoded(
+                            &buf_err,
+                            &charset_err,
+                            &mut decoder,
+                            OutputStream::Stderr,
+                            &tmp[..n],
+                        )
+                        .await;
                     }
                 }
             }
@@ -208,6 +263,10 @@ impl ManagedSession {
             stdin_tx,
             tasks: vec![stdin_task, stdout_task, stderr_task, exit_task],
             pty_master: None,
+            term_meta: Arc::default(),
+            screen: None,
+            history: Arc::default(),
+            charset,
         })
     }
 
@@ -215,12 +274,17 @@ impl ManagedSession {
     ///
     /// Only 3 background tasks: stdin writer (to PTY master), output reader
     /// (from PTY master), and exit watcher.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn_pty(
         session_id: String,
-        mut child: Child,
-        pty_master: OwnedFd,
+        mut child: PtyChild,
+        pty_master: PtyMaster,
+        rows: u16,
+        cols: u16,
         buffer_size: usize,
+        encoding: OutputEncoding,
         exit_events: Option<broadcast::Sender<serde_json::Value>>,
+        updates: Option<broadcast::Sender<serde_json::Value>>,
     ) -> Result<Self, String> {
         let process_id = child.id().unwrap_or(0);
         // For PTY sessions the child is a session leader via setsid(), so
@@ -231,58 +295,16 @@ impl ManagedSession {
         let status = Arc::new(Mutex::new(SessionStatus::Running));
         let exit_code: Arc<Mutex<Option<i32>>> = Arc::new(Mutex::new(None));
 
-        let master_raw: RawFd = pty_master.as_raw_fd();
-
-        // Dup the master fd: one for writing, one for reading, one kept for resize
-        let writer_fd: RawFd = unsafe { libc::dup(master_raw) };
-        if writer_fd < 0 {
-            return Err(format!(
-                "dup() failed for PTY master writer: {}",
-                std::io::Error::last_os_error()
-            ));
-        }
-        let reader_fd: RawFd = unsafe { libc::dup(master_raw) };
-        if reader_fd < 0 {
-            // Close the first dup'd fd before returning
-            unsafe {
-                libc::close(writer_fd);
-            }
-            return Err(format!(
-                "dup() failed for PTY master reader: {}",
-                std::io::Error::last_os_error()
-            ));
-        }
-
-        Self::set_nonblocking(writer_fd)?;
-        Self::set_nonblocking(reader_fd)?;
-
-        // SAFETY: we own these file descriptors via dup.
-        let master_write = AsyncFd::new(unsafe { std::fs::File::from_raw_fd(writer_fd) })
-            .map_err(|e| format!("AsyncFd::new(writer) failed: {e}"))?;
-        let master_read = AsyncFd::new(unsafe { std::fs::File::from_raw_fd(reader_fd) })
-            .map_err(|e| format!("AsyncFd::new(reader) failed: {e}"))?;
+        // Separate handles for reading and writing; the master is kept for resize.
+        let (mut master_read, mut master_write) =
+            pty::split(&pty_master).map_err(|e| format!("Failed to open PTY master: {e}"))?;
 
         // stdin writer task: mpsc → PTY master (write side)
         let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(64);
         let stdin_task = tokio::spawn(async move {
-            let writer = master_write;
             while let Some(data) = stdin_rx.recv().await {
-                let mut written = 0usize;
-                while written < data.len() {
-                    let Ok(mut guard) = writer.writable().await else {
-                        return;
-                    };
-                    match guard.try_io(|inner| inner.get_ref().write(&data[written..])) {
-                        Ok(Ok(0)) => return,
-                        Ok(Ok(n)) => written += n,
-                        Ok(Err(e)) => {
-                            if e.kind() == std::io::ErrorKind::WouldBlock {
-                                continue;
-                            }
-                            return;
-                        }
-                        Err(_would_block) => {}
-                    }
+                if master_write.write_all(&data).await.is_err() {
+                    return;
                 }
             }
         });
@@ -290,33 +312,61 @@ impl ManagedSession {
         // Output reader task: PTY master (read side) → buffer
         let sid_out = session_id.clone();
         let buf_out = Arc::clone(&buffer);
+        let charset = Arc::new(std::sync::Mutex::new(Charset {
+            encoding,
+            hint: None,
+        }));
+        let charset_out = Arc::clone(&charset);
+        let term_meta: Arc<std::sync::Mutex<TermMeta>> = Arc::default();
+        let meta_out = Arc::clone(&term_meta);
+        let screen = Arc::new(std::sync::Mutex::new(Screen::new(rows, cols)));
+        let screen_out = Arc::clone(&screen);
+        let history: Arc<std::sync::Mutex<CommandHistory>> = Arc::default();
+        let history_out = Arc::clone(&history);
         let output_task = tokio::spawn(async move {
+            let mut scanner = OscScanner::default();
+            let mut announced = Vec::new();
+            let mut decoder = OutputDecoder::default();
+            let mut tmp = [0u8; 4096];
             loop {
-                let Ok(mut guard) = master_read.readable().await else {
-                    break;
-                };
-                match guard.try_io(|inner| {
-                    let mut tmp = [0u8; 4096];
-                    inner
-                        .get_ref()
-                        .read(&mut tmp)
-                        .map(|n| (n, tmp[..n].to_vec()))
-                }) {
-                    Ok(Ok((0, _))) => break,
-                    Ok(Ok((n, bytes))) => {
-                        let data = String::from_utf8_lossy(&bytes[..n]).into_owned();
-                        buf_out.lock().await.push(OutputStream::Stdout, data);
-                    }
-                    Ok(Err(e)) => {
-                        if e.raw_os_error() == Some(libc::EIO) {
-                            break;
-                        }
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            continue;
+                match master_read.read(&mut tmp).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        let bytes = &tmp[..n];
+                        scanner.feed(bytes, &mut announced);
+                        screen_out
+                            .lock()
+                            .unwrap_or_else(std::sync::PoisonError::into_inner)
+                            .feed(bytes);
+                        push_decoded(
+                            &buf_out,
+                            &charset_out,
+                            &mut decoder,
+                            OutputStream::Stdout,
+                            bytes,
+                        )
+                        .await;
+                        if !announced.is_empty() {
+                            history_out
+                                .lock()
+                                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                                .apply(&announced, now_ms());
+                            let changed = {
+                                let mut meta = meta_out
+                                    .lock()
+                                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                                meta.apply(announced.drain(..)).then(|| meta.clone())
+                            };
+                            if let (Some(meta), Some(tx)) = (changed, &updates) {
+                                let _ = tx.send(serde_json::json!({
+                                    "type": "session.updated",
+                                    "session_id": sid_out,
+                                    "title": meta.title,
+                                    "cwd": meta.cwd,
+                                }));
+                            }
                         }
-                        break;
                     }
-                    Err(_would_block) => {}
                 }
             }
             info!("Session {sid_out} PTY output closed");
@@ -363,8 +413,8 @@ impl ManagedSession {
             }
         });
 
-        // pty_master OwnedFd stays alive for resize operations. The dup'd fds
-        // for read/write are independent and will be closed when their tasks end.
+        // pty_master stays alive for resize operations. The reader and writer
+        // are independent handles, closed when their tasks end.
         Ok(ManagedSession {
             pid: process_id,
             pgid: process_group_id,
@@ -374,6 +424,10 @@ impl ManagedSession {
             stdin_tx,
             tasks: vec![stdin_task, output_task, exit_task],
             pty_master: Some(pty_master),
+            term_meta,
+            screen: Some(screen),
+            history,
+            charset,
         })
     }
 
@@ -389,6 +443,10 @@ impl ManagedSession {
             stdin_tx,
             tasks: Vec::new(),
             pty_master: None,
+            term_meta: Arc::default(),
+            screen: None,
+            history: Arc::default(),
+            charset: Arc::default(),
         }
     }
 
@@ -434,32 +492,26 @@ impl ManagedSession {
     /// SIGINT only reaches the foreground job. In non-PTY (pipe) sessions there
     /// is no TTY layer, so the signal hits the shell itself and will typically
     /// terminate the entire session.
+    ///
+    /// ConPTY has no job control either, so on Windows SIGINT to a PTY session
+    /// is typed as Ctrl-C for the console to deliver.
     pub fn send_signal(&self, signal: i32) -> Result<(), String> {
-        #[allow(clippy::cast_possible_wrap)]
-        let pgid = self.pgid as i32;
-        // kill(-pgid, signal) sends to all processes in the group
-        let ret = unsafe { libc::kill(-pgid, signal) };
-        if ret == 0 {
-            Ok(())
-        } else {
-            Err(format!(
-                "kill(-{}, {}) failed: {}",
-                self.pgid,
-                signal,
-                std::io::Error::last_os_error()
-            ))
+        #[cfg(windows)]
+        if signal == crate::shell::process::SIGINT && self.is_pty() {
+            return self
+                .stdin_tx
+                .try_send(vec![0x03])
+                .map_err(|_| "Session stdin closed".to_string());
         }
+        signal_group(self.pgid, signal)
+            .map_err(|e| format!("kill(-{}, {}) failed: {e}", self.pgid, signal))
     }
 
     /// Kill the session immediately by sending SIGKILL to the process group
     /// and aborting all background tasks.
     pub fn kill(&self) {
-        #[allow(clippy::cast_possible_wrap)]
-        let pgid = self.pgid as i32;
-        if pgid > 0 {
-            unsafe {
-                libc::kill(-pgid, libc::SIGKILL);
-            }
+        if self.pgid > 0 {
+            let _ = signal_group(self.pgid, SIGKILL);
         }
         for task in &self.tasks {
             task.abort();
@@ -469,9 +521,7 @@ impl ManagedSession {
     /// Gracefully kill the session: SIGTERM first, wait up to 3 s for the
     /// process to exit, then SIGKILL if it's still running.
     pub async fn graceful_kill(&self) {
-        #[allow(clippy::cast_possible_wrap)]
-        let pgid = self.pgid as i32;
-        if pgid <= 0 {
+        if self.pgid == 0 {
             // Archived or already-dead session — just abort tasks.
             for task in &self.tasks {
                 task.abort();
@@ -480,9 +530,7 @@ impl ManagedSession {
         }
 
         // Phase 1: SIGTERM
-        unsafe {
-            libc::kill(-pgid, libc::SIGTERM);
-        }
+        let _ = signal_group(self.pgid, SIGTERM);
 
         // Phase 2: poll status for up to 3 seconds
         let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(3);
@@ -492,9 +540,7 @@ impl ManagedSession {
             }
             if tokio::time::Instant::now() >= deadline {
                 // Still running — force kill
-                unsafe {
-                    libc::kill(-pgid, libc::SIGKILL);
-                }
+                let _ = signal_group(self.pgid, SIGKILL);
                 break;
             }
             tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -508,7 +554,14 @@ impl ManagedSession {
     /// Resize the PTY (no-op error for pipe sessions).
     pub fn resize(&self, rows: u16, cols: u16) -> Result<(), String> {
         if let Some(ref master) = self.pty_master {
-            pty::resize_pty(master, rows, cols).map_err(|e| e.to_string())
+            pty::resize_pty(master, rows, cols).map_err(|e| e.to_string())?;
+            if let Some(ref screen) = self.screen {
+                screen
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .resize(rows, cols);
+            }
+            Ok(())
         } else {
             Err("Not a PTY session".into())
         }
@@ -525,4 +578,107 @@ impl ManagedSession {
     pub fn is_pty(&self) -> bool {
         self.pty_master.is_some()
     }
+
+    /// Title and cwd last announced via OSC (both `None` for pipe sessions).
+    pub fn term_meta(&self) -> TermMeta {
+        self.term_meta
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Current output encoding and charset guess.
+    pub fn charset(&self) -> Charset {
+        *self
+            .charset
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Switch the output encoding; applies from the next read.
+    pub fn set_encoding(&self, encoding: OutputEncoding) {
+        self.charset
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .encoding = encoding;
+    }
+
+    /// Record a command sent to the session with `session.exec`.
+    pub fn record_command(&self, command: &str) {
+        self.history
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .record_exec(command, now_ms());
+    }
+
+    /// The last `limit` commands, oldest first, and the total ever recorded.
+    pub fn command_history(&self, limit: usize) -> (Vec<HistoryEntry>, u64) {
+        let history = self
+            .history
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        (history.recent(limit), history.total())
+    }
+
+    /// Screen rows changed since `since` (see [`Screen::diff_since`]). `None`
+    /// for pipe sessions.
+    pub fn screen_diff(&self, since: Option<u64>) -> Option<ScreenDiff> {
+        self.screen.as_ref().map(|screen| {
+            screen
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .diff_since(since)
+        })
+    }
+
+    /// Whether the PTY currently has echo enabled (`None` for pipe sessions).
+    pub fn echo_enabled(&self) -> Option<bool> {
+        self.pty_master
+            .as_ref()
+            .and_then(|master| pty::echo_enabled(master).ok())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    async fn output(session: &ManagedSession) -> String {
+        let (entries, _) = session.buffer.lock().await.read_since(0);
+        entries.into_iter().map(|e| e.data).collect()
+    }
+
+    #[tokio::test]
+    async fn pty_session_round_trips_and_dies_with_its_group() {
+        let pair = pty::allocate_pty(24, 80).unwrap();
+        let child = pty::spawn_shell_pty(&pair, "/bin/sh", "/", None, None).unwrap();
+        let session = ManagedSession::spawn_pty(
+            "t".to_string(),
+            child,
+            pair.master,
+            24,
+            80,
+            1000,
+            OutputEncoding::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        session.write_stdin("echo sctl-$((6*7))\n").await.unwrap();
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while !output(&session).await.contains("sctl-42") {
+            assert!(tokio::time::Instant::now() < deadline, "no output");
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+        session.resize(40, 120).unwrap();
+        assert!(crate::shell::process::process_alive(session.pid));
+
+        session.send_signal(SIGKILL).unwrap();
+        while *session.status.lock().await != SessionStatus::Exited {
+            assert!(tokio::time::Instant::now() < deadline, "still running");
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+        assert!(session.send_signal(0).is_err());
+        session.abort_tasks();
+    }
 }
@@ -0,0 +1,153 @@
+//! Named `session.start` presets from `[session_templates]`.
+//!
+//! A client passes `"template": "<name>"` with `session.start` (WebSocket or
+//! relay) or `POST /api/sessions`. Fields the request leaves out are filled
+//! from the template before it is handled, so explicit fields always win;
+//! `env` is merged key by key. With `name_prefix` and no `name`, the session
+//! is named `<prefix>-<n>` with the lowest `n` not already in use.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::config::SessionTemplateConfig;
+use crate::AppState;
+
+/// `msg` with the fields of the template it names filled in. Requests
+/// without a `template` are returned unchanged.
+pub async fn expand(state: &AppState, msg: &Value) -> Result<Value, String> {
+    let mut msg = msg.clone();
+    if msg.get("template").is_none() {
+        return Ok(msg);
+    }
+    let taken: Vec<String> = state
+        .session_manager
+        .list_sessions()
+        .await
+        .into_iter()
+        .filter_map(|s| s.name)
+        .collect();
+    apply(&state.config.session_templates, &mut msg, &taken)?;
+    Ok(msg)
+}
+
+/// Fill the fields of `msg` that are missing from the named template.
+/// `taken` holds the names of existing sessions.
+pub fn apply(
+    templates: &BTreeMap<String, SessionTemplateConfig>,
+    msg: &mut Value,
+    taken: &[String],
+) -> Result<(), String> {
+    let Some(obj) = msg.as_object_mut() else {
+        return Err("Request must be a JSON object".to_string());
+    };
+    let name = match obj.get("template") {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::String(name)) => name.clone(),
+        Some(_) => return Err("template must be a string".to_string()),
+    };
+    let template = templates
+        .get(&name)
+        .ok_or_else(|| format!("Unknown session template '{name}'"))?;
+
+    let mut fill = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            if obj.get(key).is_none_or(Value::is_null) {
+                obj.insert(key.to_string(), value);
+            }
+        }
+    };
+    fill("shell", template.shell.as_ref().map(|v| json!(v)));
+    fill(
+        "working_dir",
+        template.working_dir.as_ref().map(|v| json!(v)),
+    );
+    fill("pty", template.pty.map(|v| json!(v)));
+    fill("rows", template.rows.map(|v| json!(v)));
+    fill("cols", template.cols.map(|v| json!(v)));
+    fill("idle_timeout", template.idle_timeout.map(|v| json!(v)));
+    if let Some(prefix) = &template.name_prefix {
+        fill("name", Some(json!(next_name(prefix, taken))));
+    }
+
+    if !template.env.is_empty() {
+        let env = obj
+            .entry("env")
+            .and_modify(|v| {
+                if v.is_null() {
+                    *v = Value::Object(Map::new());
+                }
+            })
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Some(env) = env.as_object_mut() else {
+            return Err("env must be an object".to_string());
+        };
+        for (key, value) in &template.env {
+            env.entry(key.clone()).or_insert_with(|| json!(value));
+        }
+    }
+    Ok(())
+}
+
+/// `<prefix>-<n>` with the lowest `n` from 1 not in `taken`.
+fn next_name(prefix: &str, taken: &[String]) -> String {
+    // Among `taken.len() + 1` candidates at least one is free.
+    (1..=taken.len() + 1)
+        .map(|n| format!("{prefix}-{n}"))
+        .find(|name| !taken.contains(name))
+        .unwrap_or_else(|| prefix.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_fills_missing_fields_only() {
+        let mut templates = BTreeMap::new();
+        templates.insert(
+            "build".to_string(),
+            SessionTemplateConfig {
+                shell: Some("/bin/bash".to_string()),
+                working_dir: Some("/srv/build".to_string()),
+                env: BTreeMap::from([
+                    ("CC".to_string(), "gcc".to_string()),
+                    ("JOBS".to_string(), "4".to_string()),
+                ]),
+                pty: Some(true),
+                rows: Some(50),
+                cols: None,
+                idle_timeout: Some(600),
+                name_prefix: Some("build".to_string()),
+            },
+        );
+        let taken = vec!["build-1".to_string()];
+
+        let mut msg = json!({
+            "type": "session.start",
+            "template": "build",
+            "working_dir": "/tmp",
+            "env": { "JOBS": "8" },
+        });
+        apply(&templates, &mut msg, &taken).unwrap();
+        assert_eq!(msg["shell"], "/bin/bash");
+        assert_eq!(msg["working_dir"], "/tmp");
+        assert_eq!(msg["pty"], true);
+        assert_eq!(msg["rows"], 50);
+        assert!(msg.get("cols").is_none());
+        assert_eq!(msg["idle_timeout"], 600);
+        assert_eq!(msg["env"], json!({ "CC": "gcc", "JOBS": "8" }));
+        assert_eq!(msg["name"], "build-2");
+
+        let mut named = json!({ "template": "build", "name": "mine" });
+        apply(&templates, &mut named, &taken).unwrap();
+        assert_eq!(named["name"], "mine");
+
+        let mut plain = json!({ "type": "session.start" });
+        apply(&templates, &mut plain, &taken).unwrap();
+        assert_eq!(plain, json!({ "type": "session.start" }));
+
+        let mut unknown = json!({ "template": "nope" });
+        assert!(apply(&templates, &mut unknown, &taken).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! SFTP bridge for standard `sftp`/`scp` clients.
+//!
+//! Many workflows can't speak gawdxfer, but OpenSSH's clients can talk SFTP
+//! to anything that carries the byte stream. With `[sftp]` configured, each
+//! connection to `GET /api/sftp` (a WebSocket) runs the system's
+//! `sftp-server` and pipes its stdin/stdout through binary messages. Through
+//! a relay, `GET /d/{serial}/api/sftp` carries the same stream over a
+//! forwarded stream ([`crate::tunnel::forward`]), opened with
+//! `tunnel.sftp.open`.
+//!
+//! On the client side, `sctl sftp` ([`bridge_stdio`]) is the other end: it
+//! pipes its own stdin/stdout to the WebSocket, so it can stand in for
+//! `sftp-server` (`sftp -D`) or for `ssh` (`scp -S`, `sshfs -o
+//! ssh_command=`). Access is decided by sctl's API key, and the server runs
+//! as the sctl user.
+
+use std::process::Stdio;
+
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tracing::{debug, warn};
+
+use crate::config::SftpConfig;
+
+/// Where OpenSSH packages put `sftp-server` (Debian, Fedora, Arch, `OpenWrt`,
+/// Alpine).
+pub const SERVER_PATHS: &[&str] = &[
+    "/usr/lib/openssh/sftp-server",
+    "/usr/libexec/openssh/sftp-server",
+    "/usr/lib/ssh/sftp-server",
+    "/usr/lib/sftp-server",
+    "/usr/libexec/sftp-server",
+];
+
+const READ_BUF_SIZE: usize = 32 * 1024;
+
+/// Start `sftp-server` with piped stdin/stdout. Its stderr goes to the log.
+///
+/// # Errors
+///
+/// No server found, or it failed to start.
+pub fn spawn(config: &SftpConfig) -> Result<(Child, ChildStdin, ChildStdout), String> {
+    let server = config
+        .server
+        .clone()
+        .or_else(|| {
+            SERVER_PATHS
+                .iter()
+                .find(|p| std::path::Path::new(p).exists())
+                .map(ToString::to_string)
+        })
+        .ok_or_else(|| "No sftp-server found; set [sftp] server".to_string())?;
+    let mut cmd = Command::new(&server);
+    // Errors only, to stderr
+    cmd.args(["-e", "-l", "ERROR"]);
+    if config.read_only {
+        cmd.arg("-R");
+    }
+    if let Some(dir) = &config.start_dir {
+        cmd.args(["-d", dir]);
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start {server}: {e}"))?;
+    let stdin = child.stdin.take().expect("piped");
+    let stdout = child.stdout.take().expect("piped");
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("sftp-server: {line}");
+            }
+        });
+    }
+    Ok((child, stdin, stdout))
+}
+
+/// Pipe a `GET /api/sftp` WebSocket to a running `sftp-server` until either
+/// side closes.
+pub async fn serve_ws(
+    socket: WebSocket,
+    mut child: Child,
+    mut stdin: ChildStdin,
+    mut stdout: ChildStdout,
+) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let upstream = async {
+        while let Some(Ok(msg)) = ws_rx.next().await {
+            let data = match msg {
+                Message::Binary(data) => data,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if stdin.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    };
+    let downstream = async {
+        let mut buf = vec![0u8; READ_BUF_SIZE];
+        loop {
+            let n = match stdout.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if ws_tx
+                .send(Message::Binary(buf[..n].to_vec().into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = ws_tx.close().await;
+    };
+    tokio::select! {
+        () = upstream => {}
+        () = downstream => {}
+    }
+    let _ = child.kill().await;
+    debug!("SFTP session closed");
+}
+
+/// `sctl sftp`: pipe stdin/stdout to the SFTP WebSocket at `url`.
+///
+/// # Errors
+///
+/// The connection failed or broke.
+pub async fn bridge_stdio(url: &str) -> Result<(), String> {
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let upstream = async {
+        let mut stdin = tokio::io::stdin();
+        let mut buf = vec![0u8; READ_BUF_SIZE];
+        loop {
+            let n = stdin.read(&mut buf).await.map_err(|e| e.to_string())?;
+            if n == 0 {
+                break;
+            }
+            ws_tx
+                .send(Message::Binary(buf[..n].to_vec().into()))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let _ = ws_tx.close().await;
+        Ok::<(), String>(())
+    };
+    let downstream = async {
+        let mut stdout = tokio::io::stdout();
+        while let Some(msg) = ws_rx.next().await {
+            match msg.map_err(|e| e.to_string())? {
+                Message::Binary(data) => {
+                    stdout.write_all(&data).await.map_err(|e| e.to_string())?;
+                    stdout.flush().await.map_err(|e| e.to_string())?;
+                }
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok::<(), String>(())
+    };
+    tokio::select! {
+        r = upstream => r,
+        r = downstream => r,
+    }
+}
+
+/// The host an `ssh`-style command line is for, as `scp -S` and
+/// `sshfs -o ssh_command=` invoke it: `[options] [--] [user@]host command...`.
+/// Options that take a value are skipped with it.
+pub fn ssh_host(args: &[String]) -> Option<&str> {
+    // ssh options that take an argument
+    const WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            return iter.next().map(|h| strip_user(h));
+        }
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                // `-p22` carries its value; `-p 22` takes the next argument
+                let takes_value = flags
+                    .char_indices()
+                    .find(|&(_, c)| WITH_VALUE.contains(c))
+                    .is_some_and(|(i, _)| i + 1 == flags.len());
+                if takes_value {
+                    iter.next();
+                }
+            }
+            _ => return Some(strip_user(arg)),
+        }
+    }
+    None
+}
+
+fn strip_user(host: &str) -> &str {
+    host.rsplit_once('@').map_or(host, |(_, h)| h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn host_from_ssh_command_lines() {
+        // scp -S (OpenSSH 9, SFTP mode)
+        let scp = "-x -oPermitLocalCommand=no -oClearAllForwardings=yes -oRemoteCommand=none \
+                   -oRequestTTY=no -oForwardX11=no -l root -s -- DEV-1 sftp";
+        assert_eq!(ssh_host(&args(scp)), Some("DEV-1"));
+        // sshfs
+        assert_eq!(
+            ssh_host(&args(
+                "-x -a -oClearAllForwardings=yes -p 22 root@DEV-2 -s sftp"
+            )),
+            Some("DEV-2")
+        );
+        assert_eq!(ssh_host(&args("-p22 DEV-3 -s sftp")), Some("DEV-3"));
+        assert_eq!(ssh_host(&args("-x -s")), None);
+    }
+}
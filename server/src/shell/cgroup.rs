@@ -0,0 +1,325 @@
+//! Per-session CPU, memory and process limits through cgroup v2.
+//!
+//! With `[session_limits]` configured, every session (terminal or job) gets
+//! its own cgroup `<cgroup>/session-<id>` before its shell starts: the child
+//! joins it in `pre_exec`, so everything the shell forks is counted and
+//! capped. A runaway `make -j` then hits `memory.max` and the kernel's OOM
+//! killer takes out that session's processes (`memory.oom.group`), instead of
+//! whatever the device can least afford to lose — often sctl itself.
+//!
+//! The configured values apply to every session. A `session.start` (or
+//! `POST /api/sessions`) can pass `"limits": {"cpu_percent", "memory_mb",
+//! "pids_max"}` to run tighter; values above the configured ones are capped
+//! to them. `cpu_percent` is a share of one CPU, so `200` is two cores.
+//!
+//! When the session goes away its cgroup is emptied with `cgroup.kill`
+//! (catching anything that left the process group) and removed.
+
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config::SessionLimitsConfig;
+
+/// `cpu.max` period in microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Controllers sessions are limited with.
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// Limits for one session. `None` means no limit from this source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Percent of one CPU.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Parse the optional `limits` field of a `session.start` message.
+    pub fn from_message(msg: &Value) -> Result<Option<Self>, String> {
+        match msg.get("limits") {
+            None | Some(Value::Null) => Ok(None),
+            Some(v) => {
+                let limits: Self = serde_json::from_value(v.clone())
+                    .map_err(|e| format!("Invalid limits: {e}"))?;
+                if limits.cpu_percent == Some(0)
+                    || limits.memory_mb == Some(0)
+                    || limits.pids_max == Some(0)
+                {
+                    return Err("Invalid limits: values must be at least 1".to_string());
+                }
+                Ok(Some(limits))
+            }
+        }
+    }
+}
+
+/// The parent cgroup sessions are created under.
+pub struct Cgroups {
+    root: PathBuf,
+    defaults: ResourceLimits,
+    /// Controllers enabled for session cgroups.
+    available: Vec<&'static str>,
+}
+
+impl Cgroups {
+    /// Create (or reuse) the parent cgroup, enable the controllers for its
+    /// children and remove session cgroups left by an earlier run.
+    pub fn open(config: &SessionLimitsConfig) -> Result<Self, String> {
+        let root = PathBuf::from(&config.cgroup);
+        let parent = root
+            .parent()
+            .filter(|p| p.join("cgroup.controllers").exists())
+            .ok_or_else(|| format!("{} is not inside a cgroup v2 hierarchy", root.display()))?;
+        if !root.exists() {
+            fs::create_dir(&root).map_err(|e| format!("{}: {e}", root.display()))?;
+        }
+
+        // The parent must pass the controllers down before ours can; on most
+        // systems it already does, so failing here is not fatal.
+        let _ = enable_controllers(parent);
+        let available = enable_controllers(&root);
+        let defaults = ResourceLimits {
+            cpu_percent: (config.cpu_percent > 0).then_some(config.cpu_percent),
+            memory_mb: (config.memory_mb > 0).then_some(config.memory_mb),
+            pids_max: (config.pids_max > 0).then_some(config.pids_max),
+        };
+        let cgroups = Self {
+            root,
+            defaults,
+            available,
+        };
+        cgroups.check(&defaults)?;
+
+        if let Ok(entries) = fs::read_dir(&cgroups.root) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with("session-") {
+                    remove(&entry.path());
+                }
+            }
+        }
+        info!(
+            cgroup = %cgroups.root.display(),
+            controllers = ?cgroups.available,
+            "Session limits enabled"
+        );
+        Ok(cgroups)
+    }
+
+    /// The configured limits tightened by a session's own.
+    pub fn effective(&self, requested: Option<&ResourceLimits>) -> ResourceLimits {
+        let Some(requested) = requested else {
+            return self.defaults;
+        };
+        let tighter = |configured: Option<u64>, asked: Option<u64>| match (configured, asked) {
+            (Some(c), Some(a)) => Some(c.min(a)),
+            (c, a) => c.or(a),
+        };
+        ResourceLimits {
+            cpu_percent: tighter(
+                self.defaults.cpu_percent.map(u64::from),
+                requested.cpu_percent.map(u64::from),
+            )
+            .and_then(|v| u32::try_from(v).ok()),
+            memory_mb: tighter(self.defaults.memory_mb, requested.memory_mb),
+            pids_max: tighter(self.defaults.pids_max, requested.pids_max),
+        }
+    }
+
+    /// Fail if `limits` needs a controller the kernel didn't give us.
+    pub fn check(&self, limits: &ResourceLimits) -> Result<(), String> {
+        let needed = [
+            ("cpu", limits.cpu_percent.is_some()),
+            ("memory", limits.memory_mb.is_some()),
+            ("pids", limits.pids_max.is_some()),
+        ];
+        match needed
+            .iter()
+            .find(|(c, used)| *used && !self.available.contains(c))
+        {
+            Some((c, _)) => Err(format!(
+                "the {c} controller is not available in {}",
+                self.root.display()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Create the cgroup for a new session, with `limits` applied.
+    pub fn create(
+        &self,
+        session_id: &str,
+        limits: &ResourceLimits,
+    ) -> Result<SessionCgroup, String> {
+        self.check(limits)?;
+        let path = self.root.join(format!("session-{session_id}"));
+        fs::create_dir(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let cgroup = SessionCgroup {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_encoded_bytes())
+                .map_err(|e| e.to_string())?,
+            path,
+            limits: *limits,
+        };
+        let mut files = Vec::new();
+        if let Some(percent) = limits.cpu_percent {
+            let quota = u64::from(percent) * CPU_PERIOD_US / 100;
+            files.push(("cpu.max", format!("{quota} {CPU_PERIOD_US}")));
+        }
+        if let Some(mb) = limits.memory_mb {
+            files.push(("memory.max", mb.saturating_mul(1024 * 1024).to_string()));
+            files.push(("memory.oom.group", "1".to_string()));
+        }
+        if let Some(pids) = limits.pids_max {
+            files.push(("pids.max", pids.to_string()));
+        }
+        for (file, value) in files {
+            fs::write(cgroup.path.join(file), &value)
+                .map_err(|e| format!("{file} = {value}: {e}"))?;
+        }
+        Ok(cgroup)
+    }
+}
+
+/// A session's cgroup; killed and removed on drop.
+pub struct SessionCgroup {
+    path: PathBuf,
+    /// `cgroup.procs`, prepared for the child to open before exec.
+    procs: CString,
+    limits: ResourceLimits,
+}
+
+impl SessionCgroup {
+    /// The limits the cgroup was created with.
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    /// Path to the `cgroup.procs` file, for [`join`] in `pre_exec`.
+    pub fn procs(&self) -> &CString {
+        &self.procs
+    }
+}
+
+impl Drop for SessionCgroup {
+    fn drop(&mut self) {
+        let path = self.path.clone();
+        // The processes take a moment to exit after `cgroup.kill`, and rmdir
+        // fails until they have.
+        std::thread::spawn(move || remove(&path));
+    }
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` is `procs`.
+///
+/// Only uses async-signal-safe calls, for `pre_exec`.
+#[cfg(unix)]
+pub fn join(procs: &CString) -> std::io::Result<()> {
+    // SAFETY: open/write/close are async-signal-safe and `procs` is a valid
+    // C string that outlives the calls.
+    unsafe {
+        let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written != 1 {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Enable [`CONTROLLERS`] for the children of `dir`; returns those that are.
+fn enable_controllers(dir: &Path) -> Vec<&'static str> {
+    let control = dir.join("cgroup.subtree_control");
+    let offered = fs::read_to_string(dir.join("cgroup.controllers")).unwrap_or_default();
+    for c in CONTROLLERS {
+        if !offered.split_whitespace().any(|o| o == c) {
+            continue;
+        }
+        if let Err(e) = fs::write(&control, format!("+{c}")) {
+            warn!("cgroup: could not enable {c} in {}: {e}", dir.display());
+        }
+    }
+    let enabled = fs::read_to_string(&control).unwrap_or_default();
+    CONTROLLERS
+        .into_iter()
+        .filter(|c| enabled.split_whitespace().any(|e| e == *c))
+        .collect()
+}
+
+/// Kill everything in a session cgroup and remove it.
+fn remove(path: &Path) {
+    #[cfg(unix)]
+    if fs::write(path.join("cgroup.kill"), "1").is_err() {
+        // Before Linux 5.14: signal the members one by one.
+        for pid in fs::read_to_string(path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| l.trim().parse::<libc::pid_t>().ok())
+        {
+            // SAFETY: kill has no memory-safety preconditions.
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+    }
+    for _ in 0..50 {
+        if fs::remove_dir(path).is_ok() || !path.exists() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    warn!("cgroup: could not remove {}", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn requested_limits_only_tighten() {
+        let cgroups = Cgroups {
+            root: PathBuf::from("/sys/fs/cgroup/sctl-sessions"),
+            defaults: ResourceLimits {
+                cpu_percent: Some(100),
+                memory_mb: Some(256),
+                pids_max: None,
+            },
+            available: vec!["cpu", "memory"],
+        };
+        let asked = ResourceLimits::from_message(&json!({
+            "limits": {"cpu_percent": 400, "memory_mb": 64}
+        }))
+        .unwrap()
+        .unwrap();
+        let limits = cgroups.effective(Some(&asked));
+        assert_eq!(limits.cpu_percent, Some(100));
+        assert_eq!(limits.memory_mb, Some(64));
+        assert_eq!(limits.pids_max, None);
+        assert!(cgroups.check(&limits).is_ok());
+        assert_eq!(cgroups.effective(None), cgroups.defaults);
+
+        let pids = cgroups.effective(Some(&ResourceLimits {
+            pids_max: Some(32),
+            ..ResourceLimits::default()
+        }));
+        assert!(cgroups.check(&pids).unwrap_err().contains("pids"));
+
+        assert_eq!(ResourceLimits::from_message(&json!({})).unwrap(), None);
+        assert!(ResourceLimits::from_message(&json!({"limits": {"memory_mb": 0}})).is_err());
+        assert!(ResourceLimits::from_message(&json!({"limits": {"disk_mb": 1}})).is_err());
+    }
+}
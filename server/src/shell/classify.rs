@@ -0,0 +1,962 @@
+//! Risk classification of exec commands.
+//!
+//! Every command journaled as `exec`, `session_exec` or `exec_rollback`
+//! carries `detail.risk`, one of (lowest to highest):
+//!
+//! - **read** — inspects state only (`ls`, `cat`, `ip addr`, `uci show`).
+//! - **modify** — changes files or processes (`cp`, `sed -i`, `> file`,
+//!   `kill`). Programs the classifier doesn't know land here.
+//! - **privileged** — changes system configuration or runs elevated
+//!   (`sudo`, `systemctl restart`, `uci commit`, `iptables -A`, `opkg install`).
+//! - **destructive** — irreversible data loss or an outage (`rm`, `dd of=`,
+//!   `mkfs`, `find -delete`, `reboot`, `> /dev/sda`).
+//!
+//! The command is split into simple commands the way the shell would: on
+//! `;`, `&&`, `||`, `|`, `&`, newlines and subshells, with quoting honoured.
+//! `$(...)`, backticks, `sh -c '...'`, `su -c` and `eval` strings are
+//! classified as commands of their own. Wrappers such as `env`, `nice`,
+//! `timeout` and `xargs` are looked through; `sudo`/`doas` make the rest at
+//! least privileged. Each simple command is matched against
+//! `[[classify.rules]]` first (shell globs on the command text, first match
+//! wins), then the built-in table. The command's risk is the highest of its
+//! parts.
+//!
+//! This is a review aid, not a sandbox: it errs towards the higher level and
+//! cannot see what scripts or binaries do internally.
+
+use std::ffi::{CStr, CString};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ClassifyConfig;
+
+/// How deep `sh -c`, `$(...)` and `eval` strings are followed.
+const MAX_DEPTH: usize = 8;
+
+/// Risk level of a command, ordered from least to most dangerous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Read,
+    Modify,
+    Privileged,
+    Destructive,
+}
+
+impl Risk {
+    /// Lowercase wire string.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Modify => "modify",
+            Self::Privileged => "privileged",
+            Self::Destructive => "destructive",
+        }
+    }
+
+    /// Parse from the wire string.
+    pub fn from_str_opt(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Self::Read),
+            "modify" => Some(Self::Modify),
+            "privileged" => Some(Self::Privileged),
+            "destructive" => Some(Self::Destructive),
+            _ => None,
+        }
+    }
+}
+
+/// Classify a shell command line.
+pub fn classify(config: &ClassifyConfig, command: &str) -> Risk {
+    let rules: Vec<(CString, Risk)> = config
+        .rules
+        .iter()
+        .filter_map(|r| Some((CString::new(r.pattern.as_str()).ok()?, r.risk)))
+        .collect();
+    classify_line(&rules, command, 0)
+}
+
+/// The simple commands a shell command line runs, split the same way as for
+/// [`classify`]. Each entry holds the forms one command can be matched in:
+/// its own text, then the text of each command it wraps (`sudo rm -rf /`
+/// also yields `rm -rf /`). Strings run through `sh -c`, `su -c`, `eval`,
+/// `find -exec`, `$(...)` and backticks are entries of their own.
+pub fn simple_commands(command: &str) -> Vec<Vec<String>> {
+    let mut out = Vec::new();
+    collect_line(command, 0, &mut out);
+    out
+}
+
+fn collect_line(line: &str, depth: usize, out: &mut Vec<Vec<String>>) {
+    if depth > MAX_DEPTH {
+        out.push(vec![line.trim().to_string()]);
+        return;
+    }
+    let parsed = parse(line);
+    for cmd in &parsed.commands {
+        let words = strip_keywords(&cmd.words);
+        if words.is_empty() {
+            continue;
+        }
+        let mut forms = Vec::new();
+        collect_forms(words, depth, &mut forms, out);
+        out.push(forms);
+    }
+    for inner in &parsed.nested {
+        collect_line(inner, depth + 1, out);
+    }
+}
+
+/// Push the text of `words`, and of the command it wraps, to `forms`;
+/// command strings it runs go to `out`.
+fn collect_forms(
+    words: &[String],
+    depth: usize,
+    forms: &mut Vec<String>,
+    out: &mut Vec<Vec<String>>,
+) {
+    let Some(first) = words.first() else {
+        return;
+    };
+    forms.push(words.join(" "));
+    let prog = basename(first);
+    let args = &words[1..];
+    if PRIVILEGE_WRAPPERS.contains(&prog) || TRANSPARENT_WRAPPERS.contains(&prog) {
+        collect_forms(skip_wrapper_args(prog, args), depth, forms, out);
+    } else if prog == "su" {
+        if let Some(c) = option_value(args, 'c', Some("--command")) {
+            collect_line(c, depth + 1, out);
+        }
+    } else if SHELLS.contains(&prog) {
+        if let Some(c) = option_value(args, 'c', None) {
+            collect_line(c, depth + 1, out);
+        }
+    } else if prog == "eval" {
+        collect_line(&args.join(" "), depth + 1, out);
+    } else if prog == "find" {
+        let mut rest = args;
+        while let Some(start) = rest
+            .iter()
+            .position(|a| matches!(a.as_str(), "-exec" | "-execdir" | "-ok" | "-okdir"))
+        {
+            let end = rest[start + 1..]
+                .iter()
+                .position(|a| a == ";" || a == "+")
+                .map_or(rest.len(), |p| start + 1 + p);
+            let inner: Vec<String> = rest[start + 1..end]
+                .iter()
+                .filter(|a| a.as_str() != "{}")
+                .cloned()
+                .collect();
+            let mut inner_forms = Vec::new();
+            collect_forms(&inner, depth + 1, &mut inner_forms, out);
+            if !inner_forms.is_empty() {
+                out.push(inner_forms);
+            }
+            rest = &rest[(end + 1).min(rest.len())..];
+        }
+    }
+}
+
+fn classify_line(rules: &[(CString, Risk)], line: &str, depth: usize) -> Risk {
+    if depth > MAX_DEPTH {
+        return Risk::Modify;
+    }
+    let parsed = parse(line);
+    let simple = parsed
+        .commands
+        .iter()
+        .map(|cmd| classify_simple(rules, cmd, depth));
+    let nested = parsed
+        .nested
+        .iter()
+        .map(|inner| classify_line(rules, inner, depth + 1));
+    simple.chain(nested).max().unwrap_or(Risk::Read)
+}
+
+// ─── Lexing ──────────────────────────────────────────────────────────────────
+
+/// One simple command: its words, and where its output is redirected.
+#[derive(Debug, Default)]
+struct SimpleCommand {
+    words: Vec<String>,
+    redirects: Vec<String>,
+}
+
+/// A command line split into simple commands, plus the bodies of `$(...)`
+/// and backticks found in it.
+#[derive(Debug, Default)]
+struct Parsed {
+    commands: Vec<SimpleCommand>,
+    nested: Vec<String>,
+}
+
+/// What the next completed word is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    Word,
+    OutputTarget,
+    InputSource,
+}
+
+struct Lexer {
+    out: Parsed,
+    current: SimpleCommand,
+    word: Option<String>,
+    pending: Pending,
+}
+
+impl Lexer {
+    fn push_char(&mut self, c: char) {
+        self.word.get_or_insert_with(String::new).push(c);
+    }
+
+    /// Mark a word as started even if it ends up empty (`''`, `"$(...)"`).
+    fn touch_word(&mut self) {
+        self.word.get_or_insert_with(String::new);
+    }
+
+    fn end_word(&mut self) {
+        let Some(word) = self.word.take() else {
+            return;
+        };
+        match std::mem::replace(&mut self.pending, Pending::Word) {
+            Pending::Word => self.current.words.push(word),
+            Pending::OutputTarget => self.current.redirects.push(word),
+            Pending::InputSource => {}
+        }
+    }
+
+    fn end_command(&mut self) {
+        self.end_word();
+        self.pending = Pending::Word;
+        let cmd = std::mem::take(&mut self.current);
+        if !cmd.words.is_empty() || !cmd.redirects.is_empty() {
+            self.out.commands.push(cmd);
+        }
+    }
+
+    /// Drop a word made only of digits: it was a file descriptor (`2>`).
+    fn take_fd_prefix(&mut self) {
+        if self
+            .word
+            .as_deref()
+            .is_some_and(|w| !w.is_empty() && w.bytes().all(|b| b.is_ascii_digit()))
+        {
+            self.word = None;
+        } else {
+            self.end_word();
+        }
+    }
+}
+
+fn parse(line: &str) -> Parsed {
+    let chars: Vec<char> = line.chars().collect();
+    let mut lx = Lexer {
+        out: Parsed::default(),
+        current: SimpleCommand::default(),
+        word: None,
+        pending: Pending::Word,
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some('\n') | None => {}
+                    Some(&next) => lx.push_char(next),
+                }
+                i += 2;
+                continue;
+            }
+            '\'' => {
+                lx.touch_word();
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    lx.push_char(chars[i]);
+                    i += 1;
+                }
+            }
+            '"' => {
+                lx.touch_word();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() => {
+                            lx.push_char(chars[i + 1]);
+                            i += 1;
+                        }
+                        '$' if chars.get(i + 1) == Some(&'(') => {
+                            let (body, end) = paren_body(&chars, i + 1);
+                            lx.out.nested.push(body);
+                            i = end;
+                        }
+                        '`' => {
+                            let (body, end) = backtick_body(&chars, i);
+                            lx.out.nested.push(body);
+                            i = end;
+                        }
+                        other => lx.push_char(other),
+                    }
+                    i += 1;
+                }
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                lx.touch_word();
+                let (body, end) = paren_body(&chars, i + 1);
+                // `$((...))` is arithmetic, not a command.
+                if !body.starts_with('(') {
+                    lx.out.nested.push(body);
+                }
+                i = end;
+            }
+            '`' => {
+                lx.touch_word();
+                let (body, end) = backtick_body(&chars, i);
+                lx.out.nested.push(body);
+                i = end;
+            }
+            '#' if lx.word.is_none() => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '>' => {
+                lx.take_fd_prefix();
+                while matches!(chars.get(i + 1), Some('>' | '|')) {
+                    i += 1;
+                }
+                if chars.get(i + 1) == Some(&'&') {
+                    // `>&2`, `2>&1`: fd duplication, no file.
+                    i += 1;
+                    while matches!(chars.get(i + 1), Some(c) if c.is_ascii_digit() || *c == '-') {
+                        i += 1;
+                    }
+                } else {
+                    lx.pending = Pending::OutputTarget;
+                }
+            }
+            '<' => {
+                lx.take_fd_prefix();
+                while matches!(chars.get(i + 1), Some('<' | '-' | '&')) {
+                    i += 1;
+                }
+                lx.pending = Pending::InputSource;
+            }
+            '&' if chars.get(i + 1) == Some(&'>') => {
+                lx.end_word();
+                i += 1;
+                while chars.get(i + 1) == Some(&'>') {
+                    i += 1;
+                }
+                lx.pending = Pending::OutputTarget;
+            }
+            ';' | '&' | '|' | '(' | ')' | '\n' => lx.end_command(),
+            c if c.is_whitespace() => lx.end_word(),
+            c => lx.push_char(c),
+        }
+        i += 1;
+    }
+    lx.end_command();
+    lx.out
+}
+
+/// Body of the parenthesis opening at `open`, and the index of its closing
+/// parenthesis (or the end of input).
+fn paren_body(chars: &[char], open: usize) -> (String, usize) {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut i = open;
+    while i < chars.len() {
+        let c = chars[i];
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => i += 1,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return (chars[open + 1..i].iter().collect(), i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    let start = (open + 1).min(chars.len());
+    (chars[start..].iter().collect(), chars.len())
+}
+
+/// Body of the backtick substitution opening at `open`, and the index of
+/// the closing backtick (or the end of input).
+fn backtick_body(chars: &[char], open: usize) -> (String, usize) {
+    let mut body = String::new();
+    let mut i = open + 1;
+    while i < chars.len() && chars[i] != '`' {
+        if chars[i] == '\\' && i + 1 < chars.len() {
+            i += 1;
+        }
+        body.push(chars[i]);
+        i += 1;
+    }
+    (body, i)
+}
+
+// ─── Classification ──────────────────────────────────────────────────────────
+
+/// Reserved words that may open a simple command without being its program.
+const LEADING_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "do", "while", "until", "!", "{", "}", "fi", "done", "esac",
+];
+
+/// Wrappers that run the rest of their arguments as a command.
+const TRANSPARENT_WRAPPERS: &[&str] = &[
+    "env", "nice", "nohup", "time", "timeout", "exec", "command", "builtin", "stdbuf", "ionice",
+    "setsid", "xargs", "flock", "watch", "busybox",
+];
+
+/// Wrappers that elevate the rest of their arguments.
+const PRIVILEGE_WRAPPERS: &[&str] = &[
+    "sudo", "doas", "pkexec", "run0", "chroot", "nsenter", "unshare",
+];
+
+/// Shells whose `-c` argument is a command line.
+const SHELLS: &[&str] = &["sh", "bash", "ash", "dash", "zsh", "ksh", "mksh", "fish"];
+
+/// A wrapper's options that take a separate value.
+fn wrapper_opts_with_value(prog: &str) -> &'static [&'static str] {
+    match prog {
+        "sudo" => &[
+            "-u", "-g", "-C", "-D", "-h", "-p", "-r", "-t", "-T", "-U", "--user", "--group",
+            "--chdir", "--prompt",
+        ],
+        "doas" | "run0" | "pkexec" => &["-u", "-C", "--user"],
+        "env" => &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"],
+        "nice" => &["-n", "--adjustment"],
+        "timeout" => &["-s", "-k", "--signal", "--kill-after"],
+        "stdbuf" => &["-i", "-o", "-e"],
+        "ionice" => &["-c", "-n", "-p"],
+        "xargs" => &["-I", "-n", "-P", "-d", "-L", "-E", "-s", "-a"],
+        "flock" => &["-w", "-E", "--timeout", "--conflict-exit-code"],
+        "watch" => &["-n", "--interval"],
+        "nsenter" => &["-t", "--target"],
+        _ => &[],
+    }
+}
+
+fn classify_simple(rules: &[(CString, Risk)], cmd: &SimpleCommand, depth: usize) -> Risk {
+    let redirect_risk = cmd
+        .redirects
+        .iter()
+        .map(|t| redirect_target_risk(t))
+        .max()
+        .unwrap_or(Risk::Read);
+
+    let words = strip_keywords(&cmd.words);
+    if words
+        .first()
+        .is_some_and(|w| matches!(w.as_str(), "for" | "case" | "select" | "in"))
+    {
+        return redirect_risk;
+    }
+
+    let text = words.join(" ");
+    if let Ok(text) = CString::new(text) {
+        if let Some((_, risk)) = rules.iter().find(|(pattern, _)| glob(pattern, &text)) {
+            return (*risk).max(redirect_risk);
+        }
+    }
+
+    command_risk(rules, words, depth).max(redirect_risk)
+}
+
+/// `words` without leading keywords (`if`, `do`, ...) and `VAR=value`
+/// assignments.
+fn strip_keywords(mut words: &[String]) -> &[String] {
+    while let Some(first) = words.first() {
+        if LEADING_KEYWORDS.contains(&first.as_str()) || is_assignment(first) {
+            words = &words[1..];
+        } else {
+            break;
+        }
+    }
+    words
+}
+
+/// Risk of running `words`, looking through wrappers.
+fn command_risk(rules: &[(CString, Risk)], words: &[String], depth: usize) -> Risk {
+    let Some(first) = words.first() else {
+        return Risk::Read;
+    };
+    let prog = basename(first);
+    let args = &words[1..];
+
+    if PRIVILEGE_WRAPPERS.contains(&prog) {
+        let rest = skip_wrapper_args(prog, args);
+        return command_risk(rules, rest, depth).max(Risk::Privileged);
+    }
+    if prog == "su" {
+        let inner = option_value(args, 'c', Some("--command"))
+            .map_or(Risk::Read, |c| classify_line(rules, c, depth + 1));
+        return inner.max(Risk::Privileged);
+    }
+    if SHELLS.contains(&prog) {
+        return match option_value(args, 'c', None) {
+            Some(c) => classify_line(rules, c, depth + 1),
+            // A script file or an interactive shell: unknown.
+            None => Risk::Modify,
+        };
+    }
+    if prog == "eval" {
+        return classify_line(rules, &args.join(" "), depth + 1);
+    }
+    if prog == "command" && args.first().is_some_and(|a| a == "-v" || a == "-V") {
+        return Risk::Read;
+    }
+    if TRANSPARENT_WRAPPERS.contains(&prog) {
+        let rest = skip_wrapper_args(prog, args);
+        if rest.is_empty() {
+            return Risk::Read;
+        }
+        return command_risk(rules, rest, depth);
+    }
+    if first.starts_with("/etc/init.d/") {
+        return if args.first().is_some_and(|a| a == "status") {
+            Risk::Read
+        } else {
+            Risk::Privileged
+        };
+    }
+    if prog == "find" {
+        return find_risk(rules, args, depth);
+    }
+    builtin_risk(prog, args)
+}
+
+/// Skip a wrapper's options (and, for `timeout`/`chroot`/`flock`, its
+/// leading operand), returning the wrapped command.
+fn skip_wrapper_args<'a>(prog: &str, args: &'a [String]) -> &'a [String] {
+    let with_value = wrapper_opts_with_value(prog);
+    let mut i = 0;
+    while i < args.len() {
+        let a = args[i].as_str();
+        if a == "--" {
+            i += 1;
+            break;
+        }
+        if a.starts_with('-') && a.len() > 1 {
+            i += if with_value.contains(&a) { 2 } else { 1 };
+        } else if matches!(prog, "env" | "sudo") && is_assignment(a) {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    let rest = &args[i.min(args.len())..];
+    match prog {
+        "timeout" | "chroot" | "flock" if !rest.is_empty() => &rest[1..],
+        _ => rest,
+    }
+}
+
+/// `find` is read-only unless it deletes or runs something.
+fn find_risk(rules: &[(CString, Risk)], args: &[String], depth: usize) -> Risk {
+    let mut risk = Risk::Read;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-delete" => risk = risk.max(Risk::Destructive),
+            "-exec" | "-execdir" | "-ok" | "-okdir" => {
+                let end = args[i + 1..]
+                    .iter()
+                    .position(|a| a == ";" || a == "+")
+                    .map_or(args.len(), |p| i + 1 + p);
+                let inner: Vec<String> = args[i + 1..end]
+                    .iter()
+                    .filter(|a| a.as_str() != "{}")
+                    .cloned()
+                    .collect();
+                risk = risk.max(command_risk(rules, &inner, depth + 1));
+                i = end;
+            }
+            a if a.starts_with("-fprint") || a == "-fls" => risk = risk.max(Risk::Modify),
+            _ => {}
+        }
+        i += 1;
+    }
+    risk
+}
+
+/// Built-in table for a program and its arguments.
+fn builtin_risk(prog: &str, args: &[String]) -> Risk {
+    let prog = if prog.starts_with("mkfs") {
+        "mkfs"
+    } else {
+        prog
+    };
+    let has = |flags: &[&str]| args.iter().any(|a| flags.contains(&a.as_str()));
+    let verb = args
+        .iter()
+        .find(|a| !a.starts_with('-'))
+        .map_or("", String::as_str);
+    let pick = |read: bool, otherwise: Risk| if read { Risk::Read } else { otherwise };
+
+    match prog {
+        // Irreversible data loss or an outage.
+        "rm" | "shred" | "wipefs" | "blkdiscard" | "fdisk" | "sfdisk" | "gdisk" | "sgdisk"
+        | "parted" | "mkswap" | "flash_erase" | "flashcp" | "sysupgrade" | "firstboot"
+        | "jffs2reset" | "reboot" | "poweroff" | "halt" | "shutdown" | "truncate" | "mkfs"
+        | "mke2fs" => Risk::Destructive,
+        "dd" => pick(
+            !args.iter().any(|a| a.starts_with("of=")),
+            Risk::Destructive,
+        ),
+        "mtd" => pick(!has(&["erase", "write", "jffs2write"]), Risk::Destructive),
+        "init" | "telinit" => match verb {
+            "0" | "6" => Risk::Destructive,
+            _ => Risk::Privileged,
+        },
+        "git" => match verb {
+            "status" | "log" | "diff" | "show" | "blame" | "rev-parse" | "ls-files"
+            | "describe" | "shortlog" | "grep" | "reflog" => Risk::Read,
+            "clean" if has(&["-f", "--force", "-fd", "-fdx", "-xdf", "-df"]) => Risk::Destructive,
+            "reset" if has(&["--hard"]) => Risk::Destructive,
+            "push" if has(&["-f", "--force"]) => Risk::Destructive,
+            _ => Risk::Modify,
+        },
+        "crontab" => {
+            if has(&["-r"]) {
+                Risk::Destructive
+            } else {
+                pick(has(&["-l"]), Risk::Privileged)
+            }
+        }
+        "iptables" | "ip6tables" | "ebtables" | "arptables" => {
+            if has(&["-F", "--flush", "-X", "--delete-chain"]) {
+                Risk::Destructive
+            } else {
+                pick(
+                    has(&["-L", "--list", "-S", "--list-rules"])
+                        && !has(&["-A", "-I", "-D", "-R", "-P", "-N", "-Z"]),
+                    Risk::Privileged,
+                )
+            }
+        }
+        "nft" => match verb {
+            "list" => Risk::Read,
+            "flush" => Risk::Destructive,
+            _ => Risk::Privileged,
+        },
+
+        // System configuration.
+        "useradd" | "userdel" | "usermod" | "groupadd" | "groupdel" | "groupmod" | "passwd"
+        | "chpasswd" | "chage" | "visudo" | "chown" | "chgrp" | "umount" | "swapon" | "swapoff"
+        | "modprobe" | "insmod" | "rmmod" | "setcap" | "ifup" | "ifdown" | "wifi"
+        | "iptables-apply" | "iptables-restore" | "ip6tables-restore" | "update-rc.d"
+        | "rc-update" => Risk::Privileged,
+        "mount" => pick(args.is_empty(), Risk::Privileged),
+        "sysctl" => pick(
+            !args.iter().any(|a| a.contains('='))
+                && !has(&["-w", "--write", "-p", "--load", "--system"]),
+            Risk::Privileged,
+        ),
+        "systemctl" => pick(
+            matches!(
+                verb,
+                "" | "status"
+                    | "show"
+                    | "cat"
+                    | "help"
+                    | "is-active"
+                    | "is-enabled"
+                    | "is-failed"
+                    | "is-system-running"
+                    | "get-default"
+                    | "list-units"
+                    | "list-unit-files"
+                    | "list-timers"
+                    | "list-sockets"
+                    | "list-dependencies"
+                    | "list-jobs"
+            ),
+            Risk::Privileged,
+        ),
+        "service" => pick(has(&["status"]), Risk::Privileged),
+        "uci" => pick(
+            matches!(verb, "show" | "get" | "export" | "changes"),
+            Risk::Privileged,
+        ),
+        "opkg" | "apt" | "apt-get" | "apt-cache" | "yum" | "dnf" | "apk" | "pacman" | "zypper"
+        | "snap" => pick(
+            matches!(
+                verb,
+                "" | "list"
+                    | "list-installed"
+                    | "list-upgradable"
+                    | "info"
+                    | "search"
+                    | "show"
+                    | "policy"
+                    | "status"
+                    | "files"
+                    | "find"
+                    | "whatdepends"
+            ),
+            Risk::Privileged,
+        ),
+        "dpkg" | "rpm" => pick(
+            has(&[
+                "-l", "-L", "-s", "-S", "-q", "-qa", "-qi", "-ql", "--list", "--status",
+            ]),
+            Risk::Privileged,
+        ),
+        "ip" => pick(
+            !has(&[
+                "add", "del", "delete", "change", "replace", "set", "flush", "append", "prepend",
+                "up", "down",
+            ]),
+            Risk::Privileged,
+        ),
+        "ifconfig" => pick(args.len() <= 1, Risk::Privileged),
+        "route" => pick(!has(&["add", "del", "delete", "flush"]), Risk::Privileged),
+        "hostname" => pick(verb.is_empty(), Risk::Privileged),
+        "date" => pick(
+            !args.iter().any(|a| a == "-s" || a.starts_with("--set")),
+            Risk::Privileged,
+        ),
+        "hwclock" => pick(
+            args.is_empty() || has(&["-r", "--show", "--get"]),
+            Risk::Privileged,
+        ),
+        "timedatectl" => pick(matches!(verb, "" | "status" | "show"), Risk::Privileged),
+
+        // Changes files or processes.
+        "sed" => pick(
+            !args.iter().any(|a| {
+                a.starts_with("--in-place")
+                    || (a.starts_with('-') && !a.starts_with("--") && a.contains('i'))
+            }),
+            Risk::Modify,
+        ),
+        "curl" => pick(
+            !args.iter().any(|a| {
+                matches!(
+                    a.as_str(),
+                    "-o" | "-O"
+                        | "--output"
+                        | "--remote-name"
+                        | "-T"
+                        | "--upload-file"
+                        | "-F"
+                        | "--form"
+                ) || a.starts_with("-d")
+                    || a.starts_with("--data")
+                    || (a.starts_with("-X") && !a.ends_with("GET"))
+            }),
+            Risk::Modify,
+        ),
+        "wget" => pick(
+            has(&["--spider", "-O-", "-qO-"])
+                || args.windows(2).any(|w| w[0] == "-O" && w[1] == "-"),
+            Risk::Modify,
+        ),
+        "tee" => pick(!args.iter().any(|a| !a.starts_with('-')), Risk::Modify),
+        "tar" => pick(
+            args.first()
+                .is_some_and(|a| a == "--list" || (!a.starts_with("--") && a.contains('t'))),
+            Risk::Modify,
+        ),
+        "dmesg" => pick(!has(&["-c", "-C", "--clear", "--read-clear"]), Risk::Modify),
+
+        // Inspects state only.
+        "ls" | "cat" | "head" | "tail" | "less" | "more" | "grep" | "egrep" | "fgrep" | "rg"
+        | "zcat" | "zgrep" | "ps" | "pgrep" | "top" | "df" | "du" | "free" | "uptime" | "uname"
+        | "whoami" | "id" | "groups" | "who" | "w" | "last" | "pwd" | "echo" | "printf"
+        | "true" | "false" | "test" | "[" | "[[" | "stat" | "file" | "wc" | "sort" | "uniq"
+        | "cut" | "tr" | "awk" | "diff" | "cmp" | "md5sum" | "sha1sum" | "sha256sum"
+        | "sha512sum" | "cksum" | "which" | "type" | "printenv" | "netstat" | "ss" | "lsof"
+        | "lsblk" | "blkid" | "lsusb" | "lspci" | "lsmod" | "journalctl" | "logread" | "tree"
+        | "readlink" | "realpath" | "basename" | "dirname" | "sleep" | "seq" | "xxd"
+        | "hexdump" | "od" | "strings" | "nproc" | "ping" | "ping6" | "traceroute" | "dig"
+        | "nslookup" | "host" | "getent" | "locale" | "jq" | "column" | "nl" | "tac" | "rev"
+        | "fold" | "expr" | "bc" | "cal" | "vmstat" | "iostat" | "mpstat" | "sensors" | "iw"
+        | "iwinfo" | "ubus" | "ethtool" | "arp" | "findmnt" | "mountpoint" | "iptables-save"
+        | "ip6tables-save" | "history" | "alias" | "read" | "exit" | "return" | "set" | "shift"
+        | "local" | "export" | "cd" | "pushd" | "popd" | "wait" | ":" => Risk::Read,
+
+        // Everything else, including cp/mv/mkdir/kill and unknown programs.
+        _ => Risk::Modify,
+    }
+}
+
+/// Risk of writing to a redirection target.
+fn redirect_target_risk(target: &str) -> Risk {
+    match target {
+        "/dev/null" | "/dev/stdout" | "/dev/stderr" | "/dev/tty" => Risk::Read,
+        t if t.starts_with("/dev/") => Risk::Destructive,
+        t if t.starts_with("/etc/") || t.starts_with("/boot/") => Risk::Privileged,
+        _ => Risk::Modify,
+    }
+}
+
+/// The value of option `-<short>` (also inside combined flags such as
+/// `-lc`) or `<long>`/`<long>=value`.
+fn option_value<'a>(args: &'a [String], short: char, long: Option<&str>) -> Option<&'a str> {
+    for (i, a) in args.iter().enumerate() {
+        if let Some(long) = long {
+            if a == long {
+                return args.get(i + 1).map(String::as_str);
+            }
+            if let Some(v) = a.strip_prefix(long).and_then(|r| r.strip_prefix('=')) {
+                return Some(v);
+            }
+        }
+        if a.len() > 1 && a.starts_with('-') && !a.starts_with("--") && a[1..].contains(short) {
+            return args.get(i + 1).map(String::as_str);
+        }
+    }
+    None
+}
+
+/// `NAME=value` (a shell variable assignment).
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn basename(word: &str) -> &str {
+    word.rsplit('/').next().unwrap_or(word)
+}
+
+/// Shell-style match of a whole command text: `*`, `?` and `[...]`.
+#[cfg(unix)]
+pub(crate) fn glob(pattern: &CStr, text: &CStr) -> bool {
+    // SAFETY: both pointers are valid NUL-terminated strings for the call.
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+/// Shell-style match of a whole command text: `*`, `?` and `[...]`, as
+/// `fnmatch(3)` without flags (the C runtime on Windows has none).
+#[cfg(windows)]
+pub(crate) fn glob(pattern: &CStr, text: &CStr) -> bool {
+    fn class(p: &[u8], c: u8) -> Option<(bool, usize)> {
+        let negate = matches!(p.get(1), Some(b'!' | b'^'));
+        let mut i = 1 + usize::from(negate);
+        let mut hit = false;
+        let mut first = true;
+        while i < p.len() && (first || p[i] != b']') {
+            first = false;
+            if p[i] == b'\\' && i + 1 < p.len() {
+                i += 1;
+            }
+            let lo = p[i];
+            if p.get(i + 1) == Some(&b'-') && p.get(i + 2).is_some_and(|&b| b != b']') {
+                let escaped = p[i + 2] == b'\\' && i + 3 < p.len();
+                let hi = p[i + 2 + usize::from(escaped)];
+                hit |= (lo..=hi).contains(&c);
+                i += 3 + usize::from(escaped);
+            } else {
+                hit |= lo == c;
+                i += 1;
+            }
+        }
+        (i < p.len()).then_some((hit != negate, i + 1))
+    }
+    fn matches(p: &[u8], t: &[u8]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some(b'*') => (0..=t.len()).any(|i| matches(&p[1..], &t[i..])),
+            Some(b'?') => !t.is_empty() && matches(&p[1..], &t[1..]),
+            Some(b'[') => match (t.first(), class(p, t.first().copied().unwrap_or(0))) {
+                (Some(_), Some((hit, len))) => hit && matches(&p[len..], &t[1..]),
+                (None, _) => false,
+                // An unclosed `[` is a literal.
+                (Some(&c), None) => c == b'[' && matches(&p[1..], &t[1..]),
+            },
+            Some(b'\\') => p.len() > 1 && t.first() == Some(&p[1]) && matches(&p[2..], &t[1..]),
+            Some(&c) => t.first() == Some(&c) && matches(&p[1..], &t[1..]),
+        }
+    }
+    matches(pattern.to_bytes(), text.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClassifyRule;
+
+    fn risk(command: &str) -> Risk {
+        classify(&ClassifyConfig::default(), command)
+    }
+
+    #[test]
+    fn takes_the_highest_risk_of_all_parts() {
+        assert_eq!(risk("ls -la /tmp && df -h | grep root"), Risk::Read);
+        assert_eq!(risk("cat /etc/os-release; cp a b"), Risk::Modify);
+        assert_eq!(risk("uci show network && uci commit"), Risk::Privileged);
+        assert_eq!(risk("cd /data && rm -rf cache"), Risk::Destructive);
+        assert_eq!(risk("echo done >/dev/null 2>&1"), Risk::Read);
+        assert_eq!(risk("echo 1 > /tmp/flag"), Risk::Modify);
+        assert_eq!(
+            risk("echo nameserver 1.1.1.1 >> /etc/resolv.conf"),
+            Risk::Privileged
+        );
+        assert_eq!(risk("cat image.bin > /dev/mmcblk0"), Risk::Destructive);
+    }
+
+    #[test]
+    fn quoting_and_substitutions() {
+        assert_eq!(risk("echo 'rm -rf /'"), Risk::Read);
+        assert_eq!(risk("grep -r \"reboot\" /var/log"), Risk::Read);
+        assert_eq!(risk("echo \"$(rm -f /tmp/x)\""), Risk::Destructive);
+        assert_eq!(risk("echo `reboot`"), Risk::Destructive);
+        assert_eq!(risk("echo $((1 + 2))"), Risk::Read);
+        assert_eq!(risk("# rm -rf /\nls"), Risk::Read);
+    }
+
+    #[test]
+    fn looks_through_wrappers() {
+        assert_eq!(risk("sudo ls /root"), Risk::Privileged);
+        assert_eq!(risk("sudo -u www rm -rf /srv/cache"), Risk::Destructive);
+        assert_eq!(risk("LANG=C timeout 5 journalctl -n 50"), Risk::Read);
+        assert_eq!(
+            risk("sh -c 'dd if=/dev/zero of=/dev/sda bs=1M'"),
+            Risk::Destructive
+        );
+        assert_eq!(
+            risk("find /tmp -name '*.log' -exec rm {} +"),
+            Risk::Destructive
+        );
+        assert_eq!(risk("find / -name core"), Risk::Read);
+        assert_eq!(risk("systemctl status sctl"), Risk::Read);
+        assert_eq!(risk("systemctl restart sctl"), Risk::Privileged);
+        assert_eq!(risk("sed -n 1,5p file"), Risk::Read);
+        assert_eq!(risk("sed -i s/a/b/ file"), Risk::Modify);
+        assert_eq!(risk("frobnicate --all"), Risk::Modify);
+    }
+
+    #[test]
+    fn configured_rules_win_over_builtins() {
+        let config = ClassifyConfig {
+            rules: vec![
+                ClassifyRule {
+                    pattern: "fw_setenv *".to_string(),
+                    risk: Risk::Destructive,
+                },
+                ClassifyRule {
+                    pattern: "rm /tmp/*".to_string(),
+                    risk: Risk::Modify,
+                },
+            ],
+        };
+        assert_eq!(classify(&config, "fw_setenv bootcmd x"), Risk::Destructive);
+        assert_eq!(classify(&config, "rm /tmp/a.lock"), Risk::Modify);
+        assert_eq!(classify(&config, "rm /data/a"), Risk::Destructive);
+    }
+}
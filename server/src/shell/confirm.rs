@@ -0,0 +1,312 @@
+//! Commit-confirm for one-shot exec.
+//!
+//! `POST /api/exec?confirm_within=60` with a `rollback` command runs the
+//! command and then waits: unless the client confirms within the window
+//! (`POST /api/exec/pending/{id}/confirm`), the rollback command runs with the
+//! same shell, working directory, env, and sudo options. This is the Junos
+//! `commit confirmed` pattern — reconfiguring the network over the link being
+//! reconfigured is safe because losing the link means losing the ability to
+//! confirm.
+//!
+//! The command runs in a detached task, so a client whose connection drops
+//! mid-request doesn't cancel it (or the rollback timer). The window starts
+//! when the command finishes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::activity::{self, ActivitySource, ActivityType};
+use crate::shell::classify;
+use crate::shell::process::{self, ExecError, ExecResult};
+use crate::shell::secrets::ResolvedEnv;
+use crate::shell::sudo::ExecSudo;
+use crate::AppState;
+
+/// Bounds for `confirm_within`, in seconds.
+pub const MIN_WINDOW_SECS: u64 = 10;
+pub const MAX_WINDOW_SECS: u64 = 3600;
+
+/// A command to run under a confirm window.
+pub struct GuardedExec {
+    pub shell: String,
+    pub working_dir: String,
+    pub command: String,
+    pub rollback: String,
+    pub timeout_ms: u64,
+    pub env: ResolvedEnv,
+    pub sudo: Option<ExecSudo>,
+    pub window: Duration,
+    pub source: ActivitySource,
+}
+
+/// Result handed back to the request handler once the command finished.
+pub struct GuardedOutcome {
+    pub result: Result<ExecResult, ExecError>,
+    /// Unix ms after which the rollback runs; `None` if nothing was armed
+    /// because the command never started.
+    pub deadline_ms: Option<u64>,
+}
+
+struct Pending {
+    command: String,
+    rollback: String,
+    created_ms: u64,
+    deadline_ms: Option<u64>,
+    confirm_tx: Option<oneshot::Sender<()>>,
+}
+
+/// Outstanding commit-confirm windows, keyed by confirm id.
+#[derive(Default)]
+pub struct ConfirmRegistry {
+    pending: std::sync::Mutex<HashMap<String, Pending>>,
+}
+
+impl ConfirmRegistry {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Pending>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Pending windows, oldest first.
+    pub fn list(&self) -> Vec<Value> {
+        let pending = self.lock();
+        let mut out: Vec<(u64, Value)> = pending
+            .iter()
+            .map(|(id, p)| {
+                (
+                    p.created_ms,
+                    json!({
+                        "id": id,
+                        "command": p.command,
+                        "rollback": p.rollback,
+                        "created_ms": p.created_ms,
+                        "deadline_ms": p.deadline_ms,
+                        "running": p.deadline_ms.is_none(),
+                    }),
+                )
+            })
+            .collect();
+        out.sort_by_key(|(created, _)| *created);
+        out.into_iter().map(|(_, v)| v).collect()
+    }
+
+    /// Confirm a pending window, cancelling its rollback. Returns the command
+    /// that was confirmed, or `None` if the id is unknown or already expired.
+    pub fn confirm(&self, id: &str) -> Option<String> {
+        let mut pending = self.lock().remove(id)?;
+        if let Some(tx) = pending.confirm_tx.take() {
+            let _ = tx.send(());
+        }
+        Some(pending.command)
+    }
+}
+
+/// Run `exec` in a detached task and arm its rollback.
+///
+/// Returns the confirm id and a receiver for the command's outcome. Dropping
+/// the receiver (client went away) doesn't affect the task.
+pub fn spawn_guarded(
+    state: &AppState,
+    exec: GuardedExec,
+) -> (String, oneshot::Receiver<GuardedOutcome>) {
+    let id = uuid::Uuid::new_v4().to_string();
+    let (confirm_tx, mut confirm_rx) = oneshot::channel();
+    let (outcome_tx, outcome_rx) = oneshot::channel();
+    state.exec_confirms.lock().insert(
+        id.clone(),
+        Pending {
+            command: exec.command.clone(),
+            rollback: exec.rollback.clone(),
+            created_ms: crate::sessions::journal::now_ms(),
+            deadline_ms: None,
+            confirm_tx: Some(confirm_tx),
+        },
+    );
+
+    let state = state.clone();
+    let confirm_id = id.clone();
+    tokio::spawn(async move {
+        let id = confirm_id;
+        let mut result = Box::pin(process::exec_command_with(
+            &exec.shell,
+            &exec.working_dir,
+            &exec.command,
+            exec.timeout_ms,
+            exec.env.env(),
+            exec.sudo.as_ref(),
+        ))
+        .await;
+        if let Ok(ref mut r) = result {
+            exec.env.redact_result(r);
+        }
+
+        // Spawn/wait failures mean nothing ran — nothing to roll back.
+        if matches!(result, Err(ref e) if !matches!(e, ExecError::Timeout)) {
+            state.exec_confirms.lock().remove(&id);
+            let _ = outcome_tx.send(GuardedOutcome {
+                result,
+                deadline_ms: None,
+            });
+            return;
+        }
+
+        let deadline_ms = crate::sessions::journal::now_ms()
+            + u64::try_from(exec.window.as_millis()).unwrap_or(u64::MAX);
+        if let Some(p) = state.exec_confirms.lock().get_mut(&id) {
+            p.deadline_ms = Some(deadline_ms);
+        }
+        let _ = outcome_tx.send(GuardedOutcome {
+            result,
+            deadline_ms: Some(deadline_ms),
+        });
+
+        tokio::select! {
+            _ = &mut confirm_rx => {}
+            () = tokio::time::sleep(exec.window) => {}
+        }
+        // Removal is the decision point: a confirm that won the race took the
+        // entry already.
+        if state.exec_confirms.lock().remove(&id).is_none() {
+            info!(confirm_id = %id, "exec confirm: confirmed, rollback cancelled");
+            return;
+        }
+
+        warn!(confirm_id = %id, "exec confirm: window expired, running rollback");
+        let rollback = Box::pin(process::exec_command_with(
+            &exec.shell,
+            &exec.working_dir,
+            &exec.rollback,
+            exec.timeout_ms,
+            exec.env.env(),
+            exec.sudo.as_ref(),
+        ))
+        .await;
+        let risk = classify::classify(&state.config.classify, &exec.rollback);
+        let detail = match rollback {
+            Ok(mut r) => {
+                exec.env.redact_result(&mut r);
+                json!({
+                    "confirm_id": id,
+                    "command": exec.command,
+                    "rollback": exec.rollback,
+                    "risk": risk,
+                    "exit_code": r.exit_code,
+                    "duration_ms": r.duration_ms,
+                    "stdout_preview": activity::truncate_str(&r.stdout, 200),
+                    "stderr_preview": activity::truncate_str(&r.stderr, 200),
+                })
+            }
+            Err(e) => json!({
+                "confirm_id": id,
+                "command": exec.command,
+                "rollback": exec.rollback,
+                "risk": risk,
+                "exit_code": -1,
+                "error": e.to_string(),
+            }),
+        };
+        state
+            .activity_log
+            .log(
+                ActivityType::ExecRollback,
+                exec.source,
+                format!(
+                    "Unconfirmed, rolled back: {}",
+                    activity::truncate_str(&exec.command, 60)
+                ),
+                Some(detail),
+                None,
+            )
+            .await;
+    });
+
+    (id, outcome_rx)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::server::{testing, ServerBuilder};
+
+    fn guarded(dir: &std::path::Path, env: ResolvedEnv, window: Duration) -> GuardedExec {
+        GuardedExec {
+            shell: "/bin/sh".to_string(),
+            working_dir: dir.display().to_string(),
+            command: "echo applied > applied".to_string(),
+            rollback: "echo rolled back > rolled_back".to_string(),
+            timeout_ms: 5000,
+            env,
+            sudo: None,
+            window,
+            source: ActivitySource::Rest,
+        }
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_window_runs_the_rollback() {
+        let (config, dir) = testing::config("confirm-lapse");
+        let server = ServerBuilder::new(config).build().await;
+        let state = &server.state;
+        let env = crate::shell::secrets::resolve_env(None, &dir.display().to_string(), None)
+            .await
+            .unwrap();
+
+        let (id, outcome) = spawn_guarded(state, guarded(&dir, env, Duration::from_millis(200)));
+        let outcome = outcome.await.unwrap();
+        assert_eq!(outcome.result.unwrap().exit_code, 0);
+        assert!(outcome.deadline_ms.is_some());
+        assert_eq!(state.exec_confirms.list()[0]["id"], id);
+
+        let entry = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let logged = state.activity_log.read_since(0, 100).await;
+                if let Some(e) = logged
+                    .into_iter()
+                    .find(|e| e.activity_type == ActivityType::ExecRollback)
+                {
+                    return e;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("rollback ran and was logged");
+        assert_eq!(entry.detail.unwrap()["confirm_id"], id);
+        assert!(dir.join("rolled_back").exists());
+        assert!(state.exec_confirms.list().is_empty());
+        assert!(state.exec_confirms.confirm(&id).is_none());
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn confirm_cancels_the_rollback() {
+        let (config, dir) = testing::config("confirm-keep");
+        let server = ServerBuilder::new(config).build().await;
+        let state = &server.state;
+        let env = crate::shell::secrets::resolve_env(None, &dir.display().to_string(), None)
+            .await
+            .unwrap();
+
+        let (id, outcome) = spawn_guarded(state, guarded(&dir, env, Duration::from_millis(300)));
+        assert!(outcome.await.unwrap().result.is_ok());
+        assert_eq!(
+            state.exec_confirms.confirm(&id).as_deref(),
+            Some("echo applied > applied")
+        );
+        assert!(state.exec_confirms.confirm(&id).is_none());
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(dir.join("applied").exists());
+        assert!(!dir.join("rolled_back").exists());
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -6,13 +6,25 @@
 //!   Used by `POST /api/exec` and `POST /api/exec/batch`.
 //! - **Interactive** ([`process::spawn_shell`]) — spawn a long-lived shell with piped
 //!   stdin/stdout/stderr, used by WebSocket sessions.
+//!
+//! Large one-shot output can be condensed for LLM callers by [`summary`].
+//! Commands are tagged with a risk level for the activity journal by
+//! [`classify`], and checked against operator allow/deny rules by [`policy`].
+//! Sessions can be held to CPU, memory and process limits by [`cgroup`].
 
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
 
+pub mod cgroup;
+pub mod classify;
+pub mod confirm;
+pub mod policy;
 pub mod process;
 pub mod pty;
+pub mod secrets;
+pub mod sudo;
+pub mod summary;
 
 /// Cached shell list — shells don't change at runtime on embedded devices.
 /// Avoids repeated blocking filesystem I/O (`read_to_string` + stat + canonicalize)
@@ -61,6 +73,7 @@ fn detect_shells_uncached() -> Vec<String> {
     shells
 }
 
+#[cfg(unix)]
 fn fallback_candidates() -> Vec<String> {
     [
         "/bin/sh",
@@ -78,6 +91,28 @@ fn fallback_candidates() -> Vec<String> {
     .collect()
 }
 
+/// PowerShell from `PATH`, then `%COMSPEC%`.
+#[cfg(windows)]
+fn fallback_candidates() -> Vec<String> {
+    let on_path = |exe: &str| {
+        std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(exe))
+                .find(|p| p.is_file())
+        })
+    };
+    ["pwsh.exe", "powershell.exe"]
+        .into_iter()
+        .filter_map(on_path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .chain(
+            std::env::var("COMSPEC")
+                .ok()
+                .filter(|p| Path::new(p).exists()),
+        )
+        .collect()
+}
+
 /// Rank shells from most elite (0) to least (5+).
 fn shell_rank(path: &str) -> u8 {
     let name = path.rsplit('/').next().unwrap_or(path);
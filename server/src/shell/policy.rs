@@ -0,0 +1,339 @@
+//! Operator allow/deny rules for commands.
+//!
+//! `[[policy.rules]]` are checked before a command runs: one-shot exec
+//! (REST exec, stream, batch, commit-confirm rollbacks and their tunnel
+//! forms), playbook steps, `session.exec`, `job.start` and service actions
+//! (as the equivalent `systemctl`/`rc-service` command). A refused command
+//! never runs; the caller gets `403 POLICY_DENIED` and a `policy_denied`
+//! activity entry records it.
+//!
+//! The command is split into simple commands as for
+//! [`classify`](super::classify::simple_commands), so `cd / && rm -rf /`,
+//! `sudo mkfs.ext4 ...` and `sh -c 'dd of=/dev/sda'` are each judged by the
+//! command that does the damage. For every simple command the rules are tried
+//! in order, skipping those whose `keys` or `sources` don't include the
+//! caller; the first whose `pattern` (shell glob on the whole text) or
+//! `regex` (searched anywhere in it) matches decides. Wrapped forms are tried
+//! too: `sudo rm -rf /` is refused by a rule for `rm -rf /*`. A command line
+//! is refused if any part is denied, or, with `default = "deny"`, if any part
+//! matches no allow rule.
+//!
+//! Like [`classify`](super::classify), this sees command text only: a script
+//! or binary that deletes files internally isn't caught. Interactive input
+//! (`session.stdin`) isn't checked.
+
+use std::ffi::CString;
+
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::activity::{ActivitySource, ActivityType};
+use crate::config::PolicyConfig;
+use crate::error::{codes, ApiError};
+use crate::shell::classify;
+use crate::state::AppState;
+
+type GuardError = (StatusCode, Json<ApiError>);
+
+/// What a rule, or the policy default, does with a command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+enum Matcher {
+    Glob(CString),
+    Regex(regex_lite::Regex),
+}
+
+struct Rule {
+    action: Action,
+    matcher: Matcher,
+    keys: Vec<String>,
+    sources: Vec<ActivitySource>,
+    reason: Option<String>,
+}
+
+impl Rule {
+    fn applies(&self, key: Option<&str>, source: ActivitySource) -> bool {
+        (self.keys.is_empty() || key.is_some_and(|k| self.keys.iter().any(|r| r == k)))
+            && (self.sources.is_empty() || self.sources.contains(&source))
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match &self.matcher {
+            Matcher::Glob(pattern) => {
+                CString::new(text).is_ok_and(|text| classify::glob(pattern, &text))
+            }
+            Matcher::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+/// Why a command was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Denial {
+    /// The simple command that was refused.
+    pub command: String,
+    /// Index into `[[policy.rules]]`; `None` when refused by `default`.
+    pub rule: Option<usize>,
+    pub message: String,
+}
+
+/// Compiled `[policy]`.
+pub struct Policy {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            default: Action::Allow,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// Compile `config`.
+    ///
+    /// # Errors
+    ///
+    /// A rule sets both or neither of `pattern` and `regex`, has an invalid
+    /// regex, or names an unknown source.
+    pub fn new(config: &PolicyConfig) -> Result<Self, String> {
+        let rules = config
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let matcher = match (&r.pattern, &r.regex) {
+                    (Some(pattern), None) => Matcher::Glob(
+                        CString::new(pattern.as_str())
+                            .map_err(|_| format!("policy.rules[{i}].pattern contains NUL"))?,
+                    ),
+                    (None, Some(regex)) => Matcher::Regex(
+                        regex_lite::Regex::new(regex)
+                            .map_err(|e| format!("policy.rules[{i}].regex is invalid: {e}"))?,
+                    ),
+                    _ => {
+                        return Err(format!(
+                            "policy.rules[{i}] must set exactly one of pattern, regex"
+                        ))
+                    }
+                };
+                let sources = r
+                    .sources
+                    .iter()
+                    .map(|s| match ActivitySource::from_str_opt(s) {
+                        Some(source) if source != ActivitySource::Unknown => Ok(source),
+                        _ => Err(format!(
+                            "policy.rules[{i}].sources '{s}' must be one of mcp, ws, rest, tunnel, mqtt"
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Rule {
+                    action: r.action,
+                    matcher,
+                    keys: r.keys.clone(),
+                    sources,
+                    reason: r.reason.clone(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            default: config.default,
+            rules,
+        })
+    }
+
+    /// A policy that refuses everything, used when `[policy]` doesn't compile.
+    pub fn deny_all() -> Self {
+        Self {
+            default: Action::Deny,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Judge `command` for a caller using `key` from `source`.
+    ///
+    /// # Errors
+    ///
+    /// The command is refused.
+    pub fn evaluate(
+        &self,
+        command: &str,
+        key: Option<&str>,
+        source: ActivitySource,
+    ) -> Result<(), Denial> {
+        if self.rules.is_empty() && self.default == Action::Allow {
+            return Ok(());
+        }
+        let applicable: Vec<(usize, &Rule)> = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.applies(key, source))
+            .collect();
+        for forms in classify::simple_commands(command) {
+            let mut allowed = false;
+            for form in &forms {
+                match applicable.iter().find(|(_, r)| r.matches(form)) {
+                    Some((i, rule)) if rule.action == Action::Deny => {
+                        let message = rule.reason.clone().unwrap_or_else(|| {
+                            format!("Command '{form}' is denied by policy rule {i}")
+                        });
+                        return Err(Denial {
+                            command: form.clone(),
+                            rule: Some(*i),
+                            message,
+                        });
+                    }
+                    Some(_) => allowed = true,
+                    None => {}
+                }
+            }
+            if !allowed && self.default == Action::Deny {
+                let command = forms.first().cloned().unwrap_or_default();
+                return Err(Denial {
+                    message: format!("Command '{command}' is not allowed by any policy rule"),
+                    command,
+                    rule: None,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check `command` against `[policy]` for the current caller. A refusal is
+/// logged as `policy_denied` and returned as `403 POLICY_DENIED`.
+pub async fn check(
+    state: &AppState,
+    source: ActivitySource,
+    command: &str,
+) -> Result<(), GuardError> {
+    let key = crate::auth::current_key();
+    let Err(denial) = state.policy.evaluate(command, key.as_deref(), source) else {
+        return Ok(());
+    };
+    tracing::warn!(command = %denial.command, rule = ?denial.rule, "Command denied by policy");
+    let detail = json!({
+        "command": command,
+        "denied": denial.command,
+        "rule": denial.rule,
+    });
+    state
+        .activity_log
+        .log(
+            ActivityType::PolicyDenied,
+            source,
+            crate::activity::truncate_str(command, 80),
+            Some(detail.clone()),
+            None,
+        )
+        .await;
+    Err(ApiError::new(codes::POLICY_DENIED, denial.message)
+        .with_detail(detail)
+        .into_response_with(StatusCode::FORBIDDEN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PolicyRule;
+
+    fn rule(action: Action, pattern: Option<&str>, regex: Option<&str>) -> PolicyRule {
+        PolicyRule {
+            action,
+            pattern: pattern.map(String::from),
+            regex: regex.map(String::from),
+            keys: Vec::new(),
+            sources: Vec::new(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn denies_dangerous_parts_wherever_they_hide() {
+        let mut mkfs = rule(Action::Deny, Some("mkfs*"), None);
+        mkfs.sources = vec!["mcp".into()];
+        mkfs.reason = Some("no mkfs from agents".into());
+        let config = PolicyConfig {
+            default: Action::Allow,
+            rules: vec![
+                rule(Action::Allow, Some("rm -rf /tmp/*"), None),
+                rule(Action::Deny, None, Some(r"^rm\s+-\w*[rR].*\s/\*?(\s|$)")),
+                mkfs,
+            ],
+        };
+        let policy = Policy::new(&config).unwrap();
+        let mcp = |c: &str| policy.evaluate(c, None, ActivitySource::Mcp);
+
+        assert!(mcp("ls -la / && df -h").is_ok());
+        assert!(mcp("rm -rf /tmp/build").is_ok());
+        for cmd in [
+            "rm -rf /",
+            "cd /data; rm -fr / --no-preserve-root",
+            "sudo rm -rf /*",
+            "sh -c 'uptime && rm -r /'",
+            "echo $(rm -Rf /)",
+            "find /data -exec sh -c 'rm -rf /' ';'",
+        ] {
+            let denial = mcp(cmd).unwrap_err();
+            assert_eq!(denial.rule, Some(1), "{cmd}");
+        }
+        let denial = mcp("timeout 60 mkfs.ext4 /dev/sda1").unwrap_err();
+        assert_eq!(denial.command, "mkfs.ext4 /dev/sda1");
+        assert_eq!(denial.message, "no mkfs from agents");
+        // Source-scoped rules leave other callers alone.
+        assert!(policy
+            .evaluate("mkfs.ext4 /dev/sda1", Some("default"), ActivitySource::Rest)
+            .is_ok());
+    }
+
+    #[test]
+    fn default_deny_is_an_allowlist_per_key() {
+        let mut ops = rule(Action::Allow, Some("*"), None);
+        ops.keys = vec!["ops".into()];
+        let config = PolicyConfig {
+            default: Action::Deny,
+            rules: vec![
+                rule(Action::Allow, Some("uptime"), None),
+                rule(Action::Allow, None, Some("^(cat|uci show) ")),
+                ops,
+            ],
+        };
+        let policy = Policy::new(&config).unwrap();
+        let ci = |c: &str| policy.evaluate(c, Some("ci"), ActivitySource::Rest);
+        assert!(ci("uptime; cat /proc/loadavg | uci show network").is_ok());
+        let denial = ci("uptime && reboot").unwrap_err();
+        assert_eq!((denial.command.as_str(), denial.rule), ("reboot", None));
+        assert!(policy
+            .evaluate("reboot", Some("ops"), ActivitySource::Rest)
+            .is_ok());
+        assert!(Policy::deny_all()
+            .evaluate("true", None, ActivitySource::Ws)
+            .is_err());
+
+        let bad = |r: PolicyRule| {
+            Policy::new(&PolicyConfig {
+                default: Action::Allow,
+                rules: vec![r],
+            })
+            .err()
+        };
+        assert!(bad(rule(Action::Deny, None, None)).is_some());
+        assert!(bad(rule(Action::Deny, Some("x"), Some("x"))).is_some());
+        assert!(bad(rule(Action::Deny, None, Some("("))).is_some());
+        let mut unknown = rule(Action::Deny, Some("x"), None);
+        unknown.sources = vec!["telnet".into()];
+        assert!(bad(unknown).is_some());
+    }
+}
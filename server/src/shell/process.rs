@@ -4,12 +4,19 @@
 //! [`spawn_shell`] for interactive sessions and [`exec_command`] for one-shot
 //! commands. Both set `kill_on_drop(true)` so orphaned processes are cleaned up
 //! if the owning task is cancelled.
+//!
+//! Windows has no process groups to signal, so each group leader is put in a
+//! job object instead and [`signal_group`] maps the POSIX signal numbers onto
+//! it: `SIGKILL`, `SIGTERM` and `SIGHUP` terminate the job, `SIGINT` sends
+//! the group a Ctrl-Break.
 
 use std::collections::HashMap;
+use std::ffi::CString;
 use std::fmt::Write;
 use std::process::Stdio;
 use tokio::io::AsyncReadExt;
 use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
 
 /// Max output size per stream for [`exec_command`] (1 MB).
 ///
@@ -44,11 +51,13 @@ pub fn spawn_shell(shell: &str, working_dir: &str) -> std::io::Result<Child> {
 /// the entire process tree via `kill(-pgid, signal)`.
 ///
 /// Also accepts optional environment variables to merge into the child's
-/// inherited environment.
+/// inherited environment, and the `cgroup.procs` of a session cgroup for the
+/// child to join before exec (see [`super::cgroup`]).
 pub fn spawn_shell_pgroup(
     shell: &str,
     working_dir: &str,
     env: Option<&HashMap<String, String>>,
+    cgroup: Option<&CString>,
 ) -> std::io::Result<Child> {
     let mut cmd = Command::new(shell);
     cmd.current_dir(working_dir)
@@ -59,14 +68,7 @@ pub fn spawn_shell_pgroup(
     if let Some(vars) = env {
         cmd.envs(vars);
     }
-    // SAFETY: setpgid is async-signal-safe per POSIX.
-    unsafe {
-        cmd.pre_exec(|| {
-            libc::setpgid(0, 0);
-            Ok(())
-        });
-    }
-    cmd.spawn()
+    spawn_group(cmd, cgroup)
 }
 
 /// Spawn `<shell> -c "<command>"` in its own process group with piped output.
@@ -81,11 +83,11 @@ pub fn spawn_command_pgroup(
     working_dir: &str,
     command: &str,
     env: Option<&HashMap<String, String>>,
+    cgroup: Option<&CString>,
 ) -> std::io::Result<Child> {
     let mut cmd = Command::new(shell);
-    cmd.arg("-c")
-        .arg(command)
-        .current_dir(working_dir)
+    shell_args(&mut cmd, shell, command);
+    cmd.current_dir(working_dir)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -93,14 +95,7 @@ pub fn spawn_command_pgroup(
     if let Some(vars) = env {
         cmd.envs(vars);
     }
-    // SAFETY: setpgid is async-signal-safe per POSIX.
-    unsafe {
-        cmd.pre_exec(|| {
-            libc::setpgid(0, 0);
-            Ok(())
-        });
-    }
-    cmd.spawn()
+    spawn_group(cmd, cgroup)
 }
 
 /// Execute a one-shot command via `<shell> -c "<command>"` and capture output.
@@ -121,22 +116,102 @@ pub async fn exec_command(
     timeout_ms: u64,
     env: Option<&HashMap<String, String>>,
 ) -> Result<ExecResult, ExecError> {
+    let mut cmd = Command::new(shell);
+    shell_args(&mut cmd, shell, command);
+    run_captured(cmd, working_dir, timeout_ms, env, None, None).await
+}
+
+/// Like [`exec_command`], optionally running the command under `sudo`.
+///
+/// With a password, runs `sudo -S -p ''` and writes the password to the
+/// child's stdin before closing it; without one, runs `sudo -n` so a missing
+/// `NOPASSWD` rule fails immediately instead of waiting on a prompt. See
+/// [`super::sudo`] for the request-level options.
+pub async fn exec_command_with(
+    shell: &str,
+    working_dir: &str,
+    command: &str,
+    timeout_ms: u64,
+    env: Option<&HashMap<String, String>>,
+    sudo: Option<&super::sudo::ExecSudo>,
+) -> Result<ExecResult, ExecError> {
+    let Some(sudo) = sudo else {
+        return exec_command(shell, working_dir, command, timeout_ms, env).await;
+    };
+    let password = sudo.password();
+    let mut cmd = Command::new("sudo");
+    cmd.args(super::sudo::exec_args(shell, command, password.is_some()));
+    run_captured(cmd, working_dir, timeout_ms, env, password, None).await
+}
+
+/// Like [`exec_command_with`], also sending output to `tee` as it is read.
+///
+/// Chunks are whole UTF-8 characters (a read that ends mid-character holds
+/// the partial bytes back for the next chunk) and are not capped; the
+/// returned [`ExecResult`] still is. A full channel slows the reads, and a
+/// closed one just stops the tee.
+pub async fn exec_command_streaming(
+    shell: &str,
+    working_dir: &str,
+    command: &str,
+    timeout_ms: u64,
+    env: Option<&HashMap<String, String>>,
+    sudo: Option<&super::sudo::ExecSudo>,
+    tee: mpsc::Sender<OutputChunk>,
+) -> Result<ExecResult, ExecError> {
+    let password = sudo.and_then(super::sudo::ExecSudo::password);
+    let cmd = if sudo.is_some() {
+        let mut cmd = Command::new("sudo");
+        cmd.args(super::sudo::exec_args(shell, command, password.is_some()));
+        cmd
+    } else {
+        let mut cmd = Command::new(shell);
+        shell_args(&mut cmd, shell, command);
+        cmd
+    };
+    run_captured(cmd, working_dir, timeout_ms, env, password, Some(&tee)).await
+}
+
+/// Spawn a prepared command and capture its output under a timeout. When
+/// `stdin_secret` is set it is written (followed by a newline) to the child's
+/// stdin, which is then closed; otherwise stdin is `/dev/null`. With `tee`,
+/// output is also sent there as it is read.
+///
+/// The command runs in its own process group. If it times out, or this future
+/// is dropped (request deadline or client disconnect), the whole group is
+/// killed — not just the shell, which would leave its children running.
+async fn run_captured(
+    mut cmd: Command,
+    working_dir: &str,
+    timeout_ms: u64,
+    env: Option<&HashMap<String, String>>,
+    stdin_secret: Option<&str>,
+    tee: Option<&mpsc::Sender<OutputChunk>>,
+) -> Result<ExecResult, ExecError> {
+    use tokio::io::AsyncWriteExt;
+
     let start = std::time::Instant::now();
 
-    let mut cmd = Command::new(shell);
-    cmd.arg("-c")
-        .arg(command)
-        .current_dir(working_dir)
-        .stdin(Stdio::null())
+    cmd.current_dir(working_dir)
+        .stdin(if stdin_secret.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .kill_on_drop(true);
     if let Some(vars) = env {
         cmd.envs(vars);
     }
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| ExecError::SpawnFailed(e.to_string()))?;
+    let mut child = spawn_group(cmd, None).map_err(|e| ExecError::SpawnFailed(e.to_string()))?;
+    let mut group = KillGroupOnDrop(child.id());
+
+    if let (Some(secret), Some(mut stdin)) = (stdin_secret, child.stdin.take()) {
+        // A failed write surfaces as sudo's own authentication error.
+        let _ = stdin.write_all(format!("{secret}\n").as_bytes()).await;
+        drop(stdin);
+    }
 
     let mut stdout = child
         .stdout
@@ -151,8 +226,16 @@ pub async fn exec_command(
     match Box::pin(tokio::time::timeout(timeout, async {
         // Read stdout and stderr concurrently to avoid pipe deadlock
         let (stdout_data, stderr_data) = tokio::join!(
-            read_capped(&mut stdout, MAX_EXEC_OUTPUT),
-            read_capped(&mut stderr, MAX_EXEC_OUTPUT),
+            read_capped(
+                &mut stdout,
+                MAX_EXEC_OUTPUT,
+                tee.map(|tx| (tx, OutputStream::Stdout))
+            ),
+            read_capped(
+                &mut stderr,
+                MAX_EXEC_OUTPUT,
+                tee.map(|tx| (tx, OutputStream::Stderr))
+            ),
         );
         // Drop pipe handles so child sees EOF
         drop(stdout);
@@ -175,11 +258,259 @@ pub async fn exec_command(
     }))
     .await
     {
-        Ok(result) => result,
+        Ok(result) => {
+            if result.is_ok() {
+                group.disarm();
+            }
+            result
+        }
         Err(_) => Err(ExecError::Timeout),
     }
 }
 
+/// Sends SIGKILL to a process group on drop unless disarmed.
+struct KillGroupOnDrop(Option<u32>);
+
+impl KillGroupOnDrop {
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for KillGroupOnDrop {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            let _ = signal_group(pgid, SIGKILL);
+        }
+    }
+}
+
+/// `SIGINT`, under the POSIX number on every platform.
+pub const SIGINT: i32 = 2;
+/// `SIGKILL`, under the POSIX number on every platform.
+pub const SIGKILL: i32 = 9;
+/// `SIGTERM`, under the POSIX number on every platform.
+pub const SIGTERM: i32 = 15;
+#[cfg(windows)]
+const SIGHUP: i32 = 1;
+
+/// Add `<shell> -c <command>`, or what the shell takes instead: `cmd.exe`
+/// wants `/C` and the command line untouched, PowerShell `-Command`.
+pub fn shell_args(cmd: &mut Command, shell: &str, command: &str) {
+    #[cfg(windows)]
+    {
+        let name = std::path::Path::new(shell)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "cmd" => {
+                cmd.arg("/C").raw_arg(command);
+                return;
+            }
+            "powershell" | "pwsh" => {
+                cmd.args(["-NoProfile", "-Command", command]);
+                return;
+            }
+            _ => {}
+        }
+    }
+    #[cfg(unix)]
+    let _ = shell;
+    cmd.arg("-c").arg(command);
+}
+
+/// Spawn `cmd` as the leader of a new process group, in the session cgroup
+/// whose `cgroup.procs` is `cgroup` if given.
+#[cfg(unix)]
+fn spawn_group(mut cmd: Command, cgroup: Option<&CString>) -> std::io::Result<Child> {
+    let cgroup = cgroup.cloned();
+    // SAFETY: setpgid and the calls in `cgroup::join` are async-signal-safe
+    // per POSIX.
+    unsafe {
+        cmd.pre_exec(move || {
+            libc::setpgid(0, 0);
+            if let Some(procs) = &cgroup {
+                super::cgroup::join(procs)?;
+            }
+            Ok(())
+        });
+    }
+    cmd.spawn()
+}
+
+/// Spawn `cmd` in a new process group and job object. Cgroups don't exist
+/// here; [`super::cgroup::Cgroups::open`] already refused to enable them.
+#[cfg(windows)]
+fn spawn_group(mut cmd: Command, _cgroup: Option<&CString>) -> std::io::Result<Child> {
+    use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+
+    let child = cmd.creation_flags(CREATE_NEW_PROCESS_GROUP).spawn()?;
+    if let (Some(pid), Some(handle)) = (child.id(), child.raw_handle()) {
+        jobs::add(pid, handle)?;
+    }
+    Ok(child)
+}
+
+/// Send `signal` to the process group led by `pgid`. Signal 0 only checks
+/// that the leader exists.
+#[cfg(unix)]
+pub fn signal_group(pgid: u32, signal: i32) -> std::io::Result<()> {
+    let pgid = i32::try_from(pgid).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    // SAFETY: kill has no memory-safety preconditions.
+    if unsafe { libc::kill(-pgid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Send `signal` to the process group led by `pgid`. Signal 0 only checks
+/// that the leader exists. A group sctl didn't start (an orphan from an
+/// earlier run) has no job, so only its leader can be terminated.
+#[cfg(windows)]
+pub fn signal_group(pgid: u32, signal: i32) -> std::io::Result<()> {
+    use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+    match signal {
+        0 if process_alive(pgid) => Ok(()),
+        0 => Err(std::io::ErrorKind::NotFound.into()),
+        SIGINT => {
+            // SAFETY: plain FFI call without pointers.
+            if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pgid) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+        SIGHUP | SIGKILL | SIGTERM => jobs::terminate(pgid),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("signal {signal} has no Windows equivalent"),
+        )),
+    }
+}
+
+/// Whether a process with this pid is running.
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    i32::try_from(pid)
+        // SAFETY: kill with signal 0 only checks for existence.
+        .is_ok_and(|pid| unsafe { libc::kill(pid, 0) } == 0)
+}
+
+/// Whether a process with this pid is running.
+#[cfg(windows)]
+pub fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    // SAFETY: the handle is checked before use and closed once.
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return false;
+        }
+        let mut code = 0u32;
+        let ok = GetExitCodeProcess(process, std::ptr::addr_of_mut!(code));
+        CloseHandle(process);
+        ok != 0 && code == STILL_ACTIVE as u32
+    }
+}
+
+/// Job objects standing in for process groups, keyed by the leader's pid.
+#[cfg(windows)]
+pub(crate) mod jobs {
+    use std::collections::HashMap;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+    use std::sync::{Mutex, PoisonError};
+
+    use windows_sys::Win32::Foundation::{DuplicateHandle, DUPLICATE_SAME_ACCESS, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, OpenProcess, TerminateProcess, WaitForSingleObject, PROCESS_TERMINATE,
+    };
+
+    struct Job {
+        job: OwnedHandle,
+        leader: OwnedHandle,
+    }
+
+    static JOBS: Mutex<Option<HashMap<u32, Job>>> = Mutex::new(None);
+
+    /// Put the process `leader` (pid `pid`) and its future children in a new
+    /// job. Jobs whose leader has exited are dropped on the way.
+    pub(crate) fn add(pid: u32, leader: RawHandle) -> std::io::Result<()> {
+        // SAFETY: `leader` is a live process handle owned by the caller; the
+        // duplicate and the new job are owned here and closed on drop.
+        let job = unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let job = OwnedHandle::from_raw_handle(job);
+            if AssignProcessToJobObject(job.as_raw_handle(), leader) == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut dup = std::ptr::null_mut();
+            if DuplicateHandle(
+                GetCurrentProcess(),
+                leader,
+                GetCurrentProcess(),
+                std::ptr::addr_of_mut!(dup),
+                0,
+                0,
+                DUPLICATE_SAME_ACCESS,
+            ) == 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Job {
+                job,
+                leader: OwnedHandle::from_raw_handle(dup),
+            }
+        };
+        let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+        let jobs = jobs.get_or_insert_with(HashMap::new);
+        // SAFETY: the leader handles are owned by the map.
+        jobs.retain(
+            |_, j| unsafe { WaitForSingleObject(j.leader.as_raw_handle(), 0) } != WAIT_OBJECT_0,
+        );
+        jobs.insert(pid, job);
+        Ok(())
+    }
+
+    /// Terminate every process in the job led by `pid`, or just the process
+    /// `pid` if it has no job.
+    pub(crate) fn terminate(pid: u32) -> std::io::Result<()> {
+        let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(job) = jobs.as_mut().and_then(|jobs| jobs.remove(&pid)) {
+            // SAFETY: the job handle is owned by `job`.
+            if unsafe { TerminateJobObject(job.job.as_raw_handle(), 1) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            return Ok(());
+        }
+        drop(jobs);
+        // SAFETY: the handle is checked before use and closed on drop.
+        unsafe {
+            let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+            if process.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let process = OwnedHandle::from_raw_handle(process);
+            if TerminateProcess(process.as_raw_handle(), 1) == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Read from an async reader, keeping the first `max_bytes` and discarding the
 /// rest.
 ///
@@ -187,10 +518,18 @@ pub async fn exec_command(
 /// the pipe early — closing a pipe while the child is still writing causes
 /// SIGPIPE / broken pipe errors and potential deadlocks when the child is also
 /// writing to the other stream.
-async fn read_capped(reader: &mut (impl tokio::io::AsyncRead + Unpin), max_bytes: usize) -> String {
+///
+/// With `tee`, everything read is also sent on as [`OutputChunk`]s.
+async fn read_capped(
+    reader: &mut (impl tokio::io::AsyncRead + Unpin),
+    max_bytes: usize,
+    mut tee: Option<(&mpsc::Sender<OutputChunk>, OutputStream)>,
+) -> String {
     let mut buf = Vec::with_capacity(max_bytes.min(65536));
     let mut tmp = [0u8; 8192];
     let mut total_read = 0usize;
+    // Tee bytes not yet sent: the start of a character split across reads.
+    let mut partial = Vec::new();
     loop {
         match reader.read(&mut tmp).await {
             Ok(0) | Err(_) => break,
@@ -200,9 +539,24 @@ async fn read_capped(reader: &mut (impl tokio::io::AsyncRead + Unpin), max_bytes
                     let take = n.min(max_bytes - buf.len());
                     buf.extend_from_slice(&tmp[..take]);
                 }
+                if let Some((tx, stream)) = tee {
+                    partial.extend_from_slice(&tmp[..n]);
+                    let complete = complete_utf8_len(&partial);
+                    if complete > 0 {
+                        let data = String::from_utf8_lossy(&partial[..complete]).into_owned();
+                        partial.drain(..complete);
+                        if tx.send(OutputChunk { stream, data }).await.is_err() {
+                            tee = None;
+                        }
+                    }
+                }
             }
         }
     }
+    if let Some((tx, stream)) = tee.filter(|_| !partial.is_empty()) {
+        let data = String::from_utf8_lossy(&partial).into_owned();
+        let _ = tx.send(OutputChunk { stream, data }).await;
+    }
     let mut s = String::from_utf8_lossy(&buf).into_owned();
     if total_read > max_bytes {
         let _ = write!(
@@ -213,6 +567,43 @@ async fn read_capped(reader: &mut (impl tokio::io::AsyncRead + Unpin), max_bytes
     s
 }
 
+/// Length of `buf` minus a trailing incomplete UTF-8 sequence, if any.
+/// Invalid bytes elsewhere count as complete (they decode lossily).
+fn complete_utf8_len(buf: &[u8]) -> usize {
+    for back in 1..=buf.len().min(3) {
+        let byte = buf[buf.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let width = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if width > back {
+                buf.len() - back
+            } else {
+                buf.len()
+            };
+        }
+    }
+    buf.len()
+}
+
+/// Which pipe an [`OutputChunk`] was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Output sent by [`exec_command_streaming`] as it is read.
+#[derive(Debug)]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub data: String,
+}
+
 /// Successful result of [`exec_command`].
 #[derive(Debug, serde::Serialize)]
 pub struct ExecResult {
@@ -246,3 +637,40 @@ impl std::fmt::Display for ExecError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_back_split_characters() {
+        let euro = "€".as_bytes(); // 3 bytes
+        assert_eq!(complete_utf8_len(b"abc"), 3);
+        assert_eq!(complete_utf8_len(&euro[..1]), 0);
+        assert_eq!(complete_utf8_len(&[b'a', euro[0], euro[1]]), 1);
+        assert_eq!(complete_utf8_len(&[b'a', euro[0], euro[1], euro[2]]), 4);
+        // Stray continuation and invalid bytes are passed through.
+        assert_eq!(complete_utf8_len(&[b'a', 0x80]), 2);
+        assert_eq!(complete_utf8_len(&[b'a', 0xFF]), 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn exec_captures_output_and_times_out() {
+        let result = exec_command("/bin/sh", "/", "echo out; echo err >&2; exit 3", 5000, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            (
+                result.exit_code,
+                result.stdout.as_str(),
+                result.stderr.as_str()
+            ),
+            (3, "out\n", "err\n")
+        );
+        assert!(matches!(
+            exec_command("/bin/sh", "/", "sleep 5", 100, None).await,
+            Err(ExecError::Timeout)
+        ));
+    }
+}
@@ -2,103 +2,560 @@
 //!
 //! Uses the `nix` crate for POSIX PTY APIs. The PTY master fd is kept alive for
 //! the session lifetime so I/O and resize operations can be performed on it.
+//!
+//! On Windows the PTY is a ConPTY pseudo console: the master is the console
+//! handle plus the pipes feeding its input and draining its output, and the
+//! shell is started with `CreateProcessW` attached to it.
 
 use std::collections::HashMap;
-use std::os::fd::{AsRawFd, OwnedFd};
-use std::process::Stdio;
+use std::ffi::CString;
+use std::io;
 
-use nix::pty::{openpty, OpenptyResult, Winsize};
-use tokio::process::{Child, Command};
+#[cfg(unix)]
+pub use unix::*;
+#[cfg(windows)]
+pub use windows::*;
 
-/// An allocated PTY pair (master + slave).
-pub struct PtyPair {
-    pub master: OwnedFd,
-    pub slave: OwnedFd,
-}
+#[cfg(unix)]
+mod unix {
+    use super::{io, CString, HashMap};
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::process::Stdio;
 
-/// Allocate a PTY pair with the given terminal size.
-pub fn allocate_pty(rows: u16, cols: u16) -> Result<PtyPair, nix::Error> {
-    let winsize = Winsize {
-        ws_row: rows,
-        ws_col: cols,
-        ws_xpixel: 0,
-        ws_ypixel: 0,
-    };
-    let OpenptyResult { master, slave } = openpty(&winsize, None)?;
-    Ok(PtyPair { master, slave })
-}
+    use nix::pty::{openpty, OpenptyResult, Winsize};
+    use tokio::io::unix::AsyncFd;
+    use tokio::process::Command;
+
+    /// The master side, kept by the session for resize and echo checks.
+    pub type PtyMaster = OwnedFd;
+
+    /// The shell running on the PTY.
+    pub type PtyChild = tokio::process::Child;
+
+    /// An allocated PTY pair (master + slave).
+    pub struct PtyPair {
+        pub master: OwnedFd,
+        pub slave: OwnedFd,
+    }
+
+    /// Allocate a PTY pair with the given terminal size.
+    pub fn allocate_pty(rows: u16, cols: u16) -> io::Result<PtyPair> {
+        let winsize = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let OpenptyResult { master, slave } = openpty(&winsize, None)?;
+        Ok(PtyPair { master, slave })
+    }
 
-/// Spawn a shell on the slave side of the PTY.
-///
-/// The child becomes a session leader with the PTY slave as its controlling
-/// terminal. stdin/stdout/stderr are all connected to the slave fd.
-pub fn spawn_shell_pty(
-    pty: &PtyPair,
-    shell: &str,
-    working_dir: &str,
-    env: Option<&HashMap<String, String>>,
-) -> std::io::Result<Child> {
-    let slave_fd = pty.slave.as_raw_fd();
-    let mut cmd = Command::new(shell);
-    // Start as login shell so rc files (.zshrc, .bashrc, .profile, etc.) are sourced.
-    // This matches the behaviour of standard terminal emulators.
-    cmd.arg("-l");
-    cmd.current_dir(working_dir).kill_on_drop(true);
-
-    // The child's stdio is handled by pre_exec (dup2 to PTY slave), so tell
-    // tokio not to set up pipes.
-    cmd.stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
-
-    if let Some(vars) = env {
-        cmd.envs(vars);
-    }
-
-    // SAFETY: All syscalls used here are async-signal-safe per POSIX.
-    unsafe {
-        cmd.pre_exec(move || {
-            // Create a new session so the child is the session leader
-            if libc::setsid() == -1 {
-                return Err(std::io::Error::last_os_error());
+    /// Spawn a shell on the slave side of the PTY.
+    ///
+    /// The child becomes a session leader with the PTY slave as its controlling
+    /// terminal. stdin/stdout/stderr are all connected to the slave fd. With
+    /// `cgroup`, the child first joins that session cgroup (see
+    /// [`super::super::cgroup`]).
+    // `TIOCSCTTY` is `c_uint` on macOS, `c_ulong` on glibc and `c_int` on musl.
+    #[allow(clippy::cast_lossless, clippy::unnecessary_cast)]
+    pub fn spawn_shell_pty(
+        pty: &PtyPair,
+        shell: &str,
+        working_dir: &str,
+        env: Option<&HashMap<String, String>>,
+        cgroup: Option<&CString>,
+    ) -> io::Result<PtyChild> {
+        let slave_fd = pty.slave.as_raw_fd();
+        let mut cmd = Command::new(shell);
+        // Start as login shell so rc files (.zshrc, .bashrc, .profile, etc.) are sourced.
+        // This matches the behaviour of standard terminal emulators.
+        cmd.arg("-l");
+        cmd.current_dir(working_dir).kill_on_drop(true);
+
+        // The child's stdio is handled by pre_exec (dup2 to PTY slave), so tell
+        // tokio not to set up pipes.
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        if let Some(vars) = env {
+            cmd.envs(vars);
+        }
+
+        let cgroup = cgroup.cloned();
+        // SAFETY: All syscalls used here are async-signal-safe per POSIX.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(procs) = &cgroup {
+                    super::super::cgroup::join(procs)?;
+                }
+                // Create a new session so the child is the session leader
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Set the PTY slave as the controlling terminal
+                if libc::ioctl(slave_fd, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Redirect stdin/stdout/stderr to the PTY slave
+                libc::dup2(slave_fd, 0);
+                libc::dup2(slave_fd, 1);
+                libc::dup2(slave_fd, 2);
+                if slave_fd > 2 {
+                    libc::close(slave_fd);
+                }
+                Ok(())
+            });
+        }
+
+        cmd.spawn()
+    }
+
+    /// Resize a PTY's terminal window.
+    pub fn resize_pty(master: &PtyMaster, rows: u16, cols: u16) -> io::Result<()> {
+        let winsize = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCSWINSZ is a well-defined ioctl that writes a Winsize struct.
+        let ret = unsafe {
+            libc::ioctl(
+                master.as_raw_fd(),
+                libc::TIOCSWINSZ,
+                std::ptr::addr_of!(winsize),
+            )
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the terminal currently echoes input. Programs reading a secret
+    /// (`sudo`, `passwd`, `ssh`) switch echo off for the duration of the prompt.
+    pub fn echo_enabled(master: &PtyMaster) -> io::Result<bool> {
+        let termios = nix::sys::termios::tcgetattr(master)?;
+        Ok(termios
+            .local_flags
+            .contains(nix::sys::termios::LocalFlags::ECHO))
+    }
+
+    /// Non-blocking duplicate of the master fd, registered with the reactor.
+    fn async_dup(master: &PtyMaster) -> io::Result<AsyncFd<std::fs::File>> {
+        let fd = master.try_clone()?;
+        // SAFETY: plain fcntl calls on an fd we own.
+        unsafe {
+            let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+            {
+                return Err(io::Error::last_os_error());
             }
-            // Set the PTY slave as the controlling terminal
-            if libc::ioctl(slave_fd, libc::TIOCSCTTY, 0) == -1 {
-                return Err(std::io::Error::last_os_error());
+        }
+        AsyncFd::new(std::fs::File::from(fd))
+    }
+
+    /// Independent reader and writer for the master, so output can be read
+    /// while input is written. The master itself stays usable for resize.
+    pub fn split(master: &PtyMaster) -> io::Result<(PtyReader, PtyWriter)> {
+        Ok((PtyReader(async_dup(master)?), PtyWriter(async_dup(master)?)))
+    }
+
+    /// Output side of a PTY master.
+    pub struct PtyReader(AsyncFd<std::fs::File>);
+
+    impl PtyReader {
+        /// Read the next chunk of output; `Ok(0)` once the shell side is gone
+        /// (Linux reports that as `EIO`).
+        pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                let mut guard = self.0.readable().await?;
+                match guard.try_io(|inner| inner.get_ref().read(buf)) {
+                    Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                    Ok(result) => return result,
+                    Err(_would_block) => {}
+                }
             }
-            // Redirect stdin/stdout/stderr to the PTY slave
-            libc::dup2(slave_fd, 0);
-            libc::dup2(slave_fd, 1);
-            libc::dup2(slave_fd, 2);
-            if slave_fd > 2 {
-                libc::close(slave_fd);
+        }
+    }
+
+    /// Input side of a PTY master.
+    pub struct PtyWriter(AsyncFd<std::fs::File>);
+
+    impl PtyWriter {
+        /// Write all of `data`, waiting whenever the terminal's buffer is full.
+        pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+            let mut written = 0usize;
+            while written < data.len() {
+                let mut guard = self.0.writable().await?;
+                match guard.try_io(|inner| inner.get_ref().write(&data[written..])) {
+                    Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(Ok(n)) => written += n,
+                    Ok(Err(e)) => return Err(e),
+                    Err(_would_block) => {}
+                }
             }
             Ok(())
-        });
+        }
     }
-
-    cmd.spawn()
 }
 
-/// Resize a PTY's terminal window.
-pub fn resize_pty(master: &OwnedFd, rows: u16, cols: u16) -> Result<(), nix::Error> {
-    let winsize = Winsize {
-        ws_row: rows,
-        ws_col: cols,
-        ws_xpixel: 0,
-        ws_ypixel: 0,
+#[cfg(windows)]
+mod windows {
+    use super::{io, CString, HashMap};
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::os::windows::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use windows_sys::Win32::Foundation::{ERROR_BROKEN_PIPE, HANDLE, STILL_ACTIVE, WAIT_FAILED};
+    use windows_sys::Win32::System::Console::{
+        ClosePseudoConsole, CreatePseudoConsole, ResizePseudoConsole, COORD, HPCON,
     };
-    // SAFETY: TIOCSWINSZ is a well-defined ioctl that writes a Winsize struct.
-    let ret = unsafe {
-        libc::ioctl(
-            master.as_raw_fd(),
-            libc::TIOCSWINSZ,
-            std::ptr::addr_of!(winsize),
-        )
+    use windows_sys::Win32::System::Pipes::CreatePipe;
+    use windows_sys::Win32::System::Threading::{
+        CreateProcessW, DeleteProcThreadAttributeList, GetExitCodeProcess,
+        InitializeProcThreadAttributeList, ResumeThread, TerminateProcess,
+        UpdateProcThreadAttribute, WaitForSingleObject, CREATE_SUSPENDED,
+        CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT, INFINITE,
+        LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+        STARTF_USESTDHANDLES, STARTUPINFOEXW,
     };
-    if ret == -1 {
-        Err(nix::Error::last())
-    } else {
+
+    /// A ConPTY pseudo console and the pipes connected to it.
+    pub struct PtyMaster {
+        console: HPCON,
+        /// Write end of the console's input.
+        input: OwnedHandle,
+        /// Read end of the console's output.
+        output: OwnedHandle,
+    }
+
+    // SAFETY: an HPCON is a plain handle; the ConPTY calls made on it are
+    // thread-safe.
+    unsafe impl Send for PtyMaster {}
+    // SAFETY: as above; `&PtyMaster` only allows resizing.
+    unsafe impl Sync for PtyMaster {}
+
+    impl Drop for PtyMaster {
+        fn drop(&mut self) {
+            // SAFETY: the console is owned here and closed once.
+            unsafe { ClosePseudoConsole(self.console) };
+        }
+    }
+
+    /// An allocated pseudo console. Windows has no slave side to hand out;
+    /// the shell is attached through a process attribute instead.
+    pub struct PtyPair {
+        pub master: PtyMaster,
+    }
+
+    fn size(rows: u16, cols: u16) -> COORD {
+        COORD {
+            X: i16::try_from(cols).unwrap_or(i16::MAX),
+            Y: i16::try_from(rows).unwrap_or(i16::MAX),
+        }
+    }
+
+    fn pipe() -> io::Result<(OwnedHandle, OwnedHandle)> {
+        let (mut read, mut write): (HANDLE, HANDLE) = (std::ptr::null_mut(), std::ptr::null_mut());
+        // SAFETY: the out pointers are valid; the handles are owned on success.
+        unsafe {
+            if CreatePipe(
+                std::ptr::addr_of_mut!(read),
+                std::ptr::addr_of_mut!(write),
+                std::ptr::null(),
+                0,
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((
+                OwnedHandle::from_raw_handle(read),
+                OwnedHandle::from_raw_handle(write),
+            ))
+        }
+    }
+
+    /// Create a pseudo console with the given terminal size.
+    pub fn allocate_pty(rows: u16, cols: u16) -> io::Result<PtyPair> {
+        let (console_in, input) = pipe()?;
+        let (output, console_out) = pipe()?;
+        let mut console: HPCON = 0;
+        // SAFETY: both pipe ends are valid handles; ConPTY duplicates them, so
+        // ours are closed when this function returns.
+        let hr = unsafe {
+            CreatePseudoConsole(
+                size(rows, cols),
+                console_in.as_raw_handle(),
+                console_out.as_raw_handle(),
+                0,
+                std::ptr::addr_of_mut!(console),
+            )
+        };
+        if hr < 0 {
+            return Err(io::Error::from_raw_os_error(hr));
+        }
+        Ok(PtyPair {
+            master: PtyMaster {
+                console,
+                input,
+                output,
+            },
+        })
+    }
+
+    fn wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain([0]).collect()
+    }
+
+    /// Quote `arg` for `CommandLineToArgvW`.
+    fn quote(arg: &str) -> String {
+        if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+            return arg.to_string();
+        }
+        let mut quoted = String::from("\"");
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                    quoted.push('"');
+                    backslashes = 0;
+                }
+                _ => {
+                    quoted.extend(std::iter::repeat_n('\\', backslashes));
+                    quoted.push(c);
+                    backslashes = 0;
+                }
+            }
+        }
+        quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+        quoted.push('"');
+        quoted
+    }
+
+    /// Our environment with `env` merged in, as a sorted UTF-16 block.
+    fn environment_block(env: Option<&HashMap<String, String>>) -> Vec<u16> {
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .filter(|(k, _)| !env.is_some_and(|env| env.keys().any(|e| e.eq_ignore_ascii_case(k))))
+            .collect();
+        vars.extend(
+            env.into_iter()
+                .flatten()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        vars.sort_by_key(|(k, _)| k.to_uppercase());
+        let mut block = Vec::new();
+        for (k, v) in vars {
+            block.extend(format!("{k}={v}").encode_utf16());
+            block.push(0);
+        }
+        block.push(0);
+        block
+    }
+
+    /// Start `shell` attached to the pseudo console, in its own job (see
+    /// [`super::super::process::signal_group`]). Windows has no cgroups, so
+    /// `cgroup` is always `None` here.
+    pub fn spawn_shell_pty(
+        pty: &PtyPair,
+        shell: &str,
+        working_dir: &str,
+        env: Option<&HashMap<String, String>>,
+        _cgroup: Option<&CString>,
+    ) -> io::Result<PtyChild> {
+        let mut command_line = wide(OsStr::new(&quote(shell)));
+        let working_dir = wide(OsStr::new(working_dir));
+        let mut environment = environment_block(env);
+
+        // SAFETY: every pointer passed below outlives the calls, the attribute
+        // list is sized by the first call and deleted once, and the returned
+        // process and thread handles are owned on success.
+        unsafe {
+            let mut list_size = 0usize;
+            InitializeProcThreadAttributeList(
+                std::ptr::null_mut(),
+                1,
+                0,
+                std::ptr::addr_of_mut!(list_size),
+            );
+            let mut list = vec![0u8; list_size];
+            let attributes: LPPROC_THREAD_ATTRIBUTE_LIST = list.as_mut_ptr().cast();
+            if InitializeProcThreadAttributeList(
+                attributes,
+                1,
+                0,
+                std::ptr::addr_of_mut!(list_size),
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            let result = (|| {
+                if UpdateProcThreadAttribute(
+                    attributes,
+                    0,
+                    PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE as usize,
+                    pty.master.console as *const std::ffi::c_void,
+                    std::mem::size_of::<HPCON>(),
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                ) == 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut startup: STARTUPINFOEXW = std::mem::zeroed();
+                startup.StartupInfo.cb =
+                    u32::try_from(std::mem::size_of::<STARTUPINFOEXW>()).unwrap_or(u32::MAX);
+                // Null std handles, so the shell doesn't inherit sctl's own.
+                startup.StartupInfo.dwFlags = STARTF_USESTDHANDLES;
+                startup.lpAttributeList = attributes;
+                let mut info: PROCESS_INFORMATION = std::mem::zeroed();
+                if CreateProcessW(
+                    std::ptr::null(),
+                    command_line.as_mut_ptr(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                    0,
+                    EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT | CREATE_SUSPENDED,
+                    environment.as_mut_ptr().cast(),
+                    working_dir.as_ptr(),
+                    std::ptr::addr_of!(startup.StartupInfo),
+                    std::ptr::addr_of_mut!(info),
+                ) == 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(info)
+            })();
+            DeleteProcThreadAttributeList(attributes);
+            let info = result?;
+
+            let process = OwnedHandle::from_raw_handle(info.hProcess);
+            let thread = OwnedHandle::from_raw_handle(info.hThread);
+            // Suspended until it is in its job, so nothing it starts escapes.
+            let joined = super::super::process::jobs::add(info.dwProcessId, info.hProcess);
+            if let Err(e) = joined {
+                TerminateProcess(process.as_raw_handle(), 1);
+                return Err(e);
+            }
+            ResumeThread(thread.as_raw_handle());
+            Ok(PtyChild {
+                pid: info.dwProcessId,
+                process,
+            })
+        }
+    }
+
+    /// The shell running on the pseudo console. Dropping it terminates the
+    /// shell, like `kill_on_drop` does for a [`tokio::process::Child`].
+    pub struct PtyChild {
+        pid: u32,
+        process: OwnedHandle,
+    }
+
+    impl PtyChild {
+        /// The shell's process id.
+        #[allow(clippy::unnecessary_wraps)]
+        pub fn id(&self) -> Option<u32> {
+            Some(self.pid)
+        }
+
+        /// Wait for the shell to exit.
+        pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+            let process = self.process.try_clone()?;
+            tokio::task::spawn_blocking(move || {
+                let mut code = 0u32;
+                // SAFETY: `process` is a live handle owned by this closure.
+                unsafe {
+                    if WaitForSingleObject(process.as_raw_handle(), INFINITE) == WAIT_FAILED
+                        || GetExitCodeProcess(process.as_raw_handle(), std::ptr::addr_of_mut!(code))
+                            == 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(ExitStatus::from_raw(code))
+            })
+            .await?
+        }
+    }
+
+    impl Drop for PtyChild {
+        fn drop(&mut self) {
+            let mut code = 0u32;
+            // SAFETY: the handle is owned by `self`.
+            unsafe {
+                if GetExitCodeProcess(self.process.as_raw_handle(), std::ptr::addr_of_mut!(code))
+                    != 0
+                    && code == STILL_ACTIVE as u32
+                {
+                    TerminateProcess(self.process.as_raw_handle(), 1);
+                }
+            }
+        }
+    }
+
+    /// Resize the pseudo console.
+    pub fn resize_pty(master: &PtyMaster, rows: u16, cols: u16) -> io::Result<()> {
+        // SAFETY: the console is owned by `master`.
+        let hr = unsafe { ResizePseudoConsole(master.console, size(rows, cols)) };
+        if hr < 0 {
+            return Err(io::Error::from_raw_os_error(hr));
+        }
         Ok(())
     }
+
+    /// ConPTY doesn't expose the console mode of the program it hosts.
+    pub fn echo_enabled(_master: &PtyMaster) -> io::Result<bool> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Reader and writer over duplicates of the console's pipes. Anonymous
+    /// pipes don't support overlapped I/O, so both go through tokio's
+    /// blocking pool.
+    pub fn split(master: &PtyMaster) -> io::Result<(PtyReader, PtyWriter)> {
+        let output = std::fs::File::from(master.output.try_clone()?);
+        let input = std::fs::File::from(master.input.try_clone()?);
+        Ok((
+            PtyReader(tokio::fs::File::from_std(output)),
+            PtyWriter(tokio::fs::File::from_std(input)),
+        ))
+    }
+
+    /// Output side of the pseudo console.
+    pub struct PtyReader(tokio::fs::File);
+
+    impl PtyReader {
+        /// Read the next chunk of output; `Ok(0)` once the console is closed.
+        pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf).await {
+                Err(e) if e.raw_os_error() == i32::try_from(ERROR_BROKEN_PIPE).ok() => Ok(0),
+                result => result,
+            }
+        }
+    }
+
+    /// Input side of the pseudo console.
+    pub struct PtyWriter(tokio::fs::File);
+
+    impl PtyWriter {
+        /// Write all of `data` to the console.
+        pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+            self.0.write_all(data).await?;
+            self.0.flush().await
+        }
+    }
 }
@@ -0,0 +1,369 @@
+//! `secret://` references in exec and session environments.
+//!
+//! Callers pass `env` values like `"DB_PASS": "secret://db_pass"` instead of
+//! shipping the secret through the request (and through whatever agent
+//! transcript produced it). sctl resolves the reference against the configured
+//! `[secrets]` provider immediately before spawning, and the plaintext only
+//! exists in the child's environment:
+//!
+//! - **file** — a TOML table of `name = "value"` pairs, re-read on every
+//!   resolve so rotation needs no restart. Refused if group/world accessible.
+//! - **env** — `secret://db_pass` reads `$SCTL_SECRET_DB_PASS` (prefix
+//!   configurable) from the sctl process environment.
+//! - **command** — runs `<command> <name>` and uses its stdout, for external
+//!   vaults (`pass`, `vault kv get`, a TPM-sealed store, ...).
+//!
+//! Exec output and its activity-log previews are passed through
+//! [`ResolvedEnv::redact`], so a command that echoes a secret returns
+//! `[REDACTED]` instead. Only whole values are references — `secret://` inside
+//! a longer string is passed through untouched.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::SecretsConfig;
+use crate::shell::process::ExecResult;
+
+/// Prefix marking an env value as a secret reference.
+pub const SECRET_SCHEME: &str = "secret://";
+
+/// Replacement for secret values found in output.
+const REDACTED: &str = "[REDACTED]";
+
+/// An env map with its secret references resolved.
+///
+/// Deliberately not `Debug` — the map holds plaintext values.
+pub struct ResolvedEnv {
+    env: Option<HashMap<String, String>>,
+    secrets: Vec<String>,
+}
+
+impl ResolvedEnv {
+    /// The environment to hand to the child process.
+    pub fn env(&self) -> Option<&HashMap<String, String>> {
+        self.env.as_ref()
+    }
+
+    /// Whether any value came from the secrets provider.
+    pub fn has_secrets(&self) -> bool {
+        !self.secrets.is_empty()
+    }
+
+    /// Replace every resolved secret value in `text` with `[REDACTED]`.
+    pub fn redact(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for secret in &self.secrets {
+            if out.contains(secret.as_str()) {
+                out = out.replace(secret.as_str(), REDACTED);
+            }
+        }
+        out
+    }
+
+    /// Redact stdout and stderr of an exec result in place.
+    pub fn redact_result(&self, result: &mut ExecResult) {
+        if self.has_secrets() {
+            result.stdout = self.redact(&result.stdout);
+            result.stderr = self.redact(&result.stderr);
+        }
+    }
+}
+
+/// Resolve `secret://` values in `env` using the configured provider.
+///
+/// Maps without references are returned unchanged (and without touching the
+/// provider), so requests that don't use secrets work with no `[secrets]`
+/// section.
+pub async fn resolve_env(
+    config: Option<&SecretsConfig>,
+    data_dir: &str,
+    env: Option<&HashMap<String, String>>,
+) -> Result<ResolvedEnv, String> {
+    let Some(env) = env else {
+        return Ok(ResolvedEnv {
+            env: None,
+            secrets: Vec::new(),
+        });
+    };
+    let mut resolved = env.clone();
+    let mut secrets = Vec::new();
+    for (key, value) in &mut resolved {
+        let Some(name) = value.strip_prefix(SECRET_SCHEME) else {
+            continue;
+        };
+        let Some(config) = config else {
+            return Err(format!(
+                "env {key} references a secret but no [secrets] provider is configured"
+            ));
+        };
+        if !valid_name(name) {
+            return Err(format!("env {key}: invalid secret name '{name}'"));
+        }
+        let secret = resolve_one(config, data_dir, name).await?;
+        if !secret.is_empty() {
+            secrets.push(secret.clone());
+        }
+        *value = secret;
+    }
+    // Longest first so a secret containing another is redacted whole.
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    Ok(ResolvedEnv {
+        env: Some(resolved),
+        secrets,
+    })
+}
+
+/// Whether `name` is safe to hand to a provider.
+///
+/// A leading `-` is refused so the command provider can't be fed an option.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'))
+}
+
+async fn resolve_one(config: &SecretsConfig, data_dir: &str, name: &str) -> Result<String, String> {
+    match config.provider.as_str() {
+        "file" => {
+            let path = config.file.clone().unwrap_or_else(|| {
+                std::path::Path::new(data_dir)
+                    .join("secrets.toml")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            read_secrets_file(&path)
+                .await?
+                .remove(name)
+                .ok_or_else(|| format!("secret '{name}' not found"))
+        }
+        "env" => {
+            let var = format!(
+                "{}{}",
+                config.env_prefix,
+                name.to_ascii_uppercase().replace(['-', '.', '/'], "_")
+            );
+            std::env::var(&var).map_err(|_| format!("secret '{name}' not found"))
+        }
+        "command" => {
+            let Some(ref command) = config.command else {
+                return Err("secrets.command is not configured".to_string());
+            };
+            let output = tokio::time::timeout(
+                Duration::from_secs(config.command_timeout_secs),
+                tokio::process::Command::new(command)
+                    .arg(name)
+                    .stdin(std::process::Stdio::null())
+                    .kill_on_drop(true)
+                    .output(),
+            )
+            .await
+            .map_err(|_| format!("secret '{name}': provider command timed out"))?
+            .map_err(|e| format!("secret '{name}': provider command failed: {e}"))?;
+            if !output.status.success() {
+                // stderr is not echoed back: helpers may print partial values.
+                return Err(format!(
+                    "secret '{name}' not found (provider exited with {})",
+                    output.status.code().unwrap_or(-1)
+                ));
+            }
+            let mut value = String::from_utf8(output.stdout)
+                .map_err(|_| format!("secret '{name}' is not valid UTF-8"))?;
+            if value.ends_with('\n') {
+                value.pop();
+                if value.ends_with('\r') {
+                    value.pop();
+                }
+            }
+            Ok(value)
+        }
+        other => Err(format!("unknown secrets provider '{other}'")),
+    }
+}
+
+/// Windows has no mode bits to check; the file's ACL is left to the operator.
+async fn read_secrets_file(path: &str) -> Result<HashMap<String, String>, String> {
+    let meta = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("secrets file {path}: {e}"))?;
+    if cfg!(unix) && crate::util::file_mode(&meta) & 0o077 != 0 {
+        return Err(format!(
+            "secrets file {path} is accessible by group/other (chmod 600)"
+        ));
+    }
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("secrets file {path}: {e}"))?;
+    // Parse errors can quote file content — keep only the location.
+    toml::from_str(&content).map_err(|e| {
+        format!(
+            "secrets file {path} is not a flat name = \"value\" table (at {:?})",
+            e.span()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(provider: &str) -> SecretsConfig {
+        SecretsConfig {
+            provider: provider.to_string(),
+            file: None,
+            env_prefix: "SCTL_TEST_SECRET_".to_string(),
+            command: None,
+            command_timeout_secs: 5,
+        }
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sctl_secrets_{name}_{}", std::process::id()))
+    }
+
+    #[test]
+    fn name_validation() {
+        for name in ["db_pass", "prod/db.pass", "a-b"] {
+            assert!(valid_name(name), "{name}");
+        }
+        for name in ["", "-help", "--version", "a b", "a;b", "$(x)"] {
+            assert!(!valid_name(name), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_and_option_like_names_are_refused() {
+        let mut cfg = config("command");
+        cfg.command = Some("echo".to_string());
+        let e = env(&[("X", "secret://--help")]);
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("invalid secret name"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn plain_values_need_no_provider() {
+        let e = env(&[("A", "1"), ("B", "not secret://x")]);
+        let resolved = resolve_env(None, "/tmp", Some(&e)).await.unwrap();
+        assert!(!resolved.has_secrets());
+        assert_eq!(resolved.env(), Some(&e));
+
+        let e = env(&[("A", "secret://x")]);
+        let err = resolve_env(None, "/tmp", Some(&e)).await.err().unwrap();
+        assert!(err.contains("no [secrets] provider"), "{err}");
+    }
+
+    #[test]
+    fn redacts_longest_secret_first() {
+        let mut secrets = vec!["abc".to_string(), "abcdef".to_string()];
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        let resolved = ResolvedEnv { env: None, secrets };
+        assert_eq!(
+            resolved.redact("x abcdef y abc"),
+            "x [REDACTED] y [REDACTED]"
+        );
+        let mut result = ExecResult {
+            exit_code: 0,
+            stdout: "abc\n".to_string(),
+            stderr: "ok".to_string(),
+            duration_ms: 0,
+        };
+        resolved.redact_result(&mut result);
+        assert_eq!(result.stdout, "[REDACTED]\n");
+        assert_eq!(result.stderr, "ok");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn file_provider_requires_private_mode() {
+        let path = temp_path("file");
+        std::fs::write(&path, "db_pass = \"hunter2\"\n").unwrap();
+        let mut cfg = config("file");
+        cfg.file = Some(path.to_string_lossy().into_owned());
+
+        crate::util::set_mode(&path, 0o644).unwrap();
+        let e = env(&[("P", "secret://db_pass")]);
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("chmod 600"), "{err}");
+
+        crate::util::set_mode(&path, 0o600).unwrap();
+        let resolved = resolve_env(Some(&cfg), "/tmp", Some(&e)).await.unwrap();
+        assert_eq!(resolved.env().unwrap()["P"], "hunter2");
+        assert_eq!(resolved.redact("pw=hunter2"), "pw=[REDACTED]");
+
+        let e = env(&[("P", "secret://missing")]);
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("not found"), "{err}");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn env_provider_reports_missing_variable() {
+        let cfg = config("env");
+        let e = env(&[("P", "secret://no-such.secret")]);
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err, "secret 'no-such.secret' not found");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_provider_errors() {
+        let e = env(&[("P", "secret://db_pass")]);
+
+        let err = resolve_env(Some(&config("command")), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("not configured"), "{err}");
+
+        let mut cfg = config("command");
+        cfg.command = Some("false".to_string());
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("provider exited with 1"), "{err}");
+
+        cfg.command = Some("/nonexistent/sctl-secret-helper".to_string());
+        let err = resolve_env(Some(&cfg), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.contains("provider command failed"), "{err}");
+
+        cfg.command = Some("echo".to_string());
+        let resolved = resolve_env(Some(&cfg), "/tmp", Some(&e)).await.unwrap();
+        assert_eq!(resolved.env().unwrap()["P"], "db_pass");
+    }
+
+    #[tokio::test]
+    async fn unknown_provider_is_an_error() {
+        let e = env(&[("P", "secret://x")]);
+        let err = resolve_env(Some(&config("vault")), "/tmp", Some(&e))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err, "unknown secrets provider 'vault'");
+    }
+}
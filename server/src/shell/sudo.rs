@@ -0,0 +1,257 @@
+//! Privileged execution via `sudo` for exec and sessions.
+//!
+//! Agents often drive devices where sctl itself isn't root. A request may ask
+//! for elevation with a `sudo` object; the password never travels with the
+//! request and isn't written to logs or the activity journal:
+//!
+//! - **`password_env`** names an environment variable *of the sctl process*
+//!   holding the password (e.g. set in the systemd unit). The request carries
+//!   only the name, which must start with [`PASSWORD_ENV_PREFIX`] so a client
+//!   can't have any other variable of the sctl process typed into a command.
+//!   Without it, `sudo -n` is used — passwordless sudo works, anything needing
+//!   a password fails fast instead of hanging on a prompt.
+//! - **`prompt_via: "stdin"`** (exec, default): runs
+//!   `sudo -S -k -E -p '' -- <shell> -c <command>` and writes the password to
+//!   the child's stdin pipe, then closes it. `-k` makes sudo ask even with
+//!   cached credentials, but under a `NOPASSWD` rule sudo doesn't read stdin
+//!   and the command itself receives the password there, so it can end up in
+//!   the output.
+//! - **`prompt_via: "pty"`** (sessions, default; PTY sessions only): sets
+//!   `SUDO_PROMPT` to a per-session marker. A responder task watches session
+//!   output and, when the marker appears *and* the terminal has echo switched
+//!   off, writes the password to the PTY. Only the first such prompt is
+//!   answered: the session can read `SUDO_PROMPT` and switch echo off itself
+//!   (`stty -echo`), so a fake prompt can't be told from sudo's, and later
+//!   prompts are left to the user.
+//!
+//! `-E` keeps request `env` overrides visible to the elevated command; it
+//! requires a sudoers rule that permits it (any `ALL` rule does).
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::sessions::session::SessionStatus;
+use crate::sessions::SessionManager;
+
+/// How the password reaches `sudo`.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptVia {
+    /// Piped to `sudo -S` (one-shot exec).
+    Stdin,
+    /// Typed into the session PTY when sudo prompts (interactive sessions).
+    Pty,
+}
+
+/// `sudo` option accepted by exec and session start requests.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SudoOptions {
+    /// Name of an environment variable of the sctl process holding the
+    /// password, starting with [`PASSWORD_ENV_PREFIX`]. Omit for passwordless
+    /// (`NOPASSWD`) sudo.
+    pub password_env: Option<String>,
+    /// Password delivery channel. Defaults to `stdin` for exec, `pty` for sessions.
+    pub prompt_via: Option<PromptVia>,
+}
+
+/// Required prefix of `password_env` names.
+pub const PASSWORD_ENV_PREFIX: &str = "SCTL_SUDO_";
+
+/// Delay between seeing the prompt and checking the terminal mode, giving
+/// sudo time to switch echo off.
+const ECHO_CHECK_DELAY: Duration = Duration::from_millis(50);
+
+impl SudoOptions {
+    /// Parse the optional `sudo` field of a JSON message (WS and tunnel
+    /// handlers, which read fields by hand rather than through a typed body).
+    pub fn from_message(msg: &serde_json::Value) -> Result<Option<Self>, String> {
+        match msg.get("sudo") {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(v) => serde_json::from_value(v.clone())
+                .map(Some)
+                .map_err(|e| format!("Invalid sudo: {e}")),
+        }
+    }
+
+    /// Check the options are usable for one-shot exec and read the password.
+    pub fn resolve_for_exec(&self) -> Result<ExecSudo, String> {
+        if self.prompt_via.unwrap_or(PromptVia::Stdin) != PromptVia::Stdin {
+            return Err("sudo.prompt_via must be \"stdin\" for exec".to_string());
+        }
+        Ok(ExecSudo {
+            password: self.read_password()?,
+        })
+    }
+
+    /// Check the options are usable for a session and return the environment
+    /// to start it with (the caller's `env` plus the sudo prompt marker).
+    pub fn prepare_session(
+        &self,
+        use_pty: bool,
+        env: Option<&HashMap<String, String>>,
+    ) -> Result<(HashMap<String, String>, SessionSudo), String> {
+        if self.prompt_via.unwrap_or(PromptVia::Pty) != PromptVia::Pty {
+            return Err("sudo.prompt_via must be \"pty\" for sessions".to_string());
+        }
+        if !use_pty {
+            return Err("sudo for sessions requires pty: true".to_string());
+        }
+        let Some(ref password_env) = self.password_env else {
+            return Err("sudo.password_env is required for sessions".to_string());
+        };
+        // Fail at start rather than on the first prompt.
+        self.read_password()?;
+        let marker = format!(
+            "[sctl-sudo:{}] ",
+            &uuid::Uuid::new_v4().simple().to_string()[..12]
+        );
+        let mut merged = env.cloned().unwrap_or_default();
+        merged.insert("SUDO_PROMPT".to_string(), marker.clone());
+        Ok((
+            merged,
+            SessionSudo {
+                password_env: password_env.clone(),
+                marker,
+            },
+        ))
+    }
+
+    fn read_password(&self) -> Result<Option<String>, String> {
+        let Some(ref name) = self.password_env else {
+            return Ok(None);
+        };
+        if name.len() <= PASSWORD_ENV_PREFIX.len() || !name.starts_with(PASSWORD_ENV_PREFIX) {
+            return Err(format!(
+                "sudo.password_env must name a {PASSWORD_ENV_PREFIX}* variable"
+            ));
+        }
+        match std::env::var(name) {
+            Ok(pw) if !pw.is_empty() => Ok(Some(pw)),
+            _ => Err(format!("sudo.password_env {name} is not set on the device")),
+        }
+    }
+}
+
+/// Resolved sudo settings for one exec. Deliberately not `Debug`.
+pub struct ExecSudo {
+    password: Option<String>,
+}
+
+impl ExecSudo {
+    /// The password to pipe to `sudo -S`, or `None` for `sudo -n`.
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+}
+
+/// Resolve an optional request-level `sudo` field for exec.
+pub fn resolve_exec(opts: Option<&SudoOptions>) -> Result<Option<ExecSudo>, String> {
+    opts.map(SudoOptions::resolve_for_exec).transpose()
+}
+
+/// Build the argument list for running `<shell> -c <command>` under sudo.
+pub fn exec_args(shell: &str, command: &str, with_password: bool) -> Vec<String> {
+    let mut args = vec![if with_password { "-S" } else { "-n" }.to_string()];
+    if with_password {
+        // Ask even with cached credentials, so sudo reads the password
+        // rather than leaving it on the command's stdin.
+        args.push("-k".to_string());
+    }
+    args.push("-E".to_string());
+    if with_password {
+        // Empty prompt: nothing sudo-related ends up in the captured stderr.
+        args.push("-p".to_string());
+        args.push(String::new());
+    }
+    args.extend(["--", shell, "-c", command].map(ToString::to_string));
+    args
+}
+
+/// Per-session sudo state handed to [`spawn_session_responder`].
+pub struct SessionSudo {
+    password_env: String,
+    marker: String,
+}
+
+/// Answer the first sudo prompt in a PTY session, then stop.
+pub fn spawn_session_responder(manager: SessionManager, session_id: String, sudo: SessionSudo) {
+    tokio::spawn(async move {
+        let Some(buffer) = manager.get_buffer(&session_id).await else {
+            return;
+        };
+        let notify = buffer.lock().await.notifier();
+        let mut since = buffer.lock().await.next_seq().saturating_sub(1);
+        info!(session_id = %session_id, "sudo responder attached");
+        loop {
+            let notified = notify.notified();
+            let prompted = {
+                let buf = buffer.lock().await;
+                let (entries, _) = buf.read_since(since);
+                since = buf.next_seq().saturating_sub(1);
+                entries.iter().any(|e| e.data.contains(&sudo.marker))
+            };
+            if prompted {
+                tokio::time::sleep(ECHO_CHECK_DELAY).await;
+                if manager.pty_echo_enabled(&session_id).await == Some(false) {
+                    if let Ok(pw) = std::env::var(&sudo.password_env) {
+                        let _ = manager
+                            .send_to_session(&session_id, &format!("{pw}\n"))
+                            .await;
+                    } else {
+                        warn!(
+                            session_id = %session_id,
+                            password_env = %sudo.password_env,
+                            "sudo responder: password variable no longer set"
+                        );
+                    }
+                    info!(session_id = %session_id, "sudo responder: prompt answered, detaching");
+                    return;
+                }
+            }
+            match manager.get_status(&session_id).await {
+                Some((SessionStatus::Running, _)) => {}
+                _ => return,
+            }
+            tokio::select! {
+                () = notified => {}
+                () = tokio::time::sleep(Duration::from_secs(30)) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(password_env: &str) -> SudoOptions {
+        SudoOptions {
+            password_env: Some(password_env.to_string()),
+            prompt_via: None,
+        }
+    }
+
+    #[test]
+    fn password_env_must_carry_the_prefix() {
+        for name in ["PATH", "SCTL_API_KEY", "SCTL_SUDO_", "", "sctl_sudo_pw"] {
+            let err = opts(name).resolve_for_exec().err().unwrap();
+            assert!(err.contains(PASSWORD_ENV_PREFIX), "{name}: {err}");
+        }
+        let err = opts("SCTL_SUDO_UNSET_IN_TESTS")
+            .resolve_for_exec()
+            .err()
+            .unwrap();
+        assert!(err.contains("is not set"), "{err}");
+    }
+
+    #[test]
+    fn exec_with_a_password_ignores_cached_credentials() {
+        let args = exec_args("/bin/sh", "id", true);
+        assert_eq!(&args[..3], ["-S", "-k", "-E"]);
+        let args = exec_args("/bin/sh", "id", false);
+        assert_eq!(&args[..2], ["-n", "-E"]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Compact summaries of large exec output.
+//!
+//! A build log or `journalctl` dump can run to megabytes, most of which an LLM
+//! client pays for in context and never reads. When a stream exceeds
+//! `summary.threshold_bytes`, sctl produces a summary next to the full output:
+//!
+//! - **head_tail** — the first `head_lines` and last `tail_lines` lines with an
+//!   omission marker between them.
+//! - **errors** — lines that look like errors or warnings (with their line
+//!   numbers), then the last `tail_lines` lines.
+//! - **command** — an external summarizer: the stream is piped to
+//!   `summary.command` on stdin, its stdout is the summary. Falls back to the
+//!   built-in strategy if it fails or times out.
+//!
+//! The full output stays in the exec results cache
+//! (`GET /api/activity/{id}/result`). Which callers get the summary in place of
+//! the output is decided by the exec handlers; see [`crate::routes::exec`].
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::config::SummaryConfig;
+
+/// Longest line kept in a built-in summary, in characters.
+const MAX_LINE_CHARS: usize = 400;
+
+/// Most matching lines kept by the `errors` strategy.
+const MAX_ERROR_LINES: usize = 50;
+
+/// Substrings (lower-cased) that mark a line for the `errors` strategy.
+const ERROR_MARKERS: &[&str] = &[
+    "error",
+    "warning",
+    "fail",
+    "fatal",
+    "panic",
+    "exception",
+    "traceback",
+    "denied",
+    "not found",
+    "segmentation fault",
+];
+
+/// Summary of one exec's output. Streams under the threshold are copied
+/// through unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputSummary {
+    /// `head_tail`, `errors`, or `command`.
+    pub strategy: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// Size of the full stdout in bytes.
+    pub stdout_bytes: usize,
+    /// Size of the full stderr in bytes.
+    pub stderr_bytes: usize,
+}
+
+/// Summarize `stdout`/`stderr` of `command`. Returns `None` when disabled or
+/// when neither stream exceeds the threshold.
+pub async fn summarize(
+    config: &SummaryConfig,
+    command: &str,
+    stdout: &str,
+    stderr: &str,
+) -> Option<OutputSummary> {
+    if !config.enabled
+        || (stdout.len() <= config.threshold_bytes && stderr.len() <= config.threshold_bytes)
+    {
+        return None;
+    }
+    let (stdout_summary, stdout_helper) = summarize_stream(config, command, "stdout", stdout).await;
+    let (stderr_summary, stderr_helper) = summarize_stream(config, command, "stderr", stderr).await;
+    Some(OutputSummary {
+        strategy: if stdout_helper && stderr_helper {
+            "command".to_string()
+        } else {
+            config.strategy.clone()
+        },
+        stdout: stdout_summary,
+        stderr: stderr_summary,
+        stdout_bytes: stdout.len(),
+        stderr_bytes: stderr.len(),
+    })
+}
+
+/// Summarize one stream. The flag is `false` if the built-in strategy was
+/// used (no helper configured, or it failed) on a stream over the threshold.
+async fn summarize_stream(
+    config: &SummaryConfig,
+    command: &str,
+    stream: &str,
+    text: &str,
+) -> (String, bool) {
+    if text.len() <= config.threshold_bytes {
+        return (text.to_string(), config.command.is_some());
+    }
+    if let Some(ref helper) = config.command {
+        match run_helper(config, helper, stream, command, text).await {
+            Ok(summary) => return (summary, true),
+            Err(e) => warn!("summary: {helper} failed on {stream}: {e}; using built-in"),
+        }
+    }
+    (builtin(config, text), false)
+}
+
+/// Apply the configured built-in strategy.
+fn builtin(config: &SummaryConfig, text: &str) -> String {
+    let summary = if config.strategy == "errors" {
+        errors(text, config.tail_lines)
+    } else {
+        head_tail(text, config.head_lines, config.tail_lines)
+    };
+    cap_bytes(summary, config.threshold_bytes)
+}
+
+fn clip_line(line: &str) -> String {
+    if line.chars().count() <= MAX_LINE_CHARS {
+        line.to_string()
+    } else {
+        let mut clipped: String = line.chars().take(MAX_LINE_CHARS).collect();
+        clipped.push_str(" [...]");
+        clipped
+    }
+}
+
+/// First `head` and last `tail` lines, with an omission marker in between.
+fn head_tail(text: &str, head: usize, tail: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= head + tail {
+        return lines
+            .iter()
+            .map(|l| clip_line(l))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    let omitted = &lines[head..lines.len() - tail];
+    let omitted_bytes: usize = omitted.iter().map(|l| l.len() + 1).sum();
+    let mut out: Vec<String> = lines[..head].iter().map(|l| clip_line(l)).collect();
+    out.push(format!(
+        "[... {} lines ({omitted_bytes} bytes) omitted ...]",
+        omitted.len()
+    ));
+    out.extend(lines[lines.len() - tail..].iter().map(|l| clip_line(l)));
+    out.join("\n")
+}
+
+/// Error-looking lines with line numbers, then the last `tail` lines.
+fn errors(text: &str, tail: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let tail_start = lines.len().saturating_sub(tail);
+    let matches: Vec<(usize, &str)> = lines[..tail_start]
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| {
+            let lower = l.to_lowercase();
+            ERROR_MARKERS.iter().any(|m| lower.contains(m))
+        })
+        .map(|(i, l)| (i, *l))
+        .collect();
+    let mut out = vec![format!(
+        "[{} of {} lines before the tail look like errors{}]",
+        matches.len(),
+        tail_start,
+        if matches.len() > MAX_ERROR_LINES {
+            format!(", first {MAX_ERROR_LINES} shown")
+        } else {
+            String::new()
+        }
+    )];
+    out.extend(
+        matches
+            .iter()
+            .take(MAX_ERROR_LINES)
+            .map(|(i, l)| format!("L{}: {}", i + 1, clip_line(l))),
+    );
+    out.push(format!("[last {} lines]", lines.len() - tail_start));
+    out.extend(lines[tail_start..].iter().map(|l| clip_line(l)));
+    out.join("\n")
+}
+
+/// Keep a summary under `max` bytes (on a char boundary).
+fn cap_bytes(mut s: String, max: usize) -> String {
+    if s.len() > max {
+        let mut end = max;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+        s.push_str("\n[... summary truncated ...]");
+    }
+    s
+}
+
+/// Pipe `text` through the external summarizer.
+async fn run_helper(
+    config: &SummaryConfig,
+    helper: &str,
+    stream: &str,
+    command: &str,
+    text: &str,
+) -> Result<String, String> {
+    let mut child = tokio::process::Command::new(helper)
+        .env("SCTL_STREAM", stream)
+        .env("SCTL_COMMAND", command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let mut stdin = child.stdin.take().ok_or("no stdin")?;
+    let input = text.as_bytes().to_vec();
+    // Feed stdin concurrently so a helper that writes before reading all of
+    // its input can't deadlock against us.
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+    let output = tokio::time::timeout(
+        Duration::from_secs(config.command_timeout_secs),
+        child.wait_with_output(),
+    )
+    .await
+    .map_err(|_| "timed out".to_string())?
+    .map_err(|e| e.to_string())?;
+    writer.abort();
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}",
+            output.status.code().unwrap_or(-1)
+        ));
+    }
+    let summary = String::from_utf8_lossy(&output.stdout).into_owned();
+    Ok(cap_bytes(summary, config.threshold_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(n: usize) -> String {
+        (1..=n)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn head_tail_marks_omission() {
+        let out = head_tail(&numbered(100), 2, 3);
+        assert_eq!(
+            out,
+            "line 1\nline 2\n[... 95 lines (753 bytes) omitted ...]\nline 98\nline 99\nline 100"
+        );
+        assert_eq!(head_tail(&numbered(5), 2, 3), numbered(5));
+    }
+
+    #[test]
+    fn errors_keeps_matches_with_line_numbers() {
+        let text = "ok\nerror: bad thing\nok\nWARNING x\nok\ntail1\ntail2";
+        let out = errors(text, 2);
+        assert_eq!(
+            out,
+            "[2 of 5 lines before the tail look like errors]\nL2: error: bad thing\nL4: WARNING x\n[last 2 lines]\ntail1\ntail2"
+        );
+    }
+
+    #[test]
+    fn cap_respects_char_boundaries() {
+        let out = cap_bytes("ééé".to_string(), 3);
+        assert!(out.starts_with('é'));
+        assert!(out.ends_with("[... summary truncated ...]"));
+    }
+}
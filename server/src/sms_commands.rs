@@ -0,0 +1,385 @@
+//! Signed SMS commands (`[sms_commands]`).
+//!
+//! A last-resort control path for when the tunnel is down: the device checks
+//! its modem inbox every `poll_secs` and runs commands of the form
+//!
+//! ```text
+//! SCTL <action> <unix-timestamp> <mac>
+//! ```
+//!
+//! where `mac` is the first 16 hex digits of
+//! `HMAC-SHA256(key, "<serial> <action> <unix-timestamp>")`. A command is
+//! run only if the MAC matches, the action is in `actions`, the sender is in
+//! `senders` (when set), the timestamp is within `max_age_secs` of the device
+//! clock and newer than the last accepted command. That last timestamp is kept
+//! in `<data_dir>/sms_commands.json`, so a replayed SMS is refused across
+//! restarts and reboots too.
+//!
+//! Actions ([`ACTIONS`]) are deliberately few and fixed; there is no exec:
+//!
+//! - `ping` — reply with uptime, tunnel state and session count
+//! - `reconnect_tunnel` — drop the relay connection, or cut the current
+//!   backoff short, and connect again
+//! - `reboot` — sync and reboot the device
+//!
+//! Every received message starting with `SCTL` is deleted from the modem once read,
+//! accepted or not, and journaled as `sms_command` with source `sms`. Only
+//! accepted commands are answered (with `reply`); refusals are silent so the
+//! channel gives nothing away to a stranger. Other messages are left alone.
+
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use sctl_comms_protocol::methods;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::activity::{ActivitySource, ActivityType};
+use crate::comms::CommsClient;
+use crate::config::SmsCommandsConfig;
+use crate::infra::now_epoch;
+use crate::AppState;
+
+/// Actions `[sms_commands] actions` may enable.
+pub const ACTIONS: &[&str] = &["ping", "reconnect_tunnel", "reboot"];
+
+/// Hex digits of the HMAC carried in a command (64 bits).
+const MAC_LEN: usize = 16;
+
+/// Time between answering a `reboot` and running it, so the reply and the
+/// activity entry get out first.
+const REBOOT_DELAY: Duration = Duration::from_secs(5);
+
+/// Sending waits for the network; same margin as `POST /api/modem/sms`.
+const SEND_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// A command that passed every check.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Command {
+    pub action: String,
+    pub timestamp: u64,
+}
+
+/// The MAC a command for `serial` must carry.
+#[must_use]
+pub fn sign(key: &str, serial: &str, action: &str, timestamp: u64) -> String {
+    let digest = crate::util::hmac_sha256(
+        key.as_bytes(),
+        format!("{serial} {action} {timestamp}").as_bytes(),
+    );
+    let mut mac = crate::gawdxfer::hasher::hex::encode(digest);
+    mac.truncate(MAC_LEN);
+    mac
+}
+
+/// Check one message. `None` if it isn't a command at all; otherwise the
+/// command, or why it was refused.
+///
+/// `last` is the timestamp of the last accepted command and `now` the
+/// device clock, both in Unix seconds.
+pub fn check(
+    config: &SmsCommandsConfig,
+    serial: &str,
+    sender: &str,
+    text: &str,
+    last: u64,
+    now: u64,
+) -> Option<Result<Command, &'static str>> {
+    let mut words = text.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("SCTL") {
+        return None;
+    }
+    Some((|| {
+        if !config.senders.is_empty() && !config.senders.iter().any(|s| s == sender) {
+            return Err("sender not allowed");
+        }
+        let (Some(action), Some(timestamp), Some(mac), None) =
+            (words.next(), words.next(), words.next(), words.next())
+        else {
+            return Err("malformed");
+        };
+        let timestamp: u64 = timestamp.parse().map_err(|_| "malformed")?;
+        let expected = sign(&config.key, serial, action, timestamp);
+        if !crate::auth::constant_time_eq(expected.as_bytes(), mac.to_ascii_lowercase().as_bytes())
+        {
+            return Err("bad signature");
+        }
+        if now.abs_diff(timestamp) > config.max_age_secs {
+            return Err("expired");
+        }
+        if timestamp <= last {
+            return Err("replayed");
+        }
+        if !config.actions.iter().any(|a| a == action) {
+            return Err("action not allowed");
+        }
+        Ok(Command {
+            action: action.to_string(),
+            timestamp,
+        })
+    })())
+}
+
+/// Poll the inbox every `poll_secs` for as long as the server runs.
+pub fn spawn(state: AppState, client: CommsClient, config: SmsCommandsConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let path = Path::new(&state.config.server.data_dir).join("sms_commands.json");
+        let mut last = load_last(&path).await;
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        info!(
+            "SMS commands enabled: {} (every {}s)",
+            config.actions.join(", "),
+            config.poll_secs
+        );
+        loop {
+            interval.tick().await;
+            let messages = match client.call(methods::SMS_LIST, json!({})).await {
+                Ok(result) => result["messages"].as_array().cloned().unwrap_or_default(),
+                Err(e) => {
+                    tracing::debug!("SMS commands: inbox check failed: {e}");
+                    continue;
+                }
+            };
+            for msg in &messages {
+                handle(&state, &client, &config, &path, &mut last, msg).await;
+            }
+        }
+    })
+}
+
+/// Check, journal, run and answer one inbox message.
+async fn handle(
+    state: &AppState,
+    client: &CommsClient,
+    config: &SmsCommandsConfig,
+    path: &Path,
+    last: &mut u64,
+    msg: &Value,
+) {
+    // Only received messages; stored outgoing ones (our replies among them)
+    // are never commands.
+    if !matches!(msg["status"].as_str(), Some("unread" | "read")) {
+        return;
+    }
+    let number = msg["number"].as_str().unwrap_or_default();
+    let text = msg["text"].as_str().unwrap_or_default();
+    let Some(verdict) = check(
+        config,
+        &state.config.device.serial,
+        number,
+        text,
+        *last,
+        now_epoch(),
+    ) else {
+        return;
+    };
+
+    // Gone from the inbox before anything runs, so a reboot can't loop on it.
+    if let Some(index) = msg["index"].as_u64() {
+        if let Err(e) = client
+            .call(methods::SMS_DELETE, json!({ "index": index }))
+            .await
+        {
+            warn!("SMS commands: failed to delete message {index}: {e}");
+        }
+    }
+
+    let cmd = match verdict {
+        Ok(cmd) => cmd,
+        Err(reason) => {
+            warn!("SMS commands: refused message from {number}: {reason}");
+            state
+                .activity_log
+                .log(
+                    ActivityType::SmsCommand,
+                    ActivitySource::Sms,
+                    format!("Refused SMS command from {number}: {reason}"),
+                    Some(json!({ "number": number, "accepted": false, "reason": reason })),
+                    None,
+                )
+                .await;
+            return;
+        }
+    };
+
+    *last = cmd.timestamp;
+    save_last(path, cmd.timestamp).await;
+    let result = run(state, &cmd.action).await;
+    info!(
+        "SMS commands: {} from {number}: {}",
+        cmd.action,
+        result.as_deref().unwrap_or_else(|e| e)
+    );
+    state
+        .activity_log
+        .log(
+            ActivityType::SmsCommand,
+            ActivitySource::Sms,
+            format!("SMS command {} from {number}", cmd.action),
+            Some(json!({
+                "number": number,
+                "action": cmd.action,
+                "timestamp": cmd.timestamp,
+                "accepted": true,
+                "ok": result.is_ok(),
+                "result": result.as_deref().unwrap_or_else(|e| e),
+            })),
+            None,
+        )
+        .await;
+
+    if config.reply {
+        let text = format!(
+            "sctl {}: {}",
+            state.config.device.serial,
+            result.as_deref().unwrap_or_else(|e| e)
+        );
+        if let Err(e) = client
+            .call_with_timeout(
+                methods::SMS_SEND,
+                json!({ "number": number, "text": text }),
+                SEND_TIMEOUT,
+            )
+            .await
+        {
+            warn!("SMS commands: reply to {number} failed: {e}");
+        }
+    }
+
+    if cmd.action == "reboot" && result.is_ok() {
+        tokio::time::sleep(REBOOT_DELAY).await;
+        reboot().await;
+    }
+}
+
+/// Run an action and describe the outcome for the reply. `reboot` only
+/// answers here; the caller reboots after replying.
+async fn run(state: &AppState, action: &str) -> Result<String, String> {
+    match action {
+        "ping" => {
+            let tunnel = if state.tunnel_stats.connected.load(Ordering::Relaxed) {
+                "connected"
+            } else {
+                "down"
+            };
+            Ok(format!(
+                "up {}s, tunnel {tunnel}, {} sessions",
+                state.start_time.elapsed().as_secs(),
+                state.session_manager.session_count().await
+            ))
+        }
+        "reconnect_tunnel" => {
+            let client = state
+                .config
+                .tunnel
+                .as_ref()
+                .is_some_and(|t| !t.relay && t.url.is_some());
+            if !client {
+                return Err("no tunnel client configured".to_string());
+            }
+            state.tunnel_stats.reconnect_now.notify_waiters();
+            Ok("reconnecting tunnel".to_string())
+        }
+        "reboot" => Ok(format!("rebooting in {}s", REBOOT_DELAY.as_secs())),
+        _ => Err(format!("unknown action {action}")),
+    }
+}
+
+async fn reboot() {
+    warn!("SMS commands: rebooting");
+    crate::util::sync_filesystems();
+    match tokio::process::Command::new("reboot").status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("SMS commands: reboot exited with {status}"),
+        Err(e) => warn!("SMS commands: failed to run reboot: {e}"),
+    }
+}
+
+async fn load_last(path: &Path) -> u64 {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()
+        .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+        .and_then(|v| v["last_timestamp"].as_u64())
+        .unwrap_or(0)
+}
+
+async fn save_last(path: &Path, timestamp: u64) {
+    let tmp = path.with_extension("json.tmp");
+    let body = json!({ "last_timestamp": timestamp }).to_string();
+    if let Err(e) = tokio::fs::write(&tmp, body).await {
+        warn!("Failed to write sms_commands.json.tmp: {e}");
+        return;
+    }
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        warn!("Failed to rename sms_commands.json.tmp: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_accepts_only_fresh_signed_allowed_commands() {
+        let config = SmsCommandsConfig {
+            key: "0123456789abcdef".to_string(),
+            actions: vec!["ping".to_string(), "reconnect_tunnel".to_string()],
+            senders: vec!["+15551234567".to_string()],
+            poll_secs: 60,
+            max_age_secs: 900,
+            reply: true,
+        };
+        let now = 1_760_000_000;
+        let msg = |action: &str, ts: u64| {
+            format!(
+                "SCTL {action} {ts} {}",
+                sign(&config.key, "DEV-1", action, ts)
+            )
+        };
+        let check =
+            |sender: &str, text: &str, last: u64| check(&config, "DEV-1", sender, text, last, now);
+        let ok = "+15551234567";
+
+        assert_eq!(check(ok, "hello there", 0), None);
+        assert_eq!(
+            check(ok, &msg("ping", now - 10), 0),
+            Some(Ok(Command {
+                action: "ping".to_string(),
+                timestamp: now - 10
+            }))
+        );
+        let shouted = format!(
+            "sctl ping {now} {}",
+            sign(&config.key, "DEV-1", "ping", now)
+        );
+        assert!(matches!(
+            check(ok, &shouted.to_uppercase().replace("PING", "ping"), 0),
+            Some(Ok(_))
+        ));
+        assert_eq!(
+            check("+1999", &msg("ping", now), 0),
+            Some(Err("sender not allowed"))
+        );
+        assert_eq!(check(ok, "sctl ping", 0), Some(Err("malformed")));
+        assert_eq!(
+            check(ok, &msg("ping", now).replace("ping", "reboot"), 0),
+            Some(Err("bad signature"))
+        );
+        assert_eq!(check(ok, &msg("ping", now - 901), 0), Some(Err("expired")));
+        assert_eq!(check(ok, &msg("ping", now), now), Some(Err("replayed")));
+        assert_eq!(
+            check(ok, &msg("reboot", now), 0),
+            Some(Err("action not allowed"))
+        );
+        // Signed for another device.
+        let other = format!(
+            "SCTL ping {now} {}",
+            sign(&config.key, "DEV-2", "ping", now)
+        );
+        assert_eq!(check(ok, &other, 0), Some(Err("bad signature")));
+    }
+}
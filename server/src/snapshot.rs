@@ -0,0 +1,637 @@
+//! System state snapshots and the diffs between them.
+//!
+//! `POST /api/snapshots` records what is on the device right now, section by
+//! section. Each section is a flat `name → value` map, so every diff is the
+//! same shape: entries added, removed, or with a changed value.
+//!
+//! | Section    | Key                        | Value                          | Source |
+//! |------------|----------------------------|--------------------------------|--------|
+//! | `packages` | package name               | version                        | `opkg`, `dpkg-query`, `apk` or `rpm` |
+//! | `modules`  | module name                | size in bytes                  | `/proc/modules` |
+//! | `ports`    | `tcp 0.0.0.0:22`           | owning process, if visible     | `/proc/net/{tcp,udp}{,6}` |
+//! | `mounts`   | mount point                | `fstype source options`        | `/proc/self/mounts` |
+//! | `units`    | unit name                  | `active/sub` plus enablement   | `systemctl`, else `/etc/init.d` |
+//!
+//! Snapshots are stored as `<data_dir>/snapshots/<created_at>_<id>.json`;
+//! only the newest [`MAX_SNAPSHOTS`] are kept. Taking one before an AI
+//! session and diffing afterwards shows what the session actually changed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::infra::checks::exec_args_pub;
+use crate::twin::has_tool;
+
+/// Stored snapshots beyond this are deleted, oldest first.
+pub const MAX_SNAPSHOTS: usize = 32;
+
+/// Every section, in collection order.
+pub const SECTIONS: &[&str] = &["packages", "modules", "ports", "mounts", "units"];
+
+/// Timeout for each query (`dpkg-query`, `systemctl list-units`, ...).
+const QUERY_TIMEOUT_MS: u64 = 30_000;
+
+type Section = BTreeMap<String, String>;
+
+/// One recorded system state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    /// Epoch milliseconds.
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub sections: BTreeMap<String, Section>,
+    /// Sections that couldn't be collected, with why.
+    #[serde(default)]
+    pub unavailable: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// `{id, created_at, label, counts, unavailable}`, without the entries.
+    pub fn summary(&self) -> Value {
+        let counts: BTreeMap<&str, usize> = self
+            .sections
+            .iter()
+            .map(|(name, entries)| (name.as_str(), entries.len()))
+            .collect();
+        json!({
+            "id": self.id,
+            "created_at": self.created_at,
+            "label": self.label,
+            "counts": counts,
+            "unavailable": self.unavailable,
+        })
+    }
+}
+
+/// Check requested section names.
+///
+/// # Errors
+///
+/// An unknown section.
+pub fn validate_sections(sections: &[String]) -> Result<(), String> {
+    match sections.iter().find(|s| !SECTIONS.contains(&s.as_str())) {
+        Some(s) => Err(format!(
+            "Unknown section '{s}' (expected one of {})",
+            SECTIONS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Record `sections` (all when empty) of the running system.
+pub async fn take(label: Option<String>, sections: &[String]) -> Snapshot {
+    let wanted = |name: &str| sections.is_empty() || sections.iter().any(|s| s == name);
+    let (packages, modules, ports, mounts, units) = tokio::join!(
+        collect_if(wanted("packages"), collect_packages()),
+        collect_if(wanted("modules"), collect_modules()),
+        collect_if(wanted("ports"), collect_ports()),
+        collect_if(wanted("mounts"), collect_mounts()),
+        collect_if(wanted("units"), collect_units()),
+    );
+    let mut snapshot = Snapshot {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        created_at: crate::sessions::journal::now_ms(),
+        label,
+        sections: BTreeMap::new(),
+        unavailable: BTreeMap::new(),
+    };
+    for (name, result) in SECTIONS
+        .iter()
+        .zip([packages, modules, ports, mounts, units])
+    {
+        match result {
+            Some(Ok(entries)) => {
+                snapshot.sections.insert((*name).to_string(), entries);
+            }
+            Some(Err(e)) => {
+                snapshot.unavailable.insert((*name).to_string(), e);
+            }
+            None => {}
+        }
+    }
+    snapshot
+}
+
+async fn collect_if(
+    wanted: bool,
+    collect: impl std::future::Future<Output = Result<Section, String>>,
+) -> Option<Result<Section, String>> {
+    if wanted {
+        Some(collect.await)
+    } else {
+        None
+    }
+}
+
+// ─── Diff ────────────────────────────────────────────────────────────────────
+
+/// Changes to one section between two snapshots.
+#[derive(Debug, Default, Serialize)]
+pub struct SectionDiff {
+    pub added: Section,
+    pub removed: Section,
+    /// Key → `{from, to}`.
+    pub changed: BTreeMap<String, Value>,
+}
+
+impl SectionDiff {
+    fn between(from: &Section, to: &Section) -> Self {
+        let mut diff = Self::default();
+        for (key, old) in from {
+            match to.get(key) {
+                None => {
+                    diff.removed.insert(key.clone(), old.clone());
+                }
+                Some(new) if new != old => {
+                    diff.changed
+                        .insert(key.clone(), json!({ "from": old, "to": new }));
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, new) in to {
+            if !from.contains_key(key) {
+                diff.added.insert(key.clone(), new.clone());
+            }
+        }
+        diff
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// What changed from `from` to `to`: `{from, to, identical, sections,
+/// skipped}`. Only sections with changes are listed; sections missing from
+/// either snapshot are in `skipped`.
+pub fn diff(from: &Snapshot, to: &Snapshot) -> Value {
+    let mut sections = BTreeMap::new();
+    let mut skipped = Vec::new();
+    for name in SECTIONS {
+        match (from.sections.get(*name), to.sections.get(*name)) {
+            (Some(a), Some(b)) => {
+                let d = SectionDiff::between(a, b);
+                if !d.is_empty() {
+                    sections.insert(*name, d);
+                }
+            }
+            (None, None) => {}
+            _ => skipped.push(*name),
+        }
+    }
+    let endpoint =
+        |s: &Snapshot| json!({ "id": s.id, "created_at": s.created_at, "label": s.label });
+    json!({
+        "from": endpoint(from),
+        "to": endpoint(to),
+        "identical": sections.is_empty(),
+        "sections": sections,
+        "skipped": skipped,
+    })
+}
+
+// ─── Storage ─────────────────────────────────────────────────────────────────
+
+/// `<data_dir>/snapshots`.
+fn dir(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("snapshots")
+}
+
+/// Snapshot files, oldest first (names start with `created_at`).
+async fn files(data_dir: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir(data_dir)).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn file_of<'a>(files: &'a [PathBuf], id: &str) -> Option<&'a PathBuf> {
+    let suffix = format!("_{id}.json");
+    files.iter().find(|p| {
+        p.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.ends_with(&suffix))
+    })
+}
+
+/// Store `snapshot` and drop all but the newest [`MAX_SNAPSHOTS`].
+pub async fn save(data_dir: &str, snapshot: &Snapshot) -> std::io::Result<()> {
+    let dir = dir(data_dir);
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!("{}_{}.json", snapshot.created_at, snapshot.id));
+    tokio::fs::write(&path, serde_json::to_vec(snapshot)?).await?;
+    let files = files(data_dir).await;
+    for old in &files[..files.len().saturating_sub(MAX_SNAPSHOTS)] {
+        let _ = tokio::fs::remove_file(old).await;
+    }
+    Ok(())
+}
+
+/// Stored snapshot `id`.
+///
+/// # Errors
+///
+/// `Ok(None)` if there is none; `Err` if its file is unreadable.
+pub async fn load(data_dir: &str, id: &str) -> Result<Option<Snapshot>, String> {
+    let files = files(data_dir).await;
+    let Some(path) = file_of(&files, id) else {
+        return Ok(None);
+    };
+    let data = tokio::fs::read(path).await.map_err(|e| e.to_string())?;
+    serde_json::from_slice(&data)
+        .map(Some)
+        .map_err(|e| format!("Corrupt snapshot {id}: {e}"))
+}
+
+/// Summaries of stored snapshots, newest first.
+pub async fn list(data_dir: &str) -> Vec<Value> {
+    let mut summaries = Vec::new();
+    for path in files(data_dir).await.iter().rev() {
+        let Ok(data) = tokio::fs::read(path).await else {
+            continue;
+        };
+        if let Ok(snapshot) = serde_json::from_slice::<Snapshot>(&data) {
+            summaries.push(snapshot.summary());
+        }
+    }
+    summaries
+}
+
+/// Delete stored snapshot `id`. Returns whether it existed.
+pub async fn remove(data_dir: &str, id: &str) -> std::io::Result<bool> {
+    let files = files(data_dir).await;
+    match file_of(&files, id) {
+        Some(path) => tokio::fs::remove_file(path).await.map(|()| true),
+        None => Ok(false),
+    }
+}
+
+// ─── Collectors ──────────────────────────────────────────────────────────────
+
+async fn query(program: &str, args: &[&str]) -> Result<String, String> {
+    match exec_args_pub(program, args, QUERY_TIMEOUT_MS).await? {
+        (0, stdout, _) => Ok(stdout),
+        (code, _, stderr) => Err(format!(
+            "{program} exited with {code}: {}",
+            stderr.trim().lines().last().unwrap_or_default()
+        )),
+    }
+}
+
+async fn read_proc(path: &str) -> Result<String, String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("{path}: {e}"))
+}
+
+async fn collect_packages() -> Result<Section, String> {
+    if has_tool("opkg") {
+        // name - version
+        let out = query("opkg", &["list-installed"]).await?;
+        Ok(out
+            .lines()
+            .filter_map(|l| l.split_once(" - "))
+            .map(|(name, version)| (name.to_string(), version.trim().to_string()))
+            .collect())
+    } else if has_tool("dpkg-query") {
+        let out = query(
+            "dpkg-query",
+            &["-W", "-f=${Package}\t${Version}\t${Status}\n"],
+        )
+        .await?;
+        Ok(out
+            .lines()
+            .filter_map(|l| {
+                let mut fields = l.split('\t');
+                let (name, version, status) = (fields.next()?, fields.next()?, fields.next()?);
+                status
+                    .ends_with(" installed")
+                    .then(|| (name.to_string(), version.to_string()))
+            })
+            .collect())
+    } else if has_tool("apk") {
+        // name-version-rN
+        let out = query("apk", &["info", "-v"]).await?;
+        Ok(out
+            .lines()
+            .filter_map(|l| {
+                let mut parts = l.rsplitn(3, '-');
+                let (release, version, name) = (parts.next()?, parts.next()?, parts.next()?);
+                Some((name.to_string(), format!("{version}-{release}")))
+            })
+            .collect())
+    } else if has_tool("rpm") {
+        let out = query("rpm", &["-qa", "--qf", "%{NAME}\t%{VERSION}-%{RELEASE}\n"]).await?;
+        Ok(out
+            .lines()
+            .filter_map(|l| l.split_once('\t'))
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect())
+    } else {
+        Err("No supported package manager (opkg, dpkg, apk or rpm)".to_string())
+    }
+}
+
+async fn collect_modules() -> Result<Section, String> {
+    let text = read_proc("/proc/modules").await?;
+    Ok(parse_modules(&text))
+}
+
+/// `name size refcount deps state offset` per line.
+fn parse_modules(text: &str) -> Section {
+    text.lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            Some((fields.next()?.to_string(), fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+async fn collect_mounts() -> Result<Section, String> {
+    let text = read_proc("/proc/self/mounts").await?;
+    Ok(text
+        .lines()
+        .filter_map(|l| {
+            let f: Vec<&str> = l.split_whitespace().collect();
+            let [source, target, fstype, options, ..] = f[..] else {
+                return None;
+            };
+            Some((
+                unescape_mount(target),
+                format!("{fstype} {} {options}", unescape_mount(source)),
+            ))
+        })
+        .collect())
+}
+
+/// `/proc/self/mounts` writes space, tab, newline and backslash as octal.
+fn unescape_mount(s: &str) -> String {
+    s.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+async fn collect_ports() -> Result<Section, String> {
+    let mut sockets = Vec::new();
+    let mut read_any = false;
+    for proto in ["tcp", "tcp6", "udp", "udp6"] {
+        // tcp6/udp6 are absent without IPv6
+        if let Ok(text) = read_proc(&format!("/proc/net/{proto}")).await {
+            read_any = true;
+            sockets.extend(parse_proc_net(&text, proto));
+        }
+    }
+    if !read_any {
+        return Err("/proc/net/tcp is unreadable".to_string());
+    }
+    let owners = crate::io_pool::run(|| Ok(socket_owners()))
+        .await
+        .unwrap_or_default();
+    Ok(sockets
+        .into_iter()
+        .map(|(key, inode)| {
+            let owner = owners.get(&inode).cloned().unwrap_or_default();
+            (key, owner)
+        })
+        .collect())
+}
+
+/// Listening TCP and bound, unconnected UDP sockets in a `/proc/net/<proto>`
+/// table, as `("<proto> <addr>:<port>", inode)`.
+fn parse_proc_net(text: &str, proto: &str) -> Vec<(String, u64)> {
+    let udp = proto.starts_with("udp");
+    text.lines()
+        .skip(1)
+        .filter_map(|l| {
+            let f: Vec<&str> = l.split_whitespace().collect();
+            let (local, remote, st, inode) = (f.get(1)?, f.get(2)?, f.get(3)?, f.get(9)?);
+            let listening = if udp {
+                *st == "07" && remote.trim_start_matches(['0', ':']).is_empty()
+            } else {
+                *st == "0A"
+            };
+            if !listening {
+                return None;
+            }
+            let (addr, port) = local.split_once(':')?;
+            let port = u16::from_str_radix(port, 16).ok()?;
+            let addr = match addr.len() {
+                8 => std::net::Ipv4Addr::from(u32::from_str_radix(addr, 16).ok()?.swap_bytes())
+                    .to_string(),
+                32 => {
+                    // Four host-order 32-bit words
+                    let mut bytes = [0u8; 16];
+                    for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+                        let word = u32::from_str_radix(addr.get(i * 8..i * 8 + 8)?, 16).ok()?;
+                        chunk.copy_from_slice(&word.swap_bytes().to_be_bytes());
+                    }
+                    format!("[{}]", std::net::Ipv6Addr::from(bytes))
+                }
+                _ => return None,
+            };
+            Some((format!("{proto} {addr}:{port}"), inode.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Socket inode → `comm` of a process holding it, for the processes we may
+/// look into.
+fn socket_owners() -> HashMap<u64, String> {
+    let mut owners = HashMap::new();
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return owners;
+    };
+    for proc_entry in procs.flatten() {
+        let pid_dir = proc_entry.path();
+        if !proc_entry
+            .file_name()
+            .to_str()
+            .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+        {
+            continue;
+        }
+        let Ok(fds) = std::fs::read_dir(pid_dir.join("fd")) else {
+            continue;
+        };
+        let mut comm = None;
+        for fd in fds.flatten() {
+            let Ok(link) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let Some(inode) = link
+                .to_str()
+                .and_then(|l| l.strip_prefix("socket:["))
+                .and_then(|l| l.strip_suffix(']'))
+                .and_then(|l| l.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            let comm = comm.get_or_insert_with(|| {
+                std::fs::read_to_string(pid_dir.join("comm"))
+                    .map(|c| c.trim().to_string())
+                    .unwrap_or_default()
+            });
+            owners.entry(inode).or_insert_with(|| comm.clone());
+        }
+    }
+    owners
+}
+
+async fn collect_units() -> Result<Section, String> {
+    if has_tool("systemctl") {
+        // unit load active sub description...
+        let units = query(
+            "systemctl",
+            &[
+                "list-units",
+                "--all",
+                "--no-legend",
+                "--plain",
+                "--no-pager",
+            ],
+        )
+        .await?;
+        // unit state [preset]
+        let files = query(
+            "systemctl",
+            &["list-unit-files", "--no-legend", "--plain", "--no-pager"],
+        )
+        .await
+        .unwrap_or_default();
+        let enablement: HashMap<&str, &str> = files
+            .lines()
+            .filter_map(|l| {
+                let mut f = l.split_whitespace();
+                Some((f.next()?, f.next()?))
+            })
+            .collect();
+        let mut section: Section = units
+            .lines()
+            .filter_map(|l| {
+                let f: Vec<&str> = l.split_whitespace().collect();
+                let [unit, _load, active, sub, ..] = f[..] else {
+                    return None;
+                };
+                let value = match enablement.get(unit) {
+                    Some(state) => format!("{active}/{sub} {state}"),
+                    None => format!("{active}/{sub}"),
+                };
+                Some((unit.to_string(), value))
+            })
+            .collect();
+        // Installed but never loaded
+        for (unit, state) in enablement {
+            section
+                .entry(unit.to_string())
+                .or_insert_with(|| format!("inactive/dead {state}"));
+        }
+        Ok(section)
+    } else if Path::new("/etc/init.d").is_dir() {
+        crate::io_pool::run(init_d_units)
+            .await
+            .map_err(|e| format!("/etc/init.d: {e}"))
+    } else {
+        Err("No supported service manager (systemctl or /etc/init.d)".to_string())
+    }
+}
+
+/// `/etc/init.d` scripts, `enabled` when linked from `/etc/rc.d` (`OpenWrt`).
+fn init_d_units() -> std::io::Result<Section> {
+    let links: Vec<String> = std::fs::read_dir("/etc/rc.d")
+        .map(|d| {
+            d.flatten()
+                .filter_map(|e| e.file_name().to_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    let scripts = std::fs::read_dir("/etc/init.d")?;
+    Ok(scripts
+        .flatten()
+        .filter_map(|e| e.file_name().to_str().map(String::from))
+        .map(|name| {
+            let enabled = links
+                .iter()
+                .any(|l| l.starts_with('S') && l.get(3..) == Some(name.as_str()));
+            let state = if enabled { "enabled" } else { "disabled" };
+            (name, state.to_string())
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(id: &str, ports: &[(&str, &str)]) -> Snapshot {
+        let section = ports
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        Snapshot {
+            id: id.to_string(),
+            created_at: 0,
+            label: None,
+            sections: BTreeMap::from([("ports".to_string(), section)]),
+            unavailable: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn listening_sockets_diff_between_snapshots() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 101 1 0 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:C350 01 00000000:00000000 00:00000000 00000000     0        0 102 1 0 100 0 0 10 0";
+        assert_eq!(
+            parse_proc_net(tcp, "tcp"),
+            vec![("tcp 0.0.0.0:22".to_string(), 101)]
+        );
+        let tcp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:0050 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 103 1 0 100 0 0 10 0";
+        assert_eq!(
+            parse_proc_net(tcp6, "tcp6"),
+            vec![("tcp6 [::1]:80".to_string(), 103)]
+        );
+
+        let before = snapshot(
+            "a",
+            &[("tcp 0.0.0.0:22", "sshd"), ("udp 0.0.0.0:53", "dnsmasq")],
+        );
+        let after = snapshot(
+            "b",
+            &[
+                ("tcp 0.0.0.0:22", "dropbear"),
+                ("tcp 0.0.0.0:8080", "python3"),
+            ],
+        );
+        let d = diff(&before, &after);
+        assert_eq!(d["identical"], false);
+        assert_eq!(
+            d["sections"]["ports"]["added"]["tcp 0.0.0.0:8080"],
+            "python3"
+        );
+        assert_eq!(
+            d["sections"]["ports"]["removed"]["udp 0.0.0.0:53"],
+            "dnsmasq"
+        );
+        assert_eq!(
+            d["sections"]["ports"]["changed"]["tcp 0.0.0.0:22"],
+            json!({"from": "sshd", "to": "dropbear"})
+        );
+        assert_eq!(diff(&after, &after)["identical"], true);
+        assert!(validate_sections(&["ports".to_string(), "rpms".to_string()]).is_err());
+    }
+}
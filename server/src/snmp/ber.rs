@@ -0,0 +1,440 @@
+//! The BER subset SNMP v1/v2c messages are made of.
+//!
+//! A message is `SEQUENCE { version, community, PDU }` and a PDU is
+//! `[tag] { request-id, error-status, error-index, SEQUENCE OF VarBind }`
+//! (for `GetBulkRequest` the two middle fields are non-repeaters and
+//! max-repetitions). Values in requests are decoded but only ever `NULL` in
+//! practice.
+
+/// An object identifier, one number per arc.
+pub type Oid = Vec<u32>;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIMETICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+pub const GET_REQUEST: u8 = 0xa0;
+pub const GET_NEXT_REQUEST: u8 = 0xa1;
+pub const RESPONSE: u8 = 0xa2;
+pub const SET_REQUEST: u8 = 0xa3;
+pub const GET_BULK_REQUEST: u8 = 0xa5;
+
+/// A variable's value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    ObjectId(Oid),
+    Counter32(u32),
+    Gauge32(u32),
+    /// Hundredths of a second.
+    TimeTicks(u32),
+    /// v2c only.
+    Counter64(u64),
+    /// v2c exceptions.
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    /// The SMI type name, as MIB files and `snmpwalk` print it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "INTEGER",
+            Self::OctetString(_) => "OCTET STRING",
+            Self::Null => "NULL",
+            Self::ObjectId(_) => "OBJECT IDENTIFIER",
+            Self::Counter32(_) => "Counter32",
+            Self::Gauge32(_) => "Gauge32",
+            Self::TimeTicks(_) => "TimeTicks",
+            Self::Counter64(_) => "Counter64",
+            Self::NoSuchObject => "noSuchObject",
+            Self::NoSuchInstance => "noSuchInstance",
+            Self::EndOfMibView => "endOfMibView",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarBind {
+    pub oid: Oid,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub tag: u8,
+    pub request_id: i64,
+    /// Non-repeaters in a `GetBulkRequest`.
+    pub error_status: i64,
+    /// Max-repetitions in a `GetBulkRequest`.
+    pub error_index: i64,
+    pub varbinds: Vec<VarBind>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// 0 = v1, 1 = v2c.
+    pub version: i64,
+    pub community: Vec<u8>,
+    pub pdu: Pdu,
+}
+
+/// Parse a dotted OID such as `1.3.6.1.2.1.1`; a leading dot is allowed.
+///
+/// # Errors
+///
+/// An empty or non-numeric arc, or fewer than two arcs.
+pub fn parse_oid(s: &str) -> Result<Oid, String> {
+    let oid = s
+        .strip_prefix('.')
+        .unwrap_or(s)
+        .split('.')
+        .map(|arc| arc.parse::<u32>().map_err(|_| format!("bad OID '{s}'")))
+        .collect::<Result<Oid, _>>()?;
+    if oid.len() < 2 || oid[0] > 2 {
+        return Err(format!("bad OID '{s}'"));
+    }
+    Ok(oid)
+}
+
+pub fn oid_string(oid: &[u32]) -> String {
+    oid.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Decode one message.
+///
+/// # Errors
+///
+/// Malformed or truncated BER, or an unexpected type where the message
+/// structure needs a specific one.
+pub fn decode(buf: &[u8]) -> Result<Message, String> {
+    let mut outer = Reader::new(buf);
+    let mut msg = Reader::new(outer.expect(SEQUENCE)?);
+    let version = integer(msg.expect(INTEGER)?)?;
+    let community = msg.expect(OCTET_STRING)?.to_vec();
+    let (tag, body) = msg.tlv()?;
+    let mut pdu = Reader::new(body);
+    let request_id = integer(pdu.expect(INTEGER)?)?;
+    let error_status = integer(pdu.expect(INTEGER)?)?;
+    let error_index = integer(pdu.expect(INTEGER)?)?;
+    let mut list = Reader::new(pdu.expect(SEQUENCE)?);
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let mut vb = Reader::new(list.expect(SEQUENCE)?);
+        let oid = object_id(vb.expect(OBJECT_ID)?)?;
+        let (vtag, content) = vb.tlv()?;
+        varbinds.push(VarBind {
+            oid,
+            value: value(vtag, content)?,
+        });
+    }
+    Ok(Message {
+        version,
+        community,
+        pdu: Pdu {
+            tag,
+            request_id,
+            error_status,
+            error_index,
+            varbinds,
+        },
+    })
+}
+
+pub fn encode(msg: &Message) -> Vec<u8> {
+    let mut list = Vec::new();
+    for vb in &msg.pdu.varbinds {
+        let mut body = Vec::new();
+        tlv(&mut body, OBJECT_ID, &encode_oid(&vb.oid));
+        encode_value(&mut body, &vb.value);
+        tlv(&mut list, SEQUENCE, &body);
+    }
+    let mut pdu = Vec::new();
+    tlv(&mut pdu, INTEGER, &encode_integer(msg.pdu.request_id));
+    tlv(&mut pdu, INTEGER, &encode_integer(msg.pdu.error_status));
+    tlv(&mut pdu, INTEGER, &encode_integer(msg.pdu.error_index));
+    tlv(&mut pdu, SEQUENCE, &list);
+    let mut body = Vec::new();
+    tlv(&mut body, INTEGER, &encode_integer(msg.version));
+    tlv(&mut body, OCTET_STRING, &msg.community);
+    tlv(&mut body, msg.pdu.tag, &pdu);
+    let mut out = Vec::with_capacity(body.len() + 4);
+    tlv(&mut out, SEQUENCE, &body);
+    out
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn tlv(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let (&tag, rest) = self.buf.split_first().ok_or("truncated tag")?;
+        let (&first, mut rest) = rest.split_first().ok_or("truncated length")?;
+        let len = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            let n = usize::from(first & 0x7f);
+            if n == 0 || n > 4 || rest.len() < n {
+                return Err("bad length".into());
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+            rest = &rest[n..];
+            len
+        };
+        if rest.len() < len {
+            return Err("truncated value".into());
+        }
+        let (content, rest) = rest.split_at(len);
+        self.buf = rest;
+        Ok((tag, content))
+    }
+
+    fn expect(&mut self, want: u8) -> Result<&'a [u8], String> {
+        match self.tlv()? {
+            (tag, content) if tag == want => Ok(content),
+            (tag, _) => Err(format!("expected type 0x{want:02x}, got 0x{tag:02x}")),
+        }
+    }
+}
+
+fn integer(content: &[u8]) -> Result<i64, String> {
+    if content.is_empty() || content.len() > 8 {
+        return Err("bad INTEGER".into());
+    }
+    let init = if content[0] & 0x80 == 0 { 0 } else { -1 };
+    Ok(content
+        .iter()
+        .fold(init, |acc: i64, &b| (acc << 8) | i64::from(b)))
+}
+
+fn unsigned(content: &[u8]) -> Result<u64, String> {
+    let content = match content {
+        [0, rest @ ..] => rest,
+        _ => content,
+    };
+    if content.len() > 8 {
+        return Err("unsigned value too long".into());
+    }
+    Ok(content.iter().fold(0, |acc, &b| (acc << 8) | u64::from(b)))
+}
+
+fn object_id(content: &[u8]) -> Result<Oid, String> {
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for &b in content {
+        arc = arc
+            .checked_mul(128)
+            .ok_or("OID arc too large")?
+            .saturating_add(u32::from(b & 0x7f));
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    if arcs.is_empty() || content.last().is_some_and(|b| b & 0x80 != 0) {
+        return Err("bad OBJECT IDENTIFIER".into());
+    }
+    Ok(arcs)
+}
+
+fn value(tag: u8, content: &[u8]) -> Result<Value, String> {
+    #[allow(clippy::cast_possible_truncation)]
+    let u32_of = |c| unsigned(c).map(|v| v as u32);
+    Ok(match tag {
+        INTEGER => Value::Integer(integer(content)?),
+        OCTET_STRING => Value::OctetString(content.to_vec()),
+        OBJECT_ID => Value::ObjectId(object_id(content)?),
+        COUNTER32 => Value::Counter32(u32_of(content)?),
+        GAUGE32 => Value::Gauge32(u32_of(content)?),
+        TIMETICKS => Value::TimeTicks(u32_of(content)?),
+        COUNTER64 => Value::Counter64(unsigned(content)?),
+        NO_SUCH_OBJECT => Value::NoSuchObject,
+        NO_SUCH_INSTANCE => Value::NoSuchInstance,
+        END_OF_MIB_VIEW => Value::EndOfMibView,
+        _ => Value::Null,
+    })
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Integer(v) => tlv(out, INTEGER, &encode_integer(*v)),
+        Value::OctetString(v) => tlv(out, OCTET_STRING, v),
+        Value::Null => tlv(out, NULL, &[]),
+        Value::ObjectId(v) => tlv(out, OBJECT_ID, &encode_oid(v)),
+        Value::Counter32(v) => tlv(out, COUNTER32, &encode_unsigned(u64::from(*v))),
+        Value::Gauge32(v) => tlv(out, GAUGE32, &encode_unsigned(u64::from(*v))),
+        Value::TimeTicks(v) => tlv(out, TIMETICKS, &encode_unsigned(u64::from(*v))),
+        Value::Counter64(v) => tlv(out, COUNTER64, &encode_unsigned(*v)),
+        Value::NoSuchObject => tlv(out, NO_SUCH_OBJECT, &[]),
+        Value::NoSuchInstance => tlv(out, NO_SUCH_INSTANCE, &[]),
+        Value::EndOfMibView => tlv(out, END_OF_MIB_VIEW, &[]),
+    }
+}
+
+/// Shortest two's complement form.
+fn encode_integer(v: i64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let (b, next) = (bytes[start], bytes[start + 1]);
+        if (b == 0 && next & 0x80 == 0) || (b == 0xff && next & 0x80 != 0) {
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[start..].to_vec()
+}
+
+/// Shortest form with a leading zero when the top bit is set, so it doesn't
+/// read as negative.
+fn encode_unsigned(v: u64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+    let mut out = Vec::with_capacity(9);
+    if bytes[start] & 0x80 != 0 {
+        out.push(0);
+    }
+    out.extend_from_slice(&bytes[start..]);
+    out
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(oid.len() + 4);
+    let (first, rest) = match oid {
+        [a, b, rest @ ..] => (a * 40 + b, rest),
+        [a] => (a * 40, &[][..]),
+        [] => (0, &[][..]),
+    };
+    for arc in std::iter::once(first).chain(rest.iter().copied()) {
+        let mut stack = [0u8; 5];
+        let mut n = 0;
+        let mut v = arc;
+        loop {
+            #[allow(clippy::cast_possible_truncation)]
+            let low = (v & 0x7f) as u8;
+            stack[n] = low;
+            n += 1;
+            v >>= 7;
+            if v == 0 {
+                break;
+            }
+        }
+        for i in (0..n).rev() {
+            out.push(stack[i] | if i > 0 { 0x80 } else { 0 });
+        }
+    }
+    out
+}
+
+fn tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        #[allow(clippy::cast_possible_truncation)]
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let start = bytes
+            .iter()
+            .position(|&b| b != 0)
+            .unwrap_or(bytes.len() - 1);
+        #[allow(clippy::cast_possible_truncation)]
+        out.push(0x80 | (bytes.len() - start) as u8);
+        out.extend_from_slice(&bytes[start..]);
+    }
+    out.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_request_from_net_snmp_decodes() {
+        // snmpget -v2c -c public host 1.3.6.1.2.1.1.3.0
+        let bytes = [
+            0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0,
+            0x1c, 0x02, 0x04, 0x1b, 0x4e, 0x8c, 0x51, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30,
+            0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05,
+            0x00,
+        ];
+        let msg = decode(&bytes).unwrap();
+        assert_eq!(msg.version, 1);
+        assert_eq!(msg.community, b"public");
+        assert_eq!(msg.pdu.tag, GET_REQUEST);
+        assert_eq!(msg.pdu.request_id, 0x1b4e_8c51);
+        assert_eq!(
+            msg.pdu.varbinds[0].oid,
+            parse_oid("1.3.6.1.2.1.1.3.0").unwrap()
+        );
+        assert_eq!(encode(&msg), bytes);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let values = vec![
+            Value::Integer(-129),
+            Value::Integer(128),
+            Value::OctetString(vec![b'x'; 300]),
+            Value::ObjectId(parse_oid(".1.3.6.1.4.1.8072.9999.9999").unwrap()),
+            Value::Counter32(u32::MAX),
+            Value::Gauge32(0),
+            Value::TimeTicks(12_345),
+            Value::Counter64(u64::MAX),
+            Value::EndOfMibView,
+        ];
+        let msg = Message {
+            version: 1,
+            community: b"c".to_vec(),
+            pdu: Pdu {
+                tag: RESPONSE,
+                request_id: -1,
+                error_status: 0,
+                error_index: 0,
+                varbinds: values
+                    .into_iter()
+                    .map(|value| VarBind {
+                        oid: vec![1, 3, 6, 1, 2, 1, 1, 1, 0],
+                        value,
+                    })
+                    .collect(),
+            },
+        };
+        assert_eq!(decode(&encode(&msg)).unwrap(), msg);
+        assert!(parse_oid("1").is_err());
+        assert!(parse_oid("1.3.x").is_err());
+    }
+}
@@ -0,0 +1,552 @@
+//! Read-only SNMP agent (`[snmp]`).
+//!
+//! Answers SNMP v1 and v2c `Get`, `GetNext` and (v2c) `GetBulk` requests on
+//! UDP so a network management system can poll a device without a custom
+//! integration. `Set` is refused and requests with the wrong community are
+//! dropped without a reply (counted in `bad_community`).
+//!
+//! The objects are MIB-II `system` plus scalars under `enterprise_oid`
+//! (`E`, default `1.3.6.1.4.1.8072.9999.9999`):
+//!
+//! | OID          | Name                   | Type         |
+//! |--------------|------------------------|--------------|
+//! | `E.1.1.0`    | `sctlVersion`          | OCTET STRING |
+//! | `E.1.2.0`    | `sctlSerial`           | OCTET STRING |
+//! | `E.1.3.0`    | `sctlUptimeSecs`       | Gauge32      |
+//! | `E.2.1.0`    | `sctlSessions`         | Gauge32      |
+//! | `E.2.2.0`    | `sctlSseConnections`   | Gauge32      |
+//! | `E.3.1.0`    | `sctlTunnelConfigured` | INTEGER, 1 = true, 2 = false |
+//! | `E.3.2.0`    | `sctlTunnelConnected`  | INTEGER, 1 = true, 2 = false |
+//! | `E.3.3.0`    | `sctlTunnelReconnects` | Counter32    |
+//! | `E.3.4.0`    | `sctlTunnelUptimeSecs` | Gauge32      |
+//! | `E.3.5.0`    | `sctlTunnelQuality`    | Gauge32, 0-100 |
+//! | `E.3.6.0`    | `sctlTunnelRelays`     | OCTET STRING, comma-separated |
+//! | `E.4.1-3.0`  | `sctlLoad1/5/15`       | Gauge32, load × 100 |
+//! | `E.4.4-5.0`  | `sctlMemTotalKB/AvailKB` | Gauge32    |
+//! | `E.4.6-7.0`  | `sctlDiskTotalKB/AvailKB` | Gauge32, `/` |
+//! | `E.4.8.0`    | `sctlOsUptimeSecs`     | Gauge32      |
+//!
+//! `GET /api/snmp` returns the same objects as JSON, with or without
+//! `[snmp]`.
+
+pub mod ber;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Value as Json};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use self::ber::{Message, Oid, Pdu, Value, VarBind};
+use crate::config::{Config, SnmpConfig};
+use crate::AppState;
+
+/// Most varbinds a `GetBulk` response carries.
+const MAX_BULK_VARBINDS: usize = 100;
+
+const SYSTEM: [u32; 7] = [1, 3, 6, 1, 2, 1, 1];
+
+// error-status values (RFC 3416).
+const NO_SUCH_NAME: i64 = 2;
+const NOT_WRITABLE: i64 = 17;
+
+/// One scalar, already sampled.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub oid: Oid,
+    pub name: &'static str,
+    pub value: Value,
+}
+
+/// The enterprise subtree root from `[snmp] enterprise_oid`, or the default.
+pub fn enterprise_oid(config: &Config) -> Oid {
+    config
+        .snmp
+        .as_ref()
+        .and_then(|sc| ber::parse_oid(&sc.enterprise_oid).ok())
+        .unwrap_or_else(|| {
+            ber::parse_oid(&crate::config::default_snmp_enterprise_oid())
+                .expect("default OID parses")
+        })
+}
+
+/// Sample every object, sorted by OID.
+pub async fn objects(state: &AppState) -> Vec<Object> {
+    use crate::routes::info::{get_disk_usage, parse_loadavg, parse_meminfo, read_proc_file};
+
+    let config = &state.config;
+    let base = enterprise_oid(config);
+    let sub = |arcs: &[u32]| base.iter().chain(arcs).copied().collect::<Oid>();
+    let sys = |arc: u32| SYSTEM.iter().chain(&[arc, 0]).copied().collect::<Oid>();
+    let text = |s: &str| Value::OctetString(s.as_bytes().to_vec());
+    let gauge = |v: u64| Value::Gauge32(u32::try_from(v).unwrap_or(u32::MAX));
+    let truth = |b: bool| Value::Integer(if b { 1 } else { 2 });
+
+    let uptime = state.start_time.elapsed();
+    let hostname = read_proc_file("/proc/sys/kernel/hostname");
+    let kernel = read_proc_file("/proc/version");
+    let (mem_total, mem_available) = parse_meminfo(&read_proc_file("/proc/meminfo"));
+    let load = parse_loadavg(&read_proc_file("/proc/loadavg"));
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let load_x100 = |i: usize| gauge((load.get(i).copied().unwrap_or(0.0) * 100.0).round() as u64);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let os_uptime = read_proc_file("/proc/uptime")
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok())
+        .unwrap_or(0.0) as u64;
+    let disk = get_disk_usage("/");
+    let disk_kb = |key: &str| gauge(disk[key].as_u64().unwrap_or(0) / 1024);
+
+    let ts = &state.tunnel_stats;
+    let tunnel_configured = config
+        .tunnel
+        .as_ref()
+        .is_some_and(|tc| tc.url.is_some() && !tc.relay);
+    let relays = ts.connected_relays.lock().await.join(",");
+    let sessions = state.session_manager.session_count().await;
+    let (contact, location) = config
+        .snmp
+        .as_ref()
+        .map_or(("", ""), |sc| (&*sc.sys_contact, &*sc.sys_location));
+
+    let mut objects = vec![
+        Object {
+            oid: sys(1),
+            name: "sysDescr",
+            value: text(&format!(
+                "sctl {} {}",
+                crate::VERSION,
+                kernel.split(' ').take(3).collect::<Vec<_>>().join(" ")
+            )),
+        },
+        Object {
+            oid: sys(2),
+            name: "sysObjectID",
+            value: Value::ObjectId(base.clone()),
+        },
+        Object {
+            oid: sys(3),
+            name: "sysUpTime",
+            value: Value::TimeTicks(u32::try_from(uptime.as_millis() / 10).unwrap_or(u32::MAX)),
+        },
+        Object {
+            oid: sys(4),
+            name: "sysContact",
+            value: text(contact),
+        },
+        Object {
+            oid: sys(5),
+            name: "sysName",
+            value: text(hostname.trim()),
+        },
+        Object {
+            oid: sys(6),
+            name: "sysLocation",
+            value: text(location),
+        },
+        Object {
+            oid: sys(7),
+            name: "sysServices",
+            value: Value::Integer(72),
+        },
+    ];
+    let scalars = [
+        ([1, 1], "sctlVersion", text(crate::VERSION)),
+        ([1, 2], "sctlSerial", text(&config.device.serial)),
+        ([1, 3], "sctlUptimeSecs", gauge(uptime.as_secs())),
+        ([2, 1], "sctlSessions", gauge(sessions as u64)),
+        (
+            [2, 2],
+            "sctlSseConnections",
+            gauge(u64::from(state.sse_connections.load(Ordering::Relaxed))),
+        ),
+        ([3, 1], "sctlTunnelConfigured", truth(tunnel_configured)),
+        (
+            [3, 2],
+            "sctlTunnelConnected",
+            truth(ts.connected.load(Ordering::Relaxed)),
+        ),
+        (
+            [3, 3],
+            "sctlTunnelReconnects",
+            #[allow(clippy::cast_possible_truncation)]
+            Value::Counter32(ts.reconnects.load(Ordering::Relaxed) as u32),
+        ),
+        (
+            [3, 4],
+            "sctlTunnelUptimeSecs",
+            gauge(ts.current_uptime_ms.load(Ordering::Relaxed) / 1000),
+        ),
+        (
+            [3, 5],
+            "sctlTunnelQuality",
+            gauge(ts.quality_score.load(Ordering::Relaxed)),
+        ),
+        ([3, 6], "sctlTunnelRelays", text(&relays)),
+        ([4, 1], "sctlLoad1", load_x100(0)),
+        ([4, 2], "sctlLoad5", load_x100(1)),
+        ([4, 3], "sctlLoad15", load_x100(2)),
+        ([4, 4], "sctlMemTotalKB", gauge(mem_total)),
+        ([4, 5], "sctlMemAvailKB", gauge(mem_available)),
+        ([4, 6], "sctlDiskTotalKB", disk_kb("total_bytes")),
+        ([4, 7], "sctlDiskAvailKB", disk_kb("available_bytes")),
+        ([4, 8], "sctlOsUptimeSecs", gauge(os_uptime)),
+    ];
+    objects.extend(scalars.into_iter().map(|(arcs, name, value)| Object {
+        oid: sub(&[arcs[0], arcs[1], 0]),
+        name,
+        value,
+    }));
+    objects.sort_by(|a, b| a.oid.cmp(&b.oid));
+    objects
+}
+
+/// The objects as JSON, for `GET /api/snmp`.
+pub async fn snapshot(state: &AppState) -> Json {
+    let objects: Vec<Json> = objects(state)
+        .await
+        .into_iter()
+        .map(|o| {
+            let value = match &o.value {
+                Value::Integer(v) => json!(v),
+                Value::OctetString(v) => json!(String::from_utf8_lossy(v)),
+                Value::ObjectId(v) => json!(ber::oid_string(v)),
+                Value::Counter32(v) | Value::Gauge32(v) | Value::TimeTicks(v) => json!(v),
+                Value::Counter64(v) => json!(v),
+                _ => Json::Null,
+            };
+            json!({
+                "oid": ber::oid_string(&o.oid),
+                "name": format!("{}.0", o.name),
+                "type": o.value.type_name(),
+                "value": value,
+            })
+        })
+        .collect();
+    json!({
+        "enterprise_oid": ber::oid_string(&enterprise_oid(&state.config)),
+        "objects": objects,
+    })
+}
+
+/// The UDP listener and its counters.
+pub struct Agent {
+    config: SnmpConfig,
+    listening: AtomicBool,
+    requests: AtomicU64,
+    bad_community: AtomicU64,
+}
+
+impl Agent {
+    /// Create the agent. Nothing is bound until [`spawn`](Self::spawn).
+    pub fn new(config: SnmpConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            listening: AtomicBool::new(false),
+            requests: AtomicU64::new(0),
+            bad_community: AtomicU64::new(0),
+        })
+    }
+
+    /// Agent status for `/api/info`.
+    pub fn status(&self) -> Json {
+        json!({
+            "listen": self.config.listen,
+            "listening": self.listening.load(Ordering::Relaxed),
+            "requests": self.requests.load(Ordering::Relaxed),
+            "bad_community": self.bad_community.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Bind the socket and answer requests until aborted.
+    pub fn spawn(self: &Arc<Self>, state: &AppState) -> JoinHandle<()> {
+        let agent = self.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            let socket = match UdpSocket::bind(&agent.config.listen).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    error!("SNMP: cannot bind {}: {e}", agent.config.listen);
+                    return;
+                }
+            };
+            agent.listening.store(true, Ordering::Relaxed);
+            info!("SNMP agent listening on udp://{}", agent.config.listen);
+            let mut buf = vec![0u8; 65_535];
+            loop {
+                let (len, peer) = match socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        tracing::debug!("SNMP: recv: {e}");
+                        continue;
+                    }
+                };
+                let request = match ber::decode(&buf[..len]) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        tracing::debug!("SNMP: bad message from {peer}: {e}");
+                        continue;
+                    }
+                };
+                if request.community != agent.config.community.as_bytes() {
+                    agent.bad_community.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                agent.requests.fetch_add(1, Ordering::Relaxed);
+                let objects = objects(&state).await;
+                if let Some(response) = answer(&objects, request) {
+                    if let Err(e) = socket.send_to(&ber::encode(&response), peer).await {
+                        tracing::debug!("SNMP: send to {peer}: {e}");
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The response to `request`, or `None` for a PDU or version the agent
+/// doesn't answer.
+fn answer(objects: &[Object], request: Message) -> Option<Message> {
+    let v1 = match request.version {
+        0 => true,
+        1 => false,
+        _ => return None,
+    };
+    let pdu = request.pdu;
+    let respond = |error_status: i64, error_index: i64, varbinds: Vec<VarBind>| Message {
+        version: request.version,
+        community: request.community.clone(),
+        pdu: Pdu {
+            tag: ber::RESPONSE,
+            request_id: pdu.request_id,
+            error_status,
+            error_index,
+            varbinds,
+        },
+    };
+    let lookup = |oid: &Oid| objects.iter().find(|o| &o.oid == oid);
+    let next = |oid: &Oid| objects.iter().find(|o| &o.oid > oid);
+    // v1 has no exceptions: the first miss fails the whole request.
+    let v1_error = |status: i64, index: usize| {
+        #[allow(clippy::cast_possible_wrap)]
+        respond(status, index as i64 + 1, pdu.varbinds.clone())
+    };
+
+    match pdu.tag {
+        ber::GET_REQUEST | ber::GET_NEXT_REQUEST => {
+            let get = pdu.tag == ber::GET_REQUEST;
+            let mut varbinds = Vec::with_capacity(pdu.varbinds.len());
+            for (i, vb) in pdu.varbinds.iter().enumerate() {
+                let found = if get { lookup(&vb.oid) } else { next(&vb.oid) };
+                varbinds.push(match found {
+                    Some(o) => VarBind {
+                        oid: o.oid.clone(),
+                        value: o.value.clone(),
+                    },
+                    None if v1 => return Some(v1_error(NO_SUCH_NAME, i)),
+                    None => VarBind {
+                        oid: vb.oid.clone(),
+                        value: if !get {
+                            Value::EndOfMibView
+                        } else if is_instance_miss(objects, &vb.oid) {
+                            Value::NoSuchInstance
+                        } else {
+                            Value::NoSuchObject
+                        },
+                    },
+                });
+            }
+            Some(respond(0, 0, varbinds))
+        }
+        ber::GET_BULK_REQUEST if !v1 => {
+            let non_repeaters = usize::try_from(pdu.error_status.max(0)).unwrap_or(0);
+            let max_repetitions = usize::try_from(pdu.error_index.max(0)).unwrap_or(0);
+            let (single, repeating) = pdu.varbinds.split_at(non_repeaters.min(pdu.varbinds.len()));
+            let step = |oid: &Oid| match next(oid) {
+                Some(o) => VarBind {
+                    oid: o.oid.clone(),
+                    value: o.value.clone(),
+                },
+                None => VarBind {
+                    oid: oid.clone(),
+                    value: Value::EndOfMibView,
+                },
+            };
+            let mut varbinds: Vec<VarBind> = single.iter().map(|vb| step(&vb.oid)).collect();
+            let mut cursors: Vec<Oid> = repeating.iter().map(|vb| vb.oid.clone()).collect();
+            for _ in 0..max_repetitions {
+                if varbinds.len() + cursors.len() > MAX_BULK_VARBINDS || cursors.is_empty() {
+                    break;
+                }
+                let row: Vec<VarBind> = cursors.iter().map(step).collect();
+                let done = row.iter().all(|vb| vb.value == Value::EndOfMibView);
+                cursors = row.iter().map(|vb| vb.oid.clone()).collect();
+                varbinds.extend(row);
+                if done {
+                    break;
+                }
+            }
+            Some(respond(0, 0, varbinds))
+        }
+        ber::SET_REQUEST => Some(if v1 {
+            v1_error(NO_SUCH_NAME, 0)
+        } else {
+            respond(NOT_WRITABLE, 1, pdu.varbinds.clone())
+        }),
+        _ => None,
+    }
+}
+
+/// Whether `oid` names an unknown instance of a known scalar (e.g.
+/// `sysName.1`) rather than an unknown object.
+fn is_instance_miss(objects: &[Object], oid: &[u32]) -> bool {
+    oid.split_last()
+        .is_some_and(|(_, object)| objects.iter().any(|o| o.oid.starts_with(object)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn objects() -> Vec<Object> {
+        let scalar = |oid: &str, value| Object {
+            oid: ber::parse_oid(oid).unwrap(),
+            name: "x",
+            value,
+        };
+        vec![
+            scalar("1.3.6.1.2.1.1.5.0", Value::OctetString(b"dev".to_vec())),
+            scalar("1.3.6.1.4.1.9.1.1.0", Value::Gauge32(7)),
+            scalar("1.3.6.1.4.1.9.1.2.0", Value::Integer(1)),
+        ]
+    }
+
+    fn request(version: i64, tag: u8, oids: &[&str], bulk: (i64, i64)) -> Message {
+        Message {
+            version,
+            community: b"public".to_vec(),
+            pdu: Pdu {
+                tag,
+                request_id: 42,
+                error_status: bulk.0,
+                error_index: bulk.1,
+                varbinds: oids
+                    .iter()
+                    .map(|oid| VarBind {
+                        oid: ber::parse_oid(oid).unwrap(),
+                        value: Value::Null,
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn get_next_walks_and_misses_follow_the_version() {
+        let objects = objects();
+        let values = |m: Message| {
+            m.pdu
+                .varbinds
+                .into_iter()
+                .map(|vb| vb.value)
+                .collect::<Vec<_>>()
+        };
+
+        let get = answer(
+            &objects,
+            request(
+                1,
+                ber::GET_REQUEST,
+                &[
+                    "1.3.6.1.2.1.1.5.0",
+                    "1.3.6.1.2.1.1.5.1",
+                    "1.3.6.1.2.1.1.9.0",
+                ],
+                (0, 0),
+            ),
+        )
+        .unwrap();
+        assert_eq!(get.pdu.tag, ber::RESPONSE);
+        assert_eq!(get.pdu.request_id, 42);
+        assert_eq!(
+            values(get),
+            [
+                Value::OctetString(b"dev".to_vec()),
+                Value::NoSuchInstance,
+                Value::NoSuchObject
+            ]
+        );
+
+        let next = answer(
+            &objects,
+            request(
+                1,
+                ber::GET_NEXT_REQUEST,
+                &["1.3.6.1.4.1", "1.3.6.1.4.1.9.1.2.0"],
+                (0, 0),
+            ),
+        )
+        .unwrap();
+        assert_eq!(next.pdu.varbinds[0].oid, objects[1].oid);
+        assert_eq!(values(next)[1], Value::EndOfMibView);
+
+        let v1 = answer(
+            &objects,
+            request(
+                0,
+                ber::GET_NEXT_REQUEST,
+                &["1.3.6.1.2", "1.3.6.1.5"],
+                (0, 0),
+            ),
+        )
+        .unwrap();
+        assert_eq!((v1.pdu.error_status, v1.pdu.error_index), (NO_SUCH_NAME, 2));
+        assert!(answer(
+            &objects,
+            request(0, ber::GET_BULK_REQUEST, &["1.3"], (0, 5))
+        )
+        .is_none());
+
+        let set = answer(
+            &objects,
+            request(1, ber::SET_REQUEST, &["1.3.6.1.2.1.1.5.0"], (0, 0)),
+        )
+        .unwrap();
+        assert_eq!(set.pdu.error_status, NOT_WRITABLE);
+    }
+
+    #[test]
+    fn get_bulk_repeats_until_end_of_mib() {
+        let objects = objects();
+        let bulk = answer(
+            &objects,
+            request(
+                1,
+                ber::GET_BULK_REQUEST,
+                &["1.3.6.1.2.1.1.5.0", "1.3.6.1"],
+                (1, 10),
+            ),
+        )
+        .unwrap();
+        let oids: Vec<String> = bulk
+            .pdu
+            .varbinds
+            .iter()
+            .map(|vb| ber::oid_string(&vb.oid))
+            .collect();
+        assert_eq!(
+            oids,
+            [
+                "1.3.6.1.4.1.9.1.1.0",
+                "1.3.6.1.2.1.1.5.0",
+                "1.3.6.1.4.1.9.1.1.0",
+                "1.3.6.1.4.1.9.1.2.0",
+                "1.3.6.1.4.1.9.1.2.0",
+            ]
+        );
+        assert_eq!(bulk.pdu.varbinds[4].value, Value::EndOfMibView);
+    }
+}
@@ -0,0 +1,135 @@
+//! Startup profile.
+//!
+//! Records how long each startup phase took so a slow boot (typically journal
+//! recovery after a crash with many journals) can be explained. Reported in
+//! `/api/info` as `startup` and logged as a one-line summary once the listener
+//! is bound:
+//!
+//! ```text
+//! Startup: config 4ms, orphan_kill 120ms, journal_recovery 310ms (212 journals), listener_bind 1ms — ready in 452ms
+//! ```
+//!
+//! `tunnel_connect` is added later, when the tunnel client first registers
+//! with a relay; it is measured from ready.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+/// One timed startup phase.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupPhase {
+    pub name: &'static str,
+    pub ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Phase timings for this process.
+pub struct StartupProfile {
+    started: Instant,
+    /// Epoch milliseconds at process start.
+    started_at: u64,
+    phases: Mutex<Vec<StartupPhase>>,
+    /// When the listener was bound.
+    ready: Mutex<Option<Instant>>,
+}
+
+impl StartupProfile {
+    /// Start the clock. Call first thing in startup.
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: crate::sessions::journal::now_ms(),
+            phases: Mutex::new(Vec::new()),
+            ready: Mutex::new(None),
+        }
+    }
+
+    fn lock_phases(&self) -> std::sync::MutexGuard<'_, Vec<StartupPhase>> {
+        self.phases
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Record a phase that began at `since`.
+    pub fn record(&self, name: &'static str, since: Instant, detail: Option<String>) {
+        let ms = since.elapsed().as_millis() as u64;
+        self.lock_phases().push(StartupPhase { name, ms, detail });
+    }
+
+    /// Mark the server ready (listener bound) and log the summary.
+    pub fn mark_ready(&self) {
+        *self
+            .ready
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(Instant::now());
+        info!("Startup: {}", self.summary());
+    }
+
+    /// Record the first tunnel registration, measured from ready. Later
+    /// reconnects are ignored.
+    pub fn tunnel_connected(&self, relay_url: &str) {
+        let Some(ready) = *self
+            .ready
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+        else {
+            return;
+        };
+        let mut phases = self.lock_phases();
+        if phases.iter().any(|p| p.name == "tunnel_connect") {
+            return;
+        }
+        let ms = ready.elapsed().as_millis() as u64;
+        phases.push(StartupPhase {
+            name: "tunnel_connect",
+            ms,
+            detail: Some(relay_url.to_string()),
+        });
+        drop(phases);
+        info!("Startup: tunnel connected {ms}ms after ready ({relay_url})");
+    }
+
+    fn ready_ms(&self) -> Option<u64> {
+        self.ready
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .map(|r| r.duration_since(self.started).as_millis() as u64)
+    }
+
+    /// One-line summary of the phases recorded so far.
+    pub fn summary(&self) -> String {
+        let phases = self
+            .lock_phases()
+            .iter()
+            .map(|p| match p.detail {
+                Some(ref d) => format!("{} {}ms ({d})", p.name, p.ms),
+                None => format!("{} {}ms", p.name, p.ms),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self.ready_ms() {
+            Some(ms) => format!("{phases} — ready in {ms}ms"),
+            None => phases,
+        }
+    }
+
+    /// JSON for `/api/info`.
+    pub fn snapshot(&self) -> Value {
+        json!({
+            "started_at": self.started_at,
+            "ready_ms": self.ready_ms(),
+            "phases": *self.lock_phases(),
+        })
+    }
+}
+
+impl Default for StartupProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
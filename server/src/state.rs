@@ -7,7 +7,7 @@ use std::sync::Arc;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use tokio::sync::{broadcast, Mutex};
 use tracing::warn;
 
@@ -18,12 +18,17 @@ use crate::gawdxfer::manager::TransferManager;
 use crate::infra::InfraState;
 use crate::sessions::SessionManager;
 use crate::tunnel::relay::{DeviceSnapshot, RelayConnectionHistory, RelayState};
+use crate::tunnel::spool::OfflineSpool;
 
 /// Shared application state for the sctl server.
 #[derive(Clone)]
 pub struct AppState {
     /// Immutable configuration loaded at startup.
     pub config: Arc<Config>,
+    /// File `config` was loaded from, if any; rewritten by key rotation.
+    pub config_path: Option<std::path::PathBuf>,
+    /// The primary API key, which can change at runtime (`POST /api/auth/rotate`).
+    pub api_keys: Arc<crate::auth::KeyRing>,
     /// Monotonic instant when the server started (for uptime calculation).
     pub start_time: Instant,
     /// Manages the pool of interactive WebSocket shell sessions.
@@ -35,18 +40,34 @@ pub struct AppState {
     pub activity_log: Arc<ActivityLog>,
     /// In-memory cache of full exec results, keyed by activity ID.
     pub exec_results_cache: Arc<ExecResultsCache>,
+    /// Commit-confirm windows awaiting confirmation (`confirm_within` exec).
+    pub exec_confirms: Arc<crate::shell::confirm::ConfirmRegistry>,
     /// Tunnel connection stats and event history.
     pub tunnel_stats: Arc<TunnelStats>,
+    /// Persisted health transitions (tunnel, disk, OOM, restarts).
+    pub health_history: Arc<crate::health_history::HealthHistory>,
+    /// Startup phase timings, reported in `/api/info`.
+    pub startup: Arc<crate::startup::StartupProfile>,
     /// Chunked file transfer manager (gawdxfer).
     pub transfer_manager: Arc<TransferManager>,
+    /// Off-peak windows, if `[quiet_hours]` is configured.
+    pub quiet_hours: Option<Arc<crate::quiet_hours::QuietHours>>,
     /// Current number of SSE connections (for connection limiting).
     pub sse_connections: Arc<AtomicU32>,
+    /// Open connections on the listeners and to the relay.
+    pub connections: Arc<crate::connections::Connections>,
+    /// Requests refused because a listener reached `max_connections`.
+    pub saturation: Arc<crate::limit::Saturation>,
     /// External comms provider client (None when no provider is configured or startup failed).
     pub comms_client: Option<CommsClient>,
     /// Cached comms provider projections for GPS/LTE-compatible APIs.
     pub comms_state: Option<Arc<Mutex<CommsState>>>,
     /// Notify to trigger an on-demand link poll.
     pub comms_poll_notify: Option<Arc<tokio::sync::Notify>>,
+    /// Recorded GPS track (None without `[gps]` or with track recording off).
+    pub gps_track: Option<Arc<crate::gps_track::GpsTrack>>,
+    /// LTE signal samples (None without `[lte]` or with the history off).
+    pub lte_history: Option<Arc<crate::lte_history::LteHistory>>,
     /// Relay connection history (None when not in relay mode).
     pub relay_history: Option<Arc<RelayConnectionHistory>>,
     /// Device snapshots (relay mode only) — last-known telemetry for offline devices.
@@ -55,6 +76,34 @@ pub struct AppState {
     pub relay_state: Option<RelayState>,
     /// Infrastructure monitoring state (always present, activates on config push).
     pub infra_state: Option<Arc<Mutex<InfraState>>>,
+    /// Disk spool for events raised while the tunnel is down (client mode only).
+    pub offline_spool: Option<Arc<OfflineSpool>>,
+    /// Remote syslog/Vector forwarder, if `[logging.forward]` is configured.
+    pub log_forwarder: Option<Arc<crate::log_forward::LogForwarder>>,
+    /// MQTT telemetry and command bridge, if `[mqtt]` is configured.
+    pub mqtt: Option<Arc<crate::mqtt::Bridge>>,
+    /// Read-only SNMP agent, if `[snmp]` is configured.
+    pub snmp: Option<Arc<crate::snmp::Agent>>,
+    /// A/B firmware updates, if `[firmware]` is configured.
+    pub firmware: Option<Arc<crate::firmware::Firmware>>,
+    /// AI action budget and kill-switch.
+    pub ai_guard: Arc<crate::ai_guard::AiGuard>,
+    /// Exec counters exported by `GET /api/metrics`.
+    pub metrics: Arc<crate::metrics::Metrics>,
+    /// Rolling on-disk capture, if `[flight_recorder]` is configured.
+    pub flight_recorder: Option<Arc<crate::flight_recorder::FlightRecorder>>,
+    /// Routes and message handlers registered by an embedding crate.
+    pub extensions: Arc<crate::extensions::Extensions>,
+    /// Executables discovered in `[plugins] dir`.
+    pub plugins: Arc<crate::plugins::Plugins>,
+    /// Lua hooks, if `[hooks]` is configured.
+    pub hooks: Option<Arc<crate::hooks::Hooks>>,
+    /// Compiled `[policy]` command rules.
+    pub policy: Arc<crate::shell::policy::Policy>,
+    /// Desired state and the latest drift report.
+    pub twin: Arc<crate::twin::Twin>,
+    /// Device→relay requests awaiting a reply (e.g. `tunnel.resolve`).
+    pub relay_requests: Arc<crate::tunnel::client::RelayRequests>,
 }
 
 /// Tunnel connection event types.
@@ -100,14 +149,107 @@ const EVENT_MAX_AGE_SECS: u64 = 48 * 3600;
 /// Maximum number of pong RTT samples to keep for quality tracking.
 const MAX_RTT_SAMPLES: usize = 20;
 
+/// Number of heartbeat ticks tracked for pong-loss ratio.
+const PONG_WINDOW: usize = 20;
+
+/// Reconnects within this window count against the quality score.
+const QUALITY_RECONNECT_WINDOW_SECS: u64 = 3600;
+
+/// Coarse link-quality bucket derived from [`TunnelStats::quality_score`].
+///
+/// Subsystems key their adaptive behavior off the bucket rather than the raw
+/// score so thresholds live in one place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkQuality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl LinkQuality {
+    #[must_use]
+    pub fn from_score(score: u64) -> Self {
+        match score {
+            70.. => Self::Good,
+            40..=69 => Self::Fair,
+            _ => Self::Poor,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Good => "good",
+            Self::Fair => "fair",
+            Self::Poor => "poor",
+        }
+    }
+
+    /// Tunnel output batching limits `(max_entries, max_bytes, coalesce_ms)`.
+    ///
+    /// `coalesce_ms` is how long fresh output is held for more to join it.
+    /// Good links flush at once, so interactive output isn't delayed in the
+    /// common case. Degraded links hold briefly and take larger batches, so
+    /// output leaves in fewer frames.
+    #[must_use]
+    pub fn stream_batch_limits(self) -> (usize, usize, u64) {
+        match self {
+            Self::Good => (32, 8 * 1024, 0),
+            Self::Fair => (64, 16 * 1024, 5),
+            Self::Poor => (128, 32 * 1024, 10),
+        }
+    }
+
+    /// Scale a transfer chunk size down on degraded links so a single lost
+    /// chunk costs less to retry. Never goes below 16 KiB.
+    #[must_use]
+    pub fn scale_chunk_size(self, chunk_size: u32) -> u32 {
+        let scaled = match self {
+            Self::Good => chunk_size,
+            Self::Fair => chunk_size / 2,
+            Self::Poor => chunk_size / 4,
+        };
+        scaled.max(16 * 1024).min(chunk_size)
+    }
+}
+
+/// Outcome of the most recent tunnel connect handshake (DNS+TCP, TLS+WS,
+/// registration). Recorded on every attempt so a relay-side operator can see
+/// which stage a flapping device is failing at without shell access.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TunnelSelftest {
+    /// Unix timestamp (seconds since epoch) of the attempt.
+    pub timestamp: u64,
+    pub ok: bool,
+    pub dns_tcp_ms: Option<u64>,
+    pub tls_ws_ms: Option<u64>,
+    pub register_ms: Option<u64>,
+    pub total_ms: u64,
+    pub error: Option<String>,
+}
+
 /// Tunnel connection statistics — atomics for lock-free hot-path updates,
 /// Mutex only for event log and RTT samples (cold path).
 pub struct TunnelStats {
+    /// True while at least one relay connection is registered.
     pub connected: AtomicBool,
+    /// Relay URLs with a live registration. With multiple relays configured
+    /// (active-active) `connected` is true while any of them is up.
+    pub connected_relays: Mutex<Vec<String>>,
     /// True while the tunnel client is actively attempting a connection (DNS/TCP/TLS/handshake).
     /// Used by watchdog to avoid disrupting in-progress reconnection attempts.
     pub reconnecting: AtomicBool,
     pub reconnects: AtomicU64,
+    /// Connection attempts that failed in a row, across relays. Reset when a
+    /// registration succeeds. The comms provider's watchdog reads it to judge
+    /// whether the active APN profile is dead.
+    pub connect_failures: AtomicU64,
+    /// Bumped by the comms provider each time it switches the data link
+    /// (APN or SIM profile). The tunnel client drops its backoff when it
+    /// sees a new value, since failures on the old link say nothing about
+    /// the new one.
+    pub link_generation: AtomicU64,
     pub messages_sent: AtomicU64,
     pub messages_received: AtomicU64,
     pub last_pong_age_ms: AtomicU64,
@@ -115,15 +257,27 @@ pub struct TunnelStats {
     pub dropped_outbound: AtomicU64,
     pub stream_backpressure_events: AtomicU64,
     pub stream_replay_events: AtomicU64,
+    /// Rolling connection-quality score, 0 (unusable) to 100 (ideal).
+    /// Recomputed on each heartbeat tick; read lock-free by adaptive subsystems.
+    pub quality_score: AtomicU64,
     /// Epoch for computing relative timestamps in events.
     pub epoch: Instant,
     pub events: Mutex<VecDeque<ConnectionEvent>>,
     /// Rolling window of pong RTT samples (ms).
     pub rtt_samples: Mutex<VecDeque<u64>>,
+    /// Result of the last connect handshake (None until the first attempt finishes).
+    pub last_selftest: Mutex<Option<TunnelSelftest>>,
+    /// Per-heartbeat pong outcome (true = pong seen since previous tick).
+    pub pong_window: Mutex<VecDeque<bool>>,
     /// Path to persist events on disk (None = persistence disabled).
     pub events_path: Option<PathBuf>,
     /// Dirty flag for debounced persistence.
     pub events_dirty: AtomicBool,
+    /// Flight recorder that also receives each event.
+    pub flight_recorder: Option<Arc<crate::flight_recorder::FlightRecorder>>,
+    /// Wakes the tunnel client to drop its connection, or skip the rest of
+    /// its backoff, and connect again at once (`notify_waiters`).
+    pub reconnect_now: tokio::sync::Notify,
 }
 
 impl TunnelStats {
@@ -131,8 +285,11 @@ impl TunnelStats {
     pub fn new() -> Self {
         Self {
             connected: AtomicBool::new(false),
+            connected_relays: Mutex::new(Vec::new()),
             reconnecting: AtomicBool::new(false),
             reconnects: AtomicU64::new(0),
+            connect_failures: AtomicU64::new(0),
+            link_generation: AtomicU64::new(0),
             messages_sent: AtomicU64::new(0),
             messages_received: AtomicU64::new(0),
             last_pong_age_ms: AtomicU64::new(0),
@@ -140,12 +297,27 @@ impl TunnelStats {
             dropped_outbound: AtomicU64::new(0),
             stream_backpressure_events: AtomicU64::new(0),
             stream_replay_events: AtomicU64::new(0),
+            quality_score: AtomicU64::new(100),
             epoch: Instant::now(),
             events: Mutex::new(VecDeque::with_capacity(MAX_TUNNEL_EVENTS)),
             rtt_samples: Mutex::new(VecDeque::with_capacity(MAX_RTT_SAMPLES)),
+            last_selftest: Mutex::new(None),
+            pong_window: Mutex::new(VecDeque::with_capacity(PONG_WINDOW)),
             events_path: None,
             events_dirty: AtomicBool::new(false),
+            flight_recorder: None,
+            reconnect_now: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Mark a relay registration as up or down and refresh `connected`.
+    pub async fn set_relay_connected(&self, relay_url: &str, up: bool) {
+        let mut relays = self.connected_relays.lock().await;
+        relays.retain(|u| u != relay_url);
+        if up {
+            relays.push(relay_url.to_string());
         }
+        self.connected.store(!relays.is_empty(), Ordering::Relaxed);
     }
 
     /// Push a connection event, evicting oldest if at capacity.
@@ -154,6 +326,12 @@ impl TunnelStats {
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
+        if let Some(recorder) = &self.flight_recorder {
+            recorder.record(
+                "tunnel",
+                json!({ "event": event_type.as_str(), "detail": detail }),
+            );
+        }
         let mut events = self.events.lock().await;
         if events.len() >= MAX_TUNNEL_EVENTS {
             events.pop_front();
@@ -195,6 +373,112 @@ impl TunnelStats {
         Some((median, p95))
     }
 
+    /// Record whether a pong arrived during the last heartbeat interval.
+    pub async fn record_pong_outcome(&self, received: bool) {
+        let mut window = self.pong_window.lock().await;
+        if window.len() >= PONG_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(received);
+    }
+
+    /// Current link-quality bucket (lock-free).
+    pub fn link_quality(&self) -> LinkQuality {
+        LinkQuality::from_score(self.quality_score.load(Ordering::Relaxed))
+    }
+
+    /// Recompute the quality score from RTT, pong loss and recent reconnects.
+    ///
+    /// Weighted blend: RTT median 40% (100ms → full, 2s → zero), pong loss
+    /// 35%, reconnects in the last hour 25% (six or more → zero). Returns the
+    /// new score, which is also stored in `quality_score`.
+    pub async fn update_quality_score(&self) -> u64 {
+        #[allow(clippy::cast_precision_loss)]
+        let rtt_factor = match self.rtt_stats().await {
+            Some((median, _)) => 1.0 - (median.saturating_sub(100) as f64 / 1900.0).min(1.0),
+            None => 1.0,
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let loss_factor = {
+            let window = self.pong_window.lock().await;
+            if window.is_empty() {
+                1.0
+            } else {
+                window.iter().filter(|&&ok| ok).count() as f64 / window.len() as f64
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let recent_reconnects = self
+            .events
+            .lock()
+            .await
+            .iter()
+            .filter(|e| {
+                matches!(e.event_type, TunnelEventType::Disconnected)
+                    && now.saturating_sub(e.timestamp) < QUALITY_RECONNECT_WINDOW_SECS
+            })
+            .count();
+        #[allow(clippy::cast_precision_loss)]
+        let reconnect_factor = 1.0 - (recent_reconnects as f64 / 6.0).min(1.0);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let score = ((rtt_factor * 0.40 + loss_factor * 0.35 + reconnect_factor * 0.25) * 100.0)
+            .round() as u64;
+        self.quality_score.store(score, Ordering::Relaxed);
+        score
+    }
+
+    /// Record the outcome of a connect handshake.
+    pub async fn record_selftest(&self, selftest: TunnelSelftest) {
+        *self.last_selftest.lock().await = Some(selftest);
+    }
+
+    /// Full local tunnel diagnostics: counters, link quality and the output
+    /// batching it selects, RTT history, recent events and the last handshake
+    /// selftest. Served to the relay via `tunnel.diag`.
+    pub async fn diag_snapshot(&self) -> Value {
+        let rtt_history: Vec<u64> = self.rtt_samples.lock().await.iter().copied().collect();
+        let (rtt_median, rtt_p95) = self.rtt_stats().await.unwrap_or((0, 0));
+        let events: Vec<ConnectionEvent> = self.events.lock().await.iter().cloned().collect();
+        let selftest = self.last_selftest.lock().await.clone();
+        let connected_relays = self.connected_relays.lock().await.clone();
+        let quality = self.link_quality();
+        let (max_entries, max_bytes, coalesce_ms) = quality.stream_batch_limits();
+        serde_json::json!({
+            "stats": {
+                "connected": self.connected.load(Ordering::Relaxed),
+                "connected_relays": connected_relays,
+                "reconnecting": self.reconnecting.load(Ordering::Relaxed),
+                "reconnects": self.reconnects.load(Ordering::Relaxed),
+                "messages_sent": self.messages_sent.load(Ordering::Relaxed),
+                "messages_received": self.messages_received.load(Ordering::Relaxed),
+                "last_pong_age_ms": self.last_pong_age_ms.load(Ordering::Relaxed),
+                "uptime_secs": self.current_uptime_ms.load(Ordering::Relaxed) / 1000,
+                "dropped_outbound": self.dropped_outbound.load(Ordering::Relaxed),
+                "stream_backpressure_events": self.stream_backpressure_events.load(Ordering::Relaxed),
+                "stream_replay_events": self.stream_replay_events.load(Ordering::Relaxed),
+            },
+            "quality": {
+                "score": self.quality_score.load(Ordering::Relaxed),
+                "level": quality.as_str(),
+                "stream_batch": {
+                    "max_entries": max_entries,
+                    "max_bytes": max_bytes,
+                    "coalesce_ms": coalesce_ms,
+                },
+            },
+            "rtt": {
+                "median_ms": rtt_median,
+                "p95_ms": rtt_p95,
+                "history_ms": rtt_history,
+            },
+            "events": events,
+            "selftest": selftest,
+        })
+    }
+
     /// Load persisted events from disk, pruning entries older than 48h.
     pub fn load_events(path: &Path) -> VecDeque<ConnectionEvent> {
         let Ok(data) = std::fs::read_to_string(path) else {
@@ -249,3 +533,68 @@ impl Default for TunnelStats {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_buckets_follow_score() {
+        assert_eq!(LinkQuality::from_score(100), LinkQuality::Good);
+        assert_eq!(LinkQuality::from_score(70), LinkQuality::Good);
+        assert_eq!(LinkQuality::from_score(69), LinkQuality::Fair);
+        assert_eq!(LinkQuality::from_score(40), LinkQuality::Fair);
+        assert_eq!(LinkQuality::from_score(39), LinkQuality::Poor);
+        assert_eq!(LinkQuality::from_score(0), LinkQuality::Poor);
+    }
+
+    #[test]
+    fn good_links_flush_immediately() {
+        let (_, _, coalesce_ms) = LinkQuality::Good.stream_batch_limits();
+        assert_eq!(coalesce_ms, 0);
+    }
+
+    #[test]
+    fn degraded_links_batch_more_with_a_short_hold() {
+        let good = LinkQuality::Good.stream_batch_limits();
+        let fair = LinkQuality::Fair.stream_batch_limits();
+        let poor = LinkQuality::Poor.stream_batch_limits();
+        assert!(good.0 < fair.0 && fair.0 < poor.0);
+        assert!(good.1 < fair.1 && fair.1 < poor.1);
+        assert!(good.2 < fair.2 && fair.2 < poor.2);
+        assert!(poor.2 <= 10);
+    }
+
+    #[test]
+    fn chunk_size_shrinks_with_a_floor() {
+        assert_eq!(LinkQuality::Good.scale_chunk_size(256 * 1024), 256 * 1024);
+        assert_eq!(LinkQuality::Fair.scale_chunk_size(256 * 1024), 128 * 1024);
+        assert_eq!(LinkQuality::Poor.scale_chunk_size(256 * 1024), 64 * 1024);
+        assert_eq!(LinkQuality::Poor.scale_chunk_size(32 * 1024), 16 * 1024);
+        assert_eq!(LinkQuality::Poor.scale_chunk_size(8 * 1024), 8 * 1024);
+    }
+
+    #[tokio::test]
+    async fn score_drops_with_rtt_loss_and_reconnects() {
+        let stats = TunnelStats::new();
+        assert_eq!(stats.update_quality_score().await, 100);
+
+        for _ in 0..5 {
+            stats.record_rtt(2_000).await;
+        }
+        assert_eq!(stats.update_quality_score().await, 60);
+
+        for received in [true, false, true, true] {
+            stats.record_pong_outcome(received).await;
+        }
+        assert_eq!(stats.update_quality_score().await, 51);
+
+        for _ in 0..6 {
+            stats
+                .push_event(TunnelEventType::Disconnected, String::new())
+                .await;
+        }
+        assert_eq!(stats.update_quality_score().await, 26);
+        assert_eq!(stats.link_quality(), LinkQuality::Poor);
+    }
+}
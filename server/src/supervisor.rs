@@ -138,8 +138,10 @@ pub async fn run_supervisor(config_path: Option<&str>, sup_config: &SupervisorCo
         info!("Supervisor: started server (pid {server_pid:?})");
 
         // Forward SIGINT and SIGTERM to child, and set shutdown flag
+        #[cfg(unix)]
         let fwd_pid = server_pid;
         let sd = Arc::clone(&shutting_down);
+        #[cfg(unix)]
         let _signal_task = tokio::spawn(async move {
             let mut sigint =
                 tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
@@ -166,6 +168,14 @@ pub async fn run_supervisor(config_path: Option<&str>, sup_config: &SupervisorCo
                 }
             }
         });
+        // The child shares our console, so it gets the same Ctrl-C.
+        #[cfg(windows)]
+        let _signal_task = tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                info!("Supervisor: received Ctrl-C, shutting down");
+                sd.store(true, Ordering::SeqCst);
+            }
+        });
 
         let status = child.wait().await;
         let uptime = started.elapsed();
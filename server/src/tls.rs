@@ -0,0 +1,288 @@
+//! TLS termination for the HTTP/WebSocket listeners (`[server.tls]`).
+//!
+//! Listeners with `tls = true` (the default) wrap their TCP listener in a
+//! [`TlsListener`]: HTTPS and WSS, with rustls. Handshakes run in their own
+//! tasks with a timeout, so a slow or silent client can't hold up accepting
+//! others.
+//!
+//! With `client_ca`, the handshake also requires a client certificate signed
+//! by one of those CAs (mTLS). What the certificate is then worth is up to
+//! `client_auth`:
+//!
+//! - `key` (default) — nothing beyond getting in; the API key is still
+//!   required, so a stolen key alone is not enough.
+//! - `cert` — the certificate replaces the key. Its subject CN becomes the
+//!   identity name, with the scopes of the `[[auth.keys]]` entry of that name
+//!   or every scope when there is none; a key sent along is not consulted.
+//!   A certificate without a CN falls back to the API key. See
+//!   [`crate::auth::ClientCert`].
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::http::Extensions;
+use axum::serve::Listener;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+
+use crate::auth::{ClientCert, Identity};
+use crate::config::{ClientAuth, NamedKeyConfig, TlsConfig};
+use crate::serve::PeerInfo;
+
+/// How long a client gets to finish the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Finished handshakes waiting for `accept`.
+const ACCEPT_QUEUE: usize = 64;
+
+/// Build the acceptor for `config`: load the certificate chain, the key and,
+/// with `client_ca`, the client verifier. `http2` offers `h2` in ALPN.
+///
+/// # Errors
+///
+/// A file can't be read or parsed, or rustls rejects the key or CAs.
+pub fn acceptor(config: &TlsConfig, http2: bool) -> Result<TlsAcceptor, String> {
+    let chain = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| format!("{}: {e}", config.cert))?;
+    if chain.is_empty() {
+        return Err(format!("{}: no certificates", config.cert));
+    }
+    let key =
+        PrivateKeyDer::from_pem_file(&config.key).map_err(|e| format!("{}: {e}", config.key))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path).map_err(|e| format!("{path}: {e}"))? {
+                let cert = cert.map_err(|e| format!("{path}: {e}"))?;
+                roots.add(cert).map_err(|e| format!("{path}: {e}"))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| format!("{path}: {e}"))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server = builder
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("{}: {e}", config.key))?;
+    server.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+/// Who connected: the TCP peer, and what its client certificate is worth.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub cert: ClientCert,
+}
+
+/// A TCP listener that hands out finished TLS connections.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    ready: mpsc::Receiver<(TlsStream<TcpStream>, Peer)>,
+}
+
+impl TlsListener {
+    /// Start accepting on `tcp`. `keys` gives certificate identities their
+    /// scopes when `client_auth = "cert"`.
+    ///
+    /// # Errors
+    ///
+    /// `tcp` has no local address.
+    pub fn new(
+        tcp: TcpListener,
+        acceptor: TlsAcceptor,
+        client_auth: ClientAuth,
+        keys: Arc<[NamedKeyConfig]>,
+    ) -> io::Result<Self> {
+        let local_addr = tcp.local_addr()?;
+        let (tx, ready) = mpsc::channel(ACCEPT_QUEUE);
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("TLS listener accept failed: {e}");
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+                if tx.is_closed() {
+                    break;
+                }
+                let (acceptor, tx, keys) = (acceptor.clone(), tx.clone(), keys.clone());
+                tokio::spawn(async move {
+                    let tls = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                        .await
+                    {
+                        Ok(Ok(tls)) => tls,
+                        Ok(Err(e)) => return debug!("TLS handshake with {addr} failed: {e}"),
+                        Err(_) => return debug!("TLS handshake with {addr} timed out"),
+                    };
+                    let identity = match client_auth {
+                        ClientAuth::Cert => tls
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .and_then(<[_]>::first)
+                            .and_then(|cert| subject_cn(cert))
+                            .map(|name| cert_identity(&name, &keys)),
+                        ClientAuth::Key => None,
+                    };
+                    let peer = Peer {
+                        addr,
+                        cert: ClientCert(identity),
+                    };
+                    let _ = tx.send((tls, peer)).await;
+                });
+            }
+        });
+        Ok(Self { local_addr, ready })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = Peer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.ready.recv().await {
+            Some(accepted) => accepted,
+            // The accept task only stops once this receiver is gone.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(Peer {
+            addr: self.local_addr,
+            cert: ClientCert::default(),
+        })
+    }
+}
+
+impl PeerInfo for Peer {
+    fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn insert_into(&self, extensions: &mut Extensions) {
+        extensions.insert(ConnectInfo(self.addr));
+        extensions.insert(ConnectInfo(self.cert.clone()));
+    }
+}
+
+/// The identity for a certificate with subject CN `name`.
+fn cert_identity(name: &str, keys: &[NamedKeyConfig]) -> Identity {
+    keys.iter().find(|k| k.name == name).map_or_else(
+        || Identity::full(name),
+        |k| Identity {
+            name: name.to_string(),
+            scopes: k.scopes.clone(),
+        },
+    )
+}
+
+/// The subject common name of a DER certificate, if it has one.
+fn subject_cn(cert: &[u8]) -> Option<String> {
+    let (_, cert, _) = der(cert)?;
+    let (_, mut tbs, _) = der(cert)?;
+    // version [0] (optional), serialNumber, signature, issuer, validity
+    let mut skip = 4;
+    if tbs.first() == Some(&0xa0) {
+        skip += 1;
+    }
+    for _ in 0..skip {
+        tbs = der(tbs)?.2;
+    }
+    let (_, mut subject, _) = der(tbs)?;
+    // Name: SEQUENCE OF SET OF SEQUENCE { OID, value }
+    while !subject.is_empty() {
+        let (_, mut rdn, rest) = der(subject)?;
+        subject = rest;
+        while !rdn.is_empty() {
+            let (_, attr, rest) = der(rdn)?;
+            rdn = rest;
+            let (_, oid, value) = der(attr)?;
+            if oid == [0x55, 0x04, 0x03] {
+                let (_, value, _) = der(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Split one DER element off `input`: `(tag, contents, rest)`.
+fn der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || input.len() < n {
+            return None;
+        }
+        let (bytes, rest) = input.split_at(n);
+        input = rest;
+        bytes.iter().fold(0, |len, &b| (len << 8) | usize::from(b))
+    };
+    if input.len() < len {
+        return None;
+    }
+    let (contents, rest) = input.split_at(len);
+    Some((tag, contents, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Subject `O=gawd, CN=ci-runner`, issued by `CN=sctl test CA`.
+    const CLIENT_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBfTCCASSgAwIBAgIUT4RXd9TSWqYoSS1UziuqAOjv1qQwCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMc2N0bCB0ZXN0IENBMB4XDTI2MTAxNjExNTU0M1oXDTM2MTAx
+MzExNTU0M1owIzENMAsGA1UECgwEZ2F3ZDESMBAGA1UEAwwJY2ktcnVubmVyMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEo0tuywuJUf4TU5jr8r1F/N+f+jOJtG5a
+2IDZ1Iqompm+A9tj+EZ/5uYe36tAvqAN+dO7UfWzHDssNzsW23s5KaNCMEAwHQYD
+VR0OBBYEFI69Ohlt8cZIK85ktC40N8RGn/94MB8GA1UdIwQYMBaAFDCHf3vxySjA
+SEaHrzNJe5IZsu12MAoGCCqGSM49BAMCA0cAMEQCIAoZTHbvL92MX/cQjXvqo4wP
+3cRHJKjDDV495VxRA7fbAiBNRv1F+7nukHg8WER4uYXbkTAi/15OrQ1UKRaiCRuc
+YA==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn subject_cn_skips_the_issuer() {
+        let cert = CertificateDer::from_pem_slice(CLIENT_CERT.as_bytes()).unwrap();
+        assert_eq!(subject_cn(&cert).as_deref(), Some("ci-runner"));
+        assert_eq!(subject_cn(&cert[..40]), None);
+
+        let keys = [NamedKeyConfig {
+            name: "ci-runner".to_string(),
+            key: "unused".to_string(),
+            scopes: vec!["files:read".to_string()],
+        }];
+        assert_eq!(cert_identity("ci-runner", &keys).scopes, ["files:read"]);
+        assert!(cert_identity("laptop", &keys).allows("exec"));
+    }
+}
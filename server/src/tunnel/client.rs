@@ -7,24 +7,30 @@
 use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 #[cfg(unix)]
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::panic::AssertUnwindSafe;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use base64::Engine;
 use futures_util::{FutureExt, SinkExt, StreamExt};
 use serde_json::{json, Value};
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot, watch, Mutex, Semaphore};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
 use crate::activity::{self, ActivityType, CachedExecResult};
-use crate::config::TunnelConfig;
+use crate::config::{TunnelConfig, TunnelEncoding};
+use crate::error::{codes, ApiError};
+use crate::infra::now_epoch;
 use crate::sessions::buffer::{OutputBuffer, OutputEntry};
-use crate::state::TunnelEventType;
+use crate::sessions::decode::OutputEncoding;
+use crate::state::{TunnelEventType, TunnelSelftest};
 use crate::AppState;
 
+use super::failover::Failover;
 use super::{decode_binary_frame, encode_binary_frame};
 
 /// Static heartbeat message — avoids serde allocation on every heartbeat tick.
@@ -32,14 +38,14 @@ const PING_TEXT: &str = r#"{"type":"tunnel.ping"}"#;
 /// LTE paths can stall transiently under output bursts without being truly dead.
 /// Keep the local socket/write deadlines comfortably above the relay's liveness
 /// window so the device does not self-abort first.
+#[cfg(target_os = "linux")]
 const TUNNEL_TCP_USER_TIMEOUT_MS: libc::c_int = 15_000;
 const TUNNEL_WRITER_SEND_TIMEOUT_SECS: u64 = 20;
-/// Coalesce adjacent PTY output chunks into larger tunnel frames. LTE links are
-/// much less tolerant of hundreds of tiny JSON WS frames than a handful of
-/// larger ones carrying the same bytes.
-const TUNNEL_STREAM_BATCH_MAX_ENTRIES: usize = 32;
-const TUNNEL_STREAM_BATCH_MAX_BYTES: usize = 8 * 1024;
-
+/// Max unacked lifecycle events held for resend per relay. Oldest are
+/// dropped beyond this — a long outage is covered by the offline spool.
+const EVENT_OUTBOX_CAPACITY: usize = 512;
+/// Resend an unacked lifecycle event after this long on a live connection.
+const EVENT_RESEND_AFTER: Duration = Duration::from_secs(30);
 /// Resolve a `bind_address` config value to a concrete IP address.
 ///
 /// Accepts either:
@@ -85,6 +91,24 @@ fn resolve_bind_address(value: &str) -> Option<std::net::IpAddr> {
     }
 }
 
+/// Record the aggregate tunnel state (any relay registered) in the health
+/// history after a registration change.
+async fn observe_tunnel_health(state: &AppState, relay_url: &str) {
+    let state_str = if state.tunnel_stats.connected.load(Ordering::Relaxed) {
+        "up"
+    } else {
+        "down"
+    };
+    state
+        .health_history
+        .observe(
+            crate::health_history::Condition::Tunnel,
+            state_str,
+            relay_url,
+        )
+        .await;
+}
+
 /// Probe whether a local IP address is currently available for binding.
 async fn is_local_address_available(addr: &std::net::IpAddr) -> bool {
     tokio::net::UdpSocket::bind(SocketAddr::new(*addr, 0))
@@ -101,31 +125,398 @@ struct WsSink {
     priority_tx: mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
     request_tx: mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
     stream_tx: mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
+    /// Keeps responses for replay to duplicate requests (active-active).
+    dedup: Option<Arc<RequestDedup>>,
+    /// This relay's unacked lifecycle events (depth reported by `tunnel.diag`).
+    outbox: Arc<EventOutbox>,
+}
+
+/// Requests seen recently (see [`dedup_key`]), shared by every relay
+/// connection so a request delivered through more than one relay
+/// (active-active) runs once. The first copy's response is kept, and later
+/// copies get it too instead of running again.
+struct RequestDedup {
+    inner: std::sync::Mutex<DedupState>,
+}
+
+#[derive(Default)]
+struct DedupState {
+    /// Key → when it was first seen and, once sent, the first copy's response.
+    seen: HashMap<String, (Instant, Option<Value>)>,
+    /// `request_id` of each first copy still being handled → its key.
+    running: HashMap<String, String>,
+}
+
+/// What [`RequestDedup::check`] found for a request.
+enum Seen {
+    /// First copy: handle it.
+    First,
+    /// The first copy is still being handled.
+    InFlight,
+    /// The first copy's response, or `None` if none was kept (too large,
+    /// binary, or the handler ended without one).
+    Done(Option<Value>),
+}
+
+/// Held by whatever handles a first copy; dropping it ends the copy's
+/// [`Seen::InFlight`] however the handler finished, including a panic, a
+/// binary response or no response at all.
+struct Running {
+    dedup: Arc<RequestDedup>,
+    request_id: String,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.dedup.lock().running.remove(&self.request_id);
+    }
+}
+
+impl RequestDedup {
+    const TTL: Duration = Duration::from_secs(300);
+    const PRUNE_AT: usize = 4096;
+    /// Larger responses aren't kept for replay.
+    const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+    fn new() -> Self {
+        Self {
+            inner: std::sync::Mutex::new(DedupState::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DedupState> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Look up `key`, recording `request_id` as its first copy if it is new
+    /// within the TTL. A first copy comes with the [`Running`] its handler
+    /// holds.
+    fn check(self: &Arc<Self>, key: &str, request_id: &str) -> (Seen, Option<Running>) {
+        let mut inner = self.lock();
+        if inner.seen.len() >= Self::PRUNE_AT {
+            inner.seen.retain(|_, (at, _)| at.elapsed() < Self::TTL);
+            let DedupState { seen, running } = &mut *inner;
+            running.retain(|_, key| seen.contains_key(key));
+        }
+        match inner.seen.get(key) {
+            Some((at, response)) if at.elapsed() < Self::TTL => {
+                if inner.running.values().any(|k| k == key) {
+                    (Seen::InFlight, None)
+                } else {
+                    (Seen::Done(response.clone()), None)
+                }
+            }
+            _ => {
+                inner.seen.insert(key.to_string(), (Instant::now(), None));
+                inner
+                    .running
+                    .insert(request_id.to_string(), key.to_string());
+                let running = Running {
+                    dedup: self.clone(),
+                    request_id: request_id.to_string(),
+                };
+                (Seen::First, Some(running))
+            }
+        }
+    }
+
+    /// Keep `response` if it answers a first copy. `len` is its encoded size.
+    fn record(&self, response: &Value, len: usize) {
+        let Some(request_id) = response["request_id"].as_str() else {
+            return;
+        };
+        let mut inner = self.lock();
+        let Some(key) = inner.running.remove(request_id) else {
+            return;
+        };
+        if let Some((_, kept)) = inner.seen.get_mut(&key) {
+            *kept = (len <= Self::MAX_RESPONSE_BYTES).then(|| response.clone());
+        }
+    }
+}
+
+/// What a relay message is deduplicated on: the client's `idempotency_key`
+/// when it sent one (the relay fills it in from `Idempotency-Key`; WS clients
+/// set it themselves), else the relay-issued `request_id`. Relays issue their own
+/// ids, so only the idempotency key matches a request sent through two of
+/// them. Client-tagged ids (`client:rid`) are per connection and never
+/// deduplicated. Keys are scoped to the message type.
+fn dedup_key(msg: &Value) -> Option<String> {
+    let msg_type = msg["type"].as_str().unwrap_or("");
+    msg["idempotency_key"]
+        .as_str()
+        .or_else(|| msg["request_id"].as_str().filter(|rid| !rid.contains(':')))
+        .map(|key| format!("{msg_type}:{key}"))
+}
+
+/// Unacked device→relay lifecycle events for one relay (session lifecycle,
+/// transfer progress, activity). Each forwarded broadcast gets an `event_seq`;
+/// the relay acks it with `tunnel.events.ack` and drops redeliveries. The
+/// outbox outlives individual connections so anything in flight when the link
+/// drops is resent after the next registration.
+struct EventOutbox {
+    /// Identifies this sequence space. Sent at registration so the relay resets
+    /// its dedup cursor when the device restarts and numbering starts over.
+    stream_id: String,
+    next_seq: AtomicU64,
+    /// `(event_seq, last_sent, event)` in send order.
+    unacked: Mutex<VecDeque<(u64, Instant, Value)>>,
+    /// Set once the relay acks anything. Older relays never ack, so nothing is
+    /// resent to them (they would rebroadcast duplicates).
+    relay_acks: AtomicBool,
+}
+
+impl EventOutbox {
+    fn new() -> Self {
+        Self {
+            stream_id: uuid::Uuid::new_v4().to_string(),
+            next_seq: AtomicU64::new(1),
+            unacked: Mutex::new(VecDeque::with_capacity(EVENT_OUTBOX_CAPACITY)),
+            relay_acks: AtomicBool::new(false),
+        }
+    }
+
+    /// Stamp `event` with the next sequence number and hold it until acked.
+    async fn push(&self, mut event: Value) -> Value {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        event["event_seq"] = json!(seq);
+        let mut unacked = self.unacked.lock().await;
+        if unacked.len() >= EVENT_OUTBOX_CAPACITY {
+            unacked.pop_front();
+        }
+        unacked.push_back((seq, Instant::now(), event.clone()));
+        event
+    }
+
+    /// Events not yet acked.
+    async fn len(&self) -> usize {
+        self.unacked.lock().await.len()
+    }
+
+    /// Relay confirmed delivery of `seq`.
+    async fn ack(&self, seq: u64) {
+        self.relay_acks.store(true, Ordering::Relaxed);
+        self.unacked.lock().await.retain(|(s, _, _)| *s != seq);
+    }
+
+    /// Unacked events last sent at least `older_than` ago, marked as re-sent now.
+    async fn due(&self, older_than: Duration) -> Vec<Value> {
+        if !self.relay_acks.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let mut unacked = self.unacked.lock().await;
+        unacked
+            .iter_mut()
+            .filter(|(_, sent, _)| sent.elapsed() >= older_than)
+            .map(|(_, sent, event)| {
+                *sent = Instant::now();
+                event.clone()
+            })
+            .collect()
+    }
+}
+
+/// Requests the device sends to its relay and waits on, e.g. `tunnel.resolve`
+/// for `GET /api/resolve?via=relay`. Held in [`AppState`] so route handlers
+/// can reach whichever relay connection is up; the reply is the relay's
+/// `<type>.result` with the same `request_id`.
+pub struct RelayRequests {
+    /// Request lane of each connected relay, in connection order.
+    links: Mutex<
+        Vec<(
+            String,
+            mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
+        )>,
+    >,
+    pending: Mutex<HashMap<String, oneshot::Sender<Value>>>,
+}
+
+impl Default for RelayRequests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayRequests {
+    pub fn new() -> Self {
+        Self {
+            links: Mutex::new(Vec::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn attach(
+        &self,
+        relay_url: &str,
+        tx: mpsc::Sender<tokio_tungstenite::tungstenite::Message>,
+    ) {
+        let mut links = self.links.lock().await;
+        links.retain(|(url, _)| url != relay_url);
+        links.push((relay_url.to_string(), tx));
+    }
+
+    async fn detach(&self, relay_url: &str) {
+        self.links.lock().await.retain(|(url, _)| url != relay_url);
+    }
+
+    /// Deliver a relay reply; `false` if nothing is waiting for it.
+    async fn complete(&self, reply: Value) -> bool {
+        let Some(request_id) = reply["request_id"].as_str() else {
+            return false;
+        };
+        let Some(tx) = self.pending.lock().await.remove(request_id) else {
+            return false;
+        };
+        let _ = tx.send(reply);
+        true
+    }
+
+    /// Send `msg` (which gets a fresh `request_id`) to the first connected
+    /// relay and wait up to `timeout` for its reply.
+    ///
+    /// # Errors
+    ///
+    /// No relay is connected, the send failed, or no reply came in time.
+    pub async fn request(&self, msg: Value, timeout: Duration) -> Result<Value, String> {
+        self.request_via(msg, timeout).await.map(|(_, reply)| reply)
+    }
+
+    /// [`Self::request`], also returning the URL of the relay that answered.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::request`].
+    pub async fn request_via(
+        &self,
+        mut msg: Value,
+        timeout: Duration,
+    ) -> Result<(String, Value), String> {
+        let Some((relay_url, tx)) = self.links.lock().await.first().cloned() else {
+            return Err("no relay connected".to_string());
+        };
+        let request_id = uuid::Uuid::new_v4().to_string();
+        msg["request_id"] = json!(request_id);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .await
+            .insert(request_id.clone(), reply_tx);
+        let text = tokio_tungstenite::tungstenite::Message::Text(msg.to_string().into());
+        if tx.send(text).await.is_err() {
+            self.pending.lock().await.remove(&request_id);
+            return Err(format!("relay {relay_url} disconnected"));
+        }
+        if let Ok(Ok(reply)) = tokio::time::timeout(timeout, reply_rx).await {
+            return Ok((relay_url, reply));
+        }
+        self.pending.lock().await.remove(&request_id);
+        Err(format!(
+            "no reply from relay {relay_url} within {}s",
+            timeout.as_secs()
+        ))
+    }
 }
 
 /// Spawn the tunnel client task. Returns a `JoinHandle` that runs until cancelled.
+///
+/// One connection loop runs per relay group (see
+/// [`TunnelConfig::relay_groups`]). With a single relay this is the classic
+/// client; with `failover_urls` the loop moves between the primary and its
+/// standbys; with `extra_urls` the device stays registered with every group
+/// at once. A panic in any loop propagates so the caller's supervisor
+/// restarts the whole set; a normal return of one loop (permanent error on
+/// every relay in it) only stops that group.
 pub fn spawn(state: AppState, tunnel_config: TunnelConfig) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(tunnel_client_loop(state, tunnel_config))
-}
-
-/// Main loop: connect, handle messages, reconnect on failure.
-async fn tunnel_client_loop(state: AppState, config: TunnelConfig) {
-    // Flap detection: track last N connection durations. If recent connections
-    // are all short-lived, extend backoff to avoid hammering the relay.
-    const FLAP_WINDOW: usize = 10;
-    const FLAP_THRESHOLD_SECS: u64 = 30;
-    const FLAP_CHECK_COUNT: usize = 3;
-
-    let relay_url = config
-        .url
-        .as_deref()
-        .expect("tunnel.url must be set for client mode");
-    let mut delay = Duration::from_secs(config.reconnect_delay_secs);
-    let max_delay = Duration::from_secs(config.reconnect_max_delay_secs);
+    tokio::spawn(async move {
+        let groups = tunnel_config.relay_groups();
+        let dedup = (groups.len() > 1).then(|| Arc::new(RequestDedup::new()));
+        let mut loops = tokio::task::JoinSet::new();
+        for urls in groups {
+            loops.spawn(tunnel_client_loop(
+                state.clone(),
+                tunnel_config.clone(),
+                urls,
+                dedup.clone(),
+            ));
+        }
+        while let Some(result) = loops.join_next().await {
+            if let Err(e) = result {
+                if e.is_panic() {
+                    std::panic::resume_unwind(e.into_panic());
+                }
+            }
+        }
+    })
+}
+
+/// Main loop for one relay group: pick a relay, connect, handle messages,
+/// reconnect or fail over on failure.
+#[allow(clippy::too_many_lines)]
+async fn tunnel_client_loop(
+    state: AppState,
+    config: TunnelConfig,
+    urls: Vec<String>,
+    dedup: Option<Arc<RequestDedup>>,
+) {
+    let mut relays = Failover::new(
+        urls,
+        Duration::from_secs(config.reconnect_delay_secs),
+        Duration::from_secs(config.reconnect_max_delay_secs),
+        config.failover_after_attempts,
+    );
     let mut reconnects: u64 = 0;
-    let mut connection_durations: VecDeque<u64> = VecDeque::with_capacity(FLAP_WINDOW);
+    let outbox = Arc::new(EventOutbox::new());
+    let mut current = 0;
+    let mut link_generation = state.tunnel_stats.link_generation.load(Ordering::Relaxed);
 
     loop {
+        let generation = state.tunnel_stats.link_generation.load(Ordering::Relaxed);
+        if generation != link_generation {
+            link_generation = generation;
+            info!("Tunnel: data link changed, retrying without backoff");
+            state
+                .tunnel_stats
+                .push_event(
+                    TunnelEventType::ReconnectAttempt,
+                    "data link changed".into(),
+                )
+                .await;
+            relays.reset(Instant::now());
+        }
+        let Some((index, wait)) = relays.next(Instant::now()) else {
+            error!("Tunnel: every relay rejected this device — stopping tunnel client");
+            return;
+        };
+        if index != current {
+            if relays.is_down(current) {
+                warn!(
+                    "Tunnel: relay {} is down, failing over to {}",
+                    relays.url(current),
+                    relays.url(index)
+                );
+            } else {
+                info!("Tunnel: switching to relay {}", relays.url(index));
+            }
+            state
+                .tunnel_stats
+                .push_event(
+                    TunnelEventType::ReconnectAttempt,
+                    format!("failover to {}", relays.url(index)),
+                )
+                .await;
+            current = index;
+        }
+        tokio::select! {
+            () = tokio::time::sleep(wait) => {}
+            () = state.tunnel_stats.reconnect_now.notified() => {
+                info!("Tunnel: reconnect requested, skipping backoff");
+            }
+        }
+
+        let relay_url = relays.url(index).to_string();
+        let relay_url = relay_url.as_str();
         info!("Tunnel: connecting to relay at {relay_url}");
         state
             .tunnel_stats
@@ -134,121 +525,215 @@ async fn tunnel_client_loop(state: AppState, config: TunnelConfig) {
                 format!("attempt #{reconnects}"),
             )
             .await;
-        let mut escalate_backoff = false;
         let connect_start = Instant::now();
         state
             .tunnel_stats
             .reconnecting
             .store(true, Ordering::Relaxed);
-        let result = connect_and_run(&state, &config, relay_url).await;
+        // On a standby, keep checking whether a preferred relay is back and
+        // leave this one for it as soon as it is.
+        let failback = CancellationToken::new();
+        let run = connect_and_run(
+            &state,
+            &config,
+            relay_url,
+            dedup.as_ref(),
+            &outbox,
+            &failback,
+        );
+        let preferred = relays.preferred(index);
+        let result = if preferred.is_empty() {
+            run.await
+        } else {
+            tokio::pin!(run);
+            tokio::select! {
+                result = &mut run => result,
+                back = failback_probe(&state, &config, &preferred, &failback) => {
+                    relays.recovered(back, Instant::now());
+                    run.await
+                }
+            }
+        };
         state
             .tunnel_stats
             .reconnecting
             .store(false, Ordering::Relaxed);
-        match result {
+        if let Err(ref e) = result {
+            #[allow(clippy::cast_possible_truncation)]
+            state
+                .tunnel_stats
+                .record_selftest(TunnelSelftest {
+                    timestamp: now_epoch(),
+                    ok: false,
+                    dns_tcp_ms: None,
+                    tls_ws_ms: None,
+                    register_ms: None,
+                    total_ms: connect_start.elapsed().as_millis() as u64,
+                    error: Some(e.to_string()),
+                })
+                .await;
+        }
+        let duration_secs = connect_start.elapsed().as_secs();
+        let mut escalate_backoff = false;
+        let (mut wait, flapping) = match result {
             Ok(DisconnectReason::RelayShutdown) => {
                 info!("Tunnel: relay shutting down, reconnecting immediately...");
                 state
                     .tunnel_stats
                     .push_event(TunnelEventType::Disconnected, "relay shutdown".into())
                     .await;
-                delay = Duration::ZERO;
+                (Duration::ZERO, relays.connected(index, duration_secs))
+            }
+            Ok(DisconnectReason::Requested) => {
+                info!("Tunnel: reconnect requested, reconnecting immediately...");
+                state
+                    .tunnel_stats
+                    .push_event(TunnelEventType::Disconnected, "reconnect requested".into())
+                    .await;
+                (Duration::ZERO, relays.connected(index, duration_secs))
             }
             Ok(
                 reason @ (DisconnectReason::WsClose
                 | DisconnectReason::PongTimeout
                 | DisconnectReason::WriterExit
-                | DisconnectReason::ReadError),
+                | DisconnectReason::ReadError
+                | DisconnectReason::Failback),
             ) => {
                 info!("Tunnel: disconnected (reason: {reason}), reconnecting...");
                 state
                     .tunnel_stats
                     .push_event(TunnelEventType::Disconnected, reason.to_string())
                     .await;
-                delay = Duration::ZERO;
+                (Duration::ZERO, relays.connected(index, duration_secs))
             }
             Err(ConnectError::Permanent(msg)) => {
-                error!("Tunnel: permanent error: {msg} — stopping tunnel client");
+                error!("Tunnel: permanent error from {relay_url}: {msg} — not retrying this relay");
                 state
                     .tunnel_stats
                     .push_event(TunnelEventType::Disconnected, format!("permanent: {msg}"))
                     .await;
                 state
                     .tunnel_stats
-                    .connected
-                    .store(false, std::sync::atomic::Ordering::Relaxed);
-                return;
+                    .set_relay_connected(relay_url, false)
+                    .await;
+                observe_tunnel_health(&state, relay_url).await;
+                relays.disable(index);
+                continue;
             }
             Err(ConnectError::Transient(e)) => {
                 let msg = e.to_string();
+                state
+                    .tunnel_stats
+                    .connect_failures
+                    .fetch_add(1, Ordering::Relaxed);
                 state
                     .tunnel_stats
                     .push_event(TunnelEventType::Disconnected, msg.clone())
                     .await;
+                let flapping = relays.failed(index, duration_secs);
                 if msg.contains("bind_address") && msg.contains("not available")
                     || msg.contains("Address not available")
                     || msg.contains("os error 99")
                 {
                     // Interface is down (EADDRNOTAVAIL) — use fixed 5s retry, no escalation.
                     warn!("Tunnel: bind address unavailable ({msg}), retrying in 5s");
-                    delay = Duration::from_secs(5);
+                    (Duration::from_secs(5), flapping)
                 } else {
+                    let wait = relays.backoff(index);
                     warn!(
                         "Tunnel: connection error: {msg}, reconnecting in {}s",
-                        delay.as_secs()
+                        wait.as_secs()
                     );
                     escalate_backoff = true;
+                    (wait, flapping)
                 }
             }
-        }
+        };
         reconnects += 1;
         state
             .tunnel_stats
             .reconnects
-            .store(reconnects, std::sync::atomic::Ordering::Relaxed);
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         state
             .tunnel_stats
-            .connected
-            .store(false, std::sync::atomic::Ordering::Relaxed);
+            .set_relay_connected(relay_url, false)
+            .await;
+        observe_tunnel_health(&state, relay_url).await;
         // Reset uptime on disconnect
         state
             .tunnel_stats
             .current_uptime_ms
             .store(0, Ordering::Relaxed);
 
-        // Track connection duration for flap detection
-        let duration_secs = connect_start.elapsed().as_secs();
-        if connection_durations.len() >= FLAP_WINDOW {
-            connection_durations.pop_front();
+        // Flap detection: if the last few connections to this relay were all
+        // short-lived, extend backoff to avoid hammering it.
+        if flapping {
+            warn!("Tunnel: flap detected on {relay_url}, extending backoff to 60s");
+            wait = Duration::from_secs(60);
+            escalate_backoff = false; // don't double-escalate
         }
-        connection_durations.push_back(duration_secs);
+        relays.retry_after(index, wait, escalate_backoff, Instant::now());
+    }
+}
 
-        // Flap detection: if last N connections all lasted < threshold, extend backoff
-        if connection_durations.len() >= FLAP_CHECK_COUNT {
-            let recent: Vec<&u64> = connection_durations
-                .iter()
-                .rev()
-                .take(FLAP_CHECK_COUNT)
-                .collect();
-            let all_short = recent.iter().all(|&&d| d < FLAP_THRESHOLD_SECS);
-            if all_short {
-                warn!(
-                    "Tunnel: flap detected ({FLAP_CHECK_COUNT} connections lasted <{FLAP_THRESHOLD_SECS}s), extending backoff to 60s"
-                );
-                delay = Duration::from_secs(60);
-                escalate_backoff = false; // don't double-escalate
+/// While connected to a standby, check the relays ahead of it every
+/// `failback_check_secs`. Returns the index of the first that accepts a
+/// WebSocket handshake, after cancelling `failback`.
+async fn failback_probe(
+    state: &AppState,
+    config: &TunnelConfig,
+    preferred: &[(usize, String)],
+    failback: &CancellationToken,
+) -> usize {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.failback_check_secs));
+    interval.tick().await; // consume the immediate first tick
+    loop {
+        interval.tick().await;
+        for (index, url) in preferred {
+            match probe_relay(state, config, url).await {
+                Ok(()) => {
+                    info!("Tunnel: preferred relay {url} is reachable again, failing back");
+                    failback.cancel();
+                    return *index;
+                }
+                Err(e) => tracing::debug!("Tunnel: failback check of {url} failed: {e}"),
             }
         }
-
-        tokio::time::sleep(delay).await;
-        if escalate_backoff {
-            delay = (delay * 2).min(max_delay);
-        } else {
-            delay = Duration::from_secs(config.reconnect_delay_secs);
-        }
     }
 }
 
+/// Open and close a WebSocket to the relay. The relay checks the token
+/// before upgrading, so success means it would take our registration.
+async fn probe_relay(
+    state: &AppState,
+    config: &TunnelConfig,
+    relay_url: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let url = register_url(state, config, relay_url);
+    let tcp_stream = connect_tcp_ipv4_preferred(&url, config.bind_address.as_deref()).await?;
+    let (mut ws_stream, _response) = tokio::time::timeout(
+        Duration::from_secs(15),
+        tokio_tungstenite::client_async_tls(url.as_str(), tcp_stream),
+    )
+    .await
+    .map_err(|_| "TLS/WS handshake timed out (15s)")??;
+    let _ = ws_stream.close(None).await;
+    Ok(())
+}
+
+/// The relay's registration URL with auth query params. A key this relay
+/// issued us (enrollment) replaces the configured one.
+fn register_url(state: &AppState, config: &TunnelConfig, relay_url: &str) -> String {
+    let credentials = super::credentials::Credentials::load(&state.config.server.data_dir);
+    let tunnel_key = credentials
+        .tunnel_key(relay_url)
+        .unwrap_or(&config.tunnel_key);
+    format!(
+        "{}?token={}&serial={}",
+        relay_url, tunnel_key, state.config.device.serial
+    )
+}
+
 /// Reason the tunnel connection ended.
 enum DisconnectReason {
     /// Relay sent `tunnel.relay_shutdown` — intentional, skip backoff.
@@ -261,6 +746,10 @@ enum DisconnectReason {
     WriterExit,
     /// WS read error.
     ReadError,
+    /// A preferred relay came back; this standby connection is dropped for it.
+    Failback,
+    /// [`crate::state::TunnelStats::reconnect_now`] was notified (e.g. an SMS command).
+    Requested,
 }
 
 impl DisconnectReason {
@@ -271,6 +760,8 @@ impl DisconnectReason {
             Self::PongTimeout => "pong_timeout",
             Self::WriterExit => "writer_exit",
             Self::ReadError => "read_error",
+            Self::Failback => "failback",
+            Self::Requested => "requested",
         }
     }
 }
@@ -298,6 +789,12 @@ impl std::fmt::Display for ConnectError {
     }
 }
 
+/// Idle time before the first keepalive probe. macOS calls it `TCP_KEEPALIVE`.
+#[cfg(all(unix, not(target_vendor = "apple")))]
+const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPIDLE;
+#[cfg(target_vendor = "apple")]
+const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPALIVE;
+
 /// Configure TCP keepalive on a connected stream.
 ///
 /// LTE carriers commonly have NAT timeouts of 30-60s. Without keepalive,
@@ -327,7 +824,7 @@ fn set_tcp_keepalive(stream: &TcpStream, idle: u32, interval: u32, count: u32) {
         libc::setsockopt(
             fd,
             libc::IPPROTO_TCP,
-            libc::TCP_KEEPIDLE,
+            TCP_KEEPIDLE,
             ptr::addr_of!(idle).cast(),
             sz,
         );
@@ -345,14 +842,16 @@ fn set_tcp_keepalive(stream: &TcpStream, idle: u32, interval: u32, count: u32) {
             ptr::addr_of!(count).cast(),
             sz,
         );
-        // TCP_USER_TIMEOUT: abort connection if sent data goes unacknowledged
+        // TCP_USER_TIMEOUT (Linux only): abort connection if sent data goes unacknowledged
         // for 15s. On LTE with CGNAT, NAT mappings can silently expire, causing
         // TCP retransmissions to loop for minutes. Without this, send() succeeds
         // into the local buffer but data never reaches the relay, and neither
         // keepalive (requires idle connection) nor application-level timeouts
         // (writer only sees local buffer) can detect it. Worst case detection
         // is heartbeat_interval + 15s.
+        #[cfg(target_os = "linux")]
         let user_timeout = TUNNEL_TCP_USER_TIMEOUT_MS;
+        #[cfg(target_os = "linux")]
         libc::setsockopt(
             fd,
             libc::IPPROTO_TCP,
@@ -363,6 +862,60 @@ fn set_tcp_keepalive(stream: &TcpStream, idle: u32, interval: u32, count: u32) {
     }
 }
 
+/// Force a socket's traffic through interface `iface` (`SO_BINDTODEVICE`).
+#[cfg(target_os = "linux")]
+#[allow(clippy::cast_possible_truncation)]
+fn bind_to_device(fd: RawFd, iface: &str, _ipv4: bool) -> std::io::Result<()> {
+    let c_iface = std::ffi::CString::new(iface)
+        .map_err(|_| std::io::Error::other("invalid interface name"))?;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            c_iface.as_ptr().cast(),
+            c_iface.as_bytes_with_nul().len() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Force a socket's traffic through interface `iface` (`IP_BOUND_IF`, which
+/// takes the interface index).
+#[cfg(target_vendor = "apple")]
+#[allow(clippy::cast_possible_truncation)]
+fn bind_to_device(fd: RawFd, iface: &str, ipv4: bool) -> std::io::Result<()> {
+    let c_iface = std::ffi::CString::new(iface)
+        .map_err(|_| std::io::Error::other("invalid interface name"))?;
+    let index = unsafe { libc::if_nametoindex(c_iface.as_ptr()) };
+    if index == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (level, option) = if ipv4 {
+        (libc::IPPROTO_IP, libc::IP_BOUND_IF)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_BOUND_IF)
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            option,
+            std::ptr::addr_of!(index).cast(),
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
 /// Resolve DNS for a `wss://` URL and connect TCP, preferring IPv4 addresses.
 ///
 /// Many embedded devices (LTE/CGNAT) have broken IPv6 routes that cause ~4 minute
@@ -469,24 +1022,11 @@ async fn connect_tcp_ipv4_preferred(
                 // interface (typically eth/LAN), causing asymmetric routing failures.
                 #[cfg(unix)]
                 if let Some(ref iface) = bind_iface {
-                    let fd = socket.as_raw_fd();
-                    let c_iface = std::ffi::CString::new(iface.as_str())
-                        .map_err(|_| std::io::Error::other("invalid interface name"))?;
-                    let ret = unsafe {
-                        libc::setsockopt(
-                            fd,
-                            libc::SOL_SOCKET,
-                            libc::SO_BINDTODEVICE,
-                            c_iface.as_ptr().cast(),
-                            c_iface.as_bytes_with_nul().len() as libc::socklen_t,
-                        )
-                    };
-                    if ret != 0 {
-                        let err = std::io::Error::last_os_error();
-                        warn!("Tunnel: SO_BINDTODEVICE({iface}) failed: {err}");
+                    if let Err(err) = bind_to_device(socket.as_raw_fd(), iface, addr.is_ipv4()) {
+                        warn!("Tunnel: binding socket to {iface} failed: {err}");
                         return Err(err);
                     }
-                    info!("Tunnel: SO_BINDTODEVICE({iface}) set on socket");
+                    info!("Tunnel: socket bound to interface {iface}");
                 }
 
                 socket.bind(SocketAddr::new(ba, 0))?;
@@ -529,12 +1069,11 @@ async fn connect_and_run(
     state: &AppState,
     config: &TunnelConfig,
     relay_url: &str,
+    dedup: Option<&Arc<RequestDedup>>,
+    outbox: &Arc<EventOutbox>,
+    failback: &CancellationToken,
 ) -> Result<DisconnectReason, ConnectError> {
-    // Build the URL with auth query params
-    let url = format!(
-        "{}?token={}&serial={}",
-        relay_url, config.tunnel_key, state.config.device.serial
-    );
+    let url = register_url(state, config, relay_url);
 
     let connect_start = Instant::now();
 
@@ -543,6 +1082,16 @@ async fn connect_and_run(
         .await
         .map_err(ConnectError::Transient)?;
     let tcp_elapsed = connect_start.elapsed();
+    // Listed in `GET /api/connections`, where it can also be dropped.
+    let registration = state.connections.register(
+        crate::connections::ConnectionKind::Tunnel,
+        "ws",
+        relay_url.to_string(),
+        tcp_stream
+            .local_addr()
+            .map_or_else(|_| "unknown".to_string(), |a| a.to_string()),
+    );
+    let tcp_stream = crate::connections::Tracked::new(tcp_stream, registration);
 
     // TLS + WebSocket handshake with timeout (can hang on riscv64/slow networks)
     let tls_start = Instant::now();
@@ -559,12 +1108,24 @@ async fn connect_and_run(
 
     // Send registration directly on the raw sink (before spawning writer task)
     let reg_start = Instant::now();
+    // Subscribed first so a key rotation racing registration is still pushed.
+    let mut key_changes = state.api_keys.subscribe();
     {
-        let reg = json!({
-            "type": "tunnel.register",
-            "serial": state.config.device.serial,
-            "api_key": state.config.auth.api_key,
-        });
+        let peer_relays: Vec<String> = config
+            .relay_groups()
+            .into_iter()
+            .filter(|group| !group.iter().any(|u| u == relay_url))
+            .flatten()
+            .collect();
+        let mut reg = api_keys_message(state, "tunnel.register");
+        reg["serial"] = json!(state.config.device.serial);
+        reg["peer_relays"] = json!(peer_relays);
+        reg["event_stream"] = json!(outbox.stream_id);
+        reg["tags"] = json!(state.config.device.tags);
+        reg["identity"] = json!(crate::identity::current().await);
+        if config.encoding == TunnelEncoding::Msgpack {
+            reg["encodings"] = json!([super::codec::MSGPACK]);
+        }
         raw_ws_sink
             .send(tokio_tungstenite::tungstenite::Message::Text(
                 serde_json::to_string(&reg)
@@ -576,6 +1137,7 @@ async fn connect_and_run(
     }
 
     // Wait for registration ack with timeout
+    let msgpack;
     match tokio::time::timeout(Duration::from_secs(10), ws_stream.next()).await {
         Ok(Some(Ok(tokio_tungstenite::tungstenite::Message::Text(text)))) => {
             match serde_json::from_str::<Value>(&text) {
@@ -583,6 +1145,11 @@ async fn connect_and_run(
                     let msg_type = msg["type"].as_str().unwrap_or("");
                     match msg_type {
                         "tunnel.register.ack" => {
+                            msgpack = config.encoding == TunnelEncoding::Msgpack
+                                && msg["encoding"] == super::codec::MSGPACK;
+                            if msgpack {
+                                info!("Tunnel: relay accepted MessagePack encoding");
+                            }
                             let reg_elapsed = reg_start.elapsed();
                             let total = connect_start.elapsed();
                             info!(
@@ -594,8 +1161,14 @@ async fn connect_and_run(
                             );
                             state
                                 .tunnel_stats
-                                .connected
-                                .store(true, std::sync::atomic::Ordering::Relaxed);
+                                .set_relay_connected(relay_url, true)
+                                .await;
+                            state
+                                .tunnel_stats
+                                .connect_failures
+                                .store(0, Ordering::Relaxed);
+                            observe_tunnel_health(state, relay_url).await;
+                            state.startup.tunnel_connected(relay_url);
                             state
                                 .tunnel_stats
                                 .push_event(
@@ -603,6 +1176,39 @@ async fn connect_and_run(
                                     format!("latency {}ms", total.as_millis()),
                                 )
                                 .await;
+                            #[allow(clippy::cast_possible_truncation)]
+                            state
+                                .tunnel_stats
+                                .record_selftest(TunnelSelftest {
+                                    timestamp: now_epoch(),
+                                    ok: true,
+                                    dns_tcp_ms: Some(tcp_elapsed.as_millis() as u64),
+                                    tls_ws_ms: Some(tls_elapsed.as_millis() as u64),
+                                    register_ms: Some(reg_elapsed.as_millis() as u64),
+                                    total_ms: total.as_millis() as u64,
+                                    error: None,
+                                })
+                                .await;
+                            // Flush events recorded while we were offline before
+                            // anything live goes out, so the relay sees them in order.
+                            if let Some(ref spool) = state.offline_spool {
+                                if let Some(replay) = spool.replay().await {
+                                    info!(
+                                        count = replay.message["count"].as_u64().unwrap_or(0),
+                                        dropped = replay.message["dropped"].as_u64().unwrap_or(0),
+                                        "Tunnel: replaying spooled offline events"
+                                    );
+                                    let text = serde_json::to_string(&replay.message)
+                                        .map_err(|e| ConnectError::Transient(e.into()))?;
+                                    raw_ws_sink
+                                        .send(tokio_tungstenite::tungstenite::Message::Text(
+                                            text.into(),
+                                        ))
+                                        .await
+                                        .map_err(|e| ConnectError::Transient(e.into()))?;
+                                    spool.commit(&replay).await;
+                                }
+                            }
                         }
                         "error" => {
                             let code = msg["code"].as_str().unwrap_or("");
@@ -665,7 +1271,13 @@ async fn connect_and_run(
         priority_tx: priority_tx.clone(),
         request_tx: request_tx.clone(),
         stream_tx: stream_tx.clone(),
+        dedup: dedup.cloned(),
+        outbox: outbox.clone(),
     };
+    state
+        .relay_requests
+        .attach(relay_url, request_tx.clone())
+        .await;
     let (writer_exit_tx, mut writer_exit_rx) = oneshot::channel::<()>();
     let writer_stats = state.tunnel_stats.clone();
     let writer_task = tokio::spawn(async move {
@@ -677,6 +1289,7 @@ async fn connect_and_run(
                 Some(msg) = stream_rx.recv() => msg,
                 else => break,
             };
+            let msg = if msgpack { encode_message(msg) } else { msg };
             match tokio::time::timeout(
                 Duration::from_secs(TUNNEL_WRITER_SEND_TIMEOUT_SECS),
                 raw_ws_sink.send(msg),
@@ -717,6 +1330,12 @@ async fn connect_and_run(
     // Track subscriber tasks for session output forwarding
     let subscriber_tasks: Arc<Mutex<HashMap<String, tokio::task::JoinHandle<()>>>> =
         Arc::new(Mutex::new(HashMap::new()));
+    // File watches started by relay clients (see `files.detach`)
+    let file_watches = Arc::new(Mutex::new(crate::file_watch::FileWatches::default()));
+    // Log tails started by relay clients, stopped the same way
+    let log_tails = Arc::new(Mutex::new(crate::log_tail::LogTails::default()));
+    // TCP streams forwarded for relay clients (see `tunnel::forward`)
+    let forwards = Arc::new(super::forward::Forwards::default());
     let handler_permits = Arc::new(Semaphore::new(32));
 
     // Heartbeat failure notification channel
@@ -743,6 +1362,9 @@ async fn connect_and_run(
     let heartbeat_ws_sink = ws_sink.clone();
     let heartbeat_task = tokio::spawn(async move {
         let mut interval = tokio::time::interval(heartbeat_interval);
+        // Pong timestamp observed at the previous tick; None until the first
+        // ping has gone out so the initial tick isn't scored as a loss.
+        let mut prev_tick_pong: Option<u64> = None;
         loop {
             interval.tick().await;
 
@@ -755,6 +1377,15 @@ async fn connect_and_run(
 
             // Pong watchdog: check if relay is actually responding
             let last = heartbeat_last_pong.load(Ordering::Relaxed);
+
+            // Connection quality: score pong loss per interval, then refresh
+            // the rolling score that adaptive subsystems read.
+            if let Some(prev) = prev_tick_pong {
+                heartbeat_stats.record_pong_outcome(last > prev).await;
+            }
+            prev_tick_pong = Some(last);
+            let score = heartbeat_stats.update_quality_score().await;
+            tracing::debug!(score, "Tunnel: connection quality updated");
             // Update pong age for health endpoint
             #[allow(clippy::cast_possible_truncation)]
             let pong_age = heartbeat_epoch.elapsed().as_millis() as u64;
@@ -825,6 +1456,27 @@ async fn connect_and_run(
 
     let mut disconnect_reason = DisconnectReason::WsClose;
 
+    // Resend lifecycle events that were in flight when the previous connection
+    // dropped. The relay drops any it already delivered.
+    let resend = outbox.due(Duration::ZERO).await;
+    if !resend.is_empty() {
+        info!(
+            count = resend.len(),
+            "Tunnel: resending unacked lifecycle events"
+        );
+        for event in resend {
+            let text = serde_json::to_string(&event).unwrap_or_default();
+            if ws_sink
+                .request_tx
+                .send(tokio_tungstenite::tungstenite::Message::Text(text.into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
     // Do not auto-subscribe running sessions on reconnect.
     //
     // The relay/browser side re-attaches sessions explicitly when a client is
@@ -844,6 +1496,10 @@ async fn connect_and_run(
                         break;
                     }
                 };
+                let Some(msg) = decode_message(msg) else {
+                    warn!("Tunnel: invalid MessagePack from relay");
+                    continue;
+                };
                 match msg {
                     tokio_tungstenite::tungstenite::Message::Text(text) => {
                         let parsed: Value = match serde_json::from_str(&text) {
@@ -860,6 +1516,14 @@ async fn connect_and_run(
                         #[allow(clippy::cast_possible_truncation)]
                         last_pong_ms.store(connection_epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
                         let msg_type = parsed["type"].as_str().unwrap_or("");
+                        // `running` goes to the task handling the message, or is
+                        // dropped at the end of this iteration for inline ones.
+                        let (seen, running) = match (dedup, dedup_key(&parsed)) {
+                            (Some(d), Some(key)) => {
+                                d.check(&key, parsed["request_id"].as_str().unwrap_or(""))
+                            }
+                            _ => (Seen::First, None),
+                        };
                         match msg_type {
                             "tunnel.relay_shutdown" => {
                                 info!("Tunnel: relay sent shutdown notification");
@@ -882,6 +1546,35 @@ async fn connect_and_run(
                                     tracing::debug!("Tunnel: pong received (epoch +{}ms)", ms);
                                 }
                             }
+                            // Latency probe: answered inline so the device-side
+                            // time excludes handler queueing; the reply rides the
+                            // request lane so writer backlog shows up as transit.
+                            "latency.probe" => {
+                                let device_rx_ms = crate::sessions::journal::now_ms();
+                                let reply = json!({
+                                    "type": "latency.probe.result",
+                                    "request_id": parsed["request_id"],
+                                    "client_ts": parsed["client_ts"],
+                                    "relay": parsed["relay"],
+                                    "device_rx_ms": device_rx_ms,
+                                    "device_tx_ms": crate::sessions::journal::now_ms(),
+                                    "device_queues": {
+                                        "request": ws_sink.request_tx.max_capacity() - ws_sink.request_tx.capacity(),
+                                        "stream": ws_sink.stream_tx.max_capacity() - ws_sink.stream_tx.capacity(),
+                                    },
+                                });
+                                let text = serde_json::to_string(&reply).unwrap_or_default();
+                                if let Err(e) = ws_sink.request_tx.try_send(
+                                    tokio_tungstenite::tungstenite::Message::Text(text.into()),
+                                ) {
+                                    warn!("Tunnel: latency probe reply dropped (channel: {e})");
+                                }
+                            }
+                            "tunnel.events.ack" => {
+                                if let Some(seq) = parsed["seq"].as_u64() {
+                                    outbox.ack(seq).await;
+                                }
+                            }
                             "tunnel.register.ack" | "ping" => {}
                             // Relay-initiated ping — respond with try_send to never
                             // block the read loop (if channel full, write path is stuck anyway)
@@ -893,6 +1586,66 @@ async fn connect_and_run(
                                     warn!("Tunnel: pong dropped (channel: {e}), write path likely stuck");
                                 }
                             }
+                            // Reply to a request this device sent (see RelayRequests).
+                            "tunnel.resolve.result"
+                            | "tunnel.expose.result"
+                            | "tunnel.unexpose.result" => {
+                                if !state.relay_requests.complete(parsed).await {
+                                    tracing::debug!("Tunnel: relay reply with no waiting request");
+                                }
+                            }
+                            // Port forwarding: opened in a task (the connect can
+                            // take a while), closed inline so it can't pass data.
+                            "tunnel.forward.open" => {
+                                let st = state.clone();
+                                let tx = ws_sink.clone();
+                                let fw = forwards.clone();
+                                tokio::spawn(async move {
+                                    let _running = running;
+                                    handle_forward_open(&st, &tx, &fw, &parsed).await;
+                                });
+                            }
+                            "tunnel.sftp.open" => {
+                                let st = state.clone();
+                                let tx = ws_sink.clone();
+                                let fw = forwards.clone();
+                                tokio::spawn(async move {
+                                    let _running = running;
+                                    handle_sftp_open(&st, &tx, &fw, &parsed).await;
+                                });
+                            }
+                            "tunnel.dav.open" => {
+                                handle_dav_open(state, &ws_sink, &forwards, &parsed).await;
+                            }
+                            "tunnel.forward.close" => {
+                                if let Some(stream_id) = parsed["stream_id"].as_str() {
+                                    forwards.close(stream_id).await;
+                                }
+                            }
+                            "tunnel.forward.credit" => {
+                                if let (Some(stream_id), Some(bytes)) =
+                                    (parsed["stream_id"].as_str(), parsed["bytes"].as_u64())
+                                {
+                                    forwards.grant(stream_id, bytes).await;
+                                }
+                            }
+                            // Keys are per relay, so this needs to know which one sent it.
+                            "tunnel.rotate_key" => {
+                                let primary = config.url.as_deref() == Some(relay_url);
+                                handle_rotate_key(state, &ws_sink, relay_url, primary, &parsed).await;
+                            }
+                            // Active-active: the same request arriving over a second
+                            // relay connection gets the first copy's answer instead
+                            // of running twice.
+                            _ if !matches!(seen, Seen::First) => {
+                                tracing::debug!(
+                                    msg_type,
+                                    request_id = parsed["request_id"].as_str().unwrap_or(""),
+                                    idempotency_key = parsed["idempotency_key"].as_str().unwrap_or(""),
+                                    "Tunnel: duplicate request from another relay"
+                                );
+                                answer_duplicate(&ws_sink, &parsed, seen).await;
+                            }
                             // Everything else: spawn as task to keep the read loop responsive.
                             // This prevents slow handlers (exec, file I/O) from blocking
                             // pong reads, which would trigger the pong watchdog.
@@ -900,20 +1653,28 @@ async fn connect_and_run(
                                 let st = state.clone();
                                 let tx = ws_sink.clone();
                                 let tasks = subscriber_tasks.clone();
+                                let watches = file_watches.clone();
+                                let tails = log_tails.clone();
                                 let permits = handler_permits.clone();
-                                tokio::spawn(async move {
-                                    let _permit = permits.acquire_owned().await.ok();
-                                    if let Err(e) = AssertUnwindSafe(
-                                        handle_relay_message(&st, &tx, &tasks, parsed)
-                                    ).catch_unwind().await {
-                                        error!("Panic in tunnel message handler: {e:?}");
-                                    }
-                                });
+                                tokio::spawn(handle_relay_task(running, permits, async move {
+                                    handle_relay_message(&st, &tx, &tasks, &watches, &tails, parsed).await;
+                                }));
                             }
                         }
                     }
                     tokio_tungstenite::tungstenite::Message::Binary(data) => {
                         if let Some((header, payload)) = decode_binary_frame(&data) {
+                            // Forwarded bytes are handled inline to keep their order.
+                            if header["type"] == super::forward::FORWARD_DATA {
+                                let stream_id = header["stream_id"].as_str().unwrap_or("");
+                                if !forwards.data(stream_id, payload.to_vec()).await {
+                                    let close = super::forward::close_message(stream_id);
+                                    let _ = ws_sink.request_tx.try_send(
+                                        tokio_tungstenite::tungstenite::Message::Text(close.to_string().into()),
+                                    );
+                                }
+                                continue;
+                            }
                             let st = state.clone();
                             let tx = ws_sink.clone();
                             let permits = handler_permits.clone();
@@ -932,9 +1693,21 @@ async fn connect_and_run(
                     _ => {}
                 }
             }
+            // Key rotated (`POST /api/auth/rotate`): the relay checks client
+            // requests against it.
+            Ok(()) = key_changes.changed() => {
+                let msg = api_keys_message(state, "tunnel.api_key");
+                if let Err(e) = ws_sink.request_tx.try_send(tokio_tungstenite::tungstenite::Message::Text(
+                    msg.to_string().into(),
+                )) {
+                    warn!("Tunnel: API key update dropped (channel: {e})");
+                }
+            }
             broadcast_msg = broadcast_rx.recv() => {
                 if let Ok(event) = broadcast_msg {
-                    // Forward session lifecycle events to relay
+                    // Forward session lifecycle events to relay. Sequenced so the
+                    // relay can ack; unacked events are resent (see EventOutbox).
+                    let event = outbox.push(event).await;
                     let text = serde_json::to_string(&event)
                         .unwrap_or_else(|_| r#"{"type":"error","message":"serialize failed"}"#.to_string());
                     if let Err(e) = ws_sink.request_tx.try_send(tokio_tungstenite::tungstenite::Message::Text(
@@ -946,6 +1719,14 @@ async fn connect_and_run(
             }
             _ = reap_interval.tick() => {
                 subscriber_tasks.lock().await.retain(|_, h| !h.is_finished());
+                for event in outbox.due(EVENT_RESEND_AFTER).await {
+                    let text = serde_json::to_string(&event).unwrap_or_default();
+                    if ws_sink.request_tx.try_send(tokio_tungstenite::tungstenite::Message::Text(
+                        text.into(),
+                    )).is_err() {
+                        break;
+                    }
+                }
             }
             _ = heartbeat_cancel_rx.changed() => {
                 warn!("Tunnel: heartbeat failure detected, disconnecting");
@@ -957,10 +1738,20 @@ async fn connect_and_run(
                 disconnect_reason = DisconnectReason::WriterExit;
                 break;
             }
-        }
+            () = failback.cancelled() => {
+                disconnect_reason = DisconnectReason::Failback;
+                break;
+            }
+            () = state.tunnel_stats.reconnect_now.notified() => {
+                info!("Tunnel: reconnect requested, disconnecting");
+                disconnect_reason = DisconnectReason::Requested;
+                break;
+            }
+        }
     }
 
     // Cleanup
+    state.relay_requests.detach(relay_url).await;
     heartbeat_task.abort();
     writer_task.abort();
     let attached_sessions: Vec<String> = {
@@ -974,6 +1765,10 @@ async fn connect_and_run(
     if !attached_sessions.is_empty() {
         state.session_manager.detach_all(&attached_sessions).await;
     }
+    file_watches.lock().await.clear();
+    log_tails.lock().await.clear();
+    forwards.close_all().await;
+    release_controls(state, |holder| holder.starts_with("relay:")).await;
 
     // Pause all active transfers on tunnel disconnect
     state.transfer_manager.pause_all().await;
@@ -1010,15 +1805,31 @@ async fn handle_relay_message(
     state: &AppState,
     ws_sink: &WsSink,
     subscriber_tasks: &Arc<Mutex<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    file_watches: &Arc<Mutex<crate::file_watch::FileWatches>>,
+    log_tails: &Arc<Mutex<crate::log_tail::LogTails>>,
     msg: Value,
 ) {
     let msg_type = msg["type"].as_str().unwrap_or("");
     let request_id = msg["request_id"].as_str().map(ToString::to_string);
 
+    if let Err(e) = tunnel_ai_guard(state, msg_type, &msg) {
+        send_route_result(
+            ws_sink,
+            &format!("{msg_type}.result"),
+            request_id.as_deref(),
+            Err(e),
+        )
+        .await;
+        return;
+    }
+
     match msg_type {
         "tunnel.exec" => {
             handle_tunnel_exec(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.exec.pending" | "tunnel.exec.confirm" => {
+            handle_tunnel_exec_confirm(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
         "tunnel.exec_batch" => {
             handle_tunnel_exec_batch(state, ws_sink, &msg, request_id.as_deref()).await;
         }
@@ -1028,9 +1839,24 @@ async fn handle_relay_message(
         "tunnel.health" => {
             handle_tunnel_health(state, ws_sink, request_id.as_deref()).await;
         }
+        "tunnel.resolve" => {
+            handle_tunnel_resolve(ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.diag" => {
+            handle_tunnel_diag(state, ws_sink, request_id.as_deref()).await;
+        }
         "tunnel.diagnostics" => {
             handle_tunnel_diagnostics(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.health.history" => {
+            handle_tunnel_health_history(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.support_bundle" => {
+            handle_tunnel_support_bundle(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.support_bundle.status" => {
+            handle_tunnel_support_bundle_status(ws_sink, request_id.as_deref()).await;
+        }
         "tunnel.file.read" => {
             handle_tunnel_file_read(state, ws_sink, &msg, request_id.as_deref()).await;
         }
@@ -1041,7 +1867,7 @@ async fn handle_relay_message(
             handle_tunnel_activity(state, ws_sink, &msg, request_id.as_deref()).await;
         }
         "tunnel.sessions" => {
-            handle_tunnel_sessions(state, ws_sink, request_id.as_deref()).await;
+            handle_tunnel_sessions(state, ws_sink, &msg, request_id.as_deref()).await;
         }
         "tunnel.shells" => {
             handle_tunnel_shells(state, ws_sink, request_id.as_deref()).await;
@@ -1052,12 +1878,55 @@ async fn handle_relay_message(
         "tunnel.session.kill" => {
             handle_tunnel_session_kill(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.session.share" => {
+            handle_tunnel_session_share(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.session.history" => {
+            handle_tunnel_session_history(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.session.journal" => {
+            handle_tunnel_session_journal(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
         "tunnel.session.patch" => {
             handle_tunnel_session_patch(state, ws_sink, &msg, request_id.as_deref()).await;
         }
         "tunnel.file.delete" => {
             handle_tunnel_file_delete(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.file.xattrs" => {
+            handle_tunnel_file_xattrs(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.file.copy" => {
+            handle_tunnel_file_copy(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.ai.status" | "tunnel.ai.disable" | "tunnel.ai.enable" => {
+            handle_tunnel_ai(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.flightrecorder.status" | "tunnel.flightrecorder.dump" => {
+            let st = axum::extract::State(state.clone());
+            let result = if msg_type == "tunnel.flightrecorder.dump" {
+                crate::routes::flight_recorder::dump(st).await
+            } else {
+                crate::routes::flight_recorder::status(st).await
+            };
+            send_route_result(
+                ws_sink,
+                &format!("{msg_type}.result"),
+                request_id.as_deref(),
+                result,
+            )
+            .await;
+        }
+        "tunnel.file.copy.list" => {
+            let result = crate::routes::copy::list_copies().await;
+            send_route_result(
+                ws_sink,
+                "tunnel.file.copy.list.result",
+                request_id.as_deref(),
+                result,
+            )
+            .await;
+        }
         "tunnel.playbooks.list" => {
             handle_tunnel_playbooks_list(state, ws_sink, &msg, request_id.as_deref()).await;
         }
@@ -1067,27 +1936,111 @@ async fn handle_relay_message(
         "tunnel.playbooks.put" => {
             handle_tunnel_playbooks_put(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.playbooks.run" | "tunnel.playbooks.runs" | "tunnel.playbooks.runs.get" => {
+            handle_tunnel_playbook_runs(state, ws_sink, &msg, msg_type, request_id.as_deref())
+                .await;
+        }
         "tunnel.playbooks.delete" => {
             handle_tunnel_playbooks_delete(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        "tunnel.ssh.keys.list" | "tunnel.ssh.keys.add" | "tunnel.ssh.keys.delete" => {
+            handle_tunnel_ssh_keys(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.users.list"
+        | "tunnel.users.lock"
+        | "tunnel.users.unlock"
+        | "tunnel.users.reset_password" => {
+            handle_tunnel_users(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.firewall.get" | "tunnel.firewall.apply" => {
+            handle_tunnel_firewall(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.time.get" | "tunnel.time.set" => {
+            handle_tunnel_time(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.hooks.get" | "tunnel.hooks.reload" => {
+            handle_tunnel_hooks(state, ws_sink, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.twin.get" | "tunnel.twin.put" | "tunnel.twin.reconcile" => {
+            handle_tunnel_twin(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.fetch" => {
+            handle_tunnel_fetch(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.artifacts.push" => {
+            handle_tunnel_artifacts_push(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "tunnel.session.start" => {
+            let result = crate::routes::sessions::start(state, &tunnel_headers(&msg), &msg).await;
+            send_route_result(
+                ws_sink,
+                "tunnel.session.start.result",
+                request_id.as_deref(),
+                result,
+            )
+            .await;
+        }
+        "tunnel.snapshots.create"
+        | "tunnel.snapshots.list"
+        | "tunnel.snapshots.get"
+        | "tunnel.snapshots.delete"
+        | "tunnel.snapshots.diff"
+        | "tunnel.info.diff" => {
+            handle_tunnel_snapshots(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        "tunnel.plugins.list" | "tunnel.plugins.get" => {
+            handle_tunnel_plugins(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
         "tunnel.exec_result" => {
             handle_tunnel_exec_result(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        #[cfg(feature = "comms")]
         "tunnel.gps" => {
             handle_tunnel_gps(state, ws_sink, request_id.as_deref()).await;
         }
+        #[cfg(feature = "comms")]
+        "tunnel.gps.track" => {
+            handle_tunnel_gps_track(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        #[cfg(feature = "comms")]
         "tunnel.lte" => {
             handle_tunnel_lte(state, ws_sink, request_id.as_deref()).await;
         }
+        #[cfg(feature = "comms")]
+        "tunnel.lte.history" => {
+            handle_tunnel_lte_history(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        #[cfg(feature = "comms")]
         "tunnel.lte.bands" => {
             handle_tunnel_lte_bands(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        #[cfg(feature = "comms")]
         "tunnel.lte.scan" => {
             handle_tunnel_lte_scan(state, ws_sink, &msg, request_id.as_deref()).await;
         }
+        #[cfg(feature = "comms")]
         "tunnel.lte.speedtest" => {
             handle_tunnel_lte_speedtest(state, ws_sink, request_id.as_deref()).await;
         }
+        #[cfg(not(feature = "comms"))]
+        "tunnel.gps"
+        | "tunnel.gps.track"
+        | "tunnel.lte"
+        | "tunnel.lte.history"
+        | "tunnel.lte.bands"
+        | "tunnel.lte.scan"
+        | "tunnel.lte.speedtest" => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": format!("{msg_type}.result"),
+                    "request_id": request_id,
+                    "status": 404,
+                    "body": ApiError::new(crate::error::codes::NOT_FOUND, "GPS/LTE not included in this build"),
+                }),
+            )
+            .await;
+        }
         // Infra monitoring tunnel messages
         "tunnel.infra.results" => {
             handle_tunnel_infra_results(state, ws_sink, request_id.as_deref()).await;
@@ -1138,7 +2091,10 @@ async fn handle_relay_message(
             handle_gx_status(state, ws_sink, &msg, request_id.as_deref()).await;
         }
         "gx.list" => {
-            handle_gx_list(state, ws_sink, request_id.as_deref()).await;
+            handle_gx_list(state, ws_sink, &msg, request_id.as_deref()).await;
+        }
+        "gx.throttle" => {
+            handle_gx_throttle(state, ws_sink, &msg, request_id.as_deref()).await;
         }
         // Forwarded session.*, shell.*, and job.* messages from clients via relay.
         // GUARD: Any new WS message prefix (e.g. "foo.*") requires adding it here,
@@ -1148,14 +2104,91 @@ async fn handle_relay_message(
         t if t.starts_with("session.") || t.starts_with("shell.") || t.starts_with("job.") => {
             handle_forwarded_session_message(state, ws_sink, subscriber_tasks, &msg).await;
         }
+        "files.watch" => {
+            handle_tunnel_files_watch(ws_sink, file_watches, &msg).await;
+        }
+        "files.unwatch" => {
+            let reply = crate::file_watch::handle_unwatch(&mut *file_watches.lock().await, &msg);
+            send_response_async(ws_sink, reply).await;
+        }
+        "logs.follow" => {
+            handle_tunnel_logs_follow(ws_sink, log_tails, &msg).await;
+        }
+        "logs.unfollow" => {
+            let reply = crate::log_tail::handle_unfollow(&mut *log_tails.lock().await, &msg);
+            send_response_async(ws_sink, reply).await;
+        }
+        // Sent by the relay when a client disconnects
+        "files.detach" => {
+            if let Some(client_id) = msg["client_id"].as_str() {
+                file_watches.lock().await.unwatch_owner(client_id);
+                log_tails.lock().await.unfollow_owner(client_id);
+            }
+        }
         // Client WS keep-alive ping — ignore
         "ping" => {}
+        t if t.starts_with("plugin.") => {
+            handle_tunnel_plugin_run(state, ws_sink, &msg, msg_type, request_id.as_deref()).await;
+        }
+        t if state.extensions.handler(t).is_some() => {
+            if let Some(reply) = state.extensions.dispatch(state, &msg).await {
+                send_response_async(ws_sink, reply).await;
+            }
+        }
         _ => {
             warn!(msg_type, "Unknown tunnel message type");
         }
     }
 }
 
+/// Handle `files.watch` from a relay client. Events are forwarded on the
+/// request lane; the watch is owned by the client id the relay tagged the
+/// `request_id` with, so `files.detach` can stop it.
+async fn handle_tunnel_files_watch(
+    ws_sink: &WsSink,
+    file_watches: &Arc<Mutex<crate::file_watch::FileWatches>>,
+    msg: &Value,
+) {
+    let owner = msg["request_id"]
+        .as_str()
+        .and_then(|rid| rid.split_once(':'))
+        .map(|(client_id, _)| client_id.to_string());
+    let (tx, mut rx) = mpsc::channel::<Value>(64);
+    let reply = crate::file_watch::handle_watch(&mut *file_watches.lock().await, msg, owner, tx);
+    send_response_async(ws_sink, reply).await;
+    let sink = ws_sink.clone();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if !send_response_async(&sink, event).await {
+                break;
+            }
+        }
+    });
+}
+
+/// Handle `logs.follow` from a relay client, as [`handle_tunnel_files_watch`]
+/// does for watches.
+async fn handle_tunnel_logs_follow(
+    ws_sink: &WsSink,
+    log_tails: &Arc<Mutex<crate::log_tail::LogTails>>,
+    msg: &Value,
+) {
+    let owner = msg["request_id"]
+        .as_str()
+        .and_then(|rid| rid.split_once(':'))
+        .map(|(client_id, _)| client_id.to_string());
+    let (tx, mut rx) = mpsc::channel::<Value>(64);
+    let sink = ws_sink.clone();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if !send_response_async(&sink, event).await {
+                break;
+            }
+        }
+    });
+    crate::log_tail::handle_follow(&mut *log_tails.lock().await, msg, owner, tx).await;
+}
+
 /// Build a `HeaderMap` with `x-sctl-client` from the tunnel message's `_source` field.
 ///
 /// If the relay forwarded a `_source` (e.g. `"mcp"`), use that. Otherwise default
@@ -1169,6 +2202,59 @@ fn tunnel_headers(msg: &Value) -> axum::http::HeaderMap {
     headers
 }
 
+/// Apply the AI kill-switch and exec budget to a mutating tunnel message.
+/// File writes are charged by `routes::files::put_file` itself, and upload
+/// chunks by [`handle_gx_chunk_receive`].
+fn tunnel_ai_guard(
+    state: &AppState,
+    msg_type: &str,
+    msg: &Value,
+) -> Result<(), (axum::http::StatusCode, axum::Json<ApiError>)> {
+    if !crate::ai_guard::is_tunnel_mutation(msg_type) {
+        return Ok(());
+    }
+    let headers = tunnel_headers(msg);
+    match msg_type {
+        "tunnel.exec" => state.ai_guard.charge_execs(&headers, 1),
+        "tunnel.exec_batch" => {
+            // Oversized batches are rejected unrun by the handler.
+            let count = msg["commands"].as_array().map_or(0, Vec::len);
+            let count = if count > state.config.server.max_batch_size {
+                0
+            } else {
+                count
+            };
+            state.ai_guard.charge_execs(&headers, count as u64)
+        }
+        _ => state.ai_guard.check_mutation(&headers),
+    }
+}
+
+/// Deadline from the relay's `deadline_in_ms` (relative, so clock skew between
+/// relay and device doesn't matter). Callers must [`arm`] it.
+///
+/// [`arm`]: crate::deadline::RequestDeadline::arm
+fn tunnel_deadline(msg: &Value) -> crate::deadline::RequestDeadline {
+    msg["deadline_in_ms"]
+        .as_u64()
+        .map(crate::deadline::RequestDeadline::after_ms)
+        .unwrap_or_default()
+}
+
+/// Run a relay message's handler once a handler permit is free, catching a
+/// panic. `running` is released when the handler ends, however it ends.
+async fn handle_relay_task(
+    running: Option<Running>,
+    permits: Arc<Semaphore>,
+    handler: impl std::future::Future<Output = ()>,
+) {
+    let _running = running;
+    let _permit = permits.acquire_owned().await.ok();
+    if let Err(e) = AssertUnwindSafe(handler).catch_unwind().await {
+        error!("Panic in tunnel message handler: {e:?}");
+    }
+}
+
 /// Send a JSON response back through the tunnel WS channel.
 ///
 /// Fast path uses `try_send` to avoid scheduler hops. If the request lane is
@@ -1181,6 +2267,11 @@ async fn send_response_async(ws_sink: &WsSink, msg: Value) -> bool {
     let text = serde_json::to_string(&msg)
         .unwrap_or_else(|_| r#"{"type":"error","message":"serialize failed"}"#.to_string());
     let body_len = text.len();
+    // Kept before sending, so a retry through another relay still gets it
+    // if this relay is gone.
+    if let Some(dedup) = &ws_sink.dedup {
+        dedup.record(&msg, body_len);
+    }
     let msg = tokio_tungstenite::tungstenite::Message::Text(text.into());
     match ws_sink.request_tx.try_send(msg) {
         Ok(()) => true,
@@ -1212,6 +2303,159 @@ async fn send_response_async(ws_sink: &WsSink, msg: Value) -> bool {
     }
 }
 
+/// Send a REST handler's result back as a `tunnel.*.result` message.
+async fn send_route_result(
+    ws_sink: &WsSink,
+    result_type: &str,
+    request_id: Option<&str>,
+    result: Result<axum::Json<Value>, (axum::http::StatusCode, axum::Json<ApiError>)>,
+) {
+    let (status, body) = match result {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(err))) => (status.as_u16(), json!(err)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": result_type,
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Answer a duplicate relay request: replay the first copy's response under
+/// this copy's `request_id`, or say why it can't be.
+async fn answer_duplicate(ws_sink: &WsSink, msg: &Value, seen: Seen) {
+    use axum::http::StatusCode;
+
+    let msg_type = msg["type"].as_str().unwrap_or("");
+    let request_id = msg["request_id"].as_str();
+    let err = match seen {
+        Seen::Done(Some(mut response)) => {
+            response["request_id"] = json!(request_id);
+            send_response_async(ws_sink, response).await;
+            return;
+        }
+        Seen::InFlight => ApiError::new(
+            codes::DUPLICATE_IN_FLIGHT,
+            "The same request is still running on the device; retry shortly",
+        ),
+        Seen::Done(None) | Seen::First => ApiError::new(
+            codes::DUPLICATE_REQUEST,
+            "The same request already ran on the device; its response wasn't kept",
+        ),
+    };
+    let detail = json!({ "idempotency_key": msg["idempotency_key"] });
+    send_route_result(
+        ws_sink,
+        &format!("{msg_type}.result"),
+        request_id,
+        Err(err
+            .with_detail(detail)
+            .into_response_with(StatusCode::CONFLICT)),
+    )
+    .await;
+}
+
+/// A `msg_type` message with the device's API key and, during a rotation's
+/// grace window, the previous key and how long it stays valid.
+fn api_keys_message(state: &AppState, msg_type: &str) -> Value {
+    let previous = state.api_keys.previous();
+    let now = crate::sessions::journal::now_ms();
+    json!({
+        "type": msg_type,
+        "api_key": state.api_keys.current(),
+        "previous_api_key": previous.as_ref().map(|p| &p.key),
+        "previous_expires_in_ms": previous.map(|p| p.expires_at.saturating_sub(now)),
+    })
+}
+
+/// Store keys issued by `relay_url` (see [`super::credentials`]). The API
+/// key is only taken from the primary relay, and applies from the next start.
+async fn handle_rotate_key(
+    state: &AppState,
+    ws_sink: &WsSink,
+    relay_url: &str,
+    primary: bool,
+    msg: &Value,
+) {
+    let request_id = msg["request_id"].as_str();
+    let (Some(tunnel_key), Some(api_key)) = (msg["tunnel_key"].as_str(), msg["api_key"].as_str())
+    else {
+        let err = ApiError::new(
+            crate::error::codes::INVALID_REQUEST,
+            "tunnel_key and api_key are required",
+        );
+        send_route_result(
+            ws_sink,
+            "tunnel.rotate_key.result",
+            request_id,
+            Err((axum::http::StatusCode::BAD_REQUEST, axum::Json(err))),
+        )
+        .await;
+        return;
+    };
+    let data_dir = state.config.server.data_dir.clone();
+    let (url, tunnel_key, api_key) = (
+        relay_url.to_string(),
+        tunnel_key.to_string(),
+        primary.then(|| api_key.to_string()),
+    );
+    let stored = tokio::task::spawn_blocking(move || {
+        super::credentials::Credentials::store(&data_dir, &url, &tunnel_key, api_key.as_deref())
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|r| r);
+    let result = match stored {
+        Ok(()) => {
+            info!(relay = relay_url, "Tunnel: stored keys issued by relay");
+            state
+                .activity_log
+                .log(
+                    crate::activity::ActivityType::TunnelKeyRotate,
+                    crate::activity::ActivitySource::Tunnel,
+                    format!("Tunnel keys issued by {relay_url}"),
+                    None,
+                    request_id.map(ToString::to_string),
+                )
+                .await;
+            Ok(axum::Json(json!({ "ok": true, "api_key_stored": primary })))
+        }
+        Err(e) => {
+            warn!(
+                relay = relay_url,
+                "Tunnel: failed to store issued keys: {e}"
+            );
+            Err((
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(ApiError::new(
+                    crate::error::codes::IO_ERROR,
+                    format!("Failed to store keys: {e}"),
+                )),
+            ))
+        }
+    };
+    send_route_result(ws_sink, "tunnel.rotate_key.result", request_id, result).await;
+}
+
+/// Deserialize a tunnel message into a route's query/body type, mapping
+/// failures to the 400 the REST extractor would have produced.
+fn tunnel_route_input<T: serde::de::DeserializeOwned>(
+    msg: &Value,
+) -> Result<T, (axum::http::StatusCode, axum::Json<ApiError>)> {
+    serde_json::from_value(msg.clone()).map_err(|e| {
+        ApiError::new(
+            crate::error::codes::INVALID_REQUEST,
+            format!("Invalid request: {e}"),
+        )
+        .into_response_with(axum::http::StatusCode::BAD_REQUEST)
+    })
+}
+
 /// Log a successful exec from a tunnel request (mirrors `routes::exec::log_exec_ok`).
 async fn log_tunnel_exec_ok(
     state: &AppState,
@@ -1219,20 +2463,33 @@ async fn log_tunnel_exec_ok(
     command: &str,
     result: &crate::shell::process::ExecResult,
     request_id: Option<String>,
-) {
+) -> Option<(crate::shell::summary::OutputSummary, u64)> {
+    let summary = crate::shell::summary::summarize(
+        &state.config.summary,
+        command,
+        &result.stdout,
+        &result.stderr,
+    )
+    .await;
+    state.metrics.record_exec("ok", result.duration_ms);
+    let mut detail = json!({
+        "exit_code": result.exit_code,
+        "duration_ms": result.duration_ms,
+        "risk": crate::shell::classify::classify(&state.config.classify, command),
+        "stdout_preview": activity::truncate_str(&result.stdout, 200),
+        "stderr_preview": activity::truncate_str(&result.stderr, 200),
+        "has_full_output": true,
+    });
+    if let Some(enrichment) = crate::hooks::enrich_exec(state, source, command, result) {
+        detail["enrichment"] = enrichment;
+    }
     let activity_id = state
         .activity_log
         .log(
             ActivityType::Exec,
             source,
             activity::truncate_str(command, 80),
-            Some(json!({
-                "exit_code": result.exit_code,
-                "duration_ms": result.duration_ms,
-                "stdout_preview": activity::truncate_str(&result.stdout, 200),
-                "stderr_preview": activity::truncate_str(&result.stderr, 200),
-                "has_full_output": true,
-            })),
+            Some(detail),
             request_id,
         )
         .await;
@@ -1247,8 +2504,11 @@ async fn log_tunnel_exec_ok(
             command: command.to_string(),
             status: "ok".to_string(),
             error_message: None,
+            summary: summary.clone(),
+            truncated: false,
         })
         .await;
+    summary.map(|s| (s, activity_id))
 }
 
 /// Log a failed exec from a tunnel request (mirrors `routes::exec::log_exec_err`).
@@ -1261,6 +2521,7 @@ async fn log_tunnel_exec_err(
     duration_ms: u64,
     request_id: Option<String>,
 ) {
+    state.metrics.record_exec(status, duration_ms);
     let activity_id = state
         .activity_log
         .log(
@@ -1270,6 +2531,7 @@ async fn log_tunnel_exec_err(
             Some(json!({
                 "exit_code": -1,
                 "duration_ms": duration_ms,
+                "risk": crate::shell::classify::classify(&state.config.classify, command),
                 "status": status,
                 "error": error_msg,
                 "has_full_output": true,
@@ -1288,6 +2550,8 @@ async fn log_tunnel_exec_err(
             command: command.to_string(),
             status: status.to_string(),
             error_message: Some(error_msg.to_string()),
+            summary: None,
+            truncated: false,
         })
         .await;
 }
@@ -1314,31 +2578,161 @@ async fn handle_tunnel_exec(
     let env: Option<HashMap<String, String>> = msg
         .get("env")
         .and_then(|v| serde_json::from_value(v.clone()).ok());
+    let sudo = match tunnel_exec_sudo(msg) {
+        Ok(sudo) => sudo,
+        Err(e) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.exec.result",
+                    "request_id": request_id,
+                    "status": 400,
+                    "body": {"error": e, "code": "INVALID_REQUEST"}
+                }),
+            )
+            .await;
+            return;
+        }
+    };
+    let window = match crate::routes::exec::confirm_window(
+        msg["confirm_within"].as_u64(),
+        msg["rollback"].as_str(),
+    ) {
+        Ok(window) => window,
+        Err(e) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.exec.result",
+                    "request_id": request_id,
+                    "status": 400,
+                    "body": {"error": e, "code": "INVALID_REQUEST"}
+                }),
+            )
+            .await;
+            return;
+        }
+    };
 
-    let source = activity::source_from_headers(&tunnel_headers(msg));
-    let req_id = request_id.map(ToString::to_string);
-
-    let result = match Box::pin(crate::shell::process::exec_command(
-        shell,
-        working_dir,
-        command,
-        timeout_ms,
+    let env = match crate::shell::secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
         env.as_ref(),
-    ))
+    )
     .await
     {
-        Ok(r) => {
-            log_tunnel_exec_ok(state, source, command, &r, req_id).await;
+        Ok(env) => env,
+        Err(e) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.exec.result",
+                    "request_id": request_id,
+                    "status": 400,
+                    "body": {"error": e, "code": "INVALID_REQUEST"}
+                }),
+            )
+            .await;
+            return;
+        }
+    };
+
+    let source = activity::source_from_headers(&tunnel_headers(msg));
+    let req_id = request_id.map(ToString::to_string);
+    let mut checked = crate::shell::policy::check(state, source, command).await;
+    if let (Ok(()), Some(_), Some(rollback)) = (&checked, window, msg["rollback"].as_str()) {
+        checked = crate::shell::policy::check(state, source, rollback).await;
+    }
+    if let Err((status, axum::Json(err))) =
+        checked.and_then(|()| crate::hooks::check_exec(state, source, command, shell, working_dir))
+    {
+        send_response_async(
+            ws_sink,
+            json!({
+                "type": "tunnel.exec.result",
+                "request_id": request_id,
+                "status": status.as_u16(),
+                "body": {"error": err.message, "code": err.code}
+            }),
+        )
+        .await;
+        return;
+    }
+
+    let (exec_result, confirm) = if let Some(window) = window {
+        let (id, outcome) = crate::shell::confirm::spawn_guarded(
+            state,
+            crate::shell::confirm::GuardedExec {
+                shell: shell.to_string(),
+                working_dir: working_dir.to_string(),
+                command: command.to_string(),
+                rollback: msg["rollback"].as_str().unwrap_or_default().to_string(),
+                timeout_ms,
+                env,
+                sudo,
+                window,
+                source,
+            },
+        );
+        match outcome.await {
+            Ok(outcome) => (
+                outcome.result,
+                outcome
+                    .deadline_ms
+                    .map(|deadline_ms| json!({"id": id, "deadline_ms": deadline_ms})),
+            ),
+            Err(_) => (
+                Err(crate::shell::process::ExecError::ProcessFailed(
+                    "guarded exec task failed".to_string(),
+                )),
+                None,
+            ),
+        }
+    } else {
+        let deadline = tunnel_deadline(msg);
+        let _guard = deadline.arm();
+        let mut r = deadline
+            .run(Box::pin(crate::shell::process::exec_command_with(
+                shell,
+                working_dir,
+                command,
+                deadline.cap_timeout_ms(timeout_ms),
+                env.env(),
+                sudo.as_ref(),
+            )))
+            .await
+            .unwrap_or(Err(crate::shell::process::ExecError::Timeout));
+        if let Ok(ref mut r) = r {
+            env.redact_result(r);
+        }
+        (r, None)
+    };
+
+    let result = match exec_result {
+        Ok(mut r) => {
+            let summary = log_tunnel_exec_ok(state, source, command, &r, req_id)
+                .await
+                .filter(|_| {
+                    crate::routes::exec::wants_summary(source, msg["full_output"].as_bool())
+                })
+                .map(|(s, activity_id)| crate::routes::exec::apply_summary(&mut r, s, activity_id));
+            let mut body = json!({
+                "exit_code": r.exit_code,
+                "stdout": r.stdout,
+                "stderr": r.stderr,
+                "duration_ms": r.duration_ms,
+            });
+            if let Some(confirm) = confirm {
+                body["confirm"] = confirm;
+            }
+            if let Some(summary) = summary {
+                body["summary"] = json!(summary);
+            }
             json!({
                 "type": "tunnel.exec.result",
                 "request_id": request_id,
                 "status": 200,
-                "body": {
-                    "exit_code": r.exit_code,
-                    "stdout": r.stdout,
-                    "stderr": r.stderr,
-                    "duration_ms": r.duration_ms,
-                }
+                "body": body,
             })
         }
         Err(crate::shell::process::ExecError::Timeout) => {
@@ -1356,7 +2750,7 @@ async fn handle_tunnel_exec(
                 "type": "tunnel.exec.result",
                 "request_id": request_id,
                 "status": 504,
-                "body": {"error": "Command timed out", "code": "TIMEOUT"}
+                "body": {"error": "Command timed out", "code": "TIMEOUT", "detail": {"confirm": confirm}}
             })
         }
         Err(e) => {
@@ -1373,6 +2767,36 @@ async fn handle_tunnel_exec(
     send_response_async(ws_sink, result).await;
 }
 
+/// Handle `tunnel.exec.{pending,confirm}` via the REST handlers.
+async fn handle_tunnel_exec_confirm(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::exec;
+    use axum::extract::{Path, State};
+
+    let result = if msg_type == "tunnel.exec.pending" {
+        Ok(exec::list_pending(State(state.clone())).await)
+    } else {
+        exec::confirm_pending(
+            State(state.clone()),
+            tunnel_headers(msg),
+            Path(msg["id"].as_str().unwrap_or_default().to_string()),
+        )
+        .await
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Parse and resolve the optional `sudo` field of a tunnel exec message.
+fn tunnel_exec_sudo(msg: &Value) -> Result<Option<crate::shell::sudo::ExecSudo>, String> {
+    let opts = crate::shell::sudo::SudoOptions::from_message(msg)?;
+    crate::shell::sudo::resolve_exec(opts.as_ref())
+}
+
 /// Handle `tunnel.exec_batch` — batch command execution
 async fn handle_tunnel_exec_batch(
     state: &AppState,
@@ -1420,20 +2844,72 @@ async fn handle_tunnel_exec_batch(
     let batch_env: Option<HashMap<String, String>> = msg
         .get("env")
         .and_then(|v| serde_json::from_value(v.clone()).ok());
+    let sudos = match commands
+        .iter()
+        .map(|cmd| tunnel_exec_sudo(if cmd.get("sudo").is_some() { cmd } else { msg }))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(sudos) => sudos,
+        Err(e) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.exec_batch.result",
+                    "request_id": request_id,
+                    "status": 400,
+                    "body": {"error": e, "code": "INVALID_REQUEST"}
+                }),
+            )
+            .await;
+            return;
+        }
+    };
 
     let source = activity::source_from_headers(&tunnel_headers(msg));
+    for cmd in commands {
+        let command = cmd["command"].as_str().unwrap_or("");
+        if let Err((status, axum::Json(err))) =
+            crate::shell::policy::check(state, source, command).await
+        {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.exec_batch.result",
+                    "request_id": request_id,
+                    "status": status.as_u16(),
+                    "body": {"error": err.message, "code": err.code}
+                }),
+            )
+            .await;
+            return;
+        }
+    }
     let req_id = request_id.map(ToString::to_string);
+    let summarize = crate::routes::exec::wants_summary(source, msg["full_output"].as_bool());
+    let deadline = tunnel_deadline(msg);
+    let _guard = deadline.arm();
 
     let mut results = Vec::with_capacity(commands.len());
-    for cmd in commands {
+    for (cmd, sudo) in commands.iter().zip(&sudos) {
+        if deadline.is_expired() {
+            results.push(json!({
+                "exit_code": -1,
+                "stdout": "",
+                "stderr": "Request deadline passed; command not run",
+                "duration_ms": 0,
+            }));
+            continue;
+        }
         let command = cmd["command"].as_str().unwrap_or("");
         let shell = cmd["shell"].as_str().unwrap_or(default_shell);
         let raw_cmd_dir = cmd["working_dir"].as_str().unwrap_or(&expanded_default_dir);
         let expanded_cmd_dir = crate::util::expand_tilde(raw_cmd_dir);
         let working_dir: &str = expanded_cmd_dir.as_ref();
-        let timeout = cmd["timeout_ms"]
-            .as_u64()
-            .unwrap_or(state.config.server.exec_timeout_ms);
+        let timeout = deadline.cap_timeout_ms(
+            cmd["timeout_ms"]
+                .as_u64()
+                .unwrap_or(state.config.server.exec_timeout_ms),
+        );
 
         let cmd_env: Option<HashMap<String, String>> = cmd
             .get("env")
@@ -1449,24 +2925,67 @@ async fn handle_tunnel_exec_batch(
                 Some(merged)
             }
         };
-
-        match Box::pin(crate::shell::process::exec_command(
-            shell,
-            working_dir,
-            command,
-            timeout,
+        let env = match crate::shell::secrets::resolve_env(
+            state.config.secrets.as_ref(),
+            &state.config.server.data_dir,
             merged_env.as_ref(),
-        ))
+        )
         .await
         {
-            Ok(r) => {
-                log_tunnel_exec_ok(state, source, command, &r, req_id.clone()).await;
+            Ok(env) => env,
+            Err(e) => {
+                log_tunnel_exec_err(state, source, command, "error", &e, 0, req_id.clone()).await;
                 results.push(json!({
+                    "exit_code": -1,
+                    "stdout": "",
+                    "stderr": e,
+                    "duration_ms": 0,
+                }));
+                continue;
+            }
+        };
+        if let Err((_, axum::Json(err))) =
+            crate::hooks::check_exec(state, source, command, shell, working_dir)
+        {
+            results.push(json!({
+                "exit_code": -1,
+                "stdout": "",
+                "stderr": err.message,
+                "duration_ms": 0,
+            }));
+            continue;
+        }
+
+        match deadline
+            .run(Box::pin(crate::shell::process::exec_command_with(
+                shell,
+                working_dir,
+                command,
+                timeout,
+                env.env(),
+                sudo.as_ref(),
+            )))
+            .await
+            .unwrap_or(Err(crate::shell::process::ExecError::Timeout))
+        {
+            Ok(mut r) => {
+                env.redact_result(&mut r);
+                let summary = log_tunnel_exec_ok(state, source, command, &r, req_id.clone())
+                    .await
+                    .filter(|_| summarize)
+                    .map(|(s, activity_id)| {
+                        crate::routes::exec::apply_summary(&mut r, s, activity_id)
+                    });
+                let mut item = json!({
                     "exit_code": r.exit_code,
                     "stdout": r.stdout,
                     "stderr": r.stderr,
                     "duration_ms": r.duration_ms,
-                }));
+                });
+                if let Some(summary) = summary {
+                    item["summary"] = json!(summary);
+                }
+                results.push(item);
             }
             Err(crate::shell::process::ExecError::Timeout) => {
                 log_tunnel_exec_err(
@@ -1537,7 +3056,10 @@ async fn handle_tunnel_info(
     // Call the info handler directly — it returns JSON
     match crate::routes::info::info(
         axum::extract::State(state.clone()),
-        axum::extract::Query(crate::routes::info::InfoQuery { groups }),
+        axum::extract::Query(crate::routes::info::InfoQuery {
+            groups,
+            fields: msg["fields"].as_str().map(String::from),
+        }),
     )
     .await
     {
@@ -1603,6 +3125,193 @@ async fn handle_tunnel_health(state: &AppState, ws_sink: &WsSink, request_id: Op
     .await;
 }
 
+/// Handle tunnel.diag — local tunnel stats, events, RTT history, last
+/// selftest, plus this relay's event outbox and the offline spool.
+async fn handle_tunnel_diag(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
+    let mut body = state.tunnel_stats.diag_snapshot().await;
+    body["outbox"] = json!({
+        "unacked": ws_sink.outbox.len().await,
+        "capacity": EVENT_OUTBOX_CAPACITY,
+    });
+    body["spool"] = state.offline_spool.as_ref().map_or(
+        Value::Null,
+        |spool| json!({ "entries": spool.len(), "dropped": spool.dropped() }),
+    );
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.diag.result",
+            "request_id": request_id,
+            "status": 200,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle tunnel.sftp.open — run `sftp-server` on a forwarded stream
+async fn handle_sftp_open(
+    state: &AppState,
+    ws_sink: &WsSink,
+    forwards: &Arc<super::forward::Forwards>,
+    msg: &Value,
+) {
+    use axum::http::StatusCode;
+
+    let request_id = msg["request_id"].as_str();
+    let stream_id = msg["stream_id"].as_str().unwrap_or("");
+    let result = if stream_id.is_empty() {
+        Err(ApiError::new(codes::INVALID_REQUEST, "Missing 'stream_id'")
+            .into_response_with(StatusCode::BAD_REQUEST))
+    } else if let Err(e) = tunnel_ai_guard(state, "tunnel.sftp.open", msg) {
+        // The session can write, so AI clients are refused while disabled.
+        Err(e)
+    } else if let Some(config) = &state.config.sftp {
+        match forwards.check(stream_id).await {
+            Err(super::forward::OpenError::Rejected(e) | super::forward::OpenError::Connect(e)) => {
+                Err(ApiError::new(codes::FORWARD_FAILED, e)
+                    .into_response_with(StatusCode::TOO_MANY_REQUESTS))
+            }
+            Ok(()) => match crate::sftp::spawn(config) {
+                Ok((mut child, stdin, stdout)) => {
+                    forwards
+                        .attach(stream_id, stdout, stdin, ws_sink.stream_tx.clone())
+                        .await;
+                    // Exits once the stream closes its stdin
+                    tokio::spawn(async move {
+                        let _ = child.wait().await;
+                    });
+                    Ok(axum::Json(json!({"stream_id": stream_id})))
+                }
+                Err(e) => Err(ApiError::new(codes::FORWARD_FAILED, e)
+                    .into_response_with(StatusCode::SERVICE_UNAVAILABLE)),
+            },
+        }
+    } else {
+        Err(
+            ApiError::new(codes::NOT_FOUND, "SFTP is not enabled ([sftp])")
+                .into_response_with(StatusCode::NOT_FOUND),
+        )
+    };
+    if result.is_ok() {
+        state
+            .activity_log
+            .log(
+                ActivityType::Sftp,
+                activity::ActivitySource::Tunnel,
+                "SFTP session opened".to_string(),
+                Some(json!({"stream_id": stream_id})),
+                request_id.map(ToString::to_string),
+            )
+            .await;
+    }
+    send_route_result(ws_sink, "tunnel.sftp.open.result", request_id, result).await;
+}
+
+/// Handle tunnel.dav.open — answer WebDAV requests on a forwarded stream.
+/// The relay sends one HTTP request per stream, with `prefix` to put in
+/// front of every `href`.
+async fn handle_dav_open(
+    state: &AppState,
+    ws_sink: &WsSink,
+    forwards: &Arc<super::forward::Forwards>,
+    msg: &Value,
+) {
+    use axum::http::StatusCode;
+
+    let request_id = msg["request_id"].as_str();
+    let stream_id = msg["stream_id"].as_str().unwrap_or("");
+    let result = if stream_id.is_empty() {
+        Err(ApiError::new(codes::INVALID_REQUEST, "Missing 'stream_id'")
+            .into_response_with(StatusCode::BAD_REQUEST))
+    } else if state.config.dav.is_none() {
+        Err(
+            ApiError::new(codes::NOT_FOUND, "WebDAV is not enabled ([dav])")
+                .into_response_with(StatusCode::NOT_FOUND),
+        )
+    } else {
+        match forwards.check(stream_id).await {
+            Err(super::forward::OpenError::Rejected(e) | super::forward::OpenError::Connect(e)) => {
+                Err(ApiError::new(codes::FORWARD_FAILED, e)
+                    .into_response_with(StatusCode::TOO_MANY_REQUESTS))
+            }
+            Ok(()) => {
+                let (ours, theirs) = tokio::io::duplex(4 * super::forward::MAX_FRAME);
+                let (read_half, write_half) = tokio::io::split(ours);
+                forwards
+                    .attach(stream_id, read_half, write_half, ws_sink.stream_tx.clone())
+                    .await;
+                let prefix = msg["prefix"].as_str().unwrap_or_default().to_string();
+                tokio::spawn(crate::dav::serve_connection(state.clone(), theirs, prefix));
+                Ok(axum::Json(json!({"stream_id": stream_id})))
+            }
+        }
+    };
+    send_route_result(ws_sink, "tunnel.dav.open.result", request_id, result).await;
+}
+
+/// Handle tunnel.forward.open — connect a forwarded stream to a local port
+/// listed in `[tunnel] forward_ports`
+async fn handle_forward_open(
+    state: &AppState,
+    ws_sink: &WsSink,
+    forwards: &Arc<super::forward::Forwards>,
+    msg: &Value,
+) {
+    use super::forward::{port_allowed, OpenError};
+
+    let request_id = msg["request_id"].as_str();
+    let stream_id = msg["stream_id"].as_str().unwrap_or("");
+    let port = msg["port"].as_u64().unwrap_or(0);
+    let allowed = state
+        .config
+        .tunnel
+        .as_ref()
+        .map_or(&[][..], |t| t.forward_ports.as_slice());
+    let result = if stream_id.is_empty() {
+        Err(ApiError::new(codes::INVALID_REQUEST, "Missing 'stream_id'")
+            .into_response_with(axum::http::StatusCode::BAD_REQUEST))
+    } else if !port_allowed(allowed, port) {
+        Err(ApiError::new(
+            codes::FORWARD_NOT_ALLOWED,
+            format!("Port {port} is not in [tunnel] forward_ports"),
+        )
+        .into_response_with(axum::http::StatusCode::FORBIDDEN))
+    } else {
+        #[allow(clippy::cast_possible_truncation)]
+        match forwards
+            .open(stream_id, port as u16, ws_sink.stream_tx.clone())
+            .await
+        {
+            Ok(()) => Ok(axum::Json(json!({"stream_id": stream_id, "port": port}))),
+            Err(OpenError::Rejected(e)) => Err(ApiError::new(codes::FORWARD_FAILED, e)
+                .into_response_with(axum::http::StatusCode::TOO_MANY_REQUESTS)),
+            Err(OpenError::Connect(e)) => Err(ApiError::new(codes::FORWARD_FAILED, e)
+                .into_response_with(axum::http::StatusCode::BAD_GATEWAY)),
+        }
+    };
+    if result.is_ok() {
+        state
+            .activity_log
+            .log(
+                ActivityType::Forward,
+                activity::ActivitySource::Tunnel,
+                format!("Port forward to {port} opened"),
+                Some(json!({"stream_id": stream_id, "port": port})),
+                request_id.map(ToString::to_string),
+            )
+            .await;
+    }
+    send_route_result(ws_sink, "tunnel.forward.open.result", request_id, result).await;
+}
+
+/// Handle tunnel.resolve — resolve a name the way this device sees it
+async fn handle_tunnel_resolve(ws_sink: &WsSink, msg: &Value, request_id: Option<&str>) {
+    let name = msg["name"].as_str().unwrap_or("");
+    let result = crate::routes::resolve::local(name).await;
+    send_route_result(ws_sink, "tunnel.resolve.result", request_id, result).await;
+}
+
 /// Handle tunnel.diagnostics — server diagnostics snapshot
 async fn handle_tunnel_diagnostics(
     state: &AppState,
@@ -1668,11 +3377,22 @@ async fn handle_tunnel_file_read(
         list,
         offset,
         limit,
+        sort: msg["sort"].as_str().map(ToString::to_string),
+        order: msg["order"].as_str().map(ToString::to_string),
+        glob: msg["glob"].as_str().map(ToString::to_string),
+        dirs_only: msg["dirs_only"].as_bool().unwrap_or(false),
+        files_only: msg["files_only"].as_bool().unwrap_or(false),
+        continuation_token: msg["continuation_token"].as_str().map(ToString::to_string),
+        format: None,
+        xattrs: msg["xattrs"].as_bool().unwrap_or(false),
     };
 
-    match crate::routes::files::get_file(
+    let deadline = tunnel_deadline(msg);
+    let _guard = deadline.arm();
+    match crate::routes::files::read_or_list(
         axum::extract::State(state.clone()),
         tunnel_headers(msg),
+        deadline.clone(),
         axum::extract::Query(query),
     )
     .await
@@ -1767,11 +3487,24 @@ async fn handle_tunnel_activity(
     request_id: Option<&str>,
 ) {
     let since_id = msg["since_id"].as_u64().unwrap_or(0);
-    let limit = usize::try_from(msg["limit"].as_u64().unwrap_or(50)).unwrap_or(50);
+    let limit = usize::try_from(msg["limit"].as_u64().unwrap_or(50))
+        .unwrap_or(50)
+        .min(200);
+    let filter = crate::activity::ActivityFilter {
+        from: msg["from"].as_u64(),
+        to: msg["to"].as_u64(),
+        ..Default::default()
+    };
     let entries = state
         .activity_log
-        .read_since(since_id, limit.min(200))
+        .read_since_filtered(since_id, limit, &filter)
         .await;
+    let next_since_id = entries
+        .last()
+        .filter(|_| entries.len() == limit)
+        .map(|e| e.id);
+    let mut entries = serde_json::to_value(entries).unwrap_or_default();
+    crate::util::project_fields(&mut entries, msg["fields"].as_str());
 
     send_response_async(
         ws_sink,
@@ -1779,7 +3512,7 @@ async fn handle_tunnel_activity(
             "type": "tunnel.activity.result",
             "request_id": request_id,
             "status": 200,
-            "body": { "entries": entries },
+            "body": { "entries": entries, "next_since_id": next_since_id },
         }),
     )
     .await;
@@ -1822,128 +3555,328 @@ async fn handle_tunnel_exec_result(
     }
 }
 
-/// Handle tunnel.sessions — REST session list
-async fn handle_tunnel_sessions(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
-    let axum::Json(body) =
-        crate::routes::sessions::list_sessions(axum::extract::State(state.clone())).await;
+/// Handle tunnel.sessions — REST session list
+async fn handle_tunnel_sessions(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let axum::Json(body) = crate::routes::sessions::list_sessions(
+        axum::extract::State(state.clone()),
+        axum::extract::Query(crate::routes::FieldsQuery {
+            fields: msg["fields"].as_str().map(String::from),
+        }),
+    )
+    .await;
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.sessions.result",
+            "request_id": request_id,
+            "status": 200,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle tunnel.shells — shell list
+async fn handle_tunnel_shells(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
+    let axum::Json(body) =
+        crate::routes::shells::list_shells(axum::extract::State(state.clone())).await;
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.shells.result",
+            "request_id": request_id,
+            "status": 200,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle tunnel.session.signal — signal a session
+async fn handle_tunnel_session_signal(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let session_id = msg["session_id"].as_str().unwrap_or("");
+    #[allow(clippy::cast_possible_truncation)]
+    let signal = msg["signal"].as_i64().unwrap_or(0) as i32;
+
+    let payload = crate::routes::sessions::SignalRequest { signal };
+    match crate::routes::sessions::signal_session(
+        axum::extract::State(state.clone()),
+        axum::extract::Path(session_id.to_string()),
+        tunnel_headers(msg),
+        axum::Json(payload),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.session.signal.result",
+                    "request_id": request_id,
+                    "status": 200,
+                    "body": body,
+                }),
+            )
+            .await;
+        }
+        Err((status, axum::Json(body))) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.session.signal.result",
+                    "request_id": request_id,
+                    "status": status.as_u16(),
+                    "body": body,
+                }),
+            )
+            .await;
+        }
+    }
+}
+
+/// Handle tunnel.session.kill — kill a session
+async fn handle_tunnel_session_kill(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let session_id = msg["session_id"].as_str().unwrap_or("");
+    match crate::routes::sessions::kill_session(
+        axum::extract::State(state.clone()),
+        axum::extract::Path(session_id.to_string()),
+        tunnel_headers(msg),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.session.kill.result",
+                    "request_id": request_id,
+                    "status": 200,
+                    "body": body,
+                }),
+            )
+            .await;
+        }
+        Err((status, axum::Json(body))) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "tunnel.session.kill.result",
+                    "request_id": request_id,
+                    "status": status.as_u16(),
+                    "body": body,
+                }),
+            )
+            .await;
+        }
+    }
+}
+
+/// Handle tunnel.session.share — mint a share link
+async fn handle_tunnel_session_share(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let session_id = msg["session_id"].as_str().unwrap_or("");
+    let payload: crate::routes::sessions::ShareRequest =
+        serde_json::from_value(msg["body"].clone()).unwrap_or_default();
+    let (status, body) = match crate::routes::sessions::share_session(
+        axum::extract::State(state.clone()),
+        axum::extract::Path(session_id.to_string()),
+        tunnel_headers(msg),
+        axum::Json(payload),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.session.share.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle tunnel.health.history — recorded health transitions
+async fn handle_tunnel_health_history(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let query = crate::routes::health::HealthHistoryQuery {
+        since: msg["since"].as_u64(),
+        condition: msg["condition"].as_str().map(String::from),
+        limit: msg["limit"]
+            .as_u64()
+            .map(|n| usize::try_from(n).unwrap_or(usize::MAX)),
+    };
+    let (status, body) = match crate::routes::health::health_history(
+        axum::extract::State(state.clone()),
+        axum::extract::Query(query),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.health.history.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle tunnel.support_bundle — start a support bundle build
+async fn handle_tunnel_support_bundle(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let query = crate::routes::diagnostics::DiagnosticsQuery {
+        log_lines: msg["log_lines"].as_u64().map(|n| n as u32),
+        log_since: msg["log_since"].as_str().map(String::from),
+    };
+    let (status, body) = match crate::routes::support_bundle::create_support_bundle(
+        axum::extract::State(state.clone()),
+        tunnel_headers(msg),
+        axum::extract::Query(query),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
     send_response_async(
         ws_sink,
         json!({
-            "type": "tunnel.sessions.result",
+            "type": "tunnel.support_bundle.result",
             "request_id": request_id,
-            "status": 200,
+            "status": status,
             "body": body,
         }),
     )
     .await;
 }
 
-/// Handle tunnel.shells — shell list
-async fn handle_tunnel_shells(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
-    let axum::Json(body) =
-        crate::routes::shells::list_shells(axum::extract::State(state.clone())).await;
+/// Handle tunnel.support_bundle.status — status of the last build
+async fn handle_tunnel_support_bundle_status(ws_sink: &WsSink, request_id: Option<&str>) {
+    let (status, body) = match crate::routes::support_bundle::support_bundle_status().await {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
     send_response_async(
         ws_sink,
         json!({
-            "type": "tunnel.shells.result",
+            "type": "tunnel.support_bundle.status.result",
             "request_id": request_id,
-            "status": 200,
+            "status": status,
             "body": body,
         }),
     )
     .await;
 }
 
-/// Handle tunnel.session.signal — signal a session
-async fn handle_tunnel_session_signal(
+/// Handle tunnel.session.history — commands run in a session, or output
+/// after `since`
+async fn handle_tunnel_session_history(
     state: &AppState,
     ws_sink: &WsSink,
     msg: &Value,
     request_id: Option<&str>,
 ) {
     let session_id = msg["session_id"].as_str().unwrap_or("");
-    #[allow(clippy::cast_possible_truncation)]
-    let signal = msg["signal"].as_i64().unwrap_or(0) as i32;
-
-    let payload = crate::routes::sessions::SignalRequest { signal };
-    match crate::routes::sessions::signal_session(
+    let query = crate::routes::sessions::HistoryQuery {
+        limit: msg["limit"]
+            .as_u64()
+            .map(|l| usize::try_from(l).unwrap_or(usize::MAX)),
+        since: msg["since"].as_u64(),
+    };
+    let (status, body) = match crate::routes::sessions::session_history(
         axum::extract::State(state.clone()),
         axum::extract::Path(session_id.to_string()),
-        tunnel_headers(msg),
-        axum::Json(payload),
+        axum::extract::Query(query),
     )
     .await
     {
-        Ok(axum::Json(body)) => {
-            send_response_async(
-                ws_sink,
-                json!({
-                    "type": "tunnel.session.signal.result",
-                    "request_id": request_id,
-                    "status": 200,
-                    "body": body,
-                }),
-            )
-            .await;
-        }
-        Err((status, axum::Json(body))) => {
-            send_response_async(
-                ws_sink,
-                json!({
-                    "type": "tunnel.session.signal.result",
-                    "request_id": request_id,
-                    "status": status.as_u16(),
-                    "body": body,
-                }),
-            )
-            .await;
-        }
-    }
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.session.history.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
 }
 
-/// Handle tunnel.session.kill — kill a session
-async fn handle_tunnel_session_kill(
+/// Handle tunnel.session.journal — page through on-disk session output
+async fn handle_tunnel_session_journal(
     state: &AppState,
     ws_sink: &WsSink,
     msg: &Value,
     request_id: Option<&str>,
 ) {
     let session_id = msg["session_id"].as_str().unwrap_or("");
-    match crate::routes::sessions::kill_session(
+    let query = crate::routes::sessions::JournalQuery {
+        from_seq: msg["from_seq"].as_u64().unwrap_or(0),
+        limit: usize::try_from(msg["limit"].as_u64().unwrap_or(500)).unwrap_or(usize::MAX),
+    };
+    let (status, body) = match crate::routes::sessions::session_journal(
         axum::extract::State(state.clone()),
         axum::extract::Path(session_id.to_string()),
-        tunnel_headers(msg),
+        axum::extract::Query(query),
     )
     .await
     {
-        Ok(axum::Json(body)) => {
-            send_response_async(
-                ws_sink,
-                json!({
-                    "type": "tunnel.session.kill.result",
-                    "request_id": request_id,
-                    "status": 200,
-                    "body": body,
-                }),
-            )
-            .await;
-        }
-        Err((status, axum::Json(body))) => {
-            send_response_async(
-                ws_sink,
-                json!({
-                    "type": "tunnel.session.kill.result",
-                    "request_id": request_id,
-                    "status": status.as_u16(),
-                    "body": body,
-                }),
-            )
-            .await;
-        }
-    }
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.session.journal.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
 }
 
-/// Handle tunnel.session.patch — rename, AI permission, AI status
+/// Handle tunnel.session.patch — rename, AI permission, AI status, encoding
 async fn handle_tunnel_session_patch(
     state: &AppState,
     ws_sink: &WsSink,
@@ -1957,6 +3890,9 @@ async fn handle_tunnel_session_patch(
         working: msg["working"].as_bool(),
         activity: msg["activity"].as_str().map(ToString::to_string),
         message: msg["message"].as_str().map(ToString::to_string),
+        encoding: msg["encoding"]
+            .as_str()
+            .and_then(crate::sessions::decode::OutputEncoding::from_str_opt),
     };
 
     match crate::routes::sessions::patch_session(
@@ -2060,10 +3996,30 @@ async fn handle_gx_download_init(
     request_id: Option<&str>,
 ) {
     let path = msg["path"].as_str().unwrap_or("");
+    // Without an explicit chunk size, shrink the default on degraded links so
+    // a lost chunk is cheaper to retry.
     #[allow(clippy::cast_possible_truncation)]
-    let chunk_size = msg["chunk_size"].as_u64().map(|v| v as u32);
+    let chunk_size = msg["chunk_size"].as_u64().map(|v| v as u32).or_else(|| {
+        let quality = state.tunnel_stats.link_quality();
+        Some(quality.scale_chunk_size(state.config.server.transfer_chunk_size))
+    });
 
-    match state.transfer_manager.init_download(path, chunk_size).await {
+    let rate_limit_bps = msg["rate_limit_bps"].as_u64();
+    let result = if msg["directory"].as_bool().unwrap_or(false) {
+        let files: Option<Vec<String>> = msg
+            .get("files")
+            .and_then(|f| serde_json::from_value(f.clone()).ok());
+        state
+            .transfer_manager
+            .init_directory_download(path, files.as_deref(), chunk_size, rate_limit_bps)
+            .await
+    } else {
+        state
+            .transfer_manager
+            .init_download(path, chunk_size, rate_limit_bps)
+            .await
+    };
+    match result {
         Ok(result) => {
             send_response_async(
                 ws_sink,
@@ -2103,6 +4059,8 @@ async fn handle_gx_upload_init(
         #[allow(clippy::cast_possible_truncation)]
         total_chunks: msg["total_chunks"].as_u64().unwrap_or(0) as u32,
         mode: msg["mode"].as_str().map(ToString::to_string),
+        directory: msg["directory"].as_bool().unwrap_or(false),
+        rate_limit_bps: msg["rate_limit_bps"].as_u64(),
     };
 
     match state.transfer_manager.init_upload(req).await {
@@ -2138,10 +4096,12 @@ async fn handle_gx_chunk_request(
     let transfer_id = msg["transfer_id"].as_str().unwrap_or("");
     #[allow(clippy::cast_possible_truncation)]
     let chunk_index = msg["chunk_index"].as_u64().unwrap_or(0) as u32;
+    let deadline = tunnel_deadline(msg);
+    let _guard = deadline.arm();
 
     match state
         .transfer_manager
-        .serve_chunk(transfer_id, chunk_index)
+        .serve_chunk(transfer_id, chunk_index, deadline.token())
         .await
     {
         Ok((chunk_header, data)) => {
@@ -2186,10 +4146,26 @@ async fn handle_gx_chunk_receive(
     #[allow(clippy::cast_possible_truncation)]
     let chunk_index = header["chunk_index"].as_u64().unwrap_or(0) as u32;
     let chunk_hash = header["chunk_hash"].as_str().unwrap_or("");
+    let deadline = tunnel_deadline(header);
+    let _guard = deadline.arm();
+
+    if let Err(e) = state
+        .ai_guard
+        .charge_write(&tunnel_headers(header), payload.len() as u64)
+    {
+        send_route_result(ws_sink, "gx.chunk.ack", request_id, Err(e)).await;
+        return;
+    }
 
     match state
         .transfer_manager
-        .receive_chunk(transfer_id, chunk_index, chunk_hash, payload)
+        .receive_chunk(
+            transfer_id,
+            chunk_index,
+            chunk_hash,
+            payload,
+            deadline.token(),
+        )
         .await
     {
         Ok(ack) => {
@@ -2303,21 +4279,92 @@ async fn handle_gx_status(
     }
 }
 
+/// Handle gx.throttle — change a transfer's (or the global) bandwidth cap.
+async fn handle_gx_throttle(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let transfer_id = msg["transfer_id"].as_str();
+    let rate_limit_bps = msg["rate_limit_bps"].as_u64().unwrap_or(0);
+    match state
+        .transfer_manager
+        .throttle(transfer_id, rate_limit_bps)
+        .await
+    {
+        Ok(result) => {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "gx.throttle.result",
+                    "request_id": request_id,
+                    "status": 200,
+                    "body": serde_json::to_value(&result).unwrap_or_default(),
+                }),
+            )
+            .await;
+        }
+        Err(e) => {
+            send_response_async(
+                ws_sink,
+                gx_error_response("gx.throttle.result", request_id, &e),
+            )
+            .await;
+        }
+    }
+}
+
 /// Handle gx.list — list all transfers.
-async fn handle_gx_list(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
+async fn handle_gx_list(state: &AppState, ws_sink: &WsSink, msg: &Value, request_id: Option<&str>) {
     let result = state.transfer_manager.list().await;
+    let mut body = serde_json::to_value(&result).unwrap_or_default();
+    crate::util::project_fields(&mut body["transfers"], msg["fields"].as_str());
     send_response_async(
         ws_sink,
         json!({
             "type": "gx.list.result",
             "request_id": request_id,
             "status": 200,
-            "body": serde_json::to_value(&result).unwrap_or_default(),
+            "body": body,
         }),
     )
     .await;
 }
 
+/// A text frame re-sent as MessagePack (see [`super::codec`]). Frames that
+/// don't parse and binary frames pass through.
+fn encode_message(
+    msg: tokio_tungstenite::tungstenite::Message,
+) -> tokio_tungstenite::tungstenite::Message {
+    let tokio_tungstenite::tungstenite::Message::Text(text) = &msg else {
+        return msg;
+    };
+    match serde_json::from_str(text)
+        .ok()
+        .and_then(|v: Value| super::codec::encode(&v))
+    {
+        Some(data) => tokio_tungstenite::tungstenite::Message::Binary(data.into()),
+        None => msg,
+    }
+}
+
+/// A MessagePack frame from the relay as the text frame it stands for, so
+/// the read loop handles both alike. `None` if it doesn't decode.
+fn decode_message(
+    msg: tokio_tungstenite::tungstenite::Message,
+) -> Option<tokio_tungstenite::tungstenite::Message> {
+    match &msg {
+        tokio_tungstenite::tungstenite::Message::Binary(data) if super::codec::is_message(data) => {
+            let value = super::codec::decode(data)?;
+            Some(tokio_tungstenite::tungstenite::Message::Text(
+                value.to_string().into(),
+            ))
+        }
+        _ => Some(msg),
+    }
+}
+
 /// Build a JSON error response for gx.* messages.
 fn gx_error_response(
     result_type: &str,
@@ -2329,6 +4376,7 @@ fn gx_error_response(
         "PERMISSION_DENIED" => 403,
         "DISK_FULL" => 507,
         "MAX_TRANSFERS" => 429,
+        "QUIET_HOURS" => 503,
         _ => 400,
     };
     json!({
@@ -2358,6 +4406,75 @@ async fn handle_forwarded_session_message(
     let msg_type = msg["type"].as_str().unwrap_or("");
     let request_id = msg["request_id"].as_str().map(ToString::to_string);
 
+    // Fill `session.start` from the `[session_templates]` entry it names.
+    let expanded;
+    let msg = if msg_type == "session.start" {
+        match crate::sessions::templates::expand(state, msg).await {
+            Ok(m) => {
+                expanded = m;
+                &expanded
+            }
+            Err(e) => {
+                send_response_async(
+                    ws_sink,
+                    json!({
+                        "type": "error",
+                        "code": "INVALID_REQUEST",
+                        "message": e,
+                        "request_id": request_id,
+                    }),
+                )
+                .await;
+                return;
+            }
+        }
+    } else {
+        msg
+    };
+
+    if let Err((_, axum::Json(err))) = state
+        .ai_guard
+        .check_ws_message(&tunnel_headers(msg), msg_type)
+    {
+        send_response_async(
+            ws_sink,
+            json!({
+                "type": "error",
+                "code": err.code,
+                "session_id": msg["session_id"],
+                "message": err.message,
+                "request_id": request_id,
+            }),
+        )
+        .await;
+        return;
+    }
+
+    if matches!(
+        msg_type,
+        "session.exec" | "session.stdin" | "session.signal"
+    ) {
+        let session_id = msg["session_id"].as_str().unwrap_or("");
+        if let Err(message) = state
+            .session_manager
+            .check_input(session_id, &relay_holder(msg))
+            .await
+        {
+            send_response_async(
+                ws_sink,
+                json!({
+                    "type": "error",
+                    "code": "INPUT_LOCKED",
+                    "session_id": session_id,
+                    "message": message,
+                    "request_id": request_id,
+                }),
+            )
+            .await;
+            return;
+        }
+    }
+
     match msg_type {
         "session.start" => {
             let working_dir = msg["working_dir"].as_str().map(ToString::to_string);
@@ -2380,6 +4497,7 @@ async fn handle_forwarded_session_message(
                 .unwrap_or(u64::from(state.config.server.default_terminal_cols))
                 as u16;
             let idle_timeout = msg["idle_timeout"].as_u64().unwrap_or(0);
+            let record = msg["record"].as_bool().unwrap_or(false);
 
             let raw_dir = working_dir
                 .as_deref()
@@ -2402,6 +4520,41 @@ async fn handle_forwarded_session_message(
                 "Tunnel: session.start received"
             );
 
+            let prepared = match crate::shell::secrets::resolve_env(
+                state.config.secrets.as_ref(),
+                &state.config.server.data_dir,
+                env.as_ref(),
+            )
+            .await
+            .and_then(|resolved| {
+                let encoding = OutputEncoding::from_message(msg)?;
+                let limits = state.session_manager.resource_limits(msg)?;
+                let sudo = crate::shell::sudo::SudoOptions::from_message(msg)?
+                    .map(|s| s.prepare_session(use_pty, resolved.env()))
+                    .transpose()?;
+                Ok(((resolved, sudo), (encoding, limits)))
+            }) {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    send_response_async(
+                        ws_sink,
+                        json!({
+                            "type": "error",
+                            "code": "INVALID_REQUEST",
+                            "message": e,
+                            "request_id": request_id,
+                        }),
+                    )
+                    .await;
+                    return;
+                }
+            };
+            let (prepared, (encoding, limits)) = prepared;
+            let (env, sudo) = match prepared {
+                (_, Some((merged, sudo))) => (Some(merged), Some(sudo)),
+                (resolved, None) => (resolved.env().cloned(), None),
+            };
+
             info!(
                 request_id = request_id.as_deref().unwrap_or(""),
                 shell = sh,
@@ -2420,6 +4573,8 @@ async fn handle_forwarded_session_message(
                     cols,
                     idle_timeout,
                     name.as_deref(),
+                    limits.as_ref(),
+                    Some(state.session_events.clone()),
                 )
                 .await
             {
@@ -2430,12 +4585,46 @@ async fn handle_forwarded_session_message(
                         pid,
                         "Tunnel: session.start PTY spawn succeeded"
                     );
+                    if let Some(sudo) = sudo {
+                        crate::shell::sudo::spawn_session_responder(
+                            state.session_manager.clone(),
+                            session_id.clone(),
+                            sudo,
+                        );
+                    }
                     if !allows_ai {
                         let _ = state
                             .session_manager
                             .set_user_allows_ai(&session_id, false)
                             .await;
                     }
+                    if let Some(encoding) = encoding {
+                        let _ = state
+                            .session_manager
+                            .set_encoding(&session_id, encoding)
+                            .await;
+                    }
+                    // A session that asked to be recorded must not run unrecorded.
+                    if record {
+                        if let Err(e) = state
+                            .session_manager
+                            .start_recording(&session_id, sh, rows, cols)
+                            .await
+                        {
+                            state.session_manager.kill_session(&session_id).await;
+                            send_response_async(
+                                ws_sink,
+                                json!({
+                                    "type": "error",
+                                    "code": "RECORDING_FAILED",
+                                    "message": e,
+                                    "request_id": request_id,
+                                }),
+                            )
+                            .await;
+                            return;
+                        }
+                    }
 
                     // Send session.started BEFORE spawning subscriber to avoid
                     // a race where the subscriber grabs ws_sink first and blocks
@@ -2447,10 +4636,14 @@ async fn handle_forwarded_session_message(
                         "persistent": persistent,
                         "pty": use_pty,
                         "user_allows_ai": allows_ai,
+                        "recording": record,
                     });
                     if let Some(n) = name.as_deref() {
                         resp["name"] = json!(n);
                     }
+                    if let Some(limits) = state.session_manager.limits(&session_id).await {
+                        resp["limits"] = json!(limits);
+                    }
                     if let Some(ref rid) = request_id {
                         resp["request_id"] = json!(rid);
                     }
@@ -2550,13 +4743,53 @@ async fn handle_forwarded_session_message(
                     "Tunnel: job.start received"
                 );
 
+                let source = activity::source_from_headers(&tunnel_headers(msg));
+                if let Err((_, axum::Json(err))) =
+                    crate::shell::policy::check(state, source, command).await
+                {
+                    send_response_async(
+                        ws_sink,
+                        json!({
+                            "type": "error",
+                            "code": err.code,
+                            "message": err.message,
+                            "request_id": request_id,
+                        }),
+                    )
+                    .await;
+                    return;
+                }
+
+                let env = match crate::shell::secrets::resolve_env(
+                    state.config.secrets.as_ref(),
+                    &state.config.server.data_dir,
+                    env.as_ref(),
+                )
+                .await
+                {
+                    Ok(env) => env,
+                    Err(e) => {
+                        send_response_async(
+                            ws_sink,
+                            json!({
+                                "type": "error",
+                                "code": "INVALID_REQUEST",
+                                "message": e,
+                                "request_id": request_id,
+                            }),
+                        )
+                        .await;
+                        return;
+                    }
+                };
+
                 match state
                     .session_manager
                     .create_job(
                         sh,
                         dir,
                         command,
-                        env.as_ref(),
+                        env.env(),
                         name.as_deref(),
                         crate::sessions::JOB_IDLE_TIMEOUT_SECS,
                         state.session_events.clone(),
@@ -2627,7 +4860,21 @@ async fn handle_forwarded_session_message(
             let session_id = msg["session_id"].as_str().unwrap_or("");
             let command = msg["command"].as_str().unwrap_or("");
             state.session_manager.touch_ai_activity(session_id).await;
-            if let Err(e) = state
+            let source = activity::source_from_headers(&tunnel_headers(msg));
+            if let Err((_, axum::Json(err))) =
+                crate::shell::policy::check(state, source, command).await
+            {
+                let mut resp = json!({
+                    "type": "error",
+                    "code": err.code,
+                    "session_id": session_id,
+                    "message": err.message,
+                });
+                if let Some(ref rid) = request_id {
+                    resp["request_id"] = json!(rid);
+                }
+                send_response_async(ws_sink, resp).await;
+            } else if let Err(e) = state
                 .session_manager
                 .exec_command(session_id, command)
                 .await
@@ -2842,6 +5089,16 @@ async fn handle_forwarded_session_message(
                     if let Some(ref msg) = s.ai_status_message {
                         obj["ai_status_message"] = json!(msg);
                     }
+                    if let Some(ref title) = s.title {
+                        obj["title"] = json!(title);
+                    }
+                    if let Some(ref cwd) = s.cwd {
+                        obj["cwd"] = json!(cwd);
+                    }
+                    if let Some(ref controller) = s.controller {
+                        obj["controller"] = json!(controller);
+                        obj["controller_label"] = json!(s.controller_label);
+                    }
                     obj
                 })
                 .collect();
@@ -2854,6 +5111,42 @@ async fn handle_forwarded_session_message(
             }
             send_response_async(ws_sink, resp).await;
         }
+        "session.history" => {
+            let reply = crate::ws::session_history_reply(
+                state,
+                msg["session_id"].as_str().unwrap_or(""),
+                msg["since"].as_u64().unwrap_or(0),
+                msg["limit"].as_u64(),
+                request_id.clone(),
+            )
+            .await;
+            send_response_async(ws_sink, reply).await;
+        }
+        "session.read_diff" => {
+            let session_id = msg["session_id"].as_str().unwrap_or("");
+            let mut resp = match state
+                .session_manager
+                .read_screen_diff(session_id, msg["since"].as_u64())
+                .await
+            {
+                Ok(diff) => {
+                    let mut resp = serde_json::to_value(&diff).unwrap_or_else(|_| json!({}));
+                    resp["type"] = json!("session.diff");
+                    resp["session_id"] = json!(session_id);
+                    resp
+                }
+                Err(e) => json!({
+                    "type": "error",
+                    "code": "SCREEN_UNAVAILABLE",
+                    "session_id": session_id,
+                    "message": e,
+                }),
+            };
+            if let Some(ref rid) = request_id {
+                resp["request_id"] = json!(rid);
+            }
+            send_response_async(ws_sink, resp).await;
+        }
         "session.resize" => {
             let session_id = msg["session_id"].as_str().unwrap_or("");
             #[allow(clippy::cast_possible_truncation)]
@@ -3035,6 +5328,9 @@ async fn handle_forwarded_session_message(
                 }
             }
         }
+        "session.take_control" | "session.release_control" | "session.release_client" => {
+            handle_tunnel_session_control(state, ws_sink, msg).await;
+        }
         "shell.list" => {
             let shells = crate::shell::detect_shells();
             let mut resp = json!({
@@ -3047,82 +5343,591 @@ async fn handle_forwarded_session_message(
             }
             send_response_async(ws_sink, resp).await;
         }
-        _ => {
-            warn!(msg_type, "Unknown forwarded session message type");
+        _ => {
+            warn!(msg_type, "Unknown forwarded session message type");
+        }
+    }
+}
+
+/// Input-lock holder id of the relay client that sent `msg`: the client id
+/// the relay tagged the `request_id` with.
+fn relay_holder(msg: &Value) -> String {
+    let rid = msg["request_id"].as_str().unwrap_or("");
+    let client_id = rid.split_once(':').map_or(rid, |(client_id, _)| client_id);
+    format!("relay:{client_id}")
+}
+
+/// Handle `session.take_control` / `session.release_control` from a relay
+/// client, and `session.release_client`, which the relay sends when a client
+/// that took control disconnects. See [`crate::sessions::control`].
+async fn handle_tunnel_session_control(state: &AppState, ws_sink: &WsSink, msg: &Value) {
+    use crate::ws::messages::WsServerMsg;
+
+    let msg_type = msg["type"].as_str().unwrap_or("");
+    let session_id = msg["session_id"].as_str().unwrap_or("");
+    let request_id = msg["request_id"].as_str().map(ToString::to_string);
+    let holder = relay_holder(msg);
+    let not_found = || WsServerMsg::Error {
+        code: "SESSION_NOT_FOUND".into(),
+        message: format!("Session {session_id} not found"),
+        session_id: Some(session_id.to_string()),
+        request_id: request_id.clone(),
+    };
+    let reply = match msg_type {
+        "session.take_control" => match state
+            .session_manager
+            .take_control(
+                session_id,
+                &holder,
+                msg["label"].as_str(),
+                msg["force"].as_bool().unwrap_or(false),
+            )
+            .await
+        {
+            Some(Ok(lock)) => {
+                let _ = state
+                    .session_events
+                    .send(WsServerMsg::control_changed(session_id, Some(&lock)).to_value());
+                WsServerMsg::SessionTakeControlAck {
+                    session_id: session_id.to_string(),
+                    client_id: holder,
+                    request_id: request_id.clone(),
+                }
+            }
+            Some(Err(e)) => WsServerMsg::Error {
+                code: "INPUT_LOCKED".into(),
+                message: e,
+                session_id: Some(session_id.to_string()),
+                request_id: request_id.clone(),
+            },
+            None => not_found(),
+        },
+        "session.release_control" => {
+            match state
+                .session_manager
+                .release_control(session_id, &holder)
+                .await
+            {
+                Some(released) => {
+                    if released {
+                        let _ = state
+                            .session_events
+                            .send(WsServerMsg::control_changed(session_id, None).to_value());
+                    }
+                    WsServerMsg::SessionReleaseControlAck {
+                        session_id: session_id.to_string(),
+                        released,
+                        request_id: request_id.clone(),
+                    }
+                }
+                None => not_found(),
+            }
+        }
+        // session.release_client: no reply
+        _ => {
+            let holder = format!("relay:{}", msg["client_id"].as_str().unwrap_or(""));
+            release_controls(state, |h| h == holder).await;
+            return;
+        }
+    };
+    send_response_async(ws_sink, reply.to_value()).await;
+}
+
+/// Release session input held by matching holders and announce it.
+async fn release_controls(state: &AppState, holder: impl Fn(&str) -> bool) {
+    for session_id in state.session_manager.release_controls_where(holder).await {
+        let _ = state
+            .session_events
+            .send(crate::ws::messages::WsServerMsg::control_changed(&session_id, None).to_value());
+    }
+}
+
+/// Convert an `OutputEntry` to a WS JSON message (same as `ws/mod.rs`).
+fn entry_to_ws_message(session_id: &str, entry: &OutputEntry) -> Value {
+    let mut msg = json!({
+        "type": format!("session.{}", entry.stream.as_str()),
+        "session_id": session_id,
+        "data": entry.data,
+        "seq": entry.seq,
+        "timestamp_ms": entry.timestamp_ms,
+    });
+    if let Some(raw) = &entry.raw {
+        msg["data_b64"] = json!(base64::engine::general_purpose::STANDARD.encode(raw));
+    }
+    msg
+}
+
+fn batch_output_entries(
+    session_id: &str,
+    entries: &[OutputEntry],
+    max_entries: usize,
+    max_bytes: usize,
+) -> Vec<String> {
+    let mut batched = Vec::new();
+    let mut current_stream = None;
+    let mut current_data = String::new();
+    let mut current_raw: Option<Vec<u8>> = None;
+    let mut current_seq = 0u64;
+    let mut current_timestamp_ms = 0u64;
+    let mut current_count = 0usize;
+
+    let flush_current = |batched: &mut Vec<String>,
+                         current_stream: &mut Option<crate::sessions::buffer::OutputStream>,
+                         current_data: &mut String,
+                         current_raw: &mut Option<Vec<u8>>,
+                         current_seq: &mut u64,
+                         current_timestamp_ms: &mut u64,
+                         current_count: &mut usize| {
+        if let Some(stream) = current_stream.take() {
+            let mut msg = json!({
+                "type": format!("session.{}", stream.as_str()),
+                "session_id": session_id,
+                "data": std::mem::take(current_data),
+                "seq": *current_seq,
+                "timestamp_ms": *current_timestamp_ms,
+            });
+            if let Some(raw) = current_raw.take() {
+                msg["data_b64"] = json!(base64::engine::general_purpose::STANDARD.encode(raw));
+            }
+            let text = serde_json::to_string(&msg)
+                .unwrap_or_else(|_| r#"{"type":"error","message":"serialize failed"}"#.to_string());
+            batched.push(text);
+            *current_count = 0;
+        }
+    };
+
+    for entry in entries {
+        let same_stream = current_stream == Some(entry.stream);
+        let fits_bytes = current_data.len() + entry.data.len() <= max_bytes;
+        let fits_count = current_count < max_entries;
+        // Raw bytes only make sense for a run of entries that all carry them.
+        let same_raw = current_count == 0 || current_raw.is_some() == entry.raw.is_some();
+        if !same_stream || !fits_bytes || !fits_count || !same_raw {
+            flush_current(
+                &mut batched,
+                &mut current_stream,
+                &mut current_data,
+                &mut current_raw,
+                &mut current_seq,
+                &mut current_timestamp_ms,
+                &mut current_count,
+            );
+            current_stream = Some(entry.stream);
+        }
+        current_data.push_str(&entry.data);
+        if let Some(raw) = &entry.raw {
+            current_raw
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(raw);
+        }
+        current_seq = entry.seq;
+        current_timestamp_ms = entry.timestamp_ms;
+        current_count += 1;
+    }
+
+    flush_current(
+        &mut batched,
+        &mut current_stream,
+        &mut current_data,
+        &mut current_raw,
+        &mut current_seq,
+        &mut current_timestamp_ms,
+        &mut current_count,
+    );
+    batched
+}
+
+/// Handle `tunnel.file.xattrs` via the REST handler.
+async fn handle_tunnel_file_xattrs(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let result = match tunnel_route_input(msg) {
+        Ok(body) => {
+            crate::routes::files::put_xattrs(
+                axum::extract::State(state.clone()),
+                tunnel_headers(msg),
+                axum::Json(body),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    send_route_result(ws_sink, "tunnel.file.xattrs.result", request_id, result).await;
+}
+
+/// Handle `tunnel.file.copy` via the REST handler.
+async fn handle_tunnel_file_copy(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let result = match tunnel_route_input(msg) {
+        Ok(body) => {
+            crate::routes::copy::copy_file(
+                axum::extract::State(state.clone()),
+                tunnel_headers(msg),
+                axum::Json(body),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    send_route_result(ws_sink, "tunnel.file.copy.result", request_id, result).await;
+}
+
+/// Handle `tunnel.ai.{status,disable,enable}` via the REST handlers.
+async fn handle_tunnel_ai(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::ai;
+    use axum::extract::State;
+
+    let result = match msg_type {
+        "tunnel.ai.status" => Ok(ai::status(State(state.clone())).await),
+        "tunnel.ai.disable" => match tunnel_route_input(msg) {
+            Ok(body) => {
+                Ok(ai::disable(State(state.clone()), tunnel_headers(msg), axum::Json(body)).await)
+            }
+            Err(e) => Err(e),
+        },
+        _ => ai::enable(State(state.clone()), tunnel_headers(msg)).await,
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.ssh.keys.{list,add,delete}` via the REST handlers.
+async fn handle_tunnel_ssh_keys(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::ssh;
+    use axum::extract::{Query, State};
+
+    let headers = tunnel_headers(msg);
+    let st = State(state.clone());
+    let result = match msg_type {
+        "tunnel.ssh.keys.list" => match tunnel_route_input(msg) {
+            Ok(q) => ssh::list_keys(st, headers, Query(q)).await,
+            Err(e) => Err(e),
+        },
+        "tunnel.ssh.keys.add" => match tunnel_route_input(msg) {
+            Ok(body) => ssh::add_keys(st, headers, axum::Json(body)).await,
+            Err(e) => Err(e),
+        },
+        _ => match tunnel_route_input(msg) {
+            Ok(q) => ssh::delete_key(st, headers, Query(q)).await,
+            Err(e) => Err(e),
+        },
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.users.{list,lock,unlock,reset_password}` via the REST handlers.
+async fn handle_tunnel_users(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::users;
+    use axum::extract::{Path, State};
+
+    let headers = tunnel_headers(msg);
+    let st = State(state.clone());
+    let name = Path(msg["name"].as_str().unwrap_or_default().to_string());
+    let result = match msg_type {
+        "tunnel.users.list" => users::list_users(st, headers).await,
+        "tunnel.users.lock" => match tunnel_route_input(msg) {
+            Ok(body) => users::lock_user(st, headers, name, axum::Json(body)).await,
+            Err(e) => Err(e),
+        },
+        "tunnel.users.unlock" => match tunnel_route_input(msg) {
+            Ok(body) => users::unlock_user(st, headers, name, axum::Json(body)).await,
+            Err(e) => Err(e),
+        },
+        _ => match tunnel_route_input(msg) {
+            Ok(body) => users::reset_password(st, headers, name, axum::Json(body)).await,
+            Err(e) => Err(e),
+        },
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.firewall.{get,apply}` via the REST handlers.
+async fn handle_tunnel_firewall(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::firewall;
+    use axum::extract::State;
+
+    let result = if msg_type == "tunnel.firewall.get" {
+        firewall::get_firewall(State(state.clone())).await
+    } else {
+        match tunnel_route_input(msg) {
+            Ok(body) => {
+                firewall::apply_firewall(
+                    State(state.clone()),
+                    tunnel_headers(msg),
+                    axum::Json(body),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        }
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.hooks.{get,reload}` via the REST handlers.
+async fn handle_tunnel_hooks(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::hooks;
+    use axum::extract::State;
+
+    let result = if msg_type == "tunnel.hooks.get" {
+        Ok(hooks::get_hooks(State(state.clone())).await)
+    } else {
+        hooks::reload_hooks(State(state.clone())).await
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.twin.{get,put,reconcile}` via the REST handlers. The
+/// document's fields arrive at the top level of a `put` message, next to
+/// the envelope fields.
+async fn handle_tunnel_twin(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::twin;
+    use axum::extract::State;
+
+    let headers = tunnel_headers(msg);
+    let result = match msg_type {
+        "tunnel.twin.get" => Ok(twin::get_twin(State(state.clone())).await),
+        "tunnel.twin.put" => {
+            let mut doc = msg.clone();
+            if let Some(fields) = doc.as_object_mut() {
+                fields.retain(|k, _| {
+                    !matches!(
+                        k.as_str(),
+                        "type" | "request_id" | "_source" | "deadline_in_ms"
+                    )
+                });
+            }
+            twin::put(state, &headers, doc).await
+        }
+        _ => {
+            let payload = twin::ReconcileRequest {
+                apply: msg["apply"].as_bool(),
+            };
+            twin::run_reconcile(state, &headers, &payload).await
+        }
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
+
+/// Handle `tunnel.fetch` via the REST handler; the request's fields are at
+/// the top level of the message.
+async fn handle_tunnel_fetch(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let result = match serde_json::from_value(msg.clone()) {
+        Ok(req) => crate::routes::fetch::run(state, &tunnel_headers(msg), req).await,
+        Err(e) => Err(ApiError::new(codes::INVALID_REQUEST, e.to_string())
+            .into_response_with(axum::http::StatusCode::BAD_REQUEST)),
+    };
+    send_route_result(ws_sink, "tunnel.fetch.result", request_id, result).await;
+}
+
+/// Handle `tunnel.artifacts.push` via the REST handler; the request's fields
+/// are at the top level of the message.
+async fn handle_tunnel_artifacts_push(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let result = match serde_json::from_value(msg.clone()) {
+        Ok(req) => crate::routes::artifacts::run(state, &tunnel_headers(msg), req).await,
+        Err(e) => Err(ApiError::new(codes::INVALID_REQUEST, e.to_string())
+            .into_response_with(axum::http::StatusCode::BAD_REQUEST)),
+    };
+    send_route_result(ws_sink, "tunnel.artifacts.push.result", request_id, result).await;
+}
+
+/// Handle `tunnel.snapshots.{create,list,get,delete,diff}` and
+/// `tunnel.info.diff` via the REST handlers. Path parameters arrive as `id`,
+/// `a` and `b`.
+async fn handle_tunnel_snapshots(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::snapshots;
+    use axum::extract::{Path, Query, State};
+
+    let field = |name: &str| msg[name].as_str().unwrap_or_default().to_string();
+    let result = match msg_type {
+        "tunnel.snapshots.create" => match tunnel_route_input(msg) {
+            Ok(req) => snapshots::create(state, &tunnel_headers(msg), req).await,
+            Err(e) => Err(e),
+        },
+        "tunnel.snapshots.list" => Ok(snapshots::list_snapshots(State(state.clone())).await),
+        "tunnel.snapshots.get" => snapshots::get_snapshot(State(state.clone()), Path(field("id")))
+            .await
+            .map(|axum::Json(s)| axum::Json(json!(s))),
+        "tunnel.snapshots.delete" => {
+            snapshots::delete_snapshot(State(state.clone()), Path(field("id"))).await
+        }
+        "tunnel.snapshots.diff" => {
+            snapshots::diff_snapshots(State(state.clone()), Path((field("a"), field("b")))).await
         }
-    }
+        _ => match tunnel_route_input(msg) {
+            Ok(query) => snapshots::info_diff(State(state.clone()), Query(query)).await,
+            Err(e) => Err(e),
+        },
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
 }
 
-/// Convert an `OutputEntry` to a WS JSON message (same as `ws/mod.rs`).
-fn entry_to_ws_message(session_id: &str, entry: &OutputEntry) -> Value {
-    json!({
-        "type": format!("session.{}", entry.stream.as_str()),
-        "session_id": session_id,
-        "data": entry.data,
-        "seq": entry.seq,
-        "timestamp_ms": entry.timestamp_ms,
-    })
-}
+/// Handle `tunnel.plugins.{list,get}` via the REST handlers.
+async fn handle_tunnel_plugins(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::plugins;
+    use axum::extract::{Path, State};
 
-fn batch_output_entries(session_id: &str, entries: &[OutputEntry]) -> Vec<String> {
-    let mut batched = Vec::new();
-    let mut current_stream = None;
-    let mut current_data = String::new();
-    let mut current_seq = 0u64;
-    let mut current_timestamp_ms = 0u64;
-    let mut current_count = 0usize;
+    let result = if msg_type == "tunnel.plugins.list" {
+        Ok(plugins::list_plugins(State(state.clone())).await)
+    } else {
+        let name = msg["name"].as_str().unwrap_or_default().to_string();
+        plugins::get_plugin(State(state.clone()), Path(name)).await
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
 
-    let flush_current = |batched: &mut Vec<String>,
-                         current_stream: &mut Option<crate::sessions::buffer::OutputStream>,
-                         current_data: &mut String,
-                         current_seq: &mut u64,
-                         current_timestamp_ms: &mut u64,
-                         current_count: &mut usize| {
-        if let Some(stream) = current_stream.take() {
-            let msg = json!({
-                "type": format!("session.{}", stream.as_str()),
-                "session_id": session_id,
-                "data": std::mem::take(current_data),
-                "seq": *current_seq,
-                "timestamp_ms": *current_timestamp_ms,
-            });
-            let text = serde_json::to_string(&msg)
-                .unwrap_or_else(|_| r#"{"type":"error","message":"serialize failed"}"#.to_string());
-            batched.push(text);
-            *current_count = 0;
+/// Handle `plugin.<name>.<command>`: run the command with the message's
+/// `args` and reply with `<type>.result`.
+async fn handle_tunnel_plugin_run(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    let result = match crate::plugins::parse_message_type(msg_type) {
+        Some((name, command)) => {
+            crate::routes::plugins::run(
+                state,
+                &tunnel_headers(msg),
+                name,
+                command,
+                msg["args"].clone(),
+            )
+            .await
         }
+        None => Err(ApiError::new(
+            crate::error::codes::NOT_FOUND,
+            format!("Invalid plugin message type: {msg_type}"),
+        )
+        .into_response_with(axum::http::StatusCode::NOT_FOUND)),
     };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
 
-    for entry in entries {
-        let same_stream = current_stream == Some(entry.stream);
-        let fits_bytes = current_data.len() + entry.data.len() <= TUNNEL_STREAM_BATCH_MAX_BYTES;
-        let fits_count = current_count < TUNNEL_STREAM_BATCH_MAX_ENTRIES;
-        if !same_stream || !fits_bytes || !fits_count {
-            flush_current(
-                &mut batched,
-                &mut current_stream,
-                &mut current_data,
-                &mut current_seq,
-                &mut current_timestamp_ms,
-                &mut current_count,
-            );
-            current_stream = Some(entry.stream);
+/// Handle `tunnel.time.{get,set}` via the REST handlers.
+async fn handle_tunnel_time(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::time;
+
+    let result = if msg_type == "tunnel.time.get" {
+        Ok(time::get_time().await)
+    } else {
+        match tunnel_route_input(msg) {
+            Ok(body) => {
+                time::set_time(
+                    axum::extract::State(state.clone()),
+                    tunnel_headers(msg),
+                    axum::Json(body),
+                )
+                .await
+            }
+            Err(e) => Err(e),
         }
-        current_data.push_str(&entry.data);
-        current_seq = entry.seq;
-        current_timestamp_ms = entry.timestamp_ms;
-        current_count += 1;
-    }
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
+}
 
-    flush_current(
-        &mut batched,
-        &mut current_stream,
-        &mut current_data,
-        &mut current_seq,
-        &mut current_timestamp_ms,
-        &mut current_count,
-    );
-    batched
+/// Handle `tunnel.playbooks.{run,runs,runs.get}` via the REST handlers.
+async fn handle_tunnel_playbook_runs(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    msg_type: &str,
+    request_id: Option<&str>,
+) {
+    use crate::routes::playbooks;
+    use axum::extract::{Path, State};
+
+    let name = msg["name"].as_str().unwrap_or_default().to_string();
+    let result = match msg_type {
+        "tunnel.playbooks.run" => match serde_json::from_value(msg.clone()) {
+            Ok(payload) => playbooks::run(state, &tunnel_headers(msg), &name, payload)
+                .await
+                .map(|axum::Json(report)| axum::Json(json!(report))),
+            Err(e) => Err(ApiError::new(
+                crate::error::codes::INVALID_REQUEST,
+                format!("Invalid request: {e}"),
+            )
+            .into_response_with(axum::http::StatusCode::BAD_REQUEST)),
+        },
+        "tunnel.playbooks.runs" => playbooks::list_runs(State(state.clone()), Path(name)).await,
+        _ => {
+            let run_id = msg["run_id"].as_str().unwrap_or_default().to_string();
+            playbooks::get_run(State(state.clone()), Path((name, run_id)))
+                .await
+                .map(|axum::Json(report)| axum::Json(json!(report)))
+        }
+    };
+    send_route_result(ws_sink, &format!("{msg_type}.result"), request_id, result).await;
 }
 
 /// Handle `tunnel.playbooks.list`
@@ -3294,6 +6099,7 @@ async fn handle_tunnel_playbooks_delete(
 }
 
 /// Handle `tunnel.gps` — GPS location data.
+#[cfg(feature = "comms")]
 async fn handle_tunnel_gps(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
     match crate::routes::gps::gps(axum::extract::State(state.clone())).await {
         Ok(axum::Json(body)) => {
@@ -3323,7 +6129,79 @@ async fn handle_tunnel_gps(state: &AppState, ws_sink: &WsSink, request_id: Optio
     }
 }
 
+/// Handle `tunnel.gps.track` — the recorded GPS track as GeoJSON.
+#[cfg(feature = "comms")]
+async fn handle_tunnel_gps_track(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let query = crate::routes::gps::TrackQuery {
+        since: msg["since"].as_u64(),
+        until: msg["until"].as_u64(),
+        limit: msg["limit"]
+            .as_u64()
+            .map(|n| usize::try_from(n).unwrap_or(usize::MAX)),
+    };
+    let (status, body) = match crate::routes::gps::track(
+        axum::extract::State(state.clone()),
+        axum::extract::Query(query),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.gps.track.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
+/// Handle `tunnel.lte.history` — recorded LTE signal samples.
+#[cfg(feature = "comms")]
+async fn handle_tunnel_lte_history(
+    state: &AppState,
+    ws_sink: &WsSink,
+    msg: &Value,
+    request_id: Option<&str>,
+) {
+    let query = crate::routes::lte::HistoryQuery {
+        since: msg["since"].as_u64(),
+        limit: msg["limit"]
+            .as_u64()
+            .map(|n| usize::try_from(n).unwrap_or(usize::MAX)),
+    };
+    let (status, body) = match crate::routes::lte::history(
+        axum::extract::State(state.clone()),
+        axum::extract::Query(query),
+    )
+    .await
+    {
+        Ok(axum::Json(body)) => (200, body),
+        Err((status, axum::Json(body))) => (status.as_u16(), json!(body)),
+    };
+    send_response_async(
+        ws_sink,
+        json!({
+            "type": "tunnel.lte.history.result",
+            "request_id": request_id,
+            "status": status,
+            "body": body,
+        }),
+    )
+    .await;
+}
+
 /// Handle `tunnel.lte` — LTE signal and modem data.
+#[cfg(feature = "comms")]
 async fn handle_tunnel_lte(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
     match crate::routes::lte::lte(
         axum::extract::State(state.clone()),
@@ -3359,6 +6237,7 @@ async fn handle_tunnel_lte(state: &AppState, ws_sink: &WsSink, request_id: Optio
 }
 
 /// Handle `tunnel.lte.bands` — set LTE band configuration.
+#[cfg(feature = "comms")]
 async fn handle_tunnel_lte_bands(
     state: &AppState,
     ws_sink: &WsSink,
@@ -3413,6 +6292,7 @@ async fn handle_tunnel_lte_bands(
 }
 
 /// Handle `tunnel.lte.scan` — start a background band scan.
+#[cfg(feature = "comms")]
 async fn handle_tunnel_lte_scan(
     state: &AppState,
     ws_sink: &WsSink,
@@ -3467,6 +6347,7 @@ async fn handle_tunnel_lte_scan(
 }
 
 /// Handle `tunnel.lte.speedtest` — run a quick download+upload speed test.
+#[cfg(feature = "comms")]
 async fn handle_tunnel_lte_speedtest(state: &AppState, ws_sink: &WsSink, request_id: Option<&str>) {
     match crate::routes::lte::speed_test(axum::extract::State(state.clone())).await {
         Ok(axum::Json(body)) => {
@@ -3755,7 +6636,12 @@ async fn tunnel_subscriber_task(
     let mut logged_first_output = false;
     let mut backpressure_active = false;
     loop {
-        let (entries, notify) = {
+        // Coalesce adjacent PTY output chunks into larger tunnel frames. LTE
+        // links are much less tolerant of hundreds of tiny JSON WS frames than
+        // a handful of larger ones carrying the same bytes.
+        let (max_entries, max_bytes, coalesce_ms) =
+            state.tunnel_stats.link_quality().stream_batch_limits();
+        let (mut entries, notify) = {
             let buf = buffer.lock().await;
             if buf.has_entries_since(cursor) {
                 let (entries, _dropped) = buf.read_since(cursor);
@@ -3764,6 +6650,16 @@ async fn tunnel_subscriber_task(
                 (vec![], Some(buf.notifier()))
             }
         };
+        // Output is waiting but doesn't fill a frame yet: give the PTY a
+        // moment to produce more so it leaves in fewer, larger frames.
+        if coalesce_ms > 0
+            && !entries.is_empty()
+            && entries.len() < max_entries
+            && entries.iter().map(|e| e.data.len()).sum::<usize>() < max_bytes
+        {
+            tokio::time::sleep(Duration::from_millis(coalesce_ms)).await;
+            entries = buffer.lock().await.read_since(cursor).0;
+        }
         if !entries.is_empty() {
             if !logged_first_output {
                 logged_first_output = true;
@@ -3773,7 +6669,8 @@ async fn tunnel_subscriber_task(
                     "Tunnel: session subscriber emitting first output"
                 );
             }
-            let batched_messages = batch_output_entries(&session_id, &entries);
+            let batched_messages =
+                batch_output_entries(&session_id, &entries, max_entries, max_bytes);
             for text in batched_messages {
                 let stream_capacity = ws_sink.stream_tx.capacity();
                 if stream_capacity == 0 && !backpressure_active {
@@ -3813,3 +6710,435 @@ async fn tunnel_subscriber_task(
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    use crate::tunnel::relay::{relay_router, RelayState};
+
+    const SERIAL: &str = "RELAY-TEST-1";
+    const API_KEY: &str = "relay-test-api-key";
+    const TUNNEL_KEY: &str = "relay-test-tunnel-key";
+
+    fn seqs(events: &[Value]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|e| e["event_seq"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn outbox_keeps_only_unacked_events() {
+        let outbox = EventOutbox::new();
+        for n in 1..=3 {
+            let event = outbox.push(json!({"type": "session.closed", "n": n})).await;
+            assert_eq!(event["event_seq"], n);
+        }
+        outbox.ack(2).await;
+        let due = outbox.due(Duration::ZERO).await;
+        assert_eq!(seqs(&due), [1, 3]);
+        assert_eq!(due[1]["n"], 3);
+
+        outbox.ack(1).await;
+        outbox.ack(3).await;
+        assert!(outbox.due(Duration::ZERO).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn outbox_resends_after_reconnect_once_the_relay_acks() {
+        let outbox = EventOutbox::new();
+        outbox.push(json!({"type": "session.closed"})).await;
+        outbox.push(json!({"type": "session.closed"})).await;
+        // A relay that never acked would rebroadcast resends as new events.
+        assert!(outbox.due(Duration::ZERO).await.is_empty());
+
+        outbox.ack(1).await;
+        // Reconnect: everything unacked goes out again with its old seq.
+        assert_eq!(seqs(&outbox.due(Duration::ZERO).await), [2]);
+        // Just resent, so not due again until EVENT_RESEND_AFTER.
+        assert!(outbox.due(EVENT_RESEND_AFTER).await.is_empty());
+        assert_eq!(seqs(&outbox.due(Duration::ZERO).await), [2]);
+    }
+
+    #[tokio::test]
+    async fn full_outbox_drops_its_oldest_event() {
+        let outbox = EventOutbox::new();
+        let total = EVENT_OUTBOX_CAPACITY as u64 + 2;
+        for _ in 0..total {
+            outbox.push(json!({"type": "transfer.progress"})).await;
+        }
+        outbox.ack(0).await;
+        let due = seqs(&outbox.due(Duration::ZERO).await);
+        assert_eq!(due.len(), EVENT_OUTBOX_CAPACITY);
+        assert_eq!(due.first(), Some(&3));
+        assert_eq!(due.last(), Some(&total));
+    }
+
+    #[tokio::test]
+    async fn diag_reports_batching_outbox_and_spool() {
+        let (config, dir) = crate::server::testing::config("tunnel-diag");
+        let server = crate::server::ServerBuilder::new(config).build().await;
+        let mut state = server.state.clone();
+        let spool = Arc::new(super::super::spool::OfflineSpool::open(
+            dir.to_str().unwrap(),
+            10,
+        ));
+        spool.append(&json!({"type": "session.closed"})).await;
+        state.offline_spool = Some(spool);
+        state
+            .tunnel_stats
+            .quality_score
+            .store(20, Ordering::Relaxed);
+
+        let outbox = Arc::new(EventOutbox::new());
+        outbox.push(json!({"type": "session.started"})).await;
+        outbox.push(json!({"type": "session.closed"})).await;
+        let (request_tx, mut request_rx) = mpsc::channel(4);
+        let ws_sink = WsSink {
+            priority_tx: mpsc::channel(1).0,
+            request_tx,
+            stream_tx: mpsc::channel(1).0,
+            dedup: None,
+            outbox,
+        };
+        handle_tunnel_diag(&state, &ws_sink, Some("diag-1")).await;
+        let Some(tokio_tungstenite::tungstenite::Message::Text(text)) = request_rx.recv().await
+        else {
+            panic!("no diag reply");
+        };
+        let reply: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(reply["type"], "tunnel.diag.result");
+        assert_eq!(reply["request_id"], "diag-1");
+
+        let body = &reply["body"];
+        assert_eq!(body["quality"]["score"], 20);
+        assert_eq!(body["quality"]["level"], "poor");
+        let (max_entries, max_bytes, coalesce_ms) =
+            crate::state::LinkQuality::Poor.stream_batch_limits();
+        assert_eq!(
+            body["quality"]["stream_batch"],
+            json!({
+                "max_entries": max_entries,
+                "max_bytes": max_bytes,
+                "coalesce_ms": coalesce_ms,
+            })
+        );
+        assert_eq!(
+            body["outbox"],
+            json!({ "unacked": 2, "capacity": EVENT_OUTBOX_CAPACITY })
+        );
+        assert_eq!(body["spool"], json!({ "entries": 1, "dropped": 0 }));
+        assert!(body["stats"]["connected"].is_boolean());
+        assert!(body["rtt"]["history_ms"].is_array());
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    struct TestRelay {
+        state: RelayState,
+        router: axum::Router,
+        addr: std::net::SocketAddr,
+    }
+
+    async fn start_relay() -> TestRelay {
+        let state = RelayState::new(TUNNEL_KEY.to_string(), 20, 60, None);
+        let router = relay_router(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router.clone();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        TestRelay {
+            state,
+            router,
+            addr,
+        }
+    }
+
+    /// A device registered with every relay in `relays`; the first is `url`.
+    async fn start_device(
+        name: &str,
+        relays: &[&TestRelay],
+    ) -> (crate::server::Server, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("sctl-relay-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let urls: Vec<String> = relays
+            .iter()
+            .map(|r| format!("ws://{}/api/tunnel/register", r.addr))
+            .collect();
+        let data_dir = dir.to_str().unwrap();
+        let config_path = dir.join("sctl.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "[server]\ndata_dir = {data_dir:?}\n\n[auth]\napi_key = {API_KEY:?}\n\n\
+                 [device]\nserial = {SERIAL:?}\n\n[tunnel]\ntunnel_key = {TUNNEL_KEY:?}\n\
+                 url = {:?}\nextra_urls = {:?}\n",
+                urls[0],
+                &urls[1..],
+            ),
+        )
+        .unwrap();
+        let config = crate::config::Config::load(config_path.to_str());
+        let server = crate::server::ServerBuilder::new(config).build().await;
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            for relay in relays {
+                while !relay.state.devices.read().await.contains_key(SERIAL) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        })
+        .await
+        .expect("device registered with every relay");
+        (server, dir)
+    }
+
+    fn exec_request(command: &str, idempotency_key: Option<&str>) -> Request<Body> {
+        let mut builder = Request::post(format!("/d/{SERIAL}/api/exec"))
+            .header("authorization", format!("Bearer {API_KEY}"))
+            .header("content-type", "application/json")
+            .header("x-request-id", "same-everywhere");
+        if let Some(key) = idempotency_key {
+            builder = builder.header("idempotency-key", key);
+        }
+        builder
+            .body(Body::from(json!({ "command": command }).to_string()))
+            .unwrap()
+    }
+
+    async fn body_json(response: axum::response::Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_sent_through_two_relays_runs_once() {
+        let (relay_a, relay_b) = (start_relay().await, start_relay().await);
+        let (server, dir) = start_device("dedup", &[&relay_a, &relay_b]).await;
+
+        let out = dir.join("runs");
+        let command = format!("echo run >> '{}'; date +%s%N", out.display());
+        let first = relay_a
+            .router
+            .clone()
+            .oneshot(exec_request(&command, Some("retry-1")))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), axum::http::StatusCode::OK);
+        let first = body_json(first).await;
+
+        // The retry through the other relay gets the first response back.
+        let retry = relay_b
+            .router
+            .clone()
+            .oneshot(exec_request(&command, Some("retry-1")))
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), axum::http::StatusCode::OK);
+        assert_eq!(body_json(retry).await, first);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "run\n");
+
+        // A different key through the same relay still runs, and so do
+        // requests that only share an X-Request-Id.
+        for key in [Some("retry-2"), None, None] {
+            let other = relay_b
+                .router
+                .clone()
+                .oneshot(exec_request(&command, key))
+                .await
+                .unwrap();
+            assert_eq!(other.status(), axum::http::StatusCode::OK);
+        }
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "run\n".repeat(4));
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_a_running_request_is_refused() {
+        let (relay_a, relay_b) = (start_relay().await, start_relay().await);
+        let (server, dir) = start_device("dedup-running", &[&relay_a, &relay_b]).await;
+
+        let slow = relay_a
+            .router
+            .clone()
+            .oneshot(exec_request("sleep 1", Some("slow-1")));
+        let slow = tokio::spawn(slow);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let retry = relay_b
+            .router
+            .clone()
+            .oneshot(exec_request("sleep 1", Some("slow-1")))
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), axum::http::StatusCode::CONFLICT);
+        assert_eq!(body_json(retry).await["code"], "DUPLICATE_IN_FLIGHT");
+        let slow = slow.await.unwrap().unwrap();
+        assert_eq!(slow.status(), axum::http::StatusCode::OK);
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn duplicate_of_a_binary_chunk_is_not_left_in_flight() {
+        let (relay_a, relay_b) = (start_relay().await, start_relay().await);
+        let (server, dir) = start_device("dedup-chunk", &[&relay_a, &relay_b]).await;
+        let file = dir.join("payload");
+        std::fs::write(&file, b"chunk").unwrap();
+
+        let init = Request::post(format!("/d/{SERIAL}/api/stp/download"))
+            .header("authorization", format!("Bearer {API_KEY}"))
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "path": file }).to_string()))
+            .unwrap();
+        let init = relay_a.router.clone().oneshot(init).await.unwrap();
+        assert_eq!(init.status(), axum::http::StatusCode::OK);
+        let transfer_id = body_json(init).await["transfer_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let chunk = || {
+            Request::get(format!("/d/{SERIAL}/api/stp/chunk/{transfer_id}/0"))
+                .header("authorization", format!("Bearer {API_KEY}"))
+                .header("idempotency-key", "chunk-1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let first = relay_a.router.clone().oneshot(chunk()).await.unwrap();
+        assert_eq!(first.status(), axum::http::StatusCode::OK);
+        // The binary reply isn't kept, but the first copy is over.
+        let retry = relay_b.router.clone().oneshot(chunk()).await.unwrap();
+        assert_eq!(retry.status(), axum::http::StatusCode::CONFLICT);
+        assert_eq!(body_json(retry).await["code"], "DUPLICATE_REQUEST");
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_ends_its_first_copy() {
+        let dedup = Arc::new(RequestDedup::new());
+        let (seen, running) = dedup.check("exec:k", "rid-1");
+        assert!(matches!(seen, Seen::First));
+        assert!(matches!(dedup.check("exec:k", "rid-2").0, Seen::InFlight));
+
+        handle_relay_task(running, Arc::new(Semaphore::new(1)), async {
+            panic!("handler bug");
+        })
+        .await;
+        assert!(matches!(dedup.check("exec:k", "rid-3").0, Seen::Done(None)));
+    }
+
+    /// Send one message over a new WS connection and return the reply.
+    async fn ws_round_trip(url: &str, msg: &Value) -> Value {
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        ws.send(tokio_tungstenite::tungstenite::Message::Text(
+            msg.to_string().into(),
+        ))
+        .await
+        .unwrap();
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), ws.next())
+                .await
+                .expect("reply in time")
+                .unwrap()
+                .unwrap();
+            if let tokio_tungstenite::tungstenite::Message::Text(text) = frame {
+                let reply: Value = serde_json::from_str(&text).unwrap();
+                if reply["request_id"] == msg["request_id"] {
+                    return reply;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn session_exec_is_refused_while_ai_is_disabled() {
+        let relay = start_relay().await;
+        let (server, dir) = start_device("ai-guard", &[&relay]).await;
+        server.state.ai_guard.disable(None, "rest").await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device_addr = listener.local_addr().unwrap();
+        let app = server.router();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let exec = json!({
+            "type": "session.exec",
+            "session_id": "no-such-session",
+            "command": "true",
+            "request_id": "r1",
+        });
+        let list = json!({ "type": "session.list", "request_id": "r2" });
+        let direct = format!("ws://{device_addr}/api/ws?token={API_KEY}");
+        let relayed = format!("ws://{}/d/{SERIAL}/api/ws?token={API_KEY}", relay.addr);
+        for url in [direct, relayed] {
+            let reply = ws_round_trip(&format!("{url}&client=mcp"), &exec).await;
+            assert_eq!(reply["type"], "error", "{url}");
+            assert_eq!(reply["code"], "AI_DISABLED", "{url}");
+
+            // Reads still work, and operators aren't affected.
+            let reply = ws_round_trip(&format!("{url}&client=mcp"), &list).await;
+            assert_eq!(reply["type"], "session.listed", "{url}");
+            let reply = ws_round_trip(&url, &exec).await;
+            assert_eq!(reply["code"], "SESSION_ERROR", "{url}");
+        }
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn sftp_is_refused_to_ai_while_disabled() {
+        let relay = start_relay().await;
+        let (server, dir) = start_device("ai-sftp", &[&relay]).await;
+        server.state.ai_guard.disable(None, "rest").await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let device_addr = listener.local_addr().unwrap();
+        let app = server.router();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let direct = format!("ws://{device_addr}/api/sftp?token={API_KEY}");
+        let relayed = format!("ws://{}/d/{SERIAL}/api/sftp?token={API_KEY}", relay.addr);
+        for url in [direct, relayed] {
+            let err = tokio_tungstenite::connect_async(format!("{url}&client=mcp"))
+                .await
+                .unwrap_err();
+            let tokio_tungstenite::tungstenite::Error::Http(response) = err else {
+                panic!("{url}: {err}");
+            };
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::FORBIDDEN,
+                "{url}"
+            );
+            let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default());
+            assert!(body.contains("AI_DISABLED"), "{url}: {body}");
+
+            // Operators get past the guard (to "not enabled", as there's
+            // no [sftp] section).
+            let err = tokio_tungstenite::connect_async(&url).await.unwrap_err();
+            let tokio_tungstenite::tungstenite::Error::Http(response) = err else {
+                panic!("{url}: {err}");
+            };
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::NOT_FOUND,
+                "{url}"
+            );
+        }
+
+        server.shutdown().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
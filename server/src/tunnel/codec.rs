@@ -0,0 +1,74 @@
+//! MessagePack encoding for tunnel messages.
+//!
+//! Tunnel messages are JSON text frames by default. On a constrained link
+//! the JSON framing is a good share of small, frequent messages (session
+//! output, activity polls, pings), so a device with `[tunnel] encoding =
+//! "msgpack"` offers it in `tunnel.register` (`"encodings": ["msgpack"]`).
+//! A relay that supports it answers with `"encoding": "msgpack"` in
+//! `tunnel.register.ack`, and from then on both sides send each message as
+//! a binary frame holding the same object in MessagePack. A relay that
+//! doesn't know the field ignores it and the connection stays on JSON.
+//!
+//! Encoded messages share the binary opcode with file-transfer frames
+//! ([`super::encode_binary_frame`]). Those start with a big-endian header
+//! length capped far below 2^24, so their first byte is always 0, while a
+//! MessagePack map never starts with 0. Either side still accepts text
+//! frames, and registration itself is always JSON.
+
+use serde_json::Value;
+
+/// Name of the encoding in `tunnel.register` and its ack.
+pub const MSGPACK: &str = "msgpack";
+
+/// Whether a binary frame holds an encoded message rather than a file frame.
+pub fn is_message(data: &[u8]) -> bool {
+    data.first().is_some_and(|&b| b != 0)
+}
+
+/// `value` as MessagePack, with maps keyed by name.
+pub fn encode(value: &Value) -> Option<Vec<u8>> {
+    rmp_serde::to_vec_named(value).ok()
+}
+
+/// An encoded message back as JSON.
+pub fn decode(data: &[u8]) -> Option<Value> {
+    rmp_serde::from_slice(data).ok()
+}
+
+/// Whether a `tunnel.register` message offers MessagePack.
+pub fn offered(register: &Value) -> bool {
+    register["encodings"]
+        .as_array()
+        .is_some_and(|a| a.iter().any(|e| e == MSGPACK))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_round_trip_and_stay_apart_from_file_frames() {
+        let msg = json!({
+            "type": "session.output",
+            "session_id": "abc",
+            "data": "hello\n",
+            "seq": 42,
+            "big": u64::MAX,
+            "neg": -3,
+            "ratio": 0.5,
+            "none": null,
+            "list": [true, "x"],
+        });
+        let encoded = encode(&msg).unwrap();
+        assert!(is_message(&encoded));
+        assert!(encoded.len() < msg.to_string().len());
+        assert_eq!(decode(&encoded).unwrap(), msg);
+
+        let frame = super::super::encode_binary_frame(&json!({"type": "gx.chunk"}), b"data");
+        assert!(!is_message(&frame));
+
+        assert!(offered(&json!({"encodings": ["msgpack"]})));
+        assert!(!offered(&json!({"type": "tunnel.register"})));
+    }
+}
@@ -0,0 +1,156 @@
+//! Keys a relay issued to this device (client mode).
+//!
+//! A relay with `[tunnel] enrollment_token` sends `tunnel.rotate_key` when a
+//! device first registers with that token, and again whenever an operator
+//! rotates its keys. The device keeps them in
+//! `<data_dir>/tunnel_credentials.json` (mode 0600):
+//!
+//! - a tunnel key per relay URL, used instead of `[tunnel] tunnel_key` from
+//!   the next connection on
+//! - the API key from the primary relay (`[tunnel] url`), used instead of
+//!   `[auth] api_key` from the next start on
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// A tunnel key issued by one relay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayKey {
+    pub tunnel_key: String,
+    /// Unix ms.
+    pub issued_at: u64,
+}
+
+/// Contents of `tunnel_credentials.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Credentials {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Keyed by relay URL.
+    #[serde(default)]
+    pub relays: BTreeMap<String, RelayKey>,
+}
+
+fn path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("tunnel_credentials.json")
+}
+
+impl Credentials {
+    /// Read the stored credentials; empty if there are none or they can't
+    /// be parsed.
+    pub fn load(data_dir: &str) -> Self {
+        let path = path(data_dir);
+        match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), "Ignoring invalid tunnel credentials: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write atomically, readable by the owner only.
+    ///
+    /// # Errors
+    ///
+    /// Creating, writing or renaming the file failed.
+    pub fn save(&self, data_dir: &str) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let path = path(data_dir);
+        std::fs::create_dir_all(data_dir)?;
+        let tmp = path.with_extension("json.tmp");
+        let mut file = crate::util::create_mode(
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true),
+            0o600,
+        )
+        .open(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &path)
+    }
+
+    /// The key issued by the relay at `url`, if any.
+    pub fn tunnel_key(&self, url: &str) -> Option<&str> {
+        self.relays.get(url).map(|k| k.tunnel_key.as_str())
+    }
+
+    /// Record keys issued by the relay at `url`. Serialized so relays
+    /// rotating at the same time don't overwrite each other's keys.
+    ///
+    /// # Errors
+    ///
+    /// The credentials file couldn't be written.
+    pub fn store(
+        data_dir: &str,
+        url: &str,
+        tunnel_key: &str,
+        api_key: Option<&str>,
+    ) -> std::io::Result<()> {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let _guard = LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut creds = Self::load(data_dir);
+        creds.relays.insert(
+            url.to_string(),
+            RelayKey {
+                tunnel_key: tunnel_key.to_string(),
+                issued_at: crate::sessions::journal::now_ms(),
+            },
+        );
+        if let Some(api_key) = api_key {
+            creds.api_key = Some(api_key.to_string());
+        }
+        creds.save(data_dir)
+    }
+
+    /// Replace the stored API key (`POST /api/auth/rotate`).
+    ///
+    /// # Errors
+    ///
+    /// The credentials file couldn't be written.
+    pub fn store_api_key(data_dir: &str, api_key: &str) -> std::io::Result<()> {
+        let _guard = LOCK
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut creds = Self::load(data_dir);
+        creds.api_key = Some(api_key.to_string());
+        creds.save(data_dir)
+    }
+}
+
+/// Serializes read-modify-write of the credentials file.
+static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_keys_per_relay() {
+        let dir = std::env::temp_dir().join(format!("sctl-creds-{}", std::process::id()));
+        let data_dir = dir.to_str().unwrap();
+        assert!(Credentials::load(data_dir).tunnel_key("wss://a").is_none());
+
+        Credentials::store(data_dir, "wss://a", "t", Some("k")).unwrap();
+        Credentials::store(data_dir, "wss://b", "u", None).unwrap();
+
+        let loaded = Credentials::load(data_dir);
+        assert_eq!(loaded.tunnel_key("wss://a"), Some("t"));
+        assert_eq!(loaded.tunnel_key("wss://b"), Some("u"));
+        assert!(loaded.tunnel_key("wss://c").is_none());
+        assert_eq!(loaded.api_key.as_deref(), Some("k"));
+        #[cfg(unix)]
+        {
+            let meta = std::fs::metadata(path(data_dir)).unwrap();
+            assert_eq!(crate::util::file_mode(&meta) & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
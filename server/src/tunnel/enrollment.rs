@@ -0,0 +1,304 @@
+//! Per-device keys on the relay.
+//!
+//! With `[tunnel] enrollment_token` set, a device that registers with that
+//! token instead of `tunnel_key` is enrolled: once its tunnel is up the relay
+//! sends it `tunnel.rotate_key` with a fresh tunnel key and API key, and
+//! after the device confirms it has stored them (see
+//! [`super::credentials`]), records a hash of the tunnel key in
+//! `<data_dir>/relay_enrollments.json`.
+//!
+//! From then on that serial can only register with its own key; neither
+//! `tunnel_key` nor the enrollment token work for it. Rotation repeats the
+//! same exchange, and deleting the enrollment lets the device enroll again
+//! (e.g. after a factory reset).
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use axum::{
+    extract::{Path as AxumPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use super::fleet::{reject_token, TokenQuery};
+use super::relay::{proxy_response_to_http, tunnel_request_json, RelayState};
+use crate::gawdxfer::hasher::hash_bytes;
+
+/// How long the relay waits for the device to store new keys.
+const ROTATE_TIMEOUT_SECS: u64 = 30;
+
+/// An enrolled device. Only a hash of its tunnel key is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enrollment {
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    /// Unix ms.
+    pub enrolled_at: u64,
+    /// Unix ms of the last rotation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotated_at: Option<u64>,
+}
+
+/// Enrolled devices by serial, persisted to `relay_enrollments.json`.
+pub struct Enrollments {
+    path: Option<PathBuf>,
+    records: RwLock<HashMap<String, Enrollment>>,
+}
+
+/// On-disk form; unlike the API it includes `key_hash`.
+#[derive(Serialize, Deserialize)]
+struct StoredEnrollment {
+    key_hash: String,
+    enrolled_at: u64,
+    #[serde(default)]
+    rotated_at: Option<u64>,
+}
+
+impl Enrollments {
+    pub fn load(data_dir: Option<&str>) -> Self {
+        let path = data_dir.map(|d| Path::new(d).join("relay_enrollments.json"));
+        let stored: HashMap<String, StoredEnrollment> = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        if !stored.is_empty() {
+            info!("Loaded {} device enrollment(s)", stored.len());
+        }
+        let records = stored
+            .into_iter()
+            .map(|(serial, s)| {
+                let enrollment = Enrollment {
+                    key_hash: s.key_hash,
+                    enrolled_at: s.enrolled_at,
+                    rotated_at: s.rotated_at,
+                };
+                (serial, enrollment)
+            })
+            .collect();
+        Self {
+            path,
+            records: RwLock::new(records),
+        }
+    }
+
+    /// `None` if `serial` isn't enrolled, else whether `key` is its key.
+    pub async fn verify(&self, serial: &str, key: &str) -> Option<bool> {
+        let records = self.records.read().await;
+        let record = records.get(serial)?;
+        Some(crate::auth::constant_time_eq(
+            record.key_hash.as_bytes(),
+            hash_bytes(key.as_bytes()).as_bytes(),
+        ))
+    }
+
+    pub async fn list(&self) -> HashMap<String, Enrollment> {
+        self.records.read().await.clone()
+    }
+
+    /// Record `key` as `serial`'s tunnel key.
+    async fn set(&self, serial: &str, key: &str) -> Enrollment {
+        let now = crate::sessions::journal::now_ms();
+        let mut records = self.records.write().await;
+        let record = match records.get(serial) {
+            Some(old) => Enrollment {
+                key_hash: hash_bytes(key.as_bytes()),
+                enrolled_at: old.enrolled_at,
+                rotated_at: Some(now),
+            },
+            None => Enrollment {
+                key_hash: hash_bytes(key.as_bytes()),
+                enrolled_at: now,
+                rotated_at: None,
+            },
+        };
+        records.insert(serial.to_string(), record.clone());
+        self.save(&records);
+        record
+    }
+
+    /// Forget `serial`; returns whether it was enrolled.
+    async fn remove(&self, serial: &str) -> bool {
+        let mut records = self.records.write().await;
+        let removed = records.remove(serial).is_some();
+        if removed {
+            self.save(&records);
+        }
+        removed
+    }
+
+    /// Atomically write `records` (0600; hashes only, but still secrets).
+    fn save(&self, records: &HashMap<String, Enrollment>) {
+        use std::io::Write;
+
+        let Some(path) = &self.path else {
+            return;
+        };
+        let stored: HashMap<&String, StoredEnrollment> = records
+            .iter()
+            .map(|(serial, r)| {
+                let stored = StoredEnrollment {
+                    key_hash: r.key_hash.clone(),
+                    enrolled_at: r.enrolled_at,
+                    rotated_at: r.rotated_at,
+                };
+                (serial, stored)
+            })
+            .collect();
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_vec_pretty(&stored)
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                crate::util::create_mode(
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true),
+                    0o600,
+                )
+                .open(&tmp)?
+                .write_all(&data)
+            })
+            .and_then(|()| std::fs::rename(&tmp, path));
+        if let Err(e) = result {
+            warn!("Failed to save enrollments: {e}");
+        }
+    }
+}
+
+/// 256 random bits as hex.
+pub(crate) fn generate_key() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Issue new keys to the connected device `serial` and record them once the
+/// device has stored them. The device switches to the new API key on its
+/// next start and reports it when it registers.
+///
+/// # Errors
+///
+/// The device isn't connected, didn't answer, or couldn't store the keys.
+pub async fn issue(state: &RelayState, serial: &str) -> Result<Enrollment, String> {
+    let tunnel_key = generate_key();
+    let api_key = generate_key();
+    let msg = json!({
+        "type": "tunnel.rotate_key",
+        "request_id": uuid::Uuid::new_v4().to_string(),
+        "tunnel_key": tunnel_key,
+        "api_key": api_key,
+    });
+    let _ = tunnel_request_json(state, serial, msg, ROTATE_TIMEOUT_SECS)
+        .await
+        .and_then(|r| proxy_response_to_http(&r))
+        .map_err(|(status, Json(body))| {
+            body["message"]
+                .as_str()
+                .or(body["error"].as_str())
+                .map_or_else(|| status.to_string(), ToString::to_string)
+        })?;
+    Ok(state.enrollments.set(serial, &tunnel_key).await)
+}
+
+/// Enroll a device that registered with the enrollment token.
+pub(super) async fn enroll(state: RelayState, serial: String) {
+    match issue(&state, &serial).await {
+        Ok(_) => info!(serial = %serial, "Device enrolled"),
+        Err(e) => warn!(serial = %serial, "Device enrollment failed: {e}"),
+    }
+}
+
+/// `GET /api/tunnel/enrollments?token=` — enrolled serials with their
+/// enrollment and last rotation times.
+pub async fn list_enrollments(
+    State(state): State<RelayState>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    Json(json!({ "enrollments": state.enrollments.list().await })).into_response()
+}
+
+/// `POST /api/tunnel/enrollments/{serial}/rotate?token=` — issue new keys
+/// to a connected device.
+pub async fn rotate_enrollment(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    match issue(&state, &serial).await {
+        Ok(record) => {
+            info!(serial = %serial, "Device keys rotated");
+            Json(json!({ "serial": serial, "enrollment": record })).into_response()
+        }
+        Err(e) => (StatusCode::BAD_GATEWAY, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// `DELETE /api/tunnel/enrollments/{serial}?token=` — forget a device's key
+/// so it can enroll again.
+pub async fn delete_enrollment(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    if state.enrollments.remove(&serial).await {
+        info!(serial = %serial, "Device enrollment deleted");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Device '{serial}' is not enrolled") })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_the_issued_key_verifies() {
+        let dir = std::env::temp_dir().join(format!("sctl-enroll-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_dir = dir.to_str().unwrap();
+
+        let enrollments = Enrollments::load(Some(data_dir));
+        assert_eq!(enrollments.verify("dev-1", "k1").await, None);
+        let first = enrollments.set("dev-1", "k1").await;
+        assert!(first.rotated_at.is_none());
+        assert_eq!(enrollments.verify("dev-1", "k1").await, Some(true));
+        assert_eq!(enrollments.verify("dev-1", "k2").await, Some(false));
+
+        let rotated = enrollments.set("dev-1", "k2").await;
+        assert_eq!(rotated.enrolled_at, first.enrolled_at);
+        assert!(rotated.rotated_at.is_some());
+
+        // Survives a relay restart; the API never shows the hash.
+        let reloaded = Enrollments::load(Some(data_dir));
+        assert_eq!(reloaded.verify("dev-1", "k2").await, Some(true));
+        assert!(json!(reloaded.list().await)["dev-1"]
+            .get("key_hash")
+            .is_none());
+        assert!(reloaded.remove("dev-1").await);
+        assert_eq!(reloaded.verify("dev-1", "k2").await, None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
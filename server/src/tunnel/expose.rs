@@ -0,0 +1,199 @@
+//! Reverse port exposure: a device publishes one of its local HTTP services
+//! at the relay, for a while.
+//!
+//! `POST /api/tunnel/expose` on the device sends `tunnel.expose` to its relay.
+//! The relay records an [`Exposure`] and replies with an id and a random
+//! token. The exposure is then reachable at `https://relay/x/{id}/`. The
+//! relay proxies each request there over a forwarded stream
+//! ([`super::forward`]) to `127.0.0.1:{port}` on the device, so the port must
+//! also be in `[tunnel] forward_ports`. The link ends at its TTL, or earlier
+//! with `DELETE /api/tunnel/expose/{id}`.
+//!
+//! Exposures live in the relay's memory only. They outlast a reconnect of the
+//! device but not a restart of the relay. All traffic through one exposure
+//! shares one bandwidth cap.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::gawdxfer::throttle::{effective_rate, TokenBucket};
+
+/// TTL when the device doesn't give one.
+pub const DEFAULT_TTL_SECS: u64 = 3600;
+
+/// Longest TTL an exposure may have.
+pub const MAX_TTL_SECS: u64 = 86_400;
+
+/// Most exposures one device may have at once.
+pub const MAX_PER_DEVICE: usize = 8;
+
+/// Cookie carrying the token, so pages loaded with `?token=` can fetch their
+/// assets without it.
+pub const TOKEN_COOKIE: &str = "sctl_expose";
+
+/// One published port.
+pub struct Exposure {
+    pub serial: String,
+    pub port: u16,
+    token: String,
+    deadline: Instant,
+    /// Unix seconds, as reported to the device.
+    pub expires_at: u64,
+    pub rate_limit_bps: u64,
+    /// Shared by every connection through this exposure.
+    pub bucket: Arc<Mutex<TokenBucket>>,
+}
+
+/// Where an authorized request through an exposure goes.
+pub struct Target {
+    pub serial: String,
+    pub port: u16,
+    pub bucket: Arc<Mutex<TokenBucket>>,
+}
+
+/// The relay's exposures, keyed by id.
+pub struct Exposures {
+    map: Mutex<HashMap<String, Exposure>>,
+    /// `[tunnel] expose_rate_limit_bps`: the most any exposure may use.
+    rate_cap_bps: u64,
+}
+
+impl Exposures {
+    pub fn new(rate_cap_bps: u64) -> Self {
+        Self {
+            map: Mutex::new(HashMap::new()),
+            rate_cap_bps,
+        }
+    }
+
+    /// Publish `port` of `serial` for `ttl_secs`. Returns the `tunnel.expose`
+    /// reply body: `{expose_id, token, port, expires_at, rate_limit_bps}`.
+    ///
+    /// # Errors
+    ///
+    /// A bad port or TTL, or the device already has [`MAX_PER_DEVICE`].
+    pub fn create(
+        &self,
+        serial: &str,
+        port: u64,
+        ttl_secs: Option<u64>,
+        rate_limit_bps: Option<u64>,
+    ) -> Result<Value, String> {
+        let port = u16::try_from(port)
+            .ok()
+            .filter(|&p| p != 0)
+            .ok_or_else(|| "Invalid or missing 'port'".to_string())?;
+        let ttl_secs = ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+        if ttl_secs == 0 || ttl_secs > MAX_TTL_SECS {
+            return Err(format!("ttl_secs must be 1..={MAX_TTL_SECS}"));
+        }
+        let mut map = self.map.lock().unwrap();
+        let now = Instant::now();
+        map.retain(|_, e| e.deadline > now);
+        if map.values().filter(|e| e.serial == serial).count() >= MAX_PER_DEVICE {
+            return Err(format!(
+                "Too many exposures for this device (max {MAX_PER_DEVICE})"
+            ));
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let token = super::enrollment::generate_key();
+        let rate = effective_rate(rate_limit_bps, self.rate_cap_bps);
+        let expires_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            + ttl_secs;
+        let reply = json!({
+            "expose_id": id,
+            "token": token,
+            "port": port,
+            "expires_at": expires_at,
+            "rate_limit_bps": rate,
+        });
+        map.insert(
+            id,
+            Exposure {
+                serial: serial.to_string(),
+                port,
+                token,
+                deadline: now + Duration::from_secs(ttl_secs),
+                expires_at,
+                rate_limit_bps: rate,
+                bucket: Arc::new(Mutex::new(TokenBucket::new(rate))),
+            },
+        );
+        Ok(reply)
+    }
+
+    /// The target of exposure `id`, if it is live and `token` is its token.
+    pub fn authorize(&self, id: &str, token: &str) -> Option<Target> {
+        let map = self.map.lock().unwrap();
+        let exposure = map.get(id)?;
+        let valid = Instant::now() < exposure.deadline
+            && crate::auth::constant_time_eq(exposure.token.as_bytes(), token.as_bytes());
+        valid.then(|| Target {
+            serial: exposure.serial.clone(),
+            port: exposure.port,
+            bucket: exposure.bucket.clone(),
+        })
+    }
+
+    /// Withdraw exposure `id`, if it belongs to `serial`.
+    pub fn remove(&self, serial: &str, id: &str) -> bool {
+        let mut map = self.map.lock().unwrap();
+        if map.get(id).is_some_and(|e| e.serial == serial) {
+            map.remove(id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Public URL of an exposure for a device tunnelled to `relay_url`
+/// (`wss://relay.example.com/api/tunnel/register` →
+/// `https://relay.example.com/x/<id>/?token=<token>`).
+pub fn expose_url(relay_url: &str, id: &str, token: &str) -> Option<String> {
+    let base = super::share::public_base(relay_url)?;
+    Some(format!("{base}/x/{id}/?token={token}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposures_need_the_token_and_belong_to_their_device() {
+        let exposures = Exposures::new(1_000_000);
+        let reply = exposures
+            .create("DEV-1", 8080, None, Some(5_000_000))
+            .unwrap();
+        assert_eq!(reply["rate_limit_bps"], 1_000_000);
+        let id = reply["expose_id"].as_str().unwrap();
+        let token = reply["token"].as_str().unwrap();
+
+        assert_eq!(exposures.authorize(id, token).unwrap().port, 8080);
+        assert!(exposures.authorize(id, "wrong").is_none());
+        assert!(!exposures.remove("DEV-2", id));
+        assert!(exposures.remove("DEV-1", id));
+        assert!(exposures.authorize(id, token).is_none());
+
+        assert!(exposures.create("DEV-1", 0, None, None).is_err());
+        assert!(exposures
+            .create("DEV-1", 80, Some(MAX_TTL_SECS + 1), None)
+            .is_err());
+        for _ in 0..MAX_PER_DEVICE {
+            exposures.create("DEV-1", 80, None, None).unwrap();
+        }
+        assert!(exposures.create("DEV-1", 80, None, None).is_err());
+        assert!(exposures.create("DEV-2", 80, None, None).is_ok());
+
+        assert_eq!(
+            expose_url("wss://relay.example.com/api/tunnel/register", "ab", "t").unwrap(),
+            "https://relay.example.com/x/ab/?token=t"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Ordered relay failover for one tunnel connection.
+//!
+//! `tunnel.url` and `tunnel.failover_urls` form one list, and the client is
+//! connected to at most one relay in it, preferring the earliest. Each relay
+//! keeps its own backoff and flap history. A relay that fails
+//! `failover_after_attempts` connection attempts in a row counts as down and
+//! the next one is tried. When every relay is down, the one whose backoff
+//! ends first is retried. While on a standby the client checks the relays
+//! ahead of it every `failback_check_secs` and moves back as soon as one
+//! accepts a handshake (see [`Failover::recovered`]).
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Connection durations remembered per relay for flap detection.
+const FLAP_WINDOW: usize = 10;
+/// A connection shorter than this counts towards flapping.
+const FLAP_THRESHOLD_SECS: u64 = 30;
+/// This many short connections in a row is flapping.
+const FLAP_CHECK_COUNT: usize = 3;
+
+struct Relay {
+    url: String,
+    /// Connection attempts that failed in a row.
+    failures: u32,
+    /// Wait after the next failed attempt; doubles up to the max.
+    delay: Duration,
+    retry_at: Instant,
+    /// How long recent connections lasted, in seconds.
+    durations: VecDeque<u64>,
+    /// Rejected us for good (e.g. a revoked key); never tried again.
+    disabled: bool,
+}
+
+/// Backoff and health state for an ordered list of relays.
+pub struct Failover {
+    relays: Vec<Relay>,
+    initial_delay: Duration,
+    max_delay: Duration,
+    down_after: u32,
+}
+
+impl Failover {
+    pub fn new(
+        urls: Vec<String>,
+        initial_delay: Duration,
+        max_delay: Duration,
+        down_after: u32,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            relays: urls
+                .into_iter()
+                .map(|url| Relay {
+                    url,
+                    failures: 0,
+                    delay: initial_delay,
+                    retry_at: now,
+                    durations: VecDeque::with_capacity(FLAP_WINDOW),
+                    disabled: false,
+                })
+                .collect(),
+            initial_delay,
+            max_delay,
+            down_after: down_after.max(1),
+        }
+    }
+
+    pub fn url(&self, index: usize) -> &str {
+        &self.relays[index].url
+    }
+
+    /// The relay to connect to next and how long to wait first: the first
+    /// one that isn't down, else the one whose backoff ends soonest. `None`
+    /// once every relay is disabled.
+    pub fn next(&self, now: Instant) -> Option<(usize, Duration)> {
+        let live = || self.relays.iter().enumerate().filter(|(_, r)| !r.disabled);
+        let (index, relay) = live()
+            .find(|(_, r)| r.failures < self.down_after)
+            .or_else(|| live().min_by_key(|(_, r)| r.retry_at))?;
+        Some((index, relay.retry_at.saturating_duration_since(now)))
+    }
+
+    /// Whether the relay has failed often enough to count as down.
+    pub fn is_down(&self, index: usize) -> bool {
+        self.relays[index].failures >= self.down_after
+    }
+
+    /// Relays ahead of `index` that are worth probing for failback.
+    pub fn preferred(&self, index: usize) -> Vec<(usize, String)> {
+        self.relays[..index]
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.disabled)
+            .map(|(i, r)| (i, r.url.clone()))
+            .collect()
+    }
+
+    /// The wait the relay's next failure would get.
+    pub fn backoff(&self, index: usize) -> Duration {
+        self.relays[index].delay
+    }
+
+    /// A connection to the relay registered and later ended after
+    /// `duration_secs`. Returns whether the relay is flapping.
+    pub fn connected(&mut self, index: usize, duration_secs: u64) -> bool {
+        let relay = &mut self.relays[index];
+        relay.failures = 0;
+        Self::record(relay, duration_secs)
+    }
+
+    /// A connection attempt failed after `duration_secs`. Returns whether the
+    /// relay is flapping.
+    pub fn failed(&mut self, index: usize, duration_secs: u64) -> bool {
+        let relay = &mut self.relays[index];
+        relay.failures = relay.failures.saturating_add(1);
+        Self::record(relay, duration_secs)
+    }
+
+    /// Don't try the relay again until `wait` has passed. With `escalate`
+    /// the relay's backoff doubles, otherwise it starts over.
+    pub fn retry_after(&mut self, index: usize, wait: Duration, escalate: bool, now: Instant) {
+        let relay = &mut self.relays[index];
+        relay.retry_at = now + wait;
+        relay.delay = if escalate {
+            (relay.delay * 2).min(self.max_delay)
+        } else {
+            self.initial_delay
+        };
+    }
+
+    /// A failback check reached the relay: it is up and may be used now.
+    pub fn recovered(&mut self, index: usize, now: Instant) {
+        let relay = &mut self.relays[index];
+        relay.failures = 0;
+        relay.delay = self.initial_delay;
+        relay.retry_at = now;
+    }
+
+    /// The device's data link changed (e.g. a new APN): what the relays did
+    /// on the old link says nothing about the new one, so forget failures,
+    /// backoff and flap history and make every live relay due `now`.
+    pub fn reset(&mut self, now: Instant) {
+        for relay in &mut self.relays {
+            relay.failures = 0;
+            relay.delay = self.initial_delay;
+            relay.retry_at = now;
+            relay.durations.clear();
+        }
+    }
+
+    pub fn disable(&mut self, index: usize) {
+        self.relays[index].disabled = true;
+    }
+
+    fn record(relay: &mut Relay, duration_secs: u64) -> bool {
+        if relay.durations.len() >= FLAP_WINDOW {
+            relay.durations.pop_front();
+        }
+        relay.durations.push_back(duration_secs);
+        relay.durations.len() >= FLAP_CHECK_COUNT
+            && relay
+                .durations
+                .iter()
+                .rev()
+                .take(FLAP_CHECK_COUNT)
+                .all(|&d| d < FLAP_THRESHOLD_SECS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_over_after_attempts_and_back_on_recovery() {
+        let secs = Duration::from_secs;
+        let urls = vec!["ws://a".to_string(), "ws://b".into(), "ws://c".into()];
+        let mut relays = Failover::new(urls, secs(2), secs(30), 2);
+        let now = Instant::now();
+        assert_eq!(relays.next(now), Some((0, Duration::ZERO)));
+
+        // One failure backs off on the primary, the second moves on.
+        relays.failed(0, 100);
+        relays.retry_after(0, relays.backoff(0), true, now);
+        assert_eq!(relays.next(now), Some((0, secs(2))));
+        relays.failed(0, 100);
+        relays.retry_after(0, relays.backoff(0), true, now);
+        assert!(relays.is_down(0));
+        assert_eq!(relays.next(now), Some((1, Duration::ZERO)));
+        assert_eq!(relays.backoff(0), secs(8));
+        assert_eq!(relays.backoff(1), secs(2));
+
+        // All down: whichever backoff ends first.
+        relays.disable(2);
+        for _ in 0..2 {
+            relays.failed(1, 100);
+            relays.retry_after(1, secs(1), false, now);
+        }
+        assert_eq!(relays.next(now), Some((1, secs(1))));
+        assert_eq!(relays.preferred(1), vec![(0, "ws://a".to_string())]);
+
+        relays.recovered(0, now);
+        assert_eq!(relays.next(now), Some((0, Duration::ZERO)));
+
+        relays.disable(0);
+        relays.disable(1);
+        assert_eq!(relays.next(now), None);
+    }
+
+    #[test]
+    fn short_connections_in_a_row_are_flapping() {
+        let mut relays = Failover::new(
+            vec!["ws://a".into()],
+            Duration::from_secs(2),
+            Duration::from_secs(30),
+            3,
+        );
+        assert!(!relays.connected(0, 5));
+        assert!(!relays.failed(0, 1));
+        assert!(relays.connected(0, 10));
+        assert!(!relays.connected(0, 600));
+    }
+
+    #[test]
+    fn link_change_clears_backoff() {
+        let secs = Duration::from_secs;
+        let urls = vec!["ws://a".to_string(), "ws://b".into()];
+        let mut relays = Failover::new(urls, secs(2), secs(30), 1);
+        let now = Instant::now();
+        for index in 0..2 {
+            relays.failed(index, 1);
+            relays.retry_after(index, secs(60), true, now);
+        }
+        assert_eq!(relays.next(now), Some((0, secs(60))));
+
+        relays.reset(now);
+        assert_eq!(relays.next(now), Some((0, Duration::ZERO)));
+        assert_eq!(relays.backoff(0), secs(2));
+        assert!(!relays.failed(0, 1));
+    }
+}
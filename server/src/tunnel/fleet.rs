@@ -0,0 +1,372 @@
+//! Fleet operations on the relay: one request, many tunneled devices.
+//!
+//! `POST /api/tunnel/broadcast/exec?token=<tunnel_key>` sends the same
+//! `tunnel.exec` to every targeted device at once, up to `max_concurrency`
+//! in flight, and waits for each under its own timeout. Devices that are
+//! offline, time out or refuse the command are reported per device; they
+//! never fail the whole request.
+
+use std::collections::BTreeSet;
+use std::time::Instant;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::relay::{
+    matches_tags, parse_tag_filter, proxy_response_to_http, tunnel_request_json, RelayState,
+};
+
+/// Devices asked at once when `max_concurrency` isn't given.
+const DEFAULT_MAX_CONCURRENCY: usize = 50;
+
+/// Seconds added to the command's `timeout_ms` before the relay gives up on
+/// a device, to cover the tunnel round trip.
+const TIMEOUT_MARGIN_SECS: u64 = 5;
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    pub token: String,
+}
+
+/// Which devices a fleet operation targets. Devices matching either
+/// `serials` or `tag` are included; `all` targets every connected device.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Targets {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub serials: Vec<String>,
+    /// Same syntax as `GET /api/tunnel/devices?tag=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all: bool,
+}
+
+impl Targets {
+    /// Resolve to sorted serials. Named serials that aren't connected are
+    /// kept so they show up as errors in the report.
+    ///
+    /// # Errors
+    ///
+    /// Nothing targeted, or a malformed tag filter.
+    pub async fn resolve(&self, state: &RelayState) -> Result<Vec<String>, String> {
+        if self.serials.is_empty() && self.tag.is_none() && !self.all {
+            return Err("Specify serials, tag or all".to_string());
+        }
+        let filter = self.tag.as_deref().map(parse_tag_filter).transpose()?;
+        let mut serials: BTreeSet<String> = self.serials.iter().cloned().collect();
+        for device in state.devices.read().await.values() {
+            if self.all
+                || filter
+                    .as_deref()
+                    .is_some_and(|filter| matches_tags(&device.tags, filter))
+            {
+                serials.insert(device.serial.clone());
+            }
+        }
+        Ok(serials.into_iter().collect())
+    }
+}
+
+/// A command to run on each device, as in `POST /api/exec`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecAction {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<Value>,
+    /// Per-device command timeout; the device's default when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// What a fleet operation does on each device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Exec(ExecAction),
+    /// `POST /api/playbooks/{name}/run` on each device.
+    Playbook {
+        name: String,
+        #[serde(default, skip_serializing_if = "Value::is_null")]
+        args: Value,
+        /// How long the relay waits for the whole run.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+}
+
+impl Action {
+    /// # Errors
+    ///
+    /// An empty command or playbook name.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Exec(exec) if exec.command.trim().is_empty() => {
+                Err("command is required".to_string())
+            }
+            Self::Playbook { name, .. } if name.is_empty() => {
+                Err("playbook name is required".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn timeout_ms(&self) -> Option<u64> {
+        match self {
+            Self::Exec(exec) => exec.timeout_ms,
+            Self::Playbook { timeout_ms, .. } => *timeout_ms,
+        }
+    }
+
+    fn message(&self) -> Value {
+        let mut msg = match self {
+            Self::Exec(exec) => {
+                let mut msg = json!(exec);
+                msg["type"] = json!("tunnel.exec");
+                msg
+            }
+            Self::Playbook { name, args, .. } => json!({
+                "type": "tunnel.playbooks.run",
+                "name": name,
+                "args": args,
+            }),
+        };
+        msg["request_id"] = json!(uuid::Uuid::new_v4().to_string());
+        msg
+    }
+}
+
+/// Request body for `POST /api/tunnel/broadcast/exec`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastExecRequest {
+    #[serde(flatten)]
+    pub targets: Targets,
+    #[serde(flatten)]
+    pub exec: ExecAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+}
+
+/// One device's outcome.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceResult {
+    pub serial: String,
+    /// `ok` (exit 0 / playbook `ok`), `failed` (non-zero exit / failed
+    /// playbook) or `error` (offline, timed out, refused by the device).
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Playbook run id, to fetch the full report from the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl DeviceResult {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Aggregated outcome of a broadcast.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastReport {
+    pub total: usize,
+    pub ok: usize,
+    pub failed: usize,
+    pub errors: usize,
+    pub duration_ms: u64,
+    /// Sorted by serial.
+    pub results: Vec<DeviceResult>,
+}
+
+impl BroadcastReport {
+    fn new(mut results: Vec<DeviceResult>, started: Instant) -> Self {
+        results.sort_by(|a, b| a.serial.cmp(&b.serial));
+        let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+        Self {
+            total: results.len(),
+            ok: count("ok"),
+            failed: count("failed"),
+            errors: count("error"),
+            duration_ms: elapsed_ms(started),
+            results,
+        }
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Error message from a device or relay error body.
+fn error_message(status: StatusCode, body: &Value) -> String {
+    body["message"]
+        .as_str()
+        .or(body["error"].as_str())
+        .map_or_else(|| status.to_string(), ToString::to_string)
+}
+
+/// Run `action` on `serial`.
+pub async fn run_on(state: &RelayState, serial: &str, action: &Action) -> DeviceResult {
+    let started = Instant::now();
+    let timeout_secs = action
+        .timeout_ms()
+        .map_or(state.tunnel_proxy_timeout_secs, |ms| {
+            ms / 1000 + TIMEOUT_MARGIN_SECS
+        });
+    let response = tunnel_request_json(state, serial, action.message(), timeout_secs)
+        .await
+        .and_then(|r| proxy_response_to_http(&r));
+    let mut result = DeviceResult {
+        serial: serial.to_string(),
+        status: "error".to_string(),
+        exit_code: None,
+        stdout: None,
+        stderr: None,
+        run_id: None,
+        error: None,
+        duration_ms: 0,
+    };
+    match (response, action) {
+        (Ok(Json(body)), Action::Exec(_)) => {
+            let exit_code = body["exit_code"].as_i64();
+            result.status = if exit_code == Some(0) { "ok" } else { "failed" }.to_string();
+            result.exit_code = exit_code;
+            result.stdout = body["stdout"].as_str().map(ToString::to_string);
+            result.stderr = body["stderr"].as_str().map(ToString::to_string);
+        }
+        (Ok(Json(body)), Action::Playbook { .. }) => {
+            result.status = if body["status"] == "ok" {
+                "ok"
+            } else {
+                "failed"
+            }
+            .to_string();
+            result.run_id = body["run_id"].as_str().map(ToString::to_string);
+            if let Some(step) = body["steps"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|s| s["status"] != "ok" && s["status"] != "skipped")
+            {
+                result.error = Some(format!(
+                    "step '{}' {}",
+                    step["name"].as_str().unwrap_or_default(),
+                    step["status"].as_str().unwrap_or_default()
+                ));
+            }
+        }
+        (Err((status, Json(body))), _) => result.error = Some(error_message(status, &body)),
+    }
+    result.duration_ms = elapsed_ms(started);
+    result
+}
+
+/// Run `action` on `serials`, at most `concurrency` at a time.
+pub async fn run_many(
+    state: &RelayState,
+    serials: &[String],
+    action: &Action,
+    concurrency: usize,
+) -> BroadcastReport {
+    let started = Instant::now();
+    let results = futures::stream::iter(serials.iter().cloned())
+        .map(|serial| async move { run_on(state, &serial, action).await })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    BroadcastReport::new(results, started)
+}
+
+/// `403` unless `token` is the tunnel key.
+pub(super) fn reject_token(state: &RelayState, token: &str) -> Option<Response> {
+    (!crate::auth::constant_time_eq(state.tunnel_key.as_bytes(), token.as_bytes()))
+        .then(|| (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response())
+}
+
+pub(super) fn bad_request(error: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
+
+/// `POST /api/tunnel/broadcast/exec?token=` — run one command on many
+/// devices and return every device's result.
+pub async fn broadcast_exec(
+    State(state): State<RelayState>,
+    Query(query): Query<TokenQuery>,
+    Json(req): Json<BroadcastExecRequest>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    let action = Action::Exec(req.exec);
+    if let Err(e) = action.validate() {
+        return bad_request(&e);
+    }
+    let serials = match req.targets.resolve(&state).await {
+        Ok(serials) => serials,
+        Err(e) => return bad_request(&e),
+    };
+
+    let concurrency = req.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY);
+    let report = run_many(&state, &serials, &action, concurrency).await;
+    info!(
+        devices = report.total,
+        ok = report.ok,
+        failed = report.failed,
+        errors = report.errors,
+        "Broadcast exec finished"
+    );
+    Json(report).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn offline_targets_are_reported_per_device() {
+        let state = RelayState::new("tunnel-key-123".to_string(), 20, 60, None);
+        let req: BroadcastExecRequest = serde_json::from_value(json!({
+            "serials": ["b", "a", "b"],
+            "command": "true",
+        }))
+        .unwrap();
+
+        let serials = req.targets.resolve(&state).await.unwrap();
+        assert_eq!(serials, ["a", "b"]);
+        let action = Action::Exec(req.exec);
+        let report = run_many(&state, &serials, &action, 10).await;
+        assert_eq!((report.total, report.ok, report.errors), (2, 0, 2));
+        assert_eq!(report.results[0].serial, "a");
+        assert!(report.results[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("not connected"));
+
+        assert!(Targets::default().resolve(&state).await.is_err());
+        let bad_tag = Targets {
+            tag: Some("a b".to_string()),
+            ..Targets::default()
+        };
+        assert!(bad_tag.resolve(&state).await.is_err());
+    }
+}
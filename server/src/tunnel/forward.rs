@@ -0,0 +1,471 @@
+//! TCP port forwarding through the tunnel.
+//!
+//! A client opens `GET /d/{serial}/forward/{port}` on the relay as a
+//! WebSocket. The relay asks the device to `tunnel.forward.open` a stream,
+//! and the device connects to `127.0.0.1:{port}` if the port is listed in
+//! `[tunnel] forward_ports`. Bytes then travel both ways as `forward.data`
+//! binary frames tagged with the stream's id, and either end stops the stream
+//! with `tunnel.forward.close`. Many streams share the one device connection.
+//!
+//! Each direction of a stream is flow controlled. The sender may have at most
+//! [`WINDOW`] bytes unacknowledged. The receiver hands credit back with
+//! `tunnel.forward.credit` as it writes the bytes out. A slow reader at either
+//! end stalls only its own stream, and never fills the relay's or device's
+//! queues for everything else.
+//!
+//! A stream is scoped to the client's WebSocket and to the tunnel connection
+//! it was opened on: it ends when the client disconnects, the local service
+//! closes, or the tunnel drops. Nothing reconnects behind the client's back.
+//! `sctl forward` ([`listen_local`]) turns this into an ordinary local port:
+//! every connection accepted there gets its own stream.
+//! The same streams carry SFTP sessions (`tunnel.sftp.open`, see
+//! [`crate::sftp`]), with `sftp-server`'s stdio in place of a socket.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::AbortHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use super::encode_binary_frame;
+
+/// Binary frame type carrying stream bytes, in both directions.
+pub const FORWARD_DATA: &str = "forward.data";
+
+/// Most streams open at once over one tunnel connection.
+pub const MAX_STREAMS: usize = 32;
+
+/// Bytes a sender may have in flight on one direction of a stream.
+pub const WINDOW: usize = 256 * 1024;
+
+/// Largest payload per `forward.data` frame.
+pub const MAX_FRAME: usize = 16 * 1024;
+
+/// Credit is handed back once this much is written, or when the queue runs dry.
+const CREDIT_BATCH: usize = WINDOW / 4;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether `port` may be forwarded under `[tunnel] forward_ports`.
+pub fn port_allowed(allowed: &[u16], port: u64) -> bool {
+    u16::try_from(port).is_ok_and(|p| p != 0 && allowed.contains(&p))
+}
+
+pub fn close_message(stream_id: &str) -> Value {
+    json!({"type": "tunnel.forward.close", "stream_id": stream_id})
+}
+
+pub fn credit_message(stream_id: &str, bytes: usize) -> Value {
+    json!({"type": "tunnel.forward.credit", "stream_id": stream_id, "bytes": bytes})
+}
+
+/// The sending side's window for one direction of a stream.
+#[derive(Clone)]
+pub struct Credit(Arc<Semaphore>);
+
+impl Default for Credit {
+    fn default() -> Self {
+        Self(Arc::new(Semaphore::new(WINDOW)))
+    }
+}
+
+impl Credit {
+    /// Wait until `bytes` (at most [`MAX_FRAME`]) may be sent. `false` once
+    /// the stream is closed.
+    pub async fn spend(&self, bytes: usize) -> bool {
+        let n = u32::try_from(bytes.min(MAX_FRAME)).unwrap_or(0);
+        match self.0.acquire_many(n).await {
+            Ok(permits) => {
+                permits.forget();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Credit handed back by the receiver. The window never grows past
+    /// [`WINDOW`], whatever the peer claims.
+    pub fn grant(&self, bytes: u64) {
+        let room = WINDOW.saturating_sub(self.0.available_permits());
+        let n = usize::try_from(bytes).unwrap_or(usize::MAX).min(room);
+        self.0.add_permits(n);
+    }
+
+    /// Wake a sender waiting for credit; it sees the stream as closed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+}
+
+/// Counts bytes written out on the receiving side and says when to return
+/// them as credit.
+#[derive(Default)]
+pub struct Delivered(usize);
+
+impl Delivered {
+    /// Record `bytes` written; returns the credit to send, if it's time.
+    /// `idle` is whether nothing more is queued behind them.
+    pub fn add(&mut self, bytes: usize, idle: bool) -> Option<usize> {
+        self.0 += bytes;
+        (self.0 >= CREDIT_BATCH || (idle && self.0 > 0)).then(|| std::mem::take(&mut self.0))
+    }
+}
+
+/// The relay's end of a stream: where the device's bytes go, and the credit
+/// for sending the client's.
+pub struct RelayStream {
+    pub to_client: mpsc::UnboundedSender<Vec<u8>>,
+    pub credit: Credit,
+}
+
+/// A device connection's streams on the relay, keyed by stream id.
+pub type RelayStreams = Arc<Mutex<HashMap<String, RelayStream>>>;
+
+/// Why a stream could not be opened.
+pub enum OpenError {
+    /// Too many streams already, or a reused id.
+    Rejected(String),
+    /// Connecting to the local port failed.
+    Connect(String),
+}
+
+struct Stream {
+    /// Unbounded, but never holds more than a window: the relay sends no
+    /// more until credit comes back.
+    to_socket: mpsc::UnboundedSender<Vec<u8>>,
+    credit: Credit,
+    reader: AbortHandle,
+}
+
+/// The forwarded streams of one tunnel connection on the device, keyed by
+/// stream id.
+#[derive(Default)]
+pub struct Forwards {
+    streams: Mutex<HashMap<String, Stream>>,
+}
+
+impl Forwards {
+    /// Whether a stream `stream_id` could be added now.
+    ///
+    /// # Errors
+    ///
+    /// [`OpenError::Rejected`] when there are too many streams or the id is
+    /// taken.
+    pub async fn check(&self, stream_id: &str) -> Result<(), OpenError> {
+        let streams = self.streams.lock().await;
+        if streams.len() >= MAX_STREAMS {
+            return Err(OpenError::Rejected(format!(
+                "Too many forwarded streams (max {MAX_STREAMS})"
+            )));
+        }
+        if streams.contains_key(stream_id) {
+            return Err(OpenError::Rejected(format!(
+                "Stream {stream_id} is already open"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Connect to `127.0.0.1:port` and [`Self::attach`] the socket.
+    ///
+    /// # Errors
+    ///
+    /// [`OpenError`] when the stream can't be added or the connect fails.
+    pub async fn open(
+        self: &Arc<Self>,
+        stream_id: &str,
+        port: u16,
+        out: mpsc::Sender<Message>,
+    ) -> Result<(), OpenError> {
+        self.check(stream_id).await?;
+        let socket =
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(("127.0.0.1", port)))
+                .await
+            {
+                Ok(Ok(socket)) => socket,
+                Ok(Err(e)) => return Err(OpenError::Connect(format!("127.0.0.1:{port}: {e}"))),
+                Err(_) => {
+                    return Err(OpenError::Connect(format!(
+                        "127.0.0.1:{port}: connect timed out"
+                    )))
+                }
+            };
+        let _ = socket.set_nodelay(true);
+        let (read_half, write_half) = socket.into_split();
+        self.attach(stream_id, read_half, write_half, out).await;
+        info!(stream_id, port, "Forwarded stream opened");
+        Ok(())
+    }
+
+    /// Start relaying between a stream and a local byte stream. Bytes read
+    /// from `read_half` go out on `out` as `forward.data` frames as credit
+    /// allows, and credit for bytes written to `write_half` goes back the
+    /// same way. When `read_half` ends, `tunnel.forward.close` follows.
+    pub async fn attach<R, W>(
+        self: &Arc<Self>,
+        stream_id: &str,
+        mut read_half: R,
+        mut write_half: W,
+        out: mpsc::Sender<Message>,
+    ) where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (to_socket, mut from_relay) = mpsc::unbounded_channel::<Vec<u8>>();
+        let credit_out = out.clone();
+        let id = stream_id.to_string();
+        tokio::spawn(async move {
+            let mut delivered = Delivered::default();
+            while let Some(data) = from_relay.recv().await {
+                if write_half.write_all(&data).await.is_err() {
+                    break;
+                }
+                if let Some(bytes) = delivered.add(data.len(), from_relay.is_empty()) {
+                    let msg = credit_message(&id, bytes).to_string();
+                    if credit_out.send(Message::Text(msg.into())).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = write_half.shutdown().await;
+        });
+
+        // Held until the stream is registered, so a socket that closes at
+        // once can't be removed before it is added.
+        let mut streams = self.streams.lock().await;
+        let credit = Credit::default();
+        let reader_credit = credit.clone();
+        let forwards = self.clone();
+        let id = stream_id.to_string();
+        let reader = tokio::spawn(async move {
+            let header = json!({"type": FORWARD_DATA, "stream_id": id});
+            let mut buf = vec![0u8; MAX_FRAME];
+            loop {
+                let n = match read_half.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if !reader_credit.spend(n).await {
+                    break;
+                }
+                let frame = encode_binary_frame(&header, &buf[..n]);
+                if out.send(Message::Binary(frame.into())).await.is_err() {
+                    break;
+                }
+            }
+            forwards.streams.lock().await.remove(&id);
+            let close = close_message(&id).to_string();
+            let _ = out.send(Message::Text(close.into())).await;
+            debug!(stream_id = %id, "Forwarded stream closed by local side");
+        });
+
+        streams.insert(
+            stream_id.to_string(),
+            Stream {
+                to_socket,
+                credit,
+                reader: reader.abort_handle(),
+            },
+        );
+    }
+
+    /// Queue bytes from the relay for the stream's socket. `false` when the
+    /// stream is unknown, so the caller can tell the relay it's gone.
+    pub async fn data(&self, stream_id: &str, payload: Vec<u8>) -> bool {
+        self.streams
+            .lock()
+            .await
+            .get(stream_id)
+            .is_some_and(|s| s.to_socket.send(payload).is_ok())
+    }
+
+    /// Credit from the relay for bytes it has passed on to the client.
+    pub async fn grant(&self, stream_id: &str, bytes: u64) {
+        if let Some(stream) = self.streams.lock().await.get(stream_id) {
+            stream.credit.grant(bytes);
+        }
+    }
+
+    /// Stop a stream. Bytes already queued are still written before the
+    /// socket is shut down.
+    pub async fn close(&self, stream_id: &str) {
+        if let Some(stream) = self.streams.lock().await.remove(stream_id) {
+            stream.credit.close();
+            stream.reader.abort();
+            debug!(stream_id, "Forwarded stream closed by relay");
+        }
+    }
+
+    /// Stop every stream (tunnel connection lost).
+    pub async fn close_all(&self) {
+        for (_, stream) in self.streams.lock().await.drain() {
+            stream.credit.close();
+            stream.reader.abort();
+        }
+    }
+}
+
+// ─── Local listener (`sctl forward`) ────────────────────────────────────────
+
+/// The forward WS URL for `port` under a device's relay URL, e.g.
+/// `https://relay.example.com/d/DEV-1` → `wss://…/d/DEV-1/forward/80?token=…`.
+///
+/// # Errors
+///
+/// The URL isn't `http(s)://` or `ws(s)://`.
+pub fn forward_url(device_url: &str, port: u16, token: &str) -> Result<String, String> {
+    ws_url(device_url, &format!("/forward/{port}"), token)
+}
+
+/// A WS URL for `path` under a device URL, with `token` as the query.
+///
+/// # Errors
+///
+/// The URL isn't `http(s)://` or `ws(s)://`.
+pub fn ws_url(device_url: &str, path: &str, token: &str) -> Result<String, String> {
+    let base = device_url.trim_end_matches('/');
+    let base = if let Some(rest) = base.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if base.starts_with("wss://") || base.starts_with("ws://") {
+        base.to_string()
+    } else {
+        return Err(format!(
+            "Device URL must start with http(s):// or ws(s)://: {device_url}"
+        ));
+    };
+    Ok(format!("{base}{path}?token={token}"))
+}
+
+/// Accept connections on `listen` and carry each one to `port` on the
+/// device over its own stream. Runs until the listener fails.
+///
+/// # Errors
+///
+/// The URL is unusable, or binding or accepting failed.
+pub async fn listen_local(
+    listen: SocketAddr,
+    device_url: &str,
+    port: u16,
+    token: &str,
+) -> Result<(), String> {
+    let url = forward_url(device_url, port, token)?;
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|e| format!("Failed to listen on {listen}: {e}"))?;
+    info!("Forwarding {listen} to device port {port}");
+    loop {
+        let (socket, peer) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Accept failed: {e}"))?;
+        let url = url.clone();
+        tokio::spawn(async move {
+            match pipe_local(socket, &url).await {
+                Ok(()) => debug!(%peer, "Forward connection closed"),
+                Err(e) => warn!(%peer, "Forward connection failed: {e}"),
+            }
+        });
+    }
+}
+
+/// Pipe one local connection through a forward WS until either end closes.
+async fn pipe_local(socket: TcpStream, url: &str) -> Result<(), String> {
+    let (ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let _ = socket.set_nodelay(true);
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (mut read_half, mut write_half) = socket.into_split();
+    let upstream = async {
+        let mut buf = vec![0u8; MAX_FRAME];
+        loop {
+            let n = read_half.read(&mut buf).await.map_err(|e| e.to_string())?;
+            if n == 0 {
+                break;
+            }
+            ws_tx
+                .send(Message::Binary(buf[..n].to_vec().into()))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let _ = ws_tx.close().await;
+        Ok::<(), String>(())
+    };
+    let downstream = async {
+        while let Some(msg) = ws_rx.next().await {
+            match msg.map_err(|e| e.to_string())? {
+                Message::Binary(data) => write_half
+                    .write_all(&data)
+                    .await
+                    .map_err(|e| e.to_string())?,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        let _ = write_half.shutdown().await;
+        Ok::<(), String>(())
+    };
+    tokio::select! {
+        r = upstream => r,
+        r = downstream => r,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listed_ports_are_forwarded() {
+        let allowed = [8080, 502];
+        assert!(port_allowed(&allowed, 8080));
+        assert!(port_allowed(&allowed, 502));
+        assert!(!port_allowed(&allowed, 22));
+        assert!(!port_allowed(&allowed, 0));
+        assert!(!port_allowed(&allowed, 65_536 + 8080));
+        assert!(!port_allowed(&[], 8080));
+    }
+
+    #[tokio::test]
+    async fn credit_bounds_what_is_in_flight() {
+        let credit = Credit::default();
+        for _ in 0..WINDOW / MAX_FRAME {
+            assert!(credit.spend(MAX_FRAME).await);
+        }
+        // Window used up: the next frame waits for the receiver
+        let waiting = tokio::time::timeout(Duration::from_millis(20), credit.spend(1)).await;
+        assert!(waiting.is_err());
+        credit.grant(u64::MAX); // a peer can't grow the window
+        assert_eq!(credit.0.available_permits(), WINDOW);
+        credit.close();
+        assert!(!credit.spend(1).await);
+
+        let mut delivered = Delivered::default();
+        assert_eq!(delivered.add(100, false), None);
+        assert_eq!(delivered.add(100, true), Some(200));
+        assert_eq!(delivered.add(CREDIT_BATCH, false), Some(CREDIT_BATCH));
+    }
+
+    #[test]
+    fn forward_urls() {
+        assert_eq!(
+            forward_url("https://relay.example.com/d/DEV-1/", 80, "k").unwrap(),
+            "wss://relay.example.com/d/DEV-1/forward/80?token=k"
+        );
+        assert_eq!(
+            forward_url("ws://10.0.0.2:8443/d/DEV-1", 502, "k").unwrap(),
+            "ws://10.0.0.2:8443/d/DEV-1/forward/502?token=k"
+        );
+        assert!(forward_url("relay.example.com/d/DEV-1", 80, "k").is_err());
+    }
+}
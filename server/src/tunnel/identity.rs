@@ -0,0 +1,237 @@
+//! Hardware identity pinning on the relay.
+//!
+//! Devices send their [`crate::identity`] with `tunnel.register`. The first
+//! fingerprint seen for a serial is pinned in
+//! `<data_dir>/relay_identities.json` (trust on first use). After that, a
+//! registration with a different fingerprint — the same config on other
+//! hardware, or a reflashed board — is logged and recorded as a mismatch,
+//! shown in `GET /api/tunnel/devices` and `GET /api/tunnel/identities`.
+//! With `[tunnel] enforce_identity = true` it is refused with
+//! `IDENTITY_MISMATCH`.
+//!
+//! Devices that don't report an identity (older builds) are let through
+//! unpinned. Deleting a pin (e.g. after a board swap) lets the next
+//! registration pin again.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use axum::{
+    extract::{Path as AxumPath, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use super::fleet::{reject_token, TokenQuery};
+use super::relay::RelayState;
+
+/// The identity pinned for a serial.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityPin {
+    pub fingerprint: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Unix ms.
+    pub pinned_at: u64,
+    /// The last registration that didn't match, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mismatch: Option<IdentityMismatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityMismatch {
+    pub fingerprint: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Unix ms.
+    pub seen_at: u64,
+}
+
+/// Outcome of checking a registration against the pins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityStatus {
+    /// The device didn't report an identity.
+    Unreported,
+    /// First registration for the serial; its fingerprint is now pinned.
+    Pinned,
+    /// The fingerprint matches the pin.
+    Verified,
+    /// The fingerprint differs from the pin.
+    Mismatch,
+}
+
+/// Pinned identities by serial, persisted to `relay_identities.json`.
+pub struct IdentityPins {
+    path: Option<PathBuf>,
+    records: RwLock<HashMap<String, IdentityPin>>,
+}
+
+impl IdentityPins {
+    pub fn load(data_dir: Option<&str>) -> Self {
+        let path = data_dir.map(|d| Path::new(d).join("relay_identities.json"));
+        let records: HashMap<String, IdentityPin> = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        if !records.is_empty() {
+            info!("Loaded {} pinned device identities", records.len());
+        }
+        Self {
+            path,
+            records: RwLock::new(records),
+        }
+    }
+
+    /// Check `identity` (the registration's `identity` object) against the
+    /// pin for `serial`, pinning it if there is none.
+    pub async fn check(&self, serial: &str, identity: &Value) -> IdentityStatus {
+        let Some(fingerprint) = identity["fingerprint"].as_str().filter(|f| !f.is_empty()) else {
+            return IdentityStatus::Unreported;
+        };
+        let model = identity["model"].as_str().map(ToString::to_string);
+        let now = crate::sessions::journal::now_ms();
+        let mut records = self.records.write().await;
+        let status = match records.get_mut(serial) {
+            None => {
+                records.insert(
+                    serial.to_string(),
+                    IdentityPin {
+                        fingerprint: fingerprint.to_string(),
+                        model,
+                        pinned_at: now,
+                        mismatch: None,
+                    },
+                );
+                IdentityStatus::Pinned
+            }
+            Some(pin) if pin.fingerprint == fingerprint => return IdentityStatus::Verified,
+            Some(pin) => {
+                pin.mismatch = Some(IdentityMismatch {
+                    fingerprint: fingerprint.to_string(),
+                    model,
+                    seen_at: now,
+                });
+                IdentityStatus::Mismatch
+            }
+        };
+        self.save(&records);
+        status
+    }
+
+    pub async fn list(&self) -> HashMap<String, IdentityPin> {
+        self.records.read().await.clone()
+    }
+
+    /// Forget `serial`'s pin; returns whether it had one.
+    async fn remove(&self, serial: &str) -> bool {
+        let mut records = self.records.write().await;
+        let removed = records.remove(serial).is_some();
+        if removed {
+            self.save(&records);
+        }
+        removed
+    }
+
+    /// Atomically write `records`.
+    fn save(&self, records: &HashMap<String, IdentityPin>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_vec_pretty(records)
+            .map_err(std::io::Error::from)
+            .and_then(|data| std::fs::write(&tmp, data))
+            .and_then(|()| std::fs::rename(&tmp, path));
+        if let Err(e) = result {
+            warn!("Failed to save device identities: {e}");
+        }
+    }
+}
+
+/// `GET /api/tunnel/identities?token=` — pinned fingerprints by serial, with
+/// the last mismatching registration.
+pub async fn list_identities(
+    State(state): State<RelayState>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    Json(json!({
+        "enforce": state.enforce_identity,
+        "identities": state.identities.list().await,
+    }))
+    .into_response()
+}
+
+/// `DELETE /api/tunnel/identities/{serial}?token=` — forget a device's pin so
+/// its next registration pins again.
+pub async fn delete_identity(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    if state.identities.remove(&serial).await {
+        info!(serial = %serial, "Device identity pin deleted");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("Device '{serial}' has no pinned identity") })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pins_first_fingerprint_and_flags_others() {
+        let dir = std::env::temp_dir().join(format!("sctl-identities-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data_dir = dir.to_str().unwrap();
+        let board = |fingerprint: &str| json!({"fingerprint": fingerprint, "model": "RV1126"});
+
+        let pins = IdentityPins::load(Some(data_dir));
+        assert_eq!(
+            pins.check("dev-1", &Value::Null).await,
+            IdentityStatus::Unreported
+        );
+        assert_eq!(
+            pins.check("dev-1", &board("aa")).await,
+            IdentityStatus::Pinned
+        );
+        assert_eq!(
+            pins.check("dev-1", &board("aa")).await,
+            IdentityStatus::Verified
+        );
+        assert_eq!(
+            pins.check("dev-1", &board("bb")).await,
+            IdentityStatus::Mismatch
+        );
+
+        // The pin and the mismatch survive a relay restart.
+        let reloaded = IdentityPins::load(Some(data_dir));
+        let pin = &reloaded.list().await["dev-1"];
+        assert_eq!(pin.fingerprint, "aa");
+        assert_eq!(pin.mismatch.as_ref().unwrap().fingerprint, "bb");
+        assert!(reloaded.remove("dev-1").await);
+        assert_eq!(
+            reloaded.check("dev-1", &board("bb")).await,
+            IdentityStatus::Pinned
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
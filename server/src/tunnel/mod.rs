@@ -4,13 +4,35 @@
 //!
 //! - **Relay** (`tunnel.relay = true`): accepts device registrations over WS,
 //!   proxies client REST/WS requests to devices via the tunnel connection.
-//! - **Client** (`tunnel.url` is set): connects outbound to a relay, handles
-//!   proxied requests by calling local route handlers directly.
+//! - **Client** (`tunnel.url` is set): connects outbound to a relay (or to
+//!   several at once via `tunnel.extra_urls`, active-active, with standbys
+//!   via `tunnel.failover_urls`, see [`failover`]), handles
+//!   proxied requests by calling local route handlers directly. Events raised
+//!   while disconnected are spooled to disk and replayed on reconnect.
+//!
+//! Messages are JSON text frames, or MessagePack once both sides agree on it
+//! at registration (see [`codec`]).
+//!
+//! Session share links ([`share`]) are minted by the device and served by the
+//! relay at `/s/{token}`. Exposed ports ([`expose`]) are requested by the
+//! device and served by the relay at `/x/{id}/`.
 
 use serde_json::Value;
 
 pub mod client;
+pub mod codec;
+pub mod credentials;
+pub mod enrollment;
+pub mod expose;
+pub mod failover;
+pub mod fleet;
+pub mod forward;
+pub mod identity;
 pub mod relay;
+pub mod resolve;
+pub mod rollout;
+pub mod share;
+pub mod spool;
 
 /// A message that can be sent to a device over the tunnel WS.
 /// Text for JSON, Binary for file transfer frames.
@@ -25,6 +47,23 @@ pub enum TunnelResponse {
     Binary { header: Value, data: Vec<u8> },
 }
 
+/// Most tags a device can register with; extras are dropped by the relay.
+pub const MAX_DEVICE_TAGS: usize = 32;
+
+/// Whether `key = value` is an acceptable device tag: a key of 1-64
+/// `[A-Za-z0-9._-]` characters and a value of up to 128 characters without
+/// `,` or `=` (the separators of `GET /api/tunnel/devices?tag=`).
+pub fn valid_tag(key: &str, value: &str) -> bool {
+    (1..=64).contains(&key.len())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        && value.len() <= 128
+        && !value
+            .chars()
+            .any(|c| c == ',' || c == '=' || c.is_control())
+}
+
 /// Encode a binary frame: `[header_len: u32 BE][JSON header][payload]`.
 pub fn encode_binary_frame(header: &Value, payload: &[u8]) -> Vec<u8> {
     let header_bytes = serde_json::to_vec(header).expect("Value serializes");
@@ -4,8 +4,9 @@
 //! 1. Listens for device WS connections at `/api/tunnel/register`
 //! 2. Exposes REST + WS proxy at `/d/{serial}/api/*`
 //! 3. Translates client requests to tunnel messages over the device WS
+//! 4. Serves session share links at `/s/{token}` (see [`super::share`])
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
@@ -15,8 +16,9 @@ use axum::{
     body::Body,
     extract::{Path as AxumPath, Query, State, WebSocketUpgrade},
     http::{Request, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{delete, get, post},
+    routing::{any, delete, get, post, put},
     Json, Router,
 };
 use futures::{SinkExt, StreamExt};
@@ -25,7 +27,9 @@ use serde_json::{json, Value};
 use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock};
 use tracing::{info, info_span, warn, Instrument};
 
+use super::share;
 use super::{decode_binary_frame, encode_binary_frame, TunnelMessage, TunnelResponse};
+use crate::deadline::RequestDeadline;
 
 /// Maximum number of connection sessions to retain in history.
 const MAX_CONNECTION_HISTORY: usize = 100;
@@ -321,6 +325,21 @@ pub struct RelayState {
     pub snapshots_dirty: Arc<AtomicBool>,
     /// Path to snapshot persistence file (None if no data_dir configured).
     pub snapshots_path: Option<PathBuf>,
+    /// Fleet rollouts keyed by id, in memory only.
+    pub rollouts: Arc<RwLock<HashMap<String, super::rollout::Rollout>>>,
+    /// Token that lets an unenrolled device register and be issued its own
+    /// keys (see [`super::enrollment`]).
+    pub enrollment_token: Option<String>,
+    /// Reject the shared `tunnel_key` for devices that aren't enrolled.
+    pub require_enrollment: bool,
+    /// Per-device tunnel keys issued by this relay.
+    pub enrollments: Arc<super::enrollment::Enrollments>,
+    /// Device ports published at `/x/{id}/` (see [`super::expose`]).
+    pub exposures: Arc<super::expose::Exposures>,
+    /// Hardware fingerprints pinned per serial (see [`super::identity`]).
+    pub identities: Arc<super::identity::IdentityPins>,
+    /// Refuse registrations whose fingerprint doesn't match the pin.
+    pub enforce_identity: bool,
 }
 
 /// A device connected to the relay via its outbound WS tunnel.
@@ -348,6 +367,88 @@ pub struct ConnectedDevice {
     pub last_gps_fix: Arc<RwLock<Option<Value>>>,
     /// Latest LTE signal broadcast from device.
     pub last_lte_signal: Arc<RwLock<Option<Value>>>,
+    /// Dedup cursor for sequenced lifecycle events (`event_seq`). Carried over
+    /// across reconnects of the same event stream so resends aren't rebroadcast.
+    pub event_cursor: Arc<Mutex<EventCursor>>,
+    /// Other relays the device is registered with at the same time
+    /// (active-active). Lets clients and load balancers route around this
+    /// relay during a deploy without the device going unreachable.
+    pub peer_relays: Vec<String>,
+    /// Labels from the device's `[device] tags`, e.g. `site = "warehouse-3"`.
+    pub tags: BTreeMap<String, String>,
+    /// The key `api_key` replaced, accepted until the instant given while
+    /// the device's rotation grace window lasts.
+    pub previous_api_key: Option<(String, Instant)>,
+    /// Forwarded TCP streams open over this connection, keyed by stream id;
+    /// each feeds bytes to a client's `/d/{serial}/forward/{port}` WS.
+    pub forwards: super::forward::RelayStreams,
+    /// Hardware identity the device registered with (`null` from older
+    /// devices), and how it compared to the pin.
+    pub identity: Value,
+    pub identity_status: super::identity::IdentityStatus,
+}
+
+impl ConnectedDevice {
+    /// Whether `key` is the device's API key, or its previous key during a
+    /// rotation's grace window.
+    pub fn accepts_key(&self, key: &str) -> bool {
+        let current = crate::auth::constant_time_eq(self.api_key.as_bytes(), key.as_bytes());
+        let previous = self.previous_api_key.as_ref().is_some_and(|(old, until)| {
+            Instant::now() < *until && crate::auth::constant_time_eq(old.as_bytes(), key.as_bytes())
+        });
+        current || previous
+    }
+}
+
+/// The previous key and its deadline from a `tunnel.register` or
+/// `tunnel.api_key` message.
+fn parse_previous_key(msg: &Value) -> Option<(String, Instant)> {
+    let key = msg["previous_api_key"].as_str()?;
+    let expires_in = Duration::from_millis(msg["previous_expires_in_ms"].as_u64()?);
+    Some((key.to_string(), Instant::now() + expires_in))
+}
+
+/// Tracks which sequenced lifecycle events from a device have been delivered.
+///
+/// Events normally arrive in order, but one dropped on the device side and
+/// resent later arrives below the high-water mark; `missing` remembers those
+/// gaps so a late resend is still delivered exactly once.
+pub struct EventCursor {
+    /// Device-chosen id of the sequence space (changes on device restart).
+    pub stream_id: String,
+    /// Highest `event_seq` delivered.
+    pub last_seq: u64,
+    /// Sequence numbers below `last_seq` not yet seen.
+    pub missing: BTreeSet<u64>,
+}
+
+impl EventCursor {
+    /// Max gap entries remembered; older gaps are forgotten (treated as lost).
+    const MAX_MISSING: usize = 1024;
+
+    pub fn new(stream_id: String) -> Self {
+        Self {
+            stream_id,
+            last_seq: 0,
+            missing: BTreeSet::new(),
+        }
+    }
+
+    /// Record `seq`; returns `true` if it had not been delivered before.
+    pub fn accept(&mut self, seq: u64) -> bool {
+        if seq > self.last_seq {
+            for gap in (self.last_seq + 1..seq).rev().take(Self::MAX_MISSING) {
+                self.missing.insert(gap);
+            }
+            while self.missing.len() > Self::MAX_MISSING {
+                self.missing.pop_first();
+            }
+            self.last_seq = seq;
+            true
+        } else {
+            self.missing.remove(&seq)
+        }
+    }
 }
 
 /// Drain all pending requests for a device, sending error responses on each oneshot.
@@ -371,6 +472,11 @@ async fn drain_device(device: &ConnectedDevice, reason: &str) {
         );
     }
 
+    // End forwarded streams; their clients' WSs close
+    for (_, stream) in device.forwards.lock().await.drain() {
+        stream.credit.close();
+    }
+
     // Notify all connected WS clients
     let clients = device.clients.read().await;
     if !clients.is_empty() {
@@ -410,9 +516,40 @@ impl RelayState {
             next_connection_id: Arc::new(AtomicU64::new(1)),
             snapshots_dirty: Arc::new(AtomicBool::new(false)),
             snapshots_path,
+            rollouts: Arc::default(),
+            enrollment_token: None,
+            require_enrollment: false,
+            enrollments: Arc::new(super::enrollment::Enrollments::load(data_dir)),
+            exposures: Arc::new(super::expose::Exposures::new(0)),
+            identities: Arc::new(super::identity::IdentityPins::load(data_dir)),
+            enforce_identity: false,
         }
     }
 
+    /// Cap the bandwidth of every exposed port at `rate_bps` (`0` = none).
+    #[must_use]
+    pub fn with_expose_rate_limit(mut self, rate_bps: u64) -> Self {
+        self.exposures = Arc::new(super::expose::Exposures::new(rate_bps));
+        self
+    }
+
+    /// Accept `enrollment_token` from unenrolled devices, and with `require`
+    /// stop accepting the shared tunnel key from them.
+    #[must_use]
+    pub fn with_enrollment(mut self, enrollment_token: Option<String>, require: bool) -> Self {
+        self.enrollment_token = enrollment_token;
+        self.require_enrollment = require;
+        self
+    }
+
+    /// Refuse devices whose hardware fingerprint differs from the pinned one,
+    /// rather than only flagging them.
+    #[must_use]
+    pub fn with_identity_enforcement(mut self, enforce: bool) -> Self {
+        self.enforce_identity = enforce;
+        self
+    }
+
     /// Evict devices whose heartbeat is older than `heartbeat_timeout_secs`.
     /// Returns the serials of evicted devices.
     ///
@@ -634,21 +771,83 @@ pub fn relay_router(relay_state: RelayState) -> Router {
     // Tunnel management endpoints (authenticated with tunnel_key)
     let tunnel_admin = Router::new()
         .route("/api/tunnel/register", get(device_register_ws))
-        .route("/api/tunnel/devices", get(list_devices));
+        .route("/api/tunnel/devices", get(list_devices))
+        .route("/api/tunnel/twin", get(fleet_twin))
+        .route("/api/tunnel/resolve", get(relay_resolve))
+        .route(
+            "/api/tunnel/broadcast/exec",
+            post(super::fleet::broadcast_exec),
+        )
+        .route(
+            "/api/fleet/rollouts",
+            get(super::rollout::list_rollouts).post(super::rollout::start_rollout),
+        )
+        .route("/api/fleet/rollouts/{id}", get(super::rollout::get_rollout))
+        .route(
+            "/api/fleet/rollouts/{id}/abort",
+            post(super::rollout::abort_rollout),
+        )
+        .route(
+            "/api/tunnel/enrollments",
+            get(super::enrollment::list_enrollments),
+        )
+        .route(
+            "/api/tunnel/enrollments/{serial}",
+            delete(super::enrollment::delete_enrollment),
+        )
+        .route(
+            "/api/tunnel/enrollments/{serial}/rotate",
+            post(super::enrollment::rotate_enrollment),
+        )
+        .route(
+            "/api/tunnel/identities",
+            get(super::identity::list_identities),
+        )
+        .route(
+            "/api/tunnel/identities/{serial}",
+            delete(super::identity::delete_identity),
+        );
 
     // Device proxy endpoints: /d/{serial}/api/*
     let device_proxy = Router::new()
         .route("/d/{serial}/api/health", get(proxy_health))
         .route("/d/{serial}/api/info", get(proxy_info))
         .route("/d/{serial}/api/diagnostics", get(proxy_diagnostics))
+        .route("/d/{serial}/api/health/history", get(proxy_health_history))
+        .route(
+            "/d/{serial}/api/support-bundle",
+            get(proxy_support_bundle_status).post(proxy_support_bundle),
+        )
+        .route("/d/{serial}/api/tunnel/diag", get(proxy_tunnel_diag))
         .route("/d/{serial}/api/exec", post(proxy_exec))
         .route("/d/{serial}/api/exec/batch", post(proxy_exec_batch))
+        .route("/d/{serial}/api/exec/pending", get(proxy_exec_pending))
+        .route(
+            "/d/{serial}/api/exec/pending/{id}/confirm",
+            post(proxy_exec_confirm),
+        )
         .route(
             "/d/{serial}/api/files",
             get(proxy_file_read)
                 .put(proxy_file_write)
                 .delete(proxy_file_delete),
         )
+        .route("/d/{serial}/api/files/xattrs", put(proxy_file_xattrs))
+        .route(
+            "/d/{serial}/api/files/copy",
+            get(proxy_file_copy_list).post(proxy_file_copy),
+        )
+        .route("/d/{serial}/api/ai", get(proxy_ai_status))
+        .route("/d/{serial}/api/ai/disable", post(proxy_ai_disable))
+        .route(
+            "/d/{serial}/api/flightrecorder",
+            get(proxy_flight_recorder_status),
+        )
+        .route(
+            "/d/{serial}/api/flightrecorder/dump",
+            post(proxy_flight_recorder_dump),
+        )
+        .route("/d/{serial}/api/ai/enable", post(proxy_ai_enable))
         // gawdxfer STP proxy endpoints (replaces old /api/files/raw and /api/files/upload proxy)
         .route(
             "/d/{serial}/api/stp/download",
@@ -660,6 +859,7 @@ pub fn relay_router(relay_state: RelayState) -> Router {
             get(proxy_stp_download_chunk).post(proxy_stp_upload_chunk),
         )
         .route("/d/{serial}/api/stp/resume/{xfer}", post(proxy_stp_resume))
+        .route("/d/{serial}/api/stp/throttle", post(proxy_stp_throttle))
         .route("/d/{serial}/api/stp/status/{xfer}", get(proxy_stp_status))
         .route("/d/{serial}/api/stp/transfers", get(proxy_stp_list))
         .route("/d/{serial}/api/stp/{xfer}", delete(proxy_stp_abort))
@@ -668,7 +868,10 @@ pub fn relay_router(relay_state: RelayState) -> Router {
             "/d/{serial}/api/activity/{id}/result",
             get(proxy_exec_result),
         )
-        .route("/d/{serial}/api/sessions", get(proxy_sessions))
+        .route(
+            "/d/{serial}/api/sessions",
+            get(proxy_sessions).post(proxy_session_start),
+        )
         .route(
             "/d/{serial}/api/sessions/{id}",
             delete(proxy_session_kill).patch(proxy_session_patch),
@@ -677,6 +880,18 @@ pub fn relay_router(relay_state: RelayState) -> Router {
             "/d/{serial}/api/sessions/{id}/signal",
             post(proxy_session_signal),
         )
+        .route(
+            "/d/{serial}/api/sessions/{id}/history",
+            get(proxy_session_history),
+        )
+        .route(
+            "/d/{serial}/api/sessions/{id}/journal",
+            get(proxy_session_journal),
+        )
+        .route(
+            "/d/{serial}/api/sessions/{id}/share",
+            post(proxy_session_share),
+        )
         .route("/d/{serial}/api/shells", get(proxy_shells))
         .route("/d/{serial}/api/playbooks", get(proxy_playbooks_list))
         .route(
@@ -685,8 +900,68 @@ pub fn relay_router(relay_state: RelayState) -> Router {
                 .put(proxy_playbook_put)
                 .delete(proxy_playbook_delete),
         )
+        .route(
+            "/d/{serial}/api/playbooks/{name}/run",
+            post(proxy_playbook_run),
+        )
+        .route(
+            "/d/{serial}/api/playbooks/{name}/runs",
+            get(proxy_playbook_runs),
+        )
+        .route(
+            "/d/{serial}/api/playbooks/{name}/runs/{run_id}",
+            get(proxy_playbook_run_get),
+        )
+        .route(
+            "/d/{serial}/api/ssh/authorized_keys",
+            get(proxy_ssh_keys_list)
+                .post(proxy_ssh_keys_add)
+                .delete(proxy_ssh_keys_delete),
+        )
+        .route("/d/{serial}/api/hooks", get(proxy_hooks_get))
+        .route("/d/{serial}/api/hooks/reload", post(proxy_hooks_reload))
+        .route(
+            "/d/{serial}/api/twin",
+            get(proxy_twin_get).put(proxy_twin_put),
+        )
+        .route("/d/{serial}/api/twin/reconcile", post(proxy_twin_reconcile))
+        .route("/d/{serial}/api/fetch", post(proxy_fetch))
+        .route("/d/{serial}/api/artifacts/push", post(proxy_artifacts_push))
+        .route("/d/{serial}/api/resolve", get(proxy_resolve))
+        .route(
+            "/d/{serial}/api/snapshots",
+            get(proxy_snapshots_list).post(proxy_snapshots_create),
+        )
+        .route(
+            "/d/{serial}/api/snapshots/{id}",
+            get(proxy_snapshots_get).delete(proxy_snapshots_delete),
+        )
+        .route(
+            "/d/{serial}/api/snapshots/{a}/diff/{b}",
+            get(proxy_snapshots_diff),
+        )
+        .route("/d/{serial}/api/info/diff", get(proxy_info_diff))
+        .route("/d/{serial}/api/plugins", get(proxy_plugins_list))
+        .route("/d/{serial}/api/plugins/{name}", get(proxy_plugins_get))
+        .route(
+            "/d/{serial}/api/plugins/{name}/{command}",
+            post(proxy_plugins_run),
+        )
+        .route("/d/{serial}/api/firewall", get(proxy_firewall_get))
+        .route("/d/{serial}/api/firewall/apply", post(proxy_firewall_apply))
+        .route(
+            "/d/{serial}/api/time",
+            get(proxy_time_get).post(proxy_time_set),
+        )
+        .route("/d/{serial}/api/users", get(proxy_users_list))
+        .route(
+            "/d/{serial}/api/users/{name}/{action}",
+            post(proxy_users_action),
+        )
         .route("/d/{serial}/api/gps", get(proxy_gps))
+        .route("/d/{serial}/api/gps/track", get(proxy_gps_track))
         .route("/d/{serial}/api/lte", get(proxy_lte))
+        .route("/d/{serial}/api/lte/history", get(proxy_lte_history))
         .route("/d/{serial}/api/lte/bands", post(proxy_lte_bands))
         .route("/d/{serial}/api/lte/scan", post(proxy_lte_scan))
         .route("/d/{serial}/api/lte/speedtest", post(proxy_lte_speedtest))
@@ -709,7 +984,18 @@ pub fn relay_router(relay_state: RelayState) -> Router {
             "/d/{serial}/api/infra/check/{target_id}",
             post(proxy_infra_check),
         )
-        .route("/d/{serial}/api/ws", get(proxy_ws));
+        .route("/d/{serial}/api/ws", get(proxy_ws))
+        .route("/d/{serial}/forward/{port}", get(proxy_forward))
+        .route("/d/{serial}/api/sftp", get(proxy_sftp))
+        .route("/d/{serial}/dav", any(proxy_dav))
+        .route("/d/{serial}/dav/", any(proxy_dav))
+        .route("/d/{serial}/dav/{*path}", any(proxy_dav))
+        .route("/s/{token}", get(share_page))
+        .route("/s/{token}/ws", get(share_ws))
+        .route("/x/{id}", any(proxy_exposed))
+        .route("/x/{id}/", any(proxy_exposed))
+        .route("/x/{id}/{*path}", any(proxy_exposed))
+        .layer(middleware::from_fn(scope_idempotency_key));
 
     tunnel_admin.merge(device_proxy).with_state(relay_state)
 }
@@ -734,32 +1020,64 @@ fn is_valid_serial(s: &str) -> bool {
 /// Maximum concurrent WS clients per device.
 const MAX_CLIENTS_PER_DEVICE: usize = 32;
 
+/// Viewer served at `/s/{token}`: polls `session.read_diff` (or follows the
+/// raw output for pipe sessions) and sends keystrokes in interactive mode.
+const SHARE_PAGE: &str = include_str!("share_page.html");
+
 /// `GET /api/tunnel/register?token=<tunnel_key>&serial=<serial>` — device WS registration.
 async fn device_register_ws(
     State(state): State<RelayState>,
     Query(query): Query<RegisterQuery>,
+    extensions: axum::http::Extensions,
     ws: WebSocketUpgrade,
 ) -> Response {
-    if !crate::auth::constant_time_eq(state.tunnel_key.as_bytes(), query.token.as_bytes()) {
-        return (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response();
-    }
-
     if !is_valid_serial(&query.serial) {
         return (StatusCode::BAD_REQUEST, "Invalid serial format").into_response();
     }
 
+    // An enrolled device only gets in with its own key. Otherwise the
+    // enrollment token gets it enrolled, and the shared key is accepted
+    // unless enrollment is required.
+    let enroll = match state.enrollments.verify(&query.serial, &query.token).await {
+        Some(true) => false,
+        Some(false) => {
+            return (StatusCode::FORBIDDEN, "Invalid device key").into_response();
+        }
+        None if state.enrollment_token.as_ref().is_some_and(|t| {
+            crate::auth::constant_time_eq(t.as_bytes(), query.token.as_bytes())
+        }) =>
+        {
+            true
+        }
+        None if !state.require_enrollment
+            && crate::auth::constant_time_eq(
+                state.tunnel_key.as_bytes(),
+                query.token.as_bytes(),
+            ) =>
+        {
+            false
+        }
+        None => return (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response(),
+    };
+
     let serial = query.serial.clone();
-    info!(serial = %serial, "Device connecting...");
+    crate::connections::identify(&extensions, &format!("device:{serial}"));
+    info!(serial = %serial, enroll, "Device connecting...");
 
     ws.on_upgrade(move |socket| {
-        handle_device_ws(socket, state, serial.clone())
+        handle_device_ws(socket, state, serial.clone(), enroll)
             .instrument(info_span!("tunnel_device", serial = %serial))
     })
 }
 
 /// Handle a registered device's WebSocket connection.
 #[allow(clippy::too_many_lines)]
-async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayState, serial: String) {
+async fn handle_device_ws(
+    socket: axum::extract::ws::WebSocket,
+    state: RelayState,
+    serial: String,
+    enroll: bool,
+) {
     let (mut ws_sink, mut ws_stream) = socket.split();
     let (device_tx, mut device_rx) = mpsc::channel::<TunnelMessage>(256);
     // Priority channel for ping/pong — bypasses the main device_tx queue so
@@ -775,21 +1093,59 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
         warn!(serial = %serial, "Device disconnected before registration");
         return;
     };
-    let api_key = match serde_json::from_str::<Value>(&text) {
-        Ok(msg) if msg["type"].as_str() == Some("tunnel.register") => {
-            msg["api_key"].as_str().unwrap_or("").to_string()
-        }
-        _ => {
-            warn!(serial = %serial, "Device sent invalid registration");
-            return;
-        }
-    };
+    let (api_key, previous_api_key, peer_relays, event_stream, tags, msgpack, identity) =
+        match serde_json::from_str::<Value>(&text) {
+            Ok(msg) if msg["type"].as_str() == Some("tunnel.register") => {
+                let peers: Vec<String> = msg["peer_relays"]
+                    .as_array()
+                    .map(|a| {
+                        a.iter()
+                            .filter_map(|v| v.as_str().map(ToString::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (
+                    msg["api_key"].as_str().unwrap_or("").to_string(),
+                    parse_previous_key(&msg),
+                    peers,
+                    msg["event_stream"].as_str().unwrap_or("").to_string(),
+                    registration_tags(&serial, &msg["tags"]),
+                    super::codec::offered(&msg),
+                    msg["identity"].clone(),
+                )
+            }
+            _ => {
+                warn!(serial = %serial, "Device sent invalid registration");
+                return;
+            }
+        };
 
     if api_key.is_empty() {
         warn!(serial = %serial, "Device registered with empty api_key");
         return;
     }
 
+    let identity_status = state.identities.check(&serial, &identity).await;
+    if identity_status == super::identity::IdentityStatus::Mismatch {
+        warn!(
+            serial = %serial,
+            fingerprint = identity["fingerprint"].as_str().unwrap_or(""),
+            enforced = state.enforce_identity,
+            "Device hardware fingerprint doesn't match the pinned identity"
+        );
+        if state.enforce_identity {
+            let error = json!({
+                "type": "error",
+                "code": "IDENTITY_MISMATCH",
+                "message": "hardware fingerprint doesn't match the identity pinned for this serial",
+            });
+            let _ = ws_sink
+                .send(axum::extract::ws::Message::Text(error.to_string().into()))
+                .await;
+            return;
+        }
+    }
+
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
 
     #[allow(clippy::cast_possible_truncation)]
@@ -798,13 +1154,20 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
     // When a device reconnects (LTE flap, etc.), WS clients are still connected
     // to the relay. By sharing the same Arcs, client handlers' references stay
     // valid — cleanup (remove on disconnect) works regardless of tunnel reconnects.
-    let (shared_clients, shared_subs, shared_gps, shared_lte) = {
+    let (shared_clients, shared_subs, shared_gps, shared_lte, shared_cursor) = {
         let devices = state.devices.read().await;
         if let Some(old_device) = devices.get(&serial) {
             let clients = old_device.clients.clone();
             let subs = old_device.session_subscriptions.clone();
             let gps = old_device.last_gps_fix.clone();
             let lte = old_device.last_lte_signal.clone();
+            // Same event stream (tunnel reconnect) → keep dedup state so
+            // resent events aren't rebroadcast; new stream → start fresh.
+            let cursor = if old_device.event_cursor.lock().await.stream_id == event_stream {
+                old_device.event_cursor.clone()
+            } else {
+                Arc::new(Mutex::new(EventCursor::new(event_stream.clone())))
+            };
             let n = clients.read().await.len();
             if n > 0 {
                 info!(
@@ -813,13 +1176,14 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                     "Preserving {n} WS clients across device reconnect"
                 );
             }
-            (clients, subs, gps, lte)
+            (clients, subs, gps, lte, cursor)
         } else {
             (
                 Arc::new(RwLock::new(HashMap::new())),
                 Arc::new(RwLock::new(HashMap::new())),
                 Arc::new(RwLock::new(None)),
                 Arc::new(RwLock::new(None)),
+                Arc::new(Mutex::new(EventCursor::new(event_stream.clone()))),
             )
         }
     };
@@ -840,6 +1204,13 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
         shutdown_tx,
         last_gps_fix: shared_gps,
         last_lte_signal: shared_lte,
+        event_cursor: shared_cursor,
+        peer_relays,
+        tags,
+        previous_api_key,
+        forwards: Arc::new(Mutex::new(HashMap::new())),
+        identity,
+        identity_status,
     };
 
     let pending_requests = device.pending_requests.clone();
@@ -850,6 +1221,8 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
     let dropped_messages = device.dropped_messages.clone();
     let last_gps_fix = device.last_gps_fix.clone();
     let last_lte_signal = device.last_lte_signal.clone();
+    let event_cursor = device.event_cursor.clone();
+    let forwards = device.forwards.clone();
 
     // Handle duplicate serial: signal old handler to shut down, drain pending
     // REST requests, then replace. Don't notify WS clients — they were migrated above.
@@ -878,10 +1251,13 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
         devices.insert(serial.clone(), device);
     }
     state.history.record_connect(&serial).await;
-    info!(serial = %serial, "Device registered");
+    info!(serial = %serial, msgpack, "Device registered");
 
     // Send ack
-    let ack = json!({"type": "tunnel.register.ack", "serial": &serial});
+    let mut ack = json!({"type": "tunnel.register.ack", "serial": &serial});
+    if msgpack {
+        ack["encoding"] = json!(super::codec::MSGPACK);
+    }
     let _ = ws_sink
         .send(axum::extract::ws::Message::Text(
             serde_json::to_string(&ack).unwrap().into(),
@@ -905,6 +1281,13 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
             };
             let Some(msg) = msg else { break };
             let ws_msg = match msg {
+                TunnelMessage::Text(val) if msgpack => {
+                    let Some(data) = super::codec::encode(&val) else {
+                        tracing::error!(serial = %writer_serial, "MessagePack encode failed in writer");
+                        continue;
+                    };
+                    axum::extract::ws::Message::Binary(data.into())
+                }
                 TunnelMessage::Text(val) => {
                     let text = match serde_json::to_string(&val) {
                         Ok(t) => t,
@@ -934,6 +1317,12 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
         }
         let _ = writer_exit_tx.send(());
     });
+
+    // Keys are issued over the tunnel, so this needs the read loop below
+    // running to receive the device's reply.
+    if enroll {
+        tokio::spawn(super::enrollment::enroll(state.clone(), serial.clone()));
+    }
     let mut writer_exit_rx = writer_exit_rx;
 
     // Relay-side active ping: send tunnel.ping via priority channel so pings
@@ -1013,6 +1402,17 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                 break;
             }
         };
+        // MessagePack frames are handled as the text frames they stand for.
+        let msg = match msg {
+            axum::extract::ws::Message::Binary(data) if super::codec::is_message(&data) => {
+                let Some(value) = super::codec::decode(&data) else {
+                    warn!(serial = %serial, "Invalid MessagePack from device");
+                    continue;
+                };
+                axum::extract::ws::Message::Text(value.to_string().into())
+            }
+            msg => msg,
+        };
         match msg {
             axum::extract::ws::Message::Text(text) => {
                 let Ok(parsed) = serde_json::from_str::<Value>(&text) else {
@@ -1020,6 +1420,19 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                 };
                 let msg_type = parsed["type"].as_str().unwrap_or("");
 
+                // Sequenced lifecycle event: ack it (on the priority lane so acks
+                // never queue behind proxied requests) and drop redeliveries.
+                if let Some(seq) = parsed["event_seq"].as_u64() {
+                    let fresh = event_cursor.lock().await.accept(seq);
+                    let _ = priority_tx.try_send(TunnelMessage::Text(
+                        json!({"type": "tunnel.events.ack", "seq": seq}),
+                    ));
+                    if !fresh {
+                        tracing::debug!(serial = %serial, seq, msg_type, "Duplicate lifecycle event dropped");
+                        continue;
+                    }
+                }
+
                 // Fast path: session output (hot path, bulk of traffic).
                 // These come from the device's tunnel_subscriber_task and are NEVER
                 // tagged with client_id prefixes — the subscriber doesn't know about
@@ -1148,6 +1561,9 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                                 if let Some(client_tx) = clients_read.get(client_id) {
                                     let mut response = parsed.clone();
                                     response["request_id"] = json!(original_rid);
+                                    if t == "latency.probe.result" {
+                                        fill_latency_breakdown(&mut response);
+                                    }
                                     let _ = client_tx.send(Arc::new(response)).await;
                                 }
                             } else {
@@ -1173,13 +1589,16 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                     | "session.closed"
                     | "session.exited"
                     | "session.renamed"
+                    | "session.updated"
                     | "session.ai_status_changed"
                     | "session.ai_permission_changed"
+                    | "session.control_changed"
                     | "session.exec.ack"
                     | "session.signal.ack"
                     | "session.resize.ack"
                     | "session.attached"
                     | "session.listed"
+                    | "session.diff"
                     | "session.allow_ai.ack"
                     | "session.ai_status.ack"
                     | "session.rename.ack"
@@ -1188,6 +1607,28 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                     | "gx.progress"
                     | "gx.complete"
                     | "gx.error"
+                    | "support_bundle.progress"
+                    | "support_bundle.ready"
+                    | "support_bundle.failed"
+                    | "file.copy.progress"
+                    | "file.copy.done"
+                    | "file.copy.failed"
+                    | "files.changed"
+                    | "files.watch.closed"
+                    | "logs.lines"
+                    | "logs.closed"
+                    | "ai.disabled"
+                    | "ai.enabled"
+                    | "ai.budget_exceeded"
+                    | "infra.status"
+                    | "infra.recovery"
+                    | "firmware.installing"
+                    | "firmware.installed"
+                    | "firmware.failed"
+                    | "firmware.rebooting"
+                    | "firmware.booted"
+                    | "firmware.rolled_back"
+                    | "firmware.committed"
                     | "error" => {
                         // Clean up session subscriptions when session is destroyed/closed
                         if msg_type == "session.destroyed" || msg_type == "session.closed" {
@@ -1250,6 +1691,91 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                             }
                         }
                     }
+                    // Device rotated its API key (`POST /api/auth/rotate`)
+                    "tunnel.api_key" => {
+                        if let Some(api_key) = parsed["api_key"].as_str().filter(|k| !k.is_empty())
+                        {
+                            let mut devices = state.devices.write().await;
+                            if let Some(device) = devices
+                                .get_mut(&serial)
+                                .filter(|d| d.connection_id == connection_id)
+                            {
+                                device.api_key = api_key.to_string();
+                                device.previous_api_key = parse_previous_key(&parsed);
+                                info!(serial = %serial, "Device API key rotated");
+                            }
+                        }
+                    }
+                    // Local side of a forwarded stream closed; dropping the
+                    // sender ends the client's WS.
+                    "tunnel.forward.close" => {
+                        if let Some(stream_id) = parsed["stream_id"].as_str() {
+                            if let Some(stream) = forwards.lock().await.remove(stream_id) {
+                                stream.credit.close();
+                            }
+                        }
+                    }
+                    // The device wrote a forward client's bytes to its socket
+                    "tunnel.forward.credit" => {
+                        if let (Some(stream_id), Some(bytes)) =
+                            (parsed["stream_id"].as_str(), parsed["bytes"].as_u64())
+                        {
+                            if let Some(stream) = forwards.lock().await.get(stream_id) {
+                                stream.credit.grant(bytes);
+                            }
+                        }
+                    }
+                    // Device asking how a name resolves from here (`GET /api/resolve?via=relay`)
+                    "tunnel.resolve" => {
+                        let reply_tx = device_tx.clone();
+                        let request_id = parsed["request_id"].clone();
+                        let name = parsed["name"].as_str().unwrap_or("").to_string();
+                        tokio::spawn(async move {
+                            let (status, body) = if super::resolve::valid_name(&name) {
+                                (200, super::resolve::lookup(&name).await)
+                            } else {
+                                (400, json!({"error": "Invalid or missing 'name'"}))
+                            };
+                            let _ = reply_tx
+                                .send(TunnelMessage::Text(json!({
+                                    "type": "tunnel.resolve.result",
+                                    "request_id": request_id,
+                                    "status": status,
+                                    "body": body,
+                                })))
+                                .await;
+                        });
+                    }
+                    // Device publishing or withdrawing a port (`/api/tunnel/expose`)
+                    "tunnel.expose" | "tunnel.unexpose" => {
+                        let (status, body) = if msg_type == "tunnel.expose" {
+                            match state.exposures.create(
+                                &serial,
+                                parsed["port"].as_u64().unwrap_or(0),
+                                parsed["ttl_secs"].as_u64(),
+                                parsed["rate_limit_bps"].as_u64(),
+                            ) {
+                                Ok(body) => {
+                                    info!(serial = %serial, port = %body["port"], "Device port exposed");
+                                    (200, body)
+                                }
+                                Err(e) => (400, json!({"error": e})),
+                            }
+                        } else {
+                            let id = parsed["expose_id"].as_str().unwrap_or("");
+                            if state.exposures.remove(&serial, id) {
+                                (200, json!({"ok": true, "expose_id": id}))
+                            } else {
+                                (404, json!({"error": format!("No exposure {id}")}))
+                            }
+                        };
+                        let _ = device_tx.try_send(TunnelMessage::Text(json!({
+                            "type": format!("{msg_type}.result"),
+                            "request_id": parsed["request_id"],
+                            "status": status,
+                            "body": body,
+                        })));
+                    }
                     // Device telemetry broadcasts — store latest and forward to WS clients
                     "gps.fix" | "lte.signal" | "lte.watchdog" => {
                         match msg_type {
@@ -1267,6 +1793,32 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
                             }
                         }
                     }
+                    // Events the device spooled while offline — fan out to all
+                    // clients in original order, marked so UIs can tell them apart.
+                    "tunnel.replay" => {
+                        let events = parsed["events"].as_array().cloned().unwrap_or_default();
+                        info!(
+                            serial = %serial,
+                            count = events.len(),
+                            dropped = parsed["dropped"].as_u64().unwrap_or(0),
+                            "Device replayed spooled offline events"
+                        );
+                        let clients_read = clients.read().await;
+                        for entry in events {
+                            let mut event = entry["event"].clone();
+                            if !event.is_object() {
+                                continue;
+                            }
+                            event["replayed"] = json!(true);
+                            event["spooled_at"] = entry["spooled_at"].clone();
+                            let payload = Arc::new(event);
+                            for (_, client_tx) in clients_read.iter() {
+                                if client_tx.try_send(payload.clone()).is_err() {
+                                    dropped_messages.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    }
                     _ => {
                         warn!(serial = %serial, msg_type, "Unknown message from device");
                     }
@@ -1275,7 +1827,14 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
             axum::extract::ws::Message::Binary(data) => {
                 // Binary frame from device — decode header and route to pending request
                 if let Some((header, payload)) = decode_binary_frame(&data) {
-                    if let Some(request_id) = header["request_id"].as_str() {
+                    if header["type"] == super::forward::FORWARD_DATA {
+                        // Never waits: the device sends no more than a
+                        // window ahead of the credit this client returns.
+                        let stream_id = header["stream_id"].as_str().unwrap_or("");
+                        if let Some(stream) = forwards.lock().await.get(stream_id) {
+                            let _ = stream.to_client.send(payload.to_vec());
+                        }
+                    } else if let Some(request_id) = header["request_id"].as_str() {
                         let mut pending = pending_requests.lock().await;
                         if let Some(sender) = pending.remove(request_id) {
                             let _ = sender.send(TunnelResponse::Binary {
@@ -1348,10 +1907,58 @@ async fn handle_device_ws(socket: axum::extract::ws::WebSocket, state: RelayStat
     relay_ping_task.abort();
 }
 
+/// Valid tags from a `tunnel.register` message; others are logged and dropped.
+fn registration_tags(serial: &str, tags: &Value) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for (key, value) in tags.as_object().into_iter().flatten() {
+        match value.as_str() {
+            Some(value) if out.len() < super::MAX_DEVICE_TAGS && super::valid_tag(key, value) => {
+                out.insert(key.clone(), value.to_string());
+            }
+            _ => warn!(serial = %serial, tag = %key, "Ignoring invalid device tag"),
+        }
+    }
+    out
+}
+
 /// `GET /api/tunnel/devices` — list connected devices (admin, requires `tunnel_key`).
 #[derive(Deserialize)]
 struct DevicesQuery {
     token: String,
+    /// Comma-separated `key=value` or bare `key` (tag present); all must match.
+    tag: Option<String>,
+    /// Tag key to summarize the matching devices by.
+    group_by: Option<String>,
+}
+
+/// One `?tag=` condition: the key, and the value it must have (if any).
+pub(super) type TagFilter = (String, Option<String>);
+
+pub(super) fn parse_tag_filter(filter: &str) -> Result<Vec<TagFilter>, String> {
+    filter
+        .split(',')
+        .map(|part| {
+            let (key, value) = match part.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (part, None),
+            };
+            if super::valid_tag(key, value.unwrap_or_default()) {
+                Ok((key.to_string(), value.map(ToString::to_string)))
+            } else {
+                Err(format!("Invalid tag filter '{part}'"))
+            }
+        })
+        .collect()
+}
+
+pub(super) fn matches_tags(tags: &BTreeMap<String, String>, filter: &[TagFilter]) -> bool {
+    filter
+        .iter()
+        .all(|(key, value)| match (tags.get(key), value) {
+            (Some(actual), Some(value)) => actual == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
 }
 
 async fn list_devices(
@@ -1362,12 +1969,25 @@ async fn list_devices(
         return (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response();
     }
 
+    let filter = match query.tag.as_deref().map(parse_tag_filter).transpose() {
+        Ok(filter) => filter.unwrap_or_default(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
     let devices = state.devices.read().await;
     let mut list: Vec<Value> = Vec::with_capacity(devices.len());
+    // group_by value (None = tag absent) -> serials
+    let mut groups: BTreeMap<Option<&String>, Vec<&String>> = BTreeMap::new();
 
     #[allow(clippy::cast_possible_truncation)]
     let now_ms = state.epoch.elapsed().as_millis() as u64;
     for d in devices.values() {
+        if !matches_tags(&d.tags, &filter) {
+            continue;
+        }
+        if let Some(key) = &query.group_by {
+            groups.entry(d.tags.get(key)).or_default().push(&d.serial);
+        }
         let last_hb_ms = d.last_heartbeat_ms.load(Ordering::Relaxed);
         let hb_ago_ms = now_ms.saturating_sub(last_hb_ms);
         let pending_count = d.pending_requests.lock().await.len();
@@ -1392,21 +2012,169 @@ async fn list_devices(
             "dropped_messages": d.dropped_messages.load(Ordering::Relaxed),
             "last_gps_fix": *d.last_gps_fix.read().await,
             "last_lte_signal": *d.last_lte_signal.read().await,
+            "peer_relays": d.peer_relays,
+            "tags": d.tags,
+            "identity_status": d.identity_status,
+            "fingerprint": d.identity["fingerprint"],
         }));
     }
 
-    Json(json!({"devices": list})).into_response()
+    let mut body = json!({"devices": list});
+    if let Some(key) = &query.group_by {
+        body["group_by"] = json!(key);
+        body["groups"] = groups
+            .into_iter()
+            .map(|(value, mut serials)| {
+                serials.sort();
+                json!({ "value": value, "count": serials.len(), "serials": serials })
+            })
+            .collect();
+    }
+    Json(body).into_response()
+}
+
+#[derive(Deserialize)]
+struct ResolveQuery {
+    token: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// `GET /api/tunnel/resolve?token=&name=` — resolve a name at the relay, to
+/// compare with a device's `GET /d/{serial}/api/resolve`.
+async fn relay_resolve(
+    State(state): State<RelayState>,
+    Query(query): Query<ResolveQuery>,
+) -> Response {
+    if !crate::auth::constant_time_eq(state.tunnel_key.as_bytes(), query.token.as_bytes()) {
+        return (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response();
+    }
+    if !super::resolve::valid_name(&query.name) {
+        return (StatusCode::BAD_REQUEST, "Invalid or missing 'name'").into_response();
+    }
+    let mut body = super::resolve::lookup(&query.name).await;
+    body["via"] = json!("relay");
+    Json(body).into_response()
+}
+
+/// How long `GET /api/tunnel/twin` waits for each device.
+const FLEET_TWIN_TIMEOUT_SECS: u64 = 10;
+
+/// `GET /api/tunnel/twin?token=[&tag=]` — drift summary for every connected
+/// (matching) device, asked for with `tunnel.twin.get` in parallel.
+async fn fleet_twin(
+    State(state): State<RelayState>,
+    Query(query): Query<DevicesQuery>,
+) -> Response {
+    if !crate::auth::constant_time_eq(state.tunnel_key.as_bytes(), query.token.as_bytes()) {
+        return (StatusCode::FORBIDDEN, "Invalid tunnel key").into_response();
+    }
+
+    let filter = match query.tag.as_deref().map(parse_tag_filter).transpose() {
+        Ok(filter) => filter.unwrap_or_default(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let mut serials: Vec<String> = state
+        .devices
+        .read()
+        .await
+        .values()
+        .filter(|d| matches_tags(&d.tags, &filter))
+        .map(|d| d.serial.clone())
+        .collect();
+    serials.sort();
+    let devices: Vec<Value> = futures::future::join_all(serials.into_iter().map(|serial| {
+        let state = state.clone();
+        async move {
+            let msg = json!({
+                "type": "tunnel.twin.get",
+                "request_id": uuid::Uuid::new_v4().to_string(),
+            });
+            let response = tunnel_request_json(&state, &serial, msg, FLEET_TWIN_TIMEOUT_SECS)
+                .await
+                .and_then(|r| proxy_response_to_http(&r));
+            match response {
+                Ok(Json(twin)) => twin_summary(&serial, &twin),
+                Err((status, Json(body))) => json!({
+                    "serial": serial,
+                    "error": body["message"]
+                        .as_str()
+                        .or(body["error"].as_str())
+                        .map_or_else(|| status.to_string(), ToString::to_string),
+                }),
+            }
+        }
+    }))
+    .await;
+
+    let count = |pred: fn(&Value) -> bool| devices.iter().filter(|d| pred(d)).count();
+    let totals = json!({
+        "devices": devices.len(),
+        "in_sync": count(|d| d["in_sync"] == json!(true)),
+        "drifted": count(|d| d["in_sync"] == json!(false)),
+        "unmanaged": count(|d| d["managed"] == json!(false)),
+        "errors": count(|d| d.get("error").is_some()),
+    });
+    Json(json!({ "totals": totals, "devices": devices })).into_response()
+}
+
+/// One device's row in `GET /api/tunnel/twin`.
+fn twin_summary(serial: &str, twin: &Value) -> Value {
+    let report = &twin["report"];
+    let outstanding = report["drift"].as_array().map_or(0, |drift| {
+        drift.iter().filter(|d| d["fixed"] != json!(true)).count()
+    });
+    json!({
+        "serial": serial,
+        "managed": !twin["desired"].is_null(),
+        "in_sync": report["in_sync"],
+        "drift_count": outstanding,
+        "checked_at": report["checked_at"],
+        "apply": twin["apply"],
+    })
 }
 
 // ─── REST Proxy Helpers ──────────────────────────────────────────────────────
 
+tokio::task_local! {
+    static IDEMPOTENCY_KEY: String;
+}
+
+/// Axum middleware that makes the client's `Idempotency-Key` header the
+/// idempotency key of every tunnel request the handler sends. A device
+/// registered with several relays runs a request once even when the client
+/// sends it through each of them, although every relay issues its own
+/// `request_id`. `X-Request-Id` isn't used, since proxies often rewrite it
+/// per hop.
+async fn scope_idempotency_key(request: Request<Body>, next: Next) -> Response {
+    let key = request
+        .headers()
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|k| !k.is_empty())
+        .map(ToString::to_string);
+    match key {
+        Some(key) => IDEMPOTENCY_KEY.scope(key, next.run(request)).await,
+        None => next.run(request).await,
+    }
+}
+
 /// Send a tunnel request to a device and await the response.
 pub async fn tunnel_request(
     state: &RelayState,
     serial: &str,
-    msg: Value,
+    mut msg: Value,
     timeout_secs: u64,
 ) -> Result<TunnelResponse, (StatusCode, Json<Value>)> {
+    // The device stops working on the request when the relay stops waiting.
+    if msg.get("deadline_in_ms").is_none() {
+        msg["deadline_in_ms"] = json!(timeout_secs.saturating_mul(1000));
+    }
+    if msg.get("idempotency_key").is_none() {
+        if let Ok(key) = IDEMPOTENCY_KEY.try_with(Clone::clone) {
+            msg["idempotency_key"] = json!(key);
+        }
+    }
     let devices = state.devices.read().await;
     let device = devices.get(serial).ok_or_else(|| {
         (
@@ -1597,7 +2365,7 @@ fn validate_device_auth<'a>(
         }
     };
 
-    if !crate::auth::constant_time_eq(device.api_key.as_bytes(), provided_key.as_bytes()) {
+    if !device.accepts_key(provided_key) {
         return Err((
             StatusCode::FORBIDDEN,
             Json(json!({"error": "Invalid API key"})),
@@ -1639,11 +2407,12 @@ async fn proxy_health(
 #[derive(Deserialize)]
 struct InfoProxyQuery {
     groups: Option<String>,
+    fields: Option<String>,
 }
 
 fn parse_info_groups_csv(groups: Option<&str>) -> Vec<String> {
     let mut parsed = groups
-        .unwrap_or("core,interfaces,disk,tunnel,gps,lte")
+        .unwrap_or("core,interfaces,disk,tunnel,gps,lte,sensors,identity")
         .split(',')
         .map(str::trim)
         .filter(|g| !g.is_empty())
@@ -1657,6 +2426,8 @@ fn parse_info_groups_csv(groups: Option<&str>) -> Vec<String> {
             "tunnel".to_string(),
             "gps".to_string(),
             "lte".to_string(),
+            "sensors".to_string(),
+            "identity".to_string(),
         ];
     }
     parsed.sort();
@@ -1693,6 +2464,7 @@ async fn proxy_info(
             "type": "tunnel.info",
             "request_id": request_id,
             "groups": [group],
+            "fields": query.fields,
         });
         futures.push(tunnel_request_json(&state, &serial, msg, 10));
     }
@@ -1765,10 +2537,19 @@ async fn proxy_diagnostics(
     proxy_response_to_http(&response)
 }
 
-/// `POST /d/{serial}/api/exec` — proxied command execution.
-async fn proxy_exec(
+/// Query parameters for the health history proxy endpoint.
+#[derive(Deserialize)]
+struct HealthHistoryProxyQuery {
+    since: Option<u64>,
+    condition: Option<String>,
+    limit: Option<u64>,
+}
+
+/// `GET /d/{serial}/api/health/history` — proxied device health history.
+async fn proxy_health_history(
     State(state): State<RelayState>,
     AxumPath(serial): AxumPath<String>,
+    Query(query): Query<HealthHistoryProxyQuery>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -1776,24 +2557,161 @@ async fn proxy_exec(
         .get("authorization")
         .and_then(|v| v.to_str().ok())
         .map(ToString::to_string);
-    let sctl_client = request
-        .headers()
-        .get("x-sctl-client")
-        .and_then(|v| v.to_str().ok())
-        .map(ToString::to_string);
 
-    let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024 * 1024)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(json!({"error": "Failed to read request body"})),
-            )
-        })?;
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
 
-    let payload: Value = serde_json::from_slice(&body_bytes).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.health.history",
+        "request_id": request_id,
+        "since": query.since,
+        "condition": query.condition,
+        "limit": query.limit,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `POST /d/{serial}/api/support-bundle` — start a support bundle build on the
+/// device. Progress arrives as `support_bundle.*` events on the WS proxy.
+async fn proxy_support_bundle(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<DiagnosticsProxyQuery>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut msg = json!({
+        "type": "tunnel.support_bundle",
+        "request_id": request_id,
+    });
+    if let Some(n) = query.log_lines {
+        msg["log_lines"] = json!(n);
+    }
+    if let Some(ref s) = query.log_since {
+        msg["log_since"] = json!(s);
+    }
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `GET /d/{serial}/api/support-bundle` — proxied status of the last build.
+async fn proxy_support_bundle_status(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.support_bundle.status",
+        "request_id": request_id,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `GET /d/{serial}/api/tunnel/diag` — the device's own view of its tunnel:
+/// counters, link quality and output batching, recent connection events, RTT
+/// history, last connect selftest, and event outbox and offline spool depth.
+async fn proxy_tunnel_diag(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.diag",
+        "request_id": request_id,
+    });
+
+    let response = tunnel_request_json(&state, &serial, msg, 10).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `POST /d/{serial}/api/exec` — proxied command execution.
+async fn proxy_exec(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let sctl_client = request
+        .headers()
+        .get("x-sctl-client")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let deadline = RequestDeadline::from_headers(request.headers());
+    let confirm_within = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(q)| q.get("confirm_within").cloned())
+        .map(|v| {
+            v.parse::<u64>().map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"error": "confirm_within must be a number of seconds"})),
+                )
+            })
+        })
+        .transpose()?;
+
+    let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024 * 1024)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Failed to read request body"})),
+            )
+        })?;
+
+    let payload: Value = serde_json::from_slice(&body_bytes).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
             Json(json!({"error": "Invalid JSON"})),
         )
     })?;
@@ -1809,11 +2727,15 @@ async fn proxy_exec(
         .as_u64()
         .map_or(state.tunnel_proxy_timeout_secs, |ms| ms / 1000 + 5);
     let mut msg = payload;
+    if let Some(secs) = confirm_within {
+        msg["confirm_within"] = json!(secs);
+    }
     msg["type"] = json!("tunnel.exec");
     msg["request_id"] = json!(request_id);
     if let Some(ref client) = sctl_client {
         msg["_source"] = json!(client);
     }
+    let timeout_secs = apply_client_deadline(&deadline, &mut msg, timeout_secs);
 
     let response = tunnel_request_json(&state, &serial, msg, timeout_secs).await?;
     proxy_response_to_http(&response)
@@ -1835,6 +2757,7 @@ async fn proxy_exec_batch(
         .get("x-sctl-client")
         .and_then(|v| v.to_str().ok())
         .map(ToString::to_string);
+    let deadline = RequestDeadline::from_headers(request.headers());
 
     let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024 * 1024)
         .await
@@ -1875,17 +2798,42 @@ async fn proxy_exec_batch(
     if let Some(ref client) = sctl_client {
         msg["_source"] = json!(client);
     }
+    let timeout_secs = apply_client_deadline(&deadline, &mut msg, timeout_secs);
 
     let response = tunnel_request_json(&state, &serial, msg, timeout_secs).await?;
     proxy_response_to_http(&response)
 }
 
+/// Forward a client's `X-Request-Deadline` to the device as the relative
+/// `deadline_in_ms`, and return `timeout_secs` reduced so the relay stops
+/// waiting shortly after the device gives up.
+fn apply_client_deadline(deadline: &RequestDeadline, msg: &mut Value, timeout_secs: u64) -> u64 {
+    let Some(remaining) = deadline.remaining() else {
+        return timeout_secs;
+    };
+    let ms = u64::try_from(remaining.as_millis()).unwrap_or(u64::MAX);
+    msg["deadline_in_ms"] = json!(ms);
+    timeout_secs.min(ms.div_ceil(1000) + 1)
+}
+
 /// `GET /d/{serial}/api/files` — proxied file read/list.
 #[derive(Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 struct FilesProxyQuery {
     path: String,
     #[serde(default)]
     list: bool,
+    limit: Option<u64>,
+    sort: Option<String>,
+    order: Option<String>,
+    glob: Option<String>,
+    #[serde(default)]
+    dirs_only: bool,
+    #[serde(default)]
+    files_only: bool,
+    continuation_token: Option<String>,
+    #[serde(default)]
+    xattrs: bool,
 }
 
 async fn proxy_file_read(
@@ -1916,13 +2864,35 @@ async fn proxy_file_read(
         "request_id": request_id,
         "path": query.path,
         "list": query.list,
+        "dirs_only": query.dirs_only,
+        "files_only": query.files_only,
+        "xattrs": query.xattrs,
     });
+    if let Some(limit) = query.limit {
+        msg["limit"] = json!(limit);
+    }
+    if let Some(sort) = query.sort {
+        msg["sort"] = json!(sort);
+    }
+    if let Some(order) = query.order {
+        msg["order"] = json!(order);
+    }
+    if let Some(glob) = query.glob {
+        msg["glob"] = json!(glob);
+    }
+    if let Some(token) = query.continuation_token {
+        msg["continuation_token"] = json!(token);
+    }
     if let Some(ref client) = sctl_client {
         msg["_source"] = json!(client);
     }
+    let timeout_secs = apply_client_deadline(
+        &RequestDeadline::from_headers(request.headers()),
+        &mut msg,
+        state.tunnel_proxy_timeout_secs,
+    );
 
-    let response =
-        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    let response = tunnel_request_json(&state, &serial, msg, timeout_secs).await?;
     proxy_response_to_http(&response)
 }
 
@@ -1977,6 +2947,93 @@ async fn proxy_file_write(
     proxy_response_to_http(&response)
 }
 
+/// `PUT /d/{serial}/api/files/xattrs` — proxied xattr/ACL change.
+async fn proxy_file_xattrs(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.file.xattrs", json!({})).await
+}
+
+/// `POST /d/{serial}/api/files/copy` — proxied server-side copy. Progress
+/// arrives as `file.copy.*` events on the WS proxy.
+async fn proxy_file_copy(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.file.copy", json!({})).await
+}
+
+/// `GET /d/{serial}/api/files/copy` — proxied list of running and recent copies.
+async fn proxy_file_copy_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.file.copy.list", json!({})).await
+}
+
+/// `GET /d/{serial}/api/ai` — proxied AI kill-switch and budget status.
+async fn proxy_ai_status(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.ai.status", json!({})).await
+}
+
+/// `POST /d/{serial}/api/ai/disable` — proxied AI kill-switch.
+async fn proxy_ai_disable(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.ai.disable", json!({})).await
+}
+
+/// `POST /d/{serial}/api/ai/enable` — proxied AI re-enable.
+async fn proxy_ai_enable(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.ai.enable", json!({})).await
+}
+
+/// `GET /d/{serial}/api/flightrecorder` — proxied flight recorder status.
+async fn proxy_flight_recorder_status(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.flightrecorder.status",
+        json!({}),
+    )
+    .await
+}
+
+/// `POST /d/{serial}/api/flightrecorder/dump` — proxied flight recorder dump.
+async fn proxy_flight_recorder_dump(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.flightrecorder.dump",
+        json!({}),
+    )
+    .await
+}
+
 /// `DELETE /d/{serial}/api/files` — proxied file delete.
 async fn proxy_file_delete(
     State(state): State<RelayState>,
@@ -2070,6 +3127,9 @@ async fn proxy_activity(
         "request_id": request_id,
         "since_id": query.since_id,
         "limit": query.limit,
+        "from": query.from,
+        "to": query.to,
+        "fields": query.fields,
     });
 
     let response =
@@ -2084,6 +3144,9 @@ struct ActivityProxyQuery {
     since_id: u64,
     #[serde(default = "default_activity_limit")]
     limit: usize,
+    from: Option<u64>,
+    to: Option<u64>,
+    fields: Option<String>,
 }
 
 fn default_activity_limit() -> usize {
@@ -2123,6 +3186,7 @@ async fn proxy_exec_result(
 async fn proxy_sessions(
     State(state): State<RelayState>,
     AxumPath(serial): AxumPath<String>,
+    Query(query): Query<crate::routes::FieldsQuery>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -2140,6 +3204,7 @@ async fn proxy_sessions(
     let msg = json!({
         "type": "tunnel.sessions",
         "request_id": request_id,
+        "fields": query.fields,
     });
 
     let response =
@@ -2177,6 +3242,16 @@ async fn proxy_shells(
 
 // ─── Session Control Proxy Endpoints ──────────────────────────────────────────
 
+/// `POST /d/{serial}/api/sessions` — proxied session start. The session is
+/// persistent; attach to it over the relay's WebSocket.
+async fn proxy_session_start(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.session.start", json!({})).await
+}
+
 /// `POST /d/{serial}/api/sessions/{id}/signal` — proxied session signal.
 async fn proxy_session_signal(
     State(state): State<RelayState>,
@@ -2223,10 +3298,12 @@ async fn proxy_session_signal(
     proxy_response_to_http(&response)
 }
 
-/// `DELETE /d/{serial}/api/sessions/{id}` — proxied session kill.
-async fn proxy_session_kill(
+/// `GET /d/{serial}/api/sessions/{id}/history` — proxied session command
+/// history, or output scrollback with `?since=`.
+async fn proxy_session_history(
     State(state): State<RelayState>,
     AxumPath((serial, id)): AxumPath<(String, String)>,
+    Query(query): Query<HistoryProxyQuery>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -2242,9 +3319,11 @@ async fn proxy_session_kill(
 
     let request_id = uuid::Uuid::new_v4().to_string();
     let msg = json!({
-        "type": "tunnel.session.kill",
+        "type": "tunnel.session.history",
         "request_id": request_id,
         "session_id": id,
+        "limit": query.limit,
+        "since": query.since,
     });
 
     let response =
@@ -2252,10 +3331,11 @@ async fn proxy_session_kill(
     proxy_response_to_http(&response)
 }
 
-/// `PATCH /d/{serial}/api/sessions/{id}` — proxied session patch.
-async fn proxy_session_patch(
+/// `GET /d/{serial}/api/sessions/{id}/journal` — proxied on-disk output paging.
+async fn proxy_session_journal(
     State(state): State<RelayState>,
     AxumPath((serial, id)): AxumPath<(String, String)>,
+    Query(query): Query<JournalProxyQuery>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -2264,44 +3344,29 @@ async fn proxy_session_patch(
         .and_then(|v| v.to_str().ok())
         .map(ToString::to_string);
 
-    let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(json!({"error": "Failed to read request body"})),
-            )
-        })?;
-
-    let payload: Value = serde_json::from_slice(&body_bytes).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(json!({"error": "Invalid JSON"})),
-        )
-    })?;
-
     {
         let devices = state.devices.read().await;
         validate_device_auth(&devices, &serial, auth_header.as_deref())?;
     }
 
     let request_id = uuid::Uuid::new_v4().to_string();
-    let mut msg = payload;
-    msg["type"] = json!("tunnel.session.patch");
-    msg["request_id"] = json!(request_id);
-    msg["session_id"] = json!(id);
+    let msg = json!({
+        "type": "tunnel.session.journal",
+        "request_id": request_id,
+        "session_id": id,
+        "from_seq": query.from_seq,
+        "limit": query.limit,
+    });
 
     let response =
         tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
     proxy_response_to_http(&response)
 }
 
-// ─── Playbook Proxy Endpoints ─────────────────────────────────────────────────
-
-/// `GET /d/{serial}/api/playbooks` -- proxied playbook list.
-async fn proxy_playbooks_list(
+/// `POST /d/{serial}/api/sessions/{id}/share` — proxied share link minting.
+async fn proxy_session_share(
     State(state): State<RelayState>,
-    AxumPath(serial): AxumPath<String>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -2309,11 +3374,150 @@ async fn proxy_playbooks_list(
         .get("authorization")
         .and_then(|v| v.to_str().ok())
         .map(ToString::to_string);
-    let sctl_client = request
-        .headers()
-        .get("x-sctl-client")
-        .and_then(|v| v.to_str().ok())
-        .map(ToString::to_string);
+
+    let body_bytes = axum::body::to_bytes(request.into_body(), 1024)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Failed to read request body"})),
+            )
+        })?;
+    let payload: Value = if body_bytes.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_slice(&body_bytes).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Invalid JSON"})),
+            )
+        })?
+    };
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.session.share",
+        "request_id": request_id,
+        "session_id": id,
+        "body": payload,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// Query parameters for the session history proxy endpoint.
+#[derive(Deserialize)]
+struct HistoryProxyQuery {
+    limit: Option<usize>,
+    since: Option<u64>,
+}
+
+/// Query parameters for the session journal proxy endpoint.
+#[derive(Deserialize)]
+struct JournalProxyQuery {
+    #[serde(default)]
+    from_seq: u64,
+    limit: Option<usize>,
+}
+
+/// `DELETE /d/{serial}/api/sessions/{id}` — proxied session kill.
+async fn proxy_session_kill(
+    State(state): State<RelayState>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.session.kill",
+        "request_id": request_id,
+        "session_id": id,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `PATCH /d/{serial}/api/sessions/{id}` — proxied session patch.
+async fn proxy_session_patch(
+    State(state): State<RelayState>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Failed to read request body"})),
+            )
+        })?;
+
+    let payload: Value = serde_json::from_slice(&body_bytes).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Invalid JSON"})),
+        )
+    })?;
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut msg = payload;
+    msg["type"] = json!("tunnel.session.patch");
+    msg["request_id"] = json!(request_id);
+    msg["session_id"] = json!(id);
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+// ─── Playbook Proxy Endpoints ─────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/playbooks` -- proxied playbook list.
+async fn proxy_playbooks_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let sctl_client = request
+        .headers()
+        .get("x-sctl-client")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
 
     {
         let devices = state.devices.read().await;
@@ -2462,12 +3666,476 @@ async fn proxy_playbook_delete(
     proxy_response_to_http(&response)
 }
 
+/// `POST /d/{serial}/api/playbooks/{name}/run` -- proxied playbook run.
+async fn proxy_playbook_run(
+    State(state): State<RelayState>,
+    AxumPath((serial, name)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.playbooks.run",
+        json!({ "name": name }),
+    )
+    .await
+}
+
+/// `GET /d/{serial}/api/playbooks/{name}/runs` -- proxied run list.
+async fn proxy_playbook_runs(
+    State(state): State<RelayState>,
+    AxumPath((serial, name)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.playbooks.runs",
+        json!({ "name": name }),
+    )
+    .await
+}
+
+/// `GET /d/{serial}/api/playbooks/{name}/runs/{run_id}` -- proxied run report.
+async fn proxy_playbook_run_get(
+    State(state): State<RelayState>,
+    AxumPath((serial, name, run_id)): AxumPath<(String, String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.playbooks.runs.get",
+        json!({ "name": name, "run_id": run_id }),
+    )
+    .await
+}
+
+// ─── SSH Key Proxy Endpoints ──────────────────────────────────────────────────
+
+/// Forward a REST request to the device as a `msg_type` tunnel message.
+///
+/// Query parameters become message fields, and a JSON object body (if any) is
+/// merged on top — the device side deserializes the message straight into the
+/// route's query/body type. `path_fields` (path parameters) are applied last
+/// so the body can't override them.
+async fn proxy_json_message(
+    state: &RelayState,
+    serial: &str,
+    request: Request<Body>,
+    msg_type: &str,
+    path_fields: Value,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let sctl_client = request
+        .headers()
+        .get("x-sctl-client")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let query: HashMap<String, String> = Query::try_from_uri(request.uri())
+        .map(|Query(q)| q)
+        .unwrap_or_default();
+
+    let body_bytes = axum::body::to_bytes(request.into_body(), 1024 * 1024)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Failed to read request body"})),
+            )
+        })?;
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, serial, auth_header.as_deref())?;
+    }
+
+    let mut msg = json!(query);
+    if !body_bytes.is_empty() {
+        let Ok(Value::Object(body)) = serde_json::from_slice::<Value>(&body_bytes) else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Body must be a JSON object"})),
+            ));
+        };
+        for (k, v) in body {
+            msg[k] = v;
+        }
+    }
+    if let Value::Object(fields) = path_fields {
+        for (k, v) in fields {
+            msg[k] = v;
+        }
+    }
+    msg["type"] = json!(msg_type);
+    msg["request_id"] = json!(uuid::Uuid::new_v4().to_string());
+    if let Some(ref client) = sctl_client {
+        msg["_source"] = json!(client);
+    }
+
+    let response = tunnel_request_json(state, serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
+/// `GET /d/{serial}/api/ssh/authorized_keys` — proxied key list.
+async fn proxy_ssh_keys_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.ssh.keys.list", json!({})).await
+}
+
+/// `POST /d/{serial}/api/ssh/authorized_keys` — proxied key add/replace.
+async fn proxy_ssh_keys_add(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.ssh.keys.add", json!({})).await
+}
+
+/// `DELETE /d/{serial}/api/ssh/authorized_keys` — proxied key removal.
+async fn proxy_ssh_keys_delete(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.ssh.keys.delete",
+        json!({}),
+    )
+    .await
+}
+
+// ─── Exec Confirm Proxy Endpoints ─────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/exec/pending` — proxied commit-confirm list.
+async fn proxy_exec_pending(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.exec.pending", json!({})).await
+}
+
+/// `POST /d/{serial}/api/exec/pending/{id}/confirm` — proxied confirmation.
+async fn proxy_exec_confirm(
+    State(state): State<RelayState>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.exec.confirm",
+        json!({ "id": id }),
+    )
+    .await
+}
+
+// ─── Firewall Proxy Endpoints ─────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/firewall` — proxied ruleset and template list.
+async fn proxy_firewall_get(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.firewall.get", json!({})).await
+}
+
+/// `POST /d/{serial}/api/firewall/apply` — proxied template apply.
+async fn proxy_firewall_apply(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.firewall.apply", json!({})).await
+}
+
+// ─── Hook Proxy Endpoints ─────────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/hooks` — proxied hook status.
+async fn proxy_hooks_get(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.hooks.get", json!({})).await
+}
+
+/// `POST /d/{serial}/api/hooks/reload` — proxied hook reload.
+async fn proxy_hooks_reload(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.hooks.reload", json!({})).await
+}
+
+// ─── Twin Proxy Endpoints ─────────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/twin` — proxied desired state and drift report.
+async fn proxy_twin_get(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.twin.get", json!({})).await
+}
+
+/// `PUT /d/{serial}/api/twin` — proxied desired-state update.
+async fn proxy_twin_put(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.twin.put", json!({})).await
+}
+
+/// `POST /d/{serial}/api/twin/reconcile` — proxied reconcile pass.
+async fn proxy_twin_reconcile(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.twin.reconcile", json!({})).await
+}
+
+// ─── Fetch Proxy Endpoint ─────────────────────────────────────────────────────
+
+/// `POST /d/{serial}/api/fetch` — proxied outbound fetch from the device.
+/// Slow fetches are bounded by `tunnel_proxy_timeout_secs` as well as the
+/// device's own limit.
+async fn proxy_fetch(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.fetch", json!({})).await
+}
+
+/// `POST /d/{serial}/api/artifacts/push` — proxied artifact upload. The file
+/// goes from the device to the bucket directly; only the reply comes back
+/// through the relay, within `tunnel_proxy_timeout_secs`.
+async fn proxy_artifacts_push(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.artifacts.push", json!({})).await
+}
+
+/// `GET /d/{serial}/api/resolve?name=` — resolve a name on the device.
+async fn proxy_resolve(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.resolve", json!({})).await
+}
+
+// ─── Snapshot Proxy Endpoints ─────────────────────────────────────────────────
+
+/// `POST /d/{serial}/api/snapshots` — proxied snapshot recording.
+async fn proxy_snapshots_create(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.snapshots.create",
+        json!({}),
+    )
+    .await
+}
+
+/// `GET /d/{serial}/api/snapshots` — proxied snapshot list.
+async fn proxy_snapshots_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.snapshots.list", json!({})).await
+}
+
+/// `GET /d/{serial}/api/snapshots/{id}` — proxied snapshot.
+async fn proxy_snapshots_get(
+    State(state): State<RelayState>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.snapshots.get",
+        json!({ "id": id }),
+    )
+    .await
+}
+
+/// `DELETE /d/{serial}/api/snapshots/{id}` — proxied snapshot removal.
+async fn proxy_snapshots_delete(
+    State(state): State<RelayState>,
+    AxumPath((serial, id)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.snapshots.delete",
+        json!({ "id": id }),
+    )
+    .await
+}
+
+/// `GET /d/{serial}/api/snapshots/{a}/diff/{b}` — proxied snapshot diff.
+async fn proxy_snapshots_diff(
+    State(state): State<RelayState>,
+    AxumPath((serial, a, b)): AxumPath<(String, String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.snapshots.diff",
+        json!({ "a": a, "b": b }),
+    )
+    .await
+}
+
+/// `GET /d/{serial}/api/info/diff?since=` — proxied diff against now.
+async fn proxy_info_diff(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.info.diff", json!({})).await
+}
+
+// ─── Plugin Proxy Endpoints ───────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/plugins` — proxied plugin list.
+async fn proxy_plugins_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.plugins.list", json!({})).await
+}
+
+/// `GET /d/{serial}/api/plugins/{name}` — proxied plugin description.
+async fn proxy_plugins_get(
+    State(state): State<RelayState>,
+    AxumPath((serial, name)): AxumPath<(String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(
+        &state,
+        &serial,
+        request,
+        "tunnel.plugins.get",
+        json!({ "name": name }),
+    )
+    .await
+}
+
+/// `POST /d/{serial}/api/plugins/{name}/{command}` — proxied plugin run,
+/// sent to the device as `plugin.<name>.<command>`.
+async fn proxy_plugins_run(
+    State(state): State<RelayState>,
+    AxumPath((serial, name, command)): AxumPath<(String, String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if !crate::plugins::valid_name(&name) || !crate::plugins::valid_name(&command) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Unknown plugin command '{name}/{command}'")})),
+        ));
+    }
+    let msg_type = format!("plugin.{name}.{command}");
+    proxy_json_message(&state, &serial, request, &msg_type, json!({})).await
+}
+
+// ─── Time Proxy Endpoints ─────────────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/time` — proxied clock/NTP status.
+async fn proxy_time_get(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.time.get", json!({})).await
+}
+
+/// `POST /d/{serial}/api/time` — proxied timezone/NTP change or sync.
+async fn proxy_time_set(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.time.set", json!({})).await
+}
+
+// ─── User Account Proxy Endpoints ─────────────────────────────────────────────
+
+/// `GET /d/{serial}/api/users` — proxied account list.
+async fn proxy_users_list(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "tunnel.users.list", json!({})).await
+}
+
+/// `POST /d/{serial}/api/users/{name}/{action}` — proxied lock, unlock, or
+/// reset-password.
+async fn proxy_users_action(
+    State(state): State<RelayState>,
+    AxumPath((serial, name, action)): AxumPath<(String, String, String)>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let msg_type = match action.as_str() {
+        "lock" => "tunnel.users.lock",
+        "unlock" => "tunnel.users.unlock",
+        "reset-password" => "tunnel.users.reset_password",
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": format!("Unknown user action '{action}'")})),
+            ))
+        }
+    };
+    proxy_json_message(&state, &serial, request, msg_type, json!({ "name": name })).await
+}
+
 // ─── WS Proxy ────────────────────────────────────────────────────────────────
 
 /// Query params for client WS proxy.
 #[derive(Deserialize)]
 struct WsProxyQuery {
     token: String,
+    /// Client name, as `X-Sctl-Client` (see [`crate::ai_guard`]).
+    #[serde(default)]
+    client: Option<String>,
 }
 
 /// `GET /d/{serial}/api/gps` — proxied GPS data.
@@ -2498,6 +4166,45 @@ async fn proxy_gps(
     proxy_response_to_http(&response)
 }
 
+#[derive(Deserialize)]
+struct GpsTrackProxyQuery {
+    since: Option<u64>,
+    until: Option<u64>,
+    limit: Option<u64>,
+}
+
+/// `GET /d/{serial}/api/gps/track` — proxied GPS track (GeoJSON).
+async fn proxy_gps_track(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<GpsTrackProxyQuery>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.gps.track",
+        "request_id": request_id,
+        "since": query.since,
+        "until": query.until,
+        "limit": query.limit,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
 /// `GET /d/{serial}/api/lte` — proxied LTE signal data.
 async fn proxy_lte(
     State(state): State<RelayState>,
@@ -2526,6 +4233,43 @@ async fn proxy_lte(
     proxy_response_to_http(&response)
 }
 
+#[derive(Deserialize)]
+struct LteHistoryProxyQuery {
+    since: Option<u64>,
+    limit: Option<u64>,
+}
+
+/// `GET /d/{serial}/api/lte/history` — proxied LTE signal history.
+async fn proxy_lte_history(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<LteHistoryProxyQuery>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let auth_header = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+
+    {
+        let devices = state.devices.read().await;
+        validate_device_auth(&devices, &serial, auth_header.as_deref())?;
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let msg = json!({
+        "type": "tunnel.lte.history",
+        "request_id": request_id,
+        "since": query.since,
+        "limit": query.limit,
+    });
+
+    let response =
+        tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
+    proxy_response_to_http(&response)
+}
+
 /// `POST /d/{serial}/api/lte/bands` — proxied band mode control.
 async fn proxy_lte_bands(
     State(state): State<RelayState>,
@@ -2881,11 +4625,14 @@ async fn proxy_infra_check(
     proxy_response_to_http(&response)
 }
 
-/// `GET /d/{serial}/api/ws?token=<api_key>` — WS proxy to device.
+/// `GET /d/{serial}/api/ws?token=<api_key>` — WS proxy to device. The client
+/// named by `X-Sctl-Client` or `?client=` is sent with every message as
+/// `_source`, so the device's AI guard applies as on a direct connection.
 async fn proxy_ws(
     State(state): State<RelayState>,
     AxumPath(serial): AxumPath<String>,
     Query(query): Query<WsProxyQuery>,
+    headers: axum::http::HeaderMap,
     ws: WebSocketUpgrade,
 ) -> Response {
     // Validate token against device's api_key
@@ -2894,7 +4641,7 @@ async fn proxy_ws(
         return (StatusCode::NOT_FOUND, "Device not connected").into_response();
     };
 
-    if !crate::auth::constant_time_eq(device.api_key.as_bytes(), query.token.as_bytes()) {
+    if !device.accepts_key(&query.token) {
         return (StatusCode::FORBIDDEN, "Invalid API key").into_response();
     }
 
@@ -2911,14 +4658,586 @@ async fn proxy_ws(
     let clients = device.clients.clone();
     let session_subs = device.session_subscriptions.clone();
     drop(devices);
+    let source = crate::ai_guard::upgrade_client(&headers, query.client.as_deref());
 
     ws.on_upgrade(move |socket| {
         let span = info_span!("tunnel_client", serial = %serial);
-        handle_client_ws(socket, state, serial, device_tx, clients, session_subs).instrument(span)
+        handle_client_ws(
+            socket,
+            state,
+            serial,
+            device_tx,
+            clients,
+            session_subs,
+            source,
+            None,
+        )
+        .instrument(span)
+    })
+}
+
+/// `GET /d/{serial}/forward/{port}?token=<api_key>` — a WS carrying a raw TCP
+/// stream to `127.0.0.1:{port}` on the device (see [`super::forward`]).
+/// Binary (or text) messages are written to the socket as-is; bytes from the
+/// socket arrive as binary messages. The device is asked before the upgrade,
+/// so a port outside `[tunnel] forward_ports` or a refused connect is a plain
+/// HTTP error. Both directions are flow controlled with
+/// `tunnel.forward.credit`.
+async fn proxy_forward(
+    State(state): State<RelayState>,
+    AxumPath((serial, port)): AxumPath<(String, u16)>,
+    Query(query): Query<WsProxyQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    {
+        let devices = state.devices.read().await;
+        let Some(device) = devices.get(&serial) else {
+            return (StatusCode::NOT_FOUND, "Device not connected").into_response();
+        };
+        if !device.accepts_key(&query.token) {
+            return (StatusCode::FORBIDDEN, "Invalid API key").into_response();
+        }
+    }
+    let open = json!({"type": "tunnel.forward.open", "port": port});
+    let (link, from_device) = match open_forward_stream(&state, &serial, open).await {
+        Ok(stream) => stream,
+        Err(e) => return e,
+    };
+    info!(serial = %serial, port, stream_id = %link.id, "Forward client connected");
+    ws.on_upgrade(move |socket| {
+        let span = info_span!("tunnel_forward", serial = %serial, port);
+        handle_forward_ws(socket, link, from_device).instrument(span)
+    })
+}
+
+/// The relay's sending end of a stream opened on a device.
+struct ForwardLink {
+    id: String,
+    device_tx: mpsc::Sender<TunnelMessage>,
+    forwards: super::forward::RelayStreams,
+    credit: super::forward::Credit,
+}
+
+impl ForwardLink {
+    /// Send bytes to the device as credit allows; `false` once the stream
+    /// or the device is gone.
+    async fn send(&self, header: &Value, payload: &[u8]) -> bool {
+        for chunk in payload.chunks(super::forward::MAX_FRAME) {
+            if !self.credit.spend(chunk.len()).await {
+                return false;
+            }
+            let frame = TunnelMessage::Binary(encode_binary_frame(header, chunk));
+            if !self.to_device(frame).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn to_device(&self, msg: TunnelMessage) -> bool {
+        matches!(
+            tokio::time::timeout(
+                Duration::from_secs(DEVICE_QUEUE_SEND_TIMEOUT_SECS),
+                self.device_tx.send(msg),
+            )
+            .await,
+            Ok(Ok(()))
+        )
+    }
+
+    /// Forget the stream and tell the device, unless it closed it first.
+    async fn close(&self) {
+        if let Some(stream) = self.forwards.lock().await.remove(&self.id) {
+            stream.credit.close();
+            let _ = self
+                .device_tx
+                .try_send(TunnelMessage::Text(super::forward::close_message(&self.id)));
+        }
+    }
+}
+
+/// Ask the device to open a stream with `open` (`tunnel.forward.open` or
+/// `tunnel.sftp.open`). Returns the link and what the device sends back, or
+/// the HTTP error to answer with.
+async fn open_forward_stream(
+    state: &RelayState,
+    serial: &str,
+    mut open: Value,
+) -> Result<(ForwardLink, mpsc::UnboundedReceiver<Vec<u8>>), Response> {
+    let (device_tx, forwards) = {
+        let devices = state.devices.read().await;
+        let Some(device) = devices.get(serial) else {
+            return Err((StatusCode::NOT_FOUND, "Device not connected").into_response());
+        };
+        (device.device_tx.clone(), device.forwards.clone())
+    };
+
+    // Registered before the open so bytes the device sends straight away
+    // are buffered, not dropped.
+    let stream_id = uuid::Uuid::new_v4().to_string();
+    let (to_client, from_device) = mpsc::unbounded_channel::<Vec<u8>>();
+    let credit = super::forward::Credit::default();
+    forwards.lock().await.insert(
+        stream_id.clone(),
+        super::forward::RelayStream {
+            to_client,
+            credit: credit.clone(),
+        },
+    );
+
+    open["request_id"] = json!(uuid::Uuid::new_v4().to_string());
+    open["stream_id"] = json!(stream_id);
+    let opened = match tunnel_request_json(state, serial, open, 15).await {
+        Ok(response) => proxy_response_to_http(&response),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = opened {
+        forwards.lock().await.remove(&stream_id);
+        return Err(e.into_response());
+    }
+    let link = ForwardLink {
+        id: stream_id,
+        device_tx,
+        forwards,
+        credit,
+    };
+    Ok((link, from_device))
+}
+
+/// Pump one forwarded stream between the client's WS and the device until
+/// either side closes. The two directions run independently, so waiting for
+/// credit one way never holds up the other.
+async fn handle_forward_ws(
+    socket: axum::extract::ws::WebSocket,
+    link: ForwardLink,
+    mut from_device: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    use super::forward::{credit_message, Delivered, FORWARD_DATA};
+    use axum::extract::ws::Message;
+
+    let (mut ws_sink, mut ws_stream) = socket.split();
+    let header = json!({"type": FORWARD_DATA, "stream_id": link.id});
+    let upstream = async {
+        while let Some(msg) = ws_stream.next().await {
+            let payload = match msg {
+                Ok(Message::Binary(data)) => data.to_vec(),
+                Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                Ok(Message::Ping(_) | Message::Pong(_)) => continue,
+                _ => break,
+            };
+            if !link.send(&header, &payload).await {
+                break;
+            }
+        }
+    };
+    let downstream = async {
+        let mut delivered = Delivered::default();
+        // `None`: the device closed the stream or disconnected
+        while let Some(data) = from_device.recv().await {
+            let len = data.len();
+            if ws_sink.send(Message::Binary(data.into())).await.is_err() {
+                break;
+            }
+            if let Some(bytes) = delivered.add(len, from_device.is_empty()) {
+                let credit = TunnelMessage::Text(credit_message(&link.id, bytes));
+                if !link.to_device(credit).await {
+                    break;
+                }
+            }
+        }
+    };
+    tokio::select! {
+        () = upstream => {}
+        () = downstream => {}
+    }
+    link.close().await;
+    let _ = ws_sink.close().await;
+    info!(stream_id = %link.id, "Forward client disconnected");
+}
+
+/// `GET /d/{serial}/api/sftp?token=<api_key>` — the device's `/api/sftp`,
+/// carried over a forwarded stream (see [`crate::sftp`]).
+async fn proxy_sftp(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    Query(query): Query<WsProxyQuery>,
+    headers: axum::http::HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    {
+        let devices = state.devices.read().await;
+        let Some(device) = devices.get(&serial) else {
+            return (StatusCode::NOT_FOUND, "Device not connected").into_response();
+        };
+        if !device.accepts_key(&query.token) {
+            return (StatusCode::FORBIDDEN, "Invalid API key").into_response();
+        }
+    }
+    let mut open = json!({"type": "tunnel.sftp.open"});
+    if let Some(client) = crate::ai_guard::upgrade_client(&headers, query.client.as_deref()) {
+        open["_source"] = json!(client);
+    }
+    let (link, from_device) = match open_forward_stream(&state, &serial, open).await {
+        Ok(stream) => stream,
+        Err(e) => return e,
+    };
+    info!(serial = %serial, stream_id = %link.id, "SFTP client connected");
+    ws.on_upgrade(move |socket| {
+        let span = info_span!("tunnel_sftp", serial = %serial);
+        handle_forward_ws(socket, link, from_device).instrument(span)
+    })
+}
+
+/// `/d/{serial}/dav/...` — the device's read-only WebDAV export (see
+/// [`crate::dav`]). The device key comes as Basic or Bearer auth. Each
+/// request is carried over its own forwarded stream with `/d/{serial}`
+/// removed; the device puts it back in front of the `href`s it returns.
+async fn proxy_dav(
+    State(state): State<RelayState>,
+    AxumPath(params): AxumPath<HashMap<String, String>>,
+    mut req: axum::extract::Request,
+) -> Response {
+    let serial = params.get("serial").cloned().unwrap_or_default();
+    {
+        let devices = state.devices.read().await;
+        let Some(device) = devices.get(&serial) else {
+            return (StatusCode::NOT_FOUND, "Device not connected").into_response();
+        };
+        let key = crate::dav::credentials(req.headers());
+        if !key.is_some_and(|k| device.accepts_key(&k)) {
+            return crate::dav::unauthorized();
+        }
+    }
+    let prefix = format!("/d/{serial}");
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |pq| pq.as_str())
+        .strip_prefix(prefix.as_str())
+        .unwrap_or("/")
+        .to_string();
+    *req.uri_mut() = match path_and_query.parse() {
+        Ok(uri) => uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let open = json!({"type": "tunnel.dav.open", "prefix": prefix});
+    http_over_stream(&state, &serial, open, req, None)
+        .await
+        .unwrap_or_else(|e| e)
+}
+
+// ─── Exposed Ports ───────────────────────────────────────────────────────────
+
+/// `/x/{id}/{*path}` — a device port published with `POST /api/tunnel/expose`
+/// (see [`super::expose`]). The token comes as `?token=`, a
+/// `Bearer` header, or the cookie set the first time `?token=` is used. Each
+/// request is proxied as HTTP/1.1 over its own forwarded stream, with the
+/// `/x/{id}` prefix and the token removed. An unknown id, wrong token or
+/// expired link is `404`.
+async fn proxy_exposed(
+    State(state): State<RelayState>,
+    AxumPath(params): AxumPath<HashMap<String, String>>,
+    req: axum::extract::Request,
+) -> Response {
+    use super::expose::TOKEN_COOKIE;
+    use axum::http::header;
+
+    let id = params.get("id").cloned().unwrap_or_default();
+    let prefix = format!("/x/{id}");
+    let (query_token, query) = split_token_query(req.uri().query());
+    let token = query_token
+        .clone()
+        .or_else(|| cookie_value(req.headers(), TOKEN_COOKIE))
+        .or_else(|| {
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(str::to_string)
+        });
+    let Some(target) = token.and_then(|t| state.exposures.authorize(&id, &t)) else {
+        return (StatusCode::NOT_FOUND, "Unknown or expired link").into_response();
+    };
+    // Relative links in the page only resolve under the prefix with a slash
+    if req.uri().path() == prefix {
+        let query = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        return axum::response::Redirect::permanent(&format!("{prefix}/{query}")).into_response();
+    }
+
+    let path = &req.uri().path()[prefix.len()..];
+    let path_and_query = match query {
+        Some(q) => format!("{path}?{q}"),
+        None => path.to_string(),
+    };
+    let mut req = req;
+    *req.uri_mut() = match path_and_query.parse() {
+        Ok(uri) => uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    if let Ok(host) = header::HeaderValue::from_str(&format!("127.0.0.1:{}", target.port)) {
+        req.headers_mut().insert(header::HOST, host);
+    }
+    let open = json!({"type": "tunnel.forward.open", "port": target.port});
+    let mut response =
+        match http_over_stream(&state, &target.serial, open, req, Some(target.bucket)).await {
+            Ok(response) => response,
+            Err(e) => return e,
+        };
+    if let Some(token) = query_token {
+        let cookie = format!("{TOKEN_COOKIE}={token}; Path={prefix}/; HttpOnly; SameSite=Lax");
+        if let Ok(value) = header::HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+/// Send one HTTP/1.1 request to the device over a stream opened with `open`,
+/// and return the device's response. Bytes are charged to `bucket`, if any.
+async fn http_over_stream(
+    state: &RelayState,
+    serial: &str,
+    open: Value,
+    req: axum::extract::Request,
+    bucket: Option<Arc<std::sync::Mutex<crate::gawdxfer::throttle::TokenBucket>>>,
+) -> Result<Response, Response> {
+    let (link, from_device) = open_forward_stream(state, serial, open).await?;
+    let (client_io, relay_io) = tokio::io::duplex(4 * super::forward::MAX_FRAME);
+    tokio::spawn(pump_http(relay_io, link, from_device, bucket));
+
+    let response = match hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(
+        client_io,
+    ))
+    .await
+    {
+        Ok((mut sender, conn)) => {
+            tokio::spawn(async move {
+                let _ = conn.await;
+            });
+            sender.send_request(req).await
+        }
+        Err(e) => Err(e),
+    };
+    match response {
+        Ok(resp) => {
+            let (parts, body) = resp.into_parts();
+            Ok(Response::from_parts(parts, Body::new(body)))
+        }
+        Err(e) => Err((
+            StatusCode::BAD_GATEWAY,
+            format!("Device service did not answer: {e}"),
+        )
+            .into_response()),
+    }
+}
+
+/// Carry one proxied HTTP connection over its stream, charging every byte to
+/// `bucket` (an exposure's bandwidth cap) if there is one.
+async fn pump_http(
+    io: tokio::io::DuplexStream,
+    link: ForwardLink,
+    mut from_device: mpsc::UnboundedReceiver<Vec<u8>>,
+    bucket: Option<Arc<std::sync::Mutex<crate::gawdxfer::throttle::TokenBucket>>>,
+) {
+    use super::forward::{credit_message, Delivered, FORWARD_DATA, MAX_FRAME};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let pace = |bytes: usize| {
+        let delay = bucket
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.lock().unwrap().take(bytes as u64));
+        async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    };
+    let (mut reader, mut writer) = tokio::io::split(io);
+    let header = json!({"type": FORWARD_DATA, "stream_id": link.id});
+    let upstream = async {
+        let mut buf = vec![0u8; MAX_FRAME];
+        loop {
+            let n = match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            pace(n).await;
+            if !link.send(&header, &buf[..n]).await {
+                break;
+            }
+        }
+    };
+    let downstream = async {
+        let mut delivered = Delivered::default();
+        while let Some(data) = from_device.recv().await {
+            pace(data.len()).await;
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+            if let Some(bytes) = delivered.add(data.len(), from_device.is_empty()) {
+                let credit = TunnelMessage::Text(credit_message(&link.id, bytes));
+                if !link.to_device(credit).await {
+                    break;
+                }
+            }
+        }
+        let _ = writer.shutdown().await;
+    };
+    tokio::select! {
+        () = upstream => {}
+        () = downstream => {}
+    }
+    link.close().await;
+}
+
+/// Split `token` out of a query string: `(token, the rest)`.
+fn split_token_query(query: Option<&str>) -> (Option<String>, Option<String>) {
+    let Some(query) = query else {
+        return (None, None);
+    };
+    let mut token = None;
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|pair| match pair.strip_prefix("token=") {
+            Some(value) => {
+                token = Some(value.to_string());
+                false
+            }
+            None => !pair.is_empty(),
+        })
+        .collect();
+    (token, (!rest.is_empty()).then(|| rest.join("&")))
+}
+
+/// The value of cookie `name` in a request's `Cookie` headers.
+fn cookie_value(headers: &axum::http::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then(|| value.to_string())
+        })
+}
+
+// ─── Session Share Links ─────────────────────────────────────────────────────
+
+/// Verify a share token against the key of the device it names.
+async fn verify_share(
+    state: &RelayState,
+    token: &str,
+) -> Result<share::ShareClaims, (StatusCode, &'static str)> {
+    let serial = share::peek(token)
+        .ok_or((StatusCode::FORBIDDEN, "Invalid share link"))?
+        .serial;
+    let devices = state.devices.read().await;
+    let device = devices
+        .get(&serial)
+        .ok_or((StatusCode::NOT_FOUND, "Device not connected"))?;
+    let now_secs = crate::sessions::journal::now_ms() / 1000;
+    share::verify(token, &device.api_key, now_secs).map_err(|e| (StatusCode::FORBIDDEN, e))
+}
+
+/// `GET /s/{token}` — viewer page for a shared session.
+async fn share_page(
+    State(state): State<RelayState>,
+    AxumPath(token): AxumPath<String>,
+) -> Response {
+    match verify_share(&state, &token).await {
+        Ok(_) => axum::response::Html(SHARE_PAGE).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `GET /s/{token}/ws` — WS access to a shared session, scoped by the token.
+async fn share_ws(
+    State(state): State<RelayState>,
+    AxumPath(token): AxumPath<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let claims = match verify_share(&state, &token).await {
+        Ok(claims) => claims,
+        Err(e) => return e.into_response(),
+    };
+
+    let devices = state.devices.read().await;
+    let Some(device) = devices.get(&claims.serial) else {
+        return (StatusCode::NOT_FOUND, "Device not connected").into_response();
+    };
+    if device.clients.read().await.len() >= MAX_CLIENTS_PER_DEVICE {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many clients for this device",
+        )
+            .into_response();
+    }
+    let device_tx = device.device_tx.clone();
+    let clients = device.clients.clone();
+    let session_subs = device.session_subscriptions.clone();
+    drop(devices);
+
+    info!(
+        serial = %claims.serial,
+        share_id = %claims.id,
+        session_id = %claims.session_id,
+        mode = ?claims.mode,
+        "Share link client connected"
+    );
+    let serial = claims.serial.clone();
+    ws.on_upgrade(move |socket| {
+        let span = info_span!("tunnel_share", serial = %serial);
+        handle_client_ws(
+            socket,
+            state,
+            serial,
+            device_tx,
+            clients,
+            session_subs,
+            None,
+            Some(claims),
+        )
+        .instrument(span)
     })
 }
 
+/// Complete a `latency.probe.result` on its way back to the client: stamp the
+/// relay return time and derive a per-hop breakdown. Durations on the same
+/// host are exact; `client_to_relay_ms` compares client and relay clocks and
+/// is only meaningful when they are in sync (the client has the total RTT).
+fn fill_latency_breakdown(response: &mut Value) {
+    let back_ms = crate::sessions::journal::now_ms();
+    let relay = &response["relay"];
+    let (Some(rx), Some(tx)) = (relay["rx_ms"].as_u64(), relay["tx_ms"].as_u64()) else {
+        return;
+    };
+    let device_ms = response["device_tx_ms"]
+        .as_u64()
+        .zip(response["device_rx_ms"].as_u64())
+        .map_or(0, |(t, r)| t.saturating_sub(r));
+    let client_to_relay_ms = response["client_ts"].as_u64().map(|c| rx.saturating_sub(c));
+    response["relay"]["back_ms"] = json!(back_ms);
+    response["relay"]["breakdown"] = json!({
+        "client_to_relay_ms": client_to_relay_ms,
+        "relay_queue_ms": tx.saturating_sub(rx),
+        "relay_device_rtt_ms": back_ms.saturating_sub(tx).saturating_sub(device_ms),
+        "device_processing_ms": device_ms,
+    });
+}
+
 /// Handle a client's WS connection proxied to a device.
+///
+/// `source` is the client named at the upgrade, stamped on every forwarded
+/// message as `_source`. `share` scopes a share-link client (see [`share`]) to one session: it may
+/// only send [`share::allowed`] types, every message targets the shared
+/// session, it only receives messages about that session, and it is
+/// disconnected when the link expires.
+#[allow(clippy::too_many_arguments)]
 async fn handle_client_ws(
     socket: axum::extract::ws::WebSocket,
     _state: RelayState,
@@ -2926,19 +5245,42 @@ async fn handle_client_ws(
     device_tx: mpsc::Sender<TunnelMessage>,
     clients: Arc<RwLock<HashMap<String, mpsc::Sender<Arc<Value>>>>>,
     session_subs: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    source: Option<String>,
+    share: Option<share::ShareClaims>,
 ) {
     let (mut ws_sink, mut ws_stream) = socket.split();
     let client_id = uuid::Uuid::new_v4().to_string();
     let (client_tx, mut client_rx) = mpsc::channel::<Arc<Value>>(256);
+    let reply_tx = client_tx.clone();
 
     // Register this client
     clients.write().await.insert(client_id.clone(), client_tx);
 
     info!(client_id = %client_id, serial = %serial, "Client connected to device");
 
+    let share_session = share.as_ref().map(|c| c.session_id.clone());
+    let share_ttl = share.as_ref().map(|c| {
+        Duration::from_secs(
+            c.exp
+                .saturating_sub(crate::sessions::journal::now_ms() / 1000),
+        )
+    });
+    let expired = async move {
+        match share_ttl {
+            Some(ttl) => tokio::time::sleep(ttl).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(expired);
+
     // Forward client_rx messages to WS sink
     let send_task = tokio::spawn(async move {
         while let Some(msg) = client_rx.recv().await {
+            if let Some(ref sid) = share_session {
+                if msg["session_id"].as_str() != Some(sid.as_str()) && msg["type"] != "pong" {
+                    continue;
+                }
+            }
             let text = match serde_json::to_string(msg.as_ref()) {
                 Ok(t) => t,
                 Err(e) => {
@@ -2956,8 +5298,23 @@ async fn handle_client_ws(
         }
     });
 
+    // Whether this client started a file watch (stopped on disconnect)
+    let mut started_watch = false;
+    // Whether this client took a session's input (released on disconnect)
+    let mut took_control = false;
+
     // Process messages from the client
-    while let Some(Ok(msg)) = ws_stream.next().await {
+    loop {
+        let msg = tokio::select! {
+            msg = ws_stream.next() => msg,
+            () = &mut expired => {
+                info!(client_id = %client_id, serial = %serial, "Share link expired, disconnecting");
+                break;
+            }
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
         match msg {
             axum::extract::ws::Message::Text(text) => {
                 let Ok(mut parsed) = serde_json::from_str::<Value>(&text) else {
@@ -2966,6 +5323,32 @@ async fn handle_client_ws(
 
                 let msg_type = parsed["type"].as_str().unwrap_or("").to_string();
 
+                if let Some(ref claims) = share {
+                    if !share::allowed(claims.mode, &msg_type) {
+                        let _ = reply_tx
+                            .send(Arc::new(json!({
+                                "type": "error",
+                                "code": "SHARE_FORBIDDEN",
+                                "message": format!("{msg_type} is not permitted on this share link"),
+                                "session_id": claims.session_id,
+                                "request_id": parsed["request_id"],
+                            })))
+                            .await;
+                        continue;
+                    }
+                    parsed["session_id"] = json!(claims.session_id);
+                }
+
+                // Latency probe: stamp relay arrival; the device echoes `relay`
+                // back and the breakdown is filled in on the return path.
+                if msg_type == "latency.probe" {
+                    parsed["relay"] = json!({ "rx_ms": crate::sessions::journal::now_ms() });
+                }
+
+                if let Some(ref source) = source {
+                    parsed["_source"] = json!(source);
+                }
+
                 let original_rid = parsed["request_id"].as_str().unwrap_or("").to_string();
 
                 // Tag request_id with client_id for routing responses back
@@ -3002,10 +5385,19 @@ async fn handle_client_ws(
                                 .remove(&client_id);
                         }
                     }
+                    "files.watch" | "logs.follow" => started_watch = true,
+                    "session.take_control" => took_control = true,
+                    // Relay-only: would stop another client's watches, free
+                    // its session input, or replace the device's keys
+                    // (issued by the relay itself)
+                    "files.detach" | "session.release_client" | "tunnel.rotate_key" => continue,
                     _ => {}
                 }
 
                 // Forward to device
+                if msg_type == "latency.probe" {
+                    parsed["relay"]["tx_ms"] = json!(crate::sessions::journal::now_ms());
+                }
                 match tokio::time::timeout(
                     Duration::from_secs(DEVICE_QUEUE_SEND_TIMEOUT_SECS),
                     device_tx.send(TunnelMessage::Text(parsed)),
@@ -3052,6 +5444,48 @@ async fn handle_client_ws(
         subs.retain(|_, v| !v.is_empty());
     }
 
+    // Stop the file watches and log tails this client started on the device.
+    if started_watch
+        && !matches!(
+            tokio::time::timeout(
+                Duration::from_secs(DEVICE_QUEUE_SEND_TIMEOUT_SECS),
+                device_tx.send(TunnelMessage::Text(json!({
+                    "type": "files.detach",
+                    "client_id": client_id,
+                }))),
+            )
+            .await,
+            Ok(Ok(()))
+        )
+    {
+        warn!(
+            serial = %serial,
+            client_id = %client_id,
+            "Relay WS files.detach not delivered to device"
+        );
+    }
+
+    // Free any session input this client held on the device.
+    if took_control
+        && !matches!(
+            tokio::time::timeout(
+                Duration::from_secs(DEVICE_QUEUE_SEND_TIMEOUT_SECS),
+                device_tx.send(TunnelMessage::Text(json!({
+                    "type": "session.release_client",
+                    "client_id": client_id,
+                }))),
+            )
+            .await,
+            Ok(Ok(()))
+        )
+    {
+        warn!(
+            serial = %serial,
+            client_id = %client_id,
+            "Relay WS session.release_client not delivered to device"
+        );
+    }
+
     // Tell the device to detach sessions that no longer have any subscribers.
     // After `retain` above, sessions with zero subscribers were removed from the
     // map entirely, so `get()` returns None. `map_or(true, ...)` means:
@@ -3099,6 +5533,9 @@ async fn handle_client_ws(
 
 // ─── STP (gawdxfer) Proxy Endpoints ──────────────────────────────────────────
 
+/// How long a directory download init may take to archive and hash the tree.
+const STP_DIRECTORY_INIT_TIMEOUT_SECS: u64 = 300;
+
 /// `POST /d/{serial}/api/stp/download` — proxied download init.
 async fn proxy_stp_download_init(
     State(state): State<RelayState>,
@@ -3138,9 +5575,18 @@ async fn proxy_stp_download_init(
         "request_id": request_id,
         "path": payload["path"],
         "chunk_size": payload["chunk_size"],
+        "directory": payload["directory"],
+        "files": payload["files"],
+        "rate_limit_bps": payload["rate_limit_bps"],
     });
 
-    let response = tunnel_request_json(&state, &serial, msg, 30).await?;
+    // A directory is archived before the reply, which can take a while
+    let timeout = if payload["directory"].as_bool() == Some(true) {
+        STP_DIRECTORY_INIT_TIMEOUT_SECS
+    } else {
+        30
+    };
+    let response = tunnel_request_json(&state, &serial, msg, timeout).await?;
     proxy_response_to_http(&response)
 }
 
@@ -3204,14 +5650,19 @@ async fn proxy_stp_download_chunk(
     }
 
     let request_id = uuid::Uuid::new_v4().to_string();
-    let msg = json!({
+    let mut msg = json!({
         "type": "gx.chunk.request",
         "request_id": request_id,
         "transfer_id": xfer,
         "chunk_index": idx,
     });
+    let timeout_secs = apply_client_deadline(
+        &RequestDeadline::from_headers(request.headers()),
+        &mut msg,
+        60,
+    );
 
-    let response = tunnel_request(&state, &serial, msg, 60).await?;
+    let response = tunnel_request(&state, &serial, msg, timeout_secs).await?;
 
     match response {
         TunnelResponse::Binary { header, data } => {
@@ -3272,13 +5723,19 @@ async fn proxy_stp_upload_chunk(
     }
 
     let request_id = uuid::Uuid::new_v4().to_string();
-    let header = json!({
+    let mut header = json!({
         "type": "gx.chunk",
         "request_id": request_id,
         "transfer_id": xfer,
         "chunk_index": idx,
         "chunk_hash": chunk_hash,
+        "deadline_in_ms": 60_000,
     });
+    if let Some(client) = headers.get("x-sctl-client").and_then(|v| v.to_str().ok()) {
+        header["_source"] = json!(client);
+    }
+    let timeout_secs =
+        apply_client_deadline(&RequestDeadline::from_headers(&headers), &mut header, 60);
     let frame = encode_binary_frame(&header, &body);
 
     let response = tunnel_request_binary(
@@ -3286,7 +5743,7 @@ async fn proxy_stp_upload_chunk(
         &serial,
         TunnelMessage::Binary(frame),
         &request_id,
-        60,
+        timeout_secs,
     )
     .await?;
 
@@ -3374,6 +5831,7 @@ async fn proxy_stp_status(
 async fn proxy_stp_list(
     State(state): State<RelayState>,
     AxumPath(serial): AxumPath<String>,
+    Query(query): Query<crate::routes::FieldsQuery>,
     request: Request<Body>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let auth_header = request
@@ -3391,6 +5849,7 @@ async fn proxy_stp_list(
     let msg = json!({
         "type": "gx.list",
         "request_id": request_id,
+        "fields": query.fields,
     });
 
     let response =
@@ -3398,6 +5857,15 @@ async fn proxy_stp_list(
     proxy_response_to_http(&response)
 }
 
+/// `POST /d/{serial}/api/stp/throttle` — proxied bandwidth change (`gx.throttle`).
+async fn proxy_stp_throttle(
+    State(state): State<RelayState>,
+    AxumPath(serial): AxumPath<String>,
+    request: Request<Body>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    proxy_json_message(&state, &serial, request, "gx.throttle", json!({})).await
+}
+
 /// `DELETE /d/{serial}/api/stp/{xfer}` — proxied abort.
 async fn proxy_stp_abort(
     State(state): State<RelayState>,
@@ -3427,3 +5895,29 @@ async fn proxy_stp_abort(
         tunnel_request_json(&state, &serial, msg, state.tunnel_proxy_timeout_secs).await?;
     proxy_response_to_http(&response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_deadline_caps_the_proxy_timeout() {
+        let mut msg = json!({"type": "tunnel.file.read"});
+        assert_eq!(
+            apply_client_deadline(&RequestDeadline::default(), &mut msg, 30),
+            30
+        );
+        assert!(msg.get("deadline_in_ms").is_none());
+
+        let timeout = apply_client_deadline(&RequestDeadline::after_ms(4_500), &mut msg, 30);
+        assert_eq!(timeout, 6);
+        let sent = msg["deadline_in_ms"].as_u64().unwrap();
+        assert!(sent <= 4_500 && sent > 4_000);
+
+        // A deadline further out than the default doesn't extend it.
+        assert_eq!(
+            apply_client_deadline(&RequestDeadline::after_ms(600_000), &mut msg, 30),
+            30
+        );
+    }
+}
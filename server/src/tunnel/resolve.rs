@@ -0,0 +1,116 @@
+//! Name resolution across the tunnel (`tunnel.resolve`).
+//!
+//! Field devices often sit behind split-horizon DNS: a site resolver, VPN
+//! search domains or `/etc/hosts` entries make a name resolve differently on
+//! the device than at the relay. Either side can ask the other. The relay
+//! sends `tunnel.resolve` to a device for `GET /d/{serial}/api/resolve`, and
+//! a device sends it to its relay for `GET /api/resolve?via=relay`. Both
+//! answer with [`lookup`], so the two views compare field for field.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+/// Longest DNS name accepted.
+pub const MAX_NAME_LEN: usize = 253;
+
+/// How long one lookup may take; the system resolver can retry for much
+/// longer than a caller wants to wait.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether `name` is a host name or IP literal worth passing to the resolver.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && (name.parse::<IpAddr>().is_ok()
+            || name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_')))
+}
+
+/// Resolve `name` with the system resolver (`/etc/hosts`, nsswitch, the
+/// configured nameservers), as any program on this host would.
+///
+/// Returns `{name, addresses, error, duration_ms, nameservers, search}`. A
+/// failed lookup is an answer too: `addresses` is empty and `error` says why.
+pub async fn lookup(name: &str) -> Value {
+    let started = Instant::now();
+    let result = tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host((name, 0))).await;
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    let (addresses, error) = match result {
+        Ok(Ok(addrs)) => {
+            let mut ips: Vec<String> = Vec::new();
+            for addr in addrs {
+                let ip = addr.ip().to_string();
+                if !ips.contains(&ip) {
+                    ips.push(ip);
+                }
+            }
+            (ips, None)
+        }
+        Ok(Err(e)) => (Vec::new(), Some(e.to_string())),
+        Err(_) => (
+            Vec::new(),
+            Some(format!(
+                "lookup timed out after {}s",
+                LOOKUP_TIMEOUT.as_secs()
+            )),
+        ),
+    };
+    let (nameservers, search) = tokio::fs::read_to_string("/etc/resolv.conf")
+        .await
+        .map(|text| parse_resolv_conf(&text))
+        .unwrap_or_default();
+    json!({
+        "name": name,
+        "addresses": addresses,
+        "error": error,
+        "duration_ms": duration_ms,
+        "nameservers": nameservers,
+        "search": search,
+    })
+}
+
+/// `(nameservers, search domains)` from `resolv.conf` text. A later
+/// `search` or `domain` line replaces an earlier one, as in glibc.
+fn parse_resolv_conf(text: &str) -> (Vec<String>, Vec<String>) {
+    let mut nameservers = Vec::new();
+    let mut search = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("nameserver") => nameservers.extend(words.next().map(String::from)),
+            Some("search" | "domain") => search = words.map(String::from).collect(),
+            _ => {}
+        }
+    }
+    (nameservers, search)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resolv_conf_and_checks_names() {
+        let (ns, search) = parse_resolv_conf(
+            "# generated by NetworkManager\n\
+             domain old.lan\n\
+             search site.lan corp.example\n\
+             nameserver 10.8.0.1\n\
+             nameserver fd00::53\n\
+             options edns0\n",
+        );
+        assert_eq!(ns, ["10.8.0.1", "fd00::53"]);
+        assert_eq!(search, ["site.lan", "corp.example"]);
+
+        assert!(valid_name("registry.site.lan"));
+        assert!(valid_name("_sip._udp.example.com"));
+        assert!(valid_name("fd00::53"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("a b"));
+        assert!(!valid_name("host;reboot"));
+        assert!(!valid_name(&"a".repeat(MAX_NAME_LEN + 1)));
+    }
+}
@@ -0,0 +1,418 @@
+//! Staged fleet rollouts on the relay.
+//!
+//! `POST /api/fleet/rollouts?token=` starts a background rollout of an exec
+//! or playbook [`Action`] to the targeted devices. It runs first on
+//! `canary` devices, then in waves of `wave_size`:
+//!
+//! - the canary wave must succeed on every device, or the rollout aborts
+//! - after that it aborts once more than `max_failures` devices have failed
+//! - `pause_secs` separates waves; `POST .../{id}/abort` stops it before the
+//!   next wave
+//!
+//! A device succeeds when its [`DeviceResult`] is `ok` and, if set, its
+//! stdout contains `expect_stdout`. Progress is kept in memory and read with
+//! `GET /api/fleet/rollouts/{id}`; the newest [`MAX_ROLLOUTS`] are kept.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::sessions::journal::now_ms;
+
+use super::fleet::{
+    bad_request, reject_token, run_many, Action, BroadcastReport, Targets, TokenQuery,
+};
+use super::relay::RelayState;
+
+/// Rollouts kept in memory; the oldest finished ones are dropped.
+pub const MAX_ROLLOUTS: usize = 50;
+
+fn default_canary() -> usize {
+    1
+}
+
+fn default_wave_size() -> usize {
+    10
+}
+
+/// Request body for `POST /api/fleet/rollouts`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolloutRequest {
+    #[serde(flatten)]
+    pub targets: Targets,
+    pub action: Action,
+    /// Devices in the first wave (default 1).
+    #[serde(default = "default_canary")]
+    pub canary: usize,
+    /// Devices per later wave, run concurrently (default 10).
+    #[serde(default = "default_wave_size")]
+    pub wave_size: usize,
+    /// Failed devices tolerated after the canary wave (default 0).
+    #[serde(default)]
+    pub max_failures: usize,
+    /// Wait between waves.
+    #[serde(default)]
+    pub pause_secs: u64,
+    /// Exec only: stdout must contain this for a device to count as ok.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_stdout: Option<String>,
+}
+
+/// One wave's devices and, once run, its results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wave {
+    pub index: usize,
+    pub canary: bool,
+    pub serials: Vec<String>,
+    /// `pending`, `running`, `done` or `skipped`.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<BroadcastReport>,
+}
+
+/// A rollout's plan and progress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rollout {
+    pub id: String,
+    /// `running`, `succeeded` or `aborted`.
+    pub status: String,
+    /// Unix ms.
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    pub request: RolloutRequest,
+    pub waves: Vec<Wave>,
+    /// Devices that weren't ok so far.
+    pub failures: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abort_reason: Option<String>,
+    /// Set by `POST .../abort`; checked before each wave.
+    #[serde(default)]
+    pub abort_requested: bool,
+}
+
+impl Rollout {
+    fn finished(&self) -> bool {
+        self.status != "running"
+    }
+
+    fn finish(&mut self, status: &str, reason: Option<String>) {
+        self.status = status.to_string();
+        self.abort_reason = reason;
+        self.finished_at = Some(now_ms());
+        for wave in &mut self.waves {
+            if wave.status == "pending" {
+                wave.status = "skipped".to_string();
+            }
+        }
+    }
+}
+
+/// Split sorted `serials` into the canary wave and waves of `wave_size`.
+fn plan_waves(serials: &[String], canary: usize, wave_size: usize) -> Vec<Wave> {
+    let canary = canary.clamp(1, serials.len().max(1)).min(serials.len());
+    let (first, rest) = serials.split_at(canary);
+    std::iter::once((true, first))
+        .filter(|(_, s)| !s.is_empty())
+        .chain(rest.chunks(wave_size.max(1)).map(|chunk| (false, chunk)))
+        .enumerate()
+        .map(|(index, (canary, serials))| Wave {
+            index,
+            canary,
+            serials: serials.to_vec(),
+            status: "pending".to_string(),
+            report: None,
+        })
+        .collect()
+}
+
+/// Mark results that miss `expect_stdout` as failed.
+fn apply_expectation(report: &mut BroadcastReport, expect_stdout: Option<&str>) {
+    let Some(expected) = expect_stdout else {
+        return;
+    };
+    for result in &mut report.results {
+        if result.is_ok()
+            && !result
+                .stdout
+                .as_deref()
+                .is_some_and(|s| s.contains(expected))
+        {
+            result.status = "failed".to_string();
+            result.error = Some(format!("stdout does not contain '{expected}'"));
+            report.ok -= 1;
+            report.failed += 1;
+        }
+    }
+}
+
+/// Insert `rollout`, dropping the oldest finished ones past [`MAX_ROLLOUTS`].
+async fn store(state: &RelayState, rollout: Rollout) {
+    let mut rollouts = state.rollouts.write().await;
+    rollouts.insert(rollout.id.clone(), rollout);
+    while rollouts.len() > MAX_ROLLOUTS {
+        let Some(oldest) = rollouts
+            .values()
+            .filter(|r| r.finished())
+            .min_by_key(|r| r.created_at)
+            .map(|r| r.id.clone())
+        else {
+            break;
+        };
+        rollouts.remove(&oldest);
+    }
+}
+
+/// Apply `f` to rollout `id`, returning a copy of the result.
+async fn update(state: &RelayState, id: &str, f: impl FnOnce(&mut Rollout)) -> Option<Rollout> {
+    let mut rollouts = state.rollouts.write().await;
+    let rollout = rollouts.get_mut(id)?;
+    f(rollout);
+    Some(rollout.clone())
+}
+
+/// Run rollout `id` wave by wave until done or aborted.
+async fn run(state: RelayState, id: String) {
+    let mut index = 0;
+    loop {
+        let Some(rollout) = update(&state, &id, |r| {
+            if r.finished() {
+                // Aborted after the previous wave.
+            } else if r.abort_requested {
+                r.finish("aborted", Some("Aborted by request".to_string()));
+            } else if let Some(wave) = r.waves.get_mut(index) {
+                wave.status = "running".to_string();
+            } else {
+                r.finish("succeeded", None);
+            }
+        })
+        .await
+        else {
+            return;
+        };
+        if rollout.finished() {
+            info!(
+                rollout = %id,
+                status = %rollout.status,
+                failures = rollout.failures,
+                "Rollout finished"
+            );
+            return;
+        }
+        let wave = &rollout.waves[index];
+        let request = &rollout.request;
+        let mut report = run_many(&state, &wave.serials, &request.action, wave.serials.len()).await;
+        apply_expectation(&mut report, request.expect_stdout.as_deref());
+        let wave_failures = report.total - report.ok;
+        info!(
+            rollout = %id,
+            wave = index,
+            devices = report.total,
+            failures = wave_failures,
+            "Rollout wave finished"
+        );
+
+        let Some(rollout) = update(&state, &id, |r| {
+            r.failures += wave_failures;
+            let canary = r.waves[index].canary;
+            r.waves[index].status = "done".to_string();
+            r.waves[index].report = Some(report);
+            if canary && wave_failures > 0 {
+                r.finish(
+                    "aborted",
+                    Some(format!("{wave_failures} canary device(s) failed")),
+                );
+            } else if r.failures > r.request.max_failures {
+                r.finish(
+                    "aborted",
+                    Some(format!(
+                        "{} device(s) failed, more than max_failures {}",
+                        r.failures, r.request.max_failures
+                    )),
+                );
+            }
+        })
+        .await
+        else {
+            return;
+        };
+        index += 1;
+        let pause = rollout.request.pause_secs;
+        if pause > 0 && !rollout.finished() && index < rollout.waves.len() {
+            tokio::time::sleep(Duration::from_secs(pause)).await;
+        }
+    }
+}
+
+/// `POST /api/fleet/rollouts?token=` — plan and start a rollout; returns it
+/// with `202 Accepted`.
+pub async fn start_rollout(
+    State(state): State<RelayState>,
+    Query(query): Query<TokenQuery>,
+    Json(request): Json<RolloutRequest>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    if let Err(e) = request.action.validate() {
+        return bad_request(&e);
+    }
+    let serials = match request.targets.resolve(&state).await {
+        Ok(serials) if serials.is_empty() => return bad_request("No devices match the targets"),
+        Ok(serials) => serials,
+        Err(e) => return bad_request(&e),
+    };
+
+    let rollout = Rollout {
+        id: uuid::Uuid::new_v4().to_string(),
+        status: "running".to_string(),
+        created_at: now_ms(),
+        finished_at: None,
+        waves: plan_waves(&serials, request.canary, request.wave_size),
+        request,
+        failures: 0,
+        abort_reason: None,
+        abort_requested: false,
+    };
+    info!(
+        rollout = %rollout.id,
+        devices = serials.len(),
+        waves = rollout.waves.len(),
+        "Rollout started"
+    );
+    store(&state, rollout.clone()).await;
+    tokio::spawn(run(state, rollout.id.clone()));
+    (StatusCode::ACCEPTED, Json(rollout)).into_response()
+}
+
+/// `GET /api/fleet/rollouts?token=` — all kept rollouts, newest first,
+/// without per-device results.
+pub async fn list_rollouts(
+    State(state): State<RelayState>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    let mut rollouts: Vec<Rollout> = state.rollouts.read().await.values().cloned().collect();
+    rollouts.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+    let summaries: Vec<_> = rollouts
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.id,
+                "status": r.status,
+                "created_at": r.created_at,
+                "finished_at": r.finished_at,
+                "waves": r.waves.len(),
+                "waves_done": r.waves.iter().filter(|w| w.status == "done").count(),
+                "devices": r.waves.iter().map(|w| w.serials.len()).sum::<usize>(),
+                "failures": r.failures,
+                "abort_reason": r.abort_reason,
+            })
+        })
+        .collect();
+    Json(json!({ "rollouts": summaries })).into_response()
+}
+
+/// `GET /api/fleet/rollouts/{id}?token=` — one rollout with every wave's
+/// results.
+pub async fn get_rollout(
+    State(state): State<RelayState>,
+    Path(id): Path<String>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    match state.rollouts.read().await.get(&id) {
+        Some(rollout) => Json(rollout).into_response(),
+        None => not_found(&id),
+    }
+}
+
+/// `POST /api/fleet/rollouts/{id}/abort?token=` — stop before the next
+/// wave. The wave in flight runs to completion.
+pub async fn abort_rollout(
+    State(state): State<RelayState>,
+    Path(id): Path<String>,
+    Query(query): Query<TokenQuery>,
+) -> Response {
+    if let Some(response) = reject_token(&state, &query.token) {
+        return response;
+    }
+    match update(&state, &id, |r| r.abort_requested = true).await {
+        Some(rollout) => {
+            if !rollout.finished() {
+                warn!(rollout = %id, "Rollout abort requested");
+            }
+            Json(rollout).into_response()
+        }
+        None => not_found(&id),
+    }
+}
+
+fn not_found(id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("Rollout '{id}' not found") })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::fleet::DeviceResult;
+
+    fn serials(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("d{i:02}")).collect()
+    }
+
+    #[test]
+    fn waves_start_with_canaries() {
+        let waves = plan_waves(&serials(7), 2, 3);
+        let sizes: Vec<_> = waves.iter().map(|w| (w.canary, w.serials.len())).collect();
+        assert_eq!(sizes, [(true, 2), (false, 3), (false, 2)]);
+        assert_eq!(waves[2].index, 2);
+
+        // canary = 0 still runs one device first
+        let waves = plan_waves(&serials(2), 0, 10);
+        assert_eq!(waves.len(), 2);
+        assert!(waves[0].canary && waves[0].serials == ["d00"]);
+        assert_eq!(plan_waves(&serials(1), 5, 10).len(), 1);
+    }
+
+    #[test]
+    fn expectation_turns_ok_into_failed() {
+        let result = |serial: &str, stdout: &str| DeviceResult {
+            serial: serial.to_string(),
+            status: "ok".to_string(),
+            exit_code: Some(0),
+            stdout: Some(stdout.to_string()),
+            stderr: None,
+            run_id: None,
+            error: None,
+            duration_ms: 0,
+        };
+        let mut report = BroadcastReport {
+            total: 2,
+            ok: 2,
+            failed: 0,
+            errors: 0,
+            duration_ms: 0,
+            results: vec![result("a", "version 2.1\n"), result("b", "version 2.0\n")],
+        };
+        apply_expectation(&mut report, Some("2.1"));
+        assert_eq!((report.ok, report.failed), (1, 1));
+        assert_eq!(report.results[1].status, "failed");
+    }
+}
@@ -0,0 +1,166 @@
+//! Session share links.
+//!
+//! `POST /api/sessions/{id}/share` mints a token that lets someone without the
+//! API key watch (or, in `interactive` mode, type into) one session through
+//! the relay at `/s/{token}` — the "look at this terminal" link for support.
+//!
+//! ## Token format
+//!
+//! `<claims>.<signature>`, both base64url without padding. `claims` is the
+//! JSON [`ShareClaims`]; `signature` is HMAC-SHA256 over the encoded claims,
+//! keyed with the device's API key. The relay already knows each connected
+//! device's key (it's sent on registration), so it verifies tokens without any
+//! per-share state. Tokens expire at `exp`; rotating the API key revokes all
+//! outstanding ones.
+//!
+//! ## Scope
+//!
+//! A share connection is pinned to its session: every message it sends is
+//! rewritten to target that session, only [`allowed`] message types are
+//! forwarded, and it only receives messages about that session.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::util::hmac_sha256;
+
+/// Default and maximum share lifetime, in seconds.
+pub const DEFAULT_TTL_SECS: u64 = 3600;
+pub const MAX_TTL_SECS: u64 = 7 * 24 * 3600;
+
+const B64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+/// What a share link permits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareMode {
+    /// Watch output only.
+    #[default]
+    Read,
+    /// Also send input, resize, and signal.
+    Interactive,
+}
+
+/// Signed contents of a share token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareClaims {
+    /// Share id, for logs.
+    pub id: String,
+    /// Device serial (tells the relay which key to verify with).
+    pub serial: String,
+    pub session_id: String,
+    pub mode: ShareMode,
+    /// Expiry, Unix seconds.
+    pub exp: u64,
+}
+
+/// Sign `claims` with `key` into a token.
+pub fn mint(key: &str, claims: &ShareClaims) -> String {
+    let payload = B64.encode(serde_json::to_vec(claims).expect("claims serialize"));
+    let sig = B64.encode(hmac_sha256(key.as_bytes(), payload.as_bytes()));
+    format!("{payload}.{sig}")
+}
+
+/// Read a token's claims without checking the signature — only to find out
+/// which device's key to [`verify`] it with.
+pub fn peek(token: &str) -> Option<ShareClaims> {
+    let (payload, _) = token.split_once('.')?;
+    serde_json::from_slice(&B64.decode(payload).ok()?).ok()
+}
+
+/// Check a token's signature against `key` and its expiry against `now_secs`.
+pub fn verify(token: &str, key: &str, now_secs: u64) -> Result<ShareClaims, &'static str> {
+    let (payload, sig) = token.split_once('.').ok_or("malformed token")?;
+    let sig = B64.decode(sig).map_err(|_| "malformed token")?;
+    let expected = hmac_sha256(key.as_bytes(), payload.as_bytes());
+    if !crate::auth::constant_time_eq(&expected, &sig) {
+        return Err("invalid signature");
+    }
+    let claims: ShareClaims = B64
+        .decode(payload)
+        .ok()
+        .and_then(|p| serde_json::from_slice(&p).ok())
+        .ok_or("malformed token")?;
+    if claims.exp <= now_secs {
+        return Err("share link expired");
+    }
+    Ok(claims)
+}
+
+/// Whether a share connection in `mode` may send a message of `msg_type`.
+pub fn allowed(mode: ShareMode, msg_type: &str) -> bool {
+    match msg_type {
+        "ping" | "session.attach" | "session.read_diff" => true,
+        "session.stdin" | "session.exec" | "session.resize" | "session.signal" => {
+            mode == ShareMode::Interactive
+        }
+        _ => false,
+    }
+}
+
+/// Public share URL for a device whose tunnel connects to `relay_url`
+/// (`wss://relay.example.com/api/tunnel/register` →
+/// `https://relay.example.com/s/<token>`).
+pub fn share_url(relay_url: &str, token: &str) -> Option<String> {
+    Some(format!("{}/s/{token}", public_base(relay_url)?))
+}
+
+/// The relay's public origin from the URL a device tunnels to
+/// (`wss://relay.example.com/api/tunnel/register` → `https://relay.example.com`).
+pub fn public_base(relay_url: &str) -> Option<String> {
+    let (scheme, rest) = relay_url.split_once("://")?;
+    let scheme = match scheme {
+        "wss" | "https" => "https",
+        "ws" | "http" => "http",
+        _ => return None,
+    };
+    let host = rest.split('/').next().filter(|h| !h.is_empty())?;
+    Some(format!("{scheme}://{host}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(exp: u64) -> ShareClaims {
+        ShareClaims {
+            id: "s1".into(),
+            serial: "DEV-1".into(),
+            session_id: "abc".into(),
+            mode: ShareMode::Read,
+            exp,
+        }
+    }
+
+    #[test]
+    fn mint_verify_roundtrip_and_rejections() {
+        let token = mint("key", &claims(200));
+        assert_eq!(peek(&token), Some(claims(200)));
+        assert_eq!(verify(&token, "key", 100), Ok(claims(200)));
+        assert_eq!(verify(&token, "other", 100), Err("invalid signature"));
+        assert_eq!(verify(&token, "key", 200), Err("share link expired"));
+
+        // Claims swapped under an existing signature.
+        let (_, sig) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{sig}",
+            mint("key", &claims(9999)).split_once('.').unwrap().0
+        );
+        assert_eq!(verify(&forged, "key", 100), Err("invalid signature"));
+        assert!(verify("garbage", "key", 100).is_err());
+    }
+
+    #[test]
+    fn mode_scopes_and_url() {
+        assert!(allowed(ShareMode::Read, "session.attach"));
+        assert!(!allowed(ShareMode::Read, "session.stdin"));
+        assert!(allowed(ShareMode::Interactive, "session.stdin"));
+        assert!(!allowed(ShareMode::Interactive, "session.kill"));
+        assert!(!allowed(ShareMode::Interactive, "session.start"));
+        assert_eq!(
+            share_url("wss://relay.example.com:8443/api/tunnel/register", "t").as_deref(),
+            Some("https://relay.example.com:8443/s/t")
+        );
+        assert_eq!(share_url("relay.example.com", "t"), None);
+    }
+}
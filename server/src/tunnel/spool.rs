@@ -0,0 +1,323 @@
+//! Offline event spool for tunnel client mode.
+//!
+//! Local work keeps running while the relay is unreachable — infra checks and
+//! recovery actions, LTE watchdog steps, sessions started over the LAN — but
+//! the lifecycle broadcasts it produces have nowhere to go. The spool records
+//! them so a long outage doesn't leave a silent gap on the relay side.
+//!
+//! ## Design
+//!
+//! - **Recorder task**: subscribes to `session_events` for the lifetime of the
+//!   process. While `tunnel_stats.connected` is false, each event is appended
+//!   as one NDJSON line to `<data_dir>/tunnel_spool.jsonl`.
+//! - **Bounded**: capped at `tunnel.offline_spool_max_entries` lines. Once
+//!   full, further events are counted but not written, so the replay can say
+//!   how much was lost instead of pretending the record is complete.
+//! - **Survives restarts**: the file is counted on startup, so events spooled
+//!   before a reboot are still replayed on the next connect.
+//! - **Replay**: after registration the client reads the file and sends one
+//!   `tunnel.replay` message; the relay fans the events out to its clients
+//!   with `"replayed": true`. The replayed lines are removed only once the
+//!   send succeeded, so a connection that drops mid-replay loses nothing.
+//! - **First relay only**: the spool is shared by all relay connections
+//!   (`extra_urls`). Whichever registers first after an outage gets the
+//!   replay; relays that connect later don't see the offline events.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+
+use crate::sessions::journal::now_ms;
+use crate::state::AppState;
+
+/// Event types that are never spooled. Transfer progress is meaningless once
+/// the transfer has been paused, and errors are request-scoped replies.
+const SKIP_TYPES: &[&str] = &["gx.progress", "error"];
+
+/// Disk-backed queue of events observed while the tunnel was down.
+pub struct OfflineSpool {
+    path: PathBuf,
+    max_entries: usize,
+    len: AtomicUsize,
+    dropped: AtomicU64,
+    /// Serializes appends against replay reads and commits.
+    io_lock: Mutex<()>,
+}
+
+impl OfflineSpool {
+    /// Open (or prepare) the spool at `<data_dir>/tunnel_spool.jsonl`,
+    /// counting any entries left over from a previous run.
+    pub fn open(data_dir: &str, max_entries: usize) -> Self {
+        let path = std::path::Path::new(data_dir).join("tunnel_spool.jsonl");
+        let existing = std::fs::read_to_string(&path)
+            .map_or(0, |s| s.lines().filter(|l| !l.trim().is_empty()).count());
+        if existing > 0 {
+            info!("Tunnel spool: {existing} events pending replay from previous run");
+        }
+        Self {
+            path,
+            max_entries,
+            len: AtomicUsize::new(existing),
+            dropped: AtomicU64::new(0),
+            io_lock: Mutex::new(()),
+        }
+    }
+
+    /// Number of events currently spooled.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Whether the spool holds no events.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of events discarded because the spool was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Append one event, stamped with the time it was spooled.
+    pub async fn append(&self, event: &Value) {
+        let _guard = self.io_lock.lock().await;
+        if self.len.load(Ordering::Relaxed) >= self.max_entries {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let line = json!({ "spooled_at": now_ms(), "event": event }).to_string() + "\n";
+        let path = self.path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            use std::io::Write;
+            let mut f = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            f.write_all(line.as_bytes())?;
+            f.flush()
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r);
+        match result {
+            Ok(()) => {
+                self.len.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => warn!("Tunnel spool: append failed: {e}"),
+        }
+    }
+
+    /// Read everything spooled so far and build the `tunnel.replay` message.
+    ///
+    /// Returns `None` when there is nothing to replay. The spool is left
+    /// untouched: once the message has been sent, pass it to [`Self::commit`]
+    /// to remove the events it carried. If the send fails they stay on disk
+    /// for the next connect.
+    pub async fn replay(&self) -> Option<Replay> {
+        let _guard = self.io_lock.lock().await;
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if self.len.load(Ordering::Relaxed) == 0 && dropped == 0 {
+            return None;
+        }
+        let path = self.path.clone();
+        let contents = tokio::task::spawn_blocking(move || std::fs::read_to_string(&path))
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|r| r)
+            .unwrap_or_default();
+        Some(Replay::build(&contents, dropped))
+    }
+
+    /// Remove the events `replay` carried, after it was sent. Events appended
+    /// since [`Self::replay`] read the file are kept for the next replay.
+    pub async fn commit(&self, replay: &Replay) {
+        let _guard = self.io_lock.lock().await;
+        let path = self.path.clone();
+        let lines = replay.lines;
+        let result = tokio::task::spawn_blocking(move || {
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let rest = remaining(&contents, lines);
+            if rest.is_empty() {
+                return std::fs::remove_file(&path);
+            }
+            let tmp = path.with_extension("jsonl.tmp");
+            std::fs::write(&tmp, rest).and_then(|()| std::fs::rename(&tmp, &path))
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r);
+        if let Err(e) = result {
+            warn!("Tunnel spool: clearing replayed events failed: {e}");
+            return;
+        }
+        let _ = self
+            .len
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(lines))
+            });
+        let _ = self
+            .dropped
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(replay.dropped))
+            });
+    }
+}
+
+/// A `tunnel.replay` message read from the spool, not yet committed.
+pub struct Replay {
+    /// The message to send.
+    pub message: Value,
+    /// Spool lines the message was built from.
+    lines: usize,
+    /// Dropped-event count the message reports.
+    dropped: u64,
+}
+
+impl Replay {
+    fn build(contents: &str, dropped: u64) -> Self {
+        let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+        let events: Vec<Value> = lines
+            .iter()
+            .filter_map(|l| serde_json::from_str::<Value>(l).ok())
+            .collect();
+        let since = events.first().and_then(|e| e["spooled_at"].as_u64());
+        Self {
+            message: json!({
+                "type": "tunnel.replay",
+                "count": events.len(),
+                "dropped": dropped,
+                "spooled_since": since,
+                "events": events,
+            }),
+            lines: lines.len(),
+            dropped,
+        }
+    }
+}
+
+/// What is left of the spool file after its first `lines` entries.
+fn remaining(contents: &str, lines: usize) -> String {
+    contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .skip(lines)
+        .fold(String::new(), |mut out, l| {
+            out.push_str(l);
+            out.push('\n');
+            out
+        })
+}
+
+/// Spawn the recorder task. Runs for the lifetime of the process; events are
+/// only written while the tunnel is disconnected.
+pub fn spawn_recorder(state: AppState, spool: Arc<OfflineSpool>) -> tokio::task::JoinHandle<()> {
+    let mut rx = state.session_events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    if !state.tunnel_stats.connected.load(Ordering::Relaxed) {
+                        spool.dropped.fetch_add(n, Ordering::Relaxed);
+                    }
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if state.tunnel_stats.connected.load(Ordering::Relaxed) {
+                continue;
+            }
+            let msg_type = event["type"].as_str().unwrap_or("");
+            if SKIP_TYPES.contains(&msg_type) {
+                continue;
+            }
+            spool.append(&event).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("sctl-spool-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_events_until_committed() {
+        let dir = temp_dir("commit");
+        let spool = OfflineSpool::open(&dir, 10);
+        assert!(spool.replay().await.is_none());
+
+        spool
+            .append(&json!({"type": "session.closed", "n": 1}))
+            .await;
+        spool
+            .append(&json!({"type": "session.closed", "n": 2}))
+            .await;
+        let replay = spool.replay().await.unwrap();
+        assert_eq!(replay.message["type"], "tunnel.replay");
+        assert_eq!(replay.message["count"], 2);
+        assert_eq!(replay.message["events"][1]["event"]["n"], 2);
+        assert!(replay.message["spooled_since"].is_u64());
+
+        // An unsent replay leaves the spool alone, and a reopened spool
+        // (restart) still finds the events.
+        assert_eq!(spool.len(), 2);
+        assert_eq!(OfflineSpool::open(&dir, 10).len(), 2);
+
+        // Events appended while the replay was in flight survive the commit.
+        spool
+            .append(&json!({"type": "session.closed", "n": 3}))
+            .await;
+        spool.commit(&replay).await;
+        assert_eq!(spool.len(), 1);
+        let next = spool.replay().await.unwrap();
+        assert_eq!(next.message["count"], 1);
+        assert_eq!(next.message["events"][0]["event"]["n"], 3);
+
+        spool.commit(&next).await;
+        assert!(spool.is_empty());
+        assert!(spool.replay().await.is_none());
+        assert!(!std::path::Path::new(&dir)
+            .join("tunnel_spool.jsonl")
+            .exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn full_spool_counts_dropped_events() {
+        let dir = temp_dir("full");
+        let spool = OfflineSpool::open(&dir, 1);
+        spool.append(&json!({"type": "a"})).await;
+        spool.append(&json!({"type": "b"})).await;
+        spool.append(&json!({"type": "c"})).await;
+        assert_eq!((spool.len(), spool.dropped()), (1, 2));
+
+        let replay = spool.replay().await.unwrap();
+        assert_eq!(replay.message["count"], 1);
+        assert_eq!(replay.message["dropped"], 2);
+        spool.commit(&replay).await;
+        assert_eq!((spool.len(), spool.dropped()), (0, 0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remaining_skips_committed_lines() {
+        assert_eq!(remaining("a\nb\n\nc\n", 2), "c\n");
+        assert_eq!(remaining("a\n", 1), "");
+        assert_eq!(remaining("", 3), "");
+    }
+}
@@ -0,0 +1,803 @@
+//! Device twin: a desired-state document and the reconciler that keeps the
+//! device in line with it.
+//!
+//! `PUT /api/twin` stores the document in `<data_dir>/twin.json`. It lists:
+//!
+//! - `files` — `{path, content, mode?}`: exact content, optional octal mode
+//! - `services` — `{name, running?, enabled?}`: via `systemctl`, else
+//!   `/etc/init.d/<name>`
+//! - `packages` — `{name, installed}`: via `opkg`, `dpkg-query`/`apt-get` or
+//!   `apk`
+//! - `playbooks` — `{name, every_secs, args?}`: due once the playbook's
+//!   newest stored run is older than `every_secs`
+//!
+//! Every `[twin] interval_secs`, and right after a `PUT`, the reconciler
+//! compares each entry with the device and keeps a [`Report`] of the drift
+//! it found. With `[twin] apply = true`, or `POST /api/twin/reconcile` with
+//! `{"apply": true}`, it also fixes what drifted and runs due playbooks.
+//! When the set of drifted entries changes it broadcasts `twin.drift`.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, Notify, RwLock};
+use tracing::{info, warn};
+
+use crate::activity::{ActivitySource, ActivityType};
+use crate::gawdxfer::hasher;
+use crate::infra::checks::exec_args_pub;
+use crate::state::AppState;
+
+/// Timeout for state queries (`systemctl is-active`, `dpkg-query`, ...).
+const QUERY_TIMEOUT_MS: u64 = 10_000;
+
+/// Timeout for fixes; package installs can be slow on flash storage.
+const APPLY_TIMEOUT_MS: u64 = 600_000;
+
+/// Shortest playbook schedule accepted.
+const MIN_PLAYBOOK_EVERY_SECS: u64 = 60;
+
+// ─── Desired state ───────────────────────────────────────────────────────────
+
+/// The document behind `GET/PUT /api/twin`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    #[serde(default)]
+    pub files: Vec<DesiredFile>,
+    #[serde(default)]
+    pub services: Vec<DesiredService>,
+    #[serde(default)]
+    pub packages: Vec<DesiredPackage>,
+    #[serde(default)]
+    pub playbooks: Vec<PlaybookSchedule>,
+}
+
+/// A file that must exist with exactly this content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredFile {
+    pub path: String,
+    pub content: String,
+    /// Octal permission bits, e.g. `"0644"`. Left alone when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+/// A service's run and boot state; an absent field is left alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredService {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub running: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+/// A package that must be installed (or absent).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredPackage {
+    pub name: String,
+    #[serde(default = "default_installed")]
+    pub installed: bool,
+}
+
+fn default_installed() -> bool {
+    true
+}
+
+/// A playbook to run every `every_secs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaybookSchedule {
+    pub name: String,
+    pub every_secs: u64,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub args: Value,
+}
+
+/// Service and package names are passed as arguments, never through a shell;
+/// a leading `-` would still read as an option.
+fn valid_unit_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@._+:-".contains(c))
+}
+
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o7777)
+}
+
+impl DesiredState {
+    /// Reject entries the reconciler couldn't act on.
+    ///
+    /// # Errors
+    ///
+    /// A message naming the first bad entry.
+    pub fn validate(&self) -> Result<(), String> {
+        for f in &self.files {
+            if !f.path.starts_with('/') || f.path.split('/').any(|c| c == "..") {
+                return Err(format!(
+                    "files: '{}' must be an absolute path without '..'",
+                    f.path
+                ));
+            }
+            if let Some(mode) = &f.mode {
+                if parse_mode(mode).is_none() {
+                    return Err(format!("files: '{}' has invalid mode '{mode}'", f.path));
+                }
+            }
+        }
+        for s in &self.services {
+            if !valid_unit_name(&s.name) {
+                return Err(format!("services: invalid name '{}'", s.name));
+            }
+        }
+        for p in &self.packages {
+            if !valid_unit_name(&p.name) {
+                return Err(format!("packages: invalid name '{}'", p.name));
+            }
+        }
+        for p in &self.playbooks {
+            if p.name.is_empty()
+                || !p
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("playbooks: invalid name '{}'", p.name));
+            }
+            if p.every_secs < MIN_PLAYBOOK_EVERY_SECS {
+                return Err(format!(
+                    "playbooks: '{}' every_secs must be at least {MIN_PLAYBOOK_EVERY_SECS}",
+                    p.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// ─── Reports ─────────────────────────────────────────────────────────────────
+
+/// One entry that didn't match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Drift {
+    /// `file`, `service`, `package` or `playbook`.
+    pub kind: String,
+    /// Path, service, package or playbook name.
+    pub name: String,
+    /// What differs: `content`, `mode`, `running`, `enabled`, `installed`
+    /// or `due`.
+    pub field: String,
+    pub expected: Value,
+    /// `null` if it couldn't be read.
+    pub actual: Value,
+    /// Set on passes that apply: whether the fix worked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Drift {
+    fn new(kind: &str, name: &str, field: &str, expected: Value, actual: Value) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            field: field.to_string(),
+            expected,
+            actual,
+            fixed: None,
+            error: None,
+        }
+    }
+
+    /// A state that couldn't be read at all.
+    fn unknown(kind: &str, name: &str, field: &str, expected: Value, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(kind, name, field, expected, Value::Null)
+        }
+    }
+
+    fn outstanding(&self) -> bool {
+        self.fixed != Some(true)
+    }
+}
+
+/// Outcome of one reconcile pass.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    /// Unix ms.
+    pub checked_at: u64,
+    pub duration_ms: u64,
+    /// Whether this pass fixed drift or only reported it.
+    pub applied: bool,
+    /// No drift left after this pass.
+    pub in_sync: bool,
+    pub drift: Vec<Drift>,
+}
+
+// ─── Twin ────────────────────────────────────────────────────────────────────
+
+/// Desired state, the latest report, and the reconciler's wake-up.
+pub struct Twin {
+    path: PathBuf,
+    desired: RwLock<Option<DesiredState>>,
+    report: RwLock<Option<Report>>,
+    /// Held for the length of a pass so passes never overlap.
+    pass: Mutex<()>,
+    wake: Notify,
+}
+
+impl Twin {
+    /// Load `<data_dir>/twin.json`, if present.
+    pub fn load(data_dir: &str) -> Self {
+        let path = Path::new(data_dir).join("twin.json");
+        let desired = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .inspect_err(
+                    |e| warn!(path = %path.display(), "Ignoring invalid twin document: {e}"),
+                )
+                .ok(),
+            Err(_) => None,
+        };
+        Self {
+            path,
+            desired: RwLock::new(desired),
+            report: RwLock::new(None),
+            pass: Mutex::new(()),
+            wake: Notify::new(),
+        }
+    }
+
+    pub async fn desired(&self) -> Option<DesiredState> {
+        self.desired.read().await.clone()
+    }
+
+    pub async fn report(&self) -> Option<Report> {
+        self.report.read().await.clone()
+    }
+
+    /// Store a validated document and wake the reconciler.
+    ///
+    /// # Errors
+    ///
+    /// Writing `twin.json` failed.
+    pub async fn set_desired(&self, desired: DesiredState) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(&desired)?;
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        *self.desired.write().await = Some(desired);
+        *self.report.write().await = None;
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+/// Spawn the periodic reconciler. It also runs whenever the document changes.
+pub fn spawn(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = state.config.twin.interval_secs;
+        loop {
+            if interval == 0 {
+                state.twin.wake.notified().await;
+            } else {
+                tokio::select! {
+                    () = tokio::time::sleep(Duration::from_secs(interval)) => {}
+                    () = state.twin.wake.notified() => {}
+                }
+            }
+            reconcile(&state, state.config.twin.apply, ActivitySource::Unknown).await;
+        }
+    })
+}
+
+/// Run one pass now; `None` if no document has been stored.
+pub async fn reconcile(state: &AppState, apply: bool, source: ActivitySource) -> Option<Report> {
+    let twin = &state.twin;
+    let _pass = twin.pass.lock().await;
+    let desired = twin.desired().await?;
+    let started = Instant::now();
+
+    let mut drift = Vec::new();
+    for file in &desired.files {
+        drift.extend(check_file(file, apply).await);
+    }
+    let services = ServiceManager::detect();
+    for service in &desired.services {
+        drift.extend(check_service(services, service, apply).await);
+    }
+    let packages = PackageManager::detect();
+    for package in &desired.packages {
+        drift.extend(check_package(packages, package, apply).await);
+    }
+    for schedule in &desired.playbooks {
+        drift.extend(check_playbook(state, schedule, apply).await);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let report = Report {
+        checked_at: crate::sessions::journal::now_ms(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        applied: apply,
+        in_sync: !drift.iter().any(Drift::outstanding),
+        drift,
+    };
+
+    let key = |r: &Report| -> Vec<(String, String, String)> {
+        r.drift
+            .iter()
+            .filter(|d| d.outstanding())
+            .map(|d| (d.kind.clone(), d.name.clone(), d.field.clone()))
+            .collect()
+    };
+    let previous = twin.report.read().await.as_ref().map(key);
+    if previous.as_ref() != Some(&key(&report)) {
+        let outstanding = report.drift.iter().filter(|d| d.outstanding()).count();
+        info!(in_sync = report.in_sync, outstanding, "Twin drift changed");
+        let _ = state.session_events.send(json!({
+            "type": "twin.drift",
+            "in_sync": report.in_sync,
+            "outstanding": outstanding,
+            "drift": report.drift,
+        }));
+    }
+    let attempts: Vec<&Drift> = report.drift.iter().filter(|d| d.fixed.is_some()).collect();
+    if !attempts.is_empty() {
+        let ok = attempts.iter().filter(|d| d.fixed == Some(true)).count();
+        state
+            .activity_log
+            .log(
+                ActivityType::TwinApply,
+                source,
+                format!("Twin applied {ok}/{} fixes", attempts.len()),
+                Some(json!({ "fixes": attempts })),
+                None,
+            )
+            .await;
+    }
+    *twin.report.write().await = Some(report.clone());
+    Some(report)
+}
+
+/// Record a fix attempt on `drift`.
+fn fixed(mut drift: Drift, result: Result<(), String>) -> Drift {
+    drift.fixed = Some(result.is_ok());
+    drift.error = result.err();
+    drift
+}
+
+// ─── Files ───────────────────────────────────────────────────────────────────
+
+async fn check_file(file: &DesiredFile, apply: bool) -> Vec<Drift> {
+    let path = Path::new(&file.path);
+    let expected_hash = hasher::hash_bytes(file.content.as_bytes());
+    let mut drift = Vec::new();
+    match hasher::hash_file(path).await {
+        Ok(actual) if actual == expected_hash => {}
+        Ok(actual) => drift.push(Drift::new(
+            "file",
+            &file.path,
+            "content",
+            json!(expected_hash),
+            json!(actual),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => drift.push(Drift::new(
+            "file",
+            &file.path,
+            "content",
+            json!(expected_hash),
+            Value::Null,
+        )),
+        Err(e) => drift.push(Drift::unknown(
+            "file",
+            &file.path,
+            "content",
+            json!(expected_hash),
+            e.to_string(),
+        )),
+    }
+    if apply && !drift.is_empty() {
+        let result = write_file(path, &file.content).await;
+        drift = drift
+            .into_iter()
+            .map(|d| fixed(d, result.clone()))
+            .collect();
+    }
+
+    if let Some(mode) = file.mode.as_deref().and_then(parse_mode) {
+        let actual = tokio::fs::metadata(path)
+            .await
+            .ok()
+            .map(|m| crate::util::file_mode(&m));
+        if actual != Some(mode) {
+            let d = Drift::new(
+                "file",
+                &file.path,
+                "mode",
+                json!(format!("{mode:04o}")),
+                actual.map_or(Value::Null, |m| json!(format!("{m:04o}"))),
+            );
+            drift.push(if apply {
+                let result = set_mode(path, mode).await;
+                fixed(d, result)
+            } else {
+                d
+            });
+        }
+    }
+    drift
+}
+
+/// Replace `path` atomically, creating its directory if needed.
+async fn write_file(path: &Path, content: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("create {}: {e}", dir.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".sctl-tmp");
+    let tmp = PathBuf::from(tmp);
+    // Keep the current mode; a `mode` entry is applied after.
+    let permissions = tokio::fs::metadata(path).await.map(|m| m.permissions());
+    tokio::fs::write(&tmp, content)
+        .await
+        .map_err(|e| format!("write: {e}"))?;
+    if let Ok(permissions) = permissions {
+        let _ = tokio::fs::set_permissions(&tmp, permissions).await;
+    }
+    tokio::fs::rename(&tmp, path)
+        .await
+        .map_err(|e| format!("rename: {e}"))
+}
+
+async fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    let path = path.to_path_buf();
+    crate::io_pool::run(move || crate::util::set_mode(&path, mode))
+        .await
+        .map_err(|e| format!("chmod: {e}"))
+}
+
+// ─── Commands ────────────────────────────────────────────────────────────────
+
+pub(crate) fn has_tool(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+}
+
+/// Exit code and stdout of a query.
+async fn query(program: &str, args: &[&str]) -> Result<(i32, String), String> {
+    exec_args_pub(program, args, QUERY_TIMEOUT_MS)
+        .await
+        .map(|(code, stdout, _)| (code, stdout))
+}
+
+/// Run a fix; non-zero exit is an error carrying the last stderr line.
+async fn run_fix(program: &str, args: &[&str]) -> Result<(), String> {
+    match exec_args_pub(program, args, APPLY_TIMEOUT_MS).await? {
+        (0, _, _) => Ok(()),
+        (code, stdout, stderr) => {
+            let msg = if stderr.trim().is_empty() {
+                stdout
+            } else {
+                stderr
+            };
+            let last = msg.trim().lines().last().unwrap_or_default().to_string();
+            Err(format!("{program} exited with {code}: {last}"))
+        }
+    }
+}
+
+// ─── Services ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy)]
+enum ServiceManager {
+    Systemd,
+    /// `/etc/init.d/<name>` scripts with `running`/`enabled` (OpenWrt procd).
+    InitD,
+}
+
+impl ServiceManager {
+    fn detect() -> Option<Self> {
+        if has_tool("systemctl") {
+            Some(Self::Systemd)
+        } else if Path::new("/etc/init.d").is_dir() {
+            Some(Self::InitD)
+        } else {
+            None
+        }
+    }
+
+    /// `(program, args)` for `verb` (`is-active`, `start`, `enable`, ...).
+    fn command(self, name: &str, verb: &str) -> (String, Vec<String>) {
+        match self {
+            Self::Systemd => {
+                let mut args = vec![verb.to_string()];
+                if verb.starts_with("is-") {
+                    args.push("--quiet".to_string());
+                }
+                args.push(name.to_string());
+                ("systemctl".to_string(), args)
+            }
+            Self::InitD => {
+                let verb = match verb {
+                    "is-active" => "running",
+                    "is-enabled" => "enabled",
+                    other => other,
+                };
+                (format!("/etc/init.d/{name}"), vec![verb.to_string()])
+            }
+        }
+    }
+
+    async fn run(self, name: &str, verb: &str, fix: bool) -> Result<i32, String> {
+        let (program, args) = self.command(name, verb);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if fix {
+            run_fix(&program, &args).await.map(|()| 0)
+        } else {
+            query(&program, &args).await.map(|(code, _)| code)
+        }
+    }
+}
+
+async fn check_service(
+    manager: Option<ServiceManager>,
+    service: &DesiredService,
+    apply: bool,
+) -> Vec<Drift> {
+    let checks = [
+        ("running", service.running, "is-active", ["start", "stop"]),
+        (
+            "enabled",
+            service.enabled,
+            "is-enabled",
+            ["enable", "disable"],
+        ),
+    ];
+    let mut drift = Vec::new();
+    for (field, want, query_verb, [on, off]) in checks {
+        let Some(want) = want else {
+            continue;
+        };
+        let Some(manager) = manager else {
+            drift.push(Drift::unknown(
+                "service",
+                &service.name,
+                field,
+                json!(want),
+                "No supported service manager (systemctl or /etc/init.d)".to_string(),
+            ));
+            continue;
+        };
+        let actual = match manager.run(&service.name, query_verb, false).await {
+            Ok(code) => code == 0,
+            Err(e) => {
+                drift.push(Drift::unknown(
+                    "service",
+                    &service.name,
+                    field,
+                    json!(want),
+                    e,
+                ));
+                continue;
+            }
+        };
+        if actual == want {
+            continue;
+        }
+        let d = Drift::new("service", &service.name, field, json!(want), json!(actual));
+        drift.push(if apply {
+            let verb = if want { on } else { off };
+            let result = manager.run(&service.name, verb, true).await.map(|_| ());
+            fixed(d, result)
+        } else {
+            d
+        });
+    }
+    drift
+}
+
+// ─── Packages ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy)]
+enum PackageManager {
+    Opkg,
+    Apt,
+    Apk,
+}
+
+impl PackageManager {
+    fn detect() -> Option<Self> {
+        if has_tool("opkg") {
+            Some(Self::Opkg)
+        } else if has_tool("dpkg-query") && has_tool("apt-get") {
+            Some(Self::Apt)
+        } else if has_tool("apk") {
+            Some(Self::Apk)
+        } else {
+            None
+        }
+    }
+
+    async fn installed(self, name: &str) -> Result<bool, String> {
+        match self {
+            Self::Opkg => {
+                let (_, out) = query("opkg", &["list-installed", name]).await?;
+                Ok(out.lines().any(|l| l.split(" - ").next() == Some(name)))
+            }
+            Self::Apt => {
+                let (code, out) = query("dpkg-query", &["-W", "-f=${Status}", name]).await?;
+                Ok(code == 0 && out.contains("install ok installed"))
+            }
+            Self::Apk => Ok(query("apk", &["info", "-e", name]).await?.0 == 0),
+        }
+    }
+
+    async fn set(self, name: &str, installed: bool) -> Result<(), String> {
+        match (self, installed) {
+            (Self::Opkg, true) => run_fix("opkg", &["install", name]).await,
+            (Self::Opkg, false) => run_fix("opkg", &["remove", name]).await,
+            (Self::Apt, true) => run_fix("apt-get", &["install", "-y", name]).await,
+            (Self::Apt, false) => run_fix("apt-get", &["remove", "-y", name]).await,
+            (Self::Apk, true) => run_fix("apk", &["add", name]).await,
+            (Self::Apk, false) => run_fix("apk", &["del", name]).await,
+        }
+    }
+}
+
+async fn check_package(
+    manager: Option<PackageManager>,
+    package: &DesiredPackage,
+    apply: bool,
+) -> Option<Drift> {
+    let want = package.installed;
+    let Some(manager) = manager else {
+        return Some(Drift::unknown(
+            "package",
+            &package.name,
+            "installed",
+            json!(want),
+            "No supported package manager (opkg, apt or apk)".to_string(),
+        ));
+    };
+    let actual = match manager.installed(&package.name).await {
+        Ok(actual) => actual,
+        Err(e) => {
+            return Some(Drift::unknown(
+                "package",
+                &package.name,
+                "installed",
+                json!(want),
+                e,
+            ))
+        }
+    };
+    if actual == want {
+        return None;
+    }
+    let d = Drift::new(
+        "package",
+        &package.name,
+        "installed",
+        json!(want),
+        json!(actual),
+    );
+    Some(if apply {
+        let result = manager.set(&package.name, want).await;
+        fixed(d, result)
+    } else {
+        d
+    })
+}
+
+// ─── Playbooks ───────────────────────────────────────────────────────────────
+
+async fn check_playbook(
+    state: &AppState,
+    schedule: &PlaybookSchedule,
+    apply: bool,
+) -> Option<Drift> {
+    let now = crate::sessions::journal::now_ms();
+    let last = crate::routes::playbooks::last_run_at(state, &schedule.name).await;
+    if last.is_some_and(|at| now.saturating_sub(at) < schedule.every_secs * 1000) {
+        return None;
+    }
+    let d = Drift::new(
+        "playbook",
+        &schedule.name,
+        "due",
+        json!({ "every_secs": schedule.every_secs }),
+        json!({ "last_run_at": last }),
+    );
+    if !apply {
+        return Some(d);
+    }
+    let payload = crate::routes::playbooks::RunRequest {
+        args: schedule.args.clone(),
+        ..Default::default()
+    };
+    let result = match crate::routes::playbooks::run(
+        state,
+        &axum::http::HeaderMap::new(),
+        &schedule.name,
+        payload,
+    )
+    .await
+    {
+        Ok(axum::Json(report)) if report.status == "ok" => Ok(()),
+        Ok(axum::Json(report)) => Err(format!("run {} {}", report.run_id, report.status)),
+        Err((_, axum::Json(e))) => Err(e.message),
+    };
+    Some(fixed(d, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_unusable_entries() {
+        let ok: DesiredState = serde_json::from_value(json!({
+            "files": [{"path": "/etc/motd", "content": "hi\n", "mode": "0644"}],
+            "services": [{"name": "dropbear", "running": true}],
+            "packages": [{"name": "tcpdump-mini"}],
+            "playbooks": [{"name": "rotate-logs", "every_secs": 3600}],
+        }))
+        .unwrap();
+        assert!(ok.validate().is_ok());
+        assert!(ok.packages[0].installed);
+
+        let bad = |doc: Value| {
+            serde_json::from_value::<DesiredState>(doc)
+                .map_err(|e| e.to_string())
+                .and_then(|d| d.validate())
+                .unwrap_err()
+        };
+        assert!(bad(json!({"files": [{"path": "etc/motd", "content": ""}]})).contains("absolute"));
+        assert!(bad(json!({"files": [{"path": "/a/../b", "content": ""}]})).contains(".."));
+        assert!(
+            bad(json!({"files": [{"path": "/a", "content": "", "mode": "999"}]})).contains("mode")
+        );
+        assert!(bad(json!({"services": [{"name": "--now"}]})).contains("invalid name"));
+        assert!(bad(json!({"packages": [{"name": "a b"}]})).contains("invalid name"));
+        assert!(bad(json!({"playbooks": [{"name": "x", "every_secs": 5}]})).contains("at least"));
+        assert!(bad(json!({"users": []})).contains("unknown field"));
+    }
+
+    #[tokio::test]
+    async fn file_drift_is_reported_then_fixed() {
+        let dir = std::env::temp_dir().join(format!("sctl-twin-{}", std::process::id()));
+        let path = dir.join("conf");
+        let file = DesiredFile {
+            path: path.display().to_string(),
+            content: "a=1\n".to_string(),
+            mode: Some("0600".to_string()),
+        };
+
+        let drift = check_file(&file, false).await;
+        assert_eq!(drift.len(), 2);
+        assert_eq!(drift[0].field, "content");
+        assert!(drift[0].actual.is_null());
+        assert!(!path.exists());
+
+        let drift = check_file(&file, true).await;
+        assert!(drift.iter().all(|d| d.fixed == Some(true)), "{drift:?}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a=1\n");
+        assert!(check_file(&file, false).await.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
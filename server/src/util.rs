@@ -3,6 +3,8 @@
 use std::borrow::Cow;
 use std::path::Path;
 
+use sha2::{Digest, Sha256};
+
 /// Expand a leading `~` to `$HOME`.
 ///
 /// - `"~"` → `"/home/user"`
@@ -67,10 +69,294 @@ pub async fn append_rotating(path: &Path, line: &str, max_bytes: u64) -> std::io
     .map_err(std::io::Error::other)?
 }
 
+/// Keep only the requested fields of `value` (the `?fields=` projection).
+///
+/// `fields` is comma-separated; dotted names reach into nested objects
+/// (`tunnel.connected`). Arrays are projected element by element, so a list
+/// keeps the same fields of every item. Unknown names are ignored. `None` or
+/// an empty list leaves `value` untouched.
+pub fn project_fields(value: &mut serde_json::Value, fields: Option<&str>) {
+    let Some(fields) = fields else {
+        return;
+    };
+    let paths: Vec<Vec<&str>> = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| f.split('.').collect())
+        .collect();
+    if paths.is_empty() {
+        return;
+    }
+    let paths: Vec<&[&str]> = paths.iter().map(Vec::as_slice).collect();
+    project(value, &paths);
+}
+
+fn project(value: &mut serde_json::Value, paths: &[&[&str]]) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                project(item, paths);
+            }
+        }
+        serde_json::Value::Object(map) => map.retain(|key, child| {
+            let tails: Vec<&[&str]> = paths
+                .iter()
+                .filter(|p| p.first() == Some(&key.as_str()))
+                .map(|p| &p[1..])
+                .collect();
+            if tails.is_empty() {
+                return false;
+            }
+            if !tails.iter().any(|t| t.is_empty()) {
+                project(child, &tails);
+            }
+            true
+        }),
+        _ => {}
+    }
+}
+
+/// Format a UTC unix timestamp as `YYYY-MM-DDTHH:MM:SSZ` without pulling in
+/// `chrono`.
+pub fn format_iso8601_utc(unix_secs: u64) -> String {
+    let (y, m, d) = civil_from_days(unix_secs / 86_400);
+    let (hour, minute, second) = time_of_day(unix_secs);
+    format!("{y:04}-{m:02}-{d:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// Format a UTC unix timestamp as an HTTP date (RFC 9110 IMF-fixdate),
+/// e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(unix_secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = unix_secs / 86_400;
+    let (y, m, d) = civil_from_days(days);
+    let (hour, minute, second) = time_of_day(unix_secs);
+    #[allow(clippy::cast_possible_truncation)]
+    let (weekday, month) = (WEEKDAYS[(days % 7) as usize], MONTHS[(m - 1) as usize]);
+    format!("{weekday}, {d:02} {month} {y:04} {hour:02}:{minute:02}:{second:02} GMT")
+}
+
+fn time_of_day(unix_secs: u64) -> (u64, u64, u64) {
+    let secs_of_day = unix_secs % 86_400;
+    (
+        secs_of_day / 3600,
+        (secs_of_day / 60) % 60,
+        secs_of_day % 60,
+    )
+}
+
+/// Days since 1970-01-01 → (year, month, day), by Howard Hinnant's
+/// `civil_from_days` algorithm — exact for any valid `time_t`, no leap-second
+/// handling required (matches kernel clock).
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn civil_from_days(days: u64) -> (i64, u64, u64) {
+    let z: i64 = days as i64 + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let mut y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    if m <= 2 {
+        y += 1;
+    }
+    (y, m, d)
+}
+
+/// HMAC-SHA256 (RFC 2104).
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Space on the filesystem holding `path`: `(total, available, free)` in
+/// bytes, where `available` is what an unprivileged writer may still use.
+pub fn disk_space(path: &Path) -> std::io::Result<(u64, u64, u64)> {
+    #[cfg(unix)]
+    {
+        let stat = nix::sys::statvfs::statvfs(path)?;
+        // `fsblkcnt_t` is 32-bit on macOS.
+        #[allow(clippy::useless_conversion)]
+        let block = u64::from(stat.fragment_size());
+        #[allow(clippy::useless_conversion)]
+        Ok((
+            u64::from(stat.blocks()) * block,
+            u64::from(stat.blocks_available()) * block,
+            u64::from(stat.blocks_free()) * block,
+        ))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        let (mut available, mut total, mut free) = (0u64, 0u64, 0u64);
+        // SAFETY: `wide` is NUL-terminated and the out pointers are valid.
+        let ok = unsafe {
+            windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                std::ptr::addr_of_mut!(available),
+                std::ptr::addr_of_mut!(total),
+                std::ptr::addr_of_mut!(free),
+            )
+        };
+        if ok == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok((total, available, free))
+    }
+}
+
+/// Flush filesystem buffers before a reboot. Windows flushes on shutdown.
+pub fn sync_filesystems() {
+    // SAFETY: sync() takes no arguments and cannot fail.
+    #[cfg(unix)]
+    unsafe {
+        libc::sync();
+    }
+}
+
+/// Permission bits of a file. Windows has no mode bits, so a read-only file
+/// reports `0o444` and any other `0o644`.
+pub fn file_mode(meta: &std::fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o7777
+    }
+    #[cfg(windows)]
+    {
+        if meta.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        }
+    }
+}
+
+/// Set the permission bits of `path`. On Windows only the owner write bit
+/// counts, as the read-only attribute.
+pub fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+    #[cfg(windows)]
+    {
+        let mut perms = std::fs::metadata(path)?.permissions();
+        perms.set_readonly(mode & 0o200 == 0);
+        std::fs::set_permissions(path, perms)
+    }
+}
+
+/// Effective uid of sctl. Windows has no uids and reports `u32::MAX`, which
+/// is neither root nor any `/etc/passwd` entry.
+pub fn effective_uid() -> u32 {
+    // SAFETY: geteuid has no failure mode.
+    #[cfg(unix)]
+    unsafe {
+        libc::geteuid()
+    }
+    #[cfg(windows)]
+    u32::MAX
+}
+
+/// Owner uid and gid of a file; `None` on Windows.
+pub fn file_owner(meta: &std::fs::Metadata) -> Option<(u32, u32)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((meta.uid(), meta.gid()))
+    }
+    #[cfg(windows)]
+    {
+        let _ = meta;
+        None
+    }
+}
+
+/// Change the owner of `path`. A no-op on Windows, where new files already
+/// belong to the account sctl runs as.
+pub fn chown(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid))
+    }
+    #[cfg(windows)]
+    {
+        let _ = (path, uid, gid);
+        Ok(())
+    }
+}
+
+/// Create files opened with `opts` with `mode` (less the umask). On Windows
+/// new files take the directory's ACL instead.
+pub fn create_mode(opts: &mut std::fs::OpenOptions, mode: u32) -> &mut std::fs::OpenOptions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(mode)
+    }
+    #[cfg(windows)]
+    {
+        let _ = mode;
+        opts
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn projects_lists_and_nested_fields() {
+        let mut list = serde_json::json!([
+            {"id": 1, "summary": "ls", "detail": {"risk": "read", "exit_code": 0}},
+            {"id": 2, "summary": "rm", "detail": {"risk": "destructive"}},
+        ]);
+        project_fields(&mut list, Some("id, detail.risk,nope"));
+        assert_eq!(
+            list,
+            serde_json::json!([
+                {"id": 1, "detail": {"risk": "read"}},
+                {"id": 2, "detail": {"risk": "destructive"}},
+            ])
+        );
+
+        let mut obj = serde_json::json!({"hostname": "h", "tunnel": {"connected": true, "rtt": 5}});
+        project_fields(&mut obj, Some("tunnel,tunnel.connected"));
+        assert_eq!(
+            obj,
+            serde_json::json!({"tunnel": {"connected": true, "rtt": 5}})
+        );
+
+        let before = obj.clone();
+        project_fields(&mut obj, Some(" , "));
+        project_fields(&mut obj, None);
+        assert_eq!(obj, before);
+    }
+
     #[tokio::test]
     async fn rotates_when_over_max_bytes() {
         let tmp = tempfile_path("append_rotating_rotate");
@@ -109,4 +395,47 @@ mod tests {
     fn tempfile_path(name: &str) -> std::path::PathBuf {
         std::env::temp_dir().join(format!("sctl_test_{name}_{}.log", std::process::id()))
     }
+
+    #[test]
+    fn hmac_matches_rfc4231() {
+        // RFC 4231 test cases 2 and 6 (key longer than the block).
+        let hex = crate::gawdxfer::hasher::hex::encode;
+        assert_eq!(
+            hex(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn iso8601_epoch() {
+        assert_eq!(format_iso8601_utc(0), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn iso8601_known_timestamp() {
+        // 2026-05-20T13:07:50 UTC (LiveBarn first wedge symptom onset)
+        assert_eq!(format_iso8601_utc(1_779_282_470), "2026-05-20T13:07:50Z");
+    }
+
+    #[test]
+    fn iso8601_leap_year_boundary() {
+        // 2024-02-29 12:00:00 UTC
+        assert_eq!(format_iso8601_utc(1_709_208_000), "2024-02-29T12:00:00Z");
+    }
+
+    #[test]
+    fn http_date_example_from_the_rfc() {
+        assert_eq!(
+            format_http_date(784_111_777),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(format_http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
 }
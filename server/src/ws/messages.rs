@@ -20,7 +20,11 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::activity::ActivityEntry;
+use crate::file_watch::FileChange;
 use crate::gawdxfer::types::{Complete, Progress};
+use crate::log_tail::LogLine;
+use crate::sessions::control::InputLock;
+use crate::sessions::screen::ScreenDiff;
 use crate::sessions::SessionListItem;
 
 /// Server → client message. Wire format is `{"type": "<code>", ...fields}`
@@ -60,6 +64,8 @@ pub enum WsServerMsg {
         persistent: bool,
         pty: bool,
         user_allows_ai: bool,
+        /// Output is being recorded (`record: true`).
+        recording: bool,
         created_at: u64,
         #[serde(default, skip_serializing_if = "Option::is_none")]
         name: Option<String>,
@@ -114,6 +120,39 @@ pub enum WsServerMsg {
     #[serde(rename = "session.renamed")]
     SessionRenamed { session_id: String, name: String },
 
+    /// Broadcast when a PTY session's OSC title or cwd changes. Carries the
+    /// full current values; `null` means unset.
+    #[serde(rename = "session.updated")]
+    SessionUpdated {
+        session_id: String,
+        title: Option<String>,
+        cwd: Option<String>,
+    },
+
+    /// Response to `session.read_diff`: screen rows changed since `since`.
+    #[serde(rename = "session.diff")]
+    SessionDiff {
+        session_id: String,
+        #[serde(flatten)]
+        diff: ScreenDiff,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// Response to `session.history` — output entries after `since` read
+    /// from the session journal, as `session.stdout`/`stderr`/`system`
+    /// messages. `next_seq` is set while more follow.
+    #[serde(rename = "session.history.result")]
+    SessionHistory {
+        session_id: String,
+        entries: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_seq: Option<u64>,
+        has_more: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
     /// Response to `session.rename`.
     #[serde(rename = "session.rename.ack")]
     SessionRenameAck {
@@ -175,6 +214,36 @@ pub enum WsServerMsg {
         message: Option<String>,
     },
 
+    // ─── Input control ───────────────────────────────────────────────────────
+    /// Response to `session.take_control`. `client_id` is this connection's
+    /// id, as it appears in `session.control_changed`.
+    #[serde(rename = "session.take_control.ack")]
+    SessionTakeControlAck {
+        session_id: String,
+        client_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// Response to `session.release_control`. `released` is false if this
+    /// connection didn't hold the input.
+    #[serde(rename = "session.release_control.ack")]
+    SessionReleaseControlAck {
+        session_id: String,
+        released: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// Broadcast when a session's input changes hands. `controller` is `null`
+    /// when the input is free again.
+    #[serde(rename = "session.control_changed")]
+    SessionControlChanged {
+        session_id: String,
+        controller: Option<String>,
+        label: Option<String>,
+    },
+
     /// Response to `session.ai_status` — confirms an AI status update from the
     /// originating connection.
     #[serde(rename = "session.ai_status.ack")]
@@ -204,6 +273,9 @@ pub enum WsServerMsg {
     SessionStdout {
         session_id: String,
         data: String,
+        /// Original bytes, base64 (`raw` encoding sessions only).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data_b64: Option<String>,
         seq: u64,
         timestamp_ms: u64,
     },
@@ -213,6 +285,9 @@ pub enum WsServerMsg {
     SessionStderr {
         session_id: String,
         data: String,
+        /// Original bytes, base64 (`raw` encoding sessions only).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data_b64: Option<String>,
         seq: u64,
         timestamp_ms: u64,
     },
@@ -226,6 +301,87 @@ pub enum WsServerMsg {
         timestamp_ms: u64,
     },
 
+    // ─── File watches ────────────────────────────────────────────────────────
+    /// Response to `files.watch`. `path` is the directory being watched.
+    #[serde(rename = "files.watch.ack")]
+    FilesWatchAck {
+        watch_id: String,
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        glob: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// Response to `files.unwatch`.
+    #[serde(rename = "files.unwatch.ack")]
+    FilesUnwatchAck {
+        watch_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// A debounced batch of changes under a watch. `overflow` means the
+    /// kernel dropped events, so clients should rescan the directory.
+    #[serde(rename = "files.changed")]
+    FilesChanged {
+        watch_id: String,
+        changes: Vec<FileChange>,
+        overflow: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// The watched directory was removed or moved; the watch has ended.
+    #[serde(rename = "files.watch.closed")]
+    FilesWatchClosed {
+        watch_id: String,
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    // ─── Log tails ───────────────────────────────────────────────────────────
+    /// Response to `logs.follow`: the tail's id and its `path` or `unit`.
+    #[serde(rename = "logs.follow.ack")]
+    LogsFollowAck {
+        tail_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// Response to `logs.unfollow`.
+    #[serde(rename = "logs.unfollow.ack")]
+    LogsUnfollowAck {
+        tail_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// A batch of lines. `dropped` counts lines lost since the previous
+    /// batch because the client fell behind.
+    #[serde(rename = "logs.lines")]
+    LogsLines {
+        tail_id: String,
+        lines: Vec<LogLine>,
+        dropped: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
+    /// The source ended (`journalctl` exited); the tail has stopped.
+    #[serde(rename = "logs.closed")]
+    LogsClosed {
+        tail_id: String,
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+
     // ─── Activity log ────────────────────────────────────────────────────────
     /// Broadcast for every new activity log entry.
     #[serde(rename = "activity.new")]
@@ -239,9 +395,39 @@ pub enum WsServerMsg {
     /// Broadcast for every chunk progress tick.
     #[serde(rename = "gx.progress")]
     GxProgress { data: Progress },
+
+    // ─── Diagnostics ─────────────────────────────────────────────────────────
+    /// Response to `latency.probe`. Timestamps are wall-clock Unix ms on the
+    /// device; `client_ts` is echoed from the probe. When the probe crossed a
+    /// relay, `relay` carries the relay's timestamps and a per-hop breakdown.
+    #[serde(rename = "latency.probe.result")]
+    LatencyProbeResult {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_ts: Option<u64>,
+        device_rx_ms: u64,
+        device_tx_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(test, ts(type = "unknown"))]
+        relay: Option<Value>,
+        /// Tunnel writer queue depths at reply time (tunnel path only).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[cfg_attr(test, ts(type = "unknown"))]
+        device_queues: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
 }
 
 impl WsServerMsg {
+    /// `session.control_changed` for `session_id`, now held by `lock`.
+    pub fn control_changed(session_id: &str, lock: Option<&InputLock>) -> Self {
+        WsServerMsg::SessionControlChanged {
+            session_id: session_id.to_string(),
+            controller: lock.map(|l| l.holder.clone()),
+            label: lock.and_then(|l| l.label.clone()),
+        }
+    }
+
     /// Convert to a `serde_json::Value` for transmission through the existing
     /// `mpsc::Sender<Value>` plumbing. Serialization cannot fail for any of
     /// the typed variants — every field is a primitive, string, or another
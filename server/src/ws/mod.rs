@@ -3,29 +3,46 @@
 //! ## Connection lifecycle
 //!
 //! 1. Client connects to `GET /api/ws?token=<api_key>` — token is validated
-//!    before the upgrade completes.
+//!    before the upgrade completes. An AI client names itself here with
+//!    `X-Sctl-Client` or `?client=`; its mutating `session.*` / `job.*`
+//!    messages are then subject to the AI kill-switch and exec budget (see
+//!    [`crate::ai_guard`]).
 //! 2. All messages are JSON objects with a `"type"` field. An optional
 //!    `"request_id"` on any incoming message is echoed on the corresponding
 //!    response(s), enabling correlation in async/multiplexed clients.
 //! 3. On disconnect, non-persistent sessions are killed and persistent
 //!    sessions are detached (output keeps buffering for later re-attach).
+//!    File watches (see [`crate::file_watch`]) and log tails (see
+//!    [`crate::log_tail`]) are stopped, and session input this connection
+//!    held is released.
+//!
+//! While one connection holds a session's input (`session.take_control`, see
+//! [`crate::sessions::control`]), `session.stdin`, `session.exec` and
+//! `session.signal` from other connections get an `INPUT_LOCKED` error.
 //!
 //! ## Message types (client → server)
 //!
 //! | Type              | Fields                                                        | Response type(s)                |
 //! |-------------------|---------------------------------------------------------------|---------------------------------|
 //! | `ping`            | —                                                             | `pong`                          |
-//! | `session.start`   | `working_dir?`, `persistent?`, `env?`, `shell?`, `pty?`, `rows?`, `cols?`, `idle_timeout?` | `session.started` or `error` |
+//! | `session.start`   | `working_dir?`, `persistent?`, `env?`, `shell?`, `pty?`, `rows?`, `cols?`, `idle_timeout?`, `encoding?` | `session.started` or `error` |
 //! | `session.exec`    | `session_id`, `command`                                       | `session.exec.ack` or `error`   |
 //! | `session.stdin`   | `session_id`, `data`                                          | (none on success, `error` on failure) |
 //! | `session.kill`    | `session_id`                                                  | `session.closed` or `error`     |
 //! | `session.signal`  | `session_id`, `signal`                                        | `session.signal.ack` or `error` |
 //! | `session.attach`  | `session_id`, `since?`                                        | `session.attached` or `error`   |
 //! | `session.resize`  | `session_id`, `rows`, `cols`                                  | `session.resize.ack` or `error` |
-//! | `session.list`    | —                                                             | `session.listed`                |
+//! | `session.list`    | `fields?` (comma-separated, like REST `?fields=`)             | `session.listed`                |
+//! | `session.read_diff` | `session_id`, `since?`                                      | `session.diff` or `error`       |
 //! | `session.allow_ai`    | `session_id`, `allowed` (bool)                                | `session.allow_ai.ack` + broadcast `session.ai_permission_changed` |
 //! | `session.ai_status`   | `session_id`, `working` (bool), `activity?`, `message?`       | `session.ai_status.ack` + broadcast `session.ai_status_changed` |
+//! | `session.take_control`    | `session_id`, `label?`, `force?`                          | `session.take_control.ack` + broadcast `session.control_changed`, or `error` |
+//! | `session.release_control` | `session_id`                                              | `session.release_control.ack` + broadcast `session.control_changed` |
 //! | `shell.list`      | —                                                             | `shell.listed`                  |
+//! | `files.watch`     | `path`, `glob?`, `debounce_ms?`                               | `files.watch.ack` or `error`, then `files.changed` |
+//! | `files.unwatch`   | `watch_id`                                                    | `files.unwatch.ack` or `error`  |
+//! | `logs.follow`     | `path` or `unit`, `lines?`, `grep?`                           | `logs.follow.ack` or `error`, then `logs.lines` |
+//! | `logs.unfollow`   | `tail_id`                                                     | `logs.unfollow.ack` or `error`  |
 //!
 //! ## Message types (server → client)
 //!
@@ -43,7 +60,13 @@
 //! | `session.attached`   | `session_id`, `entries[]`             |
 //! | `session.resize.ack` | `session_id`, `rows`, `cols`          |
 //! | `session.listed`     | `sessions[]` (incl. `status`, `idle`) |
+//! | `session.diff`       | `session_id`, `token`, `full`, `lines[]`, `cursor` |
 //! | `shell.listed`       | `shells[]`, `default_shell`           |
+//! | `session.control_changed` | `session_id`, `controller`, `label` |
+//! | `files.changed`      | `watch_id`, `changes[]` (`path`, `kind`), `overflow` |
+//! | `files.watch.closed` | `watch_id`, `reason`                  |
+//! | `logs.lines`         | `tail_id`, `lines[]` (`line`, `ts_ms?`, `priority?`, `truncated?`), `dropped` |
+//! | `logs.closed`        | `tail_id`, `reason`                   |
 //! | `error`              | `code`, `message`, `session_id?`      |
 
 pub mod messages;
@@ -53,9 +76,10 @@ use std::sync::Arc;
 
 use axum::{
     extract::{Query, State, WebSocketUpgrade},
-    http::StatusCode,
+    http::{Extensions, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
+use base64::Engine;
 use futures::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -66,45 +90,71 @@ use tracing::{error, info};
 
 use crate::activity::{ActivitySource, ActivityType};
 use crate::sessions::buffer::{OutputBuffer, OutputEntry, OutputStream};
+use crate::sessions::decode::OutputEncoding;
+use crate::shell::cgroup::ResourceLimits;
+use crate::shell::sudo::SudoOptions;
 use crate::AppState;
 
 /// Query parameters for the WebSocket upgrade request.
 #[derive(Deserialize)]
 pub struct WsQuery {
     /// API key passed as a query parameter (since HTTP headers aren't available
-    /// during a browser WebSocket upgrade).
+    /// during a browser WebSocket upgrade). Not needed on listeners with
+    /// `auth = false`.
+    #[serde(default)]
     pub token: String,
+    /// Client name, for clients that can't send `X-Sctl-Client`.
+    #[serde(default)]
+    pub client: Option<String>,
 }
 
 /// `GET /api/ws?token=<key>` — WebSocket upgrade handler.
 ///
 /// Validates the token before upgrading. Returns `403 Forbidden` on auth
-/// failure.
+/// failure, or for a named key without the `sessions` scope.
 pub async fn ws_upgrade(
     State(state): State<AppState>,
     Query(query): Query<WsQuery>,
+    headers: HeaderMap,
+    extensions: Extensions,
     ws: WebSocketUpgrade,
 ) -> Response {
-    if !crate::auth::constant_time_eq(state.config.auth.api_key.as_bytes(), query.token.as_bytes())
-    {
+    let identity = crate::auth::keyless_identity(&extensions).or_else(|| {
+        state
+            .api_keys
+            .resolve(&state.config.auth.keys, &query.token)
+    });
+    let Some(identity) = identity.filter(|i| i.allows("sessions")) else {
         return (StatusCode::FORBIDDEN, "Forbidden").into_response();
-    }
+    };
+    crate::connections::identify(&extensions, &identity.name);
+
+    // The AI guard reads the client from headers, as for REST requests.
+    let client_headers = crate::ai_guard::upgrade_headers(&headers, query.client.as_deref());
 
-    ws.on_upgrade(move |socket| handle_ws(socket, state))
+    ws.on_upgrade(move |socket| {
+        crate::auth::with_identity(identity, handle_ws(socket, state, client_headers))
+    })
 }
 
 /// Convert an [`OutputEntry`] to a WebSocket JSON message.
 fn entry_to_ws_message(session_id: &str, entry: &OutputEntry) -> Value {
+    let data_b64 = entry
+        .raw
+        .as_ref()
+        .map(|r| base64::engine::general_purpose::STANDARD.encode(r));
     let msg = match entry.stream {
         OutputStream::Stdout => WsServerMsg::SessionStdout {
             session_id: session_id.to_string(),
             data: entry.data.clone(),
+            data_b64,
             seq: entry.seq,
             timestamp_ms: entry.timestamp_ms,
         },
         OutputStream::Stderr => WsServerMsg::SessionStderr {
             session_id: session_id.to_string(),
             data: entry.data.clone(),
+            data_b64,
             seq: entry.seq,
             timestamp_ms: entry.timestamp_ms,
         },
@@ -160,7 +210,11 @@ async fn subscriber_task(
 /// - Incoming WebSocket messages from the client
 /// - Broadcast events (session lifecycle) from other connections
 #[allow(clippy::too_many_lines)]
-async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
+async fn handle_ws(
+    socket: axum::extract::ws::WebSocket,
+    state: AppState,
+    client_headers: HeaderMap,
+) {
     let (mut ws_sink, mut ws_stream) = socket.split();
 
     // Channel for sending messages back to the WebSocket
@@ -181,12 +235,21 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
         )
         .await;
 
+    // Identifies this connection as a holder of session input
+    let client_id = format!("ws:{}", uuid::Uuid::new_v4());
+
     // Track sessions created by this connection for cleanup on disconnect
     let mut connection_sessions: Vec<String> = Vec::new();
 
     // Track subscriber tasks so they can be aborted on disconnect
     let mut subscriber_tasks: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
 
+    // File watches started by this connection; dropped (and stopped) on disconnect
+    let mut file_watches = crate::file_watch::FileWatches::default();
+
+    // Log tails, likewise; shared so a slow backlog read runs off the loop
+    let log_tails = Arc::new(Mutex::new(crate::log_tail::LogTails::default()));
+
     // Task: forward channel messages to WebSocket sink
     let send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
@@ -226,15 +289,58 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                             continue;
                         };
 
+                        // Fill `session.start` from the `[session_templates]` entry it names.
+                        let parsed = if parsed["type"] == "session.start" {
+                            match crate::sessions::templates::expand(&state, &parsed).await {
+                                Ok(parsed) => parsed,
+                                Err(e) => {
+                                    let _ = tx
+                                        .send(WsServerMsg::Error {
+                                            code: "INVALID_REQUEST".into(),
+                                            message: e,
+                                            session_id: None,
+                                            request_id: parsed["request_id"].as_str().map(String::from),
+                                        }.to_value())
+                                        .await;
+                                    continue;
+                                }
+                            }
+                        } else {
+                            parsed
+                        };
+
                         let msg_type = parsed["type"].as_str().unwrap_or("");
                         let request_id = parsed["request_id"].as_str().map(ToString::to_string);
 
+                        if let Err((_, axum::Json(err))) =
+                            state.ai_guard.check_ws_message(&client_headers, msg_type)
+                        {
+                            let _ = tx.send(WsServerMsg::Error {
+                                code: err.code,
+                                message: err.message,
+                                session_id: parsed["session_id"].as_str().map(String::from),
+                                request_id: request_id.clone(),
+                            }.to_value()).await;
+                            continue;
+                        }
+
                         match msg_type {
                             "ping" => {
                                 let _ = tx.send(WsServerMsg::Pong {
                                     request_id: request_id.clone(),
                                 }.to_value()).await;
                             }
+                            "latency.probe" => {
+                                let now_ms = crate::sessions::journal::now_ms();
+                                let _ = tx.send(WsServerMsg::LatencyProbeResult {
+                                    client_ts: parsed["client_ts"].as_u64(),
+                                    device_rx_ms: now_ms,
+                                    device_tx_ms: crate::sessions::journal::now_ms(),
+                                    relay: None,
+                                    device_queues: None,
+                                    request_id: request_id.clone(),
+                                }.to_value()).await;
+                            }
                             "session.start" => {
                                 let working_dir = parsed["working_dir"].as_str().map(ToString::to_string);
                                 let persistent = parsed["persistent"].as_bool().unwrap_or(false);
@@ -256,6 +362,10 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     .unwrap_or(u64::from(state.config.server.default_terminal_cols))
                                     as u16;
                                 let idle_timeout = parsed["idle_timeout"].as_u64().unwrap_or(0);
+                                let record = parsed["record"].as_bool().unwrap_or(false);
+                                let sudo = SudoOptions::from_message(&parsed);
+                                let encoding = OutputEncoding::from_message(&parsed);
+                                let limits = state.session_manager.resource_limits(&parsed);
 
                                 if let Some(session_id) = handle_session_start(
                                     &state,
@@ -271,6 +381,10 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     idle_timeout,
                                     name.as_deref(),
                                     user_allows_ai,
+                                    record,
+                                    sudo,
+                                    encoding,
+                                    limits,
                                 )
                                 .await
                                 {
@@ -347,6 +461,9 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     }.to_value()).await;
                                     continue;
                                 }
+                                if input_refused(&state, &tx, session_id, &client_id, request_id.as_deref()).await {
+                                    continue;
+                                }
                                 state.session_manager.touch_ai_activity(session_id).await;
                                 handle_session_exec(
                                     &state,
@@ -360,7 +477,9 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                             "session.stdin" => {
                                 let session_id = parsed["session_id"].as_str().unwrap_or("");
                                 let data = parsed["data"].as_str().unwrap_or("");
-                                if !session_id.is_empty() {
+                                if !session_id.is_empty()
+                                    && !input_refused(&state, &tx, session_id, &client_id, None).await
+                                {
                                     state.session_manager.touch_ai_activity(session_id).await;
                                     handle_session_stdin(&state, &tx, session_id, data).await;
                                 }
@@ -394,6 +513,9 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     }.to_value()).await;
                                     continue;
                                 }
+                                if input_refused(&state, &tx, session_id, &client_id, request_id.as_deref()).await {
+                                    continue;
+                                }
                                 #[allow(clippy::cast_possible_truncation)]
                                 let signal_i32 = signal as i32;
                                 handle_session_signal(
@@ -429,10 +551,42 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                 .await;
                             }
                             "session.list" => {
-                                let _ = tx.send(WsServerMsg::SessionListed {
+                                let mut reply = WsServerMsg::SessionListed {
                                     sessions: state.session_manager.list_sessions().await,
                                     request_id: request_id.clone(),
-                                }.to_value()).await;
+                                }.to_value();
+                                crate::util::project_fields(&mut reply["sessions"], parsed["fields"].as_str());
+                                let _ = tx.send(reply).await;
+                            }
+                            "session.read_diff" => {
+                                let session_id = parsed["session_id"].as_str().unwrap_or("");
+                                let since = parsed["since"].as_u64();
+                                let reply = match state.session_manager.read_screen_diff(session_id, since).await {
+                                    Ok(diff) => WsServerMsg::SessionDiff {
+                                        session_id: session_id.to_string(),
+                                        diff,
+                                        request_id: request_id.clone(),
+                                    },
+                                    Err(e) => WsServerMsg::Error {
+                                        code: "SCREEN_UNAVAILABLE".into(),
+                                        message: e,
+                                        session_id: Some(session_id.to_string()),
+                                        request_id: request_id.clone(),
+                                    },
+                                };
+                                let _ = tx.send(reply.to_value()).await;
+                            }
+                            "session.history" => {
+                                let session_id = parsed["session_id"].as_str().unwrap_or("");
+                                let reply = session_history_reply(
+                                    &state,
+                                    session_id,
+                                    parsed["since"].as_u64().unwrap_or(0),
+                                    parsed["limit"].as_u64(),
+                                    request_id.clone(),
+                                )
+                                .await;
+                                let _ = tx.send(reply).await;
                             }
                             "session.resize" => {
                                 let session_id = parsed["session_id"].as_str().unwrap_or("");
@@ -548,6 +702,68 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     }
                                 }
                             }
+                            "session.take_control" => {
+                                let session_id = parsed["session_id"].as_str().unwrap_or("");
+                                let label = parsed["label"].as_str();
+                                let force = parsed["force"].as_bool().unwrap_or(false);
+                                let reply = match state
+                                    .session_manager
+                                    .take_control(session_id, &client_id, label, force)
+                                    .await
+                                {
+                                    Some(Ok(lock)) => {
+                                        let _ = state.session_events.send(
+                                            WsServerMsg::control_changed(session_id, Some(&lock)).to_value(),
+                                        );
+                                        WsServerMsg::SessionTakeControlAck {
+                                            session_id: session_id.to_string(),
+                                            client_id: client_id.clone(),
+                                            request_id: request_id.clone(),
+                                        }
+                                    }
+                                    Some(Err(e)) => WsServerMsg::Error {
+                                        code: "INPUT_LOCKED".into(),
+                                        message: e,
+                                        session_id: Some(session_id.to_string()),
+                                        request_id: request_id.clone(),
+                                    },
+                                    None => WsServerMsg::Error {
+                                        code: "SESSION_NOT_FOUND".into(),
+                                        message: format!("Session {session_id} not found"),
+                                        session_id: Some(session_id.to_string()),
+                                        request_id: request_id.clone(),
+                                    },
+                                };
+                                let _ = tx.send(reply.to_value()).await;
+                            }
+                            "session.release_control" => {
+                                let session_id = parsed["session_id"].as_str().unwrap_or("");
+                                let reply = match state
+                                    .session_manager
+                                    .release_control(session_id, &client_id)
+                                    .await
+                                {
+                                    Some(released) => {
+                                        if released {
+                                            let _ = state.session_events.send(
+                                                WsServerMsg::control_changed(session_id, None).to_value(),
+                                            );
+                                        }
+                                        WsServerMsg::SessionReleaseControlAck {
+                                            session_id: session_id.to_string(),
+                                            released,
+                                            request_id: request_id.clone(),
+                                        }
+                                    }
+                                    None => WsServerMsg::Error {
+                                        code: "SESSION_NOT_FOUND".into(),
+                                        message: format!("Session {session_id} not found"),
+                                        session_id: Some(session_id.to_string()),
+                                        request_id: request_id.clone(),
+                                    },
+                                };
+                                let _ = tx.send(reply.to_value()).await;
+                            }
                             "session.rename" => {
                                 let session_id = parsed["session_id"].as_str().unwrap_or("");
                                 let name = parsed["name"].as_str().unwrap_or("");
@@ -590,6 +806,36 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
                                     request_id: request_id.clone(),
                                 }.to_value()).await;
                             }
+                            "files.watch" => {
+                                let reply = crate::file_watch::handle_watch(
+                                    &mut file_watches, &parsed, None, tx.clone(),
+                                );
+                                let _ = tx.send(reply).await;
+                            }
+                            "files.unwatch" => {
+                                let reply = crate::file_watch::handle_unwatch(&mut file_watches, &parsed);
+                                let _ = tx.send(reply).await;
+                            }
+                            "logs.follow" => {
+                                let (tails, tx, msg) = (log_tails.clone(), tx.clone(), parsed.clone());
+                                crate::auth::spawn_with_identity(async move {
+                                    crate::log_tail::handle_follow(&mut *tails.lock().await, &msg, None, tx).await;
+                                });
+                            }
+                            "logs.unfollow" => {
+                                let reply = crate::log_tail::handle_unfollow(&mut *log_tails.lock().await, &parsed);
+                                let _ = tx.send(reply).await;
+                            }
+                            t if state.extensions.handler(t).is_some() => {
+                                // Run off the read loop so a slow handler
+                                // doesn't stall this connection's input.
+                                let (state, tx, msg) = (state.clone(), tx.clone(), parsed.clone());
+                                tokio::spawn(async move {
+                                    if let Some(reply) = state.extensions.dispatch(&state, &msg).await {
+                                        let _ = tx.send(reply).await;
+                                    }
+                                });
+                            }
                             _ => {
                                 let _ = tx.send(WsServerMsg::Error {
                                     code: "UNKNOWN_TYPE".into(),
@@ -633,6 +879,17 @@ async fn handle_ws(socket: axum::extract::ws::WebSocket, state: AppState) {
         )
         .await;
 
+    // Free any session input this connection held
+    for session_id in state
+        .session_manager
+        .release_controls_where(|holder| holder == client_id)
+        .await
+    {
+        let _ = state
+            .session_events
+            .send(WsServerMsg::control_changed(&session_id, None).to_value());
+    }
+
     // Connection closed — handle cleanup based on persistence
     if !connection_sessions.is_empty() {
         info!(
@@ -672,6 +929,10 @@ async fn handle_session_start(
     idle_timeout: u64,
     name: Option<&str>,
     user_allows_ai: Option<bool>,
+    record: bool,
+    sudo: Result<Option<SudoOptions>, String>,
+    encoding: Result<Option<OutputEncoding>, String>,
+    limits: Result<Option<ResourceLimits>, String>,
 ) -> Option<String> {
     let raw_dir = working_dir.unwrap_or(&state.config.shell.default_working_dir);
     let expanded = crate::util::expand_tilde(raw_dir);
@@ -679,6 +940,45 @@ async fn handle_session_start(
     let sh = shell.unwrap_or(&state.config.shell.default_shell);
     let allows_ai = user_allows_ai.unwrap_or(true);
 
+    // Resolve `secret://` env values, then give elevated sessions a prompt
+    // marker; the sudo password itself is only ever read from the device
+    // environment by the responder task.
+    let prepared = match crate::shell::secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
+        env,
+    )
+    .await
+    .and_then(|resolved| {
+        let encoding = encoding?;
+        let limits = limits?;
+        let sudo = sudo?
+            .map(|s| s.prepare_session(use_pty, resolved.env()))
+            .transpose()?;
+        Ok(((resolved, sudo), (encoding, limits)))
+    }) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            let _ = tx
+                .send(
+                    WsServerMsg::Error {
+                        code: "INVALID_REQUEST".into(),
+                        message: e,
+                        session_id: None,
+                        request_id: request_id.map(String::from),
+                    }
+                    .to_value(),
+                )
+                .await;
+            return None;
+        }
+    };
+    let (prepared, (encoding, limits)) = prepared;
+    let (env, sudo) = match prepared {
+        (_, Some((merged, sudo))) => (Some(merged), Some(sudo)),
+        (resolved, None) => (resolved.env().cloned(), None),
+    };
+
     tracing::info!(
         request_id = request_id.unwrap_or(""),
         shell = sh,
@@ -695,13 +995,15 @@ async fn handle_session_start(
         .create_session_with_pty(
             sh,
             dir,
-            env,
+            env.as_ref(),
             persistent,
             use_pty,
             rows,
             cols,
             idle_timeout,
             name,
+            limits.as_ref(),
+            Some(state.session_events.clone()),
         )
         .await
     {
@@ -712,6 +1014,13 @@ async fn handle_session_start(
                 pid,
                 "WS: session.start PTY spawn succeeded"
             );
+            if let Some(sudo) = sudo {
+                crate::shell::sudo::spawn_session_responder(
+                    state.session_manager.clone(),
+                    session_id.clone(),
+                    sudo,
+                );
+            }
             // Override default AI permission if explicitly disabled
             if !allows_ai {
                 let _ = state
@@ -719,6 +1028,34 @@ async fn handle_session_start(
                     .set_user_allows_ai(&session_id, false)
                     .await;
             }
+            if let Some(encoding) = encoding {
+                let _ = state
+                    .session_manager
+                    .set_encoding(&session_id, encoding)
+                    .await;
+            }
+            // A session that asked to be recorded must not run unrecorded.
+            if record {
+                if let Err(e) = state
+                    .session_manager
+                    .start_recording(&session_id, sh, rows, cols)
+                    .await
+                {
+                    state.session_manager.kill_session(&session_id).await;
+                    let _ = tx
+                        .send(
+                            WsServerMsg::Error {
+                                code: "RECORDING_FAILED".into(),
+                                message: e,
+                                session_id: None,
+                                request_id: request_id.map(String::from),
+                            }
+                            .to_value(),
+                        )
+                        .await;
+                    return None;
+                }
+            }
 
             let queued = tx
                 .send(
@@ -728,6 +1065,7 @@ async fn handle_session_start(
                         persistent,
                         pty: use_pty,
                         user_allows_ai: allows_ai,
+                        recording: record,
                         created_at: crate::sessions::journal::now_ms(),
                         name: name.map(String::from),
                         request_id: request_id.map(String::from),
@@ -766,6 +1104,8 @@ async fn handle_session_start(
                         "session_id": session_id,
                         "pty": use_pty,
                         "persistent": persistent,
+                        "recording": record,
+                        "limits": state.session_manager.limits(&session_id).await,
                     })),
                     None,
                 )
@@ -826,13 +1166,54 @@ async fn handle_job_start(
         "WS: job.start received"
     );
 
+    if let Err((_, axum::Json(err))) =
+        crate::shell::policy::check(state, ActivitySource::Ws, command).await
+    {
+        let _ = tx
+            .send(
+                WsServerMsg::Error {
+                    code: err.code,
+                    message: err.message,
+                    session_id: None,
+                    request_id: request_id.map(String::from),
+                }
+                .to_value(),
+            )
+            .await;
+        return None;
+    }
+
+    let env = match crate::shell::secrets::resolve_env(
+        state.config.secrets.as_ref(),
+        &state.config.server.data_dir,
+        env,
+    )
+    .await
+    {
+        Ok(env) => env,
+        Err(e) => {
+            let _ = tx
+                .send(
+                    WsServerMsg::Error {
+                        code: "INVALID_REQUEST".into(),
+                        message: e,
+                        session_id: None,
+                        request_id: request_id.map(String::from),
+                    }
+                    .to_value(),
+                )
+                .await;
+            return None;
+        }
+    };
+
     match state
         .session_manager
         .create_job(
             sh,
             dir,
             command,
-            env,
+            env.env(),
             name,
             crate::sessions::JOB_IDLE_TIMEOUT_SECS,
             state.session_events.clone(),
@@ -854,6 +1235,7 @@ async fn handle_job_start(
                         persistent: true,
                         pty: false,
                         user_allows_ai: true,
+                        recording: false,
                         created_at: crate::sessions::journal::now_ms(),
                         name: name.map(String::from),
                         request_id: request_id.map(String::from),
@@ -911,6 +1293,22 @@ async fn handle_session_exec(
     command: &str,
     request_id: Option<&str>,
 ) {
+    if let Err((_, axum::Json(err))) =
+        crate::shell::policy::check(state, ActivitySource::Ws, command).await
+    {
+        let _ = tx
+            .send(
+                WsServerMsg::Error {
+                    code: err.code,
+                    message: err.message,
+                    session_id: Some(session_id.to_string()),
+                    request_id: request_id.map(String::from),
+                }
+                .to_value(),
+            )
+            .await;
+        return;
+    }
     if let Err(e) = state
         .session_manager
         .exec_command(session_id, command)
@@ -945,7 +1343,10 @@ async fn handle_session_exec(
                 ActivityType::SessionExec,
                 ActivitySource::Ws,
                 crate::activity::truncate_str(command, 80),
-                Some(json!({ "session_id": session_id })),
+                Some(json!({
+                    "session_id": session_id,
+                    "risk": crate::shell::classify::classify(&state.config.classify, command),
+                })),
                 None,
             )
             .await;
@@ -978,6 +1379,36 @@ async fn handle_session_stdin(
     }
 }
 
+/// Refuse input to `session_id` from `client_id` if another connection holds
+/// it, sending `INPUT_LOCKED`. Returns whether the input was refused.
+async fn input_refused(
+    state: &AppState,
+    tx: &mpsc::Sender<Value>,
+    session_id: &str,
+    client_id: &str,
+    request_id: Option<&str>,
+) -> bool {
+    let Err(message) = state
+        .session_manager
+        .check_input(session_id, client_id)
+        .await
+    else {
+        return false;
+    };
+    let _ = tx
+        .send(
+            WsServerMsg::Error {
+                code: "INPUT_LOCKED".into(),
+                message,
+                session_id: Some(session_id.to_string()),
+                request_id: request_id.map(String::from),
+            }
+            .to_value(),
+        )
+        .await;
+    true
+}
+
 /// Handle `session.kill` — terminate a session and remove it from the manager.
 async fn handle_session_kill(
     state: &AppState,
@@ -1121,6 +1552,39 @@ async fn handle_session_resize(
     }
 }
 
+/// Reply to `session.history` (WS and tunnel): up to `limit` (default 500)
+/// journal entries after `since`, for scrolling back past what
+/// `session.attach` could replay.
+pub(crate) async fn session_history_reply(
+    state: &AppState,
+    session_id: &str,
+    since: u64,
+    limit: Option<u64>,
+    request_id: Option<String>,
+) -> Value {
+    let limit = limit.map_or(500, |l| usize::try_from(l).unwrap_or(usize::MAX));
+    match crate::routes::sessions::scrollback(state, session_id, since, limit).await {
+        Ok(page) => WsServerMsg::SessionHistory {
+            session_id: session_id.to_string(),
+            entries: page
+                .entries
+                .iter()
+                .map(|e| entry_to_ws_message(session_id, e))
+                .collect(),
+            next_seq: page.next_seq,
+            has_more: page.next_seq.is_some(),
+            request_id,
+        },
+        Err((_, axum::Json(err))) => WsServerMsg::Error {
+            code: err.code,
+            message: err.message,
+            session_id: Some(session_id.to_string()),
+            request_id,
+        },
+    }
+    .to_value()
+}
+
 /// Handle `session.attach` — re-attach to a detached session, replay missed
 /// output, and start a subscriber.
 async fn handle_session_attach(
@@ -0,0 +1,440 @@
+//! Extended attributes and POSIX ACLs.
+//!
+//! Thin wrappers over the `l*xattr` syscalls (symlinks are never followed),
+//! plus a codec for the kernel's `system.posix_acl_*` binary format so ACLs
+//! read and write as `getfacl`-style text entries (`user::rwx`,
+//! `group:100:r-x`, `mask::rwx`) without linking libacl. Qualifiers are
+//! numeric uids/gids.
+//!
+//! Windows has no xattrs or POSIX ACLs: paths list none, and reading or
+//! writing one fails as unsupported.
+//!
+//! All functions block; call them via [`crate::io_pool::run`].
+
+#[cfg(unix)]
+use std::ffi::CString;
+use std::ffi::OsString;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Access ACL of a file or directory.
+pub const ACL_ACCESS: &str = "system.posix_acl_access";
+/// Default ACL inherited by new entries in a directory.
+pub const ACL_DEFAULT: &str = "system.posix_acl_default";
+
+const ACL_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+const TAG_USER_OBJ: u16 = 0x01;
+const TAG_USER: u16 = 0x02;
+const TAG_GROUP_OBJ: u16 = 0x04;
+const TAG_GROUP: u16 = 0x08;
+const TAG_MASK: u16 = 0x10;
+const TAG_OTHER: u16 = 0x20;
+
+/// One extended attribute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Xattr {
+    /// Full name including namespace, e.g. `user.comment`.
+    pub name: String,
+    /// UTF-8 text, or base64 if `encoding` is `"base64"`.
+    pub value: String,
+    /// `"base64"` for binary values, absent for UTF-8 text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+impl Xattr {
+    /// Raw value bytes.
+    pub fn bytes(&self) -> Result<Vec<u8>, String> {
+        match self.encoding.as_deref() {
+            None | Some("utf8") => Ok(self.value.clone().into_bytes()),
+            Some("base64") => base64::engine::general_purpose::STANDARD
+                .decode(&self.value)
+                .map_err(|e| format!("{}: invalid base64: {e}", self.name)),
+            Some(other) => Err(format!("{}: unknown encoding '{other}'", self.name)),
+        }
+    }
+}
+
+/// Extended attributes and ACLs of one path. ACL xattrs appear as `acl` and
+/// `default_acl` rather than in `xattrs`.
+#[derive(Debug, Default, Serialize)]
+pub struct Attrs {
+    pub xattrs: Vec<Xattr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_acl: Option<Vec<String>>,
+}
+
+/// The `l*xattr` calls. macOS spells them as the plain calls with
+/// `XATTR_NOFOLLOW`, and reports a missing attribute as `ENOATTR` where Linux
+/// uses `ENODATA`.
+#[cfg(target_os = "linux")]
+mod sys {
+    pub(super) use libc::{lgetxattr, llistxattr, lremovexattr, lsetxattr, ENODATA as ENOATTR};
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use libc::{c_char, c_int, c_void, size_t, ssize_t, XATTR_NOFOLLOW};
+
+    pub(super) use libc::ENOATTR;
+
+    pub(super) unsafe fn lgetxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *mut c_void,
+        size: size_t,
+    ) -> ssize_t {
+        libc::getxattr(path, name, value, size, 0, XATTR_NOFOLLOW)
+    }
+
+    pub(super) unsafe fn llistxattr(
+        path: *const c_char,
+        list: *mut c_char,
+        size: size_t,
+    ) -> ssize_t {
+        libc::listxattr(path, list, size, XATTR_NOFOLLOW)
+    }
+
+    pub(super) unsafe fn lsetxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        size: size_t,
+        flags: c_int,
+    ) -> c_int {
+        libc::setxattr(path, name, value, size, 0, flags | XATTR_NOFOLLOW)
+    }
+
+    pub(super) unsafe fn lremovexattr(path: *const c_char, name: *const c_char) -> c_int {
+        libc::removexattr(path, name, XATTR_NOFOLLOW)
+    }
+}
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+#[cfg(unix)]
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+/// Call a size-probing xattr syscall: once with an empty buffer for the size,
+/// then again with a buffer that big, retrying if the value grew in between.
+#[cfg(unix)]
+fn read_sized(
+    mut call: impl FnMut(*mut libc::c_void, usize) -> libc::ssize_t,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        let Ok(size) = usize::try_from(size) else {
+            return Err(io::Error::last_os_error());
+        };
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buf = vec![0u8; size];
+        let n = call(buf.as_mut_ptr().cast(), buf.len());
+        match usize::try_from(n) {
+            Ok(n) => {
+                buf.truncate(n);
+                return Ok(buf);
+            }
+            Err(_) if io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) => {}
+            Err(_) => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Attribute names on `path`. Filesystems without xattr support have none.
+#[cfg(unix)]
+pub fn list(path: &Path) -> io::Result<Vec<OsString>> {
+    let path = c_path(path)?;
+    // SAFETY: `path` is NUL-terminated and the buffer is valid for `size` bytes.
+    let raw =
+        match read_sized(|buf, size| unsafe { sys::llistxattr(path.as_ptr(), buf.cast(), size) }) {
+            Ok(raw) => raw,
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+    Ok(raw
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| OsString::from_vec(name.to_vec()))
+        .collect())
+}
+
+/// Value of attribute `name`.
+#[cfg(unix)]
+pub fn get(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    // SAFETY: both strings are NUL-terminated and the buffer is valid for `size` bytes.
+    read_sized(|buf, size| unsafe { sys::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size) })
+}
+
+/// Create or replace attribute `name`.
+#[cfg(unix)]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    // SAFETY: both strings are NUL-terminated and `value` is valid for its length.
+    let rc = unsafe {
+        sys::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Remove attribute `name`. Removing one that isn't set is not an error.
+#[cfg(unix)]
+pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    // SAFETY: both strings are NUL-terminated.
+    if unsafe { sys::lremovexattr(path.as_ptr(), name.as_ptr()) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(sys::ENOATTR) {
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(windows)]
+pub fn list(_path: &Path) -> io::Result<Vec<OsString>> {
+    Ok(Vec::new())
+}
+
+#[cfg(windows)]
+pub fn get(_path: &Path, _name: &str) -> io::Result<Vec<u8>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(windows)]
+pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(windows)]
+pub fn remove(_path: &Path, _name: &str) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// All attributes of `path`, with ACLs decoded. Attributes that vanish or
+/// can't be read between listing and reading are left out.
+pub fn read_all(path: &Path) -> io::Result<Attrs> {
+    let mut attrs = Attrs::default();
+    for name in list(path)? {
+        let name = name.to_string_lossy().into_owned();
+        let Ok(value) = get(path, &name) else {
+            continue;
+        };
+        match name.as_str() {
+            ACL_ACCESS => attrs.acl = decode_acl(&value).ok(),
+            ACL_DEFAULT => attrs.default_acl = decode_acl(&value).ok(),
+            _ => attrs.xattrs.push(match std::str::from_utf8(&value) {
+                Ok(text) => Xattr {
+                    name,
+                    value: text.to_string(),
+                    encoding: None,
+                },
+                Err(_) => Xattr {
+                    name,
+                    value: base64::engine::general_purpose::STANDARD.encode(&value),
+                    encoding: Some("base64".to_string()),
+                },
+            }),
+        }
+    }
+    attrs.xattrs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attrs)
+}
+
+fn perm_text(perm: u16) -> String {
+    [(4, 'r'), (2, 'w'), (1, 'x')]
+        .iter()
+        .map(|&(bit, c)| if perm & bit == 0 { '-' } else { c })
+        .collect()
+}
+
+/// Decode the kernel's ACL xattr into text entries, in kernel order.
+pub fn decode_acl(raw: &[u8]) -> Result<Vec<String>, String> {
+    let (header, body) = raw.split_first_chunk::<4>().ok_or("ACL too short")?;
+    if u32::from_le_bytes(*header) != ACL_VERSION {
+        return Err("unsupported ACL version".into());
+    }
+    if body.len() % 8 != 0 {
+        return Err("truncated ACL entry".into());
+    }
+    body.chunks_exact(8)
+        .map(|e| {
+            let tag = u16::from_le_bytes([e[0], e[1]]);
+            let perm = perm_text(u16::from_le_bytes([e[2], e[3]]));
+            let id = u32::from_le_bytes([e[4], e[5], e[6], e[7]]);
+            Ok(match tag {
+                TAG_USER_OBJ => format!("user::{perm}"),
+                TAG_USER => format!("user:{id}:{perm}"),
+                TAG_GROUP_OBJ => format!("group::{perm}"),
+                TAG_GROUP => format!("group:{id}:{perm}"),
+                TAG_MASK => format!("mask::{perm}"),
+                TAG_OTHER => format!("other::{perm}"),
+                _ => return Err(format!("unknown ACL tag {tag:#x}")),
+            })
+        })
+        .collect()
+}
+
+fn parse_perm(text: &str) -> Option<u16> {
+    let b = text.as_bytes();
+    if b.len() != 3 {
+        return None;
+    }
+    let bit = |i: usize, c: u8, v: u16| match b[i] {
+        x if x == c => Some(v),
+        b'-' => Some(0),
+        _ => None,
+    };
+    Some(bit(0, b'r', 4)? | bit(1, b'w', 2)? | bit(2, b'x', 1)?)
+}
+
+/// Encode text entries (`user::rwx`, `u:1000:r-x`, `g::r--`, `m::rwx`,
+/// `o::---`) into the kernel's ACL xattr. Entries are sorted as the kernel
+/// requires; the base `user::`, `group::` and `other::` entries are
+/// mandatory, and `mask::` is required once named entries are present.
+pub fn encode_acl(entries: &[String]) -> Result<Vec<u8>, String> {
+    let mut parsed = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let invalid = || format!("invalid ACL entry '{entry}'");
+        let mut parts = entry.trim().split(':');
+        let (Some(kind), Some(qualifier), Some(perm), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let perm = parse_perm(perm).ok_or_else(invalid)?;
+        let (tag, id) = match (kind, qualifier) {
+            ("user" | "u", "") => (TAG_USER_OBJ, ACL_UNDEFINED_ID),
+            ("group" | "g", "") => (TAG_GROUP_OBJ, ACL_UNDEFINED_ID),
+            ("mask" | "m", "") => (TAG_MASK, ACL_UNDEFINED_ID),
+            ("other" | "o", "") => (TAG_OTHER, ACL_UNDEFINED_ID),
+            ("user" | "u", id) => (TAG_USER, id.parse().map_err(|_| invalid())?),
+            ("group" | "g", id) => (TAG_GROUP, id.parse().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        };
+        parsed.push((tag, id, perm));
+    }
+    parsed.sort_unstable_by_key(|&(tag, id, _)| (tag, id));
+    if parsed
+        .windows(2)
+        .any(|w| (w[0].0, w[0].1) == (w[1].0, w[1].1))
+    {
+        return Err("duplicate ACL entry".into());
+    }
+    let has = |tag| parsed.iter().any(|&(t, _, _)| t == tag);
+    if !(has(TAG_USER_OBJ) && has(TAG_GROUP_OBJ) && has(TAG_OTHER)) {
+        return Err("ACL needs user::, group:: and other:: entries".into());
+    }
+    if (has(TAG_USER) || has(TAG_GROUP)) && !has(TAG_MASK) {
+        return Err("ACL with named entries needs a mask:: entry".into());
+    }
+
+    let mut raw = ACL_VERSION.to_le_bytes().to_vec();
+    for (tag, id, perm) in parsed {
+        raw.extend_from_slice(&tag.to_le_bytes());
+        raw.extend_from_slice(&perm.to_le_bytes());
+        raw.extend_from_slice(&id.to_le_bytes());
+    }
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn acl_round_trips_in_kernel_order() {
+        let raw = encode_acl(&entries(&[
+            "other::r--",
+            "g:100:r-x",
+            "user::rwx",
+            "m::rwx",
+            "user:1000:rw-",
+            "group::r-x",
+        ]))
+        .unwrap();
+        assert_eq!(raw.len(), 4 + 6 * 8);
+        assert_eq!(
+            decode_acl(&raw).unwrap(),
+            entries(&[
+                "user::rwx",
+                "user:1000:rw-",
+                "group::r-x",
+                "group:100:r-x",
+                "mask::rwx",
+                "other::r--",
+            ])
+        );
+    }
+
+    #[test]
+    fn acl_decodes_kernel_bytes() {
+        // user::rw- group::r-- other::r--, as written by `setfacl -m u::rw-`.
+        let raw = [
+            2, 0, 0, 0, //
+            1, 0, 6, 0, 255, 255, 255, 255, //
+            4, 0, 4, 0, 255, 255, 255, 255, //
+            32, 0, 4, 0, 255, 255, 255, 255,
+        ];
+        assert_eq!(
+            decode_acl(&raw).unwrap(),
+            entries(&["user::rw-", "group::r--", "other::r--"])
+        );
+        assert!(decode_acl(&raw[..10]).is_err());
+        assert!(decode_acl(&[1, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn acl_rejects_incomplete_or_malformed() {
+        for bad in [
+            &["user::rwx", "group::r-x"][..],
+            &["user::rwx", "group::r-x", "other::---", "user:1000:r--"],
+            &[
+                "user::rwx",
+                "group::r-x",
+                "other::---",
+                "user:alice:r--",
+                "mask::r--",
+            ],
+            &["user::rwx", "group::r-x", "other::rw"],
+            &["user::rwx", "user::r-x", "group::r-x", "other::---"],
+            &["user::rwx", "group::r-x", "other::---", "everyone::r--"],
+        ] {
+            assert!(encode_acl(&entries(bad)).is_err(), "{bad:?}");
+        }
+    }
+}
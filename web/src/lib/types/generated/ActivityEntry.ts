@@ -5,4 +5,9 @@ import type { ActivityType } from "./ActivityType";
 /**
  * A single activity journal entry.
  */
-export type ActivityEntry = { id: number, timestamp: number, activity_type: ActivityType, source: ActivitySource, summary: string, detail?: unknown, request_id?: string, };
+export type ActivityEntry = { id: number, timestamp: number, activity_type: ActivityType, source: ActivitySource, summary: string, detail?: unknown, request_id?: string, 
+/**
+ * Name of the API key the action was made with (`default` for
+ * `auth.api_key`, `local` on loopback listeners).
+ */
+key?: string, };
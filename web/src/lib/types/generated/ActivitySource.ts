@@ -3,4 +3,4 @@
 /**
  * Where the request originated.
  */
-export type ActivitySource = "mcp" | "ws" | "rest" | "tunnel" | "unknown";
+export type ActivitySource = "mcp" | "ws" | "rest" | "tunnel" | "mqtt" | "sms" | "unknown";
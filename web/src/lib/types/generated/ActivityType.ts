@@ -3,4 +3,4 @@
 /**
  * Types of activities tracked by the journal.
  */
-export type ActivityType = "exec" | "file_read" | "file_write" | "file_list" | "session_start" | "session_exec" | "session_kill" | "session_signal" | "file_delete" | "playbook_list" | "playbook_read" | "playbook_write" | "playbook_delete" | "ws_connect" | "ws_disconnect" | "tunnel_connect" | "tunnel_disconnect" | "transfer_start" | "transfer_complete";
+export type ActivityType = "exec" | "file_read" | "file_write" | "file_list" | "session_start" | "session_exec" | "session_kill" | "session_signal" | "file_delete" | "playbook_list" | "playbook_read" | "playbook_write" | "playbook_delete" | "ws_connect" | "ws_disconnect" | "tunnel_connect" | "tunnel_disconnect" | "transfer_start" | "transfer_complete" | "ssh_key_list" | "ssh_key_write" | "ssh_key_delete" | "user_list" | "user_modify" | "time_set" | "firewall_apply" | "firewall_rollback" | "exec_confirm" | "exec_rollback" | "session_share" | "support_bundle" | "activity_export" | "ai_kill_switch" | "plugin_run" | "playbook_run" | "twin_update" | "twin_apply" | "tunnel_key_rotate" | "auth_rotate" | "policy_denied" | "fetch" | "forward" | "sftp" | "snapshot" | "artifact_push" | "netman_up" | "netman_down" | "sms_send" | "sms_delete" | "sms_command" | "service_control" | "connection_close" | "firmware_update";
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ChangeKind } from "./ChangeKind";
+
+/**
+ * One entry of a `files.changed` batch.
+ */
+export type FileChange = { 
+/**
+ * Absolute path of the changed entry.
+ */
+path: string, kind: ChangeKind, };
@@ -1,3 +1,15 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type InitDownload = { path: string, chunk_size?: number, };
+export type InitDownload = { path: string, chunk_size?: number, 
+/**
+ * `path` is a directory, sent as a tar archive.
+ */
+directory: boolean, 
+/**
+ * With `directory`, archive only these paths (relative to `path`).
+ */
+files?: Array<string>, 
+/**
+ * Bandwidth cap in bytes/s, at most `transfer_rate_limit_bps`.
+ */
+rate_limit_bps?: number, };
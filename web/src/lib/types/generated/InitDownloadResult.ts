@@ -1,3 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type InitDownloadResult = { transfer_id: string, file_size: number, file_hash: string, chunk_size: number, total_chunks: number, filename: string, };
+export type InitDownloadResult = { transfer_id: string, file_size: number, file_hash: string, chunk_size: number, total_chunks: number, filename: string, 
+/**
+ * The download is a tar of the directory at `path`.
+ */
+directory: boolean, };
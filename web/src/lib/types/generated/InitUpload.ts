@@ -4,4 +4,13 @@ export type InitUpload = { path: string, filename: string, file_size: number,
 /**
  * Whole-file SHA-256 hash. If empty, the server computes it after all chunks are received.
  */
-file_hash: string, chunk_size: number, total_chunks: number, mode?: string, };
+file_hash: string, chunk_size: number, total_chunks: number, mode?: string, 
+/**
+ * The upload is a tar archive, unpacked into the directory `path/filename`
+ * (created if missing) instead of saved as a file.
+ */
+directory: boolean, 
+/**
+ * Bandwidth cap in bytes/s, at most `transfer_rate_limit_bps`.
+ */
+rate_limit_bps?: number, };
@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * One line of a tail.
+ */
+export type LogLine = { line: string, 
+/**
+ * Journal entry time, Unix ms. Not set for files.
+ */
+ts_ms?: number, 
+/**
+ * Journal priority, 0 (emerg) to 7 (debug). Not set for files.
+ */
+priority?: number, 
+/**
+ * The line was longer than 16 KiB and has been cut.
+ */
+truncated: boolean, };
@@ -0,0 +1,23 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ScreenLine } from "./ScreenLine";
+
+/**
+ * Result of [`Screen::diff_since`].
+ */
+export type ScreenDiff = { 
+/**
+ * Pass back as `since` to get only later changes.
+ */
+token: number, 
+/**
+ * `true` if `lines` holds every row (first read, stale token, resize).
+ */
+full: boolean, rows: number, cols: number, 
+/**
+ * `[row, col]`, zero-based.
+ */
+cursor: [number, number], 
+/**
+ * Whether a full-screen program has the alternate screen active.
+ */
+alt_screen: boolean, lines: Array<ScreenLine>, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * One changed row in a [`ScreenDiff`].
+ */
+export type ScreenLine = { row: number, 
+/**
+ * Row text with trailing blanks trimmed.
+ */
+text: string, };
@@ -47,4 +47,28 @@ ai_activity?: string,
 /**
  * Short status message from the AI (e.g. "Running tests").
  */
-ai_status_message?: string, };
+ai_status_message?: string, 
+/**
+ * Terminal title last set by the program (OSC 0/2), PTY sessions only.
+ */
+title?: string, 
+/**
+ * Working directory last reported by the shell (OSC 7), PTY sessions only.
+ */
+cwd?: string, 
+/**
+ * Output encoding: `"utf8"`, `"latin1"` or `"raw"`.
+ */
+encoding: string, 
+/**
+ * Charset guessed from output that wasn't valid UTF-8 (`"gbk"`, `"latin1"`).
+ */
+charset_hint?: string, 
+/**
+ * Connection holding the session's input (`session.take_control`).
+ */
+controller?: string, 
+/**
+ * Label the controller gave itself.
+ */
+controller_label?: string, };
@@ -1,4 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { Direction } from "./Direction";
 
-export type StatusResult = { transfer_id: string, direction: Direction, phase: string, filename: string, file_size: number, chunks_done: number, total_chunks: number, bytes_transferred: number, elapsed_ms: number, error_count: number, };
+export type StatusResult = { transfer_id: string, direction: Direction, phase: string, filename: string, file_size: number, chunks_done: number, total_chunks: number, bytes_transferred: number, elapsed_ms: number, error_count: number, 
+/**
+ * Current bandwidth cap in bytes/s, `0` when unlimited.
+ */
+rate_limit_bps: number, };
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * `gx.throttle`: change a transfer's bandwidth cap, or the global one when
+ * `transfer_id` is absent. Also the reply, with the cap now in effect.
+ */
+export type Throttle = { transfer_id?: string, 
+/**
+ * Bytes/s, `0` for unlimited (a transfer still can't exceed
+ * `transfer_rate_limit_bps`).
+ */
+rate_limit_bps: number, };
@@ -1,7 +1,10 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { ActivityEntry } from "./ActivityEntry";
 import type { Complete } from "./Complete";
+import type { FileChange } from "./FileChange";
+import type { LogLine } from "./LogLine";
 import type { Progress } from "./Progress";
+import type { ScreenLine } from "./ScreenLine";
 import type { SessionListItem } from "./SessionListItem";
 import type { JsonValue } from "./serde_json/JsonValue";
 
@@ -9,4 +12,36 @@ import type { JsonValue } from "./serde_json/JsonValue";
  * Server → client message. Wire format is `{"type": "<code>", ...fields}`
  * via serde's internally-tagged enum representation.
  */
-export type WsServerMsg = { "type": "pong", request_id?: string, } | { "type": "error", code: string, message: string, session_id?: string, request_id?: string, } | { "type": "session.started", session_id: string, pid: number, persistent: boolean, pty: boolean, user_allows_ai: boolean, created_at: number, name?: string, request_id?: string, } | { "type": "session.created", session_id: string, pid: number, pty: boolean, persistent: boolean, user_allows_ai: boolean, name?: string, } | { "type": "session.destroyed", session_id: string, reason: string, } | { "type": "session.closed", session_id: string, reason: string, request_id?: string, } | { "type": "session.attached", session_id: string, entries: Array<JsonValue>, dropped: number, request_id?: string, } | { "type": "session.listed", sessions: Array<SessionListItem>, request_id?: string, } | { "type": "session.renamed", session_id: string, name: string, } | { "type": "session.rename.ack", session_id: string, name: string, request_id?: string, } | { "type": "session.exec.ack", session_id: string, command: string, request_id?: string, } | { "type": "session.signal.ack", session_id: string, signal: number, request_id?: string, } | { "type": "session.resize.ack", session_id: string, rows: number, cols: number, request_id?: string, } | { "type": "session.allow_ai.ack", session_id: string, allowed: boolean, request_id?: string, } | { "type": "session.ai_permission_changed", session_id: string, allowed: boolean, } | { "type": "session.ai_status_changed", session_id: string, working: boolean, activity?: string, message?: string, } | { "type": "session.ai_status.ack", session_id: string, working: boolean, activity?: string, message?: string, request_id?: string, } | { "type": "shell.listed", shells: Array<string>, default_shell: string, request_id?: string, } | { "type": "session.stdout", session_id: string, data: string, seq: number, timestamp_ms: number, } | { "type": "session.stderr", session_id: string, data: string, seq: number, timestamp_ms: number, } | { "type": "session.system", session_id: string, data: string, seq: number, timestamp_ms: number, } | { "type": "activity.new", entry: ActivityEntry, } | { "type": "gx.complete", data: Complete, } | { "type": "gx.progress", data: Progress, };
+export type WsServerMsg = { "type": "pong", request_id?: string, } | { "type": "error", code: string, message: string, session_id?: string, request_id?: string, } | { "type": "session.started", session_id: string, pid: number, persistent: boolean, pty: boolean, user_allows_ai: boolean, 
+/**
+ * Output is being recorded (`record: true`).
+ */
+recording: boolean, created_at: number, name?: string, request_id?: string, } | { "type": "session.created", session_id: string, pid: number, pty: boolean, persistent: boolean, user_allows_ai: boolean, name?: string, } | { "type": "session.destroyed", session_id: string, reason: string, } | { "type": "session.closed", session_id: string, reason: string, request_id?: string, } | { "type": "session.attached", session_id: string, entries: Array<JsonValue>, dropped: number, request_id?: string, } | { "type": "session.listed", sessions: Array<SessionListItem>, request_id?: string, } | { "type": "session.renamed", session_id: string, name: string, } | { "type": "session.updated", session_id: string, title?: string, cwd?: string, } | { "type": "session.diff", session_id: string, request_id?: string, 
+/**
+ * Pass back as `since` to get only later changes.
+ */
+token: number, 
+/**
+ * `true` if `lines` holds every row (first read, stale token, resize).
+ */
+full: boolean, rows: number, cols: number, 
+/**
+ * `[row, col]`, zero-based.
+ */
+cursor: [number, number], 
+/**
+ * Whether a full-screen program has the alternate screen active.
+ */
+alt_screen: boolean, lines: Array<ScreenLine>, } | { "type": "session.history.result", session_id: string, entries: Array<JsonValue>, next_seq?: number, has_more: boolean, request_id?: string, } | { "type": "session.rename.ack", session_id: string, name: string, request_id?: string, } | { "type": "session.exec.ack", session_id: string, command: string, request_id?: string, } | { "type": "session.signal.ack", session_id: string, signal: number, request_id?: string, } | { "type": "session.resize.ack", session_id: string, rows: number, cols: number, request_id?: string, } | { "type": "session.allow_ai.ack", session_id: string, allowed: boolean, request_id?: string, } | { "type": "session.ai_permission_changed", session_id: string, allowed: boolean, } | { "type": "session.ai_status_changed", session_id: string, working: boolean, activity?: string, message?: string, } | { "type": "session.take_control.ack", session_id: string, client_id: string, request_id?: string, } | { "type": "session.release_control.ack", session_id: string, released: boolean, request_id?: string, } | { "type": "session.control_changed", session_id: string, controller?: string, label?: string, } | { "type": "session.ai_status.ack", session_id: string, working: boolean, activity?: string, message?: string, request_id?: string, } | { "type": "shell.listed", shells: Array<string>, default_shell: string, request_id?: string, } | { "type": "session.stdout", session_id: string, data: string, 
+/**
+ * Original bytes, base64 (`raw` encoding sessions only).
+ */
+data_b64?: string, seq: number, timestamp_ms: number, } | { "type": "session.stderr", session_id: string, data: string, 
+/**
+ * Original bytes, base64 (`raw` encoding sessions only).
+ */
+data_b64?: string, seq: number, timestamp_ms: number, } | { "type": "session.system", session_id: string, data: string, seq: number, timestamp_ms: number, } | { "type": "files.watch.ack", watch_id: string, path: string, glob?: string, request_id?: string, } | { "type": "files.unwatch.ack", watch_id: string, request_id?: string, } | { "type": "files.changed", watch_id: string, changes: Array<FileChange>, overflow: boolean, request_id?: string, } | { "type": "files.watch.closed", watch_id: string, reason: string, request_id?: string, } | { "type": "logs.follow.ack", tail_id: string, path?: string, unit?: string, request_id?: string, } | { "type": "logs.unfollow.ack", tail_id: string, request_id?: string, } | { "type": "logs.lines", tail_id: string, lines: Array<LogLine>, dropped: number, request_id?: string, } | { "type": "logs.closed", tail_id: string, reason: string, request_id?: string, } | { "type": "activity.new", entry: ActivityEntry, } | { "type": "gx.complete", data: Complete, } | { "type": "gx.progress", data: Progress, } | { "type": "latency.probe.result", client_ts?: number, device_rx_ms: number, device_tx_ms: number, relay?: unknown, 
+/**
+ * Tunnel writer queue depths at reply time (tunnel path only).
+ */
+device_queues?: unknown, request_id?: string, };
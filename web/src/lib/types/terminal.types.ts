@@ -208,6 +208,8 @@ export interface WsSessionStartMsg {
 	rows?: number;
 	cols?: number;
 	name?: string;
+	/** Output decoding: `utf8` (default), `latin1`, or `raw` (adds `data_b64`). */
+	encoding?: 'utf8' | 'latin1' | 'raw';
 }
 
 export interface WsJobStartMsg {
@@ -263,6 +265,8 @@ export interface WsSessionResizeMsg {
 
 export interface WsSessionListMsg {
 	type: 'session.list';
+	/** Comma-separated fields to keep in each session. */
+	fields?: string;
 	request_id?: string;
 }
 
@@ -285,6 +289,23 @@ export interface WsSessionAllowAiMsg {
 	allowed: boolean;
 }
 
+export interface WsFilesWatchMsg {
+	type: 'files.watch';
+	request_id?: string;
+	/** Directory, file, or directory plus a glob in the last component. */
+	path: string;
+	/** Name filter for a directory watch, e.g. `*.log`. */
+	glob?: string;
+	/** Batch window for `files.changed` (default 200). */
+	debounce_ms?: number;
+}
+
+export interface WsFilesUnwatchMsg {
+	type: 'files.unwatch';
+	request_id?: string;
+	watch_id: string;
+}
+
 export type WsClientMsg =
 	| WsPingMsg
 	| WsSessionStartMsg
@@ -298,7 +319,9 @@ export type WsClientMsg =
 	| WsSessionListMsg
 	| WsShellListMsg
 	| WsSessionRenameMsg
-	| WsSessionAllowAiMsg;
+	| WsSessionAllowAiMsg
+	| WsFilesWatchMsg
+	| WsFilesUnwatchMsg;
 
 // ── Wire protocol: server → client ─────────────────────────────────
 
@@ -611,6 +634,8 @@ export type WsSessionRenameAckMsg = Extract<GeneratedWsServerMsg, { type: 'sessi
 export type WsSessionCreatedBroadcast = Extract<GeneratedWsServerMsg, { type: 'session.created' }>;
 export type WsSessionDestroyedBroadcast = Extract<GeneratedWsServerMsg, { type: 'session.destroyed' }>;
 export type WsSessionRenamedBroadcast = Extract<GeneratedWsServerMsg, { type: 'session.renamed' }>;
+export type WsSessionUpdatedBroadcast = Extract<GeneratedWsServerMsg, { type: 'session.updated' }>;
+export type WsSessionDiffMsg = Extract<GeneratedWsServerMsg, { type: 'session.diff' }>;
 export type WsSessionAllowAiAckMsg = Extract<GeneratedWsServerMsg, { type: 'session.allow_ai.ack' }>;
 export type WsSessionAiPermissionChangedBroadcast = Extract<
 	GeneratedWsServerMsg,
@@ -620,6 +645,9 @@ export type WsSessionAiStatusChangedBroadcast = Extract<
 	GeneratedWsServerMsg,
 	{ type: 'session.ai_status_changed' }
 >;
+export type WsFilesWatchAckMsg = Extract<GeneratedWsServerMsg, { type: 'files.watch.ack' }>;
+export type WsFilesChangedMsg = Extract<GeneratedWsServerMsg, { type: 'files.changed' }>;
+export type WsFilesWatchClosedMsg = Extract<GeneratedWsServerMsg, { type: 'files.watch.closed' }>;
 
 // Canonical server → client union sourced from the Rust enum via ts-rs.
 // Synthetic variants (`session.gap`, `session.exited`) that don't exist on
@@ -706,6 +734,13 @@ export interface CachedExecResult {
 	command: string;
 	status: string;
 	error_message?: string;
+	summary?: {
+		strategy: string;
+		stdout: string;
+		stderr: string;
+		stdout_bytes: number;
+		stderr_bytes: number;
+	};
 }
 
 // ── History/Activity filtering ─────────────────────────────────